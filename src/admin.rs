@@ -0,0 +1,351 @@
+//! The administrative HTTP API.
+//!
+//! The endpoints under `/admin/` change the running application rather than
+//! report on it, so they only accept POST requests. The work is done by
+//! the main task, which owns the [`Manager`](crate::manager::Manager), so
+//! requests are passed to it and its outcome passed back.
+//!
+//! The endpoints are only served if the config file has an `[admin]`
+//! section, described by [`AdminConfig`]. Requests then need to carry its
+//! token as a bearer token in their `Authorization` header.
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwapOption;
+use async_trait::async_trait;
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::common::openapi;
+use crate::http::{PercentDecodedPath, ProcessRequest};
+
+//------------ AdminConfig ---------------------------------------------------
+
+/// The settings of the administrative HTTP API.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    /// The bearer token requests need to carry.
+    pub token: String,
+}
+
+impl AdminConfig {
+    /// Returns whether a request carries the token.
+    fn authorizes(&self, request: &Request<Body>) -> bool {
+        let Some(token) = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+
+        // Compare all bytes, so the time taken does not reveal how much of
+        // the token was right.
+        let (token, expected) =
+            (token.trim().as_bytes(), self.token.as_bytes());
+        token.len() == expected.len()
+            && token
+                .iter()
+                .zip(expected)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+//------------ AdminApi ------------------------------------------------------
+
+/// The processor of the administrative HTTP API.
+pub struct AdminApi {
+    requests: mpsc::Sender<AdminRequest>,
+
+    /// The settings, if the API is enabled.
+    config: ArcSwapOption<AdminConfig>,
+}
+
+impl AdminApi {
+    /// The path under which the endpoints are served.
+    pub const REL_BASE_URL: &'static str = "/admin/";
+
+    /// Creates the processor and the receiver of its requests.
+    pub fn new() -> (Self, mpsc::Receiver<AdminRequest>) {
+        let (requests, rx) = mpsc::channel(1);
+        let config = ArcSwapOption::empty();
+        (Self { requests, config }, rx)
+    }
+
+    /// Enables the API with the given settings or disables it.
+    pub fn configure(&self, config: Option<AdminConfig>) {
+        self.config.store(config.map(Arc::new));
+    }
+
+    fn unauthorized() -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .header("Content-Type", "text/plain")
+            .body("A valid bearer token is required".into())
+            .unwrap()
+    }
+
+    async fn request(&self, action: AdminAction) -> Response<Body> {
+        let (tx, rx) = oneshot::channel();
+        let request = AdminRequest { action, reply: tx };
+        let res = match self.requests.send(request).await {
+            Ok(()) => rx.await.ok(),
+            Err(_) => None,
+        };
+        let (status, body) = match res {
+            Some(Ok(summary)) => (StatusCode::OK, summary),
+            Some(Err(AdminError::NotFound(err))) => {
+                (StatusCode::NOT_FOUND, err)
+            }
+            Some(Err(AdminError::Failed(err))) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, err)
+            }
+            None => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The administrative API is not available".to_string(),
+            ),
+        };
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "text/plain")
+            .body(body.into())
+            .unwrap()
+    }
+}
+
+#[async_trait]
+impl ProcessRequest for AdminApi {
+    async fn process_request(
+        &self,
+        request: &Request<Body>,
+    ) -> Option<Response<Body>> {
+        if request.method() != Method::POST {
+            return None;
+        }
+        let path = request.uri().decoded_path();
+        let path = path.strip_prefix(Self::REL_BASE_URL)?;
+        let config = self.config.load_full()?;
+        if !config.authorizes(request) {
+            return Some(Self::unauthorized());
+        }
+        if path == "reload" {
+            return Some(self.request(AdminAction::Reload).await);
+        }
+        let (kind, rest) = path.split_once('/')?;
+        let (name, verb) = rest.rsplit_once('/')?;
+        if name.is_empty() {
+            return None;
+        }
+        let name = name.to_string();
+        let action = match (kind, verb) {
+            ("units", "pause") => AdminAction::PauseUnit(name),
+            ("units", "resume") => AdminAction::ResumeUnit(name),
+            ("targets", "drain") => AdminAction::DrainTarget(name),
+            _ => return None,
+        };
+        Some(self.request(action).await)
+    }
+
+    fn describe(&self, paths: &mut openapi::Paths) {
+        paths
+            .post(
+                format!("{}reload", Self::REL_BASE_URL),
+                "Reload the configuration file",
+            )
+            .produces("text/plain")
+            .description(
+                "As on SIGHUP, the config file is read again and applied: \
+                new units and targets are started, removed ones stopped, \
+                and those affected by the changes reconfigured or \
+                restarted. The response summarises the changes. Requires \
+                the token of the `[admin]` section as a bearer token.",
+            );
+        paths
+            .post(
+                format!("{}units/{{name}}/pause", Self::REL_BASE_URL),
+                "Pause a unit",
+            )
+            .path_param("name", "The name of the unit")
+            .produces("text/plain")
+            .description(
+                "The unit stops passing on updates and, if it receives \
+                them from the network, stops reading from it while \
+                keeping its connections open. Reconfiguring, restarting \
+                or stopping the unit resumes it. Requires the token of the \
+                `[admin]` section as a bearer token.",
+            );
+        paths
+            .post(
+                format!("{}units/{{name}}/resume", Self::REL_BASE_URL),
+                "Resume a paused unit",
+            )
+            .path_param("name", "The name of the unit")
+            .produces("text/plain")
+            .description(
+                "Requires the token of the `[admin]` section as a bearer \
+                token.",
+            );
+        paths
+            .post(
+                format!("{}targets/{{name}}/drain", Self::REL_BASE_URL),
+                "Drain and stop a target",
+            )
+            .path_param("name", "The name of the target")
+            .produces("text/plain")
+            .description(
+                "No more updates are sent to the target. Once it has \
+                processed those already queued for it, it is stopped, \
+                flushing what it buffered. The response is sent when the \
+                target has stopped. Reloading the configuration starts it \
+                again. Requires the token of the `[admin]` section as a \
+                bearer token.",
+            );
+    }
+}
+
+//------------ AdminAction ---------------------------------------------------
+
+/// What an administrative request asks for.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AdminAction {
+    /// Reload the configuration file.
+    Reload,
+
+    /// Pause the unit with the given name.
+    PauseUnit(String),
+
+    /// Resume the unit with the given name.
+    ResumeUnit(String),
+
+    /// Drain and stop the target with the given name.
+    DrainTarget(String),
+}
+
+//------------ AdminRequest --------------------------------------------------
+
+/// A request made via the administrative HTTP API.
+pub struct AdminRequest {
+    action: AdminAction,
+    reply: oneshot::Sender<Result<String, AdminError>>,
+}
+
+impl AdminRequest {
+    /// Returns what is asked for.
+    pub fn action(&self) -> &AdminAction {
+        &self.action
+    }
+
+    /// Answers the request with a summary of what was done or an error.
+    pub fn reply<E: Into<AdminError>>(self, res: Result<String, E>) {
+        let _ = self.reply.send(res.map_err(Into::into));
+    }
+}
+
+//------------ AdminError ----------------------------------------------------
+
+/// Why an administrative request could not be carried out.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AdminError {
+    /// There is no running unit or target by the name given.
+    NotFound(String),
+
+    /// Carrying out the request failed.
+    Failed(String),
+}
+
+impl From<String> for AdminError {
+    fn from(err: String) -> Self {
+        AdminError::Failed(err)
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(path: &str, token: Option<&str>) -> Request<Body> {
+        let mut request = Request::post(path);
+        if let Some(token) = token {
+            request = request
+                .header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reload_requires_the_token() {
+        let (api, mut requests) = AdminApi::new();
+        let reload = |token| post("/admin/reload", token);
+
+        // Without an [admin] section, the endpoints are not served.
+        assert!(api.process_request(&reload(None)).await.is_none());
+        assert!(api.process_request(&reload(Some("s3cret"))).await.is_none());
+
+        api.configure(Some(AdminConfig {
+            token: "s3cret".into(),
+        }));
+        for token in [None, Some("secret"), Some("s3cret2"), Some("")] {
+            let res = api.process_request(&reload(token)).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert!(requests.try_recv().is_err());
+
+        let answer = async {
+            let request = requests.recv().await.unwrap();
+            assert_eq!(request.action(), &AdminAction::Reload);
+            request.reply::<String>(Ok("reloaded".into()));
+        };
+        let (res, ()) = tokio::join!(
+            api.process_request(&reload(Some("s3cret"))),
+            answer
+        );
+        assert_eq!(res.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unit_and_target_actions_require_the_token() {
+        let (api, mut requests) = AdminApi::new();
+        let paths = [
+            ("/admin/units/bmp-in/pause", AdminAction::PauseUnit),
+            ("/admin/units/bmp-in/resume", AdminAction::ResumeUnit),
+            ("/admin/targets/bmp-in/drain", AdminAction::DrainTarget),
+        ];
+        for (path, _) in &paths {
+            assert!(api.process_request(&post(path, None)).await.is_none());
+        }
+
+        api.configure(Some(AdminConfig {
+            token: "s3cret".into(),
+        }));
+        for (path, action) in paths {
+            for token in [None, Some("secret")] {
+                let res = api.process_request(&post(path, token)).await;
+                assert_eq!(res.unwrap().status(), StatusCode::UNAUTHORIZED);
+            }
+            assert!(requests.try_recv().is_err());
+
+            let answer = async {
+                let request = requests.recv().await.unwrap();
+                assert_eq!(request.action(), &action("bmp-in".into()));
+                request.reply::<String>(Ok("done".into()));
+            };
+            let (res, ()) = tokio::join!(
+                api.process_request(&post(path, Some("s3cret"))),
+                answer
+            );
+            assert_eq!(res.unwrap().status(), StatusCode::OK);
+        }
+
+        // Disabling the API again stops serving the endpoints.
+        api.configure(None);
+        let pause = post("/admin/units/bmp-in/pause", Some("s3cret"));
+        assert!(api.process_request(&pause).await.is_none());
+    }
+}
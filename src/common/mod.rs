@@ -3,6 +3,10 @@ pub(crate) mod frim;
 pub(crate) mod json;
 pub(crate) mod memory;
 pub(crate) mod net;
+pub mod openapi;
+pub(crate) mod quic;
 pub(crate) mod routecore_extra;
 pub(crate) mod status_reporter;
+pub(crate) mod tcp_auth;
+pub(crate) mod tls;
 pub(crate) mod unit;
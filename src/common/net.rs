@@ -3,10 +3,12 @@
 // These traits enable us to swap out the real TCP listener for a mock when
 // testing.
 
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
 use tokio::net::TcpStream;
 
+use super::tcp_auth::TcpAuth;
+
 #[async_trait::async_trait]
 pub trait TcpListenerFactory<T> {
     async fn bind(&self, addr: String) -> std::io::Result<T>;
@@ -15,6 +17,16 @@ pub trait TcpListenerFactory<T> {
 #[async_trait::async_trait]
 pub trait TcpListener<T> {
     async fn accept(&self) -> std::io::Result<(T, SocketAddr)>;
+
+    /// Authenticates connections from peers in `peer/prefix_len`.
+    fn set_tcp_auth(
+        &self,
+        _peer: IpAddr,
+        _prefix_len: u8,
+        _auth: &TcpAuth,
+    ) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -47,13 +59,45 @@ impl TcpListener<StandardTcpStream> for StandardTcpListener {
         let (stream, addr) = self.0.accept().await?;
         Ok((StandardTcpStream(stream), addr))
     }
+
+    fn set_tcp_auth(
+        &self,
+        peer: IpAddr,
+        prefix_len: u8,
+        auth: &TcpAuth,
+    ) -> std::io::Result<()> {
+        auth.apply(&self.0, peer, prefix_len)
+    }
 }
 
 pub struct StandardTcpStream(::tokio::net::TcpStream);
 
+impl From<TcpStream> for StandardTcpStream {
+    fn from(stream: TcpStream) -> Self {
+        Self(stream)
+    }
+}
+
 /// A thin wrapper around the Tokio TcpListener accept() call result.
 impl TcpStreamWrapper for StandardTcpStream {
     fn into_inner(self) -> std::io::Result<TcpStream> {
         Ok(self.0)
     }
 }
+
+//--- UNIX sockets -----------------------------------------------------------
+
+/// Listens on the UNIX socket at `path`, replacing a socket left behind by
+/// an earlier run.
+pub fn bind_unix(
+    path: &std::path::Path,
+) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    tokio::net::UnixListener::bind(path)
+}
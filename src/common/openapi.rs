@@ -0,0 +1,353 @@
+//! Describing the HTTP API as an OpenAPI document.
+//!
+//! Every [`ProcessRequest`] describes the endpoints it serves by adding
+//! them to the [`Paths`] given to its [`describe`] method, under the paths
+//! it was configured with. The document served at `/openapi.json` is
+//! assembled from the processors registered at the time of the request, so
+//! it lists exactly the endpoints of the running components.
+//!
+//! [`ProcessRequest`]: crate::http::ProcessRequest
+//! [`describe`]: crate::http::ProcessRequest::describe
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use serde_json::{json, Map, Value};
+
+/// The version of the OpenAPI specification the document follows.
+pub const OPENAPI_VERSION: &str = "3.0.3";
+
+//------------ Paths ---------------------------------------------------------
+
+/// The endpoints of the HTTP API, by path and method.
+#[derive(Clone, Debug, Default)]
+pub struct Paths {
+    /// The tag given to the operations added, naming their component.
+    tag: Option<Arc<str>>,
+
+    paths: BTreeMap<String, BTreeMap<&'static str, Operation>>,
+}
+
+impl Paths {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the tag for the operations added from now on.
+    pub fn set_tag(&mut self, tag: Option<Arc<str>>) {
+        self.tag = tag;
+    }
+
+    /// Adds a GET operation.
+    pub fn get(
+        &mut self,
+        path: impl Into<String>,
+        summary: &str,
+    ) -> &mut Operation {
+        self.add("get", path.into(), summary)
+    }
+
+    /// Adds a POST operation.
+    pub fn post(
+        &mut self,
+        path: impl Into<String>,
+        summary: &str,
+    ) -> &mut Operation {
+        self.add("post", path.into(), summary)
+    }
+
+    fn add(
+        &mut self,
+        method: &'static str,
+        path: String,
+        summary: &str,
+    ) -> &mut Operation {
+        let operation = Operation {
+            summary: summary.to_string(),
+            description: None,
+            tag: self.tag.clone(),
+            parameters: Vec::new(),
+            content_type: "application/json",
+        };
+        let methods = self.paths.entry(path).or_default();
+        methods.insert(method, operation);
+        methods.get_mut(method).unwrap()
+    }
+
+    /// Returns the paths and methods of the operations, in order.
+    pub fn operations(
+        &self,
+    ) -> impl Iterator<Item = (&str, &'static str, &Operation)> {
+        self.paths.iter().flat_map(|(path, methods)| {
+            methods
+                .iter()
+                .map(move |(method, op)| (path.as_str(), *method, op))
+        })
+    }
+
+    /// Returns the OpenAPI document.
+    pub fn document(&self, title: &str, version: &str) -> Value {
+        let mut paths = Map::new();
+        for (path, methods) in &self.paths {
+            let mut item = Map::new();
+            for (method, operation) in methods {
+                item.insert(
+                    method.to_string(),
+                    operation.to_json(method, path),
+                );
+            }
+            paths.insert(path.clone(), item.into());
+        }
+        json!({
+            "openapi": OPENAPI_VERSION,
+            "info": {
+                "title": title,
+                "version": version,
+            },
+            "paths": paths,
+        })
+    }
+}
+
+//------------ Operation -----------------------------------------------------
+
+/// An endpoint and method of the HTTP API.
+#[derive(Clone, Debug)]
+pub struct Operation {
+    summary: String,
+    description: Option<String>,
+    tag: Option<Arc<str>>,
+    parameters: Vec<Parameter>,
+
+    /// The content type of a successful response.
+    content_type: &'static str,
+}
+
+#[derive(Clone, Debug)]
+struct Parameter {
+    name: String,
+    location: &'static str,
+    description: String,
+    schema: Schema,
+    required: bool,
+    repeated: bool,
+}
+
+impl Operation {
+    /// Adds a longer description.
+    pub fn description(&mut self, description: &str) -> &mut Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    /// Sets the content type of a successful response.
+    pub fn produces(&mut self, content_type: &'static str) -> &mut Self {
+        self.content_type = content_type;
+        self
+    }
+
+    /// Adds an optional query parameter.
+    pub fn query(
+        &mut self,
+        name: &str,
+        schema: Schema,
+        description: &str,
+    ) -> &mut Self {
+        self.param(name, "query", schema, description, false, false)
+    }
+
+    /// Adds a required query parameter.
+    pub fn required_query(
+        &mut self,
+        name: &str,
+        schema: Schema,
+        description: &str,
+    ) -> &mut Self {
+        self.param(name, "query", schema, description, true, false)
+    }
+
+    /// Adds a query parameter that can be given more than once.
+    pub fn repeated_query(
+        &mut self,
+        name: &str,
+        schema: Schema,
+        description: &str,
+    ) -> &mut Self {
+        self.param(name, "query", schema, description, false, true)
+    }
+
+    /// Adds a parameter for the `{name}` part of the path.
+    pub fn path_param(&mut self, name: &str, description: &str) -> &mut Self {
+        self.param(name, "path", Schema::String, description, true, false)
+    }
+
+    fn param(
+        &mut self,
+        name: &str,
+        location: &'static str,
+        schema: Schema,
+        description: &str,
+        required: bool,
+        repeated: bool,
+    ) -> &mut Self {
+        self.parameters.push(Parameter {
+            name: name.to_string(),
+            location,
+            description: description.to_string(),
+            schema,
+            required,
+            repeated,
+        });
+        self
+    }
+
+    /// Returns the names of the query parameters.
+    pub fn query_params(&self) -> impl Iterator<Item = &str> {
+        self.parameters
+            .iter()
+            .filter(|param| param.location == "query")
+            .map(|param| param.name.as_str())
+    }
+
+    fn to_json(&self, method: &str, path: &str) -> Value {
+        let mut res = json!({
+            "summary": self.summary,
+            "operationId": operation_id(method, path),
+            "responses": {
+                "200": {
+                    "description": "Success",
+                    "content": { self.content_type: {} },
+                },
+                "400": {
+                    "description": "Invalid request",
+                    "content": { "text/plain": {} },
+                },
+            },
+        });
+        if let Some(description) = &self.description {
+            res["description"] = description.as_str().into();
+        }
+        if let Some(tag) = &self.tag {
+            res["tags"] = json!([tag]);
+        }
+        if !self.parameters.is_empty() {
+            res["parameters"] = self
+                .parameters
+                .iter()
+                .map(|param| {
+                    let mut schema = param.schema.to_json();
+                    if param.repeated {
+                        schema = json!({ "type": "array", "items": schema });
+                    }
+                    json!({
+                        "name": param.name,
+                        "in": param.location,
+                        "description": param.description,
+                        "required": param.required,
+                        "schema": schema,
+                    })
+                })
+                .collect();
+        }
+        res
+    }
+}
+
+/// Derives an operation id from a method and path, e.g. `get_prefixes_routes`
+/// for GET `/prefixes/routes`.
+fn operation_id(method: &str, path: &str) -> String {
+    std::iter::once(method)
+        .chain(
+            path.split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|part| !part.is_empty()),
+        )
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+//------------ Schema --------------------------------------------------------
+
+/// The type of a parameter.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Schema {
+    String,
+    Integer,
+    Boolean,
+}
+
+impl Schema {
+    fn to_json(self) -> Value {
+        match self {
+            Schema::String => json!({ "type": "string" }),
+            Schema::Integer => json!({ "type": "integer" }),
+            Schema::Boolean => json!({ "type": "boolean" }),
+        }
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_are_assembled() {
+        let mut paths = Paths::new();
+        paths.get("/metrics", "Metrics").produces("text/plain");
+        paths.set_tag(Some("rib".into()));
+        paths
+            .get("/prefixes/{prefix}", "Routes for a prefix")
+            .path_param("prefix", "The prefix")
+            .query("limit", Schema::Integer, "At most this many")
+            .repeated_query("tag", Schema::String, "Tags to match");
+        paths.post("/prefixes/compaction", "Compact");
+
+        assert_eq!(
+            paths
+                .operations()
+                .map(|(path, method, _)| format!("{method} {path}"))
+                .collect::<Vec<_>>(),
+            [
+                "get /metrics",
+                "post /prefixes/compaction",
+                "get /prefixes/{prefix}",
+            ]
+        );
+
+        let doc = paths.document("Rotonda", "1.0");
+        assert_eq!(doc["openapi"], OPENAPI_VERSION);
+        assert_eq!(doc["info"]["version"], "1.0");
+        let metrics = &doc["paths"]["/metrics"]["get"];
+        assert_eq!(metrics["operationId"], "get_metrics");
+        assert!(metrics.get("tags").is_none());
+        assert!(metrics.get("parameters").is_none());
+        assert!(
+            metrics["responses"]["200"]["content"]["text/plain"].is_object()
+        );
+
+        let prefix = &doc["paths"]["/prefixes/{prefix}"]["get"];
+        assert_eq!(prefix["operationId"], "get_prefixes_prefix");
+        assert_eq!(prefix["tags"], json!(["rib"]));
+        assert_eq!(
+            prefix["parameters"],
+            json!([
+                {
+                    "name": "prefix", "in": "path",
+                    "description": "The prefix", "required": true,
+                    "schema": {"type": "string"},
+                },
+                {
+                    "name": "limit", "in": "query",
+                    "description": "At most this many", "required": false,
+                    "schema": {"type": "integer"},
+                },
+                {
+                    "name": "tag", "in": "query",
+                    "description": "Tags to match", "required": false,
+                    "schema": {"type": "array", "items": {"type": "string"}},
+                },
+            ])
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! QUIC for incoming connections.
+//!
+//! Ingestion over long or lossy paths suffers from TCP's head-of-line
+//! blocking and slow recovery. Units can accept connections over QUIC
+//! instead, using the endpoints of _quinn_ with the certificates and
+//! optional client authentication of the [`tls`] module.
+//!
+//! The [`QuicServerConfig`] is the part of a unit's configuration for a
+//! QUIC listener. The [`QuicEndpoint`] bound with it hands out the
+//! connections, which hand out their streams.
+//!
+//! [`tls`]: crate::common::tls
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use quinn::{crypto::rustls::QuicServerConfig as QuicCrypto, IdleTimeout};
+use rustls::pki_types::CertificateDer;
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use crate::config::ConfigPath;
+
+use super::tls::{self, TlsServerConfig};
+
+pub use quinn::{
+    Connection as QuicConnection, Endpoint as QuicEndpoint, Incoming,
+};
+
+//------------ QuicServerConfig ----------------------------------------------
+
+/// The settings of a QUIC listener.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QuicServerConfig {
+    /// The UDP address and port to listen on.
+    pub listen: SocketAddr,
+
+    /// The server certificate in PEM format, followed by any intermediate
+    /// certificates.
+    pub certificate: ConfigPath,
+
+    /// The private key of the certificate, in PKCS#8 or PKCS#1 PEM format.
+    pub key: ConfigPath,
+
+    /// The CA certificates in PEM format that client certificates must be
+    /// issued by.
+    #[serde(default)]
+    pub client_ca: Option<ConfigPath>,
+
+    /// How long a connection may be silent before it is dropped.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "QuicServerConfig::default_idle_timeout_secs")]
+    pub idle_timeout_secs: Duration,
+}
+
+impl QuicServerConfig {
+    fn default_idle_timeout_secs() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    /// Returns the TLS part of the settings.
+    pub fn tls(&self) -> TlsServerConfig {
+        TlsServerConfig {
+            certificate: self.certificate.clone(),
+            key: self.key.clone(),
+            client_ca: self.client_ca.clone(),
+        }
+    }
+
+    /// Reads the certificate and key, and returns the settings of an
+    /// endpoint accepting the given application protocols.
+    pub fn server_config(
+        &self,
+        alpn: &[&[u8]],
+    ) -> Result<quinn::ServerConfig, String> {
+        let crypto = QuicCrypto::try_from(self.tls().rustls_config(alpn)?)
+            .map_err(|err| format!("cannot use TLS settings: {err}"))?;
+        let idle_timeout = IdleTimeout::try_from(self.idle_timeout_secs)
+            .map_err(|_| "idle_timeout_secs is too large".to_string())?;
+        let mut transport = quinn::TransportConfig::default();
+        transport.max_idle_timeout(Some(idle_timeout));
+        let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        config.transport_config(Arc::new(transport));
+        Ok(config)
+    }
+}
+
+//------------ Helpers -------------------------------------------------------
+
+/// Binds an endpoint to `listen`.
+///
+/// This must be called from within the Tokio runtime.
+pub fn bind(
+    listen: SocketAddr,
+    config: quinn::ServerConfig,
+) -> Result<QuicEndpoint, String> {
+    QuicEndpoint::server(config, listen)
+        .map_err(|err| format!("cannot listen on {listen}: {err}"))
+}
+
+/// Returns the common name of the certificate the client presented.
+pub fn peer_name(conn: &QuicConnection) -> Option<String> {
+    let certificates = conn
+        .peer_identity()?
+        .downcast::<Vec<CertificateDer<'static>>>()
+        .ok()?;
+    tls::common_name(certificates.first()?)
+}
@@ -0,0 +1,349 @@
+//! Authentication of TCP connections with TCP MD5 signatures and TCP-AO.
+//!
+//! Many routers refuse BGP sessions, and some BMP connections, that are not
+//! protected with either the TCP MD5 Signature Option (RFC 2385) or its
+//! successor, the TCP Authentication Option (RFC 5925). Both are enabled on
+//! a socket per peer address or prefix, before the connection is set up:
+//! on the listening socket for incoming connections and on the connecting
+//! socket for outgoing ones. Connections from peers without a key on the
+//! listening socket are accepted unauthenticated.
+//!
+//! Only Linux is supported, TCP-AO only from Linux 6.7 onwards.
+
+use std::io;
+use std::net::IpAddr;
+
+use serde::Deserialize;
+
+//------------ TcpAuth -------------------------------------------------------
+
+/// The authentication of the TCP connection with a peer.
+///
+/// Included in the configuration of a peer as the optional `md5_password`
+/// and `tcp_ao` settings, of which at most one can be used.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq)]
+pub struct TcpAuth {
+    /// The password for TCP MD5 signatures.
+    #[serde(default)]
+    pub md5_password: Option<String>,
+
+    /// The key for the TCP Authentication Option.
+    #[serde(default)]
+    pub tcp_ao: Option<TcpAoKey>,
+}
+
+/// A TCP-AO master key tuple.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq)]
+pub struct TcpAoKey {
+    pub key: String,
+    pub send_id: u8,
+    pub recv_id: u8,
+
+    /// The MAC algorithm, by its Linux crypto API name.
+    #[serde(default = "TcpAoKey::default_algorithm")]
+    pub algorithm: String,
+}
+
+impl TcpAoKey {
+    fn default_algorithm() -> String {
+        "hmac(sha1)".to_string()
+    }
+}
+
+impl TcpAuth {
+    /// The longest key the kernel accepts.
+    const MAX_KEY_LEN: usize = 80;
+
+    pub fn is_enabled(&self) -> bool {
+        self.md5_password.is_some() || self.tcp_ao.is_some()
+    }
+
+    /// Checks that the keys can be used.
+    pub fn check(&self) -> Result<(), String> {
+        let key = match (&self.md5_password, &self.tcp_ao) {
+            (Some(_), Some(_)) => {
+                return Err("only one of md5_password and tcp_ao can be \
+                    configured"
+                    .to_string())
+            }
+            (Some(password), None) => password,
+            (None, Some(ao)) => {
+                if ao.algorithm.is_empty() || ao.algorithm.len() >= 64 {
+                    return Err(format!(
+                        "invalid TCP-AO algorithm '{}'",
+                        ao.algorithm
+                    ));
+                }
+                &ao.key
+            }
+            (None, None) => return Ok(()),
+        };
+        if key.is_empty() || key.len() > Self::MAX_KEY_LEN {
+            return Err(format!(
+                "TCP authentication keys must be 1 to {} bytes long",
+                Self::MAX_KEY_LEN
+            ));
+        }
+        Ok(())
+    }
+
+    /// Enables the authentication on `socket` for peers in `peer/prefix_len`.
+    ///
+    /// Does nothing if no key is configured.
+    #[cfg(target_os = "linux")]
+    pub fn apply(
+        &self,
+        socket: &impl std::os::fd::AsRawFd,
+        peer: IpAddr,
+        prefix_len: u8,
+    ) -> io::Result<()> {
+        let fd = socket.as_raw_fd();
+        if let Some(password) = &self.md5_password {
+            linux::add_md5_key(fd, peer, prefix_len, password.as_bytes())
+        } else if let Some(ao) = &self.tcp_ao {
+            linux::add_ao_key(fd, peer, prefix_len, ao)
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply<T>(
+        &self,
+        _socket: &T,
+        _peer: IpAddr,
+        _prefix_len: u8,
+    ) -> io::Result<()> {
+        if self.is_enabled() {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "TCP authentication is only supported on Linux",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+//------------ Linux socket options ------------------------------------------
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+    use std::mem;
+    use std::net::IpAddr;
+    use std::os::fd::RawFd;
+
+    use super::TcpAoKey;
+
+    const TCP_MD5SIG_EXT: libc::c_int = 32;
+    const TCP_MD5SIG_FLAG_PREFIX: u8 = 1;
+    const TCP_AO_ADD_KEY: libc::c_int = 38;
+
+    /// `struct tcp_md5sig` from `linux/tcp.h`.
+    #[repr(C)]
+    struct TcpMd5Sig {
+        addr: libc::sockaddr_storage,
+        flags: u8,
+        prefix_len: u8,
+        key_len: u16,
+        ifindex: libc::c_int,
+        key: [u8; 80],
+    }
+
+    /// `struct tcp_ao_add` from `linux/tcp.h`.
+    #[repr(C, align(8))]
+    struct TcpAoAdd {
+        addr: libc::sockaddr_storage,
+        alg_name: [u8; 64],
+        ifindex: i32,
+        // The set_current and set_rnext bits, left at zero.
+        flags: u32,
+        reserved: u16,
+        prefix: u8,
+        send_id: u8,
+        recv_id: u8,
+        mac_len: u8,
+        key_flags: u8,
+        key_len: u8,
+        key: [u8; 80],
+    }
+
+    pub fn add_md5_key(
+        fd: RawFd,
+        peer: IpAddr,
+        prefix_len: u8,
+        key: &[u8],
+    ) -> io::Result<()> {
+        // SAFETY: all zeroes is a valid value for this plain C struct.
+        let mut sig: TcpMd5Sig = unsafe { mem::zeroed() };
+        sig.addr = sockaddr(fd, peer)?;
+        sig.flags = TCP_MD5SIG_FLAG_PREFIX;
+        sig.prefix_len = prefix_len;
+        sig.key_len = key.len() as u16;
+        sig.key[..key.len()].copy_from_slice(key);
+        setsockopt(fd, TCP_MD5SIG_EXT, &sig)
+    }
+
+    pub fn add_ao_key(
+        fd: RawFd,
+        peer: IpAddr,
+        prefix_len: u8,
+        ao: &TcpAoKey,
+    ) -> io::Result<()> {
+        // SAFETY: all zeroes is a valid value for this plain C struct.
+        let mut add: TcpAoAdd = unsafe { mem::zeroed() };
+        add.addr = sockaddr(fd, peer)?;
+        add.alg_name[..ao.algorithm.len()]
+            .copy_from_slice(ao.algorithm.as_bytes());
+        add.prefix = prefix_len;
+        add.send_id = ao.send_id;
+        add.recv_id = ao.recv_id;
+        add.key_len = ao.key.len() as u8;
+        add.key[..ao.key.len()].copy_from_slice(ao.key.as_bytes());
+        setsockopt(fd, TCP_AO_ADD_KEY, &add).map_err(|err| {
+            if err.raw_os_error() == Some(libc::ENOPROTOOPT) {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "TCP-AO is not supported by this system",
+                )
+            } else {
+                err
+            }
+        })
+    }
+
+    /// The address of `peer` for the keys of a socket.
+    ///
+    /// The kernel wants IPv4 peers of an IPv6 socket as mapped addresses.
+    fn sockaddr(
+        fd: RawFd,
+        peer: IpAddr,
+    ) -> io::Result<libc::sockaddr_storage> {
+        let peer = match (socket_family(fd)?, peer) {
+            (libc::AF_INET6, IpAddr::V4(addr)) => {
+                IpAddr::V6(addr.to_ipv6_mapped())
+            }
+            (_, peer) => peer,
+        };
+
+        // SAFETY: all zeroes is a valid sockaddr_storage.
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        match peer {
+            IpAddr::V4(addr) => {
+                // SAFETY: sockaddr_storage is large enough and suitably
+                // aligned for any sockaddr.
+                let sin = unsafe {
+                    &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in)
+                };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_addr.s_addr = u32::from(addr).to_be();
+            }
+            IpAddr::V6(addr) => {
+                // SAFETY: as above.
+                let sin6 = unsafe {
+                    &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6)
+                };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_addr.s6_addr = addr.octets();
+            }
+        }
+        Ok(storage)
+    }
+
+    fn socket_family(fd: RawFd) -> io::Result<libc::c_int> {
+        let mut family: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: family and len are valid for writes of len bytes.
+        let res = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_DOMAIN,
+                &mut family as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(family)
+    }
+
+    fn setsockopt<T>(
+        fd: RawFd,
+        option: libc::c_int,
+        value: &T,
+    ) -> io::Result<()> {
+        // SAFETY: value points to a T of the given size.
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::IPPROTO_TCP,
+                option,
+                value as *const T as *const libc::c_void,
+                mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at_most_one_key() {
+        let auth: TcpAuth = toml::from_str(
+            r#"
+            md5_password = "secret"
+            tcp_ao = { key = "secret", send_id = 1, recv_id = 2 }
+            "#,
+        )
+        .unwrap();
+        assert!(auth.check().is_err());
+
+        let auth: TcpAuth = toml::from_str(
+            r#"tcp_ao = { key = "secret", send_id = 1, recv_id = 2 }"#,
+        )
+        .unwrap();
+        assert!(auth.check().is_ok());
+        assert_eq!(auth.tcp_ao.unwrap().algorithm, "hmac(sha1)");
+
+        let auth: TcpAuth = toml::from_str(&format!(
+            r#"md5_password = "{}""#,
+            "x".repeat(81)
+        ))
+        .unwrap();
+        assert!(auth.check().is_err());
+        assert!(TcpAuth::default().check().is_ok());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn md5_signed_connection() {
+        let auth = TcpAuth {
+            md5_password: Some("secret".to_string()),
+            tcp_ao: None,
+        };
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        auth.apply(&listener, localhost, 32).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        auth.apply(&socket, localhost, 32).unwrap();
+        let (connected, accepted) =
+            tokio::join!(socket.connect(addr), listener.accept());
+        connected.unwrap();
+        accepted.unwrap();
+    }
+}
@@ -0,0 +1,511 @@
+//! TLS for incoming and outgoing connections.
+//!
+//! Some routers can send BMP, and other protocols, over TLS rather than
+//! plain TCP, and some servers that targets send to require TLS. This
+//! module wraps _rustls_ for both, limited to TLS 1.3.
+//!
+//! Optionally, clients must present a certificate issued by one of a set of
+//! CAs, making TLS authenticate the routers as well as encrypt their data.
+//!
+//! The [`TlsServerConfig`] is the part of a unit's configuration naming the
+//! files with the certificates and keys. The [`TlsAcceptor`] created from
+//! it reads them and performs the handshake on accepted connections,
+//! returning a [`TlsStream`] to read and write through.
+//!
+//! Targets sending to servers that require TLS use the client side: the
+//! [`TlsConnector`] created from a [`TlsClientConfig`] verifies the server
+//! certificate and can present a client certificate.
+
+use std::{io, sync::Arc};
+
+use rustls::{
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+    server::WebPkiClientVerifier,
+    ClientConfig, RootCertStore, ServerConfig,
+};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::config::ConfigPath;
+
+pub use tokio_rustls::TlsStream;
+
+//------------ TlsServerConfig -----------------------------------------------
+
+/// The TLS settings of a listener.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsServerConfig {
+    /// The server certificate in PEM format, followed by any intermediate
+    /// certificates.
+    pub certificate: ConfigPath,
+
+    /// The private key of the certificate, in PKCS#8 or PKCS#1 PEM format.
+    pub key: ConfigPath,
+
+    /// The CA certificates in PEM format that client certificates must be
+    /// issued by.
+    ///
+    /// If given, clients must present a certificate.
+    #[serde(default)]
+    pub client_ca: Option<ConfigPath>,
+}
+
+impl TlsServerConfig {
+    /// Reads the files and returns the settings for _rustls_, negotiating
+    /// one of the given application protocols.
+    pub fn rustls_config(
+        &self,
+        alpn: &[&[u8]],
+    ) -> Result<ServerConfig, String> {
+        let builder = ServerConfig::builder_with_provider(provider())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|err| err.to_string())?;
+        let builder = match &self.client_ca {
+            Some(path) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(read_roots(path)?),
+                    provider(),
+                )
+                .build()
+                .map_err(|err| format!("{}: {err}", path.display()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder
+            .with_single_cert(
+                read_certificates(&self.certificate)?,
+                read_key(&self.key)?,
+            )
+            .map_err(|err| {
+                format!(
+                    "cannot use the key in {} with the certificate in {}: \
+                     {err}",
+                    self.key.display(),
+                    self.certificate.display()
+                )
+            })?;
+        config.alpn_protocols =
+            alpn.iter().map(|protocol| protocol.to_vec()).collect();
+        Ok(config)
+    }
+}
+
+//------------ TlsClientConfig -----------------------------------------------
+
+/// The TLS settings of a connection to a server.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsClientConfig {
+    /// The CA certificates in PEM format that the server certificate must
+    /// be issued by.
+    ///
+    /// If not given, the CA certificates of the system are used.
+    #[serde(default)]
+    pub ca: Option<ConfigPath>,
+
+    /// The client certificate in PEM format, followed by any intermediate
+    /// certificates, for servers that ask for one.
+    #[serde(default)]
+    pub certificate: Option<ConfigPath>,
+
+    /// The private key of the client certificate.
+    #[serde(default)]
+    pub key: Option<ConfigPath>,
+
+    /// The name the server certificate must be for.
+    ///
+    /// If not given, the host name or address connected to is used.
+    #[serde(default)]
+    pub server_name: Option<String>,
+}
+
+impl TlsClientConfig {
+    /// Reads the files and returns the settings for _rustls_.
+    ///
+    /// The server name is not part of these, it is up to the caller.
+    pub fn rustls_config(&self) -> Result<ClientConfig, String> {
+        let roots = match &self.ca {
+            Some(path) => read_roots(path)?,
+            None => system_roots()?,
+        };
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|err| err.to_string())?
+            .with_root_certificates(roots);
+        match (&self.certificate, &self.key) {
+            (Some(certificate), Some(key)) => builder
+                .with_client_auth_cert(
+                    read_certificates(certificate)?,
+                    read_key(key)?,
+                )
+                .map_err(|err| {
+                    format!(
+                        "cannot use the key in {} with the certificate in \
+                         {}: {err}",
+                        key.display(),
+                        certificate.display()
+                    )
+                }),
+            (None, None) => Ok(builder.with_no_client_auth()),
+            _ => Err("a client certificate needs both a certificate and a \
+                key"
+            .into()),
+        }
+    }
+
+    /// Returns the builder of an HTTP client using these settings.
+    ///
+    /// The HTTP client does its own TLS, which cannot check the server
+    /// certificate for another name than the host of the URL.
+    pub fn http_client_builder(
+        &self,
+    ) -> Result<reqwest::ClientBuilder, String> {
+        if self.server_name.is_some() {
+            return Err("server_name is not supported for HTTPS".into());
+        }
+        let mut builder = reqwest::Client::builder().use_rustls_tls();
+        if let Some(path) = &self.ca {
+            builder = builder.tls_built_in_root_certs(false);
+            for certificate in read_certificates(path)? {
+                let certificate = reqwest::Certificate::from_der(
+                    &certificate,
+                )
+                .map_err(|err| format!("{}: {err}", path.display()))?;
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        match (&self.certificate, &self.key) {
+            (Some(certificate), Some(key)) => {
+                let mut pem = read_file(certificate)?;
+                pem.push(b'\n');
+                pem.extend(read_file(key)?);
+                let identity =
+                    reqwest::Identity::from_pem(&pem).map_err(|err| {
+                        format!(
+                            "cannot use the key in {} with the certificate \
+                             in {}: {err}",
+                            key.display(),
+                            certificate.display()
+                        )
+                    })?;
+                builder = builder.identity(identity);
+            }
+            (None, None) => {}
+            _ => {
+                return Err("a client certificate needs both a certificate \
+                    and a key"
+                    .into())
+            }
+        }
+        Ok(builder)
+    }
+}
+
+//------------ TlsAcceptor ---------------------------------------------------
+
+/// Performs the TLS handshake on accepted connections.
+#[derive(Debug)]
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsAcceptor {
+    /// Reads the files named in the configuration.
+    pub fn new(config: &TlsServerConfig) -> Result<Self, String> {
+        Ok(TlsAcceptor {
+            config: Arc::new(config.rustls_config(&[])?),
+        })
+    }
+
+    /// Negotiates one of the given application protocols with ALPN.
+    ///
+    /// Clients offering application protocols must offer one of these, but
+    /// clients not offering any are accepted as well.
+    pub fn with_alpn(mut self, protocols: &[&[u8]]) -> Self {
+        Arc::make_mut(&mut self.config).alpn_protocols =
+            protocols.iter().map(|p| p.to_vec()).collect();
+        self
+    }
+
+    /// Performs the handshake on a new connection.
+    ///
+    /// The handshake is not limited in time, so callers should make sure it
+    /// does not take forever.
+    pub async fn accept<S>(&self, io: S) -> io::Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        tokio_rustls::TlsAcceptor::from(self.config.clone())
+            .accept(io)
+            .await
+            .map(TlsStream::Server)
+    }
+}
+
+//------------ TlsConnector --------------------------------------------------
+
+/// Performs the TLS handshake on connections to a server.
+#[derive(Debug)]
+pub struct TlsConnector {
+    config: Arc<ClientConfig>,
+    server_name: Option<String>,
+}
+
+impl TlsConnector {
+    /// Reads the files named in the configuration.
+    pub fn new(config: &TlsClientConfig) -> Result<Self, String> {
+        Ok(TlsConnector {
+            config: Arc::new(config.rustls_config()?),
+            server_name: config.server_name.clone(),
+        })
+    }
+
+    /// Performs the handshake on a new connection to `host`.
+    ///
+    /// Unless another server name was configured, the server certificate
+    /// must be for `host`. As with accepting, the handshake is not limited
+    /// in time.
+    pub async fn connect<S>(
+        &self,
+        host: &str,
+        io: S,
+    ) -> io::Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let server_name = self.server_name.as_deref().unwrap_or(host);
+        // URLs put IPv6 addresses in brackets.
+        let server_name = server_name.trim_start_matches('[');
+        let server_name = server_name.trim_end_matches(']');
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidInput, err)
+            })?;
+        tokio_rustls::TlsConnector::from(self.config.clone())
+            .connect(server_name, io)
+            .await
+            .map(TlsStream::Client)
+    }
+}
+
+//------------ Helpers -------------------------------------------------------
+
+/// Returns the common name of the certificate the peer presented.
+pub fn peer_name<S>(stream: &TlsStream<S>) -> Option<String> {
+    let (_, state) = stream.get_ref();
+    common_name(state.peer_certificates()?.first()?)
+}
+
+/// Returns the common name in the subject of a certificate.
+pub fn common_name(certificate: &CertificateDer) -> Option<String> {
+    let (_, certificate) =
+        x509_parser::parse_x509_certificate(certificate).ok()?;
+    let name = certificate.subject().iter_common_name().next()?;
+    name.as_str().ok().map(Into::into)
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn read_file(path: &ConfigPath) -> Result<Vec<u8>, String> {
+    std::fs::read(path)
+        .map_err(|err| format!("cannot read {}: {err}", path.display()))
+}
+
+/// Reads a certificate chain.
+fn read_certificates(
+    path: &ConfigPath,
+) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certificates =
+        rustls_pemfile::certs(&mut read_file(path)?.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+    if certificates.is_empty() {
+        return Err(format!("{}: no certificates found", path.display()));
+    }
+    Ok(certificates)
+}
+
+fn read_key(path: &ConfigPath) -> Result<PrivateKeyDer<'static>, String> {
+    rustls_pemfile::private_key(&mut read_file(path)?.as_slice())
+        .map_err(|err| format!("{}: {err}", path.display()))?
+        .ok_or_else(|| format!("{}: no private key found", path.display()))
+}
+
+fn read_roots(path: &ConfigPath) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    for certificate in read_certificates(path)? {
+        roots
+            .add(certificate)
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+    }
+    Ok(roots)
+}
+
+fn system_roots() -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(
+        rustls_native_certs::load_native_certs().certs,
+    );
+    if added == 0 {
+        return Err("no usable CA certificates found on the system".into());
+    }
+    Ok(roots)
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// Writes test files to a new directory, returning their paths.
+    fn write_files(files: &[(&str, String)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, content) in files {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        dir
+    }
+
+    /// Returns the params of a certificate for `names` and `common_name`.
+    fn params(names: &[&str], common_name: &str) -> CertificateParams {
+        let names = names.iter().map(|name| name.to_string());
+        let mut params =
+            CertificateParams::new(names.collect::<Vec<_>>()).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        params
+    }
+
+    /// Runs a handshake, then echoes a message through the connection.
+    async fn exchange(
+        acceptor: TlsAcceptor,
+        connector: TlsConnector,
+        server_name: &str,
+    ) -> std::io::Result<Option<String>> {
+        let (client, server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server).await?;
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await?;
+            stream.write_all(&buf).await?;
+            stream.flush().await?;
+            Ok::<_, std::io::Error>(peer_name(&stream))
+        });
+        let res = async {
+            let mut stream = connector.connect(server_name, client).await?;
+            assert_eq!(peer_name(&stream).as_deref(), Some("broker"));
+            stream.write_all(b"hello").await?;
+            stream.flush().await?;
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"hello");
+            Ok(())
+        }
+        .await;
+        let server = server.await.unwrap();
+        res.and(server)
+    }
+
+    #[tokio::test]
+    async fn client_connects_to_server() {
+        let broker_key = KeyPair::generate().unwrap();
+        let broker = params(&["broker.example", "127.0.0.1"], "broker")
+            .self_signed(&broker_key)
+            .unwrap();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = params(&[], "Test CA");
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let client = params(&[], "router1")
+            .signed_by(&client_key, &ca, &ca_key)
+            .unwrap();
+
+        let dir = write_files(&[
+            ("broker.pem", broker.pem()),
+            ("broker.key", broker_key.serialize_pem()),
+            ("ca.pem", ca.pem()),
+            ("client.pem", client.pem()),
+            ("client.key", client_key.serialize_pem()),
+        ]);
+        let path = |name: &str| ConfigPath::from(dir.join(name));
+        let server_config = TlsServerConfig {
+            certificate: path("broker.pem"),
+            key: path("broker.key"),
+            client_ca: None,
+        };
+        let client_config = TlsClientConfig {
+            ca: Some(path("broker.pem")),
+            ..Default::default()
+        };
+
+        let acceptor = || TlsAcceptor::new(&server_config).unwrap();
+        let connector =
+            |config: &TlsClientConfig| TlsConnector::new(config).unwrap();
+        assert_eq!(
+            exchange(acceptor(), connector(&client_config), "broker.example")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            exchange(acceptor(), connector(&client_config), "127.0.0.1")
+                .await
+                .unwrap(),
+            None
+        );
+
+        // The certificate must be for the name connected to.
+        assert!(exchange(acceptor(), connector(&client_config), "other")
+            .await
+            .is_err());
+
+        // And issued by the CA.
+        let untrusted = TlsClientConfig {
+            ca: Some(path("ca.pem")),
+            ..Default::default()
+        };
+        assert!(exchange(
+            acceptor(),
+            connector(&untrusted),
+            "broker.example"
+        )
+        .await
+        .is_err());
+
+        // The server may require a client certificate.
+        let server_config = TlsServerConfig {
+            client_ca: Some(path("ca.pem")),
+            ..server_config
+        };
+        let acceptor = || TlsAcceptor::new(&server_config).unwrap();
+        assert!(exchange(acceptor(), connector(&client_config), "127.0.0.1")
+            .await
+            .is_err());
+        let client_config = TlsClientConfig {
+            certificate: Some(path("client.pem")),
+            key: Some(path("client.key")),
+            ..client_config
+        };
+        assert_eq!(
+            exchange(acceptor(), connector(&client_config), "127.0.0.1")
+                .await
+                .unwrap(),
+            Some("router1".into())
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
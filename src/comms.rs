@@ -89,7 +89,7 @@ use std::{
     fmt::{self, Debug, Display},
 };
 use std::{future::pending, sync::atomic::AtomicUsize};
-use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::sync::{mpsc, oneshot, watch, RwLock};
 use tokio::time::{timeout_at, Instant};
 use uuid::Uuid;
 
@@ -176,6 +176,9 @@ pub struct Gate {
     /// The gate metrics.
     metrics: Arc<GateMetrics>,
 
+    /// Whether updates are held until the unit is resumed.
+    pause: Arc<Pause>,
+
     /// Gate type dependent state.
     state: GateState,
 
@@ -231,6 +234,7 @@ impl Gate {
             queue_size,
             suspended: Default::default(),
             metrics: Default::default(),
+            pause: Default::default(),
             state: GateState::Normal(NormalGateState {
                 command_sender: tx.clone(),
                 clone_senders: Default::default(),
@@ -359,6 +363,13 @@ impl Gate {
         self.tracer = Some(tracer);
     }
 
+    /// Sets the pause shared by the gates of the unit.
+    ///
+    /// Must be called before the gate is cloned.
+    pub(crate) fn set_pause(&mut self, pause: Arc<Pause>) {
+        self.pause = pause;
+    }
+
     /// Runs the gate’s internal machine.
     ///
     /// This method returns a future that runs the gate’s internal machine.
@@ -440,12 +451,21 @@ impl Gate {
                     suspended,
                     response,
                     direct_update,
+                    routes,
+                    metrics,
                 } => {
                     assert!(
                         !self.is_clone(),
                         "Cloned gates do not support the Subscribe command"
                     );
-                    self.subscribe(suspended, response, direct_update).await
+                    self.subscribe(
+                        suspended,
+                        response,
+                        direct_update,
+                        routes,
+                        metrics,
+                    )
+                    .await
                 }
 
                 GateCommand::Unsubscribe { slot } => {
@@ -659,6 +679,23 @@ impl Gate {
     /// Returns true if the update was sent to a downstream unit, false
     /// otherwise.
     pub async fn update_data(&self, update: Update) {
+        self.update_data_routed(update, &[]).await
+    }
+
+    /// Updates the data set of the unit with data for the given routes.
+    ///
+    /// Links that accept only certain routes receive the update if they
+    /// accept one of `routes`, all other active links receive it anyway.
+    /// Status changes are sent to all active links regardless of routes.
+    ///
+    /// While the unit is paused, waits until it is resumed.
+    pub async fn update_data_routed(
+        &self,
+        update: Update,
+        routes: &[Arc<str>],
+    ) {
+        self.pause.resumed().await;
+
         // let mut sender_lost = false;
         let mut sent_at_least_once = false;
 
@@ -676,6 +713,9 @@ impl Gate {
             );
         }
         for (uuid, item) in self.updates.guard().iter() {
+            if !item.accepts(&update, routes) {
+                continue;
+            }
             match (&item.queue, &item.direct) {
                 (Some(sender), None) => {
                     if let Some(tracer) = &self.tracer {
@@ -688,6 +728,7 @@ impl Gate {
                         }
                     }
                     if sender.send(Ok(update.clone())).await.is_ok() {
+                        item.metrics.num_updates.fetch_add(1, SeqCst);
                         sent_at_least_once = true;
                         continue;
                     }
@@ -721,6 +762,7 @@ impl Gate {
                     }
                     if let Some(direct) = direct.upgrade() {
                         direct.direct_update(update.clone()).await;
+                        item.metrics.num_updates.fetch_add(1, SeqCst);
                         sent_at_least_once = true;
                     }
                     continue;
@@ -787,19 +829,27 @@ impl Gate {
         suspended: bool,
         response: oneshot::Sender<SubscribeResponse>,
         direct_update: Option<Weak<dyn AnyDirectUpdate>>,
+        routes: Vec<Arc<str>>,
+        metrics: Arc<LinkMetrics>,
     ) {
         let (update_sender, receiver) =
             if let Some(direct_update) = direct_update {
+                *metrics.queue.lock().unwrap() = None;
                 let update_sender = UpdateSender {
                     queue: None,
                     direct: Some(direct_update),
+                    routes,
+                    metrics,
                 };
                 (update_sender, None)
             } else {
                 let (tx, receiver) = mpsc::channel(self.queue_size);
+                *metrics.queue.lock().unwrap() = Some(tx.downgrade());
                 let update_sender = UpdateSender {
                     queue: Some(tx),
                     direct: None,
+                    routes,
+                    metrics,
                 };
                 (update_sender, Some(receiver))
             };
@@ -890,6 +940,7 @@ impl Clone for Gate {
             queue_size: self.queue_size,
             suspended: self.suspended.clone(),
             metrics: self.metrics.clone(),
+            pause: self.pause.clone(),
             state: GateState::Clone(CloneGateState {
                 clone_id,
                 parent_command_sender: parent_command_sender.clone(),
@@ -974,6 +1025,19 @@ impl GateAgent {
             .await
             .map_err(|err| format!("{}", err))
     }
+
+    /// Stops sending updates to the link connected to the given slot.
+    ///
+    /// Updates already queued for the link can still be received.
+    pub async fn suspend_link(&self, slot: Uuid) -> Result<(), String> {
+        self.commands
+            .send(GateCommand::Suspension {
+                slot,
+                suspend: true,
+            })
+            .await
+            .map_err(|err| format!("{}", err))
+    }
 }
 
 //------------ GraphMetrics --------------------------------------------------
@@ -1125,6 +1189,85 @@ impl metrics::Source for GateMetrics {
     }
 }
 
+//------------ LinkMetrics ---------------------------------------------------
+
+/// Metrics about the updates sent over a link.
+///
+/// A link hands its metrics to the gate when connecting to it, so they can
+/// be reported on whether or not the link has connected yet.
+#[derive(Debug, Default)]
+pub struct LinkMetrics {
+    /// The number of updates sent over the link.
+    pub num_updates: AtomicUsize,
+
+    /// The queue of the updates not yet received, if a queued link.
+    queue: Mutex<Option<mpsc::WeakSender<Result<Update, UnitStatus>>>>,
+}
+
+impl LinkMetrics {
+    /// Returns the number of updates in the queue and its capacity.
+    ///
+    /// Returns `None` if the link is direct or not connected.
+    pub fn queue(&self) -> Option<(usize, usize)> {
+        let queue = self.queue.lock().unwrap().as_ref()?.upgrade()?;
+        let capacity = queue.max_capacity();
+        Some((capacity - queue.capacity(), capacity))
+    }
+}
+
+//------------ Pause ---------------------------------------------------------
+
+/// Whether a unit is paused.
+///
+/// The gates of a paused unit hold the updates sent to them until the unit
+/// is resumed. A unit receiving its data from the network thereby stops
+/// reading from it, leaving the other end waiting rather than closing its
+/// session, for as long as that is willing to wait.
+///
+/// A unit keeps its pause when it is reconfigured in place and takes over
+/// a new gate, as the manager gives all gates of a unit the same one. A
+/// unit replaced by a new one is resumed, so that the old one can stop.
+#[derive(Debug)]
+pub struct Pause {
+    paused: watch::Sender<bool>,
+}
+
+impl Default for Pause {
+    fn default() -> Self {
+        Self {
+            paused: watch::Sender::new(false),
+        }
+    }
+}
+
+impl Pause {
+    /// Pauses the unit.
+    ///
+    /// Returns whether it wasn't paused already.
+    pub fn pause(&self) -> bool {
+        !self.paused.send_replace(true)
+    }
+
+    /// Resumes the unit.
+    ///
+    /// Returns whether it was paused.
+    pub fn resume(&self) -> bool {
+        self.paused.send_replace(false)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Waits until the unit is not paused.
+    async fn resumed(&self) {
+        if self.is_paused() {
+            let mut paused = self.paused.subscribe();
+            let _ = paused.wait_for(|paused| !paused).await;
+        }
+    }
+}
+
 //------------ DirectLink ----------------------------------------------------
 
 /// A direct link to a unit.
@@ -1153,6 +1296,10 @@ impl DirectLink {
         self.0.connection.as_ref().map(|connection| connection.slot)
     }
 
+    pub fn metrics(&self) -> Arc<LinkMetrics> {
+        self.0.metrics()
+    }
+
     /// Suspends the link.
     ///
     /// A suspended link will not receive any payload updates from the
@@ -1255,6 +1402,14 @@ pub struct Link {
     suspended: bool,
 
     direct_update_target: Option<Weak<dyn AnyDirectUpdate>>,
+
+    /// The routes of the updates the link accepts.
+    ///
+    /// If this is empty, all updates are accepted.
+    routes: Vec<Arc<str>>,
+
+    /// The metrics of the link, kept by the gate.
+    metrics: Arc<LinkMetrics>,
 }
 
 impl PartialEq for Link {
@@ -1277,6 +1432,7 @@ impl std::fmt::Debug for Link {
                 "direct_update_target",
                 &self.direct_update_target.is_some(),
             )
+            .field("routes", &self.routes)
             .finish()
     }
 }
@@ -1291,6 +1447,8 @@ impl Clone for Link {
             unit_status: self.unit_status,
             suspended: self.suspended,
             direct_update_target: self.direct_update_target.clone(),
+            routes: self.routes.clone(),
+            metrics: self.metrics.clone(),
         }
     }
 }
@@ -1306,9 +1464,19 @@ impl Link {
             unit_status: UnitStatus::Healthy,
             suspended: false,
             direct_update_target: None,
+            routes: Vec::new(),
+            metrics: Default::default(),
         }
     }
 
+    /// Restricts the link to updates for any of the given routes.
+    ///
+    /// See [`Gate::update_data_routed`] for how updates are routed.
+    pub fn with_routes(mut self, routes: Vec<Arc<str>>) -> Self {
+        self.routes = routes;
+        self
+    }
+
     pub fn id(&self) -> Uuid {
         self.id
     }
@@ -1321,6 +1489,11 @@ impl Link {
         self.connection.as_ref().map(|connection| connection.slot)
     }
 
+    /// Returns the metrics of the updates sent over the link.
+    pub fn metrics(&self) -> Arc<LinkMetrics> {
+        self.metrics.clone()
+    }
+
     pub fn close(&mut self) {
         if let Some(conn) = self.connection.as_mut() {
             if let Some(updates) = &mut conn.updates {
@@ -1453,6 +1626,8 @@ impl Link {
                 suspended,
                 response: tx,
                 direct_update: self.direct_update_target.clone(),
+                routes: self.routes.clone(),
+                metrics: self.metrics.clone(),
             })
             .await
             .is_err()
@@ -1694,6 +1869,12 @@ enum GateCommand {
         response: oneshot::Sender<SubscribeResponse>,
 
         direct_update: Option<Weak<dyn AnyDirectUpdate>>,
+
+        /// The routes the link accepts.
+        routes: Vec<Arc<str>>,
+
+        /// The metrics of the link to update.
+        metrics: Arc<LinkMetrics>,
     },
 
     Unsubscribe {
@@ -1801,6 +1982,21 @@ struct UpdateSender {
     queue: Option<mpsc::Sender<Result<Update, UnitStatus>>>,
 
     direct: Option<Weak<dyn AnyDirectUpdate>>,
+
+    /// The routes of the updates the link accepts, all if empty.
+    routes: Vec<Arc<str>>,
+
+    /// The metrics of the link.
+    metrics: Arc<LinkMetrics>,
+}
+
+impl UpdateSender {
+    /// Returns whether the link accepts `update` for `routes`.
+    fn accepts(&self, update: &Update, routes: &[Arc<str>]) -> bool {
+        self.routes.is_empty()
+            || matches!(update, Update::UpstreamStatusChange(_))
+            || routes.iter().any(|route| self.routes.contains(route))
+    }
 }
 
 //------------ UpdateReceiver ------------------------------------------------
@@ -1834,7 +2030,7 @@ mod tests {
     use tokio::sync::Notify;
 
     use crate::{
-        payload::Payload,
+        payload::{Payload, UpstreamStatus},
         tests::util::internal::{
             enable_logging, get_testable_metrics_snapshot,
         },
@@ -2136,4 +2332,124 @@ mod tests {
         assert!(matches!(&gate.state, GateState::Normal(NormalGateState { 
                 clone_senders, .. }) if clone_senders.is_empty()));
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn updates_are_routed() {
+        let (gate, mut agent) = Gate::new(10);
+        let mut all = agent.create_link();
+        let mut alerts =
+            agent.create_link().with_routes(vec!["alerts".into()]);
+
+        let gate = Arc::new(gate);
+        let gate_clone = gate.clone();
+        tokio::spawn(async move {
+            loop {
+                gate.process().await.unwrap();
+            }
+        });
+        all.connect(false).await.unwrap();
+        alerts.connect(false).await.unwrap();
+
+        gate_clone.update_data(Update::Withdraw(1, None)).await;
+        gate_clone
+            .update_data_routed(
+                Update::Withdraw(2, None),
+                &["archive".into()],
+            )
+            .await;
+        gate_clone
+            .update_data_routed(
+                Update::Withdraw(3, None),
+                &["archive".into(), "alerts".into()],
+            )
+            .await;
+        gate_clone
+            .update_data_routed(
+                Update::UpstreamStatusChange(UpstreamStatus::EndOfStream {
+                    ingress_id: 4,
+                }),
+                &[],
+            )
+            .await;
+
+        // A link without routes receives everything.
+        for id in [1, 2, 3] {
+            assert!(matches!(
+                all.query().await,
+                Ok(Update::Withdraw(received, None)) if received == id
+            ));
+        }
+        assert!(matches!(
+            all.query().await,
+            Ok(Update::UpstreamStatusChange(..))
+        ));
+
+        // A link with routes only receives updates for one of them and status
+        // changes.
+        assert!(matches!(
+            alerts.query().await,
+            Ok(Update::Withdraw(3, None))
+        ));
+        assert!(matches!(
+            alerts.query().await,
+            Ok(Update::UpstreamStatusChange(..))
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn link_metrics_are_kept() {
+        let (gate, mut agent) = Gate::new(10);
+        let mut link = agent.create_link();
+        let metrics = link.metrics();
+        assert_eq!(metrics.queue(), None);
+
+        let gate = Arc::new(gate);
+        let gate_clone = gate.clone();
+        tokio::spawn(async move {
+            loop {
+                gate.process().await.unwrap();
+            }
+        });
+        link.connect(false).await.unwrap();
+        assert_eq!(metrics.queue(), Some((0, 10)));
+
+        for id in [1, 2, 3] {
+            gate_clone.update_data(Update::Withdraw(id, None)).await;
+        }
+        assert_eq!(metrics.num_updates.load(SeqCst), 3);
+        assert_eq!(metrics.queue(), Some((3, 10)));
+
+        link.query().await.unwrap();
+        assert_eq!(metrics.queue(), Some((2, 10)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn paused_gates_hold_updates() {
+        let (mut gate, mut agent) = Gate::new(10);
+        let pause = Arc::new(Pause::default());
+        gate.set_pause(pause.clone());
+        let mut link = agent.create_link();
+
+        let gate = Arc::new(gate);
+        let gate_clone = gate.clone();
+        tokio::spawn(async move {
+            loop {
+                gate.process().await.unwrap();
+            }
+        });
+        link.connect(false).await.unwrap();
+
+        assert!(pause.pause());
+        assert!(!pause.pause());
+        let update = tokio::spawn(async move {
+            gate_clone.update_data(Update::Withdraw(1, None)).await;
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!update.is_finished());
+        assert_eq!(link.metrics().num_updates.load(SeqCst), 0);
+
+        assert!(pause.resume());
+        update.await.unwrap();
+        assert!(matches!(link.query().await, Ok(Update::Withdraw(1, None))));
+    }
 }
@@ -5,14 +5,20 @@
 //! this module. This struct also provides the facilities to load the config
 //! file referred to in command line options.
 
+use crate::admin::AdminConfig;
 use crate::http;
 use crate::log::{LogConfig, Terminate};
 use crate::manager::{Manager, TargetSet, UnitSet};
+use crate::roto_runtime::external_data::ExternalDataSource;
+use crate::roto_runtime::bogons::BogonConfig;
+use crate::roto_runtime::filter_metrics::SlowFilterConfig;
+use crate::roto_runtime::reload::ReloadConfig;
+use crate::roto_runtime::state::StateConfig;
 use clap::{Arg, ArgMatches, Command};
 use log::{error, trace};
 use serde::Deserialize;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::{borrow, error, fmt, fs, io, ops};
@@ -25,6 +31,8 @@ const CFG_TARGETS: &str = "targets";
 
 const ARG_CONFIG: &str = "config";
 
+const CMD_CHECK: &str = "check";
+
 //------------ Config --------------------------------------------------------
 
 /// The complete Rotonda configuration.
@@ -49,6 +57,30 @@ pub struct Config {
     /// The set of configured targets.
     pub targets: TargetSet,
 
+    /// The external data sources made available to the Roto script.
+    #[serde(default)]
+    pub external_data: Vec<ExternalDataSource>,
+
+    /// Where to keep the state of the Roto filters.
+    #[serde(default)]
+    pub roto_state: StateConfig,
+
+    /// Whether to reload the Roto script when it changes.
+    #[serde(default)]
+    pub roto_reload: ReloadConfig,
+
+    /// The bogon prefixes and ASNs known to the Roto script.
+    #[serde(default)]
+    pub roto_bogons: BogonConfig,
+
+    /// How slow invocations of the Roto filters are handled.
+    #[serde(default)]
+    pub roto_slow_filters: SlowFilterConfig,
+
+    /// The administrative HTTP API, if enabled.
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+
     /// The logging configuration.
     #[serde(flatten)]
     pub log: LogConfig,
@@ -82,15 +114,28 @@ impl Config {
 
     /// Configures a clap app with the arguments to load the configuration.
     pub fn config_args(app: Command) -> Command {
-        let app = app.arg(
+        LogConfig::config_args(Self::config_file_arg(app))
+    }
+
+    /// Configures a clap app with the argument for the config file only.
+    fn config_file_arg(app: Command) -> Command {
+        app.arg(
             Arg::new(ARG_CONFIG)
                 .short('c')
                 .long(ARG_CONFIG)
                 .required(true)
                 .value_name("PATH")
                 .help("Config file to use"),
-        );
-        LogConfig::config_args(app)
+        )
+    }
+
+    /// Configures a clap app with the `check` subcommand.
+    pub fn check_command(app: Command) -> Command {
+        app.subcommand_negates_reqs(true).subcommand(Self::config_file_arg(
+            Command::new(CMD_CHECK).about(
+                "Checks the config file and the Roto script, then exits",
+            ),
+        ))
     }
 
     /// Loads the configuration based on command line options provided.
@@ -108,25 +153,85 @@ impl Config {
         cur_dir: &Path,
         manager: &mut Manager,
     ) -> Result<(Source, Self), Terminate> {
-        let config_file = {
-            // With ARG_CONFIG required, we can unwrap here.
-            let conf_path_arg = args.get_one::<String>(ARG_CONFIG).unwrap();
-            let config_path = cur_dir.join(conf_path_arg);
-            ConfigFile::load(&config_path).map_err(|err| {
-                error!(
-                    "Failed to read config file '{}': {}",
-                    config_path.display(),
-                    err
-                );
-                Terminate::error()
-            })?
-        };
-
+        let config_file = Self::load_config_file(args, cur_dir)?;
         let mut config = manager.load(&config_file)?;
         config.log.update_with_arg_matches(args, cur_dir)?;
         config.finalise(config_file, manager)
     }
 
+    /// Runs the `check` subcommand if it was given.
+    ///
+    /// Returns `None` if it was not. Otherwise, checks the config file as
+    /// if Rotonda were started with it: the file is parsed, the Roto script
+    /// is compiled and all links between units and targets are resolved.
+    /// Additionally, units that no other unit or target takes updates from
+    /// are an error, as they would not be started. Any problems are logged
+    /// and result in an error.
+    pub fn check_arg_matches(
+        args: &ArgMatches,
+        cur_dir: &Path,
+        manager: &mut Manager,
+    ) -> Option<Result<(), Terminate>> {
+        let args = args.subcommand_matches(CMD_CHECK)?;
+        Some(Self::check(args, cur_dir, manager))
+    }
+
+    fn check(
+        args: &ArgMatches,
+        cur_dir: &Path,
+        manager: &mut Manager,
+    ) -> Result<(), Terminate> {
+        let config_file = Self::load_config_file(args, cur_dir)?;
+        let config = manager.load(&config_file)?;
+        manager.prepare(&config, &config_file)?;
+
+        let source = config_file
+            .source
+            .path()
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        let mut unused = manager.unused_units(&config);
+        unused.sort();
+        for name in &unused {
+            error!(
+                "{source}: unit '{name}' is not used by any other unit or \
+                target and would not be started"
+            );
+        }
+        if config.targets.targets().is_empty() {
+            error!("{source}: there are no targets");
+        }
+        if !unused.is_empty() || config.targets.targets().is_empty() {
+            return Err(Terminate::error());
+        }
+
+        println!(
+            "{source}: {} units and {} targets, configuration OK",
+            config.units.units().len(),
+            config.targets.targets().len(),
+        );
+        Ok(())
+    }
+
+    /// Loads the config file given on the command line.
+    fn load_config_file(
+        args: &ArgMatches,
+        cur_dir: &Path,
+    ) -> Result<ConfigFile, Terminate> {
+        // With ARG_CONFIG required, we can unwrap here.
+        let conf_path_arg = args.get_one::<String>(ARG_CONFIG).unwrap();
+        let config_path = cur_dir.join(conf_path_arg);
+        ConfigFile::load(&config_path).map_err(|err| {
+            error!(
+                "Failed to read config file '{}': {}",
+                config_path.display(),
+                err
+            );
+            Terminate::error()
+        })
+    }
+
     pub fn from_config_file(
         config_file: ConfigFile,
         manager: &mut Manager,
@@ -612,6 +717,197 @@ impl ConfigFile {
     }
 }
 
+//------------ ComponentSections ---------------------------------------------
+
+/// The sections of the units and targets in a config file.
+///
+/// These are kept for the running configuration, so that when the config
+/// file is reloaded only the units and targets affected by the changes
+/// need to be touched.
+#[derive(Clone, Debug, Default)]
+pub struct ComponentSections {
+    units: HashMap<String, Value>,
+    targets: HashMap<String, Value>,
+}
+
+impl ComponentSections {
+    pub fn new(file: &ConfigFile) -> Self {
+        let toml: toml::Table =
+            toml::from_str(&file.to_string()).unwrap_or_default();
+        let sections = |name: &str| match toml.get(name) {
+            Some(Value::Table(table)) => table.clone().into_iter().collect(),
+            _ => HashMap::new(),
+        };
+        ComponentSections {
+            units: sections(CFG_UNITS),
+            targets: sections(CFG_TARGETS),
+        }
+    }
+
+    /// Returns the type of the unit or target with the given name.
+    pub fn type_name(&self, name: &str) -> Option<&str> {
+        self.units
+            .get(name)
+            .or_else(|| self.targets.get(name))
+            .and_then(|section| section.get("type"))
+            .and_then(Value::as_str)
+    }
+
+    /// Returns the hash of the section of the unit or target `name`.
+    pub fn config_hash(&self, name: &str) -> Option<String> {
+        self.units
+            .get(name)
+            .or_else(|| self.targets.get(name))
+            .map(crate::health::config_hash)
+    }
+
+    /// Compares the sections with those of the `running` configuration.
+    ///
+    /// Besides the units and targets whose sections changed, those linked
+    /// to or from the units that need updating need updating themselves:
+    /// an updated unit takes over the gate created for the new
+    /// configuration, which ends the links of the running configuration to
+    /// it, and an updated unit or target links to the new gates of its
+    /// upstream units.
+    pub fn diff(&self, running: &Self) -> ConfigDiff {
+        let mut units: HashSet<&str> = self
+            .units
+            .iter()
+            .filter(|(name, section)| {
+                running.units.get(*name) != Some(section)
+            })
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let modified_units =
+            units.iter().map(|name| name.to_string()).collect();
+        let changed_settings = units
+            .iter()
+            .map(|name| {
+                let settings = changed_settings(
+                    &self.units[*name],
+                    running.units.get(*name),
+                );
+                (name.to_string(), settings)
+            })
+            .collect();
+        let mut targets: HashSet<&str> = self
+            .targets
+            .iter()
+            .filter(|(name, section)| {
+                running.targets.get(*name) != Some(section)
+            })
+            .map(|(name, _)| name.as_str())
+            .collect();
+
+        // The links as (is the downstream a unit, downstream, upstream).
+        let mut links = Vec::new();
+        for (name, section) in &self.units {
+            for upstream in self.upstreams(section) {
+                if upstream != name {
+                    links.push((true, name.as_str(), upstream));
+                }
+            }
+        }
+        for (name, section) in &self.targets {
+            for upstream in self.upstreams(section) {
+                links.push((false, name.as_str(), upstream));
+            }
+        }
+
+        loop {
+            let mut done = true;
+            for &(is_unit, downstream, upstream) in &links {
+                let affected = if is_unit {
+                    units.contains(downstream)
+                } else {
+                    targets.contains(downstream)
+                };
+                if affected {
+                    done &= !units.insert(upstream);
+                } else if units.contains(upstream) {
+                    if is_unit {
+                        units.insert(downstream);
+                    } else {
+                        targets.insert(downstream);
+                    }
+                    done = false;
+                }
+            }
+            if done {
+                break;
+            }
+        }
+
+        ConfigDiff {
+            modified_units,
+            changed_settings,
+            units: units.into_iter().map(Into::into).collect(),
+            targets: targets.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Returns the names of the units a section may link to.
+    ///
+    /// Rather than knowing which settings of which unit or target are
+    /// links, any string naming a unit, possibly followed by the queue
+    /// size and routes of a link, is taken to be one. This errs on the side
+    /// of updating too much.
+    fn upstreams<'a>(&'a self, section: &'a Value) -> HashSet<&'a str> {
+        let mut res = HashSet::new();
+        let mut values = vec![section];
+        while let Some(value) = values.pop() {
+            match value {
+                Value::String(link_id) => {
+                    let name = link_id.split([':', '#']).next().unwrap();
+                    if let Some((name, _)) = self.units.get_key_value(name) {
+                        res.insert(name.as_str());
+                    }
+                }
+                Value::Array(array) => values.extend(array),
+                Value::Table(table) => values.extend(table.values()),
+                _ => {}
+            }
+        }
+        res
+    }
+}
+
+/// Returns the names of the settings that differ between two sections.
+///
+/// If there is no `running` section, all settings count as changed.
+fn changed_settings(
+    section: &Value,
+    running: Option<&Value>,
+) -> HashSet<String> {
+    let empty = toml::Table::new();
+    let section = section.as_table().unwrap_or(&empty);
+    let running = running.and_then(Value::as_table).unwrap_or(&empty);
+    section
+        .keys()
+        .chain(running.keys())
+        .filter(|key| section.get(*key) != running.get(*key))
+        .cloned()
+        .collect()
+}
+
+//------------ ConfigDiff ----------------------------------------------------
+
+/// The units and targets affected by a change of the configuration.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigDiff {
+    /// The units whose own section is new or changed.
+    pub modified_units: HashSet<String>,
+
+    /// The names of the changed settings of each modified unit.
+    pub changed_settings: HashMap<String, HashSet<String>>,
+
+    /// The units that need updating.
+    pub units: HashSet<String>,
+
+    /// The targets that need updating.
+    pub targets: HashSet<String>,
+}
+
 //------------ ConfigError --------------------------------------------------
 
 /// An error occurred during parsing of a configuration file.
@@ -0,0 +1,361 @@
+//! The health of the units and targets.
+//!
+//! Every component has a [`ComponentHealth`], available via
+//! [`Component::health`](crate::manager::Component::health). The manager
+//! records in it when the component was started, became ready and stopped,
+//! the component itself what it is doing beyond that, such as connecting to
+//! a server or waiting to retry after an error. The manager serves the
+//! health of each component at `/status/units/<name>` and whether all of
+//! them are ready at `/status/ready`.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::atomic::Ordering::SeqCst;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::comms::GateMetrics;
+
+//------------ ComponentState ------------------------------------------------
+
+/// What a unit or target is doing.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentState {
+    /// Started but not yet running.
+    #[default]
+    Starting,
+
+    /// Connecting to a server or binding to its listen address.
+    Connecting,
+
+    /// Doing its work.
+    Running,
+
+    /// Doing its work, but not keeping up or only partially.
+    Degraded,
+
+    /// Waiting to retry after an error.
+    Retrying,
+
+    /// Paused via the administrative API.
+    Paused,
+
+    /// Processing the updates queued for it before stopping.
+    Draining,
+
+    /// No longer running.
+    Stopped,
+}
+
+impl ComponentState {
+    /// Returns whether a component in this state is doing its work.
+    pub fn is_ready(self) -> bool {
+        matches!(self, ComponentState::Running | ComponentState::Degraded)
+    }
+}
+
+//------------ ComponentHealth -----------------------------------------------
+
+/// The health of a unit or target.
+#[derive(Debug, Default)]
+pub struct ComponentHealth {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// The number of times the component was started.
+    ///
+    /// A component being replaced by a new one stops after the new one
+    /// has been started, so only the latest may mark it as stopped.
+    generation: u64,
+
+    type_name: Option<&'static str>,
+    state: ComponentState,
+
+    /// Whether the unit was paused.
+    paused: bool,
+
+    /// When the component entered its current state.
+    since: Option<DateTime<Utc>>,
+
+    started: Option<DateTime<Utc>>,
+    last_error: Option<LastError>,
+    config_hash: Option<String>,
+
+    /// The metrics of the gate of a unit, counting the updates it sent.
+    gate_metrics: Option<Arc<GateMetrics>>,
+}
+
+impl Inner {
+    fn set_state(&mut self, state: ComponentState) {
+        if self.state != state {
+            self.state = state;
+            self.since = Some(Utc::now());
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct LastError {
+    at: DateTime<Utc>,
+    message: String,
+}
+
+impl ComponentHealth {
+    /// Records that the component is doing something else now.
+    pub fn set_state(&self, state: ComponentState) {
+        self.inner.lock().unwrap().set_state(state);
+    }
+
+    /// Records an error.
+    ///
+    /// The state is left alone, as only the component knows whether it
+    /// is going to retry or can carry on.
+    pub fn report_error(&self, err: impl Display) {
+        self.inner.lock().unwrap().last_error = Some(LastError {
+            at: Utc::now(),
+            message: err.to_string(),
+        });
+    }
+
+    /// Records that a new instance of the component was started.
+    ///
+    /// Returns the generation to pass to [`stopped`](Self::stopped).
+    pub(crate) fn started(
+        &self,
+        type_name: &'static str,
+        gate_metrics: Option<Arc<GateMetrics>>,
+    ) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.type_name = Some(type_name);
+        inner.started = Some(Utc::now());
+        inner.gate_metrics = gate_metrics;
+        inner.state = ComponentState::Starting;
+        inner.since = inner.started;
+        inner.generation
+    }
+
+    /// Records that the component is running, unless it said otherwise.
+    pub(crate) fn ready(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == ComponentState::Starting {
+            inner.set_state(ComponentState::Running);
+        }
+    }
+
+    /// Records that the instance of the given generation has stopped.
+    pub(crate) fn stopped(&self, generation: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation == generation {
+            inner.set_state(ComponentState::Stopped);
+        }
+    }
+
+    /// Records that the unit was paused or resumed.
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.inner.lock().unwrap().paused = paused;
+    }
+
+    /// Records the hash of the configuration of the component.
+    pub(crate) fn set_config_hash(&self, hash: Option<String>) {
+        self.inner.lock().unwrap().config_hash = hash;
+    }
+
+    /// Returns the current state.
+    pub fn state(&self) -> ComponentState {
+        self.inner.lock().unwrap().state
+    }
+
+    /// Returns a report on the health of the component.
+    ///
+    /// The updates a unit sent are taken from its gate, those a target
+    /// received are given as `received`. A running component some of
+    /// whose incoming queues are full is reported as degraded, a paused
+    /// unit as paused whatever it is doing.
+    pub fn report(
+        &self,
+        name: &str,
+        received: Option<usize>,
+        backlogged: bool,
+    ) -> HealthReport {
+        let inner = self.inner.lock().unwrap();
+        let now = Utc::now();
+        let uptime_secs = inner
+            .started
+            .map(|started| (now - started).num_seconds().max(0) as u64);
+        let updates = match &inner.gate_metrics {
+            Some(metrics) => Some(metrics.num_updates.load(SeqCst)),
+            None => received,
+        };
+        let updates_per_second = match (updates, uptime_secs) {
+            (Some(updates), Some(uptime)) if uptime > 0 => {
+                Some(updates as f64 / uptime as f64)
+            }
+            _ => None,
+        };
+        let state = match inner.state {
+            ComponentState::Stopped => ComponentState::Stopped,
+            _ if inner.paused => ComponentState::Paused,
+            ComponentState::Running if backlogged => ComponentState::Degraded,
+            state => state,
+        };
+        HealthReport {
+            name: name.to_string(),
+            type_name: inner.type_name,
+            state,
+            since: inner.since,
+            started: inner.started,
+            uptime_secs,
+            last_error: inner.last_error.clone(),
+            updates,
+            updates_per_second,
+            config_hash: inner.config_hash.clone(),
+        }
+    }
+}
+
+//------------ HealthReport --------------------------------------------------
+
+/// The health of a unit or target as reported over HTTP.
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthReport {
+    name: String,
+
+    #[serde(rename = "type")]
+    type_name: Option<&'static str>,
+
+    state: ComponentState,
+    since: Option<DateTime<Utc>>,
+    started: Option<DateTime<Utc>>,
+    uptime_secs: Option<u64>,
+    last_error: Option<LastError>,
+
+    /// The updates sent by a unit or received by a target.
+    updates: Option<usize>,
+
+    /// The updates per second on average since the component started.
+    updates_per_second: Option<f64>,
+
+    /// The SHA-256 hash of the configuration section of the component.
+    config_hash: Option<String>,
+}
+
+impl HealthReport {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> ComponentState {
+        self.state
+    }
+}
+
+//------------ HealthRegister ------------------------------------------------
+
+/// The health of all units and targets, by name.
+#[derive(Debug, Default)]
+pub struct HealthRegister {
+    components: Mutex<HashMap<String, Arc<ComponentHealth>>>,
+}
+
+impl HealthRegister {
+    /// Returns the health of the component named `name`.
+    pub fn get(&self, name: &str) -> Arc<ComponentHealth> {
+        self.components
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Returns the health of the component named `name`, if there is one.
+    pub fn find(&self, name: &str) -> Option<Arc<ComponentHealth>> {
+        self.components.lock().unwrap().get(name).cloned()
+    }
+
+    /// Forgets about a component that was removed.
+    pub fn remove(&self, name: &str) {
+        self.components.lock().unwrap().remove(name);
+    }
+
+    /// Returns the health of all components, sorted by name.
+    pub fn all(&self) -> Vec<(String, Arc<ComponentHealth>)> {
+        let mut res: Vec<_> = self
+            .components
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, health)| (name.clone(), health.clone()))
+            .collect();
+        res.sort_by(|a, b| a.0.cmp(&b.0));
+        res
+    }
+}
+
+/// Returns the hash of the configuration of a component.
+pub fn config_hash(section: &toml::Value) -> String {
+    let section = toml::to_string(section).unwrap_or_default();
+    format!("{:x}", Sha256::digest(section.as_bytes()))
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaced_components_are_not_stopped_by_their_predecessor() {
+        let health = ComponentHealth::default();
+        let old = health.started("bmp-tcp-in", None);
+        health.ready();
+        assert_eq!(health.state(), ComponentState::Running);
+
+        let new = health.started("bmp-tcp-in", None);
+        assert_eq!(health.state(), ComponentState::Starting);
+        health.stopped(old);
+        assert_eq!(health.state(), ComponentState::Starting);
+        health.stopped(new);
+        assert_eq!(health.state(), ComponentState::Stopped);
+    }
+
+    #[test]
+    fn components_report_their_health() {
+        let health = ComponentHealth::default();
+        health.started("mqtt-out", None);
+        health.set_state(ComponentState::Connecting);
+        health.ready();
+        assert_eq!(health.state(), ComponentState::Connecting);
+        health.report_error("connection refused");
+        health.set_state(ComponentState::Retrying);
+        health.set_config_hash(Some("abc".into()));
+
+        let report =
+            serde_json::to_value(health.report("mqtt", Some(3), false))
+                .unwrap();
+        assert_eq!(report["name"], "mqtt");
+        assert_eq!(report["type"], "mqtt-out");
+        assert_eq!(report["state"], "retrying");
+        assert_eq!(report["last_error"]["message"], "connection refused");
+        assert_eq!(report["updates"], 3);
+        assert_eq!(report["config_hash"], "abc");
+
+        health.set_state(ComponentState::Running);
+        assert_eq!(
+            health.report("mqtt", Some(3), true).state(),
+            ComponentState::Degraded
+        );
+        health.set_paused(true);
+        assert_eq!(
+            health.report("mqtt", Some(3), true).state(),
+            ComponentState::Paused
+        );
+    }
+}
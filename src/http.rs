@@ -7,6 +7,7 @@
 //! Server configuration happens via the [`Server`] struct that normally is
 //! part of the [`Config`](crate::config::Config).
 
+use crate::common::openapi;
 use crate::log::ExitError;
 use crate::metrics;
 use arc_swap::ArcSwap;
@@ -201,16 +202,20 @@ impl Server {
         metrics: &metrics::Collection,
         resources: &Resources,
     ) -> Result<Response<Body>, Infallible> {
-        if *req.method() != Method::GET {
-            return Ok(Self::method_not_allowed());
-        }
+        let is_get = match *req.method() {
+            Method::GET => true,
+            Method::POST => false,
+            _ => return Ok(Self::method_not_allowed()),
+        };
 
         let res = match req.uri().decoded_path().as_ref() {
-            "/metrics" => Self::metrics(metrics),
-            "/status" => Self::status(metrics),
+            "/metrics" if is_get => Self::metrics(metrics),
+            "/status" if is_get => Self::status(metrics),
+            "/openapi.json" if is_get => Self::openapi(resources),
             _ => match resources.process_request(&req).await {
                 Some(response) => response,
-                None => Self::not_found(),
+                None if is_get => Self::not_found(),
+                None => Self::method_not_allowed(),
             },
         };
 
@@ -236,6 +241,24 @@ impl Server {
             .unwrap()
     }
 
+    /// Produces the response for a call to the `/openapi.json` endpoint.
+    fn openapi(resources: &Resources) -> Response<Body> {
+        let mut paths = openapi::Paths::new();
+        paths
+            .get("/metrics", "The metrics in the Prometheus format")
+            .produces("text/plain");
+        paths
+            .get("/status", "The metrics in a plain text format")
+            .produces("text/plain");
+        paths.get("/openapi.json", "This description of the HTTP API");
+        resources.describe(&mut paths);
+        let document = paths.document("Rotonda", env!("CARGO_PKG_VERSION"));
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string_pretty(&document).unwrap().into())
+            .unwrap()
+    }
+
     #[cfg(not(feature = "http-api-gzip"))]
     async fn encode_response(
         _req: Request<Body>,
@@ -251,7 +274,12 @@ impl Server {
         res: Response<Body>,
         compress_responses: bool,
     ) -> Response<Body> {
-        if compress_responses {
+        // Event streams never end, so cannot be compressed as a whole.
+        let is_event_stream = res
+            .headers()
+            .get("Content-Type")
+            .is_some_and(|v| v == "text/event-stream");
+        if compress_responses && !is_event_stream {
             // Streaming the response through the encoder as it's being built
             // would be more efficient, and maybe also only creating the encoder
             // once rather than every time, but this works for now.
@@ -390,6 +418,18 @@ impl Resources {
         None
     }
 
+    /// Describes the endpoints of the registered processors, tagged with
+    /// the name of their component.
+    pub fn describe(&self, paths: &mut openapi::Paths) {
+        for item in self.sources.load().iter() {
+            if let Some(process) = item.processor.upgrade() {
+                paths.set_tag(Some(item.component_name.clone()));
+                process.describe(paths);
+            }
+        }
+        paths.set_tag(None);
+    }
+
     pub fn resources(&self) -> SmallVec<[Arc<RegisteredResource>; 8]> {
         self.sources
             .load()
@@ -456,6 +496,13 @@ pub trait ProcessRequest: Send + Sync {
         &self,
         request: &Request<Body>,
     ) -> Option<Response<Body>>;
+
+    /// Adds the endpoints the processor serves to `paths`.
+    ///
+    /// This is used for the OpenAPI document of the HTTP API. Every
+    /// endpoint the processor answers should be added, so that the
+    /// document lists all of them.
+    fn describe(&self, paths: &mut openapi::Paths);
 }
 
 #[async_trait]
@@ -466,10 +513,28 @@ impl<T: ProcessRequest> ProcessRequest for Arc<T> {
     ) -> Option<Response<Body>> {
         AsRef::<T>::as_ref(self).process_request(request).await
     }
+
+    fn describe(&self, paths: &mut openapi::Paths) {
+        AsRef::<T>::as_ref(self).describe(paths)
+    }
+}
+
+//------------ Described -----------------------------------------------------
+
+/// A processor given as a closure, with a function describing its endpoints.
+pub struct Described<F> {
+    process: F,
+    describe: fn(&mut openapi::Paths),
+}
+
+impl<F> Described<F> {
+    pub fn new(process: F, describe: fn(&mut openapi::Paths)) -> Self {
+        Self { process, describe }
+    }
 }
 
 #[async_trait]
-impl<F> ProcessRequest for F
+impl<F> ProcessRequest for Described<F>
 where
     F: Fn(&Request<Body>) -> Option<Response<Body>> + Sync + Send,
 {
@@ -477,7 +542,11 @@ where
         &self,
         request: &Request<Body>,
     ) -> Option<Response<Body>> {
-        (self)(request)
+        (self.process)(request)
+    }
+
+    fn describe(&self, paths: &mut openapi::Paths) {
+        (self.describe)(paths)
     }
 }
 
@@ -652,6 +721,22 @@ pub fn get_all_params<'a>(
         .collect()
 }
 
+/// Checks that all query parameters were used, naming those that weren't.
+pub fn check_unused_params(params: &QueryParams) -> Result<(), String> {
+    let unused_params: Vec<&str> = params
+        .iter()
+        .filter(|param| !param.used())
+        .map(|param| param.name())
+        .collect();
+    if !unused_params.is_empty() {
+        return Err(format!(
+            "Unrecognized query parameters: {}",
+            unused_params.join(",")
+        ));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Eq, PartialEq, Hash)]
 pub enum MatchedParam<'a> {
     Exact(&'a str),           // value
@@ -700,3 +785,36 @@ impl<'a> Display for MatchedParam<'a> {
         }
     }
 }
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manager::Manager;
+
+    #[tokio::test]
+    async fn every_registered_resource_is_described() {
+        let manager = Manager::new();
+        let resources = manager.http_resources();
+        let sources = resources.sources.load();
+        assert!(!sources.is_empty());
+        for item in sources.iter() {
+            let process = item.processor.upgrade().unwrap();
+            let mut paths = openapi::Paths::new();
+            process.describe(&mut paths);
+            let base = item.rel_base_url.as_str();
+            assert!(paths.operations().next().is_some(), "{base}");
+
+            // The described endpoints are the ones the processor serves.
+            for (path, method, _) in paths.operations() {
+                if method != "get" || path.contains('{') {
+                    continue;
+                }
+                let request = Request::get(path).body(Body::empty()).unwrap();
+                let response = process.process_request(&request).await;
+                assert!(response.is_some(), "{path}");
+            }
+        }
+    }
+}
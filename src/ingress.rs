@@ -105,6 +105,41 @@ impl Register {
         res
     }
 
+    /// Find all [`IngressId`]s that descend from the given `ancestor`
+    ///
+    /// Besides the direct children as returned by [`Self::ids_for_parent`],
+    /// this includes their children, and so on. For the ingress of a BMP
+    /// connector unit, this returns both the monitored routers and the BGP
+    /// sessions monitored through them.
+    pub fn descendants(&self, ancestor: IngressId) -> Vec<IngressId> {
+        let lock = self.info.read().unwrap();
+        let mut res = Vec::new();
+        let mut todo = vec![ancestor];
+        while let Some(parent) = todo.pop() {
+            for (id, info) in lock.iter() {
+                if info.parent_ingress == Some(parent) && !res.contains(id) {
+                    res.push(*id);
+                    todo.push(*id);
+                }
+            }
+        }
+        res
+    }
+
+    /// Find all [`IngressId`]s for which the info matches `predicate`
+    pub fn find_all(
+        &self,
+        predicate: impl Fn(&IngressInfo) -> bool,
+    ) -> Vec<IngressId> {
+        self.info
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, info)| predicate(info))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
     // find_existing methods:
     // cases to cover:
     //  * match MRT update messages (to ingresses from bdumps):
@@ -239,6 +274,26 @@ impl IngressInfo {
 
 #[cfg(test)]
 mod tests {
-
-
+    use super::*;
+
+    #[test]
+    fn descendants_span_multiple_levels() {
+        let register = Register::new();
+        let unit = register.register();
+        let router = register.register();
+        register.update_info(router, IngressInfo::new().with_parent(unit));
+        let peer = register.register();
+        register.update_info(peer, IngressInfo::new().with_parent(router));
+        let other = register.register();
+        register.update_info(other, IngressInfo::new());
+
+        let mut res = register.descendants(unit);
+        res.sort();
+        assert_eq!(res, [router, peer]);
+        assert!(register.descendants(peer).is_empty());
+        assert_eq!(
+            register.find_all(|info| info.parent_ingress == Some(router)),
+            [peer]
+        );
+    }
 }
@@ -2,9 +2,11 @@
 #![allow(renamed_and_removed_lints)]
 #![allow(clippy::unknown_clippy_lints)]
 
+pub mod admin;
 pub mod common;
 pub mod comms;
 pub mod config;
+pub mod health;
 pub mod http;
 pub mod ingress;
 pub mod log;
@@ -5,14 +5,18 @@ use futures::{
     pin_mut,
 };
 use log::{debug, error, info, warn};
+use rotonda::admin::AdminAction;
 use rotonda::log::ExitError;
 use rotonda::manager::Manager;
+use rotonda::roto_runtime::filter_tests;
 use rotonda::{
     config::{Config, ConfigFile, Source},
     log::Terminate,
 };
 use std::env::current_dir;
+use std::path::Path;
 use std::process::exit;
+use std::sync::Arc;
 use tokio::{
     runtime::{self, Runtime},
     signal::{self, unix::signal, unix::SignalKind},
@@ -26,7 +30,9 @@ fn run_with_cmdline_args() -> Result<(), Terminate> {
         .author(crate_authors!())
         .next_line_help(true);
 
-    let config_args = Config::config_args(app);
+    let config_args = filter_tests::command(Config::check_command(
+        Config::config_args(app),
+    ));
     let matches = config_args.try_get_matches().map_err(|err| {
         let _ = err.print();
         match err.kind() {
@@ -49,7 +55,16 @@ fn run_with_cmdline_args() -> Result<(), Terminate> {
     //   - https://github.com/NLnetLabs/routinator/blob/main/src/process.rs#L241
     //   - https://github.com/NLnetLabs/routinator/blob/main/src/process.rs#L363
 
+    if let Some(res) = filter_tests::run_arg_matches(&matches, &cur_dir) {
+        return res;
+    }
+
     let mut manager = Manager::new();
+    if let Some(res) =
+        Config::check_arg_matches(&matches, &cur_dir, &mut manager)
+    {
+        return res;
+    }
     let (config_source, config) =
         Config::from_arg_matches(&matches, &cur_dir, &mut manager)?;
     debug!("application working directory {:?}", cur_dir);
@@ -70,6 +85,7 @@ async fn handle_signals(
         error!("Fatal: cannot listen for HUP signals ({}). Aborting.", err);
         ExitError
     })?;
+    let mut admin_requests = manager.admin_requests();
 
     loop {
         let ctrl_c = signal::ctrl_c();
@@ -78,7 +94,58 @@ async fn handle_signals(
         let hup = hup_signals.recv();
         pin_mut!(hup);
 
-        match select(hup, ctrl_c).await {
+        let watch_interval = manager.roto_watch_interval();
+        let watch = async {
+            match watch_interval {
+                Some(interval) => tokio::time::sleep(interval).await,
+                None => std::future::pending().await,
+            }
+        };
+        pin_mut!(watch);
+
+        let admin = async {
+            match admin_requests.as_mut() {
+                Some(requests) => match requests.recv().await {
+                    Some(request) => request,
+                    None => std::future::pending().await,
+                },
+                None => std::future::pending().await,
+            }
+        };
+        pin_mut!(admin);
+
+        let signal = match select(select(hup, ctrl_c), select(watch, admin))
+            .await
+        {
+            Either::Left((signal, _)) => signal,
+            Either::Right((Either::Left(_), _)) => {
+                manager.reload_changed_roto_script();
+                continue;
+            }
+            Either::Right((Either::Right((request, _)), _)) => {
+                if *request.action() != AdminAction::Reload {
+                    manager.process_admin_request(request);
+                    continue;
+                }
+                let res = match config_source.path() {
+                    Some(config_path) => {
+                        info!(
+                            "Reload requested, re-reading configuration file '{}'",
+                            config_path.display()
+                        );
+                        reload_config(config_path, &mut manager)
+                    }
+                    None => Err("No configuration file to re-read".into()),
+                };
+                if let Err(err) = &res {
+                    error!("{err}");
+                }
+                request.reply(res);
+                continue;
+            }
+        };
+
+        match signal {
             Either::Left((None, _)) => {
                 error!(
                     "Fatal: listening for SIGHUP signals failed. Aborting."
@@ -94,33 +161,10 @@ async fn handle_signals(
                         "SIGHUP signal received, re-reading configuration file '{}'",
                         config_path.display()
                         );
-                        match ConfigFile::load(&config_path) {
-                            Ok(config_file) => {
-                                match Config::from_config_file(
-                                    config_file,
-                                    &mut manager,
-                                ) {
-                                    Err(_) => {
-                                        error!(
-                                            "Failed to re-read config file '{}'",
-                                            config_path.display()
-                                        );
-                                    }
-                                    Ok((_source, mut config)) => {
-                                        manager.spawn(&mut config);
-                                        info!(
-                                            "Configuration changes applied"
-                                        );
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                error!(
-                                    "Failed to re-read config file '{}': {}",
-                                    config_path.display(),
-                                    err
-                                );
-                            }
+                        if let Err(err) =
+                            reload_config(config_path, &mut manager)
+                        {
+                            error!("{err}");
                         }
                     }
                     None => {
@@ -163,6 +207,31 @@ async fn handle_signals(
     }
 }
 
+/// Re-reads the config file and applies the changes to the running units
+/// and targets, returning a summary of what was done.
+fn reload_config(
+    config_path: &Arc<Path>,
+    manager: &mut Manager,
+) -> Result<String, String> {
+    let config_file = ConfigFile::load(&config_path).map_err(|err| {
+        format!(
+            "Failed to re-read config file '{}': {}",
+            config_path.display(),
+            err
+        )
+    })?;
+    let (_source, mut config) =
+        Config::from_config_file(config_file, manager).map_err(|_| {
+            format!(
+                "Failed to re-read config file '{}'",
+                config_path.display()
+            )
+        })?;
+    let summary = manager.spawn(&mut config);
+    info!("Configuration changes applied: {summary}");
+    Ok(summary.to_string())
+}
+
 fn run_with_config(
     manager: &mut Manager,
     mut config: Config,
@@ -1,26 +1,39 @@
 //! Controlling the entire operation.
 
+use crate::admin::{AdminAction, AdminApi, AdminError, AdminRequest};
 use crate::common::file_io::TheFileIo;
+use crate::common::openapi::Schema;
 use crate::roto_runtime::types::FilterName;
-use crate::roto_runtime::types::CompiledRoto;
+use crate::roto_runtime::bogons;
+use crate::roto_runtime::filter_metrics;
+use crate::roto_runtime::reload::{LiveRoto, ReloadConfig, ScriptFiles};
 use crate::roto_runtime::create_runtime;
+use crate::roto_runtime::external_data::ExternalDataManager;
+use crate::roto_runtime::state::{StateStore, StateStores};
+use crate::roto_runtime::user_metrics;
 use crate::comms::{
-    DirectLink, Gate, GateAgent, GraphStatus, Link, DEF_UPDATE_QUEUE_LEN,
+    DirectLink, Gate, GateAgent, GraphStatus, Link, LinkMetrics, Pause,
+    DEF_UPDATE_QUEUE_LEN,
 };
-use crate::config::{Config, ConfigFile, Marked};
+use crate::config::{ComponentSections, Config, ConfigFile, Marked};
+use crate::health::{ComponentHealth, ComponentState, HealthRegister};
 use crate::log::Terminate;
 use crate::targets::Target;
 use crate::tracing::{MsgRelation, Trace, Tracer};
+use crate::units::flow_in::counters::TrafficCounterSets;
+use crate::units::rib_unit::rpki::RtrCaches;
 use crate::units::Unit;
 use crate::{http, ingress, metrics};
 use arc_swap::ArcSwap;
-use futures::future::{join_all, select, Either};
+use futures::future::{join_all, select, Either, Future};
 use log::{debug, error, info, log_enabled, trace, warn};
 use non_empty_vec::NonEmpty;
 use reqwest::Client as HttpClient;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering::SeqCst;
 use std::sync::{Arc, Mutex, RwLock, Weak};
 use std::time::{Duration, Instant};
 use std::{cell::RefCell, fmt::Display};
@@ -30,7 +43,7 @@ use tokio::sync::Barrier;
 use uuid::Uuid;
 
 use {
-    crate::http::{PercentDecodedPath, ProcessRequest},
+    crate::http::{Described, PercentDecodedPath, ProcessRequest},
     hyper::{Body, Method, Request, Response},
 };
 
@@ -57,13 +70,25 @@ pub struct Component {
     http_resources: http::Resources,
 
     /// A reference to the compiled Roto script.
-    roto_compiled: Option<Arc<CompiledRoto>>,
+    roto: Arc<LiveRoto>,
 
     /// A reference to the Tracer
     tracer: Arc<Tracer>,
 
     /// A reference to the ingress sources.
     ingresses: Arc<ingress::Register>,
+
+    /// A reference to the RTR caches of the RTR client units.
+    rtr_caches: Arc<RtrCaches>,
+
+    /// A reference to the traffic counters of the flow-in units.
+    traffic_counters: Arc<TrafficCounterSets>,
+
+    /// A reference to the state stores of the Roto filters.
+    roto_state: Arc<StateStores>,
+
+    /// A reference to the health of the components.
+    health: Arc<HealthRegister>,
 }
 
 #[cfg(test)]
@@ -75,24 +100,33 @@ impl Default for Component {
             http_client: Default::default(),
             metrics: Default::default(),
             http_resources: Default::default(),
-            roto_compiled: Default::default(),
+            roto: Default::default(),
             tracer: Default::default(),
             ingresses: Default::default(),
+            rtr_caches: Default::default(),
+            traffic_counters: Default::default(),
+            roto_state: Default::default(),
+            health: Default::default(),
         }
     }
 }
 
 impl Component {
     /// Creates a new component from its, well, components.
+    #[allow(clippy::too_many_arguments)]
     fn new(
         name: String,
         type_name: &'static str,
         http_client: HttpClient,
         metrics: metrics::Collection,
         http_resources: http::Resources,
-        roto_compiled: Option<Arc<CompiledRoto>>,
+        roto: Arc<LiveRoto>,
         tracer: Arc<Tracer>,
         ingresses: Arc<ingress::Register>,
+        rtr_caches: Arc<RtrCaches>,
+        traffic_counters: Arc<TrafficCounterSets>,
+        roto_state: Arc<StateStores>,
+        health: Arc<HealthRegister>,
     ) -> Self {
         Component {
             name: name.into(),
@@ -100,9 +134,13 @@ impl Component {
             http_client: Some(http_client),
             metrics: Some(metrics),
             http_resources,
-            roto_compiled,
+            roto,
             tracer,
             ingresses,
+            rtr_caches,
+            traffic_counters,
+            roto_state,
+            health,
         }
     }
 
@@ -125,16 +163,20 @@ impl Component {
         &self.http_resources
     }
 
-    pub fn roto_compiled(
-        &self,
-    ) -> &Option<Arc<CompiledRoto>> {
-        &self.roto_compiled
+    /// Returns the compiled Roto script, which changes when reloaded.
+    pub fn roto(&self) -> &Arc<LiveRoto> {
+        &self.roto
     }
 
     pub fn tracer(&self) -> &Arc<Tracer> {
         &self.tracer
     }
 
+    /// Returns the metrics collection, if there is one.
+    pub fn metrics(&self) -> Option<&metrics::Collection> {
+        self.metrics.as_ref()
+    }
+
     /// Register a metrics source.
     pub fn register_metrics(&mut self, source: Arc<dyn metrics::Source>) {
         if let Some(metrics) = &self.metrics {
@@ -181,6 +223,24 @@ impl Component {
     pub fn ingresses(&self) -> Arc<ingress::Register> {
         self.ingresses.clone()
     }
+
+    pub fn rtr_caches(&self) -> &Arc<RtrCaches> {
+        &self.rtr_caches
+    }
+
+    pub fn traffic_counters(&self) -> &Arc<TrafficCounterSets> {
+        &self.traffic_counters
+    }
+
+    /// Returns the state store of the Roto filter of this component.
+    pub fn roto_state(&self) -> Arc<StateStore> {
+        self.roto_state.get(&self.name)
+    }
+
+    /// Returns the health of this component.
+    pub fn health(&self) -> Arc<ComponentHealth> {
+        self.health.get(&self.name)
+    }
 }
 
 //------------ Manager -------------------------------------------------------
@@ -192,12 +252,13 @@ pub enum LinkType {
 }
 
 #[allow(dead_code)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct LinkInfo {
     link_type: LinkType,
     id: Uuid,
     gate_id: Uuid,
     connected_gate_slot: Option<Uuid>,
+    metrics: Arc<LinkMetrics>,
 }
 
 impl From<&Link> for LinkInfo {
@@ -207,6 +268,7 @@ impl From<&Link> for LinkInfo {
             id: link.id(),
             gate_id: link.gate_id(),
             connected_gate_slot: link.connected_gate_slot(),
+            metrics: link.metrics(),
         }
     }
 }
@@ -218,6 +280,7 @@ impl From<&DirectLink> for LinkInfo {
             id: link.id(),
             gate_id: link.gate_id(),
             connected_gate_slot: link.connected_gate_slot(),
+            metrics: link.metrics(),
         }
     }
 }
@@ -226,6 +289,7 @@ impl From<&DirectLink> for LinkInfo {
 pub struct LinkReport {
     gates: HashMap<String, Uuid>,
     links: HashMap<String, UpstreamLinkReport>,
+    types: HashMap<String, String>,
 }
 
 impl LinkReport {
@@ -241,6 +305,10 @@ impl LinkReport {
         self.links.insert(name, report);
     }
 
+    fn add_type(&mut self, name: String, type_name: String) {
+        self.types.insert(name, type_name);
+    }
+
     fn ready(&self) -> Result<(), usize> {
         let remaining = self
             .links
@@ -258,6 +326,86 @@ impl LinkReport {
         self.gates.get(name).copied()
     }
 
+    fn get_gate_name(&self, id: Uuid) -> Option<&str> {
+        self.gates
+            .iter()
+            .find(|(_, &gate_id)| gate_id == id)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Returns the units and targets and the links between them.
+    pub fn graph(&self) -> PipelineGraph {
+        let mut components: Vec<_> = self
+            .links
+            .iter()
+            .map(|(name, report)| {
+                let graph_status = report
+                    .graph_status()
+                    .and_then(|weak_ref| weak_ref.upgrade());
+                GraphComponent {
+                    name: name.clone(),
+                    kind: if self.gates.contains_key(name) {
+                        "unit"
+                    } else {
+                        "target"
+                    },
+                    type_name: self.types.get(name).cloned(),
+                    status: graph_status
+                        .as_ref()
+                        .map(|status| status.status_text()),
+                    okay: graph_status.and_then(|status| status.okay()),
+                }
+            })
+            .collect();
+        components.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut links: Vec<_> = self
+            .links
+            .iter()
+            .flat_map(|(name, report)| {
+                report.into_vec().into_iter().map(|link| {
+                    let queue = link.metrics.queue();
+                    GraphLink {
+                        from: self
+                            .get_gate_name(link.gate_id)
+                            .map(Into::into),
+                        to: name.clone(),
+                        link_type: match link.link_type {
+                            LinkType::Queued => "queued",
+                            LinkType::Direct => "direct",
+                        },
+                        connected: link.connected_gate_slot.is_some(),
+                        num_updates: link.metrics.num_updates.load(SeqCst),
+                        queue_len: queue.map(|(len, _)| len),
+                        queue_capacity: queue.map(|(_, capacity)| capacity),
+                    }
+                })
+            })
+            .collect();
+        links.sort_by(|a, b| (&a.to, &a.from).cmp(&(&b.to, &b.from)));
+
+        PipelineGraph { components, links }
+    }
+
+    /// Returns the updates received over the incoming links of a component
+    /// and whether any of their queues is full.
+    fn incoming(&self, name: &str) -> (Option<usize>, bool) {
+        let Some(report) = self.links.get(name) else {
+            return (None, false);
+        };
+        let links = report.into_vec();
+        let received = links
+            .iter()
+            .map(|link| link.metrics.num_updates.load(SeqCst))
+            .sum();
+        let backlogged = links.iter().any(|link| {
+            link.metrics
+                .queue()
+                .is_some_and(|(len, capacity)| len >= capacity)
+        });
+        (Some(received), backlogged)
+    }
+
     fn get_svg(&self, tracer: Arc<Tracer>, trace_id: Option<u8>) -> String {
         use chrono::Utc;
         use layout::backends::svg::SVGWriter;
@@ -407,6 +555,167 @@ impl LinkReport {
     }
 }
 
+//------------ PipelineGraph -------------------------------------------------
+
+/// The units and targets and the links between them.
+#[derive(Clone, Debug, Serialize)]
+pub struct PipelineGraph {
+    components: Vec<GraphComponent>,
+    links: Vec<GraphLink>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct GraphComponent {
+    name: String,
+
+    /// Whether this is a "unit" or a "target".
+    kind: &'static str,
+
+    #[serde(rename = "type")]
+    type_name: Option<String>,
+
+    /// The status text of the component, as shown in the SVG graph.
+    status: Option<String>,
+
+    okay: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct GraphLink {
+    /// The upstream unit, if it is still running.
+    from: Option<String>,
+
+    to: String,
+
+    #[serde(rename = "type")]
+    link_type: &'static str,
+
+    connected: bool,
+
+    num_updates: usize,
+
+    /// The number of updates waiting to be received, if a queued link.
+    queue_len: Option<usize>,
+
+    queue_capacity: Option<usize>,
+}
+
+impl GraphComponent {
+    fn label(&self) -> String {
+        let mut res = self.name.clone();
+        for line in [&self.type_name, &self.status].into_iter().flatten() {
+            res.push('\n');
+            res.push_str(line);
+        }
+        res
+    }
+}
+
+impl GraphLink {
+    fn label(&self) -> String {
+        let mut res = format!("{} updates", self.num_updates);
+        if let (Some(len), Some(capacity)) =
+            (self.queue_len, self.queue_capacity)
+        {
+            res.push_str(&format!(", queued {len}/{capacity}"));
+        }
+        res
+    }
+}
+
+impl PipelineGraph {
+    /// Returns the graph in the Graphviz DOT language.
+    pub fn to_dot(&self) -> String {
+        fn quote(s: &str) -> String {
+            let escaped = s
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("\"{escaped}\"")
+        }
+
+        let mut res = String::from("digraph rotonda {\n  rankdir=LR;\n");
+        for component in &self.components {
+            let shape = match component.kind {
+                "unit" => "box",
+                _ => "ellipse",
+            };
+            let colour = match component.okay {
+                Some(false) => "red",
+                Some(true) => "green",
+                None => "black",
+            };
+            res.push_str(&format!(
+                "  {} [shape={shape}, color={colour}, label={}];\n",
+                quote(&component.name),
+                quote(&component.label()),
+            ));
+        }
+        for link in &self.links {
+            if let Some(from) = &link.from {
+                res.push_str(&format!(
+                    "  {} -> {} [label={}{}];\n",
+                    quote(from),
+                    quote(&link.to),
+                    quote(&link.label()),
+                    if link.link_type == "direct" {
+                        ", style=dashed"
+                    } else {
+                        ""
+                    },
+                ));
+            }
+        }
+        res.push_str("}\n");
+        res
+    }
+
+    /// Returns the graph as a Mermaid flowchart.
+    pub fn to_mermaid(&self) -> String {
+        fn quote(s: &str) -> String {
+            format!("\"{}\"", s.replace('"', "#quot;").replace('\n', "<br/>"))
+        }
+
+        let ids: HashMap<&str, String> = self
+            .components
+            .iter()
+            .enumerate()
+            .map(|(idx, component)| {
+                (component.name.as_str(), format!("c{idx}"))
+            })
+            .collect();
+
+        let mut res = String::from("flowchart LR\n");
+        for component in &self.components {
+            let (open, close) = match component.kind {
+                "unit" => ("[", "]"),
+                _ => ("([", "])"),
+            };
+            res.push_str(&format!(
+                "  {}{open}{}{close}\n",
+                ids[component.name.as_str()],
+                quote(&component.label()),
+            ));
+        }
+        for link in &self.links {
+            let from = link.from.as_deref().and_then(|from| ids.get(from));
+            let to = ids.get(link.to.as_str());
+            if let (Some(from), Some(to)) = (from, to) {
+                let arrow = if link.link_type == "direct" {
+                    "-.->"
+                } else {
+                    "-->"
+                };
+                res.push_str(&format!(
+                    "  {from} {arrow}|{}| {to}\n",
+                    quote(&link.label()),
+                ));
+            }
+        }
+        res
+    }
+}
+
 fn extract_msg_indices(trace: &Trace, gate_id: Uuid) -> String {
     let (mut msg_indices, first, last) =
         trace.msg_indices(gate_id, MsgRelation::ALL).iter().fold(
@@ -589,6 +898,12 @@ pub struct Manager {
     /// Gates for newly loaded, not yet spawned units.
     pending_gates: HashMap<String, (Gate, GateAgent)>,
 
+    /// The sections of the running units and targets in the config file.
+    running_sections: ComponentSections,
+
+    /// The sections of the newly loaded, not yet spawned config.
+    pending_sections: ComponentSections,
+
     /// An HTTP client.
     http_client: HttpClient,
 
@@ -598,8 +913,20 @@ pub struct Manager {
     /// The HTTP resources collection maintained by this manager.
     http_resources: http::Resources,
 
-    /// A reference to the compiled Roto script.
-    roto_compiled: Option<Arc<CompiledRoto>>,
+    /// The compiled Roto script.
+    roto: Arc<LiveRoto>,
+
+    /// The location of the Roto script, if any.
+    roto_script: Option<PathBuf>,
+
+    /// The modification times of the files of the Roto script.
+    roto_script_files: ScriptFiles,
+
+    /// Whether and how often to check the Roto script for changes.
+    roto_reload: ReloadConfig,
+
+    /// The external data sources available to the Roto script.
+    external_data: ExternalDataManager,
 
     graph_svg_processor: Arc<dyn ProcessRequest>,
 
@@ -611,7 +938,27 @@ pub struct Manager {
 
     tracer_processor: Arc<dyn ProcessRequest>,
 
+    admin_processor: Arc<AdminApi>,
+
+    /// The requests made via the administrative HTTP API.
+    admin_requests: Option<Receiver<AdminRequest>>,
+
     ingresses: Arc<ingress::Register>,
+
+    rtr_caches: Arc<RtrCaches>,
+
+    traffic_counters: Arc<TrafficCounterSets>,
+
+    /// The state stores of the Roto filters, by unit name.
+    roto_state: Arc<StateStores>,
+
+    /// The health of the units and targets, by name.
+    health: Arc<HealthRegister>,
+
+    health_processor: Arc<dyn ProcessRequest>,
+
+    /// The pauses of the running units, by name.
+    pauses: HashMap<String, Arc<Pause>>,
 }
 
 impl Default for Manager {
@@ -621,6 +968,9 @@ impl Default for Manager {
 }
 
 impl Manager {
+    /// How long to wait for a target being drained to receive its updates.
+    pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(60);
+
     /// Creates a new manager.
     pub fn new() -> Self {
         let graph_svg_data = Arc::new(ArcSwap::from_pointee((
@@ -639,6 +989,15 @@ impl Manager {
         let (tracer_processor, tracer_rel_base_url) =
             Self::mk_tracer_http_processor(tracer.clone());
 
+        let (admin_processor, admin_requests) = AdminApi::new();
+
+        let health = Arc::new(HealthRegister::default());
+        let (health_processor, health_rel_base_url) =
+            Self::mk_health_http_processor(
+                health.clone(),
+                graph_svg_data.clone(),
+            );
+
         #[allow(
             clippy::let_and_return,
             clippy::default_constructed_unit_structs
@@ -647,18 +1006,41 @@ impl Manager {
             running_units: Default::default(),
             running_targets: Default::default(),
             pending_gates: Default::default(),
+            running_sections: Default::default(),
+            pending_sections: Default::default(),
             http_client: Default::default(),
             metrics: Default::default(),
             http_resources: Default::default(),
-            roto_compiled: Default::default(),
+            roto: Default::default(),
+            roto_script: Default::default(),
+            roto_script_files: Default::default(),
+            roto_reload: Default::default(),
+            external_data: Default::default(),
             graph_svg_processor,
             graph_svg_data,
             file_io: TheFileIo::default(),
             tracer,
             tracer_processor,
+            admin_processor: Arc::new(admin_processor),
+            admin_requests: Some(admin_requests),
             ingresses,
+            rtr_caches: Default::default(),
+            traffic_counters: Default::default(),
+            roto_state: Default::default(),
+            health,
+            health_processor,
+            pauses: Default::default(),
         };
 
+        // Register the /status/units and /status/ready endpoints.
+        manager.http_resources.register(
+            Arc::downgrade(&manager.health_processor),
+            "status_units".into(),
+            "status_units",
+            health_rel_base_url,
+            true,
+        );
+
         // Register the /status/graph endpoint.
         manager.http_resources.register(
             Arc::downgrade(&manager.graph_svg_processor),
@@ -676,9 +1058,41 @@ impl Manager {
             true,
         );
 
+        manager.http_resources.register(
+            Arc::downgrade(&manager.admin_processor),
+            "admin".into(),
+            "admin",
+            AdminApi::REL_BASE_URL,
+            true,
+        );
+
+        // Register the metrics defined by roto scripts.
+        manager.metrics.register(
+            "roto".into(),
+            Arc::downgrade(user_metrics::shared()),
+        );
+        manager.metrics.register(
+            "roto".into(),
+            Arc::downgrade(filter_metrics::shared()),
+        );
+        manager.metrics.register(
+            "roto".into(),
+            Arc::downgrade(&manager.roto),
+        );
+
         manager
     }
 
+    /// Takes the receiver of the requests made via the administrative API.
+    ///
+    /// Requests to reload the config file should be answered with the
+    /// outcome of loading, preparing and spawning the config file anew, as
+    /// is done on SIGHUP. All others can be passed on to
+    /// [`process_admin_request`](Self::process_admin_request).
+    pub fn admin_requests(&mut self) -> Option<Receiver<AdminRequest>> {
+        self.admin_requests.take()
+    }
+
     #[cfg(test)]
     pub fn set_file_io(&mut self, file_io: TheFileIo) {
         self.file_io = file_io;
@@ -838,11 +1252,25 @@ impl Manager {
                     })
             });
 
+        if let Err(err) = self.external_data.configure(&config.external_data)
+        {
+            error!("Invalid external data configuration: {err}.");
+            Err(Terminate::error())?
+        }
+
         if let Err(err) = self.compile_roto_script(&roto_script) {
             let msg = format!("Unable to load main Roto script: {err}.");
             error!("{msg}");
             Err(Terminate::error())?
         }
+        self.roto_reload = config.roto_reload.clone();
+        self.admin_processor.configure(config.admin.clone());
+        bogons::set_current(config.roto_bogons.bogons());
+        filter_metrics::shared()
+            .set_slow_filters(config.roto_slow_filters.clone());
+
+        self.roto_state.configure(&config.roto_state);
+        self.pending_sections = ComponentSections::new(file);
 
         // Drain the singleton static GATES contents to a local variable.
         let gates = GATES
@@ -887,9 +1315,27 @@ impl Manager {
         Ok(())
     }
 
+    /// Returns the names of the units of a prepared config that are unused.
+    ///
+    /// These are the units that no other unit or target takes updates from,
+    /// which [`spawn`](Self::spawn) would not start.
+    pub fn unused_units<'a>(&self, config: &'a Config) -> Vec<&'a str> {
+        config
+            .units
+            .units
+            .keys()
+            .filter(|name| !self.pending_gates.contains_key(*name))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Compiles the Roto script at `roto_scripts_path`.
+    ///
+    /// On success, the script replaces the current one, including in the
+    /// running units. Otherwise, the current one stays in use.
     pub fn compile_roto_script(
         &mut self,
-        roto_scripts_path: &Option<std::path::PathBuf>,
+        roto_scripts_path: &Option<PathBuf>,
     ) -> Result<(), String> {
         let path = if let Some(p) = roto_scripts_path {
             p
@@ -898,14 +1344,58 @@ impl Manager {
             return Ok(());
         };
 
+        let files = ScriptFiles::read(path);
+        let c = self.compile_roto_files(path).inspect_err(|_| {
+            self.roto.failed();
+        })?;
+
+        self.roto.store(Arc::new(Mutex::new(c)));
+        self.roto_script = Some(path.clone());
+        self.roto_script_files = files;
+        Ok(())
+    }
+
+    fn compile_roto_files(
+        &self,
+        path: &Path,
+    ) -> Result<roto::Compiled, String> {
+        let mut rt = create_runtime()?;
+        self.external_data.register(&mut rt)?;
+
         let i = roto::FileTree::read(path);
             // .map_err(|e| e.to_string())?;
-        let c = i
-            .compile(create_runtime().unwrap())
-            .map_err(|e| e.to_string())?;
+        i.compile(rt).map_err(|e| e.to_string())
+    }
 
-        self.roto_compiled = Some(Arc::new(Mutex::new(c)));
-        Ok(())
+    /// Returns how often to check the Roto script for changes, if at all.
+    pub fn roto_watch_interval(&self) -> Option<Duration> {
+        self.roto_script.as_ref()?;
+        self.roto_reload.watch_interval()
+    }
+
+    /// Recompiles the Roto script if its files have changed.
+    ///
+    /// If the changed script does not compile, the error is logged and the
+    /// current script stays in use until the files change again.
+    pub fn reload_changed_roto_script(&mut self) {
+        let Some(path) = self.roto_script.clone() else {
+            return;
+        };
+        let files = ScriptFiles::read(&path);
+        if files == self.roto_script_files {
+            return;
+        }
+        info!("Roto script {} changed, reloading", path.display());
+        match self.compile_roto_script(&Some(path)) {
+            Ok(()) => info!("Done reloading roto scripts"),
+            Err(err) => {
+                error!(
+                    "Cannot reload roto scripts: {err}. Keeping the \
+                    previous version"
+                );
+                self.roto_script_files = files;
+            }
+        }
     }
 
     /// Spawns all units and targets in the config into the given runtime.
@@ -918,13 +1408,16 @@ impl Manager {
     /// # Hot reloading
     ///
     /// Running units and targets that do not exist in the config by the same
-    /// name, or exist by the same name but have a different type, will be
-    /// terminated.
+    /// name will be terminated.
     ///
-    /// Running units and targets with the same name and type as in the config
-    /// will be signalled to reconfigure themselves per the new config (even
-    /// if unchanged, it is the responsibility of the unit/target to decide
-    /// how to react to the "new" config).
+    /// Running units and targets that are not affected by the changes to the
+    /// config, i.e. whose section is unchanged and that do not link to or
+    /// from a unit that is affected, keep running as they are. The others
+    /// with the same name and type as in the config will be signalled to
+    /// reconfigure themselves per the new config if they can, see
+    /// [`Unit::reconfigures_in_place`] and
+    /// [`Target::reconfigures_in_place`]. Otherwise, or if their type
+    /// changed, they are terminated and, once finished, started anew.
     ///
     /// Units receive the reconfigure signal via their gate. The gate will
     /// automatically update itself and its clones to use the new set of
@@ -936,13 +1429,17 @@ impl Manager {
     /// responsibility of the unit/target to switch from the old links to the
     /// new links and, if desired, to drain old link queues before ceasing to
     /// query them further.
-    pub fn spawn(&mut self, config: &mut Config) {
+    pub fn spawn(&mut self, config: &mut Config) -> SpawnSummary {
+        self.external_data.start(self.http_client.clone());
+        self.roto_state.start();
         self.spawn_internal(
             config,
             Self::spawn_unit,
             Self::spawn_target,
             Self::reconfigure_unit,
             Self::reconfigure_target,
+            Self::restart_unit,
+            Self::restart_target,
             Self::terminate_unit,
             Self::terminate_target,
         )
@@ -950,7 +1447,9 @@ impl Manager {
 
     /// Separated out from [spawn](Self::spawn) for testing purposes.
     ///
-    /// Pass the new unit to the existing unit to reconfigure itself. If the
+    /// Only the running units and targets affected by the changes to the
+    /// config are touched, see [`ComponentSections::diff`]. For those, pass
+    /// the new unit to the existing unit to reconfigure itself. If the
     /// set of downstream units and targets that refer to the unit being
     /// reconfigured have changed, we need to ensure that the gates and links
     /// in use correspond to the newly configured topology. For example:
@@ -1064,6 +1563,8 @@ impl Manager {
         SpawnTarget,
         ReconfUnit,
         ReconfTarget,
+        RestartUnit,
+        RestartTarget,
         TermUnit,
         TermTarget,
     >(
@@ -1073,14 +1574,25 @@ impl Manager {
         spawn_target: SpawnTarget,
         reconfigure_unit: ReconfUnit,
         reconfigure_target: ReconfTarget,
+        restart_unit: RestartUnit,
+        restart_target: RestartTarget,
         terminate_unit: TermUnit,
         terminate_target: TermTarget,
-    ) where
+    ) -> SpawnSummary
+    where
         SpawnUnit: Fn(Component, Unit, Gate, WaitPoint),
         SpawnTarget:
             Fn(Component, Target, Receiver<TargetCommand>, WaitPoint),
         ReconfUnit: Fn(&str, GateAgent, Unit, Gate),
         ReconfTarget: Fn(&str, Sender<TargetCommand>, Target),
+        RestartUnit: Fn(Arc<GateAgent>, Component, Unit, Gate, WaitPoint),
+        RestartTarget: Fn(
+            Arc<Sender<TargetCommand>>,
+            Component,
+            Target,
+            Receiver<TargetCommand>,
+            WaitPoint,
+        ),
         TermUnit: Fn(&str, Arc<GateAgent>),
         TermTarget: Fn(&str, Arc<Sender<TargetCommand>>),
     {
@@ -1092,22 +1604,44 @@ impl Manager {
         // to terminate.
         let mut new_running_units = HashMap::new();
         let mut new_running_targets = HashMap::new();
+        let mut summary = SpawnSummary::default();
+
+        let diff = self.pending_sections.diff(&self.running_sections);
+        self.running_sections = std::mem::take(&mut self.pending_sections);
 
         let num_targets = config.targets.targets.len();
         let num_units = config.units.units.len();
         let coordinator = Coordinator::new(num_targets + num_units);
 
+        for name in config
+            .targets
+            .targets
+            .keys()
+            .chain(config.units.units.keys())
+        {
+            self.health
+                .get(name)
+                .set_config_hash(self.running_sections.config_hash(name));
+        }
+
         // Spawn, reconfigure and terminate targets according to the config
         for (name, new_target) in config.targets.targets.drain() {
+            let new_target_type = std::mem::discriminant(&new_target);
             if let Some(running_target) = self.running_targets.remove(&name) {
                 let (running_target_type, running_target_sender) =
                     running_target;
-                let new_target_type = std::mem::discriminant(&new_target);
-                if new_target_type != running_target_type {
-                    // Terminate the current target. The new one replacing it
-                    // will be spawned below.
-                    terminate_target(&name, running_target_sender.into());
-                } else {
+                if !diff.targets.contains(&name) {
+                    // Unaffected by the changes, keep it running as is.
+                    new_running_targets.insert(
+                        name,
+                        (running_target_type, running_target_sender),
+                    );
+                    summary.unchanged += 1;
+                    continue;
+                }
+                if new_target_type == running_target_type
+                    && new_target.reconfigures_in_place()
+                {
                     reconfigure_target(
                         &name,
                         running_target_sender.clone(),
@@ -1117,24 +1651,26 @@ impl Manager {
                         name,
                         (running_target_type, running_target_sender),
                     );
-                    // Skip spawning a new target.
+                    summary.reconfigured += 1;
                     continue;
                 }
+
+                // Replace the current target once it has terminated.
+                let (cmd_tx, cmd_rx) = mpsc::channel(100);
+                restart_target(
+                    running_target_sender.into(),
+                    self.component(&name, new_target.type_name()),
+                    new_target,
+                    cmd_rx,
+                    coordinator.clone().track(name.clone()),
+                );
+                new_running_targets.insert(name, (new_target_type, cmd_tx));
+                summary.restarted += 1;
+                continue;
             }
 
             // Spawn the new target
-            let component = Component::new(
-                name.clone(),
-                new_target.type_name(),
-                self.http_client.clone(),
-                self.metrics.clone(),
-                self.http_resources.clone(),
-                self.roto_compiled.clone(),
-                self.tracer.clone(),
-                self.ingresses.clone(),
-            );
-
-            let target_type = std::mem::discriminant(&new_target);
+            let component = self.component(&name, new_target.type_name());
             let (cmd_tx, cmd_rx) = mpsc::channel(100);
             spawn_target(
                 component,
@@ -1142,7 +1678,8 @@ impl Manager {
                 cmd_rx,
                 coordinator.clone().track(name.clone()),
             );
-            new_running_targets.insert(name, (target_type, cmd_tx));
+            new_running_targets.insert(name, (new_target_type, cmd_tx));
+            summary.started += 1;
         }
 
         // Spawn, reconfigure and terminate units according to the config
@@ -1159,35 +1696,57 @@ impl Manager {
                                 name
                             );
                             let running_unit_agent = running_unit.1;
+                            self.unpause(&name);
                             terminate_unit(&name, running_unit_agent.into());
+                            summary.stopped += 1;
                         } else {
                             error!(
                             "Unit '{}' is unused and will not be started.",
                             name
                         );
                         }
+                        self.health.remove(&name);
                         continue;
                     }
                 };
 
             new_gate.set_name(&name);
+            new_gate.set_pause(
+                self.pauses.entry(name.clone()).or_default().clone(),
+            );
+            let new_unit_type = std::mem::discriminant(&new_unit);
 
             // For the Unit that was created for configuration file section
             // [units.<name>], see if we already have a GateAgent for a Unit
             // by that name, i.e. a Unit by that name is already running.
             if let Some(running_unit) = self.running_units.remove(&name) {
-                // Yes, a Unit by that name is already running. Is it the same
-                // type? If so, command it to reconfigure itself to match the
-                // new Unit settings (if changed). Otherwise, command it to
-                // terminate as it will be replaced by a unit of the same name
-                // but different type.
+                // Yes, a Unit by that name is already running. If it is not
+                // affected by the changes, it keeps running with its current
+                // gate and the new one is dropped. Otherwise, if it is the
+                // same type and either can reconfigure itself or only needs
+                // to take over the new gate, command it to reconfigure
+                // itself to match the new Unit settings. Otherwise, replace
+                // it by a new unit once it has terminated.
                 let (running_unit_type, running_unit_agent) = running_unit;
-                let new_unit_type = std::mem::discriminant(&new_unit);
-                if new_unit_type != running_unit_type {
-                    // Terminate the current unit. The new one replacing it
-                    // will be launched below.
-                    terminate_unit(&name, running_unit_agent.into());
-                } else {
+                if !diff.units.contains(&name) {
+                    new_running_units.insert(
+                        name,
+                        (running_unit_type, running_unit_agent),
+                    );
+                    summary.unchanged += 1;
+                    continue;
+                }
+                let changed = diff
+                    .changed_settings
+                    .get(&name)
+                    .cloned()
+                    .unwrap_or_default();
+                if new_unit_type == running_unit_type
+                    && (new_unit.reconfigures_in_place(&changed)
+                        || !diff.modified_units.contains(&name))
+                {
+                    // A paused unit stays paused, it picks up the new
+                    // settings once resumed.
                     reconfigure_unit(
                         &name,
                         running_unit_agent,
@@ -1196,48 +1755,77 @@ impl Manager {
                     );
                     new_running_units
                         .insert(name, (new_unit_type, new_agent));
+                    summary.reconfigured += 1;
                     continue;
                 }
+
+                // The old unit would wait forever to pass on its next update
+                // and never terminate.
+                self.set_paused(&name, false);
+                restart_unit(
+                    running_unit_agent.into(),
+                    self.component(&name, new_unit.type_name()),
+                    new_unit,
+                    new_gate,
+                    coordinator.clone().track(name.clone()),
+                );
+                new_running_units.insert(name, (new_unit_type, new_agent));
+                summary.restarted += 1;
+                continue;
             }
 
             // Spawn the new unit
-            let component = Component::new(
-                name.clone(),
-                new_unit.type_name(),
-                self.http_client.clone(),
-                self.metrics.clone(),
-                self.http_resources.clone(),
-                self.roto_compiled.clone(),
-                self.tracer.clone(),
-                self.ingresses.clone(),
-            );
-
-            let unit_type = std::mem::discriminant(&new_unit);
+            let component = self.component(&name, new_unit.type_name());
             spawn_unit(
                 component,
                 new_unit,
                 new_gate,
                 coordinator.clone().track(name.clone()),
             );
-            new_running_units.insert(name, (unit_type, new_agent));
+            new_running_units.insert(name, (new_unit_type, new_agent));
+            summary.started += 1;
         }
 
         // Terminate running units whose corresponding configuration file
         // block was removed or commented out and thus not encountered above.
-        for (name, (_, agent)) in self.running_units.drain() {
+        for (name, (_, agent)) in std::mem::take(&mut self.running_units) {
+            self.unpause(&name);
             terminate_unit(&name, agent.into());
+            self.health.remove(&name);
+            summary.stopped += 1;
         }
 
         // Terminate running targets whose corresponding configuration file
         // block was removed or commented out and thus not encountered above.
         for (name, (_, cmd_tx)) in self.running_targets.drain() {
             terminate_target(&name, cmd_tx.into());
+            self.health.remove(&name);
+            summary.stopped += 1;
         }
 
         self.running_units = new_running_units;
         self.running_targets = new_running_targets;
 
         self.coordinate_and_track_startup(coordinator);
+        summary
+    }
+
+    /// Creates the component for a new unit or target.
+    fn component(&self, name: &str, type_name: &'static str) -> Component {
+        Component::new(
+            name.to_string(),
+            type_name,
+            self.http_client.clone(),
+            self.metrics.clone(),
+            self.http_resources.clone(),
+            self.roto.clone(),
+            self.tracer.clone(),
+            self.ingresses.clone(),
+            self.rtr_caches.clone(),
+            self.traffic_counters.clone(),
+            self.roto_state.clone(),
+            self.health.clone(),
+        )
     }
 
     fn coordinate_and_track_startup(
@@ -1253,6 +1841,9 @@ impl Manager {
             let report = UpstreamLinkReport::new();
             reports.add_gate(name.clone(), gate_agent.id());
             reports.add_link(name.clone(), report.clone());
+            if let Some(type_name) = self.running_sections.type_name(name) {
+                reports.add_type(name.clone(), type_name.into());
+            }
             let agent = gate_agent.clone();
             let name = name.clone();
             agent_cmd_futures.push(async move {
@@ -1269,6 +1860,9 @@ impl Manager {
         for (name, (_target_type, cmd_sender)) in &self.running_targets {
             let report = UpstreamLinkReport::new();
             reports.add_link(name.clone(), report.clone());
+            if let Some(type_name) = self.running_sections.type_name(name) {
+                reports.add_type(name.clone(), type_name.into());
+            }
 
             let sender = cmd_sender.clone();
             let name = name.clone();
@@ -1337,7 +1931,174 @@ impl Manager {
         self.graph_svg_data.load().0
     }
 
+    /// Carries out a request made via the administrative HTTP API.
+    ///
+    /// Reloading the config file is left to the caller, who knows where
+    /// to find it, so such requests are answered with an error.
+    pub fn process_admin_request(&mut self, request: AdminRequest) {
+        let res = match request.action().clone() {
+            AdminAction::Reload => {
+                Err(AdminError::Failed("Reloading is not available".into()))
+            }
+            AdminAction::PauseUnit(name) => self.pause_unit(&name),
+            AdminAction::ResumeUnit(name) => self.resume_unit(&name),
+            AdminAction::DrainTarget(name) => {
+                match self.drain_target(&name) {
+                    Ok(drained) => {
+                        crate::tokio::spawn("target-drainer", async move {
+                            request.reply(drained.await);
+                        });
+                        return;
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+        };
+        request.reply(res);
+    }
+
+    /// Pauses the unit with the given name.
+    ///
+    /// The updates the unit sends are held at its gate until it is resumed,
+    /// see [`Pause`].
+    pub fn pause_unit(&self, name: &str) -> Result<String, AdminError> {
+        if !self.running_units.contains_key(name) {
+            return Err(AdminError::NotFound(format!(
+                "No running unit named '{name}'"
+            )));
+        }
+        if self.set_paused(name, true) {
+            info!("Paused unit '{}'", name);
+            Ok(format!("Unit '{name}' paused"))
+        } else {
+            Ok(format!("Unit '{name}' was already paused"))
+        }
+    }
+
+    /// Resumes the paused unit with the given name.
+    pub fn resume_unit(&self, name: &str) -> Result<String, AdminError> {
+        if !self.running_units.contains_key(name) {
+            return Err(AdminError::NotFound(format!(
+                "No running unit named '{name}'"
+            )));
+        }
+        if self.set_paused(name, false) {
+            info!("Resumed unit '{}'", name);
+            Ok(format!("Unit '{name}' resumed"))
+        } else {
+            Ok(format!("Unit '{name}' was not paused"))
+        }
+    }
+
+    /// Drains the target with the given name and stops it.
+    ///
+    /// The gates of the units the target is linked to stop sending it
+    /// updates. Once it has received those already queued for it, or after
+    /// [`DRAIN_TIMEOUT`](Self::DRAIN_TIMEOUT), it is told to terminate,
+    /// upon which targets flush what they buffered.
+    ///
+    /// The target is no longer considered running, so it is started again
+    /// when the config file is reloaded. Returns a future that resolves
+    /// once the target has stopped.
+    pub fn drain_target(
+        &mut self,
+        name: &str,
+    ) -> Result<impl Future<Output = Result<String, AdminError>>, AdminError>
+    {
+        let Some((_, sender)) = self.running_targets.remove(name) else {
+            return Err(AdminError::NotFound(format!(
+                "No running target named '{name}'"
+            )));
+        };
+        let links: Vec<_> = self
+            .graph_svg_data
+            .load()
+            .1
+            .links
+            .get(name)
+            .map(UpstreamLinkReport::into_vec)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|link| {
+                let slot = link.connected_gate_slot?;
+                let (_, agent) = self
+                    .running_units
+                    .values()
+                    .find(|(_, agent)| agent.id() == link.gate_id)?;
+                Some((agent.clone(), slot, link.metrics))
+            })
+            .collect();
+
+        info!("Draining target '{}'", name);
+        self.health.get(name).set_state(ComponentState::Draining);
+        let name = name.to_string();
+        Ok(async move {
+            for (agent, slot, _) in &links {
+                if let Err(err) = agent.suspend_link(*slot).await {
+                    warn!(
+                        "Cannot stop updates to target '{}': {}",
+                        name, err
+                    );
+                }
+            }
+            let queued = || {
+                links
+                    .iter()
+                    .filter_map(|(_, _, metrics)| metrics.queue())
+                    .map(|(len, _)| len)
+                    .sum::<usize>()
+            };
+            let deadline = Instant::now() + Self::DRAIN_TIMEOUT;
+            while queued() > 0 && Instant::now() < deadline {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            let left = queued();
+
+            info!("Stopping target '{}'", name);
+            let _ = sender.send(TargetCommand::Terminate).await;
+            sender.closed().await;
+            if left == 0 {
+                Ok(format!("Target '{name}' drained and stopped"))
+            } else {
+                warn!(
+                    "Target '{}' stopped with {} updates not received",
+                    name, left
+                );
+                Ok(format!(
+                    "Target '{name}' stopped with {left} updates not received"
+                ))
+            }
+        })
+    }
+
+    /// Pauses or resumes a running unit.
+    ///
+    /// Returns whether the unit wasn't already paused or resumed.
+    fn set_paused(&self, name: &str, paused: bool) -> bool {
+        let Some(pause) = self.pauses.get(name) else {
+            return false;
+        };
+        let changed = if paused {
+            pause.pause()
+        } else {
+            pause.resume()
+        };
+        self.health.get(name).set_paused(paused);
+        changed
+    }
+
+    /// Resumes a unit that is being stopped and forgets its pause.
+    fn unpause(&mut self, name: &str) {
+        self.set_paused(name, false);
+        self.pauses.remove(name);
+    }
+
     pub fn terminate(&mut self) {
+        // Paused units would wait forever to pass on their next update.
+        for pause in self.pauses.values() {
+            pause.resume();
+        }
+
         for (name, (_, agent)) in self.running_units.drain() {
             let agent = Arc::new(agent);
             Self::terminate_unit(&name, agent.clone());
@@ -1353,18 +2114,27 @@ impl Manager {
                 std::thread::sleep(Duration::from_millis(10));
             }
         }
+
+        self.roto_state.save();
     }
 
     fn spawn_unit(
         component: Component,
         new_unit: Unit,
         new_gate: Gate,
-        waitpoint: WaitPoint,
+        mut waitpoint: WaitPoint,
     ) {
         info!("Starting unit '{}'", component.name);
+        let health = component.health();
+        let generation =
+            health.started(component.type_name(), Some(new_gate.metrics()));
+        waitpoint.set_health(health.clone());
         crate::tokio::spawn(
             &format!("unit[{}]", component.name),
-            new_unit.run(component, new_gate, waitpoint),
+            async move {
+                new_unit.run(component, new_gate, waitpoint).await;
+                health.stopped(generation);
+            },
         );
     }
 
@@ -1372,12 +2142,18 @@ impl Manager {
         component: Component,
         new_target: Target,
         cmd_rx: Receiver<TargetCommand>,
-        waitpoint: WaitPoint,
+        mut waitpoint: WaitPoint,
     ) {
         info!("Starting target '{}'", component.name);
+        let health = component.health();
+        let generation = health.started(component.type_name(), None);
+        waitpoint.set_health(health.clone());
         crate::tokio::spawn(
             &format!("target[{}]", component.name),
-            new_target.run(component, cmd_rx, waitpoint),
+            async move {
+                let _ = new_target.run(component, cmd_rx, waitpoint).await;
+                health.stopped(generation);
+            },
         );
     }
 
@@ -1418,6 +2194,40 @@ impl Manager {
         });
     }
 
+    fn restart_unit(
+        agent: Arc<GateAgent>,
+        component: Component,
+        new_unit: Unit,
+        new_gate: Gate,
+        waitpoint: WaitPoint,
+    ) {
+        info!("Restarting unit '{}'", component.name);
+        crate::tokio::spawn("unit-restarter", async move {
+            // The new unit may need the resources of the current one, such
+            // as its listen address, so wait for it to finish first.
+            agent.terminate().await;
+            while !agent.is_terminated() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Self::spawn_unit(component, new_unit, new_gate, waitpoint);
+        });
+    }
+
+    fn restart_target(
+        sender: Arc<Sender<TargetCommand>>,
+        component: Component,
+        new_target: Target,
+        cmd_rx: Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) {
+        info!("Restarting target '{}'", component.name);
+        crate::tokio::spawn("target-restarter", async move {
+            let _ = sender.send(TargetCommand::Terminate).await;
+            sender.closed().await;
+            Self::spawn_target(component, new_target, cmd_rx, waitpoint);
+        });
+    }
+
     fn terminate_unit(name: &str, agent: Arc<GateAgent>) {
         info!("Stopping unit '{}'", name);
         crate::tokio::spawn("unit-terminator", async move {
@@ -1449,13 +2259,22 @@ impl Manager {
     ) -> (Arc<dyn ProcessRequest>, &'static str) {
         const REL_BASE_URL: &str = "/status/graph";
 
-        let processor = Arc::new(move |request: &Request<_>| {
+        let processor = move |request: &Request<Body>| {
             let req_path = request.uri().decoded_path();
             if request.method() == Method::GET
                 && req_path.starts_with(REL_BASE_URL)
             {
                 let (_base_path, restant) =
                     req_path.split_at(REL_BASE_URL.len());
+                if restant.is_empty() {
+                    let response = Self::graph_response(
+                        request,
+                        &graph_svg_data.load().1,
+                    );
+                    if response.is_some() {
+                        return response;
+                    }
+                }
                 let trace_id = if restant.contains("/traces/") {
                     restant.split_at("/traces/".len()).1.parse::<u8>().ok()
                 } else {
@@ -1530,17 +2349,86 @@ impl Manager {
             } else {
                 None
             }
-        });
+        };
+        let processor = Arc::new(Described::new(processor, |paths| {
+            paths
+                .get(REL_BASE_URL, "The graph of the units and targets")
+                .query("format", Schema::String, "html, json, dot or mermaid")
+                .description(
+                    "The units and targets with their status and the links \
+                    between them with the number of updates sent and the \
+                    updates queued. Without a format, clients accepting \
+                    HTML get a page rendering the graph as SVG, others \
+                    JSON.",
+                );
+            paths
+                .get(
+                    format!("{REL_BASE_URL}/traces/{{trace_id}}"),
+                    "The graph with the path of a traced message",
+                )
+                .path_param("trace_id", "The id of the trace")
+                .produces("text/html");
+        }));
 
         (processor, REL_BASE_URL)
     }
 
+    /// Produces the graph in the format asked for, unless that is HTML.
+    fn graph_response(
+        request: &Request<Body>,
+        report: &LinkReport,
+    ) -> Option<Response<Body>> {
+        let params = http::extract_params(request);
+        let format = match http::get_param(&params, "format") {
+            Some(format) => format.value().to_string(),
+            None => {
+                let accepts_html = request
+                    .headers()
+                    .get(hyper::header::ACCEPT)
+                    .and_then(|accept| accept.to_str().ok())
+                    .is_some_and(|accept| accept.contains("text/html"));
+                if accepts_html {
+                    return None;
+                }
+                "json".to_string()
+            }
+        };
+        let graph = report.graph();
+        let (content_type, body) = match format.as_str() {
+            "html" => return None,
+            "json" => (
+                "application/json",
+                serde_json::to_string_pretty(&graph).unwrap(),
+            ),
+            "dot" => ("text/vnd.graphviz", graph.to_dot()),
+            "mermaid" => ("text/plain", graph.to_mermaid()),
+            other => {
+                return Some(
+                    Response::builder()
+                        .status(hyper::StatusCode::BAD_REQUEST)
+                        .header("Content-Type", "text/plain")
+                        .body(Body::from(format!(
+                            "Unknown format '{other}', expected html, json, \
+                            dot or mermaid"
+                        )))
+                        .unwrap(),
+                )
+            }
+        };
+        Some(
+            Response::builder()
+                .header("Content-Type", content_type)
+                .body(Body::from(body))
+                .unwrap(),
+        )
+    }
+
     fn mk_tracer_http_processor(
         tracer: Arc<Tracer>,
     ) -> (Arc<dyn ProcessRequest>, &'static str) {
         const REL_BASE_URL: &str = "/status/traces";
 
-        let processor = Arc::new(move |request: &Request<_>| {
+        let processor = move |request: &Request<Body>| {
             let req_path = request.uri().decoded_path();
             if request.method() == Method::GET && req_path == REL_BASE_URL {
                 let response = Response::builder()
@@ -1553,18 +2441,174 @@ impl Manager {
             } else {
                 None
             }
-        });
+        };
+        let processor = Arc::new(Described::new(processor, |paths| {
+            paths
+                .get(REL_BASE_URL, "The messages traced")
+                .produces("text/plain");
+        }));
+
+        (processor, REL_BASE_URL)
+    }
+
+    fn mk_health_http_processor(
+        health: Arc<HealthRegister>,
+        graph_svg_data: Arc<ArcSwap<(Instant, LinkReport)>>,
+    ) -> (Arc<dyn ProcessRequest>, &'static str) {
+        const REL_BASE_URL: &str = "/status/units";
+        const READY_URL: &str = "/status/ready";
+
+        let processor = move |request: &Request<Body>| {
+            if request.method() != Method::GET {
+                return None;
+            }
+            let req_path = request.uri().decoded_path();
+            let link_report = graph_svg_data.load();
+            let report = |name: &str, health: &ComponentHealth| {
+                let (received, backlogged) = link_report.1.incoming(name);
+                health.report(name, received, backlogged)
+            };
+            let (status, body) = if req_path == REL_BASE_URL {
+                let reports: Vec<_> = health
+                    .all()
+                    .iter()
+                    .map(|(name, health)| report(name, health))
+                    .collect();
+                (
+                    hyper::StatusCode::OK,
+                    serde_json::to_string_pretty(&reports).unwrap(),
+                )
+            } else if req_path == READY_URL {
+                // Not ready before any components were started, so that
+                // load balancers don't send traffic too early.
+                let all = health.all();
+                let not_ready: Vec<_> = all
+                    .iter()
+                    .map(|(name, health)| report(name, health))
+                    .filter(|report| !report.state().is_ready())
+                    .map(|report| report.name().to_string())
+                    .collect();
+                let ready = !all.is_empty() && not_ready.is_empty();
+                (
+                    if ready {
+                        hyper::StatusCode::OK
+                    } else {
+                        hyper::StatusCode::SERVICE_UNAVAILABLE
+                    },
+                    serde_json::json!({
+                        "ready": ready,
+                        "not_ready": not_ready,
+                    })
+                    .to_string(),
+                )
+            } else {
+                let name =
+                    req_path.strip_prefix(REL_BASE_URL)?.strip_prefix('/')?;
+                match health.find(name) {
+                    Some(health) => (
+                        hyper::StatusCode::OK,
+                        serde_json::to_string_pretty(&report(name, &health))
+                            .unwrap(),
+                    ),
+                    None => {
+                        return Some(
+                            Response::builder()
+                                .status(hyper::StatusCode::NOT_FOUND)
+                                .header("Content-Type", "text/plain")
+                                .body(Body::from(format!(
+                                    "No unit or target named '{name}'"
+                                )))
+                                .unwrap(),
+                        )
+                    }
+                }
+            };
+            Some(
+                Response::builder()
+                    .status(status)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+        };
+        let processor = Arc::new(Described::new(processor, |paths| {
+            paths
+                .get(REL_BASE_URL, "The health of all units and targets")
+                .description(
+                    "A list of the health reports of the units and \
+                    targets, see below.",
+                );
+            paths
+                .get(
+                    format!("{REL_BASE_URL}/{{name}}"),
+                    "The health of a unit or target",
+                )
+                .path_param("name", "The name of the unit or target")
+                .description(
+                    "The state of the unit or target (starting, \
+                    connecting, running, degraded, retrying, paused, \
+                    draining or stopped), its last error, uptime, the number of updates it sent \
+                    or received and the hash of its configuration.",
+                );
+            paths
+                .get(READY_URL, "Whether all units and targets are running")
+                .description(
+                    "Responds with status 200 if all units and targets are \
+                    running, possibly degraded, and 503 otherwise, for use \
+                    as a readiness probe. The body lists those not ready.",
+                );
+        }));
 
         (processor, REL_BASE_URL)
     }
 }
 
+//------------ SpawnSummary --------------------------------------------------
+
+/// What spawning a config did to the units and targets.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SpawnSummary {
+    /// The number of units and targets started.
+    pub started: usize,
+
+    /// The number of running units and targets that reconfigured
+    /// themselves.
+    pub reconfigured: usize,
+
+    /// The number of running units and targets replaced by new ones.
+    pub restarted: usize,
+
+    /// The number of running units and targets stopped.
+    pub stopped: usize,
+
+    /// The number of running units and targets left as they were.
+    pub unchanged: usize,
+}
+
+impl Display for SpawnSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} started, {} reconfigured, {} restarted, {} stopped, \
+            {} unchanged",
+            self.started,
+            self.reconfigured,
+            self.restarted,
+            self.stopped,
+            self.unchanged
+        )
+    }
+}
+
 //------------ Checkpoint ----------------------------------------------------
 
 pub struct WaitPoint {
     coordinator: Arc<Coordinator>,
     name: String,
     ready: bool,
+
+    /// The health of the component, marked as running when it is.
+    health: Option<Arc<ComponentHealth>>,
 }
 
 impl WaitPoint {
@@ -1573,9 +2617,14 @@ impl WaitPoint {
             coordinator,
             name,
             ready: false,
+            health: None,
         }
     }
 
+    pub(crate) fn set_health(&mut self, health: Arc<ComponentHealth>) {
+        self.health = Some(health);
+    }
+
     pub async fn ready(&mut self) {
         self.coordinator.clone().ready(&self.name).await;
         self.ready = true;
@@ -1589,6 +2638,9 @@ impl WaitPoint {
         if !self.ready {
             self.ready().await;
         }
+        if let Some(health) = &self.health {
+            health.ready();
+        }
         self.coordinator.ready(&self.name).await
     }
 }
@@ -1800,22 +2852,35 @@ pub fn load_link(link_id: Marked<String>) -> Link {
 
         let mark = link_id.mark(());
         let link_id = link_id.into_inner();
-        let (name, queue_size) = get_queue_size_for_link(link_id);
+        let (name, queue_size, routes) = parse_link_id(link_id);
         let unit = gates
             .entry(name)
             .or_insert_with(|| LoadUnit::new(queue_size));
         unit.links.push(mark);
-        unit.agent.create_link()
+        unit.agent.create_link().with_routes(routes)
     })
 }
 
 /// Support link names of the form <name>:<queue_size> where queue_size is an
-/// unsigned integer value.
+/// unsigned integer value, optionally followed by #<route>,<route>,... to
+/// only receive the updates the unit sends for those routes.
 ///
 /// TODO: Don't overload the meaning of the link name, instead support a
 /// richer more meaningful configuration syntax for configuring the queue
 /// size.
-fn get_queue_size_for_link(link_id: String) -> (String, usize) {
+fn parse_link_id(link_id: String) -> (String, usize, Vec<Arc<str>>) {
+    let (link_id, routes) = match link_id.split_once('#') {
+        Some((link_id, routes)) => (
+            link_id.to_string(),
+            routes
+                .split(',')
+                .map(str::trim)
+                .filter(|route| !route.is_empty())
+                .map(Into::into)
+                .collect(),
+        ),
+        None => (link_id, Vec::new()),
+    };
     let (name, queue_size) =
         if let Some((name, options)) = link_id.split_once(':') {
             let queue_len = options.parse::<usize>().unwrap_or_else(|err| {
@@ -1830,7 +2895,7 @@ fn get_queue_size_for_link(link_id: String) -> (String, usize) {
         } else {
             (link_id, DEF_UPDATE_QUEUE_LEN)
         };
-    (name, queue_size)
+    (name, queue_size, routes)
 }
 
 //------------ Loading FilterName --------------------------------------------
@@ -2588,6 +3653,61 @@ mod tests {
         assert_eq!("[0-1, 3]", trace_txt);
     }
 
+    #[test]
+    fn link_reports_should_render_as_graphs() {
+        let (_gate, mut agent) = Gate::new(10);
+        let link = agent.create_link();
+
+        let mut reports = LinkReport::new();
+        let unit_report = UpstreamLinkReport::new();
+        unit_report.declare_source();
+        reports.add_gate("bmp-in".into(), agent.id());
+        reports.add_link("bmp-in".into(), unit_report);
+        reports.add_type("bmp-in".into(), "bmp-tcp-in".into());
+        let target_report = UpstreamLinkReport::new();
+        target_report.set_source(&link);
+        reports.add_link("null".into(), target_report);
+        reports.add_type("null".into(), "null-out".into());
+        let graph = reports.graph();
+
+        assert_eq!(
+            serde_json::to_value(&graph).unwrap(),
+            serde_json::json!({
+                "components": [
+                    {
+                        "name": "bmp-in", "kind": "unit",
+                        "type": "bmp-tcp-in", "status": null, "okay": null,
+                    },
+                    {
+                        "name": "null", "kind": "target",
+                        "type": "null-out", "status": null, "okay": null,
+                    },
+                ],
+                "links": [
+                    {
+                        "from": "bmp-in", "to": "null", "type": "queued",
+                        "connected": false, "num_updates": 0,
+                        "queue_len": null, "queue_capacity": null,
+                    },
+                ],
+            })
+        );
+        assert!(graph.to_dot().contains(
+            "  \"bmp-in\" [shape=box, color=black, \
+            label=\"bmp-in\\nbmp-tcp-in\"];\n"
+        ));
+        assert!(graph
+            .to_dot()
+            .contains("  \"bmp-in\" -> \"null\" [label=\"0 updates\"];\n"));
+        assert_eq!(
+            graph.to_mermaid(),
+            "flowchart LR\n  \
+            c0[\"bmp-in<br/>bmp-tcp-in\"]\n  \
+            c1([\"null<br/>null-out\"])\n  \
+            c0 -->|\"0 updates\"| c1\n"
+        );
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn unused_unit_should_not_be_spawned() -> Result<(), Terminate> {
         // given a config with only a single target with a link to a missing unit
@@ -2622,6 +3742,32 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unused_units_should_be_reported() -> Result<(), Terminate> {
+        let toml = r#"
+        http_listen = []
+
+        [units.unused-unit]
+        type = "bmp-tcp-in"
+        listen = "1.2.3.4:12345"
+
+        [units.some-unit]
+        type = "bmp-tcp-in"
+        listen = "1.2.3.4:12345"
+
+        [targets.null]
+        type = "null-out"
+        source = "some-unit"
+        "#;
+        let config_file = mk_config_from_toml(toml);
+
+        let mut manager = init_manager();
+        let (_source, config) =
+            Config::from_config_file(config_file, &mut manager)?;
+        assert_eq!(manager.unused_units(&config), ["unused-unit"]);
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn added_target_should_be_spawned() -> Result<(), Terminate> {
         // given a config with only a single target with a link to a single unit
@@ -2738,12 +3884,11 @@ mod tests {
             Config::from_config_file(config_file, &mut manager)?;
         spawn(&mut manager, config);
 
-        // then it should terminate the removed target
+        // then it should terminate the removed target only, as the others
+        // are not affected by the change
         let log = SPAWN_LOG.with(|log| log.take());
-        assert_eq!(log.len(), 3);
-        assert_log_contains(&log, "some-unit", SpawnAction::ReconfigureUnit);
+        assert_eq!(log.len(), 1);
         assert_log_contains(&log, "null", SpawnAction::TerminateTarget);
-        assert_log_contains(&log, "null2", SpawnAction::ReconfigureTarget);
 
         // Note: we don't check that the gate of some-unit has been updated to
         // remove the Sender for the Link to target null because that is logic
@@ -2819,6 +3964,340 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unaffected_components_should_keep_running(
+    ) -> Result<(), Terminate> {
+        // given two independent pipelines
+        let toml = r#"
+        http_listen = []
+
+        [units.unit-a]
+        type = "bmp-tcp-in"
+        listen = "1.2.3.4:12345"
+
+        [units.unit-b]
+        type = "bmp-tcp-in"
+        listen = "1.2.3.4:12346"
+
+        [targets.null-a]
+        type = "null-out"
+        source = "unit-a"
+
+        [targets.null-b]
+        type = "null-out"
+        source = "unit-b"
+        "#;
+        let mut manager = init_manager();
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(toml),
+            &mut manager,
+        )?;
+        spawn(&mut manager, config);
+
+        // when the config is reloaded unchanged
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(toml),
+            &mut manager,
+        )?;
+        let summary = spawn(&mut manager, config);
+
+        // then nothing should happen
+        assert!(SPAWN_LOG.with(|log| log.take()).is_empty());
+        assert_eq!(summary.unchanged, 4);
+
+        // when a unit of one pipeline is modified
+        let modified = toml.replace("12346", "12347");
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(&modified),
+            &mut manager,
+        )?;
+        let summary = spawn(&mut manager, config);
+
+        // then only that pipeline should be reconfigured
+        let log = SPAWN_LOG.with(|log| log.take());
+        assert_eq!(log.len(), 2);
+        assert_log_contains(&log, "unit-b", SpawnAction::ReconfigureUnit);
+        assert_log_contains(&log, "null-b", SpawnAction::ReconfigureTarget);
+        assert_eq!(
+            summary,
+            SpawnSummary {
+                reconfigured: 2,
+                unchanged: 2,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn components_that_cannot_reconfigure_should_be_restarted(
+    ) -> Result<(), Terminate> {
+        // given a target that cannot apply a changed config itself
+        let toml = r#"
+        http_listen = []
+
+        [units.some-unit]
+        type = "bmp-tcp-in"
+        listen = "1.2.3.4:12345"
+
+        [targets.sse]
+        type = "sse-out"
+        sources = "some-unit"
+        "#;
+        let mut manager = init_manager();
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(toml),
+            &mut manager,
+        )?;
+        spawn(&mut manager, config);
+
+        // when its upstream unit is modified
+        let modified = toml.replace("12345", "12346");
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(&modified),
+            &mut manager,
+        )?;
+        spawn(&mut manager, config);
+
+        // then the unit should reconfigure itself and the target, which
+        // needs the new link to it, be restarted
+        let log = SPAWN_LOG.with(|log| log.take());
+        assert_eq!(log.len(), 2);
+        assert_log_contains(&log, "some-unit", SpawnAction::ReconfigureUnit);
+        assert_log_contains(&log, "sse", SpawnAction::RestartTarget);
+
+        // when the type of the unit changes
+        let modified = r#"
+        http_listen = []
+
+        [units.some-unit]
+        type = "bgp-tcp-in"
+        listen = "1.2.3.4:12345"
+        my_asn = 64512
+        my_bgp_id = [1, 2, 3, 4]
+
+        [targets.sse]
+        type = "sse-out"
+        sources = "some-unit"
+        "#;
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(modified),
+            &mut manager,
+        )?;
+        spawn(&mut manager, config);
+
+        // then it should be replaced
+        let log = SPAWN_LOG.with(|log| log.take());
+        assert_eq!(log.len(), 2);
+        assert_log_contains(&log, "some-unit", SpawnAction::RestartUnit);
+        assert_log_contains(&log, "sse", SpawnAction::RestartTarget);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rib_units_should_restart_for_settings_they_cannot_apply(
+    ) -> Result<(), Terminate> {
+        // given a RIB unit
+        let toml = r#"
+        http_listen = []
+
+        [units.some-unit]
+        type = "bmp-tcp-in"
+        listen = "1.2.3.4:12345"
+
+        [units.rib]
+        type = "rib"
+        sources = ["some-unit"]
+
+        [targets.null]
+        type = "null-out"
+        source = "rib"
+        "#;
+        let mut manager = init_manager();
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(toml),
+            &mut manager,
+        )?;
+        spawn(&mut manager, config);
+        SPAWN_LOG.with(|log| log.take());
+
+        // when a setting it applies itself changes
+        let modified = toml.replace(
+            "sources = [\"some-unit\"]",
+            "sources = [\"some-unit\"]\n\
+            peer_down = { action = \"stale\" }",
+        );
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(&modified),
+            &mut manager,
+        )?;
+        spawn(&mut manager, config);
+
+        // then it should reconfigure itself
+        let log = SPAWN_LOG.with(|log| log.take());
+        assert_log_contains(&log, "rib", SpawnAction::ReconfigureUnit);
+
+        // when a setting it cannot apply itself changes as well
+        let modified = modified.replace(
+            "sources = [\"some-unit\"]",
+            "sources = [\"some-unit\"]\n\
+            named_ribs = [\"other\"]",
+        );
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(&modified),
+            &mut manager,
+        )?;
+        spawn(&mut manager, config);
+
+        // then it should be restarted
+        let log = SPAWN_LOG.with(|log| log.take());
+        assert_log_contains(&log, "rib", SpawnAction::RestartUnit);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn health_should_follow_the_config() -> Result<(), Terminate> {
+        // given a unit and a target
+        let toml = r#"
+        http_listen = []
+
+        [units.some-unit]
+        type = "bmp-tcp-in"
+        listen = "1.2.3.4:12345"
+
+        [targets.null]
+        type = "null-out"
+        source = "some-unit"
+        "#;
+        let mut manager = init_manager();
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(toml),
+            &mut manager,
+        )?;
+        spawn(&mut manager, config);
+
+        // then both should have the hash of their config
+        let hash = |manager: &Manager, name: &str| {
+            manager
+                .health
+                .find(name)
+                .map(|health| health.report(name, None, false))
+                .map(|report| serde_json::to_value(report).unwrap())
+                .map(|report| report["config_hash"].clone())
+        };
+        let unit_hash = hash(&manager, "some-unit").unwrap();
+        assert!(unit_hash.is_string());
+        assert!(hash(&manager, "null").unwrap().is_string());
+
+        // when the unit is modified and the target removed
+        let modified = r#"
+        http_listen = []
+
+        [units.some-unit]
+        type = "bmp-tcp-in"
+        listen = "1.2.3.4:12346"
+
+        [targets.sse]
+        type = "sse-out"
+        sources = "some-unit"
+        "#;
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(modified),
+            &mut manager,
+        )?;
+        spawn(&mut manager, config);
+
+        // then the unit should have a new hash and the target be forgotten
+        assert_ne!(hash(&manager, "some-unit").unwrap(), unit_hash);
+        assert!(hash(&manager, "sse").is_some());
+        assert!(hash(&manager, "null").is_none());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn units_should_pause_and_resume() -> Result<(), Terminate> {
+        // given a running unit
+        let toml = r#"
+        http_listen = []
+
+        [units.some-unit]
+        type = "bmp-tcp-in"
+        listen = "1.2.3.4:12345"
+
+        [targets.null]
+        type = "null-out"
+        source = "some-unit"
+        "#;
+        let mut manager = init_manager();
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(toml),
+            &mut manager,
+        )?;
+        spawn(&mut manager, config);
+        let paused =
+            |manager: &Manager| manager.pauses["some-unit"].is_paused();
+        let state = |manager: &Manager| {
+            manager
+                .health
+                .get("some-unit")
+                .report("", None, false)
+                .state()
+        };
+
+        // when it is paused
+        assert_eq!(
+            manager.pause_unit("some-unit"),
+            Ok("Unit 'some-unit' paused".to_string())
+        );
+
+        // then it should be paused, once
+        assert!(paused(&manager));
+        assert_eq!(state(&manager), ComponentState::Paused);
+        assert_eq!(
+            manager.pause_unit("some-unit"),
+            Ok("Unit 'some-unit' was already paused".to_string())
+        );
+
+        // when it is resumed
+        assert_eq!(
+            manager.resume_unit("some-unit"),
+            Ok("Unit 'some-unit' resumed".to_string())
+        );
+
+        // then it should no longer be paused
+        assert!(!paused(&manager));
+        assert_ne!(state(&manager), ComponentState::Paused);
+
+        // when it is paused and then reconfigured
+        manager.pause_unit("some-unit").unwrap();
+        let modified = toml.replace("12345", "12346");
+        let (_source, config) = Config::from_config_file(
+            mk_config_from_toml(&modified),
+            &mut manager,
+        )?;
+        spawn(&mut manager, config);
+
+        // then it should still be paused
+        assert!(paused(&manager));
+        assert_eq!(state(&manager), ComponentState::Paused);
+        assert_eq!(
+            manager.resume_unit("some-unit"),
+            Ok("Unit 'some-unit' resumed".to_string())
+        );
+
+        // and units and targets that aren't running cannot be paused or
+        // drained
+        assert!(matches!(
+            manager.pause_unit("null"),
+            Err(AdminError::NotFound(_))
+        ));
+        assert!(matches!(
+            manager.drain_target("some-unit"),
+            Err(AdminError::NotFound(_))
+        ));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn coordinator_with_no_components_should_finish_immediately() {
         let coordinator = Coordinator::new(0);
@@ -2970,6 +4449,8 @@ mod tests {
         SpawnTarget,
         ReconfigureUnit,
         ReconfigureTarget,
+        RestartUnit,
+        RestartTarget,
         TerminateUnit,
         TerminateTarget,
     }
@@ -2985,6 +4466,8 @@ mod tests {
                 SpawnAction::ReconfigureTarget => {
                     f.write_str("ReconfigureTarget")
                 }
+                SpawnAction::RestartUnit => f.write_str("RestartUnit"),
+                SpawnAction::RestartTarget => f.write_str("RestartTarget"),
                 SpawnAction::TerminateUnit => f.write_str("TerminateUnit"),
                 SpawnAction::TerminateTarget => {
                     f.write_str("TerminateTarget")
@@ -2993,6 +4476,7 @@ mod tests {
         }
     }
 
+    #[allow(clippy::large_enum_variant)]
     #[derive(Debug)]
     enum UnitOrTargetConfig {
         None,
@@ -3124,6 +4608,34 @@ mod tests {
         );
     }
 
+    fn restart_unit(
+        _: Arc<GateAgent>,
+        c: Component,
+        u: Unit,
+        _: Gate,
+        _: WaitPoint,
+    ) {
+        log_spawn_action(
+            c.name.to_string(),
+            SpawnAction::RestartUnit,
+            UnitOrTargetConfig::UnitConfig(u),
+        );
+    }
+
+    fn restart_target(
+        _: Arc<Sender<TargetCommand>>,
+        c: Component,
+        t: Target,
+        _: Receiver<TargetCommand>,
+        _: WaitPoint,
+    ) {
+        log_spawn_action(
+            c.name.to_string(),
+            SpawnAction::RestartTarget,
+            UnitOrTargetConfig::TargetConfig(t),
+        );
+    }
+
     fn terminate_unit(name: &str, _: Arc<GateAgent>) {
         log_spawn_action(
             name.to_string(),
@@ -3154,7 +4666,7 @@ mod tests {
         });
     }
 
-    fn spawn(manager: &mut Manager, mut config: Config) {
+    fn spawn(manager: &mut Manager, mut config: Config) -> SpawnSummary {
         clear_spawn_action_log();
         manager.spawn_internal(
             &mut config,
@@ -3162,9 +4674,11 @@ mod tests {
             spawn_target,
             reconfigure_unit,
             reconfigure_target,
+            restart_unit,
+            restart_target,
             terminate_unit,
             terminate_target,
-        );
+        )
     }
 
     fn init_manager() -> Manager {
@@ -3174,4 +4688,24 @@ mod tests {
         });
         Manager::new()
     }
+
+    #[test]
+    fn link_ids_are_parsed() {
+        assert_eq!(
+            parse_link_id("rib".into()),
+            ("rib".into(), DEF_UPDATE_QUEUE_LEN, vec![])
+        );
+        assert_eq!(
+            parse_link_id("rib:100".into()),
+            ("rib".into(), 100, vec![])
+        );
+        assert_eq!(
+            parse_link_id("rib:100#alerts, archive".into()),
+            ("rib".into(), 100, vec!["alerts".into(), "archive".into()])
+        );
+        assert_eq!(
+            parse_link_id("rib#alerts".into()),
+            ("rib".into(), DEF_UPDATE_QUEUE_LEN, vec!["alerts".into()])
+        );
+    }
 }
@@ -111,6 +111,19 @@ impl Collection {
         );
         target.into_string()
     }
+
+    /// Returns the names of the components with live sources, in order.
+    pub fn names(&self) -> Vec<Arc<str>> {
+        let mut res: Vec<_> = self
+            .sources
+            .load()
+            .iter()
+            .filter(|item| item.source.strong_count() > 0)
+            .map(|item| item.name.clone())
+            .collect();
+        res.dedup();
+        res
+    }
 }
 
 impl fmt::Debug for Collection {
@@ -190,6 +190,16 @@ impl RotondaPaMap {
         Self { raw }
     }
 
+    /// Reconstruct a `RotondaPaMap` from its raw representation as returned
+    /// by `as_ref()`. Returns None if `raw` is too short to hold the
+    /// RpkiInfo and PduParseInfo bytes.
+    pub fn from_raw(raw: Vec<u8>) -> Option<Self> {
+        if raw.len() < 2 {
+            return None;
+        }
+        Some(Self { raw })
+    }
+
     pub fn set_rpki_info(&mut self, rpki_info: RpkiInfo) {
         self.raw[0] = rpki_info.into();
     }
@@ -0,0 +1,191 @@
+//! AS relationships for route leak detection.
+//!
+//! An [`AsRelationships`] indexes the relationships between ASes found in
+//! the data of an external data source, in the format of the CAIDA AS
+//! relationship datasets: a text file with a line `<provider>|<customer>|-1`
+//! for every customer and `<peer>|<peer>|0` for every pair of peers, any
+//! further fields and comment lines being ignored.
+//!
+//! With these, an AS path can be checked for being valley-free: a route
+//! travels up from customers to providers, then across at most one peering,
+//! then down from providers to customers. An AS that passes a route it
+//! learned from a provider or peer on to another provider or peer leaks
+//! it.
+
+use std::collections::HashMap;
+
+use inetnum::asn::Asn;
+use routecore::bgp::aspath::{Hop, HopPath};
+
+use super::external_data::ExternalDataValue;
+
+//------------ Relationship --------------------------------------------------
+
+/// How a route travels from one AS to a neighbour.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Relationship {
+    /// From a customer to its provider.
+    Up,
+
+    /// Between peers.
+    Across,
+
+    /// From a provider to its customer.
+    Down,
+}
+
+//------------ AsRelationships -----------------------------------------------
+
+/// The relationships between ASes.
+#[derive(Clone, Debug, Default)]
+pub struct AsRelationships {
+    /// The relationship by sending and receiving AS.
+    links: HashMap<(Asn, Asn), Relationship>,
+}
+
+impl AsRelationships {
+    /// Creates the relationships from the data of an external data source.
+    ///
+    /// The relationships are taken from a list of lines in the CAIDA
+    /// format, as a `text` source provides. Anything else is left out.
+    pub fn from_value(value: &ExternalDataValue) -> Self {
+        let mut res = Self::default();
+        if let ExternalDataValue::Array(items) = value {
+            for item in items {
+                if let ExternalDataValue::String(line) = item {
+                    res.insert_line(line);
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the number of relationships.
+    pub fn len(&self) -> usize {
+        self.links.len() / 2
+    }
+
+    /// Returns whether there are no relationships.
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Returns how a route travels from `from` to `to`, if known.
+    pub fn get(&self, from: Asn, to: Asn) -> Option<Relationship> {
+        self.links.get(&(from, to)).copied()
+    }
+
+    /// Returns the AS that leaked a route with `path`, if any.
+    ///
+    /// The path is walked from the origin towards the neighbour the route
+    /// was received from. The first AS that received the route from a
+    /// provider or peer and sent it on to a provider or peer is the leaker.
+    /// Links with an unknown relationship and those to or from an AS_SET
+    /// are skipped, and prepends are ignored.
+    pub fn leaker(&self, path: &HopPath) -> Option<Asn> {
+        let mut hops: Vec<Option<Asn>> = path
+            .iter()
+            .map(|hop| match hop {
+                Hop::Asn(asn) => Some(*asn),
+                _ => None,
+            })
+            .collect();
+        hops.dedup();
+
+        // Whether the route has gone across or down already.
+        let mut descending = false;
+        for link in hops.windows(2).rev() {
+            let (Some(to), Some(from)) = (link[0], link[1]) else {
+                continue;
+            };
+            match self.get(from, to) {
+                Some(Relationship::Up | Relationship::Across)
+                    if descending =>
+                {
+                    return Some(from)
+                }
+                Some(Relationship::Across | Relationship::Down) => {
+                    descending = true
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn insert_line(&mut self, line: &str) {
+        let mut fields = line.split('|');
+        let (Some(first), Some(second), Some(kind)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return;
+        };
+        let (Ok(first), Ok(second)) =
+            (first.trim().parse::<u32>(), second.trim().parse::<u32>())
+        else {
+            return;
+        };
+        let (first, second) = (Asn::from_u32(first), Asn::from_u32(second));
+        let (there, back) = match kind.trim() {
+            "-1" => (Relationship::Down, Relationship::Up),
+            "0" => (Relationship::Across, Relationship::Across),
+            _ => return,
+        };
+        self.links.insert((first, second), there);
+        self.links.insert((second, first), back);
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(asns: &[u32]) -> HopPath {
+        HopPath::from(asns)
+    }
+
+    #[test]
+    fn leakers_are_found() {
+        // AS1 and AS2 are peering tier 1s, AS10 a customer of both, with
+        // customers AS100 and AS101. AS20 is a customer of AS2.
+        let value = ExternalDataValue::Array(
+            [
+                "# source:topology|BGP",
+                "1|2|0|bgp",
+                "1|10|-1|bgp",
+                "2|10|-1|bgp",
+                "10|100|-1",
+                "10|101|-1",
+                "2|20|-1",
+                "not|a|link",
+            ]
+            .into_iter()
+            .map(|line| ExternalDataValue::String(line.into()))
+            .collect(),
+        );
+        let rels = AsRelationships::from_value(&value);
+        assert_eq!(rels.len(), 6);
+        assert_eq!(
+            rels.get(Asn::from_u32(10), Asn::from_u32(1)),
+            Some(Relationship::Up)
+        );
+
+        // Up, across and down again.
+        assert_eq!(rels.leaker(&path(&[20, 2, 1, 10, 100])), None);
+        assert_eq!(rels.leaker(&path(&[101, 10, 10, 100])), None);
+        // AS10 passes a route from its provider AS1 on to AS2.
+        assert_eq!(
+            rels.leaker(&path(&[20, 2, 10, 1])),
+            Some(Asn::from_u32(10))
+        );
+        // AS100 passes a route from its provider AS10 back to AS10.
+        assert_eq!(
+            rels.leaker(&path(&[10, 100, 10, 101])),
+            Some(Asn::from_u32(100))
+        );
+        // Unknown links are skipped.
+        assert_eq!(rels.leaker(&path(&[64496, 10, 100])), None);
+    }
+}
@@ -0,0 +1,153 @@
+//! AS_PATH matching for Roto scripts.
+//!
+//! Paths are matched against regular expressions in the style of router
+//! configurations: the path is written as its ASNs separated by spaces, with
+//! sets as `{64496,64497}`, and an `_` in the expression matches the start
+//! or end of the path or the characters between ASNs. So `^174_.*_3356$`
+//! matches paths received from AS174 that originate in AS3356.
+//!
+//! As scripts pass the same expressions for every route, compiled
+//! expressions are kept in a process-wide cache.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+
+use inetnum::asn::Asn;
+use log::error;
+use regex::Regex;
+use routecore::bgp::aspath::{Hop, HopPath};
+
+/// The maximum number of compiled expressions kept.
+///
+/// Expressions beyond this are compiled for every match.
+const CACHE_SIZE: usize = 1024;
+
+/// What `_` in an expression stands for.
+const BOUNDARY: &str = "(?:^|$|[ ,{}])";
+
+/// The compiled expressions by their text, `None` if invalid.
+static CACHE: RwLock<Option<HashMap<Arc<str>, Option<Regex>>>> =
+    RwLock::new(None);
+
+/// Returns whether the path matches the expression.
+///
+/// An invalid expression is logged once and matches nothing.
+pub fn matches(path: &HopPath, expr: &Arc<str>) -> bool {
+    let text = to_text(path);
+    if let Ok(cache) = CACHE.read() {
+        if let Some(regex) = cache.as_ref().and_then(|c| c.get(expr)) {
+            return regex.as_ref().is_some_and(|re| re.is_match(&text));
+        }
+    }
+    let regex = compile(expr);
+    let res = regex.as_ref().is_some_and(|re| re.is_match(&text));
+    if let Ok(mut cache) = CACHE.write() {
+        let cache = cache.get_or_insert_with(HashMap::new);
+        if cache.len() < CACHE_SIZE {
+            cache.insert(expr.clone(), regex);
+        }
+    }
+    res
+}
+
+/// Returns how often `asn` was prepended to itself in the path.
+///
+/// This is the number of times the ASN directly follows itself, so zero
+/// if it appears only once or not at all.
+pub fn prepend_count(path: &HopPath, asn: Asn) -> u32 {
+    let mut count = 0;
+    let mut last = None;
+    for hop in path.iter() {
+        let hop = match hop {
+            Hop::Asn(hop) => Some(*hop),
+            Hop::Segment(_) => None,
+        };
+        if hop == Some(asn) && last == Some(asn) {
+            count += 1;
+        }
+        last = hop;
+    }
+    count
+}
+
+/// Returns the path as matched by expressions.
+fn to_text(path: &HopPath) -> String {
+    let mut res = String::new();
+    for hop in path.iter() {
+        if !res.is_empty() {
+            res.push(' ');
+        }
+        match hop {
+            Hop::Asn(asn) => {
+                let _ = write!(res, "{}", asn.into_u32());
+            }
+            Hop::Segment(segment) => {
+                res.push('{');
+                for (i, asn) in segment.asns().enumerate() {
+                    if i > 0 {
+                        res.push(',');
+                    }
+                    let _ = write!(res, "{}", asn.into_u32());
+                }
+                res.push('}');
+            }
+        }
+    }
+    res
+}
+
+fn compile(expr: &str) -> Option<Regex> {
+    Regex::new(&expr.replace('_', BOUNDARY))
+        .inspect_err(|err| {
+            error!("Invalid AS_PATH expression '{expr}': {err}")
+        })
+        .ok()
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(asns: &[u32]) -> HopPath {
+        HopPath::from(asns)
+    }
+
+    fn matches(path: &HopPath, expr: &str) -> bool {
+        super::matches(path, &expr.into())
+    }
+
+    #[test]
+    fn expressions_match() {
+        let p = path(&[174, 1299, 3356]);
+        assert!(matches(&p, "^174_.*_3356$"));
+        assert!(matches(&p, "_1299_"));
+        assert!(matches(&p, "^174_"));
+        assert!(!matches(&p, "_129_"));
+        assert!(!matches(&p, "^1299_"));
+        assert!(matches(&path(&[]), "^$"));
+        assert!(!matches(&p, "^$"));
+
+        // Invalid expressions match nothing, also when cached
+        assert!(!matches(&p, "(174"));
+        assert!(!matches(&p, "(174"));
+    }
+
+    #[test]
+    fn sets_are_matched() {
+        let mut p = path(&[64496]);
+        p.append_set([Asn::from_u32(64497), Asn::from_u32(64498)]);
+        assert_eq!(to_text(&p), "64496 {64497,64498}");
+        assert!(matches(&p, "_64498_"));
+    }
+
+    #[test]
+    fn prepends_are_counted() {
+        let p = path(&[65001, 65002, 65002, 65002, 65003, 65002]);
+        assert_eq!(prepend_count(&p, Asn::from_u32(65002)), 2);
+        assert_eq!(prepend_count(&p, Asn::from_u32(65001)), 0);
+        assert_eq!(prepend_count(&p, Asn::from_u32(65009)), 0);
+    }
+}
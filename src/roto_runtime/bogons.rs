@@ -0,0 +1,242 @@
+//! Bogon prefixes and ASNs for Roto scripts.
+//!
+//! Prefixes and ASNs that should never appear in the global routing table,
+//! because they are reserved for private use, documentation or special
+//! purposes, or not allocated at all, are known as bogons. Rotonda comes
+//! with the lists of the IANA special-purpose registries, so that scripts
+//! can check for them without configuration. The `[roto_bogons]` section
+//! replaces either list, e.g. to allow private ASNs within a confederation.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use inetnum::addr::Prefix;
+use inetnum::asn::Asn;
+use serde::Deserialize;
+
+/// The prefixes that are bogons unless configured otherwise.
+const PREFIXES: &[&str] = &[
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.0.2.0/24",
+    "192.88.99.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "198.51.100.0/24",
+    "203.0.113.0/24",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/8",
+    "100::/64",
+    "2001:2::/48",
+    "2001:10::/28",
+    "2001:db8::/32",
+    "2002::/16",
+    "3ffe::/16",
+    "fc00::/7",
+    "fe80::/10",
+    "fec0::/10",
+    "ff00::/8",
+];
+
+/// The ASNs that are bogons unless configured otherwise.
+const ASNS: &[(u32, u32)] = &[
+    (0, 0),
+    (23456, 23456),
+    (64496, 131071),
+    (4200000000, 4294967295),
+];
+
+/// The bogons used by the Roto functions.
+static CURRENT: RwLock<Option<Arc<Bogons>>> = RwLock::new(None);
+
+/// Returns the bogons used by the Roto functions.
+pub fn current() -> Arc<Bogons> {
+    if let Ok(current) = CURRENT.read() {
+        if let Some(bogons) = current.as_ref() {
+            return bogons.clone();
+        }
+    }
+    Default::default()
+}
+
+/// Sets the bogons used by the Roto functions.
+pub fn set_current(bogons: Bogons) {
+    if let Ok(mut current) = CURRENT.write() {
+        *current = Some(Arc::new(bogons));
+    }
+}
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BogonConfig {
+    /// The prefixes to use instead of the built-in list.
+    ///
+    /// More-specifics of these prefixes are bogons as well.
+    pub prefixes: Option<Vec<Prefix>>,
+
+    /// The ASNs to use instead of the built-in list.
+    pub asns: Option<Vec<AsnRange>>,
+}
+
+impl BogonConfig {
+    /// Returns the bogons following the configuration.
+    pub fn bogons(&self) -> Bogons {
+        let mut res = Bogons::default();
+        if let Some(prefixes) = &self.prefixes {
+            res.prefixes.clone_from(prefixes);
+        }
+        if let Some(asns) = &self.asns {
+            res.asns.clone_from(asns);
+        }
+        res
+    }
+}
+
+//------------ Bogons --------------------------------------------------------
+
+#[derive(Clone, Debug)]
+pub struct Bogons {
+    prefixes: Vec<Prefix>,
+    asns: Vec<AsnRange>,
+}
+
+impl Bogons {
+    /// Returns whether `prefix` is or is covered by a bogon prefix.
+    pub fn is_bogon_prefix(&self, prefix: Prefix) -> bool {
+        self.prefixes.iter().any(|bogon| bogon.covers(prefix))
+    }
+
+    /// Returns whether `asn` is a bogon ASN.
+    pub fn is_bogon_asn(&self, asn: Asn) -> bool {
+        self.asns.iter().any(|range| range.contains(asn))
+    }
+}
+
+impl Default for Bogons {
+    fn default() -> Self {
+        Self {
+            prefixes: PREFIXES
+                .iter()
+                .map(|prefix| Prefix::from_str(prefix).unwrap())
+                .collect(),
+            asns: ASNS
+                .iter()
+                .map(|&(first, last)| AsnRange {
+                    first: Asn::from_u32(first),
+                    last: Asn::from_u32(last),
+                })
+                .collect(),
+        }
+    }
+}
+
+//------------ AsnRange ------------------------------------------------------
+
+/// An inclusive range of ASNs, written as `64512-65534` or a single ASN.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub struct AsnRange {
+    first: Asn,
+    last: Asn,
+}
+
+impl AsnRange {
+    pub fn contains(&self, asn: Asn) -> bool {
+        self.first <= asn && asn <= self.last
+    }
+}
+
+impl FromStr for AsnRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |s: &str| {
+            Asn::from_str(s.trim())
+                .map_err(|err| format!("invalid ASN '{s}': {err}"))
+        };
+        let (first, last) = match s.split_once('-') {
+            Some((first, last)) => (parse(first)?, parse(last)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        if first > last {
+            return Err(format!("invalid ASN range '{s}'"));
+        }
+        Ok(Self { first, last })
+    }
+}
+
+impl TryFrom<String> for AsnRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for AsnRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first.into_u32())
+        } else {
+            write!(f, "{}-{}", self.first.into_u32(), self.last.into_u32())
+        }
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(s: &str) -> Prefix {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn builtin_bogons() {
+        let bogons = Bogons::default();
+        assert!(bogons.is_bogon_prefix(prefix("10.1.0.0/16")));
+        assert!(bogons.is_bogon_prefix(prefix("192.0.2.0/24")));
+        assert!(bogons.is_bogon_prefix(prefix("2001:db8:1::/48")));
+        assert!(!bogons.is_bogon_prefix(prefix("193.0.0.0/21")));
+        assert!(!bogons.is_bogon_prefix(prefix("2001:67c::/32")));
+        assert!(!bogons.is_bogon_prefix(prefix("0.0.0.0/0")));
+
+        for asn in [0, 23456, 64512, 65535, 4200000000, u32::MAX] {
+            assert!(bogons.is_bogon_asn(Asn::from_u32(asn)), "{asn}");
+        }
+        for asn in [1, 3333, 64495, 131072, 4199999999] {
+            assert!(!bogons.is_bogon_asn(Asn::from_u32(asn)), "{asn}");
+        }
+    }
+
+    #[test]
+    fn configured_bogons() {
+        let config: BogonConfig = toml::from_str(
+            r#"asns = ["0", "AS23456", "65000-65010"]"#,
+        )
+        .unwrap();
+        let bogons = config.bogons();
+        assert!(bogons.is_bogon_asn(Asn::from_u32(65005)));
+        assert!(!bogons.is_bogon_asn(Asn::from_u32(64512)));
+        // The prefixes are still the built-in ones.
+        assert!(bogons.is_bogon_prefix(prefix("10.0.0.0/8")));
+
+        assert!("65010-65000".parse::<AsnRange>().is_err());
+        assert!("AS-FOO".parse::<AsnRange>().is_err());
+        assert_eq!(
+            "64512 - 65534".parse::<AsnRange>().unwrap().to_string(),
+            "64512-65534"
+        );
+    }
+}
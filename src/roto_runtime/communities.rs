@@ -0,0 +1,264 @@
+//! Rewriting the communities of routes in Roto scripts.
+//!
+//! Standard, extended and large communities are treated alike: they are
+//! added in their textual representation, e.g. `65000:100`, `rt:65000:100`
+//! or `65000:1:2`, and removed or kept by the patterns of the RIB HTTP API,
+//! in which any member can be a `*` wildcard, e.g. `65000:*` or `*:*:666`.
+//! Each kind is kept in its own path attribute, which is added when the
+//! first community of that kind is and removed with the last one.
+
+use std::str::FromStr;
+
+use routecore::bgp::communities::{
+    Community, ExtendedCommunity, LargeCommunity, StandardCommunity,
+};
+use routecore::bgp::path_attributes::OwnedPathAttributes;
+
+use crate::payload::RotondaPaMap;
+use crate::targets::mrt::bgp4mp::{attribute_value, Attributes};
+use crate::units::rib_unit::index::CommunityPattern;
+
+const COMMUNITIES: u8 = 8;
+const EXTENDED_COMMUNITIES: u8 = 16;
+const LARGE_COMMUNITIES: u8 = 32;
+
+/// The flags of the community attributes.
+const OPTIONAL_TRANSITIVE: u8 = 0xc0;
+
+/// The flag for attributes with a two octet length.
+const EXTENDED_LENGTH: u8 = 0x10;
+
+/// Adds `community` if the route does not have it yet.
+pub fn add(pamap: &mut RotondaPaMap, community: Community) {
+    rewrite(pamap, |communities| {
+        if !communities.contains(&community) {
+            communities.push(community);
+        }
+    })
+}
+
+/// Adds the community written as `text`.
+///
+/// Returns `false` if `text` is not a standard, extended or large
+/// community.
+pub fn add_str(pamap: &mut RotondaPaMap, text: &str) -> bool {
+    match Community::from_str(text) {
+        Ok(community @ Community::Standard(_))
+        | Ok(community @ Community::Extended(_))
+        | Ok(community @ Community::Large(_)) => {
+            add(pamap, community);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Removes the communities matching `pattern`, returning how many.
+///
+/// Nothing is removed if the pattern is invalid.
+pub fn strip(pamap: &mut RotondaPaMap, pattern: &str) -> u32 {
+    let Ok(pattern) = CommunityPattern::from_str(pattern) else {
+        return 0;
+    };
+    let mut res = 0;
+    rewrite(pamap, |communities| {
+        let before = communities.len();
+        communities.retain(|community| !pattern.matches(community));
+        res = (before - communities.len()) as u32;
+    });
+    res
+}
+
+/// Removes the communities not matching `pattern`, returning how many.
+///
+/// Nothing is removed if the pattern is invalid.
+pub fn keep(pamap: &mut RotondaPaMap, pattern: &str) -> u32 {
+    let Ok(pattern) = CommunityPattern::from_str(pattern) else {
+        return 0;
+    };
+    let mut res = 0;
+    rewrite(pamap, |communities| {
+        let before = communities.len();
+        communities.retain(|community| pattern.matches(community));
+        res = (before - communities.len()) as u32;
+    });
+    res
+}
+
+/// Returns the number of communities matching `pattern`.
+pub fn count(pamap: &RotondaPaMap, pattern: &str) -> u32 {
+    let Ok(pattern) = CommunityPattern::from_str(pattern) else {
+        return 0;
+    };
+    let raw = pamap.path_attributes().into_vec();
+    decode(&raw)
+        .iter()
+        .filter(|community| pattern.matches(community))
+        .count() as u32
+}
+
+/// Replaces the communities of `pamap` by the result of `op` on them.
+///
+/// Only standard, extended and large communities are passed to `op`, any
+/// others that it adds are ignored.
+fn rewrite(pamap: &mut RotondaPaMap, op: impl FnOnce(&mut Vec<Community>)) {
+    let attributes = pamap.path_attributes();
+    let ppi = attributes.pdu_parse_info();
+    let raw = attributes.into_vec();
+
+    let mut communities = decode(&raw);
+    let before = communities.clone();
+    op(&mut communities);
+    if communities == before {
+        return;
+    }
+
+    let mut standard = vec![];
+    let mut extended = vec![];
+    let mut large = vec![];
+    for community in &communities {
+        match community {
+            Community::Standard(c) => standard.extend_from_slice(&c.to_raw()),
+            Community::Extended(c) => extended.extend_from_slice(&c.to_raw()),
+            Community::Large(c) => large.extend_from_slice(&c.to_raw()),
+            _ => {}
+        }
+    }
+    let mut new = [
+        (COMMUNITIES, standard),
+        (EXTENDED_COMMUNITIES, extended),
+        (LARGE_COMMUNITIES, large),
+    ]
+    .into_iter()
+    .filter(|(_, value)| !value.is_empty())
+    .peekable();
+
+    // Keep the attributes ordered by type code.
+    let mut res = Vec::with_capacity(raw.len() + 16);
+    for (type_code, attr) in Attributes(&raw) {
+        while let Some((new_type, value)) =
+            new.next_if(|(new_type, _)| *new_type < type_code)
+        {
+            push_attribute(new_type, &value, &mut res);
+        }
+        if new.peek().is_some_and(|(new_type, _)| *new_type == type_code) {
+            let (_, value) = new.next().unwrap();
+            push_attribute(type_code, &value, &mut res);
+        } else if !matches!(
+            type_code,
+            COMMUNITIES | EXTENDED_COMMUNITIES | LARGE_COMMUNITIES
+        ) {
+            res.extend_from_slice(attr);
+        }
+    }
+    for (new_type, value) in new {
+        push_attribute(new_type, &value, &mut res);
+    }
+
+    let rpki_info = pamap.rpki_info();
+    *pamap = RotondaPaMap::new(OwnedPathAttributes::new(ppi, res));
+    pamap.set_rpki_info(rpki_info);
+}
+
+/// Returns the standard, extended and large communities in `raw`.
+fn decode(raw: &[u8]) -> Vec<Community> {
+    let mut res = vec![];
+    for (type_code, attr) in Attributes(raw) {
+        let value = attribute_value(attr);
+        match type_code {
+            COMMUNITIES => res.extend(value.chunks_exact(4).map(|c| {
+                Community::Standard(StandardCommunity::from_u32(
+                    u32::from_be_bytes([c[0], c[1], c[2], c[3]]),
+                ))
+            })),
+            EXTENDED_COMMUNITIES => {
+                res.extend(value.chunks_exact(8).map(|c| {
+                    let mut raw = [0; 8];
+                    raw.copy_from_slice(c);
+                    Community::Extended(ExtendedCommunity::from_raw(raw))
+                }))
+            }
+            LARGE_COMMUNITIES => res.extend(value.chunks_exact(12).map(|c| {
+                let mut raw = [0; 12];
+                raw.copy_from_slice(c);
+                Community::Large(LargeCommunity::from_raw(raw))
+            })),
+            _ => {}
+        }
+    }
+    res
+}
+
+fn push_attribute(type_code: u8, value: &[u8], buf: &mut Vec<u8>) {
+    if value.len() > usize::from(u8::MAX) {
+        buf.extend_from_slice(&[
+            OPTIONAL_TRANSITIVE | EXTENDED_LENGTH,
+            type_code,
+        ]);
+        buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    } else {
+        buf.extend_from_slice(&[OPTIONAL_TRANSITIVE, type_code]);
+        buf.push(value.len() as u8);
+    }
+    buf.extend_from_slice(value);
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use routecore::bgp::message::PduParseInfo;
+
+    use super::*;
+
+    fn pamap(communities: &[&str]) -> RotondaPaMap {
+        // ORIGIN IGP and a NEXT_HOP, around which the communities go.
+        let raw = vec![0x40, 1, 1, 0, 0x40, 3, 4, 192, 0, 2, 1];
+        let mut res = RotondaPaMap::new(OwnedPathAttributes::new(
+            PduParseInfo::modern(),
+            raw,
+        ));
+        for community in communities {
+            assert!(add_str(&mut res, community));
+        }
+        res
+    }
+
+    fn texts(pamap: &RotondaPaMap) -> Vec<String> {
+        decode(&pamap.path_attributes().into_vec())
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn communities_are_rewritten() {
+        let mut route = pamap(&["65000:1", "65000:1:2", "65001:100"]);
+        assert_eq!(texts(&route), ["65000:1", "65001:100", "65000:1:2"]);
+        assert_eq!(count(&route, "65000:*"), 1);
+        assert_eq!(count(&route, "*:*:*"), 1);
+
+        // The attributes stay in order of their type codes.
+        let raw = route.path_attributes().into_vec();
+        let types = Attributes(&raw).map(|(t, _)| t).collect::<Vec<_>>();
+        assert_eq!(types, [1, 3, COMMUNITIES, LARGE_COMMUNITIES]);
+
+        // Adding a community twice does nothing.
+        assert!(add_str(&mut route, "65000:1"));
+        assert_eq!(texts(&route).len(), 3);
+        assert!(!add_str(&mut route, "not a community"));
+
+        assert_eq!(strip(&mut route, "65000:*"), 1);
+        assert_eq!(strip(&mut route, "65000:*:*"), 1);
+        assert_eq!(texts(&route), ["65001:100"]);
+        let raw = route.path_attributes().into_vec();
+        let types = Attributes(&raw).map(|(t, _)| t).collect::<Vec<_>>();
+        assert_eq!(types, [1, 3, COMMUNITIES]);
+
+        let mut route = pamap(&["65000:1", "65000:2", "65001:1"]);
+        assert_eq!(keep(&mut route, "65000:*"), 1);
+        assert_eq!(texts(&route), ["65000:1", "65000:2"]);
+        assert_eq!(strip(&mut route, "65000:1*"), 0);
+        assert_eq!(texts(&route).len(), 2);
+    }
+}
@@ -0,0 +1,413 @@
+//! DNS lookups for Roto scripts.
+//!
+//! With an external data source of type `dns` configured, scripts can
+//! annotate their output with names from the DNS: `dns_ptr(ip)` returns the
+//! reverse DNS name of an address and `dns_txt(name)` the text records of a
+//! name, e.g. of the origin lookups of `origin.asn.cymru.com`.
+//!
+//! As filters run for every route and cannot wait for the DNS, the answers
+//! come from a cache. Names that are not in the cache are looked up in the
+//! background, the lookup meanwhile returning an empty string, so that the
+//! answer is available the next time the filter runs. Answers are kept for
+//! the TTL of their records, but no longer than the `cache_ttl_secs` of the
+//! source, the old answer being used until the new one arrives.
+//!
+//! Lookups are made by the [hickory-resolver] crate and go to the `server`
+//! of the source, by default the nameservers in `/etc/resolv.conf`, which
+//! should resolve recursively. At most [`MAX_LOOKUPS`] are underway at a
+//! time and at most [`QUEUE_LEN`] wait for their turn: names missing the
+//! queue are looked up when asked for again. The cache keeps the names used
+//! most recently.
+//!
+//! [hickory-resolver]: https://docs.rs/hickory-resolver/
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use hickory_resolver::config::{
+    NameServerConfigGroup, ResolverConfig, ResolverOpts,
+};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::lookup::Lookup;
+use hickory_resolver::system_conf::read_system_conf;
+use hickory_resolver::TokioAsyncResolver;
+use log::{debug, warn};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, Semaphore};
+
+use super::external_data::DnsDataSource;
+
+/// The number of names kept in the cache.
+///
+/// Beyond this the least recently used tenth of the names is dropped.
+const MAX_ENTRIES: usize = 10_000;
+
+/// The number of lookups waiting for their turn.
+pub const QUEUE_LEN: usize = 1024;
+
+/// The number of lookups underway at a time.
+pub const MAX_LOOKUPS: usize = 32;
+
+/// How long to wait before looking up again after a failed lookup.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How long to keep the answer that a name does not exist.
+const NEGATIVE_TTL: Duration = Duration::from_secs(300);
+
+/// The source used by the Roto functions.
+static CURRENT: RwLock<Option<Arc<Dns>>> = RwLock::new(None);
+
+/// Returns the DNS source used by the Roto functions, if any.
+pub fn current() -> Option<Arc<Dns>> {
+    CURRENT.read().ok()?.clone()
+}
+
+/// Sets the DNS source used by the Roto functions.
+pub fn set_current(dns: Option<Arc<Dns>>) {
+    if let Ok(mut current) = CURRENT.write() {
+        *current = dns;
+    }
+}
+
+//------------ Dns -----------------------------------------------------------
+
+/// The cached answers of a DNS resolver.
+pub struct Dns {
+    resolver: TokioAsyncResolver,
+
+    /// How long to wait for an answer.
+    timeout: Duration,
+
+    /// The longest answers are used before asking again.
+    max_ttl: Duration,
+
+    cache: Mutex<Cache>,
+
+    /// The queries for the background task.
+    queries: mpsc::Sender<Query>,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    entries: HashMap<Query, Entry>,
+
+    /// The number of times an entry was used so far.
+    uses: u64,
+}
+
+impl Cache {
+    /// Returns the entry of `query`, making room for it if needed.
+    fn entry(&mut self, query: &Query) -> &mut Entry {
+        if self.entries.len() >= MAX_ENTRIES
+            && !self.entries.contains_key(query)
+        {
+            self.evict();
+        }
+        self.uses += 1;
+        let entry = self.entries.entry(query.clone()).or_default();
+        entry.last_used = self.uses;
+        entry
+    }
+
+    /// Drops the least recently used tenth of the entries.
+    fn evict(&mut self) {
+        let mut last_used: Vec<_> =
+            self.entries.values().map(|entry| entry.last_used).collect();
+        let index = last_used.len() / 10;
+        let (_, &mut threshold, _) = last_used.select_nth_unstable(index);
+        self.entries.retain(|_, entry| entry.last_used > threshold);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    /// The last answer, if any.
+    value: Option<Arc<str>>,
+
+    /// When to look up again.
+    expires: Option<Instant>,
+
+    /// Whether a lookup is underway.
+    pending: bool,
+
+    /// When the entry was last used, see [`Cache::uses`].
+    last_used: u64,
+}
+
+/// A query for the background task.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Query {
+    Ptr(IpAddr),
+    Txt(Arc<str>),
+}
+
+/// An answer to a query, `None` if the name does not exist.
+type Answer = Option<(Vec<String>, Duration)>;
+
+impl Dns {
+    /// Creates the source, returning the queries for [`run`][Self::run].
+    pub fn new(
+        config: &DnsDataSource,
+        max_ttl: Duration,
+    ) -> (Arc<Self>, mpsc::Receiver<Query>) {
+        let (tx, rx) = mpsc::channel(QUEUE_LEN);
+        let timeout = Duration::from_secs(config.timeout_secs);
+        let res = Self {
+            resolver: resolver(config.server, timeout),
+            timeout,
+            max_ttl,
+            cache: Default::default(),
+            queries: tx,
+        };
+        (Arc::new(res), rx)
+    }
+
+    /// Returns the reverse DNS name of `addr`, if known.
+    pub fn ptr(&self, addr: IpAddr) -> Option<Arc<str>> {
+        self.lookup(Query::Ptr(addr))
+    }
+
+    /// Returns the text records of `name`, if known.
+    ///
+    /// The strings of each record are joined, the records separated by a
+    /// space.
+    pub fn txt(&self, name: &str) -> Option<Arc<str>> {
+        self.lookup(Query::Txt(name.to_ascii_lowercase().into()))
+    }
+
+    /// Returns the cached answer to `query`.
+    ///
+    /// Queues a lookup if there is none or it has expired.
+    fn lookup(&self, query: Query) -> Option<Arc<str>> {
+        let mut cache = self.cache.lock().unwrap();
+        let entry = cache.entry(&query);
+        let now = Instant::now();
+        if !entry.pending
+            && !matches!(entry.expires, Some(expires) if expires > now)
+        {
+            match self.queries.try_send(query) {
+                Ok(()) => entry.pending = true,
+                Err(TrySendError::Full(query)) => {
+                    debug!("DNS lookup queue full, not looking up {query:?}");
+                }
+                // Without a running task, the entry stays pending for good,
+                // which is fine as there will not be an answer anyway.
+                Err(TrySendError::Closed(_)) => entry.pending = true,
+            }
+        }
+        entry.value.clone()
+    }
+
+    /// Stores the answer to `query`.
+    ///
+    /// A failed lookup keeps the previous answer and is retried after a
+    /// while.
+    fn store(&self, query: &Query, answer: Result<Answer, String>) {
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        let entry = cache.entry(query);
+        entry.pending = false;
+        match answer {
+            Ok(Some((records, ttl))) => {
+                entry.value = Some(records.join(" ").into());
+                entry.expires = Some(now + ttl.min(self.max_ttl));
+            }
+            Ok(None) => {
+                entry.value = Some("".into());
+                entry.expires = Some(now + NEGATIVE_TTL.min(self.max_ttl));
+            }
+            Err(_) => {
+                entry.expires = Some(now + RETRY_DELAY);
+            }
+        }
+    }
+
+    /// Answers the queries until the source is removed.
+    ///
+    /// Up to [`MAX_LOOKUPS`] lookups run concurrently, so that a slow name
+    /// does not hold up the others.
+    pub async fn run(self: Arc<Self>, mut queries: mpsc::Receiver<Query>) {
+        let lookups = Arc::new(Semaphore::new(MAX_LOOKUPS));
+        while let Some(query) = queries.recv().await {
+            let Ok(permit) = lookups.clone().acquire_owned().await else {
+                return;
+            };
+            let dns = self.clone();
+            tokio::spawn(async move {
+                debug!("Looking up {query:?}");
+                let answer =
+                    tokio::time::timeout(dns.timeout, dns.query(&query))
+                        .await
+                        .unwrap_or_else(|_| Err("timed out".into()));
+                match &answer {
+                    Ok(answer) => {
+                        debug!("DNS answer to {query:?}: {answer:?}")
+                    }
+                    Err(err) => debug!("DNS lookup {query:?} failed: {err}"),
+                }
+                dns.store(&query, answer);
+                drop(permit);
+            });
+        }
+    }
+
+    /// Asks the resolver.
+    async fn query(&self, query: &Query) -> Result<Answer, String> {
+        let res = match query {
+            Query::Ptr(addr) => {
+                self.resolver.reverse_lookup(*addr).await.map(|lookup| {
+                    let records = lookup
+                        .iter()
+                        .map(|name| {
+                            name.to_utf8().trim_end_matches('.').to_string()
+                        })
+                        .collect();
+                    (records, ttl(lookup.as_lookup()))
+                })
+            }
+            Query::Txt(name) => {
+                // Looking up the name as absolute skips the search domains.
+                let name = format!("{}.", name.trim_end_matches('.'));
+                self.resolver.txt_lookup(name).await.map(|lookup| {
+                    let records = lookup
+                        .iter()
+                        .map(|txt| {
+                            txt.txt_data()
+                                .iter()
+                                .map(|s| String::from_utf8_lossy(s))
+                                .collect()
+                        })
+                        .collect();
+                    (records, ttl(lookup.as_lookup()))
+                })
+            }
+        };
+        match res {
+            Ok(answer) => Ok(Some(answer)),
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+impl fmt::Debug for Dns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dns")
+            .field("timeout", &self.timeout)
+            .field("max_ttl", &self.max_ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns a resolver asking `server`, or the system's nameservers.
+fn resolver(
+    server: Option<SocketAddr>,
+    timeout: Duration,
+) -> TokioAsyncResolver {
+    let only = |server: SocketAddr| {
+        ResolverConfig::from_parts(
+            None,
+            vec![],
+            NameServerConfigGroup::from_ips_clear(
+                &[server.ip()],
+                server.port(),
+                true,
+            ),
+        )
+    };
+    let (config, mut opts) = match server {
+        Some(server) => (only(server), ResolverOpts::default()),
+        None => read_system_conf().unwrap_or_else(|err| {
+            warn!(
+                "Cannot read the system's DNS configuration, using \
+                localhost: {err}"
+            );
+            (
+                only((Ipv4Addr::LOCALHOST, 53).into()),
+                ResolverOpts::default(),
+            )
+        }),
+    };
+    opts.timeout = timeout;
+    TokioAsyncResolver::tokio(config, opts)
+}
+
+/// Returns how long the records of a lookup are valid for.
+fn ttl(lookup: &Lookup) -> Duration {
+    lookup
+        .valid_until()
+        .saturating_duration_since(Instant::now())
+}
+
+/// Returns whether the error means that there are no records of the name.
+fn is_not_found(err: &ResolveError) -> bool {
+    matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_names_are_evicted() {
+        let name = |name: &str| Query::Txt(name.into());
+        let mut cache = Cache::default();
+        for i in 0..MAX_ENTRIES {
+            cache.entry(&name(&i.to_string()));
+        }
+        cache.entry(&name("0"));
+        cache.entry(&name("new"));
+        assert!(cache.entries.len() < MAX_ENTRIES);
+        assert!(cache.entries.contains_key(&name("0")));
+        assert!(cache.entries.contains_key(&name("new")));
+        assert!(!cache.entries.contains_key(&name("1")));
+    }
+
+    #[tokio::test]
+    async fn answers_are_cached() {
+        let config = DnsDataSource {
+            server: Some("127.0.0.1:53".parse().unwrap()),
+            timeout_secs: 5,
+        };
+        let (dns, queries) = Dns::new(&config, Duration::from_secs(60));
+        let known = "192.0.2.1".parse().unwrap();
+        let unknown = "192.0.2.2".parse().unwrap();
+
+        // Nothing is known at first, and asking twice looks up once.
+        assert_eq!(dns.ptr(known), None);
+        assert_eq!(dns.ptr(known), None);
+        assert_eq!(dns.ptr(unknown), None);
+        assert_eq!(queries.len(), 2);
+
+        dns.store(
+            &Query::Ptr(known),
+            Ok(Some((vec!["r".into()], Duration::from_secs(600)))),
+        );
+        dns.store(&Query::Ptr(unknown), Ok(None));
+        assert_eq!(dns.ptr(known).as_deref(), Some("r"));
+        assert_eq!(dns.ptr(unknown).as_deref(), Some(""));
+        assert_eq!(queries.len(), 2);
+
+        // A failed lookup keeps the previous answer.
+        dns.store(&Query::Ptr(known), Err("timed out".into()));
+        assert_eq!(dns.ptr(known).as_deref(), Some("r"));
+
+        // Multiple records are joined.
+        dns.store(
+            &Query::Txt("example.com".into()),
+            Ok(Some((
+                vec!["hello world".into(), "foo".into()],
+                Duration::from_secs(60),
+            ))),
+        );
+        assert_eq!(
+            dns.txt("Example.com").as_deref(),
+            Some("hello world foo")
+        );
+    }
+}
@@ -1,13 +1,23 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    str::FromStr,
     sync::{Arc, RwLock},
     time::{Duration, Instant},
 };
+use inetnum::{addr::Prefix, asn::Asn};
+use routecore::bgp::aspath::HopPath;
+use reqwest::{header::CONTENT_TYPE, Client as HttpClient, Method};
 use serde::{Deserialize, Serialize};
-use tokio::{sync::mpsc, time::interval};
-use log::{debug, error, info, warn};
+use tokio::{task::JoinHandle, time::interval};
+use log::{debug, error, warn};
 use url::Url;
 
+use super::as_rel::AsRelationships;
+use super::dns::{self, Dns};
+use super::irr::{self, Irr};
+use super::prefix_set::{PrefixSet, PrefixSetEntry};
+
 /// External data source configuration
 #[derive(Clone, Debug, Deserialize)]
 pub struct ExternalDataSource {
@@ -47,6 +57,45 @@ impl ExternalDataSource {
     fn default_auto_refresh() -> bool {
         true
     }
+
+    /// Checks that the source can be used.
+    ///
+    /// The ID has to be usable as a name in Roto scripts.
+    pub fn check(&self) -> Result<(), String> {
+        let mut chars = self.id.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(
+                "the ID must start with a letter or underscore and \
+                contain only letters, digits and underscores"
+                    .into(),
+            );
+        }
+        match &self.source_type {
+            ExternalDataSourceType::File(file) => {
+                if matches!(file.format, FileFormat::Yaml) {
+                    return Err("YAML files are not supported".into());
+                }
+            }
+            ExternalDataSourceType::Http(http) => {
+                if http.url.scheme() != "http" {
+                    return Err("only http URLs are supported".into());
+                }
+            }
+            ExternalDataSourceType::Irr(_)
+            | ExternalDataSourceType::Dns(_) => {}
+            other => {
+                return Err(format!(
+                    "{} sources are not supported",
+                    other.name()
+                ))
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Types of external data sources
@@ -72,6 +121,40 @@ pub enum ExternalDataSourceType {
     /// Another RIB unit
     #[serde(rename = "rib")]
     Rib(RibDataSource),
+
+    /// An IRRd server
+    #[serde(rename = "irr")]
+    Irr(IrrDataSource),
+
+    /// A DNS resolver
+    #[serde(rename = "dns")]
+    Dns(DnsDataSource),
+}
+
+impl ExternalDataSourceType {
+    /// Returns the name of the type as used in the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExternalDataSourceType::Http(_) => "http",
+            ExternalDataSourceType::File(_) => "file",
+            ExternalDataSourceType::Database(_) => "database",
+            ExternalDataSourceType::Redis(_) => "redis",
+            ExternalDataSourceType::Rib(_) => "rib",
+            ExternalDataSourceType::Irr(_) => "irr",
+            ExternalDataSourceType::Dns(_) => "dns",
+        }
+    }
+
+    /// Returns whether the source is queried by Roto functions.
+    ///
+    /// Such sources are not available as constants and only one of each
+    /// type can be configured.
+    pub fn is_lookup(&self) -> bool {
+        matches!(
+            self,
+            ExternalDataSourceType::Irr(_) | ExternalDataSourceType::Dns(_)
+        )
+    }
 }
 
 /// HTTP data source configuration
@@ -162,6 +245,23 @@ pub enum FileFormat {
     Text,
 }
 
+impl FileFormat {
+    /// Returns the format for a media type, defaulting to JSON.
+    pub fn for_content_type(content_type: Option<&str>) -> Self {
+        let media_type = content_type
+            .and_then(|value| value.split(';').next())
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match media_type.as_str() {
+            "text/csv" => FileFormat::Csv,
+            "application/toml" => FileFormat::Toml,
+            "text/plain" => FileFormat::Text,
+            _ => FileFormat::Json,
+        }
+    }
+}
+
 /// Database data source configuration
 #[derive(Clone, Debug, Deserialize)]
 pub struct DatabaseDataSource {
@@ -224,6 +324,56 @@ pub struct RibDataSource {
     pub parameters: HashMap<String, String>,
 }
 
+/// IRR data source configuration
+///
+/// The server is queried for AS sets and route objects as Roto scripts
+/// need them, see [`irr`][super::irr].
+#[derive(Clone, Debug, Deserialize)]
+pub struct IrrDataSource {
+    /// The address of the IRRd server, with port
+    #[serde(default = "IrrDataSource::default_server")]
+    pub server: String,
+
+    /// The IRR databases to query, in order, or the server's default
+    #[serde(default)]
+    pub sources: Vec<String>,
+
+    /// Query timeout in seconds
+    #[serde(default = "IrrDataSource::default_timeout")]
+    pub timeout_secs: u64,
+}
+
+impl IrrDataSource {
+    fn default_server() -> String {
+        "whois.radb.net:43".to_string()
+    }
+
+    fn default_timeout() -> u64 {
+        30
+    }
+}
+
+/// DNS data source configuration
+///
+/// The resolver is asked for names as Roto scripts need them, see
+/// [`dns`][super::dns].
+#[derive(Clone, Debug, Deserialize)]
+pub struct DnsDataSource {
+    /// The address of the resolver, with port, or the system's resolvers
+    #[serde(default)]
+    pub server: Option<SocketAddr>,
+
+    /// Query timeout in seconds
+    #[serde(default = "DnsDataSource::default_timeout")]
+    pub timeout_secs: u64,
+}
+
+impl DnsDataSource {
+    fn default_timeout() -> u64 {
+        5
+    }
+}
+
 /// Types of RIB queries
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -288,7 +438,7 @@ impl RetryConfig {
 }
 
 /// External data value that can be used in Roto filters
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ExternalDataValue {
     String(String),
@@ -299,6 +449,94 @@ pub enum ExternalDataValue {
     Null,
 }
 
+impl ExternalDataValue {
+    /// Returns the member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&ExternalDataValue> {
+        match self {
+            ExternalDataValue::Object(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the value contains `key`.
+    ///
+    /// An object contains its member names, an array its elements and any
+    /// other value itself.
+    pub fn contains(&self, key: &str) -> bool {
+        match self {
+            ExternalDataValue::Object(map) => map.contains_key(key),
+            ExternalDataValue::Array(items) => {
+                items.iter().any(|item| item.is(key))
+            }
+            value => value.is(key),
+        }
+    }
+
+    /// Returns whether the value contains an ASN, with or without `AS`.
+    pub fn contains_asn(&self, asn: Asn) -> bool {
+        self.contains(&asn.to_string())
+            || self.contains(&asn.into_u32().to_string())
+    }
+
+    /// Returns whether a prefix the value contains covers `prefix`.
+    pub fn covers(&self, prefix: Prefix) -> bool {
+        let covers = |text: &str| {
+            Prefix::from_str(text).is_ok_and(|p| p.covers(prefix))
+        };
+        match self {
+            ExternalDataValue::Object(map) => map.keys().any(|k| covers(k)),
+            ExternalDataValue::Array(items) => items.iter().any(|item| {
+                matches!(item, ExternalDataValue::String(s) if covers(s))
+            }),
+            ExternalDataValue::String(s) => covers(s),
+            _ => false,
+        }
+    }
+
+    /// Returns a scalar value as text.
+    pub fn as_text(&self) -> Option<String> {
+        match self {
+            ExternalDataValue::String(s) => Some(s.clone()),
+            ExternalDataValue::Number(n) => Some(n.to_string()),
+            ExternalDataValue::Boolean(b) => Some(b.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Returns a number, or text that is one.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            ExternalDataValue::Number(n) => Some(*n),
+            ExternalDataValue::String(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns a boolean, or text or a number that can be taken as one.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ExternalDataValue::Boolean(b) => Some(*b),
+            ExternalDataValue::String(s) => match s.to_lowercase().as_str() {
+                "true" | "1" | "yes" | "on" => Some(true),
+                "false" | "0" | "no" | "off" => Some(false),
+                _ => None,
+            },
+            ExternalDataValue::Number(n) => Some(*n != 0.0),
+            _ => None,
+        }
+    }
+
+    /// Returns whether a scalar value equals `text`.
+    fn is(&self, text: &str) -> bool {
+        match self {
+            ExternalDataValue::String(s) => s == text,
+            ExternalDataValue::Number(n) => text.parse() == Ok(*n),
+            ExternalDataValue::Boolean(b) => text.parse() == Ok(*b),
+            _ => false,
+        }
+    }
+}
+
 /// Cached external data entry
 #[derive(Clone, Debug)]
 pub struct CachedData {
@@ -321,139 +559,468 @@ impl CachedData {
     }
 }
 
+//------------ ExternalData --------------------------------------------------
+
+/// The data of a source as seen by Roto scripts.
+///
+/// Each source is available to scripts as a constant named after its ID,
+/// so that referring to a source that is not configured is an error when
+/// compiling the script. As compiled scripts hold on to the constant, the
+/// data it refers to is kept for the rest of the process.
+///
+/// Roto stores constants without regard for their alignment, hence the
+/// packed representation.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct ExternalData(&'static RwLock<Option<Loaded>>);
+
+/// The fetched data of a source.
+struct Loaded {
+    cached: CachedData,
+
+    /// The prefixes in the data.
+    prefixes: PrefixSet,
+
+    /// The AS relationships in the data.
+    relationships: AsRelationships,
+}
+
+/// The sources by ID, for looking them up by name at run time.
+static SOURCES: RwLock<BTreeMap<String, ExternalData>> =
+    RwLock::new(BTreeMap::new());
+
+impl ExternalData {
+    fn new() -> Self {
+        Self(Box::leak(Box::new(RwLock::new(None))))
+    }
+
+    /// Returns the source with the given ID.
+    pub fn by_id(id: &str) -> Option<Self> {
+        SOURCES.read().ok()?.get(id).copied()
+    }
+
+    /// Applies `op` to the value, if the source was fetched.
+    pub fn with<R>(
+        &self,
+        op: impl FnOnce(&ExternalDataValue) -> R,
+    ) -> Option<R> {
+        let cell = self.0;
+        let data = cell.read().ok()?;
+        data.as_ref().map(|data| op(&data.cached.value))
+    }
+
+    /// Returns the most specific prefix in the data covering `prefix`.
+    pub fn longest_match(
+        &self,
+        prefix: Prefix,
+    ) -> Option<Arc<PrefixSetEntry>> {
+        let cell = self.0;
+        let data = cell.read().ok()?;
+        data.as_ref()?.prefixes.longest_match(prefix).cloned()
+    }
+
+    /// Returns the AS that leaked a route with `path`, if any.
+    ///
+    /// See [`AsRelationships::leaker`] for how the leaker is found.
+    pub fn leaker(&self, path: &HopPath) -> Option<Asn> {
+        let cell = self.0;
+        let data = cell.read().ok()?;
+        data.as_ref()?.relationships.leaker(path)
+    }
+
+    fn is(&self, other: Self) -> bool {
+        let (this, other) = (self.0, other.0);
+        std::ptr::eq(this, other)
+    }
+
+    fn cached(&self) -> Option<CachedData> {
+        let cell = self.0;
+        let data = cell.read().ok()?;
+        data.as_ref().map(|data| data.cached.clone())
+    }
+
+    fn set(&self, cached: CachedData) {
+        let prefixes = PrefixSet::from_value(&cached.value);
+        let relationships = AsRelationships::from_value(&cached.value);
+        let cell = self.0;
+        if let Ok(mut data) = cell.write() {
+            *data = Some(Loaded {
+                cached,
+                prefixes,
+                relationships,
+            });
+        }
+    }
+}
+
+//------------ ExternalDataManager -------------------------------------------
+
 /// External data manager
+///
+/// The manager keeps the configured sources and their data. Fetching the
+/// data starts with [`start`][Self::start], once a Tokio runtime is
+/// available, after which each source is fetched again every
+/// `refresh_interval_secs` if `auto_refresh` is set.
+#[derive(Default)]
 pub struct ExternalDataManager {
     sources: HashMap<String, ExternalDataSource>,
-    cache: Arc<RwLock<HashMap<String, CachedData>>>,
-    refresh_tx: mpsc::UnboundedSender<String>,
+    data: HashMap<String, ExternalData>,
+
+    /// The tasks fetching the sources.
+    tasks: HashMap<String, JoinHandle<()>>,
+
+    /// The HTTP client to use, once started.
+    http: Option<HttpClient>,
 }
 
 impl ExternalDataManager {
     pub fn new() -> Self {
-        let (refresh_tx, refresh_rx) = mpsc::unbounded_channel();
-        let cache = Arc::new(RwLock::new(HashMap::new()));
-        
-        // Start background refresh task
-        let cache_clone = cache.clone();
-        tokio::spawn(Self::refresh_task(refresh_rx, cache_clone));
-        
-        Self {
-            sources: HashMap::new(),
-            cache,
-            refresh_tx,
+        Self::default()
+    }
+
+    /// Replaces the sources with those configured.
+    pub fn configure(
+        &mut self,
+        sources: &[ExternalDataSource],
+    ) -> Result<(), String> {
+        for (index, source) in sources.iter().enumerate() {
+            source.check().map_err(|err| {
+                format!("external data source '{}': {err}", source.id)
+            })?;
+            if sources[..index].iter().any(|other| other.id == source.id) {
+                return Err(format!(
+                    "duplicate external data source '{}'",
+                    source.id
+                ));
+            }
+        }
+        for (index, source) in sources.iter().enumerate() {
+            let kind = &source.source_type;
+            if kind.is_lookup()
+                && sources[..index]
+                    .iter()
+                    .any(|other| other.source_type.name() == kind.name())
+            {
+                return Err(format!(
+                    "only one {} external data source can be configured",
+                    kind.name()
+                ));
+            }
+        }
+        let removed = self
+            .sources
+            .keys()
+            .filter(|id| !sources.iter().any(|source| &source.id == *id))
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in removed {
+            self.remove_source(&id);
+        }
+        for source in sources {
+            self.add_source(source.clone());
         }
+        Ok(())
     }
-    
+
     /// Add an external data source
+    ///
+    /// A source with the same ID is replaced, keeping its data until it is
+    /// fetched again. IRR and DNS sources are not available as constants
+    /// but through their functions, see [`irr`][super::irr] and
+    /// [`dns`][super::dns].
     pub fn add_source(&mut self, source: ExternalDataSource) {
         let source_id = source.id.clone();
+        if !source.source_type.is_lookup() {
+            let data = *self
+                .data
+                .entry(source_id.clone())
+                .or_insert_with(ExternalData::new);
+            if let Ok(mut sources) = SOURCES.write() {
+                sources.insert(source_id.clone(), data);
+            }
+        }
         self.sources.insert(source_id.clone(), source);
-        
-        // Trigger initial fetch
-        if let Err(e) = self.refresh_tx.send(source_id) {
-            error!("Failed to trigger initial fetch for external data source: {}", e);
+        if let Some(task) = self.tasks.remove(&source_id) {
+            task.abort();
+        }
+        if self.http.is_some() {
+            self.spawn(&source_id);
         }
     }
     
     /// Remove an external data source
     pub fn remove_source(&mut self, source_id: &str) {
-        self.sources.remove(source_id);
-        if let Ok(mut cache) = self.cache.write() {
-            cache.remove(source_id);
+        if let Some(source) = self.sources.remove(source_id) {
+            match source.source_type {
+                ExternalDataSourceType::Irr(_) => irr::set_current(None),
+                ExternalDataSourceType::Dns(_) => dns::set_current(None),
+                _ => {}
+            }
         }
-    }
-    
-    /// Get data from an external source
-    pub async fn get_data(&self, source_id: &str) -> Option<ExternalDataValue> {
-        // Check cache first
-        if let Ok(cache) = self.cache.read() {
-            if let Some(cached) = cache.get(source_id) {
-                if !cached.is_expired() {
-                    debug!("Returning cached data for source: {}", source_id);
-                    return Some(cached.value.clone());
+        if let Some(data) = self.data.remove(source_id) {
+            if let Ok(mut sources) = SOURCES.write() {
+                if sources.get(source_id).is_some_and(|d| d.is(data)) {
+                    sources.remove(source_id);
                 }
             }
         }
-        
-        // Cache miss or expired, trigger refresh
-        if let Err(e) = self.refresh_tx.send(source_id.to_string()) {
-            error!("Failed to trigger refresh for external data source {}: {}", source_id, e);
+        if let Some(task) = self.tasks.remove(source_id) {
+            task.abort();
         }
-        
-        // Return stale data if available
-        if let Ok(cache) = self.cache.read() {
-            if let Some(cached) = cache.get(source_id) {
-                warn!("Returning stale data for source: {}", source_id);
-                return Some(cached.value.clone());
-            }
+    }
+
+    /// Starts fetching the sources.
+    pub fn start(&mut self, http: HttpClient) {
+        self.http = Some(http);
+        let ids = self
+            .sources
+            .keys()
+            .filter(|id| !self.tasks.contains_key(*id))
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in ids {
+            self.spawn(&id);
         }
-        
-        None
     }
-    
+
+    /// Registers the sources as constants with a Roto runtime.
+    pub fn register(&self, rt: &mut roto::Runtime) -> Result<(), String> {
+        for (id, data) in &self.data {
+            rt.register_constant(
+                id.clone(),
+                "Data from the external data source of this name",
+                *data,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn spawn(&mut self, source_id: &str) {
+        let (Some(source), Some(http)) =
+            (self.sources.get(source_id), self.http.as_ref())
+        else {
+            return;
+        };
+        let task = match &source.source_type {
+            ExternalDataSourceType::Irr(config) => {
+                let (irr, queries) = Irr::new(
+                    config.clone(),
+                    Duration::from_secs(source.cache_ttl_secs),
+                );
+                irr::set_current(Some(irr.clone()));
+                tokio::spawn(irr.run(queries))
+            }
+            ExternalDataSourceType::Dns(config) => {
+                let (dns, queries) = Dns::new(
+                    config,
+                    Duration::from_secs(source.cache_ttl_secs),
+                );
+                dns::set_current(Some(dns.clone()));
+                tokio::spawn(dns.run(queries))
+            }
+            _ => {
+                let Some(data) = self.data.get(source_id) else {
+                    return;
+                };
+                tokio::spawn(Self::refresh_task(
+                    source.clone(),
+                    *data,
+                    http.clone(),
+                ))
+            }
+        };
+        self.tasks.insert(source_id.into(), task);
+    }
+
     /// Background task for refreshing external data
     async fn refresh_task(
-        mut refresh_rx: mpsc::UnboundedReceiver<String>,
-        cache: Arc<RwLock<HashMap<String, CachedData>>>,
+        source: ExternalDataSource,
+        data: ExternalData,
+        http: HttpClient,
     ) {
-        while let Some(source_id) = refresh_rx.recv().await {
-            debug!("Refreshing external data source: {}", source_id);
-            
-            // TODO: Implement actual data fetching based on source type
-            // For now, this is a placeholder
-            let placeholder_data = ExternalDataValue::Object({
-                let mut map = HashMap::new();
-                map.insert("source_id".to_string(), ExternalDataValue::String(source_id.clone()));
-                map.insert("timestamp".to_string(), ExternalDataValue::Number(
-                    std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs() as f64
-                ));
-                map.insert("status".to_string(), ExternalDataValue::String("active".to_string()));
-                map
-            });
-            
-            let cached_data = CachedData::new(
-                placeholder_data,
-                Duration::from_secs(300), // 5 minutes TTL
-            );
-            
-            if let Ok(mut cache) = cache.write() {
-                cache.insert(source_id.clone(), cached_data);
-                debug!("Updated cache for external data source: {}", source_id);
-            } else {
-                error!("Failed to update cache for external data source: {}", source_id);
+        let ttl = Duration::from_secs(source.cache_ttl_secs);
+        let refresh = source.refresh_interval_secs.max(1);
+        let mut interval = interval(Duration::from_secs(refresh));
+        loop {
+            interval.tick().await;
+            debug!("Refreshing external data source: {}", source.id);
+            match fetch_with_retries(&source, &http).await {
+                Ok(value) => {
+                    data.set(CachedData::new(value, ttl));
+                    debug!("Updated external data source: {}", source.id);
+                }
+                Err(err) => {
+                    error!(
+                        "Failed to fetch external data source {}: {err}",
+                        source.id
+                    );
+                }
+            }
+            if !source.auto_refresh {
+                break;
             }
         }
     }
-    
-    /// Start automatic refresh for all sources
-    pub fn start_auto_refresh(&self) {
-        for (source_id, source) in &self.sources {
-            if source.auto_refresh {
-                let source_id = source_id.clone();
-                let refresh_tx = self.refresh_tx.clone();
-                let refresh_interval = Duration::from_secs(source.refresh_interval_secs);
-                
-                tokio::spawn(async move {
-                    let mut interval = interval(refresh_interval);
-                    loop {
-                        interval.tick().await;
-                        if let Err(e) = refresh_tx.send(source_id.clone()) {
-                            error!("Failed to send refresh signal for {}: {}", source_id, e);
-                            break;
-                        }
-                    }
-                });
+}
+
+impl ExternalDataAccess for ExternalDataManager {
+    fn get_external_data(
+        &self,
+        source_id: &str,
+    ) -> Option<ExternalDataValue> {
+        let cached = self.data.get(source_id)?.cached()?;
+        if cached.is_expired() {
+            warn!("Returning stale data for source: {}", source_id);
+        }
+        Some(cached.value)
+    }
+
+    fn has_external_data(&self, source_id: &str) -> bool {
+        self.data.contains_key(source_id)
+    }
+}
+
+//------------ Fetching ------------------------------------------------------
+
+/// Fetches the data of a source, retrying as configured.
+async fn fetch_with_retries(
+    source: &ExternalDataSource,
+    http: &HttpClient,
+) -> Result<ExternalDataValue, String> {
+    let retry = &source.retry_config;
+    let mut delay = Duration::from_millis(retry.initial_delay_ms);
+    let mut attempt = 0;
+    loop {
+        match fetch(source, http).await {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < retry.max_retries => {
+                warn!(
+                    "Fetching external data source {} failed: {err}, \
+                    retrying in {}ms",
+                    source.id,
+                    delay.as_millis()
+                );
+                tokio::time::sleep(delay).await;
+                delay = delay
+                    .mul_f64(retry.backoff_multiplier)
+                    .min(Duration::from_millis(retry.max_delay_ms));
+                attempt += 1;
             }
+            Err(err) => return Err(err),
         }
     }
 }
 
-impl Default for ExternalDataManager {
-    fn default() -> Self {
-        Self::new()
+/// Fetches the data of a source once.
+async fn fetch(
+    source: &ExternalDataSource,
+    http: &HttpClient,
+) -> Result<ExternalDataValue, String> {
+    match &source.source_type {
+        ExternalDataSourceType::File(file) => {
+            let content = tokio::fs::read_to_string(&file.path)
+                .await
+                .map_err(|err| {
+                    format!("cannot read {}: {err}", file.path.display())
+                })?;
+            parse(&content, &file.format)
+        }
+        ExternalDataSourceType::Http(config) => {
+            let method = Method::from_bytes(config.method.as_bytes())
+                .map_err(|_| format!("invalid method '{}'", config.method))?;
+            let mut request = http
+                .request(method, config.url.clone())
+                .timeout(Duration::from_secs(config.timeout_secs));
+            for (name, value) in &config.headers {
+                request = request.header(name, value);
+            }
+            if let Some(body) = &config.body {
+                request = request.body(body.clone());
+            }
+            request = match &config.auth {
+                Some(HttpAuth::Basic { username, password }) => {
+                    request.basic_auth(username, Some(password))
+                }
+                Some(HttpAuth::Bearer { token }) => {
+                    request.bearer_auth(token)
+                }
+                Some(HttpAuth::ApiKey { header, value }) => {
+                    request.header(header, value)
+                }
+                None => request,
+            };
+            let response = request
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|err| err.to_string())?;
+            let content_type = config.content_type.clone().or_else(|| {
+                response
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .map(Into::into)
+            });
+            let content =
+                response.text().await.map_err(|err| err.to_string())?;
+            let format =
+                FileFormat::for_content_type(content_type.as_deref());
+            parse(&content, &format)
+        }
+        other => {
+            Err(format!("{} sources are not supported", other.name()))
+        }
+    }
+}
+
+/// Parses the content of a source.
+///
+/// CSV becomes an array of objects with the columns named in the header,
+/// text an array of its lines, leaving out empty lines and comments.
+fn parse(
+    content: &str,
+    format: &FileFormat,
+) -> Result<ExternalDataValue, String> {
+    match format {
+        FileFormat::Json => {
+            serde_json::from_str(content).map_err(|err| err.to_string())
+        }
+        FileFormat::Toml => {
+            toml::from_str(content).map_err(|err| err.to_string())
+        }
+        FileFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(content.as_bytes());
+            let headers =
+                reader.headers().map_err(|err| err.to_string())?.clone();
+            reader
+                .records()
+                .map(|record| {
+                    let record = record.map_err(|err| err.to_string())?;
+                    Ok(ExternalDataValue::Object(
+                        headers
+                            .iter()
+                            .zip(record.iter())
+                            .map(|(name, value)| {
+                                (
+                                    name.to_string(),
+                                    ExternalDataValue::String(value.into()),
+                                )
+                            })
+                            .collect(),
+                    ))
+                })
+                .collect::<Result<_, String>>()
+                .map(ExternalDataValue::Array)
+        }
+        FileFormat::Text => Ok(ExternalDataValue::Array(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| ExternalDataValue::String(line.into()))
+                .collect(),
+        )),
+        FileFormat::Yaml => Err("YAML is not supported".into()),
     }
 }
 
@@ -467,35 +1034,17 @@ pub trait ExternalDataAccess {
     
     /// Get external data as string
     fn get_external_string(&self, source_id: &str) -> Option<String> {
-        match self.get_external_data(source_id)? {
-            ExternalDataValue::String(s) => Some(s),
-            ExternalDataValue::Number(n) => Some(n.to_string()),
-            ExternalDataValue::Boolean(b) => Some(b.to_string()),
-            _ => None,
-        }
+        self.get_external_data(source_id)?.as_text()
     }
     
     /// Get external data as number
     fn get_external_number(&self, source_id: &str) -> Option<f64> {
-        match self.get_external_data(source_id)? {
-            ExternalDataValue::Number(n) => Some(n),
-            ExternalDataValue::String(s) => s.parse().ok(),
-            _ => None,
-        }
+        self.get_external_data(source_id)?.as_number()
     }
     
     /// Get external data as boolean
     fn get_external_boolean(&self, source_id: &str) -> Option<bool> {
-        match self.get_external_data(source_id)? {
-            ExternalDataValue::Boolean(b) => Some(b),
-            ExternalDataValue::String(s) => match s.to_lowercase().as_str() {
-                "true" | "1" | "yes" | "on" => Some(true),
-                "false" | "0" | "no" | "off" => Some(false),
-                _ => None,
-            },
-            ExternalDataValue::Number(n) => Some(n != 0.0),
-            _ => None,
-        }
+        self.get_external_data(source_id)?.as_bool()
     }
 }
 
@@ -576,4 +1125,296 @@ mod tests {
             _ => panic!("Expected object"),
         }
     }
-}
\ No newline at end of file
+
+    fn source(toml: &str) -> ExternalDataSource {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn sources_are_checked() {
+        assert!(source("id = \"customers\"\ntype = \"file\"\npath = \"x\"")
+            .check()
+            .is_ok());
+        assert!(source("id = \"my-list\"\ntype = \"file\"\npath = \"x\"")
+            .check()
+            .is_err());
+        assert!(source("id = \"1st\"\ntype = \"file\"\npath = \"x\"")
+            .check()
+            .is_err());
+        assert!(source(
+            "id = \"x\"\ntype = \"file\"\npath = \"x\"\nformat = \"yaml\""
+        )
+        .check()
+        .is_err());
+        assert!(source("id = \"x\"\ntype = \"http\"\nurl = \"https://a/\"")
+            .check()
+            .is_err());
+        assert!(source(
+            "id = \"x\"\ntype = \"redis\"\nurl = \"redis://a/\"\nkey = \"k\""
+        )
+        .check()
+        .is_err());
+
+        let mut manager = ExternalDataManager::new();
+        let twice = [
+            source("id = \"x\"\ntype = \"file\"\npath = \"a\""),
+            source("id = \"x\"\ntype = \"file\"\npath = \"b\""),
+        ];
+        assert!(manager.configure(&twice).is_err());
+
+        let irr = source("id = \"radb\"\ntype = \"irr\"");
+        assert!(irr.check().is_ok());
+        let mut other = irr.clone();
+        other.id = "ripe".into();
+        assert!(manager.configure(&[irr.clone(), other]).is_err());
+
+        let dns = source("id = \"resolver\"\ntype = \"dns\"");
+        assert!(dns.check().is_ok());
+        assert!(manager.configure(&[irr, dns.clone()]).is_ok());
+        let mut other = dns.clone();
+        other.id = "other".into();
+        assert!(manager.configure(&[dns, other]).is_err());
+    }
+
+    #[test]
+    fn formats_are_parsed() {
+        let csv = parse("asn,name\nAS65000,Example\n", &FileFormat::Csv)
+            .unwrap();
+        let ExternalDataValue::Array(rows) = csv else {
+            panic!("Expected array");
+        };
+        assert_eq!(
+            rows[0].get("name"),
+            Some(&ExternalDataValue::String("Example".into()))
+        );
+
+        let text = parse("# customers\nAS65000\n\n65001\n", &FileFormat::Text)
+            .unwrap();
+        assert!(text.contains("AS65000"));
+        assert!(text.contains("65001"));
+        assert!(!text.contains("# customers"));
+
+        let toml = parse("limit = 10\nenabled = true", &FileFormat::Toml)
+            .unwrap();
+        assert_eq!(toml.get("limit").unwrap().as_number(), Some(10.0));
+        assert_eq!(toml.get("enabled").unwrap().as_bool(), Some(true));
+
+        assert!(parse("{", &FileFormat::Json).is_err());
+        assert!(matches!(
+            FileFormat::for_content_type(Some("text/csv; charset=utf-8")),
+            FileFormat::Csv
+        ));
+    }
+
+    #[test]
+    fn values_are_looked_up() {
+        let value: ExternalDataValue = serde_json::from_str(
+            r#"{"asns": ["AS65000", 65001], "prefixes": ["192.0.2.0/24"]}"#,
+        )
+        .unwrap();
+        let asns = value.get("asns").unwrap();
+        assert!(asns.contains_asn(Asn::from_u32(65000)));
+        assert!(asns.contains_asn(Asn::from_u32(65001)));
+        assert!(!asns.contains_asn(Asn::from_u32(65002)));
+
+        let prefixes = value.get("prefixes").unwrap();
+        assert!(prefixes.covers(Prefix::from_str("192.0.2.128/25").unwrap()));
+        let other = Prefix::from_str("198.51.100.0/24").unwrap();
+        assert!(!prefixes.covers(other));
+        assert!(value.contains("asns"));
+        assert!(!value.contains("communities"));
+    }
+
+    #[tokio::test]
+    async fn files_are_fetched() {
+        let path = std::env::temp_dir().join(format!(
+            "rotonda-external-data-{}.txt",
+            std::process::id()
+        ));
+        std::fs::write(&path, "AS65000\n").unwrap();
+        let source = source(&format!(
+            "id = \"customers\"\ntype = \"file\"\npath = {:?}\n\
+            format = \"text\"",
+            path
+        ));
+        let value = fetch(&source, &HttpClient::new()).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(value.contains_asn(Asn::from_u32(65000)));
+    }
+
+    #[test]
+    fn sources_are_roto_constants() {
+        use crate::roto_runtime::Ctx;
+
+        let mut manager = ExternalDataManager::new();
+        manager
+            .configure(&[source(
+                "id = \"customers\"\ntype = \"file\"\npath = \"x\"",
+            )])
+            .unwrap();
+
+        let script = r#"
+            function is_customer(asn: Asn) -> bool {
+                customers.contains_asn(asn)
+            }
+        "#;
+        let mut rt = crate::roto_runtime::create_runtime().unwrap();
+        manager.register(&mut rt).unwrap();
+        let mut compiled = roto::FileTree::test_file("test", script, 0)
+            .compile(rt)
+            .unwrap();
+        let is_customer = compiled
+            .get_function::<Ctx, fn(Asn) -> bool>("is_customer")
+            .unwrap();
+
+        let mut ctx = Ctx::empty();
+        assert!(!is_customer.call(&mut ctx, Asn::from_u32(65000)));
+
+        manager.data["customers"].set(CachedData::new(
+            ExternalDataValue::Array(vec![ExternalDataValue::String(
+                "AS65000".into(),
+            )]),
+            Duration::from_secs(60),
+        ));
+        assert!(is_customer.call(&mut ctx, Asn::from_u32(65000)));
+        assert!(!is_customer.call(&mut ctx, Asn::from_u32(65001)));
+        assert!(manager
+            .get_external_data("customers")
+            .is_some_and(|value| value.contains("AS65000")));
+
+        // Referring to a source that is not configured fails to compile
+        let script = r#"
+            function is_peer(asn: Asn) -> bool {
+                peers.contains_asn(asn)
+            }
+        "#;
+        let mut rt = crate::roto_runtime::create_runtime().unwrap();
+        manager.register(&mut rt).unwrap();
+        assert!(roto::FileTree::test_file("test", script, 0)
+            .compile(rt)
+            .is_err());
+    }
+
+    #[test]
+    fn route_leaks_are_found_in_roto() {
+        use roto::Val;
+
+        use crate::roto_runtime::{Ctx, MutRotondaRoute};
+        use crate::units::http_in::batch::{Attributes, PeerUpdate};
+
+        let mut manager = ExternalDataManager::new();
+        manager
+            .configure(&[source(
+                "id = \"as_rel\"\ntype = \"file\"\npath = \"x\"\n\
+                format = \"text\"",
+            )])
+            .unwrap();
+        manager.data["as_rel"].set(CachedData::new(
+            parse("1|2|0\n1|10|-1\n2|10|-1\n", &FileFormat::Text)
+                .unwrap(),
+            Duration::from_secs(60),
+        ));
+
+        let script = r#"
+            function leaker(route: Route) -> Asn {
+                as_rel.route_leaker(route)
+            }
+
+            function valley_free(route: Route) -> bool {
+                as_rel.is_valley_free(route)
+            }
+        "#;
+        let mut rt = crate::roto_runtime::create_runtime().unwrap();
+        manager.register(&mut rt).unwrap();
+        let mut compiled = roto::FileTree::test_file("test", script, 0)
+            .compile(rt)
+            .unwrap();
+        let leaker = compiled
+            .get_function::<Ctx, fn(Val<MutRotondaRoute>) -> Asn>("leaker")
+            .unwrap();
+        let valley_free = compiled
+            .get_function::<Ctx, fn(Val<MutRotondaRoute>) -> bool>(
+                "valley_free",
+            )
+            .unwrap();
+
+        let route = |as_path: Vec<u32>| {
+            let update = PeerUpdate {
+                peer_address: "192.0.2.1".parse().unwrap(),
+                peer_asn: as_path[0],
+                announce: vec!["198.51.100.0/24".parse().unwrap()],
+                withdraw: vec![],
+                attributes: Attributes {
+                    as_path,
+                    next_hop: Some("192.0.2.1".parse().unwrap()),
+                    ..Default::default()
+                },
+                peer_down: false,
+            };
+            Val(update.routes().unwrap().announced.remove(0).into())
+        };
+        let mut ctx = Ctx::empty();
+        assert_eq!(
+            leaker.call(&mut ctx, route(vec![2, 10, 1])),
+            Asn::from_u32(10)
+        );
+        assert!(!valley_free.call(&mut ctx, route(vec![2, 10, 1])));
+        assert_eq!(
+            leaker.call(&mut ctx, route(vec![2, 1, 10])),
+            Asn::from_u32(0)
+        );
+        assert!(valley_free.call(&mut ctx, route(vec![2, 1, 10])));
+    }
+
+    #[test]
+    fn prefix_sets_are_matched_in_roto() {
+        use crate::roto_runtime::Ctx;
+
+        let mut manager = ExternalDataManager::new();
+        manager
+            .configure(&[source(
+                "id = \"bogons\"\ntype = \"file\"\npath = \"x\"",
+            )])
+            .unwrap();
+        manager.data["bogons"].set(CachedData::new(
+            serde_json::from_str(
+                r#"{"10.0.0.0/8": {"rank": 1}, "10.1.0.0/16": {"rank": 2}}"#,
+            )
+            .unwrap(),
+            Duration::from_secs(60),
+        ));
+
+        let script = r#"
+            function bogon_rank(prefix: Prefix) -> i64 {
+                let found = prefix_set_match("bogons", prefix);
+                if found.matched() {
+                    found.get_int("rank")
+                } else {
+                    0
+                }
+            }
+
+            function is_unknown(prefix: Prefix) -> bool {
+                prefix_set_match("unknown", prefix).matched()
+            }
+        "#;
+        let mut rt = crate::roto_runtime::create_runtime().unwrap();
+        manager.register(&mut rt).unwrap();
+        let mut compiled = roto::FileTree::test_file("test", script, 0)
+            .compile(rt)
+            .unwrap();
+        let bogon_rank = compiled
+            .get_function::<Ctx, fn(Prefix) -> i64>("bogon_rank")
+            .unwrap();
+        let is_unknown = compiled
+            .get_function::<Ctx, fn(Prefix) -> bool>("is_unknown")
+            .unwrap();
+
+        let mut ctx = Ctx::empty();
+        let prefix = |s| Prefix::from_str(s).unwrap();
+        assert_eq!(bogon_rank.call(&mut ctx, prefix("10.1.2.0/24")), 2);
+        assert_eq!(bogon_rank.call(&mut ctx, prefix("10.2.0.0/16")), 1);
+        assert_eq!(bogon_rank.call(&mut ctx, prefix("192.0.2.0/24")), 0);
+        assert!(!is_unknown.call(&mut ctx, prefix("10.1.2.0/24")));
+    }
+}
@@ -0,0 +1,469 @@
+//! Execution metrics of Roto filters and the handling of slow ones.
+//!
+//! Every invocation of a filter is timed, the durations kept in a histogram
+//! per filter along with the number of inputs accepted and rejected. These
+//! are exported as the `roto_filter_*` metrics, with the name of the filter
+//! in the `filter` label. The statistics of each filter are atomic counters
+//! of its own, so that units running filters concurrently do not contend
+//! for a lock.
+//!
+//! The `[roto_slow_filters]` section sets how long a single invocation may
+//! take. This does not bound the time spent in a filter: compiled filters
+//! cannot be interrupted while they run, so a slow invocation always runs
+//! to completion. Its verdict is discarded instead: the input is rejected
+//! and counted as too slow. With `suspend_secs`, such a filter is not run
+//! at all for that long, rejecting its input, so that a pathological script
+//! stalls the units calling it for a single invocation rather than for
+//! every input.
+//!
+//! A unit with a `shadow` section runs its filter in shadow mode: the
+//! verdicts are recorded, but all input is passed on unchanged. The inputs
+//! that would have been rejected are counted and, at the configured rate,
+//! logged, so that a new policy can be evaluated before it is enforced.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
+use log::{info, warn};
+use roto::Verdict;
+use serde::Deserialize;
+
+use crate::metrics::{self, Metric, MetricType, MetricUnit};
+
+use super::sampling;
+
+/// The upper bounds of the duration histogram buckets in microseconds.
+const DURATION_BUCKETS: [u64; 10] =
+    [1, 2, 5, 10, 20, 50, 100, 1_000, 10_000, 100_000];
+
+/// Returns the metrics of all filters.
+pub fn shared() -> &'static Arc<FilterMetrics> {
+    static METRICS: OnceLock<Arc<FilterMetrics>> = OnceLock::new();
+    METRICS.get_or_init(Default::default)
+}
+
+/// Returns the microseconds from the first call of this function to `time`.
+fn micros_since_start(time: Instant) -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    let start = *START.get_or_init(Instant::now);
+    u64::try_from(time.saturating_duration_since(start).as_micros())
+        .unwrap_or(u64::MAX)
+}
+
+//------------ Configuration -------------------------------------------------
+
+/// How slow invocations of the filters are handled.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SlowFilterConfig {
+    /// How long a single invocation may take before its verdict is
+    /// discarded.
+    pub reject_above_micros: Option<u64>,
+
+    /// For how long not to run a filter after a slow invocation.
+    #[serde(default)]
+    pub suspend_secs: u64,
+}
+
+impl SlowFilterConfig {
+    fn max_duration(&self) -> Option<Duration> {
+        self.reject_above_micros.map(Duration::from_micros)
+    }
+}
+
+/// Running the filter of a unit in shadow mode.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ShadowConfig {
+    /// The fraction of the would-be rejected inputs to log.
+    #[serde(default)]
+    pub log_rate: f64,
+}
+
+impl ShadowConfig {
+    /// Records that filter `name` would have rejected an input.
+    ///
+    /// The input, described by `input`, is passed on anyway.
+    pub fn rejected<T: fmt::Display>(
+        &self,
+        name: &'static str,
+        input: impl FnOnce() -> T,
+    ) {
+        shared().record_shadowed(name);
+        if self.log_rate > 0.0 && sampling::sample(self.log_rate) {
+            info!(
+                "Shadow mode: roto filter '{name}' would have rejected {}",
+                input()
+            );
+        }
+    }
+}
+
+//------------ FilterMetrics -------------------------------------------------
+
+/// Execution statistics by filter name.
+#[derive(Debug, Default)]
+pub struct FilterMetrics {
+    slow: ArcSwap<SlowFilterConfig>,
+
+    /// The statistics of the filters run so far.
+    ///
+    /// Only locked for writing when a filter is run for the first time.
+    filters: RwLock<HashMap<&'static str, Arc<FilterStats>>>,
+}
+
+#[derive(Debug, Default)]
+struct FilterStats {
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    too_slow: AtomicU64,
+    skipped: AtomicU64,
+    shadowed: AtomicU64,
+
+    /// The number of invocations per bucket of [`DURATION_BUCKETS`] and a
+    /// last one for those exceeding all.
+    buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+
+    /// The sum of all durations in microseconds.
+    duration_sum: AtomicU64,
+
+    /// Until when the filter is not run because it was too slow, see
+    /// [`micros_since_start`], or zero if it is run.
+    suspended_until: AtomicU64,
+
+    /// Whether a warning about being too slow was logged.
+    warned: AtomicBool,
+}
+
+impl FilterStats {
+    /// Returns whether the filter is suspended, counting it if so.
+    fn is_suspended(&self, now: Instant) -> bool {
+        let until = self.suspended_until.load(Relaxed);
+        if until == 0 {
+            return false;
+        }
+        if until > micros_since_start(now) {
+            self.skipped.fetch_add(1, Relaxed);
+            return true;
+        }
+        let _ = self
+            .suspended_until
+            .compare_exchange(until, 0, Relaxed, Relaxed);
+        false
+    }
+}
+
+impl FilterMetrics {
+    const DURATION_METRIC: Metric = Metric::new(
+        "roto_filter_duration",
+        "a histogram of the time roto filter invocations took, by filter",
+        MetricType::Histogram,
+        MetricUnit::Microsecond,
+    );
+    const ACCEPTED_METRIC: Metric = Metric::new(
+        "roto_filter_accepted",
+        "the number of inputs accepted by roto filters, by filter",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const REJECTED_METRIC: Metric = Metric::new(
+        "roto_filter_rejected",
+        "the number of inputs rejected by roto filters, by filter",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const TOO_SLOW_METRIC: Metric = Metric::new(
+        "roto_filter_too_slow",
+        "the number of roto filter invocations whose verdict was discarded \
+        for taking too long, by filter",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const SHADOWED_METRIC: Metric = Metric::new(
+        "roto_filter_shadowed",
+        "the number of inputs rejected by roto filters in shadow mode and \
+        passed on anyway, by filter",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const SKIPPED_METRIC: Metric = Metric::new(
+        "roto_filter_skipped",
+        "the number of inputs rejected without running the roto filter \
+        while it was suspended, by filter",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+
+    /// Sets how slow invocations of all filters are handled.
+    pub fn set_slow_filters(&self, config: SlowFilterConfig) {
+        self.slow.store(Arc::new(config));
+        for stats in self.filters.read().unwrap().values() {
+            stats.suspended_until.store(0, Relaxed);
+            stats.warned.store(false, Relaxed);
+        }
+    }
+
+    /// Runs the filter `name`, recording its execution.
+    ///
+    /// Returns the verdict of `filter`, or a rejection if the filter is
+    /// suspended or was too slow.
+    pub fn run<A, R: Default>(
+        &self,
+        name: &'static str,
+        filter: impl FnOnce() -> Verdict<A, R>,
+    ) -> Verdict<A, R> {
+        let stats = self.stats(name);
+        if stats.is_suspended(Instant::now()) {
+            return Verdict::Reject(R::default());
+        }
+        let start = Instant::now();
+        let verdict = filter();
+        let accepted = matches!(verdict, Verdict::Accept(_));
+        if self.record(
+            name,
+            &stats,
+            start.elapsed(),
+            accepted,
+            Instant::now(),
+        ) {
+            verdict
+        } else {
+            Verdict::Reject(R::default())
+        }
+    }
+
+    /// Returns the statistics of the filter `name`.
+    fn stats(&self, name: &'static str) -> Arc<FilterStats> {
+        if let Some(stats) = self.filters.read().unwrap().get(name) {
+            return stats.clone();
+        }
+        self.filters
+            .write()
+            .unwrap()
+            .entry(name)
+            .or_default()
+            .clone()
+    }
+
+    /// Records a rejection of the filter `name` that was ignored.
+    fn record_shadowed(&self, name: &'static str) {
+        self.stats(name).shadowed.fetch_add(1, Relaxed);
+    }
+
+    /// Records an invocation, returning whether it was fast enough.
+    fn record(
+        &self,
+        name: &'static str,
+        stats: &FilterStats,
+        duration: Duration,
+        accepted: bool,
+        now: Instant,
+    ) -> bool {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        stats.buckets[bucket].fetch_add(1, Relaxed);
+        stats.duration_sum.fetch_add(micros, Relaxed);
+
+        let slow = self.slow.load();
+        let suspend = Duration::from_secs(slow.suspend_secs);
+        match slow.max_duration() {
+            Some(max) if duration > max => {
+                stats.too_slow.fetch_add(1, Relaxed);
+                if !suspend.is_zero() {
+                    stats
+                        .suspended_until
+                        .store(micros_since_start(now + suspend), Relaxed);
+                    warn!(
+                        "Roto filter '{name}' took {micros}µs, more than \
+                        {}µs, suspending it for {}s",
+                        max.as_micros(),
+                        suspend.as_secs()
+                    );
+                } else if !stats.warned.swap(true, Relaxed) {
+                    warn!(
+                        "Roto filter '{name}' took {micros}µs, more than \
+                        {}µs, rejecting its input",
+                        max.as_micros()
+                    );
+                }
+                false
+            }
+            _ => {
+                if accepted {
+                    stats.accepted.fetch_add(1, Relaxed);
+                } else {
+                    stats.rejected.fetch_add(1, Relaxed);
+                }
+                true
+            }
+        }
+    }
+}
+
+impl metrics::Source for FilterMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        let filters = self.filters.read().unwrap();
+        if filters.is_empty() {
+            return;
+        }
+        for (metric, value) in [
+            (
+                &Self::ACCEPTED_METRIC,
+                (|stats| &stats.accepted) as fn(&FilterStats) -> &AtomicU64,
+            ),
+            (&Self::REJECTED_METRIC, |stats| &stats.rejected),
+            (&Self::TOO_SLOW_METRIC, |stats| &stats.too_slow),
+            (&Self::SKIPPED_METRIC, |stats| &stats.skipped),
+            (&Self::SHADOWED_METRIC, |stats| &stats.shadowed),
+        ] {
+            target.append(metric, Some(unit_name), |records| {
+                for (name, stats) in filters.iter() {
+                    records.label_value(
+                        &[("filter", name)],
+                        value(stats).load(Relaxed),
+                    );
+                }
+            });
+        }
+        target.append(&Self::DURATION_METRIC, Some(unit_name), |records| {
+            for (name, stats) in filters.iter() {
+                let bounds = DURATION_BUCKETS
+                    .iter()
+                    .map(ToString::to_string)
+                    .chain(["+Inf".to_string()]);
+                let mut count = 0;
+                for (bound, bucket) in bounds.zip(&stats.buckets) {
+                    count += bucket.load(Relaxed);
+                    records.suffixed_label_value(
+                        &[("filter", name), ("le", &bound)],
+                        count,
+                        Some("bucket"),
+                    );
+                }
+                records.suffixed_label_value(
+                    &[("filter", name)],
+                    stats.duration_sum.load(Relaxed),
+                    Some("sum"),
+                );
+                records.suffixed_label_value(
+                    &[("filter", name)],
+                    count,
+                    Some("count"),
+                );
+            }
+        });
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slow(verdict: Verdict<(), ()>) -> impl FnOnce() -> Verdict<(), ()> {
+        move || {
+            std::thread::sleep(Duration::from_millis(5));
+            verdict
+        }
+    }
+
+    #[test]
+    fn invocations_are_counted() {
+        let metrics = Arc::new(FilterMetrics::default());
+        for _ in 0..2 {
+            assert!(matches!(
+                metrics.run("rib_in_pre", || Verdict::<(), ()>::Accept(())),
+                Verdict::Accept(())
+            ));
+        }
+        metrics.run("rib_in_pre", || Verdict::<(), ()>::Reject(()));
+        metrics.run("bmp_in", || Verdict::<(), ()>::Accept(()));
+
+        let target =
+            crate::tests::util::internal::get_testable_metrics_snapshot(
+                &metrics,
+            );
+        let value = |metric, filter| {
+            target.with_label::<u64>(metric, ("filter", filter))
+        };
+        assert_eq!(value("roto_filter_accepted", "rib_in_pre"), 2);
+        assert_eq!(value("roto_filter_rejected", "rib_in_pre"), 1);
+        assert_eq!(value("roto_filter_accepted", "bmp_in"), 1);
+        assert_eq!(value("roto_filter_too_slow", "rib_in_pre"), 0);
+        assert_eq!(value("roto_filter_shadowed", "rib_in_pre"), 0);
+        assert_eq!(
+            target.with_labels::<u64>(
+                "roto_filter_duration",
+                &[("filter", "rib_in_pre"), ("le", "+Inf")],
+            ),
+            3
+        );
+    }
+
+    #[test]
+    fn verdicts_of_slow_invocations_are_discarded() {
+        let metrics = FilterMetrics::default();
+        metrics.set_slow_filters(SlowFilterConfig {
+            reject_above_micros: Some(1000),
+            suspend_secs: 0,
+        });
+        assert!(matches!(
+            metrics.run("rib_in_pre", slow(Verdict::Accept(()))),
+            Verdict::Reject(())
+        ));
+        assert!(matches!(
+            metrics.run("rib_in_pre", || Verdict::<(), ()>::Accept(())),
+            Verdict::Accept(())
+        ));
+        let stats = metrics.stats("rib_in_pre");
+        assert_eq!(stats.too_slow.load(Relaxed), 1);
+        assert_eq!(stats.accepted.load(Relaxed), 1);
+        assert_eq!(stats.rejected.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn slow_filters_are_suspended() {
+        let metrics = FilterMetrics::default();
+        metrics.set_slow_filters(SlowFilterConfig {
+            reject_above_micros: Some(1000),
+            suspend_secs: 60,
+        });
+        metrics.run("bmp_in", slow(Verdict::Accept(())));
+        let mut ran = false;
+        assert!(matches!(
+            metrics.run("bmp_in", || {
+                ran = true;
+                Verdict::<(), ()>::Accept(())
+            }),
+            Verdict::Reject(())
+        ));
+        assert!(!ran);
+        assert_eq!(metrics.stats("bmp_in").skipped.load(Relaxed), 1);
+
+        // Other filters still run, and the suspension ends.
+        assert!(matches!(
+            metrics.run("rib_in_pre", || Verdict::<(), ()>::Accept(())),
+            Verdict::Accept(())
+        ));
+        assert!(!metrics
+            .stats("bmp_in")
+            .is_suspended(Instant::now() + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn shadowed_rejections_are_counted() {
+        let shadow = ShadowConfig { log_rate: 1.0 };
+        shadow.rejected("shadow_test", || "192.0.2.0/24");
+        shadow.rejected("shadow_test", || "198.51.100.0/24");
+        let shadowed = shared().stats("shadow_test").shadowed.load(Relaxed);
+        assert_eq!(shadowed, 2);
+    }
+}
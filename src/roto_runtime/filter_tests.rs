@@ -0,0 +1,436 @@
+//! Regression tests for Roto filters.
+//!
+//! `rotonda test-filters tests/*.toml` runs the test cases in the given
+//! files against the filters of a Roto script, without starting any units.
+//! A test file names the script, relative to the test file, and the filter
+//! taking a route to run, `rib_in_pre` by default. Each case gives the
+//! routes to feed the filter and the expected outcome:
+//!
+//! ```toml
+//! roto_script = "../filters.roto"
+//!
+//! [[case]]
+//! name = "bogons are rejected"
+//! expect = "reject"
+//!
+//! [[case.routes]]
+//! peer_address = "192.0.2.1"
+//! peer_asn = 65000
+//! prefixes = ["10.0.0.0/8"]
+//! attributes = { next_hop = "192.0.2.1", as_path = [65000] }
+//!
+//! [[case]]
+//! name = "customer routes are tagged"
+//! json = "customer-routes.json"
+//! expect = "accept"
+//! tags = { customer = true }
+//! ribs = ["customers-only"]
+//! ```
+//!
+//! Routes are given inline with `routes`, in the format of the
+//! `static-routes-in` unit, in a JSON file of the same shape with `json`,
+//! i.e., an object with a `routes` array, or as an MRT RIB dump with `mrt`.
+//! All routes of a case must be accepted or rejected as expected. If `tags`
+//! or `ribs` are given, the filter must also have tagged each route with
+//! exactly these tags and selected exactly these named RIBs.
+//!
+//! The filters run with an empty context: there are no VRPs, external data
+//! or state from earlier runs.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use log::error;
+use routecore::bgp::message::PduParseInfo;
+use routecore::bgp::path_attributes::OwnedPathAttributes;
+use routecore::bgp::types::AfiSafiType;
+use routecore::mrt::MrtFile;
+use serde::Deserialize;
+
+use super::types::Tags;
+use super::{create_runtime, Ctx, MutRotondaRoute};
+use crate::log::Terminate;
+use crate::payload::{RotondaPaMap, RotondaRoute};
+use crate::units::static_routes_in::routes::{RouteSet, RoutesFile};
+
+const CMD_TEST_FILTERS: &str = "test-filters";
+const ARG_FILES: &str = "files";
+
+/// The filter run if a test file names none.
+const DEFAULT_FILTER: &str = "rib_in_pre";
+
+type RouteFilter = roto::TypedFunc<
+    Ctx,
+    fn(roto::Val<MutRotondaRoute>) -> roto::Verdict<(), ()>,
+>;
+
+//------------ Command Line --------------------------------------------------
+
+/// Configures a clap app with the `test-filters` subcommand.
+pub fn command(app: Command) -> Command {
+    app.subcommand_negates_reqs(true).subcommand(
+        Command::new(CMD_TEST_FILTERS)
+            .about(
+                "Runs the Roto filter tests in the given files, then exits",
+            )
+            .arg(
+                Arg::new(ARG_FILES)
+                    .required(true)
+                    .action(ArgAction::Append)
+                    .value_name("PATH")
+                    .help("Test files to run"),
+            ),
+    )
+}
+
+/// Runs the `test-filters` subcommand if it was given.
+///
+/// Returns `None` if it was not. Otherwise, runs all test files, printing
+/// the result of each case, and returns an error if any case failed or a
+/// test file could not be run.
+pub fn run_arg_matches(
+    args: &ArgMatches,
+    cur_dir: &Path,
+) -> Option<Result<(), Terminate>> {
+    let args = args.subcommand_matches(CMD_TEST_FILTERS)?;
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut broken = false;
+    for path in args.get_many::<String>(ARG_FILES).into_iter().flatten() {
+        let path = cur_dir.join(path);
+        let results = match TestFile::load(&path).and_then(|file| file.run())
+        {
+            Ok(results) => results,
+            Err(err) => {
+                error!("{}: {err}", path.display());
+                broken = true;
+                continue;
+            }
+        };
+        for (name, res) in results {
+            match res {
+                Ok(()) => {
+                    println!("{}: {name} ... ok", path.display());
+                    passed += 1;
+                }
+                Err(err) => {
+                    println!("{}: {name} ... FAILED: {err}", path.display());
+                    failed += 1;
+                }
+            }
+        }
+    }
+    println!("{passed} passed, {failed} failed");
+    Some(if failed > 0 || broken {
+        Err(Terminate::error())
+    } else {
+        Ok(())
+    })
+}
+
+//------------ TestFile ------------------------------------------------------
+
+/// A file of test cases for the filters of a script.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TestFile {
+    /// The script, relative to the test file.
+    roto_script: PathBuf,
+
+    /// The filter to run.
+    #[serde(default = "TestFile::default_filter")]
+    filter: String,
+
+    #[serde(default, rename = "case")]
+    cases: Vec<Case>,
+
+    /// The directory of the test file.
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+impl TestFile {
+    fn default_filter() -> String {
+        DEFAULT_FILTER.into()
+    }
+
+    fn load(path: &Path) -> Result<Self, String> {
+        let content =
+            std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let mut res: Self =
+            toml::from_str(&content).map_err(|err| err.to_string())?;
+        res.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(res)
+    }
+
+    /// Runs the cases, returning the result of each by name.
+    ///
+    /// Returns an error if the script does not compile or does not have the
+    /// filter.
+    fn run(&self) -> Result<Vec<(String, Result<(), String>)>, String> {
+        let script = self.dir.join(&self.roto_script);
+        let mut compiled = roto::FileTree::read(&script)
+            .compile(create_runtime()?)
+            .map_err(|err| err.to_string())?;
+        let filter: RouteFilter =
+            compiled.get_function(&self.filter).map_err(|_| {
+                format!(
+                    "{} has no filter '{}' taking a route",
+                    script.display(),
+                    self.filter
+                )
+            })?;
+        let mut ctx = Ctx::empty();
+        ctx.prepare(&mut compiled);
+
+        Ok(self
+            .cases
+            .iter()
+            .enumerate()
+            .map(|(idx, case)| {
+                let name = case
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("case {}", idx + 1));
+                (name, case.run(&filter, &mut ctx, &self.dir))
+            })
+            .collect())
+    }
+}
+
+//------------ Case ----------------------------------------------------------
+
+/// Routes and the outcome expected for them.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Case {
+    name: Option<String>,
+
+    /// Routes given inline.
+    #[serde(default)]
+    routes: Vec<RouteSet>,
+
+    /// A JSON file of routes, relative to the test file.
+    json: Option<PathBuf>,
+
+    /// An MRT RIB dump, relative to the test file.
+    mrt: Option<PathBuf>,
+
+    expect: Verdict,
+
+    /// The tags the filter should set.
+    tags: Option<Tags>,
+
+    /// The named RIBs the filter should select.
+    ribs: Option<Vec<Arc<str>>>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Verdict {
+    Accept,
+    Reject,
+}
+
+impl Case {
+    fn run(
+        &self,
+        filter: &RouteFilter,
+        ctx: &mut Ctx,
+        dir: &Path,
+    ) -> Result<(), String> {
+        let routes = self.routes(dir)?;
+        if routes.is_empty() {
+            return Err("no routes to test".into());
+        }
+        for route in routes {
+            let prefix = route_prefix(&route);
+            ctx.ribs.lock().unwrap().take();
+            ctx.tags.lock().unwrap().take();
+            let verdict = match filter.call(ctx, roto::Val(route.into())) {
+                roto::Verdict::Accept(_) => Verdict::Accept,
+                roto::Verdict::Reject(_) => Verdict::Reject,
+            };
+            let ribs = ctx.ribs.lock().unwrap().take();
+            let tags = ctx.tags.lock().unwrap().take();
+
+            if verdict != self.expect {
+                return Err(format!(
+                    "{prefix}: expected {:?}, got {verdict:?}",
+                    self.expect
+                ));
+            }
+            if let Some(expected) = &self.tags {
+                if tags != *expected {
+                    return Err(format!(
+                        "{prefix}: expected tags {}, got {}",
+                        serde_json::to_string(expected).unwrap_or_default(),
+                        serde_json::to_string(&tags).unwrap_or_default(),
+                    ));
+                }
+            }
+            if let Some(expected) = &self.ribs {
+                if ribs != *expected {
+                    return Err(format!(
+                        "{prefix}: expected ribs {expected:?}, got {ribs:?}"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the routes of the case.
+    fn routes(&self, dir: &Path) -> Result<Vec<RotondaRoute>, String> {
+        let mut res = vec![];
+        for set in &self.routes {
+            res.extend(set.routes()?);
+        }
+        if let Some(path) = &self.json {
+            let path = dir.join(path);
+            let content = std::fs::read(&path)
+                .map_err(|err| format!("{}: {err}", path.display()))?;
+            let file: RoutesFile = serde_json::from_slice(&content)
+                .map_err(|err| format!("{}: {err}", path.display()))?;
+            for set in &file.routes {
+                res.extend(set.routes()?);
+            }
+        }
+        if let Some(path) = &self.mrt {
+            let path = dir.join(path);
+            res.extend(
+                mrt_routes(&path)
+                    .map_err(|err| format!("{}: {err}", path.display()))?,
+            );
+        }
+        Ok(res)
+    }
+}
+
+/// Returns the unicast routes in the MRT RIB dump at `path`.
+fn mrt_routes(path: &Path) -> Result<Vec<RotondaRoute>, String> {
+    let raw = std::fs::read(path).map_err(|err| err.to_string())?;
+    let mrt_file = MrtFile::new(&raw);
+    let mut res = vec![];
+    let entries = mrt_file
+        .rib_entries()
+        .map_err(|_| "not an MRT RIB dump".to_string())?;
+    for (afisafi, _, _, prefix, raw_attr) in entries {
+        let pamap = RotondaPaMap::new(OwnedPathAttributes::new(
+            PduParseInfo::modern(),
+            raw_attr,
+        ));
+        let invalid = |_| format!("invalid prefix {prefix}");
+        res.push(match afisafi {
+            AfiSafiType::Ipv4Unicast => RotondaRoute::Ipv4Unicast(
+                prefix.try_into().map_err(invalid)?,
+                pamap,
+            ),
+            AfiSafiType::Ipv6Unicast => RotondaRoute::Ipv6Unicast(
+                prefix.try_into().map_err(invalid)?,
+                pamap,
+            ),
+            _ => continue,
+        });
+    }
+    Ok(res)
+}
+
+fn route_prefix(route: &RotondaRoute) -> String {
+    match route {
+        RotondaRoute::Ipv4Unicast(nlri, _) => nlri.prefix().to_string(),
+        RotondaRoute::Ipv6Unicast(nlri, _) => nlri.prefix().to_string(),
+        RotondaRoute::Ipv4Multicast(nlri, _) => nlri.prefix().to_string(),
+        RotondaRoute::Ipv6Multicast(nlri, _) => nlri.prefix().to_string(),
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"
+        filter rib_in_pre(route: Route) {
+            if route.prefix_matches(10.0.0.0/8) {
+                reject
+            } else {
+                tags.set("customer", "yes");
+                ribs.add("customers-only");
+                accept
+            }
+        }
+    "#;
+
+    const TESTS: &str = r#"
+        roto_script = "filters.roto"
+
+        [[case]]
+        name = "bogons are rejected"
+        expect = "reject"
+
+        [[case.routes]]
+        peer_address = "192.0.2.1"
+        peer_asn = 65000
+        prefixes = ["10.0.0.0/8"]
+        attributes = { next_hop = "192.0.2.1", as_path = [65000] }
+
+        [[case]]
+        name = "customer routes are tagged"
+        json = "routes.json"
+        expect = "accept"
+        tags = { customer = "yes" }
+        ribs = ["customers-only"]
+
+        [[case]]
+        name = "wrong expectation"
+        json = "routes.json"
+        expect = "accept"
+        tags = { customer = "no" }
+
+        [[case]]
+        expect = "accept"
+    "#;
+
+    const ROUTES: &str = r#"{
+        "routes": [{
+            "peer_address": "192.0.2.1",
+            "peer_asn": 65000,
+            "prefixes": ["198.51.100.0/24", "203.0.113.0/24"],
+            "attributes": { "next_hop": "192.0.2.1" }
+        }]
+    }"#;
+
+    #[test]
+    fn cases_are_run() {
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-filter-tests-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("filters.roto"), SCRIPT).unwrap();
+        std::fs::write(dir.join("tests.toml"), TESTS).unwrap();
+        std::fs::write(dir.join("routes.json"), ROUTES).unwrap();
+
+        let results =
+            TestFile::load(&dir.join("tests.toml")).unwrap().run().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0], ("bogons are rejected".into(), Ok(())));
+        assert_eq!(results[1], ("customer routes are tagged".into(), Ok(())));
+        assert_eq!(
+            results[2],
+            (
+                "wrong expectation".into(),
+                Err("198.51.100.0/24: expected tags {\"customer\":\"no\"}, \
+                    got {\"customer\":\"yes\"}"
+                    .into())
+            )
+        );
+        assert_eq!(
+            results[3],
+            ("case 4".into(), Err("no routes to test".into()))
+        );
+    }
+}
@@ -0,0 +1,409 @@
+//! IRR data for Roto scripts.
+//!
+//! With an external data source of type `irr` configured, scripts can check
+//! routes against the Internet Routing Registry: `in_as_set(name, asn)`
+//! checks whether an AS is a member of an AS set, recursively expanded,
+//! and `irr_route_exists(prefix, asn)` whether there is a route object for
+//! the prefix with the AS as its origin.
+//!
+//! As filters run for every route and cannot wait for the registry, the
+//! answers come from a cache. An AS set or the route objects of an origin
+//! AS that are not in the cache are queried from the IRRd server in the
+//! background, the lookup meanwhile answering `false`. Cached answers are
+//! queried again once they are older than the `cache_ttl_secs` of the
+//! source, the old answer being used until the new one arrives.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use inetnum::{addr::Prefix, asn::Asn};
+use log::{debug, error};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use super::external_data::IrrDataSource;
+
+/// The number of AS sets and origin ASes kept in the cache.
+///
+/// Beyond this the cache is emptied, so that everything is queried anew.
+const MAX_ENTRIES: usize = 10_000;
+
+/// How long to wait before querying again after a failed query.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// The source used by the Roto functions.
+static CURRENT: RwLock<Option<Arc<Irr>>> = RwLock::new(None);
+
+/// Returns the IRR source used by the Roto functions, if any.
+pub fn current() -> Option<Arc<Irr>> {
+    CURRENT.read().ok()?.clone()
+}
+
+/// Sets the IRR source used by the Roto functions.
+pub fn set_current(irr: Option<Arc<Irr>>) {
+    if let Ok(mut current) = CURRENT.write() {
+        *current = irr;
+    }
+}
+
+//------------ Irr -----------------------------------------------------------
+
+/// The cached answers of an IRRd server.
+#[derive(Debug)]
+pub struct Irr {
+    config: IrrDataSource,
+
+    /// How long answers are used before asking again.
+    ttl: Duration,
+
+    state: Mutex<State>,
+
+    /// The queries for the background task.
+    queries: mpsc::UnboundedSender<Query>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The members of AS sets by name.
+    as_sets: HashMap<Arc<str>, Entry<HashSet<Asn>>>,
+
+    /// The prefixes of the route objects by origin AS.
+    routes: HashMap<Asn, Entry<HashSet<Prefix>>>,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    /// The last answer, if any.
+    value: Option<Arc<T>>,
+
+    /// When to query again.
+    expires: Option<Instant>,
+
+    /// Whether a query is underway.
+    pending: bool,
+}
+
+impl<T> Default for Entry<T> {
+    fn default() -> Self {
+        Self {
+            value: None,
+            expires: None,
+            pending: false,
+        }
+    }
+}
+
+/// A query for the background task.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Query {
+    AsSet(Arc<str>),
+    Routes(Asn),
+}
+
+impl Irr {
+    /// Creates the source, returning the queries for
+    /// [`run`][Self::run].
+    pub fn new(
+        config: IrrDataSource,
+        ttl: Duration,
+    ) -> (Arc<Self>, mpsc::UnboundedReceiver<Query>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let res = Self {
+            config,
+            ttl,
+            state: Default::default(),
+            queries: tx,
+        };
+        (Arc::new(res), rx)
+    }
+
+    /// Returns whether `asn` is a member of the AS set `name`.
+    pub fn in_as_set(&self, name: &str, asn: Asn) -> bool {
+        let name: Arc<str> = name.to_ascii_uppercase().into();
+        self.lookup(
+            |state| &mut state.as_sets,
+            &name,
+            || Query::AsSet(name.clone()),
+        )
+        .is_some_and(|members| members.contains(&asn))
+    }
+
+    /// Returns whether there is a route object for `prefix` and `asn`.
+    pub fn route_exists(&self, prefix: Prefix, asn: Asn) -> bool {
+        self.lookup(|state| &mut state.routes, &asn, || Query::Routes(asn))
+            .is_some_and(|prefixes| prefixes.contains(&prefix))
+    }
+
+    /// Returns the cached answer for `key`.
+    ///
+    /// Queues a query if there is none or it has expired.
+    fn lookup<K: Clone + Eq + Hash, T>(
+        &self,
+        map: impl Fn(&mut State) -> &mut HashMap<K, Entry<T>>,
+        key: &K,
+        query: impl FnOnce() -> Query,
+    ) -> Option<Arc<T>> {
+        let mut state = self.state.lock().unwrap();
+        let map = map(&mut *state);
+        if map.len() >= MAX_ENTRIES && !map.contains_key(key) {
+            map.clear();
+        }
+        let entry = map.entry(key.clone()).or_default();
+        let now = Instant::now();
+        if !entry.pending
+            && !matches!(entry.expires, Some(expires) if expires > now)
+        {
+            entry.pending = true;
+            // Without a running task, the entry stays pending for good,
+            // which is fine as there will not be an answer anyway.
+            let _ = self.queries.send(query());
+        }
+        entry.value.clone()
+    }
+
+    /// Stores the answer to `query`.
+    ///
+    /// A failed query keeps the previous answer and is retried after a
+    /// while.
+    fn store(&self, query: &Query, answer: Result<Vec<String>, String>) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match query {
+            Query::AsSet(name) => {
+                let entry = state.as_sets.entry(name.clone()).or_default();
+                update(entry, answer, now, self.ttl, |answer| {
+                    words(&answer)
+                        .filter_map(|w| Asn::from_str(w).ok())
+                        .collect()
+                });
+            }
+            Query::Routes(asn) => {
+                let entry = state.routes.entry(*asn).or_default();
+                update(entry, answer, now, self.ttl, |answer| {
+                    words(&answer)
+                        .filter_map(|w| Prefix::from_str(w).ok())
+                        .collect()
+                });
+            }
+        }
+    }
+
+    /// Answers the queries until the source is removed.
+    pub async fn run(
+        self: Arc<Self>,
+        mut queries: mpsc::UnboundedReceiver<Query>,
+    ) {
+        while let Some(query) = queries.recv().await {
+            debug!("Querying {} for {query:?}", self.config.server);
+            let timeout = Duration::from_secs(self.config.timeout_secs);
+            let answer = tokio::time::timeout(timeout, self.query(&query))
+                .await
+                .unwrap_or_else(|_| Err("timed out".into()));
+            if let Err(err) = &answer {
+                error!(
+                    "IRR query {query:?} to {} failed: {err}",
+                    self.config.server
+                );
+            }
+            self.store(&query, answer);
+        }
+    }
+
+    /// Asks the server, returning the data of each command of the query.
+    async fn query(&self, query: &Query) -> Result<Vec<String>, String> {
+        let commands = match query {
+            Query::AsSet(name) => vec![format!("!i{name},1")],
+            Query::Routes(asn) => {
+                vec![format!("!g{asn}"), format!("!6{asn}")]
+            }
+        };
+        let mut request = String::from("!!\n");
+        if !self.config.sources.is_empty() {
+            request.push_str("!s");
+            request.push_str(&self.config.sources.join(","));
+            request.push('\n');
+        }
+        for command in &commands {
+            request.push_str(command);
+            request.push('\n');
+        }
+        request.push_str("!q\n");
+
+        let mut stream = TcpStream::connect(&self.config.server)
+            .await
+            .map_err(|err| err.to_string())?;
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .map_err(|err| err.to_string())?;
+        let mut response = vec![];
+        stream
+            .read_to_end(&mut response)
+            .await
+            .map_err(|err| err.to_string())?;
+
+        let response = String::from_utf8_lossy(&response);
+        let mut answers = parse_responses(&response)?;
+        if answers.len() < commands.len() {
+            return Err("incomplete response".into());
+        }
+        // Skip the answer to selecting the sources.
+        Ok(answers.split_off(answers.len() - commands.len()))
+    }
+}
+
+/// Updates a cache entry with the answer to a query.
+fn update<T>(
+    entry: &mut Entry<T>,
+    answer: Result<Vec<String>, String>,
+    now: Instant,
+    ttl: Duration,
+    parse: impl FnOnce(Vec<String>) -> T,
+) {
+    entry.pending = false;
+    match answer {
+        Ok(answer) => {
+            entry.value = Some(Arc::new(parse(answer)));
+            entry.expires = Some(now + ttl);
+        }
+        Err(_) => {
+            entry.expires = Some(now + RETRY_DELAY);
+        }
+    }
+}
+
+fn words(answer: &[String]) -> impl Iterator<Item = &str> {
+    answer.iter().flat_map(|data| data.split_whitespace())
+}
+
+/// Parses the responses of an IRRd server to `!` commands.
+///
+/// A response is either `A<length>` followed by that many bytes of data and
+/// `C`, `C` for success without data, `D` if the key was not found, or `E`
+/// if it has no data. Keys not found result in empty data. `F` is an error.
+fn parse_responses(mut raw: &str) -> Result<Vec<String>, String> {
+    let mut res = vec![];
+    while let Some((line, rest)) = raw.split_once('\n') {
+        raw = rest;
+        let line = line.trim_end_matches('\r');
+        if let Some(len) = line.strip_prefix('A') {
+            let len: usize = len
+                .parse()
+                .map_err(|_| format!("invalid response '{line}'"))?;
+            let data = raw
+                .get(..len)
+                .ok_or_else(|| "truncated response".to_string())?;
+            res.push(data.trim().to_string());
+            // The data is followed by a line with `C`.
+            raw = raw[len..].trim_start();
+            match raw.split_once('\n') {
+                Some((end, rest)) if end.trim_end() == "C" => raw = rest,
+                _ if raw.trim_end() == "C" => raw = "",
+                _ => return Err("missing end of response".into()),
+            }
+        } else if line == "C" || line == "D" || line == "E" {
+            res.push(String::new());
+        } else if let Some(msg) = line.strip_prefix('F') {
+            return Err(format!("server error: {}", msg.trim()));
+        } else if !line.is_empty() {
+            return Err(format!("invalid response '{line}'"));
+        }
+    }
+    Ok(res)
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncBufReadExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn config(server: &str) -> IrrDataSource {
+        toml::from_str(&format!(
+            "server = \"{server}\"\nsources = [\"RIPE\", \"RADB\"]"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn responses_are_parsed() {
+        assert_eq!(
+            parse_responses("C\nA16\nAS65000 AS65001\nC\nD\n").unwrap(),
+            ["", "AS65000 AS65001", ""]
+        );
+        assert!(parse_responses("F No such command\n").is_err());
+        assert!(parse_responses("A100\nAS65000\nC\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn answers_are_queried_and_cached() {
+        // An IRRd that knows one AS set and the routes of one AS.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = tokio::io::BufReader::new(reader).lines();
+                while let Some(line) = lines.next_line().await.unwrap() {
+                    let data = match line.as_str() {
+                        "!!" => continue,
+                        "!q" => break,
+                        "!iAS-EXAMPLE,1" => "AS65000 AS65001",
+                        "!gAS65000" => "192.0.2.0/24",
+                        "!6AS65000" => "2001:db8::/32",
+                        line if line.starts_with("!s") => "",
+                        _ => {
+                            writer.write_all(b"D\n").await.unwrap();
+                            continue;
+                        }
+                    };
+                    let response = if data.is_empty() {
+                        "C\n".to_string()
+                    } else {
+                        format!("A{}\n{data}\nC\n", data.len() + 1)
+                    };
+                    writer.write_all(response.as_bytes()).await.unwrap();
+                }
+            }
+        });
+
+        let (irr, queries) =
+            Irr::new(config(&server), Duration::from_secs(60));
+        let asn = Asn::from_u32(65000);
+        let prefix = Prefix::from_str("192.0.2.0/24").unwrap();
+
+        // Nothing is known at first, and asking twice queries once.
+        assert!(!irr.in_as_set("as-example", asn));
+        assert!(!irr.in_as_set("AS-EXAMPLE", asn));
+        assert!(!irr.route_exists(prefix, asn));
+        assert!(!irr.in_as_set("AS-OTHER", asn));
+        assert_eq!(queries.len(), 3);
+
+        let task = tokio::spawn(irr.clone().run(queries));
+        for _ in 0..100 {
+            if irr.in_as_set("AS-EXAMPLE", asn)
+                && irr.route_exists(prefix, asn)
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(irr.in_as_set("AS-EXAMPLE", Asn::from_u32(65001)));
+        assert!(!irr.in_as_set("AS-EXAMPLE", Asn::from_u32(65002)));
+        assert!(!irr.in_as_set("AS-OTHER", asn));
+        assert!(irr.route_exists(prefix, asn));
+        assert!(irr
+            .route_exists(Prefix::from_str("2001:db8::/32").unwrap(), asn));
+        assert!(!irr
+            .route_exists(Prefix::from_str("198.51.100.0/24").unwrap(), asn));
+        task.abort();
+    }
+}
@@ -0,0 +1,252 @@
+//! Structured logging for Roto scripts.
+//!
+//! Scripts add fields with `logger.field("peer", ...)` and then log a
+//! message with `logger.info(...)` or `logger.warn(...)`. The record is
+//! tagged with the name of the unit running the script and the ingress the
+//! route or message came from, if known, and written to the Rotonda log as
+//! `<unit>: <message> ingress=<id> <key>=<value> ...`.
+//!
+//! As a script runs for every route, a careless log statement would flood
+//! the log. Every message is therefore rate limited per unit, level and
+//! message text, i.e., per call site for messages that are not built from
+//! route data. Once a suppressed message is allowed again, the record notes
+//! how many were suppressed in between.
+//!
+//! A capturing logger, used when tracing a single run of a script, records
+//! all records as steps of the [`trace`](super::trace) instead, regardless
+//! of rate and log level.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use log::{log_enabled, Level};
+
+use super::rate_limit::RateLimiter;
+use super::trace::{self, TraceStep};
+use crate::ingress::IngressId;
+
+/// The number of records per second allowed for each call site.
+const RECORDS_PER_SEC: f64 = 1.0;
+
+/// The number of records allowed at once for each call site.
+const BURST: u32 = 10;
+
+/// The number of suppressed call sites remembered.
+///
+/// Beyond this the counts are forgotten, which only loses the note about
+/// how many records were suppressed.
+const MAX_SUPPRESSED: usize = 4096;
+
+//------------ ScriptLogger --------------------------------------------------
+
+/// The logger for the scripts of one unit.
+#[derive(Debug, Default)]
+pub struct ScriptLogger {
+    unit_name: Arc<str>,
+
+    /// Whether records are traced rather than logged.
+    capture: bool,
+
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The ingress of the route or message the script runs for.
+    ingress: Option<IngressId>,
+
+    /// The fields added for the next record.
+    fields: Vec<(Arc<str>, Arc<str>)>,
+
+    limiter: RateLimiter,
+
+    /// The number of suppressed records by call site.
+    suppressed: HashMap<Arc<str>, u64>,
+}
+
+impl ScriptLogger {
+    /// Creates a logger for the unit `unit_name`.
+    pub fn new(unit_name: Arc<str>) -> Self {
+        Self {
+            unit_name,
+            capture: false,
+            state: Default::default(),
+        }
+    }
+
+    /// Creates a logger for the unit `unit_name` that traces its records.
+    pub fn capturing(unit_name: Arc<str>) -> Self {
+        Self {
+            unit_name,
+            capture: true,
+            state: Default::default(),
+        }
+    }
+
+    /// Sets the ingress the script is about to run for.
+    ///
+    /// This also drops any fields left over from a previous run that did
+    /// not log them.
+    pub fn set_ingress(&self, ingress: Option<IngressId>) {
+        let mut state = self.state.lock().unwrap();
+        state.ingress = ingress;
+        state.fields.clear();
+    }
+
+    /// Adds a field to the next record.
+    pub fn field(&self, key: Arc<str>, value: Arc<str>) {
+        self.state.lock().unwrap().fields.push((key, value));
+    }
+
+    /// Logs `msg` with the fields added since the last record.
+    pub fn log(&self, level: Level, msg: &str) {
+        if self.capture {
+            let fields =
+                std::mem::take(&mut self.state.lock().unwrap().fields);
+            trace::record(|| TraceStep::Log {
+                level: level.to_string(),
+                message: msg.into(),
+                fields: fields
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), (*value).into()))
+                    .collect(),
+            });
+            return;
+        }
+        if !log_enabled!(level) {
+            self.state.lock().unwrap().fields.clear();
+            return;
+        }
+        if let Some(record) = self.record(level, msg, Instant::now()) {
+            log::log!(level, "{record}");
+        }
+    }
+
+    /// Returns the record to log for `msg` at time `now`, if any.
+    ///
+    /// Returns `None` if the call site is over its rate.
+    fn record(
+        &self,
+        level: Level,
+        msg: &str,
+        now: Instant,
+    ) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let fields = std::mem::take(&mut state.fields);
+
+        let site: Arc<str> = format!("{level} {msg}").into();
+        if !state.limiter.take(&site, RECORDS_PER_SEC, BURST, now) {
+            if state.suppressed.len() >= MAX_SUPPRESSED
+                && !state.suppressed.contains_key(&site)
+            {
+                state.suppressed.clear();
+            }
+            *state.suppressed.entry(site).or_default() += 1;
+            return None;
+        }
+
+        let mut record = self.format(state.ingress, &fields, msg);
+        if let Some(count) = state.suppressed.remove(&site) {
+            let _ = write!(
+                record,
+                " ({count} similar record{} suppressed)",
+                if count == 1 { "" } else { "s" }
+            );
+        }
+        Some(record)
+    }
+
+    /// Returns the text of a record for `msg` with `fields`.
+    fn format(
+        &self,
+        ingress: Option<IngressId>,
+        fields: &[(Arc<str>, Arc<str>)],
+        msg: &str,
+    ) -> String {
+        let mut record = format!("{}: {msg}", self.unit_name);
+        if let Some(ingress) = ingress {
+            let _ = write!(record, " ingress={ingress}");
+        }
+        for (key, value) in fields {
+            if value.is_empty() || value.contains(char::is_whitespace) {
+                let _ = write!(record, " {key}={value:?}");
+            } else {
+                let _ = write!(record, " {key}={value}");
+            }
+        }
+        record
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn records_are_tagged_and_rate_limited() {
+        let logger = ScriptLogger::new("rib-in".into());
+        let start = Instant::now();
+
+        logger.set_ingress(Some(7));
+        logger.field("peer".into(), "192.0.2.1".into());
+        logger.field("reason".into(), "too long".into());
+        assert_eq!(
+            logger.record(Level::Warn, "rejected", start).as_deref(),
+            Some(
+                "rib-in: rejected ingress=7 peer=192.0.2.1 \
+                reason=\"too long\""
+            )
+        );
+
+        // Fields are used for one record only
+        assert_eq!(
+            logger.record(Level::Warn, "rejected", start).as_deref(),
+            Some("rib-in: rejected ingress=7")
+        );
+        for _ in 2..BURST {
+            assert!(logger.record(Level::Warn, "rejected", start).is_some());
+        }
+        assert_eq!(logger.record(Level::Warn, "rejected", start), None);
+        assert_eq!(logger.record(Level::Warn, "rejected", start), None);
+
+        // Other call sites have their own limit
+        assert!(logger.record(Level::Info, "rejected", start).is_some());
+        assert!(logger.record(Level::Warn, "accepted", start).is_some());
+
+        logger.set_ingress(None);
+        assert_eq!(
+            logger
+                .record(Level::Warn, "rejected", start + Duration::from_secs(1))
+                .as_deref(),
+            Some("rib-in: rejected (2 similar records suppressed)")
+        );
+    }
+
+    #[test]
+    fn records_are_traced() {
+        let logger = ScriptLogger::capturing("rib-in".into());
+        logger.set_ingress(Some(7));
+        let ((), steps) = trace::capture(|| {
+            for _ in 0..BURST + 1 {
+                logger.field("peer".into(), "192.0.2.1".into());
+                logger.log(Level::Trace, "rejected");
+            }
+        });
+        assert_eq!(steps.len(), BURST as usize + 1);
+        assert_eq!(
+            serde_json::to_value(&steps[0]).unwrap(),
+            serde_json::json!({
+                "step": "log",
+                "level": "TRACE",
+                "message": "rejected",
+                "fields": { "peer": "192.0.2.1" }
+            })
+        );
+    }
+}
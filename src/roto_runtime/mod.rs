@@ -1,6 +1,23 @@
+mod as_rel;
+mod aspath;
+pub mod bogons;
+mod communities;
+pub mod dns;
+mod rate_limit;
 mod runtime;
+pub mod sampling;
+mod time;
 pub mod types;
 pub mod lists;
 pub mod external_data;
+pub mod filter_metrics;
+pub mod filter_tests;
+pub mod irr;
+pub mod prefix_set;
+pub mod logger;
+pub mod reload;
+pub mod state;
+pub mod trace;
+pub mod user_metrics;
 
 pub use crate::roto_runtime::runtime::*;
@@ -0,0 +1,280 @@
+//! Prefix sets for longest-prefix matching.
+//!
+//! A [`PrefixSet`] indexes the prefixes found in the data of an external
+//! data source in a binary trie per address family, so that finding the
+//! most specific entry covering a prefix takes at most as many steps as the
+//! prefix is long, however many entries the set has.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use inetnum::addr::Prefix;
+
+use super::external_data::ExternalDataValue;
+
+//------------ PrefixSetEntry ------------------------------------------------
+
+/// An entry of a prefix set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrefixSetEntry {
+    /// The prefix of the entry.
+    pub prefix: Prefix,
+
+    /// The metadata attached to the prefix.
+    ///
+    /// This is [`ExternalDataValue::Null`] if there is none.
+    pub meta: ExternalDataValue,
+}
+
+//------------ PrefixSetMatch ------------------------------------------------
+
+/// The result of matching a prefix against a prefix set.
+///
+/// Roto scripts get this rather than an optional entry, so it holds no
+/// entry if none matched.
+#[derive(Clone, Debug, Default)]
+pub struct PrefixSetMatch(pub Option<Arc<PrefixSetEntry>>);
+
+impl PrefixSetMatch {
+    /// Returns the entry that matched, if any.
+    pub fn entry(&self) -> Option<&PrefixSetEntry> {
+        self.0.as_deref()
+    }
+
+    /// Returns the metadata member `key`, if there was a match.
+    pub fn meta(&self, key: &str) -> Option<&ExternalDataValue> {
+        self.0.as_ref()?.meta.get(key)
+    }
+}
+
+//------------ PrefixSet -----------------------------------------------------
+
+/// A set of prefixes with metadata, indexed for longest-prefix matching.
+#[derive(Clone, Debug, Default)]
+pub struct PrefixSet {
+    v4: Trie,
+    v6: Trie,
+    len: usize,
+}
+
+impl PrefixSet {
+    /// Creates a set from the data of an external data source.
+    ///
+    /// The prefixes are taken from a list of prefixes, from a list of
+    /// objects with a `prefix` member, which are then the metadata, or from
+    /// the member names of an object, with the members as metadata. Anything
+    /// that is not a prefix is left out.
+    pub fn from_value(value: &ExternalDataValue) -> Self {
+        let mut set = Self::default();
+        match value {
+            ExternalDataValue::Array(items) => {
+                for item in items {
+                    match item {
+                        ExternalDataValue::String(s) => {
+                            set.insert_str(s, ExternalDataValue::Null)
+                        }
+                        ExternalDataValue::Object(_) => {
+                            if let Some(ExternalDataValue::String(s)) =
+                                item.get("prefix")
+                            {
+                                set.insert_str(s, item.clone())
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            ExternalDataValue::Object(map) => {
+                for (key, meta) in map {
+                    set.insert_str(key, meta.clone())
+                }
+            }
+            ExternalDataValue::String(s) => {
+                set.insert_str(s, ExternalDataValue::Null)
+            }
+            _ => {}
+        }
+        set
+    }
+
+    /// Returns the number of prefixes in the set.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a prefix, replacing the metadata if it is already present.
+    pub fn insert(&mut self, prefix: Prefix, meta: ExternalDataValue) {
+        let trie = self.trie_mut(prefix);
+        let entry = Arc::new(PrefixSetEntry { prefix, meta });
+        if trie.insert(bits(prefix), prefix.len(), entry) {
+            self.len += 1;
+        }
+    }
+
+    /// Returns the most specific entry that covers `prefix`.
+    pub fn longest_match(
+        &self,
+        prefix: Prefix,
+    ) -> Option<&Arc<PrefixSetEntry>> {
+        let trie = if prefix.is_v4() { &self.v4 } else { &self.v6 };
+        trie.longest_match(bits(prefix), prefix.len())
+    }
+
+    fn insert_str(&mut self, prefix: &str, meta: ExternalDataValue) {
+        if let Ok(prefix) = Prefix::from_str(prefix.trim()) {
+            self.insert(prefix, meta)
+        }
+    }
+
+    fn trie_mut(&mut self, prefix: Prefix) -> &mut Trie {
+        if prefix.is_v4() {
+            &mut self.v4
+        } else {
+            &mut self.v6
+        }
+    }
+}
+
+/// Returns the address bits of a prefix, aligned to the left.
+fn bits(prefix: Prefix) -> u128 {
+    match prefix.addr() {
+        std::net::IpAddr::V4(addr) => u128::from(u32::from(addr)) << 96,
+        std::net::IpAddr::V6(addr) => u128::from(addr),
+    }
+}
+
+//------------ Trie ----------------------------------------------------------
+
+/// A binary trie over the bits of addresses.
+///
+/// The nodes live in a vector, the first one being the root, and refer to
+/// their children by index. As the root is never a child, zero means there
+/// is no child.
+#[derive(Clone, Debug)]
+struct Trie {
+    nodes: Vec<Node>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Node {
+    children: [u32; 2],
+    entry: Option<Arc<PrefixSetEntry>>,
+}
+
+impl Default for Trie {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::default()],
+        }
+    }
+}
+
+impl Trie {
+    /// Inserts an entry, returning whether the prefix is new.
+    fn insert(
+        &mut self,
+        bits: u128,
+        len: u8,
+        entry: Arc<PrefixSetEntry>,
+    ) -> bool {
+        let mut node = 0;
+        for depth in 0..len {
+            let bit = bit(bits, depth);
+            node = match self.nodes[node].children[bit] {
+                0 => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node::default());
+                    self.nodes[node].children[bit] = child as u32;
+                    child
+                }
+                child => child as usize,
+            };
+        }
+        self.nodes[node].entry.replace(entry).is_none()
+    }
+
+    fn longest_match(
+        &self,
+        bits: u128,
+        len: u8,
+    ) -> Option<&Arc<PrefixSetEntry>> {
+        let mut node = &self.nodes[0];
+        let mut found = node.entry.as_ref();
+        for depth in 0..len {
+            match node.children[bit(bits, depth)] {
+                0 => break,
+                child => node = &self.nodes[child as usize],
+            }
+            found = node.entry.as_ref().or(found);
+        }
+        found
+    }
+}
+
+fn bit(bits: u128, depth: u8) -> usize {
+    ((bits >> (127 - depth)) & 1) as usize
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(s: &str) -> Prefix {
+        Prefix::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn most_specific_entry_matches() {
+        let value: ExternalDataValue = serde_json::from_str(
+            r#"[
+                {"prefix": "10.0.0.0/8", "reason": "rfc1918"},
+                {"prefix": "10.1.0.0/16", "reason": "lab"},
+                {"prefix": "2001:db8::/32", "reason": "documentation"},
+                "192.0.2.0/24",
+                "not a prefix"
+            ]"#,
+        )
+        .unwrap();
+        let set = PrefixSet::from_value(&value);
+        assert_eq!(set.len(), 4);
+
+        let entry = set.longest_match(prefix("10.1.2.0/24")).unwrap();
+        assert_eq!(entry.prefix, prefix("10.1.0.0/16"));
+        assert_eq!(
+            entry.meta.get("reason"),
+            Some(&ExternalDataValue::String("lab".into()))
+        );
+
+        let entry = set.longest_match(prefix("10.2.0.0/16")).unwrap();
+        assert_eq!(entry.prefix, prefix("10.0.0.0/8"));
+
+        // A less specific prefix is not covered by the entry
+        assert!(set.longest_match(prefix("10.0.0.0/7")).is_none());
+
+        let entry = set.longest_match(prefix("192.0.2.1/32")).unwrap();
+        assert_eq!(entry.meta, ExternalDataValue::Null);
+
+        let entry = set.longest_match(prefix("2001:db8:1::/48")).unwrap();
+        assert_eq!(entry.prefix, prefix("2001:db8::/32"));
+        assert!(set.longest_match(prefix("2001:db9::/32")).is_none());
+    }
+
+    #[test]
+    fn default_routes_and_replacements() {
+        let mut set = PrefixSet::default();
+        set.insert(prefix("0.0.0.0/0"), ExternalDataValue::Null);
+        set.insert(prefix("0.0.0.0/0"), ExternalDataValue::Boolean(true));
+        assert_eq!(set.len(), 1);
+
+        let entry = set.longest_match(prefix("198.51.100.0/24")).unwrap();
+        assert_eq!(entry.meta, ExternalDataValue::Boolean(true));
+        assert!(set.longest_match(prefix("::/0")).is_none());
+    }
+}
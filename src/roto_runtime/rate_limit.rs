@@ -0,0 +1,224 @@
+//! Rate limiting for Roto scripts.
+//!
+//! Scripts throttle whatever they like, alerts for a peer or log lines for a
+//! prefix, by taking tokens from a bucket named by an arbitrary key. Each
+//! bucket holds up to `burst` tokens and is refilled at `tokens_per_sec`.
+//! Every unit running a Roto filter has its own buckets, which the filter
+//! uses through the `rate_limits` context value, e.g.
+//! `rate_limits.take("flap-alerts", 0.1, 5)`, so the same key used by
+//! different units refers to different buckets.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+/// The number of buckets kept before full ones are dropped.
+///
+/// A full bucket is the same as one that does not exist, so dropping them
+/// only bounds the memory used for keys that are no longer seen.
+const SWEEP_SIZE: usize = 4096;
+
+//------------ RateLimits ----------------------------------------------------
+
+/// The buckets of the filter of a unit.
+#[derive(Debug, Default)]
+pub struct RateLimits {
+    limiter: Mutex<RateLimiter>,
+}
+
+impl RateLimits {
+    /// Returns a copy of the buckets, for a trial run of a script that is
+    /// not to take from them.
+    pub fn copy(&self) -> Self {
+        Self {
+            limiter: Mutex::new(self.lock().clone()),
+        }
+    }
+
+    /// Takes a token from the bucket for `key`.
+    ///
+    /// Returns whether there was one, i.e., whether the action is allowed.
+    pub fn take(
+        &self,
+        key: &Arc<str>,
+        tokens_per_sec: f64,
+        burst: u32,
+    ) -> bool {
+        self.lock().take(key, tokens_per_sec, burst, Instant::now())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RateLimiter> {
+        self.limiter.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+//------------ RateLimiter ---------------------------------------------------
+
+/// Token buckets by key.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimiter {
+    buckets: HashMap<Arc<str>, Bucket>,
+
+    /// The number of buckets at which to drop the full ones.
+    sweep_at: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+
+    /// The rate and burst the bucket was last taken from with.
+    tokens_per_sec: f64,
+    burst: f64,
+}
+
+impl Bucket {
+    /// Returns the tokens in the bucket at time `now`.
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated);
+        (self.tokens + elapsed.as_secs_f64() * self.tokens_per_sec)
+            .min(self.burst)
+    }
+}
+
+impl RateLimiter {
+    /// Takes a token from the bucket for `key` at time `now`.
+    pub fn take(
+        &mut self,
+        key: &Arc<str>,
+        tokens_per_sec: f64,
+        burst: u32,
+        now: Instant,
+    ) -> bool {
+        let tokens_per_sec = tokens_per_sec.max(0.0);
+        let burst = f64::from(burst);
+        let bucket = match self.buckets.get_mut(key) {
+            Some(bucket) => bucket,
+            None => {
+                self.sweep(now);
+                self.buckets.entry(key.clone()).or_insert(Bucket {
+                    tokens: burst,
+                    updated: now,
+                    tokens_per_sec,
+                    burst,
+                })
+            }
+        };
+        bucket.tokens_per_sec = tokens_per_sec;
+        bucket.burst = burst;
+        bucket.tokens = bucket.tokens_at(now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Drops the buckets that have refilled, if there are many.
+    fn sweep(&mut self, now: Instant) {
+        if self.buckets.len() < self.sweep_at.max(SWEEP_SIZE) {
+            return;
+        }
+        self.buckets
+            .retain(|_, bucket| bucket.tokens_at(now) < bucket.burst);
+        self.sweep_at = self.buckets.len() * 2;
+    }
+
+    /// Returns the number of buckets.
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Returns whether there are no buckets.
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn buckets_refill() {
+        let mut limiter = RateLimiter::default();
+        let key: Arc<str> = "peer".into();
+        let start = Instant::now();
+
+        // The burst is available at once
+        for _ in 0..3 {
+            assert!(limiter.take(&key, 2.0, 3, start));
+        }
+        assert!(!limiter.take(&key, 2.0, 3, start));
+
+        // Two tokens a second make one available after half a second
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.take(&key, 2.0, 3, later));
+        assert!(!limiter.take(&key, 2.0, 3, later));
+
+        // But never more than the burst
+        let much_later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.take(&key, 2.0, 3, much_later));
+        }
+        assert!(!limiter.take(&key, 2.0, 3, much_later));
+
+        // Other keys have their own buckets
+        assert!(limiter.take(&"other".into(), 2.0, 3, much_later));
+
+        // A burst of zero allows nothing
+        assert!(!limiter.take(&"none".into(), 2.0, 0, much_later));
+    }
+
+    #[test]
+    fn full_buckets_are_dropped() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+        for i in 0..SWEEP_SIZE {
+            limiter.take(&i.to_string().into(), 1.0, 1, start);
+        }
+        assert_eq!(limiter.len(), SWEEP_SIZE);
+
+        let later = start + Duration::from_secs(2);
+        assert!(limiter.take(&"new".into(), 1.0, 1, later));
+        assert_eq!(limiter.len(), 1);
+    }
+
+    #[test]
+    fn buckets_are_dropped_at_their_own_rate() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+        let slow: Arc<str> = "slow".into();
+        assert!(limiter.take(&slow, 0.001, 1, start));
+        for i in 1..SWEEP_SIZE {
+            limiter.take(&i.to_string().into(), 1.0, 1, start);
+        }
+
+        // A fast new key does not drop the slow bucket, still empty
+        let later = start + Duration::from_secs(2);
+        assert!(limiter.take(&"fast".into(), 1000.0, 1, later));
+        assert_eq!(limiter.len(), 2);
+        assert!(!limiter.take(&slow, 0.001, 1, later));
+    }
+
+    #[test]
+    fn copies_leave_the_buckets_alone() {
+        let limits = RateLimits::default();
+        let key: Arc<str> = "peer".into();
+        assert!(limits.take(&key, 0.001, 2));
+
+        let copy = limits.copy();
+        assert!(copy.take(&key, 0.001, 2));
+        assert!(!copy.take(&key, 0.001, 2));
+
+        assert!(limits.take(&key, 0.001, 2));
+        assert!(!limits.take(&key, 0.001, 2));
+    }
+}
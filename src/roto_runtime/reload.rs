@@ -0,0 +1,342 @@
+//! Reloading the Roto script.
+//!
+//! The compiled script is kept in a [`LiveRoto`] shared by the manager and
+//! all units. When the script is compiled anew, because its files changed,
+//! Rotonda received a SIGHUP or the configuration was reloaded, the new
+//! version replaces the old one. Units keep the filter functions they got
+//! from the script in a [`Reloadable`], which notices the new version the
+//! next time a filter is about to run and fetches the functions again, so
+//! that the filters change without restarting the unit and dropping its
+//! BMP or BGP sessions.
+//!
+//! If the new version does not compile, the previous version stays in use.
+//!
+//! With `watch` enabled in the `[roto_reload]` section, the manager checks
+//! the modification times of the script files every `interval_secs` and
+//! reloads the script when they change. This is off by default, so that
+//! editing the files of a running Rotonda does not change its filters
+//! until asked to.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use arc_swap::ArcSwap;
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use super::types::CompiledRoto;
+use super::Ctx;
+use crate::metrics::{self, Metric, MetricType, MetricUnit};
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReloadConfig {
+    /// Whether to reload the script when its files change.
+    #[serde(default = "ReloadConfig::default_watch")]
+    pub watch: bool,
+
+    /// How often to check the files for changes.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "ReloadConfig::default_interval_secs")]
+    pub interval_secs: Duration,
+}
+
+impl ReloadConfig {
+    fn default_watch() -> bool {
+        false
+    }
+
+    fn default_interval_secs() -> Duration {
+        Duration::from_secs(2)
+    }
+
+    /// Returns how often to check the script files, if at all.
+    pub fn watch_interval(&self) -> Option<Duration> {
+        (self.watch && !self.interval_secs.is_zero())
+            .then_some(self.interval_secs)
+    }
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self {
+            watch: Self::default_watch(),
+            interval_secs: Self::default_interval_secs(),
+        }
+    }
+}
+
+//------------ LiveRoto ------------------------------------------------------
+
+/// The current version of the compiled Roto script.
+#[derive(Debug, Default)]
+pub struct LiveRoto {
+    current: ArcSwap<Version>,
+    reloads: AtomicU64,
+    failures: AtomicU64,
+}
+
+#[derive(Default)]
+struct Version {
+    /// The number of times the script was replaced.
+    generation: u64,
+    compiled: Option<Arc<CompiledRoto>>,
+}
+
+impl std::fmt::Debug for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Version")
+            .field("generation", &self.generation)
+            .field("compiled", &self.compiled.is_some())
+            .finish()
+    }
+}
+
+impl LiveRoto {
+    const RELOADS_METRIC: Metric = Metric::new(
+        "roto_script_reloads",
+        "the number of times the roto script was compiled and replaced",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const FAILURES_METRIC: Metric = Metric::new(
+        "roto_script_reload_failures",
+        "the number of times compiling a changed roto script failed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+
+    /// Replaces the script by a newly compiled version.
+    pub fn store(&self, compiled: Arc<CompiledRoto>) {
+        let generation = self.current.load().generation + 1;
+        self.current.store(Arc::new(Version {
+            generation,
+            compiled: Some(compiled),
+        }));
+        if generation > 1 {
+            self.reloads.fetch_add(1, Relaxed);
+        }
+    }
+
+    /// Notes that compiling a new version failed.
+    pub fn failed(&self) {
+        self.failures.fetch_add(1, Relaxed);
+    }
+
+    fn generation(&self) -> u64 {
+        self.current.load().generation
+    }
+}
+
+impl metrics::Source for LiveRoto {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::RELOADS_METRIC,
+            Some(unit_name),
+            self.reloads.load(Relaxed),
+        );
+        target.append_simple(
+            &Self::FAILURES_METRIC,
+            Some(unit_name),
+            self.failures.load(Relaxed),
+        );
+    }
+}
+
+//------------ Reloadable ----------------------------------------------------
+
+/// A value taken from the Roto script, taken again when it is reloaded.
+///
+/// This is typically a filter function, or a struct of several functions
+/// that should change together.
+pub struct Reloadable<T> {
+    roto: Arc<LiveRoto>,
+    load: fn(&mut roto::Compiled) -> T,
+
+    /// The value and the generation of the script it was taken from.
+    current: Mutex<(u64, T)>,
+}
+
+impl<T: Clone + Default> Reloadable<T> {
+    /// Takes a value from the current script using `load`.
+    ///
+    /// The context the value will be used with is prepared for the script.
+    pub fn new(
+        roto: Arc<LiveRoto>,
+        load: fn(&mut roto::Compiled) -> T,
+        ctx: &mut Ctx,
+    ) -> Self {
+        let res = Self {
+            roto,
+            load,
+            current: Default::default(),
+        };
+        res.reload(&mut res.current.lock().unwrap(), ctx);
+        res
+    }
+
+    /// Creates a value that is never reloaded.
+    pub fn fixed(value: T) -> Self {
+        Self {
+            roto: Default::default(),
+            load: |_| T::default(),
+            current: Mutex::new((0, value)),
+        }
+    }
+
+    /// Returns the value for the current version of the script.
+    ///
+    /// If the script was reloaded since the value was taken, it is taken
+    /// from the new version and `ctx` is prepared for it.
+    pub fn get(&self, ctx: &mut Ctx) -> T {
+        let mut current = self.current.lock().unwrap();
+        if self.roto.generation() != current.0 {
+            self.reload(&mut current, ctx);
+        }
+        current.1.clone()
+    }
+
+    fn reload(&self, current: &mut (u64, T), ctx: &mut Ctx) {
+        let version = self.roto.current.load();
+        *current = match &version.compiled {
+            Some(compiled) => {
+                let mut compiled = compiled.lock().unwrap();
+                ctx.prepare(&mut compiled);
+                (version.generation, (self.load)(&mut compiled))
+            }
+            None => (version.generation, T::default()),
+        };
+    }
+}
+
+impl<T: Clone> Clone for Reloadable<T> {
+    fn clone(&self) -> Self {
+        Self {
+            roto: self.roto.clone(),
+            load: self.load,
+            current: Mutex::new(self.current.lock().unwrap().clone()),
+        }
+    }
+}
+
+//------------ ScriptFiles ---------------------------------------------------
+
+/// The modification times of the files of a Roto script.
+///
+/// The script is either a single file or a directory of `.roto` files.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScriptFiles {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl ScriptFiles {
+    /// Reads the modification times of the script at `path`.
+    ///
+    /// Files that cannot be read are included without a time, so that they
+    /// count as changed once they can be read again.
+    pub fn read(path: &Path) -> Self {
+        let mut res = Self::default();
+        res.add(path);
+        res.files.sort();
+        res
+    }
+
+    fn add(&mut self, path: &Path) {
+        if let Ok(entries) = std::fs::read_dir(path) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir()
+                    || path.extension().is_some_and(|ext| ext == "roto")
+                {
+                    self.add(&path);
+                }
+            }
+        } else {
+            let modified = std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            self.files.push((path.to_path_buf(), modified));
+        }
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roto_runtime::create_runtime;
+
+    fn compile(script: &str) -> Arc<CompiledRoto> {
+        let compiled = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .unwrap();
+        Arc::new(Mutex::new(compiled))
+    }
+
+    fn load_answer(compiled: &mut roto::Compiled) -> Option<i64> {
+        let answer = compiled.get_function::<Ctx, fn() -> i64>("answer");
+        answer.ok().map(|answer| answer.call(&mut Ctx::empty()))
+    }
+
+    #[test]
+    fn watching_must_be_enabled() {
+        assert_eq!(ReloadConfig::default().watch_interval(), None);
+
+        let config: ReloadConfig = toml::from_str("watch = true").unwrap();
+        assert_eq!(config.watch_interval(), Some(Duration::from_secs(2)));
+
+        let config: ReloadConfig =
+            toml::from_str("watch = true\ninterval_secs = 0").unwrap();
+        assert_eq!(config.watch_interval(), None);
+    }
+
+    #[test]
+    fn values_follow_reloads() {
+        let roto = Arc::new(LiveRoto::default());
+        let mut ctx = Ctx::empty();
+        let answer = Reloadable::new(roto.clone(), load_answer, &mut ctx);
+        assert_eq!(answer.get(&mut ctx), None);
+
+        roto.store(compile("function answer() -> i64 { 42 }"));
+        assert_eq!(answer.get(&mut ctx), Some(42));
+        roto.store(compile("function answer() -> i64 { 43 }"));
+        assert_eq!(answer.get(&mut ctx), Some(43));
+        roto.failed();
+
+        let target =
+            crate::tests::util::internal::get_testable_metrics_snapshot(
+                &roto,
+            );
+        assert_eq!(target.with_name::<u64>("roto_script_reloads"), 1);
+        assert_eq!(target.with_name::<u64>("roto_script_reload_failures"), 1);
+
+        let fixed = Reloadable::fixed(Some(1));
+        assert_eq!(fixed.get(&mut ctx), Some(1));
+    }
+
+    #[test]
+    fn script_files_notice_changes() {
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-reload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("filters.roto"), "").unwrap();
+        std::fs::write(dir.join("README"), "").unwrap();
+
+        let before = ScriptFiles::read(&dir);
+        assert_eq!(before.files.len(), 1);
+        assert_eq!(ScriptFiles::read(&dir), before);
+
+        std::fs::write(dir.join("sub").join("lists.roto"), "").unwrap();
+        let after = ScriptFiles::read(&dir);
+        assert_eq!(after.files.len(), 2);
+        assert_ne!(after, before);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_ne!(ScriptFiles::read(&dir), after);
+    }
+}
@@ -1,16 +1,18 @@
 use std::cell::RefCell;
+use std::net::IpAddr;
 use std::rc::Rc;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use bytes::Bytes;
-use chrono::{SecondsFormat, Utc};
+use chrono::{Datelike, SecondsFormat, Timelike, Utc};
 use inetnum::addr::Prefix;
 use inetnum::asn::Asn;
 use log::debug;
 use routecore::bgp::aspath::{AsPath, Hop, HopPath};
 use routecore::bgp::communities::{
-    LargeCommunity, StandardCommunity, Wellknown,
+    Community, LargeCommunity, StandardCommunity, Wellknown,
 };
 use routecore::bgp::message::update_builder::StandardCommunitiesList;
 use routecore::bgp::message::SessionConfig;
@@ -21,14 +23,34 @@ use routecore::bmp::message::PerPeerHeader;
 use routecore::bmp::message::{Message as BmpMsg, MessageType as BmpMsgType};
 
 use roto::{roto_function, roto_method, roto_static_method, Context, Val};
-
+use serde::Serialize;
+
+use super::aspath;
+use super::bogons;
+use super::communities;
+use super::dns;
+use super::external_data::ExternalData;
+use super::irr;
+use super::logger::ScriptLogger;
+use super::rate_limit::RateLimits;
+use super::sampling;
+use super::state::{StateStore, StateValue};
+use super::time;
+use super::trace::{self, TraceStep};
+use super::user_metrics;
+use super::prefix_set::PrefixSetMatch;
 use super::lists::{MutNamedAsnLists, MutNamedPrefixLists};
 use super::types::{
-    InsertionInfo, Output, Provenance, RotoOutputStream, RouteContext,
+    InsertionInfo, MutRibSelection, MutTags, Output, Provenance,
+    RotoOutputStream, RouteContext, TagValue,
 };
+use crate::ingress::IngressId;
 use crate::payload::RotondaRoute;
 use crate::roto_runtime::lists::{AsnList, PrefixList};
 use crate::roto_runtime::types::LogEntry;
+use crate::units::flow_in::counters::TrafficCounters;
+use crate::units::rib_unit::best_path;
+use crate::units::rib_unit::flap::FlapDamping;
 use crate::units::rib_unit::rpki::{RovStatus, RovStatusUpdate, RtrCache};
 use crate::units::rtr::client::VrpUpdate;
 
@@ -39,6 +61,11 @@ pub const COMPILE_LISTS_FUNC_NAME: &str = "compile_lists";
 
 pub(crate) type Log = Rc<RefCell<RotoOutputStream>>;
 pub(crate) type SharedRtrCache = Arc<RtrCache>;
+pub(crate) type SharedTrafficCounters = Arc<TrafficCounters>;
+pub(crate) type SharedFlapDamping = Arc<FlapDamping>;
+pub(crate) type SharedStateStore = Arc<StateStore>;
+pub(crate) type SharedScriptLogger = Arc<ScriptLogger>;
+pub(crate) type SharedRateLimits = Arc<RateLimits>;
 pub(crate) type MutRotondaRoute = Rc<RefCell<RotondaRoute>>;
 pub(crate) type MutLogEntry = Rc<RefCell<LogEntry>>;
 
@@ -56,27 +83,83 @@ impl From<RotondaRoute> for MutRotondaRoute {
 pub struct Ctx {
     pub output: Log,
     pub rpki: SharedRtrCache,
+    pub traffic: SharedTrafficCounters,
+    pub flaps: SharedFlapDamping,
+    pub state: SharedStateStore,
+    pub logger: SharedScriptLogger,
+    pub rate_limits: SharedRateLimits,
     pub asn_lists: MutNamedAsnLists,
     pub prefix_lists: MutNamedPrefixLists,
+    pub ribs: MutRibSelection,
+    pub tags: MutTags,
 }
 
 unsafe impl Send for Ctx {}
 
 impl Ctx {
-    pub fn new(log: Log, rpki: SharedRtrCache) -> Self {
+    pub fn new(
+        log: Log,
+        rpki: SharedRtrCache,
+        traffic: SharedTrafficCounters,
+    ) -> Self {
         Self {
             output: log,
             rpki,
+            traffic,
+            flaps: Default::default(),
+            state: Default::default(),
+            logger: Default::default(),
+            rate_limits: Default::default(),
             asn_lists: Default::default(),
             prefix_lists: Default::default(),
+            ribs: Default::default(),
+            tags: Default::default(),
         }
     }
     pub fn empty() -> Self {
         Self {
             output: RotoOutputStream::new_rced(),
             rpki: Arc::<RtrCache>::default(),
+            traffic: Arc::<TrafficCounters>::default(),
+            flaps: Arc::<FlapDamping>::default(),
+            state: Arc::<StateStore>::default(),
+            logger: Arc::<ScriptLogger>::default(),
+            rate_limits: Arc::<RateLimits>::default(),
             asn_lists: Default::default(),
             prefix_lists: Default::default(),
+            ribs: Default::default(),
+            tags: Default::default(),
+        }
+    }
+
+    /// Returns a throwaway context for a trial run of a script.
+    ///
+    /// The context shares the VRPs and traffic counts of this one, which
+    /// scripts only read. It has copies of the flaps, state, rate limits and
+    /// lists, so that the run leaves those of this context alone, with the
+    /// flaps referring to the paths from `ingress_id`. Output, logger, tags
+    /// and RIB selection are its own.
+    pub fn detached(
+        &self,
+        logger: SharedScriptLogger,
+        ingress_id: Option<IngressId>,
+    ) -> Self {
+        Self {
+            output: RotoOutputStream::new_rced(),
+            rpki: self.rpki.clone(),
+            traffic: self.traffic.clone(),
+            flaps: Arc::new(self.flaps.copy(ingress_id)),
+            state: Arc::new(self.state.copy()),
+            logger,
+            rate_limits: Arc::new(self.rate_limits.copy()),
+            asn_lists: Arc::new(Mutex::new(
+                self.asn_lists.lock().unwrap().clone(),
+            )),
+            prefix_lists: Arc::new(Mutex::new(
+                self.prefix_lists.lock().unwrap().clone(),
+            )),
+            ribs: Default::default(),
+            tags: Default::default(),
         }
     }
 
@@ -98,6 +181,44 @@ impl Ctx {
 #[derive(Copy, Clone, Debug)]
 pub struct OriginAsn(pub Option<Asn>);
 
+/// Records a call of a state method with the value read or written.
+fn trace_state(op: &'static str, key: &Arc<str>, value: impl Serialize) {
+    trace::record(|| TraceStep::State {
+        op,
+        key: key.clone(),
+        value: serde_json::to_value(value).unwrap_or_default(),
+    })
+}
+
+/// Sets the tag `key` to `value`, recording it.
+fn set_traced_tag(tags: &MutTags, key: &Arc<str>, value: TagValue) {
+    trace::record(|| TraceStep::Tag {
+        key: key.clone(),
+        value: value.clone(),
+    });
+    tags.lock().unwrap().set(key.clone(), value);
+}
+
+/// Records the update of a metric if tracing, returning whether it did.
+///
+/// A traced script does not update the metrics.
+fn traced_metric(op: &'static str, name: &Arc<str>, value: f64) -> bool {
+    if !trace::is_active() {
+        return false;
+    }
+    trace::record(|| TraceStep::Metric {
+        op,
+        name: name.clone(),
+        value,
+    });
+    true
+}
+
+/// Returns the time to live for a `ttl_secs` given to the state methods.
+fn state_ttl(ttl_secs: u64) -> Option<std::time::Duration> {
+    (ttl_secs > 0).then(|| std::time::Duration::from_secs(ttl_secs))
+}
+
 pub fn create_runtime() -> Result<roto::Runtime, String> {
     let mut rt = roto::Runtime::new();
 
@@ -121,6 +242,31 @@ pub fn create_runtime() -> Result<roto::Runtime, String> {
         "RPKI information retrieved via RTR",
     )?;
 
+    rt.register_clone_type_with_name::<SharedTrafficCounters>(
+        "Traffic",
+        "Traffic counted from flow telemetry by a 'flow-in' unit",
+    )?;
+
+    rt.register_clone_type_with_name::<SharedFlapDamping>(
+        "Flaps",
+        "Flap penalties of the paths of a 'rib' unit",
+    )?;
+
+    rt.register_clone_type_with_name::<SharedStateStore>(
+        "State",
+        "Values kept by the filter of a unit, by key",
+    )?;
+
+    rt.register_clone_type_with_name::<SharedScriptLogger>(
+        "Logger",
+        "Structured, rate limited logging to the Rotonda log",
+    )?;
+
+    rt.register_clone_type_with_name::<SharedRateLimits>(
+        "RateLimits",
+        "Token buckets of the filter of a unit, by key",
+    )?;
+
     rt.register_clone_type::<VrpUpdate>(
         "A single announced or withdrawn VRP"
     )?;
@@ -140,6 +286,16 @@ pub fn create_runtime() -> Result<roto::Runtime, String> {
         "Named lists of prefixes"
     ).unwrap();
 
+    rt.register_clone_type_with_name::<MutRibSelection>(
+        "Ribs",
+        "The named RIBs to write the route to"
+    )?;
+
+    rt.register_clone_type_with_name::<MutTags>(
+        "Tags",
+        "The tags to store with the route in the RIB"
+    )?;
+
     rt.register_context_type::<Ctx>()?;
 
     rt.register_copy_type::<InsertionInfo>(
@@ -169,6 +325,16 @@ pub fn create_runtime() -> Result<roto::Runtime, String> {
         "A BGP Large Community (RFC8092)",
     )?;
 
+    rt.register_copy_type_with_name::<ExternalData>(
+        "ExternalData",
+        "Data from an external data source, named by its ID",
+    )?;
+
+    rt.register_clone_type_with_name::<PrefixSetMatch>(
+        "PrefixSetMatch",
+        "The entry of a prefix set that matched a prefix, if any",
+    )?;
+
     #[roto_function(rt)]
     fn community(raw: u32) -> Val<StandardCommunity> {
         Val(StandardCommunity::from_u32(raw))
@@ -233,6 +399,43 @@ pub fn create_runtime() -> Result<roto::Runtime, String> {
         }
     }
 
+    /// Check whether the AS_PATH matches the regular expression `expr`
+    ///
+    /// The path is matched as its ASNs separated by spaces, with AS_SETs as
+    /// `{64496,64497}`. An `_` matches the start or end of the path or the
+    /// characters between ASNs, so `^174_.*_3356$` matches paths received
+    /// from AS174 originated by AS3356. Invalid expressions match nothing.
+    #[roto_method(rt, MutRotondaRoute, as_path_matches)]
+    fn rr_as_path_matches(
+        rr: Val<MutRotondaRoute>,
+        expr: Val<Arc<str>>,
+    ) -> bool {
+        let rr = rr.borrow_mut();
+        rr.owned_map()
+            .get::<HopPath>()
+            .is_some_and(|hoppath| aspath::matches(&hoppath, &expr))
+    }
+
+    /// Return the length of the AS_PATH as used in path selection
+    ///
+    /// AS_SETs count as one and confederation segments are not counted.
+    #[roto_method(rt, MutRotondaRoute, path_length)]
+    fn rr_path_length(rr: Val<MutRotondaRoute>) -> u32 {
+        let rr = rr.borrow_mut();
+        rr.owned_map().get::<HopPath>().map_or(0, |hoppath| {
+            hoppath.hop_count_path_selection() as u32
+        })
+    }
+
+    /// Return how often `asn` was prepended to itself in the AS_PATH
+    #[roto_method(rt, MutRotondaRoute, prepend_count)]
+    fn rr_prepend_count(rr: Val<MutRotondaRoute>, asn: Asn) -> u32 {
+        let rr = rr.borrow_mut();
+        rr.owned_map()
+            .get::<HopPath>()
+            .map_or(0, |hoppath| aspath::prepend_count(&hoppath, asn))
+    }
+
     /// Check whether the AS_PATH origin matches the given `Asn`
     #[roto_method(rt, MutRotondaRoute, match_aspath_origin)]
     fn rr_match_aspath_origin(
@@ -248,6 +451,71 @@ pub fn create_runtime() -> Result<roto::Runtime, String> {
         false
     }
 
+    /// Return the origin ASN of the AS_PATH
+    ///
+    /// Returns AS0 if the AS_PATH is empty or ends in an AS_SET.
+    #[roto_method(rt, MutRotondaRoute, origin_asn)]
+    fn rr_origin_asn(rr: Val<MutRotondaRoute>) -> Asn {
+        let rr = rr.borrow_mut();
+        if let Some(hoppath) = rr.owned_map().get::<HopPath>() {
+            if let Some(Hop::Asn(asn)) = hoppath.origin() {
+                return *asn;
+            }
+        }
+        Asn::from_u32(0)
+    }
+
+    /// Check whether the prefix of this route is a bogon
+    ///
+    /// Bogons are prefixes reserved for private use, documentation or other
+    /// special purposes, and their more-specifics. The built-in list can be
+    /// replaced in the `[roto_bogons]` section of the configuration.
+    #[roto_method(rt, MutRotondaRoute, is_bogon_prefix)]
+    fn rr_is_bogon_prefix(rr: Val<MutRotondaRoute>) -> bool {
+        let prefix = best_path::prefix_of(&rr.borrow());
+        bogons::current().is_bogon_prefix(prefix)
+    }
+
+    /// Check whether the AS_PATH contains a bogon ASN
+    ///
+    /// Bogon ASNs are AS0, AS_TRANS and the ASNs reserved for private use,
+    /// documentation or not allocated at all. The built-in list can be
+    /// replaced in the `[roto_bogons]` section of the configuration.
+    #[roto_method(rt, MutRotondaRoute, is_bogon_asn)]
+    fn rr_is_bogon_asn(rr: Val<MutRotondaRoute>) -> bool {
+        let rr = rr.borrow_mut();
+        let bogons = bogons::current();
+        rr.owned_map().get::<HopPath>().is_some_and(|hoppath| {
+            hoppath.into_iter().any(|hop| {
+                matches!(hop, Hop::Asn(asn) if bogons.is_bogon_asn(asn))
+            })
+        })
+    }
+
+    /// Check whether the prefix length is between `min` and `max` inclusive
+    #[roto_method(rt, MutRotondaRoute, prefix_len_in)]
+    fn rr_prefix_len_in(rr: Val<MutRotondaRoute>, min: u8, max: u8) -> bool {
+        (min..=max).contains(&best_path::prefix_of(&rr.borrow()).len())
+    }
+
+    /// Check whether this route is for the default route
+    #[roto_method(rt, MutRotondaRoute, is_default_route)]
+    fn rr_is_default_route(rr: Val<MutRotondaRoute>) -> bool {
+        best_path::prefix_of(&rr.borrow()).len() == 0
+    }
+
+    /// Check whether `prefix` is a bogon
+    #[roto_function(rt)]
+    fn is_bogon_prefix(prefix: Val<Prefix>) -> bool {
+        bogons::current().is_bogon_prefix(*prefix)
+    }
+
+    /// Check whether `asn` is a bogon
+    #[roto_function(rt)]
+    fn is_bogon_asn(asn: Asn) -> bool {
+        bogons::current().is_bogon_asn(asn)
+    }
+
     /// Check whether this `RotondaRoute` contains the given Standard Community
     #[roto_method(rt, MutRotondaRoute, contains_community)]
     fn rr_contains_community(
@@ -276,6 +544,75 @@ pub fn create_runtime() -> Result<roto::Runtime, String> {
         false
     }
 
+    /// Add the standard, extended or large community written as `community`
+    ///
+    /// The community is written like `65000:100`, `rt:65000:100` or
+    /// `65000:1:2`. Returns false if it is none of these. Adding a community
+    /// the route already has does nothing.
+    #[roto_method(rt, MutRotondaRoute, add_community)]
+    fn rr_add_community(
+        rr: Val<MutRotondaRoute>,
+        community: Val<Arc<str>>,
+    ) -> bool {
+        let mut rr = rr.borrow_mut();
+        communities::add_str(rr.rotonda_pamap_mut(), &community)
+    }
+
+    /// Add the large community `global:local1:local2`
+    #[roto_method(rt, MutRotondaRoute, add_large_community)]
+    fn rr_add_large_community(
+        rr: Val<MutRotondaRoute>,
+        global: u32,
+        local1: u32,
+        local2: u32,
+    ) {
+        let mut raw = [0; 12];
+        raw[..4].copy_from_slice(&global.to_be_bytes());
+        raw[4..8].copy_from_slice(&local1.to_be_bytes());
+        raw[8..].copy_from_slice(&local2.to_be_bytes());
+        let mut rr = rr.borrow_mut();
+        communities::add(
+            rr.rotonda_pamap_mut(),
+            Community::Large(LargeCommunity::from_raw(raw)),
+        );
+    }
+
+    /// Remove the communities matching `pattern`, returning how many
+    ///
+    /// Any member of the pattern can be a `*` wildcard, e.g. `65000:*`
+    /// matches the standard communities of AS65000 and `*:*:*` all large
+    /// communities. Invalid patterns match nothing.
+    #[roto_method(rt, MutRotondaRoute, strip_communities)]
+    fn rr_strip_communities(
+        rr: Val<MutRotondaRoute>,
+        pattern: Val<Arc<str>>,
+    ) -> u32 {
+        let mut rr = rr.borrow_mut();
+        communities::strip(rr.rotonda_pamap_mut(), &pattern)
+    }
+
+    /// Remove the communities not matching `pattern`, returning how many
+    ///
+    /// Invalid patterns match nothing, and nothing is removed.
+    #[roto_method(rt, MutRotondaRoute, keep_communities)]
+    fn rr_keep_communities(
+        rr: Val<MutRotondaRoute>,
+        pattern: Val<Arc<str>>,
+    ) -> u32 {
+        let mut rr = rr.borrow_mut();
+        communities::keep(rr.rotonda_pamap_mut(), &pattern)
+    }
+
+    /// Return the number of communities matching `pattern`
+    #[roto_method(rt, MutRotondaRoute, count_communities)]
+    fn rr_count_communities(
+        rr: Val<MutRotondaRoute>,
+        pattern: Val<Arc<str>>,
+    ) -> u32 {
+        let rr = rr.borrow();
+        communities::count(rr.rotonda_pamap(), &pattern)
+    }
+
     /// Check whether this `RotondaRoute` contains the given Path Attribute
     #[roto_method(rt, MutRotondaRoute, has_attribute)]
     fn rr_has_attribute(rr: Val<MutRotondaRoute>, to_match: u8) -> bool {
@@ -788,14 +1125,14 @@ pub fn create_runtime() -> Result<roto::Runtime, String> {
     /// Print a message to standard error
     #[roto_method(rt, Log)]
     fn print(stream: Val<Log>, msg: Val<Arc<str>>) {
-        let stream = stream.borrow();
+        let mut stream = stream.borrow_mut();
         stream.print(&*msg);
     }
 
     /// Print a timestamped message to standard error
     #[roto_method(rt, Log)]
     fn timestamped_print(stream: Val<Log>, msg: Val<Arc<str>>) {
-        let stream = stream.borrow();
+        let mut stream = stream.borrow_mut();
         stream.print(
             format!("[{}] {}",
                 Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
@@ -804,6 +1141,19 @@ pub fn create_runtime() -> Result<roto::Runtime, String> {
         );
     }
 
+    /// Route the updates for the current route to `route`
+    ///
+    /// Links that name routes, e.g. `sources = ["rib-in-post#alerts"]`,
+    /// only receive the updates routed to one of them, including any
+    /// output logged for the route. Only has an effect in the filter of a
+    /// rib unit.
+    #[roto_method(rt, Log)]
+    fn route_to(stream: Val<Log>, route: Val<Arc<str>>) {
+        trace::record(|| TraceStep::Route { name: route.0.clone() });
+        let mut stream = stream.borrow_mut();
+        stream.route_to(route.0);
+    }
+
     //------------ LogEntry --------------------------------------------------
 
     /// Get the current/new entry
@@ -1135,7 +1485,8 @@ pub fn create_runtime() -> Result<roto::Runtime, String> {
     ///
     /// In order for this method to have effect, a 'rtr-in' connector should
     /// be configured, and it should have received VRP data from the connected
-    /// RP software.
+    /// RP software. The results are counted in the 'rtr_rov_validations'
+    /// metric of that connector.
     #[roto_method(rt, SharedRtrCache)]
     fn check_rov(rpki: Val<SharedRtrCache>, rr: Val<MutRotondaRoute>) -> Val<RovStatus> {
         let mut rr = rr.borrow_mut();
@@ -1151,14 +1502,229 @@ pub fn create_runtime() -> Result<roto::Runtime, String> {
             if let Some(origin) = hoppath.origin()
                 .and_then(|o| Hop::try_into_asn(o.clone()).ok())
             {
-                rov_status = rpki.check_rov(&prefix, origin);
+                rov_status = rpki.validate(&prefix, origin);
             }
         }
 
         rr.rotonda_pamap_mut().set_rpki_info(rov_status.into());
+        trace::record(|| TraceStep::Rov { status: rov_status });
         Val(rov_status)
     }
 
+    /// Validate the origin `origin` of `prefix` against the VRPs (RFC6811)
+    ///
+    /// Returns Valid, Invalid or NotFound. Unlike `check_rov`, this does not
+    /// need a route and leaves the 'rpki_info' of routes alone. The results
+    /// are counted in the 'rtr_rov_validations' metric of the 'rtr-in'
+    /// unit.
+    #[roto_method(rt, SharedRtrCache, validate)]
+    fn rpki_validate(
+        rpki: Val<SharedRtrCache>,
+        prefix: Val<Prefix>,
+        origin: Asn,
+    ) -> Val<RovStatus> {
+        Val(rpki.validate(&prefix, origin))
+    }
+
+
+    //------------ Traffic ---------------------------------------------------
+
+    /// Returns the number of bytes seen for `prefix`
+    ///
+    /// The bytes are counted over the window of the 'flow-in' unit named in
+    /// the 'traffic' setting of the unit running the filter. Traffic is
+    /// counted per aggregate prefix, so for a prefix longer than the
+    /// aggregates this is the traffic of the aggregate covering it.
+    #[roto_method(rt, SharedTrafficCounters, bytes)]
+    fn traffic_bytes(traffic: Val<SharedTrafficCounters>, prefix: Val<Prefix>) -> u64 {
+        traffic.prefix(&prefix).bytes
+    }
+
+    /// Returns the number of packets seen for `prefix`
+    #[roto_method(rt, SharedTrafficCounters, packets)]
+    fn traffic_packets(traffic: Val<SharedTrafficCounters>, prefix: Val<Prefix>) -> u64 {
+        traffic.prefix(&prefix).packets
+    }
+
+    /// Returns the number of bytes forwarded to the BGP next hop `peer`
+    #[roto_method(rt, SharedTrafficCounters, peer_bytes)]
+    fn traffic_peer_bytes(traffic: Val<SharedTrafficCounters>, peer: IpAddr) -> u64 {
+        traffic.peer(peer).bytes
+    }
+
+    /// Returns the number of packets forwarded to the BGP next hop `peer`
+    #[roto_method(rt, SharedTrafficCounters, peer_packets)]
+    fn traffic_peer_packets(traffic: Val<SharedTrafficCounters>, peer: IpAddr) -> u64 {
+        traffic.peer(peer).packets
+    }
+
+
+    //------------ Flap damping ----------------------------------------------
+
+    /// Return the flap penalty of `route`
+    ///
+    /// Withdrawals add 1000 and updates of announced paths 500 by default,
+    /// halving every 15 minutes. Penalties are only tracked by 'rib' units
+    /// with flap_damping configured, and are 0 elsewhere.
+    #[roto_method(rt, SharedFlapDamping, penalty)]
+    fn flap_penalty(
+        flaps: Val<SharedFlapDamping>,
+        rr: Val<MutRotondaRoute>,
+    ) -> u32 {
+        let rr = rr.borrow_mut();
+        flaps.penalty(&best_path::prefix_of(&rr), Instant::now())
+    }
+
+    /// Check whether `route` is damped because it flaps
+    ///
+    /// A path is damped once its penalty exceeds the suppress threshold,
+    /// until it has decayed below the reuse threshold.
+    #[roto_method(rt, SharedFlapDamping, is_damped)]
+    fn flap_is_damped(
+        flaps: Val<SharedFlapDamping>,
+        rr: Val<MutRotondaRoute>,
+    ) -> bool {
+        let rr = rr.borrow_mut();
+        flaps.is_damped(&best_path::prefix_of(&rr), Instant::now())
+    }
+
+    //------------ State -----------------------------------------------------
+
+    /// Return the string value of `key`, or an empty string if it has none
+    ///
+    /// Integer values are returned formatted.
+    #[roto_method(rt, SharedStateStore, get)]
+    fn state_get(state: Val<SharedStateStore>, key: Val<Arc<str>>) -> Arc<str> {
+        let value: Arc<str> = match state.get(&key, Utc::now()) {
+            Some(StateValue::String(value)) => value,
+            Some(StateValue::Int(value)) => value.to_string().into(),
+            None => "".into(),
+        };
+        trace_state("get", &key, &*value);
+        value
+    }
+
+    /// Return the integer value of `key`, or 0 if it has none
+    #[roto_method(rt, SharedStateStore, get_int)]
+    fn state_get_int(state: Val<SharedStateStore>, key: Val<Arc<str>>) -> i64 {
+        let value = match state.get(&key, Utc::now()) {
+            Some(StateValue::Int(value)) => value,
+            _ => 0,
+        };
+        trace_state("get_int", &key, value);
+        value
+    }
+
+    /// Check whether `key` has a value
+    #[roto_method(rt, SharedStateStore, contains)]
+    fn state_contains(
+        state: Val<SharedStateStore>,
+        key: Val<Arc<str>>,
+    ) -> bool {
+        let value = state.get(&key, Utc::now()).is_some();
+        trace_state("contains", &key, value);
+        value
+    }
+
+    /// Set `key` to the string `value`, expiring after `ttl_secs`
+    ///
+    /// A `ttl_secs` of 0 keeps the value until it is replaced or removed.
+    #[roto_method(rt, SharedStateStore, set)]
+    fn state_set(
+        state: Val<SharedStateStore>,
+        key: Val<Arc<str>>,
+        value: Val<Arc<str>>,
+        ttl_secs: u64,
+    ) {
+        trace_state("set", &key, &**value);
+        state.set(
+            &key,
+            StateValue::String((*value).clone()),
+            state_ttl(ttl_secs),
+            Utc::now(),
+        )
+    }
+
+    /// Set `key` to the integer `value`, expiring after `ttl_secs`
+    #[roto_method(rt, SharedStateStore, set_int)]
+    fn state_set_int(
+        state: Val<SharedStateStore>,
+        key: Val<Arc<str>>,
+        value: i64,
+        ttl_secs: u64,
+    ) {
+        trace_state("set_int", &key, value);
+        state.set(&key, StateValue::Int(value), state_ttl(ttl_secs), Utc::now())
+    }
+
+    /// Increase the integer value of `key` by one, returning the new value
+    ///
+    /// A key without an integer value is set to 1, expiring after
+    /// `ttl_secs`. Otherwise it keeps its expiry time, so that
+    /// `state.incr(key, 3600)` counts per hour.
+    #[roto_method(rt, SharedStateStore, incr)]
+    fn state_incr(
+        state: Val<SharedStateStore>,
+        key: Val<Arc<str>>,
+        ttl_secs: u64,
+    ) -> i64 {
+        let value = state.incr(&key, state_ttl(ttl_secs), Utc::now());
+        trace_state("incr", &key, value);
+        value
+    }
+
+    /// Record an event for `key`, returning whether it is not a repeat
+    ///
+    /// Returns true for the first event and false for any further events
+    /// in the `ttl_secs` after it, after which the next event starts a new
+    /// window. So `if state.dedup(route.fmt_prefix(), 300) { ... }` acts
+    /// once per prefix every five minutes at most. The events of the window
+    /// are counted in the integer value of `key`. A `ttl_secs` of 0 never
+    /// starts a new window.
+    #[roto_method(rt, SharedStateStore, dedup)]
+    fn state_dedup(
+        state: Val<SharedStateStore>,
+        key: Val<Arc<str>>,
+        ttl_secs: u64,
+    ) -> bool {
+        let value = state.dedup(&key, state_ttl(ttl_secs), Utc::now());
+        trace_state("dedup", &key, value);
+        value
+    }
+
+    /// Remove the value of `key`
+    #[roto_method(rt, SharedStateStore, remove)]
+    fn state_remove(state: Val<SharedStateStore>, key: Val<Arc<str>>) {
+        trace_state("remove", &key, ());
+        state.remove(&key)
+    }
+
+    //------------ Logger ----------------------------------------------------
+
+    /// Add the field `key` with `value` to the next logged message
+    #[roto_method(rt, SharedScriptLogger, field)]
+    fn logger_field(
+        logger: Val<SharedScriptLogger>,
+        key: Val<Arc<str>>,
+        value: Val<Arc<str>>,
+    ) {
+        logger.field((*key).clone(), (*value).clone())
+    }
+
+    /// Log `msg` at level info, with the unit, ingress and added fields
+    ///
+    /// Messages are rate limited per call site, so that a message logged
+    /// for every route does not flood the log.
+    #[roto_method(rt, SharedScriptLogger, info)]
+    fn logger_info(logger: Val<SharedScriptLogger>, msg: Val<Arc<str>>) {
+        logger.log(log::Level::Info, &msg)
+    }
+
+    /// Log `msg` at level warning, with the unit, ingress and added fields
+    #[roto_method(rt, SharedScriptLogger, warn)]
+    fn logger_warn(logger: Val<SharedScriptLogger>, msg: Val<Arc<str>>) {
+        logger.log(log::Level::Warn, &msg)
+    }
 
     //------------ Lists -----------------------------------------------------
 
@@ -1232,6 +1798,320 @@ pub fn create_runtime() -> Result<roto::Runtime, String> {
 
 
 
+    //------------ Named RIBs ------------------------------------------------
+
+    /// Write the route to the named RIB of the rib unit
+    ///
+    /// The route is written to the named RIB regardless of whether the
+    /// filter accepts or rejects it. Routes that are no longer selected for
+    /// a named RIB are withdrawn from it.
+    #[roto_method(rt, MutRibSelection, add)]
+    fn add_to_rib(ribs: Val<MutRibSelection>, name: Val<Arc<str>>) {
+        trace::record(|| TraceStep::Rib { name: (*name).clone() });
+        ribs.lock().unwrap().add((*name).clone());
+    }
+
+    //------------ Tags ------------------------------------------------------
+
+    /// Tag the route with `key` set to the string `value`
+    ///
+    /// The tags are stored with the route in the RIB, replacing the tags it
+    /// was announced with before, and can be used to filter queries on.
+    #[roto_method(rt, MutTags, set)]
+    fn set_tag(tags: Val<MutTags>, key: Val<Arc<str>>, value: Val<Arc<str>>) {
+        set_traced_tag(&tags, &key, TagValue::String((*value).clone()));
+    }
+
+    /// Tag the route with `key` set to the integer `value`
+    #[roto_method(rt, MutTags, set_int)]
+    fn set_int_tag(tags: Val<MutTags>, key: Val<Arc<str>>, value: i64) {
+        set_traced_tag(&tags, &key, TagValue::Int(value));
+    }
+
+    /// Tag the route with `key` set to the boolean `value`
+    #[roto_method(rt, MutTags, set_bool)]
+    fn set_bool_tag(tags: Val<MutTags>, key: Val<Arc<str>>, value: bool) {
+        set_traced_tag(&tags, &key, TagValue::Bool(value));
+    }
+
+    //------------ External data ---------------------------------------------
+
+    /// Return whether the data of the source has been fetched
+    #[roto_method(rt, ExternalData, is_loaded)]
+    fn external_is_loaded(data: Val<ExternalData>) -> bool {
+        data.with(|_| ()).is_some()
+    }
+
+    /// Return whether the data contains `key`
+    ///
+    /// An object contains its member names, a list its elements and any
+    /// other value only itself.
+    #[roto_method(rt, ExternalData, contains)]
+    fn external_contains(data: Val<ExternalData>, key: Val<Arc<str>>) -> bool {
+        data.with(|value| value.contains(&key)).unwrap_or(false)
+    }
+
+    /// Return whether the data contains `asn`, with or without `AS`
+    #[roto_method(rt, ExternalData, contains_asn)]
+    fn external_contains_asn(data: Val<ExternalData>, asn: Asn) -> bool {
+        data.with(|value| value.contains_asn(asn)).unwrap_or(false)
+    }
+
+    /// Return whether a prefix in the data covers `prefix`
+    #[roto_method(rt, ExternalData, covers)]
+    fn external_covers(data: Val<ExternalData>, prefix: Val<Prefix>) -> bool {
+        data.longest_match(*prefix).is_some()
+    }
+
+    /// Return the AS that leaked `route`, or AS0 if there is none
+    ///
+    /// The data must be an AS relationship dataset in the CAIDA format,
+    /// loaded as text. The leaker is the first AS on the AS_PATH, from the
+    /// origin, that passed the route it learned from a provider or peer on
+    /// to another provider or peer. Links with an unknown relationship are
+    /// skipped.
+    #[roto_method(rt, ExternalData, route_leaker)]
+    fn external_route_leaker(
+        data: Val<ExternalData>,
+        rr: Val<MutRotondaRoute>,
+    ) -> Asn {
+        let rr = rr.borrow();
+        rr.owned_map()
+            .get::<HopPath>()
+            .and_then(|hoppath| data.leaker(&hoppath))
+            .unwrap_or(Asn::from_u32(0))
+    }
+
+    /// Return whether the AS_PATH of `route` is valley-free
+    ///
+    /// This is the case if `route_leaker` finds no leaker.
+    #[roto_method(rt, ExternalData, is_valley_free)]
+    fn external_is_valley_free(
+        data: Val<ExternalData>,
+        rr: Val<MutRotondaRoute>,
+    ) -> bool {
+        let rr = rr.borrow();
+        rr.owned_map()
+            .get::<HopPath>()
+            .is_none_or(|hoppath| data.leaker(&hoppath).is_none())
+    }
+
+    /// Return the most specific entry of a prefix set covering `prefix`
+    ///
+    /// The prefix set is the data of the external data source named `set`,
+    /// which can be a list of prefixes, a list of objects with a `prefix`
+    /// member, or an object with prefixes as member names. Nothing matches
+    /// if there is no source by that name.
+    #[roto_function(rt)]
+    fn prefix_set_match(
+        set: Val<Arc<str>>,
+        prefix: Val<Prefix>,
+    ) -> Val<PrefixSetMatch> {
+        Val(PrefixSetMatch(
+            ExternalData::by_id(&set).and_then(|s| s.longest_match(*prefix)),
+        ))
+    }
+
+    /// Return whether an entry matched
+    #[roto_method(rt, PrefixSetMatch, matched)]
+    fn match_matched(m: Val<PrefixSetMatch>) -> bool {
+        m.entry().is_some()
+    }
+
+    /// Return the prefix of the entry that matched
+    ///
+    /// Returns the IPv4 default route if no entry matched.
+    #[roto_method(rt, PrefixSetMatch, prefix)]
+    fn match_prefix(m: Val<PrefixSetMatch>) -> Prefix {
+        m.entry().map(|entry| entry.prefix).unwrap_or_else(|| {
+            Prefix::new_v4(std::net::Ipv4Addr::UNSPECIFIED, 0).unwrap()
+        })
+    }
+
+    /// Return member `key` of the metadata of the entry as a string
+    ///
+    /// Returns an empty string if there is no such member.
+    #[roto_method(rt, PrefixSetMatch, get)]
+    fn match_get(m: Val<PrefixSetMatch>, key: Val<Arc<str>>) -> Arc<str> {
+        m.meta(&key).and_then(|v| v.as_text()).unwrap_or_default().into()
+    }
+
+    /// Return member `key` of the metadata of the entry as an integer, or 0
+    #[roto_method(rt, PrefixSetMatch, get_int)]
+    fn match_get_int(m: Val<PrefixSetMatch>, key: Val<Arc<str>>) -> i64 {
+        m.meta(&key).and_then(|v| v.as_number()).map_or(0, |n| n as i64)
+    }
+
+    /// Return member `key` of the metadata of the entry as a boolean
+    #[roto_method(rt, PrefixSetMatch, get_bool)]
+    fn match_get_bool(m: Val<PrefixSetMatch>, key: Val<Arc<str>>) -> bool {
+        m.meta(&key).and_then(|v| v.as_bool()).unwrap_or(false)
+    }
+
+    //------------ IRR -------------------------------------------------------
+
+    /// Return whether `asn` is a member of the AS set `name`
+    ///
+    /// The AS set is expanded recursively by the IRRd server of the `irr`
+    /// external data source. Until the answer has been received, and if
+    /// there is no such source, nothing is a member.
+    #[roto_function(rt)]
+    fn in_as_set(name: Val<Arc<str>>, asn: Asn) -> bool {
+        irr::current().is_some_and(|irr| irr.in_as_set(&name, asn))
+    }
+
+    /// Return whether there is a route object for `prefix` originated by
+    /// `asn`
+    ///
+    /// The route objects are queried from the IRRd server of the `irr`
+    /// external data source. Until the answer has been received, and if
+    /// there is no such source, there are none.
+    #[roto_function(rt)]
+    fn irr_route_exists(prefix: Val<Prefix>, asn: Asn) -> bool {
+        irr::current().is_some_and(|irr| irr.route_exists(*prefix, asn))
+    }
+
+    //------------ DNS -------------------------------------------------------
+
+    /// Return the reverse DNS name of `addr`
+    ///
+    /// The name is looked up by the resolver of the `dns` external data
+    /// source. Until the answer has been received, if there is no name and
+    /// if there is no such source, the name is empty.
+    #[roto_function(rt)]
+    fn dns_ptr(addr: IpAddr) -> Arc<str> {
+        dns::current()
+            .and_then(|dns| dns.ptr(addr))
+            .unwrap_or_else(|| "".into())
+    }
+
+    /// Return the TXT records of `name`
+    ///
+    /// The strings of a record are joined and the records separated by a
+    /// space. They are looked up by the resolver of the `dns` external data
+    /// source. Until the answer has been received, if there are no records
+    /// and if there is no such source, the text is empty.
+    #[roto_function(rt)]
+    fn dns_txt(name: Val<Arc<str>>) -> Arc<str> {
+        dns::current()
+            .and_then(|dns| dns.txt(&name))
+            .unwrap_or_else(|| "".into())
+    }
+
+    //------------ Time ------------------------------------------------------
+
+    /// Return the current time as seconds since the Unix epoch
+    #[roto_function(rt)]
+    fn now() -> i64 {
+        Utc::now().timestamp()
+    }
+
+    /// Return the current day of the week in UTC, 1 for Monday to 7 for
+    /// Sunday
+    #[roto_function(rt)]
+    fn weekday() -> u32 {
+        Utc::now().weekday().number_from_monday()
+    }
+
+    /// Return the current hour of the day in UTC
+    #[roto_function(rt)]
+    fn hour() -> u32 {
+        Utc::now().hour()
+    }
+
+    /// Return whether the current time is within the time window `window`
+    ///
+    /// The window is written like `02:00-04:00 UTC Sat,Sun`: a start and
+    /// end time, optionally a time zone, being `UTC` or an offset like
+    /// `+02:00`, and optionally the weekdays it applies to, which can
+    /// include ranges like `Mon-Fri`. A window that ends before it starts
+    /// runs past midnight, one that ends when it starts lasts all day.
+    /// Invalid windows never match.
+    #[roto_function(rt)]
+    fn in_window(window: Val<Arc<str>>) -> bool {
+        time::in_window(&window, Utc::now())
+    }
+
+    //------------ Rate limiting ---------------------------------------------
+
+    /// Take a token from the bucket named `key`, returning whether there was
+    /// one
+    ///
+    /// Each bucket holds up to `burst` tokens, which it starts with, and is
+    /// refilled at `tokens_per_sec`, so that
+    /// `rate_limits.take("flap-alerts", 0.1, 5)` allows five alerts at once
+    /// and then one every ten seconds. Every unit has its own buckets.
+    #[roto_method(rt, SharedRateLimits, take)]
+    fn rate_limits_take(
+        rate_limits: Val<SharedRateLimits>,
+        key: Val<Arc<str>>,
+        tokens_per_sec: f64,
+        burst: u32,
+    ) -> bool {
+        rate_limits.take(&key, tokens_per_sec, burst)
+    }
+
+    //------------ Sampling --------------------------------------------------
+
+    /// Return true for about a fraction `rate` of the calls, at random
+    ///
+    /// With `if sample(0.01) { ... }` about one in a hundred routes is
+    /// passed on.
+    #[roto_function(rt)]
+    fn sample(rate: f64) -> bool {
+        sampling::sample(rate)
+    }
+
+    /// Return true for about a fraction `rate` of all keys
+    ///
+    /// The same key always gives the same answer, in all units and after
+    /// restarts, so that `sample_by_key(route.fmt_prefix(), 0.01)` passes
+    /// on all routes for about one in a hundred prefixes.
+    #[roto_function(rt)]
+    fn sample_by_key(key: Val<Arc<str>>, rate: f64) -> bool {
+        sampling::sample_by_key(&key, rate)
+    }
+
+    //------------ Metrics ---------------------------------------------------
+
+    /// Increase the counter `name` by one
+    ///
+    /// Counters, gauges and histograms are exported by name in the
+    /// 'roto_counter', 'roto_gauge' and 'roto_histogram' metrics. Names
+    /// consist of letters, digits and underscores.
+    #[roto_function(rt)]
+    fn metric_inc(name: Val<Arc<str>>) {
+        if !traced_metric("add", &name, 1.0) {
+            user_metrics::shared().add(&name, 1)
+        }
+    }
+
+    /// Increase the counter `name` by `value`
+    #[roto_function(rt)]
+    fn metric_add(name: Val<Arc<str>>, value: u64) {
+        if !traced_metric("add", &name, value as f64) {
+            user_metrics::shared().add(&name, value)
+        }
+    }
+
+    /// Set the gauge `name` to `value`
+    #[roto_function(rt)]
+    fn metric_set(name: Val<Arc<str>>, value: f64) {
+        if !traced_metric("set", &name, value) {
+            user_metrics::shared().set(&name, value)
+        }
+    }
+
+    /// Record `value` in the histogram `name`
+    ///
+    /// The buckets range from 1 to 100000 in steps of 1, 2 and 5.
+    #[roto_function(rt)]
+    fn metric_observe(name: Val<Arc<str>>, value: f64) {
+        if !traced_metric("observe", &name, value) {
+            user_metrics::shared().observe(&name, value)
+        }
+    }
+
     // currently unused
     //// --- InsertionInfo methods
     //#[roto_method(rt, InsertionInfo)]
@@ -1464,4 +2344,357 @@ mod tests {
         let _: RotoFuncVrpUpdate = c.get_function(ROTO_FUNC_VRP_UPDATE_FILTER_NAME).unwrap();
         let _: RotoFuncRovStatusUpdate = c.get_function(ROTO_FUNC_ROV_STATUS_UPDATE_NAME).unwrap();
     }
+
+    #[test]
+    fn rate_limits_per_context() {
+        let script = r#"
+            function allowed() -> bool {
+                rate_limits.take("runtime-test", 0.001, 2)
+            }
+        "#;
+        let mut c = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .inspect_err(|e| eprintln!("{e}"))
+            .unwrap();
+        let allowed = c.get_function::<Ctx, fn() -> bool>("allowed").unwrap();
+        let mut ctx = Ctx::empty();
+        assert!(allowed.call(&mut ctx));
+
+        // A trial run does not take from the buckets of the context.
+        let mut detached = ctx.detached(Default::default(), None);
+        assert!(allowed.call(&mut detached));
+        assert!(!allowed.call(&mut detached));
+
+        assert!(allowed.call(&mut ctx));
+        assert!(!allowed.call(&mut ctx));
+
+        // Other contexts, i.e., other units, have their own buckets.
+        assert!(allowed.call(&mut Ctx::empty()));
+    }
+
+    #[test]
+    fn sampling_functions() {
+        let script = r#"
+            function all() -> bool {
+                sample(1.0) && sample_by_key("192.0.2.0/24", 1.0)
+            }
+
+            function none() -> bool {
+                sample(0.0) || sample_by_key("192.0.2.0/24", 0.0)
+            }
+        "#;
+        let mut c = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .inspect_err(|e| eprintln!("{e}"))
+            .unwrap();
+        let all = c.get_function::<Ctx, fn() -> bool>("all").unwrap();
+        let none = c.get_function::<Ctx, fn() -> bool>("none").unwrap();
+        assert!(all.call(&mut Ctx::empty()));
+        assert!(!none.call(&mut Ctx::empty()));
+    }
+
+    #[test]
+    fn metric_functions() {
+        let script = r#"
+            function count() -> bool {
+                metric_inc("runtime_test_routes");
+                metric_add("runtime_test_routes", 2);
+                true
+            }
+        "#;
+        let mut c = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .inspect_err(|e| eprintln!("{e}"))
+            .unwrap();
+        let count = c.get_function::<Ctx, fn() -> bool>("count").unwrap();
+        assert!(count.call(&mut Ctx::empty()));
+
+        let target =
+            crate::tests::util::internal::get_testable_metrics_snapshot(
+                user_metrics::shared(),
+            );
+        assert_eq!(
+            target.with_label::<u64>(
+                "roto_counter",
+                ("name", "runtime_test_routes")
+            ),
+            3
+        );
+    }
+
+    #[test]
+    fn state_methods() {
+        let script = r#"
+            function count() -> i64 {
+                state.set("first", "AS65000", 0);
+                state.incr("seen", 60);
+                state.incr("seen", 60)
+            }
+        "#;
+        let mut c = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .inspect_err(|e| eprintln!("{e}"))
+            .unwrap();
+        let count = c.get_function::<Ctx, fn() -> i64>("count").unwrap();
+        let mut ctx = Ctx::empty();
+        assert_eq!(count.call(&mut ctx), 2);
+        assert_eq!(count.call(&mut ctx), 3);
+        assert_eq!(
+            ctx.state.get("first", Utc::now()),
+            Some(StateValue::String("AS65000".into()))
+        );
+        assert_eq!(
+            ctx.state.get("seen", Utc::now()),
+            Some(StateValue::Int(3))
+        );
+    }
+
+    #[test]
+    fn dedup_method() {
+        let script = r#"
+            function alert() -> bool {
+                state.dedup("alert AS65000", 300)
+            }
+        "#;
+        let mut c = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .inspect_err(|e| eprintln!("{e}"))
+            .unwrap();
+        let alert = c.get_function::<Ctx, fn() -> bool>("alert").unwrap();
+        let mut ctx = Ctx::empty();
+        assert!(alert.call(&mut ctx));
+        assert!(!alert.call(&mut ctx));
+        assert!(!alert.call(&mut ctx));
+        assert_eq!(
+            ctx.state.get("alert AS65000", Utc::now()),
+            Some(StateValue::Int(3))
+        );
+    }
+
+    #[test]
+    fn route_to_method() {
+        let script = r#"
+            function route() {
+                output.route_to("alerts");
+                output.route_to("archive");
+                output.route_to("alerts");
+            }
+        "#;
+        let mut c = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .inspect_err(|e| eprintln!("{e}"))
+            .unwrap();
+        let route = c.get_function::<Ctx, fn() -> ()>("route").unwrap();
+        let mut ctx = Ctx::empty();
+        route.call(&mut ctx);
+        let routes = ctx.output.borrow_mut().take_routes();
+        assert_eq!(routes, [Arc::from("alerts"), Arc::from("archive")]);
+        assert!(ctx.output.borrow_mut().take_routes().is_empty());
+    }
+
+    #[test]
+    fn logger_methods() {
+        let script = r#"
+            function log_rejected() {
+                logger.field("peer", "192.0.2.1");
+                logger.info("checking");
+                logger.field("reason", "rpki invalid");
+                logger.warn("rejected");
+            }
+        "#;
+        let mut c = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .inspect_err(|e| eprintln!("{e}"))
+            .unwrap();
+        let log_rejected =
+            c.get_function::<Ctx, fn() -> ()>("log_rejected").unwrap();
+        let mut ctx = Ctx::empty();
+        ctx.logger = Arc::new(ScriptLogger::new("rib-in".into()));
+        log_rejected.call(&mut ctx);
+    }
+
+    #[test]
+    fn irr_functions() {
+        let script = r#"
+            filter rib_in_pre(route: Route) {
+                if in_as_set("AS-EXAMPLE", route.origin_asn())
+                    || irr_route_exists(route.prefix(), route.origin_asn())
+                {
+                    accept
+                } else {
+                    reject
+                }
+            }
+        "#;
+        let mut c = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .inspect_err(|e| eprintln!("{e}"))
+            .unwrap();
+        let filter: crate::units::rib_unit::unit::RotoFuncPre =
+            c.get_function("rib_in_pre").unwrap();
+
+        // Without an IRR source nothing is known.
+        let update = crate::units::http_in::batch::PeerUpdate {
+            peer_address: "192.0.2.1".parse().unwrap(),
+            peer_asn: 65000,
+            announce: vec!["192.0.2.0/24".parse().unwrap()],
+            withdraw: vec![],
+            attributes: crate::units::http_in::batch::Attributes {
+                as_path: vec![65000],
+                next_hop: Some("192.0.2.1".parse().unwrap()),
+                ..Default::default()
+            },
+            peer_down: false,
+        };
+        let route = update.routes().unwrap().announced.remove(0);
+        assert!(matches!(
+            filter.call(&mut Ctx::empty(), Val(route.into())),
+            roto::Verdict::Reject(_)
+        ));
+    }
+
+    #[test]
+    fn dns_functions() {
+        let script = r#"
+            function reverse(addr: IpAddr) -> String {
+                dns_ptr(addr)
+            }
+
+            function text() -> String {
+                dns_txt("example.com")
+            }
+        "#;
+        let mut c = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .inspect_err(|e| eprintln!("{e}"))
+            .unwrap();
+        let reverse = c
+            .get_function::<Ctx, fn(IpAddr) -> Arc<str>>("reverse")
+            .unwrap();
+        let text = c.get_function::<Ctx, fn() -> Arc<str>>("text").unwrap();
+
+        // Without a DNS source there are no names.
+        let addr = "192.0.2.1".parse().unwrap();
+        assert_eq!(&*reverse.call(&mut Ctx::empty(), addr), "");
+        assert_eq!(&*text.call(&mut Ctx::empty()), "");
+    }
+
+    #[test]
+    fn community_methods() {
+        let script = r#"
+            filter rib_in_pre(route: Route) {
+                route.strip_communities("65000:*");
+                route.add_community("65001:100");
+                route.add_large_community(65001, 1, 2);
+                if route.count_communities("65001:*") == 1
+                    && route.contains_community(community(4259905636))
+                {
+                    accept
+                } else {
+                    reject
+                }
+            }
+        "#;
+        let mut c = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .inspect_err(|e| eprintln!("{e}"))
+            .unwrap();
+        let filter: crate::units::rib_unit::unit::RotoFuncPre =
+            c.get_function("rib_in_pre").unwrap();
+
+        let update = crate::units::http_in::batch::PeerUpdate {
+            peer_address: "192.0.2.1".parse().unwrap(),
+            peer_asn: 65000,
+            announce: vec!["192.0.2.0/24".parse().unwrap()],
+            withdraw: vec![],
+            attributes: crate::units::http_in::batch::Attributes {
+                next_hop: Some("192.0.2.1".parse().unwrap()),
+                communities: vec!["65000:1".parse().unwrap()],
+                ..Default::default()
+            },
+            peer_down: false,
+        };
+        let route: MutRotondaRoute =
+            update.routes().unwrap().announced.remove(0).into();
+        assert!(matches!(
+            filter.call(&mut Ctx::empty(), Val(route.clone())),
+            roto::Verdict::Accept(_)
+        ));
+        let route = route.borrow();
+        let pamap = route.rotonda_pamap();
+        assert_eq!(communities::count(pamap, "65000:*"), 0);
+        assert_eq!(communities::count(pamap, "65001:1:2"), 1);
+    }
+
+    #[test]
+    fn bogon_methods() {
+        let script = r#"
+            filter rib_in_pre(route: Route) {
+                if route.is_bogon_prefix()
+                    || route.is_bogon_asn()
+                    || is_bogon_asn(route.origin_asn())
+                    || route.is_default_route()
+                    || !route.prefix_len_in(8, 24)
+                {
+                    reject
+                } else {
+                    accept
+                }
+            }
+        "#;
+        let mut c = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .inspect_err(|e| eprintln!("{e}"))
+            .unwrap();
+        let filter: crate::units::rib_unit::unit::RotoFuncPre =
+            c.get_function("rib_in_pre").unwrap();
+
+        let accepts = |prefix: &str, as_path: Vec<u32>| {
+            let update = crate::units::http_in::batch::PeerUpdate {
+                peer_address: "192.0.2.1".parse().unwrap(),
+                peer_asn: 65000,
+                announce: vec![prefix.parse().unwrap()],
+                withdraw: vec![],
+                attributes: crate::units::http_in::batch::Attributes {
+                    as_path,
+                    next_hop: Some("192.0.2.1".parse().unwrap()),
+                    ..Default::default()
+                },
+                peer_down: false,
+            };
+            let route = update.routes().unwrap().announced.remove(0);
+            matches!(
+                filter.call(&mut Ctx::empty(), Val(route.into())),
+                roto::Verdict::Accept(_)
+            )
+        };
+        assert!(accepts("193.0.0.0/21", vec![3333]));
+        assert!(!accepts("192.0.2.0/24", vec![3333]));
+        assert!(!accepts("193.0.0.0/21", vec![64512, 3333]));
+        assert!(!accepts("193.0.0.0/21", vec![]));
+        assert!(!accepts("193.0.0.0/25", vec![3333]));
+        assert!(!accepts("0.0.0.0/0", vec![3333]));
+    }
+
+    #[test]
+    fn time_functions() {
+        let script = r#"
+            function always() -> bool {
+                in_window("00:00-00:00") && weekday() >= 1 && hour() < 24
+                    && now() > 0
+            }
+
+            function never() -> bool {
+                in_window("00:00-00:00 Moonday")
+            }
+        "#;
+        let mut c = roto::FileTree::test_file("test", script, 0)
+            .compile(create_runtime().unwrap())
+            .inspect_err(|e| eprintln!("{e}"))
+            .unwrap();
+        let always = c.get_function::<Ctx, fn() -> bool>("always").unwrap();
+        let never = c.get_function::<Ctx, fn() -> bool>("never").unwrap();
+        assert!(always.call(&mut Ctx::empty()));
+        assert!(!never.call(&mut Ctx::empty()));
+    }
 }
@@ -0,0 +1,77 @@
+//! Sampling for Roto scripts.
+//!
+//! Scripts can downsample high-volume streams before expensive targets.
+//! `sample(rate)` picks each call at random, so that about a fraction
+//! `rate` of the calls is picked. `sample_by_key(key, rate)` picks by a
+//! hash of the key instead, so that the same key, e.g. the text of a
+//! prefix, is always picked or never. As the hash is fixed, keys are picked
+//! the same way by all units and after restarts, and keys picked at a rate
+//! are picked at any higher rate as well.
+
+/// Returns whether to pick a call, at `rate` between 0 and 1.
+pub fn sample(rate: f64) -> bool {
+    rate > 0.0 && rand::random::<f64>() < rate
+}
+
+/// Returns whether to pick `key`, at `rate` between 0 and 1.
+pub fn sample_by_key(key: &str, rate: f64) -> bool {
+    // The hash as a fraction of the range of u64, from 0 up to 1.
+    let position = hash(key.as_bytes()) as f64 / (u64::MAX as f64 + 1.0);
+    position < rate
+}
+
+/// Returns a hash of `data` that does not change between versions.
+///
+/// This is FNV-1a followed by the SplitMix64 finalizer, so that similar
+/// keys such as prefixes spread evenly.
+fn hash(data: &[u8]) -> u64 {
+    let mut res: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in data {
+        res ^= u64::from(*byte);
+        res = res.wrapping_mul(0x0100_0000_01b3);
+    }
+    res ^= res >> 30;
+    res = res.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    res ^= res >> 27;
+    res = res.wrapping_mul(0x94d0_49bb_1331_11eb);
+    res ^ (res >> 31)
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_sampled_consistently() {
+        let keys = (0..10_000)
+            .map(|i| format!("10.{}.{}.0/24", i / 256, i % 256))
+            .collect::<Vec<_>>();
+        let picked = |rate| {
+            keys.iter()
+                .filter(|key| sample_by_key(key, rate))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let tenth = picked(0.1);
+        assert!((900..1100).contains(&tenth.len()), "{}", tenth.len());
+        assert_eq!(picked(0.1), tenth);
+        // Keys picked at a rate are picked at higher rates.
+        let half = picked(0.5);
+        assert!(tenth.iter().all(|key| half.contains(key)));
+
+        assert!(picked(0.0).is_empty());
+        assert_eq!(picked(1.0).len(), keys.len());
+        assert_eq!(hash(b""), hash(b""));
+        assert_ne!(hash(b"a"), hash(b"b"));
+    }
+
+    #[test]
+    fn calls_are_sampled() {
+        assert!(!(0..100).any(|_| sample(0.0)));
+        assert!((0..100).all(|_| sample(1.0)));
+        let picked = (0..10_000).filter(|_| sample(0.5)).count();
+        assert!((4000..6000).contains(&picked), "{picked}");
+    }
+}
@@ -0,0 +1,488 @@
+//! State kept by Roto scripts.
+//!
+//! Every unit running a Roto filter has a store of values by key, which the
+//! filter uses through the `state` context value, e.g.
+//! `state.incr("invalid-routes", 3600)` to count events per hour. Values are
+//! strings or integers, and may expire some time after they were set.
+//! `state.dedup(key, ttl_secs)` builds on this to suppress repeated events,
+//! such as alerts for a flapping prefix, within a time window.
+//!
+//! With a directory configured in the `[roto_state]` section, the store of
+//! each unit is saved in `<directory>/<unit name>.json` every
+//! `save_interval_secs` and when Rotonda shuts down, and loaded again when
+//! the unit starts, so that the state survives restarts.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use tokio::task::JoinHandle;
+
+use crate::config::ConfigPath;
+
+/// The number of entries kept before the expired ones are dropped.
+const SWEEP_SIZE: usize = 4096;
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct StateConfig {
+    /// The directory to save the state of the units to, if any.
+    #[serde(default)]
+    pub directory: Option<ConfigPath>,
+
+    /// How often to save the state.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "StateConfig::default_save_interval_secs")]
+    pub save_interval_secs: Duration,
+}
+
+impl StateConfig {
+    fn default_save_interval_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+}
+
+//------------ StateValue ----------------------------------------------------
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum StateValue {
+    Int(i64),
+    String(Arc<str>),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Entry {
+    value: StateValue,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<DateTime<Utc>>,
+}
+
+impl Entry {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+//------------ StateStore ----------------------------------------------------
+
+/// The values kept by the Roto filter of a unit.
+#[derive(Debug, Default)]
+pub struct StateStore {
+    state: Mutex<State>,
+
+    /// Whether there are changes that have not been saved.
+    dirty: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<Arc<str>, Entry>,
+
+    /// The number of entries at which to drop the expired ones.
+    sweep_at: usize,
+}
+
+impl StateStore {
+    /// Returns a copy of the values, for a trial run of a script that is
+    /// not to change them.
+    pub fn copy(&self) -> Self {
+        let state = self.state.lock().unwrap();
+        Self {
+            state: Mutex::new(State {
+                entries: state.entries.clone(),
+                sweep_at: state.sweep_at,
+            }),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the value of `key` at time `now`, if it has one.
+    pub fn get(&self, key: &str, now: DateTime<Utc>) -> Option<StateValue> {
+        let state = self.state.lock().unwrap();
+        state
+            .entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))
+            .map(|entry| entry.value.clone())
+    }
+
+    /// Sets `key` to `value` at time `now`, to expire after `ttl`, if any.
+    pub fn set(
+        &self,
+        key: &Arc<str>,
+        value: StateValue,
+        ttl: Option<Duration>,
+        now: DateTime<Utc>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.sweep(now);
+        state.entries.insert(
+            key.clone(),
+            Entry {
+                value,
+                expires: ttl.map(|ttl| expiry(now, ttl)),
+            },
+        );
+        self.dirty.store(true, Relaxed);
+    }
+
+    /// Increases the integer value of `key` at time `now`.
+    ///
+    /// If `key` has no value, or one that is not an integer, it is set to 1
+    /// and expires after `ttl`, if any. Otherwise it keeps its expiry time.
+    /// Returns the new value.
+    pub fn incr(
+        &self,
+        key: &Arc<str>,
+        ttl: Option<Duration>,
+        now: DateTime<Utc>,
+    ) -> i64 {
+        let mut state = self.state.lock().unwrap();
+        self.dirty.store(true, Relaxed);
+        if let Some(entry) = state.entries.get_mut(key) {
+            let expired = entry.is_expired(now);
+            if let (StateValue::Int(value), false) = (&mut entry.value, expired)
+            {
+                *value = value.saturating_add(1);
+                return *value;
+            }
+        }
+        state.sweep(now);
+        state.entries.insert(
+            key.clone(),
+            Entry {
+                value: StateValue::Int(1),
+                expires: ttl.map(|ttl| expiry(now, ttl)),
+            },
+        );
+        1
+    }
+
+    /// Records an event for `key` at time `now`.
+    ///
+    /// Returns whether this is the first event of a window. A window starts
+    /// with the first event for `key` and lasts `ttl`, if any. The events
+    /// in it are counted in the value of `key`, which expires with it.
+    pub fn dedup(
+        &self,
+        key: &Arc<str>,
+        ttl: Option<Duration>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        self.incr(key, ttl, now) == 1
+    }
+
+    /// Removes `key`.
+    pub fn remove(&self, key: &str) {
+        if self.state.lock().unwrap().entries.remove(key).is_some() {
+            self.dirty.store(true, Relaxed);
+        }
+    }
+
+    /// Returns the number of entries, including expired ones.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    /// Returns whether there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Loads the store saved at `path`.
+    ///
+    /// A missing file results in an empty store.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let entries: HashMap<Arc<str>, Entry> = match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Default::default()
+            }
+            Err(err) => return Err(err),
+        };
+        let now = Utc::now();
+        Ok(Self {
+            state: Mutex::new(State {
+                entries: entries
+                    .into_iter()
+                    .filter(|(_, entry)| !entry.is_expired(now))
+                    .collect(),
+                sweep_at: 0,
+            }),
+            dirty: Default::default(),
+        })
+    }
+
+    /// Saves the store to `path`, if it has changed.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if !self.dirty.swap(false, Relaxed) {
+            return Ok(());
+        }
+        let res = self.write(path);
+        if res.is_err() {
+            self.dirty.store(true, Relaxed);
+        }
+        res
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let json = {
+            let now = Utc::now();
+            let state = self.state.lock().unwrap();
+            let entries = state
+                .entries
+                .iter()
+                .filter(|(_, entry)| !entry.is_expired(now))
+                .collect::<HashMap<_, _>>();
+            serde_json::to_vec(&entries)?
+        };
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, json)?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+impl State {
+    /// Drops the expired entries, if there are many.
+    fn sweep(&mut self, now: DateTime<Utc>) {
+        if self.entries.len() < self.sweep_at.max(SWEEP_SIZE) {
+            return;
+        }
+        self.entries.retain(|_, entry| !entry.is_expired(now));
+        self.sweep_at = self.entries.len() * 2;
+    }
+}
+
+fn expiry(now: DateTime<Utc>, ttl: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(ttl)
+        .ok()
+        .and_then(|ttl| now.checked_add_signed(ttl))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+//------------ StateStores ---------------------------------------------------
+
+/// The state stores of all units, by unit name.
+#[derive(Debug, Default)]
+pub struct StateStores {
+    config: Mutex<StateConfig>,
+    stores: Mutex<HashMap<String, Arc<StateStore>>>,
+
+    /// The task saving the stores.
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl StateStores {
+    /// Applies the `[roto_state]` configuration.
+    ///
+    /// Stores already in use keep their state, and are saved to the new
+    /// directory from now on.
+    pub fn configure(&self, config: &StateConfig) {
+        *self.config.lock().unwrap() = config.clone();
+    }
+
+    /// Returns the store of the unit named `name`.
+    ///
+    /// The store is loaded from disk the first time it is asked for, if a
+    /// directory is configured.
+    pub fn get(&self, name: &str) -> Arc<StateStore> {
+        let mut stores = self.stores.lock().unwrap();
+        if let Some(store) = stores.get(name) {
+            return store.clone();
+        }
+        let store = match self.path(name) {
+            Some(path) => StateStore::load(&path).unwrap_or_else(|err| {
+                error!(
+                    "Failed to load roto state of unit {name} from {}: {err}",
+                    path.display()
+                );
+                Default::default()
+            }),
+            None => Default::default(),
+        };
+        let store = Arc::new(store);
+        stores.insert(name.to_string(), store.clone());
+        store
+    }
+
+    /// Starts saving the stores periodically, if a directory is configured.
+    pub fn start(self: &Arc<Self>) {
+        let mut task = self.task.lock().unwrap();
+        if let Some(task) = task.take() {
+            task.abort();
+        }
+        let config = self.config.lock().unwrap().clone();
+        let Some(directory) = config.directory else {
+            return;
+        };
+        if let Err(err) = fs::create_dir_all(&directory) {
+            warn!(
+                "Failed to create roto state directory {}: {err}",
+                directory.display()
+            );
+        }
+        let stores = self.clone();
+        *task = Some(tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(config.save_interval_secs);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let stores = stores.clone();
+                let _ = tokio::task::spawn_blocking(move || stores.save())
+                    .await;
+            }
+        }));
+    }
+
+    /// Saves all stores that have changed.
+    pub fn save(&self) {
+        let stores = self.stores.lock().unwrap().clone();
+        for (name, store) in stores {
+            let Some(path) = self.path(&name) else {
+                return;
+            };
+            match store.save(&path) {
+                Ok(()) => debug!("Saved roto state of unit {name}"),
+                Err(err) => error!(
+                    "Failed to save roto state of unit {name} to {}: {err}",
+                    path.display()
+                ),
+            }
+        }
+    }
+
+    /// Returns the path of the file of the store of unit `name`.
+    fn path(&self, name: &str) -> Option<PathBuf> {
+        let config = self.config.lock().unwrap();
+        config
+            .directory
+            .as_ref()
+            .map(|directory| directory.join(format!("{name}.json")))
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_expire() {
+        let store = StateStore::default();
+        let now = Utc::now();
+        let key: Arc<str> = "first-seen".into();
+        store.set(
+            &key,
+            StateValue::String("AS65000".into()),
+            Some(Duration::from_secs(60)),
+            now,
+        );
+        assert_eq!(
+            store.get(&key, now + chrono::Duration::seconds(59)),
+            Some(StateValue::String("AS65000".into()))
+        );
+        assert_eq!(store.get(&key, now + chrono::Duration::seconds(60)), None);
+
+        store.set(&key, StateValue::Int(7), None, now);
+        assert_eq!(
+            store.get(&key, now + chrono::Duration::days(365)),
+            Some(StateValue::Int(7))
+        );
+        store.remove(&key);
+        assert_eq!(store.get(&key, now), None);
+    }
+
+    #[test]
+    fn incr_keeps_the_expiry_time() {
+        let store = StateStore::default();
+        let now = Utc::now();
+        let key: Arc<str> = "updates".into();
+        let ttl = Some(Duration::from_secs(60));
+        assert_eq!(store.incr(&key, ttl, now), 1);
+        let later = now + chrono::Duration::seconds(30);
+        assert_eq!(store.incr(&key, ttl, later), 2);
+
+        // The count started at `now`, so it restarts after a minute
+        let much_later = now + chrono::Duration::seconds(61);
+        assert_eq!(store.incr(&key, ttl, much_later), 1);
+
+        // Strings are replaced
+        store.set(&key, StateValue::String("x".into()), None, now);
+        assert_eq!(store.incr(&key, None, now), 1);
+    }
+
+    #[test]
+    fn events_are_deduplicated() {
+        let store = StateStore::default();
+        let now = Utc::now();
+        let key: Arc<str> = "flap 192.0.2.0/24".into();
+        let ttl = Some(Duration::from_secs(60));
+        assert!(store.dedup(&key, ttl, now));
+        let later = now + chrono::Duration::seconds(59);
+        assert!(!store.dedup(&key, ttl, later));
+        assert_eq!(store.get(&key, later), Some(StateValue::Int(2)));
+
+        // The window started with the first event
+        let much_later = now + chrono::Duration::seconds(60);
+        assert!(store.dedup(&key, ttl, much_later));
+    }
+
+    #[test]
+    fn stores_are_saved_and_loaded() {
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-state-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = StateConfig {
+            directory: Some(dir.clone().into()),
+            ..Default::default()
+        };
+
+        let stores = StateStores::default();
+        stores.configure(&config);
+        let store = stores.get("rib");
+        let now = Utc::now();
+        store.set(&"asn".into(), StateValue::Int(65000), None, now);
+        store.set(
+            &"peer".into(),
+            StateValue::String("192.0.2.1".into()),
+            Some(Duration::from_secs(3600)),
+            now,
+        );
+        store.set(
+            &"gone".into(),
+            StateValue::Int(1),
+            Some(Duration::ZERO),
+            now,
+        );
+        stores.save();
+        assert!(dir.join("rib.json").exists());
+
+        let stores = StateStores::default();
+        stores.configure(&config);
+        let store = stores.get("rib");
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get("asn", now), Some(StateValue::Int(65000)));
+        assert_eq!(
+            store.get("peer", now),
+            Some(StateValue::String("192.0.2.1".into()))
+        );
+        assert!(stores.get("other").is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
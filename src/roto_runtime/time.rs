@@ -0,0 +1,212 @@
+//! Time windows for Roto scripts.
+//!
+//! A window is written as `02:00-04:00 UTC Sat,Sun`: a start and end time,
+//! an optional time zone, being `UTC` or an offset such as `+02:00`, and an
+//! optional list of weekdays, with ranges like `Mon-Fri` allowed. The start
+//! is part of the window, the end is not. A window ending before it starts
+//! runs past midnight, with the weekdays being those it starts on, and one
+//! ending when it starts lasts all day.
+//!
+//! Scripts check the same windows for every route, so parsed windows are
+//! kept in a process-wide cache.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, NaiveTime, Utc, Weekday,
+};
+use log::error;
+
+/// The maximum number of parsed windows kept.
+const CACHE_SIZE: usize = 1024;
+
+/// The parsed windows by their text, `None` if invalid.
+static CACHE: RwLock<Option<HashMap<Arc<str>, Option<Window>>>> =
+    RwLock::new(None);
+
+/// Returns whether `now` is in the window.
+///
+/// An invalid window is logged once and never contains any time.
+pub fn in_window(spec: &Arc<str>, now: DateTime<Utc>) -> bool {
+    if let Ok(cache) = CACHE.read() {
+        if let Some(window) = cache.as_ref().and_then(|c| c.get(spec)) {
+            return window.as_ref().is_some_and(|w| w.contains(now));
+        }
+    }
+    let window = Window::from_str(spec)
+        .inspect_err(|err| error!("Invalid time window '{spec}': {err}"))
+        .ok();
+    let res = window.as_ref().is_some_and(|w| w.contains(now));
+    if let Ok(mut cache) = CACHE.write() {
+        let cache = cache.get_or_insert_with(HashMap::new);
+        if cache.len() < CACHE_SIZE {
+            cache.insert(spec.clone(), window);
+        }
+    }
+    res
+}
+
+//------------ Window --------------------------------------------------------
+
+/// A recurring time window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Window {
+    start: NaiveTime,
+    end: NaiveTime,
+    offset: FixedOffset,
+
+    /// The days the window starts on, empty for every day.
+    days: Vec<Weekday>,
+}
+
+impl Window {
+    /// Returns whether `now` is in the window.
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.offset);
+        let time = local.time();
+        let start_day = if self.start == self.end {
+            local.weekday()
+        } else if self.start < self.end {
+            if time < self.start || time >= self.end {
+                return false;
+            }
+            local.weekday()
+        } else if time >= self.start {
+            local.weekday()
+        } else if time < self.end {
+            (local - Duration::days(1)).weekday()
+        } else {
+            return false;
+        };
+        self.days.is_empty() || self.days.contains(&start_day)
+    }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let (start, end) = parts
+            .next()
+            .and_then(|times| times.split_once('-'))
+            .ok_or("expected a time range like 02:00-04:00")?;
+        let time = |t: &str| {
+            NaiveTime::parse_from_str(t, "%H:%M")
+                .map_err(|_| format!("invalid time '{t}'"))
+        };
+        let (start, end) = (time(start)?, time(end)?);
+
+        let mut offset = FixedOffset::east_opt(0).unwrap();
+        let mut days = Vec::new();
+        for part in parts {
+            if part.eq_ignore_ascii_case("UTC") {
+                continue;
+            }
+            if part.starts_with(['+', '-']) {
+                offset = parse_offset(part)?;
+                continue;
+            }
+            for day in part.split(',').filter(|d| !d.is_empty()) {
+                match day.split_once('-') {
+                    Some((first, last)) => {
+                        let (mut day, last) =
+                            (weekday(first)?, weekday(last)?);
+                        days.push(day);
+                        while day != last {
+                            day = day.succ();
+                            days.push(day);
+                        }
+                    }
+                    None => days.push(weekday(day)?),
+                }
+            }
+        }
+        Ok(Window {
+            start,
+            end,
+            offset,
+            days,
+        })
+    }
+}
+
+fn weekday(s: &str) -> Result<Weekday, String> {
+    Weekday::from_str(s).map_err(|_| format!("invalid weekday '{s}'"))
+}
+
+/// Parses a UTC offset like `+02:00`, `+0200` or `-5`.
+fn parse_offset(s: &str) -> Result<FixedOffset, String> {
+    let err = || format!("invalid UTC offset '{s}'");
+    let (sign, rest) = s.split_at(1);
+    let digits = rest.replace(':', "");
+    let (hours, minutes) = match digits.len() {
+        1 | 2 => (digits.as_str(), "0"),
+        4 => digits.split_at(2),
+        _ => return Err(err()),
+    };
+    let hours: i32 = hours.parse().map_err(|_| err())?;
+    let minutes: i32 = minutes.parse().map_err(|_| err())?;
+    let secs = (hours * 60 + minutes) * 60;
+    let secs = if sign == "-" { -secs } else { secs };
+    FixedOffset::east_opt(secs).ok_or_else(err)
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().into()
+    }
+
+    fn window(s: &str) -> Window {
+        Window::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn windows_contain_times() {
+        // 2026-10-17 is a Saturday.
+        let w = window("02:00-04:00 UTC Sat,Sun");
+        assert!(w.contains(at("2026-10-17T02:00:00Z")));
+        assert!(w.contains(at("2026-10-18T03:59:59Z")));
+        assert!(!w.contains(at("2026-10-17T04:00:00Z")));
+        assert!(!w.contains(at("2026-10-16T03:00:00Z")));
+
+        let w = window("09:00-17:00 Mon-Fri");
+        assert!(w.contains(at("2026-10-16T12:00:00Z")));
+        assert!(!w.contains(at("2026-10-17T12:00:00Z")));
+
+        let w = window("00:00-00:00 Sat");
+        assert!(w.contains(at("2026-10-17T23:59:59Z")));
+        assert!(!w.contains(at("2026-10-18T00:00:00Z")));
+
+        let w = window("02:00-04:00 +02:00");
+        assert!(w.contains(at("2026-10-17T00:30:00Z")));
+        assert!(!w.contains(at("2026-10-17T02:30:00Z")));
+    }
+
+    #[test]
+    fn windows_run_past_midnight() {
+        let w = window("22:00-02:00 Fri");
+        assert!(w.contains(at("2026-10-16T23:00:00Z")));
+        assert!(w.contains(at("2026-10-17T01:00:00Z")));
+        assert!(!w.contains(at("2026-10-17T23:00:00Z")));
+        assert!(!w.contains(at("2026-10-16T01:00:00Z")));
+        assert!(!w.contains(at("2026-10-16T12:00:00Z")));
+    }
+
+    #[test]
+    fn invalid_windows_are_rejected() {
+        assert!(Window::from_str("02:00").is_err());
+        assert!(Window::from_str("25:00-04:00").is_err());
+        assert!(Window::from_str("02:00-04:00 Someday").is_err());
+        assert!(Window::from_str("02:00-04:00 +123").is_err());
+        assert!(!in_window(&"02:00".into(), at("2026-10-17T02:00:00Z")));
+        assert!(in_window(&"00:00-23:59".into(), at("2026-10-17T02:00:00Z")));
+    }
+}
@@ -0,0 +1,128 @@
+//! Recording the steps of a traced run of a script.
+//!
+//! Compiled scripts cannot be stepped through, but the functions they call
+//! can report what they were asked and what they answered. While a script
+//! runs inside [`capture`], these functions record a [`TraceStep`] each for
+//! the current thread, giving the values the script saw and the effects it
+//! had in the order they happened. Outside of it, nothing is recorded.
+//!
+//! Functions whose effects reach beyond the context of the script, such as
+//! the metrics, only record what they would have done while tracing.
+
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::types::TagValue;
+use crate::units::rib_unit::rpki::RovStatus;
+
+thread_local! {
+    /// The steps recorded so far, if tracing.
+    static STEPS: RefCell<Option<Vec<TraceStep>>> = const {
+        RefCell::new(None)
+    };
+}
+
+/// Runs `op`, returning its result and the steps recorded meanwhile.
+pub fn capture<T>(op: impl FnOnce() -> T) -> (T, Vec<TraceStep>) {
+    let outer = STEPS.replace(Some(Vec::new()));
+    let res = op();
+    let steps = STEPS.replace(outer).unwrap_or_default();
+    (res, steps)
+}
+
+/// Returns whether a script is being traced on the current thread.
+pub fn is_active() -> bool {
+    STEPS.with_borrow(Option::is_some)
+}
+
+/// Records the step returned by `step` if tracing.
+pub fn record(step: impl FnOnce() -> TraceStep) {
+    STEPS.with_borrow_mut(|steps| {
+        if let Some(steps) = steps {
+            steps.push(step())
+        }
+    })
+}
+
+//------------ TraceStep -----------------------------------------------------
+
+/// Something a script did while traced.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum TraceStep {
+    /// A record logged through `logger`, with its fields.
+    Log {
+        level: String,
+        message: String,
+        fields: Map<String, Value>,
+    },
+
+    /// A message printed with `output.print()`.
+    Print { message: String },
+
+    /// A call of a `state` method, with the value read or written.
+    State {
+        op: &'static str,
+        key: Arc<str>,
+        value: Value,
+    },
+
+    /// The outcome of `rpki.check_rov()`.
+    Rov { status: RovStatus },
+
+    /// A tag set with `tags.set()` and its siblings.
+    Tag { key: Arc<str>, value: TagValue },
+
+    /// A named RIB selected with `ribs.add()`.
+    Rib { name: Arc<str> },
+
+    /// A route selected with `output.route_to()`.
+    Route { name: Arc<str> },
+
+    /// A metric that would have been updated.
+    Metric {
+        op: &'static str,
+        name: Arc<str>,
+        value: f64,
+    },
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_are_only_recorded_while_capturing() {
+        let print = |message: &str| {
+            record(|| TraceStep::Print {
+                message: message.into(),
+            })
+        };
+        print("before");
+        assert!(!is_active());
+
+        let (res, steps) = capture(|| {
+            print("outer");
+            let ((), inner) = capture(|| print("inner"));
+            assert_eq!(inner.len(), 1);
+            is_active()
+        });
+        assert!(res);
+        assert_eq!(
+            steps,
+            [TraceStep::Print {
+                message: "outer".into()
+            }]
+        );
+        assert!(!is_active());
+        assert_eq!(
+            serde_json::to_string(&steps).unwrap(),
+            r#"[{"step":"print","message":"outer"}]"#
+        );
+    }
+}
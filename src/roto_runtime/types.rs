@@ -1,6 +1,7 @@
 use core::fmt;
 use std::{
-    cell::RefCell, collections::{HashMap, HashSet}, net::IpAddr, path::PathBuf, rc::Rc,
+    cell::RefCell, collections::{BTreeMap, HashMap, HashSet}, net::IpAddr, path::PathBuf, rc::Rc,
+    sync::Arc,
 };
 
 use bytes::Bytes;
@@ -12,7 +13,7 @@ use rotonda_store::prefix_record::RouteStatus;
 use routecore::bgp::{
     message::UpdateMessage, nlri::afisafi::Nlri, types::AfiSafiType,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     ingress::IngressId,
@@ -20,6 +21,7 @@ use crate::{
     payload::{RotondaPaMap, RotondaRoute},
 };
 
+use super::trace::{self, TraceStep};
 use super::MutLogEntry;
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -90,6 +92,10 @@ pub type CompiledRoto = std::sync::Mutex<roto::Compiled>;
 pub struct OutputStream<M> {
     msgs: Vec<M>,
     entry: MutLogEntry,
+    routes: Vec<Arc<str>>,
+
+    /// Whether printed messages are traced rather than printed.
+    capture_prints: bool,
 }
 
 pub type RotoOutputStream = OutputStream<Output>;
@@ -103,6 +109,8 @@ impl<M> OutputStream<M> {
         Self {
             msgs: v,
             entry: Rc::new(RefCell::new(LogEntry::new())),
+            routes: vec![],
+            capture_prints: false,
         }
     }
 
@@ -131,8 +139,31 @@ impl<M> OutputStream<M> {
         std::mem::take(&mut self.entry)
     }
 
-    pub fn print(&self, msg: impl AsRef<str>) {
-        eprintln!("{}", msg.as_ref());
+    /// Routes the updates for the current route to `route`.
+    pub fn route_to(&mut self, route: Arc<str>) {
+        if !self.routes.contains(&route) {
+            self.routes.push(route);
+        }
+    }
+
+    /// Returns the routes, leaving none behind.
+    pub fn take_routes(&mut self) -> Vec<Arc<str>> {
+        std::mem::take(&mut self.routes)
+    }
+
+    /// Traces printed messages rather than printing them.
+    pub fn capture_prints(&mut self) {
+        self.capture_prints = true;
+    }
+
+    pub fn print(&mut self, msg: impl AsRef<str>) {
+        if self.capture_prints {
+            trace::record(|| TraceStep::Print {
+                message: msg.as_ref().into(),
+            });
+        } else {
+            eprintln!("{}", msg.as_ref());
+        }
     }
 }
 
@@ -171,6 +202,84 @@ pub enum Output {
     Entry(LogEntry),
 }
 
+/// The named RIBs a roto filter selected for the route at hand.
+///
+/// Only has an effect in the filter of a rib unit that declares those RIBs,
+/// see `named_ribs` in the rib unit configuration.
+#[derive(Clone, Debug, Default)]
+pub struct RibSelection {
+    selected: Vec<Arc<str>>,
+}
+
+pub type MutRibSelection = std::sync::Arc<std::sync::Mutex<RibSelection>>;
+
+impl RibSelection {
+    pub fn add(&mut self, name: Arc<str>) {
+        if !self.selected.contains(&name) {
+            self.selected.push(name);
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.selected.iter().any(|selected| **selected == *name)
+    }
+
+    /// Returns the selected names, leaving the selection empty.
+    pub fn take(&mut self) -> Vec<Arc<str>> {
+        std::mem::take(&mut self.selected)
+    }
+}
+
+/// The tags a roto filter attached to the route at hand.
+///
+/// Only has an effect in the filter of a rib unit, which stores the tags
+/// with the route in the RIB.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Tags {
+    tags: BTreeMap<Arc<str>, TagValue>,
+}
+
+pub type MutTags = std::sync::Arc<std::sync::Mutex<Tags>>;
+
+impl Tags {
+    pub fn set(&mut self, key: Arc<str>, value: TagValue) {
+        self.tags.insert(key, value);
+    }
+
+    pub fn get(&self, key: &str) -> Option<&TagValue> {
+        self.tags.get(key)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// Returns the tags, leaving no tags behind.
+    pub fn take(&mut self) -> Tags {
+        std::mem::take(self)
+    }
+}
+
+/// The value of a tag.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum TagValue {
+    String(Arc<str>),
+    Int(i64),
+    Bool(bool),
+}
+
+impl fmt::Display for TagValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagValue::String(value) => write!(f, "{value}"),
+            TagValue::Int(value) => write!(f, "{value}"),
+            TagValue::Bool(value) => write!(f, "{value}"),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct InsertionInfo {
     pub prefix_new: bool,
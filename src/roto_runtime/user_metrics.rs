@@ -0,0 +1,270 @@
+//! Metrics defined by Roto scripts.
+//!
+//! Scripts count, measure and observe whatever they like by name, e.g.
+//! `metric_inc("rejected_rpki_invalid")`. A metric comes into existence the
+//! first time it is used, its type given by the function used: counters are
+//! increased, gauges set and histograms observed.
+//!
+//! The metrics are shared by all scripts in the process and exported as the
+//! `roto_counter`, `roto_gauge` and `roto_histogram` metrics, with the name
+//! given by the script in the `name` label.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use log::warn;
+
+use crate::metrics::{self, Metric, MetricType, MetricUnit};
+
+/// The maximum number of metrics of each type.
+///
+/// Every name is a separate time series, so names should not be derived
+/// from e.g. prefixes. Names beyond this number are ignored.
+const MAX_METRICS: usize = 1000;
+
+/// The upper bounds of the histogram buckets.
+const BUCKETS: [f64; 16] = [
+    1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0,
+    5000.0, 10000.0, 20000.0, 50000.0, 100000.0,
+];
+
+/// Returns the metrics of all scripts.
+pub fn shared() -> &'static Arc<UserMetrics> {
+    static METRICS: OnceLock<Arc<UserMetrics>> = OnceLock::new();
+    METRICS.get_or_init(Default::default)
+}
+
+//------------ UserMetrics ---------------------------------------------------
+
+/// Counters, gauges and histograms by name.
+#[derive(Debug, Default)]
+pub struct UserMetrics {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    counters: HashMap<Arc<str>, u64>,
+    gauges: HashMap<Arc<str>, f64>,
+    histograms: HashMap<Arc<str>, Histogram>,
+
+    /// Whether a warning about too many metrics was logged.
+    warned: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    /// The number of observations per bucket, the last one for those above
+    /// all bounds.
+    buckets: [u64; BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl UserMetrics {
+    const COUNTER_METRIC: Metric = Metric::new(
+        "roto_counter",
+        "counters increased by roto scripts, by name",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const GAUGE_METRIC: Metric = Metric::new(
+        "roto_gauge",
+        "gauges set by roto scripts, by name",
+        MetricType::Gauge,
+        MetricUnit::State,
+    );
+    const HISTOGRAM_METRIC: Metric = Metric::new(
+        "roto_histogram",
+        "histograms of the values observed by roto scripts, by name",
+        MetricType::Histogram,
+        MetricUnit::Total,
+    );
+
+    /// Adds `value` to the counter `name`.
+    pub fn add(&self, name: &Arc<str>, value: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(counter) = state.entry(name, |state| &mut state.counters)
+        {
+            *counter = counter.saturating_add(value);
+        }
+    }
+
+    /// Sets the gauge `name` to `value`.
+    pub fn set(&self, name: &Arc<str>, value: f64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(gauge) = state.entry(name, |state| &mut state.gauges) {
+            *gauge = value;
+        }
+    }
+
+    /// Records `value` in the histogram `name`.
+    pub fn observe(&self, name: &Arc<str>, value: f64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(histogram) =
+            state.entry(name, |state| &mut state.histograms)
+        {
+            let bucket = BUCKETS
+                .iter()
+                .position(|bound| value <= *bound)
+                .unwrap_or(BUCKETS.len());
+            histogram.buckets[bucket] += 1;
+            histogram.sum += value;
+        }
+    }
+}
+
+impl State {
+    /// Returns the metric `name` in the map selected by `map`.
+    ///
+    /// Returns `None` if the name is invalid or there are too many metrics
+    /// already, logging a warning.
+    fn entry<'a, T: Default>(
+        &'a mut self,
+        name: &Arc<str>,
+        map: impl Fn(&mut Self) -> &mut HashMap<Arc<str>, T>,
+    ) -> Option<&'a mut T> {
+        if !map(self).contains_key(name) {
+            if !is_valid_name(name) {
+                warn!("Ignoring roto metric with invalid name '{name}'");
+                return None;
+            }
+            if map(self).len() >= MAX_METRICS {
+                if !self.warned {
+                    warn!(
+                        "Ignoring roto metric '{name}' and any further new \
+                        ones: limit of {MAX_METRICS} reached"
+                    );
+                    self.warned = true;
+                }
+                return None;
+            }
+        }
+        Some(map(self).entry(name.clone()).or_default())
+    }
+}
+
+/// Returns whether `name` can be used as a metric name.
+///
+/// Names are used as label values, which could hold anything, but are kept
+/// to what would be valid as a Prometheus metric name.
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl metrics::Source for UserMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        let state = self.state.lock().unwrap();
+        if !state.counters.is_empty() {
+            target.append(&Self::COUNTER_METRIC, Some(unit_name), |records| {
+                for (name, value) in &state.counters {
+                    records.label_value(&[("name", name)], *value);
+                }
+            });
+        }
+        if !state.gauges.is_empty() {
+            target.append(&Self::GAUGE_METRIC, Some(unit_name), |records| {
+                for (name, value) in &state.gauges {
+                    records.label_value(&[("name", name)], *value);
+                }
+            });
+        }
+        if !state.histograms.is_empty() {
+            target.append(
+                &Self::HISTOGRAM_METRIC,
+                Some(unit_name),
+                |records| {
+                    for (name, histogram) in &state.histograms {
+                        let bounds = BUCKETS
+                            .iter()
+                            .map(ToString::to_string)
+                            .chain(["+Inf".to_string()]);
+                        let mut count = 0;
+                        for (bound, bucket) in bounds.zip(&histogram.buckets)
+                        {
+                            count += bucket;
+                            records.suffixed_label_value(
+                                &[("name", name), ("le", &bound)],
+                                count,
+                                Some("bucket"),
+                            );
+                        }
+                        records.suffixed_label_value(
+                            &[("name", name)],
+                            histogram.sum,
+                            Some("sum"),
+                        );
+                        records.suffixed_label_value(
+                            &[("name", name)],
+                            count,
+                            Some("count"),
+                        );
+                    }
+                },
+            );
+        }
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_are_exported_by_name() {
+        let metrics = Arc::new(UserMetrics::default());
+        let rejected: Arc<str> = "rejected".into();
+        metrics.add(&rejected, 1);
+        metrics.add(&rejected, 2);
+        metrics.set(&"peers_up".into(), 3.5);
+        for value in [1.0, 3.0, 3.0, 1e6] {
+            metrics.observe(&"path_length".into(), value);
+        }
+
+        // Invalid names are ignored
+        metrics.add(&"not a name".into(), 1);
+        metrics.add(&"".into(), 1);
+
+        let target =
+            crate::tests::util::internal::get_testable_metrics_snapshot(
+                &metrics,
+            );
+        assert_eq!(
+            target.with_label::<u64>("roto_counter", ("name", "rejected")),
+            3
+        );
+        assert_eq!(
+            target.with_label::<f64>("roto_gauge", ("name", "peers_up")),
+            3.5
+        );
+        let bucket = |le| {
+            target.with_labels::<u64>(
+                "roto_histogram",
+                &[("name", "path_length"), ("le", le)],
+            )
+        };
+        assert_eq!(bucket("1"), 1);
+        assert_eq!(bucket("2"), 1);
+        assert_eq!(bucket("5"), 3);
+        assert_eq!(bucket("+Inf"), 4);
+    }
+
+    #[test]
+    fn number_of_metrics_is_limited() {
+        let metrics = UserMetrics::default();
+        for i in 0..MAX_METRICS + 10 {
+            metrics.add(&format!("counter_{i}").into(), 1);
+        }
+        metrics.add(&"counter_0".into(), 1);
+
+        let state = metrics.state.lock().unwrap();
+        assert_eq!(state.counters.len(), MAX_METRICS);
+        assert_eq!(state.counters["counter_0"], 2);
+        assert!(state.warned);
+    }
+}
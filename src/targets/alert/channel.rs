@@ -0,0 +1,218 @@
+//! The chat services alerts are sent to.
+//!
+//! A channel is a Slack incoming webhook, a Matrix room or a Telegram chat.
+//! As these services are only reachable over HTTPS, requests are made with
+//! a client of the target's own, using its `tls` settings, rather than the
+//! shared HTTP client.
+
+use reqwest::{
+    header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+    Method, Request,
+};
+use serde::Deserialize;
+use url::Url;
+
+//------------ ChannelConfig -------------------------------------------------
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ChannelConfig {
+    /// A Slack incoming webhook.
+    Slack { webhook_url: Url },
+
+    /// A Matrix room, with the access token of the user posting to it.
+    Matrix {
+        homeserver: Url,
+        room_id: String,
+        access_token: String,
+    },
+
+    /// A Telegram chat, with the token of the bot posting to it.
+    Telegram {
+        bot_token: String,
+        chat_id: String,
+
+        #[serde(default = "ChannelConfig::default_telegram_api_url")]
+        api_url: Url,
+    },
+}
+
+impl ChannelConfig {
+    fn default_telegram_api_url() -> Url {
+        Url::parse("https://api.telegram.org/").unwrap()
+    }
+
+    /// Returns the URL requests go to, apart from their path.
+    pub fn base_url(&self) -> &Url {
+        match self {
+            ChannelConfig::Slack { webhook_url } => webhook_url,
+            ChannelConfig::Matrix { homeserver, .. } => homeserver,
+            ChannelConfig::Telegram { api_url, .. } => api_url,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        let url = self.base_url();
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!(
+                "unsupported URL scheme '{}', use http or https",
+                url.scheme()
+            ));
+        }
+        if url.host_str().is_none() {
+            return Err("missing host in URL".into());
+        }
+        Ok(())
+    }
+
+    /// Returns the request posting a message.
+    ///
+    /// Matrix needs a transaction ID unique to the message, which is used
+    /// by the server to recognize a message sent more than once.
+    pub fn request(&self, text: &str, txn_id: &str) -> Request {
+        match self {
+            ChannelConfig::Slack { webhook_url } => json_request(
+                Method::POST,
+                webhook_url.clone(),
+                None,
+                serde_json::json!({ "text": text }),
+            ),
+            ChannelConfig::Matrix {
+                homeserver,
+                room_id,
+                access_token,
+            } => json_request(
+                Method::PUT,
+                with_path(
+                    homeserver,
+                    &[
+                        "_matrix",
+                        "client",
+                        "v3",
+                        "rooms",
+                        room_id,
+                        "send",
+                        "m.room.message",
+                        txn_id,
+                    ],
+                ),
+                Some(format!("Bearer {access_token}")),
+                serde_json::json!({
+                    "msgtype": "m.text",
+                    "body": text,
+                }),
+            ),
+            ChannelConfig::Telegram {
+                bot_token,
+                chat_id,
+                api_url,
+            } => json_request(
+                Method::POST,
+                with_path(
+                    api_url,
+                    &[&format!("bot{bot_token}"), "sendMessage"],
+                ),
+                None,
+                serde_json::json!({
+                    "chat_id": chat_id,
+                    "text": text,
+                }),
+            ),
+        }
+    }
+}
+
+/// Returns the URL with the segments appended to its path.
+fn with_path(url: &Url, segments: &[&str]) -> Url {
+    let mut url = url.clone();
+    if let Ok(mut path) = url.path_segments_mut() {
+        path.pop_if_empty().extend(segments);
+    }
+    url
+}
+
+/// Returns the request posting a JSON body.
+fn json_request(
+    method: Method,
+    url: Url,
+    authorization: Option<String>,
+    body: serde_json::Value,
+) -> Request {
+    let mut request = Request::new(method, url);
+    let headers = request.headers_mut();
+    headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if let Some(value) =
+        authorization.and_then(|value| HeaderValue::try_from(value).ok())
+    {
+        headers.insert(AUTHORIZATION, value);
+    }
+    *request.body_mut() = Some(body.to_string().into());
+    request
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn body(request: &Request) -> serde_json::Value {
+        let body = request.body().and_then(|body| body.as_bytes());
+        serde_json::from_slice(body.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn requests_suit_the_service() {
+        let channel: ChannelConfig = toml::from_str(
+            "type = \"slack\"\n\
+            webhook_url = \"https://hooks.slack.com/services/T0/B0/x\"",
+        )
+        .unwrap();
+        let request = channel.request("hi", "1");
+        assert_eq!(*request.method(), Method::POST);
+        assert_eq!(
+            request.url().as_str(),
+            "https://hooks.slack.com/services/T0/B0/x"
+        );
+        assert_eq!(body(&request), json!({ "text": "hi" }));
+
+        let channel: ChannelConfig = toml::from_str(
+            "type = \"matrix\"\n\
+            homeserver = \"https://matrix.example.com\"\n\
+            room_id = \"!abc:example.com\"\n\
+            access_token = \"secret\"",
+        )
+        .unwrap();
+        let request = channel.request("hi", "42");
+        assert_eq!(*request.method(), Method::PUT);
+        assert_eq!(
+            request.url().as_str(),
+            "https://matrix.example.com/_matrix/client/v3/rooms/\
+            !abc:example.com/send/m.room.message/42"
+        );
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer secret");
+        assert_eq!(
+            body(&request),
+            json!({ "msgtype": "m.text", "body": "hi" })
+        );
+
+        let channel: ChannelConfig = toml::from_str(
+            "type = \"telegram\"\n\
+            bot_token = \"123:abc\"\n\
+            chat_id = \"-100\"",
+        )
+        .unwrap();
+        let request = channel.request("hi", "1");
+        assert_eq!(
+            request.url().as_str(),
+            "https://api.telegram.org/bot123:abc/sendMessage"
+        );
+        assert_eq!(
+            body(&request),
+            json!({ "chat_id": "-100", "text": "hi" })
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst},
+    Arc,
+};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{
+        self, util::append_labelled_metric, Metric, MetricType, MetricUnit,
+    },
+};
+
+/// The metrics of all rules and channels of the target.
+#[derive(Debug, Default)]
+pub struct AlertMetrics {
+    pub rules: Vec<(String, Arc<RuleMetrics>)>,
+    pub channels: Vec<(String, Arc<ChannelMetrics>)>,
+}
+
+#[derive(Debug, Default)]
+pub struct RuleMetrics {
+    pub alert_count: AtomicUsize,
+    pub duplicate_count: AtomicUsize,
+    pub rate_limited_count: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+pub struct ChannelMetrics {
+    pub sent_count: AtomicUsize,
+    pub request_error_count: AtomicUsize,
+    pub dropped_count: AtomicUsize,
+
+    /// Whether the last alert could not be sent.
+    pub failing: AtomicBool,
+}
+
+impl GraphStatus for AlertMetrics {
+    fn status_text(&self) -> String {
+        let (mut sent, mut dropped, mut suppressed) = (0, 0, 0);
+        for (_, metrics) in &self.channels {
+            sent += metrics.sent_count.load(SeqCst);
+            dropped += metrics.dropped_count.load(SeqCst);
+        }
+        for (_, metrics) in &self.rules {
+            suppressed += metrics.duplicate_count.load(SeqCst)
+                + metrics.rate_limited_count.load(SeqCst);
+        }
+        format!("sent: {sent}\nsuppressed: {suppressed}\ndropped: {dropped}")
+    }
+
+    fn okay(&self) -> Option<bool> {
+        Some(
+            !self
+                .channels
+                .iter()
+                .any(|(_, metrics)| metrics.failing.load(SeqCst)),
+        )
+    }
+}
+
+impl AlertMetrics {
+    const ALERT_COUNT_METRIC: Metric = Metric::new(
+        "alert_target_alert_count",
+        "the number of alerts raised per rule",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DUPLICATE_COUNT_METRIC: Metric = Metric::new(
+        "alert_target_duplicate_count",
+        "the number of alerts per rule suppressed as duplicates",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const RATE_LIMITED_COUNT_METRIC: Metric = Metric::new(
+        "alert_target_rate_limited_count",
+        "the number of alerts per rule suppressed by the rate limit",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const SENT_COUNT_METRIC: Metric = Metric::new(
+        "alert_target_sent_count",
+        "the number of alerts sent per channel",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const REQUEST_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "alert_target_request_error_count",
+        "the number of failed requests per channel, including retries",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DROPPED_COUNT_METRIC: Metric = Metric::new(
+        "alert_target_dropped_count",
+        "the number of alerts per channel that could not be sent",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for AlertMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        for (rule, metrics) in &self.rules {
+            for (metric, value) in [
+                (Self::ALERT_COUNT_METRIC, &metrics.alert_count),
+                (Self::DUPLICATE_COUNT_METRIC, &metrics.duplicate_count),
+                (
+                    Self::RATE_LIMITED_COUNT_METRIC,
+                    &metrics.rate_limited_count,
+                ),
+            ] {
+                append_labelled_metric(
+                    unit_name,
+                    target,
+                    "rule",
+                    rule,
+                    metric,
+                    value.load(SeqCst),
+                );
+            }
+        }
+        for (channel, metrics) in &self.channels {
+            for (metric, value) in [
+                (Self::SENT_COUNT_METRIC, &metrics.sent_count),
+                (
+                    Self::REQUEST_ERROR_COUNT_METRIC,
+                    &metrics.request_error_count,
+                ),
+                (Self::DROPPED_COUNT_METRIC, &metrics.dropped_count),
+            ] {
+                append_labelled_metric(
+                    unit_name,
+                    target,
+                    "channel",
+                    channel,
+                    metric,
+                    value.load(SeqCst),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,4 @@
+mod channel;
+mod metrics;
+mod rule;
+pub mod target;
@@ -0,0 +1,315 @@
+//! Deciding which rows become alerts.
+//!
+//! A rule selects rows by kind and topic and renders them into the text of
+//! an alert, with the columns as placeholders as described in the
+//! [`template`] module. Two limits keep a flood of events from flooding the
+//! chat as well: an alert with the same deduplication key as one sent less
+//! than `dedup_window_secs` ago is suppressed, and so is any alert beyond
+//! `rate_limit` alerts within `rate_limit_secs`.
+//!
+//! [`template`]: crate::targets::http::template
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use crate::targets::{
+    file::row::{Row, COLUMNS},
+    http::template::Template,
+};
+
+/// The kinds of rows rules can select.
+const KINDS: [&str; 6] = [
+    "route",
+    "announce",
+    "withdraw",
+    "peer_down",
+    "log",
+    "custom",
+];
+
+/// How many deduplication keys to keep before forgetting expired ones.
+const MAX_RECENT: usize = 1024;
+
+//------------ RuleConfig ----------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct RuleConfig {
+    /// The name of the rule in logs and metrics.
+    pub name: String,
+
+    /// The kinds of rows to alert on, all if not given.
+    #[serde(default)]
+    pub kinds: Option<Vec<String>>,
+
+    /// The topics of rows to alert on, all if not given.
+    #[serde(default)]
+    pub topics: Option<Vec<String>>,
+
+    /// The names of the channels to send alerts to.
+    pub channels: Vec<String>,
+
+    /// The template of the text of an alert.
+    pub text: String,
+
+    /// The template of the key identifying duplicate alerts, the text by
+    /// default.
+    #[serde(default)]
+    pub dedup_key: Option<String>,
+
+    /// How long alerts with the same key are suppressed.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "RuleConfig::default_dedup_window_secs")]
+    pub dedup_window_secs: Duration,
+
+    /// How many alerts may be sent within `rate_limit_secs`.
+    #[serde(default = "RuleConfig::default_rate_limit")]
+    pub rate_limit: usize,
+
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "RuleConfig::default_rate_limit_secs")]
+    pub rate_limit_secs: Duration,
+}
+
+impl RuleConfig {
+    fn default_dedup_window_secs() -> Duration {
+        Duration::from_secs(300)
+    }
+
+    fn default_rate_limit() -> usize {
+        10
+    }
+
+    fn default_rate_limit_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+}
+
+//------------ Rule ----------------------------------------------------------
+
+/// What became of a row a rule selected.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// The alert with the given text is to be sent.
+    Send(String),
+
+    /// An alert with the same key was sent recently.
+    Duplicate,
+
+    /// Too many alerts were sent recently.
+    RateLimited,
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub name: String,
+
+    /// The indexes of the channels to send alerts to.
+    pub channels: Vec<usize>,
+
+    kinds: Option<Vec<String>>,
+    topics: Option<Vec<String>>,
+    text: Template,
+    dedup_key: Option<Template>,
+    dedup_window: Duration,
+    rate_limit: usize,
+    rate_period: Duration,
+
+    /// When an alert was last sent, by deduplication key.
+    recent: HashMap<String, Instant>,
+
+    /// When the alerts within the rate limit period were sent.
+    sent: VecDeque<Instant>,
+}
+
+impl Rule {
+    /// Creates a rule, looking up its channels in `channels`.
+    pub fn new(
+        config: &RuleConfig,
+        channels: &[&String],
+    ) -> Result<Self, String> {
+        if let Some(kind) = config
+            .kinds
+            .iter()
+            .flatten()
+            .find(|kind| !KINDS.contains(&kind.as_str()))
+        {
+            return Err(format!("unknown kind '{kind}'"));
+        }
+        if config.channels.is_empty() {
+            return Err("no channels configured".into());
+        }
+        if config.rate_limit == 0 {
+            return Err("rate_limit must be at least 1".into());
+        }
+        let channel_indexes = config
+            .channels
+            .iter()
+            .map(|name| {
+                channels
+                    .iter()
+                    .position(|channel| *channel == name)
+                    .ok_or_else(|| format!("unknown channel '{name}'"))
+            })
+            .collect::<Result<_, _>>()?;
+        let columns = COLUMNS.map(|column| column.name);
+        let template = |text: &String| {
+            Template::new(serde_json::Value::String(text.clone()), &columns)
+        };
+        Ok(Self {
+            name: config.name.clone(),
+            channels: channel_indexes,
+            kinds: config.kinds.clone(),
+            topics: config.topics.clone(),
+            text: template(&config.text)?,
+            dedup_key: config.dedup_key.as_ref().map(template).transpose()?,
+            dedup_window: config.dedup_window_secs,
+            rate_limit: config.rate_limit,
+            rate_period: config.rate_limit_secs,
+            recent: HashMap::new(),
+            sent: VecDeque::new(),
+        })
+    }
+
+    pub fn wants(&self, row: &Row) -> bool {
+        self.kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.iter().any(|kind| kind == row.kind))
+            && self
+                .topics
+                .as_ref()
+                .is_none_or(|topics| topics.contains(&row.topic))
+    }
+
+    /// Decides whether to send an alert for a row the rule wants.
+    pub fn apply(&mut self, row: &Row, now: Instant) -> Outcome {
+        let lookup = |name: &str| row.json_value(name);
+        let text = self.text.render_text(&lookup);
+        let key = match &self.dedup_key {
+            Some(template) => template.render_text(&lookup),
+            None => text.clone(),
+        };
+        let window = self.dedup_window;
+        if self
+            .recent
+            .get(&key)
+            .is_some_and(|sent| now.duration_since(*sent) < window)
+        {
+            return Outcome::Duplicate;
+        }
+
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= self.rate_period)
+        {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.rate_limit {
+            return Outcome::RateLimited;
+        }
+        self.sent.push_back(now);
+
+        if self.recent.len() >= MAX_RECENT {
+            self.recent
+                .retain(|_, sent| now.duration_since(*sent) < window);
+        }
+        self.recent.insert(key, now);
+        Outcome::Send(text)
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mk_rule(extra: &str) -> Rule {
+        let config: RuleConfig = toml::from_str(&format!(
+            "name = \"hijacks\"\nchannels = [\"noc\"]\n\
+            text = \"Hijack of {{{{prefix}}}} by AS{{{{origin_as}}}}\"\n\
+            {extra}"
+        ))
+        .unwrap();
+        Rule::new(&config, &[&"chat".to_string(), &"noc".to_string()])
+            .unwrap()
+    }
+
+    fn mk_row(prefix: &str, origin_as: u32) -> Row {
+        Row {
+            topic: "hijack".into(),
+            kind: "log",
+            prefix: Some(prefix.into()),
+            origin_as: Some(origin_as),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn duplicates_are_suppressed() {
+        let mut rule = mk_rule("dedup_key = \"{{prefix}}\"");
+        assert_eq!(rule.channels, [1]);
+        let now = Instant::now();
+        assert_eq!(
+            rule.apply(&mk_row("192.0.2.0/24", 65001), now),
+            Outcome::Send("Hijack of 192.0.2.0/24 by AS65001".into())
+        );
+        assert_eq!(
+            rule.apply(&mk_row("192.0.2.0/24", 65002), now),
+            Outcome::Duplicate
+        );
+        assert!(matches!(
+            rule.apply(&mk_row("198.51.100.0/24", 65001), now),
+            Outcome::Send(_)
+        ));
+        assert!(matches!(
+            rule.apply(
+                &mk_row("192.0.2.0/24", 65002),
+                now + Duration::from_secs(300)
+            ),
+            Outcome::Send(_)
+        ));
+    }
+
+    #[test]
+    fn rate_is_limited() {
+        let mut rule = mk_rule("rate_limit = 2\nrate_limit_secs = 10");
+        let now = Instant::now();
+        for (origin_as, outcome) in
+            [(1, true), (2, true), (3, false), (4, false)]
+        {
+            assert_eq!(
+                matches!(
+                    rule.apply(&mk_row("192.0.2.0/24", origin_as), now),
+                    Outcome::Send(_)
+                ),
+                outcome
+            );
+        }
+        assert!(matches!(
+            rule.apply(
+                &mk_row("192.0.2.0/24", 3),
+                now + Duration::from_secs(10)
+            ),
+            Outcome::Send(_)
+        ));
+    }
+
+    #[test]
+    fn unknown_channels_are_refused() {
+        let config: RuleConfig = toml::from_str(
+            "name = \"r\"\nchannels = [\"pager\"]\ntext = \"x\"",
+        )
+        .unwrap();
+        assert_eq!(
+            Rule::new(&config, &[&"noc".to_string()]).unwrap_err(),
+            "unknown channel 'pager'"
+        );
+    }
+}
@@ -0,0 +1,448 @@
+//! Sending alerts to chat services.
+//!
+//! The `alert-out` target turns each route and event it receives into a row
+//! with the columns described in the [`row`] module and checks it against
+//! its `rules`. A rule that selects the row renders it into the text of an
+//! alert, unless a recent alert was a duplicate or the rule exceeded its
+//! rate limit, as described in the [`rule`] module. The alert is then sent
+//! to each of the `channels` of the rule, the Slack webhooks, Matrix rooms
+//! or Telegram chats described in the [`channel`] module.
+//!
+//! This way, roto filters can alert people about hijacks or flapping
+//! sessions by emitting log or custom messages with a topic a rule selects.
+//!
+//! Each channel has a queue of up to `queue_size` alerts waiting to be
+//! sent; alerts beyond that are dropped. A failed request is tried again up
+//! to `max_retries` times with an exponential backoff, unless the service
+//! refused it with a status code other than 408 or 429. The CA
+//! certificates of the system are used to check the certificates of the
+//! services unless others are given in the `tls` table.
+//!
+//! [`row`]: crate::targets::file::row
+//! [`rule`]: super::rule
+//! [`channel`]: super::channel
+
+use std::{
+    collections::BTreeMap,
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, warn};
+use reqwest::{Client as HttpClient, StatusCode};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::sync::mpsc;
+
+use crate::{
+    common::tls::TlsClientConfig,
+    comms::{Link, Terminated, UnitStatus},
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    targets::file::row::Row,
+};
+
+use super::{
+    channel::ChannelConfig,
+    metrics::{AlertMetrics, ChannelMetrics, RuleMetrics},
+    rule::{Outcome, Rule, RuleConfig},
+};
+
+/// The longest a single request may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct Alert {
+    sources: Link,
+
+    /// The channels alerts can be sent to, by name.
+    channels: BTreeMap<String, ChannelConfig>,
+
+    rules: Vec<RuleConfig>,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The certificates to check those of the services against.
+    #[serde(default)]
+    pub tls: Option<TlsClientConfig>,
+
+    /// How many alerts per channel may wait to be sent.
+    #[serde(default = "Config::default_queue_size")]
+    pub queue_size: usize,
+
+    /// How often to try a failed request again.
+    #[serde(default = "Config::default_max_retries")]
+    pub max_retries: usize,
+
+    /// How long to wait before the first retry. The delay doubles with
+    /// every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_retry_delay_secs")]
+    pub retry_delay_secs: Duration,
+
+    /// The longest to wait before retrying.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_max_retry_delay_secs")]
+    pub max_retry_delay_secs: Duration,
+}
+
+impl Config {
+    fn default_queue_size() -> usize {
+        100
+    }
+
+    fn default_max_retries() -> usize {
+        3
+    }
+
+    fn default_retry_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retry_delay_secs() -> Duration {
+        Duration::from_secs(30)
+    }
+}
+
+impl Alert {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let client = match self.client() {
+            Ok(client) => client,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+
+        let mut metrics = AlertMetrics::default();
+        let names = self.channels.keys().collect::<Vec<_>>();
+        let mut rules = Vec::new();
+        for config in &self.rules {
+            let rule = match Rule::new(config, &names) {
+                Ok(rule) => rule,
+                Err(err) => {
+                    error!(
+                        "Target {}: rule {}: {err}",
+                        component.name(),
+                        config.name
+                    );
+                    return Err(Terminated);
+                }
+            };
+            let rule_metrics = Arc::new(RuleMetrics::default());
+            metrics
+                .rules
+                .push((config.name.clone(), rule_metrics.clone()));
+            rules.push((rule, rule_metrics));
+        }
+
+        let mut senders = Vec::new();
+        for (name, channel) in &self.channels {
+            let channel_metrics = Arc::new(ChannelMetrics::default());
+            metrics
+                .channels
+                .push((name.clone(), channel_metrics.clone()));
+            senders.push(Sender {
+                name: name.clone(),
+                channel: channel.clone(),
+                client: client.clone(),
+                max_retries: self.config.max_retries,
+                retry_delay: self.config.retry_delay_secs,
+                max_retry_delay: self.config.max_retry_delay_secs,
+                metrics: channel_metrics,
+            });
+        }
+        let metrics = Arc::new(metrics);
+        component.register_metrics(metrics.clone());
+
+        AlertRunner {
+            rules,
+            channels: Vec::new(),
+            ingresses: component.ingresses().clone(),
+            metrics,
+            queue_size: self.config.queue_size.max(1),
+        }
+        .run(senders, self.sources, cmd, waitpoint)
+        .await
+    }
+
+    /// Returns the client for the channels, checking their configuration.
+    fn client(&self) -> Result<HttpClient, String> {
+        if self.rules.is_empty() {
+            return Err("no rules configured".into());
+        }
+        for (name, channel) in &self.channels {
+            channel
+                .check()
+                .map_err(|err| format!("channel {name}: {err}"))?;
+        }
+        self.config
+            .tls
+            .clone()
+            .unwrap_or_default()
+            .http_client_builder()?
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|err| err.to_string())
+    }
+}
+
+//------------ AlertRunner ---------------------------------------------------
+
+/// The queue of alerts to be sent to a channel.
+struct ChannelQueue {
+    name: String,
+    tx: mpsc::Sender<String>,
+    metrics: Arc<ChannelMetrics>,
+}
+
+struct AlertRunner {
+    rules: Vec<(Rule, Arc<RuleMetrics>)>,
+    channels: Vec<ChannelQueue>,
+    ingresses: Arc<ingress::Register>,
+    metrics: Arc<AlertMetrics>,
+    queue_size: usize,
+}
+
+impl AlertRunner {
+    async fn run(
+        mut self,
+        senders: Vec<Sender>,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        // Each channel has a task of its own, so that an unavailable
+        // service holds up neither the sources nor the other channels.
+        let mut tasks = Vec::new();
+        for sender in senders {
+            let (tx, rx) = mpsc::channel(self.queue_size);
+            self.channels.push(ChannelQueue {
+                name: sender.name.clone(),
+                tx,
+                metrics: sender.metrics.clone(),
+            });
+            tasks.push(tokio::spawn(sender.run(rx)));
+        }
+
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the alert-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        let now = Instant::now();
+                        for row in Row::for_update(update, &self.ingresses) {
+                            self.process(&row, now);
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of alert-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+            }
+        }
+
+        // Send what is queued before stopping.
+        self.channels.clear();
+        for task in tasks {
+            let _ = task.await;
+        }
+        Err(Terminated)
+    }
+
+    /// Applies the rules to a row, queueing the resulting alerts.
+    fn process(&mut self, row: &Row, now: Instant) {
+        for (rule, metrics) in &mut self.rules {
+            if !rule.wants(row) {
+                continue;
+            }
+            let text = match rule.apply(row, now) {
+                Outcome::Send(text) => text,
+                Outcome::Duplicate => {
+                    metrics.duplicate_count.fetch_add(1, SeqCst);
+                    continue;
+                }
+                Outcome::RateLimited => {
+                    debug!("Rule {}: rate limit exceeded", rule.name);
+                    metrics.rate_limited_count.fetch_add(1, SeqCst);
+                    continue;
+                }
+            };
+            metrics.alert_count.fetch_add(1, SeqCst);
+            for &index in &rule.channels {
+                let channel = &self.channels[index];
+                if channel.tx.try_send(text.clone()).is_err() {
+                    warn!(
+                        "Dropping alert for channel {}: too many alerts are \
+                        waiting to be sent",
+                        channel.name
+                    );
+                    channel.metrics.dropped_count.fetch_add(1, SeqCst);
+                }
+            }
+        }
+    }
+}
+
+//------------ Sender --------------------------------------------------------
+
+/// Sends alerts to a channel.
+struct Sender {
+    name: String,
+    channel: ChannelConfig,
+    client: HttpClient,
+    max_retries: usize,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    metrics: Arc<ChannelMetrics>,
+}
+
+impl Sender {
+    /// Sends alerts until there are no more.
+    async fn run(self, mut rx: mpsc::Receiver<String>) {
+        while let Some(text) = rx.recv().await {
+            let sent = self.send_with_retries(&text).await;
+            self.metrics.failing.store(!sent, SeqCst);
+            if sent {
+                self.metrics.sent_count.fetch_add(1, SeqCst);
+            } else {
+                self.metrics.dropped_count.fetch_add(1, SeqCst);
+            }
+        }
+    }
+
+    /// Sends an alert, returning whether that succeeded.
+    async fn send_with_retries(&self, text: &str) -> bool {
+        let txn_id = uuid::Uuid::new_v4().simple().to_string();
+        let mut delay = self.retry_delay;
+        for attempt in 0..=self.max_retries {
+            let request = self.channel.request(text, &txn_id);
+            // Requests refused by the service, other than for now, will be
+            // refused again.
+            let (message, retry) = match self.client.execute(request).await {
+                Ok(response) if response.status().is_success() => {
+                    return true
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    (
+                        format!("{status}: {}", body.trim()),
+                        !status.is_client_error()
+                            || status == StatusCode::REQUEST_TIMEOUT
+                            || status == StatusCode::TOO_MANY_REQUESTS,
+                    )
+                }
+                Err(err) => (err.to_string(), true),
+            };
+            self.metrics.request_error_count.fetch_add(1, SeqCst);
+            warn!("Channel {}: request failed: {message}", self.name);
+            if !retry {
+                break;
+            }
+            if attempt < self.max_retries {
+                info!(
+                    "Channel {}: retrying in {}s",
+                    self.name,
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(self.max_retry_delay);
+            }
+        }
+        false
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use serde_json::json;
+
+    use crate::tests::util::http::MockServer;
+
+    use super::*;
+
+    fn mk_sender(addr: SocketAddr) -> Sender {
+        Sender {
+            name: "noc".into(),
+            channel: toml::from_str(&format!(
+                "type = \"slack\"\nwebhook_url = \"http://{addr}/hook\""
+            ))
+            .unwrap(),
+            client: HttpClient::new(),
+            max_retries: 1,
+            retry_delay: Duration::ZERO,
+            max_retry_delay: Duration::ZERO,
+            metrics: Default::default(),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_alerts_are_retried() {
+        let server = MockServer::with_statuses(vec![503], 200).await;
+        let sender = mk_sender(server.addr);
+        let metrics = sender.metrics.clone();
+        let (tx, rx) = mpsc::channel(1);
+        tx.send("Peer down".into()).await.unwrap();
+        drop(tx);
+        sender.run(rx).await;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].uri.path(), "/hook");
+        assert_eq!(requests[1].json(), json!({"text": "Peer down"}));
+        assert_eq!(metrics.sent_count.load(SeqCst), 1);
+        assert_eq!(metrics.request_error_count.load(SeqCst), 1);
+        assert!(!metrics.failing.load(SeqCst));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn refused_alerts_are_dropped() {
+        let server = MockServer::with_statuses(vec![400], 200).await;
+        let sender = mk_sender(server.addr);
+        let metrics = sender.metrics.clone();
+        let (tx, rx) = mpsc::channel(1);
+        tx.send("Peer down".into()).await.unwrap();
+        drop(tx);
+        sender.run(rx).await;
+
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(metrics.dropped_count.load(SeqCst), 1);
+        assert!(metrics.failing.load(SeqCst));
+    }
+}
@@ -0,0 +1,251 @@
+//! The routes announced to the peers of the `bgp-out` target.
+//!
+//! The [`AdjRibOut`] holds the paths the target received for every prefix,
+//! one per ingress, of which the most recently received one is announced.
+//! When that path is withdrawn, the most recent of the remaining paths is
+//! announced in its place, and only when none remain is the prefix
+//! withdrawn.
+//!
+//! Sessions subscribe to the changes of the announced routes once they are
+//! established, receiving all current routes at the same time. Their
+//! queues are bounded: a session that cannot keep up loses its
+//! subscription, and with that its session, so that it starts over with
+//! the current routes rather than missing changes.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use inetnum::addr::Prefix;
+use tokio::sync::mpsc;
+
+use crate::ingress::IngressId;
+
+/// A route by its AFI and SAFI and its prefix.
+pub type Key = ((u16, u8), Prefix);
+
+//------------ Change --------------------------------------------------------
+
+/// A change of the route announced for a prefix.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Change {
+    pub afi_safi: (u16, u8),
+    pub prefix: Prefix,
+
+    /// The path attributes of the announced route, with four-octet AS
+    /// numbers, or `None` if the prefix is withdrawn.
+    pub attributes: Option<Arc<[u8]>>,
+}
+
+//------------ AdjRibOut -----------------------------------------------------
+
+#[derive(Debug, Default)]
+pub struct AdjRibOut {
+    /// The paths by route, from the least to the most recently received.
+    routes: BTreeMap<Key, Vec<Path>>,
+
+    subscribers: HashMap<usize, mpsc::Sender<Change>>,
+    next_subscriber: usize,
+}
+
+#[derive(Debug)]
+struct Path {
+    ingress_id: IngressId,
+    attributes: Arc<[u8]>,
+}
+
+impl AdjRibOut {
+    /// Announces the path of an ingress for a route.
+    pub fn announce(
+        &mut self,
+        key: Key,
+        ingress_id: IngressId,
+        attributes: Arc<[u8]>,
+    ) {
+        let paths = self.routes.entry(key).or_default();
+        paths.retain(|path| path.ingress_id != ingress_id);
+        paths.push(Path {
+            ingress_id,
+            attributes: attributes.clone(),
+        });
+        self.publish(key, Some(attributes));
+    }
+
+    /// Withdraws the path of an ingress for a route.
+    pub fn withdraw(&mut self, key: Key, ingress_id: IngressId) {
+        let Some(paths) = self.routes.get_mut(&key) else {
+            return;
+        };
+        let Some(pos) =
+            paths.iter().position(|path| path.ingress_id == ingress_id)
+        else {
+            return;
+        };
+        paths.remove(pos);
+        if pos < paths.len() {
+            // The announced path is still there.
+            return;
+        }
+        let attributes = paths.last().map(|path| path.attributes.clone());
+        if attributes.is_none() {
+            self.routes.remove(&key);
+        }
+        self.publish(key, attributes);
+    }
+
+    /// Withdraws all paths of an ingress, or those of one address family.
+    pub fn withdraw_ingress(
+        &mut self,
+        ingress_id: IngressId,
+        afi_safi: Option<(u16, u8)>,
+    ) {
+        let keys = self
+            .routes
+            .iter()
+            .filter(|((family, _), paths)| {
+                afi_safi.is_none_or(|afi_safi| afi_safi == *family)
+                    && paths.iter().any(|path| path.ingress_id == ingress_id)
+            })
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for key in keys {
+            self.withdraw(key, ingress_id);
+        }
+    }
+
+    /// Returns the number of routes announced.
+    pub fn route_count(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// Subscribes to the changes of the announced routes.
+    ///
+    /// Returns the ID of the subscription, the current routes, and the
+    /// receiver of the changes after them, which holds up to `queue_size`
+    /// changes. The receiver is closed if it ever falls behind further.
+    pub fn subscribe(
+        &mut self,
+        queue_size: usize,
+    ) -> (usize, Vec<Change>, mpsc::Receiver<Change>) {
+        let (tx, rx) = mpsc::channel(queue_size.max(1));
+        let id = self.next_subscriber;
+        self.next_subscriber += 1;
+        self.subscribers.insert(id, tx);
+        let routes = self
+            .routes
+            .iter()
+            .filter_map(|(&(afi_safi, prefix), paths)| {
+                Some(Change {
+                    afi_safi,
+                    prefix,
+                    attributes: Some(paths.last()?.attributes.clone()),
+                })
+            })
+            .collect();
+        (id, routes, rx)
+    }
+
+    pub fn unsubscribe(&mut self, id: usize) {
+        self.subscribers.remove(&id);
+    }
+
+    fn publish(
+        &mut self,
+        (afi_safi, prefix): Key,
+        attributes: Option<Arc<[u8]>>,
+    ) {
+        let change = Change {
+            afi_safi,
+            prefix,
+            attributes,
+        };
+        self.subscribers
+            .retain(|_, tx| tx.try_send(change.clone()).is_ok());
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn key(prefix: &str) -> Key {
+        ((1, 1), Prefix::from_str(prefix).unwrap())
+    }
+
+    fn attributes(tag: u8) -> Arc<[u8]> {
+        Arc::from(vec![tag])
+    }
+
+    fn announced(rx: &mut mpsc::Receiver<Change>) -> Vec<Option<u8>> {
+        let mut res = Vec::new();
+        while let Ok(change) = rx.try_recv() {
+            res.push(change.attributes.map(|attributes| attributes[0]));
+        }
+        res
+    }
+
+    #[test]
+    fn most_recent_path_is_announced() {
+        let mut rib = AdjRibOut::default();
+        let (_, routes, mut rx) = rib.subscribe(10);
+        assert!(routes.is_empty());
+
+        rib.announce(key("10.0.0.0/8"), 1, attributes(1));
+        rib.announce(key("10.0.0.0/8"), 2, attributes(2));
+        assert_eq!(announced(&mut rx), [Some(1), Some(2)]);
+
+        // Withdrawing a path that is not announced changes nothing.
+        rib.withdraw(key("10.0.0.0/8"), 1);
+        assert!(announced(&mut rx).is_empty());
+
+        rib.announce(key("10.0.0.0/8"), 1, attributes(3));
+        rib.withdraw(key("10.0.0.0/8"), 1);
+        assert_eq!(announced(&mut rx), [Some(3), Some(2)]);
+
+        rib.withdraw(key("10.0.0.0/8"), 2);
+        assert_eq!(announced(&mut rx), [None]);
+        assert_eq!(rib.route_count(), 0);
+    }
+
+    #[test]
+    fn ingress_is_withdrawn() {
+        let mut rib = AdjRibOut::default();
+        rib.announce(key("10.0.0.0/8"), 1, attributes(1));
+        rib.announce(key("11.0.0.0/8"), 1, attributes(1));
+        rib.announce(key("11.0.0.0/8"), 2, attributes(2));
+        rib.announce(
+            ((2, 1), Prefix::from_str("2001:db8::/32").unwrap()),
+            1,
+            attributes(1),
+        );
+
+        let (_, routes, mut rx) = rib.subscribe(10);
+        assert_eq!(routes.len(), 3);
+
+        rib.withdraw_ingress(1, Some((1, 1)));
+        assert_eq!(announced(&mut rx), [None]);
+        assert_eq!(rib.route_count(), 2);
+
+        rib.withdraw_ingress(1, None);
+        assert_eq!(announced(&mut rx), [None]);
+        assert_eq!(rib.route_count(), 1);
+    }
+
+    #[test]
+    fn slow_subscriber_is_dropped() {
+        let mut rib = AdjRibOut::default();
+        let (_, _, mut rx) = rib.subscribe(1);
+        rib.announce(key("10.0.0.0/8"), 1, attributes(1));
+        rib.announce(key("11.0.0.0/8"), 1, attributes(1));
+        assert!(rx.try_recv().is_ok());
+        assert_eq!(
+            rx.try_recv(),
+            Err(mpsc::error::TryRecvError::Disconnected)
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct BgpMetrics {
+    pub peer_count: AtomicUsize,
+    pub established_sessions: AtomicUsize,
+    pub route_count: AtomicUsize,
+    pub rejected_route_count: AtomicUsize,
+    pub update_count: AtomicUsize,
+    pub connect_error_count: AtomicUsize,
+    pub session_reset_count: AtomicUsize,
+}
+
+impl GraphStatus for BgpMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "sessions: {}/{}\nroutes: {}\nupdates: {}",
+            self.established_sessions.load(SeqCst),
+            self.peer_count.load(SeqCst),
+            self.route_count.load(SeqCst),
+            self.update_count.load(SeqCst),
+        )
+    }
+
+    fn okay(&self) -> Option<bool> {
+        Some(
+            self.established_sessions.load(SeqCst)
+                == self.peer_count.load(SeqCst),
+        )
+    }
+}
+
+impl BgpMetrics {
+    const ESTABLISHED_SESSIONS_METRIC: Metric = Metric::new(
+        "bgp_target_established_sessions",
+        "the number of established sessions",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const ROUTE_COUNT_METRIC: Metric = Metric::new(
+        "bgp_target_route_count",
+        "the number of routes announced to peers",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const REJECTED_ROUTE_COUNT_METRIC: Metric = Metric::new(
+        "bgp_target_rejected_route_count",
+        "the number of routes rejected by the roto filter",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const UPDATE_COUNT_METRIC: Metric = Metric::new(
+        "bgp_target_update_count",
+        "the number of UPDATE messages sent to peers",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const CONNECT_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "bgp_target_connect_error_count",
+        "the number of failed attempts to connect to peers",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const SESSION_RESET_COUNT_METRIC: Metric = Metric::new(
+        "bgp_target_session_reset_count",
+        "the number of established sessions that went down",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for BgpMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::ESTABLISHED_SESSIONS_METRIC,
+            Some(unit_name),
+            self.established_sessions.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::ROUTE_COUNT_METRIC,
+            Some(unit_name),
+            self.route_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::REJECTED_ROUTE_COUNT_METRIC,
+            Some(unit_name),
+            self.rejected_route_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::UPDATE_COUNT_METRIC,
+            Some(unit_name),
+            self.update_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::CONNECT_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.connect_error_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::SESSION_RESET_COUNT_METRIC,
+            Some(unit_name),
+            self.session_reset_count.load(SeqCst),
+        );
+    }
+}
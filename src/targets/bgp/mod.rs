@@ -0,0 +1,5 @@
+mod adj_rib_out;
+mod metrics;
+mod peer;
+mod session;
+pub mod target;
@@ -0,0 +1,514 @@
+//! The peers of the `bgp-out` target and the routes announced to them.
+//!
+//! Routes are announced with the path attributes they were received with,
+//! as a route server would, except for the rewrites configured for the
+//! peer:
+//!
+//! * `prepend` puts the local AS number in front of the AS_PATH that many
+//!   times. It is not added otherwise, so for eBGP peers that expect the
+//!   first AS in the path to be that of their neighbour it should be at
+//!   least 1.
+//! * `next_hop` replaces the next hop of the routes of its address family,
+//!   in the NEXT_HOP attribute for IPv4 unicast and in the MP_REACH_NLRI
+//!   attribute otherwise. Routes that were not received with a next hop,
+//!   such as those from MRT files, need it to be accepted by most peers.
+//! * `communities` are added to the COMMUNITIES attribute, e.g. the
+//!   well-known BLACKHOLE community for remote triggered black holing.
+//!
+//! The LOCAL_PREF attribute is removed for eBGP peers and added, with the
+//! default value of 100, for iBGP peers if the route did not have it.
+//!
+//! The sessions always use four-octet AS numbers, so peers need to support
+//! them.
+
+use std::net::{IpAddr, SocketAddr};
+
+use inetnum::asn::Asn;
+use routecore::bgp::{
+    communities::StandardCommunity, fsm::session::BgpConfig,
+    types::AfiSafiType,
+};
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::{
+    common::tcp_auth::TcpAuth,
+    targets::mrt::bgp4mp::{
+        attribute_value, update_body, Attributes, AS_PATH, MP_REACH_NLRI,
+        NEXT_HOP,
+    },
+};
+
+use super::adj_rib_out::Change;
+
+const LOCAL_PREF: u8 = 5;
+const COMMUNITIES: u8 = 8;
+
+/// The flags of well-known attributes.
+const TRANSITIVE: u8 = 0x40;
+
+/// The flags of optional transitive attributes.
+const OPTIONAL_TRANSITIVE: u8 = 0xc0;
+
+/// The flag of attributes with a two-octet length.
+const EXTENDED_LENGTH: u8 = 0x10;
+
+/// The AS_PATH segment type of an ordered set of AS numbers.
+const AS_SEQUENCE: u8 = 2;
+
+/// The largest BGP message without the extended message capability.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+//------------ PeerConfig ----------------------------------------------------
+
+/// Configuration for a peer to announce routes to.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct PeerConfig {
+    name: String,
+    remote_asn: Asn,
+
+    /// The ASN to use towards this peer instead of the target's `my_asn`.
+    #[serde(default)]
+    local_asn: Option<Asn>,
+
+    #[serde(default = "PeerConfig::default_port")]
+    port: u16,
+
+    hold_time: Option<u16>,
+
+    /// The TCP MD5 password or TCP-AO key of the connection.
+    #[serde(flatten)]
+    tcp_auth: TcpAuth,
+
+    /// The address families whose routes are announced.
+    #[serde(default = "PeerConfig::default_protocols")]
+    protocols: Vec<AfiSafiType>,
+
+    /// The next hop to announce routes with.
+    #[serde(default)]
+    next_hop: Option<IpAddr>,
+
+    /// The communities to add to the routes.
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[serde(default)]
+    communities: Vec<StandardCommunity>,
+
+    /// How many times to prepend the local ASN to the AS path.
+    #[serde(default)]
+    prepend: u8,
+}
+
+impl PeerConfig {
+    fn default_port() -> u16 {
+        179
+    }
+
+    fn default_protocols() -> Vec<AfiSafiType> {
+        vec![AfiSafiType::Ipv4Unicast, AfiSafiType::Ipv6Unicast]
+    }
+
+    /// Checks that the configuration can be used.
+    pub fn check(&self) -> Result<(), String> {
+        self.tcp_auth
+            .check()
+            .map_err(|err| format!("peer '{}': {}", self.name, err))
+    }
+}
+
+//------------ Peer ----------------------------------------------------------
+
+/// A peer with the parameters of the session with it.
+#[derive(Clone, Debug)]
+pub struct Peer {
+    addr: IpAddr,
+    local_asn: Asn,
+    bgp_id: [u8; 4],
+    config: PeerConfig,
+}
+
+impl Peer {
+    pub fn new(
+        addr: IpAddr,
+        my_asn: Asn,
+        bgp_id: [u8; 4],
+        config: PeerConfig,
+    ) -> Self {
+        Self {
+            addr,
+            local_asn: config.local_asn.unwrap_or(my_asn),
+            bgp_id,
+            config,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.addr, self.config.port)
+    }
+
+    pub fn tcp_auth(&self) -> &TcpAuth {
+        &self.config.tcp_auth
+    }
+
+    /// Returns whether routes of an address family are announced.
+    pub fn announces(&self, afi_safi: (u16, u8)) -> bool {
+        self.config.protocols.contains(&afi_safi.into())
+    }
+
+    /// Returns the address families whose routes are announced.
+    pub fn families(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.config
+            .protocols
+            .iter()
+            .map(|&afi_safi| afi_safi.into())
+    }
+
+    fn is_ibgp(&self) -> bool {
+        self.local_asn == self.config.remote_asn
+    }
+
+    /// Returns the UPDATE message for a change, with its header.
+    ///
+    /// Returns `None` if the message would be too long.
+    pub fn update(&self, change: &Change) -> Option<Vec<u8>> {
+        let body = match &change.attributes {
+            Some(attributes) => update_body(
+                change.prefix,
+                change.afi_safi,
+                &self.rewrite(attributes, change.afi_safi),
+                false,
+            ),
+            None => update_body(change.prefix, change.afi_safi, &[], true),
+        };
+        message(&body)
+    }
+
+    /// Returns the End-of-RIB marker of an address family, with its header.
+    pub fn end_of_rib((afi, safi): (u16, u8)) -> Vec<u8> {
+        let body = if (afi, safi) == (1, 1) {
+            vec![0, 0, 0, 0]
+        } else {
+            let mut body = vec![0, 0, 0, 6, 0x80, 15, 3];
+            body.extend_from_slice(&afi.to_be_bytes());
+            body.push(safi);
+            body
+        };
+        message(&body).unwrap_or_default()
+    }
+
+    /// Returns the path attributes of a route as announced to the peer.
+    fn rewrite(&self, raw: &[u8], (afi, safi): (u16, u8)) -> Vec<u8> {
+        let conventional = afi == 1 && safi == 1;
+        let next_hop = self.config.next_hop.and_then(|addr| match addr {
+            IpAddr::V4(addr) if afi == 1 => Some(addr.octets().to_vec()),
+            IpAddr::V6(addr) if afi == 2 => Some(addr.octets().to_vec()),
+            _ => None,
+        });
+        let mut seen = Vec::new();
+        let mut res = Vec::with_capacity(raw.len() + 32);
+        for (type_code, attr) in Attributes(raw) {
+            seen.push(type_code);
+            let value = attribute_value(attr);
+            match type_code {
+                AS_PATH if self.config.prepend > 0 => push_attribute(
+                    attr[0],
+                    AS_PATH,
+                    &self.prepend(value),
+                    &mut res,
+                ),
+                NEXT_HOP if conventional && next_hop.is_some() => {}
+                MP_REACH_NLRI if !conventional && next_hop.is_some() => {}
+                LOCAL_PREF if !self.is_ibgp() => {}
+                COMMUNITIES if !self.config.communities.is_empty() => {
+                    let mut value = value.to_vec();
+                    self.push_communities(&mut value);
+                    push_attribute(attr[0], COMMUNITIES, &value, &mut res);
+                }
+                _ => res.extend_from_slice(attr),
+            }
+        }
+
+        if !seen.contains(&AS_PATH) && self.config.prepend > 0 {
+            push_attribute(TRANSITIVE, AS_PATH, &self.prepend(&[]), &mut res);
+        }
+        if let Some(next_hop) = next_hop {
+            if conventional {
+                push_attribute(TRANSITIVE, NEXT_HOP, &next_hop, &mut res);
+            } else {
+                // The route itself is added when the message is built,
+                // which only takes the next hop from this attribute.
+                let mut value = afi.to_be_bytes().to_vec();
+                value.push(safi);
+                value.push(next_hop.len() as u8);
+                value.extend_from_slice(&next_hop);
+                value.push(0);
+                push_attribute(0x80, MP_REACH_NLRI, &value, &mut res);
+            }
+        }
+        if !seen.contains(&LOCAL_PREF) && self.is_ibgp() {
+            push_attribute(
+                TRANSITIVE,
+                LOCAL_PREF,
+                &100u32.to_be_bytes(),
+                &mut res,
+            );
+        }
+        if !seen.contains(&COMMUNITIES) && !self.config.communities.is_empty()
+        {
+            let mut value = Vec::new();
+            self.push_communities(&mut value);
+            push_attribute(
+                OPTIONAL_TRANSITIVE,
+                COMMUNITIES,
+                &value,
+                &mut res,
+            );
+        }
+        res
+    }
+
+    /// Returns an AS_PATH value with the local ASN prepended.
+    fn prepend(&self, value: &[u8]) -> Vec<u8> {
+        let count = usize::from(self.config.prepend);
+        let asn = self.local_asn.into_u32().to_be_bytes();
+        let mut res = Vec::with_capacity(value.len() + 2 + count * 4);
+        match value {
+            [AS_SEQUENCE, len, rest @ ..]
+                if usize::from(*len) + count <= usize::from(u8::MAX) =>
+            {
+                res.extend_from_slice(&[AS_SEQUENCE, len + count as u8]);
+                (0..count).for_each(|_| res.extend_from_slice(&asn));
+                res.extend_from_slice(rest);
+            }
+            _ => {
+                res.extend_from_slice(&[AS_SEQUENCE, count as u8]);
+                (0..count).for_each(|_| res.extend_from_slice(&asn));
+                res.extend_from_slice(value);
+            }
+        }
+        res
+    }
+
+    fn push_communities(&self, value: &mut Vec<u8>) {
+        for community in &self.config.communities {
+            let raw = community.to_raw();
+            if !value.chunks_exact(4).any(|present| present == raw) {
+                value.extend_from_slice(&raw);
+            }
+        }
+    }
+}
+
+impl BgpConfig for Peer {
+    fn local_asn(&self) -> Asn {
+        self.local_asn
+    }
+
+    fn bgp_id(&self) -> [u8; 4] {
+        self.bgp_id
+    }
+
+    fn remote_addr_allowed(&self, remote_addr: IpAddr) -> bool {
+        remote_addr == self.addr
+    }
+
+    fn remote_asn_allowed(&self, remote_asn: Asn) -> bool {
+        remote_asn == self.config.remote_asn
+    }
+
+    fn hold_time(&self) -> Option<u16> {
+        self.config.hold_time
+    }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+
+    fn protocols(&self) -> Vec<AfiSafiType> {
+        self.config.protocols.clone()
+    }
+
+    fn addpath(&self) -> Vec<AfiSafiType> {
+        vec![]
+    }
+}
+
+/// Appends an attribute, with an extended length if needed.
+fn push_attribute(flags: u8, type_code: u8, value: &[u8], buf: &mut Vec<u8>) {
+    if value.len() > usize::from(u8::MAX) {
+        buf.extend_from_slice(&[flags | EXTENDED_LENGTH, type_code]);
+        buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    } else {
+        buf.extend_from_slice(&[flags & !EXTENDED_LENGTH, type_code]);
+        buf.push(value.len() as u8);
+    }
+    buf.extend_from_slice(value);
+}
+
+/// Returns an UPDATE message with the given content after its header.
+fn message(body: &[u8]) -> Option<Vec<u8>> {
+    let len = body.len() + 19;
+    if len > MAX_MESSAGE_LEN {
+        return None;
+    }
+    let mut res = Vec::with_capacity(len);
+    res.extend_from_slice(&[0xff; 16]);
+    res.extend_from_slice(&(len as u16).to_be_bytes());
+    res.push(2); // UPDATE
+    res.extend_from_slice(body);
+    Some(res)
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::Arc};
+
+    use bytes::Bytes;
+    use inetnum::addr::Prefix;
+    use routecore::bgp::{
+        communities::Wellknown,
+        message::{update::SessionConfig, UpdateMessage},
+        path_attributes::PathAttributeType,
+        types::NextHop,
+    };
+
+    use super::*;
+
+    fn peer(toml: &str, remote_asn: u32) -> Peer {
+        let toml =
+            format!("name = \"peer\"\nremote_asn = {remote_asn}\n{toml}");
+        let config: PeerConfig = toml::from_str(&toml).unwrap();
+        Peer::new(
+            "192.0.2.1".parse().unwrap(),
+            Asn::from_u32(65000),
+            [1, 2, 3, 4],
+            config,
+        )
+    }
+
+    /// Returns the path attributes of a route from AS 65001 via 10.0.0.1.
+    fn received() -> Arc<[u8]> {
+        let mut raw = vec![0x40, 1, 1, 0]; // ORIGIN IGP
+        raw.extend_from_slice(&[
+            0x40,
+            2,
+            6,
+            AS_SEQUENCE,
+            1,
+            0,
+            0,
+            0xfd,
+            0xe9,
+        ]);
+        raw.extend_from_slice(&[0x40, 3, 4, 10, 0, 0, 1]);
+        raw.extend_from_slice(&[0x40, 5, 4, 0, 0, 0, 200]); // LOCAL_PREF
+        raw.into()
+    }
+
+    fn parse(pdu: Vec<u8>) -> UpdateMessage<Bytes> {
+        UpdateMessage::from_octets(Bytes::from(pdu), &SessionConfig::modern())
+            .unwrap()
+    }
+
+    fn change(prefix: &str, attributes: Option<Arc<[u8]>>) -> Change {
+        let prefix = Prefix::from_str(prefix).unwrap();
+        let afi_safi = if prefix.is_v4() { (1, 1) } else { (2, 1) };
+        Change {
+            afi_safi,
+            prefix,
+            attributes,
+        }
+    }
+
+    #[test]
+    fn route_is_announced_unchanged() {
+        let peer = peer("", 65002);
+        let update = parse(
+            peer.update(&change("10.1.0.0/16", Some(received())))
+                .unwrap(),
+        );
+        let path = update.aspath().unwrap().unwrap();
+        assert_eq!(path.to_string(), "AS_SEQUENCE(AS65001)");
+        assert_eq!(
+            update.find_next_hop(AfiSafiType::Ipv4Unicast).unwrap(),
+            NextHop::Unicast("10.0.0.1".parse().unwrap())
+        );
+        assert!(update.local_pref().unwrap().is_none());
+        assert_eq!(update.announcements().unwrap().count(), 1);
+    }
+
+    #[test]
+    fn attributes_are_rewritten() {
+        let peer = peer(
+            "next_hop = \"192.0.2.66\"\nprepend = 2\n\
+            communities = [\"BLACKHOLE\", \"65000:666\"]",
+            65002,
+        );
+        let update = parse(
+            peer.update(&change("10.1.0.0/16", Some(received())))
+                .unwrap(),
+        );
+        let path = update.aspath().unwrap().unwrap();
+        assert_eq!(
+            path.to_string(),
+            "AS_SEQUENCE(AS65000, AS65000, AS65001)"
+        );
+        assert_eq!(
+            update.find_next_hop(AfiSafiType::Ipv4Unicast).unwrap(),
+            NextHop::Unicast("192.0.2.66".parse().unwrap())
+        );
+        let communities = update
+            .communities()
+            .unwrap()
+            .unwrap()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        let blackhole = StandardCommunity::from(Wellknown::Blackhole);
+        assert_eq!(
+            communities,
+            [blackhole.to_string(), "AS65000:666".to_string()]
+        );
+    }
+
+    #[test]
+    fn ipv6_next_hop_is_rewritten() {
+        let peer = peer("next_hop = \"2001:db8::66\"", 65000);
+        let update = parse(
+            peer.update(&change("2001:db8:1::/48", Some(received())))
+                .unwrap(),
+        );
+        assert_eq!(
+            update.find_next_hop(AfiSafiType::Ipv6Unicast).unwrap(),
+            NextHop::Unicast("2001:db8::66".parse().unwrap())
+        );
+        assert_eq!(update.announcements().unwrap().count(), 1);
+        // The IPv4 NEXT_HOP attribute is of no use for IPv6 routes.
+        assert!(update
+            .path_attributes()
+            .unwrap()
+            .get(PathAttributeType::ConventionalNextHop)
+            .is_none());
+        // Towards iBGP peers, the LOCAL_PREF is kept.
+        assert_eq!(update.local_pref().unwrap().unwrap().0, 200);
+    }
+
+    #[test]
+    fn withdrawals_and_end_of_rib() {
+        let peer = peer("", 65002);
+        let update =
+            parse(peer.update(&change("10.1.0.0/16", None)).unwrap());
+        assert_eq!(update.withdrawals().unwrap().count(), 1);
+        let update =
+            parse(peer.update(&change("2001:db8::/32", None)).unwrap());
+        assert_eq!(update.withdrawals().unwrap().count(), 1);
+
+        assert!(parse(Peer::end_of_rib((1, 1))).is_eor().unwrap().is_some());
+        assert!(parse(Peer::end_of_rib((2, 1))).is_eor().unwrap().is_some());
+    }
+}
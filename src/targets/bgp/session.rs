@@ -0,0 +1,466 @@
+//! The sessions of the `bgp-out` target.
+//!
+//! Every peer has a task that connects to it and keeps reconnecting every
+//! `CONNECT_RETRY` for as long as the target runs. Once the session is
+//! established, all current routes of the address families configured for
+//! the peer are announced, followed by an End-of-RIB marker for each of
+//! them, after which the changes are announced as they come in. Routes
+//! received from the peer are ignored.
+
+use std::{
+    net::SocketAddr,
+    sync::{atomic::Ordering::SeqCst, Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use log::{debug, info, warn};
+use routecore::bgp::{
+    fsm::session::{Command, DisconnectReason, Message, Session},
+    message::{update::SessionConfig, Message as BgpMsg},
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpSocket, TcpStream},
+    sync::{mpsc, watch},
+};
+
+use crate::{
+    common::tcp_auth::TcpAuth, units::bgp_tcp_in::peer_config::PrefixOrExact,
+};
+
+use super::{
+    adj_rib_out::{AdjRibOut, Change},
+    metrics::BgpMetrics,
+    peer::Peer,
+};
+
+const CONNECT_RETRY: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the NOTIFICATION to be sent when shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Keeps a session with a peer until `stop` changes.
+pub async fn run(
+    name: String,
+    peer: Peer,
+    adj_rib_out: Arc<Mutex<AdjRibOut>>,
+    queue_size: usize,
+    metrics: Arc<BgpMetrics>,
+    mut stop: watch::Receiver<bool>,
+) {
+    let peer_addr = peer.socket_addr();
+    loop {
+        let connect = tokio::time::timeout(
+            CONNECT_TIMEOUT,
+            connect(peer_addr, peer.tcp_auth().clone()),
+        );
+        let res = tokio::select! {
+            res = connect => res,
+            _ = stop.changed() => return,
+        };
+        match res {
+            Ok(Ok(stream)) => {
+                let mut session = PeerSession {
+                    name: &name,
+                    peer: &peer,
+                    adj_rib_out: &adj_rib_out,
+                    queue_size,
+                    metrics: &metrics,
+                };
+                if session.run(stream, &mut stop).await {
+                    return;
+                }
+            }
+            Ok(Err(err)) => {
+                metrics.connect_error_count.fetch_add(1, SeqCst);
+                warn!(
+                    "{name}: cannot connect to peer '{}' at {peer_addr}: \
+                    {err}",
+                    peer.name()
+                );
+            }
+            Err(_) => {
+                metrics.connect_error_count.fetch_add(1, SeqCst);
+                warn!(
+                    "{name}: timed out connecting to peer '{}' at \
+                    {peer_addr}",
+                    peer.name()
+                );
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(CONNECT_RETRY) => {}
+            _ = stop.changed() => return,
+        }
+    }
+}
+
+/// Connects to `peer_addr`, authenticating the connection with `auth`.
+async fn connect(
+    peer_addr: SocketAddr,
+    auth: TcpAuth,
+) -> std::io::Result<TcpStream> {
+    let socket = match peer_addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    let (addr, len) = PrefixOrExact::Exact(peer_addr.ip()).addr_and_len();
+    auth.apply(&socket, addr, len)?;
+    socket.connect(peer_addr).await
+}
+
+//------------ PeerSession ---------------------------------------------------
+
+struct PeerSession<'a> {
+    name: &'a str,
+    peer: &'a Peer,
+    adj_rib_out: &'a Mutex<AdjRibOut>,
+    queue_size: usize,
+    metrics: &'a BgpMetrics,
+}
+
+impl PeerSession<'_> {
+    /// Runs the session over a connection, returning whether to stop.
+    async fn run(
+        &mut self,
+        stream: TcpStream,
+        stop: &mut watch::Receiver<bool>,
+    ) -> bool {
+        let (tcp_in, mut tcp_out) = stream.into_split();
+        let (sess_tx, mut sess_rx) = mpsc::channel(100);
+        let (cmds_tx, cmds_rx) = mpsc::channel(10);
+        let (pdu_out_tx, mut pdu_out_rx) =
+            mpsc::channel::<BgpMsg<Bytes>>(100);
+
+        let mut session = Session::new(
+            self.peer.clone(),
+            tcp_in,
+            sess_tx,
+            cmds_rx,
+            pdu_out_tx.clone(),
+        );
+        session.manual_start().await;
+        session.connection_established().await;
+
+        let mut writer = tokio::spawn(async move {
+            while let Some(pdu) = pdu_out_rx.recv().await {
+                if let Err(err) = tcp_out.write_all(pdu.as_ref()).await {
+                    debug!("cannot send BGP message: {err}");
+                    break;
+                }
+            }
+        });
+
+        let mut subscription = None;
+        let mut stopped = false;
+        loop {
+            tokio::select! {
+                res = session.tick() => {
+                    if let Err(err) = res {
+                        debug!("{}: session error: {err}", self.name);
+                        break;
+                    }
+                }
+
+                msg = sess_rx.recv() => match msg {
+                    Some(Message::SessionNegotiated(_)) => {
+                        info!(
+                            "{}: session with peer '{}' established",
+                            self.name,
+                            self.peer.name()
+                        );
+                        self.metrics
+                            .established_sessions
+                            .fetch_add(1, SeqCst);
+                        let (id, routes, rx) = self
+                            .adj_rib_out
+                            .lock()
+                            .unwrap()
+                            .subscribe(self.queue_size);
+                        subscription = Some((id, rx));
+                        if !self.initial(routes, &pdu_out_tx).await {
+                            break;
+                        }
+                    }
+                    Some(Message::NotificationMessage(pdu)) => {
+                        warn!(
+                            "{}: received NOTIFICATION from peer '{}': {:?}",
+                            self.name,
+                            self.peer.name(),
+                            pdu.details()
+                        );
+                    }
+                    Some(
+                        Message::UpdateMessage(_) | Message::Attributes(_)
+                    ) => {}
+                    Some(Message::ConnectionLost(_)) | None => break,
+                },
+
+                change = next_change(&mut subscription) => match change {
+                    Some(change) => {
+                        if !self.send(&change, &pdu_out_tx).await {
+                            break;
+                        }
+                    }
+                    None => {
+                        warn!(
+                            "{}: peer '{}' cannot keep up, resetting the \
+                            session",
+                            self.name,
+                            self.peer.name()
+                        );
+                        let reason = DisconnectReason::Other;
+                        let _ =
+                            cmds_tx.send(Command::Disconnect(reason)).await;
+                        let _ = tokio::time::timeout(
+                            SHUTDOWN_TIMEOUT,
+                            session.tick(),
+                        )
+                        .await;
+                        break;
+                    }
+                },
+
+                _ = stop.changed() => {
+                    let _ = cmds_tx
+                        .send(Command::Disconnect(DisconnectReason::Shutdown))
+                        .await;
+                    let _ =
+                        tokio::time::timeout(SHUTDOWN_TIMEOUT, session.tick())
+                            .await;
+                    stopped = true;
+                    break;
+                }
+            }
+        }
+
+        if let Some((id, _)) = subscription {
+            self.adj_rib_out.lock().unwrap().unsubscribe(id);
+            self.metrics.established_sessions.fetch_sub(1, SeqCst);
+            self.metrics.session_reset_count.fetch_add(1, SeqCst);
+            info!(
+                "{}: session with peer '{}' is down",
+                self.name,
+                self.peer.name()
+            );
+        }
+
+        // Let the writer send what is left, such as a NOTIFICATION.
+        drop(session);
+        drop(pdu_out_tx);
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, &mut writer)
+            .await
+            .is_err()
+        {
+            writer.abort();
+            debug!("{}: dropping unsent BGP messages", self.name);
+        }
+        stopped
+    }
+
+    /// Announces the current routes, returning whether the session is up.
+    async fn initial(
+        &mut self,
+        routes: Vec<Change>,
+        pdu_out_tx: &mpsc::Sender<BgpMsg<Bytes>>,
+    ) -> bool {
+        for change in &routes {
+            if !self.send(change, pdu_out_tx).await {
+                return false;
+            }
+        }
+        for afi_safi in self.peer.families() {
+            let pdu = Peer::end_of_rib(afi_safi);
+            if !send_pdu(pdu, pdu_out_tx).await {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Announces a change, returning whether the session is up.
+    async fn send(
+        &mut self,
+        change: &Change,
+        pdu_out_tx: &mpsc::Sender<BgpMsg<Bytes>>,
+    ) -> bool {
+        if !self.peer.announces(change.afi_safi) {
+            return true;
+        }
+        let Some(pdu) = self.peer.update(change) else {
+            warn!(
+                "{}: UPDATE for {} too long for peer '{}'",
+                self.name,
+                change.prefix,
+                self.peer.name()
+            );
+            return true;
+        };
+        self.metrics.update_count.fetch_add(1, SeqCst);
+        send_pdu(pdu, pdu_out_tx).await
+    }
+}
+
+/// Queues a message for sending, returning whether the session is up.
+async fn send_pdu(
+    pdu: Vec<u8>,
+    pdu_out_tx: &mpsc::Sender<BgpMsg<Bytes>>,
+) -> bool {
+    match BgpMsg::from_octets(
+        Bytes::from(pdu),
+        Some(&SessionConfig::modern()),
+    ) {
+        Ok(msg) => pdu_out_tx.send(msg).await.is_ok(),
+        Err(err) => {
+            warn!("cannot encode BGP message: {err}");
+            true
+        }
+    }
+}
+
+/// Returns the next change of a subscription, if there is one.
+///
+/// Returns `None` if the subscription was dropped for falling behind.
+async fn next_change(
+    subscription: &mut Option<(usize, mpsc::Receiver<Change>)>,
+) -> Option<Change> {
+    match subscription {
+        Some((_, rx)) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use inetnum::{addr::Prefix, asn::Asn};
+    use routecore::bgp::message::UpdateMessage;
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    use crate::targets::bgp::peer::PeerConfig;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Returns an OPEN message for IPv4 unicast from `asn`.
+    fn open(asn: u32) -> Vec<u8> {
+        let mut body = vec![4, 0x5b, 0xa0, 0, 90, 10, 0, 0, 2];
+        let mut caps = vec![1, 4, 0, 1, 0, 1, 65, 4];
+        caps.extend_from_slice(&asn.to_be_bytes());
+        body.extend_from_slice(&[caps.len() as u8 + 2, 2, caps.len() as u8]);
+        body.extend_from_slice(&caps);
+        message(1, &body)
+    }
+
+    fn message(msg_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut res = vec![0xff; 16];
+        res.extend_from_slice(&(body.len() as u16 + 19).to_be_bytes());
+        res.push(msg_type);
+        res.extend_from_slice(body);
+        res
+    }
+
+    /// Reads messages until one of `msg_type`.
+    async fn read(stream: &mut TcpStream, msg_type: u8) -> Vec<u8> {
+        loop {
+            let mut header = [0; 19];
+            stream.read_exact(&mut header).await.unwrap();
+            let len =
+                usize::from(u16::from_be_bytes([header[16], header[17]]));
+            let mut msg = header.to_vec();
+            msg.resize(len, 0);
+            stream.read_exact(&mut msg[19..]).await.unwrap();
+            if header[18] == msg_type {
+                return msg;
+            }
+        }
+    }
+
+    fn parse(msg: Vec<u8>) -> UpdateMessage<Bytes> {
+        UpdateMessage::from_octets(Bytes::from(msg), &SessionConfig::modern())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn routes_are_announced() {
+        tokio::time::timeout(TIMEOUT, announce_routes())
+            .await
+            .unwrap();
+    }
+
+    async fn announce_routes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config: PeerConfig = toml::from_str(&format!(
+            "name = \"peer\"\nremote_asn = 65002\nport = {}\n\
+            protocols = [\"Ipv4Unicast\"]",
+            listener.local_addr().unwrap().port()
+        ))
+        .unwrap();
+        let peer = Peer::new(
+            "127.0.0.1".parse().unwrap(),
+            Asn::from_u32(65000),
+            [10, 0, 0, 1],
+            config,
+        );
+
+        // ORIGIN, an empty AS_PATH and NEXT_HOP.
+        let attributes: Arc<[u8]> = Arc::from(
+            &[0x40, 1, 1, 0, 0x40, 2, 0, 0x40, 3, 4, 10, 0, 0, 1][..],
+        );
+        let key = |prefix| ((1, 1), Prefix::from_str(prefix).unwrap());
+        let adj_rib_out = Arc::new(Mutex::new(AdjRibOut::default()));
+        adj_rib_out.lock().unwrap().announce(
+            key("10.1.0.0/16"),
+            1,
+            attributes.clone(),
+        );
+
+        let metrics = Arc::new(BgpMetrics::default());
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let task = tokio::spawn(run(
+            "bgp-out".into(),
+            peer,
+            adj_rib_out.clone(),
+            10,
+            metrics.clone(),
+            stop_rx,
+        ));
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        read(&mut stream, 1).await;
+        stream.write_all(&open(65002)).await.unwrap();
+        stream.write_all(&message(4, &[])).await.unwrap();
+
+        // The current routes, followed by End-of-RIB.
+        let update = parse(read(&mut stream, 2).await);
+        assert_eq!(update.announcements().unwrap().count(), 1);
+        let update = parse(read(&mut stream, 2).await);
+        assert!(update.is_eor().unwrap().is_some());
+        assert_eq!(metrics.established_sessions.load(SeqCst), 1);
+
+        // And then the changes.
+        adj_rib_out.lock().unwrap().announce(
+            key("10.2.0.0/16"),
+            1,
+            attributes,
+        );
+        let update = parse(read(&mut stream, 2).await);
+        assert_eq!(update.announcements().unwrap().count(), 1);
+        adj_rib_out.lock().unwrap().withdraw(key("10.1.0.0/16"), 1);
+        let update = parse(read(&mut stream, 2).await);
+        assert_eq!(update.withdrawals().unwrap().count(), 1);
+
+        // The session is closed with a NOTIFICATION when stopping.
+        stop_tx.send(true).unwrap();
+        read(&mut stream, 3).await;
+        task.await.unwrap();
+        assert_eq!(metrics.established_sessions.load(SeqCst), 0);
+        assert_eq!(metrics.update_count.load(SeqCst), 3);
+    }
+}
@@ -0,0 +1,381 @@
+//! Announcing routes to BGP peers.
+//!
+//! The `bgp-out` target turns Rotonda from a passive monitor into a BGP
+//! speaker, such as a route server or a trigger for remote black holing.
+//! It connects to each of the configured `peers` and announces the routes
+//! it receives to them, as described in the [`session`] module, with the
+//! attributes rewritten per peer as described in the [`peer`] module.
+//!
+//! Of all the routes received for a prefix, from different peers of the
+//! upstream units, the most recently received one is announced, as
+//! described in the [`adj_rib_out`] module. Routes of a session that goes
+//! down are withdrawn.
+//!
+//! If the roto script has a `bgp_out` filter, it decides which routes are
+//! announced. A route it rejects is treated as withdrawn, so that a route
+//! that no longer passes the filter is no longer announced.
+//!
+//! [`adj_rib_out`]: super::adj_rib_out
+//! [`peer`]: super::peer
+//! [`session`]: super::session
+
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    sync::{atomic::Ordering::SeqCst, Arc, Mutex},
+    time::Duration,
+};
+
+use futures::future::join_all;
+use inetnum::asn::Asn;
+use log::{debug, error, warn};
+use rotonda_store::prefix_record::RouteStatus;
+use serde::Deserialize;
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+    comms::{Link, Terminated, UnitStatus},
+    manager::{Component, TargetCommand, WaitPoint},
+    payload::{Payload, RotondaRoute, Update},
+    roto_runtime::{
+        self, filter_metrics, logger::ScriptLogger, reload::Reloadable,
+        types::RouteContext, Ctx,
+    },
+    targets::mrt::bgp4mp::{afi_safi, widen_attributes},
+    units::rib_unit::best_path::prefix_of,
+};
+
+use super::{
+    adj_rib_out::AdjRibOut,
+    metrics::BgpMetrics,
+    peer::{Peer, PeerConfig},
+    session,
+};
+
+pub(crate) type RotoFunc = roto::TypedFunc<
+    Ctx,
+    fn(roto::Val<roto_runtime::MutRotondaRoute>) -> roto::Verdict<(), ()>,
+>;
+
+pub const ROTO_FUNC_FILTER_NAME: &str = "bgp_out";
+
+/// How long to wait for the sessions to shut down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct BgpOut {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    my_asn: Asn,
+    my_bgp_id: [u8; 4],
+
+    /// The peers to announce routes to, by their address.
+    peers: BTreeMap<IpAddr, PeerConfig>,
+
+    /// How many changes a session may fall behind before it is reset.
+    #[serde(default = "Config::default_queue_size")]
+    queue_size: usize,
+}
+
+impl Config {
+    fn default_queue_size() -> usize {
+        100_000
+    }
+
+    /// Checks that the configuration can be used.
+    fn check(&self) -> Result<(), String> {
+        if self.peers.is_empty() {
+            return Err("no peers configured".into());
+        }
+        self.peers.values().try_for_each(PeerConfig::check)
+    }
+}
+
+impl BgpOut {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let config = self.config;
+        if let Err(err) = config.check() {
+            error!("Target {}: {err}", component.name());
+            return Err(Terminated);
+        }
+
+        let mut roto_context = Ctx::new(
+            roto_runtime::types::RotoOutputStream::new_rced(),
+            Default::default(),
+            Default::default(),
+        );
+        roto_context.state = component.roto_state();
+        roto_context.logger =
+            Arc::new(ScriptLogger::new(component.name().clone()));
+        let roto_function: Reloadable<Option<RotoFunc>> = Reloadable::new(
+            component.roto().clone(),
+            |c| {
+                c.get_function(ROTO_FUNC_FILTER_NAME)
+                    .inspect_err(|_| {
+                        warn!("Loaded Roto script has no filter for bgp-out")
+                    })
+                    .ok()
+            },
+            &mut roto_context,
+        );
+
+        let metrics = Arc::new(BgpMetrics::default());
+        component.register_metrics(metrics.clone());
+        metrics.peer_count.store(config.peers.len(), SeqCst);
+
+        BgpRunner {
+            name: component.name().to_string(),
+            config,
+            adj_rib_out: Default::default(),
+            roto_function,
+            roto_context,
+            metrics,
+        }
+        .run(self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ BgpRunner -----------------------------------------------------
+
+struct BgpRunner {
+    name: String,
+    config: Config,
+    adj_rib_out: Arc<Mutex<AdjRibOut>>,
+    roto_function: Reloadable<Option<RotoFunc>>,
+    roto_context: Ctx,
+    metrics: Arc<BgpMetrics>,
+}
+
+impl BgpRunner {
+    async fn run(
+        mut self,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let sessions = self
+            .config
+            .peers
+            .iter()
+            .map(|(addr, config)| {
+                let peer = Peer::new(
+                    *addr,
+                    self.config.my_asn,
+                    self.config.my_bgp_id,
+                    config.clone(),
+                );
+                crate::tokio::spawn(
+                    "bgp-out-session",
+                    session::run(
+                        self.name.clone(),
+                        peer,
+                        self.adj_rib_out.clone(),
+                        self.config.queue_size,
+                        self.metrics.clone(),
+                        stop_rx.clone(),
+                    ),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the bgp-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => self.apply(&update),
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of bgp-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+            }
+        }
+
+        // Let the sessions say goodbye to their peers.
+        let _ = stop_tx.send(true);
+        let aborts = sessions
+            .iter()
+            .map(|session| session.abort_handle())
+            .collect::<Vec<_>>();
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, join_all(sessions))
+            .await
+            .is_err()
+        {
+            aborts.iter().for_each(|abort| abort.abort());
+        }
+        Err(Terminated)
+    }
+
+    /// Applies the routes and withdrawals in an update.
+    fn apply(&mut self, update: &Update) {
+        match update {
+            Update::Single(payload) => self.apply_payload(payload),
+            Update::Bulk(payloads) => payloads
+                .iter()
+                .for_each(|payload| self.apply_payload(payload)),
+            Update::Withdraw(ingress_id, afi_safi) => {
+                self.adj_rib_out
+                    .lock()
+                    .unwrap()
+                    .withdraw_ingress(*ingress_id, afi_safi.map(Into::into));
+            }
+            Update::WithdrawBulk(ingress_ids) => {
+                let mut adj_rib_out = self.adj_rib_out.lock().unwrap();
+                for ingress_id in ingress_ids {
+                    adj_rib_out.withdraw_ingress(*ingress_id, None);
+                }
+            }
+            Update::OutputStream(..)
+            | Update::QueryResult(..)
+            | Update::UpstreamStatusChange(..)
+            | Update::Rtr(..) => {}
+        }
+        self.metrics
+            .route_count
+            .store(self.adj_rib_out.lock().unwrap().route_count(), SeqCst);
+    }
+
+    fn apply_payload(&mut self, payload: &Payload) {
+        let (status, provenance) = match &payload.context {
+            RouteContext::Fresh(ctx) => (ctx.status(), ctx.provenance()),
+            RouteContext::Mrt(ctx) => (ctx.status, ctx.provenance()),
+            RouteContext::Reprocess => return,
+        };
+        let key =
+            (afi_safi(&payload.rx_value), prefix_of(&payload.rx_value));
+        let ingress_id = provenance.ingress_id;
+        let route = match status {
+            RouteStatus::Withdrawn => None,
+            _ => self.filter(payload),
+        };
+        let Some(route) = route else {
+            self.adj_rib_out.lock().unwrap().withdraw(key, ingress_id);
+            return;
+        };
+
+        let attributes = route.rotonda_pamap().path_attributes();
+        let four_octet = attributes.pdu_parse_info().four_octet_enabled();
+        let mut raw = attributes.into_vec();
+        if !four_octet {
+            raw = widen_attributes(&raw);
+        }
+        self.adj_rib_out.lock().unwrap().announce(
+            key,
+            ingress_id,
+            raw.into(),
+        );
+    }
+
+    /// Returns the route if the roto filter, if any, accepts it.
+    ///
+    /// The route is returned as rewritten by the filter.
+    fn filter(&mut self, payload: &Payload) -> Option<RotondaRoute> {
+        let Some(roto_function) = self.roto_function.get(&mut self.roto_context)
+        else {
+            return Some(payload.rx_value.clone());
+        };
+        let ingress_id = match &payload.context {
+            RouteContext::Fresh(ctx) => Some(ctx.provenance().ingress_id),
+            RouteContext::Mrt(ctx) => Some(ctx.provenance().ingress_id),
+            RouteContext::Reprocess => None,
+        };
+        self.roto_context.logger.set_ingress(ingress_id);
+        let route: roto_runtime::MutRotondaRoute =
+            payload.rx_value.clone().into();
+        let verdict =
+            filter_metrics::shared().run(ROTO_FUNC_FILTER_NAME, || {
+                roto_function
+                    .call(&mut self.roto_context, roto::Val(route.clone()))
+            });
+        // Output stream messages have nowhere to go.
+        self.roto_context.output.borrow_mut().drain();
+        match verdict {
+            roto::Verdict::Accept(_) => Some(route.borrow().clone()),
+            roto::Verdict::Reject(_) => {
+                self.metrics.rejected_route_count.fetch_add(1, SeqCst);
+                None
+            }
+        }
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mk_config(peers: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(&format!(
+            "my_asn = 65000\nmy_bgp_id = [192, 0, 2, 1]\n{peers}"
+        ))
+    }
+
+    #[test]
+    fn config_is_checked() {
+        let config = mk_config(
+            r#"
+            [peers."192.0.2.2"]
+            name = "blackhole"
+            remote_asn = 65002
+            next_hop = "192.0.2.66"
+            communities = ["BLACKHOLE", "65000:666"]
+
+            [peers."2001:db8::2"]
+            name = "route-server-client"
+            remote_asn = 65003
+            md5_password = "secret"
+            protocols = ["Ipv6Unicast"]
+            prepend = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.peers.len(), 2);
+        assert_eq!(config.queue_size, Config::default_queue_size());
+        assert!(config.check().is_ok());
+
+        assert!(mk_config("peers = {}").unwrap().check().is_err());
+        assert!(mk_config(
+            r#"
+            [peers."192.0.2.2"]
+            name = "bad"
+            remote_asn = 65002
+            communities = ["not-a-community"]
+            "#
+        )
+        .is_err());
+    }
+}
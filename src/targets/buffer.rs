@@ -0,0 +1,683 @@
+//! Buffering batches between a target and its sink.
+//!
+//! Targets that deliver to an external system collect what they receive
+//! into batches and hand these to a task of their own, so that a slow or
+//! unavailable sink does not hold up the units feeding the target. A
+//! [`Buffer`] sits in between. It holds up to `max_pending_batches`
+//! batches in memory, beyond which batches are dropped.
+//!
+//! With `buffer_spill_dir`, batches that do not fit in memory are written
+//! to a directory named after the queue below it instead, as long as less
+//! than `buffer_max_spill_bytes` are spilled already. The queue is named
+//! after the target, or `<target>/<endpoint>` for targets with a queue per
+//! endpoint, with characters other than letters, digits, `-`, `.` and `_`
+//! replaced by `_` in the directory name. Once batches are
+//! on disk, all further batches go there as well until the sink has caught
+//! up, so that they are delivered in the order they were made. Batches left
+//! on disk when Rotonda stops, including one that was being delivered, are
+//! delivered after it starts again.
+//!
+//! With `max_rate`, batches are handed to the sink at a pace of at most
+//! that many rows, documents or events per second, whatever the batches of
+//! the target consist of.
+
+use std::{
+    collections::VecDeque,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering::SeqCst},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::Utc;
+use log::{error, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::{
+    manager::Component,
+    metrics::{
+        self, util::append_labelled_metric, Metric, MetricType, MetricUnit,
+    },
+};
+
+//------------ BufferConfig --------------------------------------------------
+
+/// The buffer settings of a target, flattened into its configuration.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BufferConfig {
+    /// The directory to spill batches to when memory is full.
+    #[serde(default)]
+    pub buffer_spill_dir: Option<PathBuf>,
+
+    /// The most bytes to spill per queue.
+    #[serde(default = "BufferConfig::default_buffer_max_spill_bytes")]
+    pub buffer_max_spill_bytes: u64,
+
+    /// The most items to deliver per second.
+    #[serde(default)]
+    pub max_rate: Option<f64>,
+}
+
+impl BufferConfig {
+    fn default_buffer_max_spill_bytes() -> u64 {
+        1 << 30
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if self
+            .max_rate
+            .is_some_and(|rate| !rate.is_finite() || rate <= 0.)
+        {
+            return Err("max_rate must be a positive number".into());
+        }
+        Ok(())
+    }
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            buffer_spill_dir: None,
+            buffer_max_spill_bytes: Self::default_buffer_max_spill_bytes(),
+            max_rate: None,
+        }
+    }
+}
+
+//------------ Buffered ------------------------------------------------------
+
+/// A batch that can be buffered.
+pub trait Buffered: Serialize + DeserializeOwned + Send + 'static {
+    /// Returns the number of items the rate limit applies to.
+    fn item_count(&self) -> usize;
+}
+
+impl<T: Serialize + DeserializeOwned + Send + 'static> Buffered for Vec<T> {
+    fn item_count(&self) -> usize {
+        self.len()
+    }
+}
+
+//------------ Buffer --------------------------------------------------------
+
+/// The sending half of a buffer.
+///
+/// Dropping it closes the buffer, once the receiver has taken what is left.
+pub struct Buffer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Buffered> Buffer<T> {
+    /// Creates a buffer and registers its metrics with the component.
+    ///
+    /// The name of the queue tells the metrics and spill directories of
+    /// the buffers of a target apart, if it has more than one.
+    pub fn new(
+        queue: String,
+        capacity: usize,
+        config: &BufferConfig,
+        component: &mut Component,
+    ) -> Result<(Self, BufferReceiver<T>), String> {
+        let res = Self::open(queue, capacity, config)?;
+        component.register_metrics(res.0.shared.clone());
+        Ok(res)
+    }
+
+    /// Creates a buffer, picking up batches spilled earlier.
+    pub fn open(
+        queue: String,
+        capacity: usize,
+        config: &BufferConfig,
+    ) -> Result<(Self, BufferReceiver<T>), String> {
+        let mut state = State {
+            memory: VecDeque::new(),
+            spilled: VecDeque::new(),
+            spilled_bytes: 0,
+            sequence: 0,
+            closed: false,
+        };
+        let spill_dir = match &config.buffer_spill_dir {
+            Some(dir) => {
+                let dir = dir.join(file_name(&queue));
+                state.spilled = std::fs::create_dir_all(&dir)
+                    .and_then(|_| spilled_files(&dir))
+                    .map_err(|err| {
+                        format!(
+                            "cannot use buffer spill directory {}: {err}",
+                            dir.display()
+                        )
+                    })?;
+                state.spilled_bytes =
+                    state.spilled.iter().map(|spilled| spilled.size).sum();
+                Some(dir)
+            }
+            None => None,
+        };
+        let shared = Arc::new(Shared {
+            queue,
+            capacity,
+            spill_dir,
+            max_spill_bytes: config.buffer_max_spill_bytes,
+            state: Mutex::new(state),
+            notify: Notify::new(),
+            spill_count: Default::default(),
+            spill_error_count: Default::default(),
+            throttled_duration_ms: Default::default(),
+        });
+        if !shared.state.lock().unwrap().spilled.is_empty() {
+            shared.notify.notify_one();
+        }
+        Ok((
+            Self {
+                shared: shared.clone(),
+            },
+            BufferReceiver {
+                shared,
+                max_rate: config.max_rate,
+                ready_at: tokio::time::Instant::now(),
+                received: None,
+            },
+        ))
+    }
+
+    /// Returns the number of batches in the buffer.
+    pub fn pending_count(&self) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        state.memory.len() + state.spilled.len()
+    }
+
+    /// Adds a batch to the buffer.
+    ///
+    /// Spilled batches are written to disk right away, on a blocking
+    /// thread. If the batch neither fits in memory nor on disk, it is
+    /// handed back.
+    pub async fn push(&self, batch: T) -> Result<(), T> {
+        let shared = &self.shared;
+        let batch = {
+            let mut state = shared.state.lock().unwrap();
+            if state.spilled.is_empty()
+                && state.memory.len() < shared.capacity
+            {
+                state.memory.push_back((Instant::now(), batch));
+                None
+            } else {
+                Some(batch)
+            }
+        };
+        if let Some(batch) = batch {
+            if shared.spill_dir.is_none() {
+                return Err(batch);
+            }
+            let spilling = shared.clone();
+            match tokio::task::spawn_blocking(move || spilling.spill(batch))
+                .await
+            {
+                Ok(res) => res?,
+                Err(err) => std::panic::resume_unwind(err.into_panic()),
+            }
+        }
+        shared.notify.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.notify.notify_one();
+    }
+}
+
+//------------ BufferReceiver ------------------------------------------------
+
+/// The receiving half of a buffer.
+pub struct BufferReceiver<T> {
+    shared: Arc<Shared<T>>,
+    max_rate: Option<f64>,
+
+    /// When the rate limit allows the next batch.
+    ready_at: tokio::time::Instant,
+
+    /// The spilled batch received last, kept on disk until delivered.
+    received: Option<Spilled>,
+}
+
+impl<T: Buffered> BufferReceiver<T> {
+    /// Takes the oldest batch, waiting for one if necessary.
+    ///
+    /// Returns `None` once the buffer is closed and empty. Cancelling the
+    /// returned future does not lose a batch. Receiving the next batch
+    /// marks the previous one as [delivered](Self::delivered).
+    pub async fn recv(&mut self) -> Option<T> {
+        self.delivered().await;
+        loop {
+            let now = tokio::time::Instant::now();
+            if self.ready_at > now {
+                self.shared.throttled_duration_ms.fetch_add(
+                    (self.ready_at - now).as_millis() as u64,
+                    SeqCst,
+                );
+                tokio::time::sleep_until(self.ready_at).await;
+            }
+            let next = {
+                let mut state = self.shared.state.lock().unwrap();
+                match state.memory.pop_front() {
+                    Some((_, batch)) => Next::Batch(batch),
+                    None => match state.spilled.front() {
+                        Some(spilled) => Next::Spilled(spilled.path.clone()),
+                        None if state.closed => return None,
+                        None => Next::Wait,
+                    },
+                }
+            };
+            let batch = match next {
+                Next::Batch(batch) => batch,
+                Next::Spilled(path) => match self.unspill(&path).await {
+                    Some(batch) => batch,
+                    None => continue,
+                },
+                Next::Wait => {
+                    self.shared.notify.notified().await;
+                    continue;
+                }
+            };
+            if let Some(max_rate) = self.max_rate {
+                let delay = batch.item_count() as f64 / max_rate;
+                self.ready_at =
+                    self.ready_at.max(tokio::time::Instant::now())
+                        + Duration::from_secs_f64(delay);
+            }
+            return Some(batch);
+        }
+    }
+
+    /// Marks the batch received last as delivered.
+    ///
+    /// A spilled batch is only removed from disk then, so that it is
+    /// delivered again if Rotonda stops before the sink has taken it.
+    pub async fn delivered(&mut self) {
+        let Some(spilled) = &self.received else {
+            return;
+        };
+        match tokio::fs::remove_file(&spilled.path).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => error!(
+                "Queue {}: cannot remove spilled batch {}: {err}",
+                self.shared.queue,
+                spilled.path.display()
+            ),
+        }
+        self.shared.state.lock().unwrap().spilled_bytes -= spilled.size;
+        self.received = None;
+    }
+
+    /// Reads the oldest spilled batch.
+    ///
+    /// Returns `None` if the batch could not be read and was skipped.
+    async fn unspill(&mut self, path: &Path) -> Option<T> {
+        let res = tokio::fs::read(path).await.and_then(|json| {
+            serde_json::from_slice(&json).map_err(io::Error::other)
+        });
+        // Only this receiver removes spilled batches, so the oldest one
+        // is still the one just read.
+        self.received =
+            Some(self.shared.state.lock().unwrap().spilled.pop_front()?);
+        match res {
+            Ok(batch) => Some(batch),
+            Err(err) => {
+                warn!(
+                    "Queue {}: skipping spilled batch {}: {err}",
+                    self.shared.queue,
+                    path.display()
+                );
+                self.shared.spill_error_count.fetch_add(1, SeqCst);
+                self.delivered().await;
+                None
+            }
+        }
+    }
+}
+
+enum Next<T> {
+    Batch(T),
+    Spilled(PathBuf),
+    Wait,
+}
+
+//------------ Shared --------------------------------------------------------
+
+/// The state shared by both halves of a buffer.
+struct Shared<T> {
+    queue: String,
+    capacity: usize,
+    spill_dir: Option<PathBuf>,
+    max_spill_bytes: u64,
+    state: Mutex<State<T>>,
+
+    /// Wakes up the receiver when a batch was added or the buffer closed.
+    notify: Notify,
+
+    spill_count: AtomicUsize,
+    spill_error_count: AtomicUsize,
+    throttled_duration_ms: AtomicU64,
+}
+
+impl<T: Buffered> Shared<T> {
+    /// Writes a batch to the spill directory, handing it back on failure.
+    ///
+    /// This blocks. The state is only locked around the bookkeeping, so
+    /// that the receiver can carry on while the batch is written.
+    fn spill(&self, batch: T) -> Result<(), T> {
+        let Some(dir) = &self.spill_dir else {
+            return Err(batch);
+        };
+        let json = match serde_json::to_vec(&batch) {
+            Ok(json) => json,
+            Err(err) => {
+                self.spill_error(&err);
+                return Err(batch);
+            }
+        };
+        let size = json.len() as u64;
+        let sequence = {
+            let mut state = self.state.lock().unwrap();
+            if state.spilled_bytes + size > self.max_spill_bytes {
+                return Err(batch);
+            }
+            state.spilled_bytes += size;
+            state.sequence += 1;
+            state.sequence - 1
+        };
+        // The names sort in the order the batches were spilled, also
+        // across restarts. Writing to a hidden file first keeps partial
+        // batches from being picked up after a crash.
+        let name = format!(
+            "{}-{:010}.json",
+            Utc::now().format("%Y%m%dT%H%M%S%.6fZ"),
+            sequence
+        );
+        let path = dir.join(&name);
+        let tmp = dir.join(format!(".{name}"));
+        let res = std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &path));
+        let mut state = self.state.lock().unwrap();
+        if let Err(err) = res {
+            state.spilled_bytes -= size;
+            drop(state);
+            self.spill_error(&err);
+            return Err(batch);
+        }
+        state.spilled.push_back(Spilled {
+            path,
+            since: Instant::now(),
+            size,
+        });
+        drop(state);
+        self.spill_count.fetch_add(1, SeqCst);
+        Ok(())
+    }
+}
+
+impl<T> Shared<T> {
+    fn spill_error(&self, err: &dyn std::fmt::Display) {
+        error!("Queue {}: cannot spill batch: {err}", self.queue);
+        self.spill_error_count.fetch_add(1, SeqCst);
+    }
+}
+
+struct State<T> {
+    /// The batches in memory, with when they were added.
+    ///
+    /// These are always older than the spilled batches.
+    memory: VecDeque<(Instant, T)>,
+    spilled: VecDeque<Spilled>,
+    spilled_bytes: u64,
+
+    /// The number of the next spilled batch.
+    sequence: u64,
+    closed: bool,
+}
+
+/// A batch written to disk.
+struct Spilled {
+    path: PathBuf,
+    since: Instant,
+    size: u64,
+}
+
+/// Returns the name of the spill directory of a queue.
+///
+/// Characters that may not be safe in file names are replaced.
+fn file_name(queue: &str) -> String {
+    queue
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-._".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Returns the batches spilled to a directory, oldest first.
+fn spilled_files(dir: &Path) -> io::Result<VecDeque<Spilled>> {
+    let mut res = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') || !name.ends_with(".json") {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| {
+                SystemTime::now().duration_since(modified).ok()
+            })
+            .unwrap_or_default();
+        res.push(Spilled {
+            path,
+            since: Instant::now()
+                .checked_sub(age)
+                .unwrap_or_else(Instant::now),
+            size: metadata.len(),
+        });
+    }
+    res.sort_by(|left, right| left.path.cmp(&right.path));
+    Ok(res.into())
+}
+
+//--- Metrics
+
+impl<T> Shared<T> {
+    const MEMORY_BATCH_COUNT_METRIC: Metric = Metric::new(
+        "target_buffer_memory_batch_count",
+        "the number of batches waiting in memory",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const SPILLED_BATCH_COUNT_METRIC: Metric = Metric::new(
+        "target_buffer_spilled_batch_count",
+        "the number of batches waiting on disk",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const SPILLED_SIZE_METRIC: Metric = Metric::new(
+        "target_buffer_spilled_size",
+        "the size of the batches waiting on disk",
+        MetricType::Gauge,
+        MetricUnit::Byte,
+    );
+    const OLDEST_BATCH_AGE_METRIC: Metric = Metric::new(
+        "target_buffer_oldest_batch_age",
+        "how long the oldest waiting batch has been waiting",
+        MetricType::Gauge,
+        MetricUnit::Second,
+    );
+    const SPILL_COUNT_METRIC: Metric = Metric::new(
+        "target_buffer_spill_count",
+        "the number of batches written to disk",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const SPILL_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "target_buffer_spill_error_count",
+        "the number of batches that could not be written to or read from \
+        disk",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const THROTTLED_DURATION_METRIC: Metric = Metric::new(
+        "target_buffer_throttled_duration",
+        "how long delivery was held back by the rate limit",
+        MetricType::Counter,
+        MetricUnit::Millisecond,
+    );
+}
+
+impl<T: Send> metrics::Source for Shared<T> {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        let (memory, spilled, spilled_bytes, oldest) = {
+            let state = self.state.lock().unwrap();
+            let oldest =
+                state.memory.front().map(|(since, _)| *since).or_else(|| {
+                    state.spilled.front().map(|spilled| spilled.since)
+                });
+            (
+                state.memory.len(),
+                state.spilled.len(),
+                state.spilled_bytes,
+                oldest,
+            )
+        };
+        let oldest = oldest.map_or(0, |since| since.elapsed().as_secs());
+        for (metric, value) in [
+            (Self::MEMORY_BATCH_COUNT_METRIC, memory as u64),
+            (Self::SPILLED_BATCH_COUNT_METRIC, spilled as u64),
+            (Self::SPILLED_SIZE_METRIC, spilled_bytes),
+            (Self::OLDEST_BATCH_AGE_METRIC, oldest),
+            (
+                Self::SPILL_COUNT_METRIC,
+                self.spill_count.load(SeqCst) as u64,
+            ),
+            (
+                Self::SPILL_ERROR_COUNT_METRIC,
+                self.spill_error_count.load(SeqCst) as u64,
+            ),
+            (
+                Self::THROTTLED_DURATION_METRIC,
+                self.throttled_duration_ms.load(SeqCst),
+            ),
+        ] {
+            append_labelled_metric(
+                unit_name,
+                target,
+                "queue",
+                &self.queue,
+                metric,
+                value,
+            );
+        }
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(dir: Option<&Path>, max_rate: Option<f64>) -> BufferConfig {
+        BufferConfig {
+            buffer_spill_dir: dir.map(Path::to_path_buf),
+            max_rate,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn full_buffers_hand_batches_back() {
+        let (tx, mut rx) =
+            Buffer::open("q".into(), 2, &config(None, None)).unwrap();
+        assert!(tx.push(vec![1]).await.is_ok());
+        assert!(tx.push(vec![2]).await.is_ok());
+        assert_eq!(tx.push(vec![3]).await, Err(vec![3]));
+        drop(tx);
+        assert_eq!(rx.recv().await, Some(vec![1]));
+        assert_eq!(rx.recv().await, Some(vec![2]));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn batches_are_spilled_in_order() {
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-buffer-{}", uuid::Uuid::new_v4()));
+        let config = config(Some(&dir), None);
+        let (tx, mut rx) = Buffer::open("q".into(), 1, &config).unwrap();
+        for batch in 1..=4 {
+            assert!(tx.push(vec![batch]).await.is_ok());
+        }
+        assert_eq!(std::fs::read_dir(dir.join("q")).unwrap().count(), 3);
+        assert_eq!(rx.recv().await, Some(vec![1]));
+        assert_eq!(rx.recv().await, Some(vec![2]));
+
+        // While batches are on disk, new ones go there too.
+        assert!(tx.push(vec![5]).await.is_ok());
+        assert_eq!(rx.recv().await, Some(vec![3]));
+
+        // What is left on disk is picked up by the next buffer, including
+        // the batch that was not delivered.
+        drop((tx, rx));
+        let (tx, mut rx) =
+            Buffer::<Vec<i32>>::open("q".into(), 1, &config).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv().await, Some(vec![3]));
+        rx.delivered().await;
+        assert_eq!(std::fs::read_dir(dir.join("q")).unwrap().count(), 2);
+        assert_eq!(rx.recv().await, Some(vec![4]));
+        assert_eq!(rx.recv().await, Some(vec![5]));
+        assert_eq!(rx.recv().await, None);
+        assert_eq!(std::fs::read_dir(dir.join("q")).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn spilling_is_limited() {
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-buffer-{}", uuid::Uuid::new_v4()));
+        let config = BufferConfig {
+            buffer_max_spill_bytes: 6,
+            ..config(Some(&dir), None)
+        };
+        let (tx, _rx) = Buffer::open("q".into(), 1, &config).unwrap();
+        assert!(tx.push(vec![1]).await.is_ok());
+        assert!(tx.push(vec![2]).await.is_ok());
+        assert!(tx.push(vec![3]).await.is_ok());
+        assert_eq!(tx.push(vec![4]).await, Err(vec![4]));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delivery_is_paced() {
+        let (tx, mut rx) =
+            Buffer::open("q".into(), 4, &config(None, Some(10.))).unwrap();
+        assert!(tx.push(vec![1; 5]).await.is_ok());
+        assert!(tx.push(vec![2; 5]).await.is_ok());
+        assert!(tx.push(vec![3; 5]).await.is_ok());
+        let started = tokio::time::Instant::now();
+        rx.recv().await.unwrap();
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        rx.recv().await.unwrap();
+        rx.recv().await.unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct ClickHouseMetrics {
+    pub insert_count: AtomicUsize,
+    pub insert_error_count: AtomicUsize,
+    pub inserted_row_count: AtomicUsize,
+    pub dropped_row_count: AtomicUsize,
+    pub pending_batch_count: AtomicUsize,
+    pub last_insert_duration_ms: AtomicU64,
+    pub insert_duration_ms: AtomicU64,
+}
+
+impl GraphStatus for ClickHouseMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "inserted: {}\npending: {}\nerrors: {}\ndropped: {}",
+            self.inserted_row_count.load(SeqCst),
+            self.pending_batch_count.load(SeqCst),
+            self.insert_error_count.load(SeqCst),
+            self.dropped_row_count.load(SeqCst),
+        )
+    }
+}
+
+impl ClickHouseMetrics {
+    const INSERT_COUNT_METRIC: Metric = Metric::new(
+        "clickhouse_target_insert_count",
+        "the number of successful inserts into ClickHouse",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const INSERT_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "clickhouse_target_insert_error_count",
+        "the number of insert attempts that failed, including retries",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const INSERTED_ROW_COUNT_METRIC: Metric = Metric::new(
+        "clickhouse_target_inserted_row_count",
+        "the number of rows inserted into ClickHouse",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DROPPED_ROW_COUNT_METRIC: Metric = Metric::new(
+        "clickhouse_target_dropped_row_count",
+        "the number of rows dropped after failing to insert them or \
+        because too many batches were waiting",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const PENDING_BATCH_COUNT_METRIC: Metric = Metric::new(
+        "clickhouse_target_pending_batch_count",
+        "the number of batches waiting to be inserted",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const LAST_INSERT_DURATION_METRIC: Metric = Metric::new(
+        "clickhouse_target_last_insert_duration",
+        "how long the last successful insert took",
+        MetricType::Gauge,
+        MetricUnit::Millisecond,
+    );
+    const INSERT_DURATION_METRIC: Metric = Metric::new(
+        "clickhouse_target_insert_duration",
+        "how long all successful inserts took together",
+        MetricType::Counter,
+        MetricUnit::Millisecond,
+    );
+}
+
+impl metrics::Source for ClickHouseMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::INSERT_COUNT_METRIC,
+            Some(unit_name),
+            self.insert_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::INSERT_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.insert_error_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::INSERTED_ROW_COUNT_METRIC,
+            Some(unit_name),
+            self.inserted_row_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DROPPED_ROW_COUNT_METRIC,
+            Some(unit_name),
+            self.dropped_row_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::PENDING_BATCH_COUNT_METRIC,
+            Some(unit_name),
+            self.pending_batch_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::LAST_INSERT_DURATION_METRIC,
+            Some(unit_name),
+            self.last_insert_duration_ms.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::INSERT_DURATION_METRIC,
+            Some(unit_name),
+            self.insert_duration_ms.load(SeqCst),
+        );
+    }
+}
@@ -0,0 +1,916 @@
+//! Inserting routes and peer events into ClickHouse.
+//!
+//! The `clickhouse-out` target turns each route and peer event it receives
+//! into a row with the columns described in the [`row`] module, the same
+//! rows the Parquet and Avro output of the `file-out` target hold. Routes
+//! received directly from a unit have the kind "announce" or "withdraw",
+//! routes in an output stream message the kind "route". Routes go into the
+//! `routes_table`, peers going down into the `peer_events_table`. Other
+//! messages are ignored.
+//!
+//! Rows are collected into batches of up to `batch_size` rows, each batch
+//! being inserted after at most `batch_interval_secs`, through the HTTP
+//! interface in the `JSONEachRow` format. The `columns` setting renames
+//! columns, with an empty name leaving a column out. Columns that the table
+//! does not have are skipped by ClickHouse, so a table only needs the
+//! columns it is interested in. Timestamps are sent as RFC 3339 strings,
+//! which suit `DateTime64(6)` columns.
+//!
+//! A failed insert is tried again up to `max_retries` times, with an
+//! exponential backoff. Meanwhile, up to `max_pending_batches` batches wait
+//! for their turn; rows beyond that, or in a batch that could not be
+//! inserted at all, are dropped. With `async_insert`, ClickHouse collects
+//! the inserted rows itself, which suits many small batches.
+//!
+//! With a `dead_letter_dir`, batches that could not be inserted are kept in
+//! the [dead-letter spool] instead of being dropped, and inserted again when
+//! a replay is requested through the HTTP API. The waiting batches can spill
+//! to disk and their insertion be rate limited, as described in the
+//! [`buffer`] module.
+//!
+//! The server may be reached over HTTPS. The CA certificates of the system
+//! are used to check its certificate unless others are given in the `tls`
+//! table. The native protocol is not supported.
+//!
+//! [`row`]: crate::targets::file::row
+//! [dead-letter spool]: crate::targets::dead_letter
+//! [`buffer`]: crate::targets::buffer
+
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, warn};
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use tokio::sync::mpsc;
+use url::Url;
+
+use crate::{
+    common::tls::TlsClientConfig,
+    comms::{Link, Terminated, UnitStatus},
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    payload::Update,
+    targets::{
+        buffer::{Buffer, BufferConfig, BufferReceiver, Buffered},
+        dead_letter::{replay_requested, DeadLetterConfig, Spool},
+        file::row::{Row, COLUMNS},
+    },
+};
+
+use super::metrics::ClickHouseMetrics;
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct ClickHouse {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The URL of the HTTP interface, e.g. `http://localhost:8123/`.
+    pub url: Url,
+
+    /// The database holding the tables.
+    #[serde(default = "Config::default_database")]
+    pub database: String,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// The TLS settings for an HTTPS URL.
+    #[serde(default)]
+    pub tls: Option<TlsClientConfig>,
+
+    /// The table to insert routes into.
+    #[serde(default = "Config::default_routes_table")]
+    pub routes_table: String,
+
+    /// The table to insert peer events into.
+    #[serde(default = "Config::default_peer_events_table")]
+    pub peer_events_table: String,
+
+    /// The names of the columns in the tables, by the name of the column in
+    /// the rows. An empty name leaves the column out.
+    #[serde(default)]
+    pub columns: HashMap<String, String>,
+
+    /// The largest number of rows to insert at a time.
+    #[serde(default = "Config::default_batch_size")]
+    pub batch_size: usize,
+
+    /// The longest time rows wait before they are inserted.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_batch_interval_secs")]
+    pub batch_interval_secs: Duration,
+
+    /// Whether to let ClickHouse collect rows with asynchronous inserts.
+    #[serde(default)]
+    pub async_insert: bool,
+
+    /// How often to try a failed insert again.
+    #[serde(default = "Config::default_max_retries")]
+    pub max_retries: usize,
+
+    /// How long to wait before the first retry. The delay doubles with
+    /// every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_retry_delay_secs")]
+    pub retry_delay_secs: Duration,
+
+    /// The longest to wait before retrying.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_max_retry_delay_secs")]
+    pub max_retry_delay_secs: Duration,
+
+    /// How many batches may wait to be inserted.
+    #[serde(default = "Config::default_max_pending_batches")]
+    pub max_pending_batches: usize,
+
+    #[serde(flatten)]
+    pub buffer: BufferConfig,
+
+    #[serde(flatten)]
+    pub dead_letter: DeadLetterConfig,
+}
+
+impl Config {
+    fn default_database() -> String {
+        "default".into()
+    }
+
+    fn default_routes_table() -> String {
+        "rotonda_routes".into()
+    }
+
+    fn default_peer_events_table() -> String {
+        "rotonda_peer_events".into()
+    }
+
+    fn default_batch_size() -> usize {
+        10_000
+    }
+
+    fn default_batch_interval_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retries() -> usize {
+        5
+    }
+
+    fn default_retry_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retry_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_max_pending_batches() -> usize {
+        16
+    }
+
+    /// Checks the settings, returning what is wrong with them.
+    fn check(&self) -> Result<(), String> {
+        if !matches!(self.url.scheme(), "http" | "https") {
+            return Err(format!(
+                "unsupported URL scheme '{}', use http or https",
+                self.url.scheme()
+            ));
+        }
+        if let Some(name) = self
+            .columns
+            .keys()
+            .find(|name| !COLUMNS.iter().any(|c| c.name == *name))
+        {
+            return Err(format!("unknown column '{name}'"));
+        }
+        if self.batch_size == 0 || self.max_pending_batches == 0 {
+            return Err(
+                "batch_size and max_pending_batches must be at least 1"
+                    .into(),
+            );
+        }
+        self.buffer.check()
+    }
+
+    /// Returns the client for the server, `shared` unless there are TLS
+    /// settings.
+    fn client(&self, shared: HttpClient) -> Result<HttpClient, String> {
+        match &self.tls {
+            Some(tls) => tls
+                .http_client_builder()?
+                .build()
+                .map_err(|err| err.to_string()),
+            None => Ok(shared),
+        }
+    }
+}
+
+impl ClickHouse {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        if let Err(err) = self.config.check() {
+            error!("Target {}: {err}", component.name());
+            return Err(Terminated);
+        }
+        let http = match self.config.client(component.http_client().clone()) {
+            Ok(http) => http,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+
+        let spool = match Spool::new(&self.config.dead_letter, &mut component)
+        {
+            Ok(spool) => spool,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+        let (buffer, batch_rx) = match Buffer::new(
+            component.name().to_string(),
+            self.config.max_pending_batches,
+            &self.config.buffer,
+            &mut component,
+        ) {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+        let metrics = Arc::new(ClickHouseMetrics::default());
+        metrics
+            .pending_batch_count
+            .store(buffer.pending_count(), SeqCst);
+        component.register_metrics(metrics.clone());
+        let inserter = Inserter::new(
+            component.name().to_string(),
+            http,
+            &self.config,
+            spool,
+            metrics.clone(),
+        );
+        let batcher =
+            Batcher::new(&self.config, component.ingresses().clone());
+        ClickHouseRunner {
+            batcher,
+            inserter,
+            metrics,
+            buffer,
+            batch_rx,
+            batch_interval: self.config.batch_interval_secs,
+        }
+        .run(self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ ClickHouseRunner ----------------------------------------------
+
+struct ClickHouseRunner {
+    batcher: Batcher,
+    inserter: Inserter,
+    metrics: Arc<ClickHouseMetrics>,
+    buffer: Buffer<Batch>,
+    batch_rx: BufferReceiver<Batch>,
+    batch_interval: Duration,
+}
+
+impl ClickHouseRunner {
+    async fn run(
+        self,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let Self {
+            mut batcher,
+            inserter,
+            metrics,
+            buffer,
+            batch_rx,
+            batch_interval,
+        } = self;
+
+        // Batches are inserted by a task of their own, so that a slow or
+        // unavailable server does not hold up the sources.
+        let insert_task = tokio::spawn(inserter.run(batch_rx));
+        let send = |batch: Batch| {
+            let (buffer, metrics) = (&buffer, &metrics);
+            async move {
+                match buffer.push(batch).await {
+                    Ok(()) => {
+                        metrics.pending_batch_count.fetch_add(1, SeqCst);
+                    }
+                    Err(batch) => {
+                        warn!(
+                            "Dropping {} rows for table {}: too many \
+                            batches are waiting to be inserted",
+                            batch.rows.len(),
+                            batch.table
+                        );
+                        metrics
+                            .dropped_row_count
+                            .fetch_add(batch.rows.len(), SeqCst);
+                    }
+                }
+            }
+        };
+
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        let mut flush = tokio::time::interval(batch_interval);
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the clickhouse-out target requires \
+                            a restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        for batch in batcher.push(update) {
+                            send(batch).await;
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of clickhouse-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+
+                _ = flush.tick() => {
+                    for batch in batcher.take() {
+                        send(batch).await;
+                    }
+                }
+            }
+        }
+
+        // Insert what is left before stopping.
+        for batch in batcher.take() {
+            send(batch).await;
+        }
+        drop(buffer);
+        let _ = insert_task.await;
+        Err(Terminated)
+    }
+}
+
+//------------ Batcher -------------------------------------------------------
+
+/// The rows destined for a table.
+#[derive(Debug, Deserialize, Serialize)]
+struct Batch {
+    table: String,
+    rows: Vec<Row>,
+}
+
+impl Buffered for Batch {
+    fn item_count(&self) -> usize {
+        self.rows.len()
+    }
+}
+
+/// Collects the rows of the updates into batches.
+struct Batcher {
+    ingresses: Arc<ingress::Register>,
+    batch_size: usize,
+    routes: Batch,
+    peer_events: Batch,
+}
+
+impl Batcher {
+    fn new(config: &Config, ingresses: Arc<ingress::Register>) -> Self {
+        Self {
+            ingresses,
+            batch_size: config.batch_size,
+            routes: Batch {
+                table: config.routes_table.clone(),
+                rows: Vec::new(),
+            },
+            peer_events: Batch {
+                table: config.peer_events_table.clone(),
+                rows: Vec::new(),
+            },
+        }
+    }
+
+    /// Adds the rows of an update, returning the batches that are full.
+    fn push(&mut self, update: Update) -> Vec<Batch> {
+        let rows = Row::for_update(update, &self.ingresses);
+
+        let mut res = Vec::new();
+        for row in rows {
+            let batch = match row.kind {
+                "route" | "announce" | "withdraw" => &mut self.routes,
+                "peer_down" => &mut self.peer_events,
+                _ => continue,
+            };
+            batch.rows.push(row);
+            if batch.rows.len() >= self.batch_size {
+                res.push(Batch {
+                    table: batch.table.clone(),
+                    rows: std::mem::take(&mut batch.rows),
+                });
+            }
+        }
+        res
+    }
+
+    /// Takes the batches collected so far.
+    fn take(&mut self) -> Vec<Batch> {
+        [&mut self.routes, &mut self.peer_events]
+            .into_iter()
+            .filter(|batch| !batch.rows.is_empty())
+            .map(|batch| Batch {
+                table: batch.table.clone(),
+                rows: std::mem::take(&mut batch.rows),
+            })
+            .collect()
+    }
+}
+
+//------------ Inserter ------------------------------------------------------
+
+/// Inserts batches into ClickHouse.
+struct Inserter {
+    name: String,
+    http: HttpClient,
+    url: Url,
+    database: String,
+    username: Option<String>,
+    password: Option<String>,
+
+    /// The names of the columns in the table, `None` for left out columns.
+    columns: Vec<Option<String>>,
+    async_insert: bool,
+    max_retries: usize,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+
+    /// Where to put the rows that could not be inserted, if anywhere.
+    spool: Option<Arc<Spool>>,
+    metrics: Arc<ClickHouseMetrics>,
+}
+
+impl Inserter {
+    fn new(
+        name: String,
+        http: HttpClient,
+        config: &Config,
+        spool: Option<Arc<Spool>>,
+        metrics: Arc<ClickHouseMetrics>,
+    ) -> Self {
+        let columns = COLUMNS
+            .iter()
+            .map(|column| {
+                match config.columns.get(column.name).map(String::as_str) {
+                    Some("") => None,
+                    Some(name) => Some(name.to_string()),
+                    None => Some(column.name.to_string()),
+                }
+            })
+            .collect();
+        Self {
+            name,
+            http,
+            url: config.url.clone(),
+            database: config.database.clone(),
+            username: config.username.clone(),
+            password: config.password.clone(),
+            columns,
+            async_insert: config.async_insert,
+            max_retries: config.max_retries,
+            retry_delay: config.retry_delay_secs,
+            max_retry_delay: config.max_retry_delay_secs,
+            spool,
+            metrics,
+        }
+    }
+
+    /// Inserts batches until there are no more.
+    ///
+    /// Spooled batches are inserted again when asked to.
+    async fn run(self, mut batch_rx: BufferReceiver<Batch>) {
+        let mut replays = self.spool.as_ref().map(|spool| spool.subscribe());
+        loop {
+            tokio::select! {
+                batch = batch_rx.recv() => match batch {
+                    Some(batch) => {
+                        self.metrics.pending_batch_count.fetch_sub(1, SeqCst);
+                        self.insert_with_retries(&batch).await;
+                        batch_rx.delivered().await;
+                    }
+                    None => break,
+                },
+                _ = replay_requested(&mut replays) => {
+                    if let Some(spool) = &self.spool {
+                        let this = &self;
+                        spool
+                            .replay(None, |letter| async move {
+                                this.insert(&letter.key, letter.body).await
+                            })
+                            .await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn insert_with_retries(&self, batch: &Batch) {
+        let body = self.body(&batch.rows);
+        let mut reason = String::new();
+        let mut delay = self.retry_delay;
+        for attempt in 0..=self.max_retries {
+            let started = Instant::now();
+            match self.insert(&batch.table, body.clone()).await {
+                Ok(()) => {
+                    let duration = started.elapsed().as_millis() as u64;
+                    let metrics = &self.metrics;
+                    metrics.insert_count.fetch_add(1, SeqCst);
+                    metrics
+                        .inserted_row_count
+                        .fetch_add(batch.rows.len(), SeqCst);
+                    metrics.last_insert_duration_ms.store(duration, SeqCst);
+                    metrics.insert_duration_ms.fetch_add(duration, SeqCst);
+                    return;
+                }
+                Err(err) => {
+                    self.metrics.insert_error_count.fetch_add(1, SeqCst);
+                    warn!(
+                        "Target {}: inserting into {} failed: {err}",
+                        self.name, batch.table
+                    );
+                    reason = err;
+                    if attempt < self.max_retries {
+                        info!(
+                            "Target {}: retrying in {}s",
+                            self.name,
+                            delay.as_secs()
+                        );
+                        tokio::time::sleep(delay).await;
+                        delay = (delay * 2).min(self.max_retry_delay);
+                    }
+                }
+            }
+        }
+        if let Some(spool) = &self.spool {
+            if spool.put(&batch.table, &reason, body).await {
+                warn!(
+                    "Target {}: spooled {} rows for table {} after {} \
+                    attempts",
+                    self.name,
+                    batch.rows.len(),
+                    batch.table,
+                    self.max_retries + 1
+                );
+                return;
+            }
+        }
+        error!(
+            "Target {}: dropping {} rows for table {} after {} attempts",
+            self.name,
+            batch.rows.len(),
+            batch.table,
+            self.max_retries + 1
+        );
+        self.metrics
+            .dropped_row_count
+            .fetch_add(batch.rows.len(), SeqCst);
+    }
+
+    /// Makes a single attempt at inserting rows.
+    async fn insert(&self, table: &str, body: String) -> Result<(), String> {
+        let mut url = self.url.clone();
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair(
+                "query",
+                &format!(
+                    "INSERT INTO {}.{} FORMAT JSONEachRow",
+                    quote(&self.database),
+                    quote(table)
+                ),
+            );
+            query.append_pair("input_format_skip_unknown_fields", "1");
+            query.append_pair("date_time_input_format", "best_effort");
+            if self.async_insert {
+                query.append_pair("async_insert", "1");
+                query.append_pair("wait_for_async_insert", "1");
+            }
+        }
+
+        let mut request = self.http.post(url).body(body);
+        if let Some(username) = &self.username {
+            request = request.header("X-ClickHouse-User", username);
+        }
+        if let Some(password) = &self.password {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+        let response = request.send().await.map_err(|err| err.to_string())?;
+        if response.status().is_success() {
+            return Ok(());
+        }
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        Err(format!("{status}: {}", text.trim()))
+    }
+
+    /// Encodes rows as lines of JSON objects.
+    fn body(&self, rows: &[Row]) -> String {
+        let mut res = String::new();
+        for row in rows {
+            let mut object = serde_json::Map::new();
+            for (index, name) in self.columns.iter().enumerate() {
+                if let Some(name) = name {
+                    object.insert(name.clone(), row.value(index).to_json());
+                }
+            }
+            res.push_str(&serde_json::Value::Object(object).to_string());
+            res.push('\n');
+        }
+        res
+    }
+}
+
+/// Quotes a database or table name.
+fn quote(name: &str) -> String {
+    format!("`{}`", name.replace('\\', "\\\\").replace('`', "\\`"))
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use chrono::DateTime;
+    use inetnum::asn::Asn;
+    use serde_json::json;
+
+    use crate::{
+        ingress::IngressInfo,
+        roto_runtime::types::OutputStreamMessage,
+        targets::file::row::tests::mk_route,
+        tests::util::{http::MockServer, https},
+    };
+
+    use super::*;
+
+    fn mk_config(addr: SocketAddr, extra: &str) -> Config {
+        toml::from_str(&format!("url = \"http://{addr}/\"\n{extra}")).unwrap()
+    }
+
+    fn mk_inserter(config: &Config) -> (Inserter, Arc<ClickHouseMetrics>) {
+        let metrics = Arc::new(ClickHouseMetrics::default());
+        let inserter = Inserter::new(
+            "clickhouse".into(),
+            HttpClient::new(),
+            config,
+            None,
+            metrics.clone(),
+        );
+        (inserter, metrics)
+    }
+
+    #[test]
+    fn config_is_checked() {
+        let addr = "127.0.0.1:8123".parse().unwrap();
+        assert!(mk_config(addr, "").check().is_ok());
+        assert!(mk_config(addr, "columns = { prefixx = \"p\" }")
+            .check()
+            .is_err());
+        let config: Config =
+            toml::from_str("url = \"https://localhost:8443/\"").unwrap();
+        assert!(config.check().is_ok());
+        let config: Config =
+            toml::from_str("url = \"tcp://localhost:9000/\"").unwrap();
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn rows_are_batched_per_table() {
+        let config = mk_config(
+            "127.0.0.1:8123".parse().unwrap(),
+            "batch_size = 2\npeer_events_table = \"events\"",
+        );
+        let ingresses = Arc::new(ingress::Register::default());
+        let id = ingresses.register();
+        ingresses.update_info(
+            id,
+            IngressInfo::new()
+                .with_remote_addr("192.0.2.1".parse().unwrap())
+                .with_remote_asn(Asn::from_u32(65000)),
+        );
+        let mut batcher = Batcher::new(&config, ingresses);
+
+        let route = || {
+            OutputStreamMessage::prefix(
+                Some(mk_route("198.51.100.0/24", &[65000], &[])),
+                Some(id),
+            )
+        };
+        let update = |msgs: Vec<OutputStreamMessage>| {
+            Update::OutputStream(msgs.into_iter().collect())
+        };
+
+        assert!(batcher.push(update(vec![route()])).is_empty());
+        assert!(batcher.push(Update::Withdraw(id, None)).is_empty());
+        let full = batcher.push(update(vec![
+            route(),
+            OutputStreamMessage::custom(1, 2, None),
+        ]));
+        assert_eq!(full.len(), 1);
+        assert_eq!(full[0].table, "rotonda_routes");
+        assert_eq!(full[0].rows.len(), 2);
+
+        let rest = batcher.take();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].table, "events");
+        assert_eq!(rest[0].rows[0].peer_ip.as_deref(), Some("192.0.2.1"));
+        assert!(batcher.take().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rows_are_inserted_over_https() {
+        let server = MockServer::with_statuses(vec![], 200).await;
+        let front = https::front(server.addr).await;
+        let config: Config = toml::from_str(&format!(
+            "url = \"https://localhost:{}/\"\ntls = {{ ca = \"{}\" }}",
+            front.port(),
+            https::ca_file().display()
+        ))
+        .unwrap();
+        config.check().unwrap();
+        let metrics = Arc::new(ClickHouseMetrics::default());
+        let inserter = Inserter::new(
+            "clickhouse".into(),
+            config.client(HttpClient::new()).unwrap(),
+            &config,
+            None,
+            metrics.clone(),
+        );
+        let row = Row::new(
+            OutputStreamMessage::prefix(
+                Some(mk_route("192.0.2.0/24", &[65001], &[])),
+                None,
+            ),
+            &ingress::Register::default(),
+        );
+        inserter
+            .insert_with_retries(&Batch {
+                table: "routes".into(),
+                rows: vec![row],
+            })
+            .await;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].json()["prefix"], "192.0.2.0/24");
+        assert_eq!(metrics.inserted_row_count.load(SeqCst), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rows_are_inserted_with_renamed_columns() {
+        let server = MockServer::with_statuses(vec![], 200).await;
+        let config = mk_config(
+            server.addr,
+            "database = \"bgp\"\nasync_insert = true\n\
+            columns = { timestamp = \"ts\", custom = \"\" }",
+        );
+        let (inserter, metrics) = mk_inserter(&config);
+        let row = Row::new(
+            OutputStreamMessage::prefix(
+                Some(mk_route("192.0.2.0/24", &[65001], &[(65001, 7)])),
+                None,
+            ),
+            &ingress::Register::default(),
+        );
+        let row = Row {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            ..row
+        };
+        inserter
+            .insert_with_retries(&Batch {
+                table: "routes".into(),
+                rows: vec![row],
+            })
+            .await;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        let query = requests[0].uri.query().unwrap();
+        let query = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            query["query"],
+            "INSERT INTO `bgp`.`routes` FORMAT JSONEachRow"
+        );
+        assert_eq!(query["async_insert"], "1");
+        assert_eq!(query["input_format_skip_unknown_fields"], "1");
+
+        let row = requests[0].json();
+        assert_eq!(row["ts"], "2023-11-14T22:13:20.000000Z");
+        assert_eq!(row["prefix"], "192.0.2.0/24");
+        assert_eq!(row["as_path"], json!([65001]));
+        assert_eq!(row["communities"], json!(["AS65001:7"]));
+        assert_eq!(row["peer_ip"], serde_json::Value::Null);
+        assert!(row.get("custom").is_none());
+        assert_eq!(metrics.inserted_row_count.load(SeqCst), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_inserts_are_retried() {
+        let server = MockServer::with_statuses(vec![500, 503], 200).await;
+        let config =
+            mk_config(server.addr, "retry_delay_secs = 0\nmax_retries = 2");
+        let (inserter, metrics) = mk_inserter(&config);
+        let batch = Batch {
+            table: "routes".into(),
+            rows: vec![Row::default()],
+        };
+
+        inserter.insert_with_retries(&batch).await;
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(metrics.insert_error_count.load(SeqCst), 2);
+        assert_eq!(metrics.insert_count.load(SeqCst), 1);
+
+        // Without retries left, the rows are dropped.
+        let server = MockServer::with_statuses(vec![500], 200).await;
+        let config = mk_config(server.addr, "max_retries = 0");
+        let (inserter, metrics) = mk_inserter(&config);
+        inserter.insert_with_retries(&batch).await;
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(metrics.dropped_row_count.load(SeqCst), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_inserts_are_spooled() {
+        let server = MockServer::with_statuses(vec![500], 200).await;
+        let config = mk_config(server.addr, "max_retries = 0");
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-clickhouse-{}", uuid::Uuid::new_v4()));
+        let spool = Arc::new(Spool::open(&dir, "clickhouse".into()).unwrap());
+        let (mut inserter, metrics) = mk_inserter(&config);
+        inserter.spool = Some(spool.clone());
+        let batch = Batch {
+            table: "routes".into(),
+            rows: vec![Row::default()],
+        };
+        inserter.insert_with_retries(&batch).await;
+        assert_eq!(metrics.dropped_row_count.load(SeqCst), 0);
+
+        // Replaying inserts the same rows into the same table.
+        let (tx, rx) =
+            Buffer::open("clickhouse".into(), 1, &BufferConfig::default())
+                .unwrap();
+        let task = tokio::spawn(inserter.run(rx));
+        tokio::task::yield_now().await;
+        spool.request_replay();
+        while std::fs::read_dir(dir.join("clickhouse")).unwrap().count() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(tx);
+        task.await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].uri, requests[1].uri);
+        assert_eq!(requests[0].body, requests[1].body);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,468 @@
+//! Spooling what targets could not deliver.
+//!
+//! Targets that give up on delivering something after their retries are
+//! exhausted can put it into a dead-letter spool instead of dropping it.
+//! The spool is enabled by setting `dead_letter_dir` in the configuration
+//! of such a target. Each undelivered payload is written to a file of its
+//! own in the `<dead_letter_dir>/<target name>/` directory, holding a JSON
+//! object like this:
+//!
+//! ```json
+//! {
+//!   "timestamp": "2024-11-21T12:00:00.000000Z",
+//!   "target": "clickhouse",
+//!   "reason": "503 Service Unavailable",
+//!   "key": "rotonda_routes",
+//!   "body": "{\"timestamp\":\"2024-11-21T11:59:59.000000Z\",...}\n"
+//! }
+//! ```
+//!
+//! The body is what the target failed to send, the key what it was meant
+//! for, such as a table or an endpoint, depending on the target.
+//!
+//! The spool of a target is listed at `/dead-letters/<target name>/` of the
+//! HTTP API, and replayed with `/dead-letters/<target name>/replay`.
+//! Replaying sends the payloads again, oldest first, removing each one that
+//! was delivered, until one fails. Files can be edited or removed before
+//! replaying them.
+
+use std::{
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::{
+    common::openapi,
+    http::{PercentDecodedPath, ProcessRequest},
+    manager::Component,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+//------------ DeadLetterConfig ----------------------------------------------
+
+/// The spool settings of a target, flattened into its configuration.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DeadLetterConfig {
+    /// The directory to spool what could not be delivered to.
+    #[serde(default)]
+    pub dead_letter_dir: Option<PathBuf>,
+}
+
+//------------ DeadLetter ----------------------------------------------------
+
+/// Something a target could not deliver.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DeadLetter {
+    pub timestamp: DateTime<Utc>,
+    pub target: String,
+    pub reason: String,
+
+    /// What the body was meant for, depending on the target.
+    #[serde(default)]
+    pub key: String,
+    pub body: String,
+}
+
+//------------ Spool ---------------------------------------------------------
+
+/// The dead-letter spool of a target.
+pub struct Spool {
+    target: Arc<str>,
+    dir: PathBuf,
+    http_api_path: String,
+
+    /// Signals that replaying was requested.
+    replay: watch::Sender<()>,
+
+    /// The number of the next letter, keeping the order of letters spooled
+    /// at the same time.
+    sequence: AtomicU64,
+
+    pending_count: AtomicUsize,
+    spooled_count: AtomicUsize,
+    replayed_count: AtomicUsize,
+    error_count: AtomicUsize,
+}
+
+impl Spool {
+    /// Creates the spool of a target, if it has one.
+    ///
+    /// The spool registers its metrics and HTTP resource with the
+    /// component.
+    pub fn new(
+        config: &DeadLetterConfig,
+        component: &mut Component,
+    ) -> Result<Option<Arc<Self>>, String> {
+        let Some(dir) = &config.dead_letter_dir else {
+            return Ok(None);
+        };
+        let res = Arc::new(Self::open(dir, component.name().clone())?);
+        component.register_metrics(res.clone());
+        component.register_http_resource(res.clone(), &res.http_api_path);
+        Ok(Some(res))
+    }
+
+    /// Opens the spool of a target in the given directory.
+    pub fn open(dir: &Path, target: Arc<str>) -> Result<Self, String> {
+        let dir = dir.join(&*target);
+        let pending = std::fs::create_dir_all(&dir)
+            .and_then(|_| files(&dir))
+            .map_err(|err| {
+                format!(
+                    "cannot use dead letter directory {}: {err}",
+                    dir.display()
+                )
+            })?;
+        Ok(Self {
+            http_api_path: format!("/dead-letters/{target}/"),
+            target,
+            dir,
+            replay: watch::Sender::new(()),
+            sequence: Default::default(),
+            pending_count: pending.len().into(),
+            spooled_count: Default::default(),
+            replayed_count: Default::default(),
+            error_count: Default::default(),
+        })
+    }
+
+    /// Spools a body that could not be delivered.
+    ///
+    /// Returns whether it was spooled. If not, the error is logged.
+    pub async fn put(&self, key: &str, reason: &str, body: String) -> bool {
+        let letter = DeadLetter {
+            timestamp: Utc::now(),
+            target: self.target.to_string(),
+            reason: reason.into(),
+            key: key.into(),
+            body,
+        };
+        // The names sort in the order the letters were spooled. Writing to
+        // a hidden file first keeps replays from seeing partial letters.
+        let name = format!(
+            "{}-{:010}.json",
+            letter.timestamp.format("%Y%m%dT%H%M%S%.6fZ"),
+            self.sequence.fetch_add(1, SeqCst)
+        );
+        let path = self.dir.join(&name);
+        let tmp = self.dir.join(format!(".{name}"));
+        let res = async {
+            let json =
+                serde_json::to_vec(&letter).map_err(io::Error::other)?;
+            tokio::fs::write(&tmp, json).await?;
+            tokio::fs::rename(&tmp, &path).await
+        };
+        match res.await {
+            Ok(()) => {
+                self.spooled_count.fetch_add(1, SeqCst);
+                self.pending_count.fetch_add(1, SeqCst);
+                true
+            }
+            Err(err) => {
+                self.error_count.fetch_add(1, SeqCst);
+                error!(
+                    "Target {}: cannot write dead letter {}: {err}",
+                    self.target,
+                    path.display()
+                );
+                false
+            }
+        }
+    }
+
+    /// Returns a receiver that changes whenever replaying is requested.
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.replay.subscribe()
+    }
+
+    /// Requests the letters to be replayed.
+    pub fn request_replay(&self) {
+        self.replay.send_replace(());
+    }
+
+    /// Replays the letters with the given key, or all if it is `None`.
+    ///
+    /// Letters are handed to `deliver` oldest first and removed once
+    /// delivered. Replaying stops at the first letter that fails.
+    pub async fn replay<F, Fut>(&self, key: Option<&str>, mut deliver: F)
+    where
+        F: FnMut(DeadLetter) -> Fut,
+        Fut: Future<Output = Result<(), String>>,
+    {
+        let paths = match files(&self.dir) {
+            Ok(paths) => paths,
+            Err(err) => {
+                error!(
+                    "Target {}: cannot read dead letters in {}: {err}",
+                    self.target,
+                    self.dir.display()
+                );
+                return;
+            }
+        };
+        let mut count = 0;
+        for path in paths {
+            let letter = match read(&path).await {
+                Ok(letter) => letter,
+                Err(err) => {
+                    warn!(
+                        "Target {}: skipping dead letter {}: {err}",
+                        self.target,
+                        path.display()
+                    );
+                    continue;
+                }
+            };
+            if key.is_some_and(|key| key != letter.key) {
+                continue;
+            }
+            if let Err(err) = deliver(letter).await {
+                warn!(
+                    "Target {}: replaying dead letter {} failed: {err}",
+                    self.target,
+                    path.display()
+                );
+                break;
+            }
+            if let Err(err) = tokio::fs::remove_file(&path).await {
+                error!(
+                    "Target {}: cannot remove dead letter {}: {err}",
+                    self.target,
+                    path.display()
+                );
+                break;
+            }
+            self.pending_count.fetch_sub(1, SeqCst);
+            self.replayed_count.fetch_add(1, SeqCst);
+            count += 1;
+        }
+        info!("Target {}: replayed {count} dead letters", self.target);
+    }
+
+    /// Returns a listing of the letters without their bodies.
+    async fn list(&self) -> Result<serde_json::Value, String> {
+        let paths = files(&self.dir).map_err(|err| err.to_string())?;
+        let mut letters = Vec::new();
+        for path in paths {
+            let Ok(letter) = read(&path).await else {
+                continue;
+            };
+            letters.push(serde_json::json!({
+                "file": path.file_name().map(|name| name.to_string_lossy()),
+                "timestamp": letter
+                    .timestamp
+                    .to_rfc3339_opts(SecondsFormat::Micros, true),
+                "reason": letter.reason,
+                "key": letter.key,
+                "size": letter.body.len(),
+            }));
+        }
+        Ok(letters.into())
+    }
+}
+
+/// Waits until replaying is requested, forever if there is no spool.
+///
+/// The receiver is the one returned by [`Spool::subscribe`].
+pub async fn replay_requested(replays: &mut Option<watch::Receiver<()>>) {
+    if let Some(replays) = replays {
+        if replays.changed().await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+/// Returns the paths of the letters in a directory, oldest first.
+fn files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut res = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if !name.starts_with('.') && name.ends_with(".json") {
+            res.push(path);
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+async fn read(path: &Path) -> io::Result<DeadLetter> {
+    let json = tokio::fs::read(path).await?;
+    serde_json::from_slice(&json).map_err(io::Error::other)
+}
+
+//--- ProcessRequest
+
+#[async_trait]
+impl ProcessRequest for Spool {
+    async fn process_request(
+        &self,
+        request: &Request<Body>,
+    ) -> Option<Response<Body>> {
+        if request.method() != Method::GET {
+            return None;
+        }
+        let path = request.uri().decoded_path();
+        let (status, content_type, body) =
+            match path.strip_prefix(&self.http_api_path)? {
+                "" => match self.list().await {
+                    Ok(list) => {
+                        (StatusCode::OK, "application/json", list.to_string())
+                    }
+                    Err(err) => {
+                        (StatusCode::INTERNAL_SERVER_ERROR, "text/plain", err)
+                    }
+                },
+                "replay" => {
+                    self.request_replay();
+                    (
+                        StatusCode::OK,
+                        "text/plain",
+                        format!(
+                            "replaying {} dead letters",
+                            self.pending_count.load(SeqCst)
+                        ),
+                    )
+                }
+                _ => return None,
+            };
+        Some(
+            Response::builder()
+                .status(status)
+                .header("Content-Type", content_type)
+                .body(body.into())
+                .unwrap(),
+        )
+    }
+
+    fn describe(&self, paths: &mut openapi::Paths) {
+        paths.get(
+            self.http_api_path.as_str(),
+            "The dead letters waiting to be replayed",
+        );
+        paths
+            .get(
+                format!("{}replay", self.http_api_path),
+                "Replay the dead letters",
+            )
+            .produces("text/plain");
+    }
+}
+
+//--- Metrics
+
+impl Spool {
+    const PENDING_COUNT_METRIC: Metric = Metric::new(
+        "dead_letter_pending_count",
+        "the number of dead letters waiting to be replayed",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const SPOOLED_COUNT_METRIC: Metric = Metric::new(
+        "dead_letter_spooled_count",
+        "the number of undelivered payloads written to the spool",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const REPLAYED_COUNT_METRIC: Metric = Metric::new(
+        "dead_letter_replayed_count",
+        "the number of dead letters delivered when replaying",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const ERROR_COUNT_METRIC: Metric = Metric::new(
+        "dead_letter_error_count",
+        "the number of undelivered payloads that could not be spooled",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for Spool {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::PENDING_COUNT_METRIC,
+            Some(unit_name),
+            self.pending_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::SPOOLED_COUNT_METRIC,
+            Some(unit_name),
+            self.spooled_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::REPLAYED_COUNT_METRIC,
+            Some(unit_name),
+            self.replayed_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.error_count.load(SeqCst),
+        );
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn letters_are_spooled_and_replayed() {
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-dead-letter-{}", uuid::Uuid::new_v4()));
+        let spool = Spool::open(&dir, "ch".into()).unwrap();
+        for (key, body) in [("a", "1"), ("b", "2"), ("a", "3"), ("a", "4")] {
+            assert!(spool.put(key, "refused", body.into()).await);
+        }
+        let replays = spool.subscribe();
+        spool.request_replay();
+        assert!(replays.has_changed().unwrap());
+
+        // The letters of a key are delivered in order, until one fails.
+        let mut delivered = Vec::new();
+        spool
+            .replay(Some("a"), |letter| {
+                let res = match letter.body.as_str() {
+                    "3" => Err("refused again".into()),
+                    body => {
+                        delivered.push(body.to_string());
+                        Ok(())
+                    }
+                };
+                async { res }
+            })
+            .await;
+        assert_eq!(delivered, ["1"]);
+        assert_eq!(spool.pending_count.load(SeqCst), 3);
+
+        let mut delivered = Vec::new();
+        spool
+            .replay(None, |letter| {
+                assert_eq!(letter.target, "ch");
+                assert_eq!(letter.reason, "refused");
+                delivered.push(letter.body);
+                async { Ok(()) }
+            })
+            .await;
+        assert_eq!(delivered, ["2", "3", "4"]);
+        assert!(files(&dir.join("ch")).unwrap().is_empty());
+        assert_eq!(spool.replayed_count.load(SeqCst), 4);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
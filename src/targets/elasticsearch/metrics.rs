@@ -0,0 +1,128 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct ElasticsearchMetrics {
+    pub bulk_count: AtomicUsize,
+    pub bulk_error_count: AtomicUsize,
+    pub indexed_document_count: AtomicUsize,
+    pub rejected_document_count: AtomicUsize,
+    pub dropped_document_count: AtomicUsize,
+    pub pending_batch_count: AtomicUsize,
+    pub last_bulk_duration_ms: AtomicU64,
+    pub bulk_duration_ms: AtomicU64,
+}
+
+impl GraphStatus for ElasticsearchMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "indexed: {}\npending: {}\nerrors: {}\nrejected: {}\ndropped: {}",
+            self.indexed_document_count.load(SeqCst),
+            self.pending_batch_count.load(SeqCst),
+            self.bulk_error_count.load(SeqCst),
+            self.rejected_document_count.load(SeqCst),
+            self.dropped_document_count.load(SeqCst),
+        )
+    }
+}
+
+impl ElasticsearchMetrics {
+    const BULK_COUNT_METRIC: Metric = Metric::new(
+        "elasticsearch_target_bulk_count",
+        "the number of successful bulk requests",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const BULK_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "elasticsearch_target_bulk_error_count",
+        "the number of bulk requests that failed, including retries",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const INDEXED_DOCUMENT_COUNT_METRIC: Metric = Metric::new(
+        "elasticsearch_target_indexed_document_count",
+        "the number of documents indexed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const REJECTED_DOCUMENT_COUNT_METRIC: Metric = Metric::new(
+        "elasticsearch_target_rejected_document_count",
+        "the number of documents rejected by the server",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DROPPED_DOCUMENT_COUNT_METRIC: Metric = Metric::new(
+        "elasticsearch_target_dropped_document_count",
+        "the number of documents dropped after failing to index them or \
+        because too many batches were waiting",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const PENDING_BATCH_COUNT_METRIC: Metric = Metric::new(
+        "elasticsearch_target_pending_batch_count",
+        "the number of batches waiting to be indexed",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const LAST_BULK_DURATION_METRIC: Metric = Metric::new(
+        "elasticsearch_target_last_bulk_duration",
+        "how long the last successful bulk request took",
+        MetricType::Gauge,
+        MetricUnit::Millisecond,
+    );
+    const BULK_DURATION_METRIC: Metric = Metric::new(
+        "elasticsearch_target_bulk_duration",
+        "how long all successful bulk requests took together",
+        MetricType::Counter,
+        MetricUnit::Millisecond,
+    );
+}
+
+impl metrics::Source for ElasticsearchMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::BULK_COUNT_METRIC,
+            Some(unit_name),
+            self.bulk_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::BULK_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.bulk_error_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::INDEXED_DOCUMENT_COUNT_METRIC,
+            Some(unit_name),
+            self.indexed_document_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::REJECTED_DOCUMENT_COUNT_METRIC,
+            Some(unit_name),
+            self.rejected_document_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DROPPED_DOCUMENT_COUNT_METRIC,
+            Some(unit_name),
+            self.dropped_document_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::PENDING_BATCH_COUNT_METRIC,
+            Some(unit_name),
+            self.pending_batch_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::LAST_BULK_DURATION_METRIC,
+            Some(unit_name),
+            self.last_bulk_duration_ms.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::BULK_DURATION_METRIC,
+            Some(unit_name),
+            self.bulk_duration_ms.load(SeqCst),
+        );
+    }
+}
@@ -0,0 +1,1155 @@
+//! Indexing routes and BMP events in Elasticsearch or OpenSearch.
+//!
+//! The `elasticsearch-out` target turns each route and event it receives
+//! into a document with the columns described in the [`row`] module, the
+//! timestamp being named `@timestamp` as Kibana and OpenSearch Dashboards
+//! expect. Columns without a value are left out. Routes are indexed in
+//! `<index_prefix>-routes-<date>`, all other messages, such as peers going
+//! down and log entries, in `<index_prefix>-events-<date>`, the date being
+//! the day of the timestamp, e.g. `rotonda-routes-2024.11.21`.
+//!
+//! At start, an index template for `<index_prefix>-*` is installed, with
+//! mappings derived from the columns, or the template in `template_file`
+//! when given. Setting `install_template` to false leaves the templates to
+//! the administrator.
+//!
+//! Documents are collected into batches of up to `batch_size` documents,
+//! each batch being sent after at most `batch_interval_secs` through the
+//! bulk API. A failed request is tried again up to `max_retries` times, with
+//! an exponential backoff, as are documents the server rejects because it is
+//! too busy. Documents it rejects for any other reason, such as a mapping
+//! conflict, are appended to the `dead_letter_file` along with the error,
+//! or logged without one. Meanwhile, up to `max_pending_batches` batches
+//! wait for their turn; documents beyond that, or in a batch that could not
+//! be sent at all, are dropped.
+//!
+//! With a `dead_letter_dir`, batches that could not be sent at all are kept
+//! in the [dead-letter spool] instead, and sent again when a replay is
+//! requested through the HTTP API. Unlike the `dead_letter_file`, which
+//! records documents the server will never accept, the spool holds
+//! documents that are expected to succeed once the cluster is back.
+//!
+//! The waiting batches can spill to disk and their sending be rate limited,
+//! as described in the [`buffer`] module, so that a slow cluster does not
+//! cost documents.
+//!
+//! The cluster may be reached over HTTPS. The CA certificates of the
+//! system are used to check its certificate unless others are given in the
+//! `tls` table.
+//!
+//! [`row`]: crate::targets::file::row
+//! [dead-letter spool]: crate::targets::dead_letter
+//! [`buffer`]: crate::targets::buffer
+
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_with::serde_as;
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use url::Url;
+
+use crate::{
+    common::tls::TlsClientConfig,
+    comms::{Link, Terminated, UnitStatus},
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    payload::Update,
+    targets::{
+        buffer::{Buffer, BufferConfig, BufferReceiver},
+        dead_letter::{replay_requested, DeadLetterConfig, Spool},
+        file::row::{ColumnType, Row, Value, COLUMNS},
+    },
+};
+
+use super::metrics::ElasticsearchMetrics;
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct Elasticsearch {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The URL of the cluster, e.g. `http://localhost:9200/`.
+    pub url: Url,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// The TLS settings for an HTTPS URL.
+    #[serde(default)]
+    pub tls: Option<TlsClientConfig>,
+
+    /// The start of the names of the indexes.
+    #[serde(default = "Config::default_index_prefix")]
+    pub index_prefix: String,
+
+    /// Whether to install an index template at start.
+    #[serde(default = "Config::default_install_template")]
+    pub install_template: bool,
+
+    /// A file with the index template to install instead of the default.
+    #[serde(default)]
+    pub template_file: Option<PathBuf>,
+
+    /// A file to append the documents rejected by the server to.
+    #[serde(default)]
+    pub dead_letter_file: Option<PathBuf>,
+
+    /// The largest number of documents to send at a time.
+    #[serde(default = "Config::default_batch_size")]
+    pub batch_size: usize,
+
+    /// The longest time documents wait before they are sent.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_batch_interval_secs")]
+    pub batch_interval_secs: Duration,
+
+    /// How often to try a failed request again.
+    #[serde(default = "Config::default_max_retries")]
+    pub max_retries: usize,
+
+    /// How long to wait before the first retry. The delay doubles with
+    /// every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_retry_delay_secs")]
+    pub retry_delay_secs: Duration,
+
+    /// The longest to wait before retrying.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_max_retry_delay_secs")]
+    pub max_retry_delay_secs: Duration,
+
+    /// How many batches may wait to be sent.
+    #[serde(default = "Config::default_max_pending_batches")]
+    pub max_pending_batches: usize,
+
+    #[serde(flatten)]
+    pub buffer: BufferConfig,
+
+    #[serde(flatten)]
+    pub dead_letter: DeadLetterConfig,
+}
+
+impl Config {
+    fn default_index_prefix() -> String {
+        "rotonda".into()
+    }
+
+    fn default_install_template() -> bool {
+        true
+    }
+
+    fn default_batch_size() -> usize {
+        1000
+    }
+
+    fn default_batch_interval_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retries() -> usize {
+        5
+    }
+
+    fn default_retry_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retry_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_max_pending_batches() -> usize {
+        16
+    }
+
+    /// Checks the settings, returning what is wrong with them.
+    fn check(&self) -> Result<(), String> {
+        if !matches!(self.url.scheme(), "http" | "https") {
+            return Err(format!(
+                "unsupported URL scheme '{}', use http or https",
+                self.url.scheme()
+            ));
+        }
+        // Index names must be lowercase and cannot contain these.
+        if self.index_prefix.is_empty()
+            || self
+                .index_prefix
+                .chars()
+                .any(|c| c.is_uppercase() || "\\/*?\"<>| ,#:".contains(c))
+        {
+            return Err(format!(
+                "invalid index_prefix '{}'",
+                self.index_prefix
+            ));
+        }
+        if self.batch_size == 0 || self.max_pending_batches == 0 {
+            return Err(
+                "batch_size and max_pending_batches must be at least 1"
+                    .into(),
+            );
+        }
+        self.buffer.check()
+    }
+
+    /// Returns the client for the cluster, `shared` unless there are TLS
+    /// settings.
+    fn client(&self, shared: HttpClient) -> Result<HttpClient, String> {
+        match &self.tls {
+            Some(tls) => tls
+                .http_client_builder()?
+                .build()
+                .map_err(|err| err.to_string()),
+            None => Ok(shared),
+        }
+    }
+
+    /// Returns the index template to install.
+    fn template(&self) -> Result<serde_json::Value, String> {
+        if let Some(path) = &self.template_file {
+            let content = std::fs::read_to_string(path).map_err(|err| {
+                format!("cannot read {}: {err}", path.display())
+            })?;
+            return serde_json::from_str(&content).map_err(|err| {
+                format!("invalid template in {}: {err}", path.display())
+            });
+        }
+        let properties = COLUMNS
+            .iter()
+            .map(|column| {
+                let field_type = match (column.name, column.column_type) {
+                    ("peer_ip", _) => "ip",
+                    ("custom", _) => "text",
+                    (_, ColumnType::Timestamp) => "date",
+                    (_, ColumnType::Long | ColumnType::LongList) => "long",
+                    (_, ColumnType::String | ColumnType::StringList) => {
+                        "keyword"
+                    }
+                };
+                (field_name(column.name).into(), json!({"type": field_type}))
+            })
+            .collect::<serde_json::Map<_, _>>();
+        Ok(json!({
+            "index_patterns": [format!("{}-*", self.index_prefix)],
+            "template": {
+                "mappings": {
+                    "properties": properties,
+                },
+            },
+        }))
+    }
+}
+
+/// Returns the name of the document field holding a column.
+fn field_name(column: &str) -> &str {
+    match column {
+        "timestamp" => "@timestamp",
+        _ => column,
+    }
+}
+
+impl Elasticsearch {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let template = match self.config.check().and_then(|_| {
+            self.config
+                .install_template
+                .then(|| self.config.template())
+                .transpose()
+        }) {
+            Ok(template) => template,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+
+        let http = match self.config.client(component.http_client().clone()) {
+            Ok(http) => http,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+
+        let spool = match Spool::new(&self.config.dead_letter, &mut component)
+        {
+            Ok(spool) => spool,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+        let (buffer, batch_rx) = match Buffer::new(
+            component.name().to_string(),
+            self.config.max_pending_batches,
+            &self.config.buffer,
+            &mut component,
+        ) {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+        let metrics = Arc::new(ElasticsearchMetrics::default());
+        metrics
+            .pending_batch_count
+            .store(buffer.pending_count(), SeqCst);
+        component.register_metrics(metrics.clone());
+        let indexer = Indexer::new(
+            component.name().to_string(),
+            http,
+            &self.config,
+            spool,
+            metrics.clone(),
+        );
+        let batcher =
+            Batcher::new(&self.config, component.ingresses().clone());
+        ElasticsearchRunner {
+            batcher,
+            indexer,
+            template,
+            metrics,
+            buffer,
+            batch_rx,
+            batch_interval: self.config.batch_interval_secs,
+        }
+        .run(self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ ElasticsearchRunner -------------------------------------------
+
+struct ElasticsearchRunner {
+    batcher: Batcher,
+    indexer: Indexer,
+    template: Option<serde_json::Value>,
+    metrics: Arc<ElasticsearchMetrics>,
+    buffer: Buffer<Vec<Document>>,
+    batch_rx: BufferReceiver<Vec<Document>>,
+    batch_interval: Duration,
+}
+
+impl ElasticsearchRunner {
+    async fn run(
+        self,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let Self {
+            mut batcher,
+            indexer,
+            template,
+            metrics,
+            buffer,
+            batch_rx,
+            batch_interval,
+        } = self;
+
+        // Batches are sent by a task of their own, so that a slow or
+        // unavailable cluster does not hold up the sources.
+        let index_task = tokio::spawn(indexer.run(template, batch_rx));
+        let send = |batch: Vec<Document>| {
+            let (buffer, metrics) = (&buffer, &metrics);
+            async move {
+                match buffer.push(batch).await {
+                    Ok(()) => {
+                        metrics.pending_batch_count.fetch_add(1, SeqCst);
+                    }
+                    Err(batch) => {
+                        warn!(
+                            "Dropping {} documents: too many batches are \
+                            waiting to be indexed",
+                            batch.len()
+                        );
+                        metrics
+                            .dropped_document_count
+                            .fetch_add(batch.len(), SeqCst);
+                    }
+                }
+            }
+        };
+
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        let mut flush = tokio::time::interval(batch_interval);
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the elasticsearch-out target \
+                            requires a restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        if let Some(batch) = batcher.push(update) {
+                            send(batch).await;
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of elasticsearch-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+
+                _ = flush.tick() => {
+                    if let Some(batch) = batcher.take() {
+                        send(batch).await;
+                    }
+                }
+            }
+        }
+
+        // Index what is left before stopping.
+        if let Some(batch) = batcher.take() {
+            send(batch).await;
+        }
+        drop(buffer);
+        let _ = index_task.await;
+        Err(Terminated)
+    }
+}
+
+//------------ Batcher -------------------------------------------------------
+
+/// A document and the index it goes into.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Document {
+    index: String,
+    source: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Document {
+    fn new(index_prefix: &str, row: &Row) -> Self {
+        let category = match row.kind {
+            "route" | "announce" | "withdraw" => "routes",
+            _ => "events",
+        };
+        let index = format!(
+            "{index_prefix}-{category}-{}",
+            row.timestamp.format("%Y.%m.%d")
+        );
+        let source = COLUMNS
+            .iter()
+            .enumerate()
+            .filter_map(|(index, column)| match row.value(index) {
+                Value::Null => None,
+                value => Some((
+                    field_name(column.name).to_string(),
+                    value.to_json(),
+                )),
+            })
+            .collect();
+        Self { index, source }
+    }
+}
+
+/// Collects the documents of the updates into batches.
+struct Batcher {
+    ingresses: Arc<ingress::Register>,
+    index_prefix: String,
+    batch_size: usize,
+    documents: Vec<Document>,
+}
+
+impl Batcher {
+    fn new(config: &Config, ingresses: Arc<ingress::Register>) -> Self {
+        Self {
+            ingresses,
+            index_prefix: config.index_prefix.clone(),
+            batch_size: config.batch_size,
+            documents: Vec::new(),
+        }
+    }
+
+    /// Adds the documents of an update, returning a batch once it is full.
+    fn push(&mut self, update: Update) -> Option<Vec<Document>> {
+        let rows = Row::for_update(update, &self.ingresses);
+        self.documents.extend(
+            rows.iter()
+                .map(|row| Document::new(&self.index_prefix, row)),
+        );
+        if self.documents.len() < self.batch_size {
+            return None;
+        }
+        self.take()
+    }
+
+    /// Takes the documents collected so far.
+    fn take(&mut self) -> Option<Vec<Document>> {
+        if self.documents.is_empty() {
+            return None;
+        }
+        Some(std::mem::take(&mut self.documents))
+    }
+}
+
+//------------ Indexer -------------------------------------------------------
+
+/// What became of a document in a bulk request.
+#[derive(Debug)]
+enum Outcome {
+    Indexed,
+
+    /// The server was too busy to index the document.
+    Retry,
+
+    /// The server refused the document, for the given reason.
+    Rejected(serde_json::Value),
+}
+
+/// Sends batches to the bulk API.
+struct Indexer {
+    name: String,
+    http: HttpClient,
+    url: Url,
+    username: Option<String>,
+    password: Option<String>,
+    template_name: String,
+    dead_letter_file: Option<PathBuf>,
+    max_retries: usize,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+
+    /// Where to put the documents that could not be sent, if anywhere.
+    spool: Option<Arc<Spool>>,
+    metrics: Arc<ElasticsearchMetrics>,
+}
+
+impl Indexer {
+    fn new(
+        name: String,
+        http: HttpClient,
+        config: &Config,
+        spool: Option<Arc<Spool>>,
+        metrics: Arc<ElasticsearchMetrics>,
+    ) -> Self {
+        Self {
+            name,
+            http,
+            url: config.url.clone(),
+            username: config.username.clone(),
+            password: config.password.clone(),
+            template_name: config.index_prefix.clone(),
+            dead_letter_file: config.dead_letter_file.clone(),
+            max_retries: config.max_retries,
+            retry_delay: config.retry_delay_secs,
+            max_retry_delay: config.max_retry_delay_secs,
+            spool,
+            metrics,
+        }
+    }
+
+    /// Installs the template, then indexes batches until there are no more.
+    ///
+    /// Spooled requests are sent again when asked to.
+    async fn run(
+        self,
+        template: Option<serde_json::Value>,
+        mut batch_rx: BufferReceiver<Vec<Document>>,
+    ) {
+        if let Some(template) = template {
+            match self.install_template(&template).await {
+                Ok(()) => info!(
+                    "Target {}: installed index template {}",
+                    self.name, self.template_name
+                ),
+                Err(err) => warn!(
+                    "Target {}: installing index template {} failed: {err}",
+                    self.name, self.template_name
+                ),
+            }
+        }
+        let mut replays = self.spool.as_ref().map(|spool| spool.subscribe());
+        loop {
+            tokio::select! {
+                batch = batch_rx.recv() => match batch {
+                    Some(batch) => {
+                        self.metrics.pending_batch_count.fetch_sub(1, SeqCst);
+                        self.index_with_retries(batch).await;
+                        batch_rx.delivered().await;
+                    }
+                    None => break,
+                },
+                _ = replay_requested(&mut replays) => {
+                    if let Some(spool) = &self.spool {
+                        let this = &self;
+                        spool
+                            .replay(None, |letter| this.replay(letter.body))
+                            .await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn install_template(
+        &self,
+        template: &serde_json::Value,
+    ) -> Result<(), String> {
+        let url = self
+            .url
+            .join(&format!("_index_template/{}", self.template_name))
+            .map_err(|err| err.to_string())?;
+        let request = self
+            .auth(self.http.put(url))
+            .header("Content-Type", "application/json")
+            .body(template.to_string());
+        self.send(request).await.map(|_| ())
+    }
+
+    async fn index_with_retries(&self, mut documents: Vec<Document>) {
+        let mut reason = String::new();
+        let mut delay = self.retry_delay;
+        for attempt in 0..=self.max_retries {
+            let started = Instant::now();
+            match self.bulk(&documents).await {
+                Ok(outcomes) => {
+                    let duration = started.elapsed().as_millis() as u64;
+                    let metrics = &self.metrics;
+                    metrics.bulk_count.fetch_add(1, SeqCst);
+                    metrics.last_bulk_duration_ms.store(duration, SeqCst);
+                    metrics.bulk_duration_ms.fetch_add(duration, SeqCst);
+
+                    let mut retry = Vec::new();
+                    let mut rejected = Vec::new();
+                    for (document, outcome) in
+                        documents.into_iter().zip(outcomes)
+                    {
+                        match outcome {
+                            Outcome::Indexed => {
+                                metrics
+                                    .indexed_document_count
+                                    .fetch_add(1, SeqCst);
+                            }
+                            Outcome::Retry => retry.push(document),
+                            Outcome::Rejected(err) => {
+                                rejected.push((document, err))
+                            }
+                        }
+                    }
+                    if !rejected.is_empty() {
+                        metrics
+                            .rejected_document_count
+                            .fetch_add(rejected.len(), SeqCst);
+                        self.dead_letter(rejected).await;
+                    }
+                    if retry.is_empty() {
+                        return;
+                    }
+                    warn!(
+                        "Target {}: {} documents were not indexed as the \
+                        cluster is busy",
+                        self.name,
+                        retry.len()
+                    );
+                    documents = retry;
+                    reason = "the cluster is busy".into();
+                }
+                Err(err) => {
+                    self.metrics.bulk_error_count.fetch_add(1, SeqCst);
+                    warn!("Target {}: bulk request failed: {err}", self.name);
+                    reason = err;
+                }
+            }
+            if attempt < self.max_retries {
+                info!(
+                    "Target {}: retrying in {}s",
+                    self.name,
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(self.max_retry_delay);
+            }
+        }
+        if let Some(spool) = &self.spool {
+            let body = Self::bulk_body(&documents);
+            if spool.put("", &reason, body).await {
+                warn!(
+                    "Target {}: spooled {} documents after {} attempts",
+                    self.name,
+                    documents.len(),
+                    self.max_retries + 1
+                );
+                return;
+            }
+        }
+        error!(
+            "Target {}: dropping {} documents after {} attempts",
+            self.name,
+            documents.len(),
+            self.max_retries + 1
+        );
+        self.metrics
+            .dropped_document_count
+            .fetch_add(documents.len(), SeqCst);
+    }
+
+    /// Returns the body of a bulk request indexing documents.
+    fn bulk_body(documents: &[Document]) -> String {
+        let mut body = String::new();
+        for document in documents {
+            body.push_str(
+                &json!({"index": {"_index": document.index}}).to_string(),
+            );
+            body.push('\n');
+            body.push_str(
+                &serde_json::Value::Object(document.source.clone())
+                    .to_string(),
+            );
+            body.push('\n');
+        }
+        body
+    }
+
+    /// Sends a spooled bulk request again.
+    ///
+    /// Documents the server rejects are logged. As only the request as a
+    /// whole can be replayed again, documents the cluster is too busy for
+    /// fail the replay.
+    async fn replay(&self, body: String) -> Result<(), String> {
+        let count = body.lines().count() / 2;
+        let outcomes = self.send_bulk(body, count).await?;
+        let mut busy = false;
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Indexed => {
+                    self.metrics.indexed_document_count.fetch_add(1, SeqCst);
+                }
+                Outcome::Retry => busy = true,
+                Outcome::Rejected(err) => {
+                    self.metrics
+                        .rejected_document_count
+                        .fetch_add(1, SeqCst);
+                    warn!(
+                        "Target {}: replayed document rejected: {err}",
+                        self.name
+                    );
+                }
+            }
+        }
+        if busy {
+            return Err("the cluster is busy".into());
+        }
+        Ok(())
+    }
+
+    /// Makes a single bulk request, returning the outcome per document.
+    async fn bulk(
+        &self,
+        documents: &[Document],
+    ) -> Result<Vec<Outcome>, String> {
+        self.send_bulk(Self::bulk_body(documents), documents.len())
+            .await
+    }
+
+    /// Sends the body of a bulk request for `count` documents.
+    async fn send_bulk(
+        &self,
+        body: String,
+        count: usize,
+    ) -> Result<Vec<Outcome>, String> {
+        let url = self.url.join("_bulk").map_err(|err| err.to_string())?;
+        let request = self
+            .auth(self.http.post(url))
+            .header("Content-Type", "application/x-ndjson")
+            .body(body);
+        let response: serde_json::Value =
+            serde_json::from_str(&self.send(request).await?)
+                .map_err(|err| format!("invalid response: {err}"))?;
+
+        if response["errors"] == false {
+            return Ok((0..count).map(|_| Outcome::Indexed).collect());
+        }
+        let items = response["items"]
+            .as_array()
+            .filter(|items| items.len() == count)
+            .ok_or("invalid response: wrong number of items")?;
+        Ok(items
+            .iter()
+            .map(|item| {
+                let result = item
+                    .as_object()
+                    .and_then(|item| item.values().next())
+                    .unwrap_or(&serde_json::Value::Null);
+                match result["status"].as_u64() {
+                    Some(200..=299) => Outcome::Indexed,
+                    Some(429) => Outcome::Retry,
+                    _ => Outcome::Rejected(result["error"].clone()),
+                }
+            })
+            .collect())
+    }
+
+    fn auth(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> reqwest::RequestBuilder {
+        match &self.username {
+            Some(username) => {
+                request.basic_auth(username, self.password.as_ref())
+            }
+            None => request,
+        }
+    }
+
+    /// Sends a request, returning the body of a successful response.
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<String, String> {
+        let response = request.send().await.map_err(|err| err.to_string())?;
+        let status = response.status();
+        let text = response.text().await.map_err(|err| err.to_string())?;
+        if status.is_success() {
+            Ok(text)
+        } else {
+            Err(format!("{status}: {}", text.trim()))
+        }
+    }
+
+    /// Records documents the server rejected.
+    async fn dead_letter(
+        &self,
+        rejected: Vec<(Document, serde_json::Value)>,
+    ) {
+        let Some(path) = &self.dead_letter_file else {
+            for (document, err) in rejected {
+                warn!(
+                    "Target {}: document for {} rejected: {err}",
+                    self.name, document.index
+                );
+            }
+            return;
+        };
+
+        let now = Utc::now().to_rfc3339();
+        let mut lines = String::new();
+        for (document, err) in rejected {
+            let line = json!({
+                "@timestamp": now,
+                "index": document.index,
+                "error": err,
+                "document": document.source,
+            });
+            lines.push_str(&line.to_string());
+            lines.push('\n');
+        }
+        let res = async {
+            tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?
+                .write_all(lines.as_bytes())
+                .await
+        };
+        if let Err(err) = res.await {
+            error!(
+                "Target {}: cannot write to dead letter file {}: {err}",
+                self.name,
+                path.display()
+            );
+        }
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use chrono::DateTime;
+
+    use crate::{
+        roto_runtime::types::OutputStreamMessage,
+        targets::file::row::tests::mk_route,
+        tests::util::{
+            http::{self, MockServer},
+            https,
+        },
+    };
+
+    use super::*;
+
+    /// Starts a server answering requests with the given responses in turn,
+    /// and with an empty successful bulk response once they run out.
+    async fn start_server(
+        responses: Vec<(u16, serde_json::Value)>,
+    ) -> MockServer {
+        let mut responses = responses.into_iter();
+        MockServer::start(move |_| {
+            let (status, body) = responses
+                .next()
+                .unwrap_or((200, json!({"errors": false, "items": []})));
+            http::json(status, &body)
+        })
+        .await
+    }
+
+    fn mk_config(addr: SocketAddr, extra: &str) -> Config {
+        toml::from_str(&format!("url = \"http://{addr}/\"\n{extra}")).unwrap()
+    }
+
+    fn mk_indexer(config: &Config) -> (Indexer, Arc<ElasticsearchMetrics>) {
+        let metrics = Arc::new(ElasticsearchMetrics::default());
+        let indexer = Indexer::new(
+            "elasticsearch".into(),
+            HttpClient::new(),
+            config,
+            None,
+            metrics.clone(),
+        );
+        (indexer, metrics)
+    }
+
+    fn mk_document(prefix: &str) -> Document {
+        let row = Row::new(
+            OutputStreamMessage::prefix(
+                Some(mk_route(prefix, &[65001], &[(65001, 7)])),
+                None,
+            ),
+            &ingress::Register::default(),
+        );
+        let row = Row {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            ..row
+        };
+        Document::new("rotonda", &row)
+    }
+
+    #[test]
+    fn config_is_checked() {
+        let addr = "127.0.0.1:9200".parse().unwrap();
+        assert!(mk_config(addr, "").check().is_ok());
+        assert!(mk_config(addr, "index_prefix = \"Rotonda\"")
+            .check()
+            .is_err());
+        let config: Config =
+            toml::from_str("url = \"https://localhost:9200/\"").unwrap();
+        assert!(config.check().is_ok());
+        let config: Config =
+            toml::from_str("url = \"ftp://localhost:9200/\"").unwrap();
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn template_maps_the_columns() {
+        let config = mk_config("127.0.0.1:9200".parse().unwrap(), "");
+        let template = config.template().unwrap();
+        assert_eq!(template["index_patterns"], json!(["rotonda-*"]));
+        let properties = &template["template"]["mappings"]["properties"];
+        assert_eq!(properties["@timestamp"]["type"], "date");
+        assert_eq!(properties["prefix"]["type"], "keyword");
+        assert_eq!(properties["as_path"]["type"], "long");
+        assert_eq!(properties["peer_ip"]["type"], "ip");
+    }
+
+    #[test]
+    fn documents_go_into_daily_indexes() {
+        let document = mk_document("192.0.2.0/24");
+        assert_eq!(document.index, "rotonda-routes-2023.11.14");
+        assert_eq!(
+            serde_json::Value::Object(document.source),
+            json!({
+                "@timestamp": "2023-11-14T22:13:20.000000Z",
+                "topic": "prefix",
+                "kind": "route",
+                "prefix": "192.0.2.0/24",
+                "origin_as": 65001,
+                "as_path": [65001],
+                "communities": ["AS65001:7"],
+            })
+        );
+
+        let config = mk_config(
+            "127.0.0.1:9200".parse().unwrap(),
+            "index_prefix = \"bgp\"\nbatch_size = 2",
+        );
+        let ingresses = Arc::new(ingress::Register::default());
+        let id = ingresses.register();
+        let mut batcher = Batcher::new(&config, ingresses);
+        assert!(batcher.push(Update::Withdraw(id, None)).is_none());
+        let batch =
+            batcher.push(Update::WithdrawBulk([id][..].into())).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert!(batch[0].index.starts_with("bgp-events-"));
+        assert!(batcher.take().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn busy_documents_are_retried_and_rejected_ones_recorded() {
+        let dead_letters = std::env::temp_dir()
+            .join(format!("rotonda-rejected-{}.json", uuid::Uuid::new_v4()));
+        let server = start_server(vec![
+            (503, json!({})),
+            (
+                200,
+                json!({"errors": true, "items": [
+                    {"index": {"status": 201}},
+                    {"index": {"status": 429}},
+                    {"index": {
+                        "status": 400,
+                        "error": {"type": "mapper_parsing_exception"},
+                    }},
+                ]}),
+            ),
+        ])
+        .await;
+        let config = mk_config(
+            server.addr,
+            &format!(
+                "retry_delay_secs = 0\ndead_letter_file = {:?}",
+                dead_letters.display().to_string()
+            ),
+        );
+        let (indexer, metrics) = mk_indexer(&config);
+        let documents = vec![
+            mk_document("192.0.2.0/24"),
+            mk_document("198.51.100.0/24"),
+            mk_document("203.0.113.0/24"),
+        ];
+        indexer.index_with_retries(documents).await;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].method, "POST");
+        assert_eq!(requests[1].uri.path(), "/_bulk");
+        let body = requests[1].text();
+        let lines = body.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[0],
+            r#"{"index":{"_index":"rotonda-routes-2023.11.14"}}"#
+        );
+
+        // Only the document the cluster was too busy for is sent again.
+        let body = requests[2].text();
+        let lines = body.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains("198.51.100.0/24"));
+
+        let content = std::fs::read_to_string(&dead_letters).unwrap();
+        std::fs::remove_file(dead_letters).unwrap();
+        let dead_letter: serde_json::Value =
+            serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(dead_letter["error"]["type"], "mapper_parsing_exception");
+        assert_eq!(dead_letter["document"]["prefix"], "203.0.113.0/24");
+
+        assert_eq!(metrics.indexed_document_count.load(SeqCst), 2);
+        assert_eq!(metrics.rejected_document_count.load(SeqCst), 1);
+        assert_eq!(metrics.bulk_error_count.load(SeqCst), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unsent_documents_are_spooled_and_replayed() {
+        let server = start_server(vec![(503, json!({}))]).await;
+        let config = mk_config(server.addr, "max_retries = 0");
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-elasticsearch-{}", uuid::Uuid::new_v4()));
+        let spool = Arc::new(Spool::open(&dir, "es".into()).unwrap());
+        let (mut indexer, metrics) = mk_indexer(&config);
+        indexer.spool = Some(spool.clone());
+        indexer
+            .index_with_retries(vec![mk_document("192.0.2.0/24")])
+            .await;
+        assert_eq!(metrics.dropped_document_count.load(SeqCst), 0);
+
+        spool
+            .replay(None, |letter| indexer.replay(letter.body))
+            .await;
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].uri, requests[1].uri);
+        assert_eq!(requests[0].body, requests[1].body);
+        assert_eq!(metrics.indexed_document_count.load(SeqCst), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn template_is_installed_before_indexing() {
+        let server =
+            start_server(vec![(200, json!({"acknowledged": true}))]).await;
+        let config = mk_config(server.addr, "");
+        let (indexer, metrics) = mk_indexer(&config);
+        let (tx, rx) =
+            Buffer::open("es".into(), 1, &BufferConfig::default()).unwrap();
+        tx.push(vec![mk_document("192.0.2.0/24")]).unwrap();
+        metrics.pending_batch_count.fetch_add(1, SeqCst);
+        drop(tx);
+        indexer.run(Some(config.template().unwrap()), rx).await;
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(requests[0].uri.path(), "/_index_template/rotonda");
+        assert_eq!(requests[1].uri.path(), "/_bulk");
+        assert_eq!(metrics.indexed_document_count.load(SeqCst), 1);
+        assert_eq!(metrics.pending_batch_count.load(SeqCst), 0);
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn documents_are_indexed_over_https() {
+        let server = start_server(vec![]).await;
+        let front = https::front(server.addr).await;
+        let config: Config = toml::from_str(&format!(
+            "url = \"https://localhost:{}/\"\ntls = {{ ca = \"{}\" }}",
+            front.port(),
+            https::ca_file().display()
+        ))
+        .unwrap();
+        config.check().unwrap();
+        let metrics = Arc::new(ElasticsearchMetrics::default());
+        let indexer = Indexer::new(
+            "elasticsearch".into(),
+            config.client(HttpClient::new()).unwrap(),
+            &config,
+            None,
+            metrics.clone(),
+        );
+        indexer
+            .index_with_retries(vec![mk_document("192.0.2.0/24")])
+            .await;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].uri.path(), "/_bulk");
+        assert_eq!(metrics.bulk_error_count.load(SeqCst), 0);
+    }
+}
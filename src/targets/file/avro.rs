@@ -0,0 +1,301 @@
+//! Writing Avro object container files.
+//!
+//! The file starts with a header holding the schema of the rows, as
+//! returned by [`schema`], and the codec compressing the blocks of rows that
+//! follow it. Each block holds up to `row_group_size` rows, but a block is
+//! also written whenever the output is flushed, so that the file is
+//! readable up to the last flush while it is still being written. The
+//! encoding is left to the [apache-avro] crate.
+//!
+//! Of the compression settings, "gzip" selects the "deflate" codec, which
+//! uses the same compression without the gzip framing.
+//!
+//! [apache-avro]: https://docs.rs/apache-avro/
+
+use std::{fmt, sync::OnceLock};
+
+use apache_avro::{types::Value as AvroValue, Codec, Schema, Writer};
+use serde_json::json;
+
+use super::{
+    row::{ColumnType, Row, Value, COLUMNS},
+    target::Compression,
+};
+
+/// Returns the Avro schema of the rows.
+///
+/// Nullable columns are unions of null and their type.
+pub fn schema() -> serde_json::Value {
+    let fields = COLUMNS
+        .iter()
+        .map(|column| {
+            let column_type = match column.column_type {
+                ColumnType::Timestamp => {
+                    json!({"type": "long", "logicalType": "timestamp-micros"})
+                }
+                ColumnType::Long => json!("long"),
+                ColumnType::String => json!("string"),
+                ColumnType::LongList => {
+                    json!({"type": "array", "items": "long"})
+                }
+                ColumnType::StringList => {
+                    json!({"type": "array", "items": "string"})
+                }
+            };
+            if column.nullable {
+                json!({
+                    "name": column.name,
+                    "type": ["null", column_type],
+                    "default": null,
+                })
+            } else {
+                json!({"name": column.name, "type": column_type})
+            }
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "type": "record",
+        "name": "Message",
+        "namespace": "nl.nlnetlabs.rotonda",
+        "fields": fields,
+    })
+}
+
+/// Returns the parsed schema of the rows.
+fn parsed_schema() -> &'static Schema {
+    static SCHEMA: OnceLock<Schema> = OnceLock::new();
+    // The schema is fixed, so parsing it cannot fail.
+    SCHEMA.get_or_init(|| Schema::parse(&schema()).unwrap())
+}
+
+//------------ AvroWriter ----------------------------------------------------
+
+/// Encodes rows as an Avro object container file.
+///
+/// The bytes returned by [`push`](Self::push), [`flush`](Self::flush) and
+/// [`finish`](Self::finish) make up the file when written one after the
+/// other.
+pub struct AvroWriter {
+    block_size: usize,
+
+    /// The number of rows in the next block.
+    block_rows: usize,
+
+    /// Whether the header has been returned.
+    started: bool,
+
+    /// The writer, with the bytes not yet returned in its buffer.
+    writer: Writer<'static, Vec<u8>>,
+}
+
+impl AvroWriter {
+    pub fn new(block_size: usize, compression: Compression) -> Self {
+        // Blocks are only written when asked to, not once they reach a
+        // size in bytes.
+        let writer = Writer::builder()
+            .schema(parsed_schema())
+            .writer(Vec::new())
+            .codec(compression.avro_codec())
+            .block_size(usize::MAX)
+            .build();
+        Self {
+            block_size: block_size.max(1),
+            block_rows: 0,
+            started: false,
+            writer,
+        }
+    }
+
+    /// Adds a row, returning the bytes of a block once one is complete.
+    pub fn push(&mut self, row: &Row) -> Option<Vec<u8>> {
+        // The rows match the schema, so appending them cannot fail, here
+        // and below.
+        self.writer.append(record(row)).unwrap();
+        self.block_rows += 1;
+        if self.block_rows < self.block_size {
+            return None;
+        }
+        self.flush()
+    }
+
+    /// Returns the bytes of the rows added since the last block, if any.
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        if self.block_rows == 0 && self.started {
+            return None;
+        }
+        self.writer.flush().unwrap();
+        self.block_rows = 0;
+        self.started = true;
+        Some(std::mem::take(self.writer.get_mut()))
+    }
+
+    /// Returns the bytes of the remaining rows.
+    pub fn finish(self) -> Vec<u8> {
+        self.writer.into_inner().unwrap()
+    }
+}
+
+impl fmt::Debug for AvroWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AvroWriter")
+            .field("block_size", &self.block_size)
+            .field("block_rows", &self.block_rows)
+            .field("started", &self.started)
+            .finish()
+    }
+}
+
+/// Returns a row as a record of the schema.
+///
+/// The values of nullable columns are the second variant of their union.
+fn record(row: &Row) -> AvroValue {
+    AvroValue::Record(
+        COLUMNS
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                let value = match row.value(index) {
+                    Value::Null => AvroValue::Null,
+                    Value::Timestamp(value) => {
+                        AvroValue::TimestampMicros(value)
+                    }
+                    Value::Long(value) => AvroValue::Long(value),
+                    Value::String(value) => AvroValue::String(value.into()),
+                    Value::LongList(values) => AvroValue::Array(
+                        values
+                            .iter()
+                            .map(|value| AvroValue::Long((*value).into()))
+                            .collect(),
+                    ),
+                    Value::StringList(values) => AvroValue::Array(
+                        values
+                            .iter()
+                            .map(|value| AvroValue::String(value.clone()))
+                            .collect(),
+                    ),
+                };
+                let value = if column.nullable {
+                    let index = u32::from(value != AvroValue::Null);
+                    AvroValue::Union(index, Box::new(value))
+                } else {
+                    value
+                };
+                (column.name.to_string(), value)
+            })
+            .collect(),
+    )
+}
+
+//------------ Compression ---------------------------------------------------
+
+impl Compression {
+    fn avro_codec(&self) -> Codec {
+        match self {
+            Compression::None => Codec::Null,
+            Compression::Snappy => Codec::Snappy,
+            Compression::Gzip => Codec::Deflate,
+        }
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use apache_avro::Reader;
+    use chrono::DateTime;
+
+    use super::*;
+
+    fn mk_row() -> Row {
+        Row {
+            timestamp: DateTime::from_timestamp(1, 0).unwrap(),
+            topic: "prefix".into(),
+            kind: "route",
+            origin_as: Some(65000),
+            as_path: Some(vec![]),
+            communities: Some(vec!["AS65000:1".into()]),
+            ..Default::default()
+        }
+    }
+
+    /// Reads the records of a file, as field names and values.
+    fn read(file: &[u8]) -> Vec<Vec<(String, AvroValue)>> {
+        Reader::new(file)
+            .unwrap()
+            .map(|record| match record.unwrap() {
+                AvroValue::Record(fields) => fields,
+                value => panic!("not a record: {value:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rows_are_encoded_in_schema_order() {
+        let mut writer = AvroWriter::new(10, Compression::None);
+        assert!(writer.push(&mk_row()).is_none());
+        let records = read(&writer.finish());
+        assert_eq!(records.len(), 1);
+
+        let fields = &records[0];
+        let names = fields
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, COLUMNS.map(|column| column.name));
+        let field = |name: &str| {
+            &fields.iter().find(|(key, _)| key == name).unwrap().1
+        };
+        assert_eq!(
+            field("timestamp"),
+            &AvroValue::TimestampMicros(1_000_000)
+        );
+        assert_eq!(field("topic"), &AvroValue::String("prefix".into()));
+        assert_eq!(
+            field("prefix"),
+            &AvroValue::Union(0, Box::new(AvroValue::Null))
+        );
+        assert_eq!(
+            field("origin_as"),
+            &AvroValue::Union(1, Box::new(AvroValue::Long(65000)))
+        );
+        assert_eq!(
+            field("as_path"),
+            &AvroValue::Union(1, Box::new(AvroValue::Array(vec![])))
+        );
+        assert_eq!(
+            field("communities"),
+            &AvroValue::Union(
+                1,
+                Box::new(AvroValue::Array(vec![AvroValue::String(
+                    "AS65000:1".into()
+                )]))
+            )
+        );
+    }
+
+    #[test]
+    fn blocks_follow_the_header() {
+        let mut writer = AvroWriter::new(2, Compression::Snappy);
+        assert!(writer.push(&mk_row()).is_none());
+        let mut file = writer.push(&mk_row()).unwrap();
+        assert!(writer.flush().is_none());
+
+        // The file is readable up to the last block returned.
+        assert_eq!(read(&file).len(), 2);
+
+        writer.push(&mk_row());
+        file.extend(writer.finish());
+        assert_eq!(read(&file).len(), 3);
+    }
+
+    #[test]
+    fn files_without_rows_have_a_header() {
+        let mut writer = AvroWriter::new(2, Compression::Gzip);
+        let file = writer.flush().unwrap();
+        assert!(file.starts_with(b"Obj\x01"));
+        assert!(writer.flush().is_none());
+        assert!(writer.finish().is_empty());
+        assert!(read(&file).is_empty());
+    }
+}
@@ -1 +1,5 @@
+pub(crate) mod avro;
+pub(crate) mod parquet;
+pub(crate) mod row;
+mod rotation;
 pub mod target;
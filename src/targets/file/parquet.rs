@@ -0,0 +1,327 @@
+//! Writing Parquet files.
+//!
+//! Rows are collected into row groups of `row_group_size` rows, which are
+//! encoded by the [parquet] crate. The file metadata describing the row
+//! groups follows the last of them, so a file can only be read once it has
+//! been finished.
+//!
+//! The columns are those of [`COLUMNS`]. Lists are written in the standard
+//! three-level structure of an optional `LIST` annotated group containing a
+//! repeated group `list` with a required field `element`.
+//!
+//! [parquet]: https://docs.rs/parquet/
+
+use std::{fmt, fmt::Write as _, sync::Arc};
+
+use parquet::{
+    basic::{Compression as Codec, GzipLevel},
+    column::writer::ColumnWriter,
+    data_type::ByteArray,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::{parser::parse_message_type, types::Type},
+};
+
+use super::{
+    row::{Column, ColumnType, Row, Value, COLUMNS},
+    target::Compression,
+};
+
+//------------ ParquetWriter -------------------------------------------------
+
+/// Encodes rows as a Parquet file.
+///
+/// The bytes returned by [`push`](Self::push) and [`finish`](Self::finish)
+/// make up the file when written one after the other.
+pub struct ParquetWriter {
+    row_group_size: usize,
+
+    /// The rows of the next row group.
+    rows: Vec<Row>,
+
+    /// The writer, with the bytes not yet returned in its buffer.
+    writer: SerializedFileWriter<Vec<u8>>,
+}
+
+impl ParquetWriter {
+    pub fn new(row_group_size: usize, compression: Compression) -> Self {
+        let properties = WriterProperties::builder()
+            .set_compression(compression.parquet_codec())
+            .set_created_by(
+                concat!("rotonda version ", env!("CARGO_PKG_VERSION")).into(),
+            )
+            .build();
+        // The schema is fixed and writing to a Vec cannot fail, here and
+        // below.
+        let writer = SerializedFileWriter::new(
+            Vec::new(),
+            schema(),
+            Arc::new(properties),
+        )
+        .unwrap();
+        Self {
+            row_group_size: row_group_size.max(1),
+            rows: Vec::new(),
+            writer,
+        }
+    }
+
+    /// Adds a row, returning the bytes of a row group once one is complete.
+    pub fn push(&mut self, row: Row) -> Option<Vec<u8>> {
+        self.rows.push(row);
+        if self.rows.len() < self.row_group_size {
+            return None;
+        }
+        self.write_row_group();
+        Some(std::mem::take(self.writer.inner_mut()))
+    }
+
+    /// Returns the bytes of the remaining rows and the file metadata.
+    pub fn finish(mut self) -> Vec<u8> {
+        if !self.rows.is_empty() {
+            self.write_row_group();
+        }
+        self.writer.into_inner().unwrap()
+    }
+
+    fn write_row_group(&mut self) {
+        let mut group = self.writer.next_row_group().unwrap();
+        let mut index = 0;
+        while let Some(mut writer) = group.next_column().unwrap() {
+            let column = &COLUMNS[index];
+            let values = ColumnValues::new(column, index, &self.rows);
+            let (definition_levels, repetition_levels) =
+                values.levels(column);
+            match writer.untyped() {
+                ColumnWriter::Int64ColumnWriter(typed) => typed.write_batch(
+                    &values.longs,
+                    definition_levels,
+                    repetition_levels,
+                ),
+                ColumnWriter::ByteArrayColumnWriter(typed) => typed
+                    .write_batch(
+                        &values.strings,
+                        definition_levels,
+                        repetition_levels,
+                    ),
+                _ => unreachable!("column {} has no writer", column.name),
+            }
+            .unwrap();
+            writer.close().unwrap();
+            index += 1;
+        }
+        group.close().unwrap();
+        self.rows.clear();
+    }
+}
+
+impl fmt::Debug for ParquetWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParquetWriter")
+            .field("row_group_size", &self.row_group_size)
+            .field("rows", &self.rows.len())
+            .finish()
+    }
+}
+
+/// Returns the schema of the files, with a field per column.
+fn schema() -> Arc<Type> {
+    let mut message = String::from("message schema {\n");
+    for column in &COLUMNS {
+        let repetition = match column.nullable {
+            true => "optional",
+            false => "required",
+        };
+        let (physical_type, logical_type) = match column.column_type {
+            ColumnType::Timestamp => ("int64", " (TIMESTAMP(MICROS,true))"),
+            ColumnType::Long | ColumnType::LongList => ("int64", ""),
+            ColumnType::String | ColumnType::StringList => {
+                ("binary", " (STRING)")
+            }
+        };
+        // Writing to a String cannot fail.
+        if column.is_list() {
+            writeln!(
+                message,
+                "{repetition} group {} (LIST) {{ repeated group list {{ \
+                required {physical_type} element{logical_type}; }} }}",
+                column.name
+            )
+            .unwrap();
+        } else {
+            writeln!(
+                message,
+                "{repetition} {physical_type} {}{logical_type};",
+                column.name
+            )
+            .unwrap();
+        }
+    }
+    message.push('}');
+    Arc::new(parse_message_type(&message).unwrap())
+}
+
+//------------ Columns -------------------------------------------------------
+
+impl Column {
+    fn is_list(&self) -> bool {
+        matches!(
+            self.column_type,
+            ColumnType::LongList | ColumnType::StringList
+        )
+    }
+
+    /// Returns the highest definition level of the values of the column.
+    fn max_definition_level(&self) -> i16 {
+        i16::from(self.nullable) + i16::from(self.is_list())
+    }
+}
+
+/// The values of a column of a row group, with their levels.
+#[derive(Debug, Default, PartialEq)]
+struct ColumnValues {
+    repetition_levels: Vec<i16>,
+    definition_levels: Vec<i16>,
+    longs: Vec<i64>,
+    strings: Vec<ByteArray>,
+}
+
+impl ColumnValues {
+    fn new(column: &Column, index: usize, rows: &[Row]) -> Self {
+        let max_definition_level = column.max_definition_level();
+        let mut res = Self::default();
+        for row in rows {
+            match row.value(index) {
+                Value::Null => res.push_levels(0, 0),
+                Value::LongList([]) | Value::StringList([]) => {
+                    res.push_levels(0, max_definition_level - 1);
+                }
+                Value::LongList(list) => {
+                    for (i, value) in list.iter().enumerate() {
+                        res.push_levels(
+                            i16::from(i > 0),
+                            max_definition_level,
+                        );
+                        res.longs.push(i64::from(*value));
+                    }
+                }
+                Value::StringList(list) => {
+                    for (i, value) in list.iter().enumerate() {
+                        res.push_levels(
+                            i16::from(i > 0),
+                            max_definition_level,
+                        );
+                        res.strings.push(value.as_str().into());
+                    }
+                }
+                Value::Timestamp(value) | Value::Long(value) => {
+                    res.push_levels(0, max_definition_level);
+                    res.longs.push(value);
+                }
+                Value::String(value) => {
+                    res.push_levels(0, max_definition_level);
+                    res.strings.push(value.into());
+                }
+            }
+        }
+        res
+    }
+
+    fn push_levels(&mut self, repetition: i16, definition: i16) {
+        self.repetition_levels.push(repetition);
+        self.definition_levels.push(definition);
+    }
+
+    /// Returns the definition and repetition levels, as far as the column
+    /// has them.
+    fn levels(&self, column: &Column) -> (Option<&[i16]>, Option<&[i16]>) {
+        (
+            Some(self.definition_levels.as_slice())
+                .filter(|_| column.max_definition_level() > 0),
+            Some(self.repetition_levels.as_slice())
+                .filter(|_| column.is_list()),
+        )
+    }
+}
+
+//------------ Compression ---------------------------------------------------
+
+impl Compression {
+    fn parquet_codec(&self) -> Codec {
+        match self {
+            Compression::None => Codec::UNCOMPRESSED,
+            Compression::Snappy => Codec::SNAPPY,
+            Compression::Gzip => Codec::GZIP(GzipLevel::default()),
+        }
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use chrono::DateTime;
+    use parquet::{
+        file::reader::{FileReader, SerializedFileReader},
+        record::{Field, RowAccessor},
+    };
+
+    use super::*;
+
+    fn mk_row(as_path: Option<Vec<u32>>) -> Row {
+        Row {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            topic: "prefix".into(),
+            kind: "route",
+            as_path,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lists_are_encoded_with_levels() {
+        let rows = [
+            mk_row(Some(vec![65000, 65001])),
+            mk_row(None),
+            mk_row(Some(vec![])),
+        ];
+        let index = COLUMNS.iter().position(|c| c.name == "as_path").unwrap();
+        let values = ColumnValues::new(&COLUMNS[index], index, &rows);
+
+        assert_eq!(values.repetition_levels, [0, 1, 0, 0]);
+        assert_eq!(values.definition_levels, [2, 2, 0, 1]);
+        assert_eq!(values.longs, [65000, 65001]);
+        assert!(values.strings.is_empty());
+    }
+
+    #[test]
+    fn files_are_framed_by_row_groups_and_metadata() {
+        let mut writer = ParquetWriter::new(2, Compression::Snappy);
+        let mut file = Vec::new();
+        assert!(writer.push(mk_row(None)).is_none());
+        file.extend(writer.push(mk_row(Some(vec![65000]))).unwrap());
+        assert!(writer.push(mk_row(None)).is_none());
+        file.extend(writer.finish());
+
+        let reader = SerializedFileReader::new(Bytes::from(file)).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.num_row_groups(), 2);
+        assert_eq!(metadata.file_metadata().num_rows(), 3);
+        assert_eq!(
+            metadata.file_metadata().schema_descr().num_columns(),
+            COLUMNS.len()
+        );
+
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(rows[0].get_string(1).unwrap(), "prefix");
+        assert_eq!(rows[0].get_column_iter().nth(5).unwrap().1, &Field::Null);
+        assert_eq!(
+            rows[1].get_list(5).unwrap().elements(),
+            [Field::Long(65000)]
+        );
+    }
+}
@@ -0,0 +1,424 @@
+//! Rotating output files.
+//!
+//! The `filename` of the `file-out` target may contain the time
+//! placeholders of the `s3-out` key template, e.g. `{{year}}` or `{{id}}`,
+//! rendered with the time a file is started.
+//!
+//! A new file is started once the current one holds `rotate_size` bytes or
+//! when its time window of `rotate_secs` is over, aligned to the clock as
+//! for the `s3-out` target. If the new file gets the same name as the
+//! current one, as with a file name without placeholders, the current file
+//! is first renamed by appending the time it was started, e.g.
+//! `rotonda.log.20250102T030405Z`. Windows without any messages don't
+//! leave files behind.
+//!
+//! Finished files are compressed with `rotated_compression`, `"gzip"` or
+//! `"bzip2"`, adding the extension `.gz` or `.bz2`. Of the finished files
+//! in the directory of the current file, only the `max_files` most recent
+//! ones are kept, and none older than `max_age_secs`. Finished files are
+//! recognized by their names matching the file name: starting with it
+//! followed by a dot if it has no placeholders, or having its text between
+//! the placeholders otherwise.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use bzip2::write::BzEncoder;
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use crate::targets::{
+    http::template::Template,
+    s3::target::{render_key, window_end},
+};
+
+/// The placeholders of file names.
+const FILENAME_NAMES: [&str; 8] = [
+    "year",
+    "month",
+    "day",
+    "hour",
+    "minute",
+    "second",
+    "timestamp",
+    "id",
+];
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RotationConfig {
+    /// The time window a file covers.
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    #[serde(default)]
+    rotate_secs: Option<Duration>,
+
+    /// The size in bytes after which a new file is started.
+    #[serde(default)]
+    rotate_size: Option<u64>,
+
+    #[serde(default)]
+    rotated_compression: RotatedCompression,
+
+    /// The number of finished files to keep.
+    #[serde(default)]
+    max_files: Option<usize>,
+
+    /// How long to keep finished files.
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    #[serde(default)]
+    max_age_secs: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RotatedCompression {
+    #[default]
+    None,
+    Gzip,
+    Bzip2,
+}
+
+//------------ Rotation ------------------------------------------------------
+
+/// The names of the output files and when to start a new one.
+#[derive(Clone, Debug)]
+pub struct Rotation {
+    filename: Template,
+
+    /// Whether the file name has placeholders.
+    templated: bool,
+
+    /// The text of the last component of the file name around its
+    /// placeholders.
+    segments: Vec<String>,
+
+    config: RotationConfig,
+}
+
+impl Rotation {
+    /// Creates the rotation, checking the configuration.
+    pub fn new(
+        filename: &Path,
+        config: RotationConfig,
+    ) -> Result<Self, String> {
+        if config.rotate_secs.is_some_and(|secs| secs.is_zero()) {
+            return Err("rotate_secs must be at least 1".into());
+        }
+        if config.rotate_size == Some(0) {
+            return Err("rotate_size must be at least 1".into());
+        }
+        let filename = filename.to_string_lossy();
+        let template =
+            Template::new(filename.as_ref().into(), &FILENAME_NAMES)
+                .map_err(|err| format!("filename: {err}"))?;
+        let name = filename.rsplit('/').next().unwrap_or_default();
+        let mut segments: Vec<String> = vec![];
+        for (index, part) in name.split("{{").enumerate() {
+            match part.split_once("}}") {
+                Some((_, text)) if index > 0 => segments.push(text.into()),
+                _ => segments.push(part.into()),
+            }
+        }
+        let templated = segments.len() > 1;
+        if !templated {
+            segments[0].push('.');
+        }
+        Ok(Self {
+            filename: template,
+            templated,
+            segments,
+            config,
+        })
+    }
+
+    /// Returns the path of a file started at the given time.
+    pub fn path(&self, started: DateTime<Utc>) -> PathBuf {
+        render_key(&self.filename, started, "").into()
+    }
+
+    /// Returns when a file started at the given time is to be rotated.
+    pub fn rotate_at(&self, started: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.config
+            .rotate_secs
+            .map(|rotate| window_end(started, rotate))
+    }
+
+    /// Returns whether a file with `size` bytes is to be rotated.
+    pub fn is_full(&self, size: u64) -> bool {
+        self.config.rotate_size.is_some_and(|max| size >= max)
+    }
+
+    /// Returns the name of a finished file.
+    ///
+    /// The file at `path` was started at `started`. If the next file,
+    /// at `next`, gets the same name, it is renamed, so that it doesn't
+    /// clash with any earlier file finished in the same second.
+    pub fn finished_path(
+        &self,
+        path: &Path,
+        started: DateTime<Utc>,
+        next: &Path,
+    ) -> PathBuf {
+        if path != next {
+            return path.into();
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = format!("{name}.{}", started.format("%Y%m%dT%H%M%SZ"));
+        let mut res = path.with_file_name(&name);
+        let mut count = 0;
+        while res.exists() || self.compressed_path(&res).exists() {
+            count += 1;
+            res = path.with_file_name(format!("{name}.{count}"));
+        }
+        res
+    }
+
+    /// Compresses a finished file and removes expired ones.
+    ///
+    /// `current` is the path of the file being written, which is left
+    /// alone. Returns the paths of the files removed.
+    pub fn finish(
+        &self,
+        finished: &Path,
+        current: &Path,
+    ) -> io::Result<Vec<PathBuf>> {
+        self.compress(finished)?;
+        self.expire(current, SystemTime::now())
+    }
+
+    fn compressed_path(&self, path: &Path) -> PathBuf {
+        let extension = match self.config.rotated_compression {
+            RotatedCompression::None => return path.into(),
+            RotatedCompression::Gzip => "gz",
+            RotatedCompression::Bzip2 => "bz2",
+        };
+        let mut res = path.as_os_str().to_owned();
+        res.push(".");
+        res.push(extension);
+        res.into()
+    }
+
+    /// Compresses a file, replacing it with the compressed file.
+    fn compress(&self, path: &Path) -> io::Result<()> {
+        let target = self.compressed_path(path);
+        if target == path {
+            return Ok(());
+        }
+        let name = target.file_name().unwrap_or_default().to_string_lossy();
+        let tmp_path = target.with_file_name(format!(".{name}.tmp"));
+        let res = (|| {
+            let mut input = fs::File::open(path)?;
+            let output = fs::File::create(&tmp_path)?;
+            match self.config.rotated_compression {
+                RotatedCompression::None => {}
+                RotatedCompression::Gzip => {
+                    let mut encoder = GzEncoder::new(
+                        output,
+                        flate2::Compression::default(),
+                    );
+                    io::copy(&mut input, &mut encoder)?;
+                    encoder.finish()?;
+                }
+                RotatedCompression::Bzip2 => {
+                    let mut encoder =
+                        BzEncoder::new(output, bzip2::Compression::default());
+                    io::copy(&mut input, &mut encoder)?;
+                    encoder.finish()?;
+                }
+            }
+            fs::rename(&tmp_path, &target)
+        })();
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+            return res;
+        }
+        fs::remove_file(path)
+    }
+
+    /// Removes the finished files beyond the limits.
+    fn expire(
+        &self,
+        current: &Path,
+        now: SystemTime,
+    ) -> io::Result<Vec<PathBuf>> {
+        if self.config.max_files.is_none()
+            && self.config.max_age_secs.is_none()
+        {
+            return Ok(vec![]);
+        }
+        let dir = match current.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut finished = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if path == current
+                || name.starts_with('.')
+                || !self.matches(&name)
+                || !entry.file_type()?.is_file()
+            {
+                continue;
+            }
+            finished.push((entry.metadata()?.modified()?, path));
+        }
+
+        // Most recent first.
+        finished.sort_by(|a, b| b.cmp(a));
+        let mut removed = vec![];
+        for (index, (modified, path)) in finished.into_iter().enumerate() {
+            let too_many =
+                self.config.max_files.is_some_and(|max| index >= max);
+            let too_old = self.config.max_age_secs.is_some_and(|max| {
+                now.duration_since(modified).unwrap_or_default() > max
+            });
+            if too_many || too_old {
+                fs::remove_file(&path)?;
+                removed.push(path);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Returns whether a file name is that of a finished file.
+    fn matches(&self, name: &str) -> bool {
+        let Some(mut rest) = name.strip_prefix(self.segments[0].as_str())
+        else {
+            return false;
+        };
+        if !self.templated {
+            return true;
+        }
+        for segment in &self.segments[1..] {
+            match rest.find(segment.as_str()) {
+                Some(pos) => rest = &rest[pos + segment.len()..],
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn mk_rotation(filename: &Path, config: &str) -> Rotation {
+        Rotation::new(filename, toml::from_str(config).unwrap()).unwrap()
+    }
+
+    fn started() -> DateTime<Utc> {
+        "2025-01-02T03:04:05Z".parse().unwrap()
+    }
+
+    #[test]
+    fn config_is_checked() {
+        let path = Path::new("rotonda.log");
+        let config = |s| toml::from_str(s).unwrap();
+        assert!(Rotation::new(path, config("rotate_secs = 0")).is_err());
+        assert!(Rotation::new(path, config("rotate_size = 0")).is_err());
+        assert!(Rotation::new(
+            Path::new("rotonda-{{date}}.log"),
+            Default::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn files_are_named() {
+        let rotation = mk_rotation(
+            Path::new("/var/log/{{year}}/rotonda-{{hour}}{{minute}}.log"),
+            "rotate_secs = 3600\nrotate_size = 10",
+        );
+        let path = rotation.path(started());
+        assert_eq!(path, Path::new("/var/log/2025/rotonda-0304.log"));
+        assert_eq!(
+            rotation.rotate_at(started()),
+            Some("2025-01-02T04:00:00Z".parse().unwrap())
+        );
+        assert!(!rotation.is_full(9));
+        assert!(rotation.is_full(10));
+
+        let next = Path::new("/var/log/2025/rotonda-0400.log");
+        assert_eq!(rotation.finished_path(&path, started(), next), path);
+        assert!(rotation.matches("rotonda-0304.log"));
+        assert!(rotation.matches("rotonda-0304.log.20250102T030405Z.gz"));
+        assert!(!rotation.matches("other-0304.log"));
+        assert!(!rotation.matches("rotonda-0304.csv"));
+
+        let rotation = mk_rotation(Path::new("rotonda.log"), "");
+        let path = rotation.path(started());
+        assert_eq!(
+            rotation.finished_path(&path, started(), &path),
+            Path::new("rotonda.log.20250102T030405Z")
+        );
+        assert!(rotation.matches("rotonda.log.20250102T030405Z"));
+        assert!(!rotation.matches("rotonda.log"));
+    }
+
+    #[test]
+    fn files_are_compressed_and_expired() {
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-file-out-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let current = dir.join("rotonda.log");
+        let rotation = mk_rotation(
+            &current,
+            "rotated_compression = \"gzip\"\nmax_files = 2",
+        );
+        fs::write(dir.join("other.log"), "other").unwrap();
+        for second in 0..3 {
+            let started = started() + chrono::Duration::seconds(second);
+            fs::write(&current, format!("file {second}")).unwrap();
+            let finished =
+                rotation.finished_path(&current, started, &current);
+            fs::rename(&current, &finished).unwrap();
+            fs::write(&current, "").unwrap();
+            let removed = rotation.finish(&finished, &current).unwrap();
+            assert_eq!(removed.len(), usize::from(second == 2));
+            // Make sure the modification times differ.
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut names = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "other.log",
+                "rotonda.log",
+                "rotonda.log.20250102T030406Z.gz",
+                "rotonda.log.20250102T030407Z.gz",
+            ]
+        );
+
+        let mut content = String::new();
+        GzDecoder::new(
+            fs::File::open(dir.join("rotonda.log.20250102T030407Z.gz"))
+                .unwrap(),
+        )
+        .read_to_string(&mut content)
+        .unwrap();
+        assert_eq!(content, "file 2");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
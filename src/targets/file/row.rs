@@ -0,0 +1,485 @@
+//! The rows written by the columnar output formats.
+//!
+//! Unlike the JSON and CSV output, which serialize each message as it is,
+//! the columnar formats need a fixed schema. Each message becomes a single
+//! row with the columns in [`COLUMNS`], those not applicable to the kind of
+//! message being left empty.
+
+use std::net::IpAddr;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use inetnum::asn::Asn;
+use rotonda_store::prefix_record::RouteStatus;
+use routecore::bgp::aspath::{Hop, HopPath};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+
+use crate::{
+    ingress::{self, IngressId},
+    payload::{Payload, RotondaRoute, Update},
+    roto_runtime::types::{
+        OutputStreamMessage, OutputStreamMessageRecord, RouteContext,
+    },
+    units::rib_unit::{best_path::prefix_of, index::communities},
+};
+
+//------------ Column --------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColumnType {
+    /// Microseconds since the Unix epoch.
+    Timestamp,
+    Long,
+    String,
+    LongList,
+    StringList,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Column {
+    pub name: &'static str,
+    pub column_type: ColumnType,
+    pub nullable: bool,
+}
+
+impl Column {
+    const fn new(
+        name: &'static str,
+        column_type: ColumnType,
+        nullable: bool,
+    ) -> Self {
+        Self {
+            name,
+            column_type,
+            nullable,
+        }
+    }
+}
+
+/// The columns of each row, in order.
+pub const COLUMNS: [Column; 10] = [
+    Column::new("timestamp", ColumnType::Timestamp, false),
+    Column::new("topic", ColumnType::String, false),
+    Column::new("kind", ColumnType::String, false),
+    Column::new("prefix", ColumnType::String, true),
+    Column::new("origin_as", ColumnType::Long, true),
+    Column::new("as_path", ColumnType::LongList, true),
+    Column::new("communities", ColumnType::StringList, true),
+    Column::new("peer_ip", ColumnType::String, true),
+    Column::new("peer_as", ColumnType::Long, true),
+    Column::new("custom", ColumnType::String, true),
+];
+
+//------------ Value ---------------------------------------------------------
+
+/// The value of a single column of a row.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value<'a> {
+    Null,
+    Timestamp(i64),
+    Long(i64),
+    String(&'a str),
+    LongList(&'a [u32]),
+    StringList(&'a [String]),
+}
+
+impl Value<'_> {
+    /// Returns the value as JSON.
+    ///
+    /// Timestamps become RFC 3339 strings with microseconds.
+    pub fn to_json(self) -> serde_json::Value {
+        match self {
+            Value::Null => serde_json::Value::Null,
+            Value::Timestamp(micros) => {
+                DateTime::from_timestamp_micros(micros)
+                    .map(|ts| ts.to_rfc3339_opts(SecondsFormat::Micros, true))
+                    .into()
+            }
+            Value::Long(value) => value.into(),
+            Value::String(value) => value.into(),
+            Value::LongList(values) => values.into(),
+            Value::StringList(values) => values.into(),
+        }
+    }
+}
+
+//------------ Row -----------------------------------------------------------
+
+/// The kinds of rows.
+const KINDS: [&str; 6] =
+    ["route", "announce", "withdraw", "peer_down", "log", "custom"];
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Row {
+    pub timestamp: DateTime<Utc>,
+    pub topic: String,
+
+    /// What the message is about: "route", "announce", "withdraw",
+    /// "peer_down", "log" or "custom".
+    pub kind: &'static str,
+    pub prefix: Option<String>,
+    pub origin_as: Option<u32>,
+
+    /// The AS path, with the members of AS sets in the order they appear.
+    pub as_path: Option<Vec<u32>>,
+    pub communities: Option<Vec<String>>,
+    pub peer_ip: Option<String>,
+    pub peer_as: Option<u32>,
+
+    /// Any other content, as text or JSON.
+    pub custom: Option<String>,
+}
+
+impl Row {
+    /// Creates the row for a message.
+    ///
+    /// The peer of a route is looked up in `ingresses`.
+    pub fn new(
+        msg: OutputStreamMessage,
+        ingresses: &ingress::Register,
+    ) -> Self {
+        let mut res = Self {
+            timestamp: Utc::now(),
+            topic: msg.get_topic().clone(),
+            ..Default::default()
+        };
+        let ingress_id = msg.get_ingress_id();
+        match msg.into_record() {
+            OutputStreamMessageRecord::Route(route) => {
+                res.kind = "route";
+                if let Some(info) =
+                    ingress_id.and_then(|id| ingresses.get(id))
+                {
+                    res.set_peer(info.remote_addr, info.remote_asn);
+                }
+                if let Some(route) = route {
+                    res.set_route(&route);
+                }
+            }
+            OutputStreamMessageRecord::Peerdown(ip, asn) => {
+                res.kind = "peer_down";
+                res.set_peer(Some(ip), Some(asn));
+            }
+            OutputStreamMessageRecord::Custom(entry) => {
+                res.kind = "custom";
+                res.custom = serde_json::to_string(&entry).ok();
+            }
+            OutputStreamMessageRecord::Entry(entry) => {
+                res.kind = "log";
+                if entry.timestamp != DateTime::UNIX_EPOCH {
+                    res.timestamp = entry.timestamp;
+                }
+                res.origin_as = entry.origin_as.map(Asn::into_u32);
+                res.peer_as = entry.peer_as.map(Asn::into_u32);
+                res.custom = entry.custom;
+            }
+        }
+        res
+    }
+
+    /// Creates the row for a route received directly from a unit.
+    ///
+    /// The kind of the row is "announce" or "withdraw".
+    pub fn for_payload(payload: &Payload) -> Self {
+        let (status, provenance) = match &payload.context {
+            RouteContext::Fresh(ctx) => {
+                (ctx.status(), Some(ctx.provenance()))
+            }
+            RouteContext::Mrt(ctx) => (ctx.status, Some(ctx.provenance())),
+            RouteContext::Reprocess => (RouteStatus::Active, None),
+        };
+        let mut res = Self {
+            timestamp: provenance.map_or_else(Utc::now, |p| p.timestamp),
+            topic: "route".into(),
+            kind: match status {
+                RouteStatus::Withdrawn => "withdraw",
+                RouteStatus::Active | RouteStatus::InActive => "announce",
+            },
+            ..Default::default()
+        };
+        if let Some(provenance) = provenance {
+            res.set_peer(Some(provenance.peer_ip), Some(provenance.peer_asn));
+        }
+        res.set_route(&payload.rx_value);
+        res
+    }
+
+    /// Creates the row for a session that went down.
+    ///
+    /// The peer of the session is looked up in `ingresses`.
+    pub fn for_session_down(
+        ingress_id: IngressId,
+        ingresses: &ingress::Register,
+    ) -> Self {
+        let mut res = Self {
+            timestamp: Utc::now(),
+            topic: "session".into(),
+            kind: "peer_down",
+            ..Default::default()
+        };
+        if let Some(info) = ingresses.get(ingress_id) {
+            res.set_peer(info.remote_addr, info.remote_asn);
+        }
+        res
+    }
+
+    /// Creates the rows for an update.
+    ///
+    /// Routes and sessions going down become rows as described by
+    /// [`for_payload`](Self::for_payload) and
+    /// [`for_session_down`](Self::for_session_down), output stream messages
+    /// as described by [`new`](Self::new). Other updates have no rows.
+    pub fn for_update(
+        update: Update,
+        ingresses: &ingress::Register,
+    ) -> Vec<Self> {
+        match update {
+            Update::Single(payload) => vec![Self::for_payload(&payload)],
+            Update::Bulk(payloads) => {
+                payloads.iter().map(Self::for_payload).collect()
+            }
+            Update::Withdraw(ingress_id, None) => {
+                vec![Self::for_session_down(ingress_id, ingresses)]
+            }
+            Update::WithdrawBulk(ingress_ids) => ingress_ids
+                .iter()
+                .map(|id| Self::for_session_down(*id, ingresses))
+                .collect(),
+            Update::OutputStream(msgs) => msgs
+                .into_iter()
+                .map(|msg| Self::new(msg, ingresses))
+                .collect(),
+
+            // Withdrawing a single address family does not take the
+            // session down.
+            Update::Withdraw(_, Some(_))
+            | Update::QueryResult(..)
+            | Update::UpstreamStatusChange(..)
+            | Update::Rtr(..) => vec![],
+        }
+    }
+
+    fn set_route(&mut self, route: &RotondaRoute) {
+        self.prefix = Some(prefix_of(route).to_string());
+        let pamap = route.rotonda_pamap();
+        if let Some(hop_path) = pamap.path_attributes().get::<HopPath>() {
+            self.origin_as = hop_path
+                .origin()
+                .and_then(|hop| hop.clone().try_into_asn().ok())
+                .map(Asn::into_u32);
+            self.as_path = Some(flatten(&hop_path));
+        }
+        self.communities = Some(
+            communities(pamap).iter().map(ToString::to_string).collect(),
+        );
+    }
+
+    fn set_peer(&mut self, ip: Option<IpAddr>, asn: Option<Asn>) {
+        self.peer_ip = ip.map(|ip| ip.to_string());
+        self.peer_as = asn.map(Asn::into_u32);
+    }
+
+    /// Returns the value of the column with the given index in [`COLUMNS`].
+    pub fn value(&self, column: usize) -> Value<'_> {
+        fn opt<'a, T>(
+            value: Option<T>,
+            f: impl FnOnce(T) -> Value<'a>,
+        ) -> Value<'a> {
+            value.map_or(Value::Null, f)
+        }
+
+        match column {
+            0 => Value::Timestamp(self.timestamp.timestamp_micros()),
+            1 => Value::String(&self.topic),
+            2 => Value::String(self.kind),
+            3 => opt(self.prefix.as_deref(), Value::String),
+            4 => opt(self.origin_as, |asn| Value::Long(asn.into())),
+            5 => opt(self.as_path.as_deref(), Value::LongList),
+            6 => opt(self.communities.as_deref(), Value::StringList),
+            7 => opt(self.peer_ip.as_deref(), Value::String),
+            8 => opt(self.peer_as, |asn| Value::Long(asn.into())),
+            9 => opt(self.custom.as_deref(), Value::String),
+            _ => panic!("no column {column}"),
+        }
+    }
+
+    /// Returns the value of the column with the given name as JSON.
+    ///
+    /// Unknown columns are null.
+    pub fn json_value(&self, name: &str) -> serde_json::Value {
+        COLUMNS
+            .iter()
+            .position(|column| column.name == name)
+            .map_or(serde_json::Value::Null, |index| {
+                self.value(index).to_json()
+            })
+    }
+}
+
+fn flatten(hop_path: &HopPath) -> Vec<u32> {
+    let mut res = Vec::new();
+    for hop in hop_path.iter() {
+        match hop {
+            Hop::Asn(asn) => res.push(asn.into_u32()),
+            Hop::Segment(segment) => {
+                res.extend(segment.asns().map(Asn::into_u32))
+            }
+        }
+    }
+    res
+}
+
+//--- Deserialize
+
+/// A row as deserialized, before its kind is checked.
+///
+/// Deriving `Deserialize` for [`Row`] itself would only allow deserializing
+/// from data that lives forever, because of the static kind.
+#[derive(Deserialize)]
+struct OwnedRow {
+    timestamp: DateTime<Utc>,
+    topic: String,
+    kind: String,
+    prefix: Option<String>,
+    origin_as: Option<u32>,
+    as_path: Option<Vec<u32>>,
+    communities: Option<Vec<String>>,
+    peer_ip: Option<String>,
+    peer_as: Option<u32>,
+    custom: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for Row {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let row = OwnedRow::deserialize(deserializer)?;
+        let Some(kind) = KINDS.into_iter().find(|kind| *kind == row.kind)
+        else {
+            return Err(D::Error::custom(format!(
+                "unknown kind '{}'",
+                row.kind
+            )));
+        };
+        Ok(Self {
+            timestamp: row.timestamp,
+            topic: row.topic,
+            kind,
+            prefix: row.prefix,
+            origin_as: row.origin_as,
+            as_path: row.as_path,
+            communities: row.communities,
+            peer_ip: row.peer_ip,
+            peer_as: row.peer_as,
+            custom: row.custom,
+        })
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use inetnum::addr::Prefix;
+    use routecore::bgp::{
+        message::PduParseInfo, path_attributes::OwnedPathAttributes,
+        types::PathAttributeType,
+    };
+
+    use crate::{
+        ingress::IngressInfo,
+        payload::{RotondaPaMap, RotondaRoute},
+    };
+
+    use super::*;
+
+    /// Returns a route for `prefix` with the given AS path and communities.
+    pub fn mk_route(
+        prefix: &str,
+        as_path: &[u32],
+        communities: &[(u16, u16)],
+    ) -> RotondaRoute {
+        let mut raw = vec![0x40, PathAttributeType::AsPath.into()];
+        raw.push(2 + 4 * as_path.len() as u8);
+        raw.extend([2, as_path.len() as u8]);
+        for asn in as_path {
+            raw.extend(asn.to_be_bytes());
+        }
+        raw.extend([0xc0, PathAttributeType::Communities.into()]);
+        raw.push(4 * communities.len() as u8);
+        for (asn, value) in communities {
+            raw.extend(asn.to_be_bytes());
+            raw.extend(value.to_be_bytes());
+        }
+        let pamap = RotondaPaMap::new(OwnedPathAttributes::new(
+            PduParseInfo::modern(),
+            raw,
+        ));
+        let prefix: Prefix = prefix.parse().unwrap();
+        RotondaRoute::Ipv4Unicast(prefix.try_into().unwrap(), pamap)
+    }
+
+    #[test]
+    fn routes_are_described_with_their_peer() {
+        let ingresses = ingress::Register::default();
+        let id = ingresses.register();
+        ingresses.update_info(
+            id,
+            IngressInfo::new()
+                .with_remote_addr("192.0.2.1".parse().unwrap())
+                .with_remote_asn(Asn::from_u32(65000)),
+        );
+        let route =
+            mk_route("198.51.100.0/24", &[65000, 65001], &[(65000, 1)]);
+        let row = Row::new(
+            OutputStreamMessage::prefix(Some(route), Some(id)),
+            &ingresses,
+        );
+
+        assert_eq!(row.topic, "prefix");
+        assert_eq!(row.kind, "route");
+        assert_eq!(row.prefix.as_deref(), Some("198.51.100.0/24"));
+        assert_eq!(row.origin_as, Some(65001));
+        assert_eq!(row.as_path, Some(vec![65000, 65001]));
+        assert_eq!(row.communities, Some(vec!["AS65000:1".to_string()]));
+        assert_eq!(row.peer_ip.as_deref(), Some("192.0.2.1"));
+        assert_eq!(row.peer_as, Some(65000));
+        assert_eq!(row.value(4), Value::Long(65001));
+        assert_eq!(row.value(9), Value::Null);
+    }
+
+    #[test]
+    fn peer_downs_have_no_route_columns() {
+        let row = Row::new(
+            OutputStreamMessage::peer_down(
+                "mqtt".into(),
+                "peer-down".into(),
+                "2001:db8::1".parse().unwrap(),
+                Asn::from_u32(65002),
+                None,
+            ),
+            &ingress::Register::default(),
+        );
+
+        assert_eq!(row.kind, "peer_down");
+        assert_eq!(row.prefix, None);
+        assert_eq!(row.as_path, None);
+        assert_eq!(row.peer_ip.as_deref(), Some("2001:db8::1"));
+        assert_eq!(row.peer_as, Some(65002));
+    }
+
+    #[test]
+    fn rows_survive_serialization() {
+        let row = Row {
+            kind: "withdraw",
+            prefix: Some("192.0.2.0/24".into()),
+            as_path: Some(vec![65000]),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&row).unwrap();
+        assert_eq!(serde_json::from_str::<Row>(&json).unwrap(), row);
+        assert!(serde_json::from_str::<Row>(
+            &json.replace("withdraw", "unknown")
+        )
+        .is_err());
+    }
+}
@@ -1,11 +1,12 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
-use chrono::SecondsFormat;
+use chrono::{DateTime, SecondsFormat, Utc};
 //use async_trait::async_trait;
 use futures::future::{select, Either};
 use futures::FutureExt;
-use log::{debug, error, warn};
+use log::{debug, error, info};
 //use non_empty_vec::NonEmpty;
 use serde::Deserialize;
 
@@ -19,10 +20,16 @@ use crate::config::ConfigPath;
 use crate::ingress;
 use crate::payload::Update;
 use crate::roto_runtime::types::OutputStreamMessageRecord;
+use crate::targets::http::template::Template;
 use crate::targets::Component;
 use crate::targets::TargetCommand;
 use crate::targets::WaitPoint;
 
+use super::avro::AvroWriter;
+use super::parquet::ParquetWriter;
+use super::rotation::{Rotation, RotationConfig};
+use super::row::{Row, COLUMNS};
+
 // For low-traffic logging, make sure we flush to disk at least every N secs:
 const LAST_FLUSH_TIMEOUT_SECS: u64 = 1;
 
@@ -38,6 +45,40 @@ pub struct File {
 pub struct Config {
     format: Format,
     filename: ConfigPath,
+
+    /// The number of rows per Parquet row group or Avro block.
+    #[serde(default = "Config::default_row_group_size")]
+    row_group_size: usize,
+
+    /// The compression of Parquet and Avro output.
+    #[serde(default)]
+    compression: Compression,
+
+    /// The line each row is rendered into with the template format.
+    #[serde(default)]
+    template: Option<String>,
+
+    #[serde(flatten)]
+    rotation: RotationConfig,
+}
+
+impl Config {
+    fn default_row_group_size() -> usize {
+        10_000
+    }
+
+    /// Returns the template of the template format, checking it.
+    fn template(&self) -> Result<Option<Template>, String> {
+        let template = match (&self.format, &self.template) {
+            (Format::Template, Some(template)) => template,
+            (Format::Template, None) => {
+                return Err("the template format requires a template".into())
+            }
+            (_, _) => return Ok(None),
+        };
+        let columns = COLUMNS.map(|column| column.name);
+        Template::new(template.as_str().into(), &columns).map(Some)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -49,6 +90,55 @@ pub enum Format {
     Json,
     #[serde(rename = "json-min")]
     JsonMin,
+    #[serde(rename = "parquet")]
+    Parquet,
+    #[serde(rename = "avro")]
+    Avro,
+
+    /// A line per message rendered from the template.
+    #[serde(rename = "template")]
+    Template,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    #[default]
+    Snappy,
+    #[serde(alias = "deflate")]
+    Gzip,
+}
+
+/// The writer of the formats with a row per message.
+#[derive(Debug)]
+enum RowWriter {
+    Parquet(ParquetWriter),
+    Avro(AvroWriter),
+}
+
+impl RowWriter {
+    fn push(&mut self, row: Row) -> Option<Vec<u8>> {
+        match self {
+            RowWriter::Parquet(writer) => writer.push(row),
+            RowWriter::Avro(writer) => writer.push(&row),
+        }
+    }
+
+    /// Returns any bytes to write before flushing the file.
+    fn flush(&mut self) -> Option<Vec<u8>> {
+        match self {
+            RowWriter::Parquet(_) => None,
+            RowWriter::Avro(writer) => writer.flush(),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            RowWriter::Parquet(writer) => writer.finish(),
+            RowWriter::Avro(writer) => writer.finish(),
+        }
+    }
 }
 
 
@@ -72,7 +162,18 @@ pub struct FileRunner {
     config: Config,
     ingresses: Arc<ingress::Register>,
     target_file: Option<BufWriter<tokio::fs::File>>,
+    rows: Option<RowWriter>,
+    template: Option<Template>,
     last_flush: Instant,
+
+    /// The file currently written to, its start, size and number of
+    /// messages, and when to start the next one.
+    rotation: Option<Rotation>,
+    path: PathBuf,
+    started: DateTime<Utc>,
+    written: u64,
+    messages: u64,
+    rotate_at: Option<DateTime<Utc>>,
 }
 
 
@@ -84,32 +185,187 @@ impl FileRunner {
             component,
             ingresses,
             target_file: None,
+            rows: None,
+            template: None,
             last_flush: Instant::now(),
+            rotation: None,
+            path: PathBuf::new(),
+            started: DateTime::UNIX_EPOCH,
+            written: 0,
+            messages: 0,
+            rotate_at: None,
+        }
+    }
+
+    /// Starts a new file.
+    async fn open(&mut self, started: DateTime<Utc>) -> std::io::Result<()> {
+        let Some(rotation) = self.rotation.as_ref() else {
+            return Ok(());
+        };
+        self.path = rotation.path(started);
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+        }
+        let f = tokio::fs::File::create(&self.path).await?;
+        self.target_file = Some(BufWriter::new(f));
+        self.rows = match self.config.format {
+            Format::Parquet => Some(RowWriter::Parquet(ParquetWriter::new(
+                self.config.row_group_size,
+                self.config.compression,
+            ))),
+            Format::Avro => Some(RowWriter::Avro(AvroWriter::new(
+                self.config.row_group_size,
+                self.config.compression,
+            ))),
+            Format::Csv
+            | Format::Json
+            | Format::JsonMin
+            | Format::Template => None,
+        };
+        self.started = started;
+        self.written = 0;
+        self.messages = 0;
+        self.rotate_at = rotation.rotate_at(started);
+        Ok(())
+    }
+
+    /// Returns whether it is time to start a new file.
+    fn rotation_due(&self) -> bool {
+        let Some(rotation) = self.rotation.as_ref() else {
+            return false;
+        };
+        rotation.is_full(self.written)
+            || self.rotate_at.is_some_and(|at| at <= Utc::now())
+    }
+
+    /// Finishes the current file and starts a new one.
+    ///
+    /// The finished file is compressed and expired files are removed in
+    /// the background.
+    async fn rotate(&mut self) -> Result<(), Terminated> {
+        let Some(rotation) = self.rotation.clone() else {
+            return Ok(());
+        };
+        let now = Utc::now();
+        let next = rotation.path(now);
+        let finished = if self.messages == 0 {
+            if next == self.path {
+                self.rotate_at = rotation.rotate_at(now);
+                return Ok(());
+            }
+            // Don't leave an empty file behind.
+            self.target_file = None;
+            let _ = tokio::fs::remove_file(&self.path).await;
+            None
+        } else {
+            if let Some(rows) = self.rows.take() {
+                self.write(&rows.finish()).await;
+            }
+            self.flush().await;
+            self.target_file = None;
+            let finished =
+                rotation.finished_path(&self.path, self.started, &next);
+            if finished != self.path {
+                if let Err(err) =
+                    tokio::fs::rename(&self.path, &finished).await
+                {
+                    error!(
+                        "Target {}: cannot rename {} to {}: {err}",
+                        self.component.name(),
+                        self.path.display(),
+                        finished.display(),
+                    );
+                }
+            }
+            Some(finished)
+        };
+
+        if let Err(err) = self.open(now).await {
+            error!(
+                "Target {}: cannot create {}: {err}",
+                self.component.name(),
+                self.path.display()
+            );
+            return Err(Terminated);
+        }
 
+        if let Some(finished) = finished {
+            let name = self.component.name().clone();
+            let current = self.path.clone();
+            tokio::task::spawn_blocking(move || {
+                match rotation.finish(&finished, &current) {
+                    Ok(removed) => {
+                        for path in removed {
+                            info!(
+                                "Target {name}: removed expired file {}",
+                                path.display()
+                            );
+                        }
+                    }
+                    Err(err) => {
+                        error!(
+                            "Target {name}: cannot finish {}: {err}",
+                            finished.display()
+                        );
+                    }
+                }
+            });
         }
+        Ok(())
     }
 
     async fn flush(&mut self) {
+        if let Some(bytes) = self.rows.as_mut().and_then(RowWriter::flush) {
+            self.write(&bytes).await;
+        }
         if let Some(dst) = self.target_file.as_mut() {
             let _ = dst.flush().await;
             self.last_flush = Instant::now();
         }
     }
 
+    async fn write(&mut self, bytes: &[u8]) {
+        if let Some(dst) = self.target_file.as_mut() {
+            if let Err(err) = dst.write_all(bytes).await {
+                error!(
+                    "Failed to write to {}: {}",
+                    self.path.display(),
+                    err
+                );
+            }
+            self.written += bytes.len() as u64;
+        }
+    }
+
     pub async fn run(
         mut self,
         mut sources: Link,
         mut cmd_rx: mpsc::Receiver<TargetCommand>,
         waitpoint: WaitPoint,
     ) -> Result<(), Terminated> {
-        let f = tokio::fs::File::create(self.config.filename.clone())
+        self.template = self.config.template().map_err(|err| {
+            error!("Target {}: {err}", self.component.name());
+            Terminated
+        })?;
+
+        self.rotation = Some(
+            Rotation::new(
+                &self.config.filename,
+                self.config.rotation.clone(),
+            )
+            .map_err(|err| {
+                error!("Target {}: {err}", self.component.name());
+                Terminated
+            })?,
+        );
+        self.open(Utc::now())
             .await
             .inspect_err(|e| error!("{}", e))
             .map_err(|_| Terminated)
             ?;
 
-        self.target_file = Some(BufWriter::new(f));
-
         //let arc_self = Arc::new(self);
         // Register as a direct update receiver with the linked gates.
 
@@ -126,6 +382,9 @@ impl FileRunner {
         waitpoint.running().await;
 
         loop {
+            if self.rotation_due() {
+                self.rotate().await?;
+            }
             let select_fut = select(
                 cmd_rx.recv().boxed(),
                 sources.query().boxed(),
@@ -147,7 +406,8 @@ impl FileRunner {
                     match gate_cmd {
                         Some(cmd) => match cmd {
                             TargetCommand::Reconfigure { .. } => {
-                                warn!("Reconfiguration for FileOut component not yet implemented");
+                                // The target is restarted instead when
+                                // its settings or sources change.
                             }
                             TargetCommand::ReportLinks { report } => {
                                 report.set_source(&sources2);
@@ -179,51 +439,27 @@ impl FileRunner {
                     match update {
                         Update::OutputStream(msgs) => {
                             for m in msgs {
-                                let m = m.into_record();
-                                if let Some(dst) = self.target_file.as_mut() {
-                                    if let OutputStreamMessageRecord::Entry(ref e) = m {
-                                        if let Some(ref custom_str) = e.custom {
-                                            if e.timestamp != chrono::DateTime::UNIX_EPOCH {
-                                                dst.write_all(
-                                                    format!(
-                                                        "[{}] ",
-                                                        e.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
-                                                    ).as_ref()
-                                                ).await.unwrap();
-                                            }
-                                            dst.write_all(custom_str.as_ref()).await.unwrap();
-                                            dst.write_all(b"\n").await.unwrap();
-                                            continue;
-                                        } 
-                                    }
-                                    match self.config.format {
-                                        Format::Csv => {
-                                            let mut wrt = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
-                                            wrt.serialize(m).unwrap();
-                                            dst.write_all(&wrt.into_inner().unwrap()).await.unwrap();
-                                        }
-                                        Format::Json => {
-                                            if let Ok(bytes) = serde_json::to_vec(&m) {
-                                                dst.write_all(&bytes).await.unwrap();
-                                                dst.write_all(b"\n").await.unwrap();
-                                            }
-                                        }
-                                        Format::JsonMin => {
-                                            if let OutputStreamMessageRecord::Entry(e) = m {
-                                                if let Ok(bytes) = serde_json::to_vec(&e.into_minimal()) {
-                                                    dst.write_all(&bytes).await.unwrap();
-                                                    dst.write_all(b"\n").await.unwrap();
-                                                }
-                                            } else {
-                                                // same as Json case
-                                                if let Ok(bytes) = serde_json::to_vec(&m) {
-                                                    dst.write_all(&bytes).await.unwrap();
-                                                    dst.write_all(b"\n").await.unwrap();
-                                                }
-                                            }
-                                        }
+                                if let Some(rows) = self.rows.as_mut() {
+                                    let row = Row::new(m, &self.ingresses);
+                                    if let Some(bytes) = rows.push(row) {
+                                        self.write(&bytes).await;
                                     }
+                                    self.messages += 1;
+                                    continue;
+                                }
+                                if let Some(template) = self.template.as_ref() {
+                                    let row = Row::new(m, &self.ingresses);
+                                    let line = render_line(template, &row);
+                                    self.write(line.as_bytes()).await;
+                                    self.messages += 1;
+                                    continue;
                                 }
+                                let bytes = format_record(
+                                    &self.config.format,
+                                    m.into_record(),
+                                );
+                                self.write(&bytes).await;
+                                self.messages += 1;
                             }
                         }
 
@@ -240,11 +476,73 @@ impl FileRunner {
             }
 
         }
+        // Parquet files are only readable with the metadata at the end.
+        if let Some(rows) = self.rows.take() {
+            self.write(&rows.finish()).await;
+        }
         self.flush().await;
         Ok(())
     }
 }
 
+/// Formats a message in the CSV or JSON formats.
+fn format_record(format: &Format, m: OutputStreamMessageRecord) -> Vec<u8> {
+    let mut bytes = vec![];
+    if let OutputStreamMessageRecord::Entry(ref e) = m {
+        if let Some(ref custom_str) = e.custom {
+            if e.timestamp != chrono::DateTime::UNIX_EPOCH {
+                bytes.extend_from_slice(
+                    format!(
+                        "[{}] ",
+                        e.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
+                    )
+                    .as_bytes(),
+                );
+            }
+            bytes.extend_from_slice(custom_str.as_bytes());
+            bytes.push(b'\n');
+            return bytes;
+        }
+    }
+    match format {
+        Format::Csv => {
+            let mut wrt = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(bytes);
+            wrt.serialize(m).unwrap();
+            return wrt.into_inner().unwrap();
+        }
+        Format::Json => {
+            if let Ok(json) = serde_json::to_vec(&m) {
+                bytes = json;
+                bytes.push(b'\n');
+            }
+        }
+        Format::JsonMin => {
+            let json = if let OutputStreamMessageRecord::Entry(e) = m {
+                serde_json::to_vec(&e.into_minimal())
+            } else {
+                // same as Json case
+                serde_json::to_vec(&m)
+            };
+            if let Ok(json) = json {
+                bytes = json;
+                bytes.push(b'\n');
+            }
+        }
+        // Written by the caller.
+        Format::Parquet | Format::Avro | Format::Template => {}
+    }
+    bytes
+}
+
+/// Renders a row into a line of the template format.
+fn render_line(template: &Template, row: &Row) -> String {
+    let mut line = template.render_text(&|name| row.json_value(name));
+    line.push('\n');
+    line
+}
+
 /*
 impl AnyDirectUpdate for FileRunner { }
 
@@ -287,3 +585,50 @@ impl fmt::Debug for FileRunner {
     }
 }
 */
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mk_config(extra: &str) -> Config {
+        toml::from_str(&format!(
+            "format = \"template\"\nfilename = \"/dev/stdout\"\n{extra}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn template_is_checked() {
+        assert!(mk_config("").template().is_err());
+        assert_eq!(
+            mk_config("template = \"{{ nexthop }}\"")
+                .template()
+                .unwrap_err(),
+            "unknown placeholder '{{nexthop}}'"
+        );
+    }
+
+    #[test]
+    fn rows_are_rendered() {
+        let template = mk_config(
+            "template = \"{{kind}} route {{prefix}} next-hop self \
+            as-path [ {{as_path}} ]{{custom}}\"",
+        )
+        .template()
+        .unwrap()
+        .unwrap();
+        let row = Row {
+            kind: "announce",
+            prefix: Some("192.0.2.0/24".into()),
+            as_path: Some(vec![65000, 65001]),
+            ..Default::default()
+        };
+        assert_eq!(
+            render_line(&template, &row),
+            "announce route 192.0.2.0/24 next-hop self \
+            as-path [ 65000 65001 ]\n"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Formatting rows as GELF messages.
+//!
+//! A message is a JSON object like this:
+//!
+//! ```json
+//! {
+//!   "version": "1.1",
+//!   "host": "rr1",
+//!   "short_message": "Peer 192.0.2.1 AS65000 went down",
+//!   "timestamp": 1732190400.0,
+//!   "level": 4,
+//!   "_kind": "peer_down",
+//!   "_topic": "session",
+//!   "_peer_ip": "192.0.2.1",
+//!   "_peer_as": 65000
+//! }
+//! ```
+//!
+//! The level is the syslog severity of the row. The columns of the row that
+//! have a value become additional fields, with the elements of lists
+//! separated by spaces as GELF only allows strings and numbers, followed by
+//! the configured additional fields.
+//!
+//! Over UDP, messages that don't fit into a datagram of `chunk_size` bytes
+//! are split into up to 128 chunks, as described in [`chunks`].
+
+use serde_json::{Map, Value as Json};
+
+use crate::targets::{
+    file::row::{Row, Value, COLUMNS},
+    syslog::message::{text, Severity},
+};
+
+/// The magic bytes starting a chunk.
+const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+
+/// The length of the header of a chunk.
+const CHUNK_HEADER_LEN: usize = 12;
+
+/// The largest number of chunks of a message.
+const MAX_CHUNKS: usize = 128;
+
+//------------ MessageFormat -------------------------------------------------
+
+/// The parts of the messages that are the same for all rows.
+#[derive(Clone, Debug)]
+pub struct MessageFormat {
+    pub host: String,
+
+    /// The additional fields added to every message, with their
+    /// underscores.
+    pub additional_fields: Map<String, Json>,
+}
+
+impl MessageFormat {
+    /// Returns the message for a row.
+    pub fn format(&self, row: &Row, level: Severity) -> Vec<u8> {
+        let mut message = Map::new();
+        message.insert("version".into(), "1.1".into());
+        message.insert("host".into(), self.host.as_str().into());
+        message.insert("short_message".into(), text(row).into());
+        let micros = row.timestamp.timestamp_micros();
+        message.insert("timestamp".into(), (micros as f64 / 1e6).into());
+        message.insert("level".into(), (level as u8).into());
+        for (index, column) in COLUMNS.iter().enumerate() {
+            let value = match row.value(index) {
+                Value::Null | Value::Timestamp(_) => continue,
+                Value::Long(value) => value.into(),
+                Value::String(value) => value.into(),
+                Value::LongList(values) => values
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .into(),
+                Value::StringList(values) => values.join(" ").into(),
+            };
+            message.insert(format!("_{}", column.name), value);
+        }
+        message.extend(self.additional_fields.clone());
+        serde_json::to_vec(&message).unwrap_or_default()
+    }
+}
+
+/// Checks the name of an additional field, without its underscore.
+pub fn check_field_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || name == "id"
+        || !name.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')
+        })
+    {
+        return Err(format!("invalid additional field name '{name}'"));
+    }
+    if COLUMNS.iter().any(|column| column.name == name) {
+        return Err(format!("additional field '{name}' is a column"));
+    }
+    Ok(())
+}
+
+/// Returns the datagrams of a message sent over UDP.
+///
+/// A message of up to `chunk_size` bytes is sent as is. Larger messages
+/// are split into chunks of that size, each starting with the magic bytes,
+/// the message `id`, and the sequence number and count of the chunk.
+/// Returns `None` if that takes more than 128 chunks.
+pub fn chunks(
+    message: &[u8],
+    chunk_size: usize,
+    id: [u8; 8],
+) -> Option<Vec<Vec<u8>>> {
+    if message.len() <= chunk_size {
+        return Some(vec![message.to_vec()]);
+    }
+    let data_size = chunk_size.checked_sub(CHUNK_HEADER_LEN)?.max(1);
+    let count = message.len().div_ceil(data_size);
+    if count > MAX_CHUNKS {
+        return None;
+    }
+    let res = message
+        .chunks(data_size)
+        .enumerate()
+        .map(|(seq, data)| {
+            let mut chunk = Vec::with_capacity(CHUNK_HEADER_LEN + data.len());
+            chunk.extend_from_slice(&CHUNK_MAGIC);
+            chunk.extend_from_slice(&id);
+            chunk.push(seq as u8);
+            chunk.push(count as u8);
+            chunk.extend_from_slice(data);
+            chunk
+        })
+        .collect();
+    Some(res)
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn rows_become_messages() {
+        let format = MessageFormat {
+            host: "rr1".into(),
+            additional_fields: [("_environment".into(), "lab".into())]
+                .into_iter()
+                .collect(),
+        };
+        let row = Row {
+            timestamp: DateTime::from_timestamp_micros(1_500_000).unwrap(),
+            topic: "hijack".into(),
+            kind: "log",
+            as_path: Some(vec![65000, 65001]),
+            origin_as: Some(65001),
+            custom: Some("unexpected origin".into()),
+            ..Default::default()
+        };
+        let message: Json =
+            serde_json::from_slice(&format.format(&row, Severity::Warning))
+                .unwrap();
+        assert_eq!(
+            message,
+            json!({
+                "version": "1.1",
+                "host": "rr1",
+                "short_message": "unexpected origin",
+                "timestamp": 1.5,
+                "level": 4,
+                "_topic": "hijack",
+                "_kind": "log",
+                "_origin_as": 65001,
+                "_as_path": "65000 65001",
+                "_custom": "unexpected origin",
+                "_environment": "lab",
+            })
+        );
+    }
+
+    #[test]
+    fn field_names_are_checked() {
+        assert!(check_field_name("environment").is_ok());
+        assert!(check_field_name("site.name-1").is_ok());
+        assert!(check_field_name("id").is_err());
+        assert!(check_field_name("peer_ip").is_err());
+        assert!(check_field_name("with space").is_err());
+    }
+
+    #[test]
+    fn large_messages_are_chunked() {
+        let id = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(chunks(b"short", 20, id).unwrap(), [b"short"]);
+
+        let message = (0..20).collect::<Vec<u8>>();
+        let res = chunks(&message, 20, id).unwrap();
+        assert_eq!(res.len(), 1);
+        let res = chunks(&message, 19, id).unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0][..12], [0x1e, 0x0f, 1, 2, 3, 4, 5, 6, 7, 8, 0, 3]);
+        assert_eq!(res[0][12..], [0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(res[2][10..], [2, 3, 14, 15, 16, 17, 18, 19]);
+
+        assert!(chunks(&[0; 129], 13, id).is_none());
+        assert_eq!(chunks(&[0; 128], 13, id).unwrap().len(), 128);
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct GelfMetrics {
+    pub connected: AtomicBool,
+    pub sent_count: AtomicUsize,
+    pub dropped_count: AtomicUsize,
+    pub connection_error_count: AtomicUsize,
+    pub queued_count: AtomicUsize,
+}
+
+impl GraphStatus for GelfMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "sent: {}\nqueued: {}\ndropped: {}",
+            self.sent_count.load(SeqCst),
+            self.queued_count.load(SeqCst),
+            self.dropped_count.load(SeqCst),
+        )
+    }
+
+    fn okay(&self) -> Option<bool> {
+        Some(self.connected.load(SeqCst))
+    }
+}
+
+impl GelfMetrics {
+    const CONNECTED_METRIC: Metric = Metric::new(
+        "gelf_target_connected",
+        "whether messages can be sent to the GELF server: 0=no, 1=yes",
+        MetricType::Gauge,
+        MetricUnit::State,
+    );
+    const SENT_COUNT_METRIC: Metric = Metric::new(
+        "gelf_target_sent_count",
+        "the number of messages sent to the GELF server",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DROPPED_COUNT_METRIC: Metric = Metric::new(
+        "gelf_target_dropped_count",
+        "the number of messages dropped because the queue was full or \
+        they were too large",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const CONNECTION_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "gelf_target_connection_error_count",
+        "the number of times connecting or sending to the server failed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const QUEUED_COUNT_METRIC: Metric = Metric::new(
+        "gelf_target_queued_count",
+        "the number of messages waiting to be sent",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for GelfMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::CONNECTED_METRIC,
+            Some(unit_name),
+            u8::from(self.connected.load(SeqCst)),
+        );
+        target.append_simple(
+            &Self::SENT_COUNT_METRIC,
+            Some(unit_name),
+            self.sent_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DROPPED_COUNT_METRIC,
+            Some(unit_name),
+            self.dropped_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::CONNECTION_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.connection_error_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::QUEUED_COUNT_METRIC,
+            Some(unit_name),
+            self.queued_count.load(SeqCst),
+        );
+    }
+}
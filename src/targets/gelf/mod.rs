@@ -0,0 +1,3 @@
+mod message;
+mod metrics;
+pub mod target;
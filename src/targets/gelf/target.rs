@@ -0,0 +1,550 @@
+//! Sending events to Graylog.
+//!
+//! The `gelf-out` target turns the events it receives into GELF messages
+//! as described in the [`message`] module, and sends them to the server at
+//! `url`: over UDP with `udp://`, chunked as needed and compressed with
+//! gzip if `compression` says so, or over TCP with `tcp://` or TLS with
+//! `tls://`, each message followed by a null byte. TLS is configured in the
+//! `tls` table, as for the `mqtt-out` target.
+//!
+//! Events are selected with `events` and `topics` and queued while the
+//! server is unreachable as for the `syslog-out` target, with their level
+//! set per kind in `levels`. Messages too large to be sent in 128 chunks
+//! are dropped.
+//!
+//! [`message`]: super::message
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::Duration,
+};
+
+use flate2::write::GzEncoder;
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use url::Url;
+
+use crate::{
+    common::tls::TlsClientConfig,
+    comms::{Link, Terminated, UnitStatus},
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    payload::Update,
+    targets::syslog::{
+        message::Severity,
+        target::{system_hostname, Connection, Destination, Events, KINDS},
+    },
+};
+
+use super::{
+    message::{check_field_name, chunks, MessageFormat},
+    metrics::GelfMetrics,
+};
+
+/// How long to keep trying to send the queued messages when stopping.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct Gelf {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The server to send to, a `udp://`, `tcp://` or `tls://` URL.
+    url: Url,
+
+    #[serde(default)]
+    tls: Option<TlsClientConfig>,
+
+    /// The kinds of rows to send.
+    #[serde(default = "Config::default_events")]
+    events: Vec<String>,
+
+    /// The topics of rows to send, all if not given.
+    #[serde(default)]
+    topics: Option<Vec<String>>,
+
+    /// The level of messages by kind of row, if not the default.
+    #[serde(default)]
+    levels: HashMap<String, Severity>,
+
+    /// The host in the messages, the name of the system by default.
+    #[serde(default)]
+    host: Option<String>,
+
+    /// The fields added to every message, without their underscore.
+    #[serde(default)]
+    additional_fields: HashMap<String, String>,
+
+    /// The largest datagram to send over UDP.
+    #[serde(default = "Config::default_chunk_size")]
+    chunk_size: usize,
+
+    /// The compression of messages sent over UDP.
+    #[serde(default)]
+    compression: Compression,
+
+    /// How many messages may wait to be sent.
+    #[serde(default = "Config::default_queue_size")]
+    queue_size: usize,
+
+    /// How long to wait before connecting again. The delay doubles with
+    /// every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_retry_delay_secs")]
+    retry_delay_secs: Duration,
+
+    /// The longest to wait before connecting again.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_max_retry_delay_secs")]
+    max_retry_delay_secs: Duration,
+}
+
+impl Config {
+    fn default_events() -> Vec<String> {
+        ["peer_up", "peer_down", "log", "custom"]
+            .map(String::from)
+            .into()
+    }
+
+    fn default_chunk_size() -> usize {
+        // What Graylog suggests for networks beyond the local one.
+        1420
+    }
+
+    fn default_queue_size() -> usize {
+        1000
+    }
+
+    fn default_retry_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retry_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if let Some(kind) = self
+            .events
+            .iter()
+            .chain(self.levels.keys())
+            .find(|kind| !KINDS.contains(&kind.as_str()))
+        {
+            return Err(format!("unknown event '{kind}'"));
+        }
+        for name in self.additional_fields.keys() {
+            check_field_name(name)?;
+        }
+        // A chunk needs room for its header and some data.
+        if self.chunk_size <= 12 {
+            return Err("chunk_size must be larger than 12".into());
+        }
+        if self.queue_size == 0 {
+            return Err("queue_size must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+}
+
+impl Gelf {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let config = self.config;
+        let destination = match config.check().and_then(|_| {
+            Destination::with_ports(
+                &config.url,
+                config.tls.as_ref(),
+                [12201; 3],
+            )
+        }) {
+            Ok(destination) => destination,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+
+        let metrics = Arc::new(GelfMetrics::default());
+        component.register_metrics(metrics.clone());
+        let format = MessageFormat {
+            host: config.host.unwrap_or_else(system_hostname),
+            additional_fields: config
+                .additional_fields
+                .into_iter()
+                .map(|(name, value)| (format!("_{name}"), value.into()))
+                .collect(),
+        };
+        let sender = Sender {
+            name: component.name().to_string(),
+            destination,
+            chunk_size: config.chunk_size,
+            compression: config.compression,
+            retry_delay: config.retry_delay_secs,
+            max_retry_delay: config.max_retry_delay_secs,
+            metrics: metrics.clone(),
+        };
+        GelfRunner {
+            events: Events::default(),
+            selected: config.events,
+            topics: config.topics,
+            levels: config.levels,
+            format,
+            ingresses: component.ingresses().clone(),
+            metrics,
+            queue_size: config.queue_size,
+        }
+        .run(sender, self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ GelfRunner ----------------------------------------------------
+
+struct GelfRunner {
+    events: Events,
+    selected: Vec<String>,
+    topics: Option<Vec<String>>,
+    levels: HashMap<String, Severity>,
+    format: MessageFormat,
+    ingresses: Arc<ingress::Register>,
+    metrics: Arc<GelfMetrics>,
+    queue_size: usize,
+}
+
+impl GelfRunner {
+    async fn run(
+        mut self,
+        sender: Sender,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        // Messages are sent by a task of their own, so that an unreachable
+        // server does not hold up the sources.
+        let (tx, rx) = mpsc::channel(self.queue_size);
+        let send_task = tokio::spawn(sender.run(rx));
+
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the gelf-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        for message in self.messages(update) {
+                            if tx.try_send(message).is_ok() {
+                                self.metrics
+                                    .queued_count
+                                    .fetch_add(1, SeqCst);
+                            } else {
+                                self.metrics
+                                    .dropped_count
+                                    .fetch_add(1, SeqCst);
+                            }
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of gelf-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+            }
+        }
+
+        drop(tx);
+        if tokio::time::timeout(DRAIN_TIMEOUT, send_task)
+            .await
+            .is_err()
+        {
+            warn!(
+                "Dropping {} GELF messages that could not be sent",
+                self.metrics.queued_count.load(SeqCst)
+            );
+        }
+        Err(Terminated)
+    }
+
+    /// Returns the messages for the selected events of an update.
+    fn messages(&mut self, update: Update) -> Vec<Vec<u8>> {
+        self.events
+            .rows(update, &self.ingresses)
+            .into_iter()
+            .filter(|row| {
+                self.selected.iter().any(|kind| kind == row.kind)
+                    && self
+                        .topics
+                        .as_ref()
+                        .is_none_or(|topics| topics.contains(&row.topic))
+            })
+            .map(|row| {
+                let level = self
+                    .levels
+                    .get(row.kind)
+                    .copied()
+                    .unwrap_or_else(|| Severity::for_kind(row.kind));
+                self.format.format(&row, level)
+            })
+            .collect()
+    }
+}
+
+//------------ Sender --------------------------------------------------------
+
+/// Sends queued messages, connecting to the server as needed.
+struct Sender {
+    name: String,
+    destination: Destination,
+    chunk_size: usize,
+    compression: Compression,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    metrics: Arc<GelfMetrics>,
+}
+
+impl Sender {
+    /// Sends messages until there are no more.
+    async fn run(self, mut rx: mpsc::Receiver<Vec<u8>>) {
+        let mut connection = None;
+        let mut delay = self.retry_delay;
+        while let Some(message) = rx.recv().await {
+            let Some(frames) = self.frames(message) else {
+                self.metrics.queued_count.fetch_sub(1, SeqCst);
+                self.metrics.dropped_count.fetch_add(1, SeqCst);
+                warn!("Target {}: dropping a message too large", self.name);
+                continue;
+            };
+            loop {
+                let conn = match &mut connection {
+                    Some(conn) => conn,
+                    None => match self.destination.connect().await {
+                        Ok(conn) => {
+                            info!(
+                                "Target {}: connected to {}:{}",
+                                self.name,
+                                self.destination.host,
+                                self.destination.port
+                            );
+                            self.metrics.connected.store(true, SeqCst);
+                            delay = self.retry_delay;
+                            connection.insert(conn)
+                        }
+                        Err(err) => {
+                            self.failed("connecting", err);
+                            tokio::time::sleep(delay).await;
+                            delay = (delay * 2).min(self.max_retry_delay);
+                            continue;
+                        }
+                    },
+                };
+                match send(conn, &frames).await {
+                    Ok(()) => {
+                        self.metrics.queued_count.fetch_sub(1, SeqCst);
+                        self.metrics.sent_count.fetch_add(1, SeqCst);
+                        break;
+                    }
+                    Err(err) => {
+                        connection = None;
+                        self.failed("sending", err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns what to send for a message.
+    ///
+    /// Over UDP, these are the datagrams, or `None` if the message is too
+    /// large. Over TCP, the message is followed by a null byte.
+    fn frames(&self, mut message: Vec<u8>) -> Option<Vec<Vec<u8>>> {
+        if !self.destination.is_udp() {
+            message.push(0);
+            return Some(vec![message]);
+        }
+        if self.compression == Compression::Gzip {
+            let mut encoder =
+                GzEncoder::new(Vec::new(), flate2::Compression::default());
+            // Writing to a vec does not fail.
+            let _ = encoder.write_all(&message);
+            message = encoder.finish().unwrap_or_default();
+        }
+        let id = uuid::Uuid::new_v4().as_bytes()[..8].try_into().unwrap();
+        chunks(&message, self.chunk_size, id)
+    }
+
+    fn failed(&self, action: &str, err: io::Error) {
+        self.metrics.connected.store(false, SeqCst);
+        self.metrics.connection_error_count.fetch_add(1, SeqCst);
+        warn!(
+            "Target {}: {action} to {}:{} failed: {err}",
+            self.name, self.destination.host, self.destination.port
+        );
+    }
+}
+
+async fn send(conn: &mut Connection, frames: &[Vec<u8>]) -> io::Result<()> {
+    match conn {
+        Connection::Udp(socket) => {
+            for frame in frames {
+                socket.send(frame).await?;
+            }
+        }
+        Connection::Stream(stream) => {
+            for frame in frames {
+                stream.write_all(frame).await?;
+            }
+            stream.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use tokio::{
+        io::AsyncReadExt,
+        net::{TcpListener, UdpSocket},
+    };
+
+    use super::*;
+
+    fn mk_sender(url: &str, chunk_size: usize) -> Sender {
+        Sender {
+            name: "gelf".into(),
+            destination: Destination::with_ports(
+                &url.parse().unwrap(),
+                None,
+                [12201; 3],
+            )
+            .unwrap(),
+            chunk_size,
+            compression: Compression::None,
+            retry_delay: Duration::from_millis(10),
+            max_retry_delay: Duration::from_millis(10),
+            metrics: Default::default(),
+        }
+    }
+
+    async fn send(sender: Sender, messages: &[&[u8]]) {
+        let (tx, rx) = mpsc::channel(10);
+        for message in messages {
+            sender.metrics.queued_count.fetch_add(1, SeqCst);
+            tx.send(message.to_vec()).await.unwrap();
+        }
+        drop(tx);
+        sender.run(rx).await;
+    }
+
+    #[test]
+    fn config_is_checked() {
+        let config = |extra: &str| -> Config {
+            toml::from_str(&format!("url = \"udp://localhost\"\n{extra}"))
+                .unwrap()
+        };
+        assert!(config("levels = { peer_up = \"info\" }").check().is_ok());
+        assert!(config("events = [\"up\"]").check().is_err());
+        assert!(config("chunk_size = 12").check().is_err());
+        assert!(config("additional_fields = { _id = \"x\" }")
+            .check()
+            .is_ok());
+        assert!(config("additional_fields = { id = \"x\" }")
+            .check()
+            .is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn messages_are_chunked_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut sender =
+            mk_sender(&format!("udp://{}", server.local_addr().unwrap()), 20);
+        let metrics = sender.metrics.clone();
+        send(sender, &[b"{\"a\":1}", &[b'x'; 21], &[b'x'; 2000]]).await;
+
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"{\"a\":1}");
+        for seq in 0..3 {
+            let len = server.recv(&mut buf).await.unwrap();
+            assert_eq!(buf[..2], [0x1e, 0x0f]);
+            assert_eq!(buf[10..12], [seq, 3]);
+            assert_eq!(len, if seq < 2 { 20 } else { 17 });
+        }
+        assert_eq!(metrics.sent_count.load(SeqCst), 2);
+        assert_eq!(metrics.dropped_count.load(SeqCst), 1);
+        assert_eq!(metrics.queued_count.load(SeqCst), 0);
+
+        sender = mk_sender("udp://localhost", 1420);
+        sender.compression = Compression::Gzip;
+        let frames = sender.frames(b"{\"a\":1}".to_vec()).unwrap();
+        let mut message = String::new();
+        GzDecoder::new(frames[0].as_slice())
+            .read_to_string(&mut message)
+            .unwrap();
+        assert_eq!(message, "{\"a\":1}");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn messages_are_delimited_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sender = mk_sender(
+            &format!("tcp://{}", listener.local_addr().unwrap()),
+            20,
+        );
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            stream.read_to_end(&mut buf).await.unwrap();
+            buf
+        });
+        send(sender, &[b"{\"a\":1}", &[b'x'; 30]]).await;
+
+        let mut expected = b"{\"a\":1}\0".to_vec();
+        expected.extend_from_slice(&[b'x'; 30]);
+        expected.push(0);
+        assert_eq!(server.await.unwrap(), expected);
+    }
+}
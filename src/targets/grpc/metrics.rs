@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct GrpcMetrics {
+    pub client_count: AtomicUsize,
+    pub connection_count: AtomicUsize,
+    pub refused_count: AtomicUsize,
+    pub sent_count: AtomicUsize,
+    pub dropped_count: AtomicUsize,
+}
+
+impl GraphStatus for GrpcMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "clients: {}\nsent: {}\ndropped: {}",
+            self.client_count.load(SeqCst),
+            self.sent_count.load(SeqCst),
+            self.dropped_count.load(SeqCst),
+        )
+    }
+}
+
+impl GrpcMetrics {
+    const CLIENT_COUNT_METRIC: Metric = Metric::new(
+        "grpc_target_client_count",
+        "the number of clients currently subscribed",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const CONNECTION_COUNT_METRIC: Metric = Metric::new(
+        "grpc_target_connection_count",
+        "the number of subscriptions made by clients",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const REFUSED_COUNT_METRIC: Metric = Metric::new(
+        "grpc_target_refused_count",
+        "the number of subscriptions refused because there were too many \
+        clients",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const SENT_COUNT_METRIC: Metric = Metric::new(
+        "grpc_target_sent_count",
+        "the number of routes sent to clients",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DROPPED_COUNT_METRIC: Metric = Metric::new(
+        "grpc_target_dropped_count",
+        "the number of routes clients missed because they were too slow",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for GrpcMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::CLIENT_COUNT_METRIC,
+            Some(unit_name),
+            self.client_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::CONNECTION_COUNT_METRIC,
+            Some(unit_name),
+            self.connection_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::REFUSED_COUNT_METRIC,
+            Some(unit_name),
+            self.refused_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::SENT_COUNT_METRIC,
+            Some(unit_name),
+            self.sent_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DROPPED_COUNT_METRIC,
+            Some(unit_name),
+            self.dropped_count.load(SeqCst),
+        );
+    }
+}
@@ -0,0 +1,3 @@
+mod metrics;
+mod proto;
+pub mod target;
@@ -0,0 +1,278 @@
+//! The messages of the `rotonda.stream.v1` gRPC service.
+//!
+//! The schema is in `proto/stream.proto`. Like those of the `grpc-in`
+//! unit, its messages are declared with the derive macros of [prost].
+//!
+//! [prost]: https://docs.rs/prost/
+
+use std::net::IpAddr;
+
+use inetnum::addr::Prefix;
+use prost::Message;
+
+use crate::targets::{file::row::Row, websocket::filter::Filter};
+
+/// The path of the `Subscribe` method.
+pub const SUBSCRIBE_PATH: &str = "/rotonda.stream.v1.RouteStream/Subscribe";
+
+//------------ SubscribeRequest ----------------------------------------------
+
+#[derive(Clone, PartialEq, Message)]
+pub struct SubscribeRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub filters: Vec<ApiFilter>,
+}
+
+/// A filter as sent by a client.
+#[derive(Clone, PartialEq, Message)]
+pub struct ApiFilter {
+    #[prost(string, tag = "1")]
+    pub prefix: String,
+
+    #[prost(bool, optional, tag = "2")]
+    pub more_specific: Option<bool>,
+
+    #[prost(bool, tag = "3")]
+    pub less_specific: bool,
+
+    #[prost(uint32, tag = "4")]
+    pub asn: u32,
+
+    #[prost(uint32, tag = "5")]
+    pub origin_as: u32,
+
+    #[prost(uint32, tag = "6")]
+    pub peer_as: u32,
+
+    #[prost(string, tag = "7")]
+    pub peer_ip: String,
+
+    #[prost(string, tag = "8")]
+    pub community: String,
+
+    #[prost(string, repeated, tag = "9")]
+    pub kinds: Vec<String>,
+
+    #[prost(string, repeated, tag = "10")]
+    pub topics: Vec<String>,
+}
+
+impl SubscribeRequest {
+    /// Returns the filters of the request.
+    pub fn into_filters(self) -> Result<Vec<Filter>, String> {
+        self.filters
+            .into_iter()
+            .map(ApiFilter::into_filter)
+            .collect()
+    }
+}
+
+impl ApiFilter {
+    /// Returns the filter, with empty and zero fields matching anything.
+    fn into_filter(self) -> Result<Filter, String> {
+        let mut res = Filter::default();
+        if !self.prefix.is_empty() {
+            let prefix = self.prefix;
+            res.prefix = vec![prefix
+                .parse::<Prefix>()
+                .map_err(|_| format!("invalid prefix '{prefix}'"))?];
+        }
+        if let Some(more_specific) = self.more_specific {
+            res.more_specific = more_specific;
+        }
+        res.less_specific = self.less_specific;
+        res.asn = asn(self.asn);
+        res.origin_as = asn(self.origin_as);
+        res.peer_as = asn(self.peer_as);
+        if !self.peer_ip.is_empty() {
+            let peer_ip = self.peer_ip;
+            res.peer_ip = vec![peer_ip
+                .parse::<IpAddr>()
+                .map_err(|_| format!("invalid peer_ip '{peer_ip}'"))?];
+        }
+        if !self.community.is_empty() {
+            res.community = vec![self.community];
+        }
+        res.kinds = Some(self.kinds).filter(|kinds| !kinds.is_empty());
+        res.topics = Some(self.topics).filter(|topics| !topics.is_empty());
+        Ok(res)
+    }
+}
+
+/// Returns the AS numbers to match, none for zero meaning any AS.
+fn asn(asn: u32) -> Vec<u32> {
+    Some(asn).filter(|asn| *asn != 0).into_iter().collect()
+}
+
+//------------ RouteEvent ----------------------------------------------------
+
+#[derive(Clone, PartialEq, Message)]
+pub struct RouteEvent {
+    #[prost(oneof = "RouteEventKind", tags = "1, 2")]
+    pub event: Option<RouteEventKind>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum RouteEventKind {
+    #[prost(message, tag = "1")]
+    Route(Route),
+
+    #[prost(message, tag = "2")]
+    Dropped(Dropped),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Route {
+    #[prost(int64, tag = "1")]
+    pub timestamp: i64,
+
+    #[prost(string, tag = "2")]
+    pub topic: String,
+
+    #[prost(string, tag = "3")]
+    pub kind: String,
+
+    #[prost(string, tag = "4")]
+    pub prefix: String,
+
+    #[prost(uint32, optional, tag = "5")]
+    pub origin_as: Option<u32>,
+
+    #[prost(uint32, repeated, tag = "6")]
+    pub as_path: Vec<u32>,
+
+    #[prost(string, repeated, tag = "7")]
+    pub communities: Vec<String>,
+
+    #[prost(string, tag = "8")]
+    pub peer_ip: String,
+
+    #[prost(uint32, optional, tag = "9")]
+    pub peer_as: Option<u32>,
+
+    #[prost(string, tag = "10")]
+    pub custom: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct Dropped {
+    #[prost(uint64, tag = "1")]
+    pub count: u64,
+}
+
+impl RouteEvent {
+    /// Returns the event for a row, with the columns it lacks left out.
+    pub fn route(row: &Row) -> Self {
+        let route = Route {
+            timestamp: row.timestamp.timestamp_micros(),
+            topic: row.topic.clone(),
+            kind: row.kind.into(),
+            prefix: row.prefix.clone().unwrap_or_default(),
+            origin_as: row.origin_as,
+            as_path: row.as_path.clone().unwrap_or_default(),
+            communities: row.communities.clone().unwrap_or_default(),
+            peer_ip: row.peer_ip.clone().unwrap_or_default(),
+            peer_as: row.peer_as,
+            custom: row.custom.clone().unwrap_or_default(),
+        };
+        Self {
+            event: Some(RouteEventKind::Route(route)),
+        }
+    }
+
+    /// Returns the event telling a client it missed `count` routes.
+    pub fn dropped(count: u64) -> Self {
+        Self {
+            event: Some(RouteEventKind::Dropped(Dropped { count })),
+        }
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+
+    #[test]
+    fn subscribe_requests_are_converted() {
+        let filter = ApiFilter {
+            prefix: "198.51.100.0/24".into(),
+            more_specific: Some(false),
+            origin_as: 65001,
+            peer_ip: "2001:db8::1".into(),
+            kinds: vec!["announce".into(), "withdraw".into()],
+            ..Default::default()
+        };
+        let request = SubscribeRequest {
+            filters: vec![filter, ApiFilter::default()],
+        };
+        // Unknown fields are skipped.
+        let mut encoded = request.encode_to_vec();
+        encoded.extend_from_slice(&[0x58, 0x01]);
+        let request = SubscribeRequest::decode(&encoded[..]).unwrap();
+
+        let filters = request.into_filters().unwrap();
+        assert_eq!(filters.len(), 2);
+        let filter = &filters[0];
+        assert_eq!(filter.prefix, ["198.51.100.0/24".parse().unwrap()]);
+        assert!(!filter.more_specific);
+        assert!(filter.asn.is_empty());
+        assert_eq!(filter.origin_as, [65001]);
+        assert_eq!(
+            filter.peer_ip,
+            ["2001:db8::1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            filter.kinds.as_deref(),
+            Some(&["announce".to_string(), "withdraw".to_string()][..])
+        );
+        assert_eq!(filters[1], Filter::default());
+
+        let request = SubscribeRequest {
+            filters: vec![ApiFilter {
+                prefix: "198.51.100.0".into(),
+                ..Default::default()
+            }],
+        };
+        assert!(request.into_filters().is_err());
+    }
+
+    #[test]
+    fn route_events_are_encoded() {
+        let row = Row {
+            timestamp: DateTime::from_timestamp_micros(1).unwrap(),
+            topic: "bgp".into(),
+            kind: "announce",
+            prefix: Some("10.0.0.0/8".into()),
+            origin_as: Some(0),
+            as_path: Some(vec![300, 0]),
+            communities: Some(vec!["NO_EXPORT".into()]),
+            ..Default::default()
+        };
+        let mut route = vec![
+            0x08, 0x01, // timestamp
+            0x12, 0x03, b'b', b'g', b'p', // topic
+            0x1a, 0x08, // kind
+        ];
+        route.extend_from_slice(b"announce");
+        route.extend_from_slice(&[0x22, 0x0a]); // prefix
+        route.extend_from_slice(b"10.0.0.0/8");
+        route.extend_from_slice(&[
+            0x28, 0x00, // origin_as, present although zero
+            0x32, 0x03, 0xac, 0x02, 0x00, // as_path, packed
+            0x3a, 0x09, // communities
+        ]);
+        route.extend_from_slice(b"NO_EXPORT");
+        let mut expected = vec![0x0a, route.len() as u8];
+        expected.extend_from_slice(&route);
+        assert_eq!(RouteEvent::route(&row).encode_to_vec(), expected);
+
+        assert_eq!(
+            RouteEvent::dropped(300).encode_to_vec(),
+            [0x12, 0x03, 0x08, 0xac, 0x02]
+        );
+    }
+}
@@ -0,0 +1,605 @@
+//! Streaming routes to gRPC clients.
+//!
+//! The `grpc-out` target runs a gRPC server on `listen` with the
+//! `rotonda.stream.v1.RouteStream` service, whose schema ships with Rotonda
+//! in `proto/stream.proto`. Clients call `Subscribe` with a list of filters,
+//! the same as those of the `websocket-out` target described in the
+//! [`filter`] module, and receive each route and event matching any of
+//! them, or all of them if there are none, as a `Route` message with the
+//! columns of the [`row`] module, for as long as the call lasts.
+//!
+//! Sending to a client is subject to HTTP/2 flow control: a client reading
+//! slowly holds back the routes sent to it, and can fall behind by up to
+//! `queue_size` routes. A client falling behind further misses routes,
+//! which it is told about with a `Dropped` message. At most `max_clients`
+//! calls can be open at the same time; further calls fail with
+//! `RESOURCE_EXHAUSTED`. When the target stops, open calls end with
+//! `UNAVAILABLE`.
+//!
+//! With `tls`, clients connect over TLS 1.3, negotiating HTTP/2 with ALPN,
+//! and may be required to present a client certificate.
+//!
+//! [`filter`]: crate::targets::websocket::filter
+//! [`row`]: crate::targets::file::row
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::Duration,
+};
+
+use futures::{
+    future::{self, Ready},
+    stream::BoxStream,
+    StreamExt,
+};
+use hyper::{
+    server::conn::Http, service::service_fn, Body, Method, Request, Response,
+    StatusCode,
+};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, mpsc},
+    time::timeout,
+};
+use tonic::{
+    body::{empty_body, BoxBody},
+    codec::ProstCodec,
+    server::{Grpc, ServerStreamingService},
+    Status,
+};
+
+use crate::{
+    common::tls::{TlsAcceptor, TlsServerConfig},
+    comms::{Link, Terminated, UnitStatus},
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    targets::{file::row::Row, websocket::filter::Filter},
+};
+
+use super::{
+    metrics::GrpcMetrics,
+    proto::{RouteEvent, SubscribeRequest, SUBSCRIBE_PATH},
+};
+
+/// The largest `SubscribeRequest` accepted, in bytes.
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// How often to ping clients, dropping the connection if they don't answer
+/// within the same time.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a client may take for the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct GrpcOut {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The address to listen on for clients.
+    listen: SocketAddr,
+
+    /// The certificate and key to accept clients over TLS with, and
+    /// optionally the CAs client certificates must be issued by.
+    #[serde(default)]
+    tls: Option<TlsServerConfig>,
+
+    /// The most calls open at the same time.
+    #[serde(default = "Config::default_max_clients")]
+    max_clients: usize,
+
+    /// The most filters in a single call.
+    #[serde(default = "Config::default_max_filters")]
+    max_filters: usize,
+
+    /// How many routes a client can fall behind.
+    #[serde(default = "Config::default_queue_size")]
+    queue_size: usize,
+}
+
+impl Config {
+    fn default_max_clients() -> usize {
+        100
+    }
+
+    fn default_max_filters() -> usize {
+        32
+    }
+
+    fn default_queue_size() -> usize {
+        1000
+    }
+}
+
+impl GrpcOut {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let config = self.config;
+        if config.queue_size == 0 {
+            error!(
+                "Target {}: queue_size must be at least 1",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+        let tls = match config.tls.as_ref().map(TlsAcceptor::new).transpose()
+        {
+            Ok(tls) => tls.map(|tls| Arc::new(tls.with_alpn(&[b"h2"]))),
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+        let listener = match TcpListener::bind(config.listen).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!(
+                    "Target {}: cannot listen on {}: {err}",
+                    component.name(),
+                    config.listen
+                );
+                return Err(Terminated);
+            }
+        };
+        info!(
+            "Target {}: listening for gRPC clients on {}",
+            component.name(),
+            config.listen
+        );
+
+        let metrics = Arc::new(GrpcMetrics::default());
+        component.register_metrics(metrics.clone());
+        GrpcRunner::new(
+            listener,
+            tls,
+            &config,
+            component.ingresses().clone(),
+            metrics,
+        )
+        .run(self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ GrpcRunner ----------------------------------------------------
+
+/// A row along with its `RouteEvent`, shared by all clients.
+#[derive(Debug)]
+struct Event {
+    row: Row,
+    message: RouteEvent,
+}
+
+impl Event {
+    fn new(row: Row) -> Self {
+        let message = RouteEvent::route(&row);
+        Self { row, message }
+    }
+}
+
+struct GrpcRunner {
+    listener: TcpListener,
+    tls: Option<Arc<TlsAcceptor>>,
+    http: Http,
+    events: broadcast::Sender<Arc<Event>>,
+    server: Arc<GrpcServer>,
+    ingresses: Arc<ingress::Register>,
+}
+
+impl GrpcRunner {
+    fn new(
+        listener: TcpListener,
+        tls: Option<Arc<TlsAcceptor>>,
+        config: &Config,
+        ingresses: Arc<ingress::Register>,
+        metrics: Arc<GrpcMetrics>,
+    ) -> Self {
+        let mut http = Http::new();
+        http.http2_only(true)
+            .http2_keep_alive_interval(Some(PING_INTERVAL))
+            .http2_keep_alive_timeout(PING_INTERVAL);
+        let (events, _) = broadcast::channel(config.queue_size);
+        let server = Arc::new(GrpcServer {
+            events: events.downgrade(),
+            max_clients: config.max_clients,
+            max_filters: config.max_filters,
+            metrics,
+        });
+        Self {
+            listener,
+            tls,
+            http,
+            events,
+            server,
+            ingresses,
+        }
+    }
+
+    async fn run(
+        self,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the grpc-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.server.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        // Without clients, there is no need to convert rows.
+                        if self.events.receiver_count() == 0 {
+                            continue;
+                        }
+                        let rows = Row::for_update(update, &self.ingresses);
+                        for row in rows {
+                            let event = Arc::new(Event::new(row));
+                            let _ = self.events.send(event);
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of grpc-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+
+                res = self.listener.accept() => match res {
+                    Ok((stream, addr)) => self.accept(stream, addr),
+                    Err(err) => {
+                        warn!("Accepting a gRPC client failed: {err}")
+                    }
+                },
+            }
+        }
+
+        // Dropping the sender ends the calls of the clients.
+        Err(Terminated)
+    }
+
+    /// Serves the calls of a new connection.
+    fn accept(&self, stream: TcpStream, addr: SocketAddr) {
+        debug!("gRPC connection from {addr}");
+        let http = self.http.clone();
+        let tls = self.tls.clone();
+        let server = self.server.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| server.clone().handle(req));
+            let res = match tls {
+                Some(tls) => {
+                    match timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await
+                    {
+                        Ok(Ok(stream)) => {
+                            http.serve_connection(stream, service).await
+                        }
+                        Ok(Err(err)) => {
+                            debug!("TLS handshake with {addr} failed: {err}");
+                            return;
+                        }
+                        Err(_) => {
+                            debug!("TLS handshake with {addr} timed out");
+                            return;
+                        }
+                    }
+                }
+                None => http.serve_connection(stream, service).await,
+            };
+            if let Err(err) = res {
+                debug!("gRPC connection from {addr} failed: {err}");
+            }
+        });
+    }
+}
+
+//------------ GrpcServer ----------------------------------------------------
+
+/// The state shared by all connections.
+struct GrpcServer {
+    /// The rows to send, weak so that the calls end once the runner stops.
+    events: broadcast::WeakSender<Arc<Event>>,
+
+    max_clients: usize,
+    max_filters: usize,
+    metrics: Arc<GrpcMetrics>,
+}
+
+impl GrpcServer {
+    /// Handles a single gRPC call.
+    async fn handle(
+        self: Arc<Self>,
+        req: Request<Body>,
+    ) -> Result<Response<BoxBody>, Infallible> {
+        let is_grpc = req
+            .headers()
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/grpc"));
+        if !is_grpc {
+            return Ok(Response::builder()
+                .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .body(empty_body())
+                .unwrap());
+        }
+        if req.method() != Method::POST || req.uri().path() != SUBSCRIBE_PATH
+        {
+            let status = Status::unimplemented(format!(
+                "unknown method {}",
+                req.uri().path()
+            ));
+            return Ok(status.to_http());
+        }
+
+        Ok(Grpc::new(ProstCodec::default())
+            .max_decoding_message_size(MAX_REQUEST_SIZE)
+            .server_streaming(Subscribe(self), req)
+            .await)
+    }
+
+    /// Starts a subscription, returning the events to send.
+    fn subscribe(
+        &self,
+        request: SubscribeRequest,
+    ) -> Result<BoxStream<'static, Result<RouteEvent, Status>>, Status> {
+        if request.filters.len() > self.max_filters {
+            return Err(Status::invalid_argument(format!(
+                "more than {} filters",
+                self.max_filters
+            )));
+        }
+        let filters =
+            request.into_filters().map_err(Status::invalid_argument)?;
+        let Some(events) = self.events.upgrade().map(|tx| tx.subscribe())
+        else {
+            return Err(Status::unavailable("shutting down"));
+        };
+        if self.metrics.client_count.load(SeqCst) >= self.max_clients {
+            self.metrics.refused_count.fetch_add(1, SeqCst);
+            return Err(Status::resource_exhausted("too many clients"));
+        }
+        self.metrics.client_count.fetch_add(1, SeqCst);
+        self.metrics.connection_count.fetch_add(1, SeqCst);
+
+        let client = Client {
+            filters,
+            events,
+            metrics: self.metrics.clone(),
+        };
+        Ok(client.into_stream())
+    }
+}
+
+//------------ Subscribe -----------------------------------------------------
+
+/// The `Subscribe` method.
+struct Subscribe(Arc<GrpcServer>);
+
+impl ServerStreamingService<SubscribeRequest> for Subscribe {
+    type Response = RouteEvent;
+    type ResponseStream = BoxStream<'static, Result<RouteEvent, Status>>;
+    type Future =
+        Ready<Result<tonic::Response<Self::ResponseStream>, Status>>;
+
+    fn call(
+        &mut self,
+        request: tonic::Request<SubscribeRequest>,
+    ) -> Self::Future {
+        future::ready(
+            self.0
+                .subscribe(request.into_inner())
+                .map(tonic::Response::new),
+        )
+    }
+}
+
+//------------ Client --------------------------------------------------------
+
+/// The call of a client.
+///
+/// The call ends when the client goes away, as its stream is dropped then.
+struct Client {
+    filters: Vec<Filter>,
+    events: broadcast::Receiver<Arc<Event>>,
+    metrics: Arc<GrpcMetrics>,
+}
+
+impl Client {
+    /// Returns the events to send, until the target stops.
+    fn into_stream(self) -> BoxStream<'static, Result<RouteEvent, Status>> {
+        futures::stream::unfold(Some(self), |client| async move {
+            let mut client = client?;
+            loop {
+                match client.events.recv().await {
+                    Ok(event) => {
+                        if !client.matches(&event.row) {
+                            continue;
+                        }
+                        client.metrics.sent_count.fetch_add(1, SeqCst);
+                        return Some((
+                            Ok(event.message.clone()),
+                            Some(client),
+                        ));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        let dropped =
+                            usize::try_from(count).unwrap_or(usize::MAX);
+                        client
+                            .metrics
+                            .dropped_count
+                            .fetch_add(dropped, SeqCst);
+                        let dropped = RouteEvent::dropped(count);
+                        return Some((Ok(dropped), Some(client)));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        let status = Status::unavailable("target stopped");
+                        return Some((Err(status), None));
+                    }
+                }
+            }
+        })
+        .boxed()
+    }
+
+    fn matches(&self, row: &Row) -> bool {
+        self.filters.is_empty()
+            || self.filters.iter().any(|filter| filter.matches(row))
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        debug!("gRPC subscription ended");
+        self.metrics.client_count.fetch_sub(1, SeqCst);
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use hyper::http::uri::PathAndQuery;
+    use tonic::{client, transport::Endpoint, Code};
+
+    use super::{super::proto::ApiFilter, *};
+
+    fn route(prefix: &str) -> Arc<Event> {
+        Arc::new(Event::new(Row {
+            topic: "bgp".into(),
+            kind: "announce",
+            prefix: Some(prefix.into()),
+            ..Default::default()
+        }))
+    }
+
+    fn subscribe(filters: &[&str]) -> tonic::Request<SubscribeRequest> {
+        let filters = filters
+            .iter()
+            .map(|prefix| ApiFilter {
+                prefix: prefix.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        tonic::Request::new(SubscribeRequest { filters })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn clients_receive_what_they_subscribed_to() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config: Config = toml::from_str(&format!(
+            "listen = \"{addr}\"\nmax_clients = 1\nmax_filters = 1"
+        ))
+        .unwrap();
+        let runner = GrpcRunner::new(
+            listener,
+            None,
+            &config,
+            Default::default(),
+            Default::default(),
+        );
+        let metrics = runner.server.metrics.clone();
+        let events = runner.events.clone();
+        let accepting = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = runner.listener.accept().await.unwrap();
+                runner.accept(stream, peer);
+            }
+        });
+
+        let channel = Endpoint::from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = client::Grpc::new(channel);
+        let path = PathAndQuery::from_static(SUBSCRIBE_PATH);
+        let codec = ProstCodec::<SubscribeRequest, RouteEvent>::default;
+
+        // Invalid calls fail right away.
+        client.ready().await.unwrap();
+        let status = client
+            .server_streaming(
+                subscribe(&["10.0.0.0/8", "10.0.0.0/8"]),
+                path.clone(),
+                codec(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        client.ready().await.unwrap();
+        let status = client
+            .server_streaming(
+                subscribe(&[]),
+                PathAndQuery::from_static("/other.Service/Call"),
+                codec(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unimplemented);
+
+        client.ready().await.unwrap();
+        let mut stream = client
+            .server_streaming(
+                subscribe(&["10.0.0.0/8"]),
+                path.clone(),
+                codec(),
+            )
+            .await
+            .unwrap()
+            .into_inner();
+
+        // Only one client at a time.
+        client.ready().await.unwrap();
+        let status = client
+            .server_streaming(subscribe(&[]), path.clone(), codec())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(metrics.refused_count.load(SeqCst), 1);
+
+        events.send(route("192.0.2.0/24")).unwrap();
+        let matching = route("10.1.0.0/16");
+        events.send(matching.clone()).unwrap();
+        let message = stream.message().await.unwrap().unwrap();
+        assert_eq!(message, matching.message);
+        assert_eq!(metrics.sent_count.load(SeqCst), 1);
+
+        // Dropping the sender ends the call.
+        accepting.abort();
+        let _ = accepting.await;
+        drop(events);
+        let status = stream.message().await.unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(metrics.client_count.load(SeqCst), 0);
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst},
+    Arc,
+};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{
+        self, util::append_labelled_metric, Metric, MetricType, MetricUnit,
+    },
+};
+
+/// The metrics of all endpoints of the target.
+#[derive(Debug, Default)]
+pub struct HttpMetrics {
+    pub endpoints: Vec<(String, Arc<EndpointMetrics>)>,
+}
+
+#[derive(Debug, Default)]
+pub struct EndpointMetrics {
+    pub request_count: AtomicUsize,
+    pub request_error_count: AtomicUsize,
+    pub sent_event_count: AtomicUsize,
+    pub dropped_event_count: AtomicUsize,
+    pub pending_batch_count: AtomicUsize,
+    pub circuit_open: AtomicBool,
+}
+
+impl GraphStatus for HttpMetrics {
+    fn status_text(&self) -> String {
+        let (mut sent, mut errors, mut dropped, mut open) = (0, 0, 0, 0);
+        for (_, metrics) in &self.endpoints {
+            sent += metrics.sent_event_count.load(SeqCst);
+            errors += metrics.request_error_count.load(SeqCst);
+            dropped += metrics.dropped_event_count.load(SeqCst);
+            open += usize::from(metrics.circuit_open.load(SeqCst));
+        }
+        format!(
+            "sent: {sent}\nerrors: {errors}\ndropped: {dropped}\nopen: {open}"
+        )
+    }
+
+    fn okay(&self) -> Option<bool> {
+        Some(
+            !self
+                .endpoints
+                .iter()
+                .any(|(_, metrics)| metrics.circuit_open.load(SeqCst)),
+        )
+    }
+}
+
+impl HttpMetrics {
+    const REQUEST_COUNT_METRIC: Metric = Metric::new(
+        "http_target_request_count",
+        "the number of successful requests per endpoint",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const REQUEST_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "http_target_request_error_count",
+        "the number of failed requests per endpoint, including retries",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const SENT_EVENT_COUNT_METRIC: Metric = Metric::new(
+        "http_target_sent_event_count",
+        "the number of events sent per endpoint",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DROPPED_EVENT_COUNT_METRIC: Metric = Metric::new(
+        "http_target_dropped_event_count",
+        "the number of events per endpoint that could not be sent",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const PENDING_BATCH_COUNT_METRIC: Metric = Metric::new(
+        "http_target_pending_batch_count",
+        "the number of batches per endpoint waiting to be sent",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const CIRCUIT_OPEN_METRIC: Metric = Metric::new(
+        "http_target_circuit_open",
+        "whether requests to the endpoint are suspended: 0=no, 1=yes",
+        MetricType::Gauge,
+        MetricUnit::State,
+    );
+}
+
+impl metrics::Source for HttpMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        for (endpoint, metrics) in &self.endpoints {
+            for (metric, value) in [
+                (Self::REQUEST_COUNT_METRIC, &metrics.request_count),
+                (
+                    Self::REQUEST_ERROR_COUNT_METRIC,
+                    &metrics.request_error_count,
+                ),
+                (Self::SENT_EVENT_COUNT_METRIC, &metrics.sent_event_count),
+                (
+                    Self::DROPPED_EVENT_COUNT_METRIC,
+                    &metrics.dropped_event_count,
+                ),
+                (
+                    Self::PENDING_BATCH_COUNT_METRIC,
+                    &metrics.pending_batch_count,
+                ),
+            ] {
+                append_labelled_metric(
+                    unit_name,
+                    target,
+                    "endpoint",
+                    endpoint,
+                    metric,
+                    value.load(SeqCst),
+                );
+            }
+            append_labelled_metric(
+                unit_name,
+                target,
+                "endpoint",
+                endpoint,
+                Self::CIRCUIT_OPEN_METRIC,
+                u8::from(metrics.circuit_open.load(SeqCst)),
+            );
+        }
+    }
+}
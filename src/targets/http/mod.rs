@@ -0,0 +1,3 @@
+mod metrics;
+pub mod target;
+pub(crate) mod template;
@@ -0,0 +1,1009 @@
+//! Posting events to webhooks.
+//!
+//! The `http-out` target turns each route and event it receives into a row
+//! with the columns described in the [`row`] module and posts them as JSON
+//! to one or more `endpoints`. Each endpoint only receives the rows of the
+//! `kinds` and `topics` it lists, if any.
+//!
+//! By default, each row becomes a JSON object holding all columns. With a
+//! `template`, the row is rendered into the template instead, with the
+//! columns as placeholders as described in the [`template`] module. The
+//! body of a request is a JSON array of the rendered rows, or the
+//! `envelope` with its `{{events}}` placeholder replaced by that array.
+//! With a `batch_size` of 1, the body is the rendered row itself, which is
+//! what most chat webhooks expect.
+//!
+//! Rows are collected per endpoint into batches of up to `batch_size` rows,
+//! each batch being sent after at most `batch_interval_secs`, along with the
+//! `headers` of the endpoint, e.g. for authentication. A failed request is
+//! tried again up to `max_retries` times, with an exponential backoff,
+//! unless the endpoint refused it with a status code other than 408 or 429.
+//! After `failure_threshold` failed requests in a row, the circuit of the
+//! endpoint opens: for `circuit_open_secs`, its batches are dropped without
+//! trying to send them. After that, a single failure opens the circuit again
+//! while a success closes it. Meanwhile, up to `max_pending_batches` batches
+//! per endpoint wait for their turn; rows beyond that are dropped as well.
+//!
+//! With a `dead_letter_dir`, batches that could not be sent, including
+//! those skipped while the circuit is open, are kept in the [dead-letter
+//! spool] instead of being dropped. When a replay is requested through the
+//! HTTP API, each endpoint sends its own spooled batches again.
+//!
+//! The waiting batches of each endpoint can spill to disk and their sending
+//! be rate limited, as described in the [`buffer`] module.
+//!
+//! Endpoints may be reached over HTTPS. The CA certificates of the system
+//! are used to check their certificates unless others are given in the
+//! `tls` table of the endpoint.
+//!
+//! [`row`]: crate::targets::file::row
+//! [`template`]: super::template
+//! [dead-letter spool]: crate::targets::dead_letter
+//! [`buffer`]: crate::targets::buffer
+
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, warn};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Client as HttpClient, StatusCode,
+};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::sync::mpsc;
+use url::Url;
+
+use crate::{
+    common::tls::TlsClientConfig,
+    comms::{Link, Terminated, UnitStatus},
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    targets::{
+        buffer::{Buffer, BufferConfig, BufferReceiver},
+        dead_letter::{replay_requested, DeadLetterConfig, Spool},
+        file::row::{Row, COLUMNS},
+    },
+};
+
+use super::{
+    metrics::{EndpointMetrics, HttpMetrics},
+    template::Template,
+};
+
+/// The kinds of rows endpoints can select.
+const KINDS: [&str; 6] = [
+    "route",
+    "announce",
+    "withdraw",
+    "peer_down",
+    "log",
+    "custom",
+];
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct Http {
+    sources: Link,
+
+    endpoints: Vec<EndpointConfig>,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The longest time rows wait before they are sent.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_batch_interval_secs")]
+    pub batch_interval_secs: Duration,
+
+    /// How often to try a failed request again.
+    #[serde(default = "Config::default_max_retries")]
+    pub max_retries: usize,
+
+    /// How long to wait before the first retry. The delay doubles with
+    /// every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_retry_delay_secs")]
+    pub retry_delay_secs: Duration,
+
+    /// The longest to wait before retrying.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_max_retry_delay_secs")]
+    pub max_retry_delay_secs: Duration,
+
+    /// How many failed requests in a row open the circuit.
+    #[serde(default = "Config::default_failure_threshold")]
+    pub failure_threshold: usize,
+
+    /// How long an open circuit stays open.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_circuit_open_secs")]
+    pub circuit_open_secs: Duration,
+
+    /// How many batches per endpoint may wait to be sent.
+    #[serde(default = "Config::default_max_pending_batches")]
+    pub max_pending_batches: usize,
+
+    #[serde(flatten)]
+    pub buffer: BufferConfig,
+
+    #[serde(flatten)]
+    pub dead_letter: DeadLetterConfig,
+}
+
+impl Config {
+    fn default_batch_interval_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retries() -> usize {
+        5
+    }
+
+    fn default_retry_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retry_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_failure_threshold() -> usize {
+        5
+    }
+
+    fn default_circuit_open_secs() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    fn default_max_pending_batches() -> usize {
+        16
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EndpointConfig {
+    /// The name of the endpoint in logs and metrics, the URL by default.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    pub url: Url,
+
+    /// Headers to send with each request, e.g. `Authorization`.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// The kinds of rows to send, all if not given.
+    #[serde(default)]
+    pub kinds: Option<Vec<String>>,
+
+    /// The topics of rows to send, all if not given.
+    #[serde(default)]
+    pub topics: Option<Vec<String>>,
+
+    /// The largest number of rows to send at a time.
+    #[serde(default = "EndpointConfig::default_batch_size")]
+    pub batch_size: usize,
+
+    /// The template each row is rendered into.
+    #[serde(default)]
+    pub template: Option<serde_json::Value>,
+
+    /// The template the rendered rows of a batch are placed in.
+    #[serde(default)]
+    pub envelope: Option<serde_json::Value>,
+
+    /// The TLS settings for an HTTPS URL.
+    #[serde(default)]
+    pub tls: Option<TlsClientConfig>,
+}
+
+impl EndpointConfig {
+    fn default_batch_size() -> usize {
+        100
+    }
+
+    fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.url.to_string())
+    }
+
+    fn headers(&self) -> Result<HeaderMap, String> {
+        self.headers
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| format!("invalid header name '{name}'"))?;
+                let value = HeaderValue::from_str(value).map_err(|_| {
+                    format!("invalid value for header '{name}'")
+                })?;
+                Ok((name, value))
+            })
+            .collect()
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if !matches!(self.url.scheme(), "http" | "https") {
+            return Err(format!(
+                "unsupported URL scheme '{}', use http or https",
+                self.url.scheme()
+            ));
+        }
+        if let Some(kind) = self
+            .kinds
+            .iter()
+            .flatten()
+            .find(|kind| !KINDS.contains(&kind.as_str()))
+        {
+            return Err(format!("unknown kind '{kind}'"));
+        }
+        if self.batch_size == 0 {
+            return Err("batch_size must be at least 1".into());
+        }
+        Ok(())
+    }
+
+    /// Returns the client for the endpoint, `shared` unless it has its
+    /// own TLS settings.
+    fn client(&self, shared: HttpClient) -> Result<HttpClient, String> {
+        match &self.tls {
+            Some(tls) => tls
+                .http_client_builder()?
+                .build()
+                .map_err(|err| err.to_string()),
+            None => Ok(shared),
+        }
+    }
+}
+
+impl Http {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        if self.endpoints.is_empty() {
+            error!("Target {}: no endpoints configured", component.name());
+            return Err(Terminated);
+        }
+        if let Err(err) = self.config.buffer.check() {
+            error!("Target {}: {err}", component.name());
+            return Err(Terminated);
+        }
+
+        let spool = match Spool::new(&self.config.dead_letter, &mut component)
+        {
+            Ok(spool) => spool,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+        let mut endpoints = Vec::new();
+        let mut senders = Vec::new();
+        let mut metrics = HttpMetrics::default();
+        for config in &self.endpoints {
+            let endpoint_metrics = Arc::new(EndpointMetrics::default());
+            let (mut endpoint, mut sender) = match Endpoint::new(
+                config,
+                &self.config,
+                component.http_client().clone(),
+                endpoint_metrics.clone(),
+            ) {
+                Ok(res) => res,
+                Err(err) => {
+                    error!(
+                        "Target {}: endpoint {}: {err}",
+                        component.name(),
+                        config.name()
+                    );
+                    return Err(Terminated);
+                }
+            };
+            let (buffer, rx) = match Buffer::new(
+                format!("{}/{}", component.name(), config.name()),
+                self.config.max_pending_batches.max(1),
+                &self.config.buffer,
+                &mut component,
+            ) {
+                Ok(buffer) => buffer,
+                Err(err) => {
+                    error!("Target {}: {err}", component.name());
+                    return Err(Terminated);
+                }
+            };
+            endpoint_metrics
+                .pending_batch_count
+                .store(buffer.pending_count(), SeqCst);
+            endpoint.tx = Some(buffer);
+            sender.spool = spool.clone();
+            metrics.endpoints.push((config.name(), endpoint_metrics));
+            endpoints.push(endpoint);
+            senders.push((sender, rx));
+        }
+        let metrics = Arc::new(metrics);
+        component.register_metrics(metrics.clone());
+
+        HttpRunner {
+            endpoints,
+            ingresses: component.ingresses().clone(),
+            metrics,
+            batch_interval: self.config.batch_interval_secs,
+        }
+        .run(senders, self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ HttpRunner ----------------------------------------------------
+
+struct HttpRunner {
+    endpoints: Vec<Endpoint>,
+    ingresses: Arc<ingress::Register>,
+    metrics: Arc<HttpMetrics>,
+    batch_interval: Duration,
+}
+
+impl HttpRunner {
+    async fn run(
+        mut self,
+        senders: Vec<(Sender, BufferReceiver<Vec<serde_json::Value>>)>,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        // Each endpoint has a task of its own, so that a slow or
+        // unavailable endpoint holds up neither the sources nor the other
+        // endpoints.
+        let tasks = senders
+            .into_iter()
+            .map(|(sender, rx)| tokio::spawn(sender.run(rx)))
+            .collect::<Vec<_>>();
+
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        let mut flush = tokio::time::interval(self.batch_interval);
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the http-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        for row in Row::for_update(update, &self.ingresses) {
+                            for endpoint in &mut self.endpoints {
+                                endpoint.push(&row).await;
+                            }
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of http-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+
+                _ = flush.tick() => {
+                    for endpoint in &mut self.endpoints {
+                        endpoint.flush().await;
+                    }
+                }
+            }
+        }
+
+        // Send what is left before stopping.
+        for mut endpoint in self.endpoints {
+            endpoint.flush().await;
+        }
+        for task in tasks {
+            let _ = task.await;
+        }
+        Err(Terminated)
+    }
+}
+
+//------------ Endpoint ------------------------------------------------------
+
+/// Collects the rows for an endpoint into batches.
+struct Endpoint {
+    name: String,
+    kinds: Option<Vec<String>>,
+    topics: Option<Vec<String>>,
+    template: Option<Template>,
+    batch_size: usize,
+
+    /// The rendered rows of the next batch.
+    events: Vec<serde_json::Value>,
+
+    /// Where to send full batches.
+    tx: Option<Buffer<Vec<serde_json::Value>>>,
+    metrics: Arc<EndpointMetrics>,
+}
+
+impl Endpoint {
+    fn new(
+        config: &EndpointConfig,
+        target: &Config,
+        http: HttpClient,
+        metrics: Arc<EndpointMetrics>,
+    ) -> Result<(Self, Sender), String> {
+        config.check()?;
+        let columns = COLUMNS.map(|column| column.name);
+        let template = config
+            .template
+            .clone()
+            .map(|template| Template::new(template, &columns))
+            .transpose()?;
+        let envelope = config
+            .envelope
+            .clone()
+            .map(|envelope| Template::new(envelope, &["events"]))
+            .transpose()?;
+
+        let endpoint = Self {
+            name: config.name(),
+            kinds: config.kinds.clone(),
+            topics: config.topics.clone(),
+            template,
+            batch_size: config.batch_size,
+            events: Vec::new(),
+            tx: None,
+            metrics: metrics.clone(),
+        };
+        let sender = Sender {
+            name: config.name(),
+            http: config.client(http)?,
+            url: config.url.clone(),
+            headers: config.headers()?,
+            envelope,
+            single: config.batch_size == 1,
+            max_retries: target.max_retries,
+            retry_delay: target.retry_delay_secs,
+            max_retry_delay: target.max_retry_delay_secs,
+            breaker: CircuitBreaker::new(
+                target.failure_threshold,
+                target.circuit_open_secs,
+            ),
+            spool: None,
+            metrics,
+        };
+        Ok((endpoint, sender))
+    }
+
+    /// Adds a row if the endpoint wants it.
+    async fn push(&mut self, row: &Row) {
+        if !self.wants(row) {
+            return;
+        }
+        let lookup = |name: &str| row.json_value(name);
+        let event = match &self.template {
+            Some(template) => template.render(&lookup),
+            None => COLUMNS
+                .iter()
+                .map(|column| (column.name.to_string(), lookup(column.name)))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+        };
+        self.events.push(event);
+        if self.events.len() >= self.batch_size {
+            self.flush().await;
+        }
+    }
+
+    fn wants(&self, row: &Row) -> bool {
+        self.kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.iter().any(|kind| kind == row.kind))
+            && self
+                .topics
+                .as_ref()
+                .is_none_or(|topics| topics.contains(&row.topic))
+    }
+
+    /// Hands the rows collected so far to the sender.
+    async fn flush(&mut self) {
+        if self.events.is_empty() {
+            return;
+        }
+        let events = std::mem::take(&mut self.events);
+        let len = events.len();
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        match tx.push(events).await {
+            Ok(()) => {
+                self.metrics.pending_batch_count.fetch_add(1, SeqCst);
+            }
+            Err(_) => {
+                warn!(
+                    "Dropping {len} events for endpoint {}: too many batches \
+                    are waiting to be sent",
+                    self.name
+                );
+                self.metrics.dropped_event_count.fetch_add(len, SeqCst);
+            }
+        }
+    }
+}
+
+//------------ CircuitBreaker ------------------------------------------------
+
+/// Suspends requests to an endpoint that keeps failing.
+#[derive(Debug)]
+struct CircuitBreaker {
+    threshold: usize,
+    open_for: Duration,
+
+    /// The number of failed requests in a row.
+    failures: usize,
+
+    /// Until when the circuit is open.
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn new(threshold: usize, open_for: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            open_for,
+            failures: 0,
+            open_until: None,
+        }
+    }
+
+    /// Returns whether a request may be made.
+    fn allows(&self, now: Instant) -> bool {
+        self.open_until.is_none_or(|until| now >= until)
+    }
+
+    fn success(&mut self) {
+        self.failures = 0;
+        self.open_until = None;
+    }
+
+    /// Notes a failed request, returning whether the circuit opened.
+    fn failure(&mut self, now: Instant) -> bool {
+        self.failures += 1;
+        if self.failures < self.threshold {
+            return false;
+        }
+        self.open_until = Some(now + self.open_for);
+        true
+    }
+}
+
+//------------ Sender --------------------------------------------------------
+
+/// Why a request failed.
+#[derive(Debug)]
+struct SendError {
+    message: String,
+
+    /// Whether trying again might help.
+    retry: bool,
+}
+
+/// Sends batches to an endpoint.
+struct Sender {
+    name: String,
+    http: HttpClient,
+    url: Url,
+    headers: HeaderMap,
+    envelope: Option<Template>,
+
+    /// Whether batches hold a single event that is sent by itself.
+    single: bool,
+    max_retries: usize,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    breaker: CircuitBreaker,
+
+    /// Where to put the batches that could not be sent, if anywhere.
+    spool: Option<Arc<Spool>>,
+    metrics: Arc<EndpointMetrics>,
+}
+
+impl Sender {
+    /// Sends batches until there are no more.
+    ///
+    /// The spooled batches of the endpoint are sent again when asked to.
+    async fn run(mut self, mut rx: BufferReceiver<Vec<serde_json::Value>>) {
+        let mut replays = self.spool.as_ref().map(|spool| spool.subscribe());
+        loop {
+            tokio::select! {
+                events = rx.recv() => match events {
+                    Some(events) => {
+                        self.metrics.pending_batch_count.fetch_sub(1, SeqCst);
+                        self.send_with_retries(events).await;
+                        rx.delivered().await;
+                    }
+                    None => break,
+                },
+                _ = replay_requested(&mut replays) => {
+                    if let Some(spool) = &self.spool {
+                        let this = &self;
+                        spool
+                            .replay(Some(&self.name), |letter| async move {
+                                this.send(letter.body)
+                                    .await
+                                    .map_err(|err| err.message)
+                            })
+                            .await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn send_with_retries(&mut self, events: Vec<serde_json::Value>) {
+        let body = self.body(events.clone()).to_string();
+        let mut reason = String::new();
+        let mut delay = self.retry_delay;
+        for attempt in 0..=self.max_retries {
+            if !self.breaker.allows(Instant::now()) {
+                debug!(
+                    "Endpoint {}: circuit is open, not sending {} events",
+                    self.name,
+                    events.len()
+                );
+                reason = "the circuit is open".into();
+                break;
+            }
+            match self.send(body.clone()).await {
+                Ok(()) => {
+                    self.breaker.success();
+                    self.metrics.circuit_open.store(false, SeqCst);
+                    self.metrics.request_count.fetch_add(1, SeqCst);
+                    self.metrics
+                        .sent_event_count
+                        .fetch_add(events.len(), SeqCst);
+                    return;
+                }
+                Err(err) => {
+                    self.metrics.request_error_count.fetch_add(1, SeqCst);
+                    warn!(
+                        "Endpoint {}: request failed: {}",
+                        self.name, err.message
+                    );
+                    reason = err.message;
+                    if self.breaker.failure(Instant::now()) {
+                        warn!(
+                            "Endpoint {}: suspending requests for {}s after \
+                            {} failures",
+                            self.name,
+                            self.breaker.open_for.as_secs(),
+                            self.breaker.failures
+                        );
+                        self.metrics.circuit_open.store(true, SeqCst);
+                        break;
+                    }
+                    if !err.retry {
+                        break;
+                    }
+                    if attempt < self.max_retries {
+                        info!(
+                            "Endpoint {}: retrying in {}s",
+                            self.name,
+                            delay.as_secs()
+                        );
+                        tokio::time::sleep(delay).await;
+                        delay = (delay * 2).min(self.max_retry_delay);
+                    }
+                }
+            }
+        }
+        if let Some(spool) = &self.spool {
+            if spool.put(&self.name, &reason, body).await {
+                warn!(
+                    "Endpoint {}: spooled {} events that could not be sent",
+                    self.name,
+                    events.len()
+                );
+                return;
+            }
+        }
+        self.metrics
+            .dropped_event_count
+            .fetch_add(events.len(), SeqCst);
+    }
+
+    fn body(&self, mut events: Vec<serde_json::Value>) -> serde_json::Value {
+        if self.single && events.len() == 1 {
+            return events.remove(0);
+        }
+        let events = serde_json::Value::Array(events);
+        match &self.envelope {
+            Some(envelope) => envelope.render(&|_| events.clone()),
+            None => events,
+        }
+    }
+
+    /// Makes a single attempt at sending a body.
+    async fn send(&self, body: String) -> Result<(), SendError> {
+        let response = self
+            .http
+            .post(self.url.clone())
+            .headers(self.headers.clone())
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| SendError {
+                message: err.to_string(),
+                retry: true,
+            })?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let text = response.text().await.unwrap_or_default();
+        Err(SendError {
+            message: format!("{status}: {}", text.trim()),
+            retry: !status.is_client_error()
+                || status == StatusCode::REQUEST_TIMEOUT
+                || status == StatusCode::TOO_MANY_REQUESTS,
+        })
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use inetnum::asn::Asn;
+    use serde_json::json;
+
+    use crate::{
+        roto_runtime::types::OutputStreamMessage,
+        tests::util::{http::MockServer, https},
+    };
+
+    use super::*;
+
+    fn mk_endpoint(
+        addr: SocketAddr,
+        endpoint: &str,
+        target: &str,
+    ) -> (Endpoint, Sender) {
+        let endpoint: EndpointConfig = toml::from_str(&format!(
+            "url = \"http://{addr}/hook\"\n{endpoint}"
+        ))
+        .unwrap();
+        let target: Config = toml::from_str(target).unwrap();
+        Endpoint::new(
+            &endpoint,
+            &target,
+            HttpClient::new(),
+            Arc::new(EndpointMetrics::default()),
+        )
+        .unwrap()
+    }
+
+    fn peer_down() -> Row {
+        Row::new(
+            OutputStreamMessage::peer_down(
+                "hook".into(),
+                "peer-down".into(),
+                "192.0.2.1".parse().unwrap(),
+                Asn::from_u32(65000),
+                None,
+            ),
+            &ingress::Register::default(),
+        )
+    }
+
+    #[test]
+    fn endpoints_are_checked() {
+        let config = |endpoint: &str| -> EndpointConfig {
+            toml::from_str(&format!(
+                "url = \"http://localhost/\"\n{endpoint}"
+            ))
+            .unwrap()
+        };
+        let target: Config = toml::from_str("").unwrap();
+        let new = |endpoint: &EndpointConfig| {
+            Endpoint::new(
+                endpoint,
+                &target,
+                HttpClient::new(),
+                Default::default(),
+            )
+            .map(|_| ())
+        };
+        assert!(new(&config("kinds = [\"peer_down\"]")).is_ok());
+        assert!(new(&config("kinds = [\"peerdown\"]")).is_err());
+        assert!(new(&config("template = { text = \"{{peer}}\" }")).is_err());
+        assert!(new(&config("envelope = { x = \"{{prefix}}\" }")).is_err());
+        assert!(new(&config("headers = { \"a b\" = \"c\" }")).is_err());
+        assert!(new(&config("tls = { ca = \"/nonexistent\" }")).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rows_are_filtered_and_rendered() {
+        let server = MockServer::with_statuses(vec![], 200).await;
+        let (mut endpoint, mut sender) = mk_endpoint(
+            server.addr,
+            "kinds = [\"peer_down\"]\nbatch_size = 1\n\
+            headers = { Authorization = \"Bearer secret\" }\n\
+            template = { text = \"Peer {{peer_ip}} went down\", \
+            asn = \"{{peer_as}}\" }",
+            "",
+        );
+        let (tx, mut rx) =
+            Buffer::open("hook".into(), 4, &BufferConfig::default()).unwrap();
+        endpoint.tx = Some(tx);
+
+        endpoint
+            .push(&Row {
+                kind: "announce",
+                ..Default::default()
+            })
+            .await;
+        endpoint.push(&peer_down()).await;
+        drop(endpoint);
+        while let Some(events) = rx.recv().await {
+            sender.send_with_retries(events).await;
+        }
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].header("authorization"),
+            Some("Bearer secret")
+        );
+        assert_eq!(
+            requests[0].json(),
+            json!({"text": "Peer 192.0.2.1 went down", "asn": 65000})
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn batches_are_wrapped_in_the_envelope() {
+        let server = MockServer::with_statuses(vec![], 200).await;
+        let (mut endpoint, mut sender) = mk_endpoint(
+            server.addr,
+            "batch_size = 2\nenvelope = { source = \"rotonda\", \
+            events = \"{{events}}\" }",
+            "",
+        );
+        let (tx, mut rx) =
+            Buffer::open("hook".into(), 4, &BufferConfig::default()).unwrap();
+        endpoint.tx = Some(tx);
+        endpoint.push(&peer_down()).await;
+        endpoint.push(&peer_down()).await;
+        sender.send_with_retries(rx.recv().await.unwrap()).await;
+
+        let body = server.requests()[0].json();
+        assert_eq!(body["source"], "rotonda");
+        assert_eq!(body["events"].as_array().unwrap().len(), 2);
+        assert_eq!(body["events"][0]["kind"], "peer_down");
+        assert_eq!(body["events"][0]["prefix"], serde_json::Value::Null);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rows_are_posted_over_https() {
+        let server = MockServer::with_statuses(vec![], 200).await;
+        let front = https::front(server.addr).await;
+        let endpoint: EndpointConfig = toml::from_str(&format!(
+            "url = \"https://localhost:{}/hook\"\nbatch_size = 1\n\
+            tls = {{ ca = \"{}\" }}",
+            front.port(),
+            https::ca_file().display()
+        ))
+        .unwrap();
+        let target: Config = toml::from_str("").unwrap();
+        let (mut endpoint, mut sender) = Endpoint::new(
+            &endpoint,
+            &target,
+            HttpClient::new(),
+            Default::default(),
+        )
+        .unwrap();
+        let (tx, mut rx) =
+            Buffer::open("hook".into(), 4, &BufferConfig::default()).unwrap();
+        endpoint.tx = Some(tx);
+        endpoint.push(&peer_down()).await;
+        sender.send_with_retries(rx.recv().await.unwrap()).await;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].json()["kind"], "peer_down");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failing_endpoints_open_the_circuit() {
+        let server =
+            MockServer::with_statuses(vec![500, 503, 500], 200).await;
+        let (_, mut sender) = mk_endpoint(
+            server.addr,
+            "",
+            "retry_delay_secs = 0\nfailure_threshold = 2",
+        );
+        let metrics = sender.metrics.clone();
+
+        sender.send_with_retries(vec![json!(1)]).await;
+        assert_eq!(server.requests().len(), 2);
+        assert!(metrics.circuit_open.load(SeqCst));
+
+        // While the circuit is open, nothing is sent.
+        sender.send_with_retries(vec![json!(2)]).await;
+        assert_eq!(server.requests().len(), 2);
+        assert_eq!(metrics.dropped_event_count.load(SeqCst), 2);
+
+        // Once it closes, a single failure opens it again.
+        sender.breaker.open_until = Some(Instant::now());
+        sender.send_with_retries(vec![json!(3)]).await;
+        assert_eq!(server.requests().len(), 3);
+        sender.breaker.open_until = Some(Instant::now());
+        sender.send_with_retries(vec![json!(4)]).await;
+        assert_eq!(server.requests().len(), 4);
+        assert!(!metrics.circuit_open.load(SeqCst));
+        assert_eq!(metrics.sent_event_count.load(SeqCst), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn refused_requests_are_not_retried() {
+        let server = MockServer::with_statuses(vec![400, 429], 200).await;
+        let (_, mut sender) =
+            mk_endpoint(server.addr, "", "retry_delay_secs = 0");
+
+        sender.send_with_retries(vec![json!(1)]).await;
+        assert_eq!(server.requests().len(), 1);
+        sender.send_with_retries(vec![json!(2)]).await;
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(sender.metrics.dropped_event_count.load(SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn unsent_batches_are_spooled_per_endpoint() {
+        let server = MockServer::with_statuses(vec![400], 200).await;
+        let (_, mut sender) =
+            mk_endpoint(server.addr, "", "retry_delay_secs = 0");
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-http-{}", uuid::Uuid::new_v4()));
+        let spool = Arc::new(Spool::open(&dir, "http".into()).unwrap());
+        sender.spool = Some(spool.clone());
+        sender.send_with_retries(vec![json!(1)]).await;
+        assert_eq!(sender.metrics.dropped_event_count.load(SeqCst), 0);
+        assert!(spool.put("other", "refused", "[2]".into()).await);
+
+        // Only the batch of this endpoint is sent again.
+        let (tx, rx) =
+            Buffer::open("hook".into(), 1, &BufferConfig::default()).unwrap();
+        let task = tokio::spawn(sender.run(rx));
+        tokio::task::yield_now().await;
+        spool.request_replay();
+        while std::fs::read_dir(dir.join("http")).unwrap().count() > 1 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(tx);
+        task.await.unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].body, requests[1].body);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,185 @@
+//! Templates for rendering rows.
+//!
+//! These render the JSON bodies of the `http-out` target and the lines of
+//! the template format of the `file-out` target. A template is any JSON
+//! value, a line being a string. Strings in it may contain placeholders of
+//! the form `{{name}}`. A string consisting of nothing but a placeholder is
+//! replaced by the value itself, so that numbers and lists keep their type,
+//! e.g. `{"path": "{{as_path}}"}` becomes `{"path": [65000, 65001]}`. In
+//! other strings, the placeholder is replaced by the value as text, with
+//! the elements of lists separated by spaces and missing values left empty,
+//! e.g. `"Peer {{peer_ip}} went down"` becomes `"Peer 192.0.2.1 went down"`.
+
+//------------ Template ------------------------------------------------------
+
+#[derive(Clone, Debug)]
+pub struct Template {
+    value: serde_json::Value,
+}
+
+impl Template {
+    /// Creates a template, checking that it only uses the given names.
+    pub fn new(
+        value: serde_json::Value,
+        names: &[&str],
+    ) -> Result<Self, String> {
+        let mut unknown = None;
+        visit_strings(&value, &mut |s| {
+            for name in placeholders(s) {
+                if !names.contains(&name) && unknown.is_none() {
+                    unknown = Some(name.to_string());
+                }
+            }
+        });
+        match unknown {
+            Some(name) => {
+                Err(format!("unknown placeholder '{{{{{name}}}}}'"))
+            }
+            None => Ok(Self { value }),
+        }
+    }
+
+    /// Returns the template with the placeholders replaced by the values
+    /// `lookup` returns for their names.
+    pub fn render(
+        &self,
+        lookup: &impl Fn(&str) -> serde_json::Value,
+    ) -> serde_json::Value {
+        render(&self.value, lookup)
+    }
+
+    /// Returns the rendered template as text.
+    ///
+    /// Values other than strings are turned into text the same way as
+    /// within strings.
+    pub fn render_text(
+        &self,
+        lookup: &impl Fn(&str) -> serde_json::Value,
+    ) -> String {
+        let mut res = String::new();
+        push_text(&mut res, &self.render(lookup));
+        res
+    }
+}
+
+fn render(
+    value: &serde_json::Value,
+    lookup: &impl Fn(&str) -> serde_json::Value,
+) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(s) => {
+            if let Some(name) = s
+                .strip_prefix("{{")
+                .and_then(|s| s.strip_suffix("}}"))
+                .filter(|name| !name.contains("{{"))
+            {
+                return lookup(name.trim());
+            }
+            let mut res = String::new();
+            let mut rest = s.as_str();
+            while let Some((before, after)) = rest.split_once("{{") {
+                let Some((name, after)) = after.split_once("}}") else {
+                    break;
+                };
+                res.push_str(before);
+                push_text(&mut res, &lookup(name.trim()));
+                rest = after;
+            }
+            res.push_str(rest);
+            res.into()
+        }
+        serde_json::Value::Array(values) => values
+            .iter()
+            .map(|value| render(value, lookup))
+            .collect::<Vec<_>>()
+            .into(),
+        serde_json::Value::Object(object) => object
+            .iter()
+            .map(|(key, value)| (key.clone(), render(value, lookup)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        value => value.clone(),
+    }
+}
+
+fn push_text(buf: &mut String, value: &serde_json::Value) {
+    match value {
+        serde_json::Value::Null => {}
+        serde_json::Value::String(s) => buf.push_str(s),
+        serde_json::Value::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    buf.push(' ');
+                }
+                push_text(buf, value);
+            }
+        }
+        value => buf.push_str(&value.to_string()),
+    }
+}
+
+fn placeholders(s: &str) -> impl Iterator<Item = &str> {
+    s.split("{{")
+        .skip(1)
+        .filter_map(|s| s.split_once("}}"))
+        .map(|(name, _)| name.trim())
+}
+
+fn visit_strings(value: &serde_json::Value, f: &mut impl FnMut(&str)) {
+    match value {
+        serde_json::Value::String(s) => f(s),
+        serde_json::Value::Array(values) => {
+            values.iter().for_each(|value| visit_strings(value, f))
+        }
+        serde_json::Value::Object(object) => {
+            object.values().for_each(|value| visit_strings(value, f))
+        }
+        _ => {}
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn lookup(name: &str) -> serde_json::Value {
+        match name {
+            "peer_ip" => json!("192.0.2.1"),
+            "as_path" => json!([65000, 65001]),
+            _ => serde_json::Value::Null,
+        }
+    }
+
+    #[test]
+    fn placeholders_are_replaced() {
+        let template = Template::new(
+            json!({
+                "text": "Peer {{ peer_ip }} via {{as_path}}{{custom}}!",
+                "path": "{{as_path}}",
+                "tags": ["bgp", "{{custom}}"],
+                "count": 1,
+            }),
+            &["peer_ip", "as_path", "custom"],
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&lookup),
+            json!({
+                "text": "Peer 192.0.2.1 via 65000 65001!",
+                "path": [65000, 65001],
+                "tags": ["bgp", null],
+                "count": 1,
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_refused() {
+        let err = Template::new(json!(["{{prefix}}"]), &["peer_ip"]);
+        assert_eq!(err.unwrap_err(), "unknown placeholder '{{prefix}}'");
+    }
+}
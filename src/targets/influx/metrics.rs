@@ -0,0 +1,89 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct InfluxMetrics {
+    pub write_count: AtomicUsize,
+    pub write_error_count: AtomicUsize,
+    pub written_line_count: AtomicUsize,
+    pub peer_count: AtomicUsize,
+    pub last_write_duration_ms: AtomicU64,
+}
+
+impl GraphStatus for InfluxMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "peers: {}\nwritten: {}\nerrors: {}",
+            self.peer_count.load(SeqCst),
+            self.written_line_count.load(SeqCst),
+            self.write_error_count.load(SeqCst),
+        )
+    }
+}
+
+impl InfluxMetrics {
+    const WRITE_COUNT_METRIC: Metric = Metric::new(
+        "influx_target_write_count",
+        "the number of successful writes of measurements",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const WRITE_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "influx_target_write_error_count",
+        "the number of writes of measurements that failed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const WRITTEN_LINE_COUNT_METRIC: Metric = Metric::new(
+        "influx_target_written_line_count",
+        "the number of lines of measurements written",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const PEER_COUNT_METRIC: Metric = Metric::new(
+        "influx_target_peer_count",
+        "the number of peers measurements are kept for",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const LAST_WRITE_DURATION_METRIC: Metric = Metric::new(
+        "influx_target_last_write_duration",
+        "how long the last successful write took",
+        MetricType::Gauge,
+        MetricUnit::Millisecond,
+    );
+}
+
+impl metrics::Source for InfluxMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::WRITE_COUNT_METRIC,
+            Some(unit_name),
+            self.write_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::WRITE_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.write_error_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::WRITTEN_LINE_COUNT_METRIC,
+            Some(unit_name),
+            self.written_line_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::PEER_COUNT_METRIC,
+            Some(unit_name),
+            self.peer_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::LAST_WRITE_DURATION_METRIC,
+            Some(unit_name),
+            self.last_write_duration_ms.load(SeqCst),
+        );
+    }
+}
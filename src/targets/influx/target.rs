@@ -0,0 +1,787 @@
+//! Writing route measurements in the InfluxDB line protocol.
+//!
+//! The `influx-out` target counts the announcements and withdrawals it
+//! receives from its sources and keeps track of the prefixes each peer
+//! currently announces. Every `interval_secs`, it writes one line per peer,
+//! with the measurement `<measurement_prefix>_peer`:
+//!
+//! ```text
+//! rotonda_peer,peer_ip=192.0.2.1,peer_as=65000 announcements=120i,
+//!     withdrawals=3i,update_rate=12.3,prefixes=950312i 1732186800000000000
+//! ```
+//!
+//! and one line for each of the prefixes in `aggregates`, measuring the
+//! prefixes it covers, with the measurement
+//! `<measurement_prefix>_prefix_aggregate`:
+//!
+//! ```text
+//! rotonda_prefix_aggregate,aggregate=0.0.0.0/0 announcements=120i,
+//!     withdrawals=3i,update_rate=12.3,churn=101i,prefixes=950312i
+//!     1732186800000000000
+//! ```
+//!
+//! (without the line breaks). The announcements and withdrawals are counted
+//! over the last interval, with the update rate being their sum per second.
+//! The churn is the number of distinct prefixes updated in the interval, the
+//! prefixes are those announced by at least one peer at the time of the
+//! measurement. The `tags` are added to every line.
+//!
+//! With an `http://` or `https://` URL, such as
+//! `http://localhost:8086/api/v2/write?org=noc&bucket=bgp`, the lines are
+//! posted to it, along with the `token` if given. The CA certificates of
+//! the system are used to check the certificate of an HTTPS server unless
+//! others are given in the `tls` table. With a `udp://` URL, the
+//! lines are sent as datagrams of at most `max_datagram_size` bytes.
+//! Measurements that cannot be written are lost, as the next write holds
+//! the measurements of the next interval.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use inetnum::{addr::Prefix, asn::Asn};
+use log::{debug, error, warn};
+use reqwest::Client as HttpClient;
+use rotonda_store::prefix_record::RouteStatus;
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::{net::UdpSocket, sync::mpsc, task::JoinHandle};
+use url::Url;
+
+use crate::{
+    common::tls::TlsClientConfig,
+    comms::{Link, Terminated, UnitStatus},
+    ingress::IngressId,
+    manager::{Component, TargetCommand, WaitPoint},
+    payload::{Payload, Update},
+    roto_runtime::types::RouteContext,
+    units::rib_unit::best_path::prefix_of,
+};
+
+use super::metrics::InfluxMetrics;
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct Influx {
+    sources: Link,
+
+    /// Where to write the lines to, an `http://`, `https://` or `udp://`
+    /// URL.
+    url: Url,
+
+    /// The API token sent with HTTP writes.
+    #[serde(default)]
+    token: Option<String>,
+
+    /// The TLS settings for an HTTPS URL.
+    #[serde(default)]
+    tls: Option<TlsClientConfig>,
+
+    /// How often to write the measurements.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Influx::default_interval_secs")]
+    interval_secs: Duration,
+
+    /// The prefixes to measure the more specific prefixes of.
+    #[serde(default = "Influx::default_aggregates")]
+    aggregates: Vec<Prefix>,
+
+    /// The start of the names of the measurements.
+    #[serde(default = "Influx::default_measurement_prefix")]
+    measurement_prefix: String,
+
+    /// Tags to add to every line.
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+
+    /// The largest datagram to send over UDP.
+    #[serde(default = "Influx::default_max_datagram_size")]
+    max_datagram_size: usize,
+}
+
+impl Influx {
+    fn default_interval_secs() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    fn default_aggregates() -> Vec<Prefix> {
+        vec![
+            Prefix::new_v4([0, 0, 0, 0].into(), 0).unwrap(),
+            Prefix::new_v6(0.into(), 0).unwrap(),
+        ]
+    }
+
+    fn default_measurement_prefix() -> String {
+        "rotonda".into()
+    }
+
+    fn default_max_datagram_size() -> usize {
+        1400
+    }
+
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let writer = async {
+            let http = match &self.tls {
+                Some(tls) => tls
+                    .http_client_builder()?
+                    .build()
+                    .map_err(|err| err.to_string())?,
+                None => component.http_client().clone(),
+            };
+            Writer::new(
+                &self.url,
+                self.token.clone(),
+                self.max_datagram_size,
+                http,
+            )
+            .await
+        };
+        let writer = match writer.await {
+            Ok(writer) => writer,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+
+        let metrics = Arc::new(InfluxMetrics::default());
+        component.register_metrics(metrics.clone());
+        let stats = Stats::new(&self.aggregates);
+        let lines = LineFormat::new(&self.measurement_prefix, &self.tags);
+        InfluxRunner {
+            stats,
+            lines,
+            writer,
+            metrics,
+            interval: self.interval_secs,
+        }
+        .run(self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ InfluxRunner --------------------------------------------------
+
+struct InfluxRunner {
+    stats: Stats,
+    lines: LineFormat,
+    writer: Writer,
+    metrics: Arc<InfluxMetrics>,
+    interval: Duration,
+}
+
+impl InfluxRunner {
+    async fn run(
+        mut self,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        let mut interval = tokio::time::interval(self.interval);
+        // The first tick completes immediately.
+        interval.tick().await;
+        let mut started = Instant::now();
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the influx-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => self.stats.update(update),
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of influx-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+
+                _ = interval.tick() => {
+                    self.write(started.elapsed());
+                    started = Instant::now();
+                }
+            }
+        }
+
+        // Wait for the last write before stopping.
+        let _ = self.write(started.elapsed()).await;
+        Err(Terminated)
+    }
+
+    /// Writes the measurements of an interval in the background.
+    fn write(&mut self, elapsed: Duration) -> JoinHandle<()> {
+        self.metrics
+            .peer_count
+            .store(self.stats.peers.len(), SeqCst);
+        let lines = self.lines.format(&mut self.stats, Utc::now(), elapsed);
+        let writer = self.writer.clone();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            let started = Instant::now();
+            match writer.write(&lines).await {
+                Ok(()) => {
+                    let duration = started.elapsed().as_millis() as u64;
+                    metrics.write_count.fetch_add(1, SeqCst);
+                    metrics.written_line_count.fetch_add(lines.len(), SeqCst);
+                    metrics.last_write_duration_ms.store(duration, SeqCst);
+                }
+                Err(err) => {
+                    metrics.write_error_count.fetch_add(1, SeqCst);
+                    warn!(
+                        "Writing {} measurements failed: {err}",
+                        lines.len()
+                    );
+                }
+            }
+        })
+    }
+}
+
+//------------ Stats ---------------------------------------------------------
+
+/// The measurements of a peer.
+#[derive(Debug)]
+struct PeerStats {
+    peer_ip: IpAddr,
+    peer_as: Asn,
+
+    /// The prefixes currently announced by the peer.
+    prefixes: HashSet<Prefix>,
+    announcements: u64,
+    withdrawals: u64,
+}
+
+/// The measurements of the prefixes covered by an aggregate.
+#[derive(Debug)]
+struct AggregateStats {
+    aggregate: Prefix,
+
+    /// The number of covered prefixes announced by at least one peer.
+    prefixes: u64,
+    announcements: u64,
+    withdrawals: u64,
+
+    /// The covered prefixes updated in the current interval.
+    updated: HashSet<Prefix>,
+}
+
+/// Keeps the measurements of all peers and aggregates.
+#[derive(Debug, Default)]
+struct Stats {
+    peers: HashMap<IngressId, PeerStats>,
+    aggregates: Vec<AggregateStats>,
+
+    /// The number of peers announcing each prefix.
+    announced: HashMap<Prefix, usize>,
+}
+
+impl Stats {
+    fn new(aggregates: &[Prefix]) -> Self {
+        Self {
+            aggregates: aggregates
+                .iter()
+                .map(|aggregate| AggregateStats {
+                    aggregate: *aggregate,
+                    prefixes: 0,
+                    announcements: 0,
+                    withdrawals: 0,
+                    updated: HashSet::new(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn update(&mut self, update: Update) {
+        match update {
+            Update::Single(payload) => self.route(&payload),
+            Update::Bulk(payloads) => {
+                payloads.iter().for_each(|payload| self.route(payload))
+            }
+            Update::Withdraw(ingress_id, None) => {
+                self.session_down(ingress_id)
+            }
+            Update::WithdrawBulk(ingress_ids) => {
+                ingress_ids.iter().for_each(|id| self.session_down(*id))
+            }
+            _ => {}
+        }
+    }
+
+    fn route(&mut self, payload: &Payload) {
+        let (status, provenance) = match &payload.context {
+            RouteContext::Fresh(ctx) => (ctx.status(), ctx.provenance()),
+            RouteContext::Mrt(ctx) => (ctx.status, ctx.provenance()),
+            RouteContext::Reprocess => return,
+        };
+        let prefix = prefix_of(&payload.rx_value);
+        let peer =
+            self.peers.entry(provenance.ingress_id).or_insert_with(|| {
+                PeerStats {
+                    peer_ip: provenance.peer_ip,
+                    peer_as: provenance.peer_asn,
+                    prefixes: HashSet::new(),
+                    announcements: 0,
+                    withdrawals: 0,
+                }
+            });
+        let withdrawn = status == RouteStatus::Withdrawn;
+        if withdrawn {
+            peer.withdrawals += 1;
+            if peer.prefixes.remove(&prefix) {
+                self.release(prefix);
+            }
+        } else {
+            peer.announcements += 1;
+            if peer.prefixes.insert(prefix) {
+                let count = self.announced.entry(prefix).or_default();
+                *count += 1;
+                if *count == 1 {
+                    self.covering(prefix).for_each(|agg| agg.prefixes += 1);
+                }
+            }
+        }
+        for aggregate in self.covering(prefix) {
+            if withdrawn {
+                aggregate.withdrawals += 1;
+            } else {
+                aggregate.announcements += 1;
+            }
+            aggregate.updated.insert(prefix);
+        }
+    }
+
+    /// Forgets a peer and the prefixes it announced.
+    fn session_down(&mut self, ingress_id: IngressId) {
+        if let Some(peer) = self.peers.remove(&ingress_id) {
+            for prefix in peer.prefixes {
+                self.release(prefix);
+            }
+        }
+    }
+
+    /// Notes that one less peer announces a prefix.
+    fn release(&mut self, prefix: Prefix) {
+        if let Some(count) = self.announced.get_mut(&prefix) {
+            *count -= 1;
+            if *count == 0 {
+                self.announced.remove(&prefix);
+                self.covering(prefix).for_each(|agg| agg.prefixes -= 1);
+            }
+        }
+    }
+
+    fn covering(
+        &mut self,
+        prefix: Prefix,
+    ) -> impl Iterator<Item = &mut AggregateStats> {
+        self.aggregates
+            .iter_mut()
+            .filter(move |agg| agg.aggregate.covers(prefix))
+    }
+}
+
+//------------ LineFormat ----------------------------------------------------
+
+/// Formats the measurements as lines.
+struct LineFormat {
+    peer_measurement: String,
+    aggregate_measurement: String,
+
+    /// The tags added to every line, escaped, each starting with a comma.
+    tags: String,
+}
+
+impl LineFormat {
+    fn new(
+        measurement_prefix: &str,
+        tags: &BTreeMap<String, String>,
+    ) -> Self {
+        Self {
+            peer_measurement: escape(
+                &format!("{measurement_prefix}_peer"),
+                ", ",
+            ),
+            aggregate_measurement: escape(
+                &format!("{measurement_prefix}_prefix_aggregate"),
+                ", ",
+            ),
+            tags: tags
+                .iter()
+                .map(|(key, value)| {
+                    format!(
+                        ",{}={}",
+                        escape(key, ", ="),
+                        escape(value, ", =")
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the lines for the interval that took `elapsed` and starts
+    /// a new interval.
+    fn format(
+        &self,
+        stats: &mut Stats,
+        now: DateTime<Utc>,
+        elapsed: Duration,
+    ) -> Vec<String> {
+        let timestamp = now.timestamp_nanos_opt().unwrap_or_default();
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let mut res = Vec::new();
+
+        let mut peers = stats.peers.values_mut().collect::<Vec<_>>();
+        peers.sort_by_key(|peer| (peer.peer_ip, peer.peer_as));
+        for peer in peers {
+            let updates = peer.announcements + peer.withdrawals;
+            let mut line = String::new();
+            let _ = write!(
+                line,
+                "{},peer_ip={},peer_as={}{} announcements={}i,\
+                withdrawals={}i,update_rate={:.3},prefixes={}i {timestamp}",
+                self.peer_measurement,
+                peer.peer_ip,
+                peer.peer_as.into_u32(),
+                self.tags,
+                peer.announcements,
+                peer.withdrawals,
+                updates as f64 / secs,
+                peer.prefixes.len(),
+            );
+            res.push(line);
+            peer.announcements = 0;
+            peer.withdrawals = 0;
+        }
+
+        for agg in &mut stats.aggregates {
+            let updates = agg.announcements + agg.withdrawals;
+            let mut line = String::new();
+            let _ = write!(
+                line,
+                "{},aggregate={}{} announcements={}i,withdrawals={}i,\
+                update_rate={:.3},churn={}i,prefixes={}i {timestamp}",
+                self.aggregate_measurement,
+                agg.aggregate,
+                self.tags,
+                agg.announcements,
+                agg.withdrawals,
+                updates as f64 / secs,
+                agg.updated.len(),
+                agg.prefixes,
+            );
+            res.push(line);
+            agg.announcements = 0;
+            agg.withdrawals = 0;
+            agg.updated.clear();
+        }
+        res
+    }
+}
+
+/// Escapes the given characters with a backslash.
+fn escape(value: &str, chars: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    for c in value.chars() {
+        if chars.contains(c) {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+//------------ Writer --------------------------------------------------------
+
+/// Sends lines to InfluxDB.
+#[derive(Clone)]
+enum Writer {
+    Http {
+        http: HttpClient,
+        url: Url,
+        token: Option<String>,
+    },
+    Udp {
+        socket: Arc<UdpSocket>,
+        max_datagram_size: usize,
+    },
+}
+
+impl Writer {
+    async fn new(
+        url: &Url,
+        token: Option<String>,
+        max_datagram_size: usize,
+        http: HttpClient,
+    ) -> Result<Self, String> {
+        match url.scheme() {
+            "http" | "https" => Ok(Writer::Http {
+                http,
+                url: url.clone(),
+                token,
+            }),
+            "udp" => {
+                let host = url.host_str().ok_or("missing host in URL")?;
+                let port = url.port().ok_or("missing port in URL")?;
+                let addr: SocketAddr = tokio::net::lookup_host((host, port))
+                    .await
+                    .map_err(|err| format!("cannot resolve {host}: {err}"))?
+                    .next()
+                    .ok_or_else(|| format!("cannot resolve {host}"))?;
+                let local: SocketAddr = match addr {
+                    SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+                    SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+                };
+                let socket = UdpSocket::bind(local).await.map_err(|err| {
+                    format!("cannot bind UDP socket: {err}")
+                })?;
+                socket.connect(addr).await.map_err(|err| {
+                    format!("cannot connect to {addr}: {err}")
+                })?;
+                Ok(Writer::Udp {
+                    socket: Arc::new(socket),
+                    max_datagram_size,
+                })
+            }
+            scheme => Err(format!(
+                "unsupported URL scheme '{scheme}', use http, https or udp"
+            )),
+        }
+    }
+
+    async fn write(&self, lines: &[String]) -> Result<(), String> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        match self {
+            Writer::Http { http, url, token } => {
+                let mut request =
+                    http.post(url.clone()).body(lines.join("\n"));
+                if let Some(token) = token {
+                    request = request
+                        .header("Authorization", format!("Token {token}"));
+                }
+                let response =
+                    request.send().await.map_err(|err| err.to_string())?;
+                let status = response.status();
+                if status.is_success() {
+                    return Ok(());
+                }
+                let text = response.text().await.unwrap_or_default();
+                Err(format!("{status}: {}", text.trim()))
+            }
+            Writer::Udp {
+                socket,
+                max_datagram_size,
+            } => {
+                // Lines are never split, so a line longer than the maximum
+                // size is sent on its own.
+                let mut datagram = String::new();
+                for line in lines {
+                    if !datagram.is_empty()
+                        && datagram.len() + 1 + line.len()
+                            > *max_datagram_size
+                    {
+                        socket
+                            .send(datagram.as_bytes())
+                            .await
+                            .map_err(|err| err.to_string())?;
+                        datagram.clear();
+                    }
+                    if !datagram.is_empty() {
+                        datagram.push('\n');
+                    }
+                    datagram.push_str(line);
+                }
+                socket
+                    .send(datagram.as_bytes())
+                    .await
+                    .map_err(|err| err.to_string())?;
+                Ok(())
+            }
+        }
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        roto_runtime::types::{MrtContext, Provenance},
+        targets::file::row::tests::mk_route,
+        tests::util::{http::MockServer, https},
+    };
+
+    use super::*;
+
+    fn payload(
+        ingress_id: IngressId,
+        prefix: &str,
+        status: RouteStatus,
+    ) -> Payload {
+        let provenance = Provenance::for_bgp(
+            ingress_id,
+            format!("192.0.2.{ingress_id}").parse().unwrap(),
+            Asn::from_u32(65000 + ingress_id),
+        );
+        Payload::new(
+            mk_route(prefix, &[65000], &[]),
+            RouteContext::Mrt(MrtContext { status, provenance }),
+            None,
+        )
+    }
+
+    fn format(stats: &mut Stats) -> Vec<String> {
+        LineFormat::new("rotonda", &[("host".into(), "rr 1".into())].into())
+            .format(
+                stats,
+                DateTime::from_timestamp(1, 0).unwrap(),
+                Duration::from_secs(2),
+            )
+    }
+
+    #[test]
+    fn peers_and_aggregates_are_measured() {
+        let mut stats = Stats::new(&[
+            "0.0.0.0/0".parse().unwrap(),
+            "10.0.0.0/8".parse().unwrap(),
+        ]);
+        for (id, prefix, status) in [
+            (1, "10.1.0.0/16", RouteStatus::Active),
+            (1, "10.1.0.0/16", RouteStatus::Active),
+            (1, "192.0.2.0/24", RouteStatus::Active),
+            (2, "10.1.0.0/16", RouteStatus::Active),
+            (2, "192.0.2.0/24", RouteStatus::Withdrawn),
+        ] {
+            stats.update(Update::Single(payload(id, prefix, status)));
+        }
+
+        assert_eq!(
+            format(&mut stats),
+            [
+                "rotonda_peer,peer_ip=192.0.2.1,peer_as=65001,host=rr\\ 1 \
+                announcements=3i,withdrawals=0i,update_rate=1.500,\
+                prefixes=2i 1000000000",
+                "rotonda_peer,peer_ip=192.0.2.2,peer_as=65002,host=rr\\ 1 \
+                announcements=1i,withdrawals=1i,update_rate=1.000,\
+                prefixes=1i 1000000000",
+                "rotonda_prefix_aggregate,aggregate=0.0.0.0/0,host=rr\\ 1 \
+                announcements=4i,withdrawals=1i,update_rate=2.500,churn=2i,\
+                prefixes=2i 1000000000",
+                "rotonda_prefix_aggregate,aggregate=10.0.0.0/8,host=rr\\ 1 \
+                announcements=3i,withdrawals=0i,update_rate=1.500,churn=1i,\
+                prefixes=1i 1000000000",
+            ]
+        );
+
+        // The counters start over, the prefixes are remembered until the
+        // session goes down.
+        stats.update(Update::Withdraw(1, None));
+        let lines = format(&mut stats);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains(
+            "withdrawals=0i,update_rate=0.000,\
+            prefixes=1i"
+        ));
+        assert!(lines[1].contains("churn=0i,prefixes=1i"));
+        assert!(lines[2].contains("churn=0i,prefixes=1i"));
+        stats.update(Update::Withdraw(2, None));
+        assert!(stats.announced.is_empty());
+        assert!(format(&mut stats)[0].ends_with("prefixes=0i 1000000000"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lines_are_posted_with_the_token() {
+        let server = MockServer::with_statuses(vec![], 204).await;
+
+        let url = format!("http://{}/api/v2/write?bucket=bgp", server.addr)
+            .parse()
+            .unwrap();
+        let writer =
+            Writer::new(&url, Some("secret".into()), 1400, HttpClient::new())
+                .await
+                .unwrap();
+        writer
+            .write(&["a x=1i".into(), "b x=2i".into()])
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("authorization"), Some("Token secret"));
+        assert_eq!(requests[0].body, "a x=1i\nb x=2i");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lines_are_posted_over_https() {
+        let server = MockServer::with_statuses(vec![], 204).await;
+        let front = https::front(server.addr).await;
+
+        let url = format!(
+            "https://localhost:{}/api/v2/write?bucket=bgp",
+            front.port()
+        )
+        .parse()
+        .unwrap();
+        let writer = Writer::new(&url, None, 1400, https::client())
+            .await
+            .unwrap();
+        writer.write(&["a x=1i".into()]).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].body, "a x=1i");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lines_are_packed_into_datagrams() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("udp://{}", server.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let writer = Writer::new(&url, None, 15, HttpClient::new())
+            .await
+            .unwrap();
+        writer
+            .write(&[
+                "a x=1i".into(),
+                "b x=2i".into(),
+                "c x=3i".into(),
+                "long x=1234567890i".into(),
+            ])
+            .await
+            .unwrap();
+
+        let mut buf = [0; 64];
+        for expected in ["a x=1i\nb x=2i", "c x=3i", "long x=1234567890i"] {
+            let len = server.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], expected.as_bytes());
+        }
+    }
+}
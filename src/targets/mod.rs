@@ -15,9 +15,29 @@
 //------------ Sub-modules ---------------------------------------------------
 //
 // These contain all the actual unit types grouped by shared functionality.
+mod alert;
+mod bgp;
+mod buffer;
+mod clickhouse;
+mod dead_letter;
+mod elasticsearch;
 mod file;
+mod gelf;
+mod grpc;
+mod http;
+mod influx;
 mod mqtt;
+pub(crate) mod mrt;
+mod nats;
 mod null;
+mod redis;
+mod remote_write;
+mod rtr;
+mod s3;
+mod sqlite;
+mod sse;
+mod syslog;
+mod websocket;
 
 pub use mqtt::DEF_MQTT_PORT;
 
@@ -30,18 +50,73 @@ use crate::{comms::Terminated, manager::Component};
 use serde::Deserialize;
 
 /// The component for outputting data.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type")]
 
 pub enum Target {
+    #[serde(rename = "alert-out")]
+    Alert(alert::target::Alert),
+
+    #[serde(rename = "bgp-out")]
+    Bgp(bgp::target::BgpOut),
+
+    #[serde(rename = "clickhouse-out")]
+    ClickHouse(clickhouse::target::ClickHouse),
+
+    #[serde(rename = "elasticsearch-out")]
+    Elasticsearch(elasticsearch::target::Elasticsearch),
+
     #[serde(rename = "file-out")]
     File(file::target::File),
 
+    #[serde(rename = "gelf-out")]
+    Gelf(gelf::target::Gelf),
+
+    #[serde(rename = "grpc-out")]
+    Grpc(grpc::target::GrpcOut),
+
+    #[serde(rename = "http-out")]
+    Http(http::target::Http),
+
+    #[serde(rename = "influx-out")]
+    Influx(influx::target::Influx),
+
     #[serde(rename = "mqtt-out")]
     Mqtt(mqtt::target::Mqtt),
 
+    #[serde(rename = "mrt-out")]
+    Mrt(mrt::target::MrtOut),
+
+    #[serde(rename = "nats-out")]
+    Nats(nats::target::Nats),
+
     #[serde(rename = "null-out")]
     Null(null::Target),
+
+    #[serde(rename = "redis-out")]
+    Redis(redis::target::Redis),
+
+    #[serde(rename = "remote-write-out")]
+    RemoteWrite(remote_write::target::RemoteWrite),
+
+    #[serde(rename = "rtr-out")]
+    Rtr(rtr::target::RtrOut),
+
+    #[serde(rename = "s3-out")]
+    S3(s3::target::S3),
+
+    #[serde(rename = "sqlite-out")]
+    Sqlite(sqlite::target::Sqlite),
+
+    #[serde(rename = "sse-out")]
+    Sse(sse::target::SseOut),
+
+    #[serde(rename = "syslog-out")]
+    Syslog(syslog::target::Syslog),
+
+    #[serde(rename = "websocket-out")]
+    WebSocket(websocket::target::WebSocketOut),
 }
 
 impl Target {
@@ -53,23 +128,103 @@ impl Target {
         waitpoint: WaitPoint,
     ) -> Result<(), Terminated> {
         match self {
+            Target::Alert(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::Bgp(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::ClickHouse(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::Elasticsearch(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
             Target::File(target) => {
                 target.run(component, cmd, waitpoint).await
             }
+            Target::Gelf(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::Grpc(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::Http(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::Influx(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
             Target::Mqtt(target) => {
                 target.run(component, cmd, waitpoint).await
             }
+            Target::Mrt(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::Nats(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
             Target::Null(target) => {
                 target.run(component, cmd, waitpoint).await
             }
+            Target::Redis(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::RemoteWrite(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::Rtr(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::S3(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::Sqlite(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::Sse(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::Syslog(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
+            Target::WebSocket(target) => {
+                target.run(component, cmd, waitpoint).await
+            }
         }
     }
 
     pub fn type_name(&self) -> &'static str {
         match self {
+            Target::Alert(_) => "alert-out",
+            Target::Bgp(_) => "bgp-out",
+            Target::ClickHouse(_) => "clickhouse-out",
+            Target::Elasticsearch(_) => "elasticsearch-out",
             Target::File(_) => "file-out",
+            Target::Gelf(_) => "gelf-out",
+            Target::Grpc(_) => "grpc-out",
+            Target::Http(_) => "http-out",
+            Target::Influx(_) => "influx-out",
             Target::Mqtt(_) => "mqtt-out",
+            Target::Mrt(_) => "mrt-out",
+            Target::Nats(_) => "nats-out",
             Target::Null(_) => "null-out",
+            Target::Redis(_) => "redis-out",
+            Target::RemoteWrite(_) => "remote-write-out",
+            Target::Rtr(_) => "rtr-out",
+            Target::S3(_) => "s3-out",
+            Target::Sqlite(_) => "sqlite-out",
+            Target::Sse(_) => "sse-out",
+            Target::Syslog(_) => "syslog-out",
+            Target::WebSocket(_) => "websocket-out",
         }
     }
+
+    /// Returns whether a running target of this type applies a changed
+    /// configuration, including changed links, itself.
+    ///
+    /// Other targets are restarted with their changed configuration.
+    pub fn reconfigures_in_place(&self) -> bool {
+        matches!(self, Target::Mqtt(_) | Target::Null(_))
+    }
 }
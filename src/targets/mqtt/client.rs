@@ -0,0 +1,172 @@
+//! The MQTT client, speaking either MQTT 3.1.1 or MQTT 5.
+
+use async_trait::async_trait;
+use mqtt::{v5, MqttOptions, QoS};
+
+use super::{
+    config::{MessageProperties, ProtocolVersion},
+    connection::{
+        poll_result_v311, Client, EventLoop, MqttPollResult, PollEvent,
+    },
+    error::MqttError,
+};
+
+//------------ MqttClient ----------------------------------------------------
+
+#[derive(Clone, Debug)]
+pub enum MqttClient {
+    V311(mqtt::AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+#[async_trait]
+impl Client for MqttClient {
+    type EventLoopType = MqttEventLoop;
+
+    fn new(
+        options: MqttOptions,
+        protocol_version: ProtocolVersion,
+        cap: usize,
+    ) -> (Self, Self::EventLoopType) {
+        match protocol_version {
+            ProtocolVersion::V311 => {
+                let (client, event_loop) =
+                    mqtt::AsyncClient::new(options, cap);
+                (
+                    Self::V311(client),
+                    MqttEventLoop::V311(Box::new(event_loop)),
+                )
+            }
+            ProtocolVersion::V5 => {
+                let (client, event_loop) =
+                    v5::AsyncClient::new(v5_options(&options), cap);
+                (Self::V5(client), MqttEventLoop::V5(Box::new(event_loop)))
+            }
+        }
+    }
+
+    async fn publish<S, V>(
+        &self,
+        topic: S,
+        qos: QoS,
+        retain: bool,
+        payload: V,
+        properties: &MessageProperties,
+    ) -> Result<(), MqttError>
+    where
+        S: Into<String> + Send,
+        V: Into<Vec<u8>> + Send,
+    {
+        match self {
+            Self::V311(client) => client
+                .publish(topic, qos, retain, payload)
+                .await
+                .map_err(MqttError::Error),
+            Self::V5(client) => {
+                let qos = match qos {
+                    QoS::AtMostOnce => v5::mqttbytes::QoS::AtMostOnce,
+                    QoS::AtLeastOnce => v5::mqttbytes::QoS::AtLeastOnce,
+                    QoS::ExactlyOnce => v5::mqttbytes::QoS::ExactlyOnce,
+                };
+                let properties = v5::mqttbytes::v5::PublishProperties {
+                    // The content is UTF-8 encoded JSON.
+                    payload_format_indicator: Some(1),
+                    message_expiry_interval: properties.message_expiry_secs,
+                    content_type: properties.content_type.clone(),
+                    user_properties: properties
+                        .user_properties
+                        .iter()
+                        .map(|(name, value)| (name.clone(), value.clone()))
+                        .collect(),
+                    ..Default::default()
+                };
+                client
+                    .publish_with_properties(
+                        topic,
+                        qos,
+                        retain,
+                        payload.into(),
+                        properties,
+                    )
+                    .await
+                    .map_err(MqttError::ErrorV5)
+            }
+        }
+    }
+
+    async fn disconnect(&self) -> Result<(), MqttError> {
+        match self {
+            Self::V311(client) => {
+                client.disconnect().await.map_err(MqttError::Error)
+            }
+            Self::V5(client) => {
+                client.disconnect().await.map_err(MqttError::ErrorV5)
+            }
+        }
+    }
+}
+
+/// Converts the options of MQTT 3.1.1 to those of MQTT 5.
+fn v5_options(options: &MqttOptions) -> v5::MqttOptions {
+    let (host, port) = options.broker_address();
+    let mut res = v5::MqttOptions::new(options.client_id(), host, port);
+    res.set_transport(options.transport())
+        .set_keep_alive(options.keep_alive())
+        .set_clean_start(options.clean_session())
+        .set_request_channel_capacity(options.request_channel_capacity())
+        .set_outgoing_inflight_upper_limit(options.inflight());
+    if let Some((username, password)) = options.credentials() {
+        res.set_credentials(username, password);
+    }
+    res
+}
+
+//------------ MqttEventLoop -------------------------------------------------
+
+pub enum MqttEventLoop {
+    V311(Box<mqtt::EventLoop>),
+    V5(Box<v5::EventLoop>),
+}
+
+#[async_trait]
+impl EventLoop for MqttEventLoop {
+    async fn poll(&mut self) -> MqttPollResult {
+        match self {
+            Self::V311(event_loop) => {
+                poll_result_v311(event_loop.poll().await)
+            }
+            Self::V5(event_loop) => match event_loop.poll().await {
+                Ok(v5::Event::Incoming(v5::Incoming::ConnAck(ack))) => {
+                    match ack.code {
+                        v5::mqttbytes::v5::ConnectReturnCode::Success => {
+                            Ok(PollEvent::Connected)
+                        }
+                        code => Ok(PollEvent::Refused(format!("{code:?}"))),
+                    }
+                }
+                Ok(_) => Ok(PollEvent::Other),
+                Err(err) => Err(err.to_string()),
+            },
+        }
+    }
+
+    fn set_connection_timeout(&mut self, timeout: u64) {
+        match self {
+            Self::V311(event_loop) => {
+                let mut options = event_loop.network_options();
+                options.set_connection_timeout(timeout);
+                event_loop.set_network_options(options);
+            }
+            Self::V5(event_loop) => {
+                event_loop.options.set_connection_timeout(timeout);
+            }
+        }
+    }
+
+    fn inflight(&self) -> u16 {
+        match self {
+            Self::V311(event_loop) => event_loop.state.inflight(),
+            Self::V5(event_loop) => event_loop.state.inflight(),
+        }
+    }
+}
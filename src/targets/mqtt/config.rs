@@ -1,8 +1,14 @@
-use std::{fmt::Display, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    time::Duration,
+};
 
-use serde::{self, Deserialize};
+use serde::{self, Deserialize, Serialize};
 use serde_with::serde_as;
 
+use crate::{common::tls::TlsClientConfig, config::ConfigPath};
+
 pub const DEF_MQTT_PORT: u16 = 1883;
 
 // -------- Destination ------------------------------------------------------
@@ -85,6 +91,75 @@ impl From<ClientId> for String {
     }
 }
 
+// -------- Qos --------------------------------------------------------------
+
+/// MQTT quality of service level
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize,
+)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct Qos(u8);
+
+impl TryFrom<u8> for Qos {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        if value <= 2 {
+            Ok(Self(value))
+        } else {
+            Err("MQTT quality of service must be 0, 1 or 2")
+        }
+    }
+}
+
+impl From<Qos> for u8 {
+    fn from(val: Qos) -> Self {
+        val.0
+    }
+}
+
+impl From<Qos> for mqtt::QoS {
+    fn from(val: Qos) -> Self {
+        match val.0 {
+            0 => mqtt::QoS::AtMostOnce,
+            1 => mqtt::QoS::AtLeastOnce,
+            _ => mqtt::QoS::ExactlyOnce,
+        }
+    }
+}
+
+// -------- ProtocolVersion --------------------------------------------------
+
+/// The version of the MQTT protocol to speak to the server
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum ProtocolVersion {
+    #[default]
+    #[serde(rename = "3.1.1")]
+    V311,
+
+    #[serde(rename = "5")]
+    V5,
+}
+
+// -------- MessageProperties ------------------------------------------------
+
+/// Properties sent with each message when using MQTT 5
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct MessageProperties {
+    /// How many seconds the server keeps a message for subscribers
+    #[serde(default)]
+    pub message_expiry_secs: Option<u32>,
+
+    /// The MIME type of the message content
+    #[serde(default)]
+    pub content_type: Option<String>,
+
+    /// Name and value pairs to add to each message
+    #[serde(default)]
+    pub user_properties: BTreeMap<String, String>,
+}
+
 #[serde_as]
 #[derive(Debug, Default, Deserialize)]
 pub struct Config {
@@ -92,7 +167,14 @@ pub struct Config {
     pub destination: Destination,
 
     #[serde(default = "Config::default_qos")]
-    pub qos: i32,
+    pub qos: Qos,
+
+    /// The quality of service per topic, overriding `qos`.
+    ///
+    /// Topics are those of the output stream messages, i.e. what `{id}`
+    /// in `topic_template` is replaced with.
+    #[serde(default)]
+    pub topic_qos: HashMap<String, Qos>,
 
     pub client_id: ClientId,
 
@@ -110,15 +192,37 @@ pub struct Config {
     #[serde(default = "Config::default_publish_max_secs")]
     pub publish_max_secs: Duration,
 
-    /// How many messages to buffer if publishing encounters delays
+    /// How many messages to buffer in memory if publishing encounters
+    /// delays or the server cannot be reached
     #[serde(default = "Config::default_queue_size")]
     pub queue_size: u16,
 
+    /// A directory to queue messages in once the in-memory queue is full
+    ///
+    /// Messages queued here survive a restart.
+    #[serde(default)]
+    pub queue_dir: Option<ConfigPath>,
+
+    /// How many bytes of messages to queue in `queue_dir` at most
+    #[serde(default = "Config::default_queue_max_bytes")]
+    pub queue_max_bytes: u64,
+
     #[serde(default)]
     pub username: Option<String>,
 
     #[serde(default)]
     pub password: Option<String>,
+
+    /// Connect to the MQTT server using TLS
+    #[serde(default)]
+    pub tls: Option<TlsClientConfig>,
+
+    #[serde(default)]
+    pub protocol_version: ProtocolVersion,
+
+    /// Properties to send with each message, for MQTT 5 only
+    #[serde(default)]
+    pub properties: MessageProperties,
 }
 
 impl Config {
@@ -126,8 +230,8 @@ impl Config {
     ///   0 - At most once delivery
     ///   1 - At least once delivery
     ///   2 - Exactly once delivery
-    pub fn default_qos() -> i32 {
-        2
+    pub fn default_qos() -> Qos {
+        Qos(2)
     }
 
     /// The default re-connect timeout in seconds.
@@ -145,8 +249,29 @@ impl Config {
         "rotonda/{id}".to_string()
     }
 
-    /// The default re-connect timeout in seconds.
+    /// The default number of messages to buffer in memory.
     pub fn default_queue_size() -> u16 {
         1000
     }
+
+    /// The default size limit of the on-disk queue, 64 MiB.
+    pub fn default_queue_max_bytes() -> u64 {
+        64 * 1024 * 1024
+    }
+
+    /// Returns the quality of service for messages of a topic.
+    pub fn qos_for(&self, topic: &str) -> Qos {
+        self.topic_qos.get(topic).copied().unwrap_or(self.qos)
+    }
+
+    /// Returns whether a new configuration requires a new connection.
+    pub fn needs_reconnect(&self, new: &Config) -> bool {
+        new.client_id != self.client_id
+            || new.destination != self.destination
+            || new.queue_size != self.queue_size
+            || new.username != self.username
+            || new.password != self.password
+            || new.tls != self.tls
+            || new.protocol_version != self.protocol_version
+    }
 }
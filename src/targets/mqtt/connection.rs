@@ -3,13 +3,28 @@ use std::{sync::Arc, time::Duration};
 use arc_swap::ArcSwap;
 use async_trait::async_trait;
 use mqtt::{
-    ClientError, ConnAck, ConnectReturnCode, ConnectionError, Event,
-    Incoming, MqttOptions, NetworkOptions, QoS,
+    ConnAck, ConnectReturnCode, ConnectionError, Event, Incoming,
+    MqttOptions, QoS,
+};
+use tokio::{
+    sync::{oneshot, watch},
+    task::JoinHandle,
+    time::interval,
 };
-use tokio::{sync::oneshot, task::JoinHandle, time::interval};
 use ConnectionState::*;
 
-use super::{config::Config, status_reporter::MqttStatusReporter};
+use super::{
+    config::{Config, Destination, MessageProperties, ProtocolVersion},
+    error::MqttError,
+    status_reporter::MqttStatusReporter,
+    tls::TlsRelay,
+};
+
+/// How many seconds connecting, including the MQTT handshake, may take.
+const CONNECTION_TIMEOUT: u64 = 1;
+
+/// How many seconds connecting may take when also doing a TLS handshake.
+const TLS_CONNECTION_TIMEOUT: u64 = 10;
 
 // TODO: Add a state transition diagram here.
 #[derive(Debug)]
@@ -25,53 +40,64 @@ impl<C: Client> PartialEq for ConnectionState<C> {
     }
 }
 
-pub type MqttPollResult = Result<Event, ConnectionError>;
+/// What polling an event loop produced, whatever the protocol version.
+#[derive(Debug)]
+pub enum PollEvent {
+    /// The server accepted the connection.
+    Connected,
+
+    /// The server refused the connection for the given reason.
+    Refused(String),
+
+    /// Anything else, which is of no interest to us.
+    Other,
+}
+
+pub type MqttPollResult = Result<PollEvent, String>;
+
+/// Converts what polling an MQTT 3.1.1 event loop produced.
+pub fn poll_result_v311(
+    res: Result<Event, ConnectionError>,
+) -> MqttPollResult {
+    match res {
+        Ok(Event::Incoming(Incoming::ConnAck(ConnAck {
+            code: ConnectReturnCode::Success,
+            ..
+        }))) => Ok(PollEvent::Connected),
+        Ok(Event::Incoming(Incoming::ConnAck(ConnAck { code, .. }))) => {
+            Ok(PollEvent::Refused(format!("{code:?}")))
+        }
+        Ok(_) => Ok(PollEvent::Other),
+        Err(err) => Err(err.to_string()),
+    }
+}
 
 #[async_trait]
 pub trait EventLoop: Send {
     async fn poll(&mut self) -> MqttPollResult;
 
-    fn mqtt_options(&self) -> &MqttOptions;
-
-    fn network_options(&self) -> NetworkOptions;
-
-    fn set_network_options(
-        &mut self,
-        network_options: NetworkOptions,
-    ) -> &mut Self;
+    /// Sets how many seconds connecting may take.
+    fn set_connection_timeout(&mut self, timeout: u64);
 
     fn inflight(&self) -> u16;
 }
 
-#[async_trait]
-impl EventLoop for mqtt::EventLoop {
-    async fn poll(&mut self) -> MqttPollResult {
-        self.poll().await
-    }
+pub struct Connection<C: Client> {
+    mqtt_options: MqttOptions,
+    protocol_version: ProtocolVersion,
 
-    fn mqtt_options(&self) -> &MqttOptions {
-        &self.mqtt_options
-    }
+    /// The address of the server, for reporting.
+    broker_address: Destination,
 
-    fn network_options(&self) -> NetworkOptions {
-        self.network_options()
-    }
+    /// The relay TLS connections go through, if TLS is used.
+    relay: Option<TlsRelay>,
 
-    fn set_network_options(
-        &mut self,
-        network_options: NetworkOptions,
-    ) -> &mut Self {
-        self.set_network_options(network_options)
-    }
+    connection_timeout: u64,
+    retry_delay: Arc<ArcSwap<Duration>>,
 
-    fn inflight(&self) -> u16 {
-        self.state.inflight()
-    }
-}
+    /// Whether the server accepted the current connection.
+    connected: Arc<watch::Sender<bool>>,
 
-pub struct Connection<C: Client> {
-    mqtt_options: MqttOptions,
-    retry_delay: Arc<ArcSwap<Duration>>,
     status_reporter: Arc<MqttStatusReporter>,
     state: ConnectionState<C>,
 }
@@ -79,22 +105,46 @@ pub struct Connection<C: Client> {
 impl<C: Client> Connection<C> {
     pub fn new(
         mqtt_options: MqttOptions,
+        protocol_version: ProtocolVersion,
         retry_delay: Duration,
         status_reporter: Arc<MqttStatusReporter>,
     ) -> Self {
         Self {
+            broker_address: mqtt_options.broker_address().into(),
             mqtt_options,
+            protocol_version,
+            relay: None,
+            connection_timeout: CONNECTION_TIMEOUT,
             retry_delay: Arc::new(ArcSwap::from_pointee(retry_delay)),
+            connected: Arc::new(watch::channel(false).0),
             status_reporter,
             state: New,
         }
     }
 
+    /// Connects through a TLS relay to the given server.
+    ///
+    /// The MQTT options must point the client at the socket of the relay.
+    pub fn with_relay(
+        mut self,
+        relay: TlsRelay,
+        broker_address: Destination,
+    ) -> Self {
+        self.relay = Some(relay);
+        self.broker_address = broker_address;
+        self.connection_timeout = TLS_CONNECTION_TIMEOUT;
+        self
+    }
+
+    /// Returns a receiver for whether the server accepted the connection.
+    pub fn connected(&self) -> watch::Receiver<bool> {
+        self.connected.subscribe()
+    }
+
     pub async fn process(&mut self) -> Option<C> {
         match &mut self.state {
             New => {
-                let broker_address =
-                    self.mqtt_options.broker_address().into();
+                let broker_address = self.broker_address.clone();
 
                 self.status_reporter.connecting(&broker_address);
 
@@ -128,8 +178,12 @@ impl<C: Client> Connection<C> {
                     "MQTT Event Loop",
                     Self::mqtt_event_loop(
                         mqtt_options,
+                        self.protocol_version,
+                        broker_address,
+                        self.connection_timeout,
                         client_handover_tx,
                         self.retry_delay.clone(),
+                        self.connected.clone(),
                         status_reporter,
                     ),
                 );
@@ -178,13 +232,13 @@ impl<C: Client> Connection<C> {
             if let Err(err) = client.disconnect().await {
                 self.status_reporter.connection_error(err);
             }
-            self.status_reporter
-                .disconnected(&self.mqtt_options.broker_address().into());
+            self.status_reporter.disconnected(&self.broker_address);
             // TODO: Shutdown gracefully allowing in-flight messages in the
             // rumqqtc library time to be sent?
             join_handle.abort();
         }
 
+        self.connected.send_replace(false);
         self.state = Stopped;
     }
 
@@ -206,24 +260,27 @@ impl<C: Client> Connection<C> {
 
 impl<C: Client> Connection<C> {
     /// Returns false if the initial connection never succeeded, true otherwise.
+    #[allow(clippy::too_many_arguments)]
     async fn mqtt_event_loop(
         mqtt_options: MqttOptions,
+        protocol_version: ProtocolVersion,
+        broker_address: Destination,
+        connection_timeout: u64,
         client_handover_tx: oneshot::Sender<C>,
         retry_delay: Arc<ArcSwap<Duration>>,
+        connected: Arc<watch::Sender<bool>>,
         status_reporter: Arc<MqttStatusReporter>,
     ) {
-        let broker_address = mqtt_options.broker_address().into();
         let cap = mqtt_options.request_channel_capacity();
-        let (client, mut event_loop) = C::new(mqtt_options, cap);
+        let (client, mut event_loop) =
+            C::new(mqtt_options, protocol_version, cap);
 
         if client_handover_tx.send(client).is_err() {
             // Abort
             return;
         }
 
-        let mut conn_opts = event_loop.network_options();
-        conn_opts.set_connection_timeout(1);
-        event_loop.set_network_options(conn_opts);
+        event_loop.set_connection_timeout(connection_timeout);
 
         let mut conn_count = 0;
         let mut retry = false;
@@ -231,26 +288,20 @@ impl<C: Client> Connection<C> {
             match event_loop.poll().await {
                 Ok(event) => {
                     match event {
-                        Event::Incoming(Incoming::ConnAck(ConnAck {
-                            code: ConnectReturnCode::Success,
-                            ..
-                        })) => {
+                        PollEvent::Connected => {
                             // Great!
                             status_reporter.connected(&broker_address);
+                            connected.send_replace(true);
                             conn_count += 1;
                         }
 
-                        Event::Incoming(Incoming::ConnAck(ConnAck {
-                            code,
-                            ..
-                        })) => {
+                        PollEvent::Refused(reason) => {
                             // Connection failed
-                            status_reporter
-                                .connection_error(format!("{code:?}"));
+                            status_reporter.connection_error(reason);
                             retry = true;
                         }
 
-                        _ => {
+                        PollEvent::Other => {
                             // Ignored for now
                         }
                     }
@@ -273,6 +324,8 @@ impl<C: Client> Connection<C> {
             status_reporter.inflight_update(event_loop.inflight());
 
             if retry {
+                connected.send_replace(false);
+
                 let retry_delay = **retry_delay.load();
 
                 if conn_count > 0 {
@@ -296,47 +349,32 @@ impl<C: Client> Connection<C> {
 pub trait Client: Clone + Send + Sync + 'static {
     type EventLoopType: EventLoop;
 
-    fn new(options: MqttOptions, cap: usize) -> (Self, Self::EventLoopType);
-
+    /// Creates a client for the given protocol version.
+    ///
+    /// The options are those of MQTT 3.1.1, clients for MQTT 5 convert
+    /// them.
+    fn new(
+        options: MqttOptions,
+        protocol_version: ProtocolVersion,
+        cap: usize,
+    ) -> (Self, Self::EventLoopType);
+
+    /// Publishes a message.
+    ///
+    /// The properties are only sent when using MQTT 5.
     async fn publish<S, V>(
         &self,
         topic: S,
         qos: QoS,
         retain: bool,
         payload: V,
-    ) -> Result<(), ClientError>
+        properties: &MessageProperties,
+    ) -> Result<(), MqttError>
     where
         S: Into<String> + Send,
         V: Into<Vec<u8>> + Send;
 
-    async fn disconnect(&self) -> Result<(), ClientError>;
-}
-
-#[async_trait]
-impl Client for mqtt::AsyncClient {
-    type EventLoopType = mqtt::EventLoop;
-
-    fn new(options: MqttOptions, cap: usize) -> (Self, Self::EventLoopType) {
-        Self::new(options, cap)
-    }
-
-    async fn publish<S, V>(
-        &self,
-        topic: S,
-        qos: QoS,
-        retain: bool,
-        payload: V,
-    ) -> Result<(), ClientError>
-    where
-        S: Into<String> + Send,
-        V: Into<Vec<u8>> + Send,
-    {
-        self.publish(topic, qos, retain, payload).await
-    }
-
-    async fn disconnect(&self) -> Result<(), ClientError> {
-        self.disconnect().await
-    }
+    async fn disconnect(&self) -> Result<(), MqttError>;
 }
 
 pub trait ConnectionFactory {
@@ -347,5 +385,5 @@ pub trait ConnectionFactory {
     fn connect(
         config: &Config,
         status_reporter: Arc<MqttStatusReporter>,
-    ) -> Connection<Self::ClientType>;
+    ) -> Result<Connection<Self::ClientType>, String>;
 }
@@ -1,18 +1,22 @@
 use std::fmt::Display;
 
-use mqtt::ClientError;
+use mqtt::{v5, ClientError};
 
 #[derive(Debug)]
 pub enum MqttError {
     Error(ClientError),
+    ErrorV5(v5::ClientError),
     Timeout,
+    NotConnected,
 }
 
 impl Display for MqttError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             MqttError::Error(err) => err.fmt(f),
+            MqttError::ErrorV5(err) => err.fmt(f),
             MqttError::Timeout => f.write_str("MQTT Timeout"),
+            MqttError::NotConnected => f.write_str("MQTT Not Connected"),
         }
     }
 }
@@ -18,6 +18,8 @@ pub struct MqttMetrics {
     pub connection_error_count: AtomicUsize,
     pub publish_error_count: AtomicUsize,
     pub in_flight_count: AtomicU16,
+    pub queued_count: AtomicUsize,
+    pub dropped_count: AtomicUsize,
     // pub not_acknowledged_count: AtomicUsize,
     topics: Arc<FrimMap<Arc<String>, Arc<TopicMetrics>>>,
 }
@@ -39,14 +41,15 @@ impl GraphStatus for MqttMetrics {
         match self.connection_established_state.load(SeqCst) {
             true => {
                 format!(
-                    "in-flight: {}\npublished: {}\nerrors: {}",
+                    "in-flight: {}\npublished: {}\nerrors: {}\nqueued: {}",
                     self.in_flight_count.load(SeqCst),
                     self.topics.guard().iter().fold(0, |acc, v| acc
                         + v.1.publish_counts.load(SeqCst)),
                     self.publish_error_count.load(SeqCst),
+                    self.queued_count.load(SeqCst),
                 )
             }
-            false => "N/A".to_string(),
+            false => format!("queued: {}", self.queued_count.load(SeqCst)),
         }
     }
 
@@ -92,6 +95,18 @@ impl MqttMetrics {
         MetricType::Gauge,
         MetricUnit::Total,
     );
+    const QUEUED_COUNT_METRIC: Metric = Metric::new(
+        "mqtt_target_queued_count",
+        "the number of messages waiting in the queue to be published",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const DROPPED_COUNT_METRIC: Metric = Metric::new(
+        "mqtt_target_dropped_count",
+        "the number of messages dropped because the queue was full",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
     // The rumqttc library has this count internally but doesn't expose it to
     // us. const PUBLISH_NOT_ACKNOWLEDGED_COUNT_METRIC: Metric = Metric::new(
     //     "mqtt_target_published_not_acknowledged_count", "the number of QoS
@@ -133,6 +148,16 @@ impl metrics::Source for MqttMetrics {
             Some(unit_name),
             self.publish_error_count.load(SeqCst),
         );
+        target.append_simple(
+            &Self::QUEUED_COUNT_METRIC,
+            Some(unit_name),
+            self.queued_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DROPPED_COUNT_METRIC,
+            Some(unit_name),
+            self.dropped_count.load(SeqCst),
+        );
         // target.append_simple(
         //     &Self::PUBLISH_NOT_ACKNOWLEDGED_COUNT_METRIC,
         //     Some(unit_name),
@@ -1,8 +1,11 @@
+mod client;
 mod config;
 mod connection;
 mod error;
 mod metrics;
+mod queue;
 mod status_reporter;
+mod tls;
 
 pub use config::DEF_MQTT_PORT;
 pub mod target;
@@ -0,0 +1,266 @@
+//! Queueing of messages while they cannot be published.
+//!
+//! Messages that cannot be published right away, because the MQTT server
+//! cannot be reached or has not yet caught up, are queued in memory up to
+//! `queue_size` messages. With a `queue_dir`, messages beyond that are
+//! appended to a file `<unit>.queue` in that directory, up to
+//! `queue_max_bytes`, and the messages still queued in memory are written
+//! to it on shutdown, so that they are published after a restart. Only
+//! once both limits are reached are messages dropped.
+//!
+//! The file holds one message per line, in JSON. A line cut short, as left
+//! behind by a crash halfway through a write, is removed when the file is
+//! opened. The file is emptied whenever all messages in it have been
+//! published.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use super::{status_reporter::MqttStatusReporter, target::SenderMsg};
+
+/// File extension of the queue files.
+const QUEUE_EXTENSION: &str = "queue";
+
+//------------ MessageQueue --------------------------------------------------
+
+/// The messages waiting to be published, oldest first.
+pub(super) struct MessageQueue {
+    /// The oldest messages.
+    memory: VecDeque<SenderMsg>,
+
+    /// How many messages to keep in memory.
+    memory_limit: usize,
+
+    /// The messages that did not fit in memory, if there is a queue file.
+    disk: Option<DiskQueue>,
+
+    status_reporter: Arc<MqttStatusReporter>,
+}
+
+impl MessageQueue {
+    pub fn new(
+        memory_limit: usize,
+        disk: Option<DiskQueue>,
+        status_reporter: Arc<MqttStatusReporter>,
+    ) -> Self {
+        let res = Self {
+            memory: VecDeque::new(),
+            memory_limit,
+            disk,
+            status_reporter,
+        };
+        res.status_reporter.queue_update(res.len());
+        res
+    }
+
+    pub fn set_memory_limit(&mut self, memory_limit: usize) {
+        self.memory_limit = memory_limit;
+    }
+
+    /// Returns the number of queued messages.
+    pub fn len(&self) -> usize {
+        self.memory.len() + self.disk.as_ref().map_or(0, |disk| disk.count)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds a message to the end of the queue, or drops it if full.
+    pub fn push_back(&mut self, msg: SenderMsg) {
+        match &mut self.disk {
+            Some(disk)
+                if disk.count > 0
+                    || self.memory.len() >= self.memory_limit =>
+            {
+                match disk.push(&msg) {
+                    Ok(true) => {}
+                    Ok(false) => self.status_reporter.message_dropped(),
+                    Err(err) => {
+                        self.status_reporter.queue_error(&disk.path, err);
+                        self.status_reporter.message_dropped();
+                    }
+                }
+            }
+            _ if self.memory.len() >= self.memory_limit => {
+                self.status_reporter.message_dropped();
+            }
+            _ => self.memory.push_back(msg),
+        }
+        self.status_reporter.queue_update(self.len());
+    }
+
+    /// Puts a message that could not be published back at the front.
+    ///
+    /// As the message was taken from the queue, it is kept in memory even
+    /// if that is full.
+    pub fn push_front(&mut self, msg: SenderMsg) {
+        self.memory.push_front(msg);
+        self.status_reporter.queue_update(self.len());
+    }
+
+    /// Takes the oldest message from the queue.
+    pub fn pop_front(&mut self) -> Option<SenderMsg> {
+        let mut res = self.memory.pop_front();
+        if res.is_none() {
+            if let Some(disk) = &mut self.disk {
+                res = disk.pop().unwrap_or_else(|err| {
+                    self.status_reporter.queue_error(&disk.path, err);
+                    None
+                });
+            }
+        }
+        self.status_reporter.queue_update(self.len());
+        res
+    }
+
+    /// Writes the messages queued in memory to the queue file, if any.
+    pub fn persist(&mut self) {
+        if let Some(disk) = &mut self.disk {
+            if let Err(err) = disk.prepend(self.memory.make_contiguous()) {
+                self.status_reporter.queue_error(&disk.path, err);
+            } else {
+                self.memory.clear();
+            }
+        }
+    }
+}
+
+//------------ DiskQueue -----------------------------------------------------
+
+/// The messages queued in a file.
+pub(super) struct DiskQueue {
+    path: PathBuf,
+
+    /// The file, for appending messages.
+    writer: File,
+
+    /// The file, positioned at the oldest message.
+    reader: BufReader<File>,
+
+    /// The position of the oldest message in the file.
+    read_pos: u64,
+
+    /// The length of the file.
+    len: u64,
+
+    /// The number of messages in the file.
+    count: usize,
+
+    /// How long the file may get.
+    max_bytes: u64,
+}
+
+impl DiskQueue {
+    /// Opens the queue file of a unit, picking up any messages left in it.
+    pub fn open(
+        dir: &Path,
+        unit_name: &str,
+        max_bytes: u64,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{unit_name}.{QUEUE_EXTENSION}"));
+        let writer = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(&path)?;
+
+        // Count the complete lines and drop anything after the last one.
+        let mut content = Vec::new();
+        (&writer).read_to_end(&mut content)?;
+        let count = content.iter().filter(|&&ch| ch == b'\n').count();
+        let len = content
+            .iter()
+            .rposition(|&ch| ch == b'\n')
+            .map_or(0, |pos| pos as u64 + 1);
+        if len != content.len() as u64 {
+            writer.set_len(len)?;
+        }
+
+        let reader = BufReader::new(File::open(&path)?);
+        Ok(Self {
+            path,
+            writer,
+            reader,
+            read_pos: 0,
+            len,
+            count,
+            max_bytes,
+        })
+    }
+
+    /// Appends a message, returning whether there was room for it.
+    fn push(&mut self, msg: &SenderMsg) -> io::Result<bool> {
+        let mut line = serde_json::to_vec(msg)?;
+        line.push(b'\n');
+        if self.len + line.len() as u64 > self.max_bytes {
+            // Make room by removing the messages already taken.
+            if self.read_pos == 0
+                || self.len - self.read_pos + line.len() as u64
+                    > self.max_bytes
+            {
+                return Ok(false);
+            }
+            self.prepend(&[])?;
+        }
+        self.writer.write_all(&line)?;
+        self.len += line.len() as u64;
+        self.count += 1;
+        Ok(true)
+    }
+
+    /// Takes the oldest message, skipping any that cannot be read.
+    fn pop(&mut self) -> io::Result<Option<SenderMsg>> {
+        let mut line = String::new();
+        while self.count > 0 {
+            line.clear();
+            let read = self.reader.read_line(&mut line)?;
+            if read == 0 {
+                // The file was changed behind our back.
+                self.count = 0;
+                break;
+            }
+            self.read_pos += read as u64;
+            self.count -= 1;
+            if self.count == 0 {
+                self.clear()?;
+            }
+            if let Ok(msg) = serde_json::from_str(&line) {
+                return Ok(Some(msg));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Rewrites the file with the given messages before the queued ones.
+    fn prepend(&mut self, msgs: &[SenderMsg]) -> io::Result<()> {
+        let mut content = Vec::new();
+        for msg in msgs {
+            serde_json::to_writer(&mut content, msg)?;
+            content.push(b'\n');
+        }
+        self.reader.read_to_end(&mut content)?;
+        self.writer.set_len(0)?;
+        self.writer.write_all(&content)?;
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.read_pos = 0;
+        self.len = content.len() as u64;
+        self.count += msgs.len();
+        Ok(())
+    }
+
+    /// Empties the file once all messages have been taken.
+    fn clear(&mut self) -> io::Result<()> {
+        self.writer.set_len(0)?;
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.read_pos = 0;
+        self.len = 0;
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     fmt::{Debug, Display},
+    path::Path,
     sync::{atomic::Ordering::SeqCst, Arc},
     time::Duration,
 };
@@ -9,6 +10,7 @@ use log::{debug, info, trace, warn};
 use crate::common::status_reporter::{
     sr_log, AnyStatusReporter, Chainable, Named, TargetStatusReporter,
 };
+use crate::health::{ComponentHealth, ComponentState};
 
 use super::{config::Destination, metrics::MqttMetrics};
 
@@ -16,6 +18,7 @@ use super::{config::Destination, metrics::MqttMetrics};
 pub struct MqttStatusReporter {
     name: String,
     metrics: Arc<MqttMetrics>,
+    health: Arc<ComponentHealth>,
 }
 
 impl MqttStatusReporter {
@@ -23,15 +26,23 @@ impl MqttStatusReporter {
         Self {
             name: format!("{}", name),
             metrics,
+            health: Default::default(),
         }
     }
 
+    /// Reports the state of the connection to the health of the target too.
+    pub fn with_health(mut self, health: Arc<ComponentHealth>) -> Self {
+        self.health = health;
+        self
+    }
+
     pub fn metrics(&self) -> Arc<MqttMetrics> {
         self.metrics.clone()
     }
 
     pub fn connecting(&self, broker_address: &Destination) {
         sr_log!(debug: self, "Connecting to MQTT server {}", broker_address);
+        self.health.set_state(ComponentState::Connecting);
     }
 
     pub fn connected(&self, broker_address: &Destination) {
@@ -39,6 +50,7 @@ impl MqttStatusReporter {
         self.metrics
             .connection_established_state
             .store(true, SeqCst);
+        self.health.set_state(ComponentState::Running);
     }
 
     pub fn disconnected(&self, broker_address: &Destination) {
@@ -51,6 +63,7 @@ impl MqttStatusReporter {
     pub fn connection_error<T: Display>(&self, err: T) {
         sr_log!(warn: self, "MQTT connection error: {}", err);
         self.metrics.connection_error_count.fetch_add(1, SeqCst);
+        self.health.report_error(err);
     }
 
     pub fn reconnecting(&self, connect_retry_secs: Duration) {
@@ -63,6 +76,7 @@ impl MqttStatusReporter {
             .connection_established_state
             .store(false, SeqCst);
         self.metrics.connection_lost_count.fetch_add(1, SeqCst);
+        self.health.set_state(ComponentState::Retrying);
     }
 
     pub fn publishing<T: Display, C: Display>(&self, topic: T, content: C) {
@@ -92,6 +106,35 @@ impl MqttStatusReporter {
     pub fn inflight_update(&self, inflight: u16) {
         self.metrics.in_flight_count.store(inflight, SeqCst);
     }
+
+    pub fn tls_error<T: Display>(
+        &self,
+        broker_address: &Destination,
+        err: T,
+    ) {
+        sr_log!(
+            warn: self,
+            "TLS connection to MQTT server at {} failed: {}",
+            broker_address, err
+        );
+    }
+
+    pub fn queue_update(&self, queued: usize) {
+        self.metrics.queued_count.store(queued, SeqCst);
+    }
+
+    pub fn message_dropped(&self) {
+        sr_log!(debug: self, "Queue full, dropping message");
+        self.metrics.dropped_count.fetch_add(1, SeqCst);
+    }
+
+    pub fn queue_error<T: Display>(&self, path: &Path, err: T) {
+        sr_log!(
+            warn: self,
+            "Cannot use queue file {}: {}",
+            path.display(), err
+        );
+    }
 }
 
 impl TargetStatusReporter for MqttStatusReporter {}
@@ -105,6 +148,7 @@ impl AnyStatusReporter for MqttStatusReporter {
 impl Chainable for MqttStatusReporter {
     fn add_child<T: Display>(&self, child_name: T) -> Self {
         Self::new(self.link_names(child_name), self.metrics.clone())
+            .with_health(self.health.clone())
     }
 }
 
@@ -1,15 +1,21 @@
 use std::{ops::ControlFlow, sync::Arc, time::Duration};
 
 use super::{
-    config::Config,
+    client::MqttClient,
+    config::{Config, MessageProperties, Qos},
     connection::{Client, Connection, ConnectionFactory},
     error::MqttError,
     metrics::MqttMetrics,
+    queue::{DiskQueue, MessageQueue},
     status_reporter::MqttStatusReporter,
+    tls::TlsRelay,
 };
 
 use crate::{
-        common::status_reporter::{AnyStatusReporter, TargetStatusReporter},
+    common::{
+        status_reporter::{AnyStatusReporter, TargetStatusReporter},
+        tls::TlsConnector,
+    },
     comms::{AnyDirectUpdate, DirectLink, DirectUpdate, Terminated},
     ingress,
     manager::{Component, TargetCommand, WaitPoint},
@@ -22,10 +28,13 @@ use arc_swap::{ArcSwap, ArcSwapOption};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use log::error;
-use mqtt::{MqttOptions, QoS};
+use mqtt::{MqttOptions, Transport};
 use non_empty_vec::NonEmpty;
-use serde::Deserialize;
-use tokio::{sync::mpsc, time::timeout};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::mpsc,
+    time::timeout,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct Mqtt {
@@ -46,10 +55,12 @@ impl From<Config> for Mqtt {
     }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
 pub(super) struct SenderMsg {
     pub received: DateTime<Utc>,
     pub content: String,
     pub topic: String,
+    pub qos: Qos,
 }
 
 impl Mqtt {
@@ -59,7 +70,13 @@ impl Mqtt {
         cmd: mpsc::Receiver<TargetCommand>,
         waitpoint: WaitPoint,
     ) -> Result<(), Terminated> {
-        MqttRunner::<mqtt::AsyncClient>::new(self.config, component)
+        let tls = self.config.tls.as_ref();
+        if let Some(Err(err)) = tls.map(TlsConnector::new) {
+            error!("Unit {}: {err}", component.name());
+            return Err(Terminated);
+        }
+
+        MqttRunner::<MqttClient>::new(self.config, component)
             .run(self.sources, cmd, waitpoint)
             .await
     }
@@ -85,8 +102,10 @@ where
         let metrics = Arc::new(MqttMetrics::new());
         component.register_metrics(metrics.clone());
 
-        let status_reporter =
-            Arc::new(MqttStatusReporter::new(component.name(), metrics));
+        let status_reporter = Arc::new(
+            MqttStatusReporter::new(component.name(), metrics)
+                .with_health(component.health()),
+        );
 
         let ingresses = component.ingresses().clone();
         Self {
@@ -168,33 +187,70 @@ where
     where
         <F as ConnectionFactory>::EventLoopType: 'static,
     {
+        let mut queue = self.open_queue();
+
         loop {
-            let connection =
-                F::connect(&self.config.load(), self.status_reporter.clone());
-
-            if let Err(Terminated) = self
-                .process_events(
-                    connection,
-                    &mut sources,
-                    &mut cmd_rx,
-                    &mut pub_q_rx,
-                )
-                .await
-            {
+            let res = match F::connect(
+                &self.config.load(),
+                self.status_reporter.clone(),
+            ) {
+                Ok(connection) => {
+                    self.process_events(
+                        connection,
+                        &mut sources,
+                        &mut cmd_rx,
+                        &mut pub_q_rx,
+                        &mut queue,
+                    )
+                    .await
+                }
+                Err(err) => {
+                    // Without a way to connect there is nothing left to do.
+                    self.status_reporter.connection_error(err);
+                    Err(Terminated)
+                }
+            };
+
+            if let Err(Terminated) = res {
+                queue.persist();
                 self.status_reporter.terminated();
                 return Err(Terminated);
             }
         }
     }
 
+    /// Creates the queue for messages that cannot be published right away.
+    fn open_queue(&self) -> MessageQueue {
+        let config = self.config.load();
+        let disk = config.queue_dir.as_ref().and_then(|dir| {
+            DiskQueue::open(
+                dir,
+                self.component.name(),
+                config.queue_max_bytes,
+            )
+            .map_err(|err| self.status_reporter.queue_error(dir, err))
+            .ok()
+        });
+        MessageQueue::new(
+            config.queue_size.into(),
+            disk,
+            self.status_reporter.clone(),
+        )
+    }
+
     pub async fn process_events(
         self: &Arc<Self>,
         mut connection: Connection<C>,
         sources: &mut Option<NonEmpty<DirectLink>>,
         cmd_rx: &mut mpsc::Receiver<TargetCommand>,
         pub_q_rx: &mut mpsc::UnboundedReceiver<SenderMsg>,
+        queue: &mut MessageQueue,
     ) -> Result<(), Terminated> {
+        let mut connected = connection.connected();
+
         while connection.active() {
+            let can_publish = *connected.borrow_and_update();
+
             tokio::select! {
                 // Disable tokio::select!() random branch selection
                 biased;
@@ -203,6 +259,9 @@ where
                     self.client.store(client.map(Arc::new));
                 }
 
+                // Wake up to publish queued messages once connected.
+                Ok(()) = connected.changed() => {}
+
                 // If nothing happened above, check for new internal Rotonda
                 // target commands to handle.
                 cmd = cmd_rx.recv() => {
@@ -212,7 +271,11 @@ where
 
                     match cmd {
                         Some(TargetCommand::Reconfigure { new_config: Target::Mqtt(new_config) }) => {
-                            if self.reconfigure(sources, new_config, &mut connection).await.is_break() {
+                            if self
+                                .reconfigure(sources, new_config, &mut connection, queue)
+                                .await
+                                .is_break()
+                            {
                                 connection.disconnect().await;
                             }
                         }
@@ -238,32 +301,48 @@ where
                 // And finally if not doing anything else we can process
                 // messages waiting in our internal queue to be published,
                 // which were enqueued by the direct_update() method below.
+                // Unless connected and caught up, they are queued to keep
+                // them in order.
                 msg = pub_q_rx.recv() => {
                     match msg {
-                        Some(SenderMsg {
-                            received,
-                            content,
-                            topic,
-                        }) => {
-                            Self::publish_msg(
-                                self.status_reporter.clone(),
+                        Some(msg) if can_publish && queue.is_empty() => {
+                            if let Err(msg) = self.publish_msg(
                                 connection.client(),
-                                topic,
-                                received,
-                                content,
-                                self.config.load().qos,
-                                self.config.load().publish_max_secs,
+                                msg,
                                 None::<fn() -> Result<(), MqttError>>,
                             )
-                            .await;
+                            .await
+                            {
+                                queue.push_back(msg);
+                            }
                         }
 
+                        Some(msg) => queue.push_back(msg),
+
                         None => {
                             connection.disconnect().await;
                             return Err(Terminated);
                         }
                     }
                 }
+
+                // Publish the queued messages one at a time while
+                // connected, putting a message back if that fails.
+                _ = std::future::ready(()),
+                    if can_publish && !queue.is_empty() =>
+                {
+                    if let Some(msg) = queue.pop_front() {
+                        if let Err(msg) = self.publish_msg(
+                            connection.client(),
+                            msg,
+                            None::<fn() -> Result<(), MqttError>>,
+                        )
+                        .await
+                        {
+                            queue.push_front(msg);
+                        }
+                    }
+                }
             }
         }
 
@@ -280,6 +359,7 @@ where
             config: new_config,
         }: Mqtt,
         connection: &mut Connection<C>,
+        queue: &mut MessageQueue,
     ) -> ControlFlow<()> {
         if let Some(sources) = sources {
             self.status_reporter
@@ -294,11 +374,18 @@ where
             }
         }
 
+        // Keep the current settings rather than fail if the new TLS
+        // settings are unusable.
+        if let Some(Err(err)) = new_config.tls.as_ref().map(TlsConnector::new)
+        {
+            self.status_reporter.tls_error(&new_config.destination, err);
+            return ControlFlow::Continue(());
+        }
+
         // Check if the config changes impact the MQTT client
         let config = self.config.load();
-        let reconnect = new_config.client_id != config.client_id
-            || new_config.destination != config.destination
-            || new_config.queue_size != config.queue_size;
+        let reconnect = config.needs_reconnect(&new_config);
+        queue.set_memory_limit(new_config.queue_size.into());
 
         // Re-create the reconnect delay interval based on the new config
         connection.set_retry_delay(config.connect_retry_secs);
@@ -319,36 +406,37 @@ where
         }
     }
 
-    #[allow(clippy::too_many_arguments)]
+    /// Publishes a message, handing it back if that failed.
     async fn publish_msg<F>(
-        status_reporter: Arc<MqttStatusReporter>,
+        &self,
         client: Option<C>,
-        topic: String,
-        _received: DateTime<Utc>,
-        content: String,
-        qos: i32,
-        duration: Duration,
+        msg: SenderMsg,
         test_publish: Option<F>,
-    ) where
+    ) -> Result<(), SenderMsg>
+    where
         F: Fn() -> Result<(), MqttError> + Send + 'static,
     {
-        status_reporter.publishing(&topic, &content);
+        self.status_reporter.publishing(&msg.topic, &msg.content);
 
+        let config = self.config.load();
         match Self::do_publish(
             client,
-            &topic,
-            content,
-            qos,
-            duration,
+            &msg.topic,
+            msg.content.clone(),
+            msg.qos,
+            &config.properties,
+            config.publish_max_secs,
             test_publish,
         )
         .await
         {
             Ok(_) => {
-                status_reporter.publish_ok(topic);
+                self.status_reporter.publish_ok(msg.topic);
+                Ok(())
             }
             Err(err) => {
-                status_reporter.publish_error(err);
+                self.status_reporter.publish_error(err);
+                Err(msg)
             }
         }
     }
@@ -357,7 +445,8 @@ where
         client: Option<C>,
         topic: &str,
         content: String,
-        qos: i32,
+        qos: Qos,
+        properties: &MessageProperties,
         duration: Duration,
         test_publish: Option<F>,
     ) -> Result<(), MqttError>
@@ -365,30 +454,22 @@ where
         F: Fn() -> Result<(), MqttError> + Send + 'static,
         C: Client,
     {
-        let qos = match qos {
-            0 => QoS::AtMostOnce,
-            1 => QoS::AtLeastOnce,
-            2 => QoS::ExactlyOnce,
-            _ => unreachable!(),
-        };
-
         if let Some(client) = client {
             match timeout(
                 duration,
-                client.publish(topic, qos, false, content),
+                client.publish(topic, qos.into(), false, content, properties),
             )
             .await
             {
                 Err(_) => Err(MqttError::Timeout),
-                Ok(Ok(())) => Ok(()),
-                Ok(Err(err)) => Err(MqttError::Error(err)),
+                Ok(res) => res,
             }
         } else if let Some(test_publish) = test_publish {
             // instead of using duration as a timeout, use it as a delay
             tokio::time::sleep(duration).await;
             test_publish()
         } else {
-            Ok(())
+            Err(MqttError::NotConnected)
         }
     }
 
@@ -403,15 +484,15 @@ where
 
             match serde_json::to_string(&(ingress_info, osm.get_record())) {
                 Ok(content) => {
-                    let topic = self
-                        .config
-                        .load()
+                    let config = self.config.load();
+                    let topic = config
                         .topic_template
                         .replace("{id}", osm.get_topic());
                     return Some(SenderMsg {
                         received: Utc::now(),
                         content,
                         topic,
+                        qos: config.qos_for(osm.get_topic()),
                     });
                 }
                 Err(err) => {
@@ -425,36 +506,70 @@ where
     }
 }
 
-impl ConnectionFactory for MqttRunner<mqtt::AsyncClient> {
-    type EventLoopType = mqtt::EventLoop;
+impl ConnectionFactory for MqttRunner<MqttClient> {
+    type EventLoopType = <MqttClient as Client>::EventLoopType;
 
-    type ClientType = mqtt::AsyncClient;
+    type ClientType = MqttClient;
 
     fn connect(
         config: &Config,
         status_reporter: Arc<MqttStatusReporter>,
-    ) -> Connection<Self::ClientType> {
-        let mut create_opts = MqttOptions::new(
-            config.client_id.clone(),
-            config.destination.host.clone(),
-            config.destination.port,
-        );
+    ) -> Result<Connection<Self::ClientType>, String> {
+        // With TLS, the client connects to the socket of a relay instead.
+        let relay = match &config.tls {
+            Some(tls) => Some(
+                TlsRelay::new(
+                    TlsConnector::new(tls)?,
+                    config.destination.clone(),
+                    status_reporter.clone(),
+                )
+                .map_err(|err| format!("cannot start TLS relay: {err}"))?,
+            ),
+            None => None,
+        };
+
+        let mut create_opts = match &relay {
+            Some(relay) => {
+                let mut opts = MqttOptions::new(
+                    config.client_id.clone(),
+                    relay.path().to_string_lossy(),
+                    0,
+                );
+                opts.set_transport(Transport::Unix);
+                opts
+            }
+            None => MqttOptions::new(
+                config.client_id.clone(),
+                config.destination.host.clone(),
+                config.destination.port,
+            ),
+        };
         create_opts.set_request_channel_capacity(config.queue_size.into());
         create_opts.set_clean_session(true);
         create_opts.set_inflight(1000);
         create_opts.set_keep_alive(Duration::from_secs(20));
 
-        if let (Some(username), Some(password)) =
-            (&config.username, &config.password)
-        {
-            create_opts.set_credentials(username, password);
+        // A username may be used without a password, which is then left
+        // out of the connect request.
+        if let Some(username) = &config.username {
+            create_opts.set_credentials(
+                username,
+                config.password.as_deref().unwrap_or_default(),
+            );
         }
 
-        Connection::new(
+        let connection = Connection::new(
             create_opts,
+            config.protocol_version,
             config.connect_retry_secs,
             status_reporter,
-        )
+        );
+        Ok(match relay {
+            Some(relay) => {
+                connection.with_relay(relay, config.destination.clone())
+            }
+            None => connection,
+        })
     }
 }
 
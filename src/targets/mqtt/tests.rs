@@ -10,8 +10,8 @@ use std::{
 use arc_swap::ArcSwap;
 use async_trait::async_trait;
 use mqtt::{
-    ClientError, ConnAck, ConnectReturnCode, ConnectionError, Event,
-    Incoming, MqttOptions, NetworkOptions, Outgoing, PubAck, QoS,
+    ConnAck, ConnectReturnCode, ConnectionError, Event, Incoming,
+    MqttOptions, Outgoing, PubAck, QoS,
 };
 use serde_json::json;
 use tokio::{
@@ -35,11 +35,14 @@ use crate::{
 };
 
 use super::{
-    config::{Config, Destination},
+    config::{Config, Destination, MessageProperties, ProtocolVersion},
     connection::{
-        Client, Connection, ConnectionFactory, EventLoop, MqttPollResult,
+        poll_result_v311, Client, Connection, ConnectionFactory, EventLoop,
+        MqttPollResult,
     },
+    error::MqttError,
     metrics::MqttMetrics,
+    queue::{DiskQueue, MessageQueue},
     status_reporter::MqttStatusReporter,
     target::*,
 };
@@ -293,6 +296,153 @@ async fn mqtt_target_can_be_reconfigured_while_running() {
     assert_metrics(&metrics, (0, 0, 0));
 }
 
+#[tokio::test]
+async fn messages_are_queued_until_connected() {
+    enable_logging("trace");
+
+    let (join_handle, runner, status_reporter, cmd_tx, pub_q_tx) =
+        mk_mqtt_runner_task_with_queue();
+
+    let metrics = status_reporter.metrics();
+
+    // Messages arriving before the broker accepted the connection are
+    // queued rather than dropped
+    let client = assert_client_becomes_available(&runner).await;
+    for _ in 0..3 {
+        pub_q_tx.send(mk_sender_msg("rotonda/my-topic", 0)).unwrap();
+    }
+
+    assert_metric(
+        &metrics,
+        |m| m.with_name::<usize>("mqtt_target_queued_count") == 3,
+        "mqtt_target_queued_count != 3",
+    )
+    .await;
+
+    // And are published once it did
+    client.simulate_connect_ack(ConnectReturnCode::Success);
+
+    assert_metric(
+        &metrics,
+        |m| m.with_name::<usize>("mqtt_target_queued_count") == 0,
+        "mqtt_target_queued_count != 0",
+    )
+    .await;
+    assert_metric(
+        &metrics,
+        |m| {
+            m.with_labels::<usize>(
+                "mqtt_target_publish_count",
+                &[("topic", "rotonda/my-topic")],
+            ) == 3
+        },
+        "mqtt_target_publish_count != 3",
+    )
+    .await;
+    assert_eq!(
+        get_testable_metrics_snapshot(&metrics)
+            .with_name::<usize>("mqtt_target_dropped_count"),
+        0
+    );
+
+    cmd_tx.send(TargetCommand::Terminate).await.unwrap();
+    assert_eq!(join_handle.await.unwrap(), Err(Terminated));
+}
+
+#[test]
+fn queue_overflows_to_disk_and_survives_a_restart() {
+    let dir = std::env::temp_dir()
+        .join(format!("rotonda-mqtt-queue-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let status_reporter = Arc::new(MqttStatusReporter::new(
+        "mock",
+        Arc::new(MqttMetrics::new()),
+    ));
+    let open = |max_bytes| {
+        let disk = DiskQueue::open(&dir, "mqtt-out", max_bytes).unwrap();
+        MessageQueue::new(2, Some(disk), status_reporter.clone())
+    };
+    let topics = |queue: &mut MessageQueue| {
+        std::iter::from_fn(|| queue.pop_front())
+            .map(|msg| msg.topic)
+            .collect::<Vec<_>>()
+    };
+
+    // Messages beyond the memory limit go to disk, and stay behind those
+    // in memory
+    let mut queue = open(1024);
+    for topic in ["a", "b", "c", "d"] {
+        queue.push_back(mk_sender_msg(topic, 1));
+    }
+    assert_eq!(queue.len(), 4);
+    let msg = queue.pop_front().unwrap();
+    assert_eq!(msg.topic, "a");
+    queue.push_back(mk_sender_msg("e", 1));
+    queue.push_front(msg);
+    assert_eq!(topics(&mut queue), ["a", "b", "c", "d", "e"]);
+    assert!(queue.is_empty());
+
+    // Messages in memory are written to disk on shutdown, ahead of those
+    // already there, and a line cut short is dropped
+    for topic in ["f", "g", "h"] {
+        queue.push_back(mk_sender_msg(topic, 2));
+    }
+    queue.persist();
+    drop(queue);
+    let path = dir.join("mqtt-out.queue");
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    std::io::Write::write_all(&mut file, b"{\"received\":").unwrap();
+    let mut queue = open(1024);
+    assert_eq!(queue.len(), 3);
+    let msg = queue.pop_front().unwrap();
+    assert_eq!((msg.topic.as_str(), u8::from(msg.qos)), ("f", 2));
+    assert_eq!(topics(&mut queue), ["g", "h"]);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+    // Messages are dropped once the file is full
+    let line_len = serde_json::to_vec(&mk_sender_msg("x", 0)).unwrap().len();
+    let mut queue = open(3 * (line_len as u64 + 1));
+    for topic in ["i", "j", "k", "l", "m", "n"] {
+        queue.push_back(mk_sender_msg(topic, 0));
+    }
+    assert_eq!(queue.len(), 5);
+    assert_eq!(topics(&mut queue), ["i", "j", "k", "l", "m"]);
+    assert_eq!(
+        get_testable_metrics_snapshot(&status_reporter.metrics())
+            .with_name::<usize>("mqtt_target_dropped_count"),
+        1
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn qos_can_be_set_per_topic() {
+    let config = mk_config_from_toml(
+        r#"
+        client_id = "some-client-id"
+        destination = "some_host_name"
+        qos = 1
+        topic_qos = { "rib-out" = 0 }
+    "#,
+    )
+    .unwrap();
+    assert_eq!(u8::from(config.qos_for("rib-out")), 0);
+    assert_eq!(u8::from(config.qos_for("log")), 1);
+
+    assert!(mk_config_from_toml(
+        r#"
+        client_id = "some-client-id"
+        destination = "some_host_name"
+        qos = 3
+    "#
+    )
+    .is_err());
+}
+
 #[tokio::test]
 async fn connection_refused() {
     enable_logging("trace");
@@ -411,18 +561,25 @@ async fn connection_loss_and_reconnect() {
 #[derive(Clone, Debug)]
 struct MockClient {
     broker_addr: (String, u16),
-    mock_poll_result_sender: Arc<mpsc::UnboundedSender<MqttPollResult>>,
+    mock_poll_result_sender: Arc<mpsc::UnboundedSender<MockPollResult>>,
 }
 
+/// What the mock event loop is told to produce, in terms of MQTT 3.1.1.
+type MockPollResult = Result<Event, ConnectionError>;
+
 #[async_trait]
 impl Client for MockClient {
     type EventLoopType = MockEventLoop;
 
-    fn new(options: MqttOptions, _cap: usize) -> (Self, Self::EventLoopType) {
+    fn new(
+        options: MqttOptions,
+        _protocol_version: ProtocolVersion,
+        _cap: usize,
+    ) -> (Self, Self::EventLoopType) {
         let broker_addr = options.broker_address();
 
         let (event_loop, mock_poll_result_sender) =
-            MockEventLoop::new(options);
+            MockEventLoop::new();
 
         let res = Self {
             broker_addr,
@@ -438,7 +595,8 @@ impl Client for MockClient {
         _qos: QoS,
         _retain: bool,
         _payload: V,
-    ) -> Result<(), ClientError>
+        _properties: &MessageProperties,
+    ) -> Result<(), MqttError>
     where
         S: Into<String> + Send,
         V: Into<Vec<u8>> + Send,
@@ -448,7 +606,7 @@ impl Client for MockClient {
         Ok(())
     }
 
-    async fn disconnect(&self) -> Result<(), ClientError> {
+    async fn disconnect(&self) -> Result<(), MqttError> {
         // NO OP
         Ok(())
     }
@@ -490,20 +648,14 @@ impl MockClient {
 }
 
 struct MockEventLoop {
-    options: MqttOptions,
-    network_options: NetworkOptions,
-    mock_poll_result_rx: mpsc::UnboundedReceiver<MqttPollResult>,
+    mock_poll_result_rx: mpsc::UnboundedReceiver<MockPollResult>,
     inflight: Arc<AtomicU16>,
 }
 
 impl MockEventLoop {
-    fn new(
-        options: MqttOptions,
-    ) -> (Self, mpsc::UnboundedSender<MqttPollResult>) {
+    fn new() -> (Self, mpsc::UnboundedSender<MockPollResult>) {
         let (tx, rx) = mpsc::unbounded_channel();
         let res = Self {
-            options,
-            network_options: NetworkOptions::default(),
             mock_poll_result_rx: rx,
             inflight: Arc::new(AtomicU16::new(0)),
         };
@@ -514,7 +666,8 @@ impl MockEventLoop {
 #[async_trait]
 impl EventLoop for MockEventLoop {
     async fn poll(&mut self) -> MqttPollResult {
-        self.mock_poll_result_rx.recv().await.unwrap().map(|event| {
+        let res = self.mock_poll_result_rx.recv().await.unwrap();
+        poll_result_v311(res.map(|event| {
             match event {
                 Event::Outgoing(Outgoing::Publish(_)) => {
                     self.inflight.fetch_add(1, Ordering::SeqCst);
@@ -525,23 +678,11 @@ impl EventLoop for MockEventLoop {
                 _ => { /* NO OP */ }
             }
             event
-        })
+        }))
     }
 
-    fn mqtt_options(&self) -> &MqttOptions {
-        &self.options
-    }
-
-    fn network_options(&self) -> NetworkOptions {
-        self.network_options.clone()
-    }
-
-    fn set_network_options(
-        &mut self,
-        network_options: NetworkOptions,
-    ) -> &mut Self {
-        self.network_options = network_options;
-        self
+    fn set_connection_timeout(&mut self, _timeout: u64) {
+        // NO OP
     }
 
     fn inflight(&self) -> u16 {
@@ -557,14 +698,19 @@ impl ConnectionFactory for MqttRunner<MockClient> {
     fn connect(
         config: &Config,
         status_reporter: Arc<MqttStatusReporter>,
-    ) -> Connection<MockClient> {
+    ) -> Result<Connection<MockClient>, String> {
         let options = MqttOptions::new(
             config.client_id.clone(),
             config.destination.host.clone(),
             config.destination.port,
         );
 
-        Connection::new(options, Duration::from_secs(1), status_reporter)
+        Ok(Connection::new(
+            options,
+            config.protocol_version,
+            Duration::from_secs(1),
+            status_reporter,
+        ))
     }
 }
 
@@ -593,6 +739,20 @@ fn mk_mqtt_runner_task() -> (
     Arc<MqttRunner<MockClient>>,
     Arc<MqttStatusReporter>,
     Sender<TargetCommand>,
+) {
+    let (join_handle, runner, status_reporter, cmd_tx, _) =
+        mk_mqtt_runner_task_with_queue();
+    (join_handle, runner, status_reporter, cmd_tx)
+}
+
+/// Also returns the sender for messages to publish.
+#[allow(clippy::type_complexity)]
+fn mk_mqtt_runner_task_with_queue() -> (
+    JoinHandle<Result<(), Terminated>>,
+    Arc<MqttRunner<MockClient>>,
+    Arc<MqttStatusReporter>,
+    Sender<TargetCommand>,
+    mpsc::UnboundedSender<SenderMsg>,
 ) {
     // Warning: If either tx or cmd_tx are dropped the spawned runner will
     // exit, so hold on to them, don't do `let _ =` as that will drop them
@@ -603,7 +763,8 @@ fn mk_mqtt_runner_task() -> (
     let config = mk_mqtt_runner_config();
     let config = Arc::new(ArcSwap::from_pointee(config));
 
-    let (runner, status_reporter) = MqttRunner::mock(config, Some(pub_q_tx));
+    let (runner, status_reporter) =
+        MqttRunner::mock(config, Some(pub_q_tx.clone()));
     let runner = Arc::new(runner);
 
     let spawned_runner = runner.clone();
@@ -613,7 +774,16 @@ fn mk_mqtt_runner_task() -> (
             .await
     });
 
-    (join_handle, runner, status_reporter, cmd_tx)
+    (join_handle, runner, status_reporter, cmd_tx, pub_q_tx)
+}
+
+fn mk_sender_msg(topic: &str, qos: u8) -> SenderMsg {
+    SenderMsg {
+        received: chrono::Utc::now(),
+        content: r#"{"some-asn":1818}"#.to_string(),
+        topic: topic.to_string(),
+        qos: qos.try_into().unwrap(),
+    }
 }
 
 fn mk_config_from_toml(toml: &str) -> Result<Config, toml::de::Error> {
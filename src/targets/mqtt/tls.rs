@@ -0,0 +1,155 @@
+//! Connecting to the MQTT server over TLS.
+//!
+//! The MQTT client library is built without TLS support. Instead, it is
+//! pointed at a Unix socket in a private temporary directory, and each
+//! connection it makes there is relayed to the server over a TLS
+//! connection made with [`TlsConnector`].
+
+use std::{
+    env, fs, io,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use tokio::{
+    net::{TcpStream, UnixListener, UnixStream},
+    task::{JoinHandle, JoinSet},
+};
+
+use crate::common::tls::TlsConnector;
+
+use super::{config::Destination, status_reporter::MqttStatusReporter};
+
+//------------ TlsRelay ------------------------------------------------------
+
+/// Relays connections made to a Unix socket to the server over TLS.
+///
+/// The socket is removed when the relay is dropped.
+pub struct TlsRelay {
+    /// The private directory the socket lives in.
+    dir: PathBuf,
+
+    /// The path of the socket.
+    path: PathBuf,
+
+    task: JoinHandle<()>,
+}
+
+impl TlsRelay {
+    /// Starts relaying connections to `destination`.
+    pub fn new(
+        connector: TlsConnector,
+        destination: Destination,
+        status_reporter: Arc<MqttStatusReporter>,
+    ) -> io::Result<Self> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = env::temp_dir().join(format!(
+            "rotonda-mqtt-{}-{}",
+            process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        if dir.exists() {
+            // Left behind by an earlier process with the same ID.
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+        let path = dir.join("broker.sock");
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(err) => {
+                let _ = fs::remove_dir_all(&dir);
+                return Err(err);
+            }
+        };
+
+        let task = crate::tokio::spawn(
+            "MQTT TLS relay",
+            Self::run(
+                listener,
+                Arc::new(connector),
+                destination,
+                status_reporter,
+            ),
+        );
+
+        Ok(Self { dir, path, task })
+    }
+
+    /// Returns the path of the socket to connect to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    async fn run(
+        listener: UnixListener,
+        connector: Arc<TlsConnector>,
+        destination: Destination,
+        status_reporter: Arc<MqttStatusReporter>,
+    ) {
+        // Dropping the set when the relay is dropped ends the connections.
+        let mut connections = JoinSet::new();
+        loop {
+            tokio::select! {
+                res = listener.accept() => match res {
+                    Ok((stream, _)) => {
+                        connections.spawn(Self::relay(
+                            stream,
+                            connector.clone(),
+                            destination.clone(),
+                            status_reporter.clone(),
+                        ));
+                    }
+                    Err(err) => {
+                        status_reporter.tls_error(&destination, err);
+                    }
+                },
+
+                Some(_) = connections.join_next() => {}
+            }
+        }
+    }
+
+    /// Relays a single connection until either side closes it.
+    async fn relay(
+        local: UnixStream,
+        connector: Arc<TlsConnector>,
+        destination: Destination,
+        status_reporter: Arc<MqttStatusReporter>,
+    ) {
+        let res = async {
+            let tcp = TcpStream::connect((
+                destination.host.as_str(),
+                destination.port,
+            ))
+            .await?;
+            let tls = connector.connect(&destination.host, tcp).await?;
+
+            let (mut local_read, mut local_write) = local.into_split();
+            let (mut tls_read, mut tls_write) = tokio::io::split(tls);
+            tokio::select! {
+                res = tokio::io::copy(&mut local_read, &mut tls_write) => res,
+                res = tokio::io::copy(&mut tls_read, &mut local_write) => res,
+            }
+        }
+        .await;
+
+        // Closing the local side makes the client notice and reconnect.
+        if let Err(err) = res {
+            status_reporter.tls_error(&destination, err);
+        }
+    }
+}
+
+impl Drop for TlsRelay {
+    fn drop(&mut self) {
+        self.task.abort();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
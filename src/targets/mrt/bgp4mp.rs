@@ -0,0 +1,524 @@
+//! Encoding updates as BGP4MP records.
+//!
+//! These records make up the update files of the `mrt-out` target and the
+//! MRT objects of the `s3-out` target. Each route received from a unit
+//! becomes a BGP4MP_ET record of the
+//! MESSAGE_AS4 subtype, as described in [RFC 6396], holding a BGP UPDATE
+//! message that announces or withdraws the route as if it had just been
+//! received from its peer. Routes from sessions without four-octet AS
+//! numbers use the MESSAGE subtype instead, as their AS paths are encoded
+//! with two-octet AS numbers. A session going down becomes a record of the
+//! STATE_CHANGE_AS4 subtype.
+//!
+//! The local side of the session is not known, so the local AS, interface
+//! and address are left zero. IPv6 and multicast routes are carried in the
+//! MP_REACH_NLRI and MP_UNREACH_NLRI attributes, with the next hop taken
+//! from the attributes the route was received with, if they have it.
+//! Other updates, such as the messages of output streams, have no records.
+//!
+//! [RFC 6396]: https://www.rfc-editor.org/rfc/rfc6396
+
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use inetnum::{addr::Prefix, asn::Asn};
+use rotonda_store::prefix_record::RouteStatus;
+
+use crate::{
+    ingress,
+    payload::{Payload, RotondaRoute, Update},
+    roto_runtime::types::RouteContext,
+    units::rib_unit::best_path::prefix_of,
+};
+
+/// The MRT type of BGP4MP records with microsecond timestamps.
+const BGP4MP_ET: u16 = 17;
+
+const MESSAGE: u16 = 1;
+const MESSAGE_AS4: u16 = 4;
+const STATE_CHANGE_AS4: u16 = 5;
+
+/// The BGP FSM states of a session going down.
+const ESTABLISHED: u16 = 6;
+const IDLE: u16 = 1;
+
+/// The AS number used in place of AS numbers that do not fit two octets.
+const AS_TRANS: u32 = 23456;
+
+pub(crate) const AS_PATH: u8 = 2;
+pub(crate) const NEXT_HOP: u8 = 3;
+pub(crate) const AGGREGATOR: u8 = 7;
+pub(crate) const MP_REACH_NLRI: u8 = 14;
+pub(crate) const MP_UNREACH_NLRI: u8 = 15;
+
+/// The flags of the attributes added: optional, with an extended length.
+const OPTIONAL_EXTENDED: u8 = 0x90;
+
+/// The flag of attributes with a two-octet length.
+const EXTENDED_LENGTH: u8 = 0x10;
+
+/// Appends the records for an update, returning how many there were.
+pub fn push_update(
+    update: &Update,
+    ingresses: &ingress::Register,
+    buf: &mut Vec<u8>,
+) -> usize {
+    match update {
+        Update::Single(payload) => usize::from(push_payload(payload, buf)),
+        Update::Bulk(payloads) => payloads
+            .iter()
+            .filter(|payload| push_payload(payload, buf))
+            .count(),
+        Update::Withdraw(ingress_id, None) => {
+            usize::from(push_session_down(*ingress_id, ingresses, buf))
+        }
+        Update::WithdrawBulk(ingress_ids) => ingress_ids
+            .iter()
+            .filter(|id| push_session_down(**id, ingresses, buf))
+            .count(),
+        Update::Withdraw(_, Some(_))
+        | Update::OutputStream(..)
+        | Update::QueryResult(..)
+        | Update::UpstreamStatusChange(..)
+        | Update::Rtr(..) => 0,
+    }
+}
+
+/// Appends the record for a route, if its peer is known.
+fn push_payload(payload: &Payload, buf: &mut Vec<u8>) -> bool {
+    let (status, provenance) = match &payload.context {
+        RouteContext::Fresh(ctx) => (ctx.status(), ctx.provenance()),
+        RouteContext::Mrt(ctx) => (ctx.status, ctx.provenance()),
+        RouteContext::Reprocess => return false,
+    };
+    let withdrawn = status == RouteStatus::Withdrawn;
+    let (message, four_octet) = update_message(&payload.rx_value, withdrawn);
+    // The length of the message includes its 19 octet header.
+    let Ok(len) = u16::try_from(message.len() + 19) else {
+        return false;
+    };
+
+    let mut body = Vec::new();
+    let peer_asn = provenance.peer_asn.into_u32();
+    if four_octet {
+        body.extend_from_slice(&peer_asn.to_be_bytes());
+        body.extend_from_slice(&0u32.to_be_bytes());
+    } else {
+        let peer_asn = u16::try_from(peer_asn).unwrap_or(AS_TRANS as u16);
+        body.extend_from_slice(&peer_asn.to_be_bytes());
+        body.extend_from_slice(&0u16.to_be_bytes());
+    }
+    push_peer(provenance.peer_ip, &mut body);
+    body.extend_from_slice(&[0xff; 16]);
+    body.extend_from_slice(&len.to_be_bytes());
+    body.push(2); // UPDATE
+    body.extend_from_slice(&message);
+
+    let subtype = if four_octet { MESSAGE_AS4 } else { MESSAGE };
+    push_record(provenance.timestamp, subtype, &body, buf);
+    true
+}
+
+/// Appends the record for a session going down, if its peer is known.
+fn push_session_down(
+    ingress_id: ingress::IngressId,
+    ingresses: &ingress::Register,
+    buf: &mut Vec<u8>,
+) -> bool {
+    let Some(info) = ingresses.get(ingress_id) else {
+        return false;
+    };
+    let Some(peer_ip) = info.remote_addr else {
+        return false;
+    };
+    let peer_asn = info.remote_asn.map_or(0, Asn::into_u32);
+    let mut body = Vec::new();
+    body.extend_from_slice(&peer_asn.to_be_bytes());
+    body.extend_from_slice(&0u32.to_be_bytes());
+    push_peer(peer_ip, &mut body);
+    body.extend_from_slice(&ESTABLISHED.to_be_bytes());
+    body.extend_from_slice(&IDLE.to_be_bytes());
+    push_record(Utc::now(), STATE_CHANGE_AS4, &body, buf);
+    true
+}
+
+/// Appends the interface, address family and addresses of a session.
+fn push_peer(peer_ip: IpAddr, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&0u16.to_be_bytes());
+    match peer_ip {
+        IpAddr::V4(addr) => {
+            buf.extend_from_slice(&1u16.to_be_bytes());
+            buf.extend_from_slice(&addr.octets());
+            buf.extend_from_slice(&[0; 4]);
+        }
+        IpAddr::V6(addr) => {
+            buf.extend_from_slice(&2u16.to_be_bytes());
+            buf.extend_from_slice(&addr.octets());
+            buf.extend_from_slice(&[0; 16]);
+        }
+    }
+}
+
+fn push_record(
+    time: DateTime<Utc>,
+    subtype: u16,
+    body: &[u8],
+    buf: &mut Vec<u8>,
+) {
+    buf.extend_from_slice(&(time.timestamp() as u32).to_be_bytes());
+    buf.extend_from_slice(&BGP4MP_ET.to_be_bytes());
+    buf.extend_from_slice(&subtype.to_be_bytes());
+    // The length includes the microseconds.
+    buf.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+    buf.extend_from_slice(&time.timestamp_subsec_micros().to_be_bytes());
+    buf.extend_from_slice(body);
+}
+
+/// Returns the content of the UPDATE message for a route, after the BGP
+/// header, and whether its AS numbers have four octets.
+fn update_message(route: &RotondaRoute, withdrawn: bool) -> (Vec<u8>, bool) {
+    let attributes = route.rotonda_pamap().path_attributes();
+    let four_octet = attributes.pdu_parse_info().four_octet_enabled();
+    let raw = attributes.into_vec();
+    let body =
+        update_body(prefix_of(route), afi_safi(route), &raw, withdrawn);
+    (body, four_octet)
+}
+
+/// Returns the content of an UPDATE message announcing or withdrawing a
+/// prefix with the path attributes in `raw`, after the BGP header.
+///
+/// Any MP_REACH_NLRI and MP_UNREACH_NLRI attributes in `raw` are replaced
+/// by those for the prefix, keeping the next hop for its address family.
+pub(crate) fn update_body(
+    prefix: Prefix,
+    (afi, safi): (u16, u8),
+    raw: &[u8],
+    withdrawn: bool,
+) -> Vec<u8> {
+    let conventional = afi == 1 && safi == 1;
+
+    let mut withdrawn_routes = Vec::new();
+    let mut attrs = Vec::new();
+    let mut nlri = Vec::new();
+    if withdrawn && conventional {
+        push_prefix(prefix, &mut withdrawn_routes);
+    } else if withdrawn {
+        let mut value = afi.to_be_bytes().to_vec();
+        value.push(safi);
+        push_prefix(prefix, &mut value);
+        push_attribute(MP_UNREACH_NLRI, &value, &mut attrs);
+    } else {
+        for (type_code, attr) in Attributes(raw) {
+            let skip = type_code == MP_REACH_NLRI
+                || type_code == MP_UNREACH_NLRI
+                || (type_code == NEXT_HOP && !conventional);
+            if !skip {
+                attrs.extend_from_slice(attr);
+            }
+        }
+        if conventional {
+            push_prefix(prefix, &mut nlri);
+        } else {
+            let mut value = afi.to_be_bytes().to_vec();
+            value.push(safi);
+            let next_hop = next_hop(raw, afi);
+            value.push(next_hop.len() as u8);
+            value.extend_from_slice(&next_hop);
+            value.push(0);
+            push_prefix(prefix, &mut value);
+            push_attribute(MP_REACH_NLRI, &value, &mut attrs);
+        }
+    }
+
+    let mut res = Vec::new();
+    res.extend_from_slice(&(withdrawn_routes.len() as u16).to_be_bytes());
+    res.extend_from_slice(&withdrawn_routes);
+    res.extend_from_slice(&(attrs.len() as u16).to_be_bytes());
+    res.extend_from_slice(&attrs);
+    res.extend_from_slice(&nlri);
+    res
+}
+
+/// Returns the AFI and SAFI of a route.
+pub(crate) fn afi_safi(route: &RotondaRoute) -> (u16, u8) {
+    match route {
+        RotondaRoute::Ipv4Unicast(..) => (1, 1),
+        RotondaRoute::Ipv6Unicast(..) => (2, 1),
+        RotondaRoute::Ipv4Multicast(..) => (1, 2),
+        RotondaRoute::Ipv6Multicast(..) => (2, 2),
+    }
+}
+
+/// Returns the next hop for `afi` in the MP_REACH_NLRI attribute of the
+/// message a route was received in, or else the unspecified address.
+pub(crate) fn next_hop(raw: &[u8], afi: u16) -> Vec<u8> {
+    Attributes(raw)
+        .filter(|(type_code, _)| *type_code == MP_REACH_NLRI)
+        .find_map(|(_, attr)| {
+            let value = attribute_value(attr);
+            let len = usize::from(*value.get(3)?);
+            (value.get(..2)? == afi.to_be_bytes())
+                .then(|| value.get(4..4 + len))
+                .flatten()
+                .map(<[u8]>::to_vec)
+        })
+        .unwrap_or_else(|| vec![0; if afi == 1 { 4 } else { 16 }])
+}
+
+fn push_attribute(type_code: u8, value: &[u8], buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&[OPTIONAL_EXTENDED, type_code]);
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value);
+}
+
+pub(super) fn push_prefix(prefix: Prefix, buf: &mut Vec<u8>) {
+    let len = prefix.len();
+    let octets = match prefix.addr() {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    };
+    buf.push(len);
+    buf.extend_from_slice(&octets[..usize::from(len).div_ceil(8)]);
+}
+
+/// Returns path attributes with the two-octet AS numbers in the AS_PATH
+/// and AGGREGATOR attributes widened to four octets.
+///
+/// The AS4_PATH and AS4_AGGREGATOR attributes are kept as they are.
+/// Attributes that cannot be widened are kept as well.
+pub(crate) fn widen_attributes(raw: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(raw.len() + 16);
+    for (type_code, attr) in Attributes(raw) {
+        let widened = match type_code {
+            AS_PATH => widen_as_path(attribute_value(attr)),
+            AGGREGATOR => widen_aggregator(attribute_value(attr)),
+            _ => None,
+        };
+        match widened.filter(|value| value.len() <= usize::from(u16::MAX)) {
+            Some(value) => {
+                res.extend_from_slice(&[
+                    attr[0] | EXTENDED_LENGTH,
+                    type_code,
+                ]);
+                res.extend_from_slice(&(value.len() as u16).to_be_bytes());
+                res.extend_from_slice(&value);
+            }
+            None => res.extend_from_slice(attr),
+        }
+    }
+    res
+}
+
+/// Returns an AS_PATH value with two-octet AS numbers widened to four.
+fn widen_as_path(mut value: &[u8]) -> Option<Vec<u8>> {
+    let mut res = Vec::with_capacity(value.len() * 2);
+    while let [segment_type, count, rest @ ..] = value {
+        let (asns, rest) = rest.split_at_checked(usize::from(*count) * 2)?;
+        res.extend_from_slice(&[*segment_type, *count]);
+        for asn in asns.chunks_exact(2) {
+            res.extend_from_slice(&[0, 0, asn[0], asn[1]]);
+        }
+        value = rest;
+    }
+    value.is_empty().then_some(res)
+}
+
+/// Returns an AGGREGATOR value with a two-octet AS number widened to four.
+fn widen_aggregator(value: &[u8]) -> Option<Vec<u8>> {
+    (value.len() == 6).then(|| [&[0, 0], value].concat())
+}
+
+/// Returns the value of an encoded attribute.
+pub(crate) fn attribute_value(attr: &[u8]) -> &[u8] {
+    let header = if attr[0] & 0x10 != 0 { 4 } else { 3 };
+    &attr[header..]
+}
+
+//------------ Attributes ----------------------------------------------------
+
+/// Iterates over the type codes and encodings of the attributes in a raw
+/// path attributes blob.
+pub(crate) struct Attributes<'a>(pub &'a [u8]);
+
+impl<'a> Iterator for Attributes<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let raw = self.0;
+        let (flags, type_code) = (*raw.first()?, *raw.get(1)?);
+        let (header, len) = if flags & 0x10 != 0 {
+            (4, u16::from_be_bytes([*raw.get(2)?, *raw.get(3)?]).into())
+        } else {
+            (3, usize::from(*raw.get(2)?))
+        };
+        let Some(attr) = raw.get(..header + len) else {
+            self.0 = &[];
+            return None;
+        };
+        self.0 = &raw[header + len..];
+        Some((type_code, attr))
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use routecore::{
+        bgp::{
+            message::PduParseInfo, path_attributes::OwnedPathAttributes,
+            types::PathAttributeType,
+        },
+        mrt::{Bgp4Mp, MrtFile},
+    };
+
+    use crate::{
+        payload::RotondaPaMap,
+        roto_runtime::types::{MrtContext, Provenance},
+    };
+
+    use super::*;
+
+    fn payload(route: RotondaRoute, status: RouteStatus) -> Payload {
+        let mut provenance = Provenance::for_bgp(
+            1,
+            "2001:db8::1".parse().unwrap(),
+            Asn::from_u32(65000),
+        );
+        provenance.timestamp =
+            DateTime::from_timestamp(1_700_000_000, 123_000).unwrap();
+        Payload::new(
+            route,
+            RouteContext::Mrt(MrtContext { status, provenance }),
+            None,
+        )
+    }
+
+    /// Returns the encoded AS_PATH and MP_REACH_NLRI attributes.
+    fn ipv6_attributes() -> Vec<u8> {
+        let mut raw = vec![0x40, PathAttributeType::AsPath.into(), 6, 2, 1];
+        raw.extend(65000u32.to_be_bytes());
+        let mut mp_reach = vec![0, 2, 1, 16];
+        mp_reach.extend(
+            "2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        mp_reach.extend([0, 32, 0x20, 0x01, 0x0d, 0xb8]);
+        push_attribute(MP_REACH_NLRI, &mp_reach, &mut raw);
+        raw
+    }
+
+    #[test]
+    fn routes_become_update_messages() {
+        let route = crate::targets::file::row::tests::mk_route(
+            "198.51.100.0/24",
+            &[65000, 65001],
+            &[(65000, 666)],
+        );
+        let mut buf = Vec::new();
+        let update = Update::Bulk(
+            vec![
+                payload(route.clone(), RouteStatus::Active),
+                payload(route, RouteStatus::Withdrawn),
+            ]
+            .into(),
+        );
+        let register = ingress::Register::default();
+        assert_eq!(push_update(&update, &register, &mut buf), 2);
+
+        let file = MrtFile::new(&buf);
+        let mut messages = file.messages();
+        let Some(Bgp4Mp::MessageAs4(msg)) = messages.next() else {
+            panic!("no message");
+        };
+        assert_eq!(msg.peer_asn(), Asn::from_u32(65000));
+        assert_eq!(msg.peer_addr(), "2001:db8::1".parse::<IpAddr>().unwrap());
+        let update = msg.bgp_msg().unwrap();
+        let routecore::bgp::message::Message::Update(update) = update else {
+            panic!("not an update");
+        };
+        let announcements = update.announcements_vec().unwrap();
+        assert_eq!(announcements.len(), 1);
+        assert_eq!(announcements[0].to_string(), "198.51.100.0/24");
+        assert!(update.aspath().unwrap().is_some());
+
+        let Some(Bgp4Mp::MessageAs4(msg)) = messages.next() else {
+            panic!("no message");
+        };
+        let routecore::bgp::message::Message::Update(update) =
+            msg.bgp_msg().unwrap()
+        else {
+            panic!("not an update");
+        };
+        assert_eq!(update.withdrawals_vec().unwrap().len(), 1);
+        assert!(update.announcements_vec().unwrap().is_empty());
+        assert!(messages.next().is_none());
+
+        // The record header carries the time with microseconds.
+        assert_eq!(buf[..4], 1_700_000_000u32.to_be_bytes());
+        assert_eq!(buf[4..8], [0, 17, 0, 4]);
+        assert_eq!(buf[12..16], 123u32.to_be_bytes());
+    }
+
+    #[test]
+    fn ipv6_routes_keep_their_next_hop() {
+        let pamap = RotondaPaMap::new(OwnedPathAttributes::new(
+            PduParseInfo::modern(),
+            ipv6_attributes(),
+        ));
+        let prefix: Prefix = "2001:db8::/32".parse().unwrap();
+        let route =
+            RotondaRoute::Ipv6Unicast(prefix.try_into().unwrap(), pamap);
+        let (message, four_octet) = update_message(&route, false);
+        assert!(four_octet);
+        // No withdrawn routes, then the attributes.
+        assert_eq!(message[..2], [0, 0]);
+        let len = usize::from(u16::from_be_bytes([message[2], message[3]]));
+        assert_eq!(message.len(), 4 + len);
+        let attrs = Attributes(&message[4..]).collect::<Vec<_>>();
+        assert_eq!(attrs.len(), 2);
+        assert_eq!(attrs[0].0, u8::from(PathAttributeType::AsPath));
+        assert_eq!(attrs[1].0, MP_REACH_NLRI);
+        let next_hop = next_hop(&message[4..], 2);
+        assert_eq!(
+            next_hop,
+            "2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets()
+        );
+
+        let (message, _) = update_message(&route, true);
+        let attrs = Attributes(&message[4..]).collect::<Vec<_>>();
+        assert_eq!(attrs.len(), 1);
+        assert_eq!(
+            attribute_value(attrs[0].1),
+            [0, 2, 1, 32, 0x20, 0x01, 0x0d, 0xb8]
+        );
+    }
+
+    #[test]
+    fn attributes_are_widened() {
+        let raw = [
+            [0x40, AS_PATH, 6, 2, 2, 0xfd, 0xe8, 0xfd, 0xe9].as_slice(),
+            &[0xc0, AGGREGATOR, 6, 0xfd, 0xe8, 192, 0, 2, 1],
+            &[0x40, NEXT_HOP, 4, 192, 0, 2, 1],
+        ]
+        .concat();
+        let widened = widen_attributes(&raw);
+        let attrs = Attributes(&widened).collect::<Vec<_>>();
+        assert_eq!(attrs.len(), 3);
+        assert_eq!(attrs[0].1[..4], [0x50, AS_PATH, 0, 10]);
+        assert_eq!(
+            attribute_value(attrs[0].1),
+            [2, 2, 0, 0, 0xfd, 0xe8, 0, 0, 0xfd, 0xe9]
+        );
+        assert_eq!(attribute_value(attrs[1].1).len(), 8);
+        assert_eq!(attrs[2].1, &raw[raw.len() - 7..]);
+
+        assert_eq!(widen_as_path(&[2, 2, 0xfd]), None);
+    }
+}
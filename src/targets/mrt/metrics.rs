@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct MrtMetrics {
+    pub failing: AtomicBool,
+    pub record_count: AtomicUsize,
+    pub update_file_count: AtomicUsize,
+    pub dump_count: AtomicUsize,
+    pub route_count: AtomicUsize,
+    pub write_error_count: AtomicUsize,
+}
+
+impl GraphStatus for MrtMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "records: {}\nroutes: {}\ndumps: {}",
+            self.record_count.load(SeqCst),
+            self.route_count.load(SeqCst),
+            self.dump_count.load(SeqCst),
+        )
+    }
+
+    fn okay(&self) -> Option<bool> {
+        Some(!self.failing.load(SeqCst))
+    }
+}
+
+impl MrtMetrics {
+    const FAILING_METRIC: Metric = Metric::new(
+        "mrt_target_failing",
+        "whether the last write failed: 0=no, 1=yes",
+        MetricType::Gauge,
+        MetricUnit::State,
+    );
+    const RECORD_COUNT_METRIC: Metric = Metric::new(
+        "mrt_target_record_count",
+        "the number of records written to update files",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const UPDATE_FILE_COUNT_METRIC: Metric = Metric::new(
+        "mrt_target_update_file_count",
+        "the number of update files finished",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DUMP_COUNT_METRIC: Metric = Metric::new(
+        "mrt_target_dump_count",
+        "the number of RIB dumps written",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const ROUTE_COUNT_METRIC: Metric = Metric::new(
+        "mrt_target_route_count",
+        "the number of routes kept for RIB dumps",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const WRITE_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "mrt_target_write_error_count",
+        "the number of update files or dumps that could not be written",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for MrtMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::FAILING_METRIC,
+            Some(unit_name),
+            u8::from(self.failing.load(SeqCst)),
+        );
+        target.append_simple(
+            &Self::RECORD_COUNT_METRIC,
+            Some(unit_name),
+            self.record_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::UPDATE_FILE_COUNT_METRIC,
+            Some(unit_name),
+            self.update_file_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DUMP_COUNT_METRIC,
+            Some(unit_name),
+            self.dump_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::ROUTE_COUNT_METRIC,
+            Some(unit_name),
+            self.route_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::WRITE_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.write_error_count.load(SeqCst),
+        );
+    }
+}
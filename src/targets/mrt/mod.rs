@@ -0,0 +1,4 @@
+pub(crate) mod bgp4mp;
+mod metrics;
+mod table;
+pub mod target;
@@ -0,0 +1,420 @@
+//! Keeping the routes of all peers for RIB dumps.
+//!
+//! The [`Table`] follows the updates received by the `mrt-out` target and
+//! holds the current routes of every peer, so that they can be written out
+//! as a TABLE_DUMP_V2 RIB dump as described in [RFC 6396]. A dump starts
+//! with a PEER_INDEX_TABLE record listing the peers, followed by a RIB
+//! record per prefix with an entry for every peer that has a route for it.
+//!
+//! As the RFC requires, the path attributes of the entries always use
+//! four-octet AS numbers, so the AS_PATH and AGGREGATOR attributes of routes
+//! from sessions without them are widened. Their AS4_PATH and AS4_AGGREGATOR
+//! attributes are kept as they were received. The MP_REACH_NLRI attribute of
+//! IPv6 and multicast routes only holds the length of the next hop and the
+//! next hop itself.
+//!
+//! The local side of the collector is not known, so the collector BGP ID
+//! and those of the peers are left zero.
+//!
+//! [RFC 6396]: https://www.rfc-editor.org/rfc/rfc6396
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+};
+
+use chrono::{DateTime, Utc};
+use inetnum::{addr::Prefix, asn::Asn};
+use rotonda_store::prefix_record::RouteStatus;
+
+use crate::{
+    ingress::IngressId,
+    payload::{Payload, RotondaRoute, Update},
+    roto_runtime::types::RouteContext,
+    units::rib_unit::best_path::prefix_of,
+};
+
+use super::bgp4mp::{
+    afi_safi, next_hop, push_prefix, widen_attributes, Attributes,
+    MP_REACH_NLRI, MP_UNREACH_NLRI, NEXT_HOP,
+};
+
+/// The MRT type of RIB dumps.
+const TABLE_DUMP_V2: u16 = 13;
+
+const PEER_INDEX_TABLE: u16 = 1;
+
+/// The flag of attributes with a two-octet length.
+const EXTENDED_LENGTH: u8 = 0x10;
+
+//------------ Table ---------------------------------------------------------
+
+/// The current routes of all peers.
+#[derive(Debug, Default)]
+pub struct Table {
+    peers: HashMap<IngressId, Peer>,
+}
+
+#[derive(Debug)]
+struct Peer {
+    addr: IpAddr,
+    asn: Asn,
+
+    /// The routes by RIB subtype and prefix.
+    routes: HashMap<(u16, Prefix), Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// When the route was received.
+    originated: u32,
+
+    /// The path attributes as they appear in the dump.
+    attributes: Vec<u8>,
+}
+
+impl Table {
+    /// Applies the routes and withdrawals in an update.
+    pub fn apply(&mut self, update: &Update) {
+        match update {
+            Update::Single(payload) => self.apply_payload(payload),
+            Update::Bulk(payloads) => payloads
+                .iter()
+                .for_each(|payload| self.apply_payload(payload)),
+            Update::Withdraw(ingress_id, None) => {
+                self.peers.remove(ingress_id);
+            }
+            Update::Withdraw(ingress_id, Some(afisafi)) => {
+                let Some(subtype) = rib_subtype((*afisafi).into()) else {
+                    return;
+                };
+                if let Some(peer) = self.peers.get_mut(ingress_id) {
+                    peer.routes.retain(|(kind, _), _| *kind != subtype);
+                }
+            }
+            Update::WithdrawBulk(ingress_ids) => {
+                for ingress_id in ingress_ids {
+                    self.peers.remove(ingress_id);
+                }
+            }
+            Update::OutputStream(..)
+            | Update::QueryResult(..)
+            | Update::UpstreamStatusChange(..)
+            | Update::Rtr(..) => {}
+        }
+    }
+
+    fn apply_payload(&mut self, payload: &Payload) {
+        let (status, provenance) = match &payload.context {
+            RouteContext::Fresh(ctx) => (ctx.status(), ctx.provenance()),
+            RouteContext::Mrt(ctx) => (ctx.status, ctx.provenance()),
+            RouteContext::Reprocess => return,
+        };
+        let route = &payload.rx_value;
+        let Some(subtype) = rib_subtype(afi_safi(route)) else {
+            return;
+        };
+        let key = (subtype, prefix_of(route));
+        if status == RouteStatus::Withdrawn {
+            if let Some(peer) = self.peers.get_mut(&provenance.ingress_id) {
+                peer.routes.remove(&key);
+            }
+            return;
+        }
+        let peer =
+            self.peers
+                .entry(provenance.ingress_id)
+                .or_insert_with(|| Peer {
+                    addr: provenance.peer_ip,
+                    asn: provenance.peer_asn,
+                    routes: HashMap::new(),
+                });
+        peer.routes.insert(
+            key,
+            Entry {
+                originated: provenance.timestamp.timestamp() as u32,
+                attributes: rib_attributes(route),
+            },
+        );
+    }
+
+    /// Returns the number of routes of all peers.
+    pub fn route_count(&self) -> usize {
+        self.peers.values().map(|peer| peer.routes.len()).sum()
+    }
+
+    /// Appends a RIB dump taken at `time`, returning the number of routes.
+    ///
+    /// The `view` name is included in the PEER_INDEX_TABLE record.
+    pub fn dump(
+        &self,
+        view: &str,
+        time: DateTime<Utc>,
+        buf: &mut Vec<u8>,
+    ) -> usize {
+        // Peers are numbered in the order of their ingress IDs, so that
+        // dumps of the same routes are the same.
+        let mut ids = self.peers.keys().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.truncate(usize::from(u16::MAX));
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&[0; 4]);
+        let view = &view.as_bytes()[..view.len().min(usize::from(u16::MAX))];
+        body.extend_from_slice(&(view.len() as u16).to_be_bytes());
+        body.extend_from_slice(view);
+        body.extend_from_slice(&(ids.len() as u16).to_be_bytes());
+        for id in &ids {
+            let peer = &self.peers[id];
+            // Peers always have four-octet AS numbers.
+            match peer.addr {
+                IpAddr::V4(addr) => {
+                    body.extend_from_slice(&[0x02, 0, 0, 0, 0]);
+                    body.extend_from_slice(&addr.octets());
+                }
+                IpAddr::V6(addr) => {
+                    body.extend_from_slice(&[0x03, 0, 0, 0, 0]);
+                    body.extend_from_slice(&addr.octets());
+                }
+            }
+            body.extend_from_slice(&peer.asn.into_u32().to_be_bytes());
+        }
+        push_record(time, PEER_INDEX_TABLE, &body, buf);
+
+        let mut rib = BTreeMap::<_, Vec<_>>::new();
+        for (index, id) in ids.iter().enumerate() {
+            for (key, entry) in &self.peers[id].routes {
+                rib.entry(*key).or_default().push((index as u16, entry));
+            }
+        }
+        let mut count = 0;
+        for (sequence, ((subtype, prefix), entries)) in rib.iter().enumerate()
+        {
+            body.clear();
+            body.extend_from_slice(&(sequence as u32).to_be_bytes());
+            push_prefix(*prefix, &mut body);
+            let count_pos = body.len();
+            body.extend_from_slice(&[0, 0]);
+            let mut entry_count = 0u16;
+            for (index, entry) in entries {
+                let Ok(len) = u16::try_from(entry.attributes.len()) else {
+                    continue;
+                };
+                body.extend_from_slice(&index.to_be_bytes());
+                body.extend_from_slice(&entry.originated.to_be_bytes());
+                body.extend_from_slice(&len.to_be_bytes());
+                body.extend_from_slice(&entry.attributes);
+                entry_count += 1;
+            }
+            body[count_pos..count_pos + 2]
+                .copy_from_slice(&entry_count.to_be_bytes());
+            push_record(time, *subtype, &body, buf);
+            count += usize::from(entry_count);
+        }
+        count
+    }
+}
+
+/// Returns the RIB subtype for an AFI and SAFI, if there is one.
+fn rib_subtype(afi_safi: (u16, u8)) -> Option<u16> {
+    match afi_safi {
+        (1, 1) => Some(2),
+        (1, 2) => Some(3),
+        (2, 1) => Some(4),
+        (2, 2) => Some(5),
+        _ => None,
+    }
+}
+
+fn push_record(
+    time: DateTime<Utc>,
+    subtype: u16,
+    body: &[u8],
+    buf: &mut Vec<u8>,
+) {
+    buf.extend_from_slice(&(time.timestamp() as u32).to_be_bytes());
+    buf.extend_from_slice(&TABLE_DUMP_V2.to_be_bytes());
+    buf.extend_from_slice(&subtype.to_be_bytes());
+    buf.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    buf.extend_from_slice(body);
+}
+
+/// Returns the path attributes of a route as they appear in a dump.
+fn rib_attributes(route: &RotondaRoute) -> Vec<u8> {
+    let attributes = route.rotonda_pamap().path_attributes();
+    let four_octet = attributes.pdu_parse_info().four_octet_enabled();
+    let mut raw = attributes.into_vec();
+    if !four_octet {
+        raw = widen_attributes(&raw);
+    }
+    let (afi, safi) = afi_safi(route);
+    let conventional = afi == 1 && safi == 1;
+
+    let mut res = Vec::with_capacity(raw.len());
+    for (type_code, attr) in Attributes(&raw) {
+        match type_code {
+            MP_REACH_NLRI | MP_UNREACH_NLRI => {}
+            NEXT_HOP if !conventional => {}
+            _ => res.extend_from_slice(attr),
+        }
+    }
+    if !conventional {
+        let next_hop = next_hop(&raw, afi);
+        res.extend_from_slice(&[
+            0x80 | EXTENDED_LENGTH,
+            MP_REACH_NLRI,
+            0,
+            next_hop.len() as u8 + 1,
+            next_hop.len() as u8,
+        ]);
+        res.extend_from_slice(&next_hop);
+    }
+    res
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use routecore::{
+        bgp::{
+            message::PduParseInfo, path_attributes::OwnedPathAttributes,
+            types::AfiSafiType,
+        },
+        mrt::MrtFile,
+    };
+
+    use crate::{
+        payload::RotondaPaMap,
+        roto_runtime::types::{MrtContext, Provenance},
+        targets::{
+            file::row::tests::mk_route,
+            mrt::bgp4mp::{attribute_value, AS_PATH},
+        },
+    };
+
+    use super::*;
+
+    fn payload(
+        route: RotondaRoute,
+        status: RouteStatus,
+        ingress_id: IngressId,
+        peer_ip: &str,
+    ) -> Payload {
+        let provenance = Provenance::for_bgp(
+            ingress_id,
+            peer_ip.parse().unwrap(),
+            Asn::from_u32(65000 + ingress_id),
+        );
+        Payload::new(
+            route,
+            RouteContext::Mrt(MrtContext { status, provenance }),
+            None,
+        )
+    }
+
+    #[test]
+    fn routes_are_dumped() {
+        let mut table = Table::default();
+        let route = mk_route("198.51.100.0/24", &[65001, 65002], &[]);
+        table.apply(&Update::Bulk(
+            vec![
+                payload(route.clone(), RouteStatus::Active, 1, "192.0.2.1"),
+                payload(route.clone(), RouteStatus::Active, 2, "2001:db8::2"),
+                payload(
+                    mk_route("203.0.113.0/24", &[65003], &[]),
+                    RouteStatus::Active,
+                    2,
+                    "2001:db8::2",
+                ),
+                payload(
+                    mk_route("192.0.2.0/24", &[65004], &[]),
+                    RouteStatus::Active,
+                    3,
+                    "192.0.2.3",
+                ),
+            ]
+            .into(),
+        ));
+        table.apply(&Update::Single(payload(
+            mk_route("203.0.113.0/24", &[65003], &[]),
+            RouteStatus::Withdrawn,
+            2,
+            "2001:db8::2",
+        )));
+        table.apply(&Update::Withdraw(3, None));
+        assert_eq!(table.route_count(), 2);
+
+        let mut buf = Vec::new();
+        let time = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        assert_eq!(table.dump("rotonda", time, &mut buf), 2);
+
+        let file = MrtFile::new(&buf);
+        let peers = file.pi().unwrap();
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].addr, "192.0.2.1".parse::<IpAddr>().unwrap());
+        assert_eq!(peers[1].asn, Asn::from_u32(65002));
+        let entries = file.rib_entries().unwrap().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        for (index, (afisafi, peer_id, _, prefix, raw)) in
+            entries.into_iter().enumerate()
+        {
+            assert_eq!(afisafi, AfiSafiType::Ipv4Unicast);
+            assert_eq!(usize::from(peer_id), index);
+            assert_eq!(prefix.to_string(), "198.51.100.0/24");
+            let attributes =
+                OwnedPathAttributes::new(PduParseInfo::modern(), raw);
+            assert_eq!(attributes.into_vec(), rib_attributes(&route));
+        }
+    }
+
+    #[test]
+    fn families_are_withdrawn() {
+        let mut table = Table::default();
+        let pamap = RotondaPaMap::new(OwnedPathAttributes::new(
+            PduParseInfo::modern(),
+            Vec::new(),
+        ));
+        let prefix: Prefix = "2001:db8::/32".parse().unwrap();
+        let route =
+            RotondaRoute::Ipv6Unicast(prefix.try_into().unwrap(), pamap);
+        table.apply(&Update::Bulk(
+            vec![
+                payload(route, RouteStatus::Active, 1, "192.0.2.1"),
+                payload(
+                    mk_route("198.51.100.0/24", &[65001], &[]),
+                    RouteStatus::Active,
+                    1,
+                    "192.0.2.1",
+                ),
+            ]
+            .into(),
+        ));
+        table.apply(&Update::Withdraw(1, Some(AfiSafiType::Ipv6Unicast)));
+        assert_eq!(table.route_count(), 1);
+    }
+
+    #[test]
+    fn attributes_are_widened() {
+        let pamap = RotondaPaMap::new(OwnedPathAttributes::new(
+            PduParseInfo::legacy(),
+            vec![0x40, AS_PATH, 6, 2, 2, 0xfd, 0xe8, 0xfd, 0xe9],
+        ));
+        let prefix: Prefix = "2001:db8::/32".parse().unwrap();
+        let route =
+            RotondaRoute::Ipv6Unicast(prefix.try_into().unwrap(), pamap);
+        let attributes = rib_attributes(&route);
+        let attrs = Attributes(&attributes).collect::<Vec<_>>();
+        assert_eq!(attrs.len(), 2);
+        assert_eq!(
+            attribute_value(attrs[0].1),
+            [2, 2, 0, 0, 0xfd, 0xe8, 0, 0, 0xfd, 0xe9]
+        );
+        // Without a next hop, the unspecified address is used.
+        assert_eq!(attrs[1].0, MP_REACH_NLRI);
+        assert_eq!(
+            attribute_value(attrs[1].1),
+            [[16].as_slice(), &[0; 16]].concat()
+        );
+    }
+}
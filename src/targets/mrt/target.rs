@@ -0,0 +1,682 @@
+//! Writing updates and RIB dumps as MRT files.
+//!
+//! The `mrt-out` target writes the routes and events it receives to files
+//! in the `directory`, laid out as route collectors do, so that tooling
+//! built for their archives can read them:
+//!
+//! * Update files hold a BGP4MP record per route or session going down, as
+//!   described in the [`bgp4mp`] module. A new file is started for every
+//!   time window of `rotate_secs`, aligned to the clock, so that with the
+//!   default of five minutes files start on 00:00, 00:05, and so on. No
+//!   file is written for a window without updates.
+//! * RIB dumps are TABLE_DUMP_V2 files with the current routes of all
+//!   peers, as described in the [`table`] module. They are written every
+//!   `dump_interval_secs`, again aligned to the clock, or never if it is
+//!   0. The routes are those the target received, so a dump only contains
+//!   the routes of sessions that came up after it was started.
+//!
+//! The names of the files are given by the `updates` and `dumps`
+//! templates, with the placeholders described for the `s3-out` target and
+//! the start of the window or the time of the dump as their time. A
+//! template may contain slashes to spread the files over directories,
+//! which are created as needed. Files are compressed with gzip unless
+//! `compression` is `"bzip2"` or `"none"`, with the extension being `gz`,
+//! `bz2` or `mrt`, respectively.
+//!
+//! A file is written under a temporary name starting with a dot and only
+//! renamed once complete, so that it is never read half-written. An
+//! existing file of the same name is replaced.
+//!
+//! [`bgp4mp`]: super::bgp4mp
+//! [`table`]: super::table
+
+use std::{
+    fs, io,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::Duration,
+};
+
+use bzip2::write::BzEncoder;
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::{
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+};
+
+use crate::{
+    comms::{Link, Terminated, UnitStatus},
+    config::ConfigPath,
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    payload::Update,
+    targets::{
+        http::template::Template,
+        s3::target::{render_key, window_end, KEY_NAMES},
+    },
+};
+
+use super::{bgp4mp, metrics::MrtMetrics, table::Table};
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct MrtOut {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The directory to write the files to.
+    directory: ConfigPath,
+
+    /// The template for the names of update files.
+    #[serde(default = "Config::default_updates")]
+    updates: String,
+
+    /// The time window an update file covers.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_rotate_secs")]
+    rotate_secs: Duration,
+
+    /// The template for the names of RIB dumps.
+    #[serde(default = "Config::default_dumps")]
+    dumps: String,
+
+    /// How often to write a RIB dump, or never if zero.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_dump_interval_secs")]
+    dump_interval_secs: Duration,
+
+    #[serde(default)]
+    compression: Compression,
+}
+
+impl Config {
+    fn default_updates() -> String {
+        "updates.{{year}}{{month}}{{day}}.{{hour}}{{minute}}.{{extension}}"
+            .into()
+    }
+
+    fn default_rotate_secs() -> Duration {
+        Duration::from_secs(300)
+    }
+
+    fn default_dumps() -> String {
+        "bview.{{year}}{{month}}{{day}}.{{hour}}{{minute}}.{{extension}}"
+            .into()
+    }
+
+    fn default_dump_interval_secs() -> Duration {
+        Duration::from_secs(8 * 3600)
+    }
+
+    /// Returns the templates for update files and dumps, checking the
+    /// configuration.
+    fn templates(&self) -> Result<(Template, Template), String> {
+        if self.rotate_secs.is_zero() {
+            return Err("rotate_secs must be at least 1".into());
+        }
+        let updates = Template::new(self.updates.as_str().into(), &KEY_NAMES)
+            .map_err(|err| format!("updates: {err}"))?;
+        let dumps = Template::new(self.dumps.as_str().into(), &KEY_NAMES)
+            .map_err(|err| format!("dumps: {err}"))?;
+        Ok((updates, dumps))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    #[default]
+    Gzip,
+    Bzip2,
+}
+
+impl Compression {
+    /// Returns the file name extension of files.
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "mrt",
+            Compression::Gzip => "gz",
+            Compression::Bzip2 => "bz2",
+        }
+    }
+}
+
+impl MrtOut {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let config = self.config;
+        let (updates, dumps) = match config.templates() {
+            Ok(res) => res,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+
+        let metrics = Arc::new(MrtMetrics::default());
+        component.register_metrics(metrics.clone());
+        let dump_interval = (!config.dump_interval_secs.is_zero())
+            .then_some(config.dump_interval_secs);
+        MrtRunner {
+            name: component.name().to_string(),
+            directory: config.directory.to_path_buf(),
+            compression: config.compression,
+            rotate: config.rotate_secs,
+            updates,
+            dumps,
+            dump_interval,
+            file: None,
+            table: dump_interval.map(|_| Table::default()),
+            ingresses: component.ingresses().clone(),
+            metrics,
+        }
+        .run(self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ MrtRunner -----------------------------------------------------
+
+struct MrtRunner {
+    name: String,
+    directory: PathBuf,
+    compression: Compression,
+    rotate: Duration,
+    updates: Template,
+    dumps: Template,
+    dump_interval: Option<Duration>,
+
+    /// The update file being written.
+    file: Option<UpdateFile>,
+
+    /// The routes for RIB dumps, if they are written.
+    table: Option<Table>,
+
+    ingresses: Arc<ingress::Register>,
+    metrics: Arc<MrtMetrics>,
+}
+
+impl MrtRunner {
+    async fn run(
+        mut self,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        let mut next_dump = self
+            .dump_interval
+            .map(|interval| window_end(Utc::now(), interval));
+        let mut tick = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the mrt-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => self.push(update, Utc::now()).await,
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of mrt-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+
+                _ = tick.tick() => {
+                    let now = Utc::now();
+                    if self.file.as_ref().is_some_and(|file| now >= file.end)
+                    {
+                        self.finish().await;
+                    }
+                    if let (Some(time), Some(interval)) =
+                        (next_dump, self.dump_interval)
+                    {
+                        if now >= time {
+                            self.dump(time).await;
+                            next_dump = Some(window_end(now, interval));
+                        }
+                    }
+                }
+            }
+        }
+
+        // Finish the update file so that it is not left behind under its
+        // temporary name.
+        self.finish().await;
+        Err(Terminated)
+    }
+
+    /// Writes the records for an update, starting an update file if needed.
+    async fn push(&mut self, update: Update, now: DateTime<Utc>) {
+        if let Some(table) = self.table.as_mut() {
+            table.apply(&update);
+            self.metrics.route_count.store(table.route_count(), SeqCst);
+        }
+        let mut records = Vec::new();
+        let count =
+            bgp4mp::push_update(&update, &self.ingresses, &mut records);
+        if count == 0 {
+            return;
+        }
+
+        if self.file.as_ref().is_some_and(|file| now >= file.end) {
+            self.finish().await;
+        }
+        if self.file.is_none() {
+            let end = window_end(now, self.rotate);
+            let name = render_key(
+                &self.updates,
+                end - self.rotate,
+                self.compression.extension(),
+            );
+            let path = self.directory.join(name);
+            match UpdateFile::create(path.clone(), self.compression, end)
+                .await
+            {
+                Ok(file) => self.file = Some(file),
+                Err(err) => {
+                    self.failed(format!(
+                        "cannot create {}: {err}",
+                        path.display()
+                    ));
+                    return;
+                }
+            }
+        }
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        match file.write(&records).await {
+            Ok(()) => {
+                self.metrics.record_count.fetch_add(count, SeqCst);
+            }
+            Err(err) => {
+                let path = file.path.clone();
+                if let Some(file) = self.file.take() {
+                    file.discard().await;
+                }
+                self.failed(format!(
+                    "cannot write {}: {err}",
+                    path.display()
+                ));
+            }
+        }
+    }
+
+    /// Finishes the current update file, if there is one.
+    async fn finish(&mut self) {
+        let Some(file) = self.file.take() else {
+            return;
+        };
+        let path = file.path.clone();
+        match file.finish().await {
+            Ok(()) => {
+                debug!("Target {}: wrote {}", self.name, path.display());
+                self.metrics.update_file_count.fetch_add(1, SeqCst);
+                self.metrics.failing.store(false, SeqCst);
+            }
+            Err(err) => {
+                self.failed(format!("cannot write {}: {err}", path.display()))
+            }
+        }
+    }
+
+    /// Writes a RIB dump taken at `time`.
+    async fn dump(&mut self, time: DateTime<Utc>) {
+        let Some(table) = self.table.as_ref() else {
+            return;
+        };
+        let mut buf = Vec::new();
+        let routes = table.dump(&self.name, time, &mut buf);
+        let name =
+            render_key(&self.dumps, time, self.compression.extension());
+        let path = self.directory.join(name);
+        let compression = self.compression;
+        let res = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || {
+                write_file(&path, compression, &buf)
+            })
+            .await
+            .unwrap_or_else(|err| Err(io::Error::other(err)))
+        };
+        match res {
+            Ok(()) => {
+                info!(
+                    "Target {}: wrote RIB dump {} with {routes} routes",
+                    self.name,
+                    path.display()
+                );
+                self.metrics.dump_count.fetch_add(1, SeqCst);
+                self.metrics.failing.store(false, SeqCst);
+            }
+            Err(err) => {
+                self.failed(format!("cannot write {}: {err}", path.display()))
+            }
+        }
+    }
+
+    fn failed(&self, err: String) {
+        error!("Target {}: {err}", self.name);
+        self.metrics.write_error_count.fetch_add(1, SeqCst);
+        self.metrics.failing.store(true, SeqCst);
+    }
+}
+
+//------------ UpdateFile ----------------------------------------------------
+
+/// An update file being written.
+struct UpdateFile {
+    /// Where the file goes once finished.
+    path: PathBuf,
+
+    /// Where the file is written to until then.
+    tmp_path: PathBuf,
+
+    file: BufWriter<tokio::fs::File>,
+    encoder: Encoder,
+
+    /// The end of the time window of the file.
+    end: DateTime<Utc>,
+}
+
+impl UpdateFile {
+    async fn create(
+        path: PathBuf,
+        compression: Compression,
+        end: DateTime<Utc>,
+    ) -> io::Result<Self> {
+        let tmp_path = tmp_path(&path);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let file = tokio::fs::File::create(&tmp_path).await?;
+        Ok(Self {
+            path,
+            tmp_path,
+            file: BufWriter::new(file),
+            encoder: Encoder::new(compression),
+            end,
+        })
+    }
+
+    async fn write(&mut self, records: &[u8]) -> io::Result<()> {
+        self.encoder.write(records);
+        let bytes = self.encoder.take();
+        self.file.write_all(&bytes).await
+    }
+
+    /// Writes what is left and moves the file into place.
+    async fn finish(mut self) -> io::Result<()> {
+        let res = async {
+            self.file.write_all(&self.encoder.finish()).await?;
+            self.file.flush().await?;
+            tokio::fs::rename(&self.tmp_path, &self.path).await
+        }
+        .await;
+        if res.is_err() {
+            let _ = tokio::fs::remove_file(&self.tmp_path).await;
+        }
+        res
+    }
+
+    /// Removes the unfinished file.
+    async fn discard(self) {
+        drop(self.file);
+        let _ = tokio::fs::remove_file(&self.tmp_path).await;
+    }
+}
+
+/// Writes a complete file, moving it into place once written.
+fn write_file(
+    path: &Path,
+    compression: Compression,
+    records: &[u8],
+) -> io::Result<()> {
+    let tmp_path = tmp_path(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut encoder = Encoder::new(compression);
+    encoder.write(records);
+    let res = fs::write(&tmp_path, encoder.finish())
+        .and_then(|_| fs::rename(&tmp_path, path));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    res
+}
+
+/// Returns the temporary name of a file being written.
+fn tmp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.tmp"))
+}
+
+//------------ Encoder -------------------------------------------------------
+
+/// Compresses the content of a file.
+enum Encoder {
+    Plain(Vec<u8>),
+    Gzip(GzEncoder<Vec<u8>>),
+    Bzip2(BzEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(compression: Compression) -> Self {
+        match compression {
+            Compression::None => Encoder::Plain(Vec::new()),
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+            Compression::Bzip2 => Encoder::Bzip2(BzEncoder::new(
+                Vec::new(),
+                bzip2::Compression::default(),
+            )),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        // Writing to a vec does not fail.
+        let _ = match self {
+            Encoder::Plain(buf) => Write::write_all(buf, bytes),
+            Encoder::Gzip(encoder) => encoder.write_all(bytes),
+            Encoder::Bzip2(encoder) => encoder.write_all(bytes),
+        };
+    }
+
+    /// Takes the content produced so far.
+    fn take(&mut self) -> Vec<u8> {
+        match self {
+            Encoder::Plain(buf) => std::mem::take(buf),
+            Encoder::Gzip(encoder) => std::mem::take(encoder.get_mut()),
+            Encoder::Bzip2(encoder) => std::mem::take(encoder.get_mut()),
+        }
+    }
+
+    /// Returns the rest of the content.
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Encoder::Plain(buf) => buf,
+            Encoder::Gzip(encoder) => encoder.finish().unwrap_or_default(),
+            Encoder::Bzip2(encoder) => encoder.finish().unwrap_or_default(),
+        }
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use bzip2::read::BzDecoder;
+    use flate2::read::GzDecoder;
+    use inetnum::asn::Asn;
+    use rotonda_store::prefix_record::RouteStatus;
+    use routecore::mrt::MrtFile;
+
+    use crate::{
+        payload::Payload,
+        roto_runtime::types::{MrtContext, Provenance, RouteContext},
+        targets::file::row::tests::mk_route,
+    };
+
+    use super::*;
+
+    fn mk_config(extra: &str) -> Config {
+        toml::from_str(&format!("directory = \"/tmp\"\n{extra}")).unwrap()
+    }
+
+    fn mk_runner(dir: &Path, extra: &str) -> MrtRunner {
+        let config = mk_config(extra);
+        let (updates, dumps) = config.templates().unwrap();
+        let dump_interval = (!config.dump_interval_secs.is_zero())
+            .then_some(config.dump_interval_secs);
+        MrtRunner {
+            name: "mrt".into(),
+            directory: dir.to_path_buf(),
+            compression: config.compression,
+            rotate: config.rotate_secs,
+            updates,
+            dumps,
+            dump_interval,
+            file: None,
+            table: dump_interval.map(|_| Table::default()),
+            ingresses: Default::default(),
+            metrics: Default::default(),
+        }
+    }
+
+    fn mk_update(prefix: &str, ingress_id: ingress::IngressId) -> Update {
+        let provenance = Provenance::for_bgp(
+            ingress_id,
+            format!("192.0.2.{ingress_id}").parse().unwrap(),
+            Asn::from_u32(65000),
+        );
+        Update::Single(Payload::new(
+            mk_route(prefix, &[65000, 65001], &[]),
+            RouteContext::Mrt(MrtContext {
+                status: RouteStatus::Active,
+                provenance,
+            }),
+            None,
+        ))
+    }
+
+    fn tmp_dir() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("rotonda-mrt-out-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn config_is_checked() {
+        assert!(mk_config("").templates().is_ok());
+        assert_eq!(mk_config("").compression, Compression::Gzip);
+        assert_eq!(
+            mk_config("compression = \"bzip2\"").compression,
+            Compression::Bzip2
+        );
+        assert!(mk_config("rotate_secs = 0").templates().is_err());
+        assert!(mk_config("updates = \"{{date}}\"").templates().is_err());
+        assert!(mk_config("dumps = \"{{date}}\"").templates().is_err());
+    }
+
+    #[tokio::test]
+    async fn update_files_are_rotated() {
+        let dir = tmp_dir();
+        let mut runner = mk_runner(&dir, "dump_interval_secs = 0");
+        assert!(runner.table.is_none());
+
+        // 2023-11-14T22:13:20Z, in the window starting at 22:10.
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        runner.push(mk_update("198.51.100.0/24", 1), now).await;
+        runner.push(mk_update("203.0.113.0/24", 1), now).await;
+        assert!(dir.join(".updates.20231114.2210.gz.tmp").exists());
+        assert!(!dir.join("updates.20231114.2210.gz").exists());
+
+        // An update in the next window finishes the file.
+        let later = now + Duration::from_secs(100);
+        runner.push(mk_update("198.51.100.0/24", 2), later).await;
+        assert!(!dir.join(".updates.20231114.2210.gz.tmp").exists());
+        let mut buf = Vec::new();
+        GzDecoder::new(
+            fs::File::open(dir.join("updates.20231114.2210.gz")).unwrap(),
+        )
+        .read_to_end(&mut buf)
+        .unwrap();
+        assert_eq!(MrtFile::new(&buf).messages().count(), 2);
+
+        runner.finish().await;
+        assert!(dir.join("updates.20231114.2215.gz").exists());
+        assert_eq!(runner.metrics.record_count.load(SeqCst), 3);
+        assert_eq!(runner.metrics.update_file_count.load(SeqCst), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rib_dumps_are_written() {
+        let dir = tmp_dir();
+        let mut runner = mk_runner(
+            &dir,
+            "compression = \"bzip2\"\n\
+            dumps = \"{{year}}.{{month}}/bview.{{hour}}{{minute}}.\
+            {{extension}}\"",
+        );
+        let now = Utc::now();
+        runner.push(mk_update("198.51.100.0/24", 1), now).await;
+        runner.push(mk_update("198.51.100.0/24", 2), now).await;
+        runner.push(mk_update("203.0.113.0/24", 2), now).await;
+        runner.push(Update::Withdraw(2, None), now).await;
+        assert_eq!(runner.metrics.route_count.load(SeqCst), 1);
+
+        let time = "2025-01-02T08:00:00Z".parse().unwrap();
+        runner.dump(time).await;
+        let mut buf = Vec::new();
+        BzDecoder::new(
+            fs::File::open(dir.join("2025.01/bview.0800.bz2")).unwrap(),
+        )
+        .read_to_end(&mut buf)
+        .unwrap();
+        let file = MrtFile::new(&buf);
+        assert_eq!(file.pi().unwrap().len(), 1);
+        assert_eq!(file.rib_entries().unwrap().count(), 1);
+        assert_eq!(runner.metrics.dump_count.load(SeqCst), 1);
+
+        runner.finish().await;
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
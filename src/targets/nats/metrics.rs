@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct NatsMetrics {
+    pub connected: AtomicBool,
+    pub published_count: AtomicUsize,
+    pub rejected_count: AtomicUsize,
+    pub dropped_count: AtomicUsize,
+    pub connection_error_count: AtomicUsize,
+    pub queued_count: AtomicUsize,
+}
+
+impl GraphStatus for NatsMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "published: {}\nqueued: {}\ndropped: {}",
+            self.published_count.load(SeqCst),
+            self.queued_count.load(SeqCst),
+            self.dropped_count.load(SeqCst),
+        )
+    }
+
+    fn okay(&self) -> Option<bool> {
+        Some(self.connected.load(SeqCst))
+    }
+}
+
+impl NatsMetrics {
+    const CONNECTED_METRIC: Metric = Metric::new(
+        "nats_target_connected",
+        "whether messages can be published to the NATS server: 0=no, 1=yes",
+        MetricType::Gauge,
+        MetricUnit::State,
+    );
+    const PUBLISHED_COUNT_METRIC: Metric = Metric::new(
+        "nats_target_published_count",
+        "the number of messages published, and acknowledged with JetStream",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const REJECTED_COUNT_METRIC: Metric = Metric::new(
+        "nats_target_rejected_count",
+        "the number of messages JetStream refused to store",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DROPPED_COUNT_METRIC: Metric = Metric::new(
+        "nats_target_dropped_count",
+        "the number of messages dropped because the queue was full or their \
+        subject was invalid",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const CONNECTION_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "nats_target_connection_error_count",
+        "the number of times connecting or publishing to the server failed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const QUEUED_COUNT_METRIC: Metric = Metric::new(
+        "nats_target_queued_count",
+        "the number of messages waiting to be published",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for NatsMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::CONNECTED_METRIC,
+            Some(unit_name),
+            u8::from(self.connected.load(SeqCst)),
+        );
+        target.append_simple(
+            &Self::PUBLISHED_COUNT_METRIC,
+            Some(unit_name),
+            self.published_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::REJECTED_COUNT_METRIC,
+            Some(unit_name),
+            self.rejected_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DROPPED_COUNT_METRIC,
+            Some(unit_name),
+            self.dropped_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::CONNECTION_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.connection_error_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::QUEUED_COUNT_METRIC,
+            Some(unit_name),
+            self.queued_count.load(SeqCst),
+        );
+    }
+}
@@ -0,0 +1,701 @@
+//! Publishing updates to NATS.
+//!
+//! The `nats-out` target turns each route and event it receives into a row
+//! with the columns described in the [`row`] module and publishes it as a
+//! JSON object holding all columns to the [NATS] server at `url`. The
+//! `subject` may contain the columns as placeholders, as described in the
+//! [`template`] module, e.g. `"rotonda.{{topic}}.{{kind}}"`. White space in
+//! the result is replaced by underscores; rows for which it is not a valid
+//! subject, e.g. because a placeholder had no value, are dropped. Only the
+//! rows of the `kinds` and `topics` listed, if any, are published.
+//!
+//! Plainly publishing to NATS gives no guarantee that anyone received a
+//! message. With `jetstream`, each message is published to JetStream and
+//! considered sent only once the stream capturing the subject acknowledged
+//! it. Messages not acknowledged within `ack_timeout_secs` are published
+//! again, so they may be stored twice. Messages the server refuses, e.g.
+//! because no stream captures their subject, are dropped.
+//!
+//! The server is authenticated to with the user and password of the URL,
+//! a `token`, or the JWT and NKey seed of a `credentials` file. With a
+//! `tls://` URL or a `tls` table, the connection is upgraded to TLS after
+//! the server's greeting, as for the `mqtt-out` target.
+//!
+//! The connection is handled by the [async-nats] client, which reconnects
+//! with an exponential backoff. Meanwhile, messages wait in a queue of up
+//! to `queue_size` messages. Once the queue is full, further messages are
+//! dropped.
+//!
+//! [NATS]: https://nats.io/
+//! [async-nats]: https://docs.rs/async-nats/
+//! [`row`]: crate::targets::file::row
+//! [`template`]: crate::targets::http::template
+
+use std::{
+    fs,
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::Duration,
+};
+
+use async_nats::{
+    jetstream::{
+        self,
+        context::{PublishError, PublishErrorKind},
+    },
+    ConnectOptions, Event,
+};
+use bytes::Bytes;
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::sync::mpsc;
+use url::Url;
+
+use crate::{
+    common::tls::TlsClientConfig,
+    comms::{Link, Terminated, UnitStatus},
+    config::ConfigPath,
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    targets::{
+        file::row::{Row, COLUMNS},
+        http::template::Template,
+    },
+};
+
+use super::metrics::NatsMetrics;
+
+/// How long to keep trying to publish the queued messages when stopping.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct Nats {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The server to publish to, a `nats://` or `tls://` URL.
+    url: Url,
+
+    /// The subject to publish rows to.
+    subject: String,
+
+    /// The token to authenticate with, instead of a user and password.
+    #[serde(default)]
+    token: Option<String>,
+
+    /// The credentials file to authenticate with.
+    #[serde(default)]
+    credentials: Option<ConfigPath>,
+
+    #[serde(default)]
+    tls: Option<TlsClientConfig>,
+
+    /// Whether to wait for JetStream to acknowledge messages.
+    #[serde(default)]
+    jetstream: bool,
+
+    /// How long to wait for JetStream to acknowledge messages.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_ack_timeout_secs")]
+    ack_timeout_secs: Duration,
+
+    /// The kinds of rows to publish, all if not given.
+    #[serde(default)]
+    kinds: Option<Vec<String>>,
+
+    /// The topics of rows to publish, all if not given.
+    #[serde(default)]
+    topics: Option<Vec<String>>,
+
+    /// The most messages to publish before waiting for acknowledgements.
+    #[serde(default = "Config::default_batch_size")]
+    batch_size: usize,
+
+    /// How many messages may wait to be published.
+    #[serde(default = "Config::default_queue_size")]
+    queue_size: usize,
+
+    /// How long to wait before connecting again. The delay doubles with
+    /// every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_retry_delay_secs")]
+    retry_delay_secs: Duration,
+
+    /// The longest to wait before connecting again.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_max_retry_delay_secs")]
+    max_retry_delay_secs: Duration,
+}
+
+impl Config {
+    fn default_ack_timeout_secs() -> Duration {
+        Duration::from_secs(5)
+    }
+
+    fn default_batch_size() -> usize {
+        100
+    }
+
+    fn default_queue_size() -> usize {
+        1000
+    }
+
+    fn default_retry_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retry_delay_secs() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if !matches!(self.url.scheme(), "nats" | "tls") {
+            return Err(format!(
+                "unsupported URL scheme '{}', use nats or tls",
+                self.url.scheme()
+            ));
+        }
+        if self.url.host_str().is_none() {
+            return Err("missing host in URL".into());
+        }
+        if self.token.is_some() && self.credentials.is_some() {
+            return Err("only one of token and credentials can be set".into());
+        }
+        if self.batch_size == 0 || self.queue_size == 0 {
+            return Err("batch_size and queue_size must be at least 1".into());
+        }
+        Ok(())
+    }
+
+    /// Returns how to authenticate and whether to use TLS, reading the
+    /// credentials and TLS files.
+    fn connect_options(&self) -> Result<ConnectOptions, String> {
+        let options = if let Some(token) = &self.token {
+            ConnectOptions::with_token(token.clone())
+        } else if let Some(path) = &self.credentials {
+            fs::read_to_string(path)
+                .and_then(|creds| ConnectOptions::with_credentials(&creds))
+                .map_err(|err| {
+                    format!(
+                        "cannot read credentials {}: {err}",
+                        path.display()
+                    )
+                })?
+        } else {
+            ConnectOptions::new()
+        };
+        let tls = match (&self.tls, self.url.scheme()) {
+            (Some(tls), _) => tls.rustls_config()?,
+            (None, "tls") => TlsClientConfig::default().rustls_config()?,
+            (None, _) => return Ok(options),
+        };
+        Ok(options.require_tls(true).tls_client_config(tls))
+    }
+}
+
+impl Nats {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let config = self.config;
+        let res = config.check().and_then(|_| {
+            Ok((Subjects::new(&config)?, config.connect_options()?))
+        });
+        let (subjects, options) = match res {
+            Ok(res) => res,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+
+        let metrics = Arc::new(NatsMetrics::default());
+        component.register_metrics(metrics.clone());
+        let sender = Sender {
+            name: component.name().to_string(),
+            url: config.url,
+            ack_timeout: config.jetstream.then_some(config.ack_timeout_secs),
+            batch_size: config.batch_size,
+            retry_delay: config.retry_delay_secs,
+            max_retry_delay: config.max_retry_delay_secs,
+            metrics: metrics.clone(),
+        };
+        NatsRunner {
+            subjects,
+            ingresses: component.ingresses().clone(),
+            metrics,
+            queue_size: config.queue_size,
+        }
+        .run(sender, options, self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ NatsRunner ----------------------------------------------------
+
+struct NatsRunner {
+    subjects: Subjects,
+    ingresses: Arc<ingress::Register>,
+    metrics: Arc<NatsMetrics>,
+    queue_size: usize,
+}
+
+impl NatsRunner {
+    async fn run(
+        self,
+        sender: Sender,
+        options: ConnectOptions,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        // Messages are published by a task of their own, so that an
+        // unreachable server does not hold up the sources.
+        let (tx, rx) = mpsc::channel(self.queue_size);
+        let send_task = tokio::spawn(sender.run(options, rx));
+
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the nats-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        for row in Row::for_update(update, &self.ingresses) {
+                            let Some(message) = self.subjects.message(&row)
+                            else {
+                                continue;
+                            };
+                            let Some(message) = message else {
+                                self.metrics
+                                    .dropped_count
+                                    .fetch_add(1, SeqCst);
+                                continue;
+                            };
+                            if tx.try_send(message).is_ok() {
+                                self.metrics.queued_count.fetch_add(1, SeqCst);
+                            } else {
+                                self.metrics
+                                    .dropped_count
+                                    .fetch_add(1, SeqCst);
+                            }
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of nats-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+            }
+        }
+
+        drop(tx);
+        if tokio::time::timeout(DRAIN_TIMEOUT, send_task)
+            .await
+            .is_err()
+        {
+            warn!(
+                "Dropping {} NATS messages that could not be published",
+                self.metrics.queued_count.load(SeqCst)
+            );
+        }
+        Err(Terminated)
+    }
+}
+
+//------------ Subjects ------------------------------------------------------
+
+/// A message to publish.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Message {
+    subject: String,
+    payload: Bytes,
+}
+
+/// Turns rows into messages.
+struct Subjects {
+    subject: Template,
+    kinds: Option<Vec<String>>,
+    topics: Option<Vec<String>>,
+}
+
+impl Subjects {
+    fn new(config: &Config) -> Result<Self, String> {
+        let columns = COLUMNS.map(|column| column.name);
+        Ok(Self {
+            subject: Template::new(config.subject.as_str().into(), &columns)
+                .map_err(|err| format!("subject: {err}"))?,
+            kinds: config.kinds.clone(),
+            topics: config.topics.clone(),
+        })
+    }
+
+    /// Returns the message for a row if it is wanted.
+    ///
+    /// The inner option is empty if the subject is invalid.
+    fn message(&self, row: &Row) -> Option<Option<Message>> {
+        if !self.wants(row) {
+            return None;
+        }
+        let lookup = |name: &str| row.json_value(name);
+        let subject = self
+            .subject
+            .render_text(&lookup)
+            .replace(char::is_whitespace, "_");
+        if subject.split('.').any(str::is_empty) {
+            debug!("Dropping row with invalid subject '{subject}'");
+            return Some(None);
+        }
+        let payload = COLUMNS
+            .iter()
+            .map(|column| (column.name.to_string(), lookup(column.name)))
+            .collect::<serde_json::Map<_, _>>();
+        Some(Some(Message {
+            subject,
+            payload: serde_json::Value::from(payload).to_string().into(),
+        }))
+    }
+
+    fn wants(&self, row: &Row) -> bool {
+        self.kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.iter().any(|kind| kind == row.kind))
+            && self
+                .topics
+                .as_ref()
+                .is_none_or(|topics| topics.contains(&row.topic))
+    }
+}
+
+//------------ Sender --------------------------------------------------------
+
+/// Where messages are published to.
+enum Publisher {
+    Core(async_nats::Client),
+    JetStream(jetstream::Context),
+}
+
+/// Publishes queued messages, connecting to the server as needed.
+struct Sender {
+    name: String,
+    url: Url,
+
+    /// How long to wait for JetStream acknowledgements, if at all.
+    ack_timeout: Option<Duration>,
+
+    batch_size: usize,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    metrics: Arc<NatsMetrics>,
+}
+
+impl Sender {
+    /// Publishes messages until there are no more.
+    async fn run(
+        self,
+        options: ConnectOptions,
+        mut rx: mpsc::Receiver<Message>,
+    ) {
+        let client = match self.connect(options).await {
+            Ok(client) => client,
+            Err(err) => {
+                error!(
+                    "Target {}: cannot connect to {}: {err}",
+                    self.name,
+                    self.display_url()
+                );
+                return;
+            }
+        };
+        let publisher = match self.ack_timeout {
+            Some(ack_timeout) => {
+                let mut context = jetstream::new(client);
+                context.set_timeout(ack_timeout);
+                Publisher::JetStream(context)
+            }
+            None => Publisher::Core(client),
+        };
+
+        let mut delay = self.retry_delay;
+        let mut batch = Vec::new();
+        loop {
+            if batch.is_empty() {
+                let Some(message) = rx.recv().await else {
+                    break;
+                };
+                batch.push(message);
+                while batch.len() < self.batch_size {
+                    match rx.try_recv() {
+                        Ok(message) => batch.push(message),
+                        Err(_) => break,
+                    }
+                }
+            }
+
+            let len = batch.len();
+            let res = self.publish(&publisher, &mut batch).await;
+            self.metrics
+                .queued_count
+                .fetch_sub(len - batch.len(), SeqCst);
+            match res {
+                Ok(()) => delay = self.retry_delay,
+                Err(err) => {
+                    self.metrics.connection_error_count.fetch_add(1, SeqCst);
+                    warn!(
+                        "Target {}: publishing to {} failed: {err}",
+                        self.name,
+                        self.display_url()
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(self.max_retry_delay);
+                }
+            }
+        }
+    }
+
+    /// Creates the client, which connects in the background.
+    ///
+    /// The client reconnects by itself with the configured backoff,
+    /// reporting the state of the connection in the metrics.
+    async fn connect(
+        &self,
+        options: ConnectOptions,
+    ) -> Result<async_nats::Client, async_nats::ConnectError> {
+        let name = self.name.clone();
+        let url = self.display_url();
+        let metrics = self.metrics.clone();
+        let delay = self.retry_delay;
+        let max_delay = self.max_retry_delay;
+        options
+            .name(&self.name)
+            .retry_on_initial_connect()
+            .reconnect_delay_callback(move |attempts| {
+                let doublings = attempts.saturating_sub(1).min(16) as u32;
+                delay.saturating_mul(1 << doublings).min(max_delay)
+            })
+            .event_callback(move |event| {
+                let name = name.clone();
+                let url = url.clone();
+                let metrics = metrics.clone();
+                async move {
+                    match event {
+                        Event::Connected => {
+                            info!("Target {name}: connected to {url}");
+                            metrics.connected.store(true, SeqCst);
+                        }
+                        Event::Disconnected => {
+                            warn!("Target {name}: disconnected from {url}");
+                            metrics.connected.store(false, SeqCst);
+                            metrics
+                                .connection_error_count
+                                .fetch_add(1, SeqCst);
+                        }
+                        event => {
+                            debug!("Target {name}: NATS event: {event}")
+                        }
+                    }
+                }
+            })
+            .connect(self.url.as_str())
+            .await
+    }
+
+    /// Publishes a batch, removing the messages that are done with.
+    async fn publish(
+        &self,
+        publisher: &Publisher,
+        batch: &mut Vec<Message>,
+    ) -> Result<(), String> {
+        let context = match publisher {
+            Publisher::Core(client) => {
+                let mut published = 0;
+                let mut res = Ok(());
+                for message in batch.iter() {
+                    res = client
+                        .publish(
+                            message.subject.clone(),
+                            message.payload.clone(),
+                        )
+                        .await
+                        .map_err(|err| err.to_string());
+                    if res.is_err() {
+                        break;
+                    }
+                    published += 1;
+                }
+                batch.drain(..published);
+                self.metrics.published_count.fetch_add(published, SeqCst);
+                res?;
+                return client.flush().await.map_err(|err| err.to_string());
+            }
+            Publisher::JetStream(context) => context,
+        };
+
+        let mut acks = Vec::with_capacity(batch.len());
+        for message in batch.iter() {
+            acks.push(
+                context
+                    .publish(message.subject.clone(), message.payload.clone())
+                    .await,
+            );
+        }
+        // The acknowledgements are awaited together, so a batch takes at
+        // most about the acknowledgement timeout.
+        let acks = futures::future::join_all(
+            acks.into_iter().map(|ack| async move { ack?.await }),
+        )
+        .await;
+
+        let mut done = Vec::with_capacity(acks.len());
+        let mut rejected = Vec::new();
+        for (index, ack) in acks.into_iter().enumerate() {
+            match ack {
+                Ok(_) => {
+                    self.metrics.published_count.fetch_add(1, SeqCst);
+                    done.push(true);
+                }
+                Err(err) if is_retried(&err) => done.push(false),
+                Err(err) => {
+                    rejected.push((index, err));
+                    done.push(true);
+                }
+            }
+        }
+
+        if let Some((index, err)) = rejected.first() {
+            self.metrics
+                .rejected_count
+                .fetch_add(rejected.len(), SeqCst);
+            warn!(
+                "Target {}: JetStream refused {} messages, e.g. on '{}': \
+                {err}",
+                self.name,
+                rejected.len(),
+                batch[*index].subject
+            );
+        }
+        let mut done = done.into_iter();
+        batch.retain(|_| !done.next().unwrap_or_default());
+        if batch.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("{} messages were not acknowledged", batch.len()))
+        }
+    }
+
+    /// Returns the URL without the password.
+    fn display_url(&self) -> Url {
+        let mut url = self.url.clone();
+        let _ = url.set_password(None);
+        url
+    }
+}
+
+/// Returns whether a message is to be published again after an error.
+///
+/// Other errors mean that JetStream refused to store the message.
+fn is_retried(err: &PublishError) -> bool {
+    matches!(
+        err.kind(),
+        PublishErrorKind::TimedOut | PublishErrorKind::BrokenPipe
+    )
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mk_config(extra: &str) -> Config {
+        toml::from_str(&format!(
+            "url = \"nats://localhost\"\n\
+            subject = \"rotonda.{{{{topic}}}}.{{{{kind}}}}\"\n{extra}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn config_is_checked() {
+        let config = mk_config("");
+        assert!(config.check().is_ok());
+        assert!(config.connect_options().is_ok());
+        assert!(mk_config("batch_size = 0").check().is_err());
+        assert!(mk_config("token = \"t\"\ncredentials = \"/c\"")
+            .check()
+            .is_err());
+        assert!(mk_config("credentials = \"/nonexistent\"")
+            .connect_options()
+            .is_err());
+
+        let config: Config =
+            toml::from_str("url = \"http://localhost\"\nsubject = \"s\"")
+                .unwrap();
+        assert!(config.check().is_err());
+        let config: Config =
+            toml::from_str("url = \"nats://localhost\"\nsubject = \"{{x}}\"")
+                .unwrap();
+        assert!(Subjects::new(&config).is_err());
+    }
+
+    #[test]
+    fn rows_become_messages() {
+        let subjects =
+            Subjects::new(&mk_config("kinds = [\"announce\", \"log\"]"))
+                .unwrap();
+        let row = |kind, topic: &str| Row {
+            kind,
+            topic: topic.into(),
+            prefix: Some("198.51.100.0/24".into()),
+            ..Default::default()
+        };
+
+        let message =
+            subjects.message(&row("announce", "bgp")).unwrap().unwrap();
+        assert_eq!(message.subject, "rotonda.bgp.announce");
+        let payload: serde_json::Value =
+            serde_json::from_slice(&message.payload).unwrap();
+        assert_eq!(payload["prefix"], "198.51.100.0/24");
+
+        let message =
+            subjects.message(&row("log", "my topic")).unwrap().unwrap();
+        assert_eq!(message.subject, "rotonda.my_topic.log");
+        assert_eq!(subjects.message(&row("log", "")), Some(None));
+        assert_eq!(subjects.message(&row("withdraw", "bgp")), None);
+    }
+
+    #[test]
+    fn only_unacknowledged_messages_are_retried() {
+        assert!(is_retried(&PublishErrorKind::TimedOut.into()));
+        assert!(is_retried(&PublishErrorKind::BrokenPipe.into()));
+        assert!(!is_retried(&PublishErrorKind::StreamNotFound.into()));
+        assert!(!is_retried(&PublishErrorKind::Other.into()));
+    }
+}
@@ -1,38 +1,76 @@
 //! Null target.
+//!
+//! The `null-out` target discards everything it receives. By default it
+//! suspends its links so that upstream units don't spend any effort on
+//! sending it updates.
+//!
+//! With `measure = true` it instead receives and counts all updates, so
+//! that the throughput of ingest and filtering can be benchmarked without
+//! a real sink getting in the way. Besides the number of updates and
+//! payloads received, it keeps a histogram of the latency of payloads,
+//! from the moment they were received by Rotonda until they reach the
+//! target. With `max_rate` it takes no more than that many payloads per
+//! second, to find out how the pipeline copes with a slow sink.
 
+use std::{
+    num::NonZeroU32,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::{stream, Stream, StreamExt};
+use log::debug;
 use non_empty_vec::NonEmpty;
 use serde::Deserialize;
 use serde_with::{formats::PreferOne, serde_as, OneOrMany};
-use tokio::sync::mpsc;
+use tokio::{sync::mpsc, time::Instant};
 
-use crate::comms::Terminated;
+use crate::comms::{GraphStatus, Terminated, UnitStatus};
 use crate::manager::{Component, WaitPoint};
+use crate::metrics::{self, Metric, MetricType, MetricUnit};
+use crate::payload::Update;
 use crate::{comms::Link, manager::TargetCommand};
 
+/// The upper bounds of the latency histogram buckets in microseconds.
+const LATENCY_BUCKETS: [u64; 7] =
+    [10, 100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+
 #[serde_as]
 #[derive(Debug, Deserialize)]
 pub struct Target {
-    #[allow(dead_code)]
     #[serde(alias = "source")]
     #[serde_as(deserialize_as = "OneOrMany<_, PreferOne>")]
     sources: Vec<Link>,
+
+    /// Whether to receive and count updates rather than suspend the links.
+    #[serde(default)]
+    measure: bool,
+
+    /// The maximum number of payloads per second to take when measuring.
+    #[serde(default)]
+    max_rate: Option<NonZeroU32>,
 }
 
 impl Target {
     /// Runs the target.
     pub async fn run(
         mut self,
-        _: Component,
+        mut component: Component,
         mut cmd_rx: mpsc::Receiver<TargetCommand>,
         waitpoint: WaitPoint,
     ) -> Result<(), Terminated> {
-        // Prevent wasted time and effort on sending us updates that we won't
-        // use by suspending our link to upstream units. Don't just exit the
-        // function because that will cause the upstream units to terminate if
-        // we are their only downstream.
-        for source in &mut self.sources {
-            source.suspend().await;
-        }
+        let metrics = Arc::new(NullMetrics::default());
+        component.register_metrics(metrics.clone());
+
+        // Unless measuring, prevent wasted time and effort on sending us
+        // updates that we won't use by suspending our link to upstream
+        // units. Don't just exit the function because that will cause the
+        // upstream units to terminate if we are their only downstream.
+        let mut updates = self.updates().await;
 
         // Wait for other components to be, and signal to other components
         // that we are, ready to start. All units and targets start together,
@@ -40,36 +78,274 @@ impl Target {
         // the receiving component is not yet ready to accept it.
         waitpoint.running().await;
 
-        while let Some(cmd) = cmd_rx.recv().await {
-            match cmd {
-                TargetCommand::Reconfigure { new_config } => {
-                    if let crate::targets::Target::Null(Target {
-                        sources: new_sources,
-                        ..
-                        // config
-                    }) = new_config
-                    {
-                        self.sources = new_sources;
-                        for source in &mut self.sources {
-                            source.suspend().await;
+        let mut pacer = self.max_rate.map(Pacer::new);
+        let mut resume = None;
+        let mut tick = tokio::time::interval(Duration::from_secs(1));
+        let mut last_payload_count = 0;
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { new_config }) => {
+                        if let crate::targets::Target::Null(new_config) =
+                            new_config
+                        {
+                            self = new_config;
+                            updates = self.updates().await;
+                            pacer = self.max_rate.map(Pacer::new);
                         }
                     }
-                }
 
-                TargetCommand::ReportLinks { report } => {
-                    if let Ok(non_empty) =
-                        NonEmpty::try_from(self.sources.clone())
-                    {
-                        report.set_sources(&non_empty);
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        if let Ok(non_empty) =
+                            NonEmpty::try_from(self.sources.clone())
+                        {
+                            report.set_sources(&non_empty);
+                        }
+                        if self.measure {
+                            report.set_graph_status(metrics.clone());
+                        }
                     }
+
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = updates.next(), if resume.is_none() => {
+                    match update {
+                        Some(update) => {
+                            let count = metrics.record(&update);
+                            resume = pacer
+                                .as_mut()
+                                .and_then(|pacer| pacer.pace(count));
+                        }
+                        None => {
+                            debug!("Sources of null target are gone");
+                            updates = Box::pin(stream::pending());
+                        }
+                    }
+                }
+
+                _ = tokio::time::sleep_until(resume.unwrap_or_else(
+                    Instant::now
+                )), if resume.is_some() => {
+                    resume = None;
                 }
 
-                TargetCommand::Terminate => {
-                    break;
+                _ = tick.tick(), if self.measure => {
+                    let payload_count = metrics.payload_count.load(SeqCst);
+                    metrics.payload_rate.store(
+                        payload_count - last_payload_count,
+                        SeqCst,
+                    );
+                    last_payload_count = payload_count;
                 }
             }
         }
 
         Err(Terminated)
     }
+
+    /// Returns the updates to measure.
+    ///
+    /// When not measuring, the links are suspended and there are none.
+    async fn updates(
+        &mut self,
+    ) -> Pin<Box<dyn Stream<Item = Update> + Send>> {
+        if !self.measure {
+            for source in &mut self.sources {
+                source.suspend().await;
+            }
+            return Box::pin(stream::pending());
+        }
+        let updates = self.sources.iter().cloned().map(|link| {
+            Box::pin(stream::unfold(link, |mut link| async move {
+                loop {
+                    match link.query().await {
+                        Ok(update) => return Some((update, link)),
+                        Err(UnitStatus::Gone) => return None,
+                        Err(_) => {}
+                    }
+                }
+            }))
+        });
+        Box::pin(stream::select_all(updates))
+    }
+}
+
+//------------ Pacer ---------------------------------------------------------
+
+/// Limits the rate at which payloads are taken.
+#[derive(Debug)]
+struct Pacer {
+    /// The time one payload takes.
+    interval: Duration,
+
+    /// The time at which the payloads taken so far are due.
+    due: Instant,
+}
+
+impl Pacer {
+    fn new(max_rate: NonZeroU32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / max_rate.get(),
+            due: Instant::now(),
+        }
+    }
+
+    /// Takes `count` payloads, returning until when to wait for the next.
+    ///
+    /// Idle time isn't made up for with a burst of payloads later.
+    fn pace(&mut self, count: usize) -> Option<Instant> {
+        let now = Instant::now();
+        let count = u32::try_from(count).unwrap_or(u32::MAX);
+        self.due = self.due.max(now) + self.interval.saturating_mul(count);
+        (self.due > now).then_some(self.due)
+    }
+}
+
+//------------ NullMetrics ---------------------------------------------------
+
+#[derive(Debug, Default)]
+struct NullMetrics {
+    update_count: AtomicUsize,
+    payload_count: AtomicUsize,
+
+    /// The number of payloads received during the last second.
+    payload_rate: AtomicUsize,
+
+    /// The number of payloads by latency, per bucket of [`LATENCY_BUCKETS`]
+    /// and a last one for those exceeding all.
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+
+    /// The sum of all latencies in microseconds.
+    latency_sum: AtomicU64,
+}
+
+impl NullMetrics {
+    const UPDATE_COUNT_METRIC: Metric = Metric::new(
+        "null_target_update_count",
+        "the number of updates received by the null target",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const PAYLOAD_COUNT_METRIC: Metric = Metric::new(
+        "null_target_payload_count",
+        "the number of payloads received by the null target",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const LATENCY_METRIC: Metric = Metric::new(
+        "null_target_payload_latency",
+        "a histogram of the time from receiving payloads until they reach \
+        the null target",
+        MetricType::Histogram,
+        MetricUnit::Microsecond,
+    );
+
+    /// Records an update, returning the number of payloads in it.
+    fn record(&self, update: &Update) -> usize {
+        let now = std::time::Instant::now();
+        let payloads = match update {
+            Update::Single(payload) => std::slice::from_ref(payload),
+            Update::Bulk(payloads) => payloads.as_slice(),
+            _ => &[],
+        };
+        for payload in payloads {
+            let latency = now.saturating_duration_since(payload.received);
+            self.record_latency(latency);
+        }
+        self.update_count.fetch_add(1, SeqCst);
+        self.payload_count.fetch_add(payloads.len(), SeqCst);
+        payloads.len()
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency_buckets[bucket].fetch_add(1, SeqCst);
+        self.latency_sum.fetch_add(micros, SeqCst);
+    }
+}
+
+impl GraphStatus for NullMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "in: {}\nrate: {}/s",
+            self.payload_count.load(SeqCst),
+            self.payload_rate.load(SeqCst)
+        )
+    }
+}
+
+impl metrics::Source for NullMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::UPDATE_COUNT_METRIC,
+            Some(unit_name),
+            self.update_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::PAYLOAD_COUNT_METRIC,
+            Some(unit_name),
+            self.payload_count.load(SeqCst),
+        );
+        target.append(&Self::LATENCY_METRIC, Some(unit_name), |records| {
+            let bounds = LATENCY_BUCKETS
+                .iter()
+                .map(ToString::to_string)
+                .chain(["+Inf".to_string()]);
+            let mut count = 0;
+            for (bound, bucket) in bounds.zip(&self.latency_buckets) {
+                count += bucket.load(SeqCst);
+                records.suffixed_label_value(
+                    &[("le", &bound)],
+                    count,
+                    Some("bucket"),
+                );
+            }
+            records
+                .suffixed_value(self.latency_sum.load(SeqCst), Some("sum"));
+            records.suffixed_value(count, Some("count"));
+        });
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn pacing_limits_rate() {
+        let mut pacer = Pacer::new(NonZeroU32::new(10).unwrap());
+        let start = Instant::now();
+        assert_eq!(pacer.pace(5), Some(start + Duration::from_millis(500)));
+        assert_eq!(pacer.pace(5), Some(start + Duration::from_secs(1)));
+
+        // Idle time doesn't allow for a burst.
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(
+            pacer.pace(1),
+            Some(start + Duration::from_millis(10_100))
+        );
+    }
+
+    #[test]
+    fn latency_is_bucketed() {
+        let metrics = NullMetrics::default();
+        metrics.record_latency(Duration::from_micros(5));
+        metrics.record_latency(Duration::from_micros(10));
+        metrics.record_latency(Duration::from_millis(50));
+        metrics.record_latency(Duration::from_secs(60));
+        let buckets = metrics
+            .latency_buckets
+            .iter()
+            .map(|bucket| bucket.load(SeqCst))
+            .collect::<Vec<_>>();
+        assert_eq!(buckets, [2, 0, 0, 0, 1, 0, 0, 1]);
+        assert_eq!(metrics.latency_sum.load(SeqCst), 60_050_015);
+    }
 }
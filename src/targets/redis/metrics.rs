@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct RedisMetrics {
+    pub connected: AtomicBool,
+    pub command_count: AtomicUsize,
+    pub command_error_count: AtomicUsize,
+    pub dropped_count: AtomicUsize,
+    pub connection_error_count: AtomicUsize,
+    pub pending_batches: AtomicUsize,
+}
+
+impl GraphStatus for RedisMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "commands: {}\npending: {}\ndropped: {}",
+            self.command_count.load(SeqCst),
+            self.pending_batches.load(SeqCst),
+            self.dropped_count.load(SeqCst),
+        )
+    }
+
+    fn okay(&self) -> Option<bool> {
+        Some(self.connected.load(SeqCst))
+    }
+}
+
+impl RedisMetrics {
+    const CONNECTED_METRIC: Metric = Metric::new(
+        "redis_target_connected",
+        "whether commands can be sent to the Redis server: 0=no, 1=yes",
+        MetricType::Gauge,
+        MetricUnit::State,
+    );
+    const COMMAND_COUNT_METRIC: Metric = Metric::new(
+        "redis_target_command_count",
+        "the number of commands sent to the Redis server",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const COMMAND_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "redis_target_command_error_count",
+        "the number of commands the Redis server replied to with an error",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DROPPED_COUNT_METRIC: Metric = Metric::new(
+        "redis_target_dropped_count",
+        "the number of commands dropped because too many were pending",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const CONNECTION_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "redis_target_connection_error_count",
+        "the number of times connecting or sending to the server failed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const PENDING_BATCHES_METRIC: Metric = Metric::new(
+        "redis_target_pending_batches",
+        "the number of batches of commands waiting to be sent",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for RedisMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::CONNECTED_METRIC,
+            Some(unit_name),
+            u8::from(self.connected.load(SeqCst)),
+        );
+        target.append_simple(
+            &Self::COMMAND_COUNT_METRIC,
+            Some(unit_name),
+            self.command_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::COMMAND_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.command_error_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DROPPED_COUNT_METRIC,
+            Some(unit_name),
+            self.dropped_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::CONNECTION_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.connection_error_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::PENDING_BATCHES_METRIC,
+            Some(unit_name),
+            self.pending_batches.load(SeqCst),
+        );
+    }
+}
@@ -0,0 +1,627 @@
+//! Publishing updates to Redis.
+//!
+//! The `redis-out` target turns each route and event it receives into a row
+//! with the columns described in the [`row`] module, encoded as a JSON
+//! object holding all columns, and writes it to the Redis server at `url`
+//! in one or both of two ways:
+//!
+//! * With a `channel`, each row is published on that channel, so that
+//!   consumers can simply subscribe to it.
+//! * With a `key`, the current state of each prefix is kept in a key: an
+//!   announced route is set as the value of the key, and a withdrawn route
+//!   deletes it. If `key_ttl_secs` is given, keys expire unless their route
+//!   is announced again within that time. As keys are not deleted when a
+//!   session goes down, this is useful to get rid of stale routes.
+//!
+//! Both `channel` and `key` may contain the columns as placeholders, as
+//! described in the [`template`] module, e.g. `"rotonda:{{kind}}"` or
+//! `"route:{{prefix}}"`. Only the rows of the `kinds` and `topics` listed,
+//! if any, are written.
+//!
+//! The commands are collected into batches of up to `batch_size` commands,
+//! each batch being sent after at most `batch_interval_secs` as a single
+//! pipeline with the [redis] crate. If the connection fails, connecting is
+//! retried with an exponential backoff and the batch is sent again, so a
+//! command may be carried out twice. Meanwhile, up to `max_pending_batches`
+//! batches wait for their turn; commands beyond that are dropped.
+//!
+//! [redis]: https://docs.rs/redis/
+//! [`row`]: crate::targets::file::row
+//! [`template`]: crate::targets::http::template
+
+use std::{
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::Duration,
+};
+
+use log::{debug, error, info, warn};
+use redis::{aio::MultiplexedConnection, RedisError};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::sync::mpsc;
+use url::Url;
+
+use crate::{
+    comms::{Link, Terminated, UnitStatus},
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    targets::{
+        file::row::{Row, COLUMNS},
+        http::template::Template,
+    },
+};
+
+use super::metrics::RedisMetrics;
+
+/// How long to keep trying to send the pending batches when stopping.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A command and its arguments.
+type Command = redis::Cmd;
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct Redis {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The server to write to, a `redis://` URL.
+    url: Url,
+
+    /// The channel to publish rows on.
+    #[serde(default)]
+    channel: Option<String>,
+
+    /// The key to keep the current route of a prefix in.
+    #[serde(default)]
+    key: Option<String>,
+
+    /// How long keys live unless their route is announced again.
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    #[serde(default)]
+    key_ttl_secs: Option<Duration>,
+
+    /// The kinds of rows to write, all if not given.
+    #[serde(default)]
+    kinds: Option<Vec<String>>,
+
+    /// The topics of rows to write, all if not given.
+    #[serde(default)]
+    topics: Option<Vec<String>>,
+
+    /// The most commands to send in one pipeline.
+    #[serde(default = "Config::default_batch_size")]
+    batch_size: usize,
+
+    /// The longest to wait before sending the commands collected.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_batch_interval_secs")]
+    batch_interval_secs: Duration,
+
+    /// How many batches may wait to be sent.
+    #[serde(default = "Config::default_max_pending_batches")]
+    max_pending_batches: usize,
+
+    /// How long to wait before connecting again. The delay doubles with
+    /// every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_retry_delay_secs")]
+    retry_delay_secs: Duration,
+
+    /// The longest to wait before connecting again.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_max_retry_delay_secs")]
+    max_retry_delay_secs: Duration,
+}
+
+impl Config {
+    fn default_batch_size() -> usize {
+        500
+    }
+
+    fn default_batch_interval_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_pending_batches() -> usize {
+        16
+    }
+
+    fn default_retry_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retry_delay_secs() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.url.scheme() != "redis" {
+            return Err(format!(
+                "unsupported URL scheme '{}', use redis",
+                self.url.scheme()
+            ));
+        }
+        if self.channel.is_none() && self.key.is_none() {
+            return Err("at least one of channel and key must be set".into());
+        }
+        if self.key_ttl_secs.is_some_and(|ttl| ttl.is_zero()) {
+            return Err("key_ttl_secs must be at least 1".into());
+        }
+        if self.batch_size == 0 || self.max_pending_batches == 0 {
+            return Err(
+                "batch_size and max_pending_batches must be at least 1"
+                    .into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Redis {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let config = self.config;
+        let commands =
+            match config.check().and_then(|_| Commands::new(&config)) {
+                Ok(commands) => commands,
+                Err(err) => {
+                    error!("Target {}: {err}", component.name());
+                    return Err(Terminated);
+                }
+            };
+
+        let metrics = Arc::new(RedisMetrics::default());
+        component.register_metrics(metrics.clone());
+        let sender = Sender {
+            name: component.name().to_string(),
+            url: config.url,
+            retry_delay: config.retry_delay_secs,
+            max_retry_delay: config.max_retry_delay_secs,
+            metrics: metrics.clone(),
+        };
+        RedisRunner {
+            commands,
+            batch: Vec::new(),
+            batch_size: config.batch_size,
+            batch_interval: config.batch_interval_secs,
+            max_pending_batches: config.max_pending_batches,
+            tx: None,
+            ingresses: component.ingresses().clone(),
+            metrics,
+        }
+        .run(sender, self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ RedisRunner ---------------------------------------------------
+
+struct RedisRunner {
+    commands: Commands,
+    batch: Vec<Command>,
+    batch_size: usize,
+    batch_interval: Duration,
+    max_pending_batches: usize,
+    tx: Option<mpsc::Sender<Vec<Command>>>,
+    ingresses: Arc<ingress::Register>,
+    metrics: Arc<RedisMetrics>,
+}
+
+impl RedisRunner {
+    async fn run(
+        mut self,
+        sender: Sender,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        // Batches are sent by a task of their own, so that an unreachable
+        // server does not hold up the sources.
+        let (tx, rx) = mpsc::channel(self.max_pending_batches);
+        self.tx = Some(tx);
+        let send_task = tokio::spawn(sender.run(rx));
+
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        let mut flush = tokio::time::interval(self.batch_interval);
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the redis-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        for row in Row::for_update(update, &self.ingresses) {
+                            self.push(&row);
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of redis-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+
+                _ = flush.tick() => self.flush(),
+            }
+        }
+
+        // Send what is left before stopping.
+        self.flush();
+        self.tx = None;
+        if tokio::time::timeout(DRAIN_TIMEOUT, send_task)
+            .await
+            .is_err()
+        {
+            warn!(
+                "Dropping {} batches of Redis commands that could not be sent",
+                self.metrics.pending_batches.load(SeqCst)
+            );
+        }
+        Err(Terminated)
+    }
+
+    /// Adds the commands for a row, sending the batch once it is full.
+    fn push(&mut self, row: &Row) {
+        self.commands.push(row, &mut self.batch);
+        if self.batch.len() >= self.batch_size {
+            self.flush();
+        }
+    }
+
+    /// Hands the collected commands to the sender.
+    fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let batch = std::mem::take(&mut self.batch);
+        let len = batch.len();
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        if tx.try_send(batch).is_ok() {
+            self.metrics.pending_batches.fetch_add(1, SeqCst);
+        } else {
+            self.metrics.dropped_count.fetch_add(len, SeqCst);
+        }
+    }
+}
+
+//------------ Commands ------------------------------------------------------
+
+/// Turns rows into commands.
+struct Commands {
+    kinds: Option<Vec<String>>,
+    topics: Option<Vec<String>>,
+    channel: Option<Template>,
+    key: Option<Template>,
+
+    /// The lifetime of keys, in seconds.
+    key_ttl: Option<String>,
+}
+
+impl Commands {
+    fn new(config: &Config) -> Result<Self, String> {
+        let columns = COLUMNS.map(|column| column.name);
+        let template = |text: &Option<String>, what: &str| {
+            text.as_ref()
+                .map(|text| {
+                    Template::new(text.as_str().into(), &columns)
+                        .map_err(|err| format!("{what}: {err}"))
+                })
+                .transpose()
+        };
+        Ok(Self {
+            kinds: config.kinds.clone(),
+            topics: config.topics.clone(),
+            channel: template(&config.channel, "channel")?,
+            key: template(&config.key, "key")?,
+            key_ttl: config.key_ttl_secs.map(|ttl| ttl.as_secs().to_string()),
+        })
+    }
+
+    /// Appends the commands for a row if it is wanted.
+    fn push(&self, row: &Row, res: &mut Vec<Command>) {
+        if !self.wants(row) {
+            return;
+        }
+        let lookup = |name: &str| row.json_value(name);
+        let value = || {
+            let object = COLUMNS
+                .iter()
+                .map(|column| (column.name.to_string(), lookup(column.name)))
+                .collect::<serde_json::Map<_, _>>();
+            serde_json::Value::from(object).to_string()
+        };
+        if let Some(key) = &self.key {
+            if row.prefix.is_some() {
+                let key = key.render_text(&lookup);
+                if row.kind == "withdraw" {
+                    res.push(redis::cmd("DEL").arg(key).to_owned());
+                } else if matches!(row.kind, "announce" | "route") {
+                    let mut command = redis::cmd("SET");
+                    command.arg(key).arg(value());
+                    if let Some(ttl) = &self.key_ttl {
+                        command.arg("EX").arg(ttl);
+                    }
+                    res.push(command);
+                }
+            }
+        }
+        if let Some(channel) = &self.channel {
+            res.push(
+                redis::cmd("PUBLISH")
+                    .arg(channel.render_text(&lookup))
+                    .arg(value())
+                    .to_owned(),
+            );
+        }
+    }
+
+    fn wants(&self, row: &Row) -> bool {
+        self.kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.iter().any(|kind| kind == row.kind))
+            && self
+                .topics
+                .as_ref()
+                .is_none_or(|topics| topics.contains(&row.topic))
+    }
+}
+
+//------------ Sender --------------------------------------------------------
+
+/// Sends batches of commands, connecting to the server as needed.
+struct Sender {
+    name: String,
+    url: Url,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    metrics: Arc<RedisMetrics>,
+}
+
+impl Sender {
+    /// Sends batches until there are no more.
+    async fn run(self, mut rx: mpsc::Receiver<Vec<Command>>) {
+        let mut connection: Option<MultiplexedConnection> = None;
+        let mut delay = self.retry_delay;
+        while let Some(batch) = rx.recv().await {
+            let mut pipeline = redis::pipe();
+            for command in &batch {
+                pipeline.add_command(command.clone());
+            }
+            loop {
+                let conn = match &mut connection {
+                    Some(conn) => conn,
+                    None => match self.connect().await {
+                        Ok(conn) => {
+                            info!(
+                                "Target {}: connected to {}",
+                                self.name,
+                                self.display_url()
+                            );
+                            self.metrics.connected.store(true, SeqCst);
+                            delay = self.retry_delay;
+                            connection.insert(conn)
+                        }
+                        Err(err) => {
+                            self.failed("connecting", err);
+                            tokio::time::sleep(delay).await;
+                            delay = (delay * 2).min(self.max_retry_delay);
+                            continue;
+                        }
+                    },
+                };
+                let res =
+                    pipeline.query_async::<Vec<redis::Value>>(conn).await;
+                match res {
+                    Err(err) if is_connection_error(&err) => {
+                        connection = None;
+                        self.failed("sending", err);
+                        continue;
+                    }
+                    Ok(replies) => self.replied(&replies),
+                    Err(err) => {
+                        self.metrics
+                            .command_error_count
+                            .fetch_add(batch.len(), SeqCst);
+                        warn!(
+                            "Target {}: {} commands failed with: {err}",
+                            self.name,
+                            batch.len()
+                        );
+                    }
+                }
+                self.metrics.pending_batches.fetch_sub(1, SeqCst);
+                self.metrics.command_count.fetch_add(batch.len(), SeqCst);
+                break;
+            }
+        }
+    }
+
+    async fn connect(&self) -> Result<MultiplexedConnection, RedisError> {
+        redis::Client::open(self.url.as_str())?
+            .get_multiplexed_async_connection()
+            .await
+    }
+
+    /// Counts the error replies, logging the first of them.
+    fn replied(&self, replies: &[redis::Value]) {
+        let mut errors = replies.iter().filter_map(|reply| match reply {
+            redis::Value::ServerError(err) => Some(err),
+            _ => None,
+        });
+        if let Some(err) = errors.next() {
+            let count = errors.count() + 1;
+            self.metrics.command_error_count.fetch_add(count, SeqCst);
+            warn!(
+                "Target {}: {count} commands failed, e.g. with: {err:?}",
+                self.name
+            );
+        }
+    }
+
+    fn failed(&self, action: &str, err: RedisError) {
+        self.metrics.connected.store(false, SeqCst);
+        self.metrics.connection_error_count.fetch_add(1, SeqCst);
+        warn!(
+            "Target {}: {action} to {} failed: {err}",
+            self.name,
+            self.display_url()
+        );
+    }
+
+    /// Returns the URL without the password.
+    fn display_url(&self) -> Url {
+        let mut url = self.url.clone();
+        let _ = url.set_password(None);
+        url
+    }
+}
+
+/// Returns whether an error means the connection was lost, so that the
+/// batch needs to be sent again over a new one.
+fn is_connection_error(err: &RedisError) -> bool {
+    err.is_io_error()
+        || err.is_connection_dropped()
+        || err.is_connection_refusal()
+        || err.is_timeout()
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mk_commands(config: &str) -> Commands {
+        let config: Config = toml::from_str(config).unwrap();
+        config.check().unwrap();
+        Commands::new(&config).unwrap()
+    }
+
+    fn route(kind: &'static str) -> Row {
+        Row {
+            topic: "bgp".into(),
+            kind,
+            prefix: Some("198.51.100.0/24".into()),
+            peer_ip: Some("192.0.2.1".into()),
+            ..Default::default()
+        }
+    }
+
+    fn strings(commands: &[Command]) -> Vec<Vec<String>> {
+        commands
+            .iter()
+            .map(|command| {
+                command
+                    .args_iter()
+                    .map(|arg| match arg {
+                        redis::Arg::Simple(arg) => {
+                            String::from_utf8_lossy(arg).into_owned()
+                        }
+                        redis::Arg::Cursor => panic!("unexpected cursor"),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn config_is_checked() {
+        let config = |s: &str| {
+            toml::from_str::<Config>(s)
+                .map_err(|err| err.to_string())
+                .and_then(|config| {
+                    config.check()?;
+                    Commands::new(&config).map(|_| ())
+                })
+        };
+        assert!(
+            config("url = \"redis://localhost\"\nchannel = \"r\"").is_ok()
+        );
+        assert!(config("url = \"redis://localhost\"").is_err());
+        assert!(config("url = \"http://localhost\"\nkey = \"k\"").is_err());
+        assert!(config("url = \"redis://localhost\"\nkey = \"r:{{nope}}\"")
+            .is_err());
+        assert!(config(
+            "url = \"redis://localhost\"\nkey = \"k\"\nkey_ttl_secs = 0"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rows_become_commands() {
+        let commands = mk_commands(
+            r#"
+            url = "redis://localhost"
+            channel = "rotonda:{{kind}}"
+            key = "route:{{prefix}}"
+            key_ttl_secs = 600
+            kinds = ["announce", "withdraw", "peer_down"]
+            "#,
+        );
+        let mut res = Vec::new();
+        commands.push(&route("announce"), &mut res);
+        commands.push(&route("withdraw"), &mut res);
+        commands.push(&route("log"), &mut res);
+        commands.push(
+            &Row {
+                kind: "peer_down",
+                ..Default::default()
+            },
+            &mut res,
+        );
+        let res = strings(&res);
+        assert_eq!(res.len(), 5);
+        assert_eq!(res[0][..2], ["SET", "route:198.51.100.0/24"]);
+        assert_eq!(res[0][3..], ["EX", "600"]);
+        let value: serde_json::Value =
+            serde_json::from_str(&res[0][2]).unwrap();
+        assert_eq!(value["kind"], "announce");
+        assert_eq!(value["peer_ip"], "192.0.2.1");
+        assert_eq!(res[1][..2], ["PUBLISH", "rotonda:announce"]);
+        assert_eq!(res[1][2], res[0][2]);
+        assert_eq!(res[2], ["DEL", "route:198.51.100.0/24"]);
+        assert_eq!(res[3][..2], ["PUBLISH", "rotonda:withdraw"]);
+        assert_eq!(res[4][..2], ["PUBLISH", "rotonda:peer_down"]);
+    }
+
+    #[test]
+    fn only_lost_connections_are_retried() {
+        let err = |kind| RedisError::from(std::io::Error::from(kind));
+        assert!(is_connection_error(&err(std::io::ErrorKind::BrokenPipe)));
+        assert!(is_connection_error(&err(
+            std::io::ErrorKind::ConnectionRefused
+        )));
+        assert!(!is_connection_error(&RedisError::from((
+            redis::ErrorKind::ResponseError,
+            "wrong number of arguments"
+        ))));
+    }
+}
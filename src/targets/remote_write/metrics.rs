@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct RemoteWriteMetrics {
+    pub push_count: AtomicUsize,
+    pub push_error_count: AtomicUsize,
+    pub dropped_push_count: AtomicUsize,
+    pub series_count: AtomicUsize,
+
+    /// Whether the last push failed.
+    pub failing: AtomicBool,
+}
+
+impl GraphStatus for RemoteWriteMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "pushes: {}\nseries: {}\ndropped: {}",
+            self.push_count.load(SeqCst),
+            self.series_count.load(SeqCst),
+            self.dropped_push_count.load(SeqCst),
+        )
+    }
+
+    fn okay(&self) -> Option<bool> {
+        Some(!self.failing.load(SeqCst))
+    }
+}
+
+impl RemoteWriteMetrics {
+    const PUSH_COUNT_METRIC: Metric = Metric::new(
+        "remote_write_target_push_count",
+        "the number of successful pushes to the receiver",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const PUSH_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "remote_write_target_push_error_count",
+        "the number of failed requests to the receiver, including retries",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DROPPED_PUSH_COUNT_METRIC: Metric = Metric::new(
+        "remote_write_target_dropped_push_count",
+        "the number of pushes given up on",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const SERIES_COUNT_METRIC: Metric = Metric::new(
+        "remote_write_target_series_count",
+        "the number of series in the last push",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for RemoteWriteMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::PUSH_COUNT_METRIC,
+            Some(unit_name),
+            self.push_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::PUSH_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.push_error_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DROPPED_PUSH_COUNT_METRIC,
+            Some(unit_name),
+            self.dropped_push_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::SERIES_COUNT_METRIC,
+            Some(unit_name),
+            self.series_count.load(SeqCst),
+        );
+    }
+}
@@ -0,0 +1,3 @@
+mod metrics;
+mod series;
+pub mod target;
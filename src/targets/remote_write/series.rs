@@ -0,0 +1,369 @@
+//! The time series pushed via remote write.
+//!
+//! The series derived from the rows the target receives are:
+//!
+//! * `rotonda_routes_announced_total` and `rotonda_routes_withdrawn_total`,
+//!   the routes received per peer, labelled with `peer_ip` and `peer_as`,
+//! * `rotonda_peer_down_total`, the sessions that went down per peer,
+//! * `rotonda_roto_messages_total`, the messages the roto filters emitted
+//!   per `kind` and `topic`, and
+//! * `rotonda_roto_custom_total` and `rotonda_roto_custom_value_total`, the
+//!   number and the sum of the values of the custom entries the filters
+//!   logged, per `topic` and `id`. These allow filters to define counters
+//!   of their own.
+//!
+//! In addition, the metrics of all components can be included by parsing
+//! them from the Prometheus exposition format.
+
+use std::collections::HashMap;
+
+use prost::Message;
+use serde::Deserialize;
+
+use crate::targets::file::row::Row;
+
+//------------ Series --------------------------------------------------------
+
+/// A single sample of a time series.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Series {
+    /// The labels, sorted by name, including the metric name as `__name__`.
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+impl Series {
+    /// Creates a series, leaving out labels with empty values.
+    pub fn new(name: &str, labels: &[(&str, &str)], value: f64) -> Self {
+        let mut res = Self {
+            labels: vec![("__name__".into(), name.into())],
+            value,
+        };
+        res.add_labels(
+            labels
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        );
+        res
+    }
+
+    /// Adds labels, keeping existing labels of the same name.
+    pub fn add_labels(
+        &mut self,
+        labels: impl IntoIterator<Item = (String, String)>,
+    ) {
+        for (name, value) in labels {
+            if value.is_empty()
+                || self.labels.iter().any(|(existing, _)| *existing == name)
+            {
+                continue;
+            }
+            self.labels.push((name, value));
+        }
+        self.labels.sort();
+    }
+}
+
+/// Returns the protobuf encoded `WriteRequest` for the series.
+///
+/// All samples get the same timestamp, in milliseconds.
+pub fn write_request(series: &[Series], timestamp: i64) -> Vec<u8> {
+    let timeseries = series
+        .iter()
+        .map(|series| TimeSeries {
+            labels: series
+                .labels
+                .iter()
+                .map(|(name, value)| Label {
+                    name: name.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+            samples: vec![Sample {
+                value: series.value,
+                timestamp,
+            }],
+        })
+        .collect();
+    WriteRequest { timeseries }.encode_to_vec()
+}
+
+/// The messages of the remote write protocol, as far as they are used.
+#[derive(Clone, PartialEq, Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Sample {
+    #[prost(double, tag = "1")]
+    value: f64,
+
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+//------------ Counters ------------------------------------------------------
+
+/// The counters derived from rows.
+#[derive(Debug, Default)]
+pub struct Counters {
+    /// The routes announced and withdrawn and the sessions gone down, by
+    /// peer IP and AS.
+    peers: HashMap<(String, String), [u64; 3]>,
+
+    /// The messages emitted by filters, by kind and topic.
+    messages: HashMap<(&'static str, String), u64>,
+
+    /// The number and sum of the custom entries, by topic and ID.
+    custom: HashMap<(String, u32), (u64, u64)>,
+}
+
+/// A custom entry as it appears in the `custom` column.
+#[derive(Deserialize)]
+struct CustomEntry {
+    id: u32,
+    value: u32,
+}
+
+impl Counters {
+    pub fn add(&mut self, row: &Row) {
+        let peer = || {
+            (
+                row.peer_ip.clone().unwrap_or_default(),
+                row.peer_as.map(|asn| asn.to_string()).unwrap_or_default(),
+            )
+        };
+        let index = match row.kind {
+            "announce" => 0,
+            "withdraw" => 1,
+            "peer_down" => 2,
+            kind => {
+                *self
+                    .messages
+                    .entry((kind, row.topic.clone()))
+                    .or_default() += 1;
+                if kind == "custom" {
+                    if let Some(entry) = row.custom.as_deref().and_then(|s| {
+                        serde_json::from_str::<CustomEntry>(s).ok()
+                    }) {
+                        let (count, sum) = self
+                            .custom
+                            .entry((row.topic.clone(), entry.id))
+                            .or_default();
+                        *count += 1;
+                        *sum += u64::from(entry.value);
+                    }
+                }
+                return;
+            }
+        };
+        self.peers.entry(peer()).or_default()[index] += 1;
+    }
+
+    pub fn series(&self) -> Vec<Series> {
+        const PEER_METRICS: [&str; 3] = [
+            "rotonda_routes_announced_total",
+            "rotonda_routes_withdrawn_total",
+            "rotonda_peer_down_total",
+        ];
+
+        let mut res = Vec::new();
+        for ((peer_ip, peer_as), counts) in &self.peers {
+            for (name, count) in PEER_METRICS.iter().zip(counts) {
+                res.push(Series::new(
+                    name,
+                    &[("peer_ip", peer_ip), ("peer_as", peer_as)],
+                    *count as f64,
+                ));
+            }
+        }
+        for ((kind, topic), count) in &self.messages {
+            res.push(Series::new(
+                "rotonda_roto_messages_total",
+                &[("kind", kind), ("topic", topic)],
+                *count as f64,
+            ));
+        }
+        for ((topic, id), (count, sum)) in &self.custom {
+            let id = id.to_string();
+            let labels = [("topic", topic.as_str()), ("id", id.as_str())];
+            res.push(Series::new(
+                "rotonda_roto_custom_total",
+                &labels,
+                *count as f64,
+            ));
+            res.push(Series::new(
+                "rotonda_roto_custom_value_total",
+                &labels,
+                *sum as f64,
+            ));
+        }
+        res
+    }
+}
+
+//------------ Parsing the exposition format ---------------------------------
+
+/// Returns the series in the Prometheus text exposition format.
+///
+/// Comments and lines that cannot be parsed are skipped.
+pub fn parse_exposition(text: &str) -> Vec<Series> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(parse_line)
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<Series> {
+    let line = line.trim();
+    let name_end = line.find(['{', ' '])?;
+    let name = &line[..name_end];
+    let mut rest = &line[name_end..];
+    let mut labels = Vec::new();
+    if let Some(mut inner) = rest.strip_prefix('{') {
+        loop {
+            inner = inner.trim_start_matches([',', ' ']);
+            if let Some(after) = inner.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (label, after) = inner.split_once("=\"")?;
+            let mut value = String::new();
+            let mut chars = after.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (_, '\\') => match chars.next()?.1 {
+                        'n' => value.push('\n'),
+                        c => value.push(c),
+                    },
+                    (index, '"') => break index,
+                    (_, c) => value.push(c),
+                }
+            };
+            labels.push((label.trim().to_string(), value));
+            inner = &after[end + 1..];
+        }
+    }
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    let mut res = Series::new(name, &[], value);
+    res.add_labels(labels);
+    Some(res)
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposition_is_parsed() {
+        let series = parse_exposition(
+            "# HELP rotonda_x some help\n\
+            # TYPE rotonda_x counter\n\
+            rotonda_x{component=\"rib\",path=\"a\\\"b\"} 12\n\
+            rotonda_up 1\n\
+            garbage\n",
+        );
+        assert_eq!(
+            series,
+            [
+                Series {
+                    labels: vec![
+                        ("__name__".into(), "rotonda_x".into()),
+                        ("component".into(), "rib".into()),
+                        ("path".into(), "a\"b".into()),
+                    ],
+                    value: 12.0,
+                },
+                Series::new("rotonda_up", &[], 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn rows_are_counted() {
+        let mut counters = Counters::default();
+        let peer = |kind| Row {
+            kind,
+            peer_ip: Some("192.0.2.1".into()),
+            peer_as: Some(65000),
+            ..Default::default()
+        };
+        counters.add(&peer("announce"));
+        counters.add(&peer("announce"));
+        counters.add(&peer("withdraw"));
+        for value in [3, 4] {
+            counters.add(&Row {
+                topic: "hijack".into(),
+                kind: "custom",
+                custom: Some(format!("{{\"id\":7,\"value\":{value}}}")),
+                ..Default::default()
+            });
+        }
+
+        let mut series = counters.series();
+        series.sort_by(|a, b| a.labels.cmp(&b.labels));
+        let values = series
+            .iter()
+            .map(|series| (series.labels[0].1.as_str(), series.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                ("rotonda_peer_down_total", 0.0),
+                ("rotonda_roto_custom_total", 2.0),
+                ("rotonda_roto_custom_value_total", 7.0),
+                ("rotonda_roto_messages_total", 2.0),
+                ("rotonda_routes_announced_total", 2.0),
+                ("rotonda_routes_withdrawn_total", 1.0),
+            ]
+        );
+        assert_eq!(
+            series[4].labels,
+            [
+                ("__name__".into(), "rotonda_routes_announced_total".into()),
+                ("peer_as".into(), "65000".into()),
+                ("peer_ip".into(), "192.0.2.1".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn write_requests_are_encoded() {
+        let series = [Series::new("up", &[("job", "r")], 1.0)];
+        assert_eq!(
+            write_request(&series, 1),
+            [
+                // timeseries
+                0x0a, 0x27, // label __name__="up"
+                0x0a, 0x0e, 0x0a, 0x08, b'_', b'_', b'n', b'a', b'm', b'e',
+                b'_', b'_', 0x12, 0x02, b'u', b'p',
+                // label job="r"
+                0x0a, 0x08, 0x0a, 0x03, b'j', b'o', b'b', 0x12, 0x01, b'r',
+                // sample 1.0 at 1
+                0x12, 0x0b, 0x09, 0, 0, 0, 0, 0, 0, 0xf0, 0x3f, 0x10, 0x01,
+            ]
+        );
+    }
+}
@@ -0,0 +1,558 @@
+//! Pushing metrics via Prometheus remote write.
+//!
+//! The `remote-write-out` target counts the routes and events it receives
+//! into the series described in the [`series`] module and pushes them every
+//! `interval_secs` to a Prometheus remote write receiver at `url`, such as
+//! Mimir, Thanos or Prometheus itself. Unless `instance_metrics` is false,
+//! the metrics of all components are pushed as well, including the series
+//! the RIB maintains. This serves deployments where the receiver cannot
+//! scrape Rotonda, e.g. because it is behind NAT.
+//!
+//! All series get the `labels` of the target, e.g. to tell instances apart,
+//! and each request carries the `headers`, e.g. `X-Scope-OrgID` for Mimir
+//! or `Authorization`. A request holds at most `max_series_per_request`
+//! series. A failed request is tried again up to `max_retries` times with
+//! an exponential backoff, unless the receiver refused it with a status
+//! code other than 408 or 429; as the series are counters and gauges, the
+//! next push makes up for a push given up on. The receiver may be reached
+//! over HTTPS, with the `tls` table as for the `alert-out` target.
+//!
+//! [`series`]: super::series
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{atomic::Ordering::SeqCst, Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use chrono::Utc;
+use clap::crate_version;
+use log::{debug, error, info, warn};
+use reqwest::{
+    header::{HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE},
+    Client as HttpClient, HeaderMap, StatusCode,
+};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::MissedTickBehavior,
+};
+use url::Url;
+
+use crate::{
+    common::tls::TlsClientConfig,
+    comms::{Link, Terminated, UnitStatus},
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    metrics::{self, OutputFormat},
+    targets::file::row::Row,
+};
+
+use super::{
+    metrics::RemoteWriteMetrics,
+    series::{parse_exposition, write_request, Counters, Series},
+};
+
+/// The longest a single request may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct RemoteWrite {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The URL of the receiver, e.g. `http://mimir:9009/api/v1/push`.
+    pub url: Url,
+
+    #[serde(default)]
+    pub tls: Option<TlsClientConfig>,
+
+    /// Headers to send with each request.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// Labels to add to all series.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+
+    /// How often to push the series.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_interval_secs")]
+    pub interval_secs: Duration,
+
+    /// Whether to push the metrics of all components as well.
+    #[serde(default = "Config::default_instance_metrics")]
+    pub instance_metrics: bool,
+
+    #[serde(default = "Config::default_max_series_per_request")]
+    pub max_series_per_request: usize,
+
+    /// How often to try a failed request again.
+    #[serde(default = "Config::default_max_retries")]
+    pub max_retries: usize,
+
+    /// How long to wait before the first retry. The delay doubles with
+    /// every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_retry_delay_secs")]
+    pub retry_delay_secs: Duration,
+
+    /// The longest to wait before retrying.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_max_retry_delay_secs")]
+    pub max_retry_delay_secs: Duration,
+}
+
+impl Config {
+    fn default_interval_secs() -> Duration {
+        Duration::from_secs(15)
+    }
+
+    fn default_instance_metrics() -> bool {
+        true
+    }
+
+    fn default_max_series_per_request() -> usize {
+        2000
+    }
+
+    fn default_max_retries() -> usize {
+        2
+    }
+
+    fn default_retry_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retry_delay_secs() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    /// Returns the headers of each request.
+    fn headers(&self) -> Result<HeaderMap, String> {
+        let mut res = HeaderMap::new();
+        res.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-protobuf"),
+        );
+        res.insert(CONTENT_ENCODING, HeaderValue::from_static("snappy"));
+        res.insert(
+            "X-Prometheus-Remote-Write-Version",
+            HeaderValue::from_static("0.1.0"),
+        );
+        res.insert(
+            "User-Agent",
+            HeaderValue::from_static(concat!("rotonda/", crate_version!())),
+        );
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("invalid header name '{name}'"))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| format!("invalid value for header '{name}'"))?;
+            res.insert(name, value);
+        }
+        Ok(res)
+    }
+
+    /// Returns the client for the receiver, checking the configuration.
+    fn client(&self) -> Result<HttpClient, String> {
+        if !matches!(self.url.scheme(), "http" | "https") {
+            return Err(format!(
+                "unsupported URL scheme '{}', use http or https",
+                self.url.scheme()
+            ));
+        }
+        if self.url.host_str().is_none() {
+            return Err("missing host in URL".into());
+        }
+        if self.interval_secs.is_zero() {
+            return Err("interval_secs must be at least 1".into());
+        }
+        if let Some(name) = self.labels.keys().find(|name| !is_label(name)) {
+            return Err(format!("invalid label name '{name}'"));
+        }
+        self.tls
+            .clone()
+            .unwrap_or_default()
+            .http_client_builder()?
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Returns whether a label name is valid.
+fn is_label(name: &str) -> bool {
+    !name.starts_with("__")
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl RemoteWrite {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let config = self.config;
+        let (client, headers) = match config
+            .client()
+            .and_then(|client| Ok((client, config.headers()?)))
+        {
+            Ok(res) => res,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+
+        let metrics = Arc::new(RemoteWriteMetrics::default());
+        component.register_metrics(metrics.clone());
+        let counters = Arc::new(Mutex::new(Counters::default()));
+        let pusher = Pusher {
+            name: component.name().to_string(),
+            client,
+            url: config.url.clone(),
+            headers,
+            labels: config.labels.into_iter().collect(),
+            collection: config
+                .instance_metrics
+                .then(|| component.metrics().cloned())
+                .flatten(),
+            counters: counters.clone(),
+            max_series: config.max_series_per_request.max(1),
+            max_retries: config.max_retries,
+            retry_delay: config.retry_delay_secs,
+            max_retry_delay: config.max_retry_delay_secs,
+            metrics: metrics.clone(),
+        };
+
+        RemoteWriteRunner {
+            counters,
+            ingresses: component.ingresses().clone(),
+            metrics,
+        }
+        .run(pusher, config.interval_secs, self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ RemoteWriteRunner ---------------------------------------------
+
+struct RemoteWriteRunner {
+    counters: Arc<Mutex<Counters>>,
+    ingresses: Arc<ingress::Register>,
+    metrics: Arc<RemoteWriteMetrics>,
+}
+
+impl RemoteWriteRunner {
+    async fn run(
+        self,
+        pusher: Pusher,
+        interval: Duration,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        // Pushing happens in a task of its own, so that a slow receiver
+        // does not hold up the sources.
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let push_task = tokio::spawn(pusher.run(interval, stop_rx));
+
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the remote-write-out target \
+                            requires a restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        let rows = Row::for_update(update, &self.ingresses);
+                        let mut counters = self.counters.lock().unwrap();
+                        for row in &rows {
+                            counters.add(row);
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of remote-write-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+            }
+        }
+
+        // Push the final counts before stopping.
+        let _ = stop_tx.send(());
+        let _ = push_task.await;
+        Err(Terminated)
+    }
+}
+
+//------------ Pusher --------------------------------------------------------
+
+/// Pushes the series to the receiver.
+struct Pusher {
+    name: String,
+    client: HttpClient,
+    url: Url,
+    headers: HeaderMap,
+    labels: Vec<(String, String)>,
+
+    /// The metrics of all components, if they are to be pushed.
+    collection: Option<metrics::Collection>,
+
+    counters: Arc<Mutex<Counters>>,
+    max_series: usize,
+    max_retries: usize,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    metrics: Arc<RemoteWriteMetrics>,
+}
+
+impl Pusher {
+    /// Pushes every `interval` and once more when told to stop.
+    async fn run(self, interval: Duration, mut stop: oneshot::Receiver<()>) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        // The first tick is right away, when there is nothing to push yet.
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.push().await,
+                _ = &mut stop => {
+                    self.push().await;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Returns the current series.
+    fn series(&self) -> Vec<Series> {
+        let mut res = self.counters.lock().unwrap().series();
+        if let Some(collection) = &self.collection {
+            res.extend(parse_exposition(
+                &collection.assemble(OutputFormat::Prometheus),
+            ));
+        }
+        for series in &mut res {
+            series.add_labels(self.labels.iter().cloned());
+        }
+        res
+    }
+
+    async fn push(&self) {
+        let series = self.series();
+        self.metrics.series_count.store(series.len(), SeqCst);
+        let timestamp = Utc::now().timestamp_millis();
+        for chunk in series.chunks(self.max_series) {
+            // Compressing only fails for more than 4 GiB of data.
+            let body = snap::raw::Encoder::new()
+                .compress_vec(&write_request(chunk, timestamp))
+                .unwrap();
+            let sent = self.send_with_retries(body.into()).await;
+            self.metrics.failing.store(!sent, SeqCst);
+            if sent {
+                self.metrics.push_count.fetch_add(1, SeqCst);
+            } else {
+                self.metrics.dropped_push_count.fetch_add(1, SeqCst);
+            }
+        }
+    }
+
+    /// Sends a request, returning whether that succeeded.
+    async fn send_with_retries(&self, body: Bytes) -> bool {
+        let mut delay = self.retry_delay;
+        for attempt in 0..=self.max_retries {
+            let request = self
+                .client
+                .post(self.url.clone())
+                .headers(self.headers.clone())
+                .body(body.clone());
+            // Requests refused by the receiver, other than for now, will be
+            // refused again.
+            let (message, retry) = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    return true
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let text = response.text().await.unwrap_or_default();
+                    (
+                        format!("{status}: {}", text.trim()),
+                        !status.is_client_error()
+                            || status == StatusCode::REQUEST_TIMEOUT
+                            || status == StatusCode::TOO_MANY_REQUESTS,
+                    )
+                }
+                Err(err) => (err.to_string(), true),
+            };
+            self.metrics.push_error_count.fetch_add(1, SeqCst);
+            warn!("Target {}: push failed: {message}", self.name);
+            if !retry {
+                break;
+            }
+            if attempt < self.max_retries {
+                info!(
+                    "Target {}: retrying in {}s",
+                    self.name,
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(self.max_retry_delay);
+            }
+        }
+        false
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::{
+        metrics::{Metric, MetricType, MetricUnit},
+        tests::util::http::MockServer,
+    };
+
+    use super::*;
+
+    fn decompress(data: &[u8]) -> Vec<u8> {
+        snap::raw::Decoder::new().decompress_vec(data).unwrap()
+    }
+
+    struct TestMetrics;
+
+    impl metrics::Source for TestMetrics {
+        fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+            const METRIC: Metric = Metric::new(
+                "rib_unit_num_items",
+                "the number of items",
+                MetricType::Gauge,
+                MetricUnit::Total,
+            );
+            target.append_simple(&METRIC, Some(unit_name), 42);
+        }
+    }
+
+    fn mk_pusher(addr: SocketAddr, extra: &str) -> Pusher {
+        let config: Config = toml::from_str(&format!(
+            "url = \"http://{addr}/api/v1/push\"\n\
+            labels = {{ instance = \"rr1\" }}\n\
+            headers = {{ X-Scope-OrgID = \"tenant\" }}\n\
+            retry_delay_secs = 0\n{extra}"
+        ))
+        .unwrap();
+        Pusher {
+            name: "remote-write".into(),
+            client: config.client().unwrap(),
+            url: config.url.clone(),
+            headers: config.headers().unwrap(),
+            labels: config.labels.into_iter().collect(),
+            collection: None,
+            counters: Default::default(),
+            max_series: config.max_series_per_request,
+            max_retries: config.max_retries,
+            retry_delay: config.retry_delay_secs,
+            max_retry_delay: config.max_retry_delay_secs,
+            metrics: Default::default(),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn series_are_pushed() {
+        let server = MockServer::with_statuses(vec![503], 204).await;
+        let mut pusher = mk_pusher(server.addr, "max_series_per_request = 2");
+        let collection = metrics::Collection::default();
+        let source: Arc<dyn metrics::Source> = Arc::new(TestMetrics);
+        collection.register("rib".into(), Arc::downgrade(&source));
+        pusher.collection = Some(collection);
+        pusher.counters.lock().unwrap().add(&Row {
+            kind: "announce",
+            peer_ip: Some("192.0.2.1".into()),
+            ..Default::default()
+        });
+        pusher.push().await;
+
+        // Three counters and two instance metrics, in three requests, the
+        // first of which is retried.
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0].headers[CONTENT_ENCODING], "snappy");
+        assert_eq!(requests[0].headers["x-scope-orgid"], "tenant");
+        assert_eq!(requests[1].body, requests[0].body);
+        let body = decompress(&requests[0].body);
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("rotonda_routes_announced_total"));
+        assert!(body.contains("instance"));
+        let bodies = requests
+            .iter()
+            .map(|request| {
+                String::from_utf8_lossy(&decompress(&request.body))
+                    .into_owned()
+            })
+            .collect::<String>();
+        assert!(bodies.contains("rotonda_rib_unit_num_items"));
+        assert_eq!(pusher.metrics.series_count.load(SeqCst), 5);
+        assert_eq!(pusher.metrics.push_count.load(SeqCst), 3);
+        assert_eq!(pusher.metrics.push_error_count.load(SeqCst), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn refused_pushes_are_dropped() {
+        let server = MockServer::with_statuses(vec![400], 204).await;
+        let pusher = mk_pusher(server.addr, "");
+        pusher.counters.lock().unwrap().add(&Row {
+            kind: "withdraw",
+            ..Default::default()
+        });
+        pusher.push().await;
+
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(pusher.metrics.dropped_push_count.load(SeqCst), 1);
+        assert!(pusher.metrics.failing.load(SeqCst));
+    }
+
+    #[test]
+    fn config_is_checked() {
+        let config = |extra: &str| {
+            toml::from_str::<Config>(&format!(
+                "url = \"http://localhost/api/v1/push\"\n{extra}"
+            ))
+            .unwrap()
+        };
+        assert!(config("").client().is_ok());
+        assert!(config("labels = { __name__ = \"x\" }").client().is_err());
+        assert!(config("interval_secs = 0").client().is_err());
+        assert!(config("headers = { \"a b\" = \"x\" }").headers().is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct RtrMetrics {
+    pub serial: AtomicU32,
+    pub payload_count: AtomicUsize,
+    pub connection_count: AtomicUsize,
+    pub connection_accepted_count: AtomicUsize,
+}
+
+impl GraphStatus for RtrMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "serial: {}\npayloads: {}\nclients: {}",
+            self.serial.load(SeqCst),
+            self.payload_count.load(SeqCst),
+            self.connection_count.load(SeqCst),
+        )
+    }
+}
+
+impl RtrMetrics {
+    const SERIAL_METRIC: Metric = Metric::new(
+        "rtr_target_serial",
+        "the serial number of the data served",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const PAYLOAD_COUNT_METRIC: Metric = Metric::new(
+        "rtr_target_payload_count",
+        "the number of VRPs, router keys and ASPAs served",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const CONNECTION_COUNT_METRIC: Metric = Metric::new(
+        "rtr_target_connection_count",
+        "the number of connected RTR clients",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const CONNECTION_ACCEPTED_COUNT_METRIC: Metric = Metric::new(
+        "rtr_target_connection_accepted_count",
+        "the number of RTR client connections accepted",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for RtrMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::SERIAL_METRIC,
+            Some(unit_name),
+            self.serial.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::PAYLOAD_COUNT_METRIC,
+            Some(unit_name),
+            self.payload_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::CONNECTION_COUNT_METRIC,
+            Some(unit_name),
+            self.connection_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::CONNECTION_ACCEPTED_COUNT_METRIC,
+            Some(unit_name),
+            self.connection_accepted_count.load(SeqCst),
+        );
+    }
+}
@@ -0,0 +1,4 @@
+mod metrics;
+mod payloads;
+mod source;
+pub mod target;
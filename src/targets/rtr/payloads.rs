@@ -0,0 +1,426 @@
+//! Keeping the payload set of the `rtr-out` target.
+//!
+//! The [`Payloads`] combine two kinds of data, either of which may be
+//! absent:
+//!
+//! * The VRPs, router keys and ASPAs of RTR updates, such as those of an
+//!   RTR client unit, which are mirrored as they are.
+//! * The route origins of the routes received, one for each prefix and the
+//!   AS number it is originated from, without a maximum length. A route
+//!   origin stays in the set for as long as any peer has a route for it.
+//!   Routes without an origin AS, such as those with an empty AS path or
+//!   one ending in an AS_SET, add nothing.
+//!
+//! The changes to the set are collected until they are taken to publish
+//! them as a new serial, with changes that undo each other left out.
+
+use std::collections::{HashMap, HashSet};
+
+use inetnum::addr::Prefix;
+use rotonda_store::prefix_record::RouteStatus;
+use routecore::bgp::{aspath::HopPath, types::AfiSafiType};
+use rpki::{
+    resources::addr::MaxLenPrefix,
+    rtr::payload::{Action, Payload, RouteOrigin},
+};
+
+use crate::{
+    ingress::IngressId,
+    payload::{RotondaRoute, Update},
+    roto_runtime::types::RouteContext,
+    targets::mrt::bgp4mp::afi_safi,
+    units::{rib_unit::best_path::prefix_of, RtrUpdate},
+};
+
+/// A route by the ingress it came from, its AFI and SAFI and its prefix.
+type RouteKey = (IngressId, (u16, u8), Prefix);
+
+//------------ Payloads ------------------------------------------------------
+
+#[derive(Debug, Default)]
+pub struct Payloads {
+    /// The payload of the RTR updates.
+    mirrored: HashSet<Payload>,
+
+    /// The route origins of the routes, with the number of their routes.
+    origins: HashMap<RouteOrigin, usize>,
+
+    /// The route origin of every route.
+    routes: HashMap<RouteKey, RouteOrigin>,
+
+    /// The changes since they were last taken.
+    changes: HashMap<Payload, Action>,
+
+    /// Whether any data was received at all.
+    received: bool,
+}
+
+impl Payloads {
+    /// Applies the routes, withdrawals and RTR updates in an update.
+    pub fn apply(&mut self, update: Update) {
+        match update {
+            Update::Single(payload) => {
+                self.apply_route(&payload.rx_value, &payload.context)
+            }
+            Update::Bulk(payloads) => {
+                for payload in payloads {
+                    self.apply_route(&payload.rx_value, &payload.context);
+                }
+            }
+            Update::Withdraw(ingress_id, afi_safi) => {
+                self.withdraw_ingress(ingress_id, afi_safi)
+            }
+            Update::WithdrawBulk(ingress_ids) => {
+                for ingress_id in ingress_ids {
+                    self.withdraw_ingress(ingress_id, None);
+                }
+            }
+            Update::Rtr(RtrUpdate::Full(verbs)) => {
+                self.received = true;
+                let new = verbs
+                    .into_iter()
+                    .filter(|(action, _)| action.is_announce())
+                    .map(|(_, payload)| payload)
+                    .collect::<HashSet<_>>();
+                let old = std::mem::take(&mut self.mirrored);
+                for payload in old.difference(&new) {
+                    if !self.has_origin(payload) {
+                        self.record(payload.clone(), Action::Withdraw);
+                    }
+                }
+                for payload in new.difference(&old) {
+                    if !self.has_origin(payload) {
+                        self.record(payload.clone(), Action::Announce);
+                    }
+                }
+                self.mirrored = new;
+            }
+            Update::Rtr(RtrUpdate::Delta(verbs)) => {
+                self.received = true;
+                for (action, payload) in verbs {
+                    self.apply_rtr(action, payload);
+                }
+            }
+            Update::OutputStream(..)
+            | Update::QueryResult(..)
+            | Update::UpstreamStatusChange(..) => {}
+        }
+    }
+
+    /// Returns whether any routes or RTR updates were received.
+    pub fn received(&self) -> bool {
+        self.received
+    }
+
+    /// Returns whether there are changes to take.
+    pub fn has_changes(&self) -> bool {
+        !self.changes.is_empty()
+    }
+
+    /// Returns the current payload set.
+    pub fn snapshot(&self) -> Vec<Payload> {
+        let mut res = self.mirrored.iter().cloned().collect::<Vec<_>>();
+        res.extend(
+            self.origins
+                .keys()
+                .map(|origin| Payload::Origin(*origin))
+                .filter(|payload| !self.mirrored.contains(payload)),
+        );
+        res
+    }
+
+    /// Takes the changes since they were last taken, withdrawals first.
+    pub fn take_changes(&mut self) -> Vec<(Payload, Action)> {
+        let mut res = self.changes.drain().collect::<Vec<_>>();
+        res.sort_by_key(|(_, action)| action.is_announce());
+        res
+    }
+
+    fn apply_rtr(&mut self, action: Action, payload: Payload) {
+        // An ASPA replaces that of the same customer, and is withdrawn
+        // by its customer alone.
+        if let Payload::Aspa(aspa) = &payload {
+            let replaced = self
+                .mirrored
+                .iter()
+                .filter(|other| {
+                    matches!(other, Payload::Aspa(other)
+                        if other.customer == aspa.customer)
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            for other in replaced {
+                self.mirrored.remove(&other);
+                self.record(other, Action::Withdraw);
+            }
+        } else if action.is_withdraw() {
+            if self.mirrored.remove(&payload) && !self.has_origin(&payload) {
+                self.record(payload, Action::Withdraw);
+            }
+            return;
+        }
+        if action.is_announce()
+            && self.mirrored.insert(payload.clone())
+            && !self.has_origin(&payload)
+        {
+            self.record(payload, Action::Announce);
+        }
+    }
+
+    fn apply_route(&mut self, route: &RotondaRoute, context: &RouteContext) {
+        let (status, provenance) = match context {
+            RouteContext::Fresh(ctx) => (ctx.status(), ctx.provenance()),
+            RouteContext::Mrt(ctx) => (ctx.status, ctx.provenance()),
+            RouteContext::Reprocess => return,
+        };
+        self.received = true;
+        let key = (provenance.ingress_id, afi_safi(route), prefix_of(route));
+        let origin = if status == RouteStatus::Withdrawn {
+            None
+        } else {
+            route_origin(route)
+        };
+        if self.routes.get(&key) == origin.as_ref() {
+            return;
+        }
+        self.remove_route(&key);
+        if let Some(origin) = origin {
+            self.routes.insert(key, origin);
+            let count = self.origins.entry(origin).or_default();
+            *count += 1;
+            if *count == 1 && !self.mirrored.contains(&origin.into()) {
+                self.record(origin.into(), Action::Announce);
+            }
+        }
+    }
+
+    fn withdraw_ingress(
+        &mut self,
+        ingress_id: IngressId,
+        afi_safi: Option<AfiSafiType>,
+    ) {
+        let afi_safi = afi_safi.map(<(u16, u8)>::from);
+        let keys = self
+            .routes
+            .keys()
+            .filter(|(id, family, _)| {
+                *id == ingress_id
+                    && afi_safi.is_none_or(|afi_safi| afi_safi == *family)
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        for key in keys {
+            self.remove_route(&key);
+        }
+    }
+
+    fn remove_route(&mut self, key: &RouteKey) {
+        let Some(origin) = self.routes.remove(key) else {
+            return;
+        };
+        let Some(count) = self.origins.get_mut(&origin) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.origins.remove(&origin);
+            if !self.mirrored.contains(&origin.into()) {
+                self.record(origin.into(), Action::Withdraw);
+            }
+        }
+    }
+
+    /// Returns whether a payload is a route origin of a route.
+    fn has_origin(&self, payload: &Payload) -> bool {
+        match payload {
+            Payload::Origin(origin) => self.origins.contains_key(origin),
+            _ => false,
+        }
+    }
+
+    /// Records a change, dropping the opposite change if there was one.
+    fn record(&mut self, payload: Payload, action: Action) {
+        match self.changes.get(&payload) {
+            Some(recorded) if *recorded != action => {
+                self.changes.remove(&payload);
+            }
+            _ => {
+                self.changes.insert(payload, action);
+            }
+        }
+    }
+}
+
+/// Returns the route origin of a route, if it has an origin AS.
+fn route_origin(route: &RotondaRoute) -> Option<RouteOrigin> {
+    let hop_path =
+        route.rotonda_pamap().path_attributes().get::<HopPath>()?;
+    let asn = hop_path.origin()?.clone().try_into_asn().ok()?;
+    let prefix = prefix_of(route);
+    let prefix =
+        rpki::resources::addr::Prefix::new(prefix.addr(), prefix.len())
+            .ok()?;
+    Some(RouteOrigin::new(
+        MaxLenPrefix::from(prefix),
+        asn.into_u32().into(),
+    ))
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use inetnum::asn::Asn;
+
+    use crate::{
+        payload::Payload as RoutePayload,
+        roto_runtime::types::{MrtContext, Provenance},
+        targets::file::row::tests::mk_route,
+    };
+
+    use super::*;
+
+    fn route(
+        prefix: &str,
+        as_path: &[u32],
+        status: RouteStatus,
+        ingress_id: IngressId,
+    ) -> RoutePayload {
+        let provenance = Provenance::for_bgp(
+            ingress_id,
+            "192.0.2.1".parse().unwrap(),
+            Asn::from_u32(65000 + ingress_id),
+        );
+        RoutePayload::new(
+            mk_route(prefix, as_path, &[]),
+            RouteContext::Mrt(MrtContext { status, provenance }),
+            None,
+        )
+    }
+
+    fn origin(prefix: &str, asn: u32) -> Payload {
+        Payload::Origin(RouteOrigin::new(
+            MaxLenPrefix::from_str(prefix).unwrap(),
+            asn.into(),
+        ))
+    }
+
+    fn rtr_update(full: bool, verbs: Vec<(Action, Payload)>) -> Update {
+        let mut update = if full {
+            RtrUpdate::Full(Default::default())
+        } else {
+            RtrUpdate::Delta(Default::default())
+        };
+        for (action, payload) in verbs {
+            rpki::rtr::client::PayloadUpdate::push_update(
+                &mut update,
+                action,
+                payload,
+            )
+            .unwrap();
+        }
+        Update::Rtr(update)
+    }
+
+    #[test]
+    fn rtr_updates_are_mirrored() {
+        let mut payloads = Payloads::default();
+        assert!(!payloads.received());
+        payloads.apply(rtr_update(
+            true,
+            vec![
+                (Action::Announce, origin("10.0.0.0/8-16", 65001)),
+                (Action::Announce, origin("11.0.0.0/8", 65002)),
+            ],
+        ));
+        assert!(payloads.received());
+        assert_eq!(payloads.take_changes().len(), 2);
+        assert!(!payloads.has_changes());
+
+        payloads.apply(rtr_update(
+            false,
+            vec![
+                (Action::Withdraw, origin("10.0.0.0/8-16", 65001)),
+                (Action::Announce, origin("12.0.0.0/8", 65003)),
+                (Action::Withdraw, origin("12.0.0.0/8", 65003)),
+            ],
+        ));
+        assert_eq!(
+            payloads.take_changes(),
+            [(origin("10.0.0.0/8-16", 65001), Action::Withdraw)]
+        );
+
+        // A reset replaces the mirrored payloads.
+        payloads.apply(rtr_update(
+            true,
+            vec![(Action::Announce, origin("12.0.0.0/8", 65003))],
+        ));
+        assert_eq!(
+            payloads.take_changes(),
+            [
+                (origin("11.0.0.0/8", 65002), Action::Withdraw),
+                (origin("12.0.0.0/8", 65003), Action::Announce),
+            ]
+        );
+        assert_eq!(payloads.snapshot(), [origin("12.0.0.0/8", 65003)]);
+    }
+
+    #[test]
+    fn route_origins_are_kept() {
+        let mut payloads = Payloads::default();
+        payloads.apply(Update::Bulk(
+            vec![
+                route("10.0.0.0/8", &[65001, 65002], RouteStatus::Active, 1),
+                route("10.0.0.0/8", &[65003, 65002], RouteStatus::Active, 2),
+                route("11.0.0.0/8", &[], RouteStatus::Active, 1),
+            ]
+            .into(),
+        ));
+        assert_eq!(
+            payloads.take_changes(),
+            [(origin("10.0.0.0/8", 65002), Action::Announce)]
+        );
+
+        // The route origin stays until the last route is gone.
+        payloads.apply(Update::Single(route(
+            "10.0.0.0/8",
+            &[65001, 65002],
+            RouteStatus::Withdrawn,
+            1,
+        )));
+        assert!(!payloads.has_changes());
+        payloads.apply(Update::Withdraw(2, None));
+        assert_eq!(
+            payloads.take_changes(),
+            [(origin("10.0.0.0/8", 65002), Action::Withdraw)]
+        );
+
+        // A new origin replaces the old one.
+        payloads.apply(Update::Single(route(
+            "10.0.0.0/8",
+            &[65001, 65002],
+            RouteStatus::Active,
+            1,
+        )));
+        payloads.apply(Update::Single(route(
+            "10.0.0.0/8",
+            &[65001],
+            RouteStatus::Active,
+            1,
+        )));
+        assert_eq!(
+            payloads.take_changes(),
+            [(origin("10.0.0.0/8", 65001), Action::Announce)]
+        );
+
+        // Mirrored payloads are only there once.
+        payloads.apply(rtr_update(
+            true,
+            vec![(Action::Announce, origin("10.0.0.0/8", 65001))],
+        ));
+        assert!(!payloads.has_changes());
+        assert_eq!(payloads.snapshot(), [origin("10.0.0.0/8", 65001)]);
+    }
+}
@@ -0,0 +1,242 @@
+//! The data served to RTR clients.
+//!
+//! The [`Source`] holds the payload set published last, along with the
+//! changes of the most recent serials, so that clients that are not too far
+//! behind can be served the difference to their serial instead of the
+//! complete set. Clients of another session, such as one from before the
+//! target was restarted, or too far behind receive a Cache Reset.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, RwLock},
+};
+
+use rpki::rtr::{
+    payload::{Action, Payload, PayloadRef, Timing},
+    server::{PayloadDiff, PayloadSet, PayloadSource},
+    state::{Serial, State},
+};
+
+/// The changes of a serial.
+type Changes = Arc<[(Payload, Action)]>;
+
+//------------ Source --------------------------------------------------------
+
+#[derive(Clone, Debug)]
+pub struct Source(Arc<RwLock<Data>>);
+
+#[derive(Debug)]
+struct Data {
+    /// Whether a payload set was published.
+    ready: bool,
+
+    state: State,
+    timing: Timing,
+    payloads: Arc<[Payload]>,
+
+    /// The changes of the recent serials, by the serial they start from.
+    diffs: VecDeque<(Serial, Changes)>,
+    history_size: usize,
+}
+
+impl Source {
+    pub fn new(timing: Timing, history_size: usize) -> Self {
+        Self(Arc::new(RwLock::new(Data {
+            ready: false,
+            state: State::new(),
+            timing,
+            payloads: Arc::new([]),
+            diffs: VecDeque::new(),
+            history_size,
+        })))
+    }
+
+    /// Publishes the payload set and the changes that led to it.
+    ///
+    /// A new serial is only started if there are changes.
+    pub fn publish(
+        &self,
+        payloads: Vec<Payload>,
+        changes: Vec<(Payload, Action)>,
+    ) {
+        let mut data = self.0.write().unwrap();
+        data.ready = true;
+        if changes.is_empty() {
+            return;
+        }
+        let serial = data.state.serial();
+        data.diffs.push_back((serial, changes.into()));
+        while data.diffs.len() > data.history_size {
+            data.diffs.pop_front();
+        }
+        data.payloads = payloads.into();
+        data.state.inc();
+    }
+
+    /// Returns the current serial and the number of payloads.
+    pub fn serial_and_len(&self) -> (u32, usize) {
+        let data = self.0.read().unwrap();
+        (data.state.serial().into(), data.payloads.len())
+    }
+}
+
+impl PayloadSource for Source {
+    type Set = SetIter;
+    type Diff = DiffIter;
+
+    fn ready(&self) -> bool {
+        self.0.read().unwrap().ready
+    }
+
+    fn notify(&self) -> State {
+        self.0.read().unwrap().state
+    }
+
+    fn full(&self) -> (State, Self::Set) {
+        let data = self.0.read().unwrap();
+        let set = SetIter {
+            payloads: data.payloads.clone(),
+            pos: 0,
+        };
+        (data.state, set)
+    }
+
+    fn diff(&self, state: State) -> Option<(State, Self::Diff)> {
+        let data = self.0.read().unwrap();
+        if state.session() != data.state.session() {
+            return None;
+        }
+        let start = if state.serial() == data.state.serial() {
+            data.diffs.len()
+        } else {
+            data.diffs
+                .iter()
+                .position(|(serial, _)| *serial == state.serial())?
+        };
+
+        // Merge the changes of all serials since the client's, leaving out
+        // those undone later.
+        let mut merged = HashMap::new();
+        for (_, diff) in data.diffs.range(start..) {
+            for (payload, action) in diff.iter() {
+                match merged.get(payload) {
+                    Some(merged_action) if merged_action != action => {
+                        merged.remove(payload);
+                    }
+                    _ => {
+                        merged.insert(payload.clone(), *action);
+                    }
+                }
+            }
+        }
+        let mut changes = merged.into_iter().collect::<Vec<_>>();
+        changes.sort_by_key(|(_, action)| action.is_announce());
+        let diff = DiffIter { changes, pos: 0 };
+        Some((data.state, diff))
+    }
+
+    fn timing(&self) -> Timing {
+        self.0.read().unwrap().timing
+    }
+}
+
+//------------ SetIter and DiffIter ------------------------------------------
+
+pub struct SetIter {
+    payloads: Arc<[Payload]>,
+    pos: usize,
+}
+
+impl PayloadSet for SetIter {
+    fn next(&mut self) -> Option<PayloadRef<'_>> {
+        let payload = self.payloads.get(self.pos)?;
+        self.pos += 1;
+        Some(payload.as_ref())
+    }
+}
+
+pub struct DiffIter {
+    changes: Vec<(Payload, Action)>,
+    pos: usize,
+}
+
+impl PayloadDiff for DiffIter {
+    fn next(&mut self) -> Option<(PayloadRef<'_>, Action)> {
+        let (payload, action) = self.changes.get(self.pos)?;
+        self.pos += 1;
+        Some((payload.as_ref(), *action))
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rpki::{resources::addr::MaxLenPrefix, rtr::payload::RouteOrigin};
+
+    use super::*;
+
+    fn origin(prefix: &str, asn: u32) -> Payload {
+        Payload::Origin(RouteOrigin::new(
+            MaxLenPrefix::from_str(prefix).unwrap(),
+            asn.into(),
+        ))
+    }
+
+    fn diff(source: &Source, serial: u32) -> Option<Vec<(Payload, Action)>> {
+        let state = source.notify();
+        let state = State::from_parts(state.session(), serial.into());
+        let (_, mut diff) = source.diff(state)?;
+        let mut res = Vec::new();
+        while let Some((payload, action)) = diff.next() {
+            let payload = match payload {
+                PayloadRef::Origin(origin) => Payload::Origin(origin),
+                _ => unreachable!(),
+            };
+            res.push((payload, action));
+        }
+        Some(res)
+    }
+
+    #[test]
+    fn diffs_are_merged() {
+        let source = Source::new(Timing::default(), 2);
+        assert!(!source.ready());
+        source.publish(vec![], vec![]);
+        assert!(source.ready());
+        assert_eq!(source.serial_and_len(), (0, 0));
+
+        let a = origin("10.0.0.0/8", 65001);
+        let b = origin("11.0.0.0/8", 65002);
+        source.publish(vec![a.clone()], vec![(a.clone(), Action::Announce)]);
+        source.publish(
+            vec![b.clone()],
+            vec![
+                (a.clone(), Action::Withdraw),
+                (b.clone(), Action::Announce),
+            ],
+        );
+        assert_eq!(source.serial_and_len(), (2, 1));
+
+        assert_eq!(diff(&source, 2).unwrap(), []);
+        assert_eq!(
+            diff(&source, 1).unwrap(),
+            [(a.clone(), Action::Withdraw), (b.clone(), Action::Announce)]
+        );
+        assert_eq!(
+            diff(&source, 0).unwrap(),
+            [(b.clone(), Action::Announce)]
+        );
+
+        // Only two serials are kept.
+        source.publish(vec![], vec![(b, Action::Withdraw)]);
+        assert!(diff(&source, 0).is_none());
+        assert!(diff(&source, 1).is_some());
+
+        let (state, mut set) = source.full();
+        assert_eq!(u32::from(state.serial()), 3);
+        assert!(set.next().is_none());
+    }
+}
@@ -0,0 +1,465 @@
+//! Serving data from the pipeline to routers over RTR.
+//!
+//! The `rtr-out` target is an RTR server as described in [RFC 8210], so
+//! that routers can use data curated by Rotonda directly. The payload set
+//! it serves is made up of the RTR updates and the route origins of the
+//! routes it receives, as described in the [`payloads`] module: connected
+//! to an RTR client unit it mirrors its VRPs, connected to a RIB it serves
+//! the route origins of the routes that passed its filters, such as an
+//! allow-list of the origins seen.
+//!
+//! Changes are collected for `update_interval_secs` before they are
+//! published as a new serial, after which connected clients are notified.
+//! The changes of the last `history_size` serials are kept to serve clients
+//! the difference to their serial, as described in the [`source`] module.
+//! Until the first data arrives, clients are told that no data is available.
+//!
+//! The `refresh`, `retry` and `expire` intervals are passed on to clients
+//! using version 1 or later of the protocol.
+//!
+//! [RFC 8210]: https://www.rfc-editor.org/rfc/rfc8210
+//! [`payloads`]: super::payloads
+//! [`source`]: super::source
+
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{atomic::Ordering::SeqCst, Arc},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::{stream, Stream};
+use log::{debug, error, warn};
+use rpki::rtr::{
+    payload::Timing,
+    server::{NotifySender, PayloadSource, Server, Socket},
+};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, watch},
+};
+
+use crate::{
+    comms::{Link, Terminated, UnitStatus},
+    manager::{Component, TargetCommand, WaitPoint},
+};
+
+use super::{metrics::RtrMetrics, payloads::Payloads, source::Source};
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct RtrOut {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The address to listen on for RTR clients.
+    listen: SocketAddr,
+
+    /// How long to collect changes before publishing a new serial.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_update_interval_secs")]
+    update_interval_secs: Duration,
+
+    /// The number of serials to keep the changes of.
+    #[serde(default = "Config::default_history_size")]
+    history_size: usize,
+
+    #[serde(default = "Config::default_refresh")]
+    refresh: u32,
+
+    #[serde(default = "Config::default_retry")]
+    retry: u32,
+
+    #[serde(default = "Config::default_expire")]
+    expire: u32,
+}
+
+impl Config {
+    fn default_update_interval_secs() -> Duration {
+        Duration::from_secs(5)
+    }
+
+    fn default_history_size() -> usize {
+        10
+    }
+
+    fn default_refresh() -> u32 {
+        Timing::default().refresh
+    }
+
+    fn default_retry() -> u32 {
+        Timing::default().retry
+    }
+
+    fn default_expire() -> u32 {
+        Timing::default().expire
+    }
+
+    /// Returns the timing passed to clients, checking the configuration.
+    fn timing(&self) -> Result<Timing, String> {
+        // The ranges allowed by section 6 of RFC 8210.
+        if !(1..=86400).contains(&self.refresh) {
+            return Err("refresh must be between 1 and 86400".into());
+        }
+        if !(1..=7200).contains(&self.retry) {
+            return Err("retry must be between 1 and 7200".into());
+        }
+        if !(600..=172800).contains(&self.expire)
+            || self.expire <= self.refresh.max(self.retry)
+        {
+            return Err("expire must be between 600 and 172800, and \
+                larger than refresh and retry"
+                .into());
+        }
+        if self.update_interval_secs.is_zero() {
+            return Err("update_interval_secs must be at least 1".into());
+        }
+        Ok(Timing {
+            refresh: self.refresh,
+            retry: self.retry,
+            expire: self.expire,
+        })
+    }
+}
+
+impl RtrOut {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let config = self.config;
+        let timing = match config.timing() {
+            Ok(timing) => timing,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+        let listener = match TcpListener::bind(config.listen).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!(
+                    "Target {}: cannot listen on {}: {err}",
+                    component.name(),
+                    config.listen
+                );
+                return Err(Terminated);
+            }
+        };
+
+        let metrics = Arc::new(RtrMetrics::default());
+        component.register_metrics(metrics.clone());
+        RtrRunner {
+            update_interval: config.update_interval_secs,
+            source: Source::new(timing, config.history_size),
+            payloads: Payloads::default(),
+            notify: NotifySender::new(),
+            metrics,
+        }
+        .run(listener, self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ RtrRunner -----------------------------------------------------
+
+struct RtrRunner {
+    update_interval: Duration,
+    source: Source,
+    payloads: Payloads,
+    notify: NotifySender,
+    metrics: Arc<RtrMetrics>,
+}
+
+impl RtrRunner {
+    async fn run(
+        mut self,
+        listener: TcpListener,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        // Dropping stop_tx closes the connections.
+        let (stop_tx, stop_rx) = watch::channel(());
+        let server = crate::tokio::spawn(
+            "rtr-out-server",
+            serve(
+                listener,
+                self.notify.clone(),
+                self.source.clone(),
+                stop_rx,
+                self.metrics.clone(),
+            ),
+        );
+
+        let mut tick = tokio::time::interval(self.update_interval);
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the rtr-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => self.payloads.apply(update),
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of rtr-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+
+                _ = tick.tick() => self.publish(),
+            }
+        }
+
+        server.abort();
+        drop(stop_tx);
+        Err(Terminated)
+    }
+
+    /// Publishes the changes as a new serial and notifies the clients.
+    fn publish(&mut self) {
+        let first = self.payloads.received() && !self.source.ready();
+        if !self.payloads.has_changes() && !first {
+            return;
+        }
+        self.source
+            .publish(self.payloads.snapshot(), self.payloads.take_changes());
+        let (serial, len) = self.source.serial_and_len();
+        self.metrics.serial.store(serial, SeqCst);
+        self.metrics.payload_count.store(len, SeqCst);
+        self.notify.notify();
+    }
+}
+
+/// Serves RTR clients connecting to `listener` until `stop` is dropped.
+async fn serve(
+    listener: TcpListener,
+    notify: NotifySender,
+    source: Source,
+    stop: watch::Receiver<()>,
+    metrics: Arc<RtrMetrics>,
+) {
+    let connections = stream::unfold(listener, move |listener| {
+        let stop = stop.clone();
+        let metrics = metrics.clone();
+        async move {
+            loop {
+                match listener.accept().await {
+                    Ok((sock, addr)) => {
+                        debug!("RTR client connected from {addr}");
+                        let conn = Connection::new(sock, stop, metrics);
+                        return Some((Ok(conn), listener));
+                    }
+                    Err(err) => {
+                        warn!("cannot accept RTR connection: {err}");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        }
+    });
+    let connections: Pin<Box<dyn Stream<Item = _> + Send>> =
+        Box::pin(connections);
+    if let Err(err) = Server::new(connections, notify, source).run().await {
+        error!("RTR server failed: {err}");
+    }
+}
+
+//------------ Connection ----------------------------------------------------
+
+/// A connection with an RTR client that is closed once the target stops.
+struct Connection {
+    sock: TcpStream,
+    stop: Pin<Box<dyn Future<Output = ()> + Send + Sync>>,
+    stopped: bool,
+    metrics: Arc<RtrMetrics>,
+}
+
+impl Connection {
+    fn new(
+        sock: TcpStream,
+        mut stop: watch::Receiver<()>,
+        metrics: Arc<RtrMetrics>,
+    ) -> Self {
+        metrics.connection_count.fetch_add(1, SeqCst);
+        metrics.connection_accepted_count.fetch_add(1, SeqCst);
+        Self {
+            sock,
+            stop: Box::pin(
+                async move { while stop.changed().await.is_ok() {} },
+            ),
+            stopped: false,
+            metrics,
+        }
+    }
+
+    /// Returns whether the target stopped.
+    fn poll_stopped(&mut self, cx: &mut Context<'_>) -> bool {
+        if !self.stopped {
+            self.stopped = self.stop.as_mut().poll(cx).is_ready();
+        }
+        self.stopped
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.metrics.connection_count.fetch_sub(1, SeqCst);
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.poll_stopped(cx) {
+            // Reading nothing makes the server close the connection.
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.sock).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.poll_stopped(cx) {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        Pin::new(&mut self.sock).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.sock).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.sock).poll_shutdown(cx)
+    }
+}
+
+impl Socket for Connection {}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rpki::{
+        resources::addr::MaxLenPrefix,
+        rtr::payload::{Action, Payload, RouteOrigin},
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn mk_config(extra: &str) -> Config {
+        toml::from_str(&format!("listen = \"127.0.0.1:3323\"\n{extra}"))
+            .unwrap()
+    }
+
+    #[test]
+    fn config_is_checked() {
+        let config = mk_config("");
+        assert_eq!(config.update_interval_secs, Duration::from_secs(5));
+        assert_eq!(config.timing().unwrap().refresh, 3600);
+        assert!(mk_config("refresh = 0").timing().is_err());
+        assert!(mk_config("retry = 7201").timing().is_err());
+        assert!(mk_config("expire = 3600").timing().is_err());
+        assert!(mk_config("update_interval_secs = 0").timing().is_err());
+    }
+
+    /// Reads a PDU, returning its type and its content after the header.
+    async fn read_pdu(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0; 8];
+        stream.read_exact(&mut header).await.unwrap();
+        let len = u32::from_be_bytes(header[4..].try_into().unwrap());
+        let mut content = vec![0; len as usize - 8];
+        stream.read_exact(&mut content).await.unwrap();
+        (header[1], content)
+    }
+
+    #[tokio::test]
+    async fn clients_are_served() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let source = Source::new(Timing::default(), 10);
+        let metrics = Arc::new(RtrMetrics::default());
+        let (stop_tx, stop_rx) = watch::channel(());
+        let server = tokio::spawn(serve(
+            listener,
+            NotifySender::new(),
+            source.clone(),
+            stop_rx,
+            metrics.clone(),
+        ));
+
+        let origin = Payload::Origin(RouteOrigin::new(
+            MaxLenPrefix::from_str("10.0.0.0/8-16").unwrap(),
+            65001.into(),
+        ));
+        source
+            .publish(vec![origin.clone()], vec![(origin, Action::Announce)]);
+
+        // A Reset Query is answered with a Cache Response, the VRP and an
+        // End of Data.
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(&[1, 2, 0, 0, 0, 0, 0, 8]).await.unwrap();
+        assert_eq!(read_pdu(&mut client).await.0, 3);
+        let (pdu, content) = read_pdu(&mut client).await;
+        assert_eq!(pdu, 4);
+        assert_eq!(content, [1, 8, 16, 0, 10, 0, 0, 0, 0, 0, 0xfd, 0xe9]);
+        let (pdu, content) = read_pdu(&mut client).await;
+        assert_eq!(pdu, 7);
+        assert_eq!(content[..4], 1u32.to_be_bytes());
+        assert_eq!(metrics.connection_count.load(SeqCst), 1);
+
+        // Stopping closes the connection.
+        drop(stop_tx);
+        let mut buf = [0; 8];
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+        server.abort();
+    }
+}
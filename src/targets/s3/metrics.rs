@@ -0,0 +1,118 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct S3Metrics {
+    pub failing: AtomicBool,
+    pub object_count: AtomicUsize,
+    pub byte_count: AtomicUsize,
+    pub request_error_count: AtomicUsize,
+    pub failed_object_count: AtomicUsize,
+    pub dropped_object_count: AtomicUsize,
+    pub pending_objects: AtomicUsize,
+}
+
+impl GraphStatus for S3Metrics {
+    fn status_text(&self) -> String {
+        format!(
+            "objects: {}\npending: {}\nfailed: {}",
+            self.object_count.load(SeqCst),
+            self.pending_objects.load(SeqCst),
+            self.failed_object_count.load(SeqCst)
+                + self.dropped_object_count.load(SeqCst),
+        )
+    }
+
+    fn okay(&self) -> Option<bool> {
+        Some(!self.failing.load(SeqCst))
+    }
+}
+
+impl S3Metrics {
+    const FAILING_METRIC: Metric = Metric::new(
+        "s3_target_failing",
+        "whether the last upload failed: 0=no, 1=yes",
+        MetricType::Gauge,
+        MetricUnit::State,
+    );
+    const OBJECT_COUNT_METRIC: Metric = Metric::new(
+        "s3_target_object_count",
+        "the number of objects uploaded",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const BYTE_COUNT_METRIC: Metric = Metric::new(
+        "s3_target_byte_count",
+        "the number of bytes uploaded in objects",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const REQUEST_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "s3_target_request_error_count",
+        "the number of requests to the storage service that failed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const FAILED_OBJECT_COUNT_METRIC: Metric = Metric::new(
+        "s3_target_failed_object_count",
+        "the number of objects given up on after all retries failed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DROPPED_OBJECT_COUNT_METRIC: Metric = Metric::new(
+        "s3_target_dropped_object_count",
+        "the number of objects dropped because too many were pending",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const PENDING_OBJECTS_METRIC: Metric = Metric::new(
+        "s3_target_pending_objects",
+        "the number of objects waiting to be uploaded",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for S3Metrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::FAILING_METRIC,
+            Some(unit_name),
+            u8::from(self.failing.load(SeqCst)),
+        );
+        target.append_simple(
+            &Self::OBJECT_COUNT_METRIC,
+            Some(unit_name),
+            self.object_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::BYTE_COUNT_METRIC,
+            Some(unit_name),
+            self.byte_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::REQUEST_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.request_error_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::FAILED_OBJECT_COUNT_METRIC,
+            Some(unit_name),
+            self.failed_object_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DROPPED_OBJECT_COUNT_METRIC,
+            Some(unit_name),
+            self.dropped_object_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::PENDING_OBJECTS_METRIC,
+            Some(unit_name),
+            self.pending_objects.load(SeqCst),
+        );
+    }
+}
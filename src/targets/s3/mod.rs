@@ -0,0 +1,3 @@
+mod metrics;
+mod object;
+pub mod target;
@@ -0,0 +1,188 @@
+//! The objects being collected before they are uploaded.
+
+use std::io::Write;
+
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression as GzCompression};
+use serde::Deserialize;
+
+use crate::targets::file::{
+    avro::AvroWriter,
+    parquet::ParquetWriter,
+    row::{Row, COLUMNS},
+    target::Compression,
+};
+
+//------------ Format --------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// A JSON object with all columns per line.
+    Json,
+    Parquet,
+    Avro,
+
+    /// BGP4MP records, see the [`bgp4mp`] module.
+    ///
+    /// [`bgp4mp`]: crate::targets::mrt::bgp4mp
+    Mrt,
+}
+
+impl Format {
+    /// Returns whether rows are encoded, rather than updates.
+    pub fn has_rows(self) -> bool {
+        self != Format::Mrt
+    }
+
+    /// Returns the compression used if none is configured.
+    pub fn default_compression(self) -> Compression {
+        match self {
+            Format::Json | Format::Mrt => Compression::Gzip,
+            Format::Parquet | Format::Avro => Compression::Snappy,
+        }
+    }
+
+    /// Returns the file name extension of objects.
+    pub fn extension(self, compression: Compression) -> &'static str {
+        match (self, compression) {
+            (Format::Json, Compression::Gzip) => "json.gz",
+            (Format::Json, _) => "json",
+            (Format::Mrt, Compression::Gzip) => "mrt.gz",
+            (Format::Mrt, _) => "mrt",
+            (Format::Parquet, _) => "parquet",
+            (Format::Avro, _) => "avro",
+        }
+    }
+}
+
+//------------ Object --------------------------------------------------------
+
+/// An object being collected.
+pub struct Object {
+    /// When the object was started.
+    pub started: DateTime<Utc>,
+
+    /// The number of rows or records in the object.
+    pub count: usize,
+
+    encoder: Encoder,
+}
+
+/// Encodes the content of an object.
+enum Encoder {
+    Plain(Vec<u8>),
+    Gzip(GzEncoder<Vec<u8>>),
+    Parquet(ParquetWriter, Vec<u8>),
+    Avro(AvroWriter, Vec<u8>),
+}
+
+impl Object {
+    /// Starts an object.
+    ///
+    /// JSON and MRT objects are only compressed with gzip.
+    pub fn new(
+        format: Format,
+        compression: Compression,
+        row_group_size: usize,
+        started: DateTime<Utc>,
+    ) -> Self {
+        let encoder = match format {
+            Format::Json | Format::Mrt => match compression {
+                Compression::Gzip => Encoder::Gzip(GzEncoder::new(
+                    Vec::new(),
+                    GzCompression::default(),
+                )),
+                Compression::None | Compression::Snappy => {
+                    Encoder::Plain(Vec::new())
+                }
+            },
+            Format::Parquet => Encoder::Parquet(
+                ParquetWriter::new(row_group_size, compression),
+                Vec::new(),
+            ),
+            Format::Avro => Encoder::Avro(
+                AvroWriter::new(row_group_size, compression),
+                Vec::new(),
+            ),
+        };
+        Self {
+            started,
+            count: 0,
+            encoder,
+        }
+    }
+
+    /// Adds a row.
+    pub fn push_row(&mut self, row: Row) {
+        self.count += 1;
+        match &mut self.encoder {
+            Encoder::Plain(_) | Encoder::Gzip(_) => {
+                let object = COLUMNS
+                    .iter()
+                    .map(|column| {
+                        (column.name.to_string(), row.json_value(column.name))
+                    })
+                    .collect::<serde_json::Map<_, _>>();
+                let mut line = serde_json::Value::from(object).to_string();
+                line.push('\n');
+                self.write(line.as_bytes());
+            }
+            Encoder::Parquet(writer, buf) => {
+                if let Some(bytes) = writer.push(row) {
+                    buf.extend_from_slice(&bytes);
+                }
+            }
+            Encoder::Avro(writer, buf) => {
+                if let Some(bytes) = writer.push(&row) {
+                    buf.extend_from_slice(&bytes);
+                }
+            }
+        }
+    }
+
+    /// Adds `count` encoded records.
+    pub fn push_records(&mut self, records: &[u8], count: usize) {
+        self.count += count;
+        self.write(records);
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        match &mut self.encoder {
+            Encoder::Plain(buf) => buf.extend_from_slice(bytes),
+            Encoder::Gzip(encoder) => {
+                // Writing to a vec does not fail.
+                let _ = encoder.write_all(bytes);
+            }
+            Encoder::Parquet(..) | Encoder::Avro(..) => {}
+        }
+    }
+
+    /// Returns the size of the object so far.
+    ///
+    /// Rows waiting for their Parquet row group or Avro block to complete
+    /// and data still in the compressor are not included.
+    pub fn len(&self) -> usize {
+        match &self.encoder {
+            Encoder::Plain(buf) => buf.len(),
+            Encoder::Gzip(encoder) => encoder.get_ref().len(),
+            Encoder::Parquet(_, buf) | Encoder::Avro(_, buf) => buf.len(),
+        }
+    }
+
+    /// Returns the content of the object.
+    pub fn finish(self) -> Vec<u8> {
+        match self.encoder {
+            Encoder::Plain(buf) => buf,
+            Encoder::Gzip(encoder) => encoder.finish().unwrap_or_default(),
+            Encoder::Parquet(writer, mut buf) => {
+                buf.extend_from_slice(&writer.finish());
+                buf
+            }
+            Encoder::Avro(writer, mut buf) => {
+                buf.extend_from_slice(&writer.finish());
+                buf
+            }
+        }
+    }
+}
@@ -0,0 +1,1121 @@
+//! Archiving updates in S3 compatible object storage.
+//!
+//! The `s3-out` target collects the routes and events it receives into
+//! objects and uploads them to the `bucket` of an S3 compatible storage
+//! service. Objects are in one of these formats:
+//!
+//! * `json`: a JSON object per line with the columns described in the
+//!   [`row`] module,
+//! * `parquet` and `avro`: a row per route or event with the same
+//!   columns, as written by the `file-out` target,
+//! * `mrt`: BGP4MP records as described in the [`bgp4mp`] module, which
+//!   can be read back by the `mrt-file-in` unit.
+//!
+//! JSON and MRT objects are compressed with gzip unless `compression` is
+//! `"none"`; Parquet and Avro objects with Snappy, unless it says
+//! otherwise.
+//!
+//! An object is finished once it reaches `max_object_size` bytes or when
+//! its time window of `rotate_secs` is over, whichever comes first. The
+//! windows are aligned to the clock, so that with the default of an hour,
+//! objects end on the full hour. The name of an object is given by the
+//! `key` template, which may contain these placeholders:
+//!
+//! * `{{year}}`, `{{month}}`, `{{day}}`, `{{hour}}`, `{{minute}}` and
+//!   `{{second}}`: the UTC time the object was started, with leading
+//!   zeroes,
+//! * `{{timestamp}}`: the same time in the basic ISO 8601 format, e.g.
+//!   `20250102T030405Z`,
+//! * `{{id}}`: a random ID, so that objects started in the same second get
+//!   different names,
+//! * `{{extension}}`: the file name extension of the format, e.g.
+//!   `mrt.gz`.
+//!
+//! Objects are uploaded with a single request if they are no larger than
+//! `part_size` bytes, with a multipart upload in parts of that size
+//! otherwise. A failed request is tried again up to `max_retries` times
+//! with an exponential backoff, unless the service refused it with a
+//! status code other than 408 or 429, after which the object is given up
+//! on. Up to `max_pending_objects` objects wait for their turn; objects
+//! beyond that are dropped.
+//!
+//! The service is at `endpoint`, by default the AWS endpoint for `region`,
+//! and the bucket is addressed as part of the host name unless
+//! `path_style` is true, as is common for self-hosted services. The
+//! credentials and region are found as for the `sqs-in` unit. The service
+//! may be reached over HTTPS, with the `tls` table as for the `alert-out`
+//! target.
+//!
+//! [`row`]: crate::targets::file::row
+//! [`bgp4mp`]: crate::targets::mrt::bgp4mp
+
+use std::{
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{
+    header::{HeaderName, HeaderValue, ETAG},
+    Client as HttpClient, HeaderMap, Method, StatusCode,
+};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::sync::mpsc;
+use url::Url;
+
+use crate::{
+    common::tls::TlsClientConfig,
+    comms::{Link, Terminated, UnitStatus},
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    payload::Update,
+    targets::{
+        file::{row::Row, target::Compression},
+        http::template::Template,
+        mrt::bgp4mp,
+    },
+    units::cloud_queue_in::aws::{sha256_hex, CredentialChain, Signer},
+};
+
+use super::{
+    metrics::S3Metrics,
+    object::{Format, Object},
+};
+
+/// How long to keep trying to upload the pending objects when stopping.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The longest a single request may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The smallest part of a multipart upload the services accept.
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// The placeholders of the key template.
+pub(crate) const KEY_NAMES: [&str; 9] = [
+    "year",
+    "month",
+    "day",
+    "hour",
+    "minute",
+    "second",
+    "timestamp",
+    "id",
+    "extension",
+];
+
+/// The characters encoded in object keys: all but the unreserved ones and
+/// the slash.
+const KEY_ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// The characters encoded in query values: all but the unreserved ones.
+const QUERY_ENCODE: &AsciiSet = &KEY_ENCODE.add(b'/');
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct S3 {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The bucket to upload objects to.
+    bucket: String,
+
+    /// The region of the bucket. Defaults to the `AWS_REGION` or
+    /// `AWS_DEFAULT_REGION` environment variable, or else `us-east-1`.
+    #[serde(default)]
+    region: Option<String>,
+
+    /// The URL of the storage service. Defaults to the AWS endpoint of the
+    /// region.
+    #[serde(default)]
+    endpoint: Option<Url>,
+
+    /// Whether the bucket is part of the path rather than the host name.
+    #[serde(default)]
+    path_style: bool,
+
+    /// The profile in the shared credentials file to use. Defaults to the
+    /// `AWS_PROFILE` environment variable, or else `default`.
+    #[serde(default)]
+    profile: Option<String>,
+
+    format: Format,
+
+    /// The compression of objects, depending on the format if not given.
+    #[serde(default)]
+    compression: Option<Compression>,
+
+    /// The template for the names of objects.
+    #[serde(default = "Config::default_key")]
+    key: String,
+
+    /// The longest time window an object covers.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_rotate_secs")]
+    rotate_secs: Duration,
+
+    /// The size at which an object is finished.
+    #[serde(default = "Config::default_max_object_size")]
+    max_object_size: usize,
+
+    /// The size of the parts of multipart uploads.
+    #[serde(default = "Config::default_part_size")]
+    part_size: usize,
+
+    /// The number of rows per Parquet row group or Avro block.
+    #[serde(default = "Config::default_row_group_size")]
+    row_group_size: usize,
+
+    /// How many objects may wait to be uploaded.
+    #[serde(default = "Config::default_max_pending_objects")]
+    max_pending_objects: usize,
+
+    /// How often to try a failed request again.
+    #[serde(default = "Config::default_max_retries")]
+    max_retries: usize,
+
+    /// How long to wait before trying again. The delay doubles with every
+    /// failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_retry_delay_secs")]
+    retry_delay_secs: Duration,
+
+    /// The longest to wait before trying again.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_max_retry_delay_secs")]
+    max_retry_delay_secs: Duration,
+
+    /// The TLS settings for an HTTPS endpoint.
+    #[serde(default)]
+    tls: Option<TlsClientConfig>,
+}
+
+impl Config {
+    fn default_key() -> String {
+        "{{year}}/{{month}}/{{day}}/{{hour}}/{{timestamp}}-{{id}}.\
+        {{extension}}"
+            .into()
+    }
+
+    fn default_rotate_secs() -> Duration {
+        Duration::from_secs(3600)
+    }
+
+    fn default_max_object_size() -> usize {
+        256 * 1024 * 1024
+    }
+
+    fn default_part_size() -> usize {
+        16 * 1024 * 1024
+    }
+
+    fn default_row_group_size() -> usize {
+        10_000
+    }
+
+    fn default_max_pending_objects() -> usize {
+        4
+    }
+
+    fn default_max_retries() -> usize {
+        5
+    }
+
+    fn default_retry_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retry_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    /// Returns the compression of objects.
+    fn compression(&self) -> Compression {
+        self.compression
+            .unwrap_or_else(|| self.format.default_compression())
+    }
+
+    /// Returns the region of the bucket.
+    fn region(&self) -> String {
+        self.region
+            .clone()
+            .or_else(|| {
+                ["AWS_REGION", "AWS_DEFAULT_REGION"]
+                    .into_iter()
+                    .find_map(|name| std::env::var(name).ok())
+                    .filter(|region| !region.is_empty())
+            })
+            .unwrap_or_else(|| "us-east-1".into())
+    }
+
+    /// Returns the URL of the bucket, with a trailing slash.
+    fn bucket_url(&self, region: &str) -> Result<Url, String> {
+        let mut res = match &self.endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => Url::parse(&format!("https://s3.{region}.amazonaws.com"))
+                .map_err(|_| format!("invalid region '{region}'"))?,
+        };
+        let host = res.host_str().ok_or("missing host in endpoint")?;
+        let mut path = res.path().trim_end_matches('/').to_string();
+        if self.path_style {
+            path.push('/');
+            path.extend(utf8_percent_encode(&self.bucket, QUERY_ENCODE));
+        } else {
+            let host = format!("{}.{host}", self.bucket);
+            res.set_host(Some(&host))
+                .map_err(|_| format!("invalid bucket '{}'", self.bucket))?;
+        }
+        path.push('/');
+        res.set_path(&path);
+        res.set_query(None);
+        Ok(res)
+    }
+
+    /// Returns the client for the service, checking the configuration.
+    fn client(&self, bucket_url: &Url) -> Result<HttpClient, String> {
+        if !matches!(bucket_url.scheme(), "http" | "https") {
+            return Err(format!(
+                "unsupported URL scheme '{}', use http or https",
+                bucket_url.scheme()
+            ));
+        }
+        if self.bucket.is_empty() {
+            return Err("bucket must not be empty".into());
+        }
+        if matches!(self.format, Format::Json | Format::Mrt)
+            && matches!(self.compression, Some(Compression::Snappy))
+        {
+            return Err(
+                "snappy compression is only supported for parquet and avro"
+                    .into(),
+            );
+        }
+        if self.rotate_secs.is_zero() {
+            return Err("rotate_secs must be at least 1".into());
+        }
+        if self.part_size < MIN_PART_SIZE {
+            return Err(format!(
+                "part_size must be at least {MIN_PART_SIZE} bytes"
+            ));
+        }
+        if self.max_object_size == 0 || self.max_pending_objects == 0 {
+            return Err(
+                "max_object_size and max_pending_objects must be at least 1"
+                    .into(),
+            );
+        }
+        self.tls
+            .clone()
+            .unwrap_or_default()
+            .http_client_builder()?
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|err| err.to_string())
+    }
+}
+
+impl S3 {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let config = self.config;
+        let region = config.region();
+        let res = config.bucket_url(&region).and_then(|bucket_url| {
+            let client = config.client(&bucket_url)?;
+            let key = Template::new(config.key.as_str().into(), &KEY_NAMES)
+                .map_err(|err| format!("key: {err}"))?;
+            Ok((bucket_url, client, key))
+        });
+        let (bucket_url, client, key) = match res {
+            Ok(res) => res,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+
+        let metrics = Arc::new(S3Metrics::default());
+        component.register_metrics(metrics.clone());
+        let uploader = Uploader {
+            name: component.name().to_string(),
+            client,
+            credentials: CredentialChain::new(
+                component.http_client().clone(),
+                config.profile.clone(),
+            ),
+            region,
+            bucket_url,
+            part_size: config.part_size,
+            max_retries: config.max_retries,
+            retry_delay: config.retry_delay_secs,
+            max_retry_delay: config.max_retry_delay_secs,
+            metrics: metrics.clone(),
+        };
+        S3Runner {
+            name: component.name().to_string(),
+            format: config.format,
+            compression: config.compression(),
+            row_group_size: config.row_group_size,
+            rotate: config.rotate_secs,
+            max_object_size: config.max_object_size,
+            key,
+            object: None,
+            tx: None,
+            ingresses: component.ingresses().clone(),
+            metrics,
+        }
+        .run(
+            uploader,
+            config.max_pending_objects,
+            self.sources,
+            cmd,
+            waitpoint,
+        )
+        .await
+    }
+}
+
+//------------ S3Runner ------------------------------------------------------
+
+struct S3Runner {
+    name: String,
+    format: Format,
+    compression: Compression,
+    row_group_size: usize,
+    rotate: Duration,
+    max_object_size: usize,
+    key: Template,
+
+    /// The object being collected and when its time window ends.
+    object: Option<(Object, DateTime<Utc>)>,
+
+    tx: Option<mpsc::Sender<Upload>>,
+    ingresses: Arc<ingress::Register>,
+    metrics: Arc<S3Metrics>,
+}
+
+impl S3Runner {
+    async fn run(
+        mut self,
+        uploader: Uploader,
+        max_pending_objects: usize,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        // Objects are uploaded by a task of their own, so that a slow or
+        // unreachable service does not hold up the sources.
+        let (tx, rx) = mpsc::channel(max_pending_objects);
+        self.tx = Some(tx);
+        let upload_task = tokio::spawn(uploader.run(rx));
+
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        let mut rotate = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the s3-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => self.push(update, Utc::now()),
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of s3-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+
+                _ = rotate.tick() => {
+                    if self
+                        .object
+                        .as_ref()
+                        .is_some_and(|(_, end)| Utc::now() >= *end)
+                    {
+                        self.finish();
+                    }
+                }
+            }
+        }
+
+        // Upload what is left before stopping.
+        self.finish();
+        self.tx = None;
+        if tokio::time::timeout(DRAIN_TIMEOUT, upload_task)
+            .await
+            .is_err()
+        {
+            warn!(
+                "Target {}: dropping {} objects that could not be uploaded",
+                self.name,
+                self.metrics.pending_objects.load(SeqCst)
+            );
+        }
+        Err(Terminated)
+    }
+
+    /// Adds an update to the current object, finishing it once it is full.
+    fn push(&mut self, update: Update, now: DateTime<Utc>) {
+        if self.format.has_rows() {
+            for row in Row::for_update(update, &self.ingresses) {
+                self.object(now).push_row(row);
+            }
+        } else {
+            let mut records = Vec::new();
+            let count =
+                bgp4mp::push_update(&update, &self.ingresses, &mut records);
+            if count > 0 {
+                self.object(now).push_records(&records, count);
+            }
+        }
+        if self
+            .object
+            .as_ref()
+            .is_some_and(|(object, _)| object.len() >= self.max_object_size)
+        {
+            self.finish();
+        }
+    }
+
+    /// Returns the current object, starting one if needed.
+    fn object(&mut self, now: DateTime<Utc>) -> &mut Object {
+        let (format, compression) = (self.format, self.compression);
+        let row_group_size = self.row_group_size;
+        let end = window_end(now, self.rotate);
+        &mut self
+            .object
+            .get_or_insert_with(|| {
+                (Object::new(format, compression, row_group_size, now), end)
+            })
+            .0
+    }
+
+    /// Hands the current object to the uploader.
+    fn finish(&mut self) {
+        let Some((object, _)) = self.object.take() else {
+            return;
+        };
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let key = render_key(
+            &self.key,
+            object.started,
+            self.format.extension(self.compression),
+        );
+        let count = object.count;
+        let upload = Upload {
+            key,
+            body: object.finish().into(),
+        };
+        match tx.try_send(upload) {
+            Ok(()) => {
+                self.metrics.pending_objects.fetch_add(1, SeqCst);
+            }
+            Err(err) => {
+                self.metrics.dropped_object_count.fetch_add(1, SeqCst);
+                warn!(
+                    "Target {}: dropping object {} with {count} entries, too \
+                    many objects are waiting to be uploaded",
+                    self.name,
+                    err.into_inner().key
+                );
+            }
+        }
+    }
+}
+
+/// Returns the end of the time window `time` is in.
+pub(crate) fn window_end(
+    time: DateTime<Utc>,
+    rotate: Duration,
+) -> DateTime<Utc> {
+    let rotate = rotate.as_secs().max(1) as i64;
+    let start = time.timestamp() - time.timestamp().rem_euclid(rotate);
+    DateTime::from_timestamp(start + rotate, 0).unwrap_or(time)
+}
+
+/// Returns the name of an object started at `started`.
+pub(crate) fn render_key(
+    template: &Template,
+    started: DateTime<Utc>,
+    extension: &str,
+) -> String {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    template.render_text(&|name| {
+        let value = match name {
+            "year" => started.format("%Y").to_string(),
+            "month" => started.format("%m").to_string(),
+            "day" => started.format("%d").to_string(),
+            "hour" => started.format("%H").to_string(),
+            "minute" => started.format("%M").to_string(),
+            "second" => started.format("%S").to_string(),
+            "timestamp" => started.format("%Y%m%dT%H%M%SZ").to_string(),
+            "id" => id.clone(),
+            "extension" => extension.to_string(),
+            _ => return serde_json::Value::Null,
+        };
+        value.into()
+    })
+}
+
+//------------ Uploader ------------------------------------------------------
+
+/// A finished object.
+struct Upload {
+    key: String,
+    body: Bytes,
+}
+
+/// Uploads objects to the bucket.
+struct Uploader {
+    name: String,
+    client: HttpClient,
+    credentials: CredentialChain,
+    region: String,
+    bucket_url: Url,
+    part_size: usize,
+    max_retries: usize,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    metrics: Arc<S3Metrics>,
+}
+
+impl Uploader {
+    /// Uploads objects until there are no more.
+    async fn run(mut self, mut rx: mpsc::Receiver<Upload>) {
+        while let Some(upload) = rx.recv().await {
+            let res = self.upload(&upload).await;
+            self.metrics.pending_objects.fetch_sub(1, SeqCst);
+            self.metrics.failing.store(res.is_err(), SeqCst);
+            match res {
+                Ok(()) => {
+                    debug!(
+                        "Target {}: uploaded {} ({} bytes)",
+                        self.name,
+                        upload.key,
+                        upload.body.len()
+                    );
+                    self.metrics.object_count.fetch_add(1, SeqCst);
+                    self.metrics
+                        .byte_count
+                        .fetch_add(upload.body.len(), SeqCst);
+                }
+                Err(err) => {
+                    self.metrics.failed_object_count.fetch_add(1, SeqCst);
+                    error!(
+                        "Target {}: giving up on uploading {}: {err}",
+                        self.name, upload.key
+                    );
+                }
+            }
+        }
+    }
+
+    /// Uploads an object.
+    async fn upload(&mut self, upload: &Upload) -> Result<(), String> {
+        let url = self.object_url(&upload.key, &[])?;
+        if upload.body.len() <= self.part_size {
+            self.request(Method::PUT, &url, upload.body.clone()).await?;
+            return Ok(());
+        }
+
+        let response = self
+            .request(
+                Method::POST,
+                &self.object_url(&upload.key, &[("uploads", "")])?,
+                Bytes::new(),
+            )
+            .await?;
+        let upload_id = xml_text(&response.body, "UploadId")
+            .ok_or("multipart upload was not started")?;
+        let res = self.upload_parts(upload, &upload_id).await;
+        if res.is_err() {
+            // Have the service drop the parts uploaded so far. If that
+            // fails, a lifecycle rule of the bucket will have to.
+            let url =
+                self.object_url(&upload.key, &[("uploadId", &upload_id)])?;
+            if let Err(err) =
+                self.send(Method::DELETE, &url, Bytes::new()).await
+            {
+                debug!(
+                    "Target {}: aborting the upload of {} failed: {err}",
+                    self.name, upload.key
+                );
+            }
+        }
+        res
+    }
+
+    /// Uploads the parts of an object and completes the upload.
+    async fn upload_parts(
+        &mut self,
+        upload: &Upload,
+        upload_id: &str,
+    ) -> Result<(), String> {
+        let mut complete = String::from("<CompleteMultipartUpload>");
+        let mut start = 0;
+        let mut number = 1;
+        while start < upload.body.len() {
+            let end = (start + self.part_size).min(upload.body.len());
+            let url = self.object_url(
+                &upload.key,
+                &[
+                    ("partNumber", &number.to_string()),
+                    ("uploadId", upload_id),
+                ],
+            )?;
+            let response = self
+                .request(Method::PUT, &url, upload.body.slice(start..end))
+                .await?;
+            let etag = response
+                .headers
+                .get(ETAG)
+                .and_then(|etag| etag.to_str().ok())
+                .ok_or_else(|| format!("no ETag for part {number}"))?;
+            complete.push_str(&format!(
+                "<Part><PartNumber>{number}</PartNumber>\
+                <ETag>{}</ETag></Part>",
+                xml_escape(etag)
+            ));
+            start = end;
+            number += 1;
+        }
+        complete.push_str("</CompleteMultipartUpload>");
+        let url = self.object_url(&upload.key, &[("uploadId", upload_id)])?;
+        self.request(Method::POST, &url, complete.into()).await?;
+        Ok(())
+    }
+
+    /// Returns the URL of an object with the given query.
+    fn object_url(
+        &self,
+        key: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Url, String> {
+        let mut res = self.bucket_url.clone();
+        let path = format!(
+            "{}{}",
+            res.path(),
+            utf8_percent_encode(key.trim_start_matches('/'), KEY_ENCODE)
+        );
+        res.set_path(&path);
+        if !query.is_empty() {
+            let query = query
+                .iter()
+                .map(|(name, value)| {
+                    format!(
+                        "{name}={}",
+                        utf8_percent_encode(value, QUERY_ENCODE)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("&");
+            res.set_query(Some(&query));
+        }
+        Ok(res)
+    }
+
+    /// Makes a request, trying again if it fails.
+    async fn request(
+        &mut self,
+        method: Method,
+        url: &Url,
+        body: Bytes,
+    ) -> Result<Response, String> {
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+        loop {
+            // Requests refused by the service, other than for now, will be
+            // refused again. Some errors are only reported in the body of
+            // a successful response.
+            let (message, retry) =
+                match self.send(method.clone(), url, body.clone()).await {
+                    Ok(response)
+                        if response.status.is_success()
+                            && xml_text(&response.body, "Code").is_none() =>
+                    {
+                        return Ok(response)
+                    }
+                    Ok(response) => (
+                        format!(
+                            "{}: {}",
+                            response.status,
+                            xml_text(&response.body, "Message")
+                                .or_else(|| xml_text(&response.body, "Code"))
+                                .unwrap_or_default()
+                        ),
+                        !response.status.is_client_error()
+                            || response.status == StatusCode::REQUEST_TIMEOUT
+                            || response.status
+                                == StatusCode::TOO_MANY_REQUESTS,
+                    ),
+                    Err(err) => (err, true),
+                };
+            self.metrics.request_error_count.fetch_add(1, SeqCst);
+            warn!(
+                "Target {}: {method} {} failed: {message}",
+                self.name,
+                url.path()
+            );
+            if !retry || attempt >= self.max_retries {
+                return Err(message);
+            }
+            info!("Target {}: retrying in {}s", self.name, delay.as_secs());
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(self.max_retry_delay);
+            attempt += 1;
+        }
+    }
+
+    /// Signs and sends a request.
+    async fn send(
+        &mut self,
+        method: Method,
+        url: &Url,
+        body: Bytes,
+    ) -> Result<Response, String> {
+        let creds = self.credentials.get().await?;
+        let signer = Signer {
+            creds: &creds,
+            region: &self.region,
+            service: "s3",
+        };
+        let hash = sha256_hex(&body);
+        let signed = signer.sign(
+            method.as_str(),
+            url,
+            &[("x-amz-content-sha256", &hash)],
+            &body,
+            Utc::now(),
+        );
+        let mut headers = HeaderMap::new();
+        for (name, value) in signed {
+            headers.insert(
+                HeaderName::try_from(name).map_err(|err| err.to_string())?,
+                HeaderValue::try_from(value)
+                    .map_err(|err| err.to_string())?,
+            );
+        }
+        let response = self
+            .client
+            .request(method, url.clone())
+            .headers(headers)
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes().await.map_err(|err| err.to_string())?;
+        Ok(Response {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// A response of the service, read in full.
+struct Response {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+/// Returns the text of the first element `name` in an XML document.
+fn xml_text(body: &[u8], name: &str) -> Option<String> {
+    let body = std::str::from_utf8(body).ok()?;
+    let start = body.find(&format!("<{name}>"))? + name.len() + 2;
+    let len = body[start..].find(&format!("</{name}>"))?;
+    Some(xml_unescape(&body[start..start + len]))
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use hyper::Body;
+
+    use crate::{
+        tests::util::http::MockServer,
+        units::cloud_queue_in::aws::Credentials,
+    };
+
+    use super::*;
+
+    /// Starts a fake storage service that answers the first request with
+    /// `first_status` and acts like S3 after that.
+    async fn start_server(mut first_status: Option<u16>) -> MockServer {
+        MockServer::start(move |request| {
+            let uri = request.uri.to_string();
+            let mut response = hyper::Response::builder();
+            let reply = if uri.ends_with("?uploads=") {
+                Body::from(
+                    "<InitiateMultipartUploadResult>\
+                    <UploadId>a/b</UploadId>\
+                    </InitiateMultipartUploadResult>",
+                )
+            } else {
+                if let Some(number) =
+                    uri.split_once("partNumber=").map(|(_, rest)| &rest[..1])
+                {
+                    response =
+                        response.header(ETAG, format!("\"etag{number}\""));
+                }
+                Body::empty()
+            };
+            response
+                .status(first_status.take().unwrap_or(200))
+                .body(reply)
+                .unwrap()
+        })
+        .await
+    }
+
+    fn mk_config(extra: &str) -> Config {
+        toml::from_str(&format!(
+            "bucket = \"archive\"\nregion = \"eu-west-1\"\n\
+            format = \"json\"\n{extra}"
+        ))
+        .unwrap()
+    }
+
+    fn mk_uploader(addr: SocketAddr) -> Uploader {
+        let config = mk_config(&format!(
+            "endpoint = \"http://{addr}\"\npath_style = true\n\
+            part_size = {MIN_PART_SIZE}\nretry_delay_secs = 0"
+        ));
+        let bucket_url = config.bucket_url("eu-west-1").unwrap();
+        Uploader {
+            name: "s3".into(),
+            client: config.client(&bucket_url).unwrap(),
+            credentials: CredentialChain::with_credentials(
+                Default::default(),
+                Credentials {
+                    access_key_id: "AKID".into(),
+                    secret_access_key: "secret".into(),
+                    ..Default::default()
+                },
+            ),
+            region: "eu-west-1".into(),
+            bucket_url,
+            part_size: config.part_size,
+            max_retries: config.max_retries,
+            retry_delay: config.retry_delay_secs,
+            max_retry_delay: config.max_retry_delay_secs,
+            metrics: Default::default(),
+        }
+    }
+
+    #[test]
+    fn config_is_checked() {
+        let check = |extra: &str| {
+            let config = mk_config(extra);
+            let bucket_url = config.bucket_url(&config.region())?;
+            config.client(&bucket_url).map(|_| bucket_url.to_string())
+        };
+        assert_eq!(
+            check("").unwrap(),
+            "https://archive.s3.eu-west-1.amazonaws.com/"
+        );
+        assert_eq!(
+            check("endpoint = \"http://minio:9000/s3/\"\npath_style = true")
+                .unwrap(),
+            "http://minio:9000/s3/archive/"
+        );
+        assert!(check("compression = \"snappy\"").is_err());
+        assert!(check("rotate_secs = 0").is_err());
+        assert!(check("part_size = 1024").is_err());
+        assert!(check("endpoint = \"ftp://minio\"").is_err());
+        assert!(Template::new("{{date}}".into(), &KEY_NAMES).is_err());
+    }
+
+    #[test]
+    fn keys_are_rendered() {
+        let started = "2025-01-02T03:04:05Z".parse().unwrap();
+        let template =
+            Template::new(Config::default_key().into(), &KEY_NAMES).unwrap();
+        let key = render_key(&template, started, "mrt.gz");
+        let (prefix, rest) = key.split_at(14);
+        assert_eq!(prefix, "2025/01/02/03/");
+        let (timestamp, rest) = rest.split_once('-').unwrap();
+        assert_eq!(timestamp, "20250102T030405Z");
+        assert_eq!(rest.len(), 32 + ".mrt.gz".len());
+        assert!(rest.ends_with(".mrt.gz"));
+        assert_ne!(key, render_key(&template, started, "mrt.gz"));
+
+        // Time windows are aligned to the clock.
+        assert_eq!(
+            window_end(started, Duration::from_secs(3600)),
+            "2025-01-02T04:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            window_end(started, Duration::from_secs(300)),
+            "2025-01-02T03:05:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[test]
+    fn objects_are_rotated_when_full() {
+        let mut runner = S3Runner {
+            name: "s3".into(),
+            format: Format::Json,
+            compression: Compression::None,
+            row_group_size: 10,
+            rotate: Duration::from_secs(3600),
+            max_object_size: 1000,
+            key: Template::new("{{hour}}/{{id}}".into(), &KEY_NAMES).unwrap(),
+            object: None,
+            tx: None,
+            ingresses: Default::default(),
+            metrics: Default::default(),
+        };
+        let (tx, mut rx) = mpsc::channel(1);
+        runner.tx = Some(tx);
+        let now = Utc::now();
+        let update = Update::Withdraw(1, None);
+        while rx.is_empty() {
+            runner.push(update.clone(), now);
+        }
+
+        // The object is finished with the row that made it full.
+        let upload = rx.try_recv().unwrap();
+        let lines = upload.body.split_inclusive(|c| *c == b'\n');
+        let last = lines.clone().next_back().unwrap();
+        assert!(upload.body.len() >= 1000);
+        assert!(upload.body.len() - last.len() < 1000);
+        for line in lines {
+            let row: serde_json::Value =
+                serde_json::from_slice(line).unwrap();
+            assert_eq!(row["kind"], "peer_down");
+        }
+        assert!(runner.object.is_none());
+        assert_eq!(runner.metrics.pending_objects.load(SeqCst), 1);
+
+        // There is no room for another object.
+        while runner.metrics.dropped_object_count.load(SeqCst) == 0 {
+            runner.push(update.clone(), now);
+        }
+        assert_eq!(rx.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn small_objects_are_put_and_retried() {
+        let server = start_server(Some(503)).await;
+        let mut uploader = mk_uploader(server.addr);
+        let upload = Upload {
+            key: "2025/01/02/a b.json".into(),
+            body: Bytes::from_static(b"{}\n"),
+        };
+        uploader.upload(&upload).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        let request = &requests[1];
+        assert_eq!(request.method, Method::PUT);
+        assert_eq!(request.uri, "/archive/2025/01/02/a%20b.json");
+        assert_eq!(request.body, upload.body);
+        assert_eq!(
+            request.headers["x-amz-content-sha256"],
+            sha256_hex(b"{}\n")
+        );
+        assert!(request.headers["authorization"]
+            .to_str()
+            .unwrap()
+            .starts_with("AWS4-HMAC-SHA256 Credential=AKID/"));
+        assert!(request.headers["authorization"]
+            .to_str()
+            .unwrap()
+            .contains("/eu-west-1/s3/aws4_request"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn large_objects_are_uploaded_in_parts() {
+        let server = start_server(None).await;
+        let mut uploader = mk_uploader(server.addr);
+        let upload = Upload {
+            key: "big.mrt".into(),
+            body: vec![7; 2 * MIN_PART_SIZE + 10].into(),
+        };
+        uploader.upload(&upload).await.unwrap();
+
+        let requests = server.requests();
+        let summary = requests
+            .iter()
+            .map(|request| format!("{} {}", request.method, request.uri))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                "POST /archive/big.mrt?uploads=",
+                "PUT /archive/big.mrt?partNumber=1&uploadId=a%2Fb",
+                "PUT /archive/big.mrt?partNumber=2&uploadId=a%2Fb",
+                "PUT /archive/big.mrt?partNumber=3&uploadId=a%2Fb",
+                "POST /archive/big.mrt?uploadId=a%2Fb",
+            ]
+        );
+        assert_eq!(requests[1].body.len(), MIN_PART_SIZE);
+        assert_eq!(requests[3].body.len(), 10);
+        assert_eq!(
+            requests[4].body,
+            "<CompleteMultipartUpload>\
+            <Part><PartNumber>1</PartNumber><ETag>&quot;etag1&quot;</ETag>\
+            </Part>\
+            <Part><PartNumber>2</PartNumber><ETag>&quot;etag2&quot;</ETag>\
+            </Part>\
+            <Part><PartNumber>3</PartNumber><ETag>&quot;etag3&quot;</ETag>\
+            </Part>\
+            </CompleteMultipartUpload>"
+        );
+    }
+}
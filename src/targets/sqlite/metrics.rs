@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct SqliteMetrics {
+    pub write_count: AtomicUsize,
+    pub write_error_count: AtomicUsize,
+    pub written_row_count: AtomicUsize,
+    pub dropped_row_count: AtomicUsize,
+    pub pending_batch_count: AtomicUsize,
+    pub last_write_duration_ms: AtomicU64,
+    pub write_duration_ms: AtomicU64,
+}
+
+impl GraphStatus for SqliteMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "written: {}\npending: {}\nerrors: {}\ndropped: {}",
+            self.written_row_count.load(SeqCst),
+            self.pending_batch_count.load(SeqCst),
+            self.write_error_count.load(SeqCst),
+            self.dropped_row_count.load(SeqCst),
+        )
+    }
+}
+
+impl SqliteMetrics {
+    const WRITE_COUNT_METRIC: Metric = Metric::new(
+        "sqlite_target_write_count",
+        "the number of batches written to the database",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const WRITE_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "sqlite_target_write_error_count",
+        "the number of batches that could not be written",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const WRITTEN_ROW_COUNT_METRIC: Metric = Metric::new(
+        "sqlite_target_written_row_count",
+        "the number of rows written to the database",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DROPPED_ROW_COUNT_METRIC: Metric = Metric::new(
+        "sqlite_target_dropped_row_count",
+        "the number of rows dropped because their batch could not be \
+        written or because too many batches were waiting",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const PENDING_BATCH_COUNT_METRIC: Metric = Metric::new(
+        "sqlite_target_pending_batch_count",
+        "the number of batches waiting to be written",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const LAST_WRITE_DURATION_METRIC: Metric = Metric::new(
+        "sqlite_target_last_write_duration",
+        "how long the last successful write took",
+        MetricType::Gauge,
+        MetricUnit::Millisecond,
+    );
+    const WRITE_DURATION_METRIC: Metric = Metric::new(
+        "sqlite_target_write_duration",
+        "how long all successful writes took together",
+        MetricType::Counter,
+        MetricUnit::Millisecond,
+    );
+}
+
+impl metrics::Source for SqliteMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::WRITE_COUNT_METRIC,
+            Some(unit_name),
+            self.write_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::WRITE_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.write_error_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::WRITTEN_ROW_COUNT_METRIC,
+            Some(unit_name),
+            self.written_row_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DROPPED_ROW_COUNT_METRIC,
+            Some(unit_name),
+            self.dropped_row_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::PENDING_BATCH_COUNT_METRIC,
+            Some(unit_name),
+            self.pending_batch_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::LAST_WRITE_DURATION_METRIC,
+            Some(unit_name),
+            self.last_write_duration_ms.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::WRITE_DURATION_METRIC,
+            Some(unit_name),
+            self.write_duration_ms.load(SeqCst),
+        );
+    }
+}
@@ -0,0 +1,3 @@
+mod metrics;
+mod sql;
+pub mod target;
@@ -0,0 +1,241 @@
+//! The SQL sent to the database.
+//!
+//! Both tables have the columns described in the [`row`] module. Timestamps
+//! are stored as RFC 3339 text, which the date and time functions of SQLite
+//! understand, and lists as JSON arrays, which its JSON functions do.
+//!
+//! The values of rows are always bound as parameters of prepared
+//! statements. Only the table names become part of the SQL itself, so they
+//! are checked with [`check_table_name`] before they are used.
+//!
+//! [`row`]: crate::targets::file::row
+
+use std::fmt::Write;
+
+use rusqlite::{params_from_iter, types::Value as SqlValue, Connection};
+
+use crate::targets::file::row::{ColumnType, Row, Value, COLUMNS};
+
+/// The index of the prefix among the columns.
+const PREFIX: usize = 3;
+
+/// The index of the peer address among the columns.
+const PEER_IP: usize = 7;
+
+//------------ Statements ----------------------------------------------------
+
+/// The statements for a routes and a history table.
+#[derive(Clone, Debug)]
+pub struct Statements {
+    /// The statements creating the tables and their indexes.
+    pub schema: String,
+
+    /// Adds a row to the history table.
+    insert_history: String,
+
+    /// Adds a row to the routes table.
+    insert_route: String,
+
+    /// Removes the route of a prefix and peer from the routes table.
+    delete_route: String,
+
+    /// Removes all routes of a peer from the routes table.
+    delete_peer: String,
+}
+
+impl Statements {
+    /// Checks the table names and returns the statements for them.
+    pub fn new(
+        routes_table: &str,
+        history_table: &str,
+    ) -> Result<Self, String> {
+        check_table_name(routes_table)?;
+        check_table_name(history_table)?;
+        let placeholders = (1..=COLUMNS.len())
+            .map(|index| format!("?{index}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Ok(Self {
+            schema: schema(routes_table, history_table),
+            insert_history: format!(
+                "INSERT INTO {history_table} VALUES ({placeholders})"
+            ),
+            insert_route: format!(
+                "INSERT INTO {routes_table} VALUES ({placeholders})"
+            ),
+            delete_route: format!(
+                "DELETE FROM {routes_table} \
+                WHERE prefix IS ?1 AND peer_ip IS ?2"
+            ),
+            delete_peer: format!(
+                "DELETE FROM {routes_table} WHERE peer_ip IS ?1"
+            ),
+        })
+    }
+
+    /// Applies a batch of rows in a single transaction.
+    ///
+    /// Every row is added to the history table. In the routes table, an
+    /// announced route replaces the route of the same prefix and peer, a
+    /// withdrawn route removes it, and a session going down removes all
+    /// routes of the peer. If any statement fails, the transaction is
+    /// rolled back.
+    pub fn apply(
+        &self,
+        conn: &mut Connection,
+        rows: &[Row],
+    ) -> rusqlite::Result<()> {
+        let tx = conn.transaction()?;
+        {
+            let mut insert_history =
+                tx.prepare_cached(&self.insert_history)?;
+            let mut insert_route = tx.prepare_cached(&self.insert_route)?;
+            let mut delete_route = tx.prepare_cached(&self.delete_route)?;
+            let mut delete_peer = tx.prepare_cached(&self.delete_peer)?;
+            for row in rows {
+                let values = values(row);
+                insert_history.execute(params_from_iter(&values))?;
+                let (prefix, peer_ip) = (&values[PREFIX], &values[PEER_IP]);
+                match row.kind {
+                    "route" | "announce" | "withdraw" => {
+                        delete_route.execute((prefix, peer_ip))?;
+                        if row.kind != "withdraw" {
+                            insert_route
+                                .execute(params_from_iter(&values))?;
+                        }
+                    }
+                    "peer_down" => {
+                        delete_peer.execute([peer_ip])?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        tx.commit()
+    }
+}
+
+//------------ Helpers -------------------------------------------------------
+
+/// Returns the statements creating the tables and their indexes.
+fn schema(routes_table: &str, history_table: &str) -> String {
+    let mut res = String::new();
+    for table in [routes_table, history_table] {
+        let columns = COLUMNS
+            .iter()
+            .map(|column| {
+                let column_type = match column.column_type {
+                    ColumnType::Long => "INTEGER",
+                    _ => "TEXT",
+                };
+                let not_null = if column.nullable { "" } else { " NOT NULL" };
+                format!("{} {column_type}{not_null}", column.name)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let _ =
+            writeln!(res, "CREATE TABLE IF NOT EXISTS {table} ({columns});");
+    }
+    let _ = writeln!(
+        res,
+        "CREATE INDEX IF NOT EXISTS {routes_table}_prefix \
+        ON {routes_table} (prefix, peer_ip);\n\
+        CREATE INDEX IF NOT EXISTS {routes_table}_peer \
+        ON {routes_table} (peer_ip);\n\
+        CREATE INDEX IF NOT EXISTS {history_table}_timestamp \
+        ON {history_table} (timestamp);"
+    );
+    res
+}
+
+/// Checks that a table name can be used without quoting.
+pub fn check_table_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    if chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.to_ascii_lowercase().starts_with("sqlite_")
+    {
+        Ok(())
+    } else {
+        Err(format!("invalid table name '{name}'"))
+    }
+}
+
+/// Returns the values of the columns of a row.
+fn values(row: &Row) -> Vec<SqlValue> {
+    (0..COLUMNS.len())
+        .map(|index| match row.value(index) {
+            Value::Null => SqlValue::Null,
+            Value::Long(value) => SqlValue::Integer(value),
+            Value::String(text) => SqlValue::Text(text.into()),
+            value => match value.to_json() {
+                serde_json::Value::String(text) => SqlValue::Text(text),
+                json => SqlValue::Text(json.to_string()),
+            },
+        })
+        .collect()
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+
+    #[test]
+    fn rows_become_values() {
+        let announce = Row {
+            timestamp: DateTime::from_timestamp_micros(1_500_000).unwrap(),
+            topic: "route".into(),
+            kind: "announce",
+            prefix: Some("192.0.2.0/24".into()),
+            as_path: Some(vec![65000, 65001]),
+            peer_ip: Some("10.0.0.1".into()),
+            custom: Some("it's'); DROP TABLE routes; --".into()),
+            ..Default::default()
+        };
+        let text = |text: &str| SqlValue::Text(text.into());
+        assert_eq!(
+            values(&announce),
+            [
+                text("1970-01-01T00:00:01.500000Z"),
+                text("route"),
+                text("announce"),
+                text("192.0.2.0/24"),
+                SqlValue::Null,
+                text("[65000,65001]"),
+                SqlValue::Null,
+                text("10.0.0.1"),
+                SqlValue::Null,
+                text("it's'); DROP TABLE routes; --"),
+            ]
+        );
+
+        let statements = Statements::new("routes", "history").unwrap();
+        assert_eq!(
+            statements.insert_history,
+            "INSERT INTO history VALUES \
+            (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"
+        );
+        assert_eq!(
+            statements.delete_route,
+            "DELETE FROM routes WHERE prefix IS ?1 AND peer_ip IS ?2"
+        );
+    }
+
+    #[test]
+    fn table_names_are_checked() {
+        assert!(check_table_name("routes").is_ok());
+        assert!(check_table_name("_route_history2").is_ok());
+        assert!(check_table_name("").is_err());
+        assert!(check_table_name("2routes").is_err());
+        assert!(check_table_name("routes; DROP").is_err());
+        assert!(check_table_name("sqlite_master").is_err());
+        assert!(Statements::new("routes", "history; DROP").is_err());
+        assert!(Statements::new("my routes", "history").is_err());
+    }
+}
@@ -0,0 +1,486 @@
+//! Storing routes and events in a local SQLite database.
+//!
+//! The `sqlite-out` target turns each route and event it receives into a
+//! row with the columns described in the [`row`] module, and writes it to
+//! the SQLite database at `path`, which is created if needed:
+//!
+//! * The `routes_table` holds the current state: the last route announced
+//!   for each prefix by each peer. A withdrawn route is removed, as are all
+//!   routes of a peer when its session goes down.
+//! * The `history_table` holds every row received, routes as well as peer
+//!   and log events.
+//!
+//! See the [`sql`] module for how the tables look. They are created if they
+//! do not exist yet, so other columns or indexes can be added to tables
+//! created beforehand.
+//!
+//! Rows are collected into batches of up to `batch_size` rows, each batch
+//! being written in a single transaction after at most
+//! `batch_interval_secs`. The database is put in WAL mode, so that it can
+//! be queried while it is written to. Meanwhile, up to
+//! `max_pending_batches` batches wait for their turn; rows beyond that, or
+//! in a batch that could not be written, are dropped.
+//!
+//! The database is written through the SQLite library built into Rotonda,
+//! with the values of rows bound to prepared statements.
+//!
+//! [`row`]: crate::targets::file::row
+//! [`sql`]: super::sql
+
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, warn};
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::sync::mpsc;
+
+use crate::{
+    comms::{Link, Terminated, UnitStatus},
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    targets::file::row::Row,
+};
+
+use super::{
+    metrics::SqliteMetrics,
+    sql::{check_table_name, Statements},
+};
+
+/// How long to wait for a lock held by someone reading the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct Sqlite {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The path of the database file.
+    pub path: PathBuf,
+
+    /// The table holding the current routes.
+    #[serde(default = "Config::default_routes_table")]
+    pub routes_table: String,
+
+    /// The table holding all rows received.
+    #[serde(default = "Config::default_history_table")]
+    pub history_table: String,
+
+    /// The largest number of rows to write at a time.
+    #[serde(default = "Config::default_batch_size")]
+    pub batch_size: usize,
+
+    /// The longest time rows wait before they are written.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_batch_interval_secs")]
+    pub batch_interval_secs: Duration,
+
+    /// How many batches may wait to be written.
+    #[serde(default = "Config::default_max_pending_batches")]
+    pub max_pending_batches: usize,
+}
+
+impl Config {
+    fn default_routes_table() -> String {
+        "routes".into()
+    }
+
+    fn default_history_table() -> String {
+        "history".into()
+    }
+
+    fn default_batch_size() -> usize {
+        10_000
+    }
+
+    fn default_batch_interval_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_pending_batches() -> usize {
+        16
+    }
+
+    /// Checks the settings, returning what is wrong with them.
+    fn check(&self) -> Result<(), String> {
+        check_table_name(&self.routes_table)?;
+        check_table_name(&self.history_table)?;
+        if self.routes_table.eq_ignore_ascii_case(&self.history_table) {
+            return Err("routes_table and history_table must differ".into());
+        }
+        if self.batch_size == 0 || self.max_pending_batches == 0 {
+            return Err(
+                "batch_size and max_pending_batches must be at least 1"
+                    .into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Sqlite {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        if let Err(err) = self.config.check() {
+            error!("Target {}: {err}", component.name());
+            return Err(Terminated);
+        }
+
+        // Open the database before starting, so that a database that can't
+        // be written is noticed right away.
+        let name = component.name().to_string();
+        let config = self.config.clone();
+        let writer = match tokio::task::spawn_blocking(move || {
+            let mut writer = Writer::new(name, &config)?;
+            writer.open()?;
+            Ok::<_, String>(writer)
+        })
+        .await
+        {
+            Ok(Ok(writer)) => writer,
+            Ok(Err(err)) => {
+                error!(
+                    "Target {}: cannot open {}: {err}",
+                    component.name(),
+                    self.config.path.display()
+                );
+                return Err(Terminated);
+            }
+            Err(_) => return Err(Terminated),
+        };
+
+        let metrics = Arc::new(SqliteMetrics::default());
+        component.register_metrics(metrics.clone());
+        SqliteRunner {
+            ingresses: component.ingresses().clone(),
+            metrics,
+            batch_size: self.config.batch_size,
+            max_pending_batches: self.config.max_pending_batches,
+            batch_interval: self.config.batch_interval_secs,
+        }
+        .run(writer, self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ SqliteRunner --------------------------------------------------
+
+struct SqliteRunner {
+    ingresses: Arc<ingress::Register>,
+    metrics: Arc<SqliteMetrics>,
+    batch_size: usize,
+    max_pending_batches: usize,
+    batch_interval: Duration,
+}
+
+impl SqliteRunner {
+    async fn run(
+        self,
+        mut writer: Writer,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let metrics = self.metrics;
+
+        // Batches are written by a thread of their own, as SQLite blocks.
+        let (batch_tx, batch_rx) = mpsc::channel(self.max_pending_batches);
+        writer.metrics = metrics.clone();
+        let write_task =
+            tokio::task::spawn_blocking(move || writer.run(batch_rx));
+        let send = |rows: Vec<Row>| match batch_tx.try_send(rows) {
+            Ok(()) => {
+                metrics.pending_batch_count.fetch_add(1, SeqCst);
+            }
+            Err(err) => {
+                let rows = err.into_inner();
+                warn!(
+                    "Dropping {} rows: too many batches are waiting to be \
+                    written",
+                    rows.len()
+                );
+                metrics.dropped_row_count.fetch_add(rows.len(), SeqCst);
+            }
+        };
+
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        let mut rows = Vec::new();
+        let mut flush = tokio::time::interval(self.batch_interval);
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the sqlite-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        let update = Row::for_update(update, &self.ingresses);
+                        for row in update {
+                            rows.push(row);
+                            if rows.len() >= self.batch_size {
+                                send(std::mem::take(&mut rows));
+                            }
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of sqlite-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+
+                _ = flush.tick() => {
+                    if !rows.is_empty() {
+                        send(std::mem::take(&mut rows));
+                    }
+                }
+            }
+        }
+
+        // Write what is left before stopping.
+        if !rows.is_empty() {
+            send(rows);
+        }
+        drop(batch_tx);
+        let _ = write_task.await;
+        Err(Terminated)
+    }
+}
+
+//------------ Writer --------------------------------------------------------
+
+/// Writes batches to the database.
+struct Writer {
+    name: String,
+    path: PathBuf,
+    statements: Statements,
+
+    /// The connection to the database, if it is open.
+    conn: Option<Connection>,
+    metrics: Arc<SqliteMetrics>,
+}
+
+impl Writer {
+    fn new(name: String, config: &Config) -> Result<Self, String> {
+        Ok(Self {
+            name,
+            path: config.path.clone(),
+            statements: Statements::new(
+                &config.routes_table,
+                &config.history_table,
+            )?,
+            conn: None,
+            metrics: Default::default(),
+        })
+    }
+
+    /// Opens the database and creates the tables, unless already done.
+    fn open(&mut self) -> Result<(), String> {
+        if self.conn.is_none() {
+            let conn = Connection::open(&self.path)
+                .and_then(|conn| {
+                    conn.busy_timeout(BUSY_TIMEOUT)?;
+                    conn.pragma_update_and_check(
+                        None,
+                        "journal_mode",
+                        "WAL",
+                        |_| Ok(()),
+                    )?;
+                    conn.pragma_update(None, "synchronous", "NORMAL")?;
+                    conn.execute_batch(&self.statements.schema)?;
+                    Ok(conn)
+                })
+                .map_err(|err| err.to_string())?;
+            self.conn = Some(conn);
+        }
+        Ok(())
+    }
+
+    /// Writes batches until there are no more.
+    fn run(mut self, mut batch_rx: mpsc::Receiver<Vec<Row>>) {
+        while let Some(rows) = batch_rx.blocking_recv() {
+            self.metrics.pending_batch_count.fetch_sub(1, SeqCst);
+            self.write(&rows);
+        }
+    }
+
+    fn write(&mut self, rows: &[Row]) {
+        let started = Instant::now();
+        let res = self.open().and_then(|()| {
+            let conn = self.conn.as_mut().ok_or("the database is closed")?;
+            self.statements
+                .apply(conn, rows)
+                .map_err(|err| err.to_string())
+        });
+        match res {
+            Ok(()) => {
+                let duration = started.elapsed().as_millis() as u64;
+                let metrics = &self.metrics;
+                metrics.write_count.fetch_add(1, SeqCst);
+                metrics.written_row_count.fetch_add(rows.len(), SeqCst);
+                metrics.last_write_duration_ms.store(duration, SeqCst);
+                metrics.write_duration_ms.fetch_add(duration, SeqCst);
+            }
+            Err(err) => {
+                // The transaction has been rolled back. The database is
+                // opened again for the next batch, in case it was the
+                // connection that failed.
+                self.conn = None;
+                self.metrics.write_error_count.fetch_add(1, SeqCst);
+                self.metrics.dropped_row_count.fetch_add(rows.len(), SeqCst);
+                error!(
+                    "Target {}: dropping {} rows as writing to {} failed: \
+                    {err}",
+                    self.name,
+                    rows.len(),
+                    self.path.display()
+                );
+            }
+        }
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rusqlite::types::Value;
+
+    use super::*;
+
+    fn mk_config(path: &Path) -> Config {
+        toml::from_str(&format!("path = {:?}", path.display().to_string()))
+            .unwrap()
+    }
+
+    /// Returns the rows of a query, with the columns separated by `|`.
+    fn query(path: &Path, sql: &str) -> Vec<String> {
+        let conn = Connection::open(path).unwrap();
+        let mut stmt = conn.prepare(sql).unwrap();
+        let columns = stmt.column_count();
+        stmt.query_map([], |row| {
+            (0..columns)
+                .map(|index| {
+                    Ok(match row.get(index)? {
+                        Value::Integer(value) => value.to_string(),
+                        Value::Text(text) => text,
+                        value => format!("{value:?}"),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(|values| values.join("|"))
+        })
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+    }
+
+    #[test]
+    fn config_is_checked() {
+        let config = |extra: &str| -> Config {
+            toml::from_str(&format!("path = \"/tmp/rotonda.db\"\n{extra}"))
+                .unwrap()
+        };
+        assert!(config("").check().is_ok());
+        assert!(config("routes_table = \"rib\"").check().is_ok());
+        assert!(config("routes_table = \"my routes\"").check().is_err());
+        assert!(config("history_table = \"ROUTES\"").check().is_err());
+        assert!(config("batch_size = 0").check().is_err());
+    }
+
+    #[test]
+    fn rows_are_written() {
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-sqlite-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rotonda.db");
+        let mut writer =
+            Writer::new("sqlite".into(), &mk_config(&path)).unwrap();
+        writer.open().unwrap();
+
+        let route = |kind, prefix: &str, peer_ip: &str| Row {
+            topic: "route".into(),
+            kind,
+            prefix: Some(prefix.into()),
+            peer_ip: Some(peer_ip.into()),
+            ..Default::default()
+        };
+        writer.write(&[
+            route("announce", "192.0.2.0/24", "10.0.0.1"),
+            route("announce", "192.0.2.0/24", "10.0.0.2"),
+            route("announce", "198.51.100.0/24", "10.0.0.1"),
+            route("announce", "192.0.2.0/24", "10.0.0.1"),
+        ]);
+        writer.write(&[
+            route("withdraw", "198.51.100.0/24", "10.0.0.1"),
+            Row {
+                prefix: None,
+                ..route("peer_down", "", "10.0.0.2")
+            },
+        ]);
+
+        // Values are never taken for SQL.
+        writer.write(&[Row {
+            custom: Some("x'); DROP TABLE routes; --".into()),
+            ..route("log", "", "10.0.0.3")
+        }]);
+
+        // A failing batch leaves nothing behind and the database is opened
+        // again for the next one.
+        let statements = writer.statements.clone();
+        writer.statements = Statements::new("routes", "missing").unwrap();
+        writer.write(&[route("announce", "203.0.113.0/24", "10.0.0.1")]);
+        assert!(writer.conn.is_none());
+        writer.statements = statements;
+        writer.write(&[route("log", "", "10.0.0.3")]);
+        drop(writer.conn.take());
+
+        assert_eq!(
+            query(&path, "SELECT prefix, peer_ip FROM routes"),
+            ["192.0.2.0/24|10.0.0.1"]
+        );
+        assert_eq!(
+            query(&path, "SELECT kind, count(*) FROM history GROUP BY kind"),
+            ["announce|4", "log|2", "peer_down|1", "withdraw|1"]
+        );
+        assert_eq!(
+            query(&path, "SELECT custom FROM history WHERE custom NOT NULL"),
+            ["x'); DROP TABLE routes; --"]
+        );
+        assert_eq!(query(&path, "PRAGMA journal_mode"), ["wal"]);
+        assert_eq!(writer.metrics.written_row_count.load(SeqCst), 8);
+        assert_eq!(writer.metrics.dropped_row_count.load(SeqCst), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
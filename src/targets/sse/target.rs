@@ -0,0 +1,665 @@
+//! Streaming updates to browsers with Server-Sent Events.
+//!
+//! The `sse-out` target serves an event stream at `http_api_path` of the
+//! HTTP API, `/stream` by default, for simple live views in a browser with
+//! `EventSource`. Clients select what they receive with any number of
+//! `filter` query parameters, each a JSON object with the fields described
+//! in the [`filter`] module, e.g. `/stream?filter={"origin_as":65000}`
+//! once URL encoded. Each route and event the target receives becomes a row
+//! with the columns described in the [`row`] module, which is sent as an
+//! `update` event with the row as JSON data to the clients with a matching
+//! filter, or to all clients if they gave none.
+//!
+//! Every update carries an ID. A client reconnecting with the
+//! `Last-Event-ID` header, as `EventSource` does by itself, first receives
+//! the updates it missed, as far as they are among the last `history`
+//! ones. Updates missed beyond those, and those a client misses because it
+//! falls behind by more than `queue_size` rows, are reported with a
+//! `dropped` event with their `count`. The IDs start over when Rotonda
+//! restarts, so a client resuming from an ID that was not handed out yet
+//! only receives new updates.
+//!
+//! A comment is sent every `heartbeat_secs` seconds, so that proxies keep
+//! idle streams open and clients that went away are noticed. At most
+//! `max_clients` clients can be connected at the same time; further
+//! requests are refused with 503 Service Unavailable.
+//!
+//! [`filter`]: crate::targets::websocket::filter
+//! [`row`]: crate::targets::file::row
+
+use std::{
+    collections::VecDeque,
+    sync::{atomic::Ordering::SeqCst, Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use hyper::{body::Sender, Body, Method, Request, Response, StatusCode};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::{
+    common::openapi::{self, Schema},
+    comms::{Link, Terminated, UnitStatus},
+    http::{
+        check_unused_params, extract_params, get_all_params,
+        PercentDecodedPath, ProcessRequest,
+    },
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    targets::{
+        file::row::{Row, COLUMNS},
+        websocket::filter::Filter,
+    },
+};
+
+use super::metrics::SseMetrics;
+
+/// The comment sent to keep a stream alive.
+const HEARTBEAT: &[u8] = b": heartbeat\n\n";
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct SseOut {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The path of the event stream in the HTTP API.
+    #[serde(default = "Config::default_http_api_path")]
+    http_api_path: String,
+
+    /// The most clients connected at the same time.
+    #[serde(default = "Config::default_max_clients")]
+    max_clients: usize,
+
+    /// The most filters of a single client.
+    #[serde(default = "Config::default_max_filters")]
+    max_filters: usize,
+
+    /// How many rows a client can fall behind.
+    #[serde(default = "Config::default_queue_size")]
+    queue_size: usize,
+
+    /// How many of the last updates to keep for clients resuming.
+    #[serde(default = "Config::default_history")]
+    history: usize,
+
+    /// How often to send a heartbeat, in seconds.
+    #[serde(default = "Config::default_heartbeat_secs")]
+    heartbeat_secs: u64,
+}
+
+impl Config {
+    fn default_http_api_path() -> String {
+        "/stream".to_string()
+    }
+
+    fn default_max_clients() -> usize {
+        100
+    }
+
+    fn default_max_filters() -> usize {
+        32
+    }
+
+    fn default_queue_size() -> usize {
+        1000
+    }
+
+    fn default_history() -> usize {
+        1000
+    }
+
+    fn default_heartbeat_secs() -> u64 {
+        15
+    }
+}
+
+impl SseOut {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let config = self.config;
+        if config.queue_size == 0 || config.heartbeat_secs == 0 {
+            error!(
+                "Target {}: queue_size and heartbeat_secs must be at least 1",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+
+        let metrics = Arc::new(SseMetrics::default());
+        component.register_metrics(metrics.clone());
+        let server = Arc::new(SseServer::new(&config, metrics));
+        component
+            .register_http_resource(server.clone(), &config.http_api_path);
+        info!(
+            "Target {}: streaming events at {}",
+            component.name(),
+            config.http_api_path
+        );
+        SseRunner {
+            server,
+            ingresses: component.ingresses().clone(),
+        }
+        .run(self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ SseRunner -----------------------------------------------------
+
+struct SseRunner {
+    server: Arc<SseServer>,
+    ingresses: Arc<ingress::Register>,
+}
+
+impl SseRunner {
+    async fn run(
+        self,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the sse-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.server.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        // Without clients or history, there is no need to
+                        // encode rows.
+                        if self.server.is_idle() {
+                            continue;
+                        }
+                        let rows = Row::for_update(update, &self.ingresses);
+                        for row in rows {
+                            self.server.publish(row);
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of sse-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+            }
+        }
+
+        // Dropping the server, and with it the sender of the updates, ends
+        // the streams of the clients.
+        Err(Terminated)
+    }
+}
+
+//------------ Event ---------------------------------------------------------
+
+/// A row along with its encoding as an update event, shared by all
+/// clients.
+#[derive(Debug)]
+struct Event {
+    id: u64,
+    row: Row,
+    message: Bytes,
+}
+
+impl Event {
+    fn new(id: u64, row: Row) -> Self {
+        let data = COLUMNS
+            .iter()
+            .map(|column| {
+                (column.name.to_string(), row.json_value(column.name))
+            })
+            .collect::<serde_json::Map<_, _>>();
+        // Encoded JSON has no line breaks, so fits on a single data line.
+        let message = format!(
+            "id: {id}\nevent: update\ndata: {}\n\n",
+            serde_json::Value::Object(data)
+        );
+        Self {
+            id,
+            row,
+            message: message.into(),
+        }
+    }
+}
+
+//------------ SseServer -----------------------------------------------------
+
+/// The event stream resource of the HTTP API.
+struct SseServer {
+    http_api_path: String,
+    history: Mutex<History>,
+    max_clients: usize,
+    max_filters: usize,
+    heartbeat: Duration,
+    metrics: Arc<SseMetrics>,
+}
+
+/// The last updates, kept for clients resuming.
+///
+/// New updates are sent while holding the lock on the history, so that a
+/// client subscribing sees each update either in the history or as a new
+/// one.
+struct History {
+    updates: broadcast::Sender<Arc<Event>>,
+    kept: VecDeque<Arc<Event>>,
+    size: usize,
+    next_id: u64,
+}
+
+impl SseServer {
+    fn new(config: &Config, metrics: Arc<SseMetrics>) -> Self {
+        let (updates, _) = broadcast::channel(config.queue_size);
+        Self {
+            http_api_path: config.http_api_path.clone(),
+            history: Mutex::new(History {
+                updates,
+                kept: VecDeque::with_capacity(config.history),
+                size: config.history,
+                next_id: 0,
+            }),
+            max_clients: config.max_clients,
+            max_filters: config.max_filters,
+            heartbeat: Duration::from_secs(config.heartbeat_secs),
+            metrics,
+        }
+    }
+
+    /// Returns whether updates are neither sent nor kept.
+    fn is_idle(&self) -> bool {
+        let history = self.history.lock().unwrap();
+        history.size == 0 && history.updates.receiver_count() == 0
+    }
+
+    /// Sends a row to the clients, keeping it for those resuming.
+    fn publish(&self, row: Row) {
+        let mut history = self.history.lock().unwrap();
+        let event = Arc::new(Event::new(history.next_id, row));
+        history.next_id += 1;
+        if history.size > 0 {
+            if history.kept.len() >= history.size {
+                history.kept.pop_front();
+            }
+            history.kept.push_back(event.clone());
+        }
+        let _ = history.updates.send(event);
+    }
+
+    /// Subscribes to new updates.
+    ///
+    /// Returns the receiver of new updates, the kept updates after
+    /// `last_id` and the number of updates after `last_id` that are no
+    /// longer kept.
+    fn subscribe(
+        &self,
+        last_id: Option<u64>,
+    ) -> (broadcast::Receiver<Arc<Event>>, Vec<Arc<Event>>, u64) {
+        let history = self.history.lock().unwrap();
+        let updates = history.updates.subscribe();
+        let Some(last_id) = last_id.filter(|id| *id < history.next_id) else {
+            return (updates, Vec::new(), 0);
+        };
+        let first = history
+            .kept
+            .front()
+            .map_or(history.next_id, |event| event.id);
+        let missed = first.saturating_sub(last_id + 1);
+        let replay = history
+            .kept
+            .iter()
+            .filter(|event| event.id > last_id)
+            .cloned()
+            .collect();
+        (updates, replay, missed)
+    }
+
+    /// Opens the event stream for a request.
+    fn open(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, (StatusCode, String)> {
+        let bad_request = |message| (StatusCode::BAD_REQUEST, message);
+        let params = extract_params(request);
+        let filters = get_all_params(&params, "filter")
+            .into_iter()
+            .map(|filter| {
+                serde_json::from_str::<Filter>(filter.value()).map_err(
+                    |err| {
+                        format!("Invalid filter '{}': {err}", filter.value())
+                    },
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(bad_request)?;
+        check_unused_params(&params).map_err(bad_request)?;
+        if filters.len() > self.max_filters {
+            return Err(bad_request(format!(
+                "More than {} filters",
+                self.max_filters
+            )));
+        }
+        if self.metrics.client_count.load(SeqCst) >= self.max_clients {
+            self.metrics.refused_count.fetch_add(1, SeqCst);
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Too many clients".to_string(),
+            ));
+        }
+
+        // An ID we did not hand out is taken as no ID at all.
+        let last_id = request
+            .headers()
+            .get("Last-Event-ID")
+            .and_then(|id| id.to_str().ok())
+            .and_then(|id| id.trim().parse::<u64>().ok());
+        let (updates, replay, missed) = self.subscribe(last_id);
+        if last_id.is_some() {
+            self.metrics.resumed_count.fetch_add(1, SeqCst);
+        }
+        self.metrics.client_count.fetch_add(1, SeqCst);
+        self.metrics.connection_count.fetch_add(1, SeqCst);
+
+        let client = Client {
+            filters,
+            updates,
+            heartbeat: self.heartbeat,
+            metrics: self.metrics.clone(),
+        };
+        let metrics = self.metrics.clone();
+        let (mut tx, body) = Body::channel();
+        tokio::spawn(async move {
+            match client.serve(&mut tx, replay, missed).await {
+                Ok(()) => debug!("SSE stream ended"),
+                Err(err) => debug!("SSE client went away: {err}"),
+            }
+            metrics.client_count.fetch_sub(1, SeqCst);
+        });
+        Ok(Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "text/event-stream")
+            .header(hyper::header::CACHE_CONTROL, "no-cache")
+            .body(body)
+            .unwrap())
+    }
+}
+
+//--- ProcessRequest
+
+#[async_trait]
+impl ProcessRequest for SseServer {
+    async fn process_request(
+        &self,
+        request: &Request<Body>,
+    ) -> Option<Response<Body>> {
+        if request.method() != Method::GET
+            || *request.uri().decoded_path() != *self.http_api_path
+        {
+            return None;
+        }
+        Some(self.open(request).unwrap_or_else(|(status, message)| {
+            Response::builder()
+                .status(status)
+                .header(hyper::header::CONTENT_TYPE, "text/plain")
+                .body(message.into())
+                .unwrap()
+        }))
+    }
+
+    fn describe(&self, paths: &mut openapi::Paths) {
+        paths
+            .get(self.http_api_path.as_str(), "Stream the route updates")
+            .produces("text/event-stream")
+            .description(
+                "A client reconnecting with the Last-Event-ID header first \
+                receives the updates it missed, if still buffered.",
+            )
+            .repeated_query(
+                "filter",
+                Schema::String,
+                "A JSON filter the updates must match, as for the \
+                websocket-out target; updates matching any filter are sent",
+            );
+    }
+}
+
+//------------ Client --------------------------------------------------------
+
+/// A connected client.
+struct Client {
+    filters: Vec<Filter>,
+    updates: broadcast::Receiver<Arc<Event>>,
+    heartbeat: Duration,
+    metrics: Arc<SseMetrics>,
+}
+
+impl Client {
+    /// Sends the missed and then the new updates matching the filters of
+    /// the client, until it goes away or the target stops.
+    async fn serve(
+        mut self,
+        tx: &mut Sender,
+        replay: Vec<Arc<Event>>,
+        missed: u64,
+    ) -> Result<(), hyper::Error> {
+        if missed > 0 {
+            self.dropped(tx, missed).await?;
+        }
+        for event in replay {
+            self.send(tx, &event).await?;
+        }
+
+        let mut heartbeat = tokio::time::interval_at(
+            tokio::time::Instant::now() + self.heartbeat,
+            self.heartbeat,
+        );
+        heartbeat
+            .set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            tokio::select! {
+                event = self.updates.recv() => match event {
+                    Ok(event) => self.send(tx, &event).await?,
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        self.dropped(tx, count).await?
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return Ok(())
+                    }
+                },
+
+                _ = heartbeat.tick() => {
+                    tx.send_data(Bytes::from_static(HEARTBEAT)).await?
+                }
+            }
+        }
+    }
+
+    /// Sends an update if it matches the filters.
+    async fn send(
+        &self,
+        tx: &mut Sender,
+        event: &Event,
+    ) -> Result<(), hyper::Error> {
+        if self.filters.is_empty()
+            || self.filters.iter().any(|filter| filter.matches(&event.row))
+        {
+            tx.send_data(event.message.clone()).await?;
+            self.metrics.sent_count.fetch_add(1, SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Tells the client how many updates it missed.
+    async fn dropped(
+        &self,
+        tx: &mut Sender,
+        count: u64,
+    ) -> Result<(), hyper::Error> {
+        let count = usize::try_from(count).unwrap_or(usize::MAX);
+        self.metrics.dropped_count.fetch_add(count, SeqCst);
+        let message =
+            format!("event: dropped\ndata: {}\n\n", json!({"count": count}));
+        tx.send_data(message.into()).await
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use hyper::body::HttpBody;
+
+    use super::*;
+
+    fn server(history: usize, max_clients: usize) -> SseServer {
+        let mut config: Config = toml::from_str("").unwrap();
+        config.history = history;
+        config.max_clients = max_clients;
+        let mut server = SseServer::new(&config, Default::default());
+        server.heartbeat = Duration::from_millis(50);
+        server
+    }
+
+    fn route(prefix: &str) -> Row {
+        Row {
+            topic: "bgp".into(),
+            kind: "announce",
+            prefix: Some(prefix.into()),
+            ..Default::default()
+        }
+    }
+
+    fn get(uri: &str, last_id: Option<&str>) -> Request<Body> {
+        let mut request = Request::get(uri);
+        if let Some(last_id) = last_id {
+            request = request.header("Last-Event-ID", last_id);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    async fn next(body: &mut Body) -> Option<String> {
+        let data = body.data().await?.unwrap();
+        Some(String::from_utf8(data.to_vec()).unwrap())
+    }
+
+    async fn next_update(body: &mut Body) -> Option<String> {
+        loop {
+            let next = next(body).await?;
+            if next.as_bytes() != HEARTBEAT {
+                return Some(next);
+            }
+        }
+    }
+
+    #[test]
+    fn resuming_clients_get_what_they_missed() {
+        let server = server(2, 1);
+        for prefix in ["192.0.2.0/24", "10.0.0.0/8", "10.1.0.0/16"] {
+            server.publish(route(prefix));
+        }
+        let ids = |replay: Vec<Arc<Event>>| {
+            replay.iter().map(|event| event.id).collect::<Vec<_>>()
+        };
+
+        let (_, replay, missed) = server.subscribe(Some(1));
+        assert_eq!((ids(replay), missed), (vec![2], 0));
+        let (_, replay, missed) = server.subscribe(Some(0));
+        assert_eq!((ids(replay), missed), (vec![1, 2], 0));
+
+        // Only the last two updates are kept.
+        server.publish(route("10.2.0.0/16"));
+        let (_, replay, missed) = server.subscribe(Some(0));
+        assert_eq!((ids(replay), missed), (vec![2, 3], 1));
+
+        // Up to date, new and unknown IDs get nothing.
+        for last_id in [Some(3), None, Some(4)] {
+            let (_, replay, missed) = server.subscribe(last_id);
+            assert_eq!((ids(replay), missed), (vec![], 0));
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_receive_what_they_filter() {
+        let server = Arc::new(server(10, 1));
+        server.publish(route("10.0.0.0/8"));
+        server.publish(route("192.0.2.0/24"));
+        server.publish(route("10.1.0.0/16"));
+
+        assert!(server.process_request(&get("/other", None)).await.is_none());
+        for (uri, status) in [
+            ("/stream?filter=%7B", StatusCode::BAD_REQUEST),
+            ("/stream?filter=%7B%22as%22%3A1%7D", StatusCode::BAD_REQUEST),
+            ("/stream?since=1", StatusCode::BAD_REQUEST),
+        ] {
+            let response =
+                server.process_request(&get(uri, None)).await.unwrap();
+            assert_eq!(response.status(), status, "{uri}");
+        }
+
+        let response = server
+            .process_request(&get(
+                "/stream?filter=%7B%22prefix%22%3A%2210.0.0.0%2F8%22%7D",
+                Some("0"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+        let mut body = response.into_body();
+
+        // The missed update matching the filter.
+        let update = next(&mut body).await.unwrap();
+        assert!(update.starts_with("id: 2\nevent: update\ndata: {"));
+        assert!(update.contains(r#""prefix":"10.1.0.0/16""#));
+        assert!(update.ends_with("}\n\n"));
+
+        // Only one client at a time.
+        let response =
+            server.process_request(&get("/stream", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.metrics.refused_count.load(SeqCst), 1);
+
+        // New updates, and heartbeats while there are none.
+        server.publish(route("198.51.100.0/24"));
+        server.publish(route("10.2.0.0/16"));
+        assert!(next_update(&mut body).await.unwrap().starts_with("id: 4\n"));
+        assert_eq!(next(&mut body).await.unwrap(), ": heartbeat\n\n");
+
+        // Dropping the server ends the stream.
+        let metrics = server.metrics.clone();
+        drop(server);
+        assert!(next_update(&mut body).await.is_none());
+        assert_eq!(metrics.sent_count.load(SeqCst), 2);
+        assert_eq!(metrics.resumed_count.load(SeqCst), 1);
+    }
+}
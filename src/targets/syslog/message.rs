@@ -0,0 +1,253 @@
+//! Formatting rows as RFC 5424 syslog messages.
+//!
+//! A message looks like this:
+//!
+//! ```text
+//! <28>1 2024-11-21T12:00:00.000000Z rr1 rotonda 4711 peer_down
+//!     [rotonda@32473 topic="session" peer_ip="192.0.2.1" peer_as="65000"]
+//!     Peer 192.0.2.1 AS65000 went down
+//! ```
+//!
+//! (without the line breaks). The message ID is the kind of the row, the
+//! structured data element holds the other columns that have a value, with
+//! the elements of lists separated by spaces.
+
+use std::fmt::Write;
+
+use chrono::SecondsFormat;
+use serde::Deserialize;
+
+use crate::targets::file::row::{Row, Value, COLUMNS};
+
+//------------ Facility ------------------------------------------------------
+
+/// The names of the facilities, by their code.
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news",
+    "uucp", "cron", "authpriv", "ftp", "ntp", "audit", "alert", "clockd",
+    "local0", "local1", "local2", "local3", "local4", "local5", "local6",
+    "local7",
+];
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Facility(u8);
+
+impl Default for Facility {
+    fn default() -> Self {
+        Facility(3) // daemon
+    }
+}
+
+impl TryFrom<String> for Facility {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        FACILITIES
+            .iter()
+            .position(|name| *name == value)
+            .map(|code| Facility(code as u8))
+            .ok_or_else(|| format!("unknown syslog facility {value}"))
+    }
+}
+
+//------------ Severity ------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Emerg = 0,
+    Alert = 1,
+    Crit = 2,
+    Err = 3,
+    Warning = 4,
+    Notice = 5,
+    Info = 6,
+    Debug = 7,
+}
+
+impl Severity {
+    /// Returns the default severity of rows of the given kind.
+    pub fn for_kind(kind: &str) -> Self {
+        match kind {
+            "peer_down" => Severity::Warning,
+            "peer_up" | "custom" => Severity::Notice,
+            _ => Severity::Info,
+        }
+    }
+}
+
+//------------ MessageFormat -------------------------------------------------
+
+/// The parts of the messages that are the same for all rows.
+#[derive(Clone, Debug)]
+pub struct MessageFormat {
+    pub facility: Facility,
+    pub hostname: String,
+    pub app_name: String,
+    pub proc_id: String,
+
+    /// The ID of the structured data element.
+    pub sd_id: String,
+}
+
+impl MessageFormat {
+    /// Returns the message for a row.
+    pub fn format(&self, row: &Row, severity: Severity) -> String {
+        let mut res = String::new();
+        let _ = write!(
+            res,
+            "<{}>1 {} {} {} {} {} [{}",
+            u16::from(self.facility.0) * 8 + severity as u16,
+            row.timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+            header_field(&self.hostname, 255),
+            header_field(&self.app_name, 48),
+            header_field(&self.proc_id, 128),
+            header_field(row.kind, 32),
+            self.sd_id,
+        );
+        for (index, column) in COLUMNS.iter().enumerate() {
+            if matches!(column.name, "timestamp" | "kind") {
+                continue;
+            }
+            let value = row.value(index);
+            if value == Value::Null {
+                continue;
+            }
+            let _ = write!(res, " {}=\"", column.name);
+            push_param_value(&mut res, value);
+            res.push('"');
+        }
+        res.push_str("] ");
+        res.push_str(&text(row));
+        res
+    }
+}
+
+/// Returns the human readable part of the message for a row.
+pub(crate) fn text(row: &Row) -> String {
+    let peer = || match (&row.peer_ip, row.peer_as) {
+        (Some(ip), Some(asn)) => format!("{ip} AS{asn}"),
+        (Some(ip), None) => ip.clone(),
+        (None, Some(asn)) => format!("AS{asn}"),
+        (None, None) => "unknown".into(),
+    };
+    match row.kind {
+        "peer_up" => format!("Peer {} is up", peer()),
+        "peer_down" => format!("Peer {} went down", peer()),
+        "announce" | "withdraw" | "route" => format!(
+            "{} {} from peer {}",
+            row.kind,
+            row.prefix.as_deref().unwrap_or("-"),
+            peer()
+        ),
+        _ => row.custom.clone().unwrap_or_else(|| row.topic.clone()),
+    }
+}
+
+/// Returns a header field, the nil value if empty.
+///
+/// Header fields consist of at most `max_len` printable ASCII characters,
+/// others are replaced by underscores.
+fn header_field(value: &str, max_len: usize) -> String {
+    if value.is_empty() {
+        return "-".into();
+    }
+    value
+        .chars()
+        .take(max_len)
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .collect()
+}
+
+fn push_param_value(buf: &mut String, value: Value) {
+    let mut push_str = |s: &str| {
+        for c in s.chars() {
+            if matches!(c, '"' | '\\' | ']') {
+                buf.push('\\');
+            }
+            buf.push(c);
+        }
+    };
+    match value {
+        Value::Null => {}
+        Value::Timestamp(value) | Value::Long(value) => {
+            push_str(&value.to_string())
+        }
+        Value::String(value) => push_str(value),
+        Value::LongList(values) => push_str(
+            &values
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        Value::StringList(values) => push_str(&values.join(" ")),
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+
+    fn format() -> MessageFormat {
+        MessageFormat {
+            facility: Facility::try_from("local0".to_string()).unwrap(),
+            hostname: "rr 1".into(),
+            app_name: "rotonda".into(),
+            proc_id: "4711".into(),
+            sd_id: "rotonda@32473".into(),
+        }
+    }
+
+    #[test]
+    fn rows_become_messages() {
+        let row = Row {
+            timestamp: DateTime::from_timestamp(1, 0).unwrap(),
+            topic: "session".into(),
+            kind: "peer_down",
+            peer_ip: Some("192.0.2.1".into()),
+            peer_as: Some(65000),
+            ..Default::default()
+        };
+        assert_eq!(
+            format().format(&row, Severity::Warning),
+            "<132>1 1970-01-01T00:00:01.000000Z rr_1 rotonda 4711 peer_down \
+            [rotonda@32473 topic=\"session\" peer_ip=\"192.0.2.1\" \
+            peer_as=\"65000\"] Peer 192.0.2.1 AS65000 went down"
+        );
+    }
+
+    #[test]
+    fn param_values_are_escaped() {
+        let row = Row {
+            timestamp: DateTime::from_timestamp(1, 0).unwrap(),
+            topic: "hijack".into(),
+            kind: "log",
+            as_path: Some(vec![65000, 65001]),
+            custom: Some("origin \"AS65001\" [unexpected]".into()),
+            ..Default::default()
+        };
+        let message = format().format(&row, Severity::Info);
+        assert!(message.starts_with("<134>1 "));
+        assert!(message.ends_with(
+            " log [rotonda@32473 topic=\"hijack\" as_path=\"65000 65001\" \
+            custom=\"origin \\\"AS65001\\\" [unexpected\\]\"] \
+            origin \"AS65001\" [unexpected]"
+        ));
+    }
+
+    #[test]
+    fn facilities_are_parsed() {
+        assert_eq!(Facility::try_from("kern".to_string()), Ok(Facility(0)));
+        assert_eq!(
+            Facility::try_from("local7".to_string()),
+            Ok(Facility(23))
+        );
+        assert!(Facility::try_from("local8".to_string()).is_err());
+    }
+}
@@ -0,0 +1,3 @@
+pub(crate) mod message;
+mod metrics;
+pub mod target;
@@ -0,0 +1,733 @@
+//! Sending events to a syslog server.
+//!
+//! The `syslog-out` target turns the events it receives into RFC 5424
+//! syslog messages as described in the [`message`] module, and sends them
+//! to the server at `url`: over UDP with `udp://`, one message per
+//! datagram, or over TCP with `tcp://` or TLS with `tls://`, framed by
+//! octet counting as per RFC 6587 and RFC 5425. TLS is configured in the
+//! `tls` table, as for the `mqtt-out` target.
+//!
+//! Only the rows of the kinds listed in `events` are sent, and if `topics`
+//! is given, only those with one of these topics. By default, these are
+//! sessions coming up or going down and the log and custom messages of the
+//! filters, such as policy rejections or hijack alerts. As sessions coming
+//! up are not announced as such, a session is considered up when the first
+//! route of it is received, and down when it is withdrawn.
+//!
+//! Messages wait in a queue of up to `queue_size` messages while the server
+//! is unreachable, with connecting being retried with an exponential
+//! backoff. Once the queue is full, further messages are dropped.
+//!
+//! [`message`]: super::message
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::SocketAddr,
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::Duration,
+};
+
+use log::{debug, error, info, warn};
+use rotonda_store::prefix_record::RouteStatus;
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    sync::mpsc,
+};
+use url::Url;
+
+use crate::{
+    common::tls::{TlsClientConfig, TlsConnector},
+    comms::{Link, Terminated, UnitStatus},
+    ingress::{self, IngressId},
+    manager::{Component, TargetCommand, WaitPoint},
+    payload::{Payload, Update},
+    roto_runtime::types::RouteContext,
+    targets::file::row::Row,
+};
+
+use super::{
+    message::{Facility, MessageFormat, Severity},
+    metrics::SyslogMetrics,
+};
+
+/// The kinds of rows that can be sent.
+pub(crate) const KINDS: [&str; 7] = [
+    "route",
+    "announce",
+    "withdraw",
+    "peer_up",
+    "peer_down",
+    "log",
+    "custom",
+];
+
+/// How long to keep trying to send the queued messages when stopping.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct Syslog {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The server to send to, a `udp://`, `tcp://` or `tls://` URL.
+    url: Url,
+
+    #[serde(default)]
+    tls: Option<TlsClientConfig>,
+
+    /// The kinds of rows to send.
+    #[serde(default = "Config::default_events")]
+    events: Vec<String>,
+
+    /// The topics of rows to send, all if not given.
+    #[serde(default)]
+    topics: Option<Vec<String>>,
+
+    #[serde(default)]
+    facility: Facility,
+
+    /// The severity of messages by kind of row, if not the default.
+    #[serde(default)]
+    severities: HashMap<String, Severity>,
+
+    /// The host name in the messages, the name of the system by default.
+    #[serde(default)]
+    hostname: Option<String>,
+
+    #[serde(default = "Config::default_app_name")]
+    app_name: String,
+
+    /// The ID of the structured data element.
+    #[serde(default = "Config::default_sd_id")]
+    sd_id: String,
+
+    /// How many messages may wait to be sent.
+    #[serde(default = "Config::default_queue_size")]
+    queue_size: usize,
+
+    /// How long to wait before connecting again. The delay doubles with
+    /// every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_retry_delay_secs")]
+    retry_delay_secs: Duration,
+
+    /// The longest to wait before connecting again.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "Config::default_max_retry_delay_secs")]
+    max_retry_delay_secs: Duration,
+}
+
+impl Config {
+    fn default_events() -> Vec<String> {
+        ["peer_up", "peer_down", "log", "custom"]
+            .map(String::from)
+            .into()
+    }
+
+    fn default_app_name() -> String {
+        "rotonda".into()
+    }
+
+    fn default_sd_id() -> String {
+        // 32473 is the enterprise number reserved for documentation.
+        "rotonda@32473".into()
+    }
+
+    fn default_queue_size() -> usize {
+        1000
+    }
+
+    fn default_retry_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retry_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if let Some(kind) = self
+            .events
+            .iter()
+            .chain(self.severities.keys())
+            .find(|kind| !KINDS.contains(&kind.as_str()))
+        {
+            return Err(format!("unknown event '{kind}'"));
+        }
+        if self.sd_id.is_empty()
+            || self.sd_id.chars().any(|c| {
+                !c.is_ascii_graphic() || matches!(c, '=' | ']' | '"')
+            })
+        {
+            return Err(format!("invalid sd_id '{}'", self.sd_id));
+        }
+        if self.queue_size == 0 {
+            return Err("queue_size must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+impl Syslog {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let config = self.config;
+        let destination = match config
+            .check()
+            .and_then(|_| Destination::new(&config.url, config.tls.as_ref()))
+        {
+            Ok(destination) => destination,
+            Err(err) => {
+                error!("Target {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+
+        let metrics = Arc::new(SyslogMetrics::default());
+        component.register_metrics(metrics.clone());
+        let format = MessageFormat {
+            facility: config.facility,
+            hostname: config.hostname.unwrap_or_else(system_hostname),
+            app_name: config.app_name,
+            proc_id: std::process::id().to_string(),
+            sd_id: config.sd_id,
+        };
+        let sender = Sender {
+            name: component.name().to_string(),
+            destination,
+            retry_delay: config.retry_delay_secs,
+            max_retry_delay: config.max_retry_delay_secs,
+            metrics: metrics.clone(),
+        };
+        SyslogRunner {
+            events: Events::default(),
+            selected: config.events,
+            topics: config.topics,
+            severities: config.severities,
+            format,
+            ingresses: component.ingresses().clone(),
+            metrics,
+            queue_size: config.queue_size,
+        }
+        .run(sender, self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+/// Returns the name of the system.
+pub(crate) fn system_hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        // SAFETY: gethostname writes at most buf.len() bytes into buf.
+        let res =
+            unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+        if res == 0 {
+            let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+            return String::from_utf8_lossy(&buf[..len]).into_owned();
+        }
+    }
+    String::new()
+}
+
+//------------ SyslogRunner --------------------------------------------------
+
+struct SyslogRunner {
+    events: Events,
+    selected: Vec<String>,
+    topics: Option<Vec<String>>,
+    severities: HashMap<String, Severity>,
+    format: MessageFormat,
+    ingresses: Arc<ingress::Register>,
+    metrics: Arc<SyslogMetrics>,
+    queue_size: usize,
+}
+
+impl SyslogRunner {
+    async fn run(
+        mut self,
+        sender: Sender,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        // Messages are sent by a task of their own, so that an unreachable
+        // server does not hold up the sources.
+        let (tx, rx) = mpsc::channel(self.queue_size);
+        let send_task = tokio::spawn(sender.run(rx));
+
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the syslog-out target requires a \
+                            restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        for message in self.messages(update) {
+                            if tx.try_send(message).is_ok() {
+                                self.metrics.queued_count.fetch_add(1, SeqCst);
+                            } else {
+                                self.metrics
+                                    .dropped_count
+                                    .fetch_add(1, SeqCst);
+                            }
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of syslog-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+            }
+        }
+
+        drop(tx);
+        if tokio::time::timeout(DRAIN_TIMEOUT, send_task)
+            .await
+            .is_err()
+        {
+            warn!(
+                "Dropping {} syslog messages that could not be sent",
+                self.metrics.queued_count.load(SeqCst)
+            );
+        }
+        Err(Terminated)
+    }
+
+    /// Returns the messages for the selected events of an update.
+    fn messages(&mut self, update: Update) -> Vec<String> {
+        self.events
+            .rows(update, &self.ingresses)
+            .into_iter()
+            .filter(|row| {
+                self.selected.iter().any(|kind| kind == row.kind)
+                    && self
+                        .topics
+                        .as_ref()
+                        .is_none_or(|topics| topics.contains(&row.topic))
+            })
+            .map(|row| {
+                let severity = self
+                    .severities
+                    .get(row.kind)
+                    .copied()
+                    .unwrap_or_else(|| Severity::for_kind(row.kind));
+                self.format.format(&row, severity)
+            })
+            .collect()
+    }
+}
+
+//------------ Events --------------------------------------------------------
+
+/// Turns updates into rows, adding sessions coming up.
+#[derive(Debug, Default)]
+pub(crate) struct Events {
+    /// The sessions routes have been received for.
+    active: HashSet<IngressId>,
+}
+
+impl Events {
+    pub(crate) fn rows(
+        &mut self,
+        update: Update,
+        ingresses: &ingress::Register,
+    ) -> Vec<Row> {
+        let mut res = Vec::new();
+        match &update {
+            Update::Single(payload) => self.route(payload, &mut res),
+            Update::Bulk(payloads) => {
+                for payload in payloads {
+                    self.route(payload, &mut res);
+                }
+            }
+            Update::Withdraw(ingress_id, None) => {
+                self.active.remove(ingress_id);
+            }
+            Update::WithdrawBulk(ingress_ids) => {
+                for ingress_id in ingress_ids {
+                    self.active.remove(ingress_id);
+                }
+            }
+            _ => {}
+        }
+        res.extend(Row::for_update(update, ingresses));
+        res
+    }
+
+    /// Adds a row for the session of a route if it is new.
+    fn route(&mut self, payload: &Payload, res: &mut Vec<Row>) {
+        let provenance = match &payload.context {
+            RouteContext::Fresh(ctx) => ctx.provenance(),
+            RouteContext::Mrt(ctx)
+                if ctx.status != RouteStatus::Withdrawn =>
+            {
+                ctx.provenance()
+            }
+            _ => return,
+        };
+        if self.active.insert(provenance.ingress_id) {
+            res.push(Row {
+                timestamp: provenance.timestamp,
+                topic: "session".into(),
+                kind: "peer_up",
+                peer_ip: Some(provenance.peer_ip.to_string()),
+                peer_as: Some(provenance.peer_asn.into_u32()),
+                ..Default::default()
+            });
+        }
+    }
+}
+
+//------------ Destination ---------------------------------------------------
+
+#[derive(Debug)]
+enum Transport {
+    Udp,
+    Tcp,
+    Tls(Box<TlsConnector>),
+}
+
+/// Where and how to send messages.
+#[derive(Debug)]
+pub(crate) struct Destination {
+    transport: Transport,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+}
+
+impl Destination {
+    fn new(url: &Url, tls: Option<&TlsClientConfig>) -> Result<Self, String> {
+        Self::with_ports(url, tls, [514, 601, 6514])
+    }
+
+    /// Creates a destination with the default ports for UDP, TCP and TLS.
+    pub(crate) fn with_ports(
+        url: &Url,
+        tls: Option<&TlsClientConfig>,
+        [udp_port, tcp_port, tls_port]: [u16; 3],
+    ) -> Result<Self, String> {
+        let (transport, default_port) = match url.scheme() {
+            "udp" => (Transport::Udp, udp_port),
+            "tcp" => (Transport::Tcp, tcp_port),
+            "tls" => (
+                Transport::Tls(Box::new(TlsConnector::new(
+                    tls.unwrap_or(&TlsClientConfig::default()),
+                )?)),
+                tls_port,
+            ),
+            scheme => {
+                return Err(format!(
+                    "unsupported URL scheme '{scheme}', use udp, tcp or tls"
+                ))
+            }
+        };
+        let host = url
+            .host_str()
+            .ok_or("missing host in URL")?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        Ok(Self {
+            transport,
+            host,
+            port: url.port().unwrap_or(default_port),
+        })
+    }
+
+    /// Returns whether messages are sent as datagrams.
+    pub(crate) fn is_udp(&self) -> bool {
+        matches!(self.transport, Transport::Udp)
+    }
+
+    pub(crate) async fn connect(&self) -> io::Result<Connection> {
+        let addr: SocketAddr =
+            tokio::net::lookup_host((self.host.as_str(), self.port))
+                .await?
+                .next()
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "no address")
+                })?;
+        match &self.transport {
+            Transport::Udp => {
+                let local: SocketAddr = match addr {
+                    SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+                    SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+                };
+                let socket = UdpSocket::bind(local).await?;
+                socket.connect(addr).await?;
+                Ok(Connection::Udp(socket))
+            }
+            Transport::Tcp => Ok(Connection::Stream(Box::new(
+                TcpStream::connect(addr).await?,
+            ))),
+            Transport::Tls(connector) => {
+                let stream = TcpStream::connect(addr).await?;
+                let stream = connector.connect(&self.host, stream).await?;
+                Ok(Connection::Stream(Box::new(stream)))
+            }
+        }
+    }
+}
+
+pub(crate) enum Connection {
+    Udp(UdpSocket),
+    Stream(Box<dyn AsyncWrite + Send + Unpin>),
+}
+
+impl Connection {
+    async fn send(&mut self, message: &str) -> io::Result<()> {
+        match self {
+            Connection::Udp(socket) => {
+                socket.send(message.as_bytes()).await?;
+            }
+            Connection::Stream(stream) => {
+                let frame = format!("{} {message}", message.len());
+                stream.write_all(frame.as_bytes()).await?;
+                stream.flush().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+//------------ Sender --------------------------------------------------------
+
+/// Sends queued messages, connecting to the server as needed.
+struct Sender {
+    name: String,
+    destination: Destination,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    metrics: Arc<SyslogMetrics>,
+}
+
+impl Sender {
+    /// Sends messages until there are no more.
+    async fn run(self, mut rx: mpsc::Receiver<String>) {
+        let mut connection = None;
+        let mut delay = self.retry_delay;
+        while let Some(message) = rx.recv().await {
+            loop {
+                let conn = match &mut connection {
+                    Some(conn) => conn,
+                    None => match self.destination.connect().await {
+                        Ok(conn) => {
+                            info!(
+                                "Target {}: connected to {}:{}",
+                                self.name,
+                                self.destination.host,
+                                self.destination.port
+                            );
+                            self.metrics.connected.store(true, SeqCst);
+                            delay = self.retry_delay;
+                            connection.insert(conn)
+                        }
+                        Err(err) => {
+                            self.failed("connecting", err);
+                            tokio::time::sleep(delay).await;
+                            delay = (delay * 2).min(self.max_retry_delay);
+                            continue;
+                        }
+                    },
+                };
+                match conn.send(&message).await {
+                    Ok(()) => {
+                        self.metrics.queued_count.fetch_sub(1, SeqCst);
+                        self.metrics.sent_count.fetch_add(1, SeqCst);
+                        break;
+                    }
+                    Err(err) => {
+                        connection = None;
+                        self.failed("sending", err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn failed(&self, action: &str, err: io::Error) {
+        self.metrics.connected.store(false, SeqCst);
+        self.metrics.connection_error_count.fetch_add(1, SeqCst);
+        warn!(
+            "Target {}: {action} to {}:{} failed: {err}",
+            self.name, self.destination.host, self.destination.port
+        );
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use inetnum::asn::Asn;
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    use crate::{
+        roto_runtime::types::{MrtContext, Provenance},
+        targets::file::row::tests::mk_route,
+    };
+
+    use super::*;
+
+    fn route(ingress_id: IngressId) -> Update {
+        let provenance = Provenance::for_bgp(
+            ingress_id,
+            "192.0.2.1".parse().unwrap(),
+            Asn::from_u32(65000),
+        );
+        Update::Single(Payload::new(
+            mk_route("198.51.100.0/24", &[65000], &[]),
+            RouteContext::Mrt(MrtContext {
+                status: RouteStatus::Active,
+                provenance,
+            }),
+            None,
+        ))
+    }
+
+    fn mk_sender(url: &str) -> Sender {
+        Sender {
+            name: "syslog".into(),
+            destination: Destination::new(&url.parse().unwrap(), None)
+                .unwrap(),
+            retry_delay: Duration::from_millis(10),
+            max_retry_delay: Duration::from_millis(10),
+            metrics: Default::default(),
+        }
+    }
+
+    async fn send(sender: Sender, messages: &[&str]) {
+        let (tx, rx) = mpsc::channel(10);
+        for message in messages {
+            sender.metrics.queued_count.fetch_add(1, SeqCst);
+            tx.send(message.to_string()).await.unwrap();
+        }
+        drop(tx);
+        sender.run(rx).await;
+    }
+
+    #[test]
+    fn sessions_come_up_with_their_first_route() {
+        let ingresses = ingress::Register::default();
+        let mut events = Events::default();
+        let kinds = |rows: Vec<Row>| {
+            rows.into_iter().map(|row| row.kind).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            kinds(events.rows(route(1), &ingresses)),
+            ["peer_up", "announce"]
+        );
+        assert_eq!(kinds(events.rows(route(1), &ingresses)), ["announce"]);
+        assert_eq!(
+            kinds(events.rows(Update::Withdraw(1, None), &ingresses)),
+            ["peer_down"]
+        );
+        assert_eq!(
+            kinds(events.rows(route(1), &ingresses)),
+            ["peer_up", "announce"]
+        );
+    }
+
+    #[test]
+    fn events_are_selected() {
+        let config: Config = toml::from_str(
+            "url = \"udp://localhost\"\n\
+            severities = { peer_up = \"info\" }",
+        )
+        .unwrap();
+        assert!(config.check().is_ok());
+        let mut runner = SyslogRunner {
+            events: Events::default(),
+            selected: config.events,
+            topics: None,
+            severities: config.severities,
+            format: MessageFormat {
+                facility: Facility::default(),
+                hostname: "rr1".into(),
+                app_name: "rotonda".into(),
+                proc_id: "1".into(),
+                sd_id: "rotonda@32473".into(),
+            },
+            ingresses: Default::default(),
+            metrics: Default::default(),
+            queue_size: 1,
+        };
+        let messages = runner.messages(route(1));
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].starts_with("<30>1 "), "{}", messages[0]);
+        assert!(messages[0].contains(" peer_up [rotonda@32473 "));
+        let messages = runner.messages(Update::Withdraw(1, None));
+        assert!(messages[0].starts_with("<28>1 "), "{}", messages[0]);
+
+        let config: Result<Config, _> =
+            toml::from_str("url = \"udp://localhost\"\nevents = [\"up\"]");
+        assert!(config.unwrap().check().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn messages_are_sent_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender =
+            mk_sender(&format!("udp://{}", server.local_addr().unwrap()));
+        let metrics = sender.metrics.clone();
+        send(sender, &["<30>1 a", "<30>1 b"]).await;
+
+        let mut buf = [0; 64];
+        for expected in ["<30>1 a", "<30>1 b"] {
+            let len = server.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], expected.as_bytes());
+        }
+        assert_eq!(metrics.sent_count.load(SeqCst), 2);
+        assert_eq!(metrics.queued_count.load(SeqCst), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn messages_are_framed_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sender =
+            mk_sender(&format!("tcp://{}", listener.local_addr().unwrap()));
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = String::new();
+            stream.read_to_string(&mut buf).await.unwrap();
+            buf
+        });
+        send(sender, &["<30>1 a", "<30>1 bc"]).await;
+
+        assert_eq!(server.await.unwrap(), "7 <30>1 a8 <30>1 bc");
+    }
+}
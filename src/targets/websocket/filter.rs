@@ -0,0 +1,235 @@
+//! The filters clients subscribe with.
+//!
+//! A filter is a JSON object whose fields all need to match a row for the
+//! row to be sent. Fields left out match everything:
+//!
+//! * `prefix`: the prefix of the route, along with its more specific
+//!   prefixes unless `more_specific` is false, and its less specific ones
+//!   if `less_specific` is true,
+//! * `asn`: an AS anywhere in the AS path, including the origin,
+//! * `origin_as`, `peer_as` and `peer_ip`: the origin AS of the route and
+//!   the peer it was received from,
+//! * `community`: a community attached to the route, e.g. `"65000:666"`,
+//! * `kinds` and `topics`: the kind and topic of the row.
+//!
+//! Apart from `more_specific` and `less_specific`, each field can also be a
+//! list, matching if any of its values does, e.g. `{"prefix":
+//! ["192.0.2.0/24", "2001:db8::/32"], "community": ["65000:666",
+//! "BLACKHOLE"], "kinds": ["withdraw", "peer_down"]}`.
+//!
+//! The `grpc-out` target takes the same fields in the `Filter` message of
+//! `proto/stream.proto`.
+
+use std::net::IpAddr;
+
+use inetnum::addr::Prefix;
+use serde::Deserialize;
+use serde_with::{serde_as, OneOrMany};
+
+use crate::targets::file::row::Row;
+
+//------------ Filter --------------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    #[serde_as(as = "OneOrMany<_>")]
+    #[serde(default)]
+    pub prefix: Vec<Prefix>,
+
+    #[serde(default = "Filter::default_more_specific")]
+    pub more_specific: bool,
+
+    #[serde(default)]
+    pub less_specific: bool,
+
+    #[serde_as(as = "OneOrMany<_>")]
+    #[serde(default)]
+    pub asn: Vec<u32>,
+
+    #[serde_as(as = "OneOrMany<_>")]
+    #[serde(default)]
+    pub origin_as: Vec<u32>,
+
+    #[serde_as(as = "OneOrMany<_>")]
+    #[serde(default)]
+    pub peer_as: Vec<u32>,
+
+    #[serde_as(as = "OneOrMany<_>")]
+    #[serde(default)]
+    pub peer_ip: Vec<IpAddr>,
+
+    #[serde_as(as = "OneOrMany<_>")]
+    #[serde(default)]
+    pub community: Vec<String>,
+
+    #[serde_as(as = "Option<OneOrMany<_>>")]
+    #[serde(default)]
+    pub kinds: Option<Vec<String>>,
+
+    #[serde_as(as = "Option<OneOrMany<_>>")]
+    #[serde(default)]
+    pub topics: Option<Vec<String>>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            prefix: Vec::new(),
+            more_specific: Self::default_more_specific(),
+            less_specific: false,
+            asn: Vec::new(),
+            origin_as: Vec::new(),
+            peer_as: Vec::new(),
+            peer_ip: Vec::new(),
+            community: Vec::new(),
+            kinds: None,
+            topics: None,
+        }
+    }
+}
+
+impl Filter {
+    fn default_more_specific() -> bool {
+        true
+    }
+
+    /// Returns whether a row matches the filter.
+    pub fn matches(&self, row: &Row) -> bool {
+        self.matches_prefix(row)
+            && any(&self.asn, |asn| {
+                row.origin_as == Some(*asn)
+                    || row
+                        .as_path
+                        .as_ref()
+                        .is_some_and(|path| path.contains(asn))
+            })
+            && any(&self.origin_as, |asn| row.origin_as == Some(*asn))
+            && any(&self.peer_as, |asn| row.peer_as == Some(*asn))
+            && any(&self.peer_ip, |ip| {
+                row.peer_ip
+                    .as_deref()
+                    .and_then(|peer_ip| peer_ip.parse::<IpAddr>().ok())
+                    == Some(*ip)
+            })
+            && any(&self.community, |community| {
+                row.communities.as_ref().is_some_and(|communities| {
+                    communities
+                        .iter()
+                        .any(|item| item.eq_ignore_ascii_case(community))
+                })
+            })
+            && self
+                .kinds
+                .as_ref()
+                .is_none_or(|kinds| kinds.iter().any(|kind| kind == row.kind))
+            && self
+                .topics
+                .as_ref()
+                .is_none_or(|topics| topics.contains(&row.topic))
+    }
+
+    fn matches_prefix(&self, row: &Row) -> bool {
+        if self.prefix.is_empty() {
+            return true;
+        }
+        let Some(prefix) = row
+            .prefix
+            .as_deref()
+            .and_then(|prefix| prefix.parse::<Prefix>().ok())
+        else {
+            return false;
+        };
+        self.prefix.iter().any(|filter| {
+            prefix == *filter
+                || (self.more_specific && filter.covers(prefix))
+                || (self.less_specific && prefix.covers(*filter))
+        })
+    }
+}
+
+/// Returns whether `values` is empty or any of them matches.
+fn any<T>(values: &[T], op: impl Fn(&T) -> bool) -> bool {
+    values.is_empty() || values.iter().any(op)
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(json: &str) -> Filter {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn route(prefix: &str) -> Row {
+        Row {
+            topic: "bgp".into(),
+            kind: "announce",
+            prefix: Some(prefix.into()),
+            origin_as: Some(65001),
+            as_path: Some(vec![65000, 65002, 65001]),
+            communities: Some(vec!["65000:666".into(), "NO_EXPORT".into()]),
+            peer_ip: Some("2001:db8::1".into()),
+            peer_as: Some(65000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn prefixes_match() {
+        let row = route("198.51.100.0/24");
+        assert!(Filter::default().matches(&row));
+        assert!(filter(r#"{"prefix": "198.51.100.0/24"}"#).matches(&row));
+        assert!(filter(r#"{"prefix": "198.51.0.0/16"}"#).matches(&row));
+        assert!(!filter(
+            r#"{"prefix": "198.51.0.0/16", "more_specific": false}"#
+        )
+        .matches(&row));
+        assert!(!filter(r#"{"prefix": "198.51.100.0/25"}"#).matches(&row));
+        assert!(filter(
+            r#"{"prefix": "198.51.100.0/25", "less_specific": true}"#
+        )
+        .matches(&row));
+        assert!(!filter(r#"{"prefix": "2001:db8::/32"}"#).matches(&row));
+        assert!(!filter(r#"{"prefix": "198.51.100.0/24"}"#)
+            .matches(&Row::default()));
+    }
+
+    #[test]
+    fn attributes_match() {
+        let row = route("198.51.100.0/24");
+        assert!(filter(r#"{"asn": 65002}"#).matches(&row));
+        assert!(!filter(r#"{"asn": 65003}"#).matches(&row));
+        assert!(
+            filter(r#"{"origin_as": 65001, "peer_as": 65000}"#).matches(&row)
+        );
+        assert!(!filter(r#"{"origin_as": 65000}"#).matches(&row));
+        assert!(filter(r#"{"peer_ip": "2001:db8:0::1"}"#).matches(&row));
+        assert!(filter(r#"{"community": "no_export"}"#).matches(&row));
+        assert!(!filter(r#"{"community": "65000:1"}"#).matches(&row));
+        assert!(filter(r#"{"kinds": ["announce"], "topics": ["bgp"]}"#)
+            .matches(&row));
+        assert!(!filter(r#"{"kinds": ["withdraw"]}"#).matches(&row));
+        assert!(serde_json::from_str::<Filter>(r#"{"as": 1}"#).is_err());
+    }
+
+    #[test]
+    fn lists_match_any() {
+        let row = route("198.51.100.0/24");
+        assert!(filter(r#"{"prefix": ["192.0.2.0/24", "198.51.0.0/16"]}"#)
+            .matches(&row));
+        assert!(!filter(r#"{"prefix": ["192.0.2.0/24", "2001:db8::/32"]}"#)
+            .matches(&row));
+        assert!(filter(r#"{"asn": [1, 65002], "peer_as": [65000]}"#)
+            .matches(&row));
+        assert!(!filter(r#"{"origin_as": [1, 2]}"#).matches(&row));
+        assert!(filter(r#"{"community": ["65000:1", "65000:666"]}"#)
+            .matches(&row));
+        assert!(filter(r#"{"kinds": "announce"}"#).matches(&row));
+        assert!(!filter(r#"{"topics": []}"#).matches(&row));
+        assert!(filter(r#"{"peer_ip": []}"#).matches(&row));
+    }
+}
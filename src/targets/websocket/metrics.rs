@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+use crate::{
+    comms::GraphStatus,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+#[derive(Debug, Default)]
+pub struct WebSocketMetrics {
+    pub client_count: AtomicUsize,
+    pub connection_count: AtomicUsize,
+    pub refused_count: AtomicUsize,
+    pub sent_count: AtomicUsize,
+    pub dropped_count: AtomicUsize,
+    pub disconnected_count: AtomicUsize,
+}
+
+impl GraphStatus for WebSocketMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "clients: {}\nsent: {}\ndropped: {}",
+            self.client_count.load(SeqCst),
+            self.sent_count.load(SeqCst),
+            self.dropped_count.load(SeqCst),
+        )
+    }
+}
+
+impl WebSocketMetrics {
+    const CLIENT_COUNT_METRIC: Metric = Metric::new(
+        "websocket_target_client_count",
+        "the number of clients currently connected",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const CONNECTION_COUNT_METRIC: Metric = Metric::new(
+        "websocket_target_connection_count",
+        "the number of clients that connected",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const REFUSED_COUNT_METRIC: Metric = Metric::new(
+        "websocket_target_refused_count",
+        "the number of connections refused because there were too many \
+        clients",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const SENT_COUNT_METRIC: Metric = Metric::new(
+        "websocket_target_sent_count",
+        "the number of updates sent to clients",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DROPPED_COUNT_METRIC: Metric = Metric::new(
+        "websocket_target_dropped_count",
+        "the number of updates clients missed because they were too slow",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const DISCONNECTED_COUNT_METRIC: Metric = Metric::new(
+        "websocket_target_disconnected_count",
+        "the number of clients disconnected because they were too slow",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for WebSocketMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append_simple(
+            &Self::CLIENT_COUNT_METRIC,
+            Some(unit_name),
+            self.client_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::CONNECTION_COUNT_METRIC,
+            Some(unit_name),
+            self.connection_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::REFUSED_COUNT_METRIC,
+            Some(unit_name),
+            self.refused_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::SENT_COUNT_METRIC,
+            Some(unit_name),
+            self.sent_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DROPPED_COUNT_METRIC,
+            Some(unit_name),
+            self.dropped_count.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::DISCONNECTED_COUNT_METRIC,
+            Some(unit_name),
+            self.disconnected_count.load(SeqCst),
+        );
+    }
+}
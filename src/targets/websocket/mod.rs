@@ -0,0 +1,3 @@
+pub(crate) mod filter;
+mod metrics;
+pub mod target;
@@ -0,0 +1,666 @@
+//! Streaming updates to websocket clients.
+//!
+//! The `websocket-out` target runs a websocket server on `listen`, much
+//! like RIS Live but fed by our own routers. Clients connect with any path
+//! and subscribe by sending text messages:
+//!
+//! * `{"type": "subscribe", "data": {...}}` adds a subscription with the
+//!   filter given as `data`, as described in the [`filter`] module, and is
+//!   answered with `{"type": "subscribed", "data": {"subscriptions": n}}`.
+//! * `{"type": "unsubscribe"}` removes all subscriptions and is answered
+//!   with `{"type": "unsubscribed"}`.
+//!
+//! Invalid requests are answered with `{"type": "error", "data":
+//! {"message": "..."}}`. Each route and event the target receives becomes
+//! a row with the columns described in the [`row`] module, which is sent
+//! as `{"type": "update", "data": {...}}` to the clients with a matching
+//! subscription.
+//!
+//! Each client can fall behind by up to `queue_size` rows. A client that
+//! falls behind further misses rows, which it is told about with `{"type":
+//! "dropped", "data": {"count": n}}`, or, with `slow_clients =
+//! "disconnect"`, is disconnected with close code 1008 (policy violation)
+//! so that it can reconnect and catch up by other means. At most
+//! `max_clients` clients can be connected at the same time; further
+//! connections are refused with 503 Service Unavailable. Clients that do
+//! not answer pings are disconnected.
+//!
+//! [`filter`]: super::filter
+//! [`row`]: crate::targets::file::row
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{atomic::Ordering::SeqCst, Arc},
+    time::{Duration, Instant},
+};
+
+use futures::{SinkExt, StreamExt};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, mpsc},
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    WebSocketStream,
+};
+
+use crate::{
+    comms::{Link, Terminated, UnitStatus},
+    ingress,
+    manager::{Component, TargetCommand, WaitPoint},
+    targets::file::row::{Row, COLUMNS},
+};
+
+use super::{filter::Filter, metrics::WebSocketMetrics};
+
+/// How often to ping clients.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for anything from a client, including the answer to a
+/// ping, before disconnecting it.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// How long a client may take for the opening handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+pub struct WebSocketOut {
+    sources: Link,
+
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    /// The address to listen on for clients.
+    listen: SocketAddr,
+
+    /// The most clients connected at the same time.
+    #[serde(default = "Config::default_max_clients")]
+    max_clients: usize,
+
+    /// The most subscriptions of a single client.
+    #[serde(default = "Config::default_max_subscriptions")]
+    max_subscriptions: usize,
+
+    /// How many rows a client can fall behind.
+    #[serde(default = "Config::default_queue_size")]
+    queue_size: usize,
+
+    /// What to do with clients falling behind further.
+    #[serde(default)]
+    slow_clients: SlowClients,
+}
+
+/// What to do with a client that fell behind by more than the queue size.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowClients {
+    /// Skip the rows it missed, telling it how many.
+    #[default]
+    Skip,
+
+    /// Disconnect it.
+    Disconnect,
+}
+
+impl Config {
+    fn default_max_clients() -> usize {
+        100
+    }
+
+    fn default_max_subscriptions() -> usize {
+        32
+    }
+
+    fn default_queue_size() -> usize {
+        1000
+    }
+}
+
+impl WebSocketOut {
+    pub async fn run(
+        self,
+        mut component: Component,
+        cmd: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let config = self.config;
+        if config.queue_size == 0 {
+            error!(
+                "Target {}: queue_size must be at least 1",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+        let listener = match TcpListener::bind(config.listen).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!(
+                    "Target {}: cannot listen on {}: {err}",
+                    component.name(),
+                    config.listen
+                );
+                return Err(Terminated);
+            }
+        };
+        info!(
+            "Target {}: listening for websocket clients on {}",
+            component.name(),
+            config.listen
+        );
+
+        let metrics = Arc::new(WebSocketMetrics::default());
+        component.register_metrics(metrics.clone());
+        let (events, _) = broadcast::channel(config.queue_size);
+        WebSocketRunner {
+            listener,
+            events,
+            max_clients: config.max_clients,
+            max_subscriptions: config.max_subscriptions,
+            slow_clients: config.slow_clients,
+            ingresses: component.ingresses().clone(),
+            metrics,
+        }
+        .run(self.sources, cmd, waitpoint)
+        .await
+    }
+}
+
+//------------ WebSocketRunner -----------------------------------------------
+
+/// A row along with its JSON encoding, shared by all clients.
+#[derive(Debug)]
+struct Event {
+    row: Row,
+    json: String,
+}
+
+impl Event {
+    fn new(row: Row) -> Self {
+        let data = COLUMNS
+            .iter()
+            .map(|column| {
+                (column.name.to_string(), row.json_value(column.name))
+            })
+            .collect::<serde_json::Map<_, _>>();
+        let json = json!({"type": "update", "data": data}).to_string();
+        Self { row, json }
+    }
+}
+
+struct WebSocketRunner {
+    listener: TcpListener,
+    events: broadcast::Sender<Arc<Event>>,
+    max_clients: usize,
+    max_subscriptions: usize,
+    slow_clients: SlowClients,
+    ingresses: Arc<ingress::Register>,
+    metrics: Arc<WebSocketMetrics>,
+}
+
+impl WebSocketRunner {
+    async fn run(
+        self,
+        mut sources: Link,
+        mut cmd_rx: mpsc::Receiver<TargetCommand>,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        sources.connect(false).await.unwrap();
+        let sources2 = sources.clone();
+        waitpoint.running().await;
+
+        loop {
+            tokio::select! {
+                cmd = cmd_rx.recv() => match cmd {
+                    Some(TargetCommand::Reconfigure { .. }) => {
+                        warn!(
+                            "Reconfiguring the websocket-out target requires \
+                            a restart"
+                        );
+                    }
+                    Some(TargetCommand::ReportLinks { report }) => {
+                        report.set_source(&sources2);
+                        report.set_graph_status(self.metrics.clone());
+                    }
+                    None | Some(TargetCommand::Terminate) => break,
+                },
+
+                update = sources.query() => match update {
+                    Ok(update) => {
+                        // Without clients, there is no need to encode rows.
+                        if self.events.receiver_count() == 0 {
+                            continue;
+                        }
+                        let rows = Row::for_update(update, &self.ingresses);
+                        for row in rows {
+                            let event = Arc::new(Event::new(row));
+                            let _ = self.events.send(event);
+                        }
+                    }
+                    Err(UnitStatus::Gone) => {
+                        debug!("Source of websocket-out target is gone");
+                        break;
+                    }
+                    Err(_) => {}
+                },
+
+                res = self.listener.accept() => match res {
+                    Ok((stream, addr)) => self.accept(stream, addr),
+                    Err(err) => {
+                        warn!("Accepting a websocket client failed: {err}")
+                    }
+                },
+            }
+        }
+
+        // Dropping the sender closes the connections of the clients.
+        Err(Terminated)
+    }
+
+    /// Starts serving a client if there is room for it.
+    fn accept(&self, mut stream: TcpStream, addr: SocketAddr) {
+        let metrics = self.metrics.clone();
+        if metrics.client_count.load(SeqCst) >= self.max_clients {
+            metrics.refused_count.fetch_add(1, SeqCst);
+            debug!("Refusing websocket client {addr}: too many clients");
+            tokio::spawn(async move {
+                let _ = stream
+                    .write_all(
+                        b"HTTP/1.1 503 Service Unavailable\r\n\
+                        Content-Length: 0\r\n\
+                        Connection: close\r\n\r\n",
+                    )
+                    .await;
+            });
+            return;
+        }
+        metrics.client_count.fetch_add(1, SeqCst);
+        metrics.connection_count.fetch_add(1, SeqCst);
+        let client = Client {
+            subscriptions: Vec::new(),
+            max_subscriptions: self.max_subscriptions,
+            slow_clients: self.slow_clients,
+            events: self.events.subscribe(),
+            metrics: metrics.clone(),
+        };
+        tokio::spawn(async move {
+            match client.serve(stream).await {
+                Ok(()) => debug!("Websocket client {addr} disconnected"),
+                Err(err) => {
+                    debug!("Websocket client {addr} disconnected: {err}")
+                }
+            }
+            metrics.client_count.fetch_sub(1, SeqCst);
+        });
+    }
+}
+
+//------------ Client --------------------------------------------------------
+
+/// A request from a client.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Request {
+    #[serde(rename = "type")]
+    kind: RequestKind,
+
+    #[serde(default)]
+    data: Option<Filter>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RequestKind {
+    Subscribe,
+    Unsubscribe,
+}
+
+/// A connected client.
+struct Client {
+    subscriptions: Vec<Filter>,
+    max_subscriptions: usize,
+    slow_clients: SlowClients,
+    events: broadcast::Receiver<Arc<Event>>,
+    metrics: Arc<WebSocketMetrics>,
+}
+
+impl Client {
+    /// Serves the client until it disconnects or the target stops.
+    async fn serve(mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut ws = tokio::time::timeout(
+            HANDSHAKE_TIMEOUT,
+            tokio_tungstenite::accept_async(stream),
+        )
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no handshake"))?
+        .map_err(io::Error::other)?;
+
+        let mut ping = tokio::time::interval_at(
+            tokio::time::Instant::now() + PING_INTERVAL,
+            PING_INTERVAL,
+        );
+        ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut last_received = Instant::now();
+        loop {
+            // Receiving is cancel safe, so can be raced against the rows.
+            tokio::select! {
+                message = ws.next() => {
+                    last_received = Instant::now();
+                    let Some(message) = message else {
+                        return Ok(());
+                    };
+                    match message.map_err(io::Error::other)? {
+                        Message::Text(text) => {
+                            let reply = self.request(&text);
+                            send(&mut ws, Message::Text(reply.to_string()))
+                                .await?;
+                        }
+                        Message::Binary(_) => {
+                            let reply = error_reply(
+                                "binary messages are not supported",
+                            );
+                            send(&mut ws, Message::Text(reply.to_string()))
+                                .await?;
+                        }
+                        Message::Close(_) => {
+                            // Sends the answer to the close.
+                            return ws.flush().await.map_err(io::Error::other);
+                        }
+                        // Pings are answered while receiving.
+                        _ => {}
+                    }
+                }
+
+                event = self.events.recv() => match event {
+                    Ok(event) => {
+                        if self.subscriptions.iter().any(|filter| {
+                            filter.matches(&event.row)
+                        }) {
+                            let text = Message::Text(event.json.clone());
+                            send(&mut ws, text).await?;
+                            self.metrics.sent_count.fetch_add(1, SeqCst);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        let count =
+                            usize::try_from(count).unwrap_or(usize::MAX);
+                        self.metrics.dropped_count.fetch_add(count, SeqCst);
+                        if self.slow_clients == SlowClients::Disconnect {
+                            self.metrics
+                                .disconnected_count
+                                .fetch_add(1, SeqCst);
+                            send(&mut ws, close(CloseCode::Policy)).await?;
+                            return Err(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                format!("fell behind by {count} rows"),
+                            ));
+                        }
+                        let reply = json!({
+                            "type": "dropped", "data": {"count": count}
+                        });
+                        send(&mut ws, Message::Text(reply.to_string())).await?;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        send(&mut ws, close(CloseCode::Away)).await?;
+                        return Ok(());
+                    }
+                },
+
+                _ = ping.tick() => {
+                    if last_received.elapsed() > IDLE_TIMEOUT {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "no answer to pings",
+                        ));
+                    }
+                    send(&mut ws, Message::Ping(Vec::new())).await?;
+                }
+            }
+        }
+    }
+
+    /// Handles a request, returning the reply.
+    fn request(&mut self, text: &str) -> serde_json::Value {
+        match serde_json::from_str::<Request>(text) {
+            Ok(Request {
+                kind: RequestKind::Subscribe,
+                data,
+            }) => {
+                if self.subscriptions.len() >= self.max_subscriptions {
+                    return error_reply("too many subscriptions");
+                }
+                self.subscriptions.push(data.unwrap_or_default());
+                json!({
+                    "type": "subscribed",
+                    "data": {"subscriptions": self.subscriptions.len()}
+                })
+            }
+            Ok(Request {
+                kind: RequestKind::Unsubscribe,
+                ..
+            }) => {
+                self.subscriptions.clear();
+                json!({"type": "unsubscribed"})
+            }
+            Err(err) => error_reply(&format!("invalid request: {err}")),
+        }
+    }
+}
+
+fn error_reply(message: &str) -> serde_json::Value {
+    json!({"type": "error", "data": {"message": message}})
+}
+
+/// Returns a close message with the given status code.
+fn close(code: CloseCode) -> Message {
+    Message::Close(Some(CloseFrame {
+        code,
+        reason: "".into(),
+    }))
+}
+
+async fn send(
+    ws: &mut WebSocketStream<TcpStream>,
+    message: Message,
+) -> io::Result<()> {
+    ws.send(message).await.map_err(io::Error::other)
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tokio_tungstenite::MaybeTlsStream;
+
+    use super::*;
+
+    type ClientStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn connect(url: &str) -> ClientStream {
+        tokio_tungstenite::connect_async(url).await.unwrap().0
+    }
+
+    async fn recv(ws: &mut ClientStream) -> Message {
+        loop {
+            match ws.next().await.unwrap().unwrap() {
+                Message::Ping(_) | Message::Pong(_) => {}
+                message => return message,
+            }
+        }
+    }
+
+    async fn recv_json(ws: &mut ClientStream) -> serde_json::Value {
+        match recv(ws).await {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("unexpected {message:?}"),
+        }
+    }
+
+    async fn send_text(ws: &mut ClientStream, text: &str) {
+        ws.send(Message::Text(text.into())).await.unwrap();
+    }
+
+    fn route(prefix: &str) -> Arc<Event> {
+        Arc::new(Event::new(Row {
+            topic: "bgp".into(),
+            kind: "announce",
+            prefix: Some(prefix.into()),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn requests_are_answered() {
+        let (events, _) = broadcast::channel(1);
+        let mut client = Client {
+            subscriptions: Vec::new(),
+            max_subscriptions: 2,
+            slow_clients: SlowClients::Skip,
+            events: events.subscribe(),
+            metrics: Default::default(),
+        };
+        let reply = |client: &mut Client, text: &str| {
+            let reply = client.request(text);
+            (
+                reply["type"].as_str().unwrap().to_string(),
+                reply["data"].clone(),
+            )
+        };
+        assert_eq!(
+            reply(&mut client, r#"{"type": "subscribe"}"#),
+            ("subscribed".into(), json!({"subscriptions": 1}))
+        );
+        assert_eq!(
+            reply(
+                &mut client,
+                r#"{"type": "subscribe", "data": {"asn": 1}}"#
+            )
+            .0,
+            "subscribed"
+        );
+        assert_eq!(client.subscriptions[0], Filter::default());
+        assert_eq!(reply(&mut client, r#"{"type": "subscribe"}"#).0, "error");
+        assert_eq!(
+            reply(&mut client, r#"{"type": "unsubscribe"}"#).0,
+            "unsubscribed"
+        );
+        assert!(client.subscriptions.is_empty());
+        assert_eq!(
+            reply(&mut client, r#"{"type": "subscribe", "data": {"x": 1}}"#)
+                .0,
+            "error"
+        );
+        assert_eq!(reply(&mut client, "[]").0, "error");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn clients_receive_what_they_subscribed_to() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (events, _) = broadcast::channel(2);
+        let runner = WebSocketRunner {
+            listener,
+            events: events.clone(),
+            max_clients: 1,
+            max_subscriptions: 1,
+            slow_clients: SlowClients::Skip,
+            ingresses: Default::default(),
+            metrics: Default::default(),
+        };
+        let metrics = runner.metrics.clone();
+        let url = format!("ws://{addr}/");
+        let url2 = url.clone();
+
+        let client = tokio::spawn(async move {
+            let mut ws = connect(&url).await;
+            send_text(&mut ws, "{\"type\": \"bogus\"}").await;
+            assert_eq!(recv_json(&mut ws).await["type"], "error");
+            send_text(
+                &mut ws,
+                r#"{"type": "subscribe", "data": {"prefix": "10.0.0.0/8"}}"#,
+            )
+            .await;
+            assert_eq!(
+                recv_json(&mut ws).await,
+                json!({"type": "subscribed", "data": {"subscriptions": 1}})
+            );
+            send_text(&mut ws, r#"{"type": "subscribe"}"#).await;
+            assert_eq!(recv_json(&mut ws).await["type"], "error");
+            ws
+        });
+        let (stream, peer) = runner.listener.accept().await.unwrap();
+        runner.accept(stream, peer);
+        let mut ws = client.await.unwrap();
+
+        // Only one client at a time.
+        let refused = tokio::spawn(async move {
+            tokio_tungstenite::connect_async(url2).await
+        });
+        let (stream, peer) = runner.listener.accept().await.unwrap();
+        runner.accept(stream, peer);
+        assert!(refused.await.unwrap().is_err());
+        assert_eq!(metrics.refused_count.load(SeqCst), 1);
+
+        events.send(route("192.0.2.0/24")).unwrap();
+        events.send(route("10.1.0.0/16")).unwrap();
+        let update = recv_json(&mut ws).await;
+        assert_eq!(update["type"], "update");
+        assert_eq!(update["data"]["prefix"], "10.1.0.0/16");
+        assert_eq!(update["data"]["kind"], "announce");
+
+        // Dropping the sender closes the connection.
+        drop(runner);
+        drop(events);
+        assert_eq!(recv(&mut ws).await, close(CloseCode::Away));
+        assert_eq!(metrics.sent_count.load(SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn slow_clients_are_disconnected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (events, _) = broadcast::channel(1);
+        let runner = WebSocketRunner {
+            listener,
+            events: events.clone(),
+            max_clients: 1,
+            max_subscriptions: 1,
+            slow_clients: SlowClients::Disconnect,
+            ingresses: Default::default(),
+            metrics: Default::default(),
+        };
+        let url = format!("ws://{addr}/");
+
+        let client = tokio::spawn(async move {
+            let mut ws = connect(&url).await;
+            send_text(&mut ws, r#"{"type": "subscribe"}"#).await;
+            assert_eq!(recv_json(&mut ws).await["type"], "subscribed");
+            ws
+        });
+        let (stream, peer) = runner.listener.accept().await.unwrap();
+        runner.accept(stream, peer);
+        let mut ws = client.await.unwrap();
+
+        // On the single threaded runtime, the client does not get to run
+        // until we wait for it, so falls behind.
+        for prefix in ["192.0.2.0/24", "198.51.100.0/24", "203.0.113.0/24"] {
+            events.send(route(prefix)).unwrap();
+        }
+        assert_eq!(recv(&mut ws).await, close(CloseCode::Policy));
+        assert_eq!(runner.metrics.disconnected_count.load(SeqCst), 1);
+        assert_eq!(runner.metrics.dropped_count.load(SeqCst), 2);
+    }
+}
@@ -574,6 +574,25 @@ pub mod bgp {
             buf.freeze()
         }
 
+        /// A BGP UPDATE announcing 198.51.100.0/24 with AS path 65000 and
+        /// withdrawing 192.0.2.0/24, in hex.
+        ///
+        /// This is what the units receiving BGP messages from a message bus
+        /// or service are tested with.
+        pub const RAW_UPDATE: &str = concat!(
+            "ffffffffffffffffffffffffffffffff",
+            "0033",
+            "02",
+            "0004",
+            "18c00002",
+            "0014",
+            "40010100",
+            "400206020100",
+            "00fde8",
+            "400304c0000201",
+            "18c63364",
+        );
+
         #[allow(clippy::vec_init_then_push)]
         pub fn mk_bgp_update(
             withdrawals: &Prefixes,
@@ -1713,3 +1732,238 @@ pub mod net {
         }
     }
 }
+
+#[cfg(test)]
+pub mod http {
+    //! A mock HTTP server for testing clients.
+
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use bytes::Bytes;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, HeaderMap, Method, Request, Response, Server, Uri,
+    };
+
+    /// A request received by a [`MockServer`].
+    #[derive(Clone, Debug)]
+    pub struct Recorded {
+        pub method: Method,
+        pub uri: Uri,
+        pub headers: HeaderMap,
+        pub body: Bytes,
+    }
+
+    impl Recorded {
+        /// Returns the value of a header as a string.
+        pub fn header(&self, name: &str) -> Option<&str> {
+            self.headers.get(name).map(|value| value.to_str().unwrap())
+        }
+
+        /// Returns the body as text.
+        pub fn text(&self) -> String {
+            String::from_utf8(self.body.to_vec()).unwrap()
+        }
+
+        /// Returns the body parsed as JSON, or null if it isn't JSON.
+        pub fn json(&self) -> serde_json::Value {
+            serde_json::from_slice(&self.body).unwrap_or_default()
+        }
+    }
+
+    /// An HTTP server on localhost recording the requests it receives.
+    pub struct MockServer {
+        pub addr: SocketAddr,
+        requests: Arc<Mutex<Vec<Recorded>>>,
+    }
+
+    impl MockServer {
+        /// Starts a server answering each request with what `respond`
+        /// returns for it.
+        pub async fn start<F>(respond: F) -> Self
+        where
+            F: FnMut(&Recorded) -> Response<Body> + Send + 'static,
+        {
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let respond = Arc::new(Mutex::new(respond));
+            let recorded = requests.clone();
+            let make = make_service_fn(move |_| {
+                let recorded = recorded.clone();
+                let respond = respond.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(
+                        move |req: Request<Body>| {
+                            let recorded = recorded.clone();
+                            let respond = respond.clone();
+                            async move {
+                                let (parts, body) = req.into_parts();
+                                let request = Recorded {
+                                    method: parts.method,
+                                    uri: parts.uri,
+                                    headers: parts.headers,
+                                    body: hyper::body::to_bytes(body).await?,
+                                };
+                                let response =
+                                    (respond.lock().unwrap())(&request);
+                                recorded.lock().unwrap().push(request);
+                                Ok::<_, hyper::Error>(response)
+                            }
+                        },
+                    ))
+                }
+            });
+            let server =
+                Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+            let addr = server.local_addr();
+            tokio::spawn(server);
+            MockServer { addr, requests }
+        }
+
+        /// Starts a server answering requests with the given status codes
+        /// in turn, and with `then` once they run out.
+        pub async fn with_statuses(statuses: Vec<u16>, then: u16) -> Self {
+            let mut statuses = statuses.into_iter();
+            Self::start(move |_| status(statuses.next().unwrap_or(then)))
+                .await
+        }
+
+        /// Returns the requests received so far.
+        pub fn requests(&self) -> Vec<Recorded> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    /// Returns a response with `status` and an empty body.
+    pub fn status(status: u16) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    /// Returns a response with `status` and `body` as JSON.
+    pub fn json(status: u16, body: &serde_json::Value) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+pub mod https {
+    //! HTTPS for the plain HTTP servers that tests run.
+    //!
+    //! A [`front`] accepts TLS connections and relays their content to a
+    //! server, so that clients can be tested against `https://` URLs. Its
+    //! certificate is for `localhost` and `127.0.0.1`, issued by a test CA
+    //! that only the [`client`] and the [`ca_file`] trust.
+
+    use std::{
+        net::SocketAddr,
+        path::PathBuf,
+        sync::{Arc, OnceLock},
+    };
+
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use rustls::pki_types::{
+        CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer,
+    };
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::TlsAcceptor;
+
+    struct Pki {
+        ca: CertificateDer<'static>,
+        ca_pem: String,
+        certificate: CertificateDer<'static>,
+        key: Vec<u8>,
+    }
+
+    fn pki() -> &'static Pki {
+        static PKI: OnceLock<Pki> = OnceLock::new();
+        PKI.get_or_init(|| {
+            let ca_key = KeyPair::generate().unwrap();
+            let mut ca_params = CertificateParams::new(vec![]).unwrap();
+            ca_params
+                .distinguished_name
+                .push(DnType::CommonName, "Test CA");
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+            let key = KeyPair::generate().unwrap();
+            let certificate = CertificateParams::new(vec![
+                "localhost".to_string(),
+                "127.0.0.1".to_string(),
+            ])
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+            Pki {
+                ca: ca.der().clone(),
+                ca_pem: ca.pem(),
+                certificate: certificate.der().clone(),
+                key: key.serialize_der(),
+            }
+        })
+    }
+
+    /// Returns the path of a PEM file with the test CA certificate.
+    pub fn ca_file() -> PathBuf {
+        static PATH: OnceLock<PathBuf> = OnceLock::new();
+        PATH.get_or_init(|| {
+            let path = std::env::temp_dir()
+                .join(format!("rotonda-test-ca-{}.pem", std::process::id()));
+            std::fs::write(&path, &pki().ca_pem).unwrap();
+            path
+        })
+        .clone()
+    }
+
+    /// Returns an HTTP client that only trusts the test CA.
+    pub fn client() -> reqwest::Client {
+        reqwest::Client::builder()
+            .use_rustls_tls()
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(
+                reqwest::Certificate::from_der(&pki().ca).unwrap(),
+            )
+            .build()
+            .unwrap()
+    }
+
+    /// Accepts TLS connections on a new port of localhost, relaying them
+    /// to `backend`, and returns the address.
+    pub async fn front(backend: SocketAddr) -> SocketAddr {
+        let pki = pki();
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![pki.certificate.clone(), pki.ca.clone()],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pki.key.clone())),
+        )
+        .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((sock, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let mut stream = acceptor.accept(sock).await?;
+                    let mut server = TcpStream::connect(backend).await?;
+                    tokio::io::copy_bidirectional(&mut stream, &mut server)
+                        .await
+                });
+            }
+        });
+        addr
+    }
+}
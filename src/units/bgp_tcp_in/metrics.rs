@@ -10,6 +10,8 @@ pub struct BgpTcpInMetrics {
     gate: Option<Arc<GateMetrics>>,
     pub listener_bound_count: Arc<AtomicUsize>,
     pub connection_accepted_count: Arc<AtomicUsize>,
+    pub connect_count: Arc<AtomicUsize>,
+    pub connect_error_count: Arc<AtomicUsize>,
     pub established_session_count: Arc<AtomicUsize>,
     pub connection_lost_count: Arc<AtomicUsize>,
     pub disconnect_count: Arc<AtomicUsize>,
@@ -55,6 +57,18 @@ impl BgpTcpInMetrics {
         MetricType::Counter,
         MetricUnit::Total,
     );
+    const CONNECT_COUNT_METRIC: Metric = Metric::new(
+        "bgp_tcp_in_connect_count",
+        "the number of times a connection to an active peer was set up",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const CONNECT_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "bgp_tcp_in_connect_error_count",
+        "the number of times connecting to an active peer failed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
     const CONNECTION_LOST_COUNT_METRIC: Metric = Metric::new(
         "bgp_tcp_in_connection_lost_count",
         "the number of times the connection to a peer was lost",
@@ -87,6 +101,18 @@ impl metrics::Source for BgpTcpInMetrics {
             self.connection_accepted_count.load(SeqCst),
         );
 
+        target.append_simple(
+            &Self::CONNECT_COUNT_METRIC,
+            Some(unit_name),
+            self.connect_count.load(SeqCst),
+        );
+
+        target.append_simple(
+            &Self::CONNECT_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.connect_error_count.load(SeqCst),
+        );
+
         target.append_simple(
             &Self::CONNECTION_LOST_COUNT_METRIC,
             Some(unit_name),
@@ -21,6 +21,8 @@ use routecore::bgp::fsm::session::BgpConfig;
 use routecore::bgp::types::AfiSafiType;
 use serde::Deserialize;
 
+use crate::common::tcp_auth::TcpAuth;
+
 /// Enum carrying either a exact IP address, or a `Prefix`.
 #[derive(
     Clone, Copy, Debug, Deserialize, Hash, Eq, PartialEq, Ord, PartialOrd,
@@ -45,6 +47,15 @@ impl PrefixOrExact {
             Self::Prefix(p) => p.contains(addr),
         }
     }
+
+    /// Returns the address and prefix length.
+    pub fn addr_and_len(&self) -> (IpAddr, u8) {
+        match self {
+            Self::Exact(a @ IpAddr::V4(_)) => (*a, 32),
+            Self::Exact(a @ IpAddr::V6(_)) => (*a, 128),
+            Self::Prefix(p) => (p.addr(), p.len()),
+        }
+    }
 }
 
 impl From<Prefix> for PrefixOrExact {
@@ -85,8 +96,44 @@ impl OneOrManyAsns {
 
 /// Ordered collection of `PeerConfig`s, keyed on `PrefixOrExact`.
 #[derive(Clone, Debug, Default, Deserialize)]
+#[serde(try_from = "BTreeMap<PrefixOrExact, PeerConfig>")]
 pub struct PeerConfigs(BTreeMap<PrefixOrExact, PeerConfig>);
 
+impl TryFrom<BTreeMap<PrefixOrExact, PeerConfig>> for PeerConfigs {
+    type Error = String;
+
+    fn try_from(
+        peers: BTreeMap<PrefixOrExact, PeerConfig>,
+    ) -> Result<Self, Self::Error> {
+        for (key, cfg) in &peers {
+            if cfg.mode == PeerMode::Active
+                && !(key.is_exact() && cfg.single_asn())
+            {
+                return Err(format!(
+                    "peer '{}': active mode requires an exact address and \
+                    a single remote_asn",
+                    cfg.name
+                ));
+            }
+            cfg.tcp_auth
+                .check()
+                .map_err(|err| format!("peer '{}': {}", cfg.name, err))?;
+            if let (Some(hold_time), Some(keepalive)) =
+                (cfg.hold_time, cfg.keepalive)
+            {
+                if hold_time / 3 != keepalive {
+                    return Err(format!(
+                        "peer '{}': keepalive is always a third of the \
+                        hold time, configure either hold_time or keepalive",
+                        cfg.name
+                    ));
+                }
+            }
+        }
+        Ok(Self(peers))
+    }
+}
+
 impl PeerConfigs {
     /// Returns the PrefixOrExact and PeerConfig for `key`, if any.
     pub fn get(&self, key: IpAddr) -> Option<(PrefixOrExact, &PeerConfig)> {
@@ -103,6 +150,40 @@ impl PeerConfigs {
     pub fn get_exact(&self, key: &PrefixOrExact) -> Option<&PeerConfig> {
         self.0.get(key)
     }
+
+    /// Returns the networks of the peers whose connections are to be
+    /// authenticated, with the keys to do so.
+    pub fn tcp_auth(
+        &self,
+    ) -> impl Iterator<Item = (PrefixOrExact, &TcpAuth)> {
+        self.0
+            .iter()
+            .filter(|(_, cfg)| cfg.tcp_auth.is_enabled())
+            .map(|(key, cfg)| (*key, &cfg.tcp_auth))
+    }
+
+    /// Returns the address and PeerConfig of the peers we connect to
+    /// ourselves.
+    pub fn active(&self) -> impl Iterator<Item = (IpAddr, &PeerConfig)> {
+        self.0.iter().filter_map(|(key, cfg)| match key {
+            PrefixOrExact::Exact(addr) if cfg.mode == PeerMode::Active => {
+                Some((*addr, cfg))
+            }
+            _ => None,
+        })
+    }
+}
+
+/// Which side sets up the TCP connection of a session.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerMode {
+    /// Wait for the peer to connect to us.
+    #[default]
+    Passive,
+
+    /// Connect to the peer, and reconnect whenever the session is lost.
+    Active,
 }
 
 /// Configuration for a remote BGP peer.
@@ -110,7 +191,34 @@ impl PeerConfigs {
 pub struct PeerConfig {
     name: String,
     remote_asn: OneOrManyAsns,
+
+    /// The ASN to use towards this peer instead of the unit's `my_asn`.
+    #[serde(default)]
+    local_asn: Option<Asn>,
+
     hold_time: Option<u16>,
+
+    /// The interval between KEEPALIVEs. As these are always sent at a
+    /// third of the hold time, this is an alternative to `hold_time`.
+    #[serde(default)]
+    keepalive: Option<u16>,
+
+    #[serde(default)]
+    mode: PeerMode,
+
+    /// The TCP port to connect to in active mode.
+    #[serde(default = "PeerConfig::default_port")]
+    port: u16,
+
+    /// The IP TTL of packets sent to this peer, for peers more than one
+    /// hop away. The system default is used when not set.
+    #[serde(default)]
+    multihop: Option<u8>,
+
+    /// The TCP MD5 password or TCP-AO key of the connection.
+    #[serde(flatten)]
+    tcp_auth: TcpAuth,
+
     #[serde(default)]
     protocols: Vec<AfiSafiType>,
     #[serde(default)]
@@ -123,16 +231,38 @@ impl PeerConfig {
         Self {
             name: "MOCK".to_string(),
             remote_asn: OneOrManyAsns::Many(vec![]),
+            local_asn: None,
             hold_time: None,
+            keepalive: None,
+            mode: PeerMode::Passive,
+            port: Self::default_port(),
+            multihop: None,
+            tcp_auth: TcpAuth::default(),
             protocols: vec![],
             addpath: vec![],
         }
     }
 
+    fn default_port() -> u16 {
+        179
+    }
+
     pub fn name(&self) -> &String {
         &self.name
     }
 
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn multihop(&self) -> Option<u8> {
+        self.multihop
+    }
+
+    pub fn tcp_auth(&self) -> &TcpAuth {
+        &self.tcp_auth
+    }
+
     pub fn single_asn(&self) -> bool {
         self.remote_asn.is_single()
     }
@@ -150,7 +280,13 @@ impl PeerConfig {
 impl PartialEq for PeerConfig {
     fn eq(&self, other: &PeerConfig) -> bool {
         self.remote_asn == other.remote_asn
+            && self.local_asn == other.local_asn
             && self.hold_time == other.hold_time
+            && self.keepalive == other.keepalive
+            && self.mode == other.mode
+            && self.port == other.port
+            && self.multihop == other.multihop
+            && self.tcp_auth == other.tcp_auth
     }
 }
 
@@ -171,7 +307,7 @@ impl CombinedConfig {
         remote_prefix_or_exact: PrefixOrExact,
     ) -> CombinedConfig {
         CombinedConfig {
-            my_asn: b.my_asn,
+            my_asn: peer_config.local_asn.unwrap_or(b.my_asn),
             my_bgp_id: b.my_bgp_id,
             remote_prefix_or_exact,
             peer_config,
@@ -203,7 +339,9 @@ impl BgpConfig for CombinedConfig {
     }
 
     fn hold_time(&self) -> Option<u16> {
-        self.peer_config.hold_time
+        self.peer_config.hold_time.or_else(|| {
+            self.peer_config.keepalive.map(|k| k.saturating_mul(3))
+        })
     }
 
     fn is_exact(&self) -> bool {
@@ -321,4 +459,70 @@ addpath = ["Ipv4Unicast", "Ipv6Unicast"]
             vec![AfiSafiType::Ipv4Unicast, AfiSafiType::Ipv6Unicast]
         );
     }
+
+    #[test]
+    fn active_peers() {
+        let toml = r#"
+[peers."192.0.2.1"]
+name = "Route-server"
+remote_asn = 64500
+local_asn = 64511
+mode = "active"
+keepalive = 20
+multihop = 5
+
+[peers."192.0.2.2"]
+name = "Passive"
+remote_asn = 64501
+port = 1179
+md5_password = "secret"
+"#;
+
+        #[derive(Deserialize)]
+        struct Peers {
+            peers: PeerConfigs,
+        }
+
+        let peers = toml::from_str::<Peers>(toml).unwrap().peers;
+        let active = peers.active().collect::<Vec<_>>();
+        assert_eq!(active.len(), 1);
+        let (addr, cfg) = active[0];
+        assert_eq!(addr, IpAddr::from_str("192.0.2.1").unwrap());
+        assert_eq!(cfg.port(), 179);
+        assert_eq!(cfg.multihop(), Some(5));
+
+        let bgp = BgpTcpIn::mock("[::]:179", Asn::from_u32(65001));
+        let combined =
+            CombinedConfig::new(bgp.clone(), cfg.clone(), addr.into());
+        assert_eq!(combined.local_asn(), Asn::from_u32(64511));
+        assert_eq!(combined.hold_time(), Some(60));
+        assert!(combined.is_exact());
+
+        let (net, cfg) =
+            peers.get(IpAddr::from_str("192.0.2.2").unwrap()).unwrap();
+        assert_eq!(cfg.port(), 1179);
+        assert_eq!(cfg.tcp_auth().md5_password.as_deref(), Some("secret"));
+        assert_eq!(peers.tcp_auth().count(), 1);
+        let combined = CombinedConfig::new(bgp, cfg.clone(), net);
+        assert_eq!(combined.local_asn(), Asn::from_u32(65001));
+        assert_eq!(combined.hold_time(), None);
+
+        // Active mode needs a single peer to connect to.
+        let toml = r#"
+[peers."192.0.2.0/24"]
+name = "Subnet"
+remote_asn = 64500
+mode = "active"
+"#;
+        assert!(toml::from_str::<Peers>(toml).is_err());
+
+        let toml = r#"
+[peers."192.0.2.1"]
+name = "Timers"
+remote_asn = 64500
+hold_time = 90
+keepalive = 10
+"#;
+        assert!(toml::from_str::<Peers>(toml).is_err());
+    }
 }
@@ -33,6 +33,8 @@ use crate::roto_runtime::types::{
 use crate::comms::{Gate, GateStatus, Terminated};
 use crate::ingress;
 use crate::payload::{Payload, RotondaRoute, Update};
+use crate::roto_runtime::filter_metrics;
+use crate::roto_runtime::reload::Reloadable;
 use crate::roto_runtime::Ctx;
 use crate::units::bgp_tcp_in::status_reporter::BgpTcpInStatusReporter;
 use crate::units::rib_unit::rpki::RtrCache;
@@ -40,7 +42,7 @@ use crate::units::Unit;
 
 use super::peer_config::{CombinedConfig, ConfigExt};
 use super::unit::BgpTcpIn;
-use super::unit::RotoFunc;
+use super::unit::{RotoFunc, ROTO_FUNC_FILTER_NAME};
 
 #[async_trait::async_trait]
 trait BgpSession<C: BgpConfig + ConfigExt> {
@@ -75,7 +77,7 @@ impl BgpSession<CombinedConfig> for Session<CombinedConfig> {
 }
 
 struct Processor {
-    roto_function: Option<RotoFunc>,
+    roto_function: Reloadable<Option<RotoFunc>>,
     roto_context: Arc<Mutex<Ctx>>,
     gate: Gate,
     unit_cfg: BgpTcpIn,
@@ -96,7 +98,7 @@ struct Processor {
 impl Processor {
     #[allow(clippy::too_many_arguments)]
     fn new(
-        roto_function: Option<RotoFunc>,
+        roto_function: Reloadable<Option<RotoFunc>>,
         roto_context: Arc<Mutex<Ctx>>,
         gate: Gate,
         unit_cfg: BgpTcpIn,
@@ -129,7 +131,7 @@ impl Processor {
         let (pdu_out_tx, _) = mpsc::channel(16);
 
         let processor = Self {
-            roto_function: None,
+            roto_function: Reloadable::fixed(None),
             roto_context: Arc::new(Mutex::new(Ctx::empty())),
             gate,
             unit_cfg,
@@ -215,6 +217,10 @@ impl Processor {
                                         )).await;
                                     break;
                                 } else {
+                                    // Shadow mode can change without
+                                    // reconnecting.
+                                    self.unit_cfg.shadow = new_unit.shadow.clone();
+
                                     // Main unit has not changed, check for
                                     // this specific peer.
                                     // A peer might have been removed from the
@@ -280,14 +286,18 @@ impl Processor {
                             let received = std::time::Instant::now();
                             { // lock scope
                             let mut ctx = self.roto_context.lock().unwrap();
+                            ctx.logger.set_ingress(Some(session_ingress_id));
 
-                            verdict = self.roto_function.as_ref().map(
+                            verdict = self.roto_function.get(&mut ctx).map(
                                 |roto_function|
                             {
-                                roto_function.call(
-                                    &mut ctx,
-                                    roto::Val(bgp_msg.clone()),
-                                    roto::Val(provenance),
+                                filter_metrics::shared().run(
+                                    ROTO_FUNC_FILTER_NAME,
+                                    || roto_function.call(
+                                        &mut ctx,
+                                        roto::Val(bgp_msg.clone()),
+                                        roto::Val(provenance),
+                                    ),
                                 )
                             });
 
@@ -348,6 +358,21 @@ impl Processor {
 
                             self.gate.update_data(Update::OutputStream(osms)).await;
 
+                            // In shadow mode, rejected updates are
+                            // processed anyway.
+                            let verdict = match (verdict, &self.unit_cfg.shadow) {
+                                (Some(roto::Verdict::Reject(_)), Some(shadow)) => {
+                                    shadow.rejected(ROTO_FUNC_FILTER_NAME, || {
+                                        format!(
+                                            "a BGP UPDATE from {}",
+                                            negotiated.remote_addr()
+                                        )
+                                    });
+                                    None
+                                }
+                                (verdict, _) => verdict,
+                            };
+
                             match verdict {
                                 // Default action when no roto script is used
                                 // is Accept (i.e. None here).
@@ -559,7 +584,7 @@ impl Processor {
 
 #[allow(clippy::too_many_arguments)]
 pub async fn handle_connection(
-    roto_function: Option<RotoFunc>,
+    roto_function: Reloadable<Option<RotoFunc>>,
     roto_context: Arc<Mutex<Ctx>>,
     gate: Gate,
     unit_config: BgpTcpIn,
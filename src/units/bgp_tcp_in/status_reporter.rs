@@ -8,6 +8,7 @@ use log::{debug, info, warn};
 use crate::common::status_reporter::{
     sr_log, AnyStatusReporter, Chainable, Named, UnitStatusReporter,
 };
+use crate::health::{ComponentHealth, ComponentState};
 
 use super::metrics::BgpTcpInMetrics;
 
@@ -15,6 +16,7 @@ use super::metrics::BgpTcpInMetrics;
 pub struct BgpTcpInStatusReporter {
     name: String,
     metrics: Arc<BgpTcpInMetrics>,
+    health: Arc<ComponentHealth>,
 }
 
 impl BgpTcpInStatusReporter {
@@ -22,16 +24,30 @@ impl BgpTcpInStatusReporter {
         Self {
             name: format!("{}", name),
             metrics,
+            health: Default::default(),
         }
     }
 
+    /// Reports the state of the listener to the health of the unit too.
+    pub fn with_health(mut self, health: Arc<ComponentHealth>) -> Self {
+        self.health = health;
+        self
+    }
+
     pub fn bind_error<T: Display>(&self, listen_addr: &str, err: T) {
         sr_log!(warn: self, "Error while listening for connections on {}: {}", listen_addr, err);
+        self.health.report_error(&err);
+        self.health.set_state(ComponentState::Retrying);
     }
 
     pub fn listener_listening(&self, server_uri: &str) {
         sr_log!(info: self, "Listening for connections on {}", server_uri);
         self.metrics.listener_bound_count.fetch_add(1, SeqCst);
+        self.health.set_state(ComponentState::Running);
+    }
+
+    pub fn tcp_auth_error<T: Display, U: Display>(&self, peer: T, err: U) {
+        sr_log!(warn: self, "Error while setting up TCP authentication for {}: {}", peer, err);
     }
 
     pub fn listener_connection_accepted(&self, router_addr: SocketAddr) {
@@ -39,8 +55,23 @@ impl BgpTcpInStatusReporter {
         self.metrics.connection_accepted_count.fetch_add(1, SeqCst);
     }
 
+    pub fn peer_connected(&self, peer_addr: SocketAddr) {
+        sr_log!(debug: self, "Connected to peer {}", peer_addr);
+        self.metrics.connect_count.fetch_add(1, SeqCst);
+    }
+
+    pub fn peer_connect_error<T: Display>(
+        &self,
+        peer_addr: SocketAddr,
+        err: T,
+    ) {
+        sr_log!(warn: self, "Error while connecting to peer {}: {}", peer_addr, err);
+        self.metrics.connect_error_count.fetch_add(1, SeqCst);
+    }
+
     pub fn listener_io_error<T: Display>(&self, err: T) {
         sr_log!(warn: self, "Error while listening for connections: {}", err);
+        self.health.report_error(err);
     }
 
     pub fn peer_connection_lost(&self, peer_addr: Option<SocketAddr>) {
@@ -69,6 +100,7 @@ impl AnyStatusReporter for BgpTcpInStatusReporter {
 impl Chainable for BgpTcpInStatusReporter {
     fn add_child<T: Display>(&self, child_name: T) -> Self {
         Self::new(self.link_names(child_name), self.metrics.clone())
+            .with_health(self.health.clone())
     }
 }
 
@@ -1,14 +1,14 @@
 use std::collections::HashMap;
 use std::fmt;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::ControlFlow;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use arc_swap::ArcSwap;
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
-use futures::future::select;
+use futures::future::{join_all, select};
 use futures::{pin_mut, Future};
 use log::{debug, error, warn};
 use non_empty_vec::NonEmpty;
@@ -21,18 +21,22 @@ use routecore::bgp::message::{
 };
 
 use serde::Deserialize;
+use tokio::net::{TcpSocket, TcpStream};
 use tokio::sync::mpsc;
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout};
 
 use crate::common::net::{
     StandardTcpListenerFactory, StandardTcpStream, TcpListener,
     TcpListenerFactory, TcpStreamWrapper,
 };
 use crate::roto_runtime::types::{
-    CompiledRoto, FilterName, Provenance, RotoOutputStream, RotoScripts
+    FilterName, Provenance, RotoOutputStream, RotoScripts
 };
 //use crate::common::roto::{FilterName, RotoScripts};
-use crate::common::status_reporter::{Chainable, UnitStatusReporter};
+use crate::common::status_reporter::{
+    Chainable, Named, UnitStatusReporter,
+};
+use crate::common::tcp_auth::TcpAuth;
 use crate::common::unit::UnitActivity;
 use crate::comms::{
     AnyDirectUpdate, DirectLink, DirectUpdate, GateStatus, Terminated,
@@ -40,7 +44,12 @@ use crate::comms::{
 use crate::ingress;
 use crate::manager::{Component, WaitPoint};
 use crate::payload::Update;
+use crate::roto_runtime::filter_metrics::ShadowConfig;
+use crate::roto_runtime::logger::ScriptLogger;
+use crate::roto_runtime::reload::{LiveRoto, Reloadable};
+use crate::roto_runtime::state::StateStore;
 use crate::roto_runtime::Ctx;
+use crate::units::flow_in::counters::TrafficCounters;
 use crate::units::rib_unit::rpki::RtrCache;
 use crate::units::{Gate, Unit};
 
@@ -48,7 +57,7 @@ use super::metrics::BgpTcpInMetrics;
 use super::router_handler::handle_connection;
 use super::status_reporter::BgpTcpInStatusReporter;
 
-use super::peer_config::{CombinedConfig, PeerConfigs};
+use super::peer_config::{CombinedConfig, PeerConfigs, PrefixOrExact};
 
 //----------- BgpTcpIn -------------------------------------------------------
 
@@ -78,6 +87,19 @@ pub struct BgpTcpIn {
 
     #[serde(default)]
     pub filter_name: FilterName,
+
+    /// The RTR client unit whose VRPs the roto filter validates routes
+    /// against.
+    #[serde(default)]
+    pub rtr_cache: Option<String>,
+
+    /// The flow-in unit whose traffic counts the roto filter can use.
+    #[serde(default)]
+    pub traffic: Option<String>,
+
+    /// Run the roto filter in shadow mode, processing all updates.
+    #[serde(default)]
+    pub shadow: Option<ShadowConfig>,
     ///// Outgoing BGP UPDATEs can come from these sources.
     //pub sources: Vec<DirectLink>
 }
@@ -99,6 +121,9 @@ impl BgpTcpIn {
             my_bgp_id: Default::default(),
             peer_configs: Default::default(),
             filter_name: Default::default(),
+            rtr_cache: None,
+            traffic: None,
+            shadow: None,
             //sources: Vec::new(),
         }
     }
@@ -118,12 +143,26 @@ impl BgpTcpIn {
         let ingresses = component.ingresses();
 
         // Setup status reporting
-        let status_reporter = Arc::new(BgpTcpInStatusReporter::new(
-            &unit_name,
-            metrics.clone(),
-        ));
+        let status_reporter = Arc::new(
+            BgpTcpInStatusReporter::new(&unit_name, metrics.clone())
+                .with_health(component.health()),
+        );
+
+        let roto = component.roto().clone();
 
-        let roto_compiled = component.roto_compiled().clone();
+        let rtr_cache = self
+            .rtr_cache
+            .as_ref()
+            .map(|name| component.rtr_caches().get(name))
+            .unwrap_or_default();
+
+        let traffic = self
+            .traffic
+            .as_ref()
+            .map(|name| component.traffic_counters().get(name))
+            .unwrap_or_default();
+
+        let roto_state = component.roto_state();
 
         // Wait for other components to be, and signal to other components
         // that we are, ready to start. All units and targets start together,
@@ -146,7 +185,10 @@ impl BgpTcpIn {
             gate,
             metrics,
             status_reporter,
-            roto_compiled,
+            roto,
+            rtr_cache,
+            traffic,
+            roto_state,
             ingresses,
         )
         .run::<_, _, StandardTcpStream, BgpTcpInRunner>(
@@ -162,7 +204,7 @@ trait ConfigAcceptor {
     #[allow(clippy::too_many_arguments)]
     fn accept_config(
         child_name: String,
-        roto_function: Option<RotoFunc>,
+        roto_function: Reloadable<Option<RotoFunc>>,
         roto_context: Arc<Mutex<Ctx>>,
         gate: &Gate,
         bgp: &BgpTcpIn,
@@ -192,7 +234,16 @@ struct BgpTcpInRunner {
 
     status_reporter: Arc<BgpTcpInStatusReporter>,
 
-    roto_compiled: Option<Arc<CompiledRoto>>,
+    roto: Arc<LiveRoto>,
+
+    // The VRPs for the roto filter to validate routes against.
+    rtr_cache: Arc<RtrCache>,
+
+    // The traffic counts for the roto filter to use.
+    traffic: Arc<TrafficCounters>,
+
+    // The state kept by the roto filter.
+    roto_state: Arc<StateStore>,
 
     // To send commands to a Session based on peer IP + ASN.
     live_sessions: Arc<Mutex<LiveSessions>>,
@@ -209,12 +260,16 @@ impl fmt::Debug for BgpTcpInRunner {
 }
 
 impl BgpTcpInRunner {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         bgp: BgpTcpIn,
         gate: Gate,
         metrics: Arc<BgpTcpInMetrics>,
         status_reporter: Arc<BgpTcpInStatusReporter>,
-        roto_compiled: Option<Arc<CompiledRoto>>,
+        roto: Arc<LiveRoto>,
+        rtr_cache: Arc<RtrCache>,
+        traffic: Arc<TrafficCounters>,
+        roto_state: Arc<StateStore>,
         ingresses: Arc<ingress::Register>,
     ) -> Self {
         BgpTcpInRunner {
@@ -222,7 +277,10 @@ impl BgpTcpInRunner {
             gate,
             metrics,
             status_reporter,
-            roto_compiled,
+            roto,
+            rtr_cache,
+            traffic,
+            roto_state,
             live_sessions: Arc::new(Mutex::new(HashMap::new())),
             ingresses,
         }
@@ -239,7 +297,10 @@ impl BgpTcpInRunner {
             status_reporter: Default::default(),
             live_sessions: Arc::new(Mutex::new(HashMap::new())),
             ingresses: Arc::new(ingress::Register::default()),
-            roto_compiled: None,
+            roto: Default::default(),
+            rtr_cache: Default::default(),
+            traffic: Default::default(),
+            roto_state: Default::default(),
         };
 
         (runner, gate_agent)
@@ -254,7 +315,7 @@ impl BgpTcpInRunner {
         T: TcpListenerFactory<U>,
         U: TcpListener<V>,
         V: TcpStreamWrapper,
-        F: ConfigAcceptor,
+        F: ConfigAcceptor + 'static,
     {
         // Loop until terminated, accepting TCP connections from routers and
         // spawning tasks to handle them.
@@ -266,25 +327,41 @@ impl BgpTcpInRunner {
             link.connect(arc_self.clone(), false).await.unwrap();
         }
 
-        let roto_function: Option<RotoFunc> =
-            arc_self.roto_compiled.clone().and_then(|c| {
-                let mut c = c.lock().unwrap();
+        let mut roto_context = Ctx::new(
+            RotoOutputStream::new_rced(),
+            arc_self.rtr_cache.clone(),
+            arc_self.traffic.clone(),
+        );
+        roto_context.state = arc_self.roto_state.clone();
+        roto_context.logger = Arc::new(ScriptLogger::new(
+            arc_self.status_reporter.name().into(),
+        ));
+
+        let roto_function: Reloadable<Option<RotoFunc>> = Reloadable::new(
+            arc_self.roto.clone(),
+            |c| {
                 c.get_function(ROTO_FUNC_FILTER_NAME)
                 .inspect_err(|_|
                     warn!("Loaded Roto script has no filter for bgp-in")
                 )
                 .ok()
-            });
-
-        let mut roto_context = Ctx::empty();
-
-        if let Some(c) = arc_self.roto_compiled.clone() {
-            roto_context.prepare(&mut c.lock().unwrap());
-        }
+            },
+            &mut roto_context,
+        );
 
         let roto_context = Arc::new(Mutex::new(roto_context));
 
-        loop {
+        // Peers in active mode won't connect to us, so keep connecting to
+        // them for as long as the unit runs.
+        let connector = crate::tokio::spawn(
+            "bgp-tcp-in-connector",
+            arc_self.clone().connect_active_peers::<F>(
+                roto_function.clone(),
+                roto_context.clone(),
+            ),
+        );
+
+        let res = 'outer: loop {
             let listen_addr = arc_self.bgp.load().listen.clone();
 
             let bind_with_backoff = || async {
@@ -308,11 +385,22 @@ impl BgpTcpInRunner {
                 match arc_self.process_until(bind_with_backoff()).await {
                     ControlFlow::Continue(Ok(res)) => res,
                     ControlFlow::Continue(Err(_err)) => continue,
-                    ControlFlow::Break(Terminated) => return Err(Terminated),
+                    ControlFlow::Break(Terminated) => {
+                        break 'outer Err(Terminated)
+                    }
                 };
 
             status_reporter.listener_listening(&listen_addr);
 
+            let bgp = arc_self.bgp.load();
+            for (remote_net, auth) in bgp.peer_configs.tcp_auth() {
+                let (addr, len) = remote_net.addr_and_len();
+                if let Err(err) = listener.set_tcp_auth(addr, len, auth) {
+                    status_reporter
+                        .tcp_auth_error(format!("{addr}/{len}"), err);
+                }
+            }
+
             'inner: loop {
                 match arc_self.process_until(listener.accept()).await {
                     ControlFlow::Continue(Ok((tcp_stream, peer_addr))) => {
@@ -364,9 +452,117 @@ impl BgpTcpInRunner {
                         }
                     }
                     ControlFlow::Continue(Err(_err)) => break 'inner,
-                    ControlFlow::Break(Terminated) => return Err(Terminated),
+                    ControlFlow::Break(Terminated) => {
+                        break 'outer Err(Terminated)
+                    }
                 }
             }
+        };
+
+        connector.abort();
+        res
+    }
+
+    /// Connects to `peer_addr`, authenticating the connection with `auth`.
+    async fn connect(
+        peer_addr: SocketAddr,
+        auth: TcpAuth,
+    ) -> std::io::Result<TcpStream> {
+        let socket = match peer_addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        let (addr, len) =
+            PrefixOrExact::Exact(peer_addr.ip()).addr_and_len();
+        auth.apply(&socket, addr, len)?;
+        socket.connect(peer_addr).await
+    }
+
+    /// Keeps connecting to the peers configured in active mode.
+    ///
+    /// Peers without a live session are connected to at most once every
+    /// `CONNECT_RETRY`, and the connections are handed to `F` just like
+    /// those accepted by the listener.
+    async fn connect_active_peers<F: ConfigAcceptor>(
+        self: Arc<Self>,
+        roto_function: Reloadable<Option<RotoFunc>>,
+        roto_context: Arc<Mutex<Ctx>>,
+    ) {
+        const CONNECT_RETRY: Duration = Duration::from_secs(30);
+        const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+        let mut last_attempts: HashMap<IpAddr, Instant> = HashMap::new();
+        loop {
+            let bgp = self.bgp.load_full();
+            let due = {
+                let live_sessions = self.live_sessions.lock().unwrap();
+                bgp.peer_configs
+                    .active()
+                    .filter(|(addr, _)| {
+                        !live_sessions.keys().any(|(ip, _)| ip == addr)
+                            && last_attempts.get(addr).is_none_or(|at| {
+                                at.elapsed() >= CONNECT_RETRY
+                            })
+                    })
+                    .map(|(addr, cfg)| SocketAddr::new(addr, cfg.port()))
+                    .collect::<Vec<_>>()
+            };
+            for peer_addr in &due {
+                last_attempts.insert(peer_addr.ip(), Instant::now());
+            }
+
+            let attempts = due.iter().map(|peer_addr| {
+                let auth = bgp
+                    .peer_configs
+                    .get_exact(&PrefixOrExact::Exact(peer_addr.ip()))
+                    .map(|cfg| cfg.tcp_auth().clone())
+                    .unwrap_or_default();
+                timeout(CONNECT_TIMEOUT, Self::connect(*peer_addr, auth))
+            });
+            for (peer_addr, res) in due.iter().zip(join_all(attempts).await) {
+                let tcp_stream = match res {
+                    Ok(Ok(tcp_stream)) => tcp_stream,
+                    Ok(Err(err)) => {
+                        self.status_reporter
+                            .peer_connect_error(*peer_addr, err);
+                        continue;
+                    }
+                    Err(_) => {
+                        self.status_reporter
+                            .peer_connect_error(*peer_addr, "timed out");
+                        continue;
+                    }
+                };
+                self.status_reporter.peer_connected(*peer_addr);
+
+                // The configuration might have changed while connecting.
+                let bgp = self.bgp.load_full();
+                let remote_net = PrefixOrExact::Exact(peer_addr.ip());
+                let Some(cfg) = bgp.peer_configs.get_exact(&remote_net)
+                else {
+                    continue;
+                };
+                let child_name =
+                    format!("bgp[{}:{}]", peer_addr.ip(), peer_addr.port());
+                let child_status_reporter =
+                    Arc::new(self.status_reporter.add_child(&child_name));
+                F::accept_config(
+                    child_name,
+                    roto_function.clone(),
+                    roto_context.clone(),
+                    &self.gate,
+                    &bgp,
+                    StandardTcpStream::from(tcp_stream),
+                    cfg,
+                    remote_net,
+                    child_status_reporter,
+                    self.live_sessions.clone(),
+                    self.ingresses.clone(),
+                    self.ingresses.register(),
+                );
+            }
+
+            sleep(Duration::from_secs(1)).await;
         }
     }
 
@@ -403,8 +599,17 @@ impl BgpTcpInRunner {
                             // router_handler() tasks will receive their
                             // own copy of this Reconfiguring status
                             // update and can react to it accordingly.
-                            let rebind =
-                                self.bgp.load().listen != new_unit.listen;
+                            // Keys for TCP authentication are set on the
+                            // listening socket, so changing them requires
+                            // binding anew as well.
+                            let rebind = {
+                                let bgp = self.bgp.load();
+                                bgp.listen != new_unit.listen
+                                    || !bgp
+                                        .peer_configs
+                                        .tcp_auth()
+                                        .eq(new_unit.peer_configs.tcp_auth())
+                            };
 
                             self.bgp.store(new_unit.into());
 
@@ -561,7 +766,7 @@ impl AnyDirectUpdate for BgpTcpInRunner {}
 impl ConfigAcceptor for BgpTcpInRunner {
     fn accept_config(
         child_name: String,
-        roto_function: Option<RotoFunc>,
+        roto_function: Reloadable<Option<RotoFunc>>,
         roto_context: Arc<Mutex<Ctx>>,
         gate: &Gate,
         bgp: &BgpTcpIn,
@@ -576,6 +781,11 @@ impl ConfigAcceptor for BgpTcpInRunner {
         let (cmds_tx, cmds_rx) = mpsc::channel(10 * 10); //XXX this is limiting and
                                                          //causes loss
         let tcp_stream = tcp_stream.into_inner().unwrap(); // SAFETY: StandardTcpStream::into_inner() always returns Ok(...)
+        if let Some(ttl) = cfg.multihop() {
+            if let Err(err) = tcp_stream.set_ttl(ttl.into()) {
+                warn!("[{}] could not set TTL to {}: {}", child_name, ttl, err);
+            }
+        }
         crate::tokio::spawn(
             &child_name,
             handle_connection(
@@ -613,7 +823,7 @@ mod tests {
         common::{
             net::TcpStreamWrapper,
             status_reporter::AnyStatusReporter,
-        }, comms::{Gate, GateAgent, Terminated}, ingress, roto_runtime::types::RotoScripts, tests::util::{
+        }, comms::{Gate, GateAgent, Terminated}, ingress, roto_runtime::{reload::Reloadable, types::RotoScripts}, tests::util::{
             internal::get_testable_metrics_snapshot,
             net::{
                 MockTcpListener, MockTcpListenerFactory, MockTcpStreamWrapper,
@@ -745,6 +955,49 @@ mod tests {
         assert_eq!(res, Err(Terminated));
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    #[cfg(not(tarpaulin))]
+    async fn connect_to_active_peer() {
+        let peer =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = peer.local_addr().unwrap().port();
+
+        let mock_listener_factory_cb =
+            |_addr| Ok(MockTcpListener::new(std::future::pending));
+        let mut unit_settings =
+            BgpTcpIn::mock("dummy-listen-address", Asn::from_u32(12345));
+        unit_settings.peer_configs = toml::from_str(&format!(
+            r#"
+            ["127.0.0.1"]
+            name = "Active"
+            remote_asn = 64500
+            mode = "active"
+            port = {port}
+            "#
+        ))
+        .unwrap();
+
+        let (runner_fut, gate_agent, status_reporter) =
+            setup_test_with_settings(unit_settings, mock_listener_factory_cb);
+        let join_handle =
+            crate::tokio::spawn("mock_bgp_tcp_in_runner", runner_fut);
+
+        let (_stream, _addr) = peer.accept().await.unwrap();
+        loop {
+            let metrics = get_testable_metrics_snapshot(
+                &status_reporter.metrics().unwrap(),
+            );
+            if metrics.with_name::<usize>("bgp_tcp_in_connect_count") > 0 {
+                break;
+            }
+        }
+
+        gate_agent.terminate().await;
+
+        let res = join_handle.await.unwrap();
+        assert_eq!(res, Err(Terminated));
+    }
+
     //-------- Test helpers --------------------------------------------------
 
     fn setup_test<T, U, Fut>(
@@ -761,11 +1014,28 @@ mod tests {
                 Output = std::io::Result<(MockTcpStreamWrapper, SocketAddr)>,
             > + Send,
     {
-        let mock_listener_factory =
-            MockTcpListenerFactory::new(mock_listener_factory_cb);
-
         let unit_settings =
             BgpTcpIn::mock("dummy-listen-address", Asn::from_u32(12345));
+        setup_test_with_settings(unit_settings, mock_listener_factory_cb)
+    }
+
+    fn setup_test_with_settings<T, U, Fut>(
+        unit_settings: BgpTcpIn,
+        mock_listener_factory_cb: T,
+    ) -> (
+        impl Future<Output = Result<(), Terminated>>,
+        GateAgent,
+        Arc<BgpTcpInStatusReporter>,
+    )
+    where
+        T: Fn(String) -> std::io::Result<MockTcpListener<U, Fut>> + Sync,
+        U: Fn() -> Fut + Send + Sync,
+        Fut: Future<
+                Output = std::io::Result<(MockTcpStreamWrapper, SocketAddr)>,
+            > + Send,
+    {
+        let mock_listener_factory =
+            MockTcpListenerFactory::new(mock_listener_factory_cb);
 
         let (runner, gate_agent) = BgpTcpInRunner::mock(unit_settings);
 
@@ -786,7 +1056,7 @@ mod tests {
     impl ConfigAcceptor for NoOpConfigAcceptor {
         fn accept_config(
             _child_name: String,
-            _roto_function: Option<RotoFunc>,
+            _roto_function: Reloadable<Option<RotoFunc>>,
             _roto_context: Arc<std::sync::Mutex<crate::roto_runtime::Ctx>>,
             _gate: &Gate,
             _bgp: &BgpTcpIn,
@@ -10,6 +10,7 @@ use tokio::sync::Mutex;
 //use roto::types::builtin::SourceId;
 
 use crate::{
+    common::openapi,
     http::{self, PercentDecodedPath, ProcessRequest},
     ingress,
     units::bmp_tcp_in::{
@@ -60,6 +61,32 @@ impl RouterInfoApi {
             ingresses,
         }
     }
+
+    /// Describes the pages of the routers served below `base`.
+    pub fn describe_pages(base: &str, paths: &mut openapi::Paths) {
+        let router = "The ingress id, id, sysName or address of the router";
+        let peer = "The peer, as linked to from the page of the router";
+        paths
+            .get(format!("{base}{{router}}"), "A monitored router")
+            .produces("text/html")
+            .path_param("router", router);
+        paths
+            .get(
+                format!("{base}{{router}}/prefixes/{{peer}}"),
+                "A monitored router, with the prefixes of a peer",
+            )
+            .produces("text/html")
+            .path_param("router", router)
+            .path_param("peer", peer);
+        paths
+            .get(
+                format!("{base}{{router}}/flags/{{peer}}"),
+                "A monitored router, with the flags of a peer",
+            )
+            .produces("text/html")
+            .path_param("router", router)
+            .path_param("peer", peer);
+    }
 }
 
 #[async_trait]
@@ -157,4 +184,8 @@ impl ProcessRequest for RouterInfoApi {
 
         None
     }
+
+    fn describe(&self, paths: &mut openapi::Paths) {
+        Self::describe_pages(&self.http_api_path, paths);
+    }
 }
@@ -9,13 +9,17 @@ use hyper::{Body, Method, Request, Response};
 //use roto::types::builtin::ingress::IngressId;
 
 use crate::{
-    common::frim::FrimMap,
+    common::{
+        frim::FrimMap,
+        openapi::{self, Schema},
+    },
     http::{
         self, extract_params, get_param, MatchedParam, PercentDecodedPath,
         ProcessRequest,
     },
     ingress,
     units::bmp_tcp_in::{
+        http::RouterInfoApi,
         metrics::BmpTcpInMetrics,
         state_machine::{BmpState, BmpStateDetails, BmpStateMachineMetrics},
         types::RouterInfo,
@@ -75,6 +79,27 @@ impl ProcessRequest for RouterListApi {
             None
         }
     }
+
+    fn describe(&self, paths: &mut openapi::Paths) {
+        let base = self.http_api_path.as_str();
+        paths
+            .get(base, "The monitored routers")
+            .produces("text/html")
+            .query(
+                "sort_by",
+                Schema::String,
+                "addr, sys_name, sys_desc, state, peers_up, \
+                peers_up_eor_capable, peers_up_dumping, \
+                peers_up_eor_capable_pc, peers_up_dumping_pc, \
+                invalid_messages, soft_parse_errors or hard_parse_errors",
+            )
+            .query("sort_order", Schema::String, "asc or desc");
+
+        // The pages of the routers are served by a RouterInfoApi per
+        // connected router, but are described here too so they are listed
+        // even while no router is connected.
+        RouterInfoApi::describe_pages(base, paths);
+    }
 }
 
 impl RouterListApi {
@@ -17,13 +17,17 @@ pub struct BmpTcpInMetrics {
     gate: Option<Arc<GateMetrics>>, // optional to make testing easier
     pub listener_bound_count: Arc<AtomicUsize>,
     pub connection_accepted_count: Arc<AtomicUsize>,
+    pub connect_count: Arc<AtomicUsize>,
+    pub connect_error_count: Arc<AtomicUsize>,
     pub connection_lost_count: Arc<AtomicUsize>,
+    pub tls_handshake_failure_count: Arc<AtomicUsize>,
     routers: Arc<FrimMap<Arc<RouterId>, Arc<RouterMetrics>>>,
 }
 
 impl GraphStatus for BmpTcpInMetrics {
     fn status_text(&self) -> String {
         let num_clients = self.connection_accepted_count.load(SeqCst)
+            + self.connect_count.load(SeqCst)
             - self.connection_lost_count.load(SeqCst);
         let num_msgs_out = self
             .gate
@@ -35,7 +39,8 @@ impl GraphStatus for BmpTcpInMetrics {
 
     fn okay(&self) -> Option<bool> {
         let connection_accepted_count =
-            self.connection_accepted_count.load(SeqCst);
+            self.connection_accepted_count.load(SeqCst)
+                + self.connect_count.load(SeqCst);
         if connection_accepted_count > 0 {
             let connection_lost_count =
                 self.connection_lost_count.load(SeqCst);
@@ -92,12 +97,30 @@ impl BmpTcpInMetrics {
         MetricType::Counter,
         MetricUnit::Total,
     );
+    const CONNECT_COUNT_METRIC: Metric = Metric::new(
+        "bmp_tcp_in_connect_count",
+        "the number of times a connection to a router in active mode was made",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const CONNECT_ERROR_COUNT_METRIC: Metric = Metric::new(
+        "bmp_tcp_in_connect_error_count",
+        "the number of times connecting to a router in active mode failed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
     const CONNECTION_LOST_COUNT_METRIC: Metric = Metric::new(
         "bmp_tcp_in_connection_lost_count",
         "the number of times the connection to a router was lost",
         MetricType::Counter,
         MetricUnit::Total,
     );
+    const TLS_HANDSHAKE_FAILURE_COUNT_METRIC: Metric = Metric::new(
+        "bmp_tcp_in_tls_handshake_failure_count",
+        "the number of times the TLS handshake with a router failed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
     const NUM_BMP_MESSAGES_RECEIVED_METRIC: Metric = Metric::new(
         "bmp_tcp_in_num_bmp_messages_received",
         "the number of BMP messages successfully received by RFC 7854 message type code",
@@ -172,12 +195,30 @@ impl metrics::Source for BmpTcpInMetrics {
             self.connection_accepted_count.load(SeqCst),
         );
 
+        target.append_simple(
+            &Self::CONNECT_COUNT_METRIC,
+            Some(unit_name),
+            self.connect_count.load(SeqCst),
+        );
+
+        target.append_simple(
+            &Self::CONNECT_ERROR_COUNT_METRIC,
+            Some(unit_name),
+            self.connect_error_count.load(SeqCst),
+        );
+
         target.append_simple(
             &Self::CONNECTION_LOST_COUNT_METRIC,
             Some(unit_name),
             self.connection_lost_count.load(SeqCst),
         );
 
+        target.append_simple(
+            &Self::TLS_HANDSHAKE_FAILURE_COUNT_METRIC,
+            Some(unit_name),
+            self.tls_handshake_failure_count.load(SeqCst),
+        );
+
         for (router_id, metrics) in self.routers.guard().iter() {
             let router_id = router_id.as_str();
 
@@ -1,9 +1,9 @@
 mod http;
 mod io;
-mod metrics;
+pub(crate) mod metrics;
 mod router_handler;
-mod state_machine;
-mod status_reporter;
+pub(crate) mod state_machine;
+pub(crate) mod status_reporter;
 mod types;
 mod util;
 
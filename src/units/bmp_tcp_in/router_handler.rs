@@ -1,11 +1,13 @@
 //! BMP message stream handler for a single connected BMP publishing client.
 use std::cell::RefCell;
+use std::future::IntoFuture;
 use std::hash::{self, DefaultHasher, Hash};
 use std::net::IpAddr;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use std::{net::SocketAddr, ops::ControlFlow};
 
-use arc_swap::ArcSwap;
+use arc_swap::{ArcSwap, ArcSwapOption};
 use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use hash32::Hasher;
@@ -15,14 +17,21 @@ use routecore::bmp::message::Message;
 
 use smallvec::smallvec;
 use tokio::sync::Mutex;
+use tokio::time::timeout;
 use tokio::{io::AsyncRead, net::TcpStream};
 
 use crate::roto_runtime::types::{
     FilterName, Output, OutputStreamMessage, PeerRibType, Provenance, RotoOutputStream, RotoScripts, RouteContext
 };
 
+use crate::common::{
+    quic::{self, Incoming},
+    tls::{self, TlsAcceptor},
+};
 use crate::ingress::{self, IngressId};
 use crate::payload::RouterId;
+use crate::roto_runtime::filter_metrics::{self, ShadowConfig};
+use crate::roto_runtime::reload::Reloadable;
 use crate::roto_runtime::Ctx;
 use crate::tracing::Tracer;
 use crate::units::rib_unit::rpki::RtrCache;
@@ -39,12 +48,15 @@ use crate::{
 
 use super::io::FatalError;
 use super::state_machine::{BmpState, BmpStateMachineMetrics, MessageType};
-use super::unit::{RotoFunc, TracingMode};
+use super::unit::{RotoFunc, TracingMode, ROTO_FUNC_FILTER_NAME};
 use super::util::format_source_id;
 
+/// How long a router gets to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct RouterHandler {
     gate: Gate,
-    roto_function: Option<RotoFunc>,
+    roto_function: Reloadable<Option<RotoFunc>>,
     roto_context: Arc<std::sync::Mutex<Ctx>>,
     router_id_template: Arc<ArcSwap<String>>,
     filter_name: Arc<ArcSwap<FilterName>>,
@@ -52,6 +64,7 @@ pub struct RouterHandler {
     state_machine: Arc<Mutex<Option<BmpState>>>,
     tracer: Arc<Tracer>,
     tracing_mode: Arc<ArcSwap<TracingMode>>,
+    shadow: Arc<ArcSwapOption<ShadowConfig>>,
     last_msg_at: Option<Arc<RwLock<DateTime<Utc>>>>,
     bmp_metrics: Arc<BmpStateMachineMetrics>,
 
@@ -64,7 +77,7 @@ impl RouterHandler {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         gate: Gate,
-        roto_function: Option<RotoFunc>,
+        roto_function: Reloadable<Option<RotoFunc>>,
         roto_context: Arc<std::sync::Mutex<Ctx>>,
         router_id_template: Arc<ArcSwap<String>>,
         filter_name: Arc<ArcSwap<FilterName>>,
@@ -72,6 +85,7 @@ impl RouterHandler {
         state_machine: Arc<Mutex<Option<BmpState>>>,
         tracer: Arc<Tracer>,
         tracing_mode: Arc<ArcSwap<TracingMode>>,
+        shadow: Arc<ArcSwapOption<ShadowConfig>>,
         last_msg_at: Option<Arc<RwLock<DateTime<Utc>>>>,
         bmp_metrics: Arc<BmpStateMachineMetrics>,
     ) -> Self {
@@ -85,6 +99,7 @@ impl RouterHandler {
             state_machine,
             tracer,
             tracing_mode,
+            shadow,
             last_msg_at,
             bmp_metrics,
             rtr_cache: Default::default(),
@@ -129,9 +144,10 @@ impl RouterHandler {
             state_machine,
             tracer: Default::default(),
             tracing_mode: Default::default(),
+            shadow: Default::default(),
             last_msg_at: None,
             bmp_metrics,
-            roto_function: None,
+            roto_function: Reloadable::fixed(None),
             roto_context: Arc::new(std::sync::Mutex::new(Ctx::empty())),
         };
 
@@ -147,7 +163,36 @@ impl RouterHandler {
         ingress_register: Arc<ingress::Register>,
         // we need access to the ingress Register to register new IDs, for
         // every peer / session in the BMP connection
+        tls: Option<Arc<TlsAcceptor>>,
     ) {
+        if let Some(tls) = tls {
+            // TLS needs both directions of the connection, for alerts and
+            // key updates, even though no BMP messages are sent.
+            match timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(tcp_stream)).await
+            {
+                Ok(Ok(stream)) => {
+                    self.status_reporter.tls_established(
+                        router_addr,
+                        tls::peer_name(&stream).as_deref(),
+                    );
+                    self.read_from_router(
+                        stream,
+                        router_addr,
+                        ingress_id,
+                        ingress_register,
+                    )
+                    .await;
+                }
+                Ok(Err(err)) => {
+                    self.status_reporter.tls_handshake_failed(router_addr, err)
+                }
+                Err(_) => self
+                    .status_reporter
+                    .tls_handshake_failed(router_addr, "timed out"),
+            }
+            return;
+        }
+
         // Discard the write half of the TCP stream as we are a "monitoring
         // station" and per the BMP RFC 7584 specification _"No BMP message is
         // ever sent from the monitoring station to the monitored router"_.
@@ -157,6 +202,57 @@ impl RouterHandler {
             .await;
     }
 
+    /// Handles a router connecting over QUIC.
+    ///
+    /// The BMP messages are read from the first stream the router opens,
+    /// be it unidirectional or bidirectional.
+    pub async fn run_quic(
+        &self,
+        incoming: Incoming,
+        router_addr: SocketAddr,
+        ingress_id: IngressId,
+        ingress_register: Arc<ingress::Register>,
+    ) {
+        let conn = match timeout(
+            TLS_HANDSHAKE_TIMEOUT,
+            incoming.into_future(),
+        )
+        .await
+        {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(err)) => {
+                self.status_reporter.tls_handshake_failed(router_addr, err);
+                return;
+            }
+            Err(_) => {
+                self.status_reporter
+                    .tls_handshake_failed(router_addr, "timed out");
+                return;
+            }
+        };
+        self.status_reporter
+            .tls_established(router_addr, quic::peer_name(&conn).as_deref());
+        let stream = tokio::select! {
+            res = conn.accept_uni() => res,
+            res = conn.accept_bi() => res.map(|(_send, recv)| recv),
+        };
+        match stream {
+            Ok(stream) => {
+                self.read_from_router(
+                    stream,
+                    router_addr,
+                    ingress_id,
+                    ingress_register,
+                )
+                .await
+            }
+            Err(err) => debug!(
+                "Router {router_addr} closed its QUIC connection without \
+                 sending BMP messages: {err}"
+            ),
+        }
+    }
+
     async fn read_from_router<T: AsyncRead + Unpin>(
         &self,
         rx: T,
@@ -377,12 +473,15 @@ impl RouterHandler {
         let verdict;
         { // lock scope
         let mut ctx = self.roto_context.lock().unwrap();
-        verdict = self.roto_function.as_ref().map(|roto_function| {
-            roto_function.call(
-                &mut ctx,
-                roto::Val(msg.clone()),
-                roto::Val(provenance),
-            )
+        ctx.logger.set_ingress(Some(provenance.ingress_id));
+        verdict = self.roto_function.get(&mut ctx).map(|roto_function| {
+            filter_metrics::shared().run(ROTO_FUNC_FILTER_NAME, || {
+                roto_function.call(
+                    &mut ctx,
+                    roto::Val(msg.clone()),
+                    roto::Val(provenance),
+                )
+            })
         });
         
 
@@ -438,6 +537,17 @@ impl RouterHandler {
             }
         }
         } // end of lock scope
+
+        // In shadow mode, messages the filter rejects are processed anyway.
+        let verdict = match (verdict, self.shadow.load_full()) {
+            (Some(roto::Verdict::Reject(_)), Some(shadow)) => {
+                shadow.rejected(ROTO_FUNC_FILTER_NAME, || {
+                    format!("a BMP message from {addr}")
+                });
+                None
+            }
+            (verdict, _) => verdict,
+        };
             
         self.gate.update_data(Update::OutputStream(osms)).await;
         let next_state = match verdict {
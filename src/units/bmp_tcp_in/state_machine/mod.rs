@@ -7,9 +7,8 @@ mod status_reporter;
 #[cfg(test)]
 mod tests;
 
-pub(super) use {
-    machine::{BmpStateDetails, PeerAware, PeerStates},
-    processing::MessageType,
-};
+pub(super) use machine::{BmpStateDetails, PeerAware, PeerStates};
+
+pub(crate) use processing::MessageType;
 
 pub use {machine::BmpState, metrics::BmpStateMachineMetrics};
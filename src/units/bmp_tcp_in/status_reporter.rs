@@ -10,6 +10,7 @@ use crate::{
     common::status_reporter::{
         sr_log, AnyStatusReporter, Chainable, Named, UnitStatusReporter,
     },
+    health::{ComponentHealth, ComponentState},
     payload::RouterId,
 };
 
@@ -19,6 +20,7 @@ use super::metrics::BmpTcpInMetrics;
 pub struct BmpTcpInStatusReporter {
     name: String,
     metrics: Arc<BmpTcpInMetrics>,
+    health: Arc<ComponentHealth>,
 }
 
 impl BmpTcpInStatusReporter {
@@ -26,20 +28,34 @@ impl BmpTcpInStatusReporter {
         Self {
             name: format!("{}", name),
             metrics,
+            health: Default::default(),
         }
     }
 
+    /// Reports the state of the listener to the health of the unit too.
+    pub fn with_health(mut self, health: Arc<ComponentHealth>) -> Self {
+        self.health = health;
+        self
+    }
+
     pub fn _typed_metrics(&self) -> Arc<BmpTcpInMetrics> {
         self.metrics.clone()
     }
 
     pub fn bind_error<T: Display>(&self, listen_addr: &str, err: T) {
         sr_log!(warn: self, "Error while listening for connections on {}: {}", listen_addr, err);
+        self.health.report_error(&err);
+        self.health.set_state(ComponentState::Retrying);
+    }
+
+    pub fn tcp_auth_error<T: Display, U: Display>(&self, router: T, err: U) {
+        sr_log!(warn: self, "Error while setting up TCP authentication for {}: {}", router, err);
     }
 
     pub fn listener_listening(&self, server_uri: &str) {
         sr_log!(info: self, "Listening for connections on {}", server_uri);
         self.metrics.listener_bound_count.fetch_add(1, SeqCst);
+        self.health.set_state(ComponentState::Running);
     }
 
     pub fn listener_connection_accepted(&self, router_addr: SocketAddr) {
@@ -47,8 +63,52 @@ impl BmpTcpInStatusReporter {
         self.metrics.connection_accepted_count.fetch_add(1, SeqCst);
     }
 
+    pub fn tls_error<T: Display>(&self, err: T) {
+        sr_log!(error: self, "Error while setting up TLS: {}", err);
+    }
+
+    pub fn tls_established(
+        &self,
+        router_addr: SocketAddr,
+        client_name: Option<&str>,
+    ) {
+        match client_name {
+            Some(name) => {
+                sr_log!(debug: self, "TLS established with router {} presenting certificate '{}'", router_addr, name);
+            }
+            None => {
+                sr_log!(debug: self, "TLS established with router {}", router_addr);
+            }
+        }
+    }
+
+    pub fn tls_handshake_failed<T: Display>(
+        &self,
+        router_addr: SocketAddr,
+        err: T,
+    ) {
+        sr_log!(warn: self, "TLS handshake with router {} failed: {}", router_addr, err);
+        self.metrics.tls_handshake_failure_count.fetch_add(1, SeqCst);
+        self.metrics.connection_lost_count.fetch_add(1, SeqCst);
+    }
+
+    pub fn router_connected(&self, router_addr: SocketAddr) {
+        sr_log!(debug: self, "Connected to router {}", router_addr);
+        self.metrics.connect_count.fetch_add(1, SeqCst);
+    }
+
+    pub fn router_connect_error<T: Display>(
+        &self,
+        router_addr: SocketAddr,
+        err: T,
+    ) {
+        sr_log!(warn: self, "Error while connecting to router {}: {}", router_addr, err);
+        self.metrics.connect_error_count.fetch_add(1, SeqCst);
+    }
+
     pub fn listener_io_error<T: Display>(&self, err: T) {
         sr_log!(warn: self, "Error while listening for connections: {}", err);
+        self.health.report_error(err);
     }
 
     pub fn router_connection_lost(&self, router_id: &Arc<RouterId>) {
@@ -135,6 +195,7 @@ impl AnyStatusReporter for BmpTcpInStatusReporter {
 impl Chainable for BmpTcpInStatusReporter {
     fn add_child<T: Display>(&self, child_name: T) -> Self {
         Self::new(self.link_names(child_name), self.metrics.clone())
+            .with_health(self.health.clone())
     }
 }
 
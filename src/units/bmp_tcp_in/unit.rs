@@ -1,24 +1,29 @@
 //-------- Constants ---------------------------------------------------------
 
 use std::{
-    net::SocketAddr,
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
     ops::ControlFlow,
     str::FromStr,
     sync::{Arc, Weak},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use arc_swap::ArcSwap;
+use arc_swap::{ArcSwap, ArcSwapOption};
 use bytes::Bytes;
 use chrono::{DateTime, Utc};
-use futures::{future::select, pin_mut, Future};
-use log::warn;
+use futures::{
+    future::{join_all, select},
+    pin_mut, Future,
+};
+use log::{error, warn};
 use routecore::bmp::message::Message as BmpMessage;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use serde_with::{serde_as, DisplayFromStr};
 use tokio::{
+    net::{TcpSocket, TcpStream},
     sync::{Mutex, RwLock},
-    time::sleep,
+    time::{sleep, timeout},
 };
 
 use crate::{
@@ -28,22 +33,32 @@ use crate::{
             StandardTcpListenerFactory, StandardTcpStream, TcpListener,
             TcpListenerFactory, TcpStreamWrapper,
         },
-        status_reporter::Chainable,
+        quic::{self, QuicEndpoint, QuicServerConfig},
+        status_reporter::{Chainable, Named},
+        tcp_auth::TcpAuth,
+        tls::{TlsAcceptor, TlsServerConfig},
         unit::UnitActivity,
     },
     comms::{Gate, GateStatus, Terminated},
     ingress::{self, IngressId, IngressInfo},
     manager::{Component, WaitPoint},
     roto_runtime::{
+        filter_metrics::ShadowConfig,
+        logger::ScriptLogger,
+        reload::{LiveRoto, Reloadable},
+        state::StateStore,
         types::{
-            CompiledRoto, FilterName, Provenance, RotoOutputStream,
+            FilterName, Provenance, RotoOutputStream,
             RotoScripts
         },
         Ctx
     },
     tokio::TokioTaskMetrics,
     tracing::Tracer,
-    units::Unit
+    units::{
+        bgp_tcp_in::peer_config::PrefixOrExact,
+        flow_in::counters::TrafficCounters, rib_unit::rpki::RtrCache, Unit,
+    },
 };
 
 use super::{
@@ -136,6 +151,70 @@ pub struct BmpTcpIn {
 
     #[serde(default)]
     pub tracing_mode: TracingMode,
+
+    /// Run the roto filter in shadow mode, processing all messages.
+    #[serde(default)]
+    pub shadow: Option<ShadowConfig>,
+
+    /// The RTR client unit whose VRPs the roto filter validates routes
+    /// against.
+    #[serde(default)]
+    pub rtr_cache: Option<String>,
+
+    /// The flow-in unit whose traffic counts the roto filter can use.
+    #[serde(default)]
+    pub traffic: Option<String>,
+
+    /// The TCP MD5 passwords or TCP-AO keys of routers, keyed on their
+    /// address or prefix.
+    ///
+    /// On change: the listen port is bound anew, existing connections to
+    ///            routers will be unaffected.
+    #[serde(default, deserialize_with = "BmpTcpIn::deserialize_tcp_auth")]
+    pub tcp_auth: Arc<BTreeMap<PrefixOrExact, TcpAuth>>,
+
+    /// The certificate and key to accept BMP over TLS with, and optionally
+    /// the CAs router certificates must be issued by. Without it, only
+    /// plain TCP connections are accepted.
+    ///
+    /// On change: the files are read again, even if their names are the
+    ///            same, and used for new connections. Existing connections
+    ///            to routers will be unaffected.
+    #[serde(default)]
+    pub tls: Option<TlsServerConfig>,
+
+    /// The UDP address, certificate and key to accept BMP over QUIC with.
+    /// Routers open a stream on a connection with the application protocol
+    /// `bmp` and send their BMP messages on it as they would over TCP. This
+    /// is experimental, as no routers are known to support it yet.
+    ///
+    /// On change: the files are read again and used for new connections.
+    ///            A changed address is bound anew. Existing connections to
+    ///            routers will be unaffected.
+    #[serde(default)]
+    pub quic: Option<QuicServerConfig>,
+
+    /// The addresses of routers to connect to, for routers that wait for
+    /// the monitoring station to connect rather than connecting themselves.
+    /// The connections are authenticated according to `tcp_auth`, but TLS
+    /// is only used for incoming connections.
+    ///
+    /// On change: routers added are connected to, routers removed are not
+    ///            connected to again once their connection is lost.
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[serde(default)]
+    pub connect: Vec<SocketAddr>,
+
+    /// How long to wait before connecting to a router again after the
+    /// connection was lost. The delay doubles with every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "BmpTcpIn::default_reconnect_delay_secs")]
+    pub reconnect_delay_secs: Duration,
+
+    /// The longest to wait before connecting to a router again.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "BmpTcpIn::default_max_reconnect_delay_secs")]
+    pub max_reconnect_delay_secs: Duration,
 }
 
 impl BmpTcpIn {
@@ -158,10 +237,10 @@ impl BmpTcpIn {
         component.register_metrics(state_machine_metrics.clone());
 
         // Setup our status reporting
-        let status_reporter = Arc::new(BmpTcpInStatusReporter::new(
-            &unit_name,
-            bmp_in_metrics.clone(),
-        ));
+        let status_reporter = Arc::new(
+            BmpTcpInStatusReporter::new(&unit_name, bmp_in_metrics.clone())
+                .with_health(component.health()),
+        );
 
         // Setup storage for tracking multiple connected router BMP states at
         // once.
@@ -195,11 +274,41 @@ impl BmpTcpIn {
             (processor, router_info)
         };
 
-        let roto_compiled = component.roto_compiled().clone();
+        let roto = component.roto().clone();
         let tracer = component.tracer().clone();
 
+        let rtr_cache = self
+            .rtr_cache
+            .as_ref()
+            .map(|name| component.rtr_caches().get(name))
+            .unwrap_or_default();
+
+        let traffic = self
+            .traffic
+            .as_ref()
+            .map(|name| component.traffic_counters().get(name))
+            .unwrap_or_default();
+
+        let roto_state = component.roto_state();
+
         let ingress_register = component.ingresses();
 
+        let tls = match self.tls.as_ref().map(TlsAcceptor::new).transpose() {
+            Ok(tls) => tls.map(Arc::new),
+            Err(err) => {
+                error!("Unit {unit_name}: {err}");
+                return Err(Terminated);
+            }
+        };
+
+        let quic = match self.quic.map(QuicListener::new).transpose() {
+            Ok(quic) => Arc::new(ArcSwapOption::from(quic.map(Arc::new))),
+            Err(err) => {
+                error!("Unit {unit_name}: {err}");
+                return Err(Terminated);
+            }
+        };
+
         // Wait for other components to be, and signal to other components
         // that we are, ready to start. All units and targets start together,
         // otherwise data passed from one component to another may be lost if
@@ -215,10 +324,22 @@ impl BmpTcpIn {
         let component = Arc::new(RwLock::new(component));
 
         let tracing_mode = Arc::new(ArcSwap::from_pointee(self.tracing_mode));
+        let shadow = Arc::new(ArcSwapOption::from(self.shadow.map(Arc::new)));
+
+        let active = Arc::new(ArcSwap::from_pointee(ActiveRouters {
+            routers: self.connect,
+            tcp_auth: self.tcp_auth.clone(),
+            reconnect_delay: self.reconnect_delay_secs,
+            max_reconnect_delay: self.max_reconnect_delay_secs,
+        }));
 
         BmpTcpInRunner::new(
             component,
             self.listen,
+            self.tcp_auth,
+            tls,
+            quic,
+            active,
             self.http_api_path,
             gate,
             router_states,
@@ -227,11 +348,15 @@ impl BmpTcpIn {
             bmp_in_metrics,
             state_machine_metrics,
             status_reporter,
-            roto_compiled,
+            roto,
+            rtr_cache,
+            traffic,
+            roto_state,
             router_id_template,
             filter_name,
             tracer,
             tracing_mode,
+            shadow,
             ingress_register,
         )
         .run::<_, _, StandardTcpStream, BmpTcpInRunner>(Arc::new(
@@ -242,6 +367,19 @@ impl BmpTcpIn {
         Ok(())
     }
 
+    fn deserialize_tcp_auth<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Arc<BTreeMap<PrefixOrExact, TcpAuth>>, D::Error> {
+        let tcp_auth =
+            BTreeMap::<PrefixOrExact, TcpAuth>::deserialize(deserializer)?;
+        for (router, auth) in &tcp_auth {
+            auth.check().map_err(|err| {
+                serde::de::Error::custom(format!("router {router:?}: {err}"))
+            })?;
+        }
+        Ok(Arc::new(tcp_auth))
+    }
+
     fn default_http_api_path() -> Arc<String> {
         Arc::new("/routers/".to_string())
     }
@@ -249,11 +387,70 @@ impl BmpTcpIn {
     pub fn default_router_id_template() -> String {
         "{sys_name}".to_string()
     }
+
+    fn default_reconnect_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_reconnect_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+}
+
+//-------- ActiveRouters -----------------------------------------------------
+
+/// The routers to connect to and how.
+#[derive(Debug, Default)]
+struct ActiveRouters {
+    routers: Vec<SocketAddr>,
+    tcp_auth: Arc<BTreeMap<PrefixOrExact, TcpAuth>>,
+    reconnect_delay: Duration,
+    max_reconnect_delay: Duration,
+}
+
+impl ActiveRouters {
+    /// Returns the authentication for a router, from the most specific
+    /// matching entry of `tcp_auth`.
+    fn tcp_auth(&self, router: IpAddr) -> TcpAuth {
+        self.tcp_auth
+            .iter()
+            .filter(|(remote_net, _)| remote_net.contains(router))
+            .max_by_key(|(remote_net, _)| remote_net.addr_and_len().1)
+            .map(|(_, auth)| auth.clone())
+            .unwrap_or_default()
+    }
+}
+
+//-------- QuicListener ------------------------------------------------------
+
+/// The application protocol of BMP over QUIC.
+const BMP_ALPN: &[u8] = b"bmp";
+
+/// The settings of the QUIC endpoint, with the certificate loaded.
+struct QuicListener {
+    config: QuicServerConfig,
+    server: quinn::ServerConfig,
+}
+
+impl QuicListener {
+    fn new(config: QuicServerConfig) -> Result<Self, String> {
+        let server = config.server_config(&[BMP_ALPN])?;
+        Ok(QuicListener { config, server })
+    }
+}
+
+/// When to connect to a router in active mode again.
+struct Backoff {
+    not_before: Instant,
+
+    /// The delay after the next failed attempt.
+    delay: Duration,
 }
 
 //-------- BmpTcpInRunner ----------------------------------------------------
 
 trait ConfigAcceptor {
+    #[allow(clippy::too_many_arguments)]
     fn accept_config(
         child_name: String,
         router_handler: RouterHandler,
@@ -270,12 +467,18 @@ trait ConfigAcceptor {
         >, // Option is never None, instead Some is take()'n and replace()'d.
         router_info: &Arc<FrimMap<ingress::IngressId, Arc<RouterInfo>>>,
         ingress_register: Arc<ingress::Register>,
+        tls: Option<Arc<TlsAcceptor>>,
     );
 }
 
+#[derive(Clone)]
 struct BmpTcpInRunner {
     component: Arc<RwLock<Component>>,
     listen: Arc<SocketAddr>,
+    tcp_auth: Arc<BTreeMap<PrefixOrExact, TcpAuth>>,
+    tls: Option<Arc<TlsAcceptor>>,
+    quic: Arc<ArcSwapOption<QuicListener>>,
+    active: Arc<ArcSwap<ActiveRouters>>,
     http_api_path: Arc<String>,
     gate: Gate,
     router_states: Arc<
@@ -289,11 +492,15 @@ struct BmpTcpInRunner {
     bmp_in_metrics: Arc<BmpTcpInMetrics>,
     _state_machine_metrics: Arc<TokioTaskMetrics>,
     status_reporter: Arc<BmpTcpInStatusReporter>,
-    roto_compiled: Option<Arc<CompiledRoto>>,
+    roto: Arc<LiveRoto>,
+    rtr_cache: Arc<RtrCache>,
+    traffic: Arc<TrafficCounters>,
+    roto_state: Arc<StateStore>,
     router_id_template: Arc<ArcSwap<String>>,
     filter_name: Arc<ArcSwap<FilterName>>,
     tracer: Arc<Tracer>,
     tracing_mode: Arc<ArcSwap<TracingMode>>,
+    shadow: Arc<ArcSwapOption<ShadowConfig>>,
     ingress_register: Arc<ingress::Register>,
 }
 
@@ -302,6 +509,10 @@ impl BmpTcpInRunner {
     fn new(
         component: Arc<RwLock<Component>>,
         listen: Arc<SocketAddr>,
+        tcp_auth: Arc<BTreeMap<PrefixOrExact, TcpAuth>>,
+        tls: Option<Arc<TlsAcceptor>>,
+        quic: Arc<ArcSwapOption<QuicListener>>,
+        active: Arc<ArcSwap<ActiveRouters>>,
         http_api_path: Arc<String>,
         gate: Gate,
         router_states: Arc<
@@ -315,16 +526,24 @@ impl BmpTcpInRunner {
         bmp_in_metrics: Arc<BmpTcpInMetrics>,
         _state_machine_metrics: Arc<TokioTaskMetrics>,
         status_reporter: Arc<BmpTcpInStatusReporter>,
-        roto_compiled: Option<Arc<CompiledRoto>>,
+        roto: Arc<LiveRoto>,
+        rtr_cache: Arc<RtrCache>,
+        traffic: Arc<TrafficCounters>,
+        roto_state: Arc<StateStore>,
         router_id_template: Arc<ArcSwap<String>>,
         filter_name: Arc<ArcSwap<FilterName>>,
         tracer: Arc<Tracer>,
         tracing_mode: Arc<ArcSwap<TracingMode>>,
+        shadow: Arc<ArcSwapOption<ShadowConfig>>,
         ingress_register: Arc<ingress::Register>,
     ) -> Self {
         Self {
             component,
             listen,
+            tcp_auth,
+            tls,
+            quic,
+            active,
             http_api_path,
             gate,
             router_states,
@@ -333,11 +552,15 @@ impl BmpTcpInRunner {
             bmp_in_metrics,
             _state_machine_metrics,
             status_reporter,
-            roto_compiled,
+            roto,
+            rtr_cache,
+            traffic,
+            roto_state,
             router_id_template,
             filter_name,
             tracer,
             tracing_mode,
+            shadow,
             ingress_register,
         }
     }
@@ -349,6 +572,10 @@ impl BmpTcpInRunner {
         let runner = Self {
             component: Default::default(),
             listen: Arc::new("127.0.0.1:12345".parse().unwrap()),
+            tcp_auth: Default::default(),
+            tls: None,
+            quic: Default::default(),
+            active: Default::default(),
             http_api_path: BmpTcpIn::default_http_api_path(),
             gate,
             router_states: Default::default(),
@@ -364,8 +591,12 @@ impl BmpTcpInRunner {
             filter_name: Default::default(),
             tracer: Default::default(),
             tracing_mode: Default::default(),
+            shadow: Default::default(),
             ingress_register: Arc::default(),
-            roto_compiled: todo!(),
+            roto: Default::default(),
+            rtr_cache: Default::default(),
+            traffic: Default::default(),
+            roto_state: Default::default(),
         };
 
         (runner, gate_agent)
@@ -379,32 +610,58 @@ impl BmpTcpInRunner {
         T: TcpListenerFactory<U>,
         U: TcpListener<V>,
         V: TcpStreamWrapper,
-        F: ConfigAcceptor,
+        F: ConfigAcceptor + 'static,
     {
         // Loop until terminated, accepting TCP connections from routers and
         // spawning tasks to handle them.
         let status_reporter = self.status_reporter.clone();
 
-        let roto_function: Option<RotoFunc> =
-            self.roto_compiled.clone().and_then(|c| {
-                let mut c = c.lock().unwrap();
+        let mut roto_context = Ctx::new(
+            RotoOutputStream::new_rced(),
+            self.rtr_cache.clone(),
+            self.traffic.clone(),
+        );
+        roto_context.state = self.roto_state.clone();
+        roto_context.logger =
+            Arc::new(ScriptLogger::new(self.status_reporter.name().into()));
+
+        let roto_function: Reloadable<Option<RotoFunc>> = Reloadable::new(
+            self.roto.clone(),
+            |c| {
                 c.get_function(ROTO_FUNC_FILTER_NAME)
                 .inspect_err(|_|
                     warn!("Loaded Roto script has no filter for bmp-in")
                 )
                 .ok()
-            });
-
-        let mut roto_context = Ctx::empty();
-
-        if let Some(c) = self.roto_compiled.clone() {
-            roto_context.prepare(&mut c.lock().unwrap());
-        }
+            },
+            &mut roto_context,
+        );
 
         let roto_context = Arc::new(std::sync::Mutex::new(roto_context));
 
         let unit_ingress_id = self.ingress_register.register();
-        loop {
+
+        // Routers in active mode won't connect to us, so keep connecting to
+        // them for as long as the unit runs.
+        let connector = crate::tokio::spawn(
+            "bmp-tcp-in-connector",
+            Arc::new(self.clone()).connect_active_routers::<F>(
+                unit_ingress_id,
+                roto_function.clone(),
+                roto_context.clone(),
+            ),
+        );
+
+        let quic_acceptor = crate::tokio::spawn(
+            "bmp-tcp-in-quic",
+            Arc::new(self.clone()).accept_quic_routers(
+                unit_ingress_id,
+                roto_function.clone(),
+                roto_context.clone(),
+            ),
+        );
+
+        let res = 'outer: loop {
             let listen_addr = self.listen.clone();
 
             let bind_with_backoff = || async {
@@ -430,78 +687,36 @@ impl BmpTcpInRunner {
             {
                 ControlFlow::Continue(Ok(res)) => res,
                 ControlFlow::Continue(Err(_err)) => continue,
-                ControlFlow::Break(Terminated) => return Err(Terminated),
+                ControlFlow::Break(Terminated) => {
+                    break 'outer Err(Terminated)
+                }
             };
 
             status_reporter.listener_listening(&listen_addr.to_string());
 
+            for (remote_net, auth) in self.tcp_auth.iter() {
+                let (addr, len) = remote_net.addr_and_len();
+                if let Err(err) = listener.set_tcp_auth(addr, len, auth) {
+                    status_reporter
+                        .tcp_auth_error(format!("{addr}/{len}"), err);
+                }
+            }
+
             'inner: loop {
                 match self.process_until(listener.accept()).await {
                     ControlFlow::Continue(Ok((tcp_stream, client_addr))) => {
-
-                        let query_ingress = IngressInfo::new()
-                            .with_parent(unit_ingress_id)
-                            .with_remote_addr(client_addr.ip())
-                        ;
-                        let router_ingress_id;
-                        if let Some((ingress_id, _ingress_info)) = self.ingress_register.find_existing_bmp_router(&query_ingress) {
-                            router_ingress_id = ingress_id;
-                        } else {
-                            router_ingress_id = self.ingress_register.register();
-                            self.ingress_register.update_info(router_ingress_id, query_ingress);
-                        }
-
-                        let state_machine = Arc::new(Mutex::new(Some(
-                            self.router_connected(router_ingress_id),
-                        )));
-
-                        let last_msg_at = {
-                            let weak_ref = Arc::downgrade(&state_machine);
-                            self.setup_router_specific_api_endpoint(
-                                weak_ref, router_ingress_id,
+                        let (child_name, router_handler, router_ingress_id) =
+                            self.router_handler(
+                                client_addr,
+                                unit_ingress_id,
+                                &roto_function,
+                                &roto_context,
                             )
-                            .await
-                        };
-
-                        self.router_states
-                            .insert(router_ingress_id, state_machine.clone());
+                            .await;
 
                         status_reporter
                             .listener_connection_accepted(client_addr);
 
-                        // Spawn a task to handle the newly connected routers BMP
-                        // message stream.
-
-                        // Choose a name to be reported in application logs.
-                        let child_name = format!(
-                            "router[{}:{}]",
-                            client_addr.ip(),
-                            client_addr.port()
-                        );
-
-                        // Create a status reporter whose name in output will be
-                        // a combination of ours as parent and the newly chosen
-                        // child name, enabling logged messages relating to this
-                        // newly connected router to be distinguished from logged
-                        // messages relating to other connected routers.
-                        let child_status_reporter = Arc::new(
-                            self.status_reporter.add_child(&child_name),
-                        );
-
-                        let router_handler = RouterHandler::new(
-                            self.gate.clone(),
-                            roto_function.clone(),
-                            roto_context.clone(),
-                            self.router_id_template.clone(),
-                            self.filter_name.clone(),
-                            child_status_reporter,
-                            state_machine,
-                            self.tracer.clone(),
-                            self.tracing_mode.clone(),
-                            last_msg_at,
-                            self.bmp_metrics.clone(),
-                        );
-
                         F::accept_config(
                             child_name,
                             router_handler,
@@ -512,10 +727,324 @@ impl BmpTcpInRunner {
                             &self.router_states,
                             &self.router_info,
                             self.ingress_register.clone(),
+                            self.tls.clone(),
                         );
                     }
                     ControlFlow::Continue(Err(_err)) => break 'inner,
-                    ControlFlow::Break(Terminated) => return Err(Terminated),
+                    ControlFlow::Break(Terminated) => {
+                        break 'outer Err(Terminated)
+                    }
+                }
+            }
+        };
+
+        connector.abort();
+        quic_acceptor.abort();
+        res
+    }
+
+    /// Prepares the handling of a new connection with a router.
+    ///
+    /// Returns the name for the task handling the connection, the handler
+    /// and the ingress ID of the router.
+    async fn router_handler(
+        &self,
+        client_addr: SocketAddr,
+        unit_ingress_id: IngressId,
+        roto_function: &Reloadable<Option<RotoFunc>>,
+        roto_context: &Arc<std::sync::Mutex<Ctx>>,
+    ) -> (String, RouterHandler, IngressId) {
+        let query_ingress = IngressInfo::new()
+            .with_parent(unit_ingress_id)
+            .with_remote_addr(client_addr.ip());
+        let router_ingress_id;
+        if let Some((ingress_id, _ingress_info)) =
+            self.ingress_register.find_existing_bmp_router(&query_ingress)
+        {
+            router_ingress_id = ingress_id;
+        } else {
+            router_ingress_id = self.ingress_register.register();
+            self.ingress_register
+                .update_info(router_ingress_id, query_ingress);
+        }
+
+        let state_machine = Arc::new(Mutex::new(Some(
+            self.router_connected(router_ingress_id),
+        )));
+
+        let last_msg_at = {
+            let weak_ref = Arc::downgrade(&state_machine);
+            self.setup_router_specific_api_endpoint(
+                weak_ref, router_ingress_id,
+            )
+            .await
+        };
+
+        self.router_states
+            .insert(router_ingress_id, state_machine.clone());
+
+        // Choose a name to be reported in application logs.
+        let child_name = format!(
+            "router[{}:{}]",
+            client_addr.ip(),
+            client_addr.port()
+        );
+
+        // Create a status reporter whose name in output will be
+        // a combination of ours as parent and the newly chosen
+        // child name, enabling logged messages relating to this
+        // newly connected router to be distinguished from logged
+        // messages relating to other connected routers.
+        let child_status_reporter = Arc::new(
+            self.status_reporter.add_child(&child_name),
+        );
+
+        let router_handler = RouterHandler::new(
+            self.gate.clone(),
+            roto_function.clone(),
+            roto_context.clone(),
+            self.router_id_template.clone(),
+            self.filter_name.clone(),
+            child_status_reporter,
+            state_machine,
+            self.tracer.clone(),
+            self.tracing_mode.clone(),
+            self.shadow.clone(),
+            last_msg_at,
+            self.bmp_metrics.clone(),
+        );
+
+        (child_name, router_handler, router_ingress_id)
+    }
+
+    /// Connects to `router_addr`, authenticating the connection with `auth`.
+    async fn connect(
+        router_addr: SocketAddr,
+        auth: TcpAuth,
+    ) -> std::io::Result<TcpStream> {
+        let socket = match router_addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        let (addr, len) =
+            PrefixOrExact::Exact(router_addr.ip()).addr_and_len();
+        auth.apply(&socket, addr, len)?;
+        socket.connect(router_addr).await
+    }
+
+    /// Keeps connecting to the routers configured in active mode.
+    ///
+    /// Routers without a live connection are connected to again after a
+    /// delay that doubles with every failed attempt, and the connections
+    /// are handed to `F` just like those accepted by the listener.
+    async fn connect_active_routers<F: ConfigAcceptor>(
+        self: Arc<Self>,
+        unit_ingress_id: IngressId,
+        roto_function: Reloadable<Option<RotoFunc>>,
+        roto_context: Arc<std::sync::Mutex<Ctx>>,
+    ) {
+        const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+        let mut live: HashMap<SocketAddr, IngressId> = HashMap::new();
+        let mut backoffs: HashMap<SocketAddr, Backoff> = HashMap::new();
+        loop {
+            let active = self.active.load_full();
+            live.retain(|_, ingress_id| {
+                self.router_states.contains_key(ingress_id)
+            });
+            backoffs.retain(|addr, _| active.routers.contains(addr));
+            let now = Instant::now();
+            let due = active
+                .routers
+                .iter()
+                .filter(|addr| {
+                    !live.contains_key(addr)
+                        && backoffs
+                            .get(addr)
+                            .is_none_or(|backoff| backoff.not_before <= now)
+                })
+                .copied()
+                .collect::<Vec<_>>();
+
+            let attempts = due.iter().map(|router_addr| {
+                let auth = active.tcp_auth(router_addr.ip());
+                timeout(CONNECT_TIMEOUT, Self::connect(*router_addr, auth))
+            });
+            for (router_addr, res) in due.iter().zip(join_all(attempts).await)
+            {
+                let delay = backoffs
+                    .get(router_addr)
+                    .map(|backoff| backoff.delay)
+                    .unwrap_or(active.reconnect_delay);
+                let err = match res {
+                    Ok(Ok(tcp_stream)) => {
+                        // Should the connection be lost, start over with
+                        // the shortest delay.
+                        backoffs.insert(
+                            *router_addr,
+                            Backoff {
+                                not_before: Instant::now()
+                                    + active.reconnect_delay,
+                                delay: active.reconnect_delay,
+                            },
+                        );
+                        self.status_reporter.router_connected(*router_addr);
+
+                        let (child_name, router_handler, ingress_id) = self
+                            .router_handler(
+                                *router_addr,
+                                unit_ingress_id,
+                                &roto_function,
+                                &roto_context,
+                            )
+                            .await;
+                        live.insert(*router_addr, ingress_id);
+                        F::accept_config(
+                            child_name,
+                            router_handler,
+                            StandardTcpStream::from(tcp_stream),
+                            *router_addr,
+                            ingress_id,
+                            &self.router_states,
+                            &self.router_info,
+                            self.ingress_register.clone(),
+                            None,
+                        );
+                        continue;
+                    }
+                    Ok(Err(err)) => err.to_string(),
+                    Err(_) => "timed out".to_string(),
+                };
+                self.status_reporter.router_connect_error(
+                    *router_addr,
+                    format!(
+                        "{err}: Will retry in {} seconds.",
+                        delay.as_secs()
+                    ),
+                );
+                backoffs.insert(
+                    *router_addr,
+                    Backoff {
+                        not_before: Instant::now() + delay,
+                        delay: (delay * 2).min(active.max_reconnect_delay),
+                    },
+                );
+            }
+
+            // Our clone of the gate must follow the commands sent to it, or
+            // the gate would eventually block on them.
+            let next_round =
+                timeout(Duration::from_secs(1), self.gate.process()).await;
+            if let Ok(Err(Terminated)) = next_round {
+                return;
+            }
+        }
+    }
+
+    /// Accepts routers connecting over QUIC, for as long as the unit runs.
+    ///
+    /// The endpoint follows the `quic` settings: it is bound anew when the
+    /// address changes, or else given the new certificate.
+    async fn accept_quic_routers(
+        self: Arc<Self>,
+        unit_ingress_id: IngressId,
+        roto_function: Reloadable<Option<RotoFunc>>,
+        roto_context: Arc<std::sync::Mutex<Ctx>>,
+    ) {
+        let mut current: Option<Arc<QuicListener>> = None;
+        let mut endpoint: Option<QuicEndpoint> = None;
+        let mut wait = 1;
+        let mut retry_at = Instant::now();
+        loop {
+            let quic = self.quic.load_full();
+            let changed = quic.as_ref().map(Arc::as_ptr)
+                != current.as_ref().map(Arc::as_ptr);
+            let retry = endpoint.is_none() && Instant::now() >= retry_at;
+            match quic.as_deref() {
+                None => endpoint = None,
+                Some(listener) if changed || retry => {
+                    let same_addr = current.as_ref().is_some_and(|current| {
+                        current.config.listen == listener.config.listen
+                    });
+                    match endpoint.as_ref().filter(|_| same_addr) {
+                        Some(endpoint) => endpoint
+                            .set_server_config(Some(listener.server.clone())),
+                        None => {
+                            let listen = listener.config.listen;
+                            endpoint = None;
+                            match quic::bind(listen, listener.server.clone())
+                            {
+                                Ok(bound) => {
+                                    self.status_reporter.listener_listening(
+                                        &format!("quic://{listen}"),
+                                    );
+                                    endpoint = Some(bound);
+                                    wait = 1;
+                                }
+                                Err(err) => {
+                                    self.status_reporter.bind_error(
+                                        &format!("quic://{listen}"),
+                                        format!(
+                                            "{err}: Will retry in {wait} \
+                                             seconds."
+                                        ),
+                                    );
+                                    retry_at = Instant::now()
+                                        + Duration::from_secs(wait);
+                                    wait *= 2;
+                                }
+                            }
+                        }
+                    }
+                }
+                Some(_) => {}
+            }
+            current = quic;
+
+            let accept = async {
+                match endpoint.as_mut() {
+                    Some(endpoint) => endpoint.accept().await,
+                    None => std::future::pending().await,
+                }
+            };
+            // Our clone of the gate must follow the commands sent to it, or
+            // the gate would eventually block on them.
+            let next_round =
+                timeout(Duration::from_secs(1), self.gate.process());
+            tokio::select! {
+                Some(incoming) = accept => {
+                    let router_addr = incoming.remote_address();
+                    let (child_name, router_handler, ingress_id) = self
+                        .router_handler(
+                            router_addr,
+                            unit_ingress_id,
+                            &roto_function,
+                            &roto_context,
+                        )
+                        .await;
+                    self.status_reporter
+                        .listener_connection_accepted(router_addr);
+
+                    let router_states = self.router_states.clone();
+                    let router_info = self.router_info.clone();
+                    let ingress_register = self.ingress_register.clone();
+                    crate::tokio::spawn(&child_name, async move {
+                        router_handler
+                            .run_quic(
+                                incoming,
+                                router_addr,
+                                ingress_id,
+                                ingress_register,
+                            )
+                            .await;
+                        router_states.remove(&ingress_id);
+                        router_info.remove(&ingress_id);
+                    });
+                }
+                res = next_round => {
+                    if let Ok(Err(Terminated)) = res {
+                        return;
+                    }
                 }
             }
         }
@@ -547,6 +1076,16 @@ impl BmpTcpInRunner {
                                     router_id_template: new_router_id_template,
                                     filter_name: new_filter_name,
                                     tracing_mode: new_tracing_mode,
+                                    shadow: new_shadow,
+                                    rtr_cache: _rtr_cache,
+                                    traffic: _traffic,
+                                    tcp_auth: new_tcp_auth,
+                                    tls: new_tls,
+                                    quic: new_quic,
+                                    connect: new_connect,
+                                    reconnect_delay_secs: new_reconnect_delay,
+                                    max_reconnect_delay_secs:
+                                        new_max_reconnect_delay,
                                 }),
                         } => {
                             // Runtime reconfiguration of this unit has
@@ -556,13 +1095,40 @@ impl BmpTcpInRunner {
                             // router_handler() tasks will receive their
                             // own copy of this Reconfiguring status
                             // update and can react to it accordingly.
-                            let rebind = self.listen != new_listen;
-
+                            let rebind = self.listen != new_listen
+                                || self.tcp_auth != new_tcp_auth;
+
+                            self.active.store(Arc::new(ActiveRouters {
+                                routers: new_connect,
+                                tcp_auth: new_tcp_auth.clone(),
+                                reconnect_delay: new_reconnect_delay,
+                                max_reconnect_delay: new_max_reconnect_delay,
+                            }));
                             self.listen = new_listen;
+                            self.tcp_auth = new_tcp_auth;
+                            match new_tls.as_ref().map(TlsAcceptor::new) {
+                                None => self.tls = None,
+                                Some(Ok(tls)) => self.tls = Some(tls.into()),
+                                Some(Err(err)) => {
+                                    // Keep the previous settings rather
+                                    // than fail.
+                                    self.status_reporter.tls_error(err)
+                                }
+                            }
+                            match new_quic.map(QuicListener::new).transpose()
+                            {
+                                Ok(quic) => {
+                                    self.quic.store(quic.map(Arc::new))
+                                }
+                                Err(err) => {
+                                    self.status_reporter.tls_error(err)
+                                }
+                            }
                             self.filter_name.store(new_filter_name.into());
                             self.router_id_template
                                 .store(new_router_id_template.into());
                             self.tracing_mode.store(new_tracing_mode.into());
+                            self.shadow.store(new_shadow.map(Arc::new));
 
                             if rebind {
                                 // Trigger re-binding to the new listen port.
@@ -706,6 +1272,7 @@ impl ConfigAcceptor for BmpTcpInRunner {
         >, // Option is never None, instead Some is take()'n and replace()'d.
         router_info: &Arc<FrimMap<IngressId, Arc<RouterInfo>>>,
         ingress_register: Arc<ingress::Register>,
+        tls: Option<Arc<TlsAcceptor>>,
     ) {
         let router_states = router_states.clone();
         let router_info = router_info.clone();
@@ -715,7 +1282,13 @@ impl ConfigAcceptor for BmpTcpInRunner {
 
         crate::tokio::spawn(&child_name, async move {
             router_handler
-                .run(tcp_stream, client_addr, ingress_id, ingress_register)
+                .run(
+                    tcp_stream,
+                    client_addr,
+                    ingress_id,
+                    ingress_register,
+                    tls,
+                )
                 .await;
             router_states.remove(&ingress_id);
             router_info.remove(&ingress_id);
@@ -747,7 +1320,7 @@ mod tests {
     use crate::{
         common::{
             frim::FrimMap, net::TcpStreamWrapper,
-            status_reporter::AnyStatusReporter,
+            status_reporter::AnyStatusReporter, tls::TlsAcceptor,
         },
         comms::{Gate, GateAgent, Terminated},
         ingress::{self, IngressId},
@@ -768,7 +1341,7 @@ mod tests {
         },
     };
 
-    use super::{BmpTcpIn, ConfigAcceptor};
+    use super::{ActiveRouters, BmpTcpIn, ConfigAcceptor};
 
     #[test]
     fn listen_is_required() {
@@ -787,6 +1360,51 @@ mod tests {
         assert!(mk_config_from_toml("listen = '1.2.3.4:12345'").is_ok());
     }
 
+    #[test]
+    fn tls_needs_certificate_and_key() {
+        let config = mk_config_from_toml(
+            "listen = '1.2.3.4:12345'\n\
+            [tls]\n\
+            certificate = 'bmp.crt'\n\
+            key = 'bmp.key'\n\
+            client_ca = 'routers.crt'",
+        )
+        .unwrap();
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.key, "bmp.key".to_string().into());
+        assert!(tls.client_ca.is_some());
+
+        assert!(mk_config_from_toml(
+            "listen = '1.2.3.4:12345'\n[tls]\ncertificate = 'bmp.crt'"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn quic_needs_address_certificate_and_key() {
+        let config = mk_config_from_toml(
+            "listen = '1.2.3.4:12345'\n\
+            [quic]\n\
+            listen = '1.2.3.4:4433'\n\
+            certificate = 'bmp.crt'\n\
+            key = 'bmp.key'\n\
+            idle_timeout_secs = 60",
+        )
+        .unwrap();
+        let quic = config.quic.unwrap();
+        assert_eq!(quic.listen, "1.2.3.4:4433".parse().unwrap());
+        assert_eq!(quic.idle_timeout_secs, Duration::from_secs(60));
+        assert!(quic.client_ca.is_none());
+
+        assert!(mk_config_from_toml(
+            "listen = '1.2.3.4:12345'\n\
+            [quic]\n\
+            certificate = 'bmp.crt'\n\
+            key = 'bmp.key'"
+        )
+        .is_err());
+    }
+
     // --- Test helpers ------------------------------------------------------
 
     fn mk_config_from_toml(toml: &str) -> Result<BmpTcpIn, toml::de::Error> {
@@ -821,6 +1439,15 @@ mod tests {
             router_id_template: Default::default(),
             filter_name: Default::default(),
             tracing_mode: Default::default(),
+            shadow: None,
+            rtr_cache: None,
+            traffic: None,
+            tcp_auth: Default::default(),
+            tls: None,
+            quic: None,
+            connect: Default::default(),
+            reconnect_delay_secs: Default::default(),
+            max_reconnect_delay_secs: Default::default(),
         };
         let new_config = Unit::BmpTcpIn(new_config);
         agent.reconfigure(new_config, new_gate).await.unwrap();
@@ -888,6 +1515,15 @@ mod tests {
             router_id_template: Default::default(),
             filter_name: Default::default(),
             tracing_mode: Default::default(),
+            shadow: None,
+            rtr_cache: None,
+            traffic: None,
+            tcp_auth: Default::default(),
+            tls: None,
+            quic: None,
+            connect: Default::default(),
+            reconnect_delay_secs: Default::default(),
+            max_reconnect_delay_secs: Default::default(),
         };
         let new_config = Unit::BmpTcpIn(new_config);
         agent.reconfigure(new_config, new_gate).await.unwrap();
@@ -959,6 +1595,15 @@ mod tests {
             router_id_template: Default::default(),
             filter_name: Default::default(),
             tracing_mode: Default::default(),
+            shadow: None,
+            rtr_cache: None,
+            traffic: None,
+            tcp_auth: Default::default(),
+            tls: None,
+            quic: None,
+            connect: Default::default(),
+            reconnect_delay_secs: Default::default(),
+            max_reconnect_delay_secs: Default::default(),
         };
         let new_config = Unit::BmpTcpIn(new_config);
         agent.reconfigure(new_config, new_gate).await.unwrap();
@@ -996,6 +1641,90 @@ mod tests {
         );
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn connects_to_active_routers() {
+        // Given a router waiting for the monitoring station to connect, and
+        // a router that cannot be reached:
+        let router =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let router_addr = router.local_addr().unwrap();
+        let unreachable_addr = {
+            let listener =
+                tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        };
+
+        // When an instance of the BMP TCP input unit is configured to
+        // connect to both:
+        let (runner, agent, status_reporter) = setup_test("1.2.3.4:12345");
+        runner.active.store(Arc::new(ActiveRouters {
+            routers: vec![router_addr, unreachable_addr],
+            tcp_auth: Default::default(),
+            reconnect_delay: Duration::from_secs(60),
+            max_reconnect_delay: Duration::from_secs(60),
+        }));
+        let wait_forever =
+            |_addr| Ok(MockTcpListener::new(std::future::pending));
+        let mock_listener_factory =
+            Arc::new(MockTcpListenerFactory::new(wait_forever));
+        let task = runner
+            .run::<_, _, _, NoOpConfigAcceptor>(mock_listener_factory);
+        let join_handle = tokio::task::spawn(task);
+
+        // Then it connects to the first router:
+        timeout(Duration::from_secs(5), router.accept())
+            .await
+            .unwrap()
+            .unwrap();
+
+        // And reports that it failed to connect to the second:
+        let metrics = timeout(Duration::from_secs(5), async {
+            loop {
+                let metrics = get_testable_metrics_snapshot(
+                    &status_reporter.metrics().unwrap(),
+                );
+                let errors = metrics
+                    .with_name::<usize>("bmp_tcp_in_connect_error_count");
+                if errors > 0 {
+                    break metrics;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(metrics.with_name::<usize>("bmp_tcp_in_connect_count"), 1);
+        assert_eq!(
+            metrics.with_name::<usize>("bmp_tcp_in_connect_error_count"),
+            1
+        );
+
+        agent.terminate().await;
+        let res = timeout(Duration::from_secs(1), join_handle).await.unwrap();
+        assert_eq!(res.unwrap(), Err(Terminated));
+    }
+
+    #[test]
+    fn tcp_auth_of_active_routers_is_the_most_specific() {
+        let config = mk_config_from_toml(
+            "listen = '1.2.3.4:12345'\n\
+            connect = ['10.1.0.1:5000']\n\
+            [tcp_auth.'10.0.0.0/8']\n\
+            md5_password = 'wide'\n\
+            [tcp_auth.'10.1.0.0/16']\n\
+            md5_password = 'narrow'",
+        )
+        .unwrap();
+        let active = ActiveRouters {
+            routers: config.connect,
+            tcp_auth: config.tcp_auth,
+            ..Default::default()
+        };
+        let narrow = active.tcp_auth(active.routers[0].ip());
+        assert_eq!(narrow.md5_password.as_deref(), Some("narrow"));
+        assert!(!active.tcp_auth("192.0.2.1".parse().unwrap()).is_enabled());
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     #[ignore = "TCP accept panics and so the unit never responds to the terminate command."]
     async fn retry_with_backoff_on_accept_error() {
@@ -1103,6 +1832,10 @@ mod tests {
         let runner = BmpTcpInRunner {
             component: Default::default(),
             listen: Arc::new(listen.parse().unwrap()),
+            tcp_auth: Default::default(),
+            tls: None,
+            quic: Default::default(),
+            active: Default::default(),
             http_api_path: Default::default(),
             gate,
             router_states: Default::default(),
@@ -1115,9 +1848,13 @@ mod tests {
             router_id_template: Default::default(),
             filter_name: Default::default(),
             tracing_mode: Default::default(),
+            shadow: Default::default(),
             tracer: Default::default(),
             ingress_register: Arc::new(ingress::Register::default()),
-            roto_compiled: None,
+            roto: Default::default(),
+            rtr_cache: Default::default(),
+            traffic: Default::default(),
+            roto_state: Default::default(),
         };
 
         (runner, gate_agent, status_reporter)
@@ -1138,6 +1875,7 @@ mod tests {
             >, // Option is never None, instead Some is take()'n and replace()'d.
             _router_info: &Arc<FrimMap<IngressId, Arc<RouterInfo>>>,
             _ingress_register: Arc<ingress::Register>,
+            _tls: Option<Arc<TlsAcceptor>>,
         ) {
         }
     }
@@ -0,0 +1,474 @@
+//! AWS credentials and request signing.
+//!
+//! Credentials are looked up the way the AWS SDKs do, taking the first of:
+//!
+//! * the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and optionally
+//!   `AWS_SESSION_TOKEN` environment variables,
+//! * the profile given by the configuration, `AWS_PROFILE` or `default`
+//!   in the shared credentials file, `~/.aws/credentials` unless
+//!   `AWS_SHARED_CREDENTIALS_FILE` says otherwise,
+//! * the ECS container credentials endpoint given by
+//!   `AWS_CONTAINER_CREDENTIALS_RELATIVE_URI` or
+//!   `AWS_CONTAINER_CREDENTIALS_FULL_URI`,
+//! * the EC2 instance metadata service (IMDSv2), unless
+//!   `AWS_EC2_METADATA_DISABLED` is `true`.
+//!
+//! Temporary credentials are fetched again shortly before they expire.
+//! Requests are signed with [Signature Version 4].
+//!
+//! [Signature Version 4]: https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_sigv.html
+
+use std::{env, fs, path::PathBuf, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::debug;
+use reqwest::Client as HttpClient;
+use ring::{digest, hmac};
+use serde::Deserialize;
+use url::Url;
+
+/// The address of the EC2 instance metadata service.
+const IMDS_ENDPOINT: &str = "http://169.254.169.254";
+
+/// The address of the ECS container credentials endpoint.
+const ECS_ENDPOINT: &str = "http://169.254.170.2";
+
+/// How long to wait for the metadata services, which are local.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long before they expire to fetch temporary credentials again.
+const REFRESH_MARGIN: TimeDelta = TimeDelta::minutes(5);
+
+//------------ Credentials ---------------------------------------------------
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+
+    /// When temporary credentials expire.
+    pub expiration: Option<DateTime<Utc>>,
+}
+
+impl Credentials {
+    fn is_fresh(&self) -> bool {
+        self.expiration
+            .map(|expiration| Utc::now() + REFRESH_MARGIN < expiration)
+            .unwrap_or(true)
+    }
+
+    fn from_env() -> Option<Self> {
+        Some(Credentials {
+            access_key_id: non_empty_var("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: non_empty_var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: non_empty_var("AWS_SESSION_TOKEN"),
+            expiration: None,
+        })
+    }
+
+    /// Finds the credentials of `profile` in a shared credentials file.
+    fn from_file(content: &str, profile: &str) -> Option<Self> {
+        let mut section = None;
+        let mut res = Credentials::default();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(name) =
+                line.strip_prefix('[').and_then(|s| s.strip_suffix(']'))
+            {
+                section = Some(name.trim());
+                continue;
+            }
+            if section != Some(profile) {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().to_string();
+            match key.trim() {
+                "aws_access_key_id" => res.access_key_id = value,
+                "aws_secret_access_key" => res.secret_access_key = value,
+                "aws_session_token" => res.session_token = Some(value),
+                _ => {}
+            }
+        }
+        (!res.access_key_id.is_empty() && !res.secret_access_key.is_empty())
+            .then_some(res)
+    }
+}
+
+/// The credentials as returned by the metadata services.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct MetadataCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: Option<String>,
+    expiration: Option<DateTime<Utc>>,
+}
+
+impl From<MetadataCredentials> for Credentials {
+    fn from(creds: MetadataCredentials) -> Self {
+        Credentials {
+            access_key_id: creds.access_key_id,
+            secret_access_key: creds.secret_access_key,
+            session_token: creds.token,
+            expiration: creds.expiration,
+        }
+    }
+}
+
+//------------ CredentialChain -----------------------------------------------
+
+/// Finds the credentials to use and keeps them current.
+pub(crate) struct CredentialChain {
+    http: HttpClient,
+    profile: String,
+    current: Option<Credentials>,
+}
+
+impl CredentialChain {
+    pub fn new(http: HttpClient, profile: Option<String>) -> Self {
+        let profile = profile
+            .or_else(|| non_empty_var("AWS_PROFILE"))
+            .unwrap_or_else(|| "default".into());
+        CredentialChain {
+            http,
+            profile,
+            current: None,
+        }
+    }
+
+    /// Creates a chain that only knows `creds`.
+    #[cfg(test)]
+    pub fn with_credentials(http: HttpClient, creds: Credentials) -> Self {
+        CredentialChain {
+            http,
+            profile: "default".into(),
+            current: Some(creds),
+        }
+    }
+
+    /// Returns the current credentials.
+    pub async fn get(&mut self) -> Result<Credentials, String> {
+        match &self.current {
+            Some(creds) if creds.is_fresh() => Ok(creds.clone()),
+            _ => {
+                let creds = self.find().await?;
+                self.current = Some(creds.clone());
+                Ok(creds)
+            }
+        }
+    }
+
+    async fn find(&self) -> Result<Credentials, String> {
+        if let Some(creds) = Credentials::from_env() {
+            return Ok(creds);
+        }
+        let path = non_empty_var("AWS_SHARED_CREDENTIALS_FILE")
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME").map(|home| {
+                    PathBuf::from(home).join(".aws").join("credentials")
+                })
+            });
+        if let Some(content) =
+            path.and_then(|path| fs::read_to_string(path).ok())
+        {
+            if let Some(creds) =
+                Credentials::from_file(&content, &self.profile)
+            {
+                return Ok(creds);
+            }
+        }
+        if let Some(creds) = self.container_credentials().await? {
+            return Ok(creds);
+        }
+        if non_empty_var("AWS_EC2_METADATA_DISABLED")
+            .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+        {
+            return Err("no AWS credentials found".into());
+        }
+        self.instance_credentials().await.map_err(|err| {
+            format!(
+                "no AWS credentials found (instance metadata service: \
+                {err})"
+            )
+        })
+    }
+
+    /// Fetches the credentials of an ECS task, if running as one.
+    async fn container_credentials(
+        &self,
+    ) -> Result<Option<Credentials>, String> {
+        let url = if let Some(uri) =
+            non_empty_var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI")
+        {
+            format!("{ECS_ENDPOINT}{uri}")
+        } else if let Some(url) =
+            non_empty_var("AWS_CONTAINER_CREDENTIALS_FULL_URI")
+        {
+            url
+        } else {
+            return Ok(None);
+        };
+        debug!("fetching AWS credentials from {url}");
+        let mut request = self.http.get(url).timeout(METADATA_TIMEOUT);
+        if let Some(token) =
+            non_empty_var("AWS_CONTAINER_AUTHORIZATION_TOKEN")
+        {
+            request = request.header("Authorization", token);
+        }
+        let body = fetch(request).await.map_err(|err| {
+            format!("cannot get container credentials: {err}")
+        })?;
+        parse_metadata(&body).map(Some)
+    }
+
+    /// Fetches the credentials of the role of the EC2 instance.
+    async fn instance_credentials(&self) -> Result<Credentials, String> {
+        let token = fetch(
+            self.http
+                .put(format!("{IMDS_ENDPOINT}/latest/api/token"))
+                .header("X-aws-ec2-metadata-token-ttl-seconds", "21600")
+                .timeout(METADATA_TIMEOUT),
+        )
+        .await?;
+        let token = String::from_utf8_lossy(&token).into_owned();
+        let base = format!(
+            "{IMDS_ENDPOINT}/latest/meta-data/iam/security-credentials/"
+        );
+        let get = |url: String| {
+            self.http
+                .get(url)
+                .header("X-aws-ec2-metadata-token", &token)
+                .timeout(METADATA_TIMEOUT)
+        };
+        let roles = fetch(get(base.clone())).await?;
+        let roles = String::from_utf8_lossy(&roles);
+        let role = roles
+            .lines()
+            .next()
+            .filter(|role| !role.is_empty())
+            .ok_or("the instance has no role")?;
+        debug!("fetching AWS credentials of instance role {role}");
+        parse_metadata(&fetch(get(format!("{base}{role}"))).await?)
+    }
+}
+
+fn parse_metadata(body: &[u8]) -> Result<Credentials, String> {
+    serde_json::from_slice::<MetadataCredentials>(body)
+        .map(Into::into)
+        .map_err(|err| format!("invalid credentials: {err}"))
+}
+
+/// Sends a request, returning the body of a successful response.
+async fn fetch(request: reqwest::RequestBuilder) -> Result<Vec<u8>, String> {
+    let response = request.send().await.map_err(|err| err.to_string())?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("HTTP status {status}"));
+    }
+    let body = response.bytes().await.map_err(|err| err.to_string())?;
+    Ok(body.to_vec())
+}
+
+fn non_empty_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+//------------ Signing -------------------------------------------------------
+
+/// Signs requests to a service with Signature Version 4.
+pub(crate) struct Signer<'a> {
+    pub creds: &'a Credentials,
+    pub region: &'a str,
+    pub service: &'a str,
+}
+
+impl Signer<'_> {
+    /// Signs a request.
+    ///
+    /// Returns the headers to send: `headers`, which are all signed, and
+    /// the `X-Amz-Date`, `X-Amz-Security-Token` and `Authorization`
+    /// headers. The `Host` header is signed too, but left to the HTTP
+    /// client.
+    pub fn sign(
+        &self,
+        method: &str,
+        url: &Url,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+        time: DateTime<Utc>,
+    ) -> Vec<(String, String)> {
+        let Signer {
+            creds,
+            region,
+            service,
+        } = *self;
+        let amz_date = time.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+
+        let mut res: Vec<(String, String)> = headers
+            .iter()
+            .map(|(name, value)| {
+                (name.to_ascii_lowercase(), value.to_string())
+            })
+            .collect();
+        res.push(("x-amz-date".into(), amz_date.clone()));
+        if let Some(token) = &creds.session_token {
+            res.push(("x-amz-security-token".into(), token.clone()));
+        }
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or("")),
+            None => url.host_str().unwrap_or("").to_string(),
+        };
+        let mut signed: Vec<(&str, &str)> = res
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.trim()))
+            .chain([("host", host.as_str())])
+            .collect();
+        signed.sort();
+        let signed_headers = signed
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let mut query: Vec<&str> = url
+            .query()
+            .unwrap_or("")
+            .split('&')
+            .filter(|s| !s.is_empty())
+            .collect();
+        query.sort();
+        let mut canonical =
+            format!("{method}\n{}\n{}\n", url.path(), query.join("&"));
+        for (name, value) in &signed {
+            canonical.push_str(&format!("{name}:{value}\n"));
+        }
+        canonical.push_str(&format!(
+            "\n{signed_headers}\n{}",
+            sha256_hex(payload)
+        ));
+
+        let scope = format!("{date}/{region}/{service}/aws4_request");
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            sha256_hex(canonical.as_bytes())
+        );
+        let mut key = format!("AWS4{}", creds.secret_access_key).into_bytes();
+        for part in [date, region, service, "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        let signature =
+            hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        res.push((
+            "authorization".into(),
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{scope}, \
+            SignedHeaders={signed_headers}, Signature={signature}",
+                creds.access_key_id
+            ),
+        ));
+        res
+    }
+}
+
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(digest::digest(&digest::SHA256, data))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    hmac::sign(&key, data).as_ref().to_vec()
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_signed() {
+        // The get-vanilla case of the AWS Signature Version 4 test suite.
+        let creds = Credentials {
+            access_key_id: "AKIDEXAMPLE".into(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"
+                .into(),
+            ..Default::default()
+        };
+        let signer = Signer {
+            creds: &creds,
+            region: "us-east-1",
+            service: "service",
+        };
+        let headers = signer.sign(
+            "GET",
+            &Url::parse("https://example.amazonaws.com/").unwrap(),
+            &[],
+            b"",
+            "2015-08-30T12:36:00Z".parse().unwrap(),
+        );
+        assert_eq!(
+            headers,
+            [
+                ("x-amz-date".to_string(), "20150830T123600Z".to_string()),
+                (
+                    "authorization".to_string(),
+                    "AWS4-HMAC-SHA256 \
+                    Credential=AKIDEXAMPLE/20150830/us-east-1/service/\
+                    aws4_request, SignedHeaders=host;x-amz-date, \
+                    Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956\
+                    d9b8aae1d763fbf31"
+                        .to_string()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn profiles_are_read_from_the_credentials_file() {
+        let content = "\
+            [default]\n\
+            aws_access_key_id = AKID1\n\
+            aws_secret_access_key = secret1\n\
+            \n\
+            # A comment\n\
+            [other]\n\
+            aws_access_key_id=AKID2\n\
+            aws_secret_access_key=secret2\n\
+            aws_session_token=token2\n\
+        ";
+        let creds = Credentials::from_file(content, "other").unwrap();
+        assert_eq!(creds.access_key_id, "AKID2");
+        assert_eq!(creds.secret_access_key, "secret2");
+        assert_eq!(creds.session_token.as_deref(), Some("token2"));
+        let creds = Credentials::from_file(content, "default").unwrap();
+        assert_eq!(creds.access_key_id, "AKID1");
+        assert_eq!(creds.session_token, None);
+        assert!(Credentials::from_file(content, "missing").is_none());
+    }
+
+    #[test]
+    fn metadata_credentials_expire() {
+        let creds = parse_metadata(
+            br#"{
+                "Code": "Success",
+                "AccessKeyId": "ASIA",
+                "SecretAccessKey": "secret",
+                "Token": "token",
+                "Expiration": "2015-08-30T12:36:00Z"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(creds.session_token.as_deref(), Some("token"));
+        assert!(!creds.is_fresh());
+    }
+}
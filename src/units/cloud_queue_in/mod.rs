@@ -0,0 +1,8 @@
+pub(crate) mod aws;
+mod gcp;
+pub mod pubsub;
+pub mod sqs;
+mod unit;
+
+pub use pubsub::PubsubIn;
+pub use sqs::SqsIn;
@@ -0,0 +1,448 @@
+//! Receiving messages from Google Cloud Pub/Sub.
+//!
+//! The unit uses the REST API of Pub/Sub, pulling messages from the
+//! subscription and acknowledging the processed ones. If the
+//! `PUBSUB_EMULATOR_HOST` environment variable is set, the emulator it
+//! points to is used, without credentials.
+
+use std::{collections::HashMap, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::error;
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+use serde_json::json;
+use serde_with::serde_as;
+use url::Url;
+
+use crate::{
+    comms::{Gate, Terminated},
+    manager::{Component, WaitPoint},
+    units::kafka_in::unit::MessageFormat,
+};
+
+use super::{
+    gcp::TokenSource,
+    unit::{self, Queue, QueueMessage, Settings},
+};
+
+/// The endpoint of the Pub/Sub API.
+const ENDPOINT: &str = "https://pubsub.googleapis.com";
+
+/// How long to wait for a pull to return.
+const PULL_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// How long to wait for the other requests.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait before pulling again if there were no messages.
+const IDLE_DELAY: Duration = Duration::from_secs(1);
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct PubsubIn {
+    /// The subscription, as `projects/<project>/subscriptions/<name>`.
+    pub subscription: String,
+
+    /// The Pub/Sub API endpoint to use instead of the global one, e.g. a
+    /// regional or private endpoint.
+    #[serde(default)]
+    pub endpoint: Option<Url>,
+
+    /// The format of the messages.
+    #[serde(default = "PubsubIn::default_format")]
+    pub format: MessageFormat,
+
+    /// The number of messages to pull at a time, at most 1000.
+    #[serde(default = "PubsubIn::default_batch")]
+    pub batch: usize,
+
+    /// How long pulled messages are left to the unit before they are
+    /// delivered again, unless acknowledged, between 10 and 600 seconds.
+    /// Defaults to the acknowledgement deadline of the subscription.
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    #[serde(default)]
+    pub ack_deadline_secs: Option<Duration>,
+
+    /// How long to wait before retrying after a request failed. The delay
+    /// doubles with every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "PubsubIn::default_retry_delay_secs")]
+    pub retry_delay_secs: Duration,
+
+    /// The longest to wait before retrying.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "PubsubIn::default_max_retry_delay_secs")]
+    pub max_retry_delay_secs: Duration,
+}
+
+impl PubsubIn {
+    fn default_format() -> MessageFormat {
+        MessageFormat::Json
+    }
+
+    fn default_batch() -> usize {
+        100
+    }
+
+    fn default_retry_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retry_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    pub async fn run(
+        self,
+        component: Component,
+        gate: Gate,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        unit::check_format(&component, &self.format)?;
+        let name = component.name().clone();
+        let fail = |msg: &str| {
+            error!("Unit {name}: {msg}");
+            Err(Terminated)
+        };
+        if !is_subscription(&self.subscription) {
+            return fail(
+                "the subscription must be given as \
+                projects/<project>/subscriptions/<name>",
+            );
+        }
+        if !(1..=1000).contains(&self.batch) {
+            return fail("the batch size must be between 1 and 1000");
+        }
+        if let Some(deadline) = self.ack_deadline_secs {
+            if !(10..=600).contains(&deadline.as_secs()) {
+                return fail(
+                    "the ack deadline must be between 10 and 600 seconds",
+                );
+            }
+        }
+
+        let http = component.http_client().clone();
+        let emulator = std::env::var("PUBSUB_EMULATOR_HOST")
+            .ok()
+            .filter(|host| !host.is_empty());
+        let (endpoint, tokens) = match emulator {
+            Some(host) => match Url::parse(&format!("http://{host}")) {
+                Ok(endpoint) => (endpoint, None),
+                Err(err) => {
+                    return fail(&format!(
+                        "invalid PUBSUB_EMULATOR_HOST '{host}': {err}"
+                    ))
+                }
+            },
+            None => match TokenSource::new(http.clone()) {
+                Ok(tokens) => (
+                    self.endpoint
+                        .clone()
+                        .unwrap_or_else(|| Url::parse(ENDPOINT).unwrap()),
+                    Some(tokens),
+                ),
+                Err(err) => return fail(&err),
+            },
+        };
+
+        let settings = Settings {
+            unit_type: "pubsub-in",
+            format: self.format.clone(),
+            retry_delay: self.retry_delay_secs,
+            max_retry_delay: self.max_retry_delay_secs,
+        };
+        let queue = PubsubQueue {
+            http,
+            endpoint,
+            tokens,
+            config: self,
+        };
+        unit::run(component, gate, waitpoint, queue, settings).await
+    }
+}
+
+fn is_subscription(name: &str) -> bool {
+    matches!(
+        name.split('/').collect::<Vec<_>>().as_slice(),
+        ["projects", project, "subscriptions", subscription]
+            if !project.is_empty() && !subscription.is_empty()
+    )
+}
+
+//------------ PubsubQueue ---------------------------------------------------
+
+struct PubsubQueue {
+    http: HttpClient,
+    endpoint: Url,
+
+    /// The source of access tokens, unless using the emulator.
+    tokens: Option<TokenSource>,
+
+    config: PubsubIn,
+}
+
+impl PubsubQueue {
+    /// Calls `method` of the subscription.
+    async fn call(
+        &mut self,
+        method: &str,
+        request: serde_json::Value,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, String> {
+        let url = format!(
+            "{}/v1/{}:{method}",
+            self.endpoint.as_str().trim_end_matches('/'),
+            self.config.subscription
+        );
+        let mut request = self
+            .http
+            .post(url)
+            .timeout(timeout)
+            .header("Content-Type", "application/json")
+            .body(request.to_string());
+        if let Some(tokens) = &mut self.tokens {
+            request = request.bearer_auth(tokens.token().await?);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|err| format!("{method} failed: {err}"))?;
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| format!("{method} failed: {err}"))?;
+        if !status.is_success() {
+            let err: ErrorResponse =
+                serde_json::from_slice(&body).unwrap_or_default();
+            return Err(format!(
+                "{method} failed: HTTP status {status}: {}",
+                err.error.message
+            ));
+        }
+        Ok(body.to_vec())
+    }
+}
+
+impl Queue for PubsubQueue {
+    async fn receive(&mut self) -> Result<Vec<QueueMessage>, String> {
+        let request = json!({ "maxMessages": self.config.batch });
+        let body = self.call("pull", request, PULL_TIMEOUT).await?;
+        let response: PullResponse = serde_json::from_slice(&body)
+            .map_err(|err| format!("invalid pull response: {err}"))?;
+        if response.received_messages.is_empty() {
+            tokio::time::sleep(IDLE_DELAY).await;
+            return Ok(vec![]);
+        }
+        if let Some(deadline) = self.config.ack_deadline_secs {
+            let ack_ids = response
+                .received_messages
+                .iter()
+                .map(|msg| msg.ack_id.as_str())
+                .collect::<Vec<_>>();
+            let request = json!({
+                "ackIds": ack_ids,
+                "ackDeadlineSeconds": deadline.as_secs(),
+            });
+            self.call("modifyAckDeadline", request, REQUEST_TIMEOUT)
+                .await?;
+        }
+        let source = &self.config.subscription;
+        Ok(response
+            .received_messages
+            .into_iter()
+            .map(|received| QueueMessage {
+                source: source.clone(),
+                payload: STANDARD
+                    .decode(received.message.data)
+                    .map_err(|err| format!("invalid data: {err}")),
+                attributes: received.message.attributes.into_iter().collect(),
+                handle: received.ack_id,
+            })
+            .collect())
+    }
+
+    async fn ack(&mut self, handles: Vec<String>) -> Result<(), String> {
+        let request = json!({ "ackIds": handles });
+        self.call("acknowledge", request, REQUEST_TIMEOUT).await?;
+        Ok(())
+    }
+}
+
+//------------ API messages --------------------------------------------------
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PullResponse {
+    #[serde(default)]
+    received_messages: Vec<ReceivedMessage>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReceivedMessage {
+    ack_id: String,
+    message: PubsubMessage,
+}
+
+#[derive(Deserialize)]
+struct PubsubMessage {
+    #[serde(default)]
+    data: String,
+    #[serde(default)]
+    attributes: HashMap<String, String>,
+}
+
+#[derive(Default, Deserialize)]
+struct ErrorResponse {
+    #[serde(default)]
+    error: ErrorDetails,
+}
+
+#[derive(Default, Deserialize)]
+struct ErrorDetails {
+    #[serde(default)]
+    message: String,
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tests::util::https,
+        units::{
+            cloud_queue_in::unit::tests::{counts, receive, start_server},
+            Unit,
+        },
+    };
+
+    use super::*;
+
+    #[test]
+    fn subscriptions_are_checked() {
+        assert!(is_subscription("projects/p/subscriptions/bgp"));
+        assert!(!is_subscription("projects/p/topics/bgp"));
+        assert!(!is_subscription("bgp"));
+        assert!(!is_subscription("projects//subscriptions/bgp"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn messages_are_pulled_and_acknowledged() {
+        // A RIS Live message announcing 198.51.100.0/24.
+        let update = json!({
+            "type": "UPDATE",
+            "timestamp": 1700000000.0,
+            "peer": "192.0.2.1",
+            "peer_asn": "65000",
+            "host": "rrc00",
+            "raw": "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF002F02000000144001\
+                010040020602010000FDE8400304C000020118C63364",
+        });
+        let server = start_server(vec![
+            json!({ "receivedMessages": [
+                {
+                    "ackId": "ack-1",
+                    "message": {
+                        "data": STANDARD.encode(update.to_string()),
+                        "messageId": "1",
+                    },
+                },
+                {
+                    "ackId": "ack-2",
+                    "message": { "data": "e30=", "attributes": {} },
+                },
+            ]})
+            .to_string(),
+            "{}".into(),
+            "{}".into(),
+        ])
+        .await;
+        let Unit::PubsubIn(config) = toml::from_str(
+            r#"
+            type = "pubsub-in"
+            subscription = "projects/p/subscriptions/bgp"
+            ack_deadline_secs = 60
+            "#,
+        )
+        .unwrap() else {
+            panic!("expected a pubsub-in unit");
+        };
+        let mut queue = PubsubQueue {
+            http: HttpClient::new(),
+            endpoint: Url::parse(&format!("http://{}", server.addr)).unwrap(),
+            tokens: None,
+            config,
+        };
+
+        let (res, metrics) = receive(&mut queue, MessageFormat::Json).await;
+        res.unwrap();
+        assert_eq!(counts(&metrics), [2, 1, 2, 1, 0]);
+
+        let requests = server.requests();
+        let paths = requests
+            .iter()
+            .map(|request| request.uri.path())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "/v1/projects/p/subscriptions/bgp:pull",
+                "/v1/projects/p/subscriptions/bgp:modifyAckDeadline",
+                "/v1/projects/p/subscriptions/bgp:acknowledge",
+            ]
+        );
+        assert_eq!(requests[0].json(), json!({ "maxMessages": 100 }));
+        assert_eq!(
+            requests[1].json(),
+            json!({ "ackIds": ["ack-1", "ack-2"], "ackDeadlineSeconds": 60 })
+        );
+        assert_eq!(
+            requests[2].json(),
+            json!({ "ackIds": ["ack-1", "ack-2"] })
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn messages_are_pulled_over_https() {
+        let server = start_server(vec![
+            json!({ "receivedMessages": [
+                { "ackId": "ack-1", "message": { "data": "e30=" } },
+            ]})
+            .to_string(),
+            "{}".into(),
+        ])
+        .await;
+        let front = https::front(server.addr).await;
+        let Unit::PubsubIn(config) = toml::from_str(
+            r#"
+            type = "pubsub-in"
+            subscription = "projects/p/subscriptions/bgp"
+            "#,
+        )
+        .unwrap() else {
+            panic!("expected a pubsub-in unit");
+        };
+        let mut queue = PubsubQueue {
+            http: https::client(),
+            endpoint: Url::parse(&format!(
+                "https://localhost:{}",
+                front.port()
+            ))
+            .unwrap(),
+            tokens: None,
+            config,
+        };
+
+        let (res, metrics) = receive(&mut queue, MessageFormat::Json).await;
+        res.unwrap();
+        assert_eq!(counts(&metrics), [1, 1, 1, 0, 0]);
+        assert_eq!(
+            server.requests()[1].uri.path(),
+            "/v1/projects/p/subscriptions/bgp:acknowledge"
+        );
+    }
+}
@@ -0,0 +1,540 @@
+//! Receiving messages from AWS SQS.
+//!
+//! The unit uses the JSON protocol of the SQS API, long polling the queue
+//! with `ReceiveMessage` and deleting the processed messages with
+//! `DeleteMessageBatch`. As SQS message bodies are text, the bodies of
+//! messages in the binary formats are expected to be Base64 encoded.
+
+use std::{collections::HashMap, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use log::error;
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+use serde_json::json;
+use serde_with::serde_as;
+use url::Url;
+
+use crate::{
+    comms::{Gate, Terminated},
+    manager::{Component, WaitPoint},
+    units::kafka_in::unit::MessageFormat,
+};
+
+use super::{
+    aws::{CredentialChain, Signer},
+    unit::{self, Queue, QueueMessage, Settings},
+};
+
+/// How much longer than the long poll to wait for a response.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct SqsIn {
+    /// The URL of the queue, e.g.
+    /// `https://sqs.eu-west-1.amazonaws.com/123456789012/bgp`.
+    pub queue_url: Url,
+
+    /// The region of the queue. Defaults to the region in the queue URL,
+    /// or else the `AWS_REGION` or `AWS_DEFAULT_REGION` environment
+    /// variable.
+    #[serde(default)]
+    pub region: Option<String>,
+
+    /// The profile in the shared credentials file to use. Defaults to the
+    /// `AWS_PROFILE` environment variable, or else `default`.
+    #[serde(default)]
+    pub profile: Option<String>,
+
+    /// The format of the messages.
+    #[serde(default = "SqsIn::default_format")]
+    pub format: MessageFormat,
+
+    /// The number of messages to receive at a time, at most 10.
+    #[serde(default = "SqsIn::default_batch")]
+    pub batch: usize,
+
+    /// How long to wait for messages if there are none, at most 20
+    /// seconds.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "SqsIn::default_wait_time_secs")]
+    pub wait_time_secs: Duration,
+
+    /// How long received messages are hidden from other consumers before
+    /// they are delivered again, unless deleted. Defaults to the visibility
+    /// timeout of the queue.
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    #[serde(default)]
+    pub visibility_timeout_secs: Option<Duration>,
+
+    /// How long to wait before retrying after a request failed. The delay
+    /// doubles with every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "SqsIn::default_retry_delay_secs")]
+    pub retry_delay_secs: Duration,
+
+    /// The longest to wait before retrying.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "SqsIn::default_max_retry_delay_secs")]
+    pub max_retry_delay_secs: Duration,
+}
+
+impl SqsIn {
+    fn default_format() -> MessageFormat {
+        MessageFormat::Json
+    }
+
+    fn default_batch() -> usize {
+        10
+    }
+
+    fn default_wait_time_secs() -> Duration {
+        Duration::from_secs(20)
+    }
+
+    fn default_retry_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_retry_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    pub async fn run(
+        self,
+        component: Component,
+        gate: Gate,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        unit::check_format(&component, &self.format)?;
+        if !(1..=10).contains(&self.batch) {
+            error!(
+                "Unit {}: the batch size must be between 1 and 10",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+        if self.wait_time_secs > Duration::from_secs(20) {
+            error!(
+                "Unit {}: the wait time can be at most 20 seconds",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+        let Some(region) = self.region() else {
+            error!(
+                "Unit {}: the region of the queue must be configured",
+                component.name()
+            );
+            return Err(Terminated);
+        };
+
+        let settings = Settings {
+            unit_type: "sqs-in",
+            format: self.format.clone(),
+            retry_delay: self.retry_delay_secs,
+            max_retry_delay: self.max_retry_delay_secs,
+        };
+        let http = component.http_client().clone();
+        let queue = SqsQueue::new(self, region, http);
+        unit::run(component, gate, waitpoint, queue, settings).await
+    }
+
+    /// Determines the region of the queue.
+    fn region(&self) -> Option<String> {
+        if let Some(region) = &self.region {
+            return Some(region.clone());
+        }
+        // The endpoints are sqs.<region>.amazonaws.com, or the legacy
+        // <region>.queue.amazonaws.com.
+        let host = self.queue_url.host_str().unwrap_or_default();
+        let parts = host.split('.').collect::<Vec<_>>();
+        match parts.as_slice() {
+            ["sqs", region, "amazonaws", "com"]
+            | [region, "queue", "amazonaws", "com"] => {
+                return Some(region.to_string())
+            }
+            _ => {}
+        }
+        ["AWS_REGION", "AWS_DEFAULT_REGION"]
+            .into_iter()
+            .find_map(|name| std::env::var(name).ok())
+            .filter(|region| !region.is_empty())
+    }
+}
+
+//------------ SqsQueue ------------------------------------------------------
+
+struct SqsQueue {
+    http: HttpClient,
+    credentials: CredentialChain,
+    region: String,
+
+    /// The endpoint of the SQS API the queue lives at.
+    endpoint: Url,
+
+    /// The name of the queue, the source of its messages.
+    name: String,
+
+    config: SqsIn,
+}
+
+impl SqsQueue {
+    fn new(config: SqsIn, region: String, http: HttpClient) -> Self {
+        let mut endpoint = config.queue_url.clone();
+        endpoint.set_path("/");
+        endpoint.set_query(None);
+        let name = config
+            .queue_url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or_default()
+            .to_string();
+        SqsQueue {
+            credentials: CredentialChain::new(
+                http.clone(),
+                config.profile.clone(),
+            ),
+            http,
+            region,
+            endpoint,
+            name,
+            config,
+        }
+    }
+
+    /// Calls `action` of the SQS API.
+    async fn call(
+        &mut self,
+        action: &str,
+        request: serde_json::Value,
+    ) -> Result<Vec<u8>, String> {
+        let creds = self.credentials.get().await?;
+        let payload = request.to_string().into_bytes();
+        let target = format!("AmazonSQS.{action}");
+        let signer = Signer {
+            creds: &creds,
+            region: &self.region,
+            service: "sqs",
+        };
+        let headers = signer.sign(
+            "POST",
+            &self.endpoint,
+            &[
+                ("Content-Type", "application/x-amz-json-1.0"),
+                ("X-Amz-Target", &target),
+            ],
+            &payload,
+            Utc::now(),
+        );
+        let mut request = self
+            .http
+            .post(self.endpoint.clone())
+            .timeout(self.config.wait_time_secs + RESPONSE_TIMEOUT)
+            .body(payload);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|err| format!("{action} failed: {err}"))?;
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| format!("{action} failed: {err}"))?;
+        if !status.is_success() {
+            let err: ErrorResponse =
+                serde_json::from_slice(&body).unwrap_or_default();
+            return Err(format!(
+                "{action} failed: HTTP status {status}: {} {}",
+                err.error_type, err.message
+            ));
+        }
+        Ok(body.to_vec())
+    }
+
+    /// Converts a received message.
+    fn convert(&self, msg: SqsMessage) -> QueueMessage {
+        let payload = match self.config.format {
+            MessageFormat::Json => Ok(msg.body.into_bytes()),
+            _ => STANDARD
+                .decode(msg.body.trim())
+                .map_err(|err| format!("invalid Base64 body: {err}")),
+        };
+        let attributes = msg
+            .message_attributes
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value.string_value?)))
+            .collect();
+        QueueMessage {
+            source: self.name.clone(),
+            payload,
+            attributes,
+            handle: msg.receipt_handle,
+        }
+    }
+}
+
+impl Queue for SqsQueue {
+    async fn receive(&mut self) -> Result<Vec<QueueMessage>, String> {
+        let mut request = json!({
+            "QueueUrl": self.config.queue_url.as_str(),
+            "MaxNumberOfMessages": self.config.batch,
+            "WaitTimeSeconds": self.config.wait_time_secs.as_secs(),
+            "MessageAttributeNames": ["All"],
+        });
+        if let Some(timeout) = self.config.visibility_timeout_secs {
+            request["VisibilityTimeout"] = timeout.as_secs().into();
+        }
+        let body = self.call("ReceiveMessage", request).await?;
+        let response: ReceiveResponse = serde_json::from_slice(&body)
+            .map_err(|err| {
+                format!("invalid ReceiveMessage response: {err}")
+            })?;
+        Ok(response
+            .messages
+            .into_iter()
+            .map(|msg| self.convert(msg))
+            .collect())
+    }
+
+    async fn ack(&mut self, handles: Vec<String>) -> Result<(), String> {
+        let entries = handles
+            .iter()
+            .enumerate()
+            .map(|(id, handle)| {
+                json!({ "Id": id.to_string(), "ReceiptHandle": handle })
+            })
+            .collect::<Vec<_>>();
+        let request = json!({
+            "QueueUrl": self.config.queue_url.as_str(),
+            "Entries": entries,
+        });
+        let body = self.call("DeleteMessageBatch", request).await?;
+        let response: DeleteResponse = serde_json::from_slice(&body)
+            .map_err(|err| {
+                format!("invalid DeleteMessageBatch response: {err}")
+            })?;
+        match response.failed.first() {
+            None => Ok(()),
+            Some(failed) => Err(format!(
+                "failed to delete {} of {} messages: {}",
+                response.failed.len(),
+                handles.len(),
+                failed.message
+            )),
+        }
+    }
+}
+
+//------------ API messages --------------------------------------------------
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ReceiveResponse {
+    #[serde(default)]
+    messages: Vec<SqsMessage>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SqsMessage {
+    receipt_handle: String,
+    body: String,
+    #[serde(default)]
+    message_attributes: HashMap<String, AttributeValue>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AttributeValue {
+    /// The value of String and Number attributes.
+    string_value: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeleteResponse {
+    #[serde(default)]
+    failed: Vec<DeleteFailure>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeleteFailure {
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Default, Deserialize)]
+struct ErrorResponse {
+    #[serde(default, rename = "__type")]
+    error_type: String,
+    #[serde(default)]
+    message: String,
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bgp::encode::RAW_UPDATE,
+        tests::util::https,
+        units::{
+            cloud_queue_in::{
+                aws::Credentials,
+                unit::tests::{counts, receive, start_server},
+            },
+            Unit,
+        },
+    };
+
+    use super::*;
+
+    /// Returns the raw BGP UPDATE of the tests in Base64.
+    fn raw_update() -> String {
+        STANDARD.encode(hex::decode(RAW_UPDATE).unwrap())
+    }
+
+    fn config(toml: &str) -> SqsIn {
+        let Unit::SqsIn(config) = toml::from_str(toml).unwrap() else {
+            panic!("expected an sqs-in unit");
+        };
+        config
+    }
+
+    #[test]
+    fn the_region_is_found() {
+        let config = config(
+            r#"
+            type = "sqs-in"
+            queue_url = "https://sqs.eu-west-1.amazonaws.com/123456789012/bgp"
+            "#,
+        );
+        assert_eq!(config.region().as_deref(), Some("eu-west-1"));
+        assert_eq!(config.batch, 10);
+        assert_eq!(config.wait_time_secs, Duration::from_secs(20));
+        assert_eq!(config.visibility_timeout_secs, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn messages_are_received_and_deleted() {
+        let raw_update = raw_update();
+        let server = start_server(vec![
+            format!(
+                r#"{{"Messages": [
+                {{"MessageId": "1", "ReceiptHandle": "handle-1",
+                 "Body": "{raw_update}",
+                 "MessageAttributes": {{
+                    "Peer-Address":
+                        {{"DataType": "String", "StringValue": "192.0.2.1"}},
+                    "Peer-ASN":
+                        {{"DataType": "Number", "StringValue": "65000"}}}}}},
+                {{"MessageId": "2", "ReceiptHandle": "handle-2",
+                 "Body": "not base64"}}
+            ]}}"#
+            ),
+            r#"{"Successful": [{"Id": "0"}, {"Id": "1"}], "Failed": []}"#
+                .into(),
+        ])
+        .await;
+        let config = config(&format!(
+            r#"
+            type = "sqs-in"
+            queue_url = "http://{}/000000000000/bgp"
+            region = "us-east-1"
+            format = "bgpupdate"
+            visibility_timeout_secs = 60
+            "#,
+            server.addr
+        ));
+        let mut queue =
+            SqsQueue::new(config, "us-east-1".into(), HttpClient::new());
+        queue.credentials = CredentialChain::with_credentials(
+            HttpClient::new(),
+            Credentials {
+                access_key_id: "AKID".into(),
+                secret_access_key: "secret".into(),
+                ..Default::default()
+            },
+        );
+
+        let (res, metrics) =
+            receive(&mut queue, MessageFormat::BgpUpdate).await;
+        res.unwrap();
+        assert_eq!(counts(&metrics), [2, 1, 2, 1, 1]);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].uri.path(), "/");
+        assert_eq!(
+            requests[0].headers["x-amz-target"],
+            "AmazonSQS.ReceiveMessage"
+        );
+        assert!(requests[0].headers["authorization"]
+            .to_str()
+            .unwrap()
+            .starts_with("AWS4-HMAC-SHA256 Credential=AKID/"));
+        assert_eq!(requests[0].json()["MaxNumberOfMessages"], 10);
+        assert_eq!(requests[0].json()["VisibilityTimeout"], 60);
+        assert_eq!(
+            requests[1].headers["x-amz-target"],
+            "AmazonSQS.DeleteMessageBatch"
+        );
+        assert_eq!(
+            requests[1].json()["Entries"],
+            json!([
+                { "Id": "0", "ReceiptHandle": "handle-1" },
+                { "Id": "1", "ReceiptHandle": "handle-2" },
+            ])
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn messages_are_received_over_https() {
+        let raw_update = raw_update();
+        let server = start_server(vec![
+            format!(
+                r#"{{"Messages": [
+                {{"MessageId": "1", "ReceiptHandle": "handle-1",
+                 "Body": "{raw_update}"}}
+            ]}}"#
+            ),
+            r#"{"Successful": [{"Id": "0"}], "Failed": []}"#.into(),
+        ])
+        .await;
+        let front = https::front(server.addr).await;
+        let config = config(&format!(
+            r#"
+            type = "sqs-in"
+            queue_url = "https://localhost:{}/000000000000/bgp"
+            region = "us-east-1"
+            format = "bgpupdate"
+            "#,
+            front.port()
+        ));
+        let mut queue =
+            SqsQueue::new(config, "us-east-1".into(), https::client());
+        queue.credentials = CredentialChain::with_credentials(
+            https::client(),
+            Credentials {
+                access_key_id: "AKID".into(),
+                secret_access_key: "secret".into(),
+                ..Default::default()
+            },
+        );
+
+        let (res, metrics) =
+            receive(&mut queue, MessageFormat::BgpUpdate).await;
+        res.unwrap();
+        assert_eq!(counts(&metrics), [1, 0, 1, 1, 1]);
+        assert_eq!(server.requests().len(), 2);
+    }
+}
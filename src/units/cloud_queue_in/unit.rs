@@ -0,0 +1,405 @@
+//! Ingesting routes from cloud message queues.
+//!
+//! The `sqs-in` unit receives messages from an [AWS SQS] queue, the
+//! `pubsub-in` unit from a [Google Cloud Pub/Sub] subscription, so that
+//! collectors running in the cloud can hand their updates to Rotonda
+//! without a message broker of their own. Both receive the messages in
+//! batches and decode them according to the configured format as described
+//! in the [`decoder`] module, with the message attributes acting as
+//! headers. A message is acknowledged, i.e. deleted from the SQS queue or
+//! acknowledged to Pub/Sub, once its routes have been handed downstream. If
+//! Rotonda stops before that, the message is delivered again once its
+//! visibility timeout or acknowledgement deadline has passed. Messages that
+//! cannot be decoded are acknowledged as well, rather than delivered again
+//! and again.
+//!
+//! The credentials are found the way the official SDKs do, see the [`aws`]
+//! and [`gcp`] modules. As the HTTP client is built without TLS for now,
+//! only plain HTTP endpoints, such as LocalStack, ElasticMQ and the Pub/Sub
+//! emulator, can be used.
+//!
+//! If a request fails, the unit tries again with an exponential backoff.
+//!
+//! [AWS SQS]: https://aws.amazon.com/sqs/
+//! [Google Cloud Pub/Sub]: https://cloud.google.com/pubsub
+//! [`decoder`]: crate::units::kafka_in::decoder
+//! [`aws`]: super::aws
+//! [`gcp`]: super::gcp
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::{debug, error, info, warn};
+
+use crate::{
+    comms::{Gate, GateMetrics, GateStatus, Terminated},
+    ingress::IngressInfo,
+    manager::{Component, WaitPoint},
+    metrics::{self, Metric, MetricType, MetricUnit},
+    units::kafka_in::{
+        decoder::{Decoder, Message},
+        unit::MessageFormat,
+    },
+};
+
+//------------ Queue ---------------------------------------------------------
+
+/// A queue to receive messages from.
+pub(super) trait Queue: Send + 'static {
+    /// Receives the next batch of messages, waiting for a while if there
+    /// are none.
+    fn receive(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<QueueMessage>, String>> + Send;
+
+    /// Acknowledges the messages with the given handles.
+    fn ack(
+        &mut self,
+        handles: Vec<String>,
+    ) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+//------------ QueueMessage --------------------------------------------------
+
+/// A message received from a queue.
+#[derive(Clone, Debug)]
+pub(super) struct QueueMessage {
+    /// The queue or subscription the message was received from.
+    pub source: String,
+
+    /// The content of the message, unless it could not be decoded.
+    pub payload: Result<Vec<u8>, String>,
+
+    /// The attributes of the message.
+    pub attributes: Vec<(String, String)>,
+
+    /// What to acknowledge the message with.
+    pub handle: String,
+}
+
+impl Message for QueueMessage {
+    fn payload(&self) -> &[u8] {
+        self.payload.as_deref().unwrap_or_default()
+    }
+
+    fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+//------------ Running a unit ------------------------------------------------
+
+/// The settings shared by the cloud queue units.
+pub(super) struct Settings {
+    /// The type of the unit, for log messages.
+    pub unit_type: &'static str,
+    pub format: MessageFormat,
+    pub retry_delay: Duration,
+    pub max_retry_delay: Duration,
+}
+
+/// Checks the message format.
+pub(super) fn check_format(
+    component: &Component,
+    format: &MessageFormat,
+) -> Result<(), Terminated> {
+    if let MessageFormat::Custom(format) = format {
+        error!(
+            "Unit {}: unsupported message format '{format}'",
+            component.name()
+        );
+        return Err(Terminated);
+    }
+    Ok(())
+}
+
+/// Runs a unit receiving from `queue`.
+pub(super) async fn run(
+    mut component: Component,
+    gate: Gate,
+    mut waitpoint: WaitPoint,
+    queue: impl Queue,
+    settings: Settings,
+) -> Result<(), Terminated> {
+    let metrics = Arc::new(CloudQueueInMetrics::new(&gate));
+    component.register_metrics(metrics.clone());
+
+    gate.process_until(waitpoint.ready()).await?;
+    waitpoint.running().await;
+
+    let ingresses = component.ingresses().clone();
+    let parent_id = ingresses.register();
+    ingresses.update_info(
+        parent_id,
+        IngressInfo::new()
+            .with_unit_name(component.name().as_ref())
+            .with_desc(format!("{} unit", settings.unit_type)),
+    );
+
+    let decoder = Decoder::new(
+        settings.format.clone(),
+        gate.clone(),
+        ingresses,
+        parent_id,
+        "cloud queue",
+    );
+    let receiver = tokio::spawn(receive_loop(
+        queue,
+        component.name().to_string(),
+        decoder,
+        metrics,
+        settings.retry_delay,
+        settings.max_retry_delay,
+    ));
+
+    // The queue is handled in its own task, so here only the gate needs to
+    // be processed.
+    let res = loop {
+        match gate.process().await {
+            Ok(GateStatus::ReportLinks { report }) => {
+                report.declare_source();
+            }
+            Ok(GateStatus::Reconfiguring { .. }) => {
+                warn!(
+                    "Unit {}: reconfiguring {} requires a restart",
+                    component.name(),
+                    settings.unit_type
+                );
+            }
+            Ok(_) => {}
+            Err(Terminated) => break Err(Terminated),
+        }
+    };
+    receiver.abort();
+    res
+}
+
+/// Keep receiving from the queue, backing off when requests fail.
+async fn receive_loop(
+    mut queue: impl Queue,
+    name: String,
+    mut decoder: Decoder,
+    metrics: Arc<CloudQueueInMetrics>,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+) {
+    let mut delay = retry_delay;
+    loop {
+        match receive_batch(&mut queue, &mut decoder, &metrics).await {
+            Ok(()) => delay = retry_delay,
+            Err(err) => {
+                metrics.num_failed_requests.fetch_add(1, SeqCst);
+                warn!("Unit {name}: {err}");
+                info!("Unit {name}: retrying in {}s", delay.as_secs());
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(max_retry_delay);
+            }
+        }
+    }
+}
+
+/// Receives a batch of messages, sends their routes downstream and then
+/// acknowledges them.
+async fn receive_batch(
+    queue: &mut impl Queue,
+    decoder: &mut Decoder,
+    metrics: &CloudQueueInMetrics,
+) -> Result<(), String> {
+    let messages = queue.receive().await?;
+    if messages.is_empty() {
+        return Ok(());
+    }
+    let mut handles = Vec::with_capacity(messages.len());
+    for msg in messages {
+        metrics.num_messages.fetch_add(1, SeqCst);
+        let res = match &msg.payload {
+            Ok(_) => decoder.decode(&msg).await,
+            Err(err) => Err(err.clone()),
+        };
+        match res {
+            Ok((announced, withdrawn)) => {
+                metrics.num_announcements.fetch_add(announced, SeqCst);
+                metrics.num_withdrawals.fetch_add(withdrawn, SeqCst);
+            }
+            Err(err) => {
+                metrics.num_invalid_messages.fetch_add(1, SeqCst);
+                debug!("Ignoring message from '{}': {err}", msg.source);
+            }
+        }
+        handles.push(msg.handle);
+    }
+    let count = handles.len();
+    queue.ack(handles).await?;
+    metrics.num_acks.fetch_add(count, SeqCst);
+    Ok(())
+}
+
+//------------ CloudQueueInMetrics -------------------------------------------
+
+#[derive(Debug, Default)]
+pub(super) struct CloudQueueInMetrics {
+    gate: Arc<GateMetrics>,
+    num_messages: AtomicUsize,
+    num_invalid_messages: AtomicUsize,
+    num_acks: AtomicUsize,
+    num_failed_requests: AtomicUsize,
+    num_announcements: AtomicUsize,
+    num_withdrawals: AtomicUsize,
+}
+
+impl CloudQueueInMetrics {
+    fn new(gate: &Gate) -> Self {
+        Self {
+            gate: gate.metrics(),
+            ..Default::default()
+        }
+    }
+
+    const NUM_MESSAGES_METRIC: Metric = Metric::new(
+        "cloud_queue_in_num_messages",
+        "the number of messages received from the queue",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_INVALID_MESSAGES_METRIC: Metric = Metric::new(
+        "cloud_queue_in_num_invalid_messages",
+        "the number of messages that could not be decoded",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ACKS_METRIC: Metric = Metric::new(
+        "cloud_queue_in_num_acks",
+        "the number of messages acknowledged",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_FAILED_REQUESTS_METRIC: Metric = Metric::new(
+        "cloud_queue_in_num_failed_requests",
+        "the number of requests to the queue that failed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ANNOUNCEMENTS_METRIC: Metric = Metric::new(
+        "cloud_queue_in_num_announcements",
+        "the number of route announcements received from the queue",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WITHDRAWALS_METRIC: Metric = Metric::new(
+        "cloud_queue_in_num_withdrawals",
+        "the number of route withdrawals received from the queue",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for CloudQueueInMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        self.gate.append(unit_name, target);
+        target.append_simple(
+            &Self::NUM_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_INVALID_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_invalid_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ACKS_METRIC,
+            Some(unit_name),
+            self.num_acks.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_FAILED_REQUESTS_METRIC,
+            Some(unit_name),
+            self.num_failed_requests.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ANNOUNCEMENTS_METRIC,
+            Some(unit_name),
+            self.num_announcements.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WITHDRAWALS_METRIC,
+            Some(unit_name),
+            self.num_withdrawals.load(SeqCst),
+        );
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::sync::Arc;
+
+    use hyper::{Body, Response};
+
+    use crate::{ingress, tests::util::http::MockServer};
+
+    use super::*;
+
+    /// Starts a server answering the requests in turn with the given
+    /// responses.
+    pub async fn start_server(responses: Vec<String>) -> MockServer {
+        let mut responses = responses.into_iter();
+        MockServer::start(move |_| {
+            let response = responses.next().expect("unexpected request");
+            Response::new(Body::from(response))
+        })
+        .await
+    }
+
+    /// Receives a batch from `queue`, returning the metrics.
+    pub async fn receive(
+        queue: &mut impl Queue,
+        format: MessageFormat,
+    ) -> (Result<(), String>, Arc<CloudQueueInMetrics>) {
+        let (gate, _agent) = Gate::new(0);
+        let ingresses = Arc::new(ingress::Register::default());
+        let parent_id = ingresses.register();
+        let mut decoder =
+            Decoder::new(format, gate.clone(), ingresses, parent_id, "test");
+        let metrics = Arc::new(CloudQueueInMetrics::new(&gate));
+        let res = receive_batch(queue, &mut decoder, &metrics).await;
+        (res, metrics)
+    }
+
+    /// Returns the values of the metrics.
+    pub fn counts(metrics: &CloudQueueInMetrics) -> [usize; 5] {
+        [
+            metrics.num_messages.load(SeqCst),
+            metrics.num_invalid_messages.load(SeqCst),
+            metrics.num_acks.load(SeqCst),
+            metrics.num_announcements.load(SeqCst),
+            metrics.num_withdrawals.load(SeqCst),
+        ]
+    }
+
+    #[test]
+    fn attributes_are_headers() {
+        let msg = QueueMessage {
+            source: "bgp".into(),
+            payload: Ok(vec![]),
+            attributes: vec![("Peer-ASN".into(), "65000".into())],
+            handle: "1".into(),
+        };
+        assert_eq!(msg.header("peer-asn"), Some("65000"));
+        assert_eq!(msg.header("Peer-Address"), None);
+    }
+}
@@ -0,0 +1,448 @@
+//! The JSON messages of ExaBGP's API.
+//!
+//! ExaBGP writes one JSON object per line to the processes configured with
+//! `encoder json`. Of these, the messages reporting neighbor state changes,
+//! received UPDATE messages and ExaBGP shutting down are used:
+//!
+//! ```json
+//! {
+//!     "exabgp": "4.0.1", "host": "router1", "type": "update",
+//!     "neighbor": {
+//!         "address": { "local": "192.0.2.2", "peer": "192.0.2.1" },
+//!         "asn": { "local": 65001, "peer": 65000 },
+//!         "direction": "receive",
+//!         "message": { "update": {
+//!             "attribute": {
+//!                 "origin": "igp",
+//!                 "as-path": [65000, 65002],
+//!                 "local-preference": 100,
+//!                 "community": [[65000, 1]]
+//!             },
+//!             "announce": { "ipv4 unicast": {
+//!                 "192.0.2.1": [{ "nlri": "198.51.100.0/24" }]
+//!             } },
+//!             "withdraw": { "ipv4 unicast": [{ "nlri": "203.0.113.0/24" }] }
+//!         } }
+//!     }
+//! }
+//! ```
+//!
+//! Only the IPv4 and IPv6 unicast families are used, and of the path
+//! attributes only the origin, AS path, next hop, MED, local preference and
+//! standard communities. AS_SETs are flattened into the AS path, which may
+//! be given as a list of AS numbers or, as ExaBGP 5 does, as a map of
+//! segments. UPDATE messages sent by ExaBGP and all other messages, like
+//! keepalives and end-of-RIB markers, are ignored.
+
+use std::{collections::BTreeMap, net::IpAddr, str::FromStr};
+
+use inetnum::{addr::Prefix, asn::Asn};
+use routecore::bgp::communities::StandardCommunity;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::units::http_in::batch::{Attributes, Origin, PeerUpdate, Routes};
+
+//------------ Event ---------------------------------------------------------
+
+/// What a message means for the routes of its neighbor.
+pub enum Event {
+    /// Routes announced and withdrawn by a neighbor.
+    Update(Neighbor, Routes),
+
+    /// The session with a neighbor went down.
+    PeerDown(Neighbor),
+
+    /// ExaBGP is shutting down, ending the sessions with all neighbors.
+    Shutdown,
+
+    Ignored,
+}
+
+/// A neighbor of an ExaBGP host.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Neighbor {
+    /// The host name ExaBGP runs on.
+    pub host: String,
+    pub address: IpAddr,
+    pub asn: Asn,
+}
+
+/// Parses a message.
+pub fn parse(line: &str) -> Result<Event, String> {
+    let envelope: Envelope =
+        serde_json::from_str(line).map_err(|err| err.to_string())?;
+    if envelope.msg_type == "notification"
+        && envelope.notification.as_deref() == Some("shutdown")
+    {
+        return Ok(Event::Shutdown);
+    }
+    let Some(body) = envelope.neighbor else {
+        return Ok(Event::Ignored);
+    };
+    let neighbor = Neighbor {
+        host: envelope.host,
+        address: body.address.peer,
+        asn: Asn::from_u32(body.asn.peer),
+    };
+    match envelope.msg_type.as_str() {
+        "state" if body.state.as_deref() == Some("down") => {
+            Ok(Event::PeerDown(neighbor))
+        }
+        "update" if body.direction.as_deref() != Some("send") => {
+            let Some(update) = body.message.and_then(|msg| msg.update) else {
+                return Ok(Event::Ignored);
+            };
+            let routes = update.routes(&neighbor)?;
+            Ok(Event::Update(neighbor, routes))
+        }
+        _ => Ok(Event::Ignored),
+    }
+}
+
+//------------ The JSON structure --------------------------------------------
+
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(rename = "type")]
+    msg_type: String,
+
+    #[serde(default)]
+    host: String,
+
+    /// Given for messages about a neighbor.
+    #[serde(default)]
+    neighbor: Option<NeighborBody>,
+
+    /// Given as "shutdown" when ExaBGP shuts down. Notifications sent or
+    /// received by a neighbor are in its message instead.
+    #[serde(default)]
+    notification: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NeighborBody {
+    address: Addresses,
+    asn: Asns,
+
+    #[serde(default)]
+    state: Option<String>,
+
+    #[serde(default)]
+    direction: Option<String>,
+
+    #[serde(default)]
+    message: Option<MessageBody>,
+}
+
+#[derive(Deserialize)]
+struct Addresses {
+    peer: IpAddr,
+}
+
+#[derive(Deserialize)]
+struct Asns {
+    peer: u32,
+}
+
+#[derive(Deserialize)]
+struct MessageBody {
+    #[serde(default)]
+    update: Option<UpdateBody>,
+}
+
+#[derive(Deserialize)]
+struct UpdateBody {
+    #[serde(default)]
+    attribute: AttributeBody,
+
+    /// The announced NLRI by family and next hop. Families other than
+    /// unicast have NLRI of their own shapes, so are left unparsed.
+    #[serde(default)]
+    announce: Map<String, Value>,
+
+    /// The withdrawn NLRI by family.
+    #[serde(default)]
+    withdraw: Map<String, Value>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AttributeBody {
+    #[serde(default)]
+    origin: Origin,
+
+    #[serde(default)]
+    as_path: Option<Value>,
+
+    #[serde(default)]
+    med: Option<u32>,
+
+    #[serde(default)]
+    local_preference: Option<u32>,
+
+    #[serde(default)]
+    community: Vec<(u16, u16)>,
+}
+
+/// An NLRI, given as an object in ExaBGP 4 and later and as a plain prefix
+/// before.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Nlri {
+    Object { nlri: Prefix },
+    Prefix(Prefix),
+}
+
+impl Nlri {
+    fn prefix(&self) -> Prefix {
+        match self {
+            Nlri::Object { nlri } | Nlri::Prefix(nlri) => *nlri,
+        }
+    }
+}
+
+fn is_unicast(family: &str) -> bool {
+    family == "ipv4 unicast" || family == "ipv6 unicast"
+}
+
+impl UpdateBody {
+    fn routes(&self, neighbor: &Neighbor) -> Result<Routes, String> {
+        let attributes = self.attribute.to_attributes()?;
+        let update = |announce, withdraw, next_hop| PeerUpdate {
+            peer_address: neighbor.address,
+            peer_asn: neighbor.asn.into_u32(),
+            announce,
+            withdraw,
+            attributes: Attributes {
+                next_hop,
+                ..attributes.clone()
+            },
+            peer_down: false,
+        };
+
+        let mut routes = Routes {
+            announced: vec![],
+            withdrawn: vec![],
+        };
+        for (family, next_hops) in &self.announce {
+            if !is_unicast(family) {
+                continue;
+            }
+            let next_hops: BTreeMap<String, Vec<Nlri>> =
+                serde_json::from_value(next_hops.clone())
+                    .map_err(|err| format!("{family}: {err}"))?;
+            for (next_hop, nlri) in next_hops {
+                // IPv6 next hops may be followed by a link-local address.
+                let next_hop = next_hop
+                    .split_whitespace()
+                    .next()
+                    .and_then(|addr| IpAddr::from_str(addr).ok())
+                    .ok_or_else(|| format!("invalid next hop {next_hop}"))?;
+                let prefixes = nlri.iter().map(Nlri::prefix).collect();
+                routes.announced.append(
+                    &mut update(prefixes, vec![], Some(next_hop))
+                        .routes()?
+                        .announced,
+                );
+            }
+        }
+
+        let mut withdraw = vec![];
+        for (family, nlri) in &self.withdraw {
+            if !is_unicast(family) {
+                continue;
+            }
+            let nlri: Vec<Nlri> = serde_json::from_value(nlri.clone())
+                .map_err(|err| format!("{family}: {err}"))?;
+            withdraw.extend(nlri.iter().map(Nlri::prefix));
+        }
+        routes.withdrawn = update(vec![], withdraw, None).routes()?.withdrawn;
+        Ok(routes)
+    }
+}
+
+impl AttributeBody {
+    fn to_attributes(&self) -> Result<Attributes, String> {
+        let mut as_path = vec![];
+        if let Some(path) = &self.as_path {
+            flatten_as_path(path, &mut as_path)?;
+        }
+        Ok(Attributes {
+            origin: self.origin,
+            as_path,
+            next_hop: None,
+            med: self.med,
+            local_pref: self.local_preference,
+            communities: self
+                .community
+                .iter()
+                .map(|(asn, value)| {
+                    StandardCommunity::from_u32(
+                        (u32::from(*asn) << 16) | u32::from(*value),
+                    )
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Appends the AS numbers of an AS path to `as_path`.
+///
+/// The path is a list of AS numbers and of lists for AS_SETs, or a map of
+/// segments each with an `element` type and a `value` list.
+fn flatten_as_path(
+    path: &Value,
+    as_path: &mut Vec<u32>,
+) -> Result<(), String> {
+    let invalid = || format!("invalid AS path {path}");
+    match path {
+        Value::Number(asn) => as_path.push(
+            asn.as_u64()
+                .and_then(|asn| u32::try_from(asn).ok())
+                .ok_or_else(invalid)?,
+        ),
+        Value::Array(items) => {
+            for item in items {
+                flatten_as_path(item, as_path)?;
+            }
+        }
+        Value::Object(segments) => {
+            // Segments are keyed by their position.
+            let mut segments = segments
+                .iter()
+                .map(|(key, segment)| {
+                    key.parse::<usize>().map(|pos| (pos, segment))
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid())?;
+            segments.sort_by_key(|(pos, _)| *pos);
+            for (_, segment) in segments {
+                // Confederation segments are not part of the path.
+                let element = segment.get("element").and_then(Value::as_str);
+                if element.is_some_and(|element| element.contains("confed")) {
+                    continue;
+                }
+                flatten_as_path(
+                    segment.get("value").ok_or_else(invalid)?,
+                    as_path,
+                )?;
+            }
+        }
+        _ => return Err(invalid()),
+    }
+    Ok(())
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+pub(super) mod tests {
+    use routecore::bgp::{aspath::HopPath, path_attributes::PathAttribute};
+
+    use super::*;
+
+    /// An update from neighbor 192.0.2.1 of `router1`, announcing two
+    /// prefixes over two next hops and withdrawing one.
+    pub const UPDATE: &str = r#"{ "exabgp": "4.0.1", "host": "router1",
+        "type": "update", "neighbor": {
+        "address": { "local": "192.0.2.2", "peer": "192.0.2.1" },
+        "asn": { "local": 65001, "peer": 65000 }, "direction": "receive",
+        "message": { "update": {
+            "attribute": { "origin": "egp", "as-path": [65000, [65002, 65003]],
+                "med": 10, "community": [[65000, 1], [65535, 65281]] },
+            "announce": { "ipv4 unicast": {
+                "192.0.2.1": [{ "nlri": "198.51.100.0/24" }],
+                "192.0.2.3": [{ "nlri": "198.51.100.128/25" }]
+            }, "ipv4 flow": { "no-nexthop": [{ "destination-ipv4": [] }] } },
+            "withdraw": { "ipv4 unicast": [{ "nlri": "203.0.113.0/24" }] }
+        } } } }"#;
+
+    /// The neighbor of `UPDATE` going down.
+    pub const DOWN: &str = r#"{ "exabgp": "4.0.1", "host": "router1",
+        "type": "state", "neighbor": {
+        "address": { "local": "192.0.2.2", "peer": "192.0.2.1" },
+        "asn": { "local": 65001, "peer": 65000 },
+        "state": "down", "reason": "peer reset" } }"#;
+
+    #[test]
+    fn updates_are_converted() {
+        let Event::Update(neighbor, routes) = parse(UPDATE).unwrap() else {
+            panic!("expected an update");
+        };
+        assert_eq!(neighbor.host, "router1");
+        assert_eq!(neighbor.asn, Asn::from_u32(65000));
+        assert_eq!(routes.announced.len(), 2);
+        assert_eq!(routes.withdrawn.len(), 1);
+
+        let pamap = routes.announced[0].rotonda_pamap().path_attributes();
+        let attributes = pamap
+            .iter()
+            .map(|attr| attr.unwrap().to_owned().unwrap())
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            &attributes[1],
+            PathAttribute::AsPath(path)
+                if *path == HopPath::from(vec![
+                    Asn::from_u32(65000),
+                    Asn::from_u32(65002),
+                    Asn::from_u32(65003),
+                ])
+        ));
+        assert!(matches!(
+            attributes.last().unwrap(),
+            PathAttribute::StandardCommunities(list)
+                if list.communities().len() == 2
+        ));
+
+        // ExaBGP 5 gives the AS path as segments, and IPv6 next hops with
+        // their link-local address.
+        let update = r#"{ "host": "router1", "type": "update", "neighbor": {
+            "address": { "peer": "2001:db8::1" }, "asn": { "peer": 65000 },
+            "message": { "update": {
+                "attribute": { "as-path": {
+                    "1": { "element": "as-sequence", "value": [65002] },
+                    "0": { "element": "as-sequence", "value": [65000] }
+                } },
+                "announce": { "ipv6 unicast": {
+                    "2001:db8::1 fe80::1": ["2001:db8:1::/48"]
+                } }
+            } } } }"#;
+        let Event::Update(_, routes) = parse(update).unwrap() else {
+            panic!("expected an update");
+        };
+        let pamap = routes.announced[0].rotonda_pamap().path_attributes();
+        assert!(pamap.iter().any(|attr| matches!(
+            attr.unwrap().to_owned().unwrap(),
+            PathAttribute::AsPath(path)
+                if path == HopPath::from(vec![
+                    Asn::from_u32(65000),
+                    Asn::from_u32(65002),
+                ])
+        )));
+    }
+
+    #[test]
+    fn other_messages_are_recognised() {
+        assert!(matches!(parse(DOWN), Ok(Event::PeerDown(_))));
+        assert!(matches!(
+            parse(&DOWN.replace("\"down\"", "\"up\"")),
+            Ok(Event::Ignored)
+        ));
+        assert!(matches!(
+            parse(&UPDATE.replace("receive", "send")),
+            Ok(Event::Ignored)
+        ));
+        assert!(matches!(
+            parse(
+                r#"{ "exabgp": "4.0.1", "host": "router1",
+                "type": "notification", "notification": "shutdown" }"#
+            ),
+            Ok(Event::Shutdown)
+        ));
+
+        // Broken JSON, and an announcement without a valid next hop.
+        assert!(parse(&UPDATE[1..]).is_err());
+        assert!(parse(&UPDATE.replace("192.0.2.3", "none")).is_err());
+    }
+}
@@ -0,0 +1,4 @@
+mod message;
+pub mod unit;
+
+pub use unit::ExabgpIn;
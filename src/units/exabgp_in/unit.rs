@@ -0,0 +1,431 @@
+//! Ingesting routes from ExaBGP.
+//!
+//! [ExaBGP] hands what it hears from its neighbors to the processes in its
+//! API configuration as JSON, one message per line. This unit reads that
+//! stream either from standard input, with Rotonda itself run as such a
+//! process, or from the connections accepted on a UNIX socket, with the
+//! process relaying its input, e.g. by running `socat -
+//! UNIX-CONNECT:/run/rotonda/exabgp.sock`. The format of the messages is
+//! described in the [`message`] module.
+//!
+//! Every ExaBGP host, by the host name in its messages, is registered as an
+//! ingress of the unit, and every neighbor as an ingress of its host. When
+//! a neighbor goes down, all its routes are withdrawn, as are those of all
+//! neighbors heard of over a stream when ExaBGP shuts down or the stream
+//! ends.
+//!
+//! [ExaBGP]: https://github.com/Exa-Networks/exabgp
+//! [`message`]: super::message
+
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc, Mutex,
+    },
+};
+
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    net::UnixListener,
+};
+
+use crate::{
+    common::net::bind_unix,
+    comms::{Gate, GateMetrics, GateStatus, Terminated},
+    ingress::IngressInfo,
+    manager::{Component, WaitPoint},
+    metrics::{self, Metric, MetricType, MetricUnit},
+    units::{
+        http_in::batch::Routes,
+        ris_live_in::unit::{Converted, Converter},
+    },
+};
+
+use super::message::{self, Event, Neighbor};
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ExabgpIn {
+    /// The UNIX socket to accept connections from ExaBGP on. Without it,
+    /// the messages are read from standard input.
+    #[serde(default)]
+    pub socket: Option<PathBuf>,
+}
+
+impl ExabgpIn {
+    pub async fn run(
+        self,
+        mut component: Component,
+        gate: Gate,
+        mut waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let metrics = Arc::new(ExabgpInMetrics::new(&gate));
+        component.register_metrics(metrics.clone());
+
+        gate.process_until(waitpoint.ready()).await?;
+        waitpoint.running().await;
+
+        let listener = match &self.socket {
+            Some(path) => match bind_unix(path) {
+                Ok(listener) => Some(listener),
+                Err(err) => {
+                    error!(
+                        "Unit {}: cannot listen on {}: {err}",
+                        component.name(),
+                        path.display()
+                    );
+                    return Err(Terminated);
+                }
+            },
+            None => None,
+        };
+
+        let ingresses = component.ingresses().clone();
+        let parent_id = ingresses.register();
+        ingresses.update_info(
+            parent_id,
+            IngressInfo::new()
+                .with_unit_name(component.name().as_ref())
+                .with_desc("exabgp-in unit"),
+        );
+        let reader = Arc::new(ExabgpInReader {
+            name: component.name().to_string(),
+            gate: gate.clone(),
+            converter: Mutex::new(Converter::new(
+                ingresses,
+                parent_id,
+                "ExaBGP host",
+            )),
+            metrics,
+        });
+        let task = match listener {
+            Some(listener) => tokio::spawn(reader.accept(listener)),
+            None => tokio::spawn(async move {
+                reader.read(tokio::io::stdin(), "standard input").await;
+            }),
+        };
+
+        // The stream is read in its own task, so here only the gate needs
+        // to be processed.
+        let res = loop {
+            match gate.process().await {
+                Ok(GateStatus::ReportLinks { report }) => {
+                    report.declare_source();
+                }
+                Ok(GateStatus::Reconfiguring { .. }) => {
+                    warn!(
+                        "Unit {}: reconfiguring exabgp-in requires a restart",
+                        component.name()
+                    );
+                }
+                Ok(_) => {}
+                Err(Terminated) => break Err(Terminated),
+            }
+        };
+        task.abort();
+        res
+    }
+}
+
+//------------ ExabgpInReader ------------------------------------------------
+
+struct ExabgpInReader {
+    name: String,
+    gate: Gate,
+    converter: Mutex<Converter>,
+    metrics: Arc<ExabgpInMetrics>,
+}
+
+impl ExabgpInReader {
+    /// Read the streams of the connections accepted on `listener`.
+    async fn accept(self: Arc<Self>, listener: UnixListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    warn!("Unit {}: accepting failed: {err}", self.name);
+                    continue;
+                }
+            };
+            self.metrics.num_connections.fetch_add(1, SeqCst);
+            let reader = self.clone();
+            tokio::spawn(async move {
+                reader.read(stream, "connection").await;
+            });
+        }
+    }
+
+    /// Process the messages of a stream, until it ends.
+    async fn read(&self, stream: impl AsyncRead + Unpin, source: &str) {
+        info!("Unit {}: reading ExaBGP messages from {source}", self.name);
+        let mut lines = BufReader::new(stream).lines();
+        let mut neighbors = HashSet::new();
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => {
+                    if !line.trim().is_empty() {
+                        self.process(&line, &mut neighbors).await;
+                    }
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    warn!(
+                        "Unit {}: reading from {source} failed: {err}",
+                        self.name
+                    );
+                    break;
+                }
+            }
+        }
+        info!("Unit {}: {source} ended", self.name);
+        for neighbor in neighbors {
+            self.peer_down(&neighbor).await;
+        }
+    }
+
+    /// Process a message, keeping track of the neighbors with routes.
+    async fn process(&self, line: &str, neighbors: &mut HashSet<Neighbor>) {
+        self.metrics.num_messages.fetch_add(1, SeqCst);
+        let event = match message::parse(line) {
+            Ok(event) => event,
+            Err(err) => {
+                self.metrics.num_invalid_messages.fetch_add(1, SeqCst);
+                debug!("Unit {}: ignoring message: {err}", self.name);
+                return;
+            }
+        };
+        match event {
+            Event::Update(
+                neighbor,
+                Routes {
+                    announced,
+                    withdrawn,
+                },
+            ) => {
+                let converted =
+                    self.converter.lock().unwrap().convert_routes(
+                        &neighbor.host,
+                        neighbor.address,
+                        neighbor.asn,
+                        announced,
+                        withdrawn,
+                    );
+                neighbors.insert(neighbor);
+                self.send(converted).await;
+            }
+            Event::PeerDown(neighbor) => {
+                neighbors.remove(&neighbor);
+                self.peer_down(&neighbor).await;
+            }
+            Event::Shutdown => {
+                for neighbor in std::mem::take(neighbors) {
+                    self.peer_down(&neighbor).await;
+                }
+            }
+            Event::Ignored => {}
+        }
+    }
+
+    async fn peer_down(&self, neighbor: &Neighbor) {
+        self.metrics.num_peer_downs.fetch_add(1, SeqCst);
+        let converted = self.converter.lock().unwrap().peer_down(
+            &neighbor.host,
+            neighbor.address,
+            neighbor.asn,
+        );
+        self.send(converted).await;
+    }
+
+    async fn send(&self, converted: Converted) {
+        if let Converted::Update(update, announced, withdrawn) = converted {
+            self.metrics.num_announcements.fetch_add(announced, SeqCst);
+            self.metrics.num_withdrawals.fetch_add(withdrawn, SeqCst);
+            self.gate.update_data(update).await;
+        }
+    }
+}
+
+//------------ ExabgpInMetrics -----------------------------------------------
+
+#[derive(Debug, Default)]
+struct ExabgpInMetrics {
+    gate: Arc<GateMetrics>,
+    num_connections: AtomicUsize,
+    num_messages: AtomicUsize,
+    num_invalid_messages: AtomicUsize,
+    num_announcements: AtomicUsize,
+    num_withdrawals: AtomicUsize,
+    num_peer_downs: AtomicUsize,
+}
+
+impl ExabgpInMetrics {
+    fn new(gate: &Gate) -> Self {
+        Self {
+            gate: gate.metrics(),
+            ..Default::default()
+        }
+    }
+
+    const NUM_CONNECTIONS_METRIC: Metric = Metric::new(
+        "exabgp_in_num_connections",
+        "the number of connections accepted on the socket",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_MESSAGES_METRIC: Metric = Metric::new(
+        "exabgp_in_num_messages",
+        "the number of ExaBGP messages read",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_INVALID_MESSAGES_METRIC: Metric = Metric::new(
+        "exabgp_in_num_invalid_messages",
+        "the number of ExaBGP messages that could not be parsed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ANNOUNCEMENTS_METRIC: Metric = Metric::new(
+        "exabgp_in_num_announcements",
+        "the number of route announcements read",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WITHDRAWALS_METRIC: Metric = Metric::new(
+        "exabgp_in_num_withdrawals",
+        "the number of route withdrawals read",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_PEER_DOWNS_METRIC: Metric = Metric::new(
+        "exabgp_in_num_peer_downs",
+        "the number of neighbors that went down",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for ExabgpInMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        self.gate.append(unit_name, target);
+        target.append_simple(
+            &Self::NUM_CONNECTIONS_METRIC,
+            Some(unit_name),
+            self.num_connections.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_INVALID_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_invalid_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ANNOUNCEMENTS_METRIC,
+            Some(unit_name),
+            self.num_announcements.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WITHDRAWALS_METRIC,
+            Some(unit_name),
+            self.num_withdrawals.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_PEER_DOWNS_METRIC,
+            Some(unit_name),
+            self.num_peer_downs.load(SeqCst),
+        );
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use inetnum::asn::Asn;
+    use tokio::{io::AsyncWriteExt, net::UnixStream};
+
+    use crate::ingress;
+
+    use super::{
+        super::message::tests::{DOWN, UPDATE},
+        *,
+    };
+
+    #[test]
+    fn config_deserialization() {
+        let config: ExabgpIn = toml::from_str("").unwrap();
+        assert!(config.socket.is_none());
+        let config: ExabgpIn =
+            toml::from_str(r#"socket = "/run/rotonda/exabgp.sock""#).unwrap();
+        assert_eq!(
+            config.socket,
+            Some(PathBuf::from("/run/rotonda/exabgp.sock"))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn streams_are_read_from_the_socket() {
+        let path = std::env::temp_dir()
+            .join(format!("rotonda-exabgp-{}.sock", uuid::Uuid::new_v4()));
+        let (gate, _agent) = Gate::new(0);
+        let ingresses = Arc::new(ingress::Register::default());
+        let parent_id = ingresses.register();
+        let reader = Arc::new(ExabgpInReader {
+            name: "exabgp".into(),
+            gate: gate.clone(),
+            converter: Mutex::new(Converter::new(
+                ingresses.clone(),
+                parent_id,
+                "ExaBGP host",
+            )),
+            metrics: Arc::new(ExabgpInMetrics::new(&gate)),
+        });
+        let accepting = tokio::spawn(
+            reader.clone().accept(bind_unix(&path).unwrap()),
+        );
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        for line in [UPDATE, "not json", DOWN, UPDATE] {
+            stream
+                .write_all(line.replace('\n', " ").as_bytes())
+                .await
+                .unwrap();
+            stream.write_all(b"\n").await.unwrap();
+        }
+        drop(stream);
+
+        // The neighbor went down twice, the second time when the stream
+        // ended.
+        let metrics = reader.metrics.clone();
+        while metrics.num_peer_downs.load(SeqCst) < 2 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(metrics.num_connections.load(SeqCst), 1);
+        assert_eq!(metrics.num_messages.load(SeqCst), 4);
+        assert_eq!(metrics.num_invalid_messages.load(SeqCst), 1);
+        assert_eq!(metrics.num_announcements.load(SeqCst), 4);
+
+        // The neighbor is registered under its host.
+        let peer = ingresses
+            .find_all(|info| info.remote_asn == Some(Asn::from_u32(65000)))
+            .pop()
+            .unwrap();
+        let host = ingresses.get(peer).unwrap().parent_ingress.unwrap();
+        let info = ingresses.get(host).unwrap();
+        assert_eq!(info.name.as_deref(), Some("router1"));
+
+        // A socket left behind is replaced.
+        accepting.abort();
+        let _ = accepting.await;
+        assert!(bind_unix(&path).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,475 @@
+//! Traffic counters aggregated from flow telemetry.
+//!
+//! A [`TrafficCounters`] keeps the bytes and packets seen per prefix and
+//! per BGP next hop over a sliding window. The window is divided into a
+//! number of buckets, and when the oldest bucket falls out of the window its
+//! counts are subtracted from the running totals.
+//!
+//! Addresses are aggregated into prefixes of a configured length. The
+//! traffic of a shorter prefix is the sum of the aggregates it covers, while
+//! that of a longer prefix is the traffic of the aggregate covering it.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::AddAssign,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use inetnum::addr::Prefix;
+
+/// The number of buckets the window is divided into.
+const NUM_BUCKETS: u32 = 10;
+
+//------------ Counter -------------------------------------------------------
+
+/// The traffic seen for a prefix or peer.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Counter {
+    pub bytes: u64,
+    pub packets: u64,
+}
+
+impl Counter {
+    pub fn new(bytes: u64, packets: u64) -> Self {
+        Self { bytes, packets }
+    }
+
+    fn subtract(&mut self, other: Counter) {
+        self.bytes = self.bytes.saturating_sub(other.bytes);
+        self.packets = self.packets.saturating_sub(other.packets);
+    }
+
+    fn is_zero(&self) -> bool {
+        self.bytes == 0 && self.packets == 0
+    }
+}
+
+impl AddAssign for Counter {
+    fn add_assign(&mut self, other: Counter) {
+        self.bytes = self.bytes.saturating_add(other.bytes);
+        self.packets = self.packets.saturating_add(other.packets);
+    }
+}
+
+//------------ Flow ----------------------------------------------------------
+
+/// A sampled flow, scaled up to the traffic it represents.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Flow {
+    pub src: Option<IpAddr>,
+    pub dst: Option<IpAddr>,
+
+    /// The BGP next hop the traffic was forwarded to.
+    pub next_hop: Option<IpAddr>,
+
+    pub counter: Counter,
+}
+
+//------------ TrafficCounters -----------------------------------------------
+
+/// The traffic per prefix and per peer over a sliding window.
+///
+/// The counters are kept up to date by a `flow-in` unit, and read by the
+/// roto filters and RIBs configured to use them. Until the unit configures
+/// them, they aggregate IPv4 addresses into /24s and IPv6 addresses into
+/// /48s over five minutes.
+#[derive(Debug)]
+pub struct TrafficCounters {
+    state: RwLock<State>,
+}
+
+impl Default for TrafficCounters {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(300), 24, 48, usize::MAX)
+    }
+}
+
+impl TrafficCounters {
+    /// Creates counters over `window`, aggregating addresses into prefixes
+    /// of the given lengths and tracking at most `max_prefixes` of them.
+    pub fn new(
+        window: Duration,
+        ipv4_len: u8,
+        ipv6_len: u8,
+        max_prefixes: usize,
+    ) -> Self {
+        Self {
+            state: RwLock::new(State::new(
+                window,
+                ipv4_len,
+                ipv6_len,
+                max_prefixes,
+            )),
+        }
+    }
+
+    /// Changes the window and aggregation, discarding all counts if they
+    /// differ from the current ones.
+    pub fn configure(
+        &self,
+        window: Duration,
+        ipv4_len: u8,
+        ipv6_len: u8,
+        max_prefixes: usize,
+    ) {
+        let mut state = self.state.write().unwrap();
+        let new = State::new(window, ipv4_len, ipv6_len, max_prefixes);
+        if (state.bucket_len, state.ipv4_len, state.ipv6_len)
+            != (new.bucket_len, new.ipv4_len, new.ipv6_len)
+        {
+            *state = new;
+        } else {
+            state.max_prefixes = max_prefixes;
+        }
+    }
+
+    /// Counts traffic to or from `addr`, forwarded to `next_hop`.
+    ///
+    /// Returns `false` if the prefix of `addr` was not tracked yet and the
+    /// maximum number of prefixes has been reached, in which case only the
+    /// next hop is counted.
+    pub fn record(
+        &self,
+        addr: Option<IpAddr>,
+        next_hop: Option<IpAddr>,
+        counter: Counter,
+    ) -> bool {
+        self.record_at(Instant::now(), addr, next_hop, counter)
+    }
+
+    fn record_at(
+        &self,
+        now: Instant,
+        addr: Option<IpAddr>,
+        next_hop: Option<IpAddr>,
+        counter: Counter,
+    ) -> bool {
+        let mut state = self.state.write().unwrap();
+        state.advance(now);
+        let key = addr.map(|addr| truncate(addr, state.aggregate_len(addr)));
+        let state = &mut *state;
+        let bucket = state.buckets.back_mut().unwrap();
+        if let Some(next_hop) = next_hop {
+            *bucket.peers.entry(next_hop).or_default() += counter;
+            *state.peers.entry(next_hop).or_default() += counter;
+        }
+        let Some(key) = key else { return true };
+        if !state.prefixes.contains_key(&key)
+            && state.prefixes.len() >= state.max_prefixes
+        {
+            return false;
+        }
+        *bucket.prefixes.entry(key).or_default() += counter;
+        *state.prefixes.entry(key).or_default() += counter;
+        true
+    }
+
+    /// Drops the counts that fell out of the window.
+    ///
+    /// This happens when traffic is recorded, but needs to be done
+    /// regularly for the counts not to linger when no traffic comes in.
+    pub fn expire(&self) {
+        self.state.write().unwrap().advance(Instant::now())
+    }
+
+    /// Returns the traffic of `prefix`.
+    pub fn prefix(&self, prefix: &Prefix) -> Counter {
+        let state = self.state.read().unwrap();
+        let addr = prefix.addr();
+        let len = state.aggregate_len(addr);
+        if prefix.len() >= len {
+            return state
+                .prefixes
+                .get(&truncate(addr, len))
+                .copied()
+                .unwrap_or_default();
+        }
+        let first = truncate(addr, prefix.len());
+        let last = last_addr(addr, prefix.len());
+        let mut total = Counter::default();
+        for counter in state.prefixes.range(first..=last).map(|(_, c)| c) {
+            total += *counter;
+        }
+        total
+    }
+
+    /// Returns the traffic forwarded to the BGP next hop `peer`.
+    pub fn peer(&self, peer: IpAddr) -> Counter {
+        let state = self.state.read().unwrap();
+        state.peers.get(&peer).copied().unwrap_or_default()
+    }
+
+    /// Returns the number of prefixes and peers with traffic.
+    pub fn counts(&self) -> (usize, usize) {
+        let state = self.state.read().unwrap();
+        (state.prefixes.len(), state.peers.len())
+    }
+}
+
+//------------ State ---------------------------------------------------------
+
+#[derive(Debug)]
+struct State {
+    bucket_len: Duration,
+    ipv4_len: u8,
+    ipv6_len: u8,
+    max_prefixes: usize,
+
+    /// The totals over the window, by the first address of the aggregate.
+    prefixes: BTreeMap<IpAddr, Counter>,
+
+    /// The totals over the window, by next hop.
+    peers: HashMap<IpAddr, Counter>,
+
+    /// The counts per bucket, oldest first.
+    buckets: VecDeque<Bucket>,
+}
+
+impl State {
+    fn new(
+        window: Duration,
+        ipv4_len: u8,
+        ipv6_len: u8,
+        max_prefixes: usize,
+    ) -> Self {
+        Self {
+            bucket_len: (window / NUM_BUCKETS).max(Duration::from_millis(1)),
+            ipv4_len: ipv4_len.min(32),
+            ipv6_len: ipv6_len.min(128),
+            max_prefixes,
+            prefixes: Default::default(),
+            peers: Default::default(),
+            buckets: Default::default(),
+        }
+    }
+
+    fn aggregate_len(&self, addr: IpAddr) -> u8 {
+        match addr {
+            IpAddr::V4(_) => self.ipv4_len,
+            IpAddr::V6(_) => self.ipv6_len,
+        }
+    }
+
+    /// Makes sure the newest bucket covers `now`, dropping the buckets that
+    /// fell out of the window.
+    fn advance(&mut self, now: Instant) {
+        match self.buckets.back() {
+            Some(last) if now < last.start + self.bucket_len => return,
+            _ => self.buckets.push_back(Bucket::new(now)),
+        }
+        let window = self.bucket_len * NUM_BUCKETS;
+        while let Some(first) = self.buckets.front() {
+            if now.duration_since(first.start) < window {
+                break;
+            }
+            let first = self.buckets.pop_front().unwrap();
+            for (key, counter) in first.prefixes {
+                if let Some(total) = self.prefixes.get_mut(&key) {
+                    total.subtract(counter);
+                    if total.is_zero() {
+                        self.prefixes.remove(&key);
+                    }
+                }
+            }
+            for (key, counter) in first.peers {
+                if let Some(total) = self.peers.get_mut(&key) {
+                    total.subtract(counter);
+                    if total.is_zero() {
+                        self.peers.remove(&key);
+                    }
+                }
+            }
+        }
+    }
+}
+
+//------------ Bucket --------------------------------------------------------
+
+/// The counts of a part of the window.
+#[derive(Debug)]
+struct Bucket {
+    start: Instant,
+    prefixes: HashMap<IpAddr, Counter>,
+    peers: HashMap<IpAddr, Counter>,
+}
+
+impl Bucket {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            prefixes: Default::default(),
+            peers: Default::default(),
+        }
+    }
+}
+
+//------------ Helpers -------------------------------------------------------
+
+/// Returns the first address of the prefix of `addr` with length `len`.
+fn truncate(addr: IpAddr, len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+            Ipv4Addr::from(u32::from(addr) & mask).into()
+        }
+        IpAddr::V6(addr) => {
+            let mask =
+                u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+            Ipv6Addr::from(u128::from(addr) & mask).into()
+        }
+    }
+}
+
+/// Returns the last address of the prefix of `addr` with length `len`.
+fn last_addr(addr: IpAddr, len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+            Ipv4Addr::from(u32::from(addr) | !mask).into()
+        }
+        IpAddr::V6(addr) => {
+            let mask =
+                u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+            Ipv6Addr::from(u128::from(addr) | !mask).into()
+        }
+    }
+}
+
+//------------ TrafficCounterSets --------------------------------------------
+
+/// The traffic counters of the flow-in units, by unit name.
+///
+/// Counters are created empty by whichever unit asks for them first, so the
+/// units using them do not depend on the order in which units start.
+#[derive(Default)]
+pub struct TrafficCounterSets {
+    sets: Mutex<HashMap<String, Arc<TrafficCounters>>>,
+}
+
+impl TrafficCounterSets {
+    /// Returns the counters of the flow-in unit named `name`.
+    pub fn get(&self, name: &str) -> Arc<TrafficCounters> {
+        self.sets
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+impl fmt::Debug for TrafficCounterSets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sets = self.sets.lock().unwrap();
+        f.debug_list().entries(sets.keys()).finish()
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn addr(s: &str) -> Option<IpAddr> {
+        Some(IpAddr::from_str(s).unwrap())
+    }
+
+    fn prefix(counters: &TrafficCounters, s: &str) -> Counter {
+        counters.prefix(&Prefix::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn traffic_is_aggregated_per_prefix_and_peer() {
+        let counters = TrafficCounters::default();
+        let now = Instant::now();
+        let nh = addr("192.0.2.1");
+        counters.record_at(
+            now,
+            addr("198.51.100.1"),
+            nh,
+            Counter::new(10, 1),
+        );
+        counters.record_at(now, addr("198.51.100.2"), nh, Counter::new(5, 1));
+        counters.record_at(
+            now,
+            addr("198.51.101.1"),
+            None,
+            Counter::new(7, 1),
+        );
+        counters.record_at(
+            now,
+            addr("2001:db8::1"),
+            None,
+            Counter::new(3, 1),
+        );
+
+        // The aggregate, a more specific and a less specific prefix.
+        assert_eq!(prefix(&counters, "198.51.100.0/24"), Counter::new(15, 2));
+        assert_eq!(prefix(&counters, "198.51.100.0/25"), Counter::new(15, 2));
+        assert_eq!(prefix(&counters, "198.51.100.0/23"), Counter::new(22, 3));
+        assert_eq!(prefix(&counters, "198.51.102.0/23"), Counter::default());
+        assert_eq!(prefix(&counters, "0.0.0.0/0"), Counter::new(22, 3));
+        assert_eq!(prefix(&counters, "2001:db8::/32"), Counter::new(3, 1));
+        assert_eq!(prefix(&counters, "::/0"), Counter::new(3, 1));
+
+        assert_eq!(counters.peer(nh.unwrap()), Counter::new(15, 2));
+        assert_eq!(counters.counts(), (3, 1));
+    }
+
+    #[test]
+    fn counts_fall_out_of_the_window() {
+        let counters =
+            TrafficCounters::new(Duration::from_secs(10), 24, 48, 2);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let nh = addr("192.0.2.1");
+        counters.record_at(
+            at(0),
+            addr("198.51.100.1"),
+            nh,
+            Counter::new(10, 1),
+        );
+        counters.record_at(
+            at(5),
+            addr("198.51.100.1"),
+            nh,
+            Counter::new(20, 2),
+        );
+        assert_eq!(prefix(&counters, "198.51.100.0/24"), Counter::new(30, 3));
+
+        // The prefix limit only applies to new prefixes.
+        counters.record_at(
+            at(5),
+            addr("203.0.113.1"),
+            None,
+            Counter::new(1, 1),
+        );
+        assert!(!counters.record_at(
+            at(5),
+            addr("2001:db8::1"),
+            None,
+            Counter::new(1, 1)
+        ));
+        assert!(counters.record_at(
+            at(5),
+            addr("203.0.113.2"),
+            None,
+            Counter::new(1, 1)
+        ));
+
+        // The first bucket is dropped once it is a whole window old.
+        counters.state.write().unwrap().advance(at(10));
+        assert_eq!(prefix(&counters, "198.51.100.0/24"), Counter::new(20, 2));
+        assert_eq!(counters.peer(nh.unwrap()), Counter::new(20, 2));
+
+        counters.state.write().unwrap().advance(at(16));
+        assert_eq!(prefix(&counters, "198.51.100.0/24"), Counter::default());
+        assert_eq!(counters.counts(), (0, 0));
+    }
+}
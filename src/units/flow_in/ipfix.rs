@@ -0,0 +1,402 @@
+//! Parsing IPFIX messages.
+//!
+//! Data records are parsed using the templates previously received from
+//! the same exporter and observation domain. Records of templates not seen
+//! yet are skipped. The sampling interval is taken from the data record
+//! itself, or else from the options data records of the domain.
+//!
+//! See [RFC 7011](https://www.rfc-editor.org/rfc/rfc7011).
+
+use std::{collections::HashMap, net::IpAddr};
+
+use super::{
+    counters::{Counter, Flow},
+    wire::{ipv4, ipv6, uint, ParseError, Reader},
+};
+
+/// The version number in the first two bytes of an IPFIX message.
+pub const VERSION: u16 = 10;
+
+// Set IDs.
+const TEMPLATE_SET: u16 = 2;
+const OPTIONS_TEMPLATE_SET: u16 = 3;
+const MIN_DATA_SET: u16 = 256;
+
+/// The field length signalling a variable length field.
+const VARIABLE_LENGTH: u16 = 0xffff;
+
+// Information elements.
+const OCTET_DELTA_COUNT: u16 = 1;
+const PACKET_DELTA_COUNT: u16 = 2;
+const SOURCE_IPV4_ADDRESS: u16 = 8;
+const DESTINATION_IPV4_ADDRESS: u16 = 12;
+const BGP_NEXT_HOP_IPV4_ADDRESS: u16 = 18;
+const SOURCE_IPV6_ADDRESS: u16 = 27;
+const DESTINATION_IPV6_ADDRESS: u16 = 28;
+const SAMPLING_INTERVAL: u16 = 34;
+const BGP_NEXT_HOP_IPV6_ADDRESS: u16 = 63;
+const OCTET_TOTAL_COUNT: u16 = 85;
+const PACKET_TOTAL_COUNT: u16 = 86;
+const SAMPLING_PACKET_INTERVAL: u16 = 305;
+
+//------------ Decoder -------------------------------------------------------
+
+/// Parses IPFIX messages, keeping the templates of the exporters.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// The templates by exporter, observation domain and template ID.
+    templates: HashMap<(IpAddr, u32, u16), Template>,
+
+    /// The sampling interval by exporter and observation domain.
+    sampling: HashMap<(IpAddr, u32), u64>,
+}
+
+impl Decoder {
+    /// Parses a message from `exporter`, appending its flows to `flows`.
+    pub fn parse(
+        &mut self,
+        exporter: IpAddr,
+        data: &[u8],
+        flows: &mut Vec<Flow>,
+    ) -> Result<(), ParseError> {
+        let mut reader = Reader::new(data);
+        if reader.u16()? != VERSION {
+            return Err(ParseError("unsupported IPFIX version"));
+        }
+        let len = usize::from(reader.u16()?);
+        let mut reader = Reader::new(
+            data.get(..len).ok_or(ParseError("truncated datagram"))?,
+        );
+        // The version, length, export time and sequence number.
+        reader.take(12)?;
+        let domain = reader.u32()?;
+
+        while !reader.is_empty() {
+            let set_id = reader.u16()?;
+            let set_len = usize::from(reader.u16()?);
+            let mut set = reader.sub(
+                set_len
+                    .checked_sub(4)
+                    .ok_or(ParseError("invalid set length"))?,
+            )?;
+            match set_id {
+                TEMPLATE_SET | OPTIONS_TEMPLATE_SET => {
+                    self.templates_set(
+                        exporter,
+                        domain,
+                        set_id == OPTIONS_TEMPLATE_SET,
+                        &mut set,
+                    )?;
+                }
+                id if id >= MIN_DATA_SET => {
+                    self.data_set(exporter, domain, id, &mut set, flows)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn templates_set(
+        &mut self,
+        exporter: IpAddr,
+        domain: u32,
+        options: bool,
+        set: &mut Reader,
+    ) -> Result<(), ParseError> {
+        // Anything shorter than a template header is padding.
+        while set.remaining() >= 4 {
+            let id = set.u16()?;
+            let field_count = set.u16()?;
+            if field_count == 0 {
+                // A template withdrawal, possibly of all templates.
+                if id == TEMPLATE_SET || id == OPTIONS_TEMPLATE_SET {
+                    self.templates.retain(|key, _| {
+                        (key.0, key.1) != (exporter, domain)
+                    });
+                } else {
+                    self.templates.remove(&(exporter, domain, id));
+                }
+                continue;
+            }
+            if options {
+                // The scope field count.
+                set.u16()?;
+            }
+            let mut fields = Vec::with_capacity(field_count.into());
+            for _ in 0..field_count {
+                let id = set.u16()?;
+                let len = set.u16()?;
+                if id & 0x8000 != 0 {
+                    // The enterprise number of an enterprise-specific
+                    // element, which we don't use.
+                    set.u32()?;
+                    fields.push(Field { id: None, len });
+                } else {
+                    fields.push(Field { id: Some(id), len });
+                }
+            }
+            self.templates
+                .insert((exporter, domain, id), Template { fields, options });
+        }
+        Ok(())
+    }
+
+    fn data_set(
+        &mut self,
+        exporter: IpAddr,
+        domain: u32,
+        id: u16,
+        set: &mut Reader,
+        flows: &mut Vec<Flow>,
+    ) -> Result<(), ParseError> {
+        let Some(template) = self.templates.get(&(exporter, domain, id))
+        else {
+            return Ok(());
+        };
+        let min_len = template.min_len();
+        if min_len == 0 {
+            return Ok(());
+        }
+        let mut records = Vec::new();
+        while set.remaining() >= min_len {
+            records.push(template.record(set)?);
+        }
+
+        if template.options {
+            if let Some(sampling) =
+                records.iter().rev().find_map(|record| record.sampling)
+            {
+                self.sampling.insert((exporter, domain), sampling.max(1));
+            }
+            return Ok(());
+        }
+        let domain_sampling =
+            self.sampling.get(&(exporter, domain)).copied().unwrap_or(1);
+        for record in records {
+            if record.flow.dst.is_none() && record.flow.src.is_none() {
+                continue;
+            }
+            let sampling = record.sampling.unwrap_or(domain_sampling).max(1);
+            let mut flow = record.flow;
+            flow.counter = Counter::new(
+                flow.counter.bytes.saturating_mul(sampling),
+                flow.counter.packets.saturating_mul(sampling),
+            );
+            flows.push(flow);
+        }
+        Ok(())
+    }
+}
+
+//------------ Template ------------------------------------------------------
+
+#[derive(Debug)]
+struct Template {
+    fields: Vec<Field>,
+    options: bool,
+}
+
+#[derive(Debug)]
+struct Field {
+    /// The information element, if not enterprise-specific.
+    id: Option<u16>,
+    len: u16,
+}
+
+/// The values of a data record.
+struct Record {
+    flow: Flow,
+    sampling: Option<u64>,
+}
+
+impl Template {
+    /// Returns the shortest length of a record.
+    fn min_len(&self) -> usize {
+        self.fields
+            .iter()
+            .map(|field| match field.len {
+                VARIABLE_LENGTH => 1,
+                len => usize::from(len),
+            })
+            .sum()
+    }
+
+    fn record(&self, reader: &mut Reader) -> Result<Record, ParseError> {
+        let mut record = Record {
+            flow: Flow::default(),
+            sampling: None,
+        };
+        for field in &self.fields {
+            let len = match field.len {
+                VARIABLE_LENGTH => match reader.u8()? {
+                    255 => usize::from(reader.u16()?),
+                    len => usize::from(len),
+                },
+                len => usize::from(len),
+            };
+            let value = reader.take(len)?;
+            let Some(id) = field.id else { continue };
+            let flow = &mut record.flow;
+            match (id, len) {
+                (OCTET_DELTA_COUNT | OCTET_TOTAL_COUNT, _) => {
+                    flow.counter.bytes = uint(value)
+                }
+                (PACKET_DELTA_COUNT | PACKET_TOTAL_COUNT, _) => {
+                    flow.counter.packets = uint(value)
+                }
+                (SOURCE_IPV4_ADDRESS, 4) => flow.src = Some(ipv4(value)),
+                (DESTINATION_IPV4_ADDRESS, 4) => flow.dst = Some(ipv4(value)),
+                (SOURCE_IPV6_ADDRESS, 16) => flow.src = Some(ipv6(value)),
+                (DESTINATION_IPV6_ADDRESS, 16) => {
+                    flow.dst = Some(ipv6(value))
+                }
+                (BGP_NEXT_HOP_IPV4_ADDRESS, 4) => {
+                    flow.next_hop = Some(ipv4(value)).filter(is_specified)
+                }
+                (BGP_NEXT_HOP_IPV6_ADDRESS, 16) => {
+                    flow.next_hop = Some(ipv6(value)).filter(is_specified)
+                }
+                (SAMPLING_INTERVAL | SAMPLING_PACKET_INTERVAL, _) => {
+                    record.sampling = Some(uint(value))
+                }
+                _ => {}
+            }
+        }
+        Ok(record)
+    }
+}
+
+/// Exporters use the unspecified address for traffic without a next hop.
+fn is_specified(addr: &IpAddr) -> bool {
+    !addr.is_unspecified()
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    /// Returns an IPFIX message of observation domain 1 with `sets`.
+    pub fn message(sets: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (id, set) in sets {
+            body.extend_from_slice(&id.to_be_bytes());
+            body.extend_from_slice(&(set.len() as u16 + 4).to_be_bytes());
+            body.extend_from_slice(set);
+        }
+        let mut data = Vec::new();
+        data.extend_from_slice(&VERSION.to_be_bytes());
+        data.extend_from_slice(&(body.len() as u16 + 16).to_be_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&body);
+        data
+    }
+
+    fn words(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    /// A template set with template 256: the destination IPv4 address,
+    /// a four byte octet count, a two byte packet count, an
+    /// enterprise-specific variable length field and the BGP next hop.
+    pub fn template() -> (u16, Vec<u8>) {
+        let mut set = words(&[256, 5, 12, 4, 1, 4, 2, 2]);
+        set.extend_from_slice(&words(&[0x8001, VARIABLE_LENGTH]));
+        set.extend_from_slice(&29305u32.to_be_bytes());
+        set.extend_from_slice(&words(&[18, 4]));
+        (TEMPLATE_SET, set)
+    }
+
+    /// A data set of template 256 with records to 198.51.100.20 and
+    /// 198.51.100.21, both via 203.0.113.1.
+    pub fn data() -> (u16, Vec<u8>) {
+        let mut set = Vec::new();
+        set.extend_from_slice(&[198, 51, 100, 20, 0, 0, 0x05, 0xdc, 0, 1]);
+        set.extend_from_slice(&[3, 1, 2, 3, 203, 0, 113, 1]);
+        set.extend_from_slice(&[198, 51, 100, 21, 0, 0, 0, 100, 0, 2]);
+        set.extend_from_slice(&[255, 0, 1, 9, 203, 0, 113, 1]);
+        // Padding.
+        set.extend_from_slice(&[0, 0]);
+        (256, set)
+    }
+
+    fn addr(s: &str) -> Option<IpAddr> {
+        Some(IpAddr::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn data_records_are_parsed_with_their_template() {
+        let exporter = IpAddr::from_str("192.0.2.1").unwrap();
+        let mut decoder = Decoder::default();
+        let mut flows = Vec::new();
+
+        // Data before its template is skipped.
+        decoder
+            .parse(exporter, &message(&[data()]), &mut flows)
+            .unwrap();
+        assert!(flows.is_empty());
+
+        decoder
+            .parse(exporter, &message(&[template(), data()]), &mut flows)
+            .unwrap();
+        let flow = |dst, bytes, packets| Flow {
+            src: None,
+            dst: addr(dst),
+            next_hop: addr("203.0.113.1"),
+            counter: Counter::new(bytes, packets),
+        };
+        assert_eq!(
+            flows,
+            [
+                flow("198.51.100.20", 1500, 1),
+                flow("198.51.100.21", 100, 2)
+            ]
+        );
+
+        // The sampling interval of an options template applies to the
+        // other records of the domain.
+        let mut options = words(&[257, 2, 1, 149, 4, 305, 4]);
+        options.extend_from_slice(&[0; 2]);
+        let sampling = (257, [0, 0, 0, 1, 0, 0, 0, 10].to_vec());
+        flows.clear();
+        decoder
+            .parse(
+                exporter,
+                &message(&[
+                    (OPTIONS_TEMPLATE_SET, options),
+                    sampling,
+                    data(),
+                ]),
+                &mut flows,
+            )
+            .unwrap();
+        assert_eq!(flows[0].counter, Counter::new(15000, 10));
+
+        // The templates are per exporter.
+        flows.clear();
+        let other = IpAddr::from_str("192.0.2.2").unwrap();
+        decoder
+            .parse(other, &message(&[data()]), &mut flows)
+            .unwrap();
+        assert!(flows.is_empty());
+
+        // Withdrawn templates are forgotten.
+        decoder
+            .parse(
+                exporter,
+                &message(&[(TEMPLATE_SET, words(&[256, 0])), data()]),
+                &mut flows,
+            )
+            .unwrap();
+        assert!(flows.is_empty());
+
+        assert!(decoder
+            .parse(exporter, &message(&[(256, vec![])])[..19], &mut flows)
+            .is_err());
+    }
+}
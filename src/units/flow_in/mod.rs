@@ -0,0 +1,7 @@
+pub(crate) mod counters;
+mod ipfix;
+mod sflow;
+pub mod unit;
+mod wire;
+
+pub use unit::FlowIn;
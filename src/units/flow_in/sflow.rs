@@ -0,0 +1,245 @@
+//! Parsing sFlow version 5 datagrams.
+//!
+//! Only flow samples are used. Their addresses are taken from the sampled
+//! IPv4 or IPv6 records or else from the raw packet header, and their next
+//! hop from the extended gateway record. Counter samples are ignored.
+//!
+//! See <https://sflow.org/sflow_version_5.txt>.
+
+use std::net::IpAddr;
+
+use super::{
+    counters::{Counter, Flow},
+    wire::{ipv4, ipv6, ParseError, Reader},
+};
+
+/// The version number in the first four bytes of an sFlow v5 datagram.
+pub const VERSION: u32 = 5;
+
+// Sample formats.
+const FLOW_SAMPLE: u32 = 1;
+const EXPANDED_FLOW_SAMPLE: u32 = 3;
+
+// Flow record formats.
+const RAW_PACKET_HEADER: u32 = 1;
+const SAMPLED_IPV4: u32 = 3;
+const SAMPLED_IPV6: u32 = 4;
+const EXTENDED_GATEWAY: u32 = 1003;
+
+// Header protocols of the raw packet header.
+const HEADER_ETHERNET: u32 = 1;
+const HEADER_IPV4: u32 = 11;
+const HEADER_IPV6: u32 = 12;
+
+/// Parses an sFlow datagram, appending its flows to `flows`.
+pub fn parse(data: &[u8], flows: &mut Vec<Flow>) -> Result<(), ParseError> {
+    let mut reader = Reader::new(data);
+    if reader.u32()? != VERSION {
+        return Err(ParseError("unsupported sFlow version"));
+    }
+    // The agent address, sub agent ID, sequence number and uptime.
+    address(&mut reader)?;
+    reader.take(12)?;
+    let num_samples = reader.u32()?;
+    for _ in 0..num_samples {
+        let format = reader.u32()?;
+        let len = reader.u32()? as usize;
+        let mut sample = reader.sub(len)?;
+        let expanded = match format {
+            FLOW_SAMPLE => false,
+            EXPANDED_FLOW_SAMPLE => true,
+            _ => continue,
+        };
+        if let Some(flow) = flow_sample(&mut sample, expanded)? {
+            flows.push(flow);
+        }
+    }
+    Ok(())
+}
+
+/// Parses a flow sample, returning its flow if it contains any addresses.
+fn flow_sample(
+    reader: &mut Reader,
+    expanded: bool,
+) -> Result<Option<Flow>, ParseError> {
+    // The sequence number and source ID, which is split in two in the
+    // expanded format.
+    reader.take(if expanded { 12 } else { 8 })?;
+    let sampling_rate = u64::from(reader.u32()?.max(1));
+    // The sample pool, drops and input and output interfaces, which are
+    // also split in the expanded format.
+    reader.take(if expanded { 24 } else { 16 })?;
+
+    let mut flow = Flow::default();
+    let mut len = None;
+    let mut header = None;
+    let num_records = reader.u32()?;
+    for _ in 0..num_records {
+        let format = reader.u32()?;
+        let record_len = reader.u32()? as usize;
+        let mut record = reader.sub(record_len)?;
+        match format {
+            RAW_PACKET_HEADER => {
+                let protocol = record.u32()?;
+                let frame_len = record.u32()?;
+                let stripped = record.u32()?;
+                let header_len = record.u32()? as usize;
+                let bytes = record.take(header_len)?;
+                len.get_or_insert(frame_len.saturating_sub(stripped));
+                header = packet_header(protocol, bytes);
+            }
+            SAMPLED_IPV4 => {
+                len = Some(record.u32()?);
+                record.u32()?;
+                flow.src = Some(record.ipv4()?);
+                flow.dst = Some(record.ipv4()?);
+            }
+            SAMPLED_IPV6 => {
+                len = Some(record.u32()?);
+                record.u32()?;
+                flow.src = Some(record.ipv6()?);
+                flow.dst = Some(record.ipv6()?);
+            }
+            EXTENDED_GATEWAY => {
+                flow.next_hop = address(&mut record)?;
+            }
+            _ => {}
+        }
+    }
+
+    if flow.dst.is_none() {
+        let Some((src, dst)) = header else {
+            return Ok(None);
+        };
+        flow.src = Some(src);
+        flow.dst = Some(dst);
+    }
+    flow.counter = Counter::new(
+        u64::from(len.unwrap_or_default()) * sampling_rate,
+        sampling_rate,
+    );
+    Ok(Some(flow))
+}
+
+/// Reads an address preceded by its type, returning `None` if the type is
+/// unknown.
+fn address(reader: &mut Reader) -> Result<Option<IpAddr>, ParseError> {
+    match reader.u32()? {
+        1 => reader.ipv4().map(Some),
+        2 => reader.ipv6().map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Returns the source and destination address of a sampled packet header.
+fn packet_header(protocol: u32, header: &[u8]) -> Option<(IpAddr, IpAddr)> {
+    match protocol {
+        HEADER_ETHERNET => {
+            let mut ethertype =
+                u16::from_be_bytes(header.get(12..14)?.try_into().unwrap());
+            let mut payload = header.get(14..)?;
+            // Skip any VLAN tags.
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                ethertype = u16::from_be_bytes(
+                    payload.get(2..4)?.try_into().unwrap(),
+                );
+                payload = payload.get(4..)?;
+            }
+            match ethertype {
+                0x0800 => ipv4_header(payload),
+                0x86dd => ipv6_header(payload),
+                _ => None,
+            }
+        }
+        HEADER_IPV4 => ipv4_header(header),
+        HEADER_IPV6 => ipv6_header(header),
+        _ => None,
+    }
+}
+
+fn ipv4_header(header: &[u8]) -> Option<(IpAddr, IpAddr)> {
+    Some((ipv4(header.get(12..16)?), ipv4(header.get(16..20)?)))
+}
+
+fn ipv6_header(header: &[u8]) -> Option<(IpAddr, IpAddr)> {
+    Some((ipv6(header.get(8..24)?), ipv6(header.get(24..40)?)))
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    /// An sFlow datagram with a flow sample containing a raw Ethernet
+    /// header with a VLAN tag, from 192.0.2.10 to 198.51.100.20, and an
+    /// extended gateway record with next hop 203.0.113.1, as well as a
+    /// counter sample.
+    pub fn datagram() -> Vec<u8> {
+        let mut header = vec![0; 12];
+        header.extend_from_slice(&[0x81, 0x00, 0x00, 0x64, 0x08, 0x00]);
+        let mut ip = vec![0x45, 0, 0, 100, 0, 0, 0, 0, 64, 6, 0, 0];
+        ip.extend_from_slice(&[192, 0, 2, 10, 198, 51, 100, 20]);
+        header.extend_from_slice(&ip);
+        header.extend_from_slice(&[0; 2]);
+
+        let mut raw = Vec::new();
+        for field in [HEADER_ETHERNET, 1500, 4, header.len() as u32] {
+            raw.extend_from_slice(&field.to_be_bytes());
+        }
+        raw.extend_from_slice(&header);
+
+        let mut gateway = Vec::new();
+        for field in [1u32, 0xcb007101, 65000, 65001, 65002, 0, 0, 0] {
+            gateway.extend_from_slice(&field.to_be_bytes());
+        }
+
+        let mut sample = Vec::new();
+        for field in [1u32, 7, 512, 1024, 0, 1, 2, 2] {
+            sample.extend_from_slice(&field.to_be_bytes());
+        }
+        for (format, record) in
+            [(RAW_PACKET_HEADER, raw), (EXTENDED_GATEWAY, gateway)]
+        {
+            sample.extend_from_slice(&format.to_be_bytes());
+            sample.extend_from_slice(&(record.len() as u32).to_be_bytes());
+            sample.extend_from_slice(&record);
+        }
+
+        let mut data = Vec::new();
+        for field in [VERSION, 1, 0xc0000201, 0, 1, 1000, 2] {
+            data.extend_from_slice(&field.to_be_bytes());
+        }
+        for (format, sample) in [(2, vec![0; 12]), (FLOW_SAMPLE, sample)] {
+            data.extend_from_slice(&format.to_be_bytes());
+            data.extend_from_slice(&(sample.len() as u32).to_be_bytes());
+            data.extend_from_slice(&sample);
+        }
+        data
+    }
+
+    fn addr(s: &str) -> Option<IpAddr> {
+        Some(IpAddr::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn flow_samples_are_parsed() {
+        let mut flows = Vec::new();
+        parse(&datagram(), &mut flows).unwrap();
+        assert_eq!(
+            flows,
+            [Flow {
+                src: addr("192.0.2.10"),
+                dst: addr("198.51.100.20"),
+                next_hop: addr("203.0.113.1"),
+                counter: Counter::new(1496 * 512, 512),
+            }]
+        );
+
+        let data = datagram();
+        assert!(parse(&data[..data.len() - 1], &mut flows).is_err());
+        assert!(parse(&[0, 0, 0, 4], &mut flows).is_err());
+    }
+}
@@ -0,0 +1,469 @@
+//! Aggregating traffic from flow telemetry.
+//!
+//! This unit receives sFlow version 5 and IPFIX datagrams over UDP and
+//! counts the sampled traffic per prefix and per BGP next hop over a
+//! sliding window, as described in the [`counters`] module. The protocol of
+//! each datagram is recognized by its version number, so sFlow agents and
+//! IPFIX exporters can send to the same port.
+//!
+//! The unit produces no routes itself. Instead, other units name it in
+//! their `traffic` setting to use its counts: their roto filters via the
+//! `traffic` variable and RIBs by including the traffic of the queried
+//! prefix in their query results. This allows policies such as only
+//! alerting on hijacks of prefixes that actually carry traffic.
+//!
+//! [`counters`]: super::counters
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::{debug, error, warn};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::net::UdpSocket;
+
+use crate::{
+    comms::{Gate, GateMetrics, GateStatus, Terminated},
+    manager::{Component, WaitPoint},
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+use super::{
+    counters::{Flow, TrafficCounters},
+    ipfix, sflow,
+    wire::ParseError,
+};
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct FlowIn {
+    /// The addresses to listen on for sFlow and IPFIX datagrams.
+    pub listen: Vec<SocketAddr>,
+
+    /// The period over which traffic is counted.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "FlowIn::default_window_secs")]
+    pub window_secs: Duration,
+
+    /// The length of the prefixes IPv4 traffic is aggregated into.
+    #[serde(default = "FlowIn::default_ipv4_prefix_len")]
+    pub ipv4_prefix_len: u8,
+
+    /// The length of the prefixes IPv6 traffic is aggregated into.
+    #[serde(default = "FlowIn::default_ipv6_prefix_len")]
+    pub ipv6_prefix_len: u8,
+
+    /// Which addresses of a flow its traffic is counted for.
+    #[serde(default)]
+    pub direction: Direction,
+
+    /// The most prefixes to count traffic for. Traffic of further prefixes
+    /// is dropped until prefixes fall out of the window.
+    #[serde(default = "FlowIn::default_max_prefixes")]
+    pub max_prefixes: usize,
+}
+
+/// Which addresses of a flow its traffic is counted for.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Count traffic towards the destination address.
+    #[default]
+    Destination,
+
+    /// Count traffic from the source address.
+    Source,
+
+    /// Count traffic for both addresses.
+    Both,
+}
+
+impl Direction {
+    /// Counts `flow`, returning whether none of it was dropped.
+    fn record(self, counters: &TrafficCounters, flow: &Flow) -> bool {
+        match self {
+            Direction::Destination => {
+                counters.record(flow.dst, flow.next_hop, flow.counter)
+            }
+            Direction::Source => {
+                counters.record(flow.src, flow.next_hop, flow.counter)
+            }
+            Direction::Both => {
+                let dst =
+                    counters.record(flow.dst, flow.next_hop, flow.counter);
+                let src = counters.record(flow.src, None, flow.counter);
+                dst && src
+            }
+        }
+    }
+}
+
+impl FlowIn {
+    fn default_window_secs() -> Duration {
+        Duration::from_secs(300)
+    }
+
+    fn default_ipv4_prefix_len() -> u8 {
+        24
+    }
+
+    fn default_ipv6_prefix_len() -> u8 {
+        48
+    }
+
+    fn default_max_prefixes() -> usize {
+        1_000_000
+    }
+
+    pub async fn run(
+        self,
+        mut component: Component,
+        gate: Gate,
+        mut waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        if self.listen.is_empty() {
+            error!(
+                "Unit {}: at least one 'listen' address must be configured",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+        if self.ipv4_prefix_len > 32 || self.ipv6_prefix_len > 128 {
+            error!(
+                "Unit {}: invalid aggregation prefix length",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+        if self.window_secs.is_zero() {
+            error!("Unit {}: 'window_secs' must not be 0", component.name());
+            return Err(Terminated);
+        }
+
+        let metrics = Arc::new(FlowInMetrics::new(&gate));
+        component.register_metrics(metrics.clone());
+
+        let counters = component.traffic_counters().get(component.name());
+        counters.configure(
+            self.window_secs,
+            self.ipv4_prefix_len,
+            self.ipv6_prefix_len,
+            self.max_prefixes,
+        );
+
+        gate.process_until(waitpoint.ready()).await?;
+        waitpoint.running().await;
+
+        let mut sockets = Vec::new();
+        for addr in &self.listen {
+            match UdpSocket::bind(addr).await {
+                Ok(socket) => sockets.push(socket),
+                Err(err) => {
+                    error!(
+                        "Unit {}: cannot listen on {addr}: {err}",
+                        component.name()
+                    );
+                    return Err(Terminated);
+                }
+            }
+        }
+        let mut tasks = sockets
+            .into_iter()
+            .map(|socket| {
+                tokio::spawn(Self::receive(
+                    socket,
+                    self.direction,
+                    counters.clone(),
+                    metrics.clone(),
+                ))
+            })
+            .collect::<Vec<_>>();
+        tasks.push(tokio::spawn(Self::expire(
+            self.window_secs,
+            counters,
+            metrics,
+        )));
+
+        // The datagrams are received in their own tasks, so here only the
+        // gate needs to be processed.
+        let res = loop {
+            match gate.process().await {
+                Ok(GateStatus::ReportLinks { report }) => {
+                    report.declare_source();
+                }
+                Ok(GateStatus::Reconfiguring { .. }) => {
+                    warn!(
+                        "Unit {}: reconfiguring flow-in requires a restart",
+                        component.name()
+                    );
+                }
+                Ok(_) => {}
+                Err(Terminated) => break Err(Terminated),
+            }
+        };
+        tasks.iter().for_each(|task| task.abort());
+        res
+    }
+
+    /// Count the flows of the datagrams received on `socket`.
+    async fn receive(
+        socket: UdpSocket,
+        direction: Direction,
+        counters: Arc<TrafficCounters>,
+        metrics: Arc<FlowInMetrics>,
+    ) {
+        let mut buf = vec![0; 65535];
+        let mut ipfix = ipfix::Decoder::default();
+        let mut flows = Vec::new();
+        loop {
+            let (len, exporter) = match socket.recv_from(&mut buf).await {
+                Ok(res) => res,
+                Err(err) => {
+                    warn!("Failed to receive flow datagram: {err}");
+                    continue;
+                }
+            };
+            metrics.num_datagrams.fetch_add(1, SeqCst);
+            flows.clear();
+            if let Err(err) =
+                decode(&buf[..len], exporter.ip(), &mut ipfix, &mut flows)
+            {
+                metrics.num_invalid_datagrams.fetch_add(1, SeqCst);
+                debug!("Ignoring datagram from {exporter}: {err}");
+                continue;
+            }
+            metrics.num_flows.fetch_add(flows.len(), SeqCst);
+            for flow in &flows {
+                if !direction.record(&counters, flow) {
+                    metrics.num_dropped_flows.fetch_add(1, SeqCst);
+                }
+            }
+        }
+    }
+
+    /// Regularly drop the traffic that fell out of the window.
+    async fn expire(
+        window: Duration,
+        counters: Arc<TrafficCounters>,
+        metrics: Arc<FlowInMetrics>,
+    ) {
+        let period = (window / 10).max(Duration::from_secs(1));
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            counters.expire();
+            let (prefixes, peers) = counters.counts();
+            metrics.num_prefixes.store(prefixes, SeqCst);
+            metrics.num_peers.store(peers, SeqCst);
+        }
+    }
+}
+
+/// Parses an sFlow or IPFIX datagram, recognized by its version number.
+fn decode(
+    data: &[u8],
+    exporter: IpAddr,
+    ipfix: &mut ipfix::Decoder,
+    flows: &mut Vec<Flow>,
+) -> Result<(), ParseError> {
+    if data.starts_with(&sflow::VERSION.to_be_bytes()) {
+        sflow::parse(data, flows)
+    } else if data.starts_with(&ipfix::VERSION.to_be_bytes()) {
+        ipfix.parse(exporter, data, flows)
+    } else {
+        Err(ParseError("neither an sFlow v5 nor an IPFIX datagram"))
+    }
+}
+
+//------------ FlowInMetrics -------------------------------------------------
+
+#[derive(Debug, Default)]
+struct FlowInMetrics {
+    gate: Arc<GateMetrics>,
+    num_datagrams: AtomicUsize,
+    num_invalid_datagrams: AtomicUsize,
+    num_flows: AtomicUsize,
+    num_dropped_flows: AtomicUsize,
+    num_prefixes: AtomicUsize,
+    num_peers: AtomicUsize,
+}
+
+impl FlowInMetrics {
+    fn new(gate: &Gate) -> Self {
+        Self {
+            gate: gate.metrics(),
+            ..Default::default()
+        }
+    }
+
+    const NUM_DATAGRAMS_METRIC: Metric = Metric::new(
+        "flow_in_num_datagrams",
+        "the number of sFlow and IPFIX datagrams received",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_INVALID_DATAGRAMS_METRIC: Metric = Metric::new(
+        "flow_in_num_invalid_datagrams",
+        "the number of datagrams that could not be parsed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_FLOWS_METRIC: Metric = Metric::new(
+        "flow_in_num_flows",
+        "the number of sampled flows received",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_DROPPED_FLOWS_METRIC: Metric = Metric::new(
+        "flow_in_num_dropped_flows",
+        "the number of flows not counted because of the prefix limit",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_PREFIXES_METRIC: Metric = Metric::new(
+        "flow_in_num_prefixes",
+        "the number of prefixes with traffic in the window",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const NUM_PEERS_METRIC: Metric = Metric::new(
+        "flow_in_num_peers",
+        "the number of BGP next hops with traffic in the window",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for FlowInMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        self.gate.append(unit_name, target);
+        target.append_simple(
+            &Self::NUM_DATAGRAMS_METRIC,
+            Some(unit_name),
+            self.num_datagrams.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_INVALID_DATAGRAMS_METRIC,
+            Some(unit_name),
+            self.num_invalid_datagrams.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_FLOWS_METRIC,
+            Some(unit_name),
+            self.num_flows.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_DROPPED_FLOWS_METRIC,
+            Some(unit_name),
+            self.num_dropped_flows.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_PREFIXES_METRIC,
+            Some(unit_name),
+            self.num_prefixes.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_PEERS_METRIC,
+            Some(unit_name),
+            self.num_peers.load(SeqCst),
+        );
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use inetnum::addr::Prefix;
+
+    use super::{
+        super::{counters::Counter, ipfix::tests as ipfix_tests, sflow},
+        *,
+    };
+
+    #[test]
+    fn config_deserialization() {
+        let toml = r#"
+        listen = ["0.0.0.0:6343", "[::]:4739"]
+        window_secs = 60
+        ipv6_prefix_len = 32
+        direction = "both"
+        "#;
+
+        let config: FlowIn = toml::from_str(toml).unwrap();
+        assert_eq!(config.listen.len(), 2);
+        assert_eq!(config.window_secs, Duration::from_secs(60));
+        assert_eq!(config.ipv4_prefix_len, 24);
+        assert_eq!(config.ipv6_prefix_len, 32);
+        assert_eq!(config.direction, Direction::Both);
+        assert_eq!(config.max_prefixes, 1_000_000);
+
+        assert!(
+            toml::from_str::<FlowIn>(r#"listen = "0.0.0.0:6343""#).is_err()
+        );
+        assert!(toml::from_str::<FlowIn>(
+            r#"
+            listen = ["0.0.0.0:6343"]
+            direction = "sideways"
+            "#
+        )
+        .is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn datagrams_are_counted() {
+        let (gate, _agent) = Gate::new(0);
+        let metrics = Arc::new(FlowInMetrics::new(&gate));
+        let counters = Arc::new(TrafficCounters::default());
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let task = tokio::spawn(FlowIn::receive(
+            socket,
+            Direction::Destination,
+            counters.clone(),
+            metrics.clone(),
+        ));
+
+        let exporter = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        exporter.connect(addr).await.unwrap();
+        exporter.send(&sflow::tests::datagram()).await.unwrap();
+        exporter
+            .send(&ipfix_tests::message(&[
+                ipfix_tests::template(),
+                ipfix_tests::data(),
+            ]))
+            .await
+            .unwrap();
+        exporter.send(b"hello").await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while metrics.num_datagrams.load(SeqCst) < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        task.abort();
+
+        assert_eq!(metrics.num_invalid_datagrams.load(SeqCst), 1);
+        assert_eq!(metrics.num_flows.load(SeqCst), 3);
+        assert_eq!(
+            counters.prefix(&Prefix::from_str("198.51.100.0/24").unwrap()),
+            Counter::new(1496 * 512 + 1600, 512 + 3)
+        );
+        assert_eq!(
+            counters.peer(IpAddr::from_str("203.0.113.1").unwrap()),
+            Counter::new(1496 * 512 + 1600, 512 + 3)
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Reading the fields of flow telemetry datagrams.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+//------------ ParseError ----------------------------------------------------
+
+/// A datagram could not be parsed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ParseError(pub &'static str);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+//------------ Reader --------------------------------------------------------
+
+/// Reads big-endian fields from the front of a buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Takes the next `len` bytes.
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        if len > self.data.len() {
+            return Err(ParseError("truncated datagram"));
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    /// Takes the next `len` bytes as a reader of their own.
+    pub fn sub(&mut self, len: usize) -> Result<Reader<'a>, ParseError> {
+        self.take(len).map(Reader::new)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, ParseError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, ParseError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn ipv4(&mut self) -> Result<IpAddr, ParseError> {
+        Ok(ipv4(self.take(4)?))
+    }
+
+    pub fn ipv6(&mut self) -> Result<IpAddr, ParseError> {
+        Ok(ipv6(self.take(16)?))
+    }
+}
+
+/// Converts four bytes into an IPv4 address.
+pub fn ipv4(octets: &[u8]) -> IpAddr {
+    let octets: [u8; 4] = octets.try_into().unwrap();
+    Ipv4Addr::from(octets).into()
+}
+
+/// Converts sixteen bytes into an IPv6 address.
+pub fn ipv6(octets: &[u8]) -> IpAddr {
+    let octets: [u8; 16] = octets.try_into().unwrap();
+    Ipv6Addr::from(octets).into()
+}
+
+/// Reads an unsigned integer of up to eight bytes.
+///
+/// IPFIX allows integers to be exported in fewer bytes than their type.
+pub fn uint(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .take(8)
+        .fold(0, |value, byte| (value << 8) | u64::from(*byte))
+}
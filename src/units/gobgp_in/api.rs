@@ -0,0 +1,603 @@
+//! The messages of GoBGP's gRPC API used by the `gobgp-in` unit.
+//!
+//! Only the `WatchEvent` method of the `apipb.GobgpApi` service is used, as
+//! defined in `api/gobgp.proto` and `api/attribute.proto` of GoBGP 3. Of
+//! its messages, only the fields needed to turn the paths of IPv4 and IPv6
+//! unicast routes and the state changes of peers into Rotonda's updates are
+//! declared here, with the derive macros of [prost] as for the `grpc-in`
+//! unit. Unknown fields are skipped when decoding.
+//!
+//! [prost]: https://docs.rs/prost/
+
+use std::net::IpAddr;
+
+use inetnum::addr::Prefix;
+use prost::Message;
+use prost_types::Any;
+use routecore::bgp::communities::StandardCommunity;
+use serde::Deserialize;
+
+use crate::units::http_in::batch::{Attributes, Origin};
+
+/// The path of the `WatchEvent` method.
+pub const WATCH_EVENT_PATH: &str = "/apipb.GobgpApi/WatchEvent";
+
+// Address families.
+const AFI_IP: i32 = 1;
+const AFI_IP6: i32 = 2;
+const SAFI_UNICAST: i32 = 1;
+
+/// The session state of an established peer.
+const ESTABLISHED: i32 = 6;
+
+// AS path segment types.
+const AS_SET: u32 = 1;
+
+//------------ Table ---------------------------------------------------------
+
+/// The table of GoBGP to follow.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Table {
+    /// The best paths of the global RIB.
+    Best,
+
+    /// The paths received from the peers, before import policies.
+    #[default]
+    AdjIn,
+
+    /// The paths received from the peers, after import policies.
+    PostPolicy,
+}
+
+impl Table {
+    fn filter_type(self) -> i32 {
+        match self {
+            Table::Best => 0,
+            Table::AdjIn => 1,
+            Table::PostPolicy => 2,
+        }
+    }
+}
+
+/// Returns a `WatchEventRequest` for the state changes of all peers and the
+/// paths of `table`, starting with those already in it.
+pub fn watch_event_request(table: Table) -> WatchEventRequest {
+    WatchEventRequest {
+        peer: Some(WatchPeer {}),
+        table: Some(WatchTable {
+            filters: vec![WatchTableFilter {
+                filter_type: table.filter_type(),
+                init: true,
+            }],
+        }),
+    }
+}
+
+//------------ Messages ------------------------------------------------------
+
+#[derive(Clone, PartialEq, Message)]
+pub struct WatchEventRequest {
+    #[prost(message, optional, tag = "1")]
+    pub peer: Option<WatchPeer>,
+
+    #[prost(message, optional, tag = "2")]
+    pub table: Option<WatchTable>,
+}
+
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct WatchPeer {}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct WatchTable {
+    #[prost(message, repeated, tag = "1")]
+    pub filters: Vec<WatchTableFilter>,
+}
+
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct WatchTableFilter {
+    #[prost(int32, tag = "1")]
+    pub filter_type: i32,
+
+    #[prost(bool, tag = "2")]
+    pub init: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct WatchEventResponse {
+    #[prost(oneof = "WatchEvent", tags = "2, 3")]
+    pub event: Option<WatchEvent>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum WatchEvent {
+    #[prost(message, tag = "2")]
+    Peer(PeerEventMessage),
+
+    #[prost(message, tag = "3")]
+    Table(TableEvent),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct PeerEventMessage {
+    #[prost(message, optional, tag = "2")]
+    pub peer: Option<Peer>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Peer {
+    #[prost(message, optional, tag = "2")]
+    pub conf: Option<PeerConf>,
+
+    #[prost(message, optional, tag = "5")]
+    pub state: Option<PeerState>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct PeerConf {
+    #[prost(string, tag = "4")]
+    pub neighbor_address: String,
+
+    #[prost(uint32, tag = "5")]
+    pub peer_asn: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct PeerState {
+    #[prost(string, tag = "5")]
+    pub neighbor_address: String,
+
+    #[prost(uint32, tag = "6")]
+    pub peer_asn: u32,
+
+    #[prost(int32, tag = "13")]
+    pub session_state: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TableEvent {
+    #[prost(message, repeated, tag = "2")]
+    pub paths: Vec<ApiPath>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ApiPath {
+    #[prost(message, optional, tag = "1")]
+    pub nlri: Option<Any>,
+
+    #[prost(message, repeated, tag = "2")]
+    pub pattrs: Vec<Any>,
+
+    #[prost(bool, tag = "5")]
+    pub is_withdraw: bool,
+
+    #[prost(message, optional, tag = "9")]
+    pub family: Option<Family>,
+
+    #[prost(uint32, tag = "10")]
+    pub source_asn: u32,
+
+    #[prost(string, tag = "15")]
+    pub neighbor_ip: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct Family {
+    #[prost(int32, tag = "1")]
+    pub afi: i32,
+
+    #[prost(int32, tag = "2")]
+    pub safi: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct IpAddressPrefix {
+    #[prost(uint32, tag = "1")]
+    pub prefix_len: u32,
+
+    #[prost(string, tag = "2")]
+    pub prefix: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct OriginAttribute {
+    #[prost(uint32, tag = "1")]
+    pub origin: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct AsPathAttribute {
+    #[prost(message, repeated, tag = "1")]
+    pub segments: Vec<AsSegment>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct AsSegment {
+    #[prost(uint32, tag = "1")]
+    pub segment_type: u32,
+
+    #[prost(uint32, repeated, tag = "2")]
+    pub numbers: Vec<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct NextHopAttribute {
+    #[prost(string, tag = "1")]
+    pub next_hop: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct MpReachNlriAttribute {
+    #[prost(message, optional, tag = "1")]
+    pub family: Option<Family>,
+
+    #[prost(string, repeated, tag = "2")]
+    pub next_hops: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct MultiExitDiscAttribute {
+    #[prost(uint32, tag = "1")]
+    pub med: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct LocalPrefAttribute {
+    #[prost(uint32, tag = "1")]
+    pub local_pref: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct CommunitiesAttribute {
+    #[prost(uint32, repeated, tag = "1")]
+    pub communities: Vec<u32>,
+}
+
+//------------ Event ---------------------------------------------------------
+
+/// The content of a `WatchEventResponse` of use to Rotonda.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    Peer(PeerEvent),
+    Table(Vec<Path>),
+    Ignored,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PeerEvent {
+    pub address: IpAddr,
+    pub asn: u32,
+    pub established: bool,
+}
+
+/// A path of an IPv4 or IPv6 unicast route.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Path {
+    pub peer_address: IpAddr,
+    pub peer_asn: u32,
+    pub prefix: Prefix,
+    pub withdraw: bool,
+    pub attributes: Attributes,
+}
+
+impl Event {
+    pub fn from_response(
+        response: WatchEventResponse,
+    ) -> Result<Self, String> {
+        match response.event {
+            Some(WatchEvent::Peer(event)) => Ok(event
+                .peer
+                .and_then(peer_event)
+                .transpose()?
+                .map_or(Event::Ignored, Event::Peer)),
+            Some(WatchEvent::Table(event)) => {
+                let mut paths = vec![];
+                for path in event.paths {
+                    paths.extend(Path::from_api(path)?);
+                }
+                Ok(Event::Table(paths))
+            }
+            None => Ok(Event::Ignored),
+        }
+    }
+}
+
+/// Returns the state of a peer, unless it lacks the peer's address.
+///
+/// The values of the state override those of the configuration.
+fn peer_event(peer: Peer) -> Option<Result<PeerEvent, String>> {
+    let conf = peer.conf.unwrap_or_default();
+    let state = peer.state.unwrap_or_default();
+    let address = [state.neighbor_address, conf.neighbor_address]
+        .into_iter()
+        .find(|address| !address.is_empty())?;
+    let asn = [state.peer_asn, conf.peer_asn]
+        .into_iter()
+        .find(|asn| *asn != 0)
+        .unwrap_or_default();
+    Some(ip_addr(&address).map(|address| PeerEvent {
+        address,
+        asn,
+        established: state.session_state == ESTABLISHED,
+    }))
+}
+
+impl Path {
+    /// Converts a path, unless it is not of a unicast route received from
+    /// a peer.
+    fn from_api(path: ApiPath) -> Result<Option<Self>, String> {
+        let unicast = path.family.is_some_and(|family| {
+            (family.afi == AFI_IP || family.afi == AFI_IP6)
+                && family.safi == SAFI_UNICAST
+        });
+        // Locally originated paths have no valid neighbor address.
+        let peer_address = ip_addr(&path.neighbor_ip).ok();
+        let (Some(nlri), Some(peer_address), true) =
+            (path.nlri, peer_address, unicast)
+        else {
+            return Ok(None);
+        };
+        if name(&nlri) != "IPAddressPrefix" {
+            return Ok(None);
+        }
+        let nlri: IpAddressPrefix = unpack(&nlri)?;
+        let len = u8::try_from(nlri.prefix_len)
+            .map_err(|_| "invalid prefix length")?;
+        let prefix = Prefix::new(ip_addr(&nlri.prefix)?, len)
+            .map_err(|err| format!("invalid prefix: {err}"))?;
+
+        let mut attributes = Attributes::default();
+        for pattr in &path.pattrs {
+            add_attribute(pattr, &mut attributes)?;
+        }
+        Ok(Some(Path {
+            peer_address,
+            peer_asn: path.source_asn,
+            prefix,
+            withdraw: path.is_withdraw,
+            attributes,
+        }))
+    }
+}
+
+/// Adds a path attribute, packed in an `Any`, to `attributes`.
+fn add_attribute(
+    pattr: &Any,
+    attributes: &mut Attributes,
+) -> Result<(), String> {
+    match name(pattr) {
+        "OriginAttribute" => {
+            let attr: OriginAttribute = unpack(pattr)?;
+            attributes.origin = match attr.origin {
+                0 => Origin::Igp,
+                1 => Origin::Egp,
+                _ => Origin::Incomplete,
+            }
+        }
+        "AsPathAttribute" => {
+            let attr: AsPathAttribute = unpack(pattr)?;
+            for mut segment in attr.segments {
+                // An AS_SET counts as a single hop, for which its first
+                // AS number stands in.
+                if segment.segment_type == AS_SET {
+                    segment.numbers.truncate(1);
+                }
+                attributes.as_path.extend(segment.numbers);
+            }
+        }
+        "NextHopAttribute" => {
+            let attr: NextHopAttribute = unpack(pattr)?;
+            attributes.next_hop = Some(ip_addr(&attr.next_hop)?);
+        }
+        // The first of the next hops is the global address, possibly
+        // followed by a link-local one.
+        "MpReachNLRIAttribute" if attributes.next_hop.is_none() => {
+            let attr: MpReachNlriAttribute = unpack(pattr)?;
+            if let Some(next_hop) = attr.next_hops.first() {
+                attributes.next_hop = Some(ip_addr(next_hop)?);
+            }
+        }
+        "MultiExitDiscAttribute" => {
+            let attr: MultiExitDiscAttribute = unpack(pattr)?;
+            attributes.med = Some(attr.med);
+        }
+        "LocalPrefAttribute" => {
+            let attr: LocalPrefAttribute = unpack(pattr)?;
+            attributes.local_pref = Some(attr.local_pref);
+        }
+        "CommunitiesAttribute" => {
+            let attr: CommunitiesAttribute = unpack(pattr)?;
+            attributes.communities.extend(
+                attr.communities
+                    .into_iter()
+                    .map(StandardCommunity::from_u32),
+            );
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Returns the name of the message type in an `Any`.
+fn name(any: &Any) -> &str {
+    any.type_url.rsplit(['/', '.']).next().unwrap_or_default()
+}
+
+/// Decodes the message in an `Any`.
+fn unpack<M: Message + Default>(any: &Any) -> Result<M, String> {
+    M::decode(any.value.as_slice())
+        .map_err(|err| format!("invalid {}: {err}", name(any)))
+}
+
+fn ip_addr(addr: &str) -> Result<IpAddr, String> {
+    addr.parse().map_err(|_| format!("invalid address {addr}"))
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    fn any(name: &str, value: impl Message) -> Any {
+        Any {
+            type_url: format!("type.googleapis.com/apipb.{name}"),
+            value: value.encode_to_vec(),
+        }
+    }
+
+    /// Returns a `WatchEventResponse` with a `PeerEvent` for the state of
+    /// a peer.
+    pub fn peer_event(
+        address: &str,
+        asn: u32,
+        state: i32,
+    ) -> WatchEventResponse {
+        WatchEventResponse {
+            event: Some(WatchEvent::Peer(PeerEventMessage {
+                peer: Some(Peer {
+                    conf: None,
+                    state: Some(PeerState {
+                        neighbor_address: address.into(),
+                        peer_asn: asn,
+                        session_state: state,
+                    }),
+                }),
+            })),
+        }
+    }
+
+    /// Returns a `WatchEventResponse` with a `TableEvent` for a unicast
+    /// path of `prefix`, as GoBGP would.
+    pub fn table_event(
+        peer: &str,
+        asn: u32,
+        prefix: Prefix,
+        withdraw: bool,
+    ) -> WatchEventResponse {
+        let family = Family {
+            afi: if prefix.is_v4() { AFI_IP } else { AFI_IP6 },
+            safi: SAFI_UNICAST,
+        };
+        let next_hop = if prefix.is_v4() {
+            "192.0.2.1"
+        } else {
+            "2001:db8::1"
+        };
+        let mut pattrs = vec![
+            any("OriginAttribute", OriginAttribute { origin: 2 }),
+            any(
+                "AsPathAttribute",
+                AsPathAttribute {
+                    segments: vec![AsSegment {
+                        segment_type: 2,
+                        numbers: vec![asn, 65100],
+                    }],
+                },
+            ),
+            any("LocalPrefAttribute", LocalPrefAttribute { local_pref: 200 }),
+        ];
+        pattrs.push(if prefix.is_v4() {
+            any(
+                "NextHopAttribute",
+                NextHopAttribute {
+                    next_hop: next_hop.into(),
+                },
+            )
+        } else {
+            any(
+                "MpReachNLRIAttribute",
+                MpReachNlriAttribute {
+                    family: Some(family),
+                    next_hops: vec![next_hop.into(), "fe80::1".into()],
+                },
+            )
+        });
+        let path = ApiPath {
+            nlri: Some(any(
+                "IPAddressPrefix",
+                IpAddressPrefix {
+                    prefix_len: prefix.len().into(),
+                    prefix: prefix.addr().to_string(),
+                },
+            )),
+            pattrs,
+            is_withdraw: withdraw,
+            family: Some(family),
+            source_asn: asn,
+            neighbor_ip: peer.into(),
+        };
+        WatchEventResponse {
+            event: Some(WatchEvent::Table(TableEvent { paths: vec![path] })),
+        }
+    }
+
+    #[test]
+    fn peer_events_are_converted() {
+        let event = Event::from_response(peer_event("192.0.2.1", 65001, 1));
+        assert_eq!(
+            event,
+            Ok(Event::Peer(PeerEvent {
+                address: "192.0.2.1".parse().unwrap(),
+                asn: 65001,
+                established: false,
+            }))
+        );
+        assert_eq!(
+            Event::from_response(WatchEventResponse::default()),
+            Ok(Event::Ignored)
+        );
+    }
+
+    #[test]
+    fn table_events_are_converted() {
+        let prefix =
+            Prefix::new("2001:db8:1::".parse().unwrap(), 48).unwrap();
+        let event = Event::from_response(table_event(
+            "2001:db8::1",
+            65001,
+            prefix,
+            false,
+        ))
+        .unwrap();
+        let Event::Table(paths) = event else {
+            panic!("expected a table event");
+        };
+        assert_eq!(paths.len(), 1);
+        let path = &paths[0];
+        assert_eq!(
+            path.peer_address,
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(path.peer_asn, 65001);
+        assert_eq!(path.prefix, prefix);
+        assert!(!path.withdraw);
+        assert_eq!(path.attributes.origin, Origin::Incomplete);
+        assert_eq!(path.attributes.as_path, [65001, 65100]);
+        assert_eq!(
+            path.attributes.next_hop,
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(path.attributes.local_pref, Some(200));
+
+        // Paths not received from a peer are skipped.
+        let event =
+            Event::from_response(table_event("<nil>", 65001, prefix, false));
+        assert_eq!(event, Ok(Event::Table(vec![])));
+
+        // Attributes that cannot be decoded fail the event.
+        let mut response = table_event("2001:db8::1", 65001, prefix, false);
+        if let Some(WatchEvent::Table(event)) = &mut response.event {
+            event.paths[0].pattrs[0].value = vec![0x08];
+        }
+        assert!(Event::from_response(response).is_err());
+    }
+
+    #[test]
+    fn watch_event_requests_are_encoded() {
+        assert_eq!(
+            watch_event_request(Table::PostPolicy).encode_to_vec(),
+            [0x0a, 0x00, 0x12, 0x06, 0x0a, 0x04, 0x08, 0x02, 0x10, 0x01]
+        );
+    }
+}
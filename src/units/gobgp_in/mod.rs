@@ -0,0 +1,4 @@
+mod api;
+pub mod unit;
+
+pub use unit::GobgpIn;
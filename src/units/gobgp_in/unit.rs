@@ -0,0 +1,546 @@
+//! Ingesting routes from GoBGP.
+//!
+//! This unit connects to the gRPC API of a [GoBGP] daemon and follows its
+//! `WatchEvent` stream: first the paths already in the configured table,
+//! then the changes to it, and the state changes of GoBGP's peers. The
+//! paths of IPv4 and IPv6 unicast routes received from peers are turned
+//! into routes, so GoBGP deployments can make use of Rotonda's filtering
+//! and storage.
+//!
+//! The daemon is registered as an ingress of the unit, named after its
+//! address, and every peer as an ingress of the daemon. When a peer's
+//! session leaves the established state, all its routes are withdrawn, as
+//! are those of all peers when the stream ends. The unit then reconnects
+//! with an exponential backoff, receiving the table afresh.
+//!
+//! The API is called with [tonic]. TLS is not supported.
+//!
+//! [GoBGP]: https://github.com/osrg/gobgp
+//! [tonic]: https://docs.rs/tonic/
+
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+    time::Duration,
+};
+
+use hyper::http::uri::PathAndQuery;
+use inetnum::asn::Asn;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tonic::{client::Grpc, codec::ProstCodec, transport::Endpoint};
+
+use crate::{
+    comms::{Gate, GateMetrics, GateStatus, Terminated},
+    ingress::IngressInfo,
+    manager::{Component, WaitPoint},
+    metrics::{self, Metric, MetricType, MetricUnit},
+    units::{
+        http_in::batch::{PeerUpdate, Routes},
+        ris_live_in::unit::{Converted, Converter},
+    },
+};
+
+use super::api::{
+    watch_event_request, Event, Path, PeerEvent, Table, WatchEventRequest,
+    WatchEventResponse, WATCH_EVENT_PATH,
+};
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct GobgpIn {
+    /// The address of GoBGP's gRPC API, e.g. `127.0.0.1:50051`.
+    pub address: SocketAddr,
+
+    /// The table to follow: `adj-in`, `post-policy` or `best`.
+    #[serde(default)]
+    pub table: Table,
+
+    /// The largest message accepted, in bytes.
+    #[serde(default = "GobgpIn::default_max_message_size")]
+    pub max_message_size: usize,
+
+    /// How long to wait before reconnecting after the connection failed.
+    /// The delay doubles with every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "GobgpIn::default_reconnect_delay_secs")]
+    pub reconnect_delay_secs: Duration,
+
+    /// The longest to wait before reconnecting.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "GobgpIn::default_max_reconnect_delay_secs")]
+    pub max_reconnect_delay_secs: Duration,
+}
+
+impl GobgpIn {
+    fn default_max_message_size() -> usize {
+        16 * 1024 * 1024
+    }
+
+    fn default_reconnect_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_reconnect_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    pub async fn run(
+        self,
+        mut component: Component,
+        gate: Gate,
+        mut waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let metrics = Arc::new(GobgpInMetrics::new(&gate));
+        component.register_metrics(metrics.clone());
+
+        gate.process_until(waitpoint.ready()).await?;
+        waitpoint.running().await;
+
+        let ingresses = component.ingresses().clone();
+        let parent_id = ingresses.register();
+        ingresses.update_info(
+            parent_id,
+            IngressInfo::new()
+                .with_unit_name(component.name().as_ref())
+                .with_desc("gobgp-in unit"),
+        );
+        let receiver = GobgpInReceiver {
+            host: self.address.to_string(),
+            converter: Converter::new(ingresses, parent_id, "GoBGP daemon"),
+            name: component.name().to_string(),
+            gate: gate.clone(),
+            metrics,
+            config: self,
+        };
+        let receiving = tokio::spawn(receiver.connect_loop());
+
+        let res = loop {
+            match gate.process().await {
+                Ok(GateStatus::ReportLinks { report }) => {
+                    report.declare_source();
+                }
+                Ok(GateStatus::Reconfiguring { .. }) => {
+                    warn!(
+                        "Unit {}: reconfiguring gobgp-in requires a restart",
+                        component.name()
+                    );
+                }
+                Ok(_) => {}
+                Err(Terminated) => break Err(Terminated),
+            }
+        };
+        receiving.abort();
+        res
+    }
+}
+
+//------------ GobgpInReceiver -----------------------------------------------
+
+struct GobgpInReceiver {
+    config: GobgpIn,
+    name: String,
+    gate: Gate,
+
+    /// The name of the daemon's ingress.
+    host: String,
+
+    converter: Converter,
+    metrics: Arc<GobgpInMetrics>,
+}
+
+impl GobgpInReceiver {
+    /// Follow the daemon's events, reconnecting whenever the stream ends.
+    async fn connect_loop(mut self) {
+        let mut delay = self.config.reconnect_delay_secs;
+        loop {
+            let mut peers = HashSet::new();
+            let res = self.watch(&mut peers, &mut delay).await;
+            self.metrics.connected.store(0, SeqCst);
+            match res {
+                Ok(()) => info!(
+                    "Unit {}: GoBGP at {} ended the stream",
+                    self.name, self.host
+                ),
+                Err(err) => warn!(
+                    "Unit {}: watching GoBGP at {} failed: {err}",
+                    self.name, self.host
+                ),
+            }
+            for (address, asn) in peers {
+                self.peer_down(address, asn).await;
+            }
+            info!(
+                "Unit {}: reconnecting to {} in {}s",
+                self.name,
+                self.host,
+                delay.as_secs()
+            );
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(self.config.max_reconnect_delay_secs);
+        }
+    }
+
+    /// Follow the events of a single `WatchEvent` stream, keeping track of
+    /// the peers with routes.
+    async fn watch(
+        &mut self,
+        peers: &mut HashSet<(IpAddr, Asn)>,
+        delay: &mut Duration,
+    ) -> Result<(), String> {
+        debug!("Unit {}: connecting to {}", self.name, self.host);
+        let channel =
+            Endpoint::from_shared(format!("http://{}", self.config.address))
+                .map_err(|err| err.to_string())?
+                .tcp_nodelay(true)
+                .connect()
+                .await
+                .map_err(|err| err.to_string())?;
+        let mut client = Grpc::new(channel)
+            .max_decoding_message_size(self.config.max_message_size);
+        client.ready().await.map_err(|err| err.to_string())?;
+        let mut events = client
+            .server_streaming(
+                tonic::Request::new(watch_event_request(self.config.table)),
+                PathAndQuery::from_static(WATCH_EVENT_PATH),
+                ProstCodec::<WatchEventRequest, WatchEventResponse>::default(
+                ),
+            )
+            .await
+            .map_err(|status| status.to_string())?
+            .into_inner();
+
+        // A stream that got going resets the backoff.
+        info!("Unit {}: watching GoBGP at {}", self.name, self.host);
+        self.metrics.connected.store(1, SeqCst);
+        self.metrics.num_connects.fetch_add(1, SeqCst);
+        *delay = self.config.reconnect_delay_secs;
+
+        while let Some(event) = events
+            .message()
+            .await
+            .map_err(|status| status.to_string())?
+        {
+            self.process(event, peers).await;
+        }
+        Ok(())
+    }
+
+    /// Process a `WatchEventResponse`.
+    async fn process(
+        &mut self,
+        response: WatchEventResponse,
+        peers: &mut HashSet<(IpAddr, Asn)>,
+    ) {
+        self.metrics.num_events.fetch_add(1, SeqCst);
+        let event = match Event::from_response(response) {
+            Ok(event) => event,
+            Err(err) => {
+                self.metrics.num_invalid_events.fetch_add(1, SeqCst);
+                debug!("Unit {}: ignoring event: {err}", self.name);
+                return;
+            }
+        };
+        match event {
+            Event::Peer(PeerEvent {
+                address,
+                asn,
+                established,
+            }) => {
+                let peer = (address, Asn::from_u32(asn));
+                if !established && peers.remove(&peer) {
+                    self.peer_down(peer.0, peer.1).await;
+                }
+            }
+            Event::Table(paths) => {
+                for path in paths {
+                    match self.routes(&path) {
+                        Ok(Routes {
+                            announced,
+                            withdrawn,
+                        }) => {
+                            let peer_asn = Asn::from_u32(path.peer_asn);
+                            let converted = self.converter.convert_routes(
+                                &self.host,
+                                path.peer_address,
+                                peer_asn,
+                                announced,
+                                withdrawn,
+                            );
+                            peers.insert((path.peer_address, peer_asn));
+                            self.send(converted).await;
+                        }
+                        Err(err) => {
+                            self.metrics
+                                .num_invalid_events
+                                .fetch_add(1, SeqCst);
+                            debug!(
+                                "Unit {}: ignoring path: {err}",
+                                self.name
+                            );
+                        }
+                    }
+                }
+            }
+            Event::Ignored => {}
+        }
+    }
+
+    fn routes(&self, path: &Path) -> Result<Routes, String> {
+        let (announce, withdraw) = match path.withdraw {
+            true => (vec![], vec![path.prefix]),
+            false => (vec![path.prefix], vec![]),
+        };
+        PeerUpdate {
+            peer_address: path.peer_address,
+            peer_asn: path.peer_asn,
+            announce,
+            withdraw,
+            attributes: path.attributes.clone(),
+            peer_down: false,
+        }
+        .routes()
+    }
+
+    async fn peer_down(&mut self, address: IpAddr, asn: Asn) {
+        self.metrics.num_peer_downs.fetch_add(1, SeqCst);
+        let converted = self.converter.peer_down(&self.host, address, asn);
+        self.send(converted).await;
+    }
+
+    async fn send(&self, converted: Converted) {
+        if let Converted::Update(update, announced, withdrawn) = converted {
+            self.metrics.num_announcements.fetch_add(announced, SeqCst);
+            self.metrics.num_withdrawals.fetch_add(withdrawn, SeqCst);
+            self.gate.update_data(update).await;
+        }
+    }
+}
+
+//------------ GobgpInMetrics ------------------------------------------------
+
+#[derive(Debug, Default)]
+struct GobgpInMetrics {
+    gate: Arc<GateMetrics>,
+    connected: AtomicUsize,
+    num_connects: AtomicUsize,
+    num_events: AtomicUsize,
+    num_invalid_events: AtomicUsize,
+    num_announcements: AtomicUsize,
+    num_withdrawals: AtomicUsize,
+    num_peer_downs: AtomicUsize,
+}
+
+impl GobgpInMetrics {
+    fn new(gate: &Gate) -> Self {
+        Self {
+            gate: gate.metrics(),
+            ..Default::default()
+        }
+    }
+
+    const CONNECTED_METRIC: Metric = Metric::new(
+        "gobgp_in_connected",
+        "whether the events of the GoBGP daemon are being followed",
+        MetricType::Gauge,
+        MetricUnit::State,
+    );
+    const NUM_CONNECTS_METRIC: Metric = Metric::new(
+        "gobgp_in_num_connects",
+        "the number of times following the events of GoBGP was started",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_EVENTS_METRIC: Metric = Metric::new(
+        "gobgp_in_num_events",
+        "the number of events received from GoBGP",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_INVALID_EVENTS_METRIC: Metric = Metric::new(
+        "gobgp_in_num_invalid_events",
+        "the number of events and paths from GoBGP that could not be used",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ANNOUNCEMENTS_METRIC: Metric = Metric::new(
+        "gobgp_in_num_announcements",
+        "the number of route announcements received from GoBGP",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WITHDRAWALS_METRIC: Metric = Metric::new(
+        "gobgp_in_num_withdrawals",
+        "the number of route withdrawals received from GoBGP",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_PEER_DOWNS_METRIC: Metric = Metric::new(
+        "gobgp_in_num_peer_downs",
+        "the number of peers whose routes were withdrawn",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for GobgpInMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        self.gate.append(unit_name, target);
+        target.append_simple(
+            &Self::CONNECTED_METRIC,
+            Some(unit_name),
+            self.connected.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_CONNECTS_METRIC,
+            Some(unit_name),
+            self.num_connects.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_EVENTS_METRIC,
+            Some(unit_name),
+            self.num_events.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_INVALID_EVENTS_METRIC,
+            Some(unit_name),
+            self.num_invalid_events.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ANNOUNCEMENTS_METRIC,
+            Some(unit_name),
+            self.num_announcements.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WITHDRAWALS_METRIC,
+            Some(unit_name),
+            self.num_withdrawals.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_PEER_DOWNS_METRIC,
+            Some(unit_name),
+            self.num_peer_downs.load(SeqCst),
+        );
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use hyper::{
+        server::conn::Http, service::service_fn, Body, Request, Response,
+    };
+    use inetnum::addr::Prefix;
+    use prost::Message;
+    use tokio::net::TcpListener;
+
+    use crate::{ingress, units::Unit};
+
+    use super::{
+        super::api::tests::{peer_event, table_event},
+        *,
+    };
+
+    #[test]
+    fn config_deserialization() {
+        let toml = r#"
+        type = "gobgp-in"
+        address = "127.0.0.1:50051"
+        table = "post-policy"
+        "#;
+        let Unit::GobgpIn(config) = toml::from_str(toml).unwrap() else {
+            panic!("expected a gobgp-in unit");
+        };
+        assert_eq!(config.table, Table::PostPolicy);
+        assert_eq!(config.reconnect_delay_secs, Duration::from_secs(1));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn events_are_followed() {
+        // A GoBGP stand-in sending the table, a withdrawal and a peer going
+        // down, then ending the stream.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let prefix = |s: &str| s.parse::<Prefix>().unwrap();
+        let mut body = vec![];
+        for event in [
+            table_event("192.0.2.1", 65001, prefix("198.51.100.0/24"), false),
+            table_event("192.0.2.1", 65001, prefix("203.0.113.0/24"), false),
+            table_event("192.0.2.2", 65002, prefix("2001:db8::/32"), false),
+            table_event("192.0.2.1", 65001, prefix("203.0.113.0/24"), true),
+            peer_event("192.0.2.1", 65001, 1),
+        ] {
+            // Uncompressed and length-prefixed, as gRPC frames messages.
+            let msg = event.encode_to_vec();
+            body.push(0);
+            body.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+            body.extend_from_slice(&msg);
+        }
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(move |req: Request<Body>| {
+                let body = body.clone();
+                async move {
+                    assert_eq!(req.uri().path(), WATCH_EVENT_PATH);
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .header("content-type", "application/grpc")
+                            .body(Body::from(body))
+                            .unwrap(),
+                    )
+                }
+            });
+            let _ = Http::new()
+                .http2_only(true)
+                .serve_connection(stream, service)
+                .await;
+        });
+
+        let (gate, _agent) = Gate::new(0);
+        let ingresses = Arc::new(ingress::Register::default());
+        let parent_id = ingresses.register();
+        let metrics = Arc::new(GobgpInMetrics::new(&gate));
+        let receiver = GobgpInReceiver {
+            config: toml::from_str(&format!("address = \"{address}\""))
+                .unwrap(),
+            name: "gobgp".into(),
+            gate,
+            host: address.to_string(),
+            converter: Converter::new(
+                ingresses.clone(),
+                parent_id,
+                "GoBGP daemon",
+            ),
+            metrics: metrics.clone(),
+        };
+        let receiving = tokio::spawn(receiver.connect_loop());
+
+        // Both peers went down, the second one when the stream ended.
+        while metrics.num_peer_downs.load(SeqCst) < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        receiving.abort();
+        assert_eq!(metrics.num_connects.load(SeqCst), 1);
+        assert_eq!(metrics.num_events.load(SeqCst), 5);
+        assert_eq!(metrics.num_invalid_events.load(SeqCst), 0);
+        assert_eq!(metrics.num_announcements.load(SeqCst), 3);
+        assert_eq!(metrics.num_withdrawals.load(SeqCst), 1);
+
+        // The peers are registered under the daemon.
+        let daemon = ingresses
+            .find_all(|info| info.parent_ingress == Some(parent_id))
+            .pop()
+            .unwrap();
+        assert_eq!(ingresses.ids_for_parent(daemon).len(), 2);
+    }
+}
@@ -0,0 +1,4 @@
+pub(crate) mod proto;
+pub mod unit;
+
+pub use unit::GrpcIn;
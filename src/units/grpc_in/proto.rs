@@ -0,0 +1,95 @@
+//! The messages of the `rotonda.ingest.v1` gRPC service.
+//!
+//! The schema is in `proto/ingest.proto`. As it is small, its messages are
+//! declared here with the derive macros of [prost] rather than generated
+//! from the schema at build time, which would require `protoc`.
+//!
+//! [prost]: https://docs.rs/prost/
+
+use bytes::Bytes;
+
+/// The path of the `Push` method.
+pub const PUSH_PATH: &str = "/rotonda.ingest.v1.RouteIngest/Push";
+
+//------------ RouteUpdate ---------------------------------------------------
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RouteUpdate {
+    #[prost(uint64, tag = "1")]
+    pub sequence: u64,
+
+    #[prost(string, tag = "2")]
+    pub peer_address: String,
+
+    #[prost(uint32, tag = "3")]
+    pub peer_asn: u32,
+
+    #[prost(oneof = "Event", tags = "4, 5")]
+    pub event: Option<Event>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum Event {
+    #[prost(bytes = "bytes", tag = "4")]
+    BgpUpdate(Bytes),
+
+    #[prost(message, tag = "5")]
+    PeerDown(PeerDown),
+}
+
+#[derive(Clone, Copy, PartialEq, prost::Message)]
+pub struct PeerDown {}
+
+//------------ PushAck -------------------------------------------------------
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PushAck {
+    #[prost(uint64, tag = "1")]
+    pub sequence: u64,
+
+    #[prost(uint32, tag = "2")]
+    pub announcements: u32,
+
+    #[prost(uint32, tag = "3")]
+    pub withdrawals: u32,
+
+    #[prost(string, tag = "4")]
+    pub error: String,
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+
+    #[test]
+    fn route_updates_are_decoded() {
+        let update = RouteUpdate {
+            sequence: 300,
+            peer_address: "192.0.2.1".to_string(),
+            peer_asn: 4200000000,
+            event: Some(Event::BgpUpdate(Bytes::from_static(b"raw"))),
+        };
+        let mut encoded = update.encode_to_vec();
+        // Unknown fields of all wire types are skipped.
+        encoded.extend_from_slice(&[
+            0x30, 0x01, // field 6, varint
+            0x39, 0, 0, 0, 0, 0, 0, 0, 0, // field 7, fixed64
+            0x42, 0x01, 0xff, // field 8, bytes
+            0x4d, 0, 0, 0, 0, // field 9, fixed32
+        ]);
+        assert_eq!(RouteUpdate::decode(&encoded[..]), Ok(update));
+
+        // Of the oneof, the last one wins.
+        let mut encoded = vec![0x22, 0x01, 0xff, 0x2a, 0x00];
+        assert_eq!(
+            RouteUpdate::decode(&encoded[..]).unwrap().event,
+            Some(Event::PeerDown(PeerDown {}))
+        );
+        encoded.truncate(4);
+        assert!(RouteUpdate::decode(&encoded[..]).is_err());
+    }
+}
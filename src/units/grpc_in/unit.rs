@@ -0,0 +1,591 @@
+//! Ingesting routes pushed over gRPC.
+//!
+//! This unit runs a gRPC server with the `rotonda.ingest.v1.RouteIngest`
+//! service, whose schema ships with Rotonda in `proto/ingest.proto`.
+//! Producers open a bidirectional `Push` stream and send the BGP UPDATE
+//! messages they received from their peers, and the withdrawal of all
+//! routes of a peer when its session went down. Every update carrying a
+//! sequence number is acknowledged once it has been handed downstream.
+//!
+//! Every producer is registered as an ingress of the unit, by the name it
+//! gives in the `rotonda-client` request metadata or else by its address,
+//! and every peer as an ingress of its producer. A producer reconnecting
+//! under the same name keeps its ingresses. Its routes are left in place
+//! when the stream ends.
+//!
+//! The updates of a stream are processed in the order received. Messages
+//! are only read from a stream once the ones before them have been
+//! processed, so HTTP/2 flow control holds back producers sending faster
+//! than the routes can be processed, within the configured window size.
+//!
+//! The calls are handled by [tonic], on connections served by hyper.
+//!
+//! TLS is not supported, and must be terminated in front of Rotonda if
+//! needed.
+//!
+//! [tonic]: https://docs.rs/tonic/
+
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc, Mutex,
+    },
+};
+
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use hyper::{
+    server::conn::Http, service::service_fn, Body, Request, Response,
+    StatusCode,
+};
+use inetnum::asn::Asn;
+use log::{debug, error, warn};
+use serde::Deserialize;
+use tokio::net::TcpListener;
+use tonic::{
+    body::{empty_body, BoxBody},
+    codec::ProstCodec,
+    server::{Grpc, StreamingService},
+    Status, Streaming,
+};
+
+use crate::{
+    comms::{Gate, GateMetrics, GateStatus, Terminated},
+    ingress::{self, IngressInfo},
+    manager::{Component, WaitPoint},
+    metrics::{self, Metric, MetricType, MetricUnit},
+    units::ris_live_in::unit::{Converted, Converter},
+};
+
+use super::proto::{Event, PushAck, RouteUpdate, PUSH_PATH};
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GrpcIn {
+    /// The address to listen on for gRPC connections.
+    pub listen: SocketAddr,
+
+    /// The largest message accepted, in bytes.
+    #[serde(default = "GrpcIn::default_max_message_size")]
+    pub max_message_size: usize,
+
+    /// The HTTP/2 flow control window of a stream, in bytes: how much a
+    /// producer can send ahead of the updates processed.
+    #[serde(default = "GrpcIn::default_window_size")]
+    pub window_size: u32,
+
+    /// The number of streams a producer can have open at the same time.
+    #[serde(default = "GrpcIn::default_max_concurrent_streams")]
+    pub max_concurrent_streams: u32,
+}
+
+impl GrpcIn {
+    /// The largest flow control window HTTP/2 allows.
+    const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+    fn default_max_message_size() -> usize {
+        4 * 1024 * 1024
+    }
+
+    fn default_window_size() -> u32 {
+        1024 * 1024
+    }
+
+    fn default_max_concurrent_streams() -> u32 {
+        16
+    }
+
+    pub async fn run(
+        self,
+        mut component: Component,
+        gate: Gate,
+        mut waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let metrics = Arc::new(GrpcInMetrics::new(&gate));
+        component.register_metrics(metrics.clone());
+
+        let listener = match TcpListener::bind(self.listen).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!(
+                    "Unit {}: cannot listen on {}: {err}",
+                    component.name(),
+                    self.listen
+                );
+                return Err(Terminated);
+            }
+        };
+
+        gate.process_until(waitpoint.ready()).await?;
+        waitpoint.running().await;
+
+        let ingresses = component.ingresses().clone();
+        let parent_id = ingresses.register();
+        ingresses.update_info(
+            parent_id,
+            IngressInfo::new()
+                .with_unit_name(component.name().as_ref())
+                .with_desc("grpc-in unit"),
+        );
+
+        let server = Arc::new(GrpcServer {
+            config: self,
+            gate: gate.clone(),
+            converter: Mutex::new(Converter::new(
+                ingresses,
+                parent_id,
+                "gRPC producer",
+            )),
+            metrics,
+        });
+        let listener = tokio::spawn(server.serve(listener));
+
+        // The connections are handled in their own tasks, so here only the
+        // gate needs to be processed.
+        let res = loop {
+            match gate.process().await {
+                Ok(GateStatus::ReportLinks { report }) => {
+                    report.declare_source();
+                }
+                Ok(GateStatus::Reconfiguring { .. }) => {
+                    warn!(
+                        "Unit {}: reconfiguring grpc-in requires a restart",
+                        component.name()
+                    );
+                }
+                Ok(_) => {}
+                Err(Terminated) => break Err(Terminated),
+            }
+        };
+        listener.abort();
+        res
+    }
+}
+
+//------------ GrpcServer ----------------------------------------------------
+
+/// The state shared by all connections.
+struct GrpcServer {
+    config: GrpcIn,
+    gate: Gate,
+
+    /// Turns the updates into routes, keeping the producer and peer
+    /// ingresses.
+    converter: Mutex<Converter>,
+
+    metrics: Arc<GrpcInMetrics>,
+}
+
+impl GrpcServer {
+    async fn serve(self: Arc<Self>, listener: TcpListener) {
+        let window_size =
+            self.config.window_size.clamp(1, GrpcIn::MAX_WINDOW_SIZE);
+        let connection_window_size = window_size
+            .saturating_mul(self.config.max_concurrent_streams.max(1))
+            .min(GrpcIn::MAX_WINDOW_SIZE);
+        let mut http = Http::new();
+        http.http2_only(true)
+            .http2_initial_stream_window_size(window_size)
+            .http2_initial_connection_window_size(connection_window_size)
+            .http2_max_concurrent_streams(self.config.max_concurrent_streams);
+
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(res) => res,
+                Err(err) => {
+                    warn!("Failed to accept gRPC connection: {err}");
+                    continue;
+                }
+            };
+            debug!("gRPC connection from {addr}");
+            let server = self.clone();
+            let connection = http.serve_connection(
+                stream,
+                service_fn(move |req| server.clone().handle(req, addr)),
+            );
+            let metrics = self.metrics.clone();
+            tokio::spawn(async move {
+                metrics.num_connections.fetch_add(1, SeqCst);
+                metrics.connected_producers.fetch_add(1, SeqCst);
+                if let Err(err) = connection.await {
+                    debug!("gRPC connection from {addr} failed: {err}");
+                }
+                metrics.connected_producers.fetch_sub(1, SeqCst);
+            });
+        }
+    }
+
+    /// Handle a single gRPC call.
+    async fn handle(
+        self: Arc<Self>,
+        req: Request<Body>,
+        addr: SocketAddr,
+    ) -> Result<Response<BoxBody>, Infallible> {
+        let is_grpc = req
+            .headers()
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/grpc"));
+        if !is_grpc {
+            return Ok(Response::builder()
+                .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .body(empty_body())
+                .unwrap());
+        }
+        if req.uri().path() != PUSH_PATH {
+            let status = Status::unimplemented(format!(
+                "unknown method {}",
+                req.uri().path()
+            ));
+            return Ok(status.to_http());
+        }
+
+        let producer = req
+            .headers()
+            .get("rotonda-client")
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| addr.ip().to_string());
+        let max_message_size = self.config.max_message_size;
+        let push = Push {
+            server: self,
+            producer,
+        };
+        Ok(Grpc::new(ProstCodec::default())
+            .max_decoding_message_size(max_message_size)
+            .streaming(push, req)
+            .await)
+    }
+
+    /// Process the updates of a `Push` stream, returning the
+    /// acknowledgements.
+    ///
+    /// The next update is only read once the previous one has been
+    /// processed and its acknowledgement, if any, has been sent, which
+    /// leaves flow control to HTTP/2.
+    fn push(
+        self: Arc<Self>,
+        producer: String,
+        updates: Streaming<RouteUpdate>,
+    ) -> BoxStream<'static, Result<PushAck, Status>> {
+        futures::stream::unfold(
+            Some((self, producer, updates)),
+            |state| async move {
+                let (server, producer, mut updates) = state?;
+                loop {
+                    match updates.message().await {
+                        Ok(Some(update)) => {
+                            let ack = server.process(&producer, update).await;
+                            if ack.sequence != 0 {
+                                let state = Some((server, producer, updates));
+                                return Some((Ok(ack), state));
+                            }
+                        }
+                        Ok(None) => return None,
+                        Err(status) => {
+                            debug!(
+                                "gRPC push from {producer} failed: {}",
+                                status.message()
+                            );
+                            return Some((Err(status), None));
+                        }
+                    }
+                }
+            },
+        )
+        .boxed()
+    }
+
+    /// Send the routes of `update` downstream.
+    async fn process(&self, producer: &str, update: RouteUpdate) -> PushAck {
+        self.metrics.num_messages.fetch_add(1, SeqCst);
+        let mut ack = PushAck {
+            sequence: update.sequence,
+            ..Default::default()
+        };
+        match self.convert(producer, update) {
+            Ok(Converted::Update(update, announced, withdrawn)) => {
+                self.metrics.num_announcements.fetch_add(announced, SeqCst);
+                self.metrics.num_withdrawals.fetch_add(withdrawn, SeqCst);
+                ack.announcements = announced as u32;
+                ack.withdrawals = withdrawn as u32;
+                self.gate.update_data(update).await;
+            }
+            Ok(Converted::Ignored) => {}
+            Err(err) => {
+                self.metrics.num_invalid_messages.fetch_add(1, SeqCst);
+                ack.error = err;
+            }
+        }
+        ack
+    }
+
+    fn convert(
+        &self,
+        producer: &str,
+        update: RouteUpdate,
+    ) -> Result<Converted, String> {
+        let peer = update.peer_address.parse::<IpAddr>().map_err(|_| {
+            format!("invalid peer address '{}'", update.peer_address)
+        })?;
+        let peer_asn = Asn::from_u32(update.peer_asn);
+        let mut converter = self.converter.lock().unwrap();
+        match update.event {
+            Some(Event::BgpUpdate(raw)) => {
+                converter.convert_update(producer, peer, peer_asn, &raw)
+            }
+            Some(Event::PeerDown(_)) => {
+                Ok(converter.peer_down(producer, peer, peer_asn))
+            }
+            None => Err("update without an event".into()),
+        }
+    }
+}
+
+//------------ Push ----------------------------------------------------------
+
+/// The `Push` method for a call of a producer.
+struct Push {
+    server: Arc<GrpcServer>,
+    producer: String,
+}
+
+impl StreamingService<RouteUpdate> for Push {
+    type Response = PushAck;
+    type ResponseStream = BoxStream<'static, Result<PushAck, Status>>;
+    type Future = BoxFuture<
+        'static,
+        Result<tonic::Response<Self::ResponseStream>, Status>,
+    >;
+
+    fn call(
+        &mut self,
+        request: tonic::Request<Streaming<RouteUpdate>>,
+    ) -> Self::Future {
+        let acks = self
+            .server
+            .clone()
+            .push(self.producer.clone(), request.into_inner());
+        Box::pin(async move { Ok(tonic::Response::new(acks)) })
+    }
+}
+
+//------------ GrpcInMetrics -------------------------------------------------
+
+#[derive(Debug, Default)]
+struct GrpcInMetrics {
+    gate: Arc<GateMetrics>,
+    connected_producers: AtomicUsize,
+    num_connections: AtomicUsize,
+    num_messages: AtomicUsize,
+    num_invalid_messages: AtomicUsize,
+    num_announcements: AtomicUsize,
+    num_withdrawals: AtomicUsize,
+}
+
+impl GrpcInMetrics {
+    fn new(gate: &Gate) -> Self {
+        Self {
+            gate: gate.metrics(),
+            ..Default::default()
+        }
+    }
+
+    const CONNECTED_PRODUCERS_METRIC: Metric = Metric::new(
+        "grpc_in_connected_producers",
+        "the number of gRPC connections currently open",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const NUM_CONNECTIONS_METRIC: Metric = Metric::new(
+        "grpc_in_num_connections",
+        "the number of gRPC connections accepted",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_MESSAGES_METRIC: Metric = Metric::new(
+        "grpc_in_num_messages",
+        "the number of route updates received over gRPC",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_INVALID_MESSAGES_METRIC: Metric = Metric::new(
+        "grpc_in_num_invalid_messages",
+        "the number of route updates received over gRPC that were rejected",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ANNOUNCEMENTS_METRIC: Metric = Metric::new(
+        "grpc_in_num_announcements",
+        "the number of route announcements received over gRPC",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WITHDRAWALS_METRIC: Metric = Metric::new(
+        "grpc_in_num_withdrawals",
+        "the number of route withdrawals received over gRPC",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for GrpcInMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        self.gate.append(unit_name, target);
+        target.append_simple(
+            &Self::CONNECTED_PRODUCERS_METRIC,
+            Some(unit_name),
+            self.connected_producers.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_CONNECTIONS_METRIC,
+            Some(unit_name),
+            self.num_connections.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_INVALID_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_invalid_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ANNOUNCEMENTS_METRIC,
+            Some(unit_name),
+            self.num_announcements.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WITHDRAWALS_METRIC,
+            Some(unit_name),
+            self.num_withdrawals.load(SeqCst),
+        );
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use hyper::http::uri::PathAndQuery;
+    use tonic::{client, transport::Endpoint};
+
+    use crate::bgp::encode::RAW_UPDATE;
+
+    use super::{super::proto::PeerDown, *};
+
+    #[test]
+    fn config_deserialization() {
+        let config: GrpcIn =
+            toml::from_str(r#"listen = "127.0.0.1:50051""#).unwrap();
+        assert_eq!(config.max_message_size, 4 * 1024 * 1024);
+        assert_eq!(config.window_size, 1024 * 1024);
+        assert_eq!(config.max_concurrent_streams, 16);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pushed_updates_are_acknowledged() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (gate, _agent) = Gate::new(0);
+        let ingresses = Arc::new(ingress::Register::default());
+        let parent_id = ingresses.register();
+        let server = Arc::new(GrpcServer {
+            config: toml::from_str(&format!(r#"listen = "{addr}""#)).unwrap(),
+            gate: gate.clone(),
+            converter: Mutex::new(Converter::new(
+                ingresses.clone(),
+                parent_id,
+                "gRPC producer",
+            )),
+            metrics: Arc::new(GrpcInMetrics::new(&gate)),
+        });
+        let serving = tokio::spawn(server.clone().serve(listener));
+
+        let channel = Endpoint::from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = client::Grpc::new(channel);
+
+        let updates = vec![
+            RouteUpdate {
+                sequence: 1,
+                peer_address: "192.0.2.1".into(),
+                peer_asn: 65000,
+                event: Some(Event::BgpUpdate(
+                    hex::decode(RAW_UPDATE).unwrap().into(),
+                )),
+            },
+            RouteUpdate {
+                sequence: 0,
+                peer_address: "192.0.2.1".into(),
+                peer_asn: 65000,
+                event: Some(Event::PeerDown(PeerDown {})),
+            },
+            RouteUpdate {
+                sequence: 2,
+                peer_address: "192.0.2.1".into(),
+                peer_asn: 65000,
+                event: Some(Event::BgpUpdate(Bytes::from_static(b"x"))),
+            },
+        ];
+        let mut request = tonic::Request::new(futures::stream::iter(updates));
+        request
+            .metadata_mut()
+            .insert("rotonda-client", "collector-1".parse().unwrap());
+        client.ready().await.unwrap();
+        let mut acks = client
+            .streaming(
+                request,
+                PathAndQuery::from_static(PUSH_PATH),
+                ProstCodec::<RouteUpdate, PushAck>::default(),
+            )
+            .await
+            .unwrap()
+            .into_inner();
+
+        // The first and last update are acknowledged, the peer down isn't
+        // as it has no sequence number.
+        let ack = acks.message().await.unwrap().unwrap();
+        assert_eq!(
+            (ack.sequence, ack.announcements, ack.withdrawals),
+            (1, 1, 1)
+        );
+        let ack = acks.message().await.unwrap().unwrap();
+        assert_eq!(ack.sequence, 2);
+        assert!(!ack.error.is_empty());
+        assert!(acks.message().await.unwrap().is_none());
+        assert_eq!(server.metrics.num_messages.load(SeqCst), 3);
+        assert_eq!(server.metrics.num_invalid_messages.load(SeqCst), 1);
+
+        // The peer is registered under the producer.
+        let peer = ingresses
+            .find_all(|info| info.remote_asn == Some(Asn::from_u32(65000)))
+            .pop()
+            .unwrap();
+        let producer = ingresses.get(peer).unwrap().parent_ingress.unwrap();
+        let info = ingresses.get(producer).unwrap();
+        assert_eq!(info.name.as_deref(), Some("collector-1"));
+
+        // Other methods are not implemented.
+        client.ready().await.unwrap();
+        let status = client
+            .unary(
+                tonic::Request::new(RouteUpdate::default()),
+                PathAndQuery::from_static("/other.Service/Call"),
+                ProstCodec::<RouteUpdate, PushAck>::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unimplemented);
+        serving.abort();
+    }
+}
@@ -0,0 +1,381 @@
+//! The JSON body of a request to the `http-in` unit.
+//!
+//! A batch contains updates of one or more peers, each announcing and
+//! withdrawing prefixes like a BGP UPDATE message would, or reporting that
+//! the session with the peer went down:
+//!
+//! ```json
+//! {
+//!     "updates": [
+//!         {
+//!             "peer_address": "192.0.2.1",
+//!             "peer_asn": 65000,
+//!             "announce": ["198.51.100.0/24"],
+//!             "withdraw": ["203.0.113.0/24"],
+//!             "attributes": {
+//!                 "origin": "igp",
+//!                 "as_path": [65000, 65001],
+//!                 "next_hop": "192.0.2.1",
+//!                 "communities": ["65000:1", "NO_EXPORT"]
+//!             }
+//!         },
+//!         { "peer_address": "192.0.2.2", "peer_asn": 65002, "peer_down": true }
+//!     ]
+//! }
+//! ```
+
+use std::net::IpAddr;
+
+use inetnum::{addr::Prefix, asn::Asn};
+use routecore::bgp::{
+    communities::StandardCommunity, message::PduParseInfo,
+    path_attributes::OwnedPathAttributes, types::PathAttributeType,
+};
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::payload::{RotondaPaMap, RotondaRoute};
+
+//------------ Batch ---------------------------------------------------------
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Batch {
+    pub updates: Vec<PeerUpdate>,
+}
+
+/// The routes announced and withdrawn by a single peer.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerUpdate {
+    pub peer_address: IpAddr,
+    pub peer_asn: u32,
+
+    #[serde(default)]
+    pub announce: Vec<Prefix>,
+
+    #[serde(default)]
+    pub withdraw: Vec<Prefix>,
+
+    /// The path attributes of the announced routes.
+    #[serde(default)]
+    pub attributes: Attributes,
+
+    /// Whether the session with the peer went down, withdrawing all its
+    /// routes.
+    #[serde(default)]
+    pub peer_down: bool,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Attributes {
+    #[serde(default)]
+    pub origin: Origin,
+
+    /// The AS path, left empty for routes originated by the peer's iBGP
+    /// neighbours.
+    #[serde(default)]
+    pub as_path: Vec<u32>,
+
+    /// The next hop, required for announcements. It must be of the same
+    /// address family as the announced prefixes.
+    pub next_hop: Option<IpAddr>,
+
+    pub med: Option<u32>,
+    pub local_pref: Option<u32>,
+
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[serde(default)]
+    pub communities: Vec<StandardCommunity>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    #[default]
+    Igp,
+    Egp,
+    Incomplete,
+}
+
+/// The routes of a peer update, ready to be sent downstream.
+pub struct Routes {
+    pub announced: Vec<RotondaRoute>,
+    pub withdrawn: Vec<RotondaRoute>,
+}
+
+impl PeerUpdate {
+    pub fn peer_asn(&self) -> Asn {
+        Asn::from_u32(self.peer_asn)
+    }
+
+    /// Check the update and turn it into routes.
+    pub fn routes(&self) -> Result<Routes, String> {
+        if self.peer_down {
+            if !self.announce.is_empty() || !self.withdraw.is_empty() {
+                return Err(format!(
+                    "peer {}: peer_down cannot be combined with \
+                    announcements or withdrawals",
+                    self.peer_address
+                ));
+            }
+            return Ok(Routes {
+                announced: vec![],
+                withdrawn: vec![],
+            });
+        }
+
+        let announced = if self.announce.is_empty() {
+            vec![]
+        } else {
+            let next_hop = self.attributes.next_hop.ok_or_else(|| {
+                format!(
+                    "peer {}: announcements need a next_hop",
+                    self.peer_address
+                )
+            })?;
+            let pamap = self.attributes.to_pamap(next_hop);
+            self.announce
+                .iter()
+                .map(|prefix| {
+                    if prefix.is_v4() != next_hop.is_ipv4() {
+                        return Err(format!(
+                            "prefix {prefix} and next hop {next_hop} are of \
+                            different address families"
+                        ));
+                    }
+                    route(*prefix, pamap.clone())
+                })
+                .collect::<Result<_, _>>()?
+        };
+
+        // Withdrawn routes have no attributes.
+        let pamap = RotondaPaMap::new(OwnedPathAttributes::new(
+            PduParseInfo::modern(),
+            vec![],
+        ));
+        let withdrawn = self
+            .withdraw
+            .iter()
+            .map(|prefix| route(*prefix, pamap.clone()))
+            .collect::<Result<_, _>>()?;
+        Ok(Routes {
+            announced,
+            withdrawn,
+        })
+    }
+}
+
+fn route(
+    prefix: Prefix,
+    pamap: RotondaPaMap,
+) -> Result<RotondaRoute, String> {
+    let invalid = |_| format!("invalid prefix {prefix}");
+    Ok(if prefix.is_v4() {
+        RotondaRoute::Ipv4Unicast(prefix.try_into().map_err(invalid)?, pamap)
+    } else {
+        RotondaRoute::Ipv6Unicast(prefix.try_into().map_err(invalid)?, pamap)
+    })
+}
+
+impl Attributes {
+    /// Encode the attributes as they would be in a BGP UPDATE message with
+    /// four octet AS numbers.
+    fn to_pamap(&self, next_hop: IpAddr) -> RotondaPaMap {
+        let mut raw = vec![];
+
+        let origin = match self.origin {
+            Origin::Igp => 0,
+            Origin::Egp => 1,
+            Origin::Incomplete => 2,
+        };
+        push_attribute(&mut raw, PathAttributeType::Origin, &[origin]);
+
+        // AS_SEQUENCE segments hold at most 255 AS numbers each.
+        let mut as_path = vec![];
+        for segment in self.as_path.chunks(255) {
+            as_path.push(2);
+            as_path.push(segment.len() as u8);
+            for asn in segment {
+                as_path.extend_from_slice(&asn.to_be_bytes());
+            }
+        }
+        push_attribute(&mut raw, PathAttributeType::AsPath, &as_path);
+
+        match next_hop {
+            IpAddr::V4(addr) => push_attribute(
+                &mut raw,
+                PathAttributeType::ConventionalNextHop,
+                &addr.octets(),
+            ),
+            IpAddr::V6(addr) => {
+                // The next hop of IPv6 routes goes into MP_REACH_NLRI, here
+                // without the NLRI as the routes are kept separately.
+                let mut mp_reach = vec![0, 2, 1, 16];
+                mp_reach.extend_from_slice(&addr.octets());
+                mp_reach.push(0);
+                push_attribute(
+                    &mut raw,
+                    PathAttributeType::MpReachNlri,
+                    &mp_reach,
+                );
+            }
+        }
+        if let Some(med) = self.med {
+            push_attribute(
+                &mut raw,
+                PathAttributeType::MultiExitDisc,
+                &med.to_be_bytes(),
+            );
+        }
+        if let Some(local_pref) = self.local_pref {
+            push_attribute(
+                &mut raw,
+                PathAttributeType::LocalPref,
+                &local_pref.to_be_bytes(),
+            );
+        }
+        if !self.communities.is_empty() {
+            let communities = self
+                .communities
+                .iter()
+                .flat_map(|community| community.to_raw())
+                .collect::<Vec<_>>();
+            push_attribute(
+                &mut raw,
+                PathAttributeType::Communities,
+                &communities,
+            );
+        }
+        RotondaPaMap::new(OwnedPathAttributes::new(
+            PduParseInfo::modern(),
+            raw,
+        ))
+    }
+}
+
+fn push_attribute(
+    raw: &mut Vec<u8>,
+    type_code: PathAttributeType,
+    value: &[u8],
+) {
+    let mut flags = match type_code {
+        PathAttributeType::MultiExitDisc | PathAttributeType::MpReachNlri => {
+            0b1000_0000 // optional, non-transitive
+        }
+        PathAttributeType::Communities => 0b1100_0000,
+        _ => 0b0100_0000, // well-known
+    };
+    if value.len() > 255 {
+        flags |= 0b0001_0000; // extended length
+    }
+    raw.push(flags);
+    raw.push(type_code.into());
+    if value.len() > 255 {
+        raw.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    } else {
+        raw.push(value.len() as u8);
+    }
+    raw.extend_from_slice(value);
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use routecore::bgp::{
+        aspath::HopPath, path_attributes::PathAttribute,
+        types::ConventionalNextHop,
+    };
+
+    use super::*;
+
+    fn batch(json: &str) -> Result<Vec<Routes>, String> {
+        let batch: Batch =
+            serde_json::from_str(json).map_err(|err| err.to_string())?;
+        batch.updates.iter().map(PeerUpdate::routes).collect()
+    }
+
+    #[test]
+    fn attributes_are_encoded() {
+        let routes = batch(
+            r#"{ "updates": [{
+                "peer_address": "192.0.2.1",
+                "peer_asn": 65000,
+                "announce": ["198.51.100.0/24", "203.0.113.0/24"],
+                "withdraw": ["2001:db8::/32"],
+                "attributes": {
+                    "origin": "incomplete",
+                    "as_path": [65000, 4200000000],
+                    "next_hop": "192.0.2.1",
+                    "med": 10,
+                    "local_pref": 200,
+                    "communities": ["65000:1", "NO_EXPORT"]
+                }
+            }]}"#,
+        )
+        .unwrap()
+        .pop()
+        .unwrap();
+        assert_eq!(routes.announced.len(), 2);
+        assert_eq!(routes.withdrawn.len(), 1);
+
+        let pamap = routes.announced[0].rotonda_pamap().path_attributes();
+        let attributes = pamap
+            .iter()
+            .map(|attr| attr.unwrap().to_owned().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(attributes.len(), 6);
+        assert!(matches!(
+            &attributes[1],
+            PathAttribute::AsPath(path)
+                if *path == HopPath::from(vec![
+                    Asn::from_u32(65000),
+                    Asn::from_u32(4200000000)
+                ])
+        ));
+        assert_eq!(
+            pamap.get::<ConventionalNextHop>(),
+            Some(ConventionalNextHop([192, 0, 2, 1].into()))
+        );
+        assert!(matches!(
+            &attributes[5],
+            PathAttribute::StandardCommunities(list)
+                if list.communities().len() == 2
+        ));
+    }
+
+    #[test]
+    fn invalid_updates_are_rejected() {
+        // Unknown fields, a missing or mismatched next hop, and peer down
+        // together with routes.
+        for json in [
+            r#"{ "updates": [{ "peer_address": "192.0.2.1",
+                "peer_asn": 65000, "announced": [] }]}"#,
+            r#"{ "updates": [{ "peer_address": "192.0.2.1",
+                "peer_asn": 65000, "announce": ["198.51.100.0/24"] }]}"#,
+            r#"{ "updates": [{ "peer_address": "192.0.2.1",
+                "peer_asn": 65000, "announce": ["198.51.100.0/24"],
+                "attributes": { "next_hop": "2001:db8::1" } }]}"#,
+            r#"{ "updates": [{ "peer_address": "192.0.2.1",
+                "peer_asn": 65000, "withdraw": ["198.51.100.0/24"],
+                "peer_down": true }]}"#,
+            r#"{ "updates": [{ "peer_address": "192.0.2.1",
+                "peer_asn": 65000, "announce": ["198.51.100.0/24"],
+                "attributes": { "next_hop": "192.0.2.1",
+                "communities": ["nonsense"] } }]}"#,
+        ] {
+            assert!(batch(json).is_err(), "{json}");
+        }
+
+        let routes = batch(
+            r#"{ "updates": [{ "peer_address": "2001:db8::1",
+                "peer_asn": 65000, "announce": ["2001:db8:1::/48"],
+                "attributes": { "next_hop": "2001:db8::1" } }]}"#,
+        )
+        .unwrap();
+        assert_eq!(routes[0].announced.len(), 1);
+    }
+}
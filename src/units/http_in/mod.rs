@@ -0,0 +1,4 @@
+pub(crate) mod batch;
+pub mod unit;
+
+pub use unit::HttpIn;
@@ -0,0 +1,587 @@
+//! Ingesting routes posted over HTTP.
+//!
+//! This unit runs an HTTP server accepting batches of route updates in a
+//! JSON body POSTed to `/routes`, optionally gzip compressed, which is handy
+//! for injecting routes from scripts and tests. See the [`batch`] module
+//! for the format of the body.
+//!
+//! Callers authenticate with a bearer token, of which any number can be
+//! configured, each under the name of the caller. Every caller is
+//! registered as an ingress of the unit, by that name or, without tokens,
+//! by its address, and every peer as an ingress of its caller.
+//!
+//! A batch is checked completely before any of it is sent downstream, so a
+//! rejected batch has no effect. The response to an accepted batch gives
+//! the number of announcements and withdrawals sent downstream.
+//!
+//! TLS is not supported, and must be terminated in front of Rotonda if
+//! needed.
+//!
+//! [`batch`]: super::batch
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    io::Read,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc, Mutex,
+    },
+};
+
+use flate2::read::GzDecoder;
+use hyper::{
+    body::HttpBody, header, server::conn::Http, service::service_fn, Body,
+    Method, Request, Response, StatusCode,
+};
+use log::{debug, error, warn};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::net::TcpListener;
+
+use crate::{
+    comms::{Gate, GateMetrics, GateStatus, Terminated},
+    ingress::IngressInfo,
+    manager::{Component, WaitPoint},
+    metrics::{self, Metric, MetricType, MetricUnit},
+    units::ris_live_in::unit::{Converted, Converter},
+};
+
+use super::batch::{Batch, Routes};
+
+/// The path to post batches to.
+const ROUTES_PATH: &str = "/routes";
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct HttpIn {
+    /// The address to listen on for HTTP connections.
+    pub listen: SocketAddr,
+
+    /// The bearer tokens of the callers allowed to post, by caller name.
+    /// Without any, anyone who can connect can post.
+    #[serde(default)]
+    pub tokens: HashMap<String, String>,
+
+    /// The largest request body accepted, in bytes, after decompression.
+    #[serde(default = "HttpIn::default_max_body_size")]
+    pub max_body_size: usize,
+}
+
+impl HttpIn {
+    fn default_max_body_size() -> usize {
+        16 * 1024 * 1024
+    }
+
+    pub async fn run(
+        self,
+        mut component: Component,
+        gate: Gate,
+        mut waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let metrics = Arc::new(HttpInMetrics::new(&gate));
+        component.register_metrics(metrics.clone());
+
+        let listener = match TcpListener::bind(self.listen).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!(
+                    "Unit {}: cannot listen on {}: {err}",
+                    component.name(),
+                    self.listen
+                );
+                return Err(Terminated);
+            }
+        };
+        if self.tokens.is_empty() {
+            warn!(
+                "Unit {}: no tokens configured, anyone who can connect to {} \
+                can post routes",
+                component.name(),
+                self.listen
+            );
+        }
+
+        gate.process_until(waitpoint.ready()).await?;
+        waitpoint.running().await;
+
+        let ingresses = component.ingresses().clone();
+        let parent_id = ingresses.register();
+        ingresses.update_info(
+            parent_id,
+            IngressInfo::new()
+                .with_unit_name(component.name().as_ref())
+                .with_desc("http-in unit"),
+        );
+
+        let server = Arc::new(HttpInServer {
+            config: self,
+            gate: gate.clone(),
+            converter: Mutex::new(Converter::new(
+                ingresses,
+                parent_id,
+                "HTTP caller",
+            )),
+            metrics,
+        });
+        let listener = tokio::spawn(server.serve(listener));
+
+        // The connections are handled in their own tasks, so here only the
+        // gate needs to be processed.
+        let res = loop {
+            match gate.process().await {
+                Ok(GateStatus::ReportLinks { report }) => {
+                    report.declare_source();
+                }
+                Ok(GateStatus::Reconfiguring { .. }) => {
+                    warn!(
+                        "Unit {}: reconfiguring http-in requires a restart",
+                        component.name()
+                    );
+                }
+                Ok(_) => {}
+                Err(Terminated) => break Err(Terminated),
+            }
+        };
+        listener.abort();
+        res
+    }
+}
+
+//------------ HttpInServer --------------------------------------------------
+
+/// The state shared by all connections.
+struct HttpInServer {
+    config: HttpIn,
+    gate: Gate,
+
+    /// Turns the batches into routes, keeping the caller and peer
+    /// ingresses.
+    converter: Mutex<Converter>,
+
+    metrics: Arc<HttpInMetrics>,
+}
+
+impl HttpInServer {
+    async fn serve(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(res) => res,
+                Err(err) => {
+                    warn!("Failed to accept HTTP connection: {err}");
+                    continue;
+                }
+            };
+            let server = self.clone();
+            let connection = Http::new().serve_connection(
+                stream,
+                service_fn(move |req| server.clone().handle(req, addr)),
+            );
+            tokio::spawn(async move {
+                if let Err(err) = connection.await {
+                    debug!("HTTP connection from {addr} failed: {err}");
+                }
+            });
+        }
+    }
+
+    /// Handle a single request.
+    async fn handle(
+        self: Arc<Self>,
+        req: Request<Body>,
+        addr: SocketAddr,
+    ) -> Result<Response<Body>, Infallible> {
+        if req.uri().path() != ROUTES_PATH {
+            return Ok(error_response(StatusCode::NOT_FOUND, "not found"));
+        }
+        if req.method() != Method::POST {
+            return Ok(error_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method not allowed",
+            ));
+        }
+        self.metrics.num_requests.fetch_add(1, SeqCst);
+        let res = match self.authenticate(&req) {
+            Some(caller) => {
+                let caller =
+                    caller.map_or_else(|| addr.ip().to_string(), Into::into);
+                self.post(req, &caller).await
+            }
+            None => {
+                let mut res = error_response(
+                    StatusCode::UNAUTHORIZED,
+                    "missing or invalid bearer token",
+                );
+                res.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    header::HeaderValue::from_static("Bearer"),
+                );
+                res
+            }
+        };
+        if !res.status().is_success() {
+            self.metrics.num_rejected_requests.fetch_add(1, SeqCst);
+        }
+        Ok(res)
+    }
+
+    /// Returns the name of the caller, or `Some(None)` if no tokens are
+    /// configured.
+    fn authenticate(&self, req: &Request<Body>) -> Option<Option<&str>> {
+        if self.config.tokens.is_empty() {
+            return Some(None);
+        }
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")?
+            .trim();
+        self.config
+            .tokens
+            .iter()
+            .find(|(_, known)| {
+                constant_time_eq(known.as_bytes(), token.as_bytes())
+            })
+            .map(|(name, _)| Some(name.as_str()))
+    }
+
+    /// Process a posted batch.
+    async fn post(&self, req: Request<Body>, caller: &str) -> Response<Body> {
+        let gzip = match req.headers().get(header::CONTENT_ENCODING) {
+            None => false,
+            Some(value) if value == "gzip" => true,
+            Some(_) => {
+                return error_response(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "only gzip content encoding is supported",
+                )
+            }
+        };
+        let body = match self.read_body(req.into_body(), gzip).await {
+            Ok(body) => body,
+            Err(res) => return res,
+        };
+
+        let batch: Batch = match serde_json::from_slice(&body) {
+            Ok(batch) => batch,
+            Err(err) => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    &err.to_string(),
+                )
+            }
+        };
+        let routes = match batch
+            .updates
+            .iter()
+            .map(|update| update.routes())
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(routes) => routes,
+            Err(err) => return error_response(StatusCode::BAD_REQUEST, &err),
+        };
+
+        let mut counts = (0, 0);
+        for (
+            update,
+            Routes {
+                announced,
+                withdrawn,
+            },
+        ) in batch.updates.iter().zip(routes)
+        {
+            let converted = {
+                let mut converter = self.converter.lock().unwrap();
+                if update.peer_down {
+                    converter.peer_down(
+                        caller,
+                        update.peer_address,
+                        update.peer_asn(),
+                    )
+                } else {
+                    converter.convert_routes(
+                        caller,
+                        update.peer_address,
+                        update.peer_asn(),
+                        announced,
+                        withdrawn,
+                    )
+                }
+            };
+            if let Converted::Update(update, announced, withdrawn) = converted
+            {
+                counts.0 += announced;
+                counts.1 += withdrawn;
+                self.gate.update_data(update).await;
+            }
+        }
+        self.metrics.num_announcements.fetch_add(counts.0, SeqCst);
+        self.metrics.num_withdrawals.fetch_add(counts.1, SeqCst);
+        json_response(
+            StatusCode::OK,
+            json!({ "announcements": counts.0, "withdrawals": counts.1 }),
+        )
+    }
+
+    /// Read the body, up to the configured maximum size.
+    async fn read_body(
+        &self,
+        mut body: Body,
+        gzip: bool,
+    ) -> Result<Vec<u8>, Response<Body>> {
+        let max = self.config.max_body_size;
+        let too_large = || {
+            error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                &format!("request bodies are limited to {max} bytes"),
+            )
+        };
+        let mut data = vec![];
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|err| {
+                error_response(StatusCode::BAD_REQUEST, &err.to_string())
+            })?;
+            if data.len() + chunk.len() > max {
+                return Err(too_large());
+            }
+            data.extend_from_slice(&chunk);
+        }
+        if !gzip {
+            return Ok(data);
+        }
+        let mut decoded = vec![];
+        GzDecoder::new(data.as_slice())
+            .take(max as u64 + 1)
+            .read_to_end(&mut decoded)
+            .map_err(|err| {
+                error_response(
+                    StatusCode::BAD_REQUEST,
+                    &format!("invalid gzip data: {err}"),
+                )
+            })?;
+        if decoded.len() > max {
+            return Err(too_large());
+        }
+        Ok(decoded)
+    }
+}
+
+/// Compare `a` and `b` in a time independent of where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn json_response(
+    status: StatusCode,
+    body: serde_json::Value,
+) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.to_string().into())
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, error: &str) -> Response<Body> {
+    json_response(status, json!({ "error": error }))
+}
+
+//------------ HttpInMetrics -------------------------------------------------
+
+#[derive(Debug, Default)]
+struct HttpInMetrics {
+    gate: Arc<GateMetrics>,
+    num_requests: AtomicUsize,
+    num_rejected_requests: AtomicUsize,
+    num_announcements: AtomicUsize,
+    num_withdrawals: AtomicUsize,
+}
+
+impl HttpInMetrics {
+    fn new(gate: &Gate) -> Self {
+        Self {
+            gate: gate.metrics(),
+            ..Default::default()
+        }
+    }
+
+    const NUM_REQUESTS_METRIC: Metric = Metric::new(
+        "http_in_num_requests",
+        "the number of batches posted",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_REJECTED_REQUESTS_METRIC: Metric = Metric::new(
+        "http_in_num_rejected_requests",
+        "the number of batches rejected as unauthorized or invalid",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ANNOUNCEMENTS_METRIC: Metric = Metric::new(
+        "http_in_num_announcements",
+        "the number of route announcements posted",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WITHDRAWALS_METRIC: Metric = Metric::new(
+        "http_in_num_withdrawals",
+        "the number of route withdrawals posted",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for HttpInMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        self.gate.append(unit_name, target);
+        target.append_simple(
+            &Self::NUM_REQUESTS_METRIC,
+            Some(unit_name),
+            self.num_requests.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_REJECTED_REQUESTS_METRIC,
+            Some(unit_name),
+            self.num_rejected_requests.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ANNOUNCEMENTS_METRIC,
+            Some(unit_name),
+            self.num_announcements.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WITHDRAWALS_METRIC,
+            Some(unit_name),
+            self.num_withdrawals.load(SeqCst),
+        );
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use inetnum::asn::Asn;
+
+    use crate::ingress;
+
+    use super::*;
+
+    const BATCH: &str = r#"{ "updates": [{
+        "peer_address": "192.0.2.1",
+        "peer_asn": 65000,
+        "announce": ["198.51.100.0/24", "203.0.113.0/24"],
+        "withdraw": ["192.0.2.0/24"],
+        "attributes": { "as_path": [65000], "next_hop": "192.0.2.1" }
+    }]}"#;
+
+    /// Post `body` and return the status and JSON body of the response.
+    async fn post(
+        client: &mut hyper::client::conn::SendRequest<Body>,
+        token: &str,
+        body: Vec<u8>,
+        gzip: bool,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut req = Request::post(ROUTES_PATH)
+            .header(header::AUTHORIZATION, format!("Bearer {token}"));
+        if gzip {
+            req = req.header(header::CONTENT_ENCODING, "gzip");
+        }
+        futures::future::poll_fn(|cx| client.poll_ready(cx))
+            .await
+            .unwrap();
+        let res = client
+            .send_request(req.body(Body::from(body)).unwrap())
+            .await
+            .unwrap();
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn config_deserialization() {
+        let config: HttpIn = toml::from_str(
+            r#"
+            listen = "127.0.0.1:8090"
+            tokens = { ci = "secret" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.tokens["ci"], "secret");
+        assert_eq!(config.max_body_size, 16 * 1024 * 1024);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn posted_batches_are_checked_and_forwarded() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (gate, _agent) = Gate::new(0);
+        let ingresses = Arc::new(ingress::Register::default());
+        let parent_id = ingresses.register();
+        let server = Arc::new(HttpInServer {
+            config: toml::from_str(&format!(
+                r#"
+                listen = "{addr}"
+                tokens = {{ ci = "secret" }}
+                "#
+            ))
+            .unwrap(),
+            gate: gate.clone(),
+            converter: Mutex::new(Converter::new(
+                ingresses.clone(),
+                parent_id,
+                "HTTP caller",
+            )),
+            metrics: Arc::new(HttpInMetrics::new(&gate)),
+        });
+        let serving = tokio::spawn(server.clone().serve(listener));
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut client, connection) =
+            hyper::client::conn::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+        let (status, _) =
+            post(&mut client, "wrong", BATCH.into(), false).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let mut gzipped = GzEncoder::new(vec![], Compression::default());
+        gzipped.write_all(BATCH.as_bytes()).unwrap();
+        let (status, body) =
+            post(&mut client, "secret", gzipped.finish().unwrap(), true)
+                .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "announcements": 2, "withdrawals": 1 }));
+
+        // The peer is registered under the caller.
+        let peer = ingresses
+            .find_all(|info| info.remote_asn == Some(Asn::from_u32(65000)))
+            .pop()
+            .unwrap();
+        let caller = ingresses.get(peer).unwrap().parent_ingress.unwrap();
+        let info = ingresses.get(caller).unwrap();
+        assert_eq!(info.name.as_deref(), Some("ci"));
+
+        // A batch with an invalid update is rejected as a whole.
+        let invalid = BATCH.replace(
+            r#"}]}"#,
+            r#"}, { "peer_address": "192.0.2.2", "peer_asn": 65002,
+                "announce": ["198.51.100.0/24"] }]}"#,
+        );
+        let (status, _) =
+            post(&mut client, "secret", invalid.into(), false).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(server.metrics.num_announcements.load(SeqCst), 2);
+        assert_eq!(server.metrics.num_requests.load(SeqCst), 3);
+        assert_eq!(server.metrics.num_rejected_requests.load(SeqCst), 2);
+        serving.abort();
+    }
+}
@@ -0,0 +1,152 @@
+//! Decoding the message formats of the message bus input units.
+//!
+//! The [`MessageFormat`]s configured for `kafka-in` are shared by the other
+//! units consuming from a message bus, which decode their messages with a
+//! [`Decoder`]:
+//!
+//! * `json`: RIS Live messages, with or without their `ris_message`
+//!   envelope. The raw BGP message must be included.
+//! * `mrt`: one or more MRT records of the BGP4MP type.
+//! * `bgpupdate`: a single BGP UPDATE message, received from the peer
+//!   given by the `Peer-Address` and `Peer-ASN` message headers. The peers
+//!   are grouped by the [source] of the message, such as the subject or
+//!   routing key it was published with.
+//!
+//! [source]: Message::source
+
+use std::{net::IpAddr, sync::Arc};
+
+use inetnum::asn::Asn;
+use serde_json::Value;
+
+use crate::{
+    comms::Gate,
+    ingress::{self, IngressId},
+    units::{
+        mrt_file_in::{replay::Records, unit::MrtInRunner},
+        ris_live_in::unit::{Converted, Converter},
+    },
+};
+
+use super::unit::MessageFormat;
+
+//------------ Message -------------------------------------------------------
+
+/// A message received from a message bus.
+pub(crate) trait Message {
+    /// The content of the message.
+    fn payload(&self) -> &[u8];
+
+    /// Where the message was published, for grouping its peers.
+    fn source(&self) -> &str;
+
+    /// Returns the value of the header `name`, ignoring case.
+    fn header(&self, name: &str) -> Option<&str>;
+}
+
+//------------ Decoder -------------------------------------------------------
+
+/// Turns messages into routes and sends them downstream.
+pub(crate) struct Decoder {
+    format: MessageFormat,
+    gate: Gate,
+    ingresses: Arc<ingress::Register>,
+    parent_id: IngressId,
+
+    /// The converter for the JSON and BGP UPDATE formats.
+    converter: Converter,
+}
+
+impl Decoder {
+    /// Creates a decoder registering the peers below `parent_id`, grouped
+    /// by message sources described as `source_desc`.
+    pub(crate) fn new(
+        format: MessageFormat,
+        gate: Gate,
+        ingresses: Arc<ingress::Register>,
+        parent_id: IngressId,
+        source_desc: &'static str,
+    ) -> Self {
+        let converter =
+            Converter::new(ingresses.clone(), parent_id, source_desc);
+        Self {
+            format,
+            gate,
+            ingresses,
+            parent_id,
+            converter,
+        }
+    }
+
+    /// Decode `msg`, returning the number of announcements and withdrawals
+    /// sent downstream.
+    pub(crate) async fn decode(
+        &mut self,
+        msg: &impl Message,
+    ) -> Result<(usize, usize), String> {
+        let converted = match &self.format {
+            MessageFormat::Json => {
+                let value: Value = serde_json::from_slice(msg.payload())
+                    .map_err(|err| err.to_string())?;
+                if value["type"] == "ris_message" && value["data"].is_object()
+                {
+                    self.converter.convert_message(value["data"].clone())?
+                } else {
+                    self.converter.convert_message(value)?
+                }
+            }
+            MessageFormat::BgpUpdate => {
+                let peer = msg
+                    .header("Peer-Address")
+                    .ok_or("missing Peer-Address header")?;
+                let peer = peer
+                    .parse::<IpAddr>()
+                    .map_err(|_| format!("invalid peer address '{peer}'"))?;
+                let peer_asn = msg
+                    .header("Peer-ASN")
+                    .ok_or("missing Peer-ASN header")?;
+                let peer_asn = peer_asn
+                    .trim_start_matches("AS")
+                    .parse::<u32>()
+                    .map(Asn::from_u32)
+                    .map_err(|_| format!("invalid peer ASN '{peer_asn}'"))?;
+                self.converter.convert_update(
+                    msg.source(),
+                    peer,
+                    peer_asn,
+                    msg.payload(),
+                )?
+            }
+            MessageFormat::Mrt => {
+                let mut counts = (0, 0);
+                for record in Records::new(msg.payload()) {
+                    if !record.is_bgp4mp() {
+                        continue;
+                    }
+                    let (_, announced, withdrawn) =
+                        MrtInRunner::process_record(
+                            &self.gate,
+                            &self.ingresses,
+                            self.parent_id,
+                            record.raw,
+                        )
+                        .await
+                        .map_err(|err| err.to_string())?;
+                    counts.0 += announced;
+                    counts.1 += withdrawn;
+                }
+                return Ok(counts);
+            }
+            MessageFormat::Custom(format) => {
+                return Err(format!("unsupported message format '{format}'"))
+            }
+        };
+        match converted {
+            Converted::Update(update, announced, withdrawn) => {
+                self.gate.update_data(update).await;
+                Ok((announced, withdrawn))
+            }
+            Converted::Ignored => Ok((0, 0)),
+        }
+    }
+}
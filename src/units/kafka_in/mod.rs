@@ -1,3 +1,4 @@
+pub(crate) mod decoder;
 pub mod unit;
 
-pub use unit::KafkaIn;
\ No newline at end of file
+pub use unit::KafkaIn;
@@ -1,8 +1,8 @@
 use crate::{
     comms::{Gate, GateStatus, Terminated},
     manager::{Component, WaitPoint},
-    payload::{Payload, RouteContext, UpstreamStatus},
-    units::Unit,
+    payload::{Payload, UpstreamStatus},
+    roto_runtime::types::{MrtContext, RouteContext},
 };
 use async_trait::async_trait;
 use chrono::Utc;
@@ -225,7 +225,7 @@ impl KafkaInRunner {
         waitpoint.running().await;
 
         // Start the Kafka consumer task
-        let consumer_task = self.start_consumer_task();
+        let mut consumer_task = self.start_consumer_task();
         
         // Main event loop
         loop {
@@ -235,13 +235,10 @@ impl KafkaInRunner {
                     match gate_result {
                         Ok(status) => {
                             match status {
-                                GateStatus::Reconfiguring { new_config } => {
-                                    if let Unit::KafkaIn(new_kafka_config) = new_config {
-                                        info!("Reconfiguring Kafka consumer");
-                                        // TODO: Implement reconfiguration
-                                        // For now, we'll just log the change
-                                        warn!("Kafka reconfiguration not yet implemented");
-                                    }
+                                GateStatus::Reconfiguring { .. } => {
+                                    // The unit is restarted when its
+                                    // settings change, so only the gate
+                                    // has been replaced.
                                 }
                                 GateStatus::ReportLinks { report } => {
                                     report.set_graph_status(self.gate.metrics());
@@ -344,55 +341,43 @@ impl KafkaInRunner {
     }
     
     fn create_placeholder_payload(message_id: u32) -> Payload {
-        use crate::payload::{RotondaRoute, Provenance};
+        use crate::payload::{RotondaPaMap, RotondaRoute};
+        use crate::roto_runtime::types::Provenance;
         use inetnum::{addr::Prefix, asn::Asn};
-        use routecore::bgp::types::AfiSafiType;
-        use std::str::FromStr;
-        
+        use rotonda_store::prefix_record::RouteStatus;
+        use routecore::bgp::{
+            message::PduParseInfo, path_attributes::OwnedPathAttributes,
+        };
+        use std::{net::IpAddr, str::FromStr};
+
         // Create a placeholder route
-        let prefix = Prefix::from_str(&format!("192.0.2.{}/24", message_id % 256))
+        let prefix = Prefix::from_str(&format!("192.0.{}.0/24", message_id % 256))
             .unwrap_or_else(|_| Prefix::from_str("192.0.2.0/24").unwrap());
-        
-        let route = RotondaRoute::new_with_local_pref(
-            prefix,
-            AfiSafiType::Ipv4Unicast,
-            Some(100),
+
+        let pamap = RotondaPaMap::new(OwnedPathAttributes::new(
+            PduParseInfo::modern(),
+            vec![],
+        ));
+        let route = RotondaRoute::Ipv4Unicast(
+            prefix.try_into().expect("placeholder prefix is IPv4"),
+            pamap,
         );
-        
-        let provenance = Provenance::new(
+
+        let provenance = Provenance::for_bgp(
             message_id, // ingress_id
-            Some(Asn::from_u32(65000 + message_id)), // remote_asn
-            format!("kafka-message-{}", message_id), // connection_id
-        );
-        
-        let context = RouteContext::for_kafka_message(
-            crate::payload::RouteStatus::InConvergence,
-            provenance,
+            IpAddr::from([192, 0, 2, 1]),
+            Asn::from_u32(65000 + message_id),
         );
-        
-        Payload::new(route, context, None)
-    }
-}
 
-// Extension trait for RouteContext to support Kafka messages
-trait RouteContextExt {
-    fn for_kafka_message(
-        status: crate::payload::RouteStatus,
-        provenance: crate::payload::Provenance,
-    ) -> Self;
-}
-
-impl RouteContextExt for RouteContext {
-    fn for_kafka_message(
-        status: crate::payload::RouteStatus,
-        provenance: crate::payload::Provenance,
-    ) -> Self {
-        // For now, use the Fresh context type
-        // In a real implementation, we might want a dedicated Kafka context type
-        RouteContext::Fresh(crate::payload::FreshRouteContext::new(
-            status,
+        // For now, use the MRT context type as we have no BGP UPDATE to
+        // attach to a Fresh context. In a real implementation, we might want
+        // a dedicated Kafka context type.
+        let context = RouteContext::Mrt(MrtContext {
+            status: RouteStatus::Active,
             provenance,
-        ))
+        });
+
+        Payload::new(route, context, None)
     }
 }
 
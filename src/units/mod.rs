@@ -20,12 +20,25 @@
 //------------ Sub-modules ---------------------------------------------------
 //
 // These contain all the actual unit types grouped by shared functionality.
+mod amqp_in;
 pub(crate) mod bgp_tcp_in;
 pub(crate) mod bmp_tcp_in;
+pub(crate) mod cloud_queue_in;
+pub(crate) mod exabgp_in;
 mod filter;
+pub(crate) mod flow_in;
+mod gobgp_in;
+pub(crate) mod grpc_in;
+pub(crate) mod http_in;
 pub(crate) mod kafka_in;
 mod mrt_file_in;
+pub(crate) mod nats_in;
+pub(crate) mod redis_stream_in;
 pub(crate) mod rib_unit;
+pub(crate) mod ris_live_in;
+pub(crate) mod static_routes_in;
+mod unix_in;
+mod zmq_in;
 pub use bmp_tcp_in::unit::TracingMode;
 pub use rib_unit:: unit::{RibType, RibUnit};
 pub mod rtr;
@@ -33,34 +46,82 @@ pub use rtr::client::RtrUpdate;
 
 //------------ Unit ----------------------------------------------------------
 
+use std::collections::HashSet;
+
 use crate::comms::Gate;
 use crate::manager::{Component, WaitPoint};
 use serde::Deserialize;
 
 /// The fundamental entity for data processing.
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, Deserialize)]
 #[serde(tag = "type")]
 pub enum Unit {
+    #[serde(rename = "amqp-in")]
+    AmqpIn(amqp_in::unit::AmqpIn),
+
+    #[serde(rename = "bgpkit-broker-in")]
+    BgpkitBrokerIn(mrt_file_in::broker::BgpkitBrokerIn),
+
     #[serde(rename = "bgp-tcp-in")]
     BgpTcpIn(bgp_tcp_in::unit::BgpTcpIn),
 
     #[serde(rename = "bmp-tcp-in")]
     BmpTcpIn(bmp_tcp_in::unit::BmpTcpIn),
 
+    #[serde(rename = "exabgp-in")]
+    ExabgpIn(exabgp_in::unit::ExabgpIn),
+
     #[serde(rename = "filter")]
     Filter(filter::unit::Filter),
 
+    #[serde(rename = "flow-in")]
+    FlowIn(flow_in::unit::FlowIn),
+
+    #[serde(rename = "gobgp-in")]
+    GobgpIn(gobgp_in::unit::GobgpIn),
+
+    #[serde(rename = "grpc-in")]
+    GrpcIn(grpc_in::unit::GrpcIn),
+
+    #[serde(rename = "http-in")]
+    HttpIn(http_in::unit::HttpIn),
+
     #[serde(rename = "kafka-in")]
     KafkaIn(kafka_in::unit::KafkaIn),
 
+    #[serde(rename = "nats-in")]
+    NatsIn(nats_in::unit::NatsIn),
+
+    #[serde(rename = "pubsub-in")]
+    PubsubIn(cloud_queue_in::pubsub::PubsubIn),
+
+    #[serde(rename = "redis-stream-in")]
+    RedisStreamIn(redis_stream_in::unit::RedisStreamIn),
+
     #[serde(rename = "rib")]
     RibUnit(rib_unit::unit::RibUnit),
 
-    #[serde(rename = "mrt-file-in")]
+    #[serde(rename = "ris-live-in")]
+    RisLiveIn(ris_live_in::unit::RisLiveIn),
+
+    #[serde(rename = "mrt-file-in", alias = "mrt-in")]
     MrtFileIn(mrt_file_in::unit::MrtFileIn),
 
-    #[serde(rename = "rtr-tcp-in")]
+    #[serde(rename = "rtr-tcp-in", alias = "rtr-in")]
     RtrTcpIn(rtr::client::Tcp),
+
+    #[serde(rename = "sqs-in")]
+    SqsIn(cloud_queue_in::sqs::SqsIn),
+
+    #[serde(rename = "static-routes-in")]
+    StaticRoutesIn(static_routes_in::unit::StaticRoutesIn),
+
+    #[serde(rename = "zmq-in")]
+    ZmqIn(zmq_in::unit::ZmqIn),
+
+    #[serde(rename = "unix-in")]
+    UnixIn(unix_in::unit::UnixIn),
 }
 
 impl Unit {
@@ -71,33 +132,90 @@ impl Unit {
         waitpoint: WaitPoint,
     ) {
         let _ = match self {
+            Unit::AmqpIn(unit) => unit.run(component, gate, waitpoint).await,
+            Unit::BgpkitBrokerIn(unit) => {
+                unit.run(component, gate, waitpoint).await
+            }
             Unit::BgpTcpIn(unit) => {
                 unit.run(component, gate, waitpoint).await
             }
             Unit::BmpTcpIn(unit) => {
                 unit.run(component, gate, waitpoint).await
             }
+            Unit::ExabgpIn(unit) => {
+                unit.run(component, gate, waitpoint).await
+            }
             Unit::Filter(unit) => unit.run(component, gate, waitpoint).await,
+            Unit::FlowIn(unit) => unit.run(component, gate, waitpoint).await,
+            Unit::GobgpIn(unit) => unit.run(component, gate, waitpoint).await,
+            Unit::GrpcIn(unit) => unit.run(component, gate, waitpoint).await,
+            Unit::HttpIn(unit) => unit.run(component, gate, waitpoint).await,
             Unit::KafkaIn(unit) => unit.run(component, gate, waitpoint).await,
+            Unit::NatsIn(unit) => unit.run(component, gate, waitpoint).await,
+            Unit::PubsubIn(unit) => {
+                unit.run(component, gate, waitpoint).await
+            }
+            Unit::RedisStreamIn(unit) => {
+                unit.run(component, gate, waitpoint).await
+            }
             Unit::RibUnit(unit) => unit.run(component, gate, waitpoint).await,
+            Unit::RisLiveIn(unit) => {
+                unit.run(component, gate, waitpoint).await
+            }
             Unit::MrtFileIn(unit) => {
                 unit.run(component, gate, waitpoint).await
             }
             Unit::RtrTcpIn(unit) => {
                 unit.run(component, gate, waitpoint).await
             }
+            Unit::SqsIn(unit) => unit.run(component, gate, waitpoint).await,
+            Unit::StaticRoutesIn(unit) => {
+                unit.run(component, gate, waitpoint).await
+            }
+            Unit::ZmqIn(unit) => unit.run(component, gate, waitpoint).await,
+            Unit::UnixIn(unit) => unit.run(component, gate, waitpoint).await,
         };
     }
 
     pub fn type_name(&self) -> &'static str {
         match self {
+            Unit::AmqpIn(_) => "amqp-in",
+            Unit::BgpkitBrokerIn(_) => "bgpkit-broker-in",
             Unit::BgpTcpIn(_) => "bgp-tcp-in",
             Unit::BmpTcpIn(_) => "bmp-tcp-in",
+            Unit::ExabgpIn(_) => "exabgp-in",
             Unit::Filter(_) => "filter",
+            Unit::FlowIn(_) => "flow-in",
+            Unit::GobgpIn(_) => "gobgp-in",
+            Unit::GrpcIn(_) => "grpc-in",
+            Unit::HttpIn(_) => "http-in",
             Unit::KafkaIn(_) => "kafka-in",
+            Unit::NatsIn(_) => "nats-in",
+            Unit::PubsubIn(_) => "pubsub-in",
+            Unit::RedisStreamIn(_) => "redis-stream-in",
             Unit::RibUnit(_) => "rib",
+            Unit::RisLiveIn(_) => "ris-live-in",
             Unit::MrtFileIn(_) => "mrt-file-in",
             Unit::RtrTcpIn(_) => "rtr-tcp-in",
+            Unit::SqsIn(_) => "sqs-in",
+            Unit::StaticRoutesIn(_) => "static-routes-in",
+            Unit::ZmqIn(_) => "zmq-in",
+            Unit::UnixIn(_) => "unix-in",
+        }
+    }
+
+    /// Returns whether a running unit of this type applies a changed
+    /// configuration itself, given the names of the changed settings.
+    ///
+    /// Other units are restarted with their changed configuration.
+    pub fn reconfigures_in_place(&self, changed: &HashSet<String>) -> bool {
+        match self {
+            Unit::BgpTcpIn(_)
+            | Unit::BmpTcpIn(_)
+            | Unit::Filter(_)
+            | Unit::StaticRoutesIn(_) => true,
+            Unit::RibUnit(_) => RibUnit::reconfigures_in_place(changed),
+            _ => false,
         }
     }
 }
@@ -10,6 +10,7 @@ use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot;
 use tokio::time::timeout;
 
+use crate::common::openapi::{self, Schema};
 use crate::http::{
     extract_params, get_param, MatchedParam, PercentDecodedPath, ProcessRequest, QueryParams
 };
@@ -57,6 +58,19 @@ impl ProcessRequest for Processor {
     None
     }
 
+    fn describe(&self, paths: &mut openapi::Paths) {
+        paths
+            .get(
+                format!("{}queue", self.http_api_path),
+                "Queue an MRT file for processing",
+            )
+            .produces("text/plain")
+            .required_query(
+                "file",
+                Schema::String,
+                "The file to process, relative to the update_path",
+            );
+    }
 }
 impl Processor {
     async fn queue(&self, request: &Request<Body>) -> Option<Response<Body>> {
@@ -0,0 +1,388 @@
+//! Finding MRT files with the BGPKIT Broker.
+//!
+//! The [BGPKIT Broker] indexes the MRT archives of RIPE RIS and RouteViews.
+//! The `bgpkit-broker-in` unit asks it for the files of some collectors in
+//! a time range, and has them downloaded and replayed by the machinery of
+//! the `mrt-file-in` unit, so a backfill of historical data takes no more
+//! than a single unit in the configuration.
+//!
+//! [BGPKIT Broker]: https://bgpkit.com/broker
+
+use std::cmp::Ordering;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{debug, error, info};
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+use url::Url;
+
+use crate::comms::{Gate, Terminated};
+use crate::config::ConfigPath;
+use crate::manager::{Component, WaitPoint};
+
+use super::replay::ReplaySpeed;
+use super::unit::{MrtError, MrtFileIn};
+
+/// The number of files to ask the broker for at a time.
+const PAGE_SIZE: usize = 100;
+
+//------------ BgpkitBrokerIn ------------------------------------------------
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BgpkitBrokerIn {
+    /// The broker to query.
+    #[serde(default = "BgpkitBrokerIn::default_broker_url")]
+    pub broker_url: Url,
+
+    /// The collectors to get the files of, e.g. `"rrc00"` or
+    /// `"route-views2"`. Without any, the files of all collectors are used.
+    #[serde(default)]
+    pub collectors: Vec<String>,
+
+    /// The project to limit the files to: `"riperis"` or `"routeviews"`.
+    #[serde(default)]
+    pub project: Option<String>,
+
+    /// The start of the time range, in RFC 3339 format.
+    pub start: DateTime<Utc>,
+
+    /// The end of the time range, in RFC 3339 format.
+    pub end: DateTime<Utc>,
+
+    /// The kind of files to use. Without it, both RIB dumps and updates are
+    /// used.
+    #[serde(default)]
+    pub data_type: Option<DataType>,
+
+    /// Where to keep the downloaded files. Defaults to a directory named
+    /// after the unit in the system temporary directory.
+    #[serde(default)]
+    pub download_dir: Option<ConfigPath>,
+
+    /// How fast to replay the messages in MRT update files.
+    #[serde(default)]
+    pub speed: ReplaySpeed,
+
+    /// Whether to start over once all files are processed.
+    #[serde(default, rename = "loop")]
+    pub repeat: bool,
+}
+
+impl BgpkitBrokerIn {
+    fn default_broker_url() -> Url {
+        Url::parse("https://api.bgpkit.com/v3/broker").unwrap()
+    }
+
+    pub async fn run(
+        self,
+        component: Component,
+        gate: Gate,
+        waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        if self.start >= self.end {
+            error!(
+                "Unit {}: the time range must start before it ends",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+        let query = BrokerQuery {
+            broker_url: self.broker_url,
+            collectors: self.collectors,
+            project: self.project,
+            start: self.start,
+            end: self.end,
+            data_type: self.data_type,
+        };
+        MrtFileIn {
+            filename: Default::default(),
+            urls: vec![],
+            download_dir: self.download_dir,
+            update_path: None,
+            speed: self.speed,
+            repeat: self.repeat,
+            broker: Some(query),
+        }
+        .run(component, gate, waitpoint)
+        .await
+    }
+}
+
+//------------ DataType ------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DataType {
+    Rib,
+    Updates,
+}
+
+impl DataType {
+    fn as_str(self) -> &'static str {
+        match self {
+            DataType::Rib => "rib",
+            DataType::Updates => "updates",
+        }
+    }
+}
+
+//------------ BrokerQuery ---------------------------------------------------
+
+/// A search for MRT files.
+#[derive(Clone, Debug)]
+pub struct BrokerQuery {
+    broker_url: Url,
+    collectors: Vec<String>,
+    project: Option<String>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    data_type: Option<DataType>,
+}
+
+impl BrokerQuery {
+    /// Finds the URLs of the matching files, in the order to replay them.
+    ///
+    /// The files are ordered by the time they start at, with RIB dumps
+    /// before the updates starting at the same time.
+    pub async fn search(
+        &self,
+        client: &HttpClient,
+    ) -> Result<Vec<Url>, MrtError> {
+        let mut files = Vec::new();
+        let collectors = match self.collectors.is_empty() {
+            true => vec![None],
+            false => self.collectors.iter().map(Some).collect(),
+        };
+        for collector in collectors {
+            for page in 1.. {
+                let url = self.search_url(collector, page)?;
+                debug!("querying {url}");
+                let body = client
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .bytes()
+                    .await?;
+                let found = parse_page(&body)?;
+                let done = found.len() < PAGE_SIZE;
+                files.extend(found);
+                if done {
+                    break;
+                }
+            }
+        }
+        files.sort_by(BrokerItem::replay_order);
+        files.dedup_by(|a, b| a.url == b.url);
+        info!(
+            "found {} MRT files between {} and {}",
+            files.len(),
+            self.start,
+            self.end
+        );
+        Ok(files.into_iter().map(|item| item.url).collect())
+    }
+
+    /// The URL for a page of the search results for `collector`.
+    fn search_url(
+        &self,
+        collector: Option<&String>,
+        page: usize,
+    ) -> Result<Url, MrtError> {
+        let mut url = self.broker_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| MrtError::other("invalid broker URL"))?
+            .pop_if_empty()
+            .push("search");
+        {
+            let mut query = url.query_pairs_mut();
+            let time = |t: &DateTime<Utc>| {
+                t.to_rfc3339_opts(SecondsFormat::Secs, true)
+            };
+            query.append_pair("ts_start", &time(&self.start));
+            query.append_pair("ts_end", &time(&self.end));
+            if let Some(collector) = collector {
+                query.append_pair("collector_id", collector);
+            }
+            if let Some(project) = &self.project {
+                query.append_pair("project", project);
+            }
+            if let Some(data_type) = self.data_type {
+                query.append_pair("data_type", data_type.as_str());
+            }
+            query.append_pair("page", &page.to_string());
+            query.append_pair("page_size", &PAGE_SIZE.to_string());
+        }
+        Ok(url)
+    }
+}
+
+//------------ Search results ------------------------------------------------
+
+#[derive(Debug, Deserialize)]
+struct BrokerPage {
+    #[serde(default)]
+    error: Option<String>,
+
+    #[serde(default)]
+    data: Vec<BrokerItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BrokerItem {
+    ts_start: String,
+    collector_id: String,
+    data_type: String,
+    url: Url,
+}
+
+impl BrokerItem {
+    fn replay_order(&self, other: &Self) -> Ordering {
+        // The timestamps are all in the same ISO 8601 format, so they sort
+        // as strings.
+        self.ts_start
+            .cmp(&other.ts_start)
+            .then_with(|| {
+                (self.data_type != "rib").cmp(&(other.data_type != "rib"))
+            })
+            .then_with(|| self.collector_id.cmp(&other.collector_id))
+    }
+}
+
+/// Parses a page of search results.
+fn parse_page(body: &[u8]) -> Result<Vec<BrokerItem>, MrtError> {
+    let page: BrokerPage = serde_json::from_slice(body).map_err(|err| {
+        std::io::Error::other(format!("invalid broker response: {err}"))
+    })?;
+    if let Some(err) = page.error {
+        return Err(std::io::Error::other(format!(
+            "broker reported an error: {err}"
+        ))
+        .into());
+    }
+    Ok(page.data)
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        tests::util::{
+            http::{self, MockServer},
+            https,
+        },
+        units::Unit,
+    };
+
+    use super::*;
+
+    fn config() -> BgpkitBrokerIn {
+        let toml = r#"
+        type = "bgpkit-broker-in"
+        collectors = ["rrc00", "route-views2"]
+        start = "2024-01-01T00:00:00Z"
+        end = "2024-01-01T02:00:00Z"
+        data_type = "updates"
+        "#;
+        let Unit::BgpkitBrokerIn(config) = toml::from_str(toml).unwrap()
+        else {
+            panic!("expected a bgpkit-broker-in unit");
+        };
+        config
+    }
+
+    fn query(config: BgpkitBrokerIn) -> BrokerQuery {
+        BrokerQuery {
+            broker_url: config.broker_url,
+            collectors: config.collectors,
+            project: config.project,
+            start: config.start,
+            end: config.end,
+            data_type: config.data_type,
+        }
+    }
+
+    #[test]
+    fn search_urls_carry_the_query() {
+        let query = query(config());
+        let url = query.search_url(query.collectors.first(), 2).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://api.bgpkit.com/v3/broker/search\
+            ?ts_start=2024-01-01T00%3A00%3A00Z\
+            &ts_end=2024-01-01T02%3A00%3A00Z\
+            &collector_id=rrc00&data_type=updates&page=2&page_size=100"
+        );
+    }
+
+    #[test]
+    fn results_are_ordered_for_replay() {
+        let body = br#"{
+            "count": 4, "page": 1, "page_size": 100, "error": null,
+            "data": [
+                { "ts_start": "2024-01-01T00:05:00", "collector_id": "rrc00",
+                  "data_type": "updates",
+                  "url": "https://data.ris.ripe.net/rrc00/updates.0005.gz" },
+                { "ts_start": "2024-01-01T00:00:00", "collector_id": "rrc00",
+                  "data_type": "updates",
+                  "url": "https://data.ris.ripe.net/rrc00/updates.0000.gz" },
+                { "ts_start": "2024-01-01T00:00:00", "collector_id": "rrc00",
+                  "data_type": "rib",
+                  "url": "https://data.ris.ripe.net/rrc00/bview.0000.gz" }
+            ]
+        }"#;
+        let mut items = parse_page(body).unwrap();
+        items.sort_by(BrokerItem::replay_order);
+        let names = items
+            .iter()
+            .map(|item| {
+                item.url.path_segments().unwrap().next_back().unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["bview.0000.gz", "updates.0000.gz", "updates.0005.gz"]
+        );
+
+        assert!(parse_page(br#"{ "error": "invalid collector" }"#).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn the_broker_is_queried_over_https() {
+        let server = MockServer::start(|_| {
+            http::json(
+                200,
+                &json!({ "data": [
+                    { "ts_start": "2024-01-01T00:00:00",
+                      "collector_id": "rrc00", "data_type": "updates",
+                      "url": "https://data.ris.ripe.net/rrc00/u.0000.gz" },
+                ]}),
+            )
+        })
+        .await;
+        let front = https::front(server.addr).await;
+        let mut query = query(config());
+        query.broker_url =
+            format!("https://localhost:{}/v3/broker", front.port())
+                .parse()
+                .unwrap();
+
+        let urls = query.search(&https::client()).await.unwrap();
+        assert_eq!(
+            urls,
+            [Url::parse("https://data.ris.ripe.net/rrc00/u.0000.gz")
+                .unwrap()]
+        );
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].uri.path(), "/v3/broker/search");
+        assert!(requests[1]
+            .uri
+            .query()
+            .unwrap()
+            .contains("collector_id=route-views2"));
+    }
+}
@@ -0,0 +1,172 @@
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering::SeqCst},
+    Arc,
+};
+
+use crate::{
+    comms::{Gate, GateMetrics, GraphStatus},
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+//------------ MrtInMetrics --------------------------------------------------
+
+/// The progress of an `mrt-file-in` unit through its files.
+#[derive(Debug, Default)]
+pub struct MrtInMetrics {
+    gate: Arc<GateMetrics>,
+    pub num_files_processed: AtomicUsize,
+    pub num_passes: AtomicUsize,
+    pub num_routes: AtomicUsize,
+    pub num_messages: AtomicUsize,
+    pub num_announcements: AtomicUsize,
+    pub num_withdrawals: AtomicUsize,
+
+    /// The size of the file being processed, after decompression.
+    pub file_bytes: AtomicUsize,
+
+    /// How much of the file being processed was replayed.
+    pub file_bytes_processed: AtomicUsize,
+
+    /// The MRT timestamp of the last message replayed, in seconds.
+    pub replay_timestamp: AtomicU64,
+
+    /// How far the last message replayed was behind its schedule.
+    pub replay_lag_millis: AtomicU64,
+}
+
+impl MrtInMetrics {
+    pub fn new(gate: &Gate) -> Self {
+        Self {
+            gate: gate.metrics(),
+            ..Default::default()
+        }
+    }
+
+    const NUM_FILES_PROCESSED_METRIC: Metric = Metric::new(
+        "mrt_file_in_num_files_processed",
+        "the number of MRT files processed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_PASSES_METRIC: Metric = Metric::new(
+        "mrt_file_in_num_passes",
+        "the number of times the replay of all files started over",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ROUTES_METRIC: Metric = Metric::new(
+        "mrt_file_in_num_routes",
+        "the number of routes read from MRT RIB dumps",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_MESSAGES_METRIC: Metric = Metric::new(
+        "mrt_file_in_num_messages",
+        "the number of BGP4MP messages replayed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ANNOUNCEMENTS_METRIC: Metric = Metric::new(
+        "mrt_file_in_num_announcements",
+        "the number of route announcements replayed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WITHDRAWALS_METRIC: Metric = Metric::new(
+        "mrt_file_in_num_withdrawals",
+        "the number of route withdrawals replayed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const FILE_BYTES_METRIC: Metric = Metric::new(
+        "mrt_file_in_file_size",
+        "the decompressed size of the MRT file being processed",
+        MetricType::Gauge,
+        MetricUnit::Byte,
+    );
+    const FILE_BYTES_PROCESSED_METRIC: Metric = Metric::new(
+        "mrt_file_in_file_processed",
+        "how much of the MRT file being processed was replayed",
+        MetricType::Gauge,
+        MetricUnit::Byte,
+    );
+    const REPLAY_TIMESTAMP_METRIC: Metric = Metric::new(
+        "mrt_file_in_replay_timestamp",
+        "the MRT timestamp of the last message replayed",
+        MetricType::Gauge,
+        MetricUnit::Second,
+    );
+    const REPLAY_LAG_METRIC: Metric = Metric::new(
+        "mrt_file_in_replay_lag",
+        "how far the replay is behind the configured speed",
+        MetricType::Gauge,
+        MetricUnit::Millisecond,
+    );
+}
+
+impl GraphStatus for MrtInMetrics {
+    fn status_text(&self) -> String {
+        format!(
+            "files: {}\nmessages: {}\nout: {}",
+            self.num_files_processed.load(SeqCst),
+            self.num_messages.load(SeqCst),
+            self.gate.num_updates.load(SeqCst),
+        )
+    }
+}
+
+impl metrics::Source for MrtInMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        self.gate.append(unit_name, target);
+        target.append_simple(
+            &Self::NUM_FILES_PROCESSED_METRIC,
+            Some(unit_name),
+            self.num_files_processed.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_PASSES_METRIC,
+            Some(unit_name),
+            self.num_passes.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ROUTES_METRIC,
+            Some(unit_name),
+            self.num_routes.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ANNOUNCEMENTS_METRIC,
+            Some(unit_name),
+            self.num_announcements.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WITHDRAWALS_METRIC,
+            Some(unit_name),
+            self.num_withdrawals.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::FILE_BYTES_METRIC,
+            Some(unit_name),
+            self.file_bytes.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::FILE_BYTES_PROCESSED_METRIC,
+            Some(unit_name),
+            self.file_bytes_processed.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::REPLAY_TIMESTAMP_METRIC,
+            Some(unit_name),
+            self.replay_timestamp.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::REPLAY_LAG_METRIC,
+            Some(unit_name),
+            self.replay_lag_millis.load(SeqCst),
+        );
+    }
+}
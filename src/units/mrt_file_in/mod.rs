@@ -1,2 +1,5 @@
 pub mod unit;
 mod api;
+pub mod broker;
+mod metrics;
+pub(crate) mod replay;
@@ -0,0 +1,253 @@
+//! Replaying the BGP4MP messages of MRT files at a controlled speed.
+//!
+//! By default the messages in an MRT update file are pushed into the
+//! pipeline as fast as the units downstream take them. With a replay speed
+//! configured, each message is held back until the time since the first
+//! message of the pass, multiplied by the speed, has caught up with the
+//! time between their MRT timestamps, so that the pipeline sees the updates
+//! with the same spacing as the collector did, or a fixed factor of it.
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+//------------ ReplaySpeed ---------------------------------------------------
+
+/// How fast to replay the messages in MRT files.
+///
+/// Configured as `"max"`, `"realtime"` or a number by which to speed up
+/// (or, below 1, slow down) the replay compared to real time.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(try_from = "ReplaySpeedSpec")]
+pub enum ReplaySpeed {
+    /// As fast as possible, ignoring the timestamps.
+    #[default]
+    Max,
+
+    /// Following the timestamps, sped up by the given factor.
+    Multiplier(f64),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ReplaySpeedSpec {
+    Name(String),
+    Multiplier(f64),
+}
+
+impl TryFrom<ReplaySpeedSpec> for ReplaySpeed {
+    type Error = String;
+
+    fn try_from(spec: ReplaySpeedSpec) -> Result<Self, Self::Error> {
+        match spec {
+            ReplaySpeedSpec::Name(name) => match name.as_str() {
+                "max" => Ok(ReplaySpeed::Max),
+                "realtime" => Ok(ReplaySpeed::Multiplier(1.0)),
+                _ => Err(format!(
+                    "unknown replay speed '{name}', expected 'max', \
+                    'realtime' or a multiplier"
+                )),
+            },
+            ReplaySpeedSpec::Multiplier(m) if m.is_finite() && m > 0.0 => {
+                Ok(ReplaySpeed::Multiplier(m))
+            }
+            ReplaySpeedSpec::Multiplier(m) => Err(format!(
+                "invalid replay speed multiplier {m}, must be above 0"
+            )),
+        }
+    }
+}
+
+//------------ Pacer ---------------------------------------------------------
+
+/// Decides when each message is due according to the [`ReplaySpeed`].
+#[derive(Debug)]
+pub struct Pacer {
+    speed: ReplaySpeed,
+
+    /// When the first message of the pass was replayed, and its timestamp.
+    anchor: Option<(Instant, Duration)>,
+}
+
+impl Pacer {
+    pub fn new(speed: ReplaySpeed) -> Self {
+        Self {
+            speed,
+            anchor: None,
+        }
+    }
+
+    /// Start a new pass, with the next message replayed right away.
+    pub fn reset(&mut self) {
+        self.anchor = None;
+    }
+
+    /// When the message with MRT timestamp `timestamp` is due, or `None`
+    /// if it is due right away because messages are replayed as fast as
+    /// possible.
+    ///
+    /// Messages with a timestamp before that of the first message of the
+    /// pass are due right away.
+    pub fn due(&mut self, timestamp: Duration) -> Option<Instant> {
+        let ReplaySpeed::Multiplier(multiplier) = self.speed else {
+            return None;
+        };
+        let (start, first) = *self
+            .anchor
+            .get_or_insert_with(|| (Instant::now(), timestamp));
+        let offset = timestamp.saturating_sub(first).div_f64(multiplier);
+        Some(start + offset)
+    }
+}
+
+//------------ Records -------------------------------------------------------
+
+/// An MRT record.
+#[derive(Clone, Copy, Debug)]
+pub struct Record<'a> {
+    /// The time the record was written, since the Unix epoch.
+    pub timestamp: Duration,
+
+    pub mrt_type: u16,
+
+    /// The complete record, including the common header.
+    pub raw: &'a [u8],
+}
+
+impl Record<'_> {
+    const BGP4MP: u16 = 16;
+    const BGP4MP_ET: u16 = 17;
+    const ISIS_ET: u16 = 33;
+    const OSPFV3_ET: u16 = 49;
+
+    const HEADER_LEN: usize = 12;
+
+    pub fn is_bgp4mp(&self) -> bool {
+        matches!(self.mrt_type, Self::BGP4MP | Self::BGP4MP_ET)
+    }
+}
+
+/// Iterates over the records in MRT data without parsing them further.
+///
+/// Stops at the first record that is cut short.
+#[derive(Clone, Debug)]
+pub struct Records<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> Records<'a> {
+    pub fn new(raw: &'a [u8]) -> Self {
+        Self { raw }
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.raw.get(..Record::HEADER_LEN)?;
+        let u32_at = |buf: &[u8], pos: usize| {
+            u32::from_be_bytes(buf[pos..pos + 4].try_into().unwrap())
+        };
+        let secs = u32_at(header, 0);
+        let mrt_type = u16::from_be_bytes([header[4], header[5]]);
+        let len = Record::HEADER_LEN + u32_at(header, 8) as usize;
+        let Some(raw) = self.raw.get(..len) else {
+            self.raw = &[];
+            return None;
+        };
+        self.raw = &self.raw[len..];
+
+        // The extended timestamp types carry microseconds at the start of
+        // the message.
+        let micros = match mrt_type {
+            Record::BGP4MP_ET | Record::ISIS_ET | Record::OSPFV3_ET
+                if raw.len() >= Record::HEADER_LEN + 4 =>
+            {
+                u32_at(raw, Record::HEADER_LEN)
+            }
+            _ => 0,
+        };
+        Some(Record {
+            timestamp: Duration::from_secs(secs.into())
+                + Duration::from_micros(micros.into()),
+            mrt_type,
+            raw,
+        })
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Config {
+        speed: ReplaySpeed,
+    }
+
+    fn speed(toml: &str) -> Result<ReplaySpeed, toml::de::Error> {
+        toml::from_str::<Config>(toml).map(|c| c.speed)
+    }
+
+    #[test]
+    fn speeds_by_name_or_multiplier() {
+        assert_eq!(speed(r#"speed = "max""#).unwrap(), ReplaySpeed::Max);
+        assert_eq!(
+            speed(r#"speed = "realtime""#).unwrap(),
+            ReplaySpeed::Multiplier(1.0)
+        );
+        assert_eq!(
+            speed("speed = 10").unwrap(),
+            ReplaySpeed::Multiplier(10.0)
+        );
+        assert_eq!(
+            speed("speed = 0.5").unwrap(),
+            ReplaySpeed::Multiplier(0.5)
+        );
+        assert!(speed(r#"speed = "fast""#).is_err());
+        assert!(speed("speed = 0").is_err());
+        assert!(speed("speed = -2").is_err());
+    }
+
+    #[test]
+    fn messages_are_due_by_their_timestamps() {
+        let mut pacer = Pacer::new(ReplaySpeed::Max);
+        assert_eq!(pacer.due(Duration::from_secs(100)), None);
+
+        let mut pacer = Pacer::new(ReplaySpeed::Multiplier(2.0));
+        let start = pacer.due(Duration::from_secs(100)).unwrap();
+        let later = pacer.due(Duration::from_secs(110)).unwrap();
+        assert_eq!(later - start, Duration::from_secs(5));
+        let earlier = pacer.due(Duration::from_secs(90)).unwrap();
+        assert_eq!(earlier, start);
+
+        pacer.reset();
+        let restart = pacer.due(Duration::from_secs(100)).unwrap();
+        assert!(restart >= start);
+    }
+
+    #[test]
+    fn records_with_extended_timestamps() {
+        let mut raw = vec![];
+        // BGP4MP MESSAGE_AS4, 2 bytes of message
+        raw.extend_from_slice(&[0, 0, 0, 10, 0, 16, 0, 4, 0, 0, 0, 2, 1, 2]);
+        // BGP4MP_ET MESSAGE_AS4, 250ms and 1 byte of message
+        raw.extend_from_slice(&[0, 0, 0, 11, 0, 17, 0, 4, 0, 0, 0, 5]);
+        raw.extend_from_slice(&250_000u32.to_be_bytes());
+        raw.push(3);
+        // TABLE_DUMP_V2, cut short
+        raw.extend_from_slice(&[0, 0, 0, 12, 0, 13, 0, 1, 0, 0, 0, 8, 1]);
+
+        let records = Records::new(&raw).collect::<Vec<_>>();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].timestamp, Duration::from_secs(10));
+        assert_eq!(records[0].raw.len(), 14);
+        assert!(records[0].is_bgp4mp());
+        assert_eq!(records[1].timestamp, Duration::from_millis(11_250));
+        assert_eq!(records[1].raw.len(), 17);
+        assert!(records[1].is_bgp4mp());
+    }
+}
@@ -2,7 +2,8 @@ use std::collections::VecDeque;
 use std::future::{Future, IntoFuture};
 use std::io::Read;
 use std::ops::ControlFlow;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering::SeqCst;
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -20,12 +21,16 @@ use routecore::bgp::types::AfiSafiType;
 use routecore::bgp::workshop::route::RouteWorkshop;
 use routecore::bgp::ParseError;
 use routecore::mrt::MrtFile;
+use reqwest::Client as HttpClient;
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
 use smallvec::SmallVec;
+use tokio::io::AsyncWriteExt;
 use tokio::pin;
+use tokio::sync::mpsc::error::TryRecvError;
 use tokio::sync::{mpsc, oneshot};
 use tokio::task::JoinSet;
+use url::Url;
 
 use crate::config::ConfigPath;
 use crate::roto_runtime::types::{explode_announcements, explode_withdrawals, FreshRouteContext, MrtContext, Provenance, RouteContext};
@@ -37,11 +42,43 @@ use crate::payload::{Payload, RotondaPaMap, RotondaRoute, Update};
 use crate::units::{Gate, Unit};
 
 use super::api;
+use super::broker::BrokerQuery;
+use super::metrics::MrtInMetrics;
+use super::replay::{Pacer, ReplaySpeed, Records};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct MrtFileIn {
+    /// The MRT files to process. A directory stands for all the files in
+    /// it, in the order of their names.
+    #[serde(default)]
     pub filename: OneOrManyPaths,
+
+    /// The URLs of MRT files to download and process, after the files in
+    /// `filename`.
+    #[serde(default)]
+    pub urls: Vec<Url>,
+
+    /// Where to keep the files downloaded from `urls`. Defaults to a
+    /// directory named after the unit in the system temporary directory.
+    #[serde(default)]
+    pub download_dir: Option<ConfigPath>,
+
     pub update_path: Option<ConfigPath>,
+
+    /// How fast to replay the messages in MRT update files: `"max"`,
+    /// `"realtime"`, or a multiplier of real time.
+    #[serde(default)]
+    pub speed: ReplaySpeed,
+
+    /// Whether to start over with all the files processed so far once
+    /// there are no more files queued, e.g. for load testing.
+    #[serde(default, rename = "loop")]
+    pub repeat: bool,
+
+    /// A search for more files to download and process, after those in
+    /// `urls`, as set up by the `bgpkit-broker-in` unit.
+    #[serde(skip)]
+    pub broker: Option<BrokerQuery>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -50,6 +87,12 @@ pub enum OneOrManyPaths {
     One(ConfigPath),
     Many(Vec<ConfigPath>),
 }
+
+impl Default for OneOrManyPaths {
+    fn default() -> Self {
+        OneOrManyPaths::Many(vec![])
+    }
+}
 pub enum PathsIterator<'a> {
     One(Option<PathBuf>),
     Many(std::slice::Iter<'a, ConfigPath>)
@@ -82,6 +125,7 @@ pub struct MrtInRunner {
     gate: Gate,
     ingresses: Arc<ingress::Register>,
     parent_id: IngressId,
+    metrics: Arc<MrtInMetrics>,
     queue_tx: mpsc::Sender<QueueEntry>,
     processing: Option<PathBuf>,
     processed: Vec<(PathBuf, String)>,
@@ -101,6 +145,9 @@ impl MrtFileIn {
         gate: Gate,
         mut waitpoint: WaitPoint,
     ) -> Result<(), crate::comms::Terminated> {
+        let metrics = Arc::new(MrtInMetrics::new(&gate));
+        component.register_metrics(metrics.clone());
+
         gate.process_until(waitpoint.ready()).await?;
         waitpoint.running().await;
 
@@ -116,9 +163,51 @@ impl MrtFileIn {
         );
 
 
-        for f in self.filename.iter() {
-            let _ = queue_tx.send((f, None)).await;
-        }
+        // Queue the files in the background, as a directory may hold more
+        // files than fit in the queue, and downloads take a while.
+        let paths = self.filename.iter().collect::<Vec<_>>();
+        let mut urls = self.urls.clone();
+        let broker = self.broker.clone();
+        let download_dir = self.download_dir.clone().map_or_else(
+            || {
+                std::env::temp_dir()
+                    .join(format!("rotonda-mrt-{}", component.name()))
+            },
+            Into::into,
+        );
+        let http_client = component.http_client().clone();
+        let initial_tx = queue_tx.clone();
+        tokio::spawn(async move {
+            for path in paths {
+                let files = match expand_path(&path) {
+                    Ok(files) => files,
+                    Err(e) => {
+                        error!(
+                            "failed to read {}: {e}",
+                            path.to_string_lossy()
+                        );
+                        continue;
+                    }
+                };
+                for f in files {
+                    let _ = initial_tx.send((f, None)).await;
+                }
+            }
+            if let Some(query) = broker {
+                match query.search(&http_client).await {
+                    Ok(found) => urls.extend(found),
+                    Err(e) => error!("failed to query the BGPKIT Broker: {e}"),
+                }
+            }
+            for url in urls {
+                match download(&http_client, &url, &download_dir).await {
+                    Ok(f) => {
+                        let _ = initial_tx.send((f, None)).await;
+                    }
+                    Err(e) => error!("failed to download {url}: {e}"),
+                }
+            }
+        });
 
         let endpoint_path = Arc::new(format!("/mrt/{}/", component.name()));
         let api_processor = Arc::new(
@@ -134,7 +223,9 @@ impl MrtFileIn {
             &endpoint_path,
         );
 
-        MrtInRunner::new(self, gate, ingresses, parent_id, queue_tx).run(queue_rx).await
+        MrtInRunner::new(self, gate, ingresses, parent_id, metrics, queue_tx)
+            .run(queue_rx)
+            .await
     }
 }
 
@@ -144,6 +235,7 @@ impl MrtInRunner {
         gate: Gate,
         ingresses: Arc<ingress::Register>,
         parent_id: IngressId,
+        metrics: Arc<MrtInMetrics>,
         queue_tx: mpsc::Sender<QueueEntry>,
     ) -> Self {
         Self {
@@ -151,6 +243,7 @@ impl MrtInRunner {
             config: mrtin,
             ingresses,
             parent_id,
+            metrics,
             queue_tx,
             processing: None,
             processed: vec![],
@@ -294,10 +387,55 @@ impl MrtInRunner {
         Ok((announcements_sent, withdrawals_sent))
     }
 
+    /// Process the BGP4MP messages in the MRT record `raw`.
+    ///
+    /// Returns the number of messages, announcements and withdrawals. Also
+    /// used by the `nats-in` unit for MRT encoded messages.
+    pub(crate) async fn process_record(
+        gate: &Gate,
+        ingresses: &Arc<ingress::Register>,
+        parent_id: IngressId,
+        raw: &[u8],
+    ) -> Result<(usize, usize, usize), MrtError> {
+        use routecore::mrt::Bgp4Mp;
+
+        let mut messages = 0;
+        let mut announcements = 0;
+        let mut withdrawals = 0;
+        for msg in MrtFile::new(raw).messages() {
+            let (reach, unreach) = match msg {
+                Bgp4Mp::StateChange(sc) => {
+                    Self::process_state_change(gate, ingresses, sc.into())
+                        .await;
+                    (0, 0)
+                }
+                Bgp4Mp::StateChangeAs4(sc) => {
+                    Self::process_state_change(gate, ingresses, sc).await;
+                    (0, 0)
+                }
+                Bgp4Mp::Message(msg) => {
+                    Self::process_message(
+                        gate, ingresses, parent_id, msg.into()
+                    ).await?
+                }
+                Bgp4Mp::MessageAs4(msg) => {
+                    Self::process_message(gate, ingresses, parent_id, msg)
+                        .await?
+                }
+            };
+            messages += 1;
+            announcements += reach;
+            withdrawals += unreach;
+        }
+        Ok((messages, announcements, withdrawals))
+    }
+
     async fn process_file(
         gate: Gate,
         ingresses: Arc<ingress::Register>,
         parent_id: IngressId,
+        metrics: &MrtInMetrics,
+        pacer: &mut Pacer,
         filename: PathBuf,
     ) -> Result<(), MrtError> {
         info!("processing {} on thread {:?}",
@@ -312,7 +450,7 @@ impl MrtInRunner {
         let mut buf = Vec::<u8>::new();
 
         let t0 = Instant::now();
-        let mrt_file = match filename.as_path().extension()
+        let raw = match filename.as_path().extension()
             .and_then(std::ffi::OsStr::to_str)
         {
             Some("gz") => {
@@ -322,7 +460,7 @@ impl MrtInRunner {
                 info!("decompressed {} in {}ms",
                     &filename.to_string_lossy(),
                     t0.elapsed().as_millis());
-                MrtFile::new(&buf[..])
+                &buf[..]
             }
             Some("bz2") => {
                 let mut bz2 = BzDecoder::new(&mmap[..]);
@@ -334,12 +472,15 @@ impl MrtInRunner {
                 info!("decompressed {} in {}ms",
                     &filename.to_string_lossy(),
                     t0.elapsed().as_millis());
-                MrtFile::new(&buf[..])
+                &buf[..]
             }
             _ => {
-                MrtFile::new(&mmap[..])
+                &mmap[..]
             }
         };
+        let mrt_file = MrtFile::new(raw);
+        metrics.file_bytes.store(raw.len(), SeqCst);
+        metrics.file_bytes_processed.store(0, SeqCst);
 
         let mut routes_sent = 0;
 
@@ -412,45 +553,56 @@ impl MrtInRunner {
                     tokio::time::sleep(std::time::Duration::from_micros(1)).await;
                 }
                 routes_sent += 1;
+                metrics.num_routes.fetch_add(1, SeqCst);
             }
         }
 
         // --- Messages part (update file)
 
-        use routecore::mrt::Bgp4Mp;
-
         let mut announcements_sent = 0;
         let mut withdrawals_sent = 0;
 
-        let mut messages_processed = 0;
-        for msg in mrt_file.messages() {
-            match msg {
-                Bgp4Mp::StateChange(sc) => {
-                    MrtInRunner::process_state_change(&gate, &ingresses, sc.into()).await;
-                }
-                Bgp4Mp::StateChangeAs4(sc) => {
-                    MrtInRunner::process_state_change(&gate, &ingresses, sc).await;
-                }
-                Bgp4Mp::Message(msg) => {
-                    let (reach, unreach) = MrtInRunner::process_message(&gate, &ingresses, parent_id, msg.into()).await?; 
-                    announcements_sent += reach;
-                    withdrawals_sent += unreach;
-                    
-                }
-                Bgp4Mp::MessageAs4(msg) => {
-                    let (reach, unreach) = MrtInRunner::process_message(&gate, &ingresses, parent_id, msg).await?;
-                    announcements_sent += reach;
-                    withdrawals_sent += unreach;
-                    messages_processed += 1;
+        let mut records_processed = 0;
+        let mut bytes_processed = 0;
+        for record in Records::new(raw) {
+            bytes_processed += record.raw.len();
+            if !record.is_bgp4mp() {
+                continue;
+            }
+
+            if let Some(due) = pacer.due(record.timestamp) {
+                let now = Instant::now();
+                let lag = now.saturating_duration_since(due);
+                metrics.replay_lag_millis.store(
+                    u64::try_from(lag.as_millis()).unwrap_or(u64::MAX),
+                    SeqCst,
+                );
+                if due > now {
+                    metrics.file_bytes_processed.store(bytes_processed, SeqCst);
+                    tokio::time::sleep_until(due.into()).await;
                 }
             }
 
+            let (messages, reach, unreach) = MrtInRunner::process_record(
+                &gate, &ingresses, parent_id, record.raw
+            ).await?;
+            announcements_sent += reach;
+            withdrawals_sent += unreach;
+            metrics.num_messages.fetch_add(messages, SeqCst);
+            metrics.num_announcements.fetch_add(reach, SeqCst);
+            metrics.num_withdrawals.fetch_add(unreach, SeqCst);
+            metrics.replay_timestamp.store(record.timestamp.as_secs(), SeqCst);
+            records_processed += 1;
+
             // Allow other async tasks to have a go by introducing an
             // `await` every N entries:
-            if messages_processed % 100_000 == 0 {
+            if records_processed % 100_000 == 0 {
+                metrics.file_bytes_processed.store(bytes_processed, SeqCst);
                 tokio::time::sleep(std::time::Duration::from_micros(1)).await;
             }
         }
+        metrics.file_bytes_processed.store(bytes_processed, SeqCst);
+        metrics.num_files_processed.fetch_add(1, SeqCst);
 
 
         info!(
@@ -472,11 +624,39 @@ impl MrtInRunner {
 
         let gate = self.gate.clone();
         let ingresses = self.ingresses.clone();
+        let metrics = self.metrics.clone();
+        let mut pacer = Pacer::new(self.config.speed);
+        let repeat = self.config.repeat;
         let (results_tx, mut results_rx) = mpsc::unbounded_channel();
         //let (handles_tx, mut handles_rx) = mpsc::unbounded_channel();
 
         tokio::spawn(async move {
-            while let Some((p, enqueuer_tx)) = queue.recv().await {
+            // In loop mode, the files processed in the current pass and
+            // those still to be processed again.
+            let mut replayed = Vec::new();
+            let mut to_replay = VecDeque::new();
+
+            loop {
+                let (p, enqueuer_tx) = match queue.try_recv() {
+                    Ok(entry) => entry,
+                    Err(TryRecvError::Disconnected) => break,
+                    Err(TryRecvError::Empty) => {
+                        if let Some(p) = to_replay.pop_front() {
+                            (p, None)
+                        } else if repeat && !replayed.is_empty() {
+                            info!("all files processed, starting over");
+                            to_replay = std::mem::take(&mut replayed).into();
+                            metrics.num_passes.fetch_add(1, SeqCst);
+                            pacer.reset();
+                            continue;
+                        } else {
+                            match queue.recv().await {
+                                Some(entry) => entry,
+                                None => break,
+                            }
+                        }
+                    }
+                };
                 let gate = gate.clone();
                 let ingresses = ingresses.clone();
                 let results_tx = results_tx.clone();
@@ -499,15 +679,22 @@ impl MrtInRunner {
                     gate,
                     ingresses,
                     self.parent_id,
+                    &metrics,
+                    &mut pacer,
                     p.clone()
                 ).await.map(|_| p).inspect_err(|e| error!("process_file failed: {e}"));
+                if repeat {
+                    if let Ok(p) = &r {
+                        replayed.push(p.clone());
+                    }
+                }
                 if let Err(e) = results_tx.send(r) {
                     error!("failed to send result of file {e}")
                 }
                 if let Some(tx) = enqueuer_tx {
                     let _ = tx.send(Ok("OK!".into()));
                 }
-            };
+            }
         });
 
         loop {
@@ -515,6 +702,10 @@ impl MrtInRunner {
             match self.process_until(f).await {
                 ControlFlow::Continue(Ok(r)) => {
                     if let Some(Ok(p)) = r {
+                        // Files replayed in loop mode were hashed before.
+                        if self.processed.iter().any(|(q, _)| *q == p) {
+                            continue;
+                        }
 
                         let filename = p.to_string_lossy();
                         let mut hasher = Sha256::new();
@@ -599,7 +790,7 @@ impl MrtInRunner {
                         }
                         GateStatus::ReportLinks { report } => {
                             report.declare_source();
-                            //report.set_graph_status(self.metrics.clone());
+                            report.set_graph_status(self.metrics.clone());
                         }
                         GateStatus::Triggered { .. } => {
                             warn!("got unexpected Triggered for this unit");
@@ -622,17 +813,85 @@ impl MrtInRunner {
     }
 }
 
+/// The files to process for `path`: the path itself if it is a file, or
+/// the files in it ordered by name if it is a directory.
+///
+/// With the usual naming of MRT archives, e.g. `bview.<date>` and
+/// `updates.<date>` or `rib.<date>` and `updates.<date>`, this processes
+/// the RIB dump before the updates, and the updates in chronological order.
+fn expand_path(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = vec![];
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The name to store the file downloaded from `url` under.
+///
+/// Archives often use the same file names for different collectors, so
+/// the name is made from the host and the whole path, keeping the
+/// extension that tells how the file is compressed.
+fn download_name(url: &Url) -> Option<String> {
+    if url.path().ends_with('/') {
+        return None;
+    }
+    let name =
+        format!("{}{}", url.host_str().unwrap_or_default(), url.path());
+    Some(sanitise_file_name::sanitise(&name))
+}
+
+/// Download the MRT file at `url` into `dir`, unless it was downloaded
+/// before, returning the path of the downloaded file.
+async fn download(
+    client: &HttpClient,
+    url: &Url,
+    dir: &Path,
+) -> Result<PathBuf, MrtError> {
+    let name =
+        download_name(url).ok_or(MrtError::other("URL names no file"))?;
+    let path = dir.join(&name);
+    if path.exists() {
+        info!("using previously downloaded {}", path.to_string_lossy());
+        return Ok(path);
+    }
+    tokio::fs::create_dir_all(dir).await?;
+
+    // Download to a temporary name first, so that an interrupted download
+    // is not mistaken for a complete one later.
+    let t0 = Instant::now();
+    let partial = dir.join(format!("{name}.part"));
+    let mut response =
+        client.get(url.clone()).send().await?.error_for_status()?;
+    let mut file = tokio::fs::File::create(&partial).await?;
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    tokio::fs::rename(&partial, &path).await?;
+    info!("downloaded {url} in {}ms", t0.elapsed().as_millis());
+    Ok(path)
+}
+
 #[derive(Debug)]
 enum MrtErrorType {
     Io(std::io::Error),
     Parse(ParseError),
+    Http(reqwest::Error),
     Other(&'static str),
 }
 #[derive(Debug)]
 pub struct MrtError(MrtErrorType);
 
 impl MrtError {
-    fn other(s: &'static str) -> Self {
+    pub(super) fn other(s: &'static str) -> Self {
         Self(MrtErrorType::Other(s))
     }
 }
@@ -643,6 +902,7 @@ impl std::fmt::Display for MrtError {
         match &self.0 {
             MrtErrorType::Io(e) => write!(f, "io error: {}", e),
             MrtErrorType::Parse(e) => write!(f, "parse error: {}", e),
+            MrtErrorType::Http(e) => write!(f, "http error: {}", e),
             MrtErrorType::Other(e) => write!(f, "error: {}", e),
         }
     }
@@ -654,6 +914,12 @@ impl From<ParseError> for MrtError {
     }
 }
 
+impl From<reqwest::Error> for MrtError {
+    fn from(e: reqwest::Error) -> Self {
+        Self(MrtErrorType::Http(e))
+    }
+}
+
 impl From<std::io::Error> for MrtError {
     fn from(e: std::io::Error) -> Self {
         Self(MrtErrorType::Io(e))
@@ -664,3 +930,109 @@ impl From<MrtError> for std::io::Error {
         std::io::Error::other(e.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Body, Response};
+
+    use crate::tests::util::{http::MockServer, https};
+
+    use super::*;
+
+    #[test]
+    fn config_with_urls_only() {
+        let toml = r#"
+        urls = [
+            "https://data.ris.ripe.net/rrc00/2024.01/bview.20240101.0000.gz",
+            "https://data.ris.ripe.net/rrc00/2024.01/updates.20240101.0000.gz",
+        ]
+        download_dir = "/tmp/mrt"
+        "#;
+        let config: MrtFileIn = toml::from_str(toml).unwrap();
+        assert_eq!(config.filename.iter().count(), 0);
+        assert_eq!(config.urls.len(), 2);
+        assert_eq!(
+            config.download_dir.map(PathBuf::from),
+            Some(PathBuf::from("/tmp/mrt"))
+        );
+    }
+
+    #[test]
+    fn config_with_replay_speed_and_loop() {
+        let toml = r#"
+        type = "mrt-in"
+        filename = "/tmp/mrt/updates.20240101.0000.gz"
+        speed = 60
+        loop = true
+        "#;
+        let Unit::MrtFileIn(config) = toml::from_str(toml).unwrap() else {
+            panic!("expected an mrt-file-in unit");
+        };
+        assert_eq!(config.speed, ReplaySpeed::Multiplier(60.0));
+        assert!(config.repeat);
+
+        let config: MrtFileIn = toml::from_str("").unwrap();
+        assert_eq!(config.speed, ReplaySpeed::Max);
+        assert!(!config.repeat);
+    }
+
+    #[test]
+    fn directories_expand_to_their_files_by_name() {
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-mrt-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("subdir")).unwrap();
+        for name in ["updates.0005.gz", "bview.0000.gz", "updates.0000.gz"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let files = expand_path(&dir).unwrap();
+        let names = files
+            .iter()
+            .map(|f| f.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["bview.0000.gz", "updates.0000.gz", "updates.0005.gz"]
+        );
+
+        let file = dir.join("bview.0000.gz");
+        assert_eq!(expand_path(&file).unwrap(), [file]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn download_names_keep_the_collector_and_extension() {
+        let name = |url: &str| download_name(&Url::parse(url).unwrap());
+        let rrc00 = name("https://h.example/rrc00/updates.0000.gz").unwrap();
+        let rrc01 = name("https://h.example/rrc01/updates.0000.gz").unwrap();
+        assert_ne!(rrc00, rrc01);
+        assert!(rrc00.ends_with(".gz"));
+        assert!(!rrc00.contains('/'));
+        assert_eq!(name("https://h.example/rrc00/"), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn files_are_downloaded_over_https_once() {
+        let server = MockServer::start(|_| {
+            Response::new(Body::from(&b"MRT data"[..]))
+        })
+        .await;
+        let front = https::front(server.addr).await;
+        let url = Url::parse(&format!(
+            "https://localhost:{}/rrc00/updates.0000.gz",
+            front.port()
+        ))
+        .unwrap();
+        let dir = std::env::temp_dir()
+            .join(format!("rotonda-mrt-{}", uuid::Uuid::new_v4()));
+
+        let client = https::client();
+        let path = download(&client, &url, &dir).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"MRT data");
+        assert_eq!(download(&client, &url, &dir).await.unwrap(), path);
+        assert_eq!(server.requests().len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
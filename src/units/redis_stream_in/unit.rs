@@ -0,0 +1,646 @@
+//! Ingesting routes from Redis Streams.
+//!
+//! This unit reads the entries of one or more [Redis Streams] as a member
+//! of a consumer group, so that several Rotonda instances can share the
+//! load, and turns them into routes. Entries are acknowledged once they
+//! have been handed downstream. After connecting, the entries delivered to
+//! the consumer before but never acknowledged, e.g. because the connection
+//! was lost, are read again first. Entries that cannot be decoded are
+//! acknowledged as well, rather than delivered again and again.
+//!
+//! The configured field of an entry holds the message, which is decoded
+//! according to the configured format as described in the [`decoder`]
+//! module. The other fields act as its headers, e.g. `Peer-Address` and
+//! `Peer-ASN` for BGP UPDATE messages, whose peers are grouped by stream.
+//!
+//! Redis is talked to with the [redis] crate. If the connection is lost,
+//! the unit reconnects with an exponential backoff.
+//!
+//! [Redis Streams]: https://redis.io/docs/latest/develop/data-types/streams/
+//! [redis]: https://docs.rs/redis/
+//! [`decoder`]: crate::units::kafka_in::decoder
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use log::{debug, error, info, warn};
+use redis::{
+    aio::MultiplexedConnection,
+    streams::{StreamReadOptions, StreamReadReply},
+    AsyncCommands, AsyncConnectionConfig, RedisResult,
+};
+use serde::Deserialize;
+use serde_with::serde_as;
+use url::Url;
+
+use crate::{
+    comms::{Gate, GateMetrics, GateStatus, Terminated},
+    ingress::IngressInfo,
+    manager::{Component, WaitPoint},
+    metrics::{self, Metric, MetricType, MetricUnit},
+    units::kafka_in::{
+        decoder::{Decoder, Message},
+        unit::MessageFormat,
+    },
+};
+
+/// How long an `XREADGROUP` waits for new entries.
+const BLOCK: Duration = Duration::from_secs(5);
+
+/// How long to wait for the reply to a command before giving up on the
+/// connection.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct RedisStreamIn {
+    /// The Redis server, e.g. `redis://:password@localhost:6379/0`. TLS is
+    /// not supported.
+    pub url: Url,
+
+    /// The streams to read from.
+    pub streams: Vec<String>,
+
+    /// The consumer group to read as.
+    pub group: String,
+
+    /// The name of the consumer within the group. Defaults to the name of
+    /// the unit, so Rotonda instances sharing a group need to set it.
+    #[serde(default)]
+    pub consumer: Option<String>,
+
+    /// Whether to create the consumer group, and the streams, if they don't
+    /// exist. A new group starts with the entries added after it.
+    #[serde(default = "RedisStreamIn::default_create_group")]
+    pub create_group: bool,
+
+    /// The field of an entry holding the message.
+    #[serde(default = "RedisStreamIn::default_payload_field")]
+    pub payload_field: String,
+
+    /// The number of entries to read at a time.
+    #[serde(default = "RedisStreamIn::default_batch")]
+    pub batch: usize,
+
+    /// The format of the messages.
+    #[serde(default = "RedisStreamIn::default_format")]
+    pub format: MessageFormat,
+
+    /// How long to wait before reconnecting after the connection failed.
+    /// The delay doubles with every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "RedisStreamIn::default_reconnect_delay_secs")]
+    pub reconnect_delay_secs: Duration,
+
+    /// The longest to wait before reconnecting.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "RedisStreamIn::default_max_reconnect_delay_secs")]
+    pub max_reconnect_delay_secs: Duration,
+}
+
+impl RedisStreamIn {
+    fn default_create_group() -> bool {
+        true
+    }
+
+    fn default_payload_field() -> String {
+        "payload".into()
+    }
+
+    fn default_batch() -> usize {
+        100
+    }
+
+    fn default_format() -> MessageFormat {
+        MessageFormat::Json
+    }
+
+    fn default_reconnect_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_reconnect_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    pub async fn run(
+        self,
+        mut component: Component,
+        gate: Gate,
+        mut waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        if self.streams.is_empty() {
+            error!(
+                "Unit {}: at least one stream must be configured",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+        if let MessageFormat::Custom(format) = &self.format {
+            error!(
+                "Unit {}: unsupported message format '{format}'",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+
+        let metrics = Arc::new(RedisStreamInMetrics::new(&gate));
+        component.register_metrics(metrics.clone());
+
+        gate.process_until(waitpoint.ready()).await?;
+        waitpoint.running().await;
+
+        let ingresses = component.ingresses().clone();
+        let parent_id = ingresses.register();
+        ingresses.update_info(
+            parent_id,
+            IngressInfo::new()
+                .with_unit_name(component.name().as_ref())
+                .with_desc("redis-stream-in unit"),
+        );
+
+        let decoder = Decoder::new(
+            self.format.clone(),
+            gate.clone(),
+            ingresses,
+            parent_id,
+            "Redis stream",
+        );
+        let connection = tokio::spawn(Self::connect_loop(
+            self,
+            component.name().to_string(),
+            decoder,
+            metrics,
+        ));
+
+        // The connection is handled in its own task, so here only the gate
+        // needs to be processed.
+        let res = loop {
+            match gate.process().await {
+                Ok(GateStatus::ReportLinks { report }) => {
+                    report.declare_source();
+                }
+                Ok(GateStatus::Reconfiguring { .. }) => {
+                    warn!(
+                        "Unit {}: reconfiguring redis-stream-in requires a \
+                        restart",
+                        component.name()
+                    );
+                }
+                Ok(_) => {}
+                Err(Terminated) => break Err(Terminated),
+            }
+        };
+        connection.abort();
+        res
+    }
+
+    /// Keep a connection to Redis, reconnecting whenever it is lost.
+    async fn connect_loop(
+        self,
+        name: String,
+        mut decoder: Decoder,
+        metrics: Arc<RedisStreamInMetrics>,
+    ) {
+        // Don't log the password.
+        let mut url = self.url.clone();
+        let _ = url.set_password(None);
+
+        let mut delay = self.reconnect_delay_secs;
+        loop {
+            debug!("Unit {name}: connecting to {url}");
+            if let Err(err) =
+                self.session(&name, &mut decoder, &metrics).await
+            {
+                warn!("Unit {name}: connection to Redis failed: {err}")
+            }
+            metrics.connected.store(false, SeqCst);
+
+            // A connection that got going resets the backoff.
+            if metrics.session_messages.swap(0, SeqCst) > 0 {
+                delay = self.reconnect_delay_secs;
+            }
+            info!("Unit {name}: reconnecting in {}s", delay.as_secs());
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(self.max_reconnect_delay_secs);
+        }
+    }
+
+    /// Run a single connection to Redis, until it fails.
+    async fn session(
+        &self,
+        name: &str,
+        decoder: &mut Decoder,
+        metrics: &RedisStreamInMetrics,
+    ) -> RedisResult<()> {
+        let config = AsyncConnectionConfig::new()
+            .set_connection_timeout(REPLY_TIMEOUT)
+            .set_response_timeout(REPLY_TIMEOUT);
+        let mut conn = redis::Client::open(self.url.as_str())?
+            .get_multiplexed_async_connection_with_config(&config)
+            .await?;
+        metrics.connected.store(true, SeqCst);
+        metrics.num_connects.fetch_add(1, SeqCst);
+        let consumer = self.consumer.as_deref().unwrap_or(name);
+
+        if self.create_group {
+            for stream in &self.streams {
+                let res: RedisResult<()> = conn
+                    .xgroup_create_mkstream(stream, &self.group, "$")
+                    .await;
+                match res {
+                    Err(err) if err.code() == Some("BUSYGROUP") => {}
+                    res => {
+                        res?;
+                        info!(
+                            "Unit {name}: created consumer group '{}' of \
+                            stream '{stream}'",
+                            self.group
+                        );
+                    }
+                }
+            }
+        }
+
+        // The entries still pending for the consumer come first, read from
+        // the start of its pending list until none are left.
+        let mut pending = true;
+        loop {
+            let mut options = StreamReadOptions::default()
+                .group(&self.group, consumer)
+                .count(self.batch.max(1));
+            if !pending {
+                options = options.block(BLOCK.as_millis() as usize);
+            }
+            let id = if pending { "0" } else { ">" };
+            let ids = vec![id; self.streams.len()];
+            let reply: StreamReadReply =
+                conn.xread_options(&self.streams, &ids, &options).await?;
+            let entries = Entry::from_reply(reply);
+            if entries.is_empty() {
+                pending = false;
+                continue;
+            }
+            self.process(&entries, decoder, metrics).await;
+            self.acknowledge(&mut conn, &entries, metrics).await?;
+        }
+    }
+
+    /// Decode a batch of entries and send their routes downstream.
+    async fn process(
+        &self,
+        entries: &[Entry],
+        decoder: &mut Decoder,
+        metrics: &RedisStreamInMetrics,
+    ) {
+        for entry in entries {
+            metrics.num_messages.fetch_add(1, SeqCst);
+            metrics.session_messages.fetch_add(1, SeqCst);
+            let res = match entry.field(&self.payload_field) {
+                Some(payload) => {
+                    let msg = StreamMessage { entry, payload };
+                    decoder.decode(&msg).await
+                }
+                None => Err(format!("no field '{}'", self.payload_field)),
+            };
+            match res {
+                Ok((announced, withdrawn)) => {
+                    metrics.num_announcements.fetch_add(announced, SeqCst);
+                    metrics.num_withdrawals.fetch_add(withdrawn, SeqCst);
+                }
+                Err(err) => {
+                    metrics.num_invalid_messages.fetch_add(1, SeqCst);
+                    debug!(
+                        "Ignoring entry {} of '{}': {err}",
+                        entry.id, entry.stream
+                    );
+                }
+            }
+        }
+    }
+
+    /// Acknowledge a batch of entries, whether they could be decoded or
+    /// not.
+    async fn acknowledge(
+        &self,
+        conn: &mut MultiplexedConnection,
+        entries: &[Entry],
+        metrics: &RedisStreamInMetrics,
+    ) -> RedisResult<()> {
+        let mut acks: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for entry in entries {
+            acks.entry(&entry.stream).or_default().push(&entry.id);
+        }
+        for (stream, ids) in acks {
+            let _: usize = conn.xack(stream, &self.group, &ids).await?;
+            metrics.num_acks.fetch_add(ids.len(), SeqCst);
+        }
+        Ok(())
+    }
+}
+
+//------------ Entry ---------------------------------------------------------
+
+/// An entry of a stream.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Entry {
+    stream: String,
+    id: String,
+    fields: Vec<(String, Bytes)>,
+}
+
+impl Entry {
+    /// Takes the entries from the reply of `XREADGROUP`.
+    ///
+    /// Fields with values other than strings are left out.
+    fn from_reply(reply: StreamReadReply) -> Vec<Self> {
+        reply
+            .keys
+            .into_iter()
+            .flat_map(|key| {
+                let stream = key.key;
+                key.ids.into_iter().map(move |id| Entry {
+                    stream: stream.clone(),
+                    id: id.id,
+                    fields: id
+                        .map
+                        .into_iter()
+                        .filter_map(|(name, value)| {
+                            let value: Vec<u8> =
+                                redis::from_redis_value(&value).ok()?;
+                            Some((name, value.into()))
+                        })
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the value of the field `name`.
+    fn field(&self, name: &str) -> Option<&Bytes> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+//------------ StreamMessage -------------------------------------------------
+
+/// An entry as a message for the decoder.
+struct StreamMessage<'a> {
+    entry: &'a Entry,
+    payload: &'a [u8],
+}
+
+impl Message for StreamMessage<'_> {
+    fn payload(&self) -> &[u8] {
+        self.payload
+    }
+
+    fn source(&self) -> &str {
+        &self.entry.stream
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.entry
+            .fields
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| std::str::from_utf8(value).ok())
+    }
+}
+
+//------------ RedisStreamInMetrics ------------------------------------------
+
+#[derive(Debug, Default)]
+struct RedisStreamInMetrics {
+    gate: Arc<GateMetrics>,
+    connected: AtomicBool,
+    num_connects: AtomicUsize,
+    num_messages: AtomicUsize,
+    num_invalid_messages: AtomicUsize,
+    num_acks: AtomicUsize,
+    num_announcements: AtomicUsize,
+    num_withdrawals: AtomicUsize,
+
+    /// The number of messages received over the current connection.
+    session_messages: AtomicUsize,
+}
+
+impl RedisStreamInMetrics {
+    fn new(gate: &Gate) -> Self {
+        Self {
+            gate: gate.metrics(),
+            ..Default::default()
+        }
+    }
+
+    const CONNECTED_METRIC: Metric = Metric::new(
+        "redis_stream_in_connected",
+        "whether the unit is connected to Redis",
+        MetricType::Gauge,
+        MetricUnit::State,
+    );
+    const NUM_CONNECTS_METRIC: Metric = Metric::new(
+        "redis_stream_in_num_connects",
+        "the number of times a connection to Redis was established",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_MESSAGES_METRIC: Metric = Metric::new(
+        "redis_stream_in_num_messages",
+        "the number of stream entries received",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_INVALID_MESSAGES_METRIC: Metric = Metric::new(
+        "redis_stream_in_num_invalid_messages",
+        "the number of stream entries that could not be decoded",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ACKS_METRIC: Metric = Metric::new(
+        "redis_stream_in_num_acks",
+        "the number of stream entries acknowledged",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ANNOUNCEMENTS_METRIC: Metric = Metric::new(
+        "redis_stream_in_num_announcements",
+        "the number of route announcements received from Redis",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WITHDRAWALS_METRIC: Metric = Metric::new(
+        "redis_stream_in_num_withdrawals",
+        "the number of route withdrawals received from Redis",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for RedisStreamInMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        self.gate.append(unit_name, target);
+        target.append_simple(
+            &Self::CONNECTED_METRIC,
+            Some(unit_name),
+            self.connected.load(SeqCst) as u8,
+        );
+        target.append_simple(
+            &Self::NUM_CONNECTS_METRIC,
+            Some(unit_name),
+            self.num_connects.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_INVALID_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_invalid_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ACKS_METRIC,
+            Some(unit_name),
+            self.num_acks.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ANNOUNCEMENTS_METRIC,
+            Some(unit_name),
+            self.num_announcements.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WITHDRAWALS_METRIC,
+            Some(unit_name),
+            self.num_withdrawals.load(SeqCst),
+        );
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use inetnum::asn::Asn;
+    use redis::streams::{StreamId, StreamKey};
+
+    use crate::{bgp::encode::RAW_UPDATE, ingress};
+
+    use super::*;
+
+    #[test]
+    fn config_deserialization() {
+        let toml = r#"
+        url = "redis://localhost"
+        streams = ["bgp"]
+        group = "rotonda"
+        format = "bgpupdate"
+        "#;
+        let config: RedisStreamIn = toml::from_str(toml).unwrap();
+        assert!(matches!(config.format, MessageFormat::BgpUpdate));
+        assert!(config.create_group);
+        assert_eq!(config.payload_field, "payload");
+        assert_eq!(config.batch, 100);
+        assert_eq!(config.consumer, None);
+    }
+
+    fn mk_entry(id: &str, fields: &[(&str, &[u8])]) -> Entry {
+        Entry {
+            stream: "bgp".into(),
+            id: id.into(),
+            fields: fields
+                .iter()
+                .map(|(key, value)| {
+                    (key.to_string(), Bytes::copy_from_slice(value))
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn entries_are_taken_from_replies() {
+        let map = HashMap::from([
+            (
+                "payload".to_string(),
+                redis::Value::BulkString(b"\x01\x02".to_vec()),
+            ),
+            ("count".to_string(), redis::Value::Nil),
+        ]);
+        let reply = StreamReadReply {
+            keys: vec![StreamKey {
+                key: "bgp".into(),
+                ids: vec![StreamId {
+                    id: "1-0".into(),
+                    map,
+                }],
+            }],
+        };
+        assert_eq!(
+            Entry::from_reply(reply),
+            [mk_entry("1-0", &[("payload", b"\x01\x02")])]
+        );
+        assert!(Entry::from_reply(StreamReadReply::default()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn entries_are_decoded() {
+        let config: RedisStreamIn = toml::from_str(
+            r#"
+            url = "redis://localhost"
+            streams = ["bgp"]
+            group = "rotonda"
+            format = "bgpupdate"
+            "#,
+        )
+        .unwrap();
+        let (gate, _agent) = Gate::new(0);
+        let ingresses = Arc::new(ingress::Register::default());
+        let parent_id = ingresses.register();
+        let mut decoder = Decoder::new(
+            config.format.clone(),
+            gate.clone(),
+            ingresses.clone(),
+            parent_id,
+            "Redis stream",
+        );
+        let metrics = RedisStreamInMetrics::new(&gate);
+
+        let raw = hex::decode(RAW_UPDATE).unwrap();
+        let entries = [
+            mk_entry(
+                "1-0",
+                &[
+                    ("peer-address", b"192.0.2.1"),
+                    ("Peer-ASN", b"65000"),
+                    ("payload", &raw),
+                ],
+            ),
+            mk_entry("2-0", &[("other", b"x")]),
+        ];
+        config.process(&entries, &mut decoder, &metrics).await;
+
+        assert_eq!(metrics.num_messages.load(SeqCst), 2);
+        assert_eq!(metrics.num_invalid_messages.load(SeqCst), 1);
+        assert_eq!(metrics.num_announcements.load(SeqCst), 1);
+        assert_eq!(metrics.num_withdrawals.load(SeqCst), 1);
+        assert!(ingresses
+            .find_all(|info| info.remote_asn == Some(Asn::from_u32(65000)))
+            .pop()
+            .is_some());
+    }
+}
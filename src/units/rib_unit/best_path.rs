@@ -0,0 +1,163 @@
+//! BGP best path selection across the paths stored for a prefix.
+//!
+//! The RIB stores the paths learned from all ingresses for a prefix side by
+//! side. When best path selection is enabled, the standard decision process
+//! (LOCAL_PREF, AS path length, ORIGIN, MED, eBGP over iBGP, BGP identifier
+//! and peer address) picks one of these as the winner.
+//!
+//! Rotonda is not a participant in the BGP sessions it learns routes from,
+//! so some inputs to the decision process are approximated: LOCAL_PREF is
+//! used as the degree of preference whenever it is present, routes with an
+//! empty AS path are considered iBGP learned, and the remote address of the
+//! ingress stands in for its BGP identifier.
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use inetnum::{addr::Prefix, asn::Asn};
+use rotonda_store::prefix_record::{Record, RouteStatus};
+use routecore::bgp::{
+    aspath::HopPath,
+    nlri::afisafi::IsPrefix,
+    path_attributes::PaMap,
+    path_selection::{OrdRoute, RouteSource, TiebreakerInfo},
+    types::LocalPref,
+};
+use serde::Deserialize;
+
+use crate::{
+    ingress::{self, IngressId},
+    payload::{RotondaPaMap, RotondaRoute},
+};
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct BestPathConfig {
+    /// Which routes to send downstream.
+    #[serde(default)]
+    pub output: BestPathOutput,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BestPathOutput {
+    /// Send all routes downstream, as without best path selection.
+    #[default]
+    All,
+
+    /// Only send changes to the best path of a prefix downstream.
+    Best,
+}
+
+//------------ Selection -----------------------------------------------------
+
+/// Select the best path among the `records` for a single prefix.
+///
+/// Withdrawn records and records lacking the mandatory attributes for the
+/// decision process are not eligible. Returns the position of the winner in
+/// `records`, if any.
+pub fn select_best(
+    records: &[Record<RotondaPaMap>],
+    ingresses: &ingress::Register,
+) -> Option<usize> {
+    let candidates = records
+        .iter()
+        .enumerate()
+        .filter(|(_, rec)| rec.status != RouteStatus::Withdrawn)
+        .map(|(pos, rec)| {
+            let pa_map = to_pa_map(&rec.meta);
+            let tiebreakers =
+                tiebreakers(&pa_map, ingresses.get(rec.multi_uniq_id));
+            (pa_map, tiebreakers, rec.multi_uniq_id, pos)
+        })
+        .collect::<Vec<_>>();
+
+    // Ties after the full decision process are broken on the ingress id,
+    // to keep the outcome stable.
+    candidates
+        .iter()
+        .filter_map(|(pa_map, tiebreakers, ingress_id, pos)| {
+            OrdRoute::rfc4271(pa_map, *tiebreakers)
+                .ok()
+                .map(|route| (route, *ingress_id, *pos))
+        })
+        .min()
+        .map(|(_, _, pos)| pos)
+}
+
+/// Select the best path among the `records` for a single prefix, and
+/// return the ingress it was learned from.
+pub fn best_ingress(
+    records: &[Record<RotondaPaMap>],
+    ingresses: &ingress::Register,
+) -> Option<IngressId> {
+    select_best(records, ingresses).map(|pos| records[pos].multi_uniq_id)
+}
+
+/// Returns the prefix `route` is for.
+pub fn prefix_of(route: &RotondaRoute) -> Prefix {
+    match route {
+        RotondaRoute::Ipv4Unicast(n, _) => n.prefix(),
+        RotondaRoute::Ipv6Unicast(n, _) => n.prefix(),
+        RotondaRoute::Ipv4Multicast(n, _) => n.prefix(),
+        RotondaRoute::Ipv6Multicast(n, _) => n.prefix(),
+    }
+}
+
+/// Returns `route` with its path attributes replaced by `pamap`.
+pub fn with_pamap(
+    route: &RotondaRoute,
+    pamap: RotondaPaMap,
+) -> RotondaRoute {
+    match route {
+        RotondaRoute::Ipv4Unicast(n, _) => {
+            RotondaRoute::Ipv4Unicast(*n, pamap)
+        }
+        RotondaRoute::Ipv6Unicast(n, _) => {
+            RotondaRoute::Ipv6Unicast(*n, pamap)
+        }
+        RotondaRoute::Ipv4Multicast(n, _) => {
+            RotondaRoute::Ipv4Multicast(*n, pamap)
+        }
+        RotondaRoute::Ipv6Multicast(n, _) => {
+            RotondaRoute::Ipv6Multicast(*n, pamap)
+        }
+    }
+}
+
+fn to_pa_map(pamap: &RotondaPaMap) -> PaMap {
+    let mut res = PaMap::empty();
+    for attr in pamap.path_attributes().iter().flatten() {
+        if let Ok(attr) = attr.to_owned() {
+            res.attributes_mut().insert(attr.type_code(), attr);
+        }
+    }
+    res
+}
+
+fn tiebreakers(
+    pa_map: &PaMap,
+    ingress_info: Option<ingress::IngressInfo>,
+) -> TiebreakerInfo {
+    let source = match pa_map
+        .get::<HopPath>()
+        .and_then(|path| path.neighbor_path_selection())
+    {
+        Some(_) => RouteSource::Ebgp,
+        None => RouteSource::Ibgp,
+    };
+    let peer_addr = ingress_info
+        .and_then(|info| info.remote_addr)
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    let bgp_identifier = match peer_addr {
+        IpAddr::V4(addr) => addr.octets(),
+        IpAddr::V6(_) => [0; 4],
+    };
+    TiebreakerInfo::new(
+        source,
+        pa_map.get::<LocalPref>().map(Into::into),
+        Asn::from_u32(0),
+        bgp_identifier.into(),
+        peer_addr,
+    )
+}
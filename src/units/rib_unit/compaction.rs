@@ -0,0 +1,334 @@
+//! Compacting the on-disk state of a RIB unit.
+//!
+//! With disk storage, every change to the RIB is appended to the
+//! write-ahead log, which only shrinks when a snapshot covering it is
+//! written. On a busy collector the log can grow large between snapshots,
+//! and every entry in it has to be replayed on startup. Compaction writes a
+//! snapshot of the RIB out of schedule, after which the log segments it
+//! covers are removed and old snapshots beyond the retention are pruned.
+//!
+//! Compaction happens every `compaction_interval_secs`, and additionally as
+//! soon as the log grows beyond `compaction_wal_size_bytes` or holds changes
+//! older than `compaction_wal_age_secs`. It can also be forced via the HTTP
+//! API. Each compaction reports the disk usage before and after.
+
+use std::{
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, RwLock, TryLockError},
+    time::{Duration, Instant},
+};
+
+use arc_swap::{ArcSwap, ArcSwapOption};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::ingress;
+
+use super::{
+    rib::Rib,
+    snapshot::{self, SnapshotConfig},
+    status_reporter::RibUnitStatusReporter,
+    storage::DiskStorageConfig,
+    unit::RibUnitRunner,
+    wal::{self, Wal},
+};
+
+/// How often to check whether the size or age triggers are met.
+const TRIGGER_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+//------------ CompactionTrigger ---------------------------------------------
+
+/// Why a compaction was started.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompactionTrigger {
+    /// The compaction interval passed.
+    Interval,
+
+    /// The write-ahead log grew beyond the configured size.
+    WalSize,
+
+    /// The write-ahead log held changes beyond the configured age.
+    WalAge,
+
+    /// Requested via the HTTP API.
+    Manual,
+}
+
+impl CompactionTrigger {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompactionTrigger::Interval => "interval",
+            CompactionTrigger::WalSize => "wal_size",
+            CompactionTrigger::WalAge => "wal_age",
+            CompactionTrigger::Manual => "manual",
+        }
+    }
+}
+
+//------------ DiskUsage -----------------------------------------------------
+
+/// The disk space taken by the log and snapshots of a RIB unit.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DiskUsage {
+    pub wal_segments: usize,
+    pub wal_bytes: u64,
+    pub snapshots: usize,
+    pub snapshot_bytes: u64,
+
+    /// When the most recent snapshot was taken. The log holds the changes
+    /// made since.
+    pub last_snapshot: Option<DateTime<Utc>>,
+}
+
+impl DiskUsage {
+    /// Measure the disk usage of `unit_name`, with its log segments in
+    /// `wal_dir` and its snapshots in `snapshot_dir`.
+    pub fn measure(
+        wal_dir: &Path,
+        snapshot_dir: &Path,
+        unit_name: &str,
+    ) -> io::Result<Self> {
+        let mut usage = Self::default();
+        for (_, path) in wal::segments(wal_dir, unit_name)? {
+            usage.wal_segments += 1;
+            usage.wal_bytes += match fs::metadata(&path) {
+                Ok(metadata) => metadata.len(),
+                // Removed by a concurrent snapshot.
+                Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+                Err(err) => return Err(err),
+            };
+        }
+        let snapshots = match snapshot::list(snapshot_dir, unit_name) {
+            Ok(snapshots) => snapshots,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err),
+        };
+        usage.snapshots = snapshots.len();
+        usage.snapshot_bytes = snapshots.iter().map(|s| s.size).sum();
+        usage.last_snapshot = snapshots.last().map(|s| s.taken);
+        Ok(usage)
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.wal_bytes + self.snapshot_bytes
+    }
+}
+
+//------------ CompactionReport ----------------------------------------------
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CompactionReport {
+    /// When the compaction finished.
+    pub time: DateTime<Utc>,
+
+    pub trigger: CompactionTrigger,
+
+    /// The number of routes in the snapshot written.
+    pub routes: usize,
+
+    pub before: DiskUsage,
+    pub after: DiskUsage,
+
+    /// The disk space freed, in bytes. Negative if the new snapshot took
+    /// more than was freed, e.g. while the retention is not yet reached.
+    pub reclaimed_bytes: i64,
+
+    pub duration_millis: u64,
+}
+
+//------------ Compactor -----------------------------------------------------
+
+/// Compacts the on-disk state of a RIB unit, see the [module docs](self).
+pub struct Compactor {
+    disk: DiskStorageConfig,
+    snapshot: SnapshotConfig,
+    unit_name: String,
+    rib: Arc<ArcSwap<Rib>>,
+    wal: Arc<Wal>,
+    rebuild_lock: Arc<RwLock<()>>,
+    ingresses: Arc<ingress::Register>,
+    status_reporter: Arc<RibUnitStatusReporter>,
+
+    /// When the last compaction finished, or the compactor was created.
+    ///
+    /// Held for the duration of a compaction, so that only one runs at a
+    /// time.
+    last_compaction: Mutex<Instant>,
+
+    last_report: ArcSwapOption<CompactionReport>,
+}
+
+impl Compactor {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        disk: DiskStorageConfig,
+        snapshot: SnapshotConfig,
+        unit_name: String,
+        rib: Arc<ArcSwap<Rib>>,
+        wal: Arc<Wal>,
+        rebuild_lock: Arc<RwLock<()>>,
+        ingresses: Arc<ingress::Register>,
+        status_reporter: Arc<RibUnitStatusReporter>,
+    ) -> Self {
+        Self {
+            disk,
+            snapshot,
+            unit_name,
+            rib,
+            wal,
+            rebuild_lock,
+            ingresses,
+            status_reporter,
+            last_compaction: Mutex::new(Instant::now()),
+            last_report: ArcSwapOption::empty(),
+        }
+    }
+
+    /// The report of the last compaction, if any completed yet.
+    pub fn last_report(&self) -> Option<Arc<CompactionReport>> {
+        self.last_report.load_full()
+    }
+
+    /// The current disk usage.
+    ///
+    /// The entries still queued for the write-ahead log are written first,
+    /// so they are included.
+    pub fn usage(&self) -> io::Result<DiskUsage> {
+        self.wal.flush()?;
+        DiskUsage::measure(
+            &self.disk.path,
+            &self.snapshot.directory,
+            &self.unit_name,
+        )
+    }
+
+    /// How often to check whether a compaction is due, or `None` if
+    /// compaction is never due by itself.
+    pub fn check_interval(&self) -> Option<Duration> {
+        let interval = self.interval();
+        let triggers = self.disk.compaction_wal_size_bytes.is_some()
+            || self.disk.compaction_wal_age_secs.is_some();
+        match interval {
+            Some(interval) if triggers => {
+                Some(interval.min(TRIGGER_CHECK_INTERVAL))
+            }
+            Some(interval) => Some(interval),
+            None if triggers => Some(TRIGGER_CHECK_INTERVAL),
+            None => None,
+        }
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        match self.disk.compaction_interval_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    /// Compact, waiting for a compaction already running to finish first.
+    pub fn compact(
+        &self,
+        trigger: CompactionTrigger,
+    ) -> io::Result<Arc<CompactionReport>> {
+        let mut last_compaction = self.last_compaction.lock().unwrap();
+        self.usage()
+            .and_then(|before| {
+                self.compact_locked(trigger, before, &mut last_compaction)
+            })
+            .inspect_err(|err| self.status_reporter.compaction_failed(err))
+    }
+
+    /// Compact if any of the triggers is met and no compaction is running
+    /// already. Returns the report if a compaction was done.
+    pub fn compact_if_due(
+        &self,
+    ) -> io::Result<Option<Arc<CompactionReport>>> {
+        let mut last_compaction = match self.last_compaction.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Poisoned(err)) => panic!("{err}"),
+        };
+        let before = self
+            .usage()
+            .inspect_err(|err| self.status_reporter.compaction_failed(err))?;
+        let Some(trigger) = self.due(&before, *last_compaction) else {
+            return Ok(None);
+        };
+        self.compact_locked(trigger, before, &mut last_compaction)
+            .inspect_err(|err| self.status_reporter.compaction_failed(err))
+            .map(Some)
+    }
+
+    /// The trigger met given the disk usage `usage`, if any.
+    fn due(
+        &self,
+        usage: &DiskUsage,
+        last_compaction: Instant,
+    ) -> Option<CompactionTrigger> {
+        if self
+            .interval()
+            .is_some_and(|interval| last_compaction.elapsed() >= interval)
+        {
+            return Some(CompactionTrigger::Interval);
+        }
+        if self
+            .disk
+            .compaction_wal_size_bytes
+            .is_some_and(|max| usage.wal_bytes > max)
+        {
+            return Some(CompactionTrigger::WalSize);
+        }
+        if let Some(max) = self.disk.compaction_wal_age_secs {
+            // Without a snapshot, the log goes back to when the unit
+            // started, which is about when the compactor was created and
+            // so the last compaction is taken to be.
+            let age = match usage.last_snapshot {
+                Some(taken) => {
+                    (Utc::now() - taken).to_std().unwrap_or_default()
+                }
+                None => last_compaction.elapsed(),
+            };
+            if age > Duration::from_secs(max) {
+                return Some(CompactionTrigger::WalAge);
+            }
+        }
+        None
+    }
+
+    fn compact_locked(
+        &self,
+        trigger: CompactionTrigger,
+        before: DiskUsage,
+        last_compaction: &mut MutexGuard<Instant>,
+    ) -> io::Result<Arc<CompactionReport>> {
+        let t0 = Instant::now();
+        let written = RibUnitRunner::write_snapshot(
+            &self.rib,
+            Some(&self.wal),
+            &self.rebuild_lock,
+            &self.snapshot,
+            &self.unit_name,
+            &self.ingresses,
+            &self.status_reporter,
+        )?;
+        let after = self.usage()?;
+        **last_compaction = Instant::now();
+
+        let report = Arc::new(CompactionReport {
+            time: Utc::now(),
+            trigger,
+            routes: written.routes,
+            reclaimed_bytes: before.total_bytes() as i64
+                - after.total_bytes() as i64,
+            before,
+            after,
+            duration_millis: u64::try_from(t0.elapsed().as_millis())
+                .unwrap_or(u64::MAX),
+        });
+        self.last_report.store(Some(report.clone()));
+        self.status_reporter.compacted(report.clone());
+        Ok(report)
+    }
+}
@@ -0,0 +1,482 @@
+//! Checking the consistency of the RIB.
+//!
+//! Next to the store, a RIB keeps the secondary indexes, the tags of its
+//! routes and the totals its memory use is estimated from. These are all
+//! updated as routes are inserted, and a bug in any of those paths makes
+//! them drift from what is actually in the store. The consistency checker
+//! periodically walks the store and verifies that:
+//!
+//!  * every route in the AS path and community indexes is in the store,
+//!    and is indexed with the AS path and communities it has there,
+//!  * every route with tags is in the store, and
+//!  * the number of prefixes and paths counted for the memory use is the
+//!    number of prefixes and paths in the store.
+//!
+//! The walk does not hold up updates to the RIB, so it may see a route
+//! halfway through being updated. Everything found suspicious is therefore
+//! checked once more while updates are held off, and only reported if the
+//! discrepancy remains. With `repair` enabled, the discrepancies are then
+//! also resolved, taking the store as the truth. The counters can only be
+//! checked if they did not change during the walk; on a RIB where new
+//! paths keep being added the check of the counters is left out, and the
+//! report marked inconclusive.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use arc_swap::{ArcSwap, ArcSwapOption};
+use chrono::{DateTime, Utc};
+use inetnum::addr::Prefix;
+use rotonda_store::{epoch, prefix_record::Record};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::{
+    ingress::IngressId, payload::RotondaPaMap, roto_runtime::types::Tags,
+};
+
+use super::{
+    index::{as_path_string, communities, RouteKey},
+    rib::Rib,
+};
+
+/// The maximum number of discrepancies listed in a report.
+const MAX_LISTED: usize = 100;
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConsistencyConfig {
+    /// How often to check the RIB.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "ConsistencyConfig::default_interval_secs")]
+    pub interval_secs: Duration,
+
+    /// Resolve the discrepancies found, rather than only reporting them.
+    #[serde(default)]
+    pub repair: bool,
+}
+
+impl ConsistencyConfig {
+    fn default_interval_secs() -> Duration {
+        Duration::from_secs(3600)
+    }
+}
+
+impl Default for ConsistencyConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: Self::default_interval_secs(),
+            repair: false,
+        }
+    }
+}
+
+//------------ ConsistencyReport ---------------------------------------------
+
+/// The outcome of checking the RIBs of a unit.
+#[derive(Clone, Debug, Serialize)]
+pub struct ConsistencyReport {
+    /// When the check finished.
+    pub time: DateTime<Utc>,
+
+    /// The number of routes checked.
+    pub routes: usize,
+
+    /// The number of discrepancies found, per kind.
+    pub discrepancies: BTreeMap<DiscrepancyKind, usize>,
+
+    /// The number of discrepancies resolved.
+    pub repaired: usize,
+
+    /// Whether some checks were left out, as a RIB changed while it was
+    /// being checked.
+    pub inconclusive: bool,
+
+    /// The first discrepancies found.
+    pub listed: Vec<Discrepancy>,
+}
+
+impl ConsistencyReport {
+    fn new() -> Self {
+        Self {
+            time: Utc::now(),
+            routes: 0,
+            discrepancies: BTreeMap::new(),
+            repaired: 0,
+            inconclusive: false,
+            listed: vec![],
+        }
+    }
+
+    /// The total number of discrepancies found.
+    pub fn total(&self) -> usize {
+        self.discrepancies.values().sum()
+    }
+
+    fn add(&mut self, discrepancy: Discrepancy) {
+        *self.discrepancies.entry(discrepancy.kind).or_default() += 1;
+        if self.listed.len() < MAX_LISTED {
+            self.listed.push(discrepancy);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Discrepancy {
+    /// The named RIB it was found in, or none for the main RIB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rib: Option<Arc<str>>,
+
+    pub kind: DiscrepancyKind,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<Prefix>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingress_id: Option<IngressId>,
+
+    pub detail: String,
+}
+
+#[derive(
+    Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscrepancyKind {
+    /// A route indexed with another AS path than it has in the store.
+    AsPathIndex,
+
+    /// A route indexed with other communities than it has in the store.
+    CommunityIndex,
+
+    /// Tags kept for a route not in the store.
+    Tags,
+
+    /// A prefix count different from the prefixes in the store.
+    Prefixes,
+
+    /// A path count different from the paths in the store.
+    Paths,
+}
+
+impl DiscrepancyKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DiscrepancyKind::AsPathIndex => "as_path_index",
+            DiscrepancyKind::CommunityIndex => "community_index",
+            DiscrepancyKind::Tags => "tags",
+            DiscrepancyKind::Prefixes => "prefixes",
+            DiscrepancyKind::Paths => "paths",
+        }
+    }
+}
+
+//------------ ConsistencyChecker --------------------------------------------
+
+/// A RIB to check, with its name if it is a named RIB.
+pub type CheckedRib = (Option<Arc<str>>, Arc<ArcSwap<Rib>>);
+
+/// The settings of the consistency checker of a unit, and the report of
+/// its last check.
+#[derive(Debug)]
+pub struct ConsistencyChecker {
+    config: ConsistencyConfig,
+    last_report: ArcSwapOption<ConsistencyReport>,
+}
+
+impl ConsistencyChecker {
+    pub fn new(config: ConsistencyConfig) -> Self {
+        Self {
+            config,
+            last_report: ArcSwapOption::empty(),
+        }
+    }
+
+    pub fn config(&self) -> &ConsistencyConfig {
+        &self.config
+    }
+
+    /// The report of the last check, if a check has completed yet.
+    pub fn last_report(&self) -> Option<Arc<ConsistencyReport>> {
+        self.last_report.load_full()
+    }
+
+    /// Check `ribs`.
+    ///
+    /// Updates to the RIBs must hold `rebuild_lock` for reading, so that
+    /// it can be used to hold them off while confirming a discrepancy.
+    pub fn check(
+        &self,
+        ribs: &[CheckedRib],
+        rebuild_lock: &RwLock<()>,
+    ) -> Result<Arc<ConsistencyReport>, String> {
+        let mut report = ConsistencyReport::new();
+        for (name, rib) in ribs {
+            check_rib(
+                name.as_ref(),
+                rib,
+                rebuild_lock,
+                self.config.repair,
+                &mut report,
+            )?;
+        }
+        report.time = Utc::now();
+        let report = Arc::new(report);
+        self.last_report.store(Some(report.clone()));
+        Ok(report)
+    }
+}
+
+//------------ Checking ------------------------------------------------------
+
+/// What the walk over a RIB found.
+#[derive(Default)]
+struct Walk {
+    prefixes: usize,
+    paths: usize,
+    attribute_bytes: usize,
+
+    /// The routes in the store.
+    seen: HashSet<RouteKey>,
+
+    /// The routes that may be inconsistent.
+    suspects: HashSet<RouteKey>,
+}
+
+fn check_rib(
+    name: Option<&Arc<str>>,
+    rib: &ArcSwap<Rib>,
+    rebuild_lock: &RwLock<()>,
+    repair: bool,
+    report: &mut ConsistencyReport,
+) -> Result<(), String> {
+    let current = rib.load_full();
+    if !current.is_physical() {
+        return Ok(());
+    }
+    let usage = current.memory_usage();
+    let counted = (usage.prefixes(), usage.paths());
+
+    let mut walk = Walk::default();
+    let guard = &epoch::pin();
+    for (_, prefix_record) in current.prefix_records(guard) {
+        let prefix_record = prefix_record.map_err(|err| err.to_string())?;
+        walk.prefixes += 1;
+        for record in &prefix_record.meta {
+            walk.paths += 1;
+            walk.attribute_bytes += record.meta.as_ref().len();
+            let key = (prefix_record.prefix, record.multi_uniq_id);
+            walk.seen.insert(key);
+            if !mismatches(&current, &key, std::slice::from_ref(record))
+                .is_empty()
+            {
+                walk.suspects.insert(key);
+            }
+        }
+    }
+    if let Some(index) = current.as_path_index() {
+        walk.suspects.extend(index.keys_except(&walk.seen));
+    }
+    if let Some(index) = current.community_index() {
+        walk.suspects.extend(index.keys_except(&walk.seen));
+    }
+    walk.suspects.extend(current.tags().keys_except(&walk.seen));
+    report.routes += walk.paths;
+
+    let counters_stable = counted == (usage.prefixes(), usage.paths());
+    let counters_off = counted != (walk.prefixes, walk.paths);
+    if !counters_stable {
+        report.inconclusive = true;
+    }
+    if walk.suspects.is_empty() && !(counters_stable && counters_off) {
+        return Ok(());
+    }
+
+    // Confirm the suspects with updates held off, so that they cannot be
+    // halfway through being updated.
+    let _rebuild_guard = rebuild_lock.write().unwrap();
+    if !Arc::ptr_eq(&rib.load(), &current) {
+        // The RIB was rebuilt in the meantime, and will be checked again
+        // next time.
+        report.inconclusive = true;
+        return Ok(());
+    }
+
+    let mut suspects = walk.suspects.iter().collect::<Vec<_>>();
+    suspects.sort();
+    for key in suspects {
+        let records = current.route_records(&key.0, key.1)?;
+        for (kind, detail) in mismatches(&current, key, &records) {
+            report.add(Discrepancy {
+                rib: name.cloned(),
+                kind,
+                prefix: Some(key.0),
+                ingress_id: Some(key.1),
+                detail,
+            });
+            if repair {
+                resolve(&current, kind, key, &records);
+                report.repaired += 1;
+            }
+        }
+    }
+
+    if counters_stable
+        && counted == (usage.prefixes(), usage.paths())
+        && counters_off
+    {
+        for (kind, counted, found) in [
+            (DiscrepancyKind::Prefixes, counted.0, walk.prefixes),
+            (DiscrepancyKind::Paths, counted.1, walk.paths),
+        ] {
+            if counted != found {
+                report.add(Discrepancy {
+                    rib: name.cloned(),
+                    kind,
+                    prefix: None,
+                    ingress_id: None,
+                    detail: format!(
+                        "counted {counted} {}, found {found}",
+                        kind.as_str()
+                    ),
+                });
+                if repair {
+                    report.repaired += 1;
+                }
+            }
+        }
+        if repair {
+            usage.recount(walk.prefixes, walk.paths, walk.attribute_bytes);
+        }
+    } else if counters_off {
+        report.inconclusive = true;
+    }
+    Ok(())
+}
+
+/// How the side tables of `rib` disagree with `records`, the records in
+/// the store for the route `key`. As a route can be in both the unicast
+/// and the multicast store, the indexes agree if they match either.
+fn mismatches(
+    rib: &Rib,
+    key: &RouteKey,
+    records: &[Record<RotondaPaMap>],
+) -> Vec<(DiscrepancyKind, String)> {
+    let mut res = vec![];
+    if let Some(index) = rib.as_path_index() {
+        let indexed = index.path(key);
+        let in_store = records
+            .iter()
+            .map(|rec| as_path_string(&rec.meta))
+            .collect::<Vec<_>>();
+        let agrees = match records.is_empty() {
+            true => indexed.is_none(),
+            false => in_store
+                .iter()
+                .any(|path| path.as_deref() == indexed.as_deref()),
+        };
+        if !agrees {
+            res.push((
+                DiscrepancyKind::AsPathIndex,
+                describe(
+                    "AS path",
+                    indexed.map(|path| format!("'{path}'")),
+                    in_store
+                        .first()
+                        .map(|path| path.as_ref().map(|p| format!("'{p}'"))),
+                ),
+            ));
+        }
+    }
+    if let Some(index) = rib.community_index() {
+        let indexed = index.communities(key);
+        let in_store = records
+            .iter()
+            .map(|rec| communities(&rec.meta))
+            .collect::<Vec<_>>();
+        let agrees = match records.is_empty() {
+            true => indexed.is_empty(),
+            false => in_store.contains(&indexed),
+        };
+        if !agrees {
+            let render = |c: &Vec<_>| {
+                (!c.is_empty()).then(|| {
+                    c.iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+            };
+            res.push((
+                DiscrepancyKind::CommunityIndex,
+                describe(
+                    "communities",
+                    render(&indexed),
+                    in_store.first().map(render),
+                ),
+            ));
+        }
+    }
+    if records.is_empty() && rib.tags().get(key.0, key.1).is_some() {
+        res.push((
+            DiscrepancyKind::Tags,
+            "tagged, but not in the RIB".to_string(),
+        ));
+    }
+    res
+}
+
+/// Describe a disagreement about `what`, with `indexed` what the side
+/// table has, and `in_store` what the store has, which is `None` if the
+/// route is not in the store at all.
+fn describe(
+    what: &str,
+    indexed: Option<String>,
+    in_store: Option<Option<String>>,
+) -> String {
+    let indexed = indexed.unwrap_or_else(|| "none".to_string());
+    match in_store {
+        None => format!("indexed with {what} {indexed}, but not in the RIB"),
+        Some(in_store) => format!(
+            "indexed with {what} {indexed}, but the route has {}",
+            in_store.unwrap_or_else(|| "none".to_string())
+        ),
+    }
+}
+
+/// Make the side table of `rib` for `kind` agree with `records`, the
+/// records in the store for the route `key`.
+fn resolve(
+    rib: &Rib,
+    kind: DiscrepancyKind,
+    key: &RouteKey,
+    records: &[Record<RotondaPaMap>],
+) {
+    match kind {
+        DiscrepancyKind::AsPathIndex => {
+            if let Some(index) = rib.as_path_index() {
+                index.remove(key);
+                if let Some(rec) = records.first() {
+                    index.insert(key.0, key.1, &rec.meta);
+                }
+            }
+        }
+        DiscrepancyKind::CommunityIndex => {
+            if let Some(index) = rib.community_index() {
+                index.remove(key);
+                if let Some(rec) = records.first() {
+                    index.insert(key.0, key.1, &rec.meta);
+                }
+            }
+        }
+        DiscrepancyKind::Tags => {
+            rib.tags().set(key.0, key.1, Tags::default());
+        }
+        DiscrepancyKind::Prefixes | DiscrepancyKind::Paths => {}
+    }
+}
@@ -0,0 +1,335 @@
+//! Differences between two versions of the RIB contents.
+//!
+//! Either version is a snapshot written by the RIB unit (see
+//! [`super::snapshot`]) or the live RIB. Ingress IDs are not stable across
+//! restarts, so routes are matched on their prefix and the address and ASN
+//! of the peer they were learned from. Only when neither is known is the
+//! ingress ID used instead. Withdrawn routes are treated as absent.
+//!
+//! Differences are reported per prefix, in prefix order, each listing the
+//! routes that were added, removed or changed. For a changed route, the
+//! path attributes that differ are given with both their old and their new
+//! value.
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    io,
+    iter::Peekable,
+    net::IpAddr,
+    path::Path,
+};
+
+use inetnum::{addr::Prefix, asn::Asn};
+use rotonda_store::{epoch, prefix_record::RouteStatus};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    ingress::{self, IngressId},
+    payload::RotondaPaMap,
+};
+
+use super::{rib::Rib, snapshot};
+
+//------------ RibContents ---------------------------------------------------
+
+/// The peer a route was learned from.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct PeerKey {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_addr: Option<IpAddr>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_asn: Option<Asn>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingress_id: Option<IngressId>,
+}
+
+impl PeerKey {
+    fn new(
+        remote_addr: Option<IpAddr>,
+        remote_asn: Option<Asn>,
+        ingress_id: IngressId,
+    ) -> Self {
+        let ingress_id = match (remote_addr, remote_asn) {
+            (None, None) => Some(ingress_id),
+            _ => None,
+        };
+        Self {
+            remote_addr,
+            remote_asn,
+            ingress_id,
+        }
+    }
+}
+
+type Routes = BTreeMap<PeerKey, RotondaPaMap>;
+
+/// The routes that are not withdrawn in a version of the RIB.
+#[derive(Debug, Default)]
+pub struct RibContents {
+    routes: BTreeMap<(Prefix, bool), Routes>,
+}
+
+impl RibContents {
+    /// Read the contents of the native snapshot at `path`.
+    pub fn from_snapshot(path: &Path) -> io::Result<Self> {
+        let mut records = vec![];
+        let peers = snapshot::read_native_records(
+            snapshot::open(path)?,
+            |prefix, multicast, record| {
+                if record.status != RouteStatus::Withdrawn {
+                    records.push((prefix, multicast, record));
+                }
+                Ok(())
+            },
+        )?;
+        let peers = peers
+            .into_iter()
+            .map(|peer| {
+                let key =
+                    PeerKey::new(peer.remote_addr, peer.remote_asn, peer.mui);
+                (peer.mui, key)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let mut res = Self::default();
+        for (prefix, multicast, record) in records {
+            let peer = peers
+                .get(&record.multi_uniq_id)
+                .copied()
+                .unwrap_or(PeerKey::new(None, None, record.multi_uniq_id));
+            res.insert(prefix, multicast, peer, record.meta);
+        }
+        Ok(res)
+    }
+
+    /// Take the current contents of `rib`.
+    pub fn from_rib(
+        rib: &Rib,
+        ingresses: &ingress::Register,
+    ) -> Result<Self, String> {
+        let mut res = Self::default();
+        let guard = &epoch::pin();
+        for (multicast, prefix_record) in rib.prefix_records(guard) {
+            let prefix_record = prefix_record.map_err(|err| err.to_string())?;
+            for record in prefix_record.meta {
+                if record.status == RouteStatus::Withdrawn {
+                    continue;
+                }
+                let info = ingresses.get(record.multi_uniq_id);
+                let peer = PeerKey::new(
+                    info.as_ref().and_then(|info| info.remote_addr),
+                    info.as_ref().and_then(|info| info.remote_asn),
+                    record.multi_uniq_id,
+                );
+                res.insert(prefix_record.prefix, multicast, peer, record.meta);
+            }
+        }
+        Ok(res)
+    }
+
+    fn insert(
+        &mut self,
+        prefix: Prefix,
+        multicast: bool,
+        peer: PeerKey,
+        pamap: RotondaPaMap,
+    ) {
+        self.routes
+            .entry((prefix, multicast))
+            .or_default()
+            .insert(peer, pamap);
+    }
+}
+
+//------------ Differences ---------------------------------------------------
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// The differences for a single prefix.
+#[derive(Debug, Serialize)]
+pub struct PrefixDiff {
+    pub prefix: Prefix,
+
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub multicast: bool,
+
+    pub change: Change,
+
+    pub routes: Vec<RouteDiff>,
+}
+
+/// The difference for a single route.
+#[derive(Debug, Serialize)]
+pub struct RouteDiff {
+    pub peer: PeerKey,
+
+    pub change: Change,
+
+    /// The path attributes of an added or removed route, or the attributes
+    /// that differ for a changed route.
+    pub attributes: Value,
+}
+
+/// An attribute of a changed route, with its old and new value.
+#[derive(Debug, Serialize)]
+struct AttributeDelta<'a> {
+    old: Option<&'a Value>,
+    new: Option<&'a Value>,
+}
+
+/// Iterate over the differences between `from` and `to`.
+pub fn diff<'a>(
+    from: &'a RibContents,
+    to: &'a RibContents,
+) -> impl Iterator<Item = PrefixDiff> + 'a {
+    MergeIter {
+        from: from.routes.iter().peekable(),
+        to: to.routes.iter().peekable(),
+    }
+    .filter_map(|(key, from, to)| prefix_diff(key, from, to))
+}
+
+fn prefix_diff(
+    &(prefix, multicast): &(Prefix, bool),
+    from: Option<&Routes>,
+    to: Option<&Routes>,
+) -> Option<PrefixDiff> {
+    let (change, routes) = match (from, to) {
+        (Some(from), None) => (
+            Change::Removed,
+            from.iter()
+                .map(|(peer, pamap)| route_diff(*peer, Some(pamap), None))
+                .collect::<Option<Vec<_>>>()?,
+        ),
+        (None, Some(to)) => (
+            Change::Added,
+            to.iter()
+                .map(|(peer, pamap)| route_diff(*peer, None, Some(pamap)))
+                .collect::<Option<Vec<_>>>()?,
+        ),
+        (Some(from), Some(to)) => (
+            Change::Changed,
+            MergeIter {
+                from: from.iter().peekable(),
+                to: to.iter().peekable(),
+            }
+            .filter_map(|(peer, from, to)| route_diff(*peer, from, to))
+            .collect::<Vec<_>>(),
+        ),
+        (None, None) => return None,
+    };
+    if routes.is_empty() {
+        return None;
+    }
+    Some(PrefixDiff {
+        prefix,
+        multicast,
+        change,
+        routes,
+    })
+}
+
+fn route_diff(
+    peer: PeerKey,
+    from: Option<&RotondaPaMap>,
+    to: Option<&RotondaPaMap>,
+) -> Option<RouteDiff> {
+    let (change, attributes) = match (from, to) {
+        (Some(from), None) => (Change::Removed, to_json(from)),
+        (None, Some(to)) => (Change::Added, to_json(to)),
+        (Some(from), Some(to)) => {
+            if from.as_ref() == to.as_ref() {
+                return None;
+            }
+            let from = attributes_by_name(from);
+            let to = attributes_by_name(to);
+            let mut deltas = serde_json::Map::new();
+            for name in from.keys().chain(to.keys()) {
+                let (old, new) = (from.get(name), to.get(name));
+                if old != new && !deltas.contains_key(name) {
+                    deltas.insert(
+                        name.clone(),
+                        serde_json::to_value(AttributeDelta { old, new })
+                            .unwrap_or_default(),
+                    );
+                }
+            }
+            (Change::Changed, Value::Object(deltas))
+        }
+        (None, None) => return None,
+    };
+    Some(RouteDiff {
+        peer,
+        change,
+        attributes,
+    })
+}
+
+fn to_json(pamap: &RotondaPaMap) -> Value {
+    serde_json::to_value(pamap).unwrap_or_default()
+}
+
+/// The path attributes of `pamap` as serialized for the HTTP API, keyed by
+/// their name.
+fn attributes_by_name(pamap: &RotondaPaMap) -> BTreeMap<String, Value> {
+    let Value::Array(attributes) = to_json(pamap) else {
+        return BTreeMap::new();
+    };
+    attributes
+        .into_iter()
+        .flat_map(|attribute| match attribute {
+            Value::Object(map) => map.into_iter().collect::<Vec<_>>(),
+            _ => vec![],
+        })
+        .collect()
+}
+
+//------------ MergeIter -----------------------------------------------------
+
+/// Walks two sorted maps side by side, yielding each key with the value it
+/// has in either map.
+struct MergeIter<I: Iterator> {
+    from: Peekable<I>,
+    to: Peekable<I>,
+}
+
+impl<'a, K: Ord + 'a, V: 'a, I> Iterator for MergeIter<I>
+where
+    I: Iterator<Item = (&'a K, &'a V)>,
+{
+    type Item = (&'a K, Option<&'a V>, Option<&'a V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let order = match (self.from.peek(), self.to.peek()) {
+            (Some((from, _)), Some((to, _))) => from.cmp(to),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return None,
+        };
+        match order {
+            Ordering::Less => {
+                let (key, from) = self.from.next()?;
+                Some((key, Some(from), None))
+            }
+            Ordering::Greater => {
+                let (key, to) = self.to.next()?;
+                Some((key, None, Some(to)))
+            }
+            Ordering::Equal => {
+                let (key, from) = self.from.next()?;
+                let (_, to) = self.to.next()?;
+                Some((key, Some(from), Some(to)))
+            }
+        }
+    }
+}
@@ -0,0 +1,380 @@
+//! Tracing the roto filter of a RIB for single routes.
+//!
+//! With a `filter_trace` section, the HTTP API of the RIB runs the
+//! `rib_in_pre` filter on request at `<http_api_path>filter-trace` for a
+//! route given as JSON in the `route` parameter, in the format of the
+//! `static-routes-in` unit, or for one of the routes the RIB received
+//! recently, sampled at `sample_rate` and picked with the `sample`
+//! parameter. Without either, the samples kept are listed.
+//!
+//! The trace reports the verdict, the attributes as modified by the filter,
+//! the tags, named RIBs and output routes the filter selected, and how long
+//! it took. As compiled scripts cannot be stepped through, the steps of the
+//! [`trace`] are the way to follow the branches taken and the values seen:
+//! the records logged, messages printed, state read and written, RPKI
+//! checks, tags and RIBs selected, in the order the filter made them.
+//!
+//! The filter runs in a throwaway context. It sees the VRPs, traffic
+//! counts, flaps and state of the RIB, but only copies of the latter, so
+//! whatever it changes is dropped with the trace. The route is not stored
+//! or passed on, and metrics are not updated.
+//!
+//! [`trace`]: crate::roto_runtime::trace
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use inetnum::addr::Prefix;
+use serde::{Deserialize, Serialize};
+
+use crate::ingress::IngressId;
+use crate::payload::{RotondaPaMap, RotondaRoute};
+use crate::roto_runtime::logger::ScriptLogger;
+use crate::roto_runtime::reload::Reloadable;
+use crate::roto_runtime::sampling;
+use crate::roto_runtime::trace::{self, TraceStep};
+use crate::roto_runtime::types::Tags;
+use crate::roto_runtime::{Ctx, MutRotondaRoute};
+
+use super::best_path;
+use super::unit::{RibFilters, ROTO_FUNC_PRE_FILTER_NAME};
+
+//------------ FilterTraceConfig ---------------------------------------------
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FilterTraceConfig {
+    /// The fraction of the received routes to keep as samples.
+    #[serde(default = "FilterTraceConfig::default_sample_rate")]
+    pub sample_rate: f64,
+
+    /// The number of samples to keep.
+    #[serde(default = "FilterTraceConfig::default_samples")]
+    pub samples: usize,
+}
+
+impl FilterTraceConfig {
+    fn default_sample_rate() -> f64 {
+        0.001
+    }
+
+    fn default_samples() -> usize {
+        100
+    }
+}
+
+impl Default for FilterTraceConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: Self::default_sample_rate(),
+            samples: Self::default_samples(),
+        }
+    }
+}
+
+//------------ FilterTracer --------------------------------------------------
+
+/// Runs the filter of a RIB for single routes, tracing what it does.
+pub struct FilterTracer {
+    config: FilterTraceConfig,
+    unit_name: Arc<str>,
+    filters: Arc<Reloadable<RibFilters>>,
+    ctx: Arc<Mutex<Ctx>>,
+    samples: Mutex<Samples>,
+}
+
+#[derive(Default)]
+struct Samples {
+    next_id: u64,
+    routes: VecDeque<Sample>,
+}
+
+/// A route received by the RIB, kept for tracing.
+#[derive(Clone)]
+struct Sample {
+    id: u64,
+    received: DateTime<Utc>,
+    ingress_id: Option<IngressId>,
+    route: RotondaRoute,
+}
+
+impl FilterTracer {
+    pub(super) fn new(
+        config: FilterTraceConfig,
+        unit_name: Arc<str>,
+        filters: Arc<Reloadable<RibFilters>>,
+        ctx: Arc<Mutex<Ctx>>,
+    ) -> Self {
+        Self {
+            config,
+            unit_name,
+            filters,
+            ctx,
+            samples: Default::default(),
+        }
+    }
+
+    /// Keeps `route` as a sample at the configured rate.
+    pub fn sample(
+        &self,
+        route: &RotondaRoute,
+        ingress_id: Option<IngressId>,
+    ) {
+        if self.config.samples == 0
+            || !sampling::sample(self.config.sample_rate)
+        {
+            return;
+        }
+        let mut samples = self.samples.lock().unwrap();
+        let id = samples.next_id;
+        samples.next_id += 1;
+        if samples.routes.len() >= self.config.samples {
+            samples.routes.pop_front();
+        }
+        samples.routes.push_back(Sample {
+            id,
+            received: Utc::now(),
+            ingress_id,
+            route: route.clone(),
+        });
+    }
+
+    /// Returns a summary of the samples kept, oldest first.
+    pub fn samples(&self) -> Vec<SampleSummary> {
+        self.samples
+            .lock()
+            .unwrap()
+            .routes
+            .iter()
+            .map(|sample| SampleSummary {
+                id: sample.id,
+                received: sample.received,
+                ingress_id: sample.ingress_id,
+                prefix: best_path::prefix_of(&sample.route),
+            })
+            .collect()
+    }
+
+    /// Traces the filter for the sample with `id`.
+    pub fn trace_sample(&self, id: u64) -> Result<FilterTrace, String> {
+        let sample = self
+            .samples
+            .lock()
+            .unwrap()
+            .routes
+            .iter()
+            .find(|sample| sample.id == id)
+            .cloned()
+            .ok_or_else(|| format!("no sample {id}"))?;
+        self.trace(sample.route, sample.ingress_id)
+    }
+
+    /// Runs the filter for `route`, tracing what it does.
+    pub fn trace(
+        &self,
+        route: RotondaRoute,
+        ingress_id: Option<IngressId>,
+    ) -> Result<FilterTrace, String> {
+        let logger =
+            Arc::new(ScriptLogger::capturing(self.unit_name.clone()));
+        let (filter, mut ctx) = {
+            let mut live = self.ctx.lock().unwrap();
+            let filter = self.filters.get(&mut live).pre;
+            (filter, live.detached(logger.clone(), ingress_id))
+        };
+        let filter = filter.ok_or_else(|| {
+            format!("the script has no {ROTO_FUNC_PRE_FILTER_NAME} filter")
+        })?;
+        ctx.output.borrow_mut().capture_prints();
+        logger.set_ingress(ingress_id);
+
+        let prefix = best_path::prefix_of(&route);
+        let attributes = route.rotonda_pamap().clone();
+        let mutrr: MutRotondaRoute = route.into();
+        let start = Instant::now();
+        let (verdict, steps) = trace::capture(|| {
+            filter.call(&mut ctx, roto::Val(mutrr.clone()))
+        });
+        let duration = start.elapsed();
+
+        let modified = mutrr.borrow().rotonda_pamap().clone();
+        let mut output = ctx.output.borrow_mut();
+        Ok(FilterTrace {
+            prefix,
+            ingress_id,
+            verdict: match verdict {
+                roto::Verdict::Accept(_) => "accept",
+                roto::Verdict::Reject(_) => "reject",
+            },
+            modified: (modified != attributes).then_some(modified),
+            attributes,
+            tags: ctx.tags.lock().unwrap().take(),
+            ribs: ctx.ribs.lock().unwrap().take(),
+            routes: output.take_routes(),
+            steps,
+            output: output
+                .drain()
+                .map(|entry| format!("{entry:?}"))
+                .collect(),
+            duration_micros: duration.as_micros() as u64,
+        })
+    }
+}
+
+//------------ SampleSummary -------------------------------------------------
+
+/// A sample as listed by the HTTP API.
+#[derive(Clone, Debug, Serialize)]
+pub struct SampleSummary {
+    pub id: u64,
+    pub received: DateTime<Utc>,
+    pub ingress_id: Option<IngressId>,
+    pub prefix: Prefix,
+}
+
+//------------ FilterTrace ---------------------------------------------------
+
+/// What the filter did for a route.
+#[derive(Clone, Debug, Serialize)]
+pub struct FilterTrace {
+    pub prefix: Prefix,
+    pub ingress_id: Option<IngressId>,
+    pub verdict: &'static str,
+
+    /// The attributes the filter was given.
+    pub attributes: RotondaPaMap,
+
+    /// The attributes after the filter modified them, if it did.
+    pub modified: Option<RotondaPaMap>,
+
+    pub tags: Tags,
+    pub ribs: Vec<Arc<str>>,
+    pub routes: Vec<Arc<str>>,
+
+    /// What the filter did, in order.
+    ///
+    /// Records logged through `logger` are included regardless of level and
+    /// rate.
+    pub steps: Vec<TraceStep>,
+
+    /// The entries written to the output stream.
+    pub output: Vec<String>,
+
+    pub duration_micros: u64,
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roto_runtime::create_runtime;
+    use crate::units::static_routes_in::routes::RouteSet;
+
+    const SCRIPT: &str = r#"
+        filter rib_in_pre(route: Route) {
+            logger.field("origin", "65001");
+            logger.info("checking origin");
+            if route.prefix_matches(10.0.0.0/8) {
+                output.print("bogon");
+                reject
+            } else {
+                state.incr("customers", 0);
+                tags.set("customer", "yes");
+                ribs.add("customers-only");
+                accept
+            }
+        }
+    "#;
+
+    fn route(prefix: &str) -> RotondaRoute {
+        let set: RouteSet = serde_json::from_str(&format!(
+            r#"{{
+                "peer_address": "192.0.2.1",
+                "peer_asn": 65000,
+                "prefixes": ["{prefix}"],
+                "attributes": {{ "next_hop": "192.0.2.1" }}
+            }}"#
+        ))
+        .unwrap();
+        set.routes().unwrap().remove(0)
+    }
+
+    #[test]
+    fn routes_are_traced() {
+        let mut compiled = roto::FileTree::test_file("test", SCRIPT, 0)
+            .compile(create_runtime().unwrap())
+            .unwrap();
+        let filters = RibFilters {
+            pre: compiled.get_function(ROTO_FUNC_PRE_FILTER_NAME).ok(),
+            ..Default::default()
+        };
+        let ctx = Arc::new(Mutex::new(Ctx::empty()));
+        let tracer = FilterTracer::new(
+            FilterTraceConfig {
+                sample_rate: 1.0,
+                samples: 1,
+            },
+            "rib".into(),
+            Arc::new(Reloadable::fixed(filters)),
+            ctx.clone(),
+        );
+
+        let trace = tracer.trace(route("10.0.0.0/8"), Some(7)).unwrap();
+        assert_eq!(trace.verdict, "reject");
+        assert_eq!(
+            serde_json::to_value(&trace.steps).unwrap(),
+            serde_json::json!([
+                {
+                    "step": "log",
+                    "level": "INFO",
+                    "message": "checking origin",
+                    "fields": { "origin": "65001" }
+                },
+                { "step": "print", "message": "bogon" }
+            ])
+        );
+        assert!(trace.modified.is_none());
+
+        // Only the last sample is kept.
+        tracer.sample(&route("192.0.2.0/24"), None);
+        tracer.sample(&route("198.51.100.0/24"), Some(3));
+        let samples = tracer.samples();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].id, 1);
+        assert!(tracer.trace_sample(0).is_err());
+
+        let trace = tracer.trace_sample(1).unwrap();
+        assert_eq!(trace.verdict, "accept");
+        assert_eq!(trace.ingress_id, Some(3));
+        assert_eq!(trace.ribs, [Arc::<str>::from("customers-only")]);
+        assert_eq!(
+            serde_json::to_string(&trace.tags).unwrap(),
+            r#"{"customer":"yes"}"#
+        );
+        assert_eq!(
+            serde_json::to_value(&trace.steps[1..]).unwrap(),
+            serde_json::json!([
+                {
+                    "step": "state",
+                    "op": "incr",
+                    "key": "customers",
+                    "value": 1
+                },
+                { "step": "tag", "key": "customer", "value": "yes" },
+                { "step": "rib", "name": "customers-only" }
+            ])
+        );
+
+        // The state of the RIB is left alone.
+        let state = ctx.lock().unwrap().state.clone();
+        assert!(state.is_empty());
+        let trace = tracer.trace_sample(1).unwrap();
+        assert_eq!(
+            serde_json::to_value(&trace.steps[1]).unwrap()["value"],
+            1
+        );
+    }
+}
@@ -0,0 +1,445 @@
+//! Route flap damping.
+//!
+//! Every path, i.e. (prefix, ingress) combination, is given a penalty as in
+//! RFC 2439: a withdrawal adds `withdrawal_penalty` and an announcement that
+//! replaces an announced path, and so probably changes its attributes, adds
+//! `update_penalty`. Re-announcing a withdrawn path adds nothing, as the
+//! withdrawal was already counted, and neither do repeated withdrawals. The penalty decays exponentially, halving
+//! every `half_life_secs`.
+//!
+//! A path becomes damped when its penalty exceeds `suppress_threshold`, and
+//! stays damped until it has decayed below `reuse_threshold`. The penalty
+//! never exceeds the value from which it takes `max_suppress_secs` to decay
+//! to `reuse_threshold`, so paths are not damped for longer than that.
+//!
+//! Damping does not drop any routes by itself. The rib_in_pre filter decides
+//! what to do with damped paths, using `flaps.is_damped(route)` and
+//! `flaps.penalty(route)`.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use inetnum::addr::Prefix;
+use rotonda_store::prefix_record::RouteStatus;
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use crate::{
+    ingress::IngressId,
+    metrics::{self, Metric, MetricType, MetricUnit},
+};
+
+/// The number of paths tracked before the decayed ones are dropped.
+const SWEEP_SIZE: usize = 4096;
+
+/// The penalty below which an undamped path is forgotten.
+const FORGET_PENALTY: f64 = 1.0;
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct FlapDampingConfig {
+    /// The time in which the penalty of a path halves.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "FlapDampingConfig::default_half_life_secs")]
+    pub half_life_secs: Duration,
+
+    /// The penalty above which a path is damped.
+    #[serde(default = "FlapDampingConfig::default_suppress_threshold")]
+    pub suppress_threshold: u32,
+
+    /// The penalty below which a damped path is no longer damped.
+    #[serde(default = "FlapDampingConfig::default_reuse_threshold")]
+    pub reuse_threshold: u32,
+
+    /// The longest time a path stays damped after its last flap.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "FlapDampingConfig::default_max_suppress_secs")]
+    pub max_suppress_secs: Duration,
+
+    /// The penalty added for a withdrawal.
+    #[serde(default = "FlapDampingConfig::default_withdrawal_penalty")]
+    pub withdrawal_penalty: u32,
+
+    /// The penalty added for an announcement replacing an announced path.
+    #[serde(default = "FlapDampingConfig::default_update_penalty")]
+    pub update_penalty: u32,
+}
+
+impl FlapDampingConfig {
+    fn default_half_life_secs() -> Duration {
+        Duration::from_secs(900)
+    }
+
+    fn default_suppress_threshold() -> u32 {
+        2000
+    }
+
+    fn default_reuse_threshold() -> u32 {
+        750
+    }
+
+    fn default_max_suppress_secs() -> Duration {
+        Duration::from_secs(3600)
+    }
+
+    fn default_withdrawal_penalty() -> u32 {
+        1000
+    }
+
+    fn default_update_penalty() -> u32 {
+        500
+    }
+
+    /// Returns the highest penalty a path can have.
+    fn max_penalty(&self) -> f64 {
+        let half_lives = self.max_suppress_secs.as_secs_f64()
+            / self.half_life_secs.as_secs_f64().max(1.0);
+        f64::from(self.reuse_threshold) * half_lives.exp2()
+    }
+
+    /// Returns `penalty` decayed over `elapsed`.
+    fn decay(&self, penalty: f64, elapsed: Duration) -> f64 {
+        let half_lives = elapsed.as_secs_f64()
+            / self.half_life_secs.as_secs_f64().max(1.0);
+        penalty * (-half_lives).exp2()
+    }
+}
+
+impl Default for FlapDampingConfig {
+    fn default() -> Self {
+        Self {
+            half_life_secs: Self::default_half_life_secs(),
+            suppress_threshold: Self::default_suppress_threshold(),
+            reuse_threshold: Self::default_reuse_threshold(),
+            max_suppress_secs: Self::default_max_suppress_secs(),
+            withdrawal_penalty: Self::default_withdrawal_penalty(),
+            update_penalty: Self::default_update_penalty(),
+        }
+    }
+}
+
+//------------ FlapDamping ---------------------------------------------------
+
+/// The flap penalties of the paths of a RIB unit.
+///
+/// Without a configuration nothing is tracked, and no path is ever damped.
+/// This is what the filters of units other than RIB units see.
+#[derive(Debug, Default)]
+pub struct FlapDamping {
+    config: Option<FlapDampingConfig>,
+    state: Mutex<State>,
+
+    /// The number of times a path became damped.
+    suppressions: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    paths: HashMap<(Prefix, IngressId), Path>,
+
+    /// The ingress of the route being filtered, if known.
+    current: Option<IngressId>,
+
+    /// The number of paths at which to drop the decayed ones.
+    sweep_at: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Path {
+    penalty: f64,
+    updated: Instant,
+    announced: bool,
+    damped: bool,
+}
+
+impl FlapDamping {
+    const DAMPED_PATHS_METRIC: Metric = Metric::new(
+        "rib_flap_damped_paths",
+        "the number of paths currently damped",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const DAMPED_PREFIXES_METRIC: Metric = Metric::new(
+        "rib_flap_damped_prefixes",
+        "the number of prefixes with at least one damped path",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const TRACKED_PATHS_METRIC: Metric = Metric::new(
+        "rib_flap_tracked_paths",
+        "the number of paths with a flap penalty",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const SUPPRESSIONS_METRIC: Metric = Metric::new(
+        "rib_flap_suppressions",
+        "the number of times a path became damped",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+
+    pub fn new(config: FlapDampingConfig) -> Self {
+        Self {
+            config: Some(config),
+            ..Default::default()
+        }
+    }
+
+    /// Returns a copy of the penalties, with `current` as the current
+    /// ingress, for a trial run of a script that is not to change them.
+    pub fn copy(&self, current: Option<IngressId>) -> Self {
+        let state = self.state.lock().unwrap();
+        Self {
+            config: self.config.clone(),
+            state: Mutex::new(State {
+                paths: state.paths.clone(),
+                current,
+                sweep_at: state.sweep_at,
+            }),
+            suppressions: AtomicU64::new(0),
+        }
+    }
+
+    /// Records an update of the path of `prefix` from `ingress_id` at `now`.
+    ///
+    /// The ingress becomes the current one, whose paths
+    /// [`penalty`][Self::penalty] and [`is_damped`][Self::is_damped] refer
+    /// to, until the next update is recorded.
+    pub fn record(
+        &self,
+        prefix: Prefix,
+        ingress_id: IngressId,
+        status: RouteStatus,
+        now: Instant,
+    ) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        state.current = Some(ingress_id);
+        if !state.paths.contains_key(&(prefix, ingress_id)) {
+            state.sweep(config, now);
+        }
+
+        let announced = status != RouteStatus::Withdrawn;
+        let path = state.paths.entry((prefix, ingress_id)).or_insert(Path {
+            penalty: 0.0,
+            updated: now,
+            announced: false,
+            damped: false,
+        });
+        let added = match (path.announced, announced) {
+            (true, true) => config.update_penalty,
+            (true, false) => config.withdrawal_penalty,
+            (false, _) => 0,
+        };
+        path.penalty = (config.decay(
+            path.penalty,
+            now.saturating_duration_since(path.updated),
+        ) + f64::from(added))
+        .min(config.max_penalty());
+        path.updated = now;
+        path.announced = announced;
+
+        let was_damped = path.damped;
+        path.damped = is_damped(config, path.penalty, was_damped);
+        if path.damped && !was_damped {
+            self.suppressions.fetch_add(1, Relaxed);
+        }
+    }
+
+    /// Forgets the current ingress, for routes not received from one.
+    pub fn clear_current(&self) {
+        self.state.lock().unwrap().current = None;
+    }
+
+    /// Returns the penalty of the path of `prefix` from the current ingress.
+    pub fn penalty(&self, prefix: &Prefix, now: Instant) -> u32 {
+        self.path(prefix, now).map_or(0, |(penalty, _)| penalty as u32)
+    }
+
+    /// Returns whether the path of `prefix` from the current ingress is
+    /// damped.
+    pub fn is_damped(&self, prefix: &Prefix, now: Instant) -> bool {
+        self.path(prefix, now).is_some_and(|(_, damped)| damped)
+    }
+
+    /// Returns the decayed penalty of a path and whether it is damped.
+    fn path(&self, prefix: &Prefix, now: Instant) -> Option<(f64, bool)> {
+        let config = self.config.as_ref()?;
+        let state = self.state.lock().unwrap();
+        let path = state.paths.get(&(*prefix, state.current?))?;
+        let penalty = config
+            .decay(path.penalty, now.saturating_duration_since(path.updated));
+        Some((penalty, is_damped(config, penalty, path.damped)))
+    }
+
+    /// Returns the number of damped paths and prefixes, and of all paths.
+    fn counts(&self, now: Instant) -> (usize, usize, usize) {
+        let Some(config) = &self.config else {
+            return (0, 0, 0);
+        };
+        let state = self.state.lock().unwrap();
+        let mut prefixes = Vec::new();
+        for ((prefix, _), path) in &state.paths {
+            let penalty = config.decay(
+                path.penalty,
+                now.saturating_duration_since(path.updated),
+            );
+            if is_damped(config, penalty, path.damped) {
+                prefixes.push(*prefix);
+            }
+        }
+        let paths = prefixes.len();
+        prefixes.sort_unstable();
+        prefixes.dedup();
+        (paths, prefixes.len(), state.paths.len())
+    }
+}
+
+impl State {
+    /// Drops the paths whose penalty has decayed, if there are many.
+    fn sweep(&mut self, config: &FlapDampingConfig, now: Instant) {
+        if self.paths.len() < self.sweep_at.max(SWEEP_SIZE) {
+            return;
+        }
+        self.paths.retain(|_, path| {
+            let penalty = config.decay(
+                path.penalty,
+                now.saturating_duration_since(path.updated),
+            );
+            is_damped(config, penalty, path.damped)
+                || penalty >= FORGET_PENALTY
+        });
+        self.sweep_at = self.paths.len() * 2;
+    }
+}
+
+/// Returns whether a path with `penalty` is damped.
+fn is_damped(config: &FlapDampingConfig, penalty: f64, was_damped: bool) -> bool {
+    match was_damped {
+        true => penalty >= f64::from(config.reuse_threshold),
+        false => penalty > f64::from(config.suppress_threshold),
+    }
+}
+
+impl metrics::Source for FlapDamping {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        let (damped_paths, damped_prefixes, tracked_paths) =
+            self.counts(Instant::now());
+        target.append_simple(
+            &Self::DAMPED_PATHS_METRIC,
+            Some(unit_name),
+            damped_paths,
+        );
+        target.append_simple(
+            &Self::DAMPED_PREFIXES_METRIC,
+            Some(unit_name),
+            damped_prefixes,
+        );
+        target.append_simple(
+            &Self::TRACKED_PATHS_METRIC,
+            Some(unit_name),
+            tracked_paths,
+        );
+        target.append_simple(
+            &Self::SUPPRESSIONS_METRIC,
+            Some(unit_name),
+            self.suppressions.load(Relaxed),
+        );
+    }
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::Arc};
+
+    use super::*;
+
+    const WITHDRAWN: RouteStatus = RouteStatus::Withdrawn;
+    const ACTIVE: RouteStatus = RouteStatus::Active;
+
+    #[test]
+    fn flapping_paths_are_damped_until_they_decay() {
+        let damping = Arc::new(FlapDamping::new(FlapDampingConfig::default()));
+        let prefix = Prefix::from_str("192.0.2.0/24").unwrap();
+        let start = Instant::now();
+
+        // Announce, withdraw, announce, withdraw: 2000 is not above the
+        // suppress threshold yet
+        for status in [ACTIVE, WITHDRAWN, ACTIVE, WITHDRAWN] {
+            damping.record(prefix, 1, status, start);
+        }
+        assert_eq!(damping.penalty(&prefix, start), 2000);
+        assert!(!damping.is_damped(&prefix, start));
+
+        // An update of the announced path adds 500
+        damping.record(prefix, 1, ACTIVE, start);
+        damping.record(prefix, 1, ACTIVE, start);
+        assert_eq!(damping.penalty(&prefix, start), 2500);
+        assert!(damping.is_damped(&prefix, start));
+
+        // Other ingresses have their own penalty
+        damping.record(prefix, 2, ACTIVE, start);
+        assert_eq!(damping.penalty(&prefix, start), 0);
+        assert!(!damping.is_damped(&prefix, start));
+
+        let metrics =
+            crate::tests::util::internal::get_testable_metrics_snapshot(
+                &damping,
+            );
+        assert_eq!(metrics.with_name::<usize>("rib_flap_damped_paths"), 1);
+        assert_eq!(metrics.with_name::<usize>("rib_flap_damped_prefixes"), 1);
+        assert_eq!(metrics.with_name::<usize>("rib_flap_tracked_paths"), 2);
+        assert_eq!(metrics.with_name::<u64>("rib_flap_suppressions"), 1);
+
+        // After one half life the penalty is below the suppress threshold,
+        // but the path stays damped until it is below the reuse threshold
+        damping.record(prefix, 1, ACTIVE, start);
+        let later = start + Duration::from_secs(900);
+        assert_eq!(damping.penalty(&prefix, later), 1500);
+        assert!(damping.is_damped(&prefix, later));
+        let much_later = start + Duration::from_secs(2700);
+        assert_eq!(damping.penalty(&prefix, much_later), 375);
+        assert!(!damping.is_damped(&prefix, much_later));
+    }
+
+    #[test]
+    fn penalty_is_capped() {
+        let damping = FlapDamping::new(FlapDampingConfig::default());
+        let prefix = Prefix::from_str("2001:db8::/32").unwrap();
+        let start = Instant::now();
+        for _ in 0..100 {
+            damping.record(prefix, 1, ACTIVE, start);
+            damping.record(prefix, 1, WITHDRAWN, start);
+        }
+
+        // Four half lives in an hour bring 750 * 2^4 back to 750
+        assert_eq!(damping.penalty(&prefix, start), 12000);
+        let later = start + Duration::from_secs(3601);
+        assert!(!damping.is_damped(&prefix, later));
+    }
+
+    #[test]
+    fn nothing_is_tracked_without_config() {
+        let damping = FlapDamping::default();
+        let prefix = Prefix::from_str("192.0.2.0/24").unwrap();
+        let now = Instant::now();
+        for _ in 0..10 {
+            damping.record(prefix, 1, ACTIVE, now);
+            damping.record(prefix, 1, WITHDRAWN, now);
+        }
+        assert_eq!(damping.penalty(&prefix, now), 0);
+        assert!(!damping.is_damped(&prefix, now));
+    }
+}
@@ -0,0 +1,207 @@
+//! Garbage collection of withdrawn routes.
+//!
+//! A withdrawn route is kept in the store with its last seen attributes, and
+//! the store never releases the memory of a route, nor of the superseded
+//! versions of it. The garbage collector periodically rebuilds the RIB,
+//! carrying over only the latest version of each route and leaving out the
+//! routes that have been withdrawn for longer than `retention_secs`.
+//!
+//! The store does not record when a route was withdrawn, so the retention
+//! is counted from the first garbage collection run that finds the route
+//! withdrawn. A withdrawn route is thus kept for between `retention_secs`
+//! and `retention_secs + interval_secs`.
+//!
+//! The RIB is rebuilt while updates continue to be applied to it. Those
+//! updates are tracked, and replayed into the rebuilt RIB just before it
+//! replaces the current one. Only this replay holds up updates.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use inetnum::addr::Prefix;
+use rotonda_store::{
+    epoch,
+    prefix_record::{Record, RouteStatus},
+};
+use routecore::bgp::types::AfiSafiType;
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use crate::{ingress::IngressId, payload::RotondaPaMap};
+
+use super::{memory::MemoryUsage, rib::Rib};
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct GcConfig {
+    /// How often to run the garbage collector.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "GcConfig::default_interval_secs")]
+    pub interval_secs: Duration,
+
+    /// How long withdrawn routes are kept, so they can still be queried.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "GcConfig::default_retention_secs")]
+    pub retention_secs: Duration,
+}
+
+impl GcConfig {
+    fn default_interval_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_retention_secs() -> Duration {
+        Duration::from_secs(600)
+    }
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: Self::default_interval_secs(),
+            retention_secs: Self::default_retention_secs(),
+        }
+    }
+}
+
+//------------ Tracked -------------------------------------------------------
+
+/// The changes made to a RIB while it is being rebuilt.
+#[derive(Debug, Default)]
+pub struct Tracked {
+    /// The prefixes that were updated, tagged with a bool that is true for
+    /// multicast prefixes.
+    pub prefixes: HashSet<(Prefix, bool)>,
+
+    /// The ingresses, optionally limited to a single address family, that
+    /// had all their routes withdrawn.
+    pub withdrawn: Vec<(IngressId, Option<AfiSafiType>)>,
+}
+
+//------------ WithdrawnSince ------------------------------------------------
+
+/// When the garbage collector first found each withdrawn route withdrawn.
+#[derive(Debug, Default)]
+pub struct WithdrawnSince {
+    since: HashMap<(Prefix, bool, IngressId), Instant>,
+}
+
+impl WithdrawnSince {
+    /// Update the administration for the routes currently in `rib`. Returns
+    /// the number of routes that are due to be purged.
+    pub fn update(
+        &mut self,
+        rib: &Rib,
+        now: Instant,
+        retention: Duration,
+    ) -> Result<usize, String> {
+        let mut since = HashMap::with_capacity(self.since.len());
+        let mut due = 0;
+        let guard = &epoch::pin();
+        for (multicast, prefix_record) in rib.prefix_records(guard) {
+            let prefix_record = prefix_record.map_err(|err| err.to_string())?;
+            for rec in prefix_record.meta {
+                if rec.status != RouteStatus::Withdrawn {
+                    continue;
+                }
+                let key = (prefix_record.prefix, multicast, rec.multi_uniq_id);
+                let withdrawn_at =
+                    self.since.get(&key).copied().unwrap_or(now);
+                if now.duration_since(withdrawn_at) >= retention {
+                    due += 1;
+                }
+                since.insert(key, withdrawn_at);
+            }
+        }
+        self.since = since;
+        Ok(due)
+    }
+
+    /// Whether `rec` should be carried over into the rebuilt RIB.
+    pub fn keep(
+        &self,
+        prefix: &Prefix,
+        multicast: bool,
+        rec: &Record<RotondaPaMap>,
+        now: Instant,
+        retention: Duration,
+    ) -> bool {
+        if rec.status != RouteStatus::Withdrawn {
+            return true;
+        }
+        match self.since.get(&(*prefix, multicast, rec.multi_uniq_id)) {
+            Some(withdrawn_at) => now.duration_since(*withdrawn_at) < retention,
+            None => true,
+        }
+    }
+}
+
+//------------ Collection ----------------------------------------------------
+
+/// The outcome of a garbage collection run that rebuilt a RIB.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Collected {
+    /// The number of withdrawn routes purged.
+    pub purged: usize,
+
+    /// The decrease in estimated memory use, in bytes.
+    pub reclaimed_bytes: usize,
+}
+
+/// Rebuild `rib` without the routes withdrawn longer than `retention` ago.
+///
+/// Updates to the RIB must hold `rebuild_lock` for reading. It is held for
+/// writing only to start tracking updates, and to replay them into the
+/// rebuilt RIB and swap it in. Returns `None` if there was nothing to purge,
+/// or if the RIB was replaced by someone else in the meantime.
+pub fn collect(
+    rib: &ArcSwap<Rib>,
+    rebuild_lock: &RwLock<()>,
+    since: &mut WithdrawnSince,
+    retention: Duration,
+) -> Result<Option<Collected>, String> {
+    let now = Instant::now();
+    let current = rib.load_full();
+    if since.update(&current, now, retention)? == 0 {
+        return Ok(None);
+    }
+
+    let before = current.memory_usage().estimated_bytes();
+    let target = current.empty_like().map_err(|err| err.to_string())?;
+    let usage = MemoryUsage::default();
+    let keep = |prefix: &Prefix, multicast, rec: &Record<RotondaPaMap>| {
+        since.keep(prefix, multicast, rec, now, retention)
+    };
+
+    {
+        let _rebuild_guard = rebuild_lock.write().unwrap();
+        current.start_tracking();
+    }
+    let purged = match current.copy_into(&target, None, &usage, keep) {
+        Ok(purged) => purged,
+        Err(err) => {
+            current.stop_tracking();
+            return Err(err);
+        }
+    };
+
+    let _rebuild_guard = rebuild_lock.write().unwrap();
+    let tracked = current.stop_tracking();
+    if !Arc::ptr_eq(&rib.load(), &current) {
+        return Ok(None);
+    }
+    target.replay(&current, &tracked, &usage, keep)?;
+    target.memory_usage().set_to(&usage);
+    rib.store(Arc::new(target));
+
+    Ok(Some(Collected {
+        purged,
+        reclaimed_bytes: before.saturating_sub(usage.estimated_bytes()),
+    }))
+}
@@ -0,0 +1,740 @@
+//! A GraphQL API over the RIB and the state of the pipeline.
+//!
+//! With a `graphql` section, the HTTP API of the RIB answers GraphQL
+//! queries at `<http_api_path>graphql`, given in the `query` parameter
+//! with optional `variables` as JSON and an `operationName`. The query
+//! root has:
+//!
+//! - `routes`, the routes in the main RIB, filtered and paged like the
+//!   `routes` listing of the HTTP API,
+//! - `ingresses`, `ingress(id)` and `peers`, the ingresses known, peers
+//!   being those with a remote ASN, each with their own `routes`,
+//! - `units`, the units and targets with metrics or an HTTP API, and
+//! - `metrics`, the current metrics of all of those.
+//!
+//! Routes, ingresses and units link to each other, so a single query can
+//! for example select the peers of an AS with the attributes of the routes
+//! received from each. How deep selections can nest and how many routes a
+//! query can list are limited by the configuration.
+//!
+//! Queries are executed by [async-graphql], with the fields and arguments
+//! named in snake case like the rest of the HTTP API.
+//!
+//! [async-graphql]: https://docs.rs/async-graphql/
+
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+use arc_swap::ArcSwap;
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Json, Object, Request, Result,
+    Schema, Variables,
+};
+use inetnum::{addr::Prefix, asn::Asn};
+use rotonda_store::prefix_record::Record;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::http;
+use crate::ingress::{self, IngressId, IngressInfo};
+use crate::metrics::{self, OutputFormat};
+use crate::payload::RotondaPaMap;
+use crate::units::RibType;
+
+use super::index::{
+    self, compile_as_path_regex, CommunityPattern, RouteSearch,
+};
+use super::rib::{Paging, Rib, RoutePosition};
+use super::rpki::RovStatus;
+use super::tags::TagFilter;
+
+//------------ GraphQlConfig -------------------------------------------------
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GraphQlConfig {
+    /// How deep selections can be nested.
+    #[serde(default = "GraphQlConfig::default_max_depth")]
+    pub max_depth: usize,
+
+    /// The most routes a single selection of routes can list.
+    #[serde(default = "GraphQlConfig::default_max_routes")]
+    pub max_routes: usize,
+}
+
+impl GraphQlConfig {
+    fn default_max_depth() -> usize {
+        8
+    }
+
+    fn default_max_routes() -> usize {
+        1000
+    }
+}
+
+impl Default for GraphQlConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: Self::default_max_depth(),
+            max_routes: Self::default_max_routes(),
+        }
+    }
+}
+
+//------------ GraphQlApi ----------------------------------------------------
+
+/// Answers GraphQL queries.
+pub struct GraphQlApi {
+    rib: Arc<ArcSwap<Rib>>,
+    schema: Schema<Query, EmptyMutation, EmptySubscription>,
+}
+
+impl GraphQlApi {
+    pub(super) fn new(
+        config: GraphQlConfig,
+        rib: Arc<ArcSwap<Rib>>,
+        rib_type: RibType,
+        ingresses: Arc<ingress::Register>,
+        metrics: Option<metrics::Collection>,
+        http_resources: http::Resources,
+    ) -> Self {
+        let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .data(Sources {
+                max_routes: config.max_routes,
+                rib_type,
+                ingresses,
+                metrics,
+                http_resources,
+            })
+            .limit_depth(config.max_depth)
+            .finish();
+        Self { rib, schema }
+    }
+
+    /// Answers `query`, with `variables` given as a JSON object.
+    ///
+    /// The query is resolved on the current thread, searching the RIB as
+    /// it goes, so this should be run as a blocking task.
+    pub fn execute(
+        &self,
+        query: &str,
+        variables: Option<&str>,
+        operation: Option<&str>,
+    ) -> serde_json::Value {
+        let mut request = Request::new(query)
+            .data(self.rib.load_full())
+            .data(AssembledMetrics::default());
+        if let Some(operation) = operation {
+            request = request.operation_name(operation);
+        }
+        if let Some(variables) = variables.filter(|vars| !vars.is_empty()) {
+            match serde_json::from_str(variables) {
+                Ok(variables) => {
+                    request =
+                        request.variables(Variables::from_json(variables))
+                }
+                Err(err) => {
+                    return json!({
+                        "data": null,
+                        "errors": [{
+                            "message": format!("Invalid variables: {err}"),
+                        }],
+                    })
+                }
+            }
+        }
+        let response =
+            futures::executor::block_on(self.schema.execute(request));
+        serde_json::to_value(response).unwrap_or_default()
+    }
+}
+
+//------------ Sources -------------------------------------------------------
+
+/// What queries are answered from besides the RIB.
+///
+/// The RIB itself is loaded for each query, so that a query sees the same
+/// RIB throughout.
+struct Sources {
+    max_routes: usize,
+    rib_type: RibType,
+    ingresses: Arc<ingress::Register>,
+    metrics: Option<metrics::Collection>,
+    http_resources: http::Resources,
+}
+
+/// The arguments of a `routes` field.
+struct RouteArgs {
+    prefix: Option<String>,
+    origin_as: Vec<u32>,
+    community: Vec<String>,
+    as_path_regex: Option<String>,
+    rpki: Vec<String>,
+    tag: Vec<String>,
+    sort: Option<String>,
+    after: Option<String>,
+    limit: Option<i64>,
+}
+
+impl Sources {
+    /// Lists the routes matching the arguments of a `routes` field.
+    ///
+    /// The routes are limited to those learned from `ingress` and its
+    /// descendants, if given.
+    fn routes(
+        &self,
+        rib: &Rib,
+        args: RouteArgs,
+        ingress: Option<IngressId>,
+    ) -> Result<Vec<Route>, String> {
+        if self.rib_type != RibType::Physical {
+            return Err("unsupported on virtual rib".to_string());
+        }
+
+        let mut search = RouteSearch::default();
+        if let Some(prefix) = args.prefix {
+            search.prefix =
+                Some(Prefix::from_str(&prefix).map_err(|err| {
+                    format!("Invalid prefix '{prefix}': {err}")
+                })?);
+        }
+        search
+            .origin_asns
+            .extend(args.origin_as.into_iter().map(Asn::from_u32));
+        for community in &args.community {
+            search
+                .communities
+                .push(CommunityPattern::from_str(community)?);
+        }
+        if let Some(regex) = &args.as_path_regex {
+            search.as_path_regex = Some(compile_as_path_regex(regex)?);
+        }
+        for status in &args.rpki {
+            search.rov_statuses.push(RovStatus::from_str(status)?);
+        }
+        for tag in &args.tag {
+            search.tags.push(TagFilter::from_str(tag)?);
+        }
+        if let Some(id) = ingress {
+            let mut ids: HashSet<_> =
+                self.ingresses.descendants(id).into_iter().collect();
+            ids.insert(id);
+            search.ingresses = Some(ids);
+        }
+
+        let mut paging = Paging {
+            limit: self.max_routes,
+            ..Default::default()
+        };
+        if let Some(limit) = args.limit {
+            paging.limit = usize::try_from(limit)
+                .map_err(|_| format!("Invalid limit {limit}"))?
+                .min(self.max_routes);
+        }
+        if let Some(sort) = &args.sort {
+            paging.set_sort(sort)?;
+        }
+        if let Some(after) = &args.after {
+            paging.after = Some(RoutePosition::from_str(after)?);
+        }
+
+        let (routes, _) = rib.page(&search, &paging)?;
+        Ok(routes
+            .into_iter()
+            .map(|(position, record)| Route { position, record })
+            .collect())
+    }
+
+    fn ingress(&self, id: IngressId) -> Option<Ingress> {
+        self.ingresses.get(id).map(|info| Ingress { id, info })
+    }
+
+    /// Returns the ingresses with the given ids, ordered by id.
+    fn ingress_list(&self, mut ids: Vec<IngressId>) -> Vec<Ingress> {
+        ids.sort_unstable();
+        ids.into_iter().filter_map(|id| self.ingress(id)).collect()
+    }
+
+    /// Returns the names of the units and targets, in order.
+    fn unit_names(&self) -> Vec<Arc<str>> {
+        let mut res = self
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.names())
+            .unwrap_or_default();
+        res.extend(
+            self.http_resources
+                .resources()
+                .iter()
+                .map(|resource| resource.component_name.clone()),
+        );
+        res.sort();
+        res.dedup();
+        res
+    }
+
+    /// Returns the current metrics with the given name and for the given
+    /// unit.
+    ///
+    /// The metrics are assembled once per query.
+    fn metrics(
+        &self,
+        ctx: &Context<'_>,
+        name: Option<&str>,
+        unit: Option<&str>,
+    ) -> Vec<Metric> {
+        let metrics =
+            ctx.data_unchecked::<AssembledMetrics>().0.get_or_init(|| {
+                match &self.metrics {
+                    Some(metrics) => parse_metrics(
+                        &metrics.assemble(OutputFormat::Prometheus),
+                    ),
+                    None => Vec::new(),
+                }
+            });
+        metrics
+            .iter()
+            .filter(|metric| name.is_none_or(|name| metric.name == name))
+            .filter(|metric| {
+                unit.is_none_or(|unit| metric.component() == Some(unit))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// The metrics, assembled when first selected in a query.
+#[derive(Default)]
+struct AssembledMetrics(OnceLock<Vec<Metric>>);
+
+/// Returns the sources and the RIB of a query.
+fn sources<'a>(ctx: &Context<'a>) -> (&'a Sources, &'a Rib) {
+    (
+        ctx.data_unchecked::<Sources>(),
+        ctx.data_unchecked::<Arc<Rib>>(),
+    )
+}
+
+/// Returns any serializable value as JSON.
+fn json_value(value: impl Serialize) -> Json<serde_json::Value> {
+    Json(serde_json::to_value(value).unwrap_or_default())
+}
+
+//------------ Query ---------------------------------------------------------
+
+/// The query root.
+struct Query;
+
+#[Object(rename_fields = "snake_case", rename_args = "snake_case")]
+impl Query {
+    #[allow(clippy::too_many_arguments)]
+    async fn routes(
+        &self,
+        ctx: &Context<'_>,
+        prefix: Option<String>,
+        #[graphql(default)] origin_as: Vec<u32>,
+        #[graphql(default)] community: Vec<String>,
+        as_path_regex: Option<String>,
+        #[graphql(default)] rpki: Vec<String>,
+        #[graphql(default)] tag: Vec<String>,
+        sort: Option<String>,
+        after: Option<String>,
+        limit: Option<i64>,
+        ingress_id: Option<IngressId>,
+    ) -> Result<Vec<Route>> {
+        let (sources, rib) = sources(ctx);
+        let args = RouteArgs {
+            prefix,
+            origin_as,
+            community,
+            as_path_regex,
+            rpki,
+            tag,
+            sort,
+            after,
+            limit,
+        };
+        Ok(sources.routes(rib, args, ingress_id)?)
+    }
+
+    async fn ingress(
+        &self,
+        ctx: &Context<'_>,
+        id: IngressId,
+    ) -> Option<Ingress> {
+        sources(ctx).0.ingress(id)
+    }
+
+    async fn ingresses(
+        &self,
+        ctx: &Context<'_>,
+        unit: Option<String>,
+    ) -> Vec<Ingress> {
+        let sources = sources(ctx).0;
+        let ids = sources.ingresses.find_all(|info| {
+            unit.as_deref()
+                .is_none_or(|unit| info.unit_name.as_deref() == Some(unit))
+        });
+        sources.ingress_list(ids)
+    }
+
+    async fn peers(
+        &self,
+        ctx: &Context<'_>,
+        remote_asn: Option<u32>,
+        remote_addr: Option<String>,
+    ) -> Result<Vec<Ingress>> {
+        let sources = sources(ctx).0;
+        let addr = remote_addr
+            .map(|addr| {
+                addr.parse().map_err(|err| {
+                    format!("Invalid remote_addr '{addr}': {err}")
+                })
+            })
+            .transpose()?;
+        let ids = sources.ingresses.find_all(|info| {
+            info.remote_asn.is_some_and(|remote| {
+                remote_asn.is_none_or(|asn| remote.into_u32() == asn)
+            }) && addr.is_none_or(|addr| info.remote_addr == Some(addr))
+        });
+        Ok(sources.ingress_list(ids))
+    }
+
+    async fn units(&self, ctx: &Context<'_>) -> Vec<Unit> {
+        sources(ctx)
+            .0
+            .unit_names()
+            .into_iter()
+            .map(|name| Unit { name })
+            .collect()
+    }
+
+    async fn metrics(
+        &self,
+        ctx: &Context<'_>,
+        name: Option<String>,
+        unit: Option<String>,
+    ) -> Vec<Metric> {
+        sources(ctx)
+            .0
+            .metrics(ctx, name.as_deref(), unit.as_deref())
+    }
+}
+
+//------------ Route ---------------------------------------------------------
+
+struct Route {
+    position: RoutePosition,
+    record: Record<RotondaPaMap>,
+}
+
+#[Object(rename_fields = "snake_case", rename_args = "snake_case")]
+impl Route {
+    async fn prefix(&self) -> String {
+        self.position.prefix.to_string()
+    }
+
+    async fn ingress_id(&self) -> IngressId {
+        self.position.ingress_id
+    }
+
+    async fn multicast(&self) -> bool {
+        self.position.multicast
+    }
+
+    async fn cursor(&self) -> String {
+        self.position.to_string()
+    }
+
+    async fn status(&self) -> String {
+        self.record.status.to_string()
+    }
+
+    async fn origin_as(&self) -> Option<u32> {
+        index::origin_asn(&self.record.meta).map(|asn| asn.into_u32())
+    }
+
+    async fn as_path(&self) -> Option<String> {
+        index::as_path_string(&self.record.meta)
+    }
+
+    async fn communities(&self) -> Vec<String> {
+        index::communities(&self.record.meta)
+            .iter()
+            .map(|community| community.to_string())
+            .collect()
+    }
+
+    async fn rpki(&self) -> Json<serde_json::Value> {
+        json_value(self.record.meta.rpki_info())
+    }
+
+    async fn tags(&self, ctx: &Context<'_>) -> Json<serde_json::Value> {
+        json_value(
+            sources(ctx)
+                .1
+                .tags()
+                .get(self.position.prefix, self.position.ingress_id)
+                .unwrap_or_default(),
+        )
+    }
+
+    async fn attributes(&self) -> Json<serde_json::Value> {
+        json_value(&self.record.meta)
+    }
+
+    async fn ingress(&self, ctx: &Context<'_>) -> Option<Ingress> {
+        sources(ctx).0.ingress(self.position.ingress_id)
+    }
+}
+
+//------------ Ingress -------------------------------------------------------
+
+struct Ingress {
+    id: IngressId,
+    info: IngressInfo,
+}
+
+#[Object(rename_fields = "snake_case", rename_args = "snake_case")]
+impl Ingress {
+    async fn id(&self) -> IngressId {
+        self.id
+    }
+
+    async fn unit(&self) -> Option<&str> {
+        self.info.unit_name.as_deref()
+    }
+
+    async fn parent(&self, ctx: &Context<'_>) -> Option<Ingress> {
+        self.info
+            .parent_ingress
+            .and_then(|parent| sources(ctx).0.ingress(parent))
+    }
+
+    async fn children(&self, ctx: &Context<'_>) -> Vec<Ingress> {
+        let sources = sources(ctx).0;
+        sources.ingress_list(sources.ingresses.ids_for_parent(self.id))
+    }
+
+    async fn remote_addr(&self) -> Option<String> {
+        self.info.remote_addr.map(|addr| addr.to_string())
+    }
+
+    async fn remote_asn(&self) -> Option<u32> {
+        self.info.remote_asn.map(|asn| asn.into_u32())
+    }
+
+    async fn name(&self) -> Option<&str> {
+        self.info.name.as_deref()
+    }
+
+    async fn desc(&self) -> Option<&str> {
+        self.info.desc.as_deref()
+    }
+
+    /// The routes learned from the ingress and its descendants.
+    #[allow(clippy::too_many_arguments)]
+    async fn routes(
+        &self,
+        ctx: &Context<'_>,
+        prefix: Option<String>,
+        #[graphql(default)] origin_as: Vec<u32>,
+        #[graphql(default)] community: Vec<String>,
+        as_path_regex: Option<String>,
+        #[graphql(default)] rpki: Vec<String>,
+        #[graphql(default)] tag: Vec<String>,
+        sort: Option<String>,
+        after: Option<String>,
+        limit: Option<i64>,
+    ) -> Result<Vec<Route>> {
+        let (sources, rib) = sources(ctx);
+        let args = RouteArgs {
+            prefix,
+            origin_as,
+            community,
+            as_path_regex,
+            rpki,
+            tag,
+            sort,
+            after,
+            limit,
+        };
+        Ok(sources.routes(rib, args, Some(self.id))?)
+    }
+}
+
+//------------ Unit ----------------------------------------------------------
+
+/// A unit or target.
+struct Unit {
+    name: Arc<str>,
+}
+
+impl Unit {
+    fn resource(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Option<Arc<http::RegisteredResource>> {
+        sources(ctx)
+            .0
+            .http_resources
+            .resources()
+            .into_iter()
+            .find(|resource| resource.component_name == self.name)
+    }
+}
+
+#[Object(rename_fields = "snake_case", rename_args = "snake_case")]
+impl Unit {
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[graphql(name = "type")]
+    async fn component_type(&self, ctx: &Context<'_>) -> Option<&str> {
+        self.resource(ctx).map(|resource| resource.component_type)
+    }
+
+    async fn http_api_path(&self, ctx: &Context<'_>) -> Option<String> {
+        self.resource(ctx)
+            .map(|resource| resource.rel_base_url.as_ref().clone())
+    }
+
+    async fn metrics(
+        &self,
+        ctx: &Context<'_>,
+        name: Option<String>,
+    ) -> Vec<Metric> {
+        sources(ctx)
+            .0
+            .metrics(ctx, name.as_deref(), Some(&self.name))
+    }
+}
+
+//------------ Metric --------------------------------------------------------
+
+/// A single metric value.
+#[derive(Clone, Debug, PartialEq)]
+struct Metric {
+    name: String,
+    labels: Vec<(String, String)>,
+    value: serde_json::Value,
+}
+
+impl Metric {
+    /// The unit or target the value is for, if any.
+    fn component(&self) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(label, _)| label == "component")
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[Object(rename_fields = "snake_case", rename_args = "snake_case")]
+impl Metric {
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn unit(&self) -> Option<&str> {
+        self.component()
+    }
+
+    async fn labels(&self) -> Json<serde_json::Value> {
+        Json(
+            self.labels
+                .iter()
+                .map(|(label, value)| (label.clone(), json!(value)))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+        )
+    }
+
+    async fn value(&self) -> Json<serde_json::Value> {
+        Json(self.value.clone())
+    }
+}
+
+/// Parses metrics in the Prometheus text format.
+///
+/// Values are lines of the metric name, its labels in braces if any, and
+/// the value. Label values are taken as is, as those in the metrics of
+/// Rotonda need no escaping.
+fn parse_metrics(text: &str) -> Vec<Metric> {
+    text.lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (series, value) = line.rsplit_once(' ')?;
+            let (name, labels) = match series.split_once('{') {
+                Some((name, labels)) => (name, labels.strip_suffix('}')?),
+                None => (series, ""),
+            };
+            let labels = labels
+                .split("\",")
+                .filter(|label| !label.is_empty())
+                .filter_map(|label| {
+                    let (label, value) = label.split_once("=\"")?;
+                    let value = value.strip_suffix('"').unwrap_or(value);
+                    Some((label.to_string(), value.to_string()))
+                })
+                .collect();
+            let value = match value.parse::<i64>() {
+                Ok(value) => json!(value),
+                Err(_) => match value.parse::<f64>() {
+                    Ok(value) if value.is_finite() => json!(value),
+                    _ => json!(value),
+                },
+            };
+            Some(Metric {
+                name: name.to_string(),
+                labels,
+                value,
+            })
+        })
+        .collect()
+}
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_are_parsed() {
+        let metrics = parse_metrics(
+            "# HELP rotonda_x_total some help\n\
+             # TYPE rotonda_x_total counter\n\
+             rotonda_x_total{component=\"rib\",afi=\"ipv4\"} 12\n\
+             rotonda_uptime 1.5\n\
+             rotonda_odd{component=\"a\"} NaN\n",
+        );
+        assert_eq!(
+            metrics,
+            [
+                Metric {
+                    name: "rotonda_x_total".into(),
+                    labels: vec![
+                        ("component".into(), "rib".into()),
+                        ("afi".into(), "ipv4".into()),
+                    ],
+                    value: json!(12),
+                },
+                Metric {
+                    name: "rotonda_uptime".into(),
+                    labels: vec![],
+                    value: json!(1.5),
+                },
+                Metric {
+                    name: "rotonda_odd".into(),
+                    labels: vec![("component".into(), "a".into())],
+                    value: json!("NaN"),
+                },
+            ]
+        );
+        assert_eq!(metrics[0].component(), Some("rib"));
+        assert_eq!(metrics[1].component(), None);
+    }
+}
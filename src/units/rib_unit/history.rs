@@ -0,0 +1,312 @@
+//! Retaining the history of the routes in the RIB.
+//!
+//! The store only holds the latest version of each route. With history
+//! enabled, the RIB unit also keeps the earlier versions of every route in
+//! the main RIB: when the route changed, its status and its attributes, so
+//! it can be told what a prefix looked like at some point in the past, and
+//! which peer changed it.
+//!
+//! Up to `max_versions` versions are kept per route, and versions older
+//! than `max_age_secs` are dropped when the route next changes, or left out
+//! when queried. The latest version of a route is always kept, whatever its
+//! age, as it describes the route as it currently is.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use inetnum::addr::Prefix;
+use rotonda_store::prefix_record::RouteStatus;
+use routecore::bgp::types::AfiSafiType;
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use crate::{ingress::IngressId, payload::RotondaPaMap};
+
+use super::wal::WalEntry;
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct HistoryConfig {
+    /// The number of versions to keep per route. 0 does not limit the
+    /// number of versions.
+    #[serde(default = "HistoryConfig::default_max_versions")]
+    pub max_versions: usize,
+
+    /// How long to keep the versions of a route after they were replaced.
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    #[serde(default)]
+    pub max_age_secs: Option<Duration>,
+}
+
+impl HistoryConfig {
+    fn default_max_versions() -> usize {
+        10
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            max_versions: Self::default_max_versions(),
+            max_age_secs: None,
+        }
+    }
+}
+
+//------------ Version -------------------------------------------------------
+
+/// A version of a route.
+#[derive(Clone, Debug)]
+pub struct Version {
+    pub prefix: Prefix,
+    pub multicast: bool,
+    pub ingress_id: IngressId,
+
+    /// When the route changed to this version.
+    pub time: DateTime<Utc>,
+
+    pub status: RouteStatus,
+
+    /// The attributes of the route, which for a withdrawn route are those
+    /// it was last announced with.
+    pub attributes: RotondaPaMap,
+}
+
+//------------ RouteHistory --------------------------------------------------
+
+type Versions = VecDeque<Version>;
+
+/// The versions of the routes in a RIB.
+#[derive(Debug)]
+pub struct RouteHistory {
+    config: HistoryConfig,
+    routes: Mutex<HashMap<(Prefix, bool), HashMap<IngressId, Versions>>>,
+}
+
+impl RouteHistory {
+    pub fn new(config: HistoryConfig) -> Self {
+        Self {
+            config,
+            routes: Default::default(),
+        }
+    }
+
+    /// Record the change to the RIB described by `entry`.
+    pub fn record(&self, entry: &WalEntry) {
+        let time = Utc::now();
+        let mut routes = self.routes.lock().unwrap();
+        match entry {
+            WalEntry::Insert {
+                prefix,
+                multicast,
+                record,
+            } => {
+                let versions = routes
+                    .entry((*prefix, *multicast))
+                    .or_default()
+                    .entry(record.multi_uniq_id)
+                    .or_default();
+                self.push(
+                    versions,
+                    Version {
+                        prefix: *prefix,
+                        multicast: *multicast,
+                        ingress_id: record.multi_uniq_id,
+                        time,
+                        status: record.status,
+                        attributes: record.meta.clone(),
+                    },
+                );
+            }
+            WalEntry::WithdrawPrefix {
+                prefix,
+                multicast,
+                ingress_id,
+            } => {
+                // A withdrawal of a route never announced leaves nothing
+                // to record.
+                if let Some(versions) = routes
+                    .get_mut(&(*prefix, *multicast))
+                    .and_then(|ingresses| ingresses.get_mut(ingress_id))
+                {
+                    self.change_status(versions, time, |_| {
+                        Some(RouteStatus::Withdrawn)
+                    });
+                }
+            }
+            WalEntry::WithdrawIngress {
+                ingress_id,
+                afisafi,
+            } => self.change_status_for_ingress(
+                &mut routes,
+                *ingress_id,
+                *afisafi,
+                time,
+                |_| Some(RouteStatus::Withdrawn),
+            ),
+            WalEntry::MarkStale {
+                ingress_id,
+                afisafi,
+            } => self.change_status_for_ingress(
+                &mut routes,
+                *ingress_id,
+                *afisafi,
+                time,
+                |status| {
+                    (status == RouteStatus::Active)
+                        .then_some(RouteStatus::InActive)
+                },
+            ),
+            WalEntry::ExpireStale {
+                ingress_id,
+                afisafi,
+            } => self.change_status_for_ingress(
+                &mut routes,
+                *ingress_id,
+                *afisafi,
+                time,
+                |status| {
+                    (status == RouteStatus::InActive)
+                        .then_some(RouteStatus::Withdrawn)
+                },
+            ),
+        }
+    }
+
+    fn change_status_for_ingress(
+        &self,
+        routes: &mut HashMap<(Prefix, bool), HashMap<IngressId, Versions>>,
+        ingress_id: IngressId,
+        afisafi: Option<AfiSafiType>,
+        time: DateTime<Utc>,
+        new_status: impl Fn(RouteStatus) -> Option<RouteStatus>,
+    ) {
+        for (&(prefix, multicast), ingresses) in routes.iter_mut() {
+            if !in_afisafi(&prefix, multicast, afisafi) {
+                continue;
+            }
+            if let Some(versions) = ingresses.get_mut(&ingress_id) {
+                self.change_status(versions, time, &new_status);
+            }
+        }
+    }
+
+    /// Add a version with the status changed as given by `new_status`, and
+    /// the attributes of the latest version. Nothing is added if
+    /// `new_status` returns `None`, or the status would stay the same.
+    fn change_status(
+        &self,
+        versions: &mut Versions,
+        time: DateTime<Utc>,
+        new_status: impl Fn(RouteStatus) -> Option<RouteStatus>,
+    ) {
+        let Some(latest) = versions.back() else {
+            return;
+        };
+        let Some(status) = new_status(latest.status) else {
+            return;
+        };
+        if status == latest.status {
+            return;
+        }
+        let version = Version {
+            time,
+            status,
+            ..latest.clone()
+        };
+        self.push(versions, version);
+    }
+
+    /// Add `version` as the latest version, unless it is the same as the
+    /// current latest version, and drop the versions no longer retained.
+    fn push(&self, versions: &mut Versions, version: Version) {
+        if versions.back().is_some_and(|latest| {
+            latest.status == version.status
+                && latest.attributes.as_ref() == version.attributes.as_ref()
+        }) {
+            return;
+        }
+        let now = version.time;
+        versions.push_back(version);
+
+        if self.config.max_versions > 0 {
+            while versions.len() > self.config.max_versions {
+                versions.pop_front();
+            }
+        }
+        while versions.len() > 1 && self.expired(&versions[1], now) {
+            versions.pop_front();
+        }
+    }
+
+    /// Whether the versions before `next` are too old to keep.
+    fn expired(&self, next: &Version, now: DateTime<Utc>) -> bool {
+        self.config.max_age_secs.is_some_and(|max_age| {
+            (now - next.time).to_std().is_ok_and(|age| age > max_age)
+        })
+    }
+
+    /// All retained versions of the routes for `prefix`, oldest first.
+    pub fn versions(&self, prefix: &Prefix) -> Vec<Version> {
+        let now = Utc::now();
+        let routes = self.routes.lock().unwrap();
+        let mut res = vec![];
+        for multicast in [false, true] {
+            let Some(ingresses) = routes.get(&(*prefix, multicast)) else {
+                continue;
+            };
+            for versions in ingresses.values() {
+                res.extend(versions.iter().enumerate().filter_map(
+                    |(i, version)| match versions.get(i + 1) {
+                        Some(next) if self.expired(next, now) => None,
+                        _ => Some(version.clone()),
+                    },
+                ));
+            }
+        }
+        res.sort_by_key(|version| (version.time, version.ingress_id));
+        res
+    }
+
+    /// The versions of the routes for `prefix` current at `time`, if
+    /// they are still retained.
+    pub fn versions_at(
+        &self,
+        prefix: &Prefix,
+        time: DateTime<Utc>,
+    ) -> Vec<Version> {
+        let mut current = HashMap::new();
+        for version in self.versions(prefix) {
+            if version.time <= time {
+                current
+                    .insert((version.multicast, version.ingress_id), version);
+            }
+        }
+        let mut res = current.into_values().collect::<Vec<_>>();
+        res.sort_by_key(|version| (version.time, version.ingress_id));
+        res
+    }
+}
+
+/// Whether `prefix` belongs to `afisafi`, with `None` covering all.
+fn in_afisafi(
+    prefix: &Prefix,
+    multicast: bool,
+    afisafi: Option<AfiSafiType>,
+) -> bool {
+    let v4 = prefix.is_v4();
+    match afisafi {
+        None => true,
+        Some(AfiSafiType::Ipv4Unicast) => v4 && !multicast,
+        Some(AfiSafiType::Ipv6Unicast) => !v4 && !multicast,
+        Some(AfiSafiType::Ipv4Multicast) => v4 && multicast,
+        Some(AfiSafiType::Ipv6Multicast) => !v4 && multicast,
+        Some(_) => false,
+    }
+}
@@ -1,28 +1,52 @@
-use std::{ops::Deref, str::FromStr, sync::Arc};
+use std::{
+    collections::HashSet, net::IpAddr, ops::Deref, str::FromStr, sync::Arc,
+};
 
 use arc_swap::{ArcSwap, ArcSwapOption};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use hyper::{Body, Method, Request, Response};
 use inetnum::{addr::Prefix, asn::Asn};
 use log::{debug, trace};
-use rotonda_store::match_options::{self, IncludeHistory, MatchOptions};
-use routecore::bgp::communities::HumanReadableCommunity as Community;
+use rotonda_store::{
+    match_options::{self, IncludeHistory, MatchOptions, QueryResult},
+    prefix_record::Record,
+};
+use routecore::bgp::{
+    communities::HumanReadableCommunity as Community, types::Afi,
+};
 use tokio::sync::oneshot;
 use uuid::Uuid;
 
 use crate::{
+    common::openapi::{Operation, Paths, Schema},
     comms::{Link, TriggerData},
     http::{
-        extract_params, get_all_params, get_param, MatchedParam,
-        PercentDecodedPath, ProcessRequest, QueryParams,
+        check_unused_params, extract_params, get_all_params, get_param,
+        MatchedParam, PercentDecodedPath, ProcessRequest, QueryParams,
     },
     ingress,
+    payload::RotondaPaMap,
     units::{
+        flow_in::counters::TrafficCounters,
         rib_unit::{
-            http::types::{FilterKind, FilterOp},
-            rib::Rib,
+            best_path,
+            compaction::{CompactionTrigger, Compactor},
+            consistency::ConsistencyChecker,
+            diff::RibContents,
+            filter_trace::FilterTracer,
+            graphql::GraphQlApi,
+            history::RouteHistory,
+            http::types::{Dump, DumpField, FilterKind, FilterOp},
+            index::{compile_as_path_regex, CommunityPattern, RouteSearch},
+            rib::{Paging, Rib, RoutePosition},
+            rpki::RovStatus,
+            snapshot::SnapshotLocation,
+            stats::{RibStats, StatsConfig},
+            tags::TagFilter,
             unit::{PendingVirtualRibQueryResults, QueryLimits},
         },
+        static_routes_in::routes::RouteSet,
         RibType,
     },
 };
@@ -37,6 +61,13 @@ pub struct PrefixesApi {
     vrib_upstream: Arc<ArcSwapOption<Link>>,
     pending_vrib_query_results: Arc<PendingVirtualRibQueryResults>,
     ingress_register: Arc<ingress::Register>,
+    snapshots: ArcSwapOption<SnapshotLocation>,
+    history: ArcSwapOption<RouteHistory>,
+    consistency: ArcSwapOption<ConsistencyChecker>,
+    compactor: ArcSwapOption<Compactor>,
+    traffic: ArcSwapOption<TrafficCounters>,
+    filter_tracer: ArcSwapOption<FilterTracer>,
+    graphql: ArcSwapOption<GraphQlApi>,
 }
 
 impl PrefixesApi {
@@ -59,6 +90,13 @@ impl PrefixesApi {
             )),
             pending_vrib_query_results,
             ingress_register,
+            snapshots: ArcSwapOption::empty(),
+            history: ArcSwapOption::empty(),
+            consistency: ArcSwapOption::empty(),
+            compactor: ArcSwapOption::empty(),
+            traffic: ArcSwapOption::empty(),
+            filter_tracer: ArcSwapOption::empty(),
+            graphql: ArcSwapOption::empty(),
         }
     }
 
@@ -69,6 +107,41 @@ impl PrefixesApi {
     pub fn set_vrib_upstream(&self, vrib_upstream: Option<Link>) {
         self.vrib_upstream.store(vrib_upstream.map(Arc::new));
     }
+
+    /// Make the snapshots at `location` available for comparison.
+    pub fn set_snapshots(&self, location: SnapshotLocation) {
+        self.snapshots.store(Some(Arc::new(location)));
+    }
+
+    /// Make the retained versions of the routes in `history` queryable.
+    pub fn set_history(&self, history: Arc<RouteHistory>) {
+        self.history.store(Some(history));
+    }
+
+    /// Make the reports of `checker` available.
+    pub fn set_consistency(&self, checker: Arc<ConsistencyChecker>) {
+        self.consistency.store(Some(checker));
+    }
+
+    /// Allow the on-disk state to be compacted by `compactor` on request.
+    pub fn set_compactor(&self, compactor: Arc<Compactor>) {
+        self.compactor.store(Some(compactor));
+    }
+
+    /// Include the traffic counted by `traffic` in prefix query results.
+    pub fn set_traffic(&self, traffic: Arc<TrafficCounters>) {
+        self.traffic.store(Some(traffic));
+    }
+
+    /// Allow the roto filter to be traced by `tracer`.
+    pub fn set_filter_tracer(&self, tracer: Arc<FilterTracer>) {
+        self.filter_tracer.store(Some(tracer));
+    }
+
+    /// Answer GraphQL queries with `api`.
+    pub fn set_graphql(&self, api: Arc<GraphQlApi>) {
+        self.graphql.store(Some(api));
+    }
 }
 
 #[async_trait]
@@ -88,12 +161,47 @@ impl ProcessRequest for PrefixesApi {
 
         debug!("RibUnit ProcessRequest {:?}", &req_path);
         // e.g. req_path = "/prefixes/2804:1398:100::/48"
-        if request.method() == Method::GET
+        if (request.method() == Method::GET
+            || request.method() == Method::POST)
             && req_path.starts_with(self.http_api_path.deref())
         {
-            let res = match request.uri().path().split("/").count() {
-                3 => self.handle_ingress_id_query(req_path, request).await,
-                _ => self.handle_prefix_query(req_path, request).await,
+            // SAFETY: strip_prefix() cannot fail due to the starts_with()
+            // check above
+            let query =
+                req_path.strip_prefix(self.http_api_path.as_str()).unwrap();
+            let res = if request.method() == Method::POST {
+                if query != "compaction" {
+                    return None;
+                }
+                self.handle_compaction_request(request).await
+            } else if query.is_empty() {
+                self.handle_search_query(request).await
+            } else if query == "routes" {
+                self.handle_routes_query(request).await
+            } else if query == "ingresses" {
+                self.handle_ingresses_query(request).await
+            } else if query == "snapshots" {
+                self.handle_snapshots_query(request).await
+            } else if query == "diff" {
+                self.handle_diff_query(request).await
+            } else if query == "dump" {
+                self.handle_dump_query(request).await
+            } else if query == "stats" {
+                self.handle_stats_query(request).await
+            } else if query == "consistency" {
+                self.handle_consistency_query(request).await
+            } else if query == "compaction" {
+                self.handle_compaction_query(request).await
+            } else if query == "filter-trace" {
+                self.handle_filter_trace_query(request).await
+            } else if query == "graphql" {
+                self.handle_graphql_query(request).await
+            } else if let Some(prefix) = query.strip_prefix("history/") {
+                self.handle_history_query(prefix, request).await
+            } else if query.parse::<ingress::IngressId>().is_ok() {
+                self.handle_ingress_id_query(req_path, request).await
+            } else {
+                self.handle_prefix_query(req_path, request).await
             };
             match res {
                 Ok(res) => Some(res),
@@ -111,9 +219,230 @@ impl ProcessRequest for PrefixesApi {
             None
         }
     }
+
+    fn describe(&self, paths: &mut Paths) {
+        self.describe_endpoints(paths)
+    }
 }
 
 impl PrefixesApi {
+    /// Adds the endpoints of the RIB, including those of the features
+    /// enabled, to `paths`.
+    fn describe_endpoints(&self, paths: &mut Paths) {
+        let base = self.http_api_path.as_str();
+
+        let op = paths.get(base, "Search the routes by their attributes");
+        op.query(
+            "as_path_regex",
+            Schema::String,
+            "A regular expression the AS path must match",
+        )
+        .repeated_query(
+            "community",
+            Schema::String,
+            "A community the routes must have, possibly with wildcards",
+        );
+        Self::describe_ingress_params(op);
+        Self::describe_tag_params(op);
+        op.query("limit", Schema::Integer, "The most prefixes to list");
+        Self::describe_details_param(op);
+        Self::describe_filter_params(op);
+        Self::describe_sort_params(op);
+
+        let op = paths.get(
+            format!("{base}{{prefix}}"),
+            "The routes for a prefix, or for the prefix covering an address",
+        );
+        op.path_param("prefix", "A prefix, e.g. 192.0.2.0/24, or an address")
+            .query(
+                "include",
+                Schema::String,
+                "A comma separated list of exactlyMatching, lessSpecifics \
+                and moreSpecifics",
+            );
+        Self::describe_details_param(op);
+        Self::describe_filter_params(op);
+        Self::describe_sort_params(op);
+        Self::describe_ingress_params(op);
+        op.query(
+            "best_only",
+            Schema::Boolean,
+            "Whether to only list the best path of each prefix",
+        )
+        .query(
+            "format",
+            Schema::String,
+            "dump for a diagnostic dump",
+        );
+
+        let op = paths.get(
+            format!("{base}routes"),
+            "List the routes, a page at a time",
+        );
+        op.query(
+            "as_path_regex",
+            Schema::String,
+            "A regular expression the AS path must match",
+        )
+        .repeated_query(
+            "community",
+            Schema::String,
+            "A community the routes must have, possibly with wildcards",
+        )
+        .repeated_query(
+            "origin_as",
+            Schema::String,
+            "An origin AS the routes can have",
+        )
+        .repeated_query(
+            "rpki",
+            Schema::String,
+            "A ROV status the routes can have: valid, invalid, not-found \
+            or not-checked",
+        );
+        Self::describe_ingress_params(op);
+        Self::describe_tag_params(op);
+        op.query("limit", Schema::Integer, "The most routes to list")
+            .query(
+                "sort",
+                Schema::String,
+                "prefix, ingress_id, or either prefixed with - for \
+                descending order",
+            )
+            .query(
+                "cursor",
+                Schema::String,
+                "The next_cursor of the previous page",
+            );
+
+        let op = paths.get(
+            format!("{base}ingresses"),
+            "The ingresses routes were learned from, with their number of \
+            routes",
+        );
+        Self::describe_ingress_params(op);
+
+        let op = paths
+            .get(format!("{base}dump"), "Export all routes")
+            .produces("application/x-ndjson");
+        op.query(
+            "fields",
+            Schema::String,
+            &format!(
+                "A comma separated list of the fields to include: {}",
+                DumpField::ALL.map(DumpField::name).join(", ")
+            ),
+        )
+        .query("afi", Schema::String, "ipv4 or ipv6");
+        Self::describe_tag_params(op);
+
+        paths
+            .get(format!("{base}stats"), "Statistics on the routes")
+            .query(
+                "top",
+                Schema::Integer,
+                "The number of origin ASes with the most prefixes to list",
+            );
+
+        paths
+            .get(
+                format!("{base}{{ingress_id}}"),
+                "The routes learned from an ingress",
+            )
+            .path_param("ingress_id", "The id of the ingress")
+            .produces("text/plain");
+
+        if self.snapshots.load().is_some() {
+            paths.get(
+                format!("{base}snapshots"),
+                "The snapshots available for comparison",
+            );
+            paths
+                .get(
+                    format!("{base}diff"),
+                    "The differences between two snapshots, or a snapshot \
+                    and the live RIB",
+                )
+                .produces("application/x-ndjson")
+                .required_query(
+                    "from",
+                    Schema::String,
+                    "The snapshot to compare from",
+                )
+                .query(
+                    "to",
+                    Schema::String,
+                    "The snapshot to compare to, the live RIB if missing",
+                );
+        }
+        if self.history.load().is_some() {
+            paths
+                .get(
+                    format!("{base}history/{{prefix}}"),
+                    "The retained versions of the routes for a prefix",
+                )
+                .path_param("prefix", "The prefix, e.g. 192.0.2.0/24")
+                .query(
+                    "at",
+                    Schema::String,
+                    "An RFC 3339 time to list the routes as they were then",
+                );
+        }
+        if self.consistency.load().is_some() {
+            paths.get(
+                format!("{base}consistency"),
+                "The last report of the consistency checks",
+            );
+        }
+        if self.compactor.load().is_some() {
+            paths.get(
+                format!("{base}compaction"),
+                "The last report of the compaction of the on-disk state",
+            );
+            paths.post(
+                format!("{base}compaction"),
+                "Compact the on-disk state now and report on it",
+            );
+        }
+        if self.filter_tracer.load().is_some() {
+            paths
+                .get(
+                    format!("{base}filter-trace"),
+                    "Trace the roto filter for a route",
+                )
+                .description(
+                    "Without parameters, the routes sampled for tracing are \
+                    listed.",
+                )
+                .query(
+                    "route",
+                    Schema::String,
+                    "The route to trace, as JSON in the format of the \
+                    static-routes-in unit",
+                )
+                .query(
+                    "sample",
+                    Schema::Integer,
+                    "The id of the sampled route to trace",
+                );
+        }
+        if self.graphql.load().is_some() {
+            paths
+                .get(format!("{base}graphql"), "Answer a GraphQL query")
+                .required_query("query", Schema::String, "The query")
+                .query(
+                    "variables",
+                    Schema::String,
+                    "The variables of the query as a JSON object",
+                )
+                .query(
+                    "operationName",
+                    Schema::String,
+                    "The operation to run, if the query has several",
+                );
+        }
+    }
+
     async fn handle_prefix_query(
         &self,
         req_path: &str,
@@ -121,10 +450,23 @@ impl PrefixesApi {
     ) -> Result<Response<Body>, String> {
         debug!("in handle_prefix_query");
 
-        let prefix = Prefix::from_str(
-            req_path.strip_prefix(self.http_api_path.as_str()).unwrap(),
-        ) // SAFETY: unwrap() safe due to starts_with() check above
-        .map_err(|err| err.to_string())?;
+        // The query is either a prefix, or an IP address for which the
+        // covering prefix is looked up.
+        // SAFETY: unwrap() safe due to starts_with() check above
+        let query =
+            req_path.strip_prefix(self.http_api_path.as_str()).unwrap();
+        let (prefix, is_address) = match Prefix::from_str(query) {
+            Ok(prefix) => (prefix, false),
+            Err(err) => match IpAddr::from_str(query) {
+                Ok(addr) => {
+                    let len = if addr.is_ipv4() { 32 } else { 128 };
+                    let prefix = Prefix::new(addr, len)
+                        .map_err(|err| err.to_string())?;
+                    (prefix, true)
+                }
+                Err(_) => return Err(err.to_string()),
+            },
+        };
 
         //
         // Handle query parameters
@@ -136,25 +478,15 @@ impl PrefixesApi {
             self.query_limits.clone(),
             prefix,
         )?;
-        let details = Self::parse_details_param(&params)?;
+        let mut details = Self::parse_details_param(&params)?;
         let filters = Self::parse_filter_params(&params)?;
         let sort = Self::parse_sort_params(&params)?;
+        let ingresses =
+            Self::parse_ingress_params(&params, &self.ingress_register)?;
+        let best_only = Self::parse_best_only_param(&params)?;
         let format = get_param(&params, "format");
 
-        //
-        // Check for unused params
-        //
-        let unused_params: Vec<&str> = params
-            .iter()
-            .filter(|param| !param.used())
-            .map(|param| param.name())
-            .collect();
-        if !unused_params.is_empty() {
-            return Err(format!(
-                "Unrecognized query parameters: {}",
-                unused_params.join(",")
-            ));
-        }
+        check_unused_params(&params)?;
 
         //
         // Query the prefix store
@@ -165,8 +497,16 @@ impl PrefixesApi {
         // commands. Once we've sent it the query command we then have to wait
         // to receive the query result back.
         //
+        // Addresses are looked up using a longest match, unless an exact
+        // match (i.e. on the host prefix) was explicitly requested.
+        let match_type = if is_address && !includes.exactly_matching {
+            match_options::MatchType::LongestMatch
+        } else {
+            match_options::MatchType::ExactMatch
+        };
+
         let options = MatchOptions {
-            match_type: match_options::MatchType::ExactMatch,
+            match_type,
             include_less_specifics: includes.less_specifics,
             include_more_specifics: includes.more_specifics,
             include_withdrawn: true,
@@ -175,7 +515,7 @@ impl PrefixesApi {
         };
 
         // XXX res: QueryResult will be different
-        let res = match self.rib_type {
+        let mut res = match self.rib_type {
             RibType::Physical => {
                 // XXX res: QueryResult will be different
                 match self.rib.load().match_prefix(&prefix, &options) {
@@ -272,6 +612,49 @@ impl PrefixesApi {
             }
         };
 
+        // For an address, the less specifics of the host prefix include the
+        // covering prefix we matched on, which is already in the data.
+        if is_address {
+            if let (Some(matched), Some(less_specifics)) =
+                (res.prefix, res.less_specifics.as_mut())
+            {
+                less_specifics.v4.retain(|rec| rec.prefix != matched);
+                less_specifics.v6.retain(|rec| rec.prefix != matched);
+            }
+        }
+
+        // Best path selection considers all paths, so do it before
+        // restricting the result to specific ingresses.
+        if self.rib.load().best_path_enabled() {
+            let best_paths = best_paths(&res, &self.ingress_register);
+            if best_only {
+                retain_records(&mut res, |prefix, rec| {
+                    best_paths.contains(&(*prefix, rec.multi_uniq_id))
+                });
+            }
+            details.best_paths = Some(best_paths);
+        } else if best_only {
+            return Err(
+                "Best path selection is not enabled for this RIB".to_string()
+            );
+        }
+
+        if let Some(ingresses) = ingresses {
+            retain_records(&mut res, |_, rec| {
+                ingresses.contains(&rec.multi_uniq_id)
+            });
+        }
+
+        // The tags are kept by the physical RIB holding the routes.
+        if self.rib_type == RibType::Physical {
+            details.tags = Some(self.rib.load().tags().clone());
+        }
+
+        // The traffic of the prefix that was matched, if known.
+        let traffic = self.traffic.load().as_ref().map(|traffic| {
+            traffic.prefix(res.prefix.as_ref().unwrap_or(&prefix))
+        });
+
         //
         // Format the response
         //
@@ -284,6 +667,7 @@ impl PrefixesApi {
                     details,
                     filters,
                     sort,
+                    traffic,
                     &self.ingress_register,
                 )
             }
@@ -309,6 +693,538 @@ impl PrefixesApi {
         Ok(res)
     }
 
+    async fn handle_search_query(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_search_query");
+
+        let params = extract_params(request);
+
+        let mut search = RouteSearch::default();
+        if let Some(pattern) = get_param(&params, "as_path_regex") {
+            search.as_path_regex =
+                Some(compile_as_path_regex(pattern.value())?);
+        }
+        for community in get_all_params(&params, "community") {
+            search
+                .communities
+                .push(CommunityPattern::from_str(community.value())?);
+        }
+        search.ingresses =
+            Self::parse_ingress_params(&params, &self.ingress_register)?;
+        search.tags = Self::parse_tag_params(&params)?;
+        if search.is_empty() {
+            return Err(
+                "Missing query parameter 'as_path_regex', 'community', \
+                'ingress_id', 'router', 'peer' or 'tag'"
+                    .to_string(),
+            );
+        }
+        let max_results = self.query_limits.load().max_search_results;
+        let limit = match get_param(&params, "limit") {
+            Some(limit) => {
+                let limit = limit.value().parse::<usize>().map_err(|err| {
+                    format!(
+                        "Invalid value '{}' for query parameter 'limit': {}",
+                        limit.value(),
+                        err
+                    )
+                })?;
+                limit.min(max_results)
+            }
+            None => max_results,
+        };
+        let mut details = Self::parse_details_param(&params)?;
+        let filters = Self::parse_filter_params(&params)?;
+        let sort = Self::parse_sort_params(&params)?;
+
+        check_unused_params(&params)?;
+
+        if self.rib_type != RibType::Physical {
+            return Err("unsupported on virtual rib".to_string());
+        }
+
+        // Searching scans the whole RIB, keep it off the runtime.
+        let rib = self.rib.load_full();
+        let search_rib = rib.clone();
+        let (records, truncated) = tokio::task::spawn_blocking(move || {
+            search_rib.search(&search, limit)
+        })
+        .await
+        .map_err(|err| err.to_string())??;
+        details.tags = Some(rib.tags().clone());
+
+        Ok(Self::mk_search_response(
+            records,
+            truncated,
+            details,
+            filters,
+            sort,
+            &self.ingress_register,
+        ))
+    }
+
+    /// List a page of the routes in the RIB, optionally filtered, in a
+    /// stable order, continuing after the route given by `cursor`.
+    async fn handle_routes_query(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_routes_query");
+
+        let params = extract_params(request);
+
+        let mut search = RouteSearch::default();
+        if let Some(pattern) = get_param(&params, "as_path_regex") {
+            search.as_path_regex =
+                Some(compile_as_path_regex(pattern.value())?);
+        }
+        for community in get_all_params(&params, "community") {
+            search
+                .communities
+                .push(CommunityPattern::from_str(community.value())?);
+        }
+        for origin in get_all_params(&params, "origin_as") {
+            search
+                .origin_asns
+                .push(Asn::from_str(origin.value()).map_err(|err| {
+                    format!(
+                        "Invalid value '{}' for query parameter \
+                        'origin_as': {}",
+                        origin.value(),
+                        err
+                    )
+                })?);
+        }
+        search.rov_statuses = Self::parse_rpki_params(&params)?;
+        search.ingresses =
+            Self::parse_ingress_params(&params, &self.ingress_register)?;
+        search.tags = Self::parse_tag_params(&params)?;
+
+        let max_results = self.query_limits.load().max_search_results;
+        let mut paging = Paging {
+            limit: max_results,
+            ..Default::default()
+        };
+        if let Some(limit) = get_param(&params, "limit") {
+            let limit = limit.value().parse::<usize>().map_err(|err| {
+                format!(
+                    "Invalid value '{}' for query parameter 'limit': {}",
+                    limit.value(),
+                    err
+                )
+            })?;
+            paging.limit = limit.min(max_results);
+        }
+        if let Some(sort) = get_param(&params, "sort") {
+            paging.set_sort(sort.value())?;
+        }
+        if let Some(cursor) = get_param(&params, "cursor") {
+            paging.after = Some(RoutePosition::from_str(cursor.value())?);
+        }
+
+        check_unused_params(&params)?;
+
+        if self.rib_type != RibType::Physical {
+            return Err("unsupported on virtual rib".to_string());
+        }
+
+        // Listing a page scans the whole RIB, keep it off the runtime.
+        let rib = self.rib.load_full();
+        let page_rib = rib.clone();
+        let (routes, more) = tokio::task::spawn_blocking(move || {
+            page_rib.page(&search, &paging)
+        })
+        .await
+        .map_err(|err| err.to_string())??;
+        let next_cursor = if more {
+            routes.last().map(|(position, _)| position.to_string())
+        } else {
+            None
+        };
+        Ok(Self::mk_routes_response(routes, next_cursor, rib.tags()))
+    }
+
+    async fn handle_ingresses_query(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_ingresses_query");
+
+        let params = extract_params(request);
+        let ingresses =
+            Self::parse_ingress_params(&params, &self.ingress_register)?;
+
+        check_unused_params(&params)?;
+
+        if self.rib_type != RibType::Physical {
+            return Err("unsupported on virtual rib".to_string());
+        }
+
+        // Counting the routes scans the whole RIB, keep it off the runtime.
+        let rib = self.rib.load_full();
+        let route_counts =
+            tokio::task::spawn_blocking(move || rib.route_counts())
+                .await
+                .map_err(|err| err.to_string())??;
+        let mut route_counts = route_counts
+            .into_iter()
+            .filter(|(id, _)| {
+                ingresses.as_ref().is_none_or(|ids| ids.contains(id))
+            })
+            .collect::<Vec<_>>();
+        route_counts.sort_unstable();
+
+        Ok(Self::mk_ingresses_response(
+            route_counts,
+            &self.ingress_register,
+        ))
+    }
+
+    async fn handle_snapshots_query(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_snapshots_query");
+
+        let params = extract_params(request);
+        check_unused_params(&params)?;
+
+        let location = self.snapshot_location()?;
+        let snapshots = location.list().map_err(|err| err.to_string())?;
+        Ok(Self::mk_snapshots_response(snapshots))
+    }
+
+    /// Compare two versions of the RIB, each either a snapshot or the live
+    /// RIB, streaming the differences.
+    async fn handle_diff_query(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_diff_query");
+
+        let params = extract_params(request);
+        let from = get_param(&params, "from")
+            .ok_or("Missing query parameter 'from'")?
+            .value()
+            .to_string();
+        let to = get_param(&params, "to")
+            .map(|to| to.value().to_string())
+            .unwrap_or_else(|| "live".to_string());
+
+        check_unused_params(&params)?;
+
+        if self.rib_type != RibType::Physical {
+            return Err("unsupported on virtual rib".to_string());
+        }
+
+        let from = self.rib_contents(&from).await?;
+        let to = self.rib_contents(&to).await?;
+        Ok(Self::mk_diff_response(from, to))
+    }
+
+    /// Stream all routes in the RIB, optionally only those of one address
+    /// family, and only the requested fields of each route.
+    async fn handle_dump_query(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_dump_query");
+
+        let params = extract_params(request);
+        let mut dump = Dump::default();
+        if let Some(fields) = get_param(&params, "fields") {
+            dump.fields = fields
+                .value()
+                .split(',')
+                .map(|name| {
+                    DumpField::from_name(name).ok_or_else(|| {
+                        format!(
+                            "Unrecognized field '{name}', expected one of: {}",
+                            DumpField::ALL.map(DumpField::name).join(",")
+                        )
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(afi) = get_param(&params, "afi") {
+            dump.afi = match afi.value() {
+                "ipv4" => Some(Afi::Ipv4),
+                "ipv6" => Some(Afi::Ipv6),
+                other => {
+                    return Err(format!(
+                        "Unrecognized afi '{other}', expected ipv4 or ipv6"
+                    ))
+                }
+            };
+        }
+        dump.tags = Self::parse_tag_params(&params)?;
+
+        check_unused_params(&params)?;
+
+        if self.rib_type != RibType::Physical {
+            return Err("unsupported on virtual rib".to_string());
+        }
+
+        Ok(Self::mk_export_response(
+            self.rib.load_full(),
+            self.ingress_register.clone(),
+            dump,
+        ))
+    }
+
+    /// Compute statistics about the contents of the RIB, reporting the `top`
+    /// origin ASNs with the most prefixes.
+    async fn handle_stats_query(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_stats_query");
+
+        let params = extract_params(request);
+        let top = match get_param(&params, "top") {
+            Some(top) => top.value().parse::<usize>().map_err(|err| {
+                format!(
+                    "Invalid value '{}' for query parameter 'top': {}",
+                    top.value(),
+                    err
+                )
+            })?,
+            None => StatsConfig::default_top_origins(),
+        };
+
+        check_unused_params(&params)?;
+
+        if self.rib_type != RibType::Physical {
+            return Err("unsupported on virtual rib".to_string());
+        }
+
+        let rib = self.rib.load_full();
+        let ingresses = self.ingress_register.clone();
+        let stats = tokio::task::spawn_blocking(move || {
+            RibStats::compute(&rib, &ingresses, top)
+        })
+        .await
+        .map_err(|err| err.to_string())??;
+
+        Ok(Self::mk_stats_response(stats))
+    }
+
+    /// Report the outcome of the last consistency check.
+    async fn handle_consistency_query(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_consistency_query");
+
+        let params = extract_params(request);
+        check_unused_params(&params)?;
+
+        if self.rib_type != RibType::Physical {
+            return Err("unsupported on virtual rib".to_string());
+        }
+
+        let checker = self.consistency.load_full().ok_or_else(|| {
+            "consistency checking is not enabled for this rib".to_string()
+        })?;
+        Ok(Self::mk_consistency_response(checker.last_report()))
+    }
+
+    /// Report the current disk usage and the outcome of the last
+    /// compaction.
+    async fn handle_compaction_query(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_compaction_query");
+
+        let compactor = self.compactor_for(request)?;
+        let report = compactor.last_report();
+        let usage = tokio::task::spawn_blocking(move || compactor.usage())
+            .await
+            .map_err(|err| err.to_string())?
+            .map_err(|err| err.to_string())?;
+        Ok(Self::mk_compaction_response(usage, report))
+    }
+
+    /// Compact the on-disk state now, reporting the disk usage before and
+    /// after.
+    async fn handle_compaction_request(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_compaction_request");
+
+        let compactor = self.compactor_for(request)?;
+        let report = tokio::task::spawn_blocking(move || {
+            compactor.compact(CompactionTrigger::Manual)
+        })
+        .await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| format!("compaction failed: {err}"))?;
+        Ok(Self::mk_compaction_report_response(report))
+    }
+
+    /// Trace the roto filter for the route given by the `route` parameter
+    /// or the sample given by `sample`, or list the samples without either.
+    async fn handle_filter_trace_query(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_filter_trace_query");
+
+        let params = extract_params(request);
+        let route = get_param(&params, "route")
+            .map(|route| {
+                serde_json::from_str::<RouteSet>(route.value())
+                    .map_err(|err| format!("invalid route: {err}"))
+            })
+            .transpose()?;
+        let sample = get_param(&params, "sample")
+            .map(|sample| {
+                sample.value().parse::<u64>().map_err(|_| {
+                    format!("'{}' is not a sample id", sample.value())
+                })
+            })
+            .transpose()?;
+
+        check_unused_params(&params)?;
+
+        let tracer = self.filter_tracer.load_full().ok_or_else(|| {
+            "filter tracing is not enabled for this rib".to_string()
+        })?;
+        match (route, sample) {
+            (Some(_), Some(_)) => {
+                Err("only one of route and sample can be given".into())
+            }
+            (Some(route), None) => {
+                let traces = route
+                    .routes()?
+                    .into_iter()
+                    .map(|route| tracer.trace(route, None))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Self::mk_filter_trace_response(traces))
+            }
+            (None, Some(id)) => Ok(Self::mk_filter_trace_response(vec![
+                tracer.trace_sample(id)?,
+            ])),
+            (None, None) => {
+                Ok(Self::mk_filter_samples_response(tracer.samples()))
+            }
+        }
+    }
+
+    async fn handle_graphql_query(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_graphql_query");
+
+        let params = extract_params(request);
+        let query = get_param(&params, "query")
+            .ok_or_else(|| "missing query parameter 'query'".to_string())?;
+        let variables = get_param(&params, "variables");
+        let operation = get_param(&params, "operationName");
+
+        check_unused_params(&params)?;
+
+        let api = self.graphql.load_full().ok_or_else(|| {
+            "GraphQL is not enabled for this rib".to_string()
+        })?;
+        // Resolving routes searches the RIB, keep it off the runtime.
+        let query = query.value().to_string();
+        let variables =
+            variables.map(|variables| variables.value().to_string());
+        let operation =
+            operation.map(|operation| operation.value().to_string());
+        let response = tokio::task::spawn_blocking(move || {
+            api.execute(&query, variables.as_deref(), operation.as_deref())
+        })
+        .await
+        .map_err(|err| err.to_string())?;
+        Ok(Self::mk_graphql_response(response))
+    }
+
+    /// The compactor of this RIB, checking the request has no parameters.
+    fn compactor_for(
+        &self,
+        request: &Request<Body>,
+    ) -> Result<Arc<Compactor>, String> {
+        let params = extract_params(request);
+        check_unused_params(&params)?;
+
+        if self.rib_type != RibType::Physical {
+            return Err("unsupported on virtual rib".to_string());
+        }
+
+        self.compactor.load_full().ok_or_else(|| {
+            "compaction requires disk storage with snapshots for this rib"
+                .to_string()
+        })
+    }
+
+    /// List the retained versions of the routes for a prefix, or with `at`
+    /// given, the versions that were current at that time.
+    async fn handle_history_query(
+        &self,
+        prefix: &str,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>, String> {
+        debug!("in handle_history_query");
+
+        let prefix = Prefix::from_str(prefix).map_err(|err| err.to_string())?;
+        let params = extract_params(request);
+        let at = get_param(&params, "at")
+            .map(|at| {
+                DateTime::parse_from_rfc3339(at.value())
+                    .map(|at| at.with_timezone(&Utc))
+                    .map_err(|_| {
+                        format!("'{}' is not an RFC 3339 timestamp", at.value())
+                    })
+            })
+            .transpose()?;
+
+        check_unused_params(&params)?;
+
+        let history = self.history.load_full().ok_or_else(|| {
+            "Route history is not enabled for this RIB".to_string()
+        })?;
+        let versions = match at {
+            Some(at) => history.versions_at(&prefix, at),
+            None => history.versions(&prefix),
+        };
+        Ok(Self::mk_history_response(versions, &self.ingress_register))
+    }
+
+    fn snapshot_location(&self) -> Result<Arc<SnapshotLocation>, String> {
+        self.snapshots
+            .load_full()
+            .ok_or_else(|| "Snapshots are not enabled for this RIB".to_string())
+    }
+
+    /// Read the contents of the snapshot identified by `spec`, or of the
+    /// live RIB if `spec` is "live".
+    async fn rib_contents(&self, spec: &str) -> Result<RibContents, String> {
+        let res = if spec == "live" {
+            let rib = self.rib.load_full();
+            let ingresses = self.ingress_register.clone();
+            tokio::task::spawn_blocking(move || {
+                RibContents::from_rib(&rib, &ingresses)
+            })
+            .await
+        } else {
+            let snapshot = self.snapshot_location()?.find(spec)?;
+            tokio::task::spawn_blocking(move || {
+                RibContents::from_snapshot(&snapshot.path).map_err(|err| {
+                    format!("Cannot read snapshot {}: {err}", snapshot.name)
+                })
+            })
+            .await
+        };
+        res.map_err(|err| err.to_string())?
+    }
+
     async fn handle_ingress_id_query(
         &self,
         req_path: &str,
@@ -358,6 +1274,9 @@ impl PrefixesApi {
         if let Some(requested_includes) = get_param(params, "include") {
             for include in requested_includes.value().split(',') {
                 match include {
+                    // Prefix queries always match exactly, this only
+                    // affects address (covering prefix) queries.
+                    "exactlyMatching" => includes.exactly_matching = true,
                     "lessSpecifics" => includes.less_specifics = true,
                     "moreSpecifics" => includes.more_specifics = true,
                     _ => {
@@ -401,6 +1320,14 @@ impl PrefixesApi {
         Ok(includes)
     }
 
+    fn describe_details_param(op: &mut Operation) {
+        op.query(
+            "details",
+            Schema::String,
+            "communities to include the communities in the routes",
+        );
+    }
+
     fn parse_details_param(params: &QueryParams) -> Result<Details, String> {
         let mut details = Details::default();
 
@@ -449,6 +1376,170 @@ impl PrefixesApi {
         Ok(Filters::new(op, filters))
     }
 
+    fn describe_filter_params(op: &mut Operation) {
+        for mode in ["select", "discard"] {
+            op.repeated_query(
+                mode,
+                Schema::String,
+                &format!(
+                    "Given as {mode}[<kind>]=<value>, {mode} the routes \
+                    matching a value of kind as_path, peer_as, community \
+                    or tag"
+                ),
+            );
+        }
+        op.query(
+            "filter_op",
+            Schema::String,
+            "any or all, whether routes must match any or all filters",
+        );
+    }
+
+    /// Parse the `rpki` query parameters into the ROV statuses to match.
+    fn parse_rpki_params(
+        params: &QueryParams,
+    ) -> Result<Vec<RovStatus>, String> {
+        get_all_params(params, "rpki")
+            .into_iter()
+            .map(|status| RovStatus::from_str(status.value()))
+            .collect()
+    }
+
+    fn describe_tag_params(op: &mut Operation) {
+        op.repeated_query(
+            "tag",
+            Schema::String,
+            "A tag the routes must have, as key or key=value",
+        );
+    }
+
+    /// Parse the `tag` query parameters, each a tag the routes must have.
+    fn parse_tag_params(params: &QueryParams) -> Result<Vec<TagFilter>, String> {
+        get_all_params(params, "tag")
+            .into_iter()
+            .map(|tag| TagFilter::from_str(tag.value()))
+            .collect()
+    }
+
+    /// Resolve the `ingress_id`, `router` and `peer` query parameters to
+    /// the set of ingresses to restrict the results to, mirroring the
+    /// Adj-RIB-In of a peer or of all peers monitored via a BMP router.
+    ///
+    /// An `ingress_id` includes all ingresses descending from it. Multiple
+    /// parameters narrow down the set further.
+    fn describe_ingress_params(op: &mut Operation) {
+        op.query(
+            "ingress_id",
+            Schema::Integer,
+            "Only routes learned from this ingress or its descendants",
+        )
+        .query(
+            "router",
+            Schema::String,
+            "Only routes learned from the BMP router with this address",
+        )
+        .query(
+            "peer",
+            Schema::String,
+            "Only routes learned from the peer with this address",
+        );
+    }
+
+    fn parse_ingress_params(
+        params: &QueryParams,
+        register: &ingress::Register,
+    ) -> Result<Option<HashSet<ingress::IngressId>>, String> {
+        let mut res: Option<HashSet<ingress::IngressId>> = None;
+        let mut narrow = |ids: HashSet<ingress::IngressId>| {
+            res = Some(match res.take() {
+                None => ids,
+                Some(res) => res.intersection(&ids).copied().collect(),
+            });
+        };
+
+        if let Some(param) = get_param(params, "ingress_id") {
+            let id = param.value().parse::<ingress::IngressId>().map_err(
+                |err| {
+                    format!(
+                        "Invalid value '{}' for query parameter 'ingress_id': {}",
+                        param.value(),
+                        err
+                    )
+                },
+            )?;
+            let mut ids: HashSet<_> =
+                register.descendants(id).into_iter().collect();
+            ids.insert(id);
+            narrow(ids);
+        }
+
+        if let Some(param) = get_param(params, "router") {
+            let addr = Self::parse_addr_param(&param, "router")?;
+            // BMP routers have a parent (the BMP connector unit) and a
+            // remote address, but no ASN, unlike the peers below them.
+            let routers = register.find_all(|info| {
+                info.parent_ingress.is_some()
+                    && info.remote_addr == Some(addr)
+                    && info.remote_asn.is_none()
+            });
+            narrow(
+                routers
+                    .into_iter()
+                    .flat_map(|router| register.descendants(router))
+                    .collect(),
+            );
+        }
+
+        if let Some(param) = get_param(params, "peer") {
+            let addr = Self::parse_addr_param(&param, "peer")?;
+            narrow(
+                register
+                    .find_all(|info| {
+                        info.remote_addr == Some(addr)
+                            && info.remote_asn.is_some()
+                    })
+                    .into_iter()
+                    .collect(),
+            );
+        }
+
+        Ok(res)
+    }
+
+    fn parse_addr_param(
+        param: &MatchedParam,
+        name: &str,
+    ) -> Result<IpAddr, String> {
+        IpAddr::from_str(param.value()).map_err(|err| {
+            format!(
+                "Invalid value '{}' for query parameter '{}': {}",
+                param.value(),
+                name,
+                err
+            )
+        })
+    }
+
+    fn parse_best_only_param(params: &QueryParams) -> Result<bool, String> {
+        match get_param(params, "best_only").as_ref().map(MatchedParam::value)
+        {
+            Some("true") => Ok(true),
+            Some("false") | None => Ok(false),
+            Some(other) => Err(format!(
+                "Invalid value '{}' for query parameter 'best_only'",
+                other
+            )),
+        }
+    }
+
+    fn describe_sort_params(op: &mut Operation) {
+        op.query(
+            "sort",
+            Schema::String,
+            "A JSON pointer into the routes to sort them by",
+        );
+    }
+
     fn parse_sort_params(params: &QueryParams) -> Result<SortKey, String> {
         match get_param(params, "sort").as_ref().map(MatchedParam::value) {
             Some(json_pointer) => Ok(SortKey::Some(json_pointer.into())),
@@ -457,6 +1548,57 @@ impl PrefixesApi {
     }
 }
 
+/// Keep only the records in `res` for which `keep` holds, dropping prefixes
+/// that are left without records.
+fn retain_records(
+    res: &mut QueryResult<RotondaPaMap>,
+    keep: impl Fn(&Prefix, &Record<RotondaPaMap>) -> bool,
+) {
+    if let Some(prefix) = res.prefix {
+        res.records.retain(|rec| keep(&prefix, rec));
+    }
+    for record_set in
+        [res.less_specifics.as_mut(), res.more_specifics.as_mut()]
+            .into_iter()
+            .flatten()
+    {
+        for prefix_records in [&mut record_set.v4, &mut record_set.v6] {
+            for prefix_record in prefix_records.iter_mut() {
+                let prefix = prefix_record.prefix;
+                prefix_record.meta.retain(|rec| keep(&prefix, rec));
+            }
+            prefix_records
+                .retain(|prefix_record| !prefix_record.meta.is_empty());
+        }
+    }
+}
+
+/// Select the best path for every prefix in `res`.
+fn best_paths(
+    res: &QueryResult<RotondaPaMap>,
+    ingresses: &ingress::Register,
+) -> HashSet<(Prefix, ingress::IngressId)> {
+    let mut best_paths = HashSet::new();
+    if let Some(prefix) = res.prefix {
+        if let Some(id) = best_path::best_ingress(&res.records, ingresses) {
+            best_paths.insert((prefix, id));
+        }
+    }
+    for record_set in [res.less_specifics.as_ref(), res.more_specifics.as_ref()]
+        .into_iter()
+        .flatten()
+    {
+        for prefix_record in record_set.v4.iter().chain(&record_set.v6) {
+            if let Some(id) =
+                best_path::best_ingress(&prefix_record.meta, ingresses)
+            {
+                best_paths.insert((prefix_record.prefix, id));
+            }
+        }
+    }
+    best_paths
+}
+
 fn extract_filter_kind(filter: MatchedParam) -> Result<FilterKind, String> {
     let extracted_filter = match filter {
         MatchedParam::Family("as_path", v) => {
@@ -481,6 +1623,10 @@ fn extract_filter_kind(filter: MatchedParam) -> Result<FilterKind, String> {
             )),
         },
 
+        MatchedParam::Family("tag", v) => {
+            TagFilter::from_str(v).map(FilterKind::Tag)
+        }
+
         MatchedParam::Family("community", v) => {
             match Community::from_str(v) {
                 Ok(community) => Ok(FilterKind::Community(community)),
@@ -1,4 +1,4 @@
-use std::{cmp::Ordering, sync::Arc};
+use std::{cmp::Ordering, convert::Infallible, sync::Arc};
 
 use hyper::{Body, Response};
 
@@ -11,7 +11,7 @@ use inetnum::{addr::Prefix, asn::Asn};
 
 use rotonda_store::{
     match_options::QueryResult,
-    prefix_record::{Record, RouteStatus},
+    prefix_record::{PrefixRecord, Record, RouteStatus},
 };
 use routecore::bgp::{
     aspath::{AsPath, Hop, HopPath},
@@ -24,20 +24,41 @@ use routecore::bgp::{
 };
 
 use serde_json::{json, Value};
+use tokio::sync::mpsc;
 
 use crate::{
     common::json::EasilyExtendedJSONObject,
     ingress::{self, IngressId, IngressInfo},
     payload::{RotondaPaMap, RotondaRoute},
+    roto_runtime::types::Tags,
+    units::{
+        flow_in::counters::Counter,
+        rib_unit::{
+            compaction::{CompactionReport, DiskUsage},
+            consistency::ConsistencyReport,
+            diff::{self, RibContents},
+            filter_trace::{FilterTrace, SampleSummary},
+            history::Version,
+            index,
+            rib::{Rib, RoutePosition},
+            snapshot::SnapshotFile,
+            stats::RibStats,
+            tags::RouteTags,
+        },
+    },
 };
 
 use super::{
     types::{
-        Details, Filter, FilterKind, FilterOp, Filters, Includes, SortKey,
+        Details, Dump, DumpField, Filter, FilterKind, FilterOp, Filters,
+        Includes, SortKey,
     },
     PrefixesApi,
 };
 
+/// The number of prefixes whose routes a dump reads at a time.
+const DUMP_CHUNK_LEN: usize = 1000;
+
 impl PrefixesApi {
     pub fn mk_json_response(
         //res: rotonda_store::QueryResult<RotondaRoute>,
@@ -46,6 +67,7 @@ impl PrefixesApi {
         details_cfg: Details,
         filters_cfg: Filters,
         sort_cfg: SortKey,
+        traffic: Option<Counter>,
         ingress_register: &Arc<ingress::Register>,
     ) -> Response<Body> {
         let mut out_prefixes = Vec::new();
@@ -112,10 +134,19 @@ impl PrefixesApi {
             out_included.insert("moreSpecifics", json!(out_more_specifics));
         }
 
-        let response = json!({
+        let mut response = json!({
             "data": out_prefixes,
             "included": out_included,
         });
+        if let Some(traffic) = traffic {
+            response.insert(
+                "traffic",
+                json!({
+                    "bytes": traffic.bytes,
+                    "packets": traffic.packets,
+                }),
+            );
+        }
 
         Response::builder()
             .header("Content-Type", "application/json")
@@ -125,6 +156,420 @@ impl PrefixesApi {
             .unwrap()
     }
 
+    /// Build the response to a search query, e.g. an AS path regex query,
+    /// which does not revolve around a single prefix.
+    pub fn mk_search_response(
+        records: Vec<PrefixRecord<RotondaPaMap>>,
+        truncated: bool,
+        details_cfg: Details,
+        filters_cfg: Filters,
+        sort_cfg: SortKey,
+        ingress_register: &Arc<ingress::Register>,
+    ) -> Response<Body> {
+        let mut out_prefixes = Vec::new();
+
+        for record in records {
+            for pub_record in record.meta {
+                Self::prefixes_as_json(
+                    &record.prefix,
+                    &pub_record,
+                    &details_cfg,
+                    &filters_cfg,
+                    &SortKey::None,
+                    &mut out_prefixes,
+                    ingress_register,
+                );
+            }
+        }
+
+        Self::sort_results(&sort_cfg, &mut out_prefixes);
+
+        let response = json!({
+            "data": out_prefixes,
+            "truncated": truncated,
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::to_string_pretty(&response).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    /// Build the response for a page of the route listing.
+    ///
+    /// Every route has the same fields, with `null` for an origin AS or AS
+    /// path the route has none of, so that the schema stays the same for
+    /// all routes.
+    pub fn mk_routes_response(
+        routes: Vec<(RoutePosition, Record<RotondaPaMap>)>,
+        next_cursor: Option<String>,
+        tags: &RouteTags,
+    ) -> Response<Body> {
+        let out_routes = routes
+            .into_iter()
+            .map(|(position, record)| {
+                json!({
+                    "prefix": position.prefix,
+                    "ingress_id": position.ingress_id,
+                    "multicast": position.multicast,
+                    "status": record.status.to_string(),
+                    "origin_as": index::origin_asn(&record.meta)
+                        .map(|asn| asn.into_u32()),
+                    "as_path": index::as_path_string(&record.meta),
+                    "communities": index::communities(&record.meta)
+                        .iter()
+                        .map(|community| community.to_string())
+                        .collect::<Vec<_>>(),
+                    "rpki": record.meta.rpki_info(),
+                    "tags": tags
+                        .get(position.prefix, position.ingress_id)
+                        .unwrap_or_default(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let response = json!({
+            "data": out_routes,
+            "next_cursor": next_cursor,
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::to_string_pretty(&response).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    /// Build the response listing the ingresses that routes in the RIB
+    /// were learned from, together with their number of routes.
+    pub fn mk_ingresses_response(
+        route_counts: Vec<(IngressId, usize)>,
+        ingress_register: &Arc<ingress::Register>,
+    ) -> Response<Body> {
+        let out_ingresses = route_counts
+            .into_iter()
+            .map(|(ingress_id, routes)| {
+                json!({
+                    "ingress_id": ingress_id,
+                    "ingress_info": ingress_register.get(ingress_id),
+                    "routes": routes,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let response = json!({
+            "data": out_ingresses,
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::to_string_pretty(&response).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    /// Build the response listing the snapshots available for comparison.
+    pub fn mk_snapshots_response(
+        snapshots: Vec<SnapshotFile>,
+    ) -> Response<Body> {
+        let out_snapshots = snapshots
+            .into_iter()
+            .map(|snapshot| {
+                json!({
+                    "name": snapshot.name,
+                    "taken": snapshot.taken,
+                    "size": snapshot.size,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let response = json!({
+            "data": out_snapshots,
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::to_string_pretty(&response).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    pub fn mk_stats_response(stats: RibStats) -> Response<Body> {
+        let response = json!({
+            "data": stats,
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::to_string_pretty(&response).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    /// Build the response with the report of the last consistency check,
+    /// which is null if no check has completed yet.
+    pub fn mk_consistency_response(
+        report: Option<Arc<ConsistencyReport>>,
+    ) -> Response<Body> {
+        let response = json!({
+            "data": report,
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::to_string_pretty(&response).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    /// Build the response with the current disk usage and the report of
+    /// the last compaction, which is null if none has completed yet.
+    pub fn mk_compaction_response(
+        usage: DiskUsage,
+        last: Option<Arc<CompactionReport>>,
+    ) -> Response<Body> {
+        let response = json!({
+            "data": {
+                "usage": usage,
+                "last": last,
+            },
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::to_string_pretty(&response).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    pub fn mk_compaction_report_response(
+        report: Arc<CompactionReport>,
+    ) -> Response<Body> {
+        let response = json!({
+            "data": report,
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::to_string_pretty(&response).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    /// Build the response listing versions of routes, oldest first.
+    pub fn mk_history_response(
+        versions: Vec<Version>,
+        ingress_register: &Arc<ingress::Register>,
+    ) -> Response<Body> {
+        let out_versions = versions
+            .into_iter()
+            .map(|version| {
+                let mut res = json!({
+                    "time": version.time,
+                    "ingress_id": version.ingress_id,
+                    "ingress_info": ingress_register.get(version.ingress_id),
+                    "prefix": version.prefix,
+                    "status": version.status.to_string(),
+                    "attributes": version.attributes,
+                });
+                if version.multicast {
+                    res.insert("multicast", json!(true));
+                }
+                res
+            })
+            .collect::<Vec<_>>();
+
+        let response = json!({
+            "data": out_versions,
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::to_string_pretty(&response).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    /// Build the response with the traces of the roto filter, one per
+    /// route.
+    pub fn mk_filter_trace_response(
+        traces: Vec<FilterTrace>,
+    ) -> Response<Body> {
+        let response = json!({
+            "data": traces,
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::to_string_pretty(&response).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    /// Build the response listing the routes sampled for tracing, oldest
+    /// first.
+    pub fn mk_filter_samples_response(
+        samples: Vec<SampleSummary>,
+    ) -> Response<Body> {
+        let response = json!({
+            "data": samples,
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::to_string_pretty(&response).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    /// Build the response to a GraphQL query, which already holds either
+    /// the data or the errors.
+    pub fn mk_graphql_response(response: Value) -> Response<Body> {
+        Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::to_string_pretty(&response).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    /// Build the response streaming the differences between `from` and
+    /// `to`, as newline delimited JSON with one object per prefix.
+    ///
+    /// The differences are computed on a blocking thread while the response
+    /// is being sent.
+    pub fn mk_diff_response(
+        from: RibContents,
+        to: RibContents,
+    ) -> Response<Body> {
+        let (tx, rx) = mpsc::channel::<Result<String, Infallible>>(16);
+        tokio::task::spawn_blocking(move || {
+            for prefix_diff in diff::diff(&from, &to) {
+                let mut line = serde_json::to_string(&prefix_diff).unwrap();
+                line.push('\n');
+                if tx.blocking_send(Ok(line)).is_err() {
+                    // The client went away.
+                    break;
+                }
+            }
+        });
+        let body = futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|line| (line, rx))
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/x-ndjson")
+            .body(Body::wrap_stream(body))
+            .unwrap()
+    }
+
+    /// Build the response streaming every route in `rib`, one JSON object
+    /// per line.
+    ///
+    /// The routes are read on a blocking thread that waits whenever the
+    /// client falls behind, so the RIB is never buffered as a whole. Only
+    /// the prefixes are listed up front. Their routes are read in chunks
+    /// of [`DUMP_CHUNK_LEN`] prefixes, each under an epoch guard of its own
+    /// that is released before the chunk is sent, so that a slow client
+    /// does not keep the store from reclaiming memory. Routes for prefixes
+    /// added while dumping are thus left out. Should reading the RIB fail,
+    /// the response is cut off, rather than ending as if the dump were
+    /// complete.
+    pub fn mk_export_response(
+        rib: Arc<Rib>,
+        ingress_register: Arc<ingress::Register>,
+        dump: Dump,
+    ) -> Response<Body> {
+        let (tx, rx) = mpsc::channel::<std::io::Result<String>>(16);
+        tokio::task::spawn_blocking(move || {
+            let fail = |err: String| {
+                error!("Failed to read RIB for dump: {err}");
+                let _ = tx.blocking_send(Err(std::io::Error::other(err)));
+            };
+            let mut prefixes = match rib.prefixes() {
+                Ok(prefixes) => prefixes,
+                Err(err) => return fail(err),
+            };
+            prefixes.retain(|(_, prefix)| dump.includes(prefix));
+            for chunk in prefixes.chunks(DUMP_CHUNK_LEN) {
+                let prefix_records = match rib.exact_prefix_records(chunk) {
+                    Ok(prefix_records) => prefix_records,
+                    Err(err) => return fail(err),
+                };
+                for (multicast, prefix_record) in prefix_records {
+                    for record in &prefix_record.meta {
+                        let mut line = Self::mk_dump_line(
+                            &prefix_record.prefix,
+                            multicast,
+                            record,
+                            &dump.fields,
+                            &ingress_register,
+                        )
+                        .to_string();
+                        line.push('\n');
+                        if tx.blocking_send(Ok(line)).is_err() {
+                            // The client went away.
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+        let body = futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|line| (line, rx))
+        });
+
+        Response::builder()
+            .header("Content-Type", "application/x-ndjson")
+            .body(Body::wrap_stream(body))
+            .unwrap()
+    }
+
+    fn mk_dump_line(
+        prefix: &Prefix,
+        multicast: bool,
+        record: &Record<RotondaPaMap>,
+        tags: Option<&Tags>,
+        fields: &[DumpField],
+        ingress_register: &ingress::Register,
+    ) -> Value {
+        let mut res = serde_json::Map::new();
+        for field in fields {
+            let value = match field {
+                // Routes without tags leave them out.
+                DumpField::Tags => match tags {
+                    Some(tags) => json!(tags),
+                    None => continue,
+                },
+                DumpField::Prefix => json!(prefix),
+                DumpField::IngressId => json!(record.multi_uniq_id),
+                DumpField::IngressInfo => {
+                    json!(ingress_register.get(record.multi_uniq_id))
+                }
+                DumpField::Rpki => json!(record.meta.rpki_info()),
+                DumpField::Status => json!(record.status.to_string()),
+                DumpField::Attributes => json!(record.meta),
+            };
+            res.insert(field.name().to_string(), value);
+        }
+        if multicast {
+            res.insert("multicast".to_string(), json!(true));
+        }
+        Value::Object(res)
+    }
+
     fn prefixes_as_json(
         query_prefix: &Prefix,
         //rib_value: &RibValue, // RibValue is basically PrefixRoute now
@@ -140,11 +585,20 @@ impl PrefixesApi {
         let status = record.status;
 
         let ingress_info = ingress_register.get(ingress_id);
+        let tags = details_cfg
+            .tags
+            .as_ref()
+            .and_then(|tags| tags.get(*query_prefix, ingress_id));
 
         let mut sortable_results = Some(record.meta.clone())
             .iter()
             .filter(|&item| {
-                Self::include_item_in_results(filter_cfg, item, &ingress_info)
+                Self::include_item_in_results(
+                    filter_cfg,
+                    item,
+                    &ingress_info,
+                    tags.as_ref(),
+                )
             })
             .map(|item| {
                 Self::mk_result(
@@ -154,6 +608,7 @@ impl PrefixesApi {
                     status,
                     details_cfg,
                     &ingress_info,
+                    tags.as_ref(),
                 )
             })
             .collect::<Vec<Value>>();
@@ -260,15 +715,16 @@ impl PrefixesApi {
         route: &RotondaPaMap,
         ingress_id: IngressId,
         status: RouteStatus,
-        _details_cfg: &Details,
+        details_cfg: &Details,
         ingress_info: &Option<ingress::IngressInfo>,
+        tags: Option<&Tags>,
     ) -> Value {
         // TODO: Honor details_cfg
 
         // XXX LH: this is yet another place where a prefix is expected, i.e.
         // things are limited in terms of supported afisafis.
 
-        serde_json::to_value(json!({
+        let mut res = serde_json::to_value(json!({
             "ingress_id": ingress_id,
             "ingress_info": ingress_info,
             "prefix": query_prefix,
@@ -277,7 +733,20 @@ impl PrefixesApi {
             "attributes": route//.path_attributes(),
 
         }))
-        .unwrap()
+        .unwrap();
+
+        if let Some(best_paths) = &details_cfg.best_paths {
+            res.insert(
+                "best",
+                json!(best_paths.contains(&(*query_prefix, ingress_id))),
+            );
+        }
+
+        if let Some(tags) = tags {
+            res.insert("tags", json!(tags));
+        }
+
+        res
     }
 
     fn include_item_in_results(
@@ -287,6 +756,7 @@ impl PrefixesApi {
         //item: &RotondaRoute,
         item: &RotondaPaMap,
         ingress_info: &Option<IngressInfo>,
+        tags: Option<&Tags>,
     ) -> bool {
         let no_selects = filter_cfg.selects().is_empty();
         let no_discards = filter_cfg.discards().is_empty();
@@ -313,6 +783,8 @@ impl PrefixesApi {
             FilterKind::PeerAs(peer_as) => {
                 Self::match_peer_as(item, *peer_as, ingress_info)
             }
+
+            FilterKind::Tag(filter) => filter.matches(tags),
         };
 
         let mut discards = filter_cfg.discards().iter();
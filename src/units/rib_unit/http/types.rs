@@ -1,8 +1,18 @@
-use inetnum::asn::Asn;
-use routecore::bgp::communities::HumanReadableCommunity as Community;
+use std::{collections::HashSet, sync::Arc};
+
+use inetnum::{addr::Prefix, asn::Asn};
+use routecore::bgp::{
+    communities::HumanReadableCommunity as Community, types::Afi,
+};
+
+use crate::{
+    ingress::IngressId,
+    units::rib_unit::tags::{RouteTags, TagFilter},
+};
 
 #[derive(Debug, Default)]
 pub struct Includes {
+    pub exactly_matching: bool,
     pub less_specifics: bool,
     pub more_specifics: bool,
 }
@@ -10,6 +20,12 @@ pub struct Includes {
 #[derive(Debug, Default)]
 pub struct Details {
     pub communities: bool,
+
+    /// The winners of best path selection, to mark in the output.
+    pub best_paths: Option<HashSet<(Prefix, IngressId)>>,
+
+    /// The tags of the routes, to include in the output and filter on.
+    pub tags: Option<Arc<RouteTags>>,
 }
 
 #[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
@@ -25,6 +41,7 @@ pub enum FilterKind {
     AsPath(Vec<Asn>),
     PeerAs(Asn),
     Community(Community),
+    Tag(TagFilter),
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -91,3 +108,75 @@ impl Filters {
 // https://docs.rs/serde_json/latest/serde_json/value/
 // enum.Value.html#method.pointer
 pub type SortKey = Option<String>;
+
+/// A field of the routes streamed by a dump.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DumpField {
+    Prefix,
+    IngressId,
+    IngressInfo,
+    Rpki,
+    Status,
+    Attributes,
+    Tags,
+}
+
+impl DumpField {
+    pub const ALL: [DumpField; 7] = [
+        DumpField::Prefix,
+        DumpField::IngressId,
+        DumpField::IngressInfo,
+        DumpField::Rpki,
+        DumpField::Status,
+        DumpField::Attributes,
+        DumpField::Tags,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DumpField::Prefix => "prefix",
+            DumpField::IngressId => "ingress_id",
+            DumpField::IngressInfo => "ingress_info",
+            DumpField::Rpki => "rpki",
+            DumpField::Status => "status",
+            DumpField::Attributes => "attributes",
+            DumpField::Tags => "tags",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|field| field.name() == name)
+    }
+}
+
+/// What to include in a dump of the RIB.
+#[derive(Clone, Debug)]
+pub struct Dump {
+    pub fields: Vec<DumpField>,
+
+    /// Only dump the routes for this address family.
+    pub afi: Option<Afi>,
+
+    /// Only dump the routes with all of these tags.
+    pub tags: Vec<TagFilter>,
+}
+
+impl Default for Dump {
+    fn default() -> Self {
+        Self {
+            fields: DumpField::ALL.to_vec(),
+            afi: None,
+            tags: vec![],
+        }
+    }
+}
+
+impl Dump {
+    pub fn includes(&self, prefix: &Prefix) -> bool {
+        match self.afi {
+            Some(Afi::Ipv4) => prefix.is_v4(),
+            Some(Afi::Ipv6) => !prefix.is_v4(),
+            _ => true,
+        }
+    }
+}
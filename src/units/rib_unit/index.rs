@@ -0,0 +1,501 @@
+//! Optional secondary indexes on the RIB contents.
+//!
+//! The prefix store is keyed on prefix only, so answering a question like
+//! "which routes have 3356 in their AS path" or "which routes carry
+//! community 65000:100" requires a full table scan.
+//! These indexes trade memory for avoiding that scan, and are maintained on
+//! every insert into the [`Rib`](super::rib::Rib).
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use inetnum::{addr::Prefix, asn::Asn};
+use regex::Regex;
+use rotonda_store::prefix_record::Record;
+use routecore::bgp::{
+    aspath::{Hop, HopPath},
+    communities::Community,
+    message::update_builder::StandardCommunitiesList,
+    path_attributes::{
+        ExtendedCommunitiesList, Ipv6ExtendedCommunitiesList,
+        LargeCommunitiesList,
+    },
+};
+use serde::Deserialize;
+
+use crate::{ingress::IngressId, payload::RotondaPaMap};
+
+use super::rpki::RovStatus;
+use super::tags::TagFilter;
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct IndexConfig {
+    /// Maintain an index of the distinct AS paths in the RIB, to speed up
+    /// AS path regex queries.
+    #[serde(default)]
+    pub as_path: bool,
+
+    /// Maintain an inverted index from community values to routes, to speed
+    /// up community queries.
+    #[serde(default)]
+    pub communities: bool,
+}
+
+//------------ RouteSearch ---------------------------------------------------
+
+/// The criteria of a search over all routes in the RIB.
+///
+/// A route matches if it matches all of the given criteria.
+#[derive(Debug, Default)]
+pub struct RouteSearch {
+    pub as_path_regex: Option<Regex>,
+    pub communities: Vec<CommunityPattern>,
+
+    /// Only match routes learned from these ingresses.
+    pub ingresses: Option<HashSet<IngressId>>,
+
+    /// Only match routes originated by one of these ASNs.
+    pub origin_asns: Vec<Asn>,
+
+    /// Only match routes with one of these RPKI ROV statuses.
+    pub rov_statuses: Vec<RovStatus>,
+
+    /// Only match routes with all of these tags, checked by the
+    /// [`Rib`](super::rib::Rib) as it keeps the tags.
+    pub tags: Vec<TagFilter>,
+
+    /// Only match routes for this prefix or more specifics of it, checked
+    /// by the [`Rib`](super::rib::Rib) as records do not include it.
+    pub prefix: Option<Prefix>,
+}
+
+impl RouteSearch {
+    pub fn is_empty(&self) -> bool {
+        self.as_path_regex.is_none()
+            && self.communities.is_empty()
+            && self.ingresses.is_none()
+            && self.origin_asns.is_empty()
+            && self.rov_statuses.is_empty()
+            && self.tags.is_empty()
+            && self.prefix.is_none()
+    }
+
+    pub fn matches_record(&self, record: &Record<RotondaPaMap>) -> bool {
+        self.ingresses
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&record.multi_uniq_id))
+            && self.matches(&record.meta)
+    }
+
+    pub fn matches(&self, pamap: &RotondaPaMap) -> bool {
+        if let Some(regex) = &self.as_path_regex {
+            if !as_path_string(pamap).is_some_and(|path| regex.is_match(&path))
+            {
+                return false;
+            }
+        }
+        if !self.origin_asns.is_empty()
+            && !origin_asn(pamap)
+                .is_some_and(|origin| self.origin_asns.contains(&origin))
+        {
+            return false;
+        }
+        if !self.rov_statuses.is_empty()
+            && !self.rov_statuses.contains(&pamap.rpki_info().rov_status())
+        {
+            return false;
+        }
+        if !self.communities.is_empty() {
+            let communities = communities(pamap);
+            return self
+                .communities
+                .iter()
+                .all(|pattern| communities.iter().any(|c| pattern.matches(c)));
+        }
+        true
+    }
+}
+
+//------------ AS path matching ----------------------------------------------
+
+/// Render the AS path of `pamap` as a space separated list of ASNs, the
+/// form AS path regexes are matched against.
+pub fn as_path_string(pamap: &RotondaPaMap) -> Option<String> {
+    let hop_path = pamap.path_attributes().get::<HopPath>()?;
+    let mut res = String::new();
+    for hop in hop_path.iter() {
+        if !res.is_empty() {
+            res.push(' ');
+        }
+        match hop {
+            Hop::Asn(asn) => res.push_str(&asn.into_u32().to_string()),
+            Hop::Segment(segment) => res.push_str(&segment.to_string()),
+        }
+    }
+    Some(res)
+}
+
+/// The ASN that originated the route with `pamap`, if known.
+///
+/// Routes originated by an AS_SET have no origin ASN.
+pub fn origin_asn(pamap: &RotondaPaMap) -> Option<Asn> {
+    let hop_path = pamap.path_attributes().get::<HopPath>()?;
+    hop_path
+        .origin()
+        .and_then(|hop| Hop::try_into_asn(hop.clone()).ok())
+}
+
+/// Compile an AS path regex.
+///
+/// Besides the regular syntax, the `_` character commonly used in router
+/// AS path regexes is supported and matches the boundary between two ASNs
+/// or the start or end of the path. `_3356_ 1299$` thus matches paths in
+/// which 3356 is followed by 1299 as the origin.
+pub fn compile_as_path_regex(pattern: &str) -> Result<Regex, String> {
+    let mut translated = String::with_capacity(pattern.len());
+    let mut escaped = false;
+    for c in pattern.chars() {
+        match c {
+            '_' if !escaped => translated.push_str(r"\b"),
+            _ => translated.push(c),
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    Regex::new(&translated)
+        .map_err(|err| format!("Invalid AS path regex '{pattern}': {err}"))
+}
+
+//------------ Community matching --------------------------------------------
+
+/// All standard, extended and large communities of `pamap`.
+pub fn communities(pamap: &RotondaPaMap) -> Vec<Community> {
+    // Vec<Community> is only retrievable through a RouteWorkshop, so collect
+    // the separate community attributes ourselves.
+    let attrs = pamap.path_attributes();
+    let mut res = attrs
+        .get::<StandardCommunitiesList>()
+        .map(|c| c.fmap(|c| Community::Standard(*c)))
+        .unwrap_or_default();
+    res.extend(
+        attrs
+            .get::<ExtendedCommunitiesList>()
+            .map(|c| c.fmap(Community::Extended))
+            .unwrap_or_default(),
+    );
+    res.extend(
+        attrs
+            .get::<Ipv6ExtendedCommunitiesList>()
+            .map(|c| c.fmap(Community::Ipv6Extended))
+            .unwrap_or_default(),
+    );
+    res.extend(
+        attrs
+            .get::<LargeCommunitiesList>()
+            .map(|c| c.fmap(Community::Large))
+            .unwrap_or_default(),
+    );
+    res
+}
+
+/// A community value to match routes on.
+///
+/// Any member of the textual representation of a community can be replaced
+/// by a `*` wildcard, e.g. `65000:*`, `*:100`, `65000:*:1` for large
+/// communities or `rt:65000:*` for extended communities.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CommunityPattern {
+    Exact(Community),
+    Wildcard(Vec<Option<String>>),
+}
+
+impl CommunityPattern {
+    pub fn matches(&self, community: &Community) -> bool {
+        match self {
+            CommunityPattern::Exact(wanted) => wanted == community,
+            CommunityPattern::Wildcard(wanted) => {
+                let actual = community.to_string();
+                let actual = actual.split(':').map(strip_as);
+                actual.clone().count() == wanted.len()
+                    && actual.zip(wanted).all(|(actual, wanted)| {
+                        wanted
+                            .as_ref()
+                            .is_none_or(|wanted| wanted.eq_ignore_ascii_case(actual))
+                    })
+            }
+        }
+    }
+}
+
+impl FromStr for CommunityPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('*') {
+            let members = s
+                .split(':')
+                .map(|member| match member {
+                    "*" => Ok(None),
+                    m if m.is_empty() || m.contains('*') => Err(format!(
+                        "Invalid community pattern '{s}': wildcards must replace a whole member"
+                    )),
+                    m => Ok(Some(strip_as(m).to_string())),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if members.len() < 2 {
+                return Err(format!(
+                    "Invalid community pattern '{s}': expected at least two members"
+                ));
+            }
+            Ok(CommunityPattern::Wildcard(members))
+        } else {
+            Community::from_str(s)
+                .map(CommunityPattern::Exact)
+                .map_err(|err| format!("Invalid community '{s}': {err}"))
+        }
+    }
+}
+
+fn strip_as(member: &str) -> &str {
+    member
+        .strip_prefix("AS")
+        .or_else(|| member.strip_prefix("as"))
+        .unwrap_or(member)
+}
+
+//------------ AsPathIndex ---------------------------------------------------
+
+pub type RouteKey = (Prefix, IngressId);
+
+/// Index of the distinct AS paths in the RIB.
+///
+/// A regex is evaluated once per distinct AS path rather than once per
+/// route, which on a full table is an order of magnitude less work.
+#[derive(Debug, Default)]
+pub struct AsPathIndex {
+    inner: RwLock<AsPathIndexInner>,
+}
+
+#[derive(Debug, Default)]
+struct AsPathIndexInner {
+    routes: HashMap<Arc<str>, HashSet<RouteKey>>,
+    paths: HashMap<RouteKey, Arc<str>>,
+}
+
+impl AsPathIndex {
+    pub fn insert(
+        &self,
+        prefix: Prefix,
+        ingress_id: IngressId,
+        pamap: &RotondaPaMap,
+    ) {
+        let Some(path) = as_path_string(pamap) else {
+            return;
+        };
+        let key = (prefix, ingress_id);
+        let mut inner = self.inner.write().unwrap();
+
+        if let Some(old) = inner.paths.get(&key) {
+            if old.as_ref() == path {
+                return;
+            }
+            let old = old.clone();
+            inner.remove_route(&old, &key);
+        }
+
+        let path: Arc<str> = match inner.routes.get_key_value(path.as_str())
+        {
+            Some((existing, _)) => existing.clone(),
+            None => path.into(),
+        };
+        inner.routes.entry(path.clone()).or_default().insert(key);
+        inner.paths.insert(key, path);
+    }
+
+    /// The AS path indexed for the route `key`.
+    pub fn path(&self, key: &RouteKey) -> Option<Arc<str>> {
+        self.inner.read().unwrap().paths.get(key).cloned()
+    }
+
+    /// Remove the route `key` from the index.
+    pub fn remove(&self, key: &RouteKey) {
+        let mut inner = self.inner.write().unwrap();
+        if let Some(path) = inner.paths.remove(key) {
+            inner.remove_route(&path, key);
+        }
+    }
+
+    /// The routes in the index that are not in `keys`.
+    pub fn keys_except(&self, keys: &HashSet<RouteKey>) -> Vec<RouteKey> {
+        let inner = self.inner.read().unwrap();
+        inner
+            .paths
+            .keys()
+            .filter(|key| !keys.contains(key))
+            .copied()
+            .collect()
+    }
+
+    /// Returns the prefixes and ingresses of all routes with an AS path
+    /// matching `regex`.
+    pub fn matching(&self, regex: &Regex) -> HashSet<RouteKey> {
+        let inner = self.inner.read().unwrap();
+        inner
+            .routes
+            .iter()
+            .filter(|(path, _)| regex.is_match(path))
+            .flat_map(|(_, keys)| keys.iter().copied())
+            .collect()
+    }
+}
+
+impl AsPathIndexInner {
+    fn remove_route(&mut self, path: &Arc<str>, key: &RouteKey) {
+        if let Some(keys) = self.routes.get_mut(path) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.routes.remove(path);
+            }
+        }
+    }
+}
+
+//------------ CommunityIndex ------------------------------------------------
+
+/// Inverted index from community values to the routes carrying them.
+#[derive(Debug, Default)]
+pub struct CommunityIndex {
+    inner: RwLock<CommunityIndexInner>,
+}
+
+#[derive(Debug, Default)]
+struct CommunityIndexInner {
+    routes: HashMap<Community, HashSet<RouteKey>>,
+    communities: HashMap<RouteKey, Vec<Community>>,
+}
+
+impl CommunityIndex {
+    pub fn insert(
+        &self,
+        prefix: Prefix,
+        ingress_id: IngressId,
+        pamap: &RotondaPaMap,
+    ) {
+        let key = (prefix, ingress_id);
+        let new = communities(pamap);
+        let mut inner = self.inner.write().unwrap();
+
+        inner.remove_route(&key);
+
+        if new.is_empty() {
+            return;
+        }
+        for community in &new {
+            inner.routes.entry(*community).or_default().insert(key);
+        }
+        inner.communities.insert(key, new);
+    }
+
+    /// The communities indexed for the route `key`.
+    pub fn communities(&self, key: &RouteKey) -> Vec<Community> {
+        let inner = self.inner.read().unwrap();
+        inner.communities.get(key).cloned().unwrap_or_default()
+    }
+
+    /// Remove the route `key` from the index.
+    pub fn remove(&self, key: &RouteKey) {
+        self.inner.write().unwrap().remove_route(key);
+    }
+
+    /// The routes in the index that are not in `keys`.
+    pub fn keys_except(&self, keys: &HashSet<RouteKey>) -> Vec<RouteKey> {
+        let inner = self.inner.read().unwrap();
+        inner
+            .communities
+            .keys()
+            .filter(|key| !keys.contains(key))
+            .copied()
+            .collect()
+    }
+
+    /// Returns the prefixes and ingresses of all routes carrying a
+    /// community matching `pattern`.
+    pub fn matching(&self, pattern: &CommunityPattern) -> HashSet<RouteKey> {
+        let inner = self.inner.read().unwrap();
+        match pattern {
+            CommunityPattern::Exact(community) => {
+                inner.routes.get(community).cloned().unwrap_or_default()
+            }
+            CommunityPattern::Wildcard(_) => inner
+                .routes
+                .iter()
+                .filter(|(community, _)| pattern.matches(community))
+                .flat_map(|(_, keys)| keys.iter().copied())
+                .collect(),
+        }
+    }
+}
+
+impl CommunityIndexInner {
+    fn remove_route(&mut self, key: &RouteKey) {
+        let Some(old) = self.communities.remove(key) else {
+            return;
+        };
+        for community in old {
+            if let Some(keys) = self.routes.get_mut(&community) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.routes.remove(&community);
+                }
+            }
+        }
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn underscore_matches_asn_boundaries() {
+        let re = compile_as_path_regex("_3356_ 1299$").unwrap();
+        assert!(re.is_match("174 3356 1299"));
+        assert!(!re.is_match("174 33561 1299"));
+        assert!(!re.is_match("3356 1299 64500"));
+
+        let re = compile_as_path_regex("^3356_").unwrap();
+        assert!(re.is_match("3356"));
+        assert!(!re.is_match("33560 1299"));
+
+        let re = compile_as_path_regex(r"\_").unwrap();
+        assert!(!re.is_match("3356 1299"));
+    }
+
+    #[test]
+    fn community_patterns() {
+        let matches = |pattern: &str, community: &str| {
+            CommunityPattern::from_str(pattern)
+                .unwrap()
+                .matches(&Community::from_str(community).unwrap())
+        };
+        assert!(matches("65000:100", "65000:100"));
+        assert!(!matches("65000:100", "65000:101"));
+        assert!(matches("65000:*", "65000:101"));
+        assert!(matches("*:101", "AS65000:101"));
+        assert!(!matches("65000:*", "65001:101"));
+        assert!(matches("65000:*:1", "65000:7:1"));
+        assert!(!matches("65000:*", "65000:7:1"));
+        assert!(matches("rt:65000:*", "rt:65000:1"));
+        assert!(matches("no_export", "NO_EXPORT"));
+
+        assert!(CommunityPattern::from_str("*").is_err());
+        assert!(CommunityPattern::from_str("650*:1").is_err());
+        assert!(CommunityPattern::from_str("nonsense").is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Estimating and limiting the memory used by the RIB.
+//!
+//! The prefix store does not report how much memory it uses, so the usage
+//! is estimated from the number of prefixes and paths (i.e. distinct
+//! (prefix, ingress) combinations) stored, plus the size of the path
+//! attributes of those paths. When a path is overwritten, its attributes are
+//! assumed to keep the same size.
+//!
+//! The store never releases the memory of a path, not even when the path is
+//! withdrawn. Evicting routes therefore means rebuilding the RIB without
+//! them, which temporarily needs memory for both copies.
+
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+};
+
+use rotonda_store::{prefix_record::Record, stats::UpsertReport};
+use serde::Deserialize;
+
+use crate::{ingress::IngressId, payload::RotondaPaMap};
+
+/// The estimated overhead per prefix in the store, in bytes.
+const PREFIX_OVERHEAD: usize = 64;
+
+/// The estimated overhead per path in the store, excluding the path
+/// attributes themselves, in bytes.
+const PATH_OVERHEAD: usize = std::mem::size_of::<Record<RotondaPaMap>>() + 32;
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MemoryConfig {
+    /// The maximum estimated memory use of the RIB, in bytes.
+    pub max_bytes: usize,
+
+    /// Raise an alarm when the estimated memory use exceeds this percentage
+    /// of `max_bytes`.
+    #[serde(default = "MemoryConfig::default_warn_percent")]
+    pub warn_percent: u8,
+
+    /// What to do with new paths once the limit is reached.
+    #[serde(default)]
+    pub on_limit: LimitPolicy,
+}
+
+impl MemoryConfig {
+    fn default_warn_percent() -> u8 {
+        90
+    }
+
+    pub fn warn_bytes(&self) -> usize {
+        self.max_bytes / 100 * usize::from(self.warn_percent.min(100))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitPolicy {
+    /// Drop new paths. Updates to paths already in the RIB and withdrawals
+    /// are still processed.
+    #[default]
+    Reject,
+
+    /// Evict the ingress holding the most paths: the RIB is rebuilt without
+    /// its routes, and further routes from it are dropped. Should the RIB
+    /// still be over the limit after that, new paths are dropped as with
+    /// `Reject`.
+    Evict,
+}
+
+//------------ MemoryUsage ---------------------------------------------------
+
+/// Running totals from which the memory use of the RIB is estimated.
+///
+/// The usage of each RIB of a unit is kept separately, as rebuilding or
+/// recounting a RIB replaces its totals, and is also added to the totals of
+/// the unit the limit applies to.
+#[derive(Debug, Default)]
+pub struct MemoryUsage {
+    prefixes: AtomicUsize,
+    paths: AtomicUsize,
+    attribute_bytes: AtomicUsize,
+
+    /// The usage of the unit this is part of.
+    unit: Option<Arc<MemoryUsage>>,
+}
+
+impl MemoryUsage {
+    /// Creates the usage of a RIB that is also accounted in `unit`.
+    pub fn part_of(unit: Arc<MemoryUsage>) -> Self {
+        MemoryUsage {
+            unit: Some(unit),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the usage of the unit this is part of, or else this usage.
+    pub fn unit(&self) -> &MemoryUsage {
+        self.unit.as_deref().unwrap_or(self)
+    }
+
+    /// Account for the insertion of a path with `attributes_len` bytes of
+    /// path attributes.
+    ///
+    /// The `mui_new` flag of the report cannot be relied upon, so whether
+    /// the path is new has to be determined by the caller.
+    pub fn record_insert(
+        &self,
+        report: &UpsertReport,
+        path_new: bool,
+        attributes_len: usize,
+    ) {
+        if report.prefix_new {
+            self.add(&self.prefixes, 1, |unit| &unit.prefixes);
+        }
+        if path_new {
+            self.add(&self.paths, 1, |unit| &unit.paths);
+            self.add(&self.attribute_bytes, attributes_len, |unit| {
+                &unit.attribute_bytes
+            });
+        }
+    }
+
+    /// Take over the totals of `other`.
+    pub fn set_to(&self, other: &MemoryUsage) {
+        self.recount(other.prefixes(), other.paths(), other.attribute_bytes())
+    }
+
+    /// Replace the totals with those counted in the RIB itself.
+    pub fn recount(
+        &self,
+        prefixes: usize,
+        paths: usize,
+        attribute_bytes: usize,
+    ) {
+        self.store(&self.prefixes, prefixes, |unit| &unit.prefixes);
+        self.store(&self.paths, paths, |unit| &unit.paths);
+        self.store(&self.attribute_bytes, attribute_bytes, |unit| {
+            &unit.attribute_bytes
+        });
+    }
+
+    pub fn reset(&self) {
+        self.recount(0, 0, 0)
+    }
+
+    fn add(
+        &self,
+        total: &AtomicUsize,
+        n: usize,
+        unit_total: fn(&MemoryUsage) -> &AtomicUsize,
+    ) {
+        total.fetch_add(n, Relaxed);
+        if let Some(unit) = &self.unit {
+            unit_total(unit).fetch_add(n, Relaxed);
+        }
+    }
+
+    fn store(
+        &self,
+        total: &AtomicUsize,
+        n: usize,
+        unit_total: fn(&MemoryUsage) -> &AtomicUsize,
+    ) {
+        let old = total.swap(n, Relaxed);
+        if let Some(unit) = &self.unit {
+            let unit_total = unit_total(unit);
+            if n >= old {
+                unit_total.fetch_add(n - old, Relaxed);
+            } else {
+                unit_total.fetch_sub(old - n, Relaxed);
+            }
+        }
+    }
+
+    pub fn prefixes(&self) -> usize {
+        self.prefixes.load(Relaxed)
+    }
+
+    pub fn paths(&self) -> usize {
+        self.paths.load(Relaxed)
+    }
+
+    pub fn attribute_bytes(&self) -> usize {
+        self.attribute_bytes.load(Relaxed)
+    }
+
+    pub fn estimated_bytes(&self) -> usize {
+        self.prefixes() * PREFIX_OVERHEAD
+            + self.paths() * PATH_OVERHEAD
+            + self.attribute_bytes()
+    }
+}
+
+//------------ LimitState ----------------------------------------------------
+
+/// The state kept for enforcing a [`MemoryConfig`].
+#[derive(Debug, Default)]
+pub struct LimitState {
+    /// The ingresses evicted from the RIB, whose routes are dropped.
+    pub evicted: Mutex<HashSet<IngressId>>,
+
+    /// Whether the warning threshold has been exceeded.
+    pub warned: AtomicBool,
+
+    /// Whether new paths are being rejected.
+    pub rejecting: AtomicBool,
+}
+
+impl LimitState {
+    pub fn is_evicted(&self, ingress_id: IngressId) -> bool {
+        self.evicted.lock().unwrap().contains(&ingress_id)
+    }
+}
@@ -6,7 +6,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use arc_swap::{ArcSwap, ArcSwapAny};
+use arc_swap::{ArcSwap, ArcSwapAny, ArcSwapOption};
 
 use crate::{
     common::frim::FrimMap,
@@ -18,7 +18,12 @@ use crate::{
     payload::RouterId,
 };
 
-use super::statistics::RibMergeUpdateStatistics;
+use super::{
+    compaction::CompactionReport,
+    consistency::{ConsistencyReport, DiscrepancyKind},
+    memory::MemoryUsage,
+    statistics::RibMergeUpdateStatistics, stats::RibStats,
+};
 
 #[derive(Debug, Default)]
 pub struct RibUnitMetrics {
@@ -33,6 +38,31 @@ pub struct RibUnitMetrics {
     pub num_route_withdrawals_without_announcement: AtomicUsize,
     pub last_insert_duration_micros: AtomicU64,
     pub last_update_duration_micros: AtomicU64,
+    pub num_bootstrapped_routes: AtomicUsize,
+    pub num_snapshots_written: AtomicUsize,
+    pub num_snapshot_failures: AtomicUsize,
+    pub last_snapshot_duration_millis: AtomicU64,
+    pub last_snapshot_size_bytes: AtomicU64,
+    pub num_stale_routes: AtomicUsize,
+    pub num_stale_routes_expired: AtomicUsize,
+    pub num_paths_rejected: AtomicUsize,
+    pub num_ingresses_evicted: AtomicUsize,
+    pub num_gc_runs: AtomicUsize,
+    pub num_withdrawn_routes_purged: AtomicUsize,
+    pub gc_reclaimed_bytes: AtomicUsize,
+    pub last_gc_duration_millis: AtomicU64,
+    pub num_wal_entries_written: AtomicUsize,
+    pub num_wal_write_failures: AtomicUsize,
+    pub num_wal_entries_replayed: AtomicUsize,
+    pub memory_usage: Arc<MemoryUsage>,
+    pub rib_stats: ArcSwapOption<RibStats>,
+    pub num_consistency_checks: AtomicUsize,
+    pub num_consistency_repairs: AtomicUsize,
+    pub last_consistency_check_duration_millis: AtomicU64,
+    pub consistency: ArcSwapOption<ConsistencyReport>,
+    pub num_compactions: AtomicUsize,
+    pub num_compaction_failures: AtomicUsize,
+    pub compaction: ArcSwapOption<CompactionReport>,
     //routers: Arc<FrimMap<Arc<RouterId>, Arc<RouterMetrics>>>,
     routers: Arc<FrimMap<IngressId, Arc<RouterMetrics>>>,
     pub rib_merge_update_stats: Arc<RibMergeUpdateStatistics>,
@@ -132,6 +162,222 @@ impl RibUnitMetrics {
         MetricType::Gauge,
         MetricUnit::Microsecond,
     );
+    const NUM_BOOTSTRAPPED_ROUTES_METRIC: Metric = Metric::new(
+        "rib_unit_num_bootstrapped_routes",
+        "the number of routes loaded from the bootstrap snapshot that have not expired yet",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const NUM_SNAPSHOTS_WRITTEN_METRIC: Metric = Metric::new(
+        "rib_unit_num_snapshots_written",
+        "the number of RIB snapshots written to disk",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_SNAPSHOT_FAILURES_METRIC: Metric = Metric::new(
+        "rib_unit_num_snapshot_failures",
+        "the number of RIB snapshots that could not be written",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const LAST_SNAPSHOT_DURATION_METRIC: Metric = Metric::new(
+        "rib_unit_snapshot_duration",
+        "the time taken to write the last RIB snapshot",
+        MetricType::Gauge,
+        MetricUnit::Millisecond,
+    );
+    const LAST_SNAPSHOT_SIZE_METRIC: Metric = Metric::new(
+        "rib_unit_snapshot_size",
+        "the size of the last RIB snapshot written",
+        MetricType::Gauge,
+        MetricUnit::Byte,
+    );
+    const NUM_STALE_ROUTES_METRIC: Metric = Metric::new(
+        "rib_unit_num_stale_routes",
+        "the number of routes of peers that went down which are kept as stale",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const NUM_STALE_ROUTES_EXPIRED_METRIC: Metric = Metric::new(
+        "rib_unit_num_stale_routes_expired",
+        "the number of stale routes withdrawn because they were not re-announced in time",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const MEMORY_PREFIXES_METRIC: Metric = Metric::new(
+        "rib_unit_memory_prefixes",
+        "the number of prefixes accounted for in the RIB memory estimate",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const MEMORY_PATHS_METRIC: Metric = Metric::new(
+        "rib_unit_memory_paths",
+        "the number of paths accounted for in the RIB memory estimate",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const MEMORY_ATTRIBUTES_METRIC: Metric = Metric::new(
+        "rib_unit_memory_attributes",
+        "the estimated memory used for path attributes in the RIB",
+        MetricType::Gauge,
+        MetricUnit::Byte,
+    );
+    const MEMORY_ESTIMATED_METRIC: Metric = Metric::new(
+        "rib_unit_memory_estimated",
+        "the estimated total memory used by the RIB",
+        MetricType::Gauge,
+        MetricUnit::Byte,
+    );
+    const NUM_PATHS_REJECTED_METRIC: Metric = Metric::new(
+        "rib_unit_num_paths_rejected",
+        "the number of new paths dropped because the RIB memory limit was reached",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_INGRESSES_EVICTED_METRIC: Metric = Metric::new(
+        "rib_unit_num_ingresses_evicted",
+        "the number of ingresses evicted because the RIB memory limit was reached",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_GC_RUNS_METRIC: Metric = Metric::new(
+        "rib_unit_num_gc_runs",
+        "the number of times the garbage collector ran",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WITHDRAWN_ROUTES_PURGED_METRIC: Metric = Metric::new(
+        "rib_unit_num_withdrawn_routes_purged",
+        "the number of withdrawn routes purged from the rib by the garbage collector",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const GC_RECLAIMED_BYTES_METRIC: Metric = Metric::new(
+        "rib_unit_gc_reclaimed",
+        "the estimated memory reclaimed by the garbage collector",
+        MetricType::Counter,
+        MetricUnit::Byte,
+    );
+    const LAST_GC_DURATION_METRIC: Metric = Metric::new(
+        "rib_unit_gc_duration",
+        "the time taken by the last garbage collection run",
+        MetricType::Gauge,
+        MetricUnit::Millisecond,
+    );
+    const NUM_WAL_ENTRIES_WRITTEN_METRIC: Metric = Metric::new(
+        "rib_unit_num_wal_entries_written",
+        "the number of changes to the rib written to the write-ahead log",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WAL_WRITE_FAILURES_METRIC: Metric = Metric::new(
+        "rib_unit_num_wal_write_failures",
+        "the number of changes to the rib that could not be written to the write-ahead log",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WAL_ENTRIES_REPLAYED_METRIC: Metric = Metric::new(
+        "rib_unit_num_wal_entries_replayed",
+        "the number of write-ahead log entries replayed on startup",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const STATS_PREFIXES_METRIC: Metric = Metric::new(
+        "rib_unit_stats_prefixes",
+        "the number of prefixes in the rib per AFI/SAFI",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const STATS_ROUTES_METRIC: Metric = Metric::new(
+        "rib_unit_stats_routes",
+        "the number of routes in the rib that are not withdrawn",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const STATS_UNIQUE_AS_PATHS_METRIC: Metric = Metric::new(
+        "rib_unit_stats_unique_as_paths",
+        "the number of distinct AS paths among the routes in the rib",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const STATS_ORIGIN_PREFIXES_METRIC: Metric = Metric::new(
+        "rib_unit_stats_origin_prefixes",
+        "the number of prefixes in the rib for the origin ASNs with the most prefixes",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const STATS_INGRESS_ROUTES_METRIC: Metric = Metric::new(
+        "rib_unit_stats_ingress_routes",
+        "the number of routes in the rib per ingress",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const STATS_DISTINCT_ATTRIBUTES_METRIC: Metric = Metric::new(
+        "rib_unit_stats_distinct_attribute_sets",
+        "the number of distinct sets of path attributes among the routes in the rib",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const STATS_ATTRIBUTES_METRIC: Metric = Metric::new(
+        "rib_unit_stats_attributes",
+        "the size of the path attributes of the routes in the rib",
+        MetricType::Gauge,
+        MetricUnit::Byte,
+    );
+    const STATS_DISTINCT_ATTRIBUTES_SIZE_METRIC: Metric = Metric::new(
+        "rib_unit_stats_distinct_attributes",
+        "the size of the distinct sets of path attributes in the rib",
+        MetricType::Gauge,
+        MetricUnit::Byte,
+    );
+    const NUM_CONSISTENCY_CHECKS_METRIC: Metric = Metric::new(
+        "rib_unit_num_consistency_checks",
+        "the number of times the consistency of the rib was checked",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const CONSISTENCY_DISCREPANCIES_METRIC: Metric = Metric::new(
+        "rib_unit_consistency_discrepancies",
+        "the number of discrepancies found by the last consistency check, per kind",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const NUM_CONSISTENCY_REPAIRS_METRIC: Metric = Metric::new(
+        "rib_unit_num_consistency_repairs",
+        "the number of discrepancies resolved by the consistency checker",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const LAST_CONSISTENCY_CHECK_DURATION_METRIC: Metric = Metric::new(
+        "rib_unit_consistency_check_duration",
+        "the time taken by the last consistency check",
+        MetricType::Gauge,
+        MetricUnit::Millisecond,
+    );
+    const NUM_COMPACTIONS_METRIC: Metric = Metric::new(
+        "rib_unit_num_compactions",
+        "the number of times the on-disk state of the rib was compacted",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_COMPACTION_FAILURES_METRIC: Metric = Metric::new(
+        "rib_unit_num_compaction_failures",
+        "the number of times compacting the on-disk state of the rib failed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const LAST_COMPACTION_DURATION_METRIC: Metric = Metric::new(
+        "rib_unit_compaction_duration",
+        "the time taken by the last compaction",
+        MetricType::Gauge,
+        MetricUnit::Millisecond,
+    );
+    const COMPACTED_DISK_USAGE_METRIC: Metric = Metric::new(
+        "rib_unit_compacted_disk_usage",
+        "the disk space taken by the write-ahead log and snapshots right after the last compaction",
+        MetricType::Gauge,
+        MetricUnit::Byte,
+    );
     const LAST_END_TO_END_DELAY_PER_ROUTER_METRIC: Metric = Metric::new(
         "rib_unit_e2e_duration",
         "the time taken from initial receipt to completed insertion for a prefix into the RIB unit store",
@@ -144,10 +390,12 @@ impl RibUnitMetrics {
     pub fn new(
         gate: &Arc<Gate>,
         rib_merge_update_stats: Arc<RibMergeUpdateStatistics>,
+        memory_usage: Arc<MemoryUsage>,
     ) -> Self {
         RibUnitMetrics {
             gate: gate.metrics(),
             rib_merge_update_stats,
+            memory_usage,
             ..Default::default()
         }
     }
@@ -207,7 +455,141 @@ impl metrics::Source for RibUnitMetrics {
             Some(unit_name),
             self.last_update_duration_micros.load(SeqCst),
         );
+        target.append_simple(
+            &Self::NUM_BOOTSTRAPPED_ROUTES_METRIC,
+            Some(unit_name),
+            self.num_bootstrapped_routes.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_SNAPSHOTS_WRITTEN_METRIC,
+            Some(unit_name),
+            self.num_snapshots_written.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_SNAPSHOT_FAILURES_METRIC,
+            Some(unit_name),
+            self.num_snapshot_failures.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::LAST_SNAPSHOT_DURATION_METRIC,
+            Some(unit_name),
+            self.last_snapshot_duration_millis.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::LAST_SNAPSHOT_SIZE_METRIC,
+            Some(unit_name),
+            self.last_snapshot_size_bytes.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_STALE_ROUTES_METRIC,
+            Some(unit_name),
+            self.num_stale_routes.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_STALE_ROUTES_EXPIRED_METRIC,
+            Some(unit_name),
+            self.num_stale_routes_expired.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::MEMORY_PREFIXES_METRIC,
+            Some(unit_name),
+            self.memory_usage.prefixes(),
+        );
+        target.append_simple(
+            &Self::MEMORY_PATHS_METRIC,
+            Some(unit_name),
+            self.memory_usage.paths(),
+        );
+        target.append_simple(
+            &Self::MEMORY_ATTRIBUTES_METRIC,
+            Some(unit_name),
+            self.memory_usage.attribute_bytes(),
+        );
+        target.append_simple(
+            &Self::MEMORY_ESTIMATED_METRIC,
+            Some(unit_name),
+            self.memory_usage.estimated_bytes(),
+        );
+        target.append_simple(
+            &Self::NUM_PATHS_REJECTED_METRIC,
+            Some(unit_name),
+            self.num_paths_rejected.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_INGRESSES_EVICTED_METRIC,
+            Some(unit_name),
+            self.num_ingresses_evicted.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_GC_RUNS_METRIC,
+            Some(unit_name),
+            self.num_gc_runs.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WITHDRAWN_ROUTES_PURGED_METRIC,
+            Some(unit_name),
+            self.num_withdrawn_routes_purged.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::GC_RECLAIMED_BYTES_METRIC,
+            Some(unit_name),
+            self.gc_reclaimed_bytes.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::LAST_GC_DURATION_METRIC,
+            Some(unit_name),
+            self.last_gc_duration_millis.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WAL_ENTRIES_WRITTEN_METRIC,
+            Some(unit_name),
+            self.num_wal_entries_written.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WAL_WRITE_FAILURES_METRIC,
+            Some(unit_name),
+            self.num_wal_write_failures.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WAL_ENTRIES_REPLAYED_METRIC,
+            Some(unit_name),
+            self.num_wal_entries_replayed.load(SeqCst),
+        );
 
+        if let Some(stats) = self.rib_stats.load_full() {
+            Self::append_rib_stats(&stats, unit_name, target);
+        }
+        target.append_simple(
+            &Self::NUM_CONSISTENCY_CHECKS_METRIC,
+            Some(unit_name),
+            self.num_consistency_checks.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_CONSISTENCY_REPAIRS_METRIC,
+            Some(unit_name),
+            self.num_consistency_repairs.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::LAST_CONSISTENCY_CHECK_DURATION_METRIC,
+            Some(unit_name),
+            self.last_consistency_check_duration_millis.load(SeqCst),
+        );
+        if let Some(report) = self.consistency.load_full() {
+            Self::append_consistency(&report, unit_name, target);
+        }
+        target.append_simple(
+            &Self::NUM_COMPACTIONS_METRIC,
+            Some(unit_name),
+            self.num_compactions.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_COMPACTION_FAILURES_METRIC,
+            Some(unit_name),
+            self.num_compaction_failures.load(SeqCst),
+        );
+        if let Some(report) = self.compaction.load_full() {
+            Self::append_compaction(&report, unit_name, target);
+        }
         let max_age = Duration::from_secs(60);
         self.routers.retain(|_, metrics| {
             metrics.last_e2e_delay_at.load().elapsed() <= max_age
@@ -229,3 +611,120 @@ impl metrics::Source for RibUnitMetrics {
         self.rib_merge_update_stats.append(unit_name, target);
     }
 }
+
+impl RibUnitMetrics {
+    fn append_rib_stats(
+        stats: &RibStats,
+        unit_name: &str,
+        target: &mut metrics::Target,
+    ) {
+        target.append(
+            &Self::STATS_PREFIXES_METRIC,
+            Some(unit_name),
+            |records| {
+                for (afisafi, prefixes) in &stats.prefixes {
+                    records.label_value(&[("afi_safi", afisafi)], prefixes);
+                }
+            },
+        );
+        target.append_simple(
+            &Self::STATS_ROUTES_METRIC,
+            Some(unit_name),
+            stats.routes,
+        );
+        target.append_simple(
+            &Self::STATS_UNIQUE_AS_PATHS_METRIC,
+            Some(unit_name),
+            stats.unique_as_paths,
+        );
+        target.append(
+            &Self::STATS_ORIGIN_PREFIXES_METRIC,
+            Some(unit_name),
+            |records| {
+                for origin in &stats.origins {
+                    records.label_value(
+                        &[("origin", &origin.asn.into_u32().to_string())],
+                        origin.prefixes,
+                    );
+                }
+            },
+        );
+        target.append(
+            &Self::STATS_INGRESS_ROUTES_METRIC,
+            Some(unit_name),
+            |records| {
+                for ingress in &stats.ingresses {
+                    records.label_value(
+                        &[("ingress", &ingress.ingress_id.to_string())],
+                        ingress.routes,
+                    );
+                }
+            },
+        );
+        target.append_simple(
+            &Self::STATS_DISTINCT_ATTRIBUTES_METRIC,
+            Some(unit_name),
+            stats.attributes.distinct,
+        );
+        target.append_simple(
+            &Self::STATS_ATTRIBUTES_METRIC,
+            Some(unit_name),
+            stats.attributes.bytes,
+        );
+        target.append_simple(
+            &Self::STATS_DISTINCT_ATTRIBUTES_SIZE_METRIC,
+            Some(unit_name),
+            stats.attributes.distinct_bytes,
+        );
+    }
+
+    fn append_consistency(
+        report: &ConsistencyReport,
+        unit_name: &str,
+        target: &mut metrics::Target,
+    ) {
+        target.append(
+            &Self::CONSISTENCY_DISCREPANCIES_METRIC,
+            Some(unit_name),
+            |records| {
+                for kind in [
+                    DiscrepancyKind::AsPathIndex,
+                    DiscrepancyKind::CommunityIndex,
+                    DiscrepancyKind::Tags,
+                    DiscrepancyKind::Prefixes,
+                    DiscrepancyKind::Paths,
+                ] {
+                    let found =
+                        report.discrepancies.get(&kind).copied().unwrap_or(0);
+                    records.label_value(&[("kind", kind.as_str())], found);
+                }
+            },
+        );
+    }
+
+    fn append_compaction(
+        report: &CompactionReport,
+        unit_name: &str,
+        target: &mut metrics::Target,
+    ) {
+        target.append_simple(
+            &Self::LAST_COMPACTION_DURATION_METRIC,
+            Some(unit_name),
+            report.duration_millis,
+        );
+        target.append(
+            &Self::COMPACTED_DISK_USAGE_METRIC,
+            Some(unit_name),
+            |records| {
+                records.label_value(
+                    &[("kind", "wal")],
+                    report.after.wal_bytes,
+                );
+                records.label_value(
+                    &[("kind", "snapshots")],
+                    report.after.snapshot_bytes,
+                );
+            },
+        );
+    }
+}
@@ -7,9 +7,26 @@ mod rib;
 #[cfg(test)]
 mod tests;
 
+pub mod best_path;
+pub mod compaction;
+pub mod consistency;
+pub mod diff;
+pub mod filter_trace;
+pub mod flap;
+pub mod gc;
+pub mod graphql;
+pub mod history;
+pub mod index;
+pub mod memory;
+pub mod peer_down;
+pub mod shard;
+pub mod snapshot;
 pub mod statistics;
+pub mod stats;
 pub mod storage;
+pub mod tags;
 pub mod unit;
+pub mod wal;
 
 
 pub mod rpki;
@@ -0,0 +1,60 @@
+//! What to do with the routes of a peer that went down.
+//!
+//! When a BMP Peer Down notification is received or a BGP session is lost,
+//! the ingress unit signals the RIB to withdraw all routes learned from that
+//! peer. By default the RIB does so immediately. Alternatively the routes
+//! can be kept around as stale, in the spirit of BGP Graceful Restart: they
+//! stay in the RIB marked as `InActive` until either the peer re-announces
+//! them or `expire_after_secs` have passed, at which point whatever is still
+//! stale is withdrawn. Finally, the routes can be retained indefinitely.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_with::serde_as;
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct PeerDownConfig {
+    /// What to do with the routes of the peer.
+    #[serde(default)]
+    pub action: PeerDownAction,
+
+    /// How long stale routes are kept before they are withdrawn. Only used
+    /// with the `stale` action.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "PeerDownConfig::default_expire_after_secs")]
+    pub expire_after_secs: Duration,
+}
+
+impl PeerDownConfig {
+    fn default_expire_after_secs() -> Duration {
+        Duration::from_secs(300)
+    }
+}
+
+impl Default for PeerDownConfig {
+    fn default() -> Self {
+        Self {
+            action: PeerDownAction::default(),
+            expire_after_secs: Self::default_expire_after_secs(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerDownAction {
+    /// Withdraw the routes of the peer immediately.
+    #[default]
+    Withdraw,
+
+    /// Mark the routes of the peer as stale, and withdraw those that have
+    /// not been re-announced after `expire_after_secs`.
+    Stale,
+
+    /// Keep the routes of the peer as they are.
+    Retain,
+}
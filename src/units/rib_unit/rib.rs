@@ -1,10 +1,15 @@
 use std::{
-    collections::{hash_set, HashMap},
+    cmp::Ordering,
+    collections::{hash_set, BTreeMap, HashMap, HashSet},
     fmt,
     hash::{BuildHasher, Hasher},
     net::IpAddr,
     ops::Deref,
-    sync::Arc,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc, Mutex,
+    },
 };
 
 use chrono::{Duration, Utc};
@@ -14,9 +19,8 @@ use log::{debug, error, trace};
 use rotonda_store::{
     epoch,
     errors::{FatalResult, PrefixStoreError},
-    match_options::{MatchOptions, QueryResult},
+    match_options::{IncludeHistory, MatchOptions, MatchType, QueryResult},
     prefix_record::{Meta, PrefixRecord, Record, RouteStatus},
-    rib::{config::MemoryOnlyConfig, StarCastRib},
     stats::UpsertReport,
 };
 use routecore::bgp::{
@@ -28,9 +32,18 @@ use routecore::bgp::{
 use serde::Serialize;
 
 use crate::{
-    ingress::IngressId,
+    ingress::{self, IngressId},
     payload::{RotondaPaMap, RotondaRoute, RouterId},
-    roto_runtime::types::Provenance,
+    roto_runtime::types::{Provenance, Tags},
+};
+
+use super::best_path;
+use super::gc::Tracked;
+use super::memory::MemoryUsage;
+use super::shard::{ShardConfig, ShardedStore};
+use super::tags::RouteTags;
+use super::index::{
+    AsPathIndex, CommunityIndex, IndexConfig, RouteKey, RouteSearch,
 };
 
 // -------- PhysicalRib ------------------------------------------------------
@@ -46,24 +59,42 @@ use crate::{
 //     }
 // }
 
-type Store = StarCastRib<RotondaPaMap, MemoryOnlyConfig>;
+type Store = ShardedStore;
 
 pub struct Rib {
     unicast: Arc<Option<Store>>,
     multicast: Arc<Option<Store>>,
     other_fams:
         HashMap<AfiSafiType, HashMap<(IngressId, Nlri<bytes::Bytes>), PaMap>>,
+    as_path_index: Option<AsPathIndex>,
+    community_index: Option<CommunityIndex>,
+    select_best_path: bool,
+    memory_usage: Arc<MemoryUsage>,
+    tags: Arc<RouteTags>,
+    tracking: AtomicBool,
+    tracked: Mutex<Tracked>,
 }
 
 #[derive(Copy, Clone, Debug)]
 struct Multicast(bool);
 
 impl Rib {
-    pub fn new_physical() -> Result<Self, PrefixStoreError> {
+    /// Create a new physical RIB spreading its prefixes over the shards
+    /// described by `config`.
+    pub fn new_physical(
+        config: &ShardConfig,
+    ) -> Result<Self, PrefixStoreError> {
         Ok(Rib {
-            unicast: Arc::new(Some(Store::try_default()?)),
-            multicast: Arc::new(Some(Store::try_default()?)),
+            unicast: Arc::new(Some(Store::new(config)?)),
+            multicast: Arc::new(Some(Store::new(config)?)),
             other_fams: HashMap::new(),
+            as_path_index: None,
+            community_index: None,
+            select_best_path: false,
+            memory_usage: Default::default(),
+            tags: Default::default(),
+            tracking: AtomicBool::new(false),
+            tracked: Default::default(),
         })
     }
 
@@ -72,7 +103,325 @@ impl Rib {
             unicast: Arc::new(None),
             multicast: Arc::new(None),
             other_fams: HashMap::new(),
+            as_path_index: None,
+            community_index: None,
+            select_best_path: false,
+            memory_usage: Default::default(),
+            tags: Default::default(),
+            tracking: AtomicBool::new(false),
+            tracked: Default::default(),
+        }
+    }
+
+    /// Enable the secondary indexes selected in `config`.
+    ///
+    /// This should be done before any routes are inserted, as the indexes
+    /// are only updated on insert.
+    pub fn with_indexes(mut self, config: &IndexConfig) -> Self {
+        if config.as_path {
+            self.as_path_index = Some(AsPathIndex::default());
+        }
+        if config.communities {
+            self.community_index = Some(CommunityIndex::default());
+        }
+        self
+    }
+
+    /// Enable best path selection across the paths for each prefix.
+    pub fn with_best_path(mut self, enabled: bool) -> Self {
+        self.select_best_path = enabled;
+        self
+    }
+
+    pub fn best_path_enabled(&self) -> bool {
+        self.select_best_path
+    }
+
+    /// Account the memory use of this RIB also in `memory_usage`, the
+    /// usage of its unit.
+    pub fn with_memory_usage(mut self, memory_usage: Arc<MemoryUsage>) -> Self {
+        self.memory_usage = Arc::new(MemoryUsage::part_of(memory_usage));
+        self
+    }
+
+    pub fn memory_usage(&self) -> &MemoryUsage {
+        &self.memory_usage
+    }
+
+    /// Create a new empty physical RIB with the same settings as this one,
+    /// accounting its memory use in the same [`MemoryUsage`].
+    pub fn empty_like(&self) -> Result<Self, PrefixStoreError> {
+        let index_config = IndexConfig {
+            as_path: self.as_path_index.is_some(),
+            communities: self.community_index.is_some(),
+        };
+        let shard_config = (*self.unicast)
+            .as_ref()
+            .map(|store| store.config().clone())
+            .unwrap_or_default();
+        let mut rib = Rib::new_physical(&shard_config)?
+            .with_indexes(&index_config)
+            .with_best_path(self.select_best_path);
+        rib.memory_usage = self.memory_usage.clone();
+        Ok(rib)
+    }
+
+    /// Rebuild this RIB, leaving out withdrawn routes and the routes of the
+    /// `excluded` ingresses.
+    ///
+    /// As the store never releases memory, this is the only way to reclaim
+    /// it. Routes inserted into this RIB while the rebuild is in progress
+    /// are not carried over.
+    pub fn rebuild_without(
+        &self,
+        excluded: &HashSet<IngressId>,
+    ) -> Result<Self, String> {
+        self.memory_usage.reset();
+        let new = self.empty_like().map_err(|err| err.to_string())?;
+        let guard = &epoch::pin();
+        for (multicast, prefix_record) in self.prefix_records(guard) {
+            let prefix_record = prefix_record.map_err(|err| err.to_string())?;
+            for rec in prefix_record.meta {
+                if rec.status == RouteStatus::Withdrawn
+                    || excluded.contains(&rec.multi_uniq_id)
+                {
+                    continue;
+                }
+                let ingress_id = rec.multi_uniq_id;
+                new.insert_record(&prefix_record.prefix, multicast, rec)
+                    .map_err(|err| err.to_string())?;
+                self.copy_tags(&new, prefix_record.prefix, ingress_id);
+            }
+        }
+        Ok(new)
+    }
+
+    /// Start tracking the changes made to this RIB.
+    pub fn start_tracking(&self) {
+        *self.tracked.lock().unwrap() = Tracked::default();
+        self.tracking.store(true, SeqCst);
+    }
+
+    /// Stop tracking the changes made to this RIB, and return those made
+    /// since [`Rib::start_tracking`].
+    pub fn stop_tracking(&self) -> Tracked {
+        self.tracking.store(false, SeqCst);
+        std::mem::take(&mut *self.tracked.lock().unwrap())
+    }
+
+    fn track_prefix(&self, prefix: &Prefix, multicast: bool) {
+        if self.tracking.load(SeqCst) {
+            self.tracked.lock().unwrap().prefixes.insert((*prefix, multicast));
+        }
+    }
+
+    fn track_withdrawn(
+        &self,
+        ingress_id: IngressId,
+        specific_afisafi: Option<AfiSafiType>,
+    ) {
+        if self.tracking.load(SeqCst) {
+            self.tracked
+                .lock()
+                .unwrap()
+                .withdrawn
+                .push((ingress_id, specific_afisafi));
+        }
+    }
+
+    /// Copy the routes of this RIB for which `keep` returns true into
+    /// `target`, accounting their memory use in `usage` instead of in the
+    /// [`MemoryUsage`] of `target`.
+    ///
+    /// With `only` given, just the routes for those prefixes are copied,
+    /// replacing any routes for them already in `target`. Returns the number
+    /// of routes left out.
+    pub fn copy_into(
+        &self,
+        target: &Rib,
+        only: Option<&HashSet<(Prefix, bool)>>,
+        usage: &MemoryUsage,
+        mut keep: impl FnMut(&Prefix, bool, &Record<RotondaPaMap>) -> bool,
+    ) -> Result<usize, String> {
+        let guard = &epoch::pin();
+        let prefix_records: Box<dyn Iterator<Item = _>> = match only {
+            None => Box::new(self.prefix_records(guard)),
+            Some(prefixes) => {
+                Box::new(prefixes.iter().map(|&(prefix, multicast)| {
+                    let rec =
+                        self.exact_prefix_record(&prefix, multicast, guard);
+                    (multicast, rec)
+                }))
+            }
+        };
+
+        let mut dropped = 0;
+        for (multicast, prefix_record) in prefix_records {
+            let prefix_record = prefix_record.map_err(|err| err.to_string())?;
+            for rec in prefix_record.meta {
+                if !keep(&prefix_record.prefix, multicast, &rec) {
+                    dropped += 1;
+                    continue;
+                }
+                let ingress_id = rec.multi_uniq_id;
+                target
+                    .insert_record_with_usage(
+                        &prefix_record.prefix,
+                        multicast,
+                        rec,
+                        usage,
+                    )
+                    .map_err(|err| err.to_string())?;
+                self.copy_tags(target, prefix_record.prefix, ingress_id);
+            }
         }
+        Ok(dropped)
+    }
+
+    /// Replace the tags of a route in `target` by those it has in this RIB.
+    fn copy_tags(&self, target: &Rib, prefix: Prefix, ingress_id: IngressId) {
+        target.tags.set(
+            prefix,
+            ingress_id,
+            self.tags.get(prefix, ingress_id).unwrap_or_default(),
+        );
+    }
+
+    /// All records for `prefix`, withdrawn or not.
+    fn exact_prefix_record(
+        &self,
+        prefix: &Prefix,
+        multicast: bool,
+        guard: &epoch::Guard,
+    ) -> FatalResult<PrefixRecord<RotondaPaMap>> {
+        let store = match multicast {
+            true => (*self.multicast).as_ref(),
+            false => (*self.unicast).as_ref(),
+        };
+        let Some(store) = store else {
+            return Ok(PrefixRecord::new(*prefix, vec![]));
+        };
+        let match_options = MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_withdrawn: true,
+            include_less_specifics: false,
+            include_more_specifics: false,
+            mui: None,
+            include_history: IncludeHistory::None,
+        };
+        let res = store
+            .match_prefix(prefix, &match_options, guard)
+            .map_err(|_| rotonda_store::errors::FatalError)?;
+        Ok(PrefixRecord::new(*prefix, res.records))
+    }
+
+    /// All records for each of `prefixes`, withdrawn or not.
+    ///
+    /// Prefixes without any records, e.g. because they were removed since
+    /// they were listed, are left out.
+    pub fn exact_prefix_records(
+        &self,
+        prefixes: &[(bool, Prefix)],
+    ) -> Result<Vec<(bool, PrefixRecord<RotondaPaMap>)>, String> {
+        let guard = &epoch::pin();
+        let mut res = Vec::with_capacity(prefixes.len());
+        for &(multicast, prefix) in prefixes {
+            let rec = self
+                .exact_prefix_record(&prefix, multicast, guard)
+                .map_err(|err| err.to_string())?;
+            if !rec.meta.is_empty() {
+                res.push((multicast, rec));
+            }
+        }
+        Ok(res)
+    }
+
+    /// Apply the changes in `tracked`, made to `source` while it was being
+    /// copied into this RIB, to this RIB.
+    pub fn replay(
+        &self,
+        source: &Rib,
+        tracked: &Tracked,
+        usage: &MemoryUsage,
+        keep: impl FnMut(&Prefix, bool, &Record<RotondaPaMap>) -> bool,
+    ) -> Result<(), String> {
+        for &(ingress_id, specific_afisafi) in &tracked.withdrawn {
+            self.withdraw_for_ingress(ingress_id, specific_afisafi);
+        }
+        source.copy_into(self, Some(&tracked.prefixes), usage, keep)?;
+        Ok(())
+    }
+
+    /// Whether the RIB holds a path for the prefix of `val` learned from
+    /// `ingress_id`, withdrawn or not.
+    pub fn has_path(&self, val: &RotondaRoute, ingress_id: IngressId) -> bool {
+        let store = match val {
+            RotondaRoute::Ipv4Unicast(..) | RotondaRoute::Ipv6Unicast(..) => {
+                &self.unicast
+            }
+            RotondaRoute::Ipv4Multicast(..)
+            | RotondaRoute::Ipv6Multicast(..) => &self.multicast,
+        };
+        (**store).as_ref().is_some_and(|store| {
+            store.contains(&best_path::prefix_of(val), Some(ingress_id))
+        })
+    }
+
+    /// Replace the tags of the route for the prefix of `val` learned from
+    /// `ingress_id`.
+    pub fn set_tags(
+        &self,
+        val: &RotondaRoute,
+        ingress_id: IngressId,
+        tags: Tags,
+    ) {
+        self.tags.set(best_path::prefix_of(val), ingress_id, tags);
+    }
+
+    pub fn tags(&self) -> &Arc<RouteTags> {
+        &self.tags
+    }
+
+    pub fn as_path_index(&self) -> Option<&AsPathIndex> {
+        self.as_path_index.as_ref()
+    }
+
+    pub fn community_index(&self) -> Option<&CommunityIndex> {
+        self.community_index.as_ref()
+    }
+
+    /// The records for `prefix` learned from `ingress_id`, withdrawn or
+    /// not, from the unicast store followed by the multicast store.
+    pub fn route_records(
+        &self,
+        prefix: &Prefix,
+        ingress_id: IngressId,
+    ) -> Result<Vec<Record<RotondaPaMap>>, String> {
+        let options = MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_withdrawn: true,
+            include_less_specifics: false,
+            include_more_specifics: false,
+            mui: None,
+            include_history: IncludeHistory::None,
+        };
+        let guard = &epoch::pin();
+        let mut res = vec![];
+        for store in [&self.unicast, &self.multicast] {
+            let Some(store) = (**store).as_ref() else {
+                continue;
+            };
+            let found = store
+                .match_prefix(prefix, &options, guard)
+                .map_err(|err| err.to_string())?;
+            res.extend(
+                found
+                    .records
+                    .into_iter()
+                    .filter(|rec| rec.multi_uniq_id == ingress_id),
+            );
+        }
+        Ok(res)
     }
 
     // XXX LH perhaps this should become a characteristic of the Unit instead
@@ -164,6 +513,7 @@ impl Rib {
             // last seen attributes/nexthop for this {prefix,mui} combination,
             // while setting the status to Withdrawn.
             store.mark_mui_as_withdrawn_for_prefix(prefix, mui, 0)?;
+            self.track_prefix(prefix, multicast.0);
 
             // FIXME this is just to satisfy the function signature, but is
             // quite useless as-is.
@@ -182,13 +532,107 @@ impl Rib {
             val.rotonda_pamap().clone(),
         );
 
+        let path_new = !store.contains(prefix, Some(mui));
         let res = store.insert(
             prefix, pubrec, None, // Option<TBI>
+        )?;
+        self.track_prefix(prefix, multicast.0);
+        self.memory_usage.record_insert(
+            &res,
+            path_new,
+            val.rotonda_pamap().as_ref().len(),
         );
 
         //println!("store counters {}", store.prefixes_count());
 
-        res
+        self.update_indexes(prefix, mui, val.rotonda_pamap());
+
+        Ok(res)
+    }
+
+    /// Insert a [`Record`] as-is, bypassing the route status handling done
+    /// by [`Rib::insert`]. Used when restoring the RIB from a snapshot.
+    pub fn insert_record(
+        &self,
+        prefix: &Prefix,
+        multicast: bool,
+        record: Record<RotondaPaMap>,
+    ) -> Result<UpsertReport, PrefixStoreError> {
+        self.insert_record_with_usage(
+            prefix,
+            multicast,
+            record,
+            &self.memory_usage,
+        )
+    }
+
+    fn insert_record_with_usage(
+        &self,
+        prefix: &Prefix,
+        multicast: bool,
+        record: Record<RotondaPaMap>,
+        usage: &MemoryUsage,
+    ) -> Result<UpsertReport, PrefixStoreError> {
+        let store = match multicast {
+            true => (*self.multicast).as_ref(),
+            false => (*self.unicast).as_ref(),
+        }
+        .ok_or(PrefixStoreError::StoreNotReadyError)?;
+        self.update_indexes(prefix, record.multi_uniq_id, &record.meta);
+        let path_new = !store.contains(prefix, Some(record.multi_uniq_id));
+        let attributes_len = record.meta.as_ref().len();
+        let res = store.insert(prefix, record, None)?;
+        self.track_prefix(prefix, multicast);
+        usage.record_insert(&res, path_new, attributes_len);
+        Ok(res)
+    }
+
+    fn update_indexes(
+        &self,
+        prefix: &Prefix,
+        ingress_id: IngressId,
+        pamap: &RotondaPaMap,
+    ) {
+        if let Some(index) = &self.as_path_index {
+            index.insert(*prefix, ingress_id, pamap);
+        }
+        if let Some(index) = &self.community_index {
+            index.insert(*prefix, ingress_id, pamap);
+        }
+    }
+
+    /// Iterate over all prefixes in both the unicast and multicast stores.
+    ///
+    /// Every item is tagged with a bool that is true for prefixes from the
+    /// multicast store.
+    pub fn prefix_records<'a>(
+        &'a self,
+        guard: &'a epoch::Guard,
+    ) -> impl Iterator<Item = (bool, FatalResult<PrefixRecord<RotondaPaMap>>)>
+           + 'a {
+        let unicast = (*self.unicast)
+            .as_ref()
+            .into_iter()
+            .flat_map(move |store| store.prefixes_iter(guard))
+            .map(|rec| (false, rec));
+        let multicast = (*self.multicast)
+            .as_ref()
+            .into_iter()
+            .flat_map(move |store| store.prefixes_iter(guard))
+            .map(|rec| (true, rec));
+        unicast.chain(multicast)
+    }
+
+    /// List the prefixes in both the unicast and multicast stores, with
+    /// whether they are multicast.
+    pub fn prefixes(&self) -> Result<Vec<(bool, Prefix)>, String> {
+        let guard = &epoch::pin();
+        self.prefix_records(guard)
+            .map(|(multicast, rec)| {
+                rec.map(|rec| (multicast, rec.prefix))
+                    .map_err(|err| err.to_string())
+            })
+            .collect()
     }
 
     pub fn withdraw_for_ingress(
@@ -229,6 +673,8 @@ impl Rib {
         //     As such, perhaps we should leave the generation of
         //     those withdrawals to the very latest (most-East) point?
 
+        self.track_withdrawn(ingress_id, specific_afisafi);
+
         match specific_afisafi {
             None => {
                 // Set all address families to withdrawn.
@@ -303,6 +749,138 @@ impl Rib {
         }
     }
 
+    /// The records held for `ingress_id` that are not withdrawn, optionally
+    /// limited to a single address family.
+    ///
+    /// Every item is tagged with a bool that is true for prefixes from the
+    /// multicast store.
+    fn records_for_ingress(
+        &self,
+        ingress_id: IngressId,
+        specific_afisafi: Option<AfiSafiType>,
+    ) -> Result<Vec<(Prefix, bool, Record<RotondaPaMap>)>, String> {
+        let families = match specific_afisafi {
+            None => vec![
+                (false, true),
+                (false, false),
+                (true, true),
+                (true, false),
+            ],
+            Some(AfiSafiType::Ipv4Unicast) => vec![(false, true)],
+            Some(AfiSafiType::Ipv6Unicast) => vec![(false, false)],
+            Some(AfiSafiType::Ipv4Multicast) => vec![(true, true)],
+            Some(AfiSafiType::Ipv6Multicast) => vec![(true, false)],
+            Some(afisafi) => {
+                return Err(format!("no support for {afisafi:?} yet"))
+            }
+        };
+
+        // Stale records are not considered active by the store, so they
+        // would be left out if withdrawn records were not included.
+        let guard = &epoch::pin();
+        let mut res = vec![];
+        for (multicast, v4) in families {
+            let store = match multicast {
+                true => (*self.multicast).as_ref(),
+                false => (*self.unicast).as_ref(),
+            }
+            .ok_or(PrefixStoreError::StoreNotReadyError.to_string())?;
+            let prefix_records: Box<dyn Iterator<Item = _>> = match v4 {
+                true => Box::new(
+                    store.iter_records_for_mui_v4(ingress_id, true, guard),
+                ),
+                false => Box::new(
+                    store.iter_records_for_mui_v6(ingress_id, true, guard),
+                ),
+            };
+            for prefix_record in prefix_records {
+                let prefix_record =
+                    prefix_record.map_err(|err| err.to_string())?;
+                res.extend(
+                    prefix_record
+                        .meta
+                        .into_iter()
+                        .filter(|rec| {
+                            rec.multi_uniq_id == ingress_id
+                                && rec.status != RouteStatus::Withdrawn
+                        })
+                        .map(|rec| (prefix_record.prefix, multicast, rec)),
+                );
+            }
+        }
+        Ok(res)
+    }
+
+    /// Mark the active routes of `ingress_id` as stale.
+    ///
+    /// Stale routes are kept with status `InActive` until they are either
+    /// re-announced or withdrawn by [`Rib::expire_stale`]. Returns the
+    /// number of routes marked stale.
+    pub fn mark_stale(
+        &self,
+        ingress_id: IngressId,
+        specific_afisafi: Option<AfiSafiType>,
+    ) -> Result<usize, String> {
+        let mut marked = 0;
+        for (prefix, multicast, rec) in
+            self.records_for_ingress(ingress_id, specific_afisafi)?
+        {
+            if rec.status != RouteStatus::Active {
+                continue;
+            }
+            let stale = Record::new(
+                rec.multi_uniq_id,
+                rec.ltime,
+                RouteStatus::InActive,
+                rec.meta,
+            );
+            self.insert_record(&prefix, multicast, stale)
+                .map_err(|err| err.to_string())?;
+            marked += 1;
+        }
+        Ok(marked)
+    }
+
+    /// Withdraw the routes of `ingress_id` that are still stale. Returns the
+    /// number of routes withdrawn.
+    pub fn expire_stale(
+        &self,
+        ingress_id: IngressId,
+        specific_afisafi: Option<AfiSafiType>,
+    ) -> Result<usize, String> {
+        let mut expired = 0;
+        for (prefix, multicast, rec) in
+            self.records_for_ingress(ingress_id, specific_afisafi)?
+        {
+            if rec.status != RouteStatus::InActive {
+                continue;
+            }
+            self.withdraw_prefix(&prefix, multicast, ingress_id)?;
+            expired += 1;
+        }
+        Ok(expired)
+    }
+
+    /// Mark the route for `prefix` learned from `ingress_id` as withdrawn,
+    /// keeping its last seen attributes.
+    pub fn withdraw_prefix(
+        &self,
+        prefix: &Prefix,
+        multicast: bool,
+        ingress_id: IngressId,
+    ) -> Result<(), String> {
+        let store = match multicast {
+            true => (*self.multicast).as_ref(),
+            false => (*self.unicast).as_ref(),
+        }
+        .ok_or(PrefixStoreError::StoreNotReadyError.to_string())?;
+        store
+            .mark_mui_as_withdrawn_for_prefix(prefix, ingress_id, 0)
+            .map_err(|err| err.to_string())?;
+        self.track_prefix(prefix, multicast);
+        Ok(())
+    }
+
     pub fn match_prefix(
         &self,
         prefix: &Prefix,
@@ -336,6 +914,228 @@ impl Rib {
         Ok(unicast_res)
     }
 
+    /// Find the routes matching `search`.
+    ///
+    /// At most `limit` routes are returned, the returned bool signals
+    /// whether more routes matched. Uses the secondary indexes where
+    /// possible, and falls back to a full scan of the RIB otherwise.
+    pub fn search(
+        &self,
+        search: &RouteSearch,
+        limit: usize,
+    ) -> Result<(Vec<PrefixRecord<RotondaPaMap>>, bool), String> {
+        let mut res = Vec::new();
+        let mut remaining = limit;
+
+        // Keep the records in `meta`, returns false once the limit has been
+        // reached.
+        let mut push = |prefix: Prefix, mut meta: Vec<Record<RotondaPaMap>>| {
+            if meta.is_empty() {
+                return true;
+            }
+            if remaining == 0 {
+                return false;
+            }
+            let complete = meta.len() <= remaining;
+            meta.truncate(remaining);
+            remaining -= meta.len();
+            res.push(PrefixRecord::new(prefix, meta));
+            complete
+        };
+
+        let mut truncated = false;
+        if let Some(candidates) = self.search_candidates(search) {
+            let mut muis_by_prefix: BTreeMap<Prefix, HashSet<IngressId>> =
+                BTreeMap::new();
+            for (prefix, mui) in candidates {
+                muis_by_prefix.entry(prefix).or_default().insert(mui);
+            }
+            let options = MatchOptions {
+                match_type: MatchType::ExactMatch,
+                include_withdrawn: false,
+                include_less_specifics: false,
+                include_more_specifics: false,
+                mui: None,
+                include_history: IncludeHistory::None,
+            };
+            for (prefix, muis) in muis_by_prefix {
+                // The indexes are not updated on withdrawal, and only narrow
+                // down on one of the criteria, so check the records again.
+                let meta = self
+                    .match_prefix(&prefix, &options)?
+                    .records
+                    .into_iter()
+                    .filter(|rec| {
+                        rec.status != RouteStatus::Withdrawn
+                            && muis.contains(&rec.multi_uniq_id)
+                            && self.matches_search(search, &prefix, rec)
+                    })
+                    .collect();
+                if !push(prefix, meta) {
+                    truncated = true;
+                    break;
+                }
+            }
+        } else {
+            let guard = &epoch::pin();
+            for (_, rec) in self.prefix_records(guard) {
+                let rec = rec.map_err(|err| err.to_string())?;
+                let rec_prefix = rec.prefix;
+                let meta = rec
+                    .meta
+                    .into_iter()
+                    .filter(|rec| {
+                        rec.status != RouteStatus::Withdrawn
+                            && self.matches_search(search, &rec_prefix, rec)
+                    })
+                    .collect();
+                if !push(rec.prefix, meta) {
+                    truncated = true;
+                    break;
+                }
+            }
+        }
+
+        Ok((res, truncated))
+    }
+
+    /// List a page of the routes matching `search`, in the order of
+    /// `paging`.
+    ///
+    /// Returns the routes with their position and whether more routes
+    /// follow. As the RIB is not kept in any of the orders, this scans all
+    /// routes, keeping only the first ones after the position of `paging`,
+    /// and should be run as a blocking task.
+    pub fn page(
+        &self,
+        search: &RouteSearch,
+        paging: &Paging,
+    ) -> Result<(Vec<(RoutePosition, Record<RotondaPaMap>)>, bool), String>
+    {
+        // Keep one more than the limit, to know whether more follow.
+        let keep = paging.limit + 1;
+        let trim = |res: &mut Vec<(RoutePosition, Record<RotondaPaMap>)>| {
+            res.sort_by(|(a, _), (b, _)| paging.cmp(a, b));
+            res.truncate(keep);
+        };
+
+        let mut res = Vec::new();
+        let guard = &epoch::pin();
+        for (multicast, rec) in self.prefix_records(guard) {
+            let rec = rec.map_err(|err| err.to_string())?;
+            let prefix = rec.prefix;
+            for record in rec.meta {
+                if record.status == RouteStatus::Withdrawn
+                    || !self.matches_search(search, &prefix, &record)
+                {
+                    continue;
+                }
+                let pos = RoutePosition {
+                    prefix,
+                    ingress_id: record.multi_uniq_id,
+                    multicast,
+                };
+                if paging.after.as_ref().is_some_and(|after| {
+                    paging.cmp(&pos, after) != Ordering::Greater
+                }) {
+                    continue;
+                }
+                res.push((pos, record));
+                if res.len() >= 2 * keep {
+                    trim(&mut res);
+                }
+            }
+        }
+        trim(&mut res);
+        let more = res.len() > paging.limit;
+        res.truncate(paging.limit);
+        Ok((res, more))
+    }
+
+    /// Whether the route for `prefix` in `record` matches `search`,
+    /// including its tags.
+    fn matches_search(
+        &self,
+        search: &RouteSearch,
+        prefix: &Prefix,
+        record: &Record<RotondaPaMap>,
+    ) -> bool {
+        if !search.matches_record(record)
+            || search
+                .prefix
+                .is_some_and(|covering| !covering.covers(*prefix))
+        {
+            return false;
+        }
+        if search.tags.is_empty() {
+            return true;
+        }
+        let tags = self.tags.get(*prefix, record.multi_uniq_id);
+        search.tags.iter().all(|filter| filter.matches(tags.as_ref()))
+    }
+
+    /// Use the secondary indexes to find the routes that might match
+    /// `search`, or None if no index applies.
+    fn search_candidates(
+        &self,
+        search: &RouteSearch,
+    ) -> Option<HashSet<RouteKey>> {
+        if let Some(index) = &self.community_index {
+            let mut res: Option<HashSet<RouteKey>> = None;
+            for pattern in &search.communities {
+                let keys = index.matching(pattern);
+                res = Some(match res {
+                    None => keys,
+                    Some(res) => res.intersection(&keys).copied().collect(),
+                });
+            }
+            if res.is_some() {
+                return res;
+            }
+        }
+        if let (Some(index), Some(regex)) =
+            (&self.as_path_index, &search.as_path_regex)
+        {
+            return Some(index.matching(regex));
+        }
+        None
+    }
+
+    /// Select the best path for `prefix` among the paths that are not
+    /// withdrawn.
+    pub fn best_path(
+        &self,
+        prefix: &Prefix,
+        ingresses: &ingress::Register,
+    ) -> Result<Option<Record<RotondaPaMap>>, String> {
+        let options = MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_withdrawn: false,
+            include_less_specifics: false,
+            include_more_specifics: false,
+            mui: None,
+            include_history: IncludeHistory::None,
+        };
+        let mut records = self.match_prefix(prefix, &options)?.records;
+        Ok(best_path::select_best(&records, ingresses)
+            .map(|pos| records.swap_remove(pos)))
+    }
+
+    /// Count the routes that are not withdrawn, per ingress.
+    pub fn route_counts(&self) -> Result<HashMap<IngressId, usize>, String> {
+        let guard = &epoch::pin();
+        let mut res = HashMap::new();
+        for (_, rec) in self.prefix_records(guard) {
+            let rec = rec.map_err(|err| err.to_string())?;
+            for rec in rec.meta {
+                if rec.status != RouteStatus::Withdrawn {
+                    *res.entry(rec.multi_uniq_id).or_default() += 1;
+                }
+            }
+        }
+        Ok(res)
+    }
+
     pub fn match_ingress_id(
         &self,
         ingress_id: IngressId,
@@ -402,6 +1202,119 @@ pub struct StoredValue {
     i_time: u64,
 }
 
+// --- Paging ---------------------------------------------------------------
+
+/// How to page through the routes of the RIB.
+#[derive(Clone, Debug, Default)]
+pub struct Paging {
+    pub order: RouteOrder,
+    pub descending: bool,
+
+    /// Only list the routes after this one.
+    pub after: Option<RoutePosition>,
+
+    /// The maximum number of routes to list.
+    pub limit: usize,
+}
+
+impl Paging {
+    /// Sets the order from its name, `prefix` or `ingress_id`, prefixed
+    /// with `-` for descending order.
+    pub fn set_sort(&mut self, sort: &str) -> Result<(), String> {
+        (self.order, self.descending) = match sort {
+            "prefix" => (RouteOrder::Prefix, false),
+            "-prefix" => (RouteOrder::Prefix, true),
+            "ingress_id" => (RouteOrder::IngressId, false),
+            "-ingress_id" => (RouteOrder::IngressId, true),
+            other => {
+                return Err(format!(
+                    "Unrecognized sort order '{other}', expected prefix, \
+                    -prefix, ingress_id or -ingress_id"
+                ))
+            }
+        };
+        Ok(())
+    }
+
+    /// Compares the positions of two routes in the order of the paging.
+    fn cmp(&self, a: &RoutePosition, b: &RoutePosition) -> Ordering {
+        let res = match self.order {
+            RouteOrder::Prefix => (a.prefix, a.ingress_id, a.multicast)
+                .cmp(&(b.prefix, b.ingress_id, b.multicast)),
+            RouteOrder::IngressId => (a.ingress_id, a.prefix, a.multicast)
+                .cmp(&(b.ingress_id, b.prefix, b.multicast)),
+        };
+        if self.descending {
+            res.reverse()
+        } else {
+            res
+        }
+    }
+}
+
+/// The order in which routes are listed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RouteOrder {
+    /// By prefix, then by ingress.
+    #[default]
+    Prefix,
+
+    /// By ingress, then by prefix.
+    IngressId,
+}
+
+/// The position of a route in a listing of the RIB.
+///
+/// A position is handed to clients as the cursor to continue a listing
+/// from, written as `<prefix>,<ingress_id>` with `,m` appended for routes
+/// in the multicast store.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RoutePosition {
+    pub prefix: Prefix,
+    pub ingress_id: IngressId,
+    pub multicast: bool,
+}
+
+impl fmt::Display for RoutePosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.prefix, self.ingress_id)?;
+        if self.multicast {
+            write!(f, ",m")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RoutePosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid cursor '{s}'");
+        let mut parts = s.split(',');
+        let prefix = parts
+            .next()
+            .and_then(|prefix| Prefix::from_str(prefix).ok())
+            .ok_or_else(invalid)?;
+        let ingress_id = parts
+            .next()
+            .and_then(|id| id.parse::<IngressId>().ok())
+            .ok_or_else(invalid)?;
+        let multicast = match parts.next() {
+            None => false,
+            Some("m") => true,
+            Some(_) => return Err(invalid()),
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            prefix,
+            ingress_id,
+            multicast,
+        })
+    }
+}
+
 // --- Route related helpers ------------------------------------------------------------------------------------------
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
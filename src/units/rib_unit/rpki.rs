@@ -1,13 +1,17 @@
 //! RPKI related types and handlers for the Rib.
 //!
 
-use std::{collections::HashSet, sync::RwLock};
+use std::{collections::{HashMap, HashSet}, fmt, str::FromStr, sync::{Arc, Mutex, RwLock}};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use inetnum::{addr::Prefix, asn::Asn};
 use log::warn;
-use rotonda_store::{match_options::{IncludeHistory, MatchOptions, MatchType}, rib::{config::MemoryOnlyConfig, StarCastRib}};
+use rotonda_store::{match_options::{IncludeHistory, MatchOptions, MatchType}, prefix_record::{Record, RouteStatus}, rib::{config::MemoryOnlyConfig, StarCastRib}};
+use rpki::rtr::{client::PayloadError, payload::{Action, Payload, RouteOrigin}};
 use serde::Serialize;
 
+use crate::metrics::{self, Metric, MetricType, MetricUnit};
+
 
 /// RPKI related information for individual routes
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
@@ -65,6 +69,24 @@ pub enum RovStatus {
     Invalid,
 }
 
+impl FromStr for RovStatus {
+    type Err = String;
+
+    /// Parses the status as named in the HTTP API.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "valid" => Ok(RovStatus::Valid),
+            "invalid" => Ok(RovStatus::Invalid),
+            "not-found" => Ok(RovStatus::NotFound),
+            "not-checked" => Ok(RovStatus::NotChecked),
+            other => Err(format!(
+                "Unrecognized rpki status '{other}', expected valid, \
+                invalid, not-found or not-checked"
+            )),
+        }
+    }
+}
+
 
 /// Route Origin Validation status update for a route
 #[derive(Copy, Clone, Debug)]
@@ -152,9 +174,35 @@ pub struct RtrCache {
     pub router_keys: RwLock<HashSet<rpki::rtr::payload::RouterKey>>,
     pub aspas: RwLock<HashSet<rpki::rtr::payload::Aspa>>,
     pub vrps: VrpStore,
+
+    /// The number of validations by Roto scripts, by result.
+    validations: [AtomicU64; 3],
 }
 
 impl RtrCache {
+    const VALIDATIONS_METRIC: Metric = Metric::new(
+        "rtr_rov_validations",
+        "the number of route origin validations by roto scripts",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+
+    /// The results counted in `validations`, in order.
+    const RESULTS: [RovStatus; 3] =
+        [RovStatus::Valid, RovStatus::Invalid, RovStatus::NotFound];
+
+    /// Validates the origin of a prefix on behalf of a Roto script.
+    ///
+    /// This is [`check_rov`][Self::check_rov], counting the result.
+    pub fn validate(&self, prefix: &Prefix, origin: Asn) -> RovStatus {
+        let status = self.check_rov(prefix, origin);
+        if let Some(index) = Self::RESULTS.iter().position(|s| *s == status) {
+            self.validations[index].fetch_add(1, Ordering::Relaxed);
+        }
+        status
+    }
+
+
     pub fn check_rov(&self, prefix: &Prefix, origin: Asn) -> RovStatus {
         #[allow(unused_assignments)]
         let mut covered = false;
@@ -228,6 +276,170 @@ impl RtrCache {
             (false, false) => RovStatus::NotFound,
         }
     }
+
+    /// Returns whether `payload` is in the cache.
+    pub fn contains(&self, payload: &Payload) -> bool {
+        match payload {
+            Payload::Origin(origin) => {
+                self.route_origins.read().unwrap().contains(origin)
+            }
+            Payload::RouterKey(key) => {
+                self.router_keys.read().unwrap().contains(key)
+            }
+            Payload::Aspa(aspa) => self.aspas.read().unwrap().contains(aspa),
+        }
+    }
+
+    /// Returns the number of route origins, router keys and ASPAs.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        (
+            self.route_origins.read().unwrap().len(),
+            self.router_keys.read().unwrap().len(),
+            self.aspas.read().unwrap().len(),
+        )
+    }
+
+    /// Applies the changes of an RTR serial update.
+    ///
+    /// The update is checked as a whole before anything is changed, so that
+    /// the cache is left untouched if the update announces a payload that
+    /// is present already or withdraws one that is not.
+    pub fn apply_delta(
+        &self,
+        changes: &[(Action, Payload)],
+    ) -> Result<(), PayloadError> {
+        let mut pending: HashMap<&Payload, bool> = HashMap::new();
+        for (action, payload) in changes {
+            let present = pending
+                .get(payload)
+                .copied()
+                .unwrap_or_else(|| self.contains(payload));
+            match (action, present) {
+                (Action::Announce, true) => {
+                    return Err(PayloadError::DuplicateAnnounce)
+                }
+                (Action::Withdraw, false) => {
+                    return Err(PayloadError::UnknownWithdraw)
+                }
+                (Action::Announce, false) => pending.insert(payload, true),
+                (Action::Withdraw, true) => pending.insert(payload, false),
+            };
+        }
+        for (action, payload) in changes {
+            self.apply(*action, payload);
+        }
+        Ok(())
+    }
+
+    /// Replaces the content of the cache with the payloads of an RTR reset
+    /// update.
+    ///
+    /// Only the differences with the current content are applied, so that
+    /// payloads present before and after are never missing in between.
+    pub fn apply_reset(&self, payloads: &HashSet<Payload>) {
+        let current = {
+            let origins = self.route_origins.read().unwrap();
+            let keys = self.router_keys.read().unwrap();
+            let aspas = self.aspas.read().unwrap();
+            origins
+                .iter()
+                .map(|o| Payload::Origin(*o))
+                .chain(keys.iter().cloned().map(Payload::RouterKey))
+                .chain(aspas.iter().cloned().map(Payload::Aspa))
+                .collect::<Vec<_>>()
+        };
+        for payload in &current {
+            if !payloads.contains(payload) {
+                self.apply(Action::Withdraw, payload);
+            }
+        }
+        for payload in payloads {
+            if !self.contains(payload) {
+                self.apply(Action::Announce, payload);
+            }
+        }
+    }
+
+    fn apply(&self, action: Action, payload: &Payload) {
+        match (action, payload) {
+            (Action::Announce, Payload::Origin(origin)) => {
+                if self.route_origins.write().unwrap().insert(*origin) {
+                    self.update_max_lens(origin, action);
+                }
+            }
+            (Action::Withdraw, Payload::Origin(origin)) => {
+                if self.route_origins.write().unwrap().remove(origin) {
+                    self.update_max_lens(origin, action);
+                }
+            }
+            (Action::Announce, Payload::RouterKey(key)) => {
+                self.router_keys.write().unwrap().insert(key.clone());
+            }
+            (Action::Withdraw, Payload::RouterKey(key)) => {
+                self.router_keys.write().unwrap().remove(key);
+            }
+            (Action::Announce, Payload::Aspa(aspa)) => {
+                self.aspas.write().unwrap().insert(aspa.clone());
+            }
+            (Action::Withdraw, Payload::Aspa(aspa)) => {
+                self.aspas.write().unwrap().remove(aspa);
+            }
+        }
+    }
+
+    /// Adds or removes the max length of `origin` in the VRP store.
+    fn update_max_lens(&self, origin: &RouteOrigin, action: Action) {
+        // Conversions needed as we use inetnum, rpki-rs does not.
+        let asn = Asn::from_u32(origin.asn.into_u32());
+        let Ok(prefix) = Prefix::new(
+            origin.prefix.addr(),
+            origin.prefix.prefix_len(),
+        ) else {
+            warn!("invalid VRP prefix {}", origin.prefix.prefix());
+            return;
+        };
+        let match_options = MatchOptions {
+            match_type: MatchType::ExactMatch,
+            include_withdrawn: false,
+            include_less_specifics: false,
+            include_more_specifics: false,
+            mui: Some(u32::from(asn)),
+            include_history: IncludeHistory::None,
+        };
+        let mut max_lens = {
+            let guard = &rotonda_store::epoch::pin();
+            match self.vrps.match_prefix(&prefix, &match_options, guard) {
+                Ok(res) => res
+                    .records
+                    .first()
+                    .map(|r| r.meta.clone())
+                    .unwrap_or_default(),
+                Err(e) => {
+                    warn!("could not lookup {}: {}", &prefix, e);
+                    return;
+                }
+            }
+        };
+
+        let max_len = origin.prefix.resolved_max_len();
+        match action {
+            Action::Announce => max_lens.push(max_len),
+            Action::Withdraw => {
+                if let Some(pos) = max_lens.iter().position(|m| *m == max_len) {
+                    max_lens.remove(pos);
+                }
+            }
+        }
+        let record = Record {
+            multi_uniq_id: u32::from(asn),
+            ltime: 0,
+            status: RouteStatus::Active,
+            meta: max_lens,
+        };
+        if let Err(e) = self.vrps.insert(&prefix, record, None) {
+            warn!("could not update VRPs for {}: {}", &prefix, e);
+        }
+    }
 }
 
 
@@ -238,12 +450,158 @@ impl Default for RtrCache {
             router_keys: Default::default(),
             aspas: Default::default(),
             vrps: VrpStore::try_default().unwrap(),
+            validations: Default::default(),
         }
     }
 }
 
+impl metrics::Source for RtrCache {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        target.append(&Self::VALIDATIONS_METRIC, Some(unit_name), |records| {
+            for (status, count) in Self::RESULTS.iter().zip(&self.validations)
+            {
+                let result = match status {
+                    RovStatus::Valid => "valid",
+                    RovStatus::Invalid => "invalid",
+                    _ => "not-found",
+                };
+                records.label_value(
+                    &[("result", result)],
+                    count.load(Ordering::Relaxed),
+                );
+            }
+        });
+    }
+}
 
 
+/// The RTR caches kept by the RTR client units, by unit name.
+///
+/// Units with a roto filter can validate routes against the VRPs of an RTR
+/// client unit by naming it in their `rtr_cache` setting. A cache is
+/// created empty by whichever unit asks for it first, so units do not
+/// depend on the order in which they are started.
+#[derive(Default)]
+pub struct RtrCaches {
+    caches: Mutex<HashMap<String, Arc<RtrCache>>>,
+}
+
+impl RtrCaches {
+    /// Returns the cache of the RTR client unit named `name`.
+    pub fn get(&self, name: &str) -> Arc<RtrCache> {
+        self.caches
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+impl fmt::Debug for RtrCaches {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let caches = self.caches.lock().unwrap();
+        f.debug_list().entries(caches.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rpki::resources::addr::MaxLenPrefix;
+
+    use super::*;
+
+    fn origin(prefix: &str, asn: u32) -> Payload {
+        Payload::Origin(RouteOrigin::new(
+            MaxLenPrefix::from_str(prefix).unwrap(),
+            asn.into(),
+        ))
+    }
+
+    fn rov(cache: &RtrCache, prefix: &str, asn: u32) -> RovStatus {
+        cache.check_rov(&Prefix::from_str(prefix).unwrap(), Asn::from(asn))
+    }
+
+    #[test]
+    fn vrp_set_follows_resets_and_deltas() {
+        let cache = RtrCache::default();
+        cache.apply_reset(&HashSet::from([
+            origin("192.0.2.0/24", 65000),
+            origin("198.51.100.0/22-24", 65001),
+        ]));
+        assert_eq!(cache.counts(), (2, 0, 0));
+        assert_eq!(rov(&cache, "192.0.2.0/24", 65000), RovStatus::Valid);
+        assert_eq!(rov(&cache, "198.51.101.0/24", 65000), RovStatus::Invalid);
+        assert_eq!(rov(&cache, "203.0.113.0/24", 65000), RovStatus::NotFound);
+
+        // Rejected as a whole, leaving the cache as it was.
+        assert!(matches!(
+            cache.apply_delta(&[
+                (Action::Announce, origin("203.0.113.0/24", 65002)),
+                (Action::Withdraw, origin("192.0.2.0/24", 65999)),
+            ]),
+            Err(PayloadError::UnknownWithdraw)
+        ));
+        assert!(matches!(
+            cache.apply_delta(&[(
+                Action::Announce,
+                origin("192.0.2.0/24", 65000)
+            )]),
+            Err(PayloadError::DuplicateAnnounce)
+        ));
+        assert_eq!(rov(&cache, "203.0.113.0/24", 65002), RovStatus::NotFound);
+
+        cache
+            .apply_delta(&[
+                (Action::Announce, origin("203.0.113.0/24", 65002)),
+                (Action::Withdraw, origin("192.0.2.0/24", 65000)),
+            ])
+            .unwrap();
+        assert_eq!(rov(&cache, "203.0.113.0/24", 65002), RovStatus::Valid);
+        assert_eq!(rov(&cache, "192.0.2.0/24", 65000), RovStatus::NotFound);
+
+        cache.apply_reset(&HashSet::from([origin("192.0.2.0/24", 65000)]));
+        assert_eq!(cache.counts(), (1, 0, 0));
+        assert_eq!(rov(&cache, "192.0.2.0/24", 65000), RovStatus::Valid);
+        assert_eq!(rov(&cache, "203.0.113.0/24", 65002), RovStatus::NotFound);
+        assert_eq!(
+            rov(&cache, "198.51.100.0/24", 65001),
+            RovStatus::NotFound
+        );
+    }
+
+    #[test]
+    fn validations_are_counted() {
+        let cache = Arc::new(RtrCache::default());
+        cache.apply_reset(&HashSet::from([origin("192.0.2.0/24", 65000)]));
+        let validate = |prefix, asn| {
+            cache.validate(&Prefix::from_str(prefix).unwrap(), Asn::from(asn))
+        };
+        assert_eq!(validate("192.0.2.0/24", 65000), RovStatus::Valid);
+        assert_eq!(validate("192.0.2.0/24", 65001), RovStatus::Invalid);
+        assert_eq!(validate("192.0.2.0/25", 65000), RovStatus::Invalid);
+        assert_eq!(validate("203.0.113.0/24", 65000), RovStatus::NotFound);
+
+        // Checks by the RIB itself are not counted
+        rov(&cache, "192.0.2.0/24", 65000);
+
+        let metrics = crate::tests::util::internal::get_testable_metrics_snapshot(
+            &cache,
+        );
+        let count = |result| {
+            metrics.with_label::<u64>(
+                "rtr_rov_validations",
+                ("result", result),
+            )
+        };
+        assert_eq!(count("valid"), 1);
+        assert_eq!(count("invalid"), 2);
+        assert_eq!(count("not-found"), 1);
+    }
+}
+
 
 //// TODO
 //
@@ -0,0 +1,290 @@
+//! Spreading the RIB over multiple stores.
+//!
+//! Updates arrive at the RIB unit from all its upstream units at once, and
+//! are inserted concurrently. With many writers they all contend for the
+//! same store. Sharding splits the RIB into multiple independent stores,
+//! either by a hash of the prefix, or by address family, so that writers
+//! inserting different prefixes mostly touch different stores.
+//!
+//! Every prefix lives in exactly one shard. A query for a prefix asks all
+//! shards, and merges their answers into the one a single store would have
+//! given: the records of the longest matching prefix, and the less and
+//! more specifics found in any of the shards. Iterating over the RIB visits
+//! one shard after the other, so with sharding by prefix hash the prefixes
+//! come in no particular order.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use inetnum::addr::Prefix;
+use rotonda_store::{
+    epoch,
+    errors::{FatalResult, PrefixStoreError},
+    match_options::{MatchOptions, QueryResult},
+    prefix_record::{Meta, PrefixRecord, Record, RecordSet},
+    rib::{config::MemoryOnlyConfig, StarCastRib},
+    stats::UpsertReport,
+};
+use serde::Deserialize;
+
+use crate::payload::RotondaPaMap;
+
+type Store = StarCastRib<RotondaPaMap, MemoryOnlyConfig>;
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ShardConfig {
+    /// How to assign prefixes to shards.
+    #[serde(default)]
+    pub by: ShardBy,
+
+    /// The number of shards when sharding by prefix. Sharding by address
+    /// family always uses one shard for IPv4 and one for IPv6.
+    #[serde(default = "ShardConfig::default_count")]
+    pub count: usize,
+}
+
+impl ShardConfig {
+    fn default_count() -> usize {
+        4
+    }
+
+    /// The number of stores to create.
+    fn shards(&self) -> usize {
+        match self.by {
+            ShardBy::Prefix => self.count.max(1),
+            ShardBy::Afi => 2,
+        }
+    }
+}
+
+impl Default for ShardConfig {
+    /// A single shard, i.e. no sharding at all.
+    fn default() -> Self {
+        Self {
+            by: ShardBy::Prefix,
+            count: 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShardBy {
+    /// By a hash of the prefix.
+    #[default]
+    Prefix,
+
+    /// IPv4 prefixes in one shard, IPv6 prefixes in the other.
+    Afi,
+}
+
+//------------ ShardedStore --------------------------------------------------
+
+/// A store made up of one or more shards.
+///
+/// Offers the store methods the RIB uses, so that it can be used in place
+/// of a single store.
+pub struct ShardedStore {
+    config: ShardConfig,
+    shards: Vec<Store>,
+}
+
+impl ShardedStore {
+    pub fn new(config: &ShardConfig) -> Result<Self, PrefixStoreError> {
+        let shards = (0..config.shards())
+            .map(|_| Store::try_default())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            config: config.clone(),
+            shards,
+        })
+    }
+
+    pub fn config(&self) -> &ShardConfig {
+        &self.config
+    }
+
+    /// The shard that holds `prefix`.
+    fn shard_for(&self, prefix: &Prefix) -> &Store {
+        let index = match self.config.by {
+            _ if self.shards.len() == 1 => 0,
+            ShardBy::Afi => usize::from(!prefix.is_v4()),
+            ShardBy::Prefix => {
+                let mut hasher = DefaultHasher::new();
+                prefix.hash(&mut hasher);
+                (hasher.finish() % self.shards.len() as u64) as usize
+            }
+        };
+        &self.shards[index]
+    }
+
+    pub fn contains(&self, prefix: &Prefix, mui: Option<u32>) -> bool {
+        self.shard_for(prefix).contains(prefix, mui)
+    }
+
+    pub fn insert(
+        &self,
+        prefix: &Prefix,
+        record: Record<RotondaPaMap>,
+        update_path_selections: Option<<RotondaPaMap as Meta>::TBI>,
+    ) -> Result<UpsertReport, PrefixStoreError> {
+        self.shard_for(prefix)
+            .insert(prefix, record, update_path_selections)
+    }
+
+    pub fn mark_mui_as_withdrawn_for_prefix(
+        &self,
+        prefix: &Prefix,
+        mui: u32,
+        ltime: u64,
+    ) -> Result<(), PrefixStoreError> {
+        self.shard_for(prefix)
+            .mark_mui_as_withdrawn_for_prefix(prefix, mui, ltime)
+    }
+
+    pub fn mark_mui_as_withdrawn(
+        &self,
+        mui: u32,
+    ) -> Result<(), PrefixStoreError> {
+        self.for_all_shards(|shard| shard.mark_mui_as_withdrawn(mui))
+    }
+
+    pub fn mark_mui_as_withdrawn_v4(
+        &self,
+        mui: u32,
+    ) -> Result<(), PrefixStoreError> {
+        self.for_all_shards(|shard| shard.mark_mui_as_withdrawn_v4(mui))
+    }
+
+    pub fn mark_mui_as_withdrawn_v6(
+        &self,
+        mui: u32,
+    ) -> Result<(), PrefixStoreError> {
+        self.for_all_shards(|shard| shard.mark_mui_as_withdrawn_v6(mui))
+    }
+
+    /// Apply `op` to every shard, even if it fails for some, returning the
+    /// first error.
+    fn for_all_shards(
+        &self,
+        op: impl Fn(&Store) -> Result<(), PrefixStoreError>,
+    ) -> Result<(), PrefixStoreError> {
+        let mut res = Ok(());
+        for shard in &self.shards {
+            let shard_res = op(shard);
+            if res.is_ok() {
+                res = shard_res;
+            }
+        }
+        res
+    }
+
+    #[cfg(test)]
+    pub fn prefixes_count(&self) -> rotonda_store::stats::UpsertCounters {
+        let mut count = self.shards[0].prefixes_count();
+        for shard in &self.shards[1..] {
+            count += shard.prefixes_count();
+        }
+        count
+    }
+
+    pub fn prefixes_iter<'a>(
+        &'a self,
+        guard: &'a epoch::Guard,
+    ) -> impl Iterator<Item = FatalResult<PrefixRecord<RotondaPaMap>>> + 'a
+    {
+        self.shards
+            .iter()
+            .flat_map(move |shard| shard.prefixes_iter(guard))
+    }
+
+    pub fn iter_records_for_mui_v4<'a>(
+        &'a self,
+        mui: u32,
+        include_withdrawn: bool,
+        guard: &'a epoch::Guard,
+    ) -> impl Iterator<Item = FatalResult<PrefixRecord<RotondaPaMap>>> + 'a
+    {
+        self.shards.iter().flat_map(move |shard| {
+            shard.iter_records_for_mui_v4(mui, include_withdrawn, guard)
+        })
+    }
+
+    pub fn iter_records_for_mui_v6<'a>(
+        &'a self,
+        mui: u32,
+        include_withdrawn: bool,
+        guard: &'a epoch::Guard,
+    ) -> impl Iterator<Item = FatalResult<PrefixRecord<RotondaPaMap>>> + 'a
+    {
+        self.shards.iter().flat_map(move |shard| {
+            shard.iter_records_for_mui_v6(mui, include_withdrawn, guard)
+        })
+    }
+
+    /// Query all shards for `search_pfx`, merging the results.
+    pub fn match_prefix(
+        &self,
+        search_pfx: &Prefix,
+        options: &MatchOptions,
+        guard: &epoch::Guard,
+    ) -> FatalResult<QueryResult<RotondaPaMap>> {
+        // Sharding by address family keeps all prefixes related to the
+        // search prefix together.
+        if self.shards.len() == 1 || self.config.by == ShardBy::Afi {
+            return self
+                .shard_for(search_pfx)
+                .match_prefix(search_pfx, options, guard);
+        }
+
+        // Every shard reports the less and more specifics of the search
+        // prefix it holds, whether it holds the search prefix or not. The
+        // records are those of the longest match across all shards, which
+        // for an exact match can only be found in the shard owning it.
+        let mut res: Option<QueryResult<RotondaPaMap>> = None;
+        let mut less_specifics =
+            options.include_less_specifics.then(Vec::new);
+        let mut more_specifics =
+            options.include_more_specifics.then(Vec::new);
+        for shard in &self.shards {
+            let mut shard_res =
+                shard.match_prefix(search_pfx, options, guard)?;
+            if let (Some(merged), Some(found)) =
+                (&mut less_specifics, shard_res.less_specifics.take())
+            {
+                merged.extend(found.v4.into_iter().chain(found.v6));
+            }
+            if let (Some(merged), Some(found)) =
+                (&mut more_specifics, shard_res.more_specifics.take())
+            {
+                merged.extend(found.v4.into_iter().chain(found.v6));
+            }
+            let longer = match (&res, shard_res.prefix) {
+                (None, _) => true,
+                (Some(current), Some(found)) => current
+                    .prefix
+                    .is_none_or(|current| found.len() > current.len()),
+                (Some(_), None) => false,
+            };
+            if longer {
+                res = Some(shard_res);
+            }
+        }
+
+        // There is always at least one shard.
+        let mut res = res.ok_or(rotonda_store::errors::FatalError)?;
+        res.less_specifics = less_specifics.map(|mut records| {
+            records.sort_by_key(|rec| std::cmp::Reverse(rec.prefix.len()));
+            records.into_iter().collect::<RecordSet<_>>()
+        });
+        res.more_specifics = more_specifics.map(|mut records| {
+            records.sort_by_key(|rec| rec.prefix);
+            records.into_iter().collect::<RecordSet<_>>()
+        });
+        Ok(res)
+    }
+}
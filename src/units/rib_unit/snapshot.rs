@@ -0,0 +1,709 @@
+//! Reading and writing snapshots of the RIB contents.
+//!
+//! Snapshots can be written periodically by the RIB unit (see
+//! [`SnapshotConfig`]) and loaded again on startup (see
+//! [`BootstrapConfig`]).
+//!
+//! A snapshot is either an MRT TABLE_DUMP_V2 file as produced by route
+//! collectors, or a file in the Rotonda native format. The native format is
+//! a straight serialization of the records in the prefix store:
+//!
+//! ```text
+//! magic "RTNDRIB\0" | version (u8)
+//! record*           | END_OF_RECORDS (u8)
+//! ingress count (u32) | ingress*
+//! ```
+//!
+//! where a record is `kind (u8) | prefix len (u8) | addr (4 or 16 octets) |
+//! mui (u32) | ltime (u64) | status (u8) | pamap len (u32) | pamap`, and an
+//! ingress is `mui (u32) | addr | asn`. All integers are big-endian.
+//!
+//! The ingress table describes the peers the MUIs in the records referred to
+//! at the time the snapshot was taken. When loading a snapshot, every MUI is
+//! mapped onto a freshly registered [`IngressId`], as the original IDs will
+//! likely have been handed out to other sessions by then.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bzip2::bufread::BzDecoder;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use flate2::{bufread::GzDecoder, write::GzEncoder, Compression};
+use inetnum::{addr::Prefix, asn::Asn};
+use log::debug;
+use rotonda_store::{
+    epoch,
+    prefix_record::{Record, RouteStatus},
+};
+use routecore::{
+    bgp::{
+        message::PduParseInfo, path_attributes::OwnedPathAttributes,
+        types::AfiSafiType,
+    },
+    mrt::MrtFile,
+};
+use serde::Deserialize;
+use serde_with::serde_as;
+
+use crate::{
+    config::ConfigPath,
+    ingress::{self, IngressId, IngressInfo},
+    payload::{RotondaPaMap, RotondaRoute},
+    roto_runtime::types::Provenance,
+};
+
+use super::rib::Rib;
+
+const MAGIC: &[u8; 8] = b"RTNDRIB\0";
+const VERSION: u8 = 1;
+const END_OF_RECORDS: u8 = 0xff;
+
+/// File extension of native snapshots written by the RIB unit.
+const SNAPSHOT_EXTENSION: &str = "rtrib";
+
+/// Format of the time a snapshot was taken, as included in its file name.
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+const KIND_IPV4_UNICAST: u8 = 0;
+const KIND_IPV6_UNICAST: u8 = 1;
+const KIND_IPV4_MULTICAST: u8 = 2;
+const KIND_IPV6_MULTICAST: u8 = 3;
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotFormat {
+    /// The Rotonda native snapshot format.
+    #[default]
+    Native,
+
+    /// An MRT TABLE_DUMP_V2 RIB dump.
+    Mrt,
+}
+
+/// Snapshot to load into the RIB before any live updates are accepted.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct BootstrapConfig {
+    /// The snapshot file to load. Files ending in `.gz` or `.bz2` are
+    /// decompressed while loading.
+    pub path: ConfigPath,
+
+    /// The format of the snapshot file.
+    #[serde(default)]
+    pub format: SnapshotFormat,
+
+    /// Withdraw the bootstrapped routes after this many seconds, by which
+    /// time the live feeds are expected to have reconverged. When not set,
+    /// the bootstrapped routes are kept until overwritten or withdrawn.
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    #[serde(default)]
+    pub expire_after_secs: Option<Duration>,
+}
+
+/// Periodic snapshots of the RIB contents in the native format.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct SnapshotConfig {
+    /// The directory to write the snapshots to. It is created if it does
+    /// not exist.
+    pub directory: ConfigPath,
+
+    /// How often to write a snapshot, in seconds.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "SnapshotConfig::default_interval_secs")]
+    pub interval_secs: Duration,
+
+    /// The number of snapshots to keep. Older snapshots written by this
+    /// unit are removed after a new one is written. 0 keeps all snapshots.
+    #[serde(default = "SnapshotConfig::default_retention")]
+    pub retention: usize,
+
+    /// Whether to gzip the snapshots.
+    #[serde(default)]
+    pub compress: bool,
+}
+
+impl SnapshotConfig {
+    pub fn default_interval_secs() -> Duration {
+        Duration::from_secs(3600)
+    }
+
+    pub fn default_retention() -> usize {
+        24
+    }
+}
+
+//------------ Loading -------------------------------------------------------
+
+/// Load the snapshot at `path` into `rib`.
+///
+/// Every peer found in the snapshot is registered as a new ingress with
+/// `parent` as its parent ingress. Returns the number of routes loaded.
+pub fn load(
+    path: &Path,
+    format: SnapshotFormat,
+    rib: &Rib,
+    ingresses: &ingress::Register,
+    parent: IngressId,
+) -> io::Result<usize> {
+    let reader = open(path)?;
+    match format {
+        SnapshotFormat::Native => {
+            read_native(reader, rib, ingresses, parent, path)
+        }
+        SnapshotFormat::Mrt => read_mrt(reader, rib, ingresses, parent, path),
+    }
+}
+
+pub fn open(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    let file = BufReader::new(File::open(path)?);
+    match path.extension().and_then(OsStr::to_str) {
+        Some("gz") => Ok(Box::new(BufReader::new(GzDecoder::new(file)))),
+        Some("bz2") => Ok(Box::new(BufReader::new(BzDecoder::new(file)))),
+        _ => Ok(Box::new(file)),
+    }
+}
+
+pub fn read_native<R: Read>(
+    reader: R,
+    rib: &Rib,
+    ingresses: &ingress::Register,
+    parent: IngressId,
+    filename: &Path,
+) -> io::Result<usize> {
+    let mut mui_map: HashMap<u32, IngressId> = HashMap::new();
+    let mut routes = 0;
+
+    let peers = read_native_records(reader, |prefix, multicast, record| {
+        let ingress_id = *mui_map.entry(record.multi_uniq_id).or_insert_with(|| {
+            let id = ingresses.register();
+            ingresses.update_info(
+                id,
+                IngressInfo::new()
+                    .with_parent(parent)
+                    .with_filename(filename.to_path_buf()),
+            );
+            id
+        });
+
+        rib.insert_record(
+            &prefix,
+            multicast,
+            Record::new(ingress_id, record.ltime, record.status, record.meta),
+        )
+        .map_err(|e| io::Error::other(e.to_string()))?;
+        routes += 1;
+        Ok(())
+    })?;
+
+    for peer in peers {
+        if let Some(&id) = mui_map.get(&peer.mui) {
+            let mut info = IngressInfo::new();
+            if let Some(addr) = peer.remote_addr {
+                info = info.with_remote_addr(addr);
+            }
+            if let Some(asn) = peer.remote_asn {
+                info = info.with_remote_asn(asn);
+            }
+            ingresses.update_info(id, info);
+        }
+    }
+
+    debug!(
+        "loaded {routes} routes for {} ingresses from {}",
+        mui_map.len(),
+        filename.display()
+    );
+    Ok(routes)
+}
+
+/// A peer as described in the ingress table of a native snapshot.
+#[derive(Clone, Copy, Debug)]
+pub struct SnapshotPeer {
+    pub mui: u32,
+    pub remote_addr: Option<IpAddr>,
+    pub remote_asn: Option<Asn>,
+}
+
+/// Read the records of a native snapshot, passing each to `f` as-is.
+///
+/// The MUIs of the records refer to the peers in the ingress table of the
+/// snapshot, which is returned.
+pub fn read_native_records<R: Read>(
+    mut reader: R,
+    mut f: impl FnMut(Prefix, bool, Record<RotondaPaMap>) -> io::Result<()>,
+) -> io::Result<Vec<SnapshotPeer>> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a Rotonda RIB snapshot"));
+    }
+    let version = read_u8(&mut reader)?;
+    if version != VERSION {
+        return Err(invalid_data(format!(
+            "unsupported snapshot version {version}"
+        )));
+    }
+
+    loop {
+        let kind = read_u8(&mut reader)?;
+        if kind == END_OF_RECORDS {
+            break;
+        }
+        let (prefix, multicast, record) = read_record(&mut reader, kind)?;
+        f(prefix, multicast, record)?;
+    }
+
+    let count = read_u32(&mut reader)?;
+    let mut peers = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        peers.push(read_peer(&mut reader)?);
+    }
+    Ok(peers)
+}
+
+/// Read a record following its `kind`, as written by [`write_record`].
+pub(super) fn read_record<R: Read>(
+    reader: &mut R,
+    kind: u8,
+) -> io::Result<(Prefix, bool, Record<RotondaPaMap>)> {
+    let (prefix, multicast) = read_prefix(reader, kind)?;
+    let mui = read_u32(reader)?;
+    let ltime = read_u64(reader)?;
+    let status = RouteStatus::try_from(read_u8(reader)?)
+        .map_err(|e| invalid_data(e.to_string()))?;
+    let pamap_len = read_u32(reader)? as usize;
+    let mut raw = vec![0u8; pamap_len];
+    reader.read_exact(&mut raw)?;
+    let pamap = RotondaPaMap::from_raw(raw)
+        .ok_or_else(|| invalid_data("truncated path attributes"))?;
+
+    Ok((prefix, multicast, Record::new(mui, ltime, status, pamap)))
+}
+
+/// Read a prefix following its `kind`, as written by [`write_prefix`].
+///
+/// Returns the prefix and whether it is a multicast prefix.
+pub(super) fn read_prefix<R: Read>(
+    reader: &mut R,
+    kind: u8,
+) -> io::Result<(Prefix, bool)> {
+    let multicast = match kind {
+        KIND_IPV4_UNICAST | KIND_IPV6_UNICAST => false,
+        KIND_IPV4_MULTICAST | KIND_IPV6_MULTICAST => true,
+        _ => {
+            return Err(invalid_data(format!("unknown record kind {kind}")))
+        }
+    };
+    let len = read_u8(reader)?;
+    let addr = match kind {
+        KIND_IPV4_UNICAST | KIND_IPV4_MULTICAST => {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf)?;
+            IpAddr::from(Ipv4Addr::from(buf))
+        }
+        _ => {
+            let mut buf = [0u8; 16];
+            reader.read_exact(&mut buf)?;
+            IpAddr::from(Ipv6Addr::from(buf))
+        }
+    };
+    let prefix = Prefix::new(addr, len).map_err(invalid_data)?;
+    Ok((prefix, multicast))
+}
+
+/// Read a peer, as written by [`write_peer`].
+pub(super) fn read_peer<R: Read>(reader: &mut R) -> io::Result<SnapshotPeer> {
+    let mui = read_u32(reader)?;
+    let remote_addr = read_addr(reader)?;
+    let remote_asn = match read_u8(reader)? {
+        0 => None,
+        _ => Some(Asn::from_u32(read_u32(reader)?)),
+    };
+    Ok(SnapshotPeer {
+        mui,
+        remote_addr,
+        remote_asn,
+    })
+}
+
+pub fn read_mrt<R: Read>(
+    mut reader: R,
+    rib: &Rib,
+    ingresses: &ingress::Register,
+    parent: IngressId,
+    filename: &Path,
+) -> io::Result<usize> {
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    let mrt_file = MrtFile::new(&buf[..]);
+
+    let peer_index_table =
+        mrt_file.pi().map_err(|e| invalid_data(e.to_string()))?;
+    let mut ingress_map = Vec::with_capacity(peer_index_table.len());
+    for peer_entry in &peer_index_table[..] {
+        let id = ingresses.register();
+        ingresses.update_info(
+            id,
+            IngressInfo::new()
+                .with_parent(parent)
+                .with_remote_addr(peer_entry.addr)
+                .with_remote_asn(peer_entry.asn)
+                .with_filename(filename.to_path_buf()),
+        );
+        ingress_map.push(id);
+    }
+
+    let mut routes = 0;
+    let rib_entries = mrt_file
+        .rib_entries()
+        .map_err(|e| invalid_data(e.to_string()))?;
+    for (afisafi, peer_id, peer_entry, prefix, raw_attr) in rib_entries {
+        let pamap = RotondaPaMap::new(OwnedPathAttributes::new(
+            PduParseInfo::modern(),
+            raw_attr,
+        ));
+        let rr = match afisafi {
+            AfiSafiType::Ipv4Unicast => RotondaRoute::Ipv4Unicast(
+                prefix.try_into().map_err(invalid_data)?,
+                pamap,
+            ),
+            AfiSafiType::Ipv6Unicast => RotondaRoute::Ipv6Unicast(
+                prefix.try_into().map_err(invalid_data)?,
+                pamap,
+            ),
+            _ => {
+                debug!("unsupported AFI/SAFI {}, skipping", afisafi);
+                continue;
+            }
+        };
+        let provenance = Provenance::for_bgp(
+            ingress_map[usize::from(peer_id)],
+            peer_entry.addr,
+            peer_entry.asn,
+        );
+        rib.insert(&rr, RouteStatus::Active, provenance, 0)
+            .map_err(io::Error::other)?;
+        routes += 1;
+    }
+
+    Ok(routes)
+}
+
+//------------ Writing -------------------------------------------------------
+
+/// A snapshot written by [`write_to_dir`].
+#[derive(Debug)]
+pub struct WrittenSnapshot {
+    pub path: PathBuf,
+    pub size: u64,
+    pub routes: usize,
+}
+
+/// Write a snapshot of `rib` to the configured directory.
+///
+/// The file is named after `unit_name` and the current time, and is only
+/// moved into place once completely written and flushed to disk. Afterwards,
+/// snapshots of this unit exceeding the configured retention are removed.
+pub fn write_to_dir(
+    config: &SnapshotConfig,
+    unit_name: &str,
+    rib: &Rib,
+    ingresses: &ingress::Register,
+) -> io::Result<WrittenSnapshot> {
+    fs::create_dir_all(&config.directory)?;
+
+    let mut filename = format!(
+        "{unit_name}-{}.{SNAPSHOT_EXTENSION}",
+        Utc::now().format(TIMESTAMP_FORMAT)
+    );
+    if config.compress {
+        filename.push_str(".gz");
+    }
+    let path = config.directory.join(&filename);
+    let tmp_path = config.directory.join(format!(".{filename}.tmp"));
+
+    let mut file = BufWriter::new(File::create(&tmp_path)?);
+    let res = if config.compress {
+        let mut gz = GzEncoder::new(&mut file, Compression::default());
+        write_native(rib, ingresses, &mut gz)
+            .and_then(|routes| gz.finish()?.flush().map(|_| routes))
+    } else {
+        write_native(rib, ingresses, &mut file)
+    };
+    // Only replace anything once the contents are on disk, lest a crash
+    // leaves us with an empty snapshot and no write-ahead log.
+    let res = res.and_then(|routes| {
+        file.get_ref().sync_all()?;
+        Ok(routes)
+    });
+    let routes = match res {
+        Ok(routes) => routes,
+        Err(err) => {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+    };
+    fs::rename(&tmp_path, &path)?;
+    sync_dir(&config.directory)?;
+    let size = fs::metadata(&path)?.len();
+
+    if config.retention > 0 {
+        prune(&config.directory, unit_name, config.retention)?;
+    }
+
+    Ok(WrittenSnapshot { path, size, routes })
+}
+
+/// Make a rename of a file in `dir` survive a crash.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Where the snapshots of a RIB unit are written.
+#[derive(Clone, Debug)]
+pub struct SnapshotLocation {
+    pub directory: PathBuf,
+    pub unit_name: String,
+}
+
+impl SnapshotLocation {
+    pub fn list(&self) -> io::Result<Vec<SnapshotFile>> {
+        list(&self.directory, &self.unit_name)
+    }
+
+    /// Find the snapshot called `spec`, or else the most recent snapshot
+    /// taken at or before the RFC 3339 timestamp `spec`.
+    pub fn find(&self, spec: &str) -> Result<SnapshotFile, String> {
+        let snapshots = self.list().map_err(|err| err.to_string())?;
+        if let Some(snapshot) = snapshots.iter().find(|s| s.name == spec) {
+            return Ok(snapshot.clone());
+        }
+        let at = DateTime::parse_from_rfc3339(spec).map_err(|_| {
+            format!("'{spec}' is neither a snapshot nor an RFC 3339 timestamp")
+        })?;
+        snapshots
+            .into_iter()
+            .rev()
+            .find(|s| s.taken <= at)
+            .ok_or_else(|| format!("No snapshot taken at or before {spec}"))
+    }
+}
+
+/// A snapshot found by [`list`].
+#[derive(Clone, Debug)]
+pub struct SnapshotFile {
+    pub name: String,
+    pub path: PathBuf,
+    pub taken: DateTime<Utc>,
+    pub size: u64,
+}
+
+/// The snapshots of `unit_name` in `dir`, oldest first.
+pub fn list(dir: &Path, unit_name: &str) -> io::Result<Vec<SnapshotFile>> {
+    let prefix = format!("{unit_name}-");
+    let mut snapshots = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(timestamp) = name
+            .strip_prefix(&prefix)
+            .and_then(|name| name.strip_suffix(".gz").or(Some(name)))
+            .and_then(|name| {
+                name.strip_suffix(&format!(".{SNAPSHOT_EXTENSION}"))
+            })
+        else {
+            continue;
+        };
+        let Ok(taken) =
+            NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+        else {
+            continue;
+        };
+        snapshots.push(SnapshotFile {
+            name,
+            path: entry.path(),
+            taken: taken.and_utc(),
+            size: entry.metadata()?.len(),
+        });
+    }
+
+    // The timestamp in the name sorts chronologically.
+    snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(snapshots)
+}
+
+/// Remove all but the `retention` most recent snapshots of `unit_name`.
+fn prune(dir: &Path, unit_name: &str, retention: usize) -> io::Result<()> {
+    let snapshots = list(dir, unit_name)?;
+    let excess = snapshots.len().saturating_sub(retention);
+    for snapshot in &snapshots[..excess] {
+        debug!("removing old snapshot {}", snapshot.name);
+        fs::remove_file(&snapshot.path)?;
+    }
+    Ok(())
+}
+
+/// Write all records in `rib` to `writer` in the native snapshot format.
+///
+/// Returns the number of routes written.
+pub fn write_native<W: Write>(
+    rib: &Rib,
+    ingresses: &ingress::Register,
+    mut writer: W,
+) -> io::Result<usize> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION])?;
+
+    let mut muis = BTreeSet::new();
+    let mut routes = 0;
+    let guard = &epoch::pin();
+    for (multicast, prefix_record) in rib.prefix_records(guard) {
+        let prefix_record =
+            prefix_record.map_err(|e| io::Error::other(e.to_string()))?;
+        for record in &prefix_record.meta {
+            write_record(&mut writer, &prefix_record.prefix, multicast, record)?;
+            muis.insert(record.multi_uniq_id);
+            routes += 1;
+        }
+    }
+    writer.write_all(&[END_OF_RECORDS])?;
+
+    writer.write_all(&(muis.len() as u32).to_be_bytes())?;
+    for mui in muis {
+        let info = ingresses.get(mui).unwrap_or_default();
+        write_peer(&mut writer, mui, info.remote_addr, info.remote_asn)?;
+    }
+    writer.flush()?;
+
+    Ok(routes)
+}
+
+/// Write a single record, starting with its kind.
+pub(super) fn write_record<W: Write>(
+    writer: &mut W,
+    prefix: &Prefix,
+    multicast: bool,
+    record: &Record<RotondaPaMap>,
+) -> io::Result<()> {
+    write_prefix(writer, prefix, multicast)?;
+    writer.write_all(&record.multi_uniq_id.to_be_bytes())?;
+    writer.write_all(&record.ltime.to_be_bytes())?;
+    writer.write_all(&[u8::from(record.status)])?;
+    let raw = record.meta.as_ref();
+    writer.write_all(&(raw.len() as u32).to_be_bytes())?;
+    writer.write_all(raw)
+}
+
+/// Write a prefix, starting with the kind of record it is for.
+pub(super) fn write_prefix<W: Write>(
+    writer: &mut W,
+    prefix: &Prefix,
+    multicast: bool,
+) -> io::Result<()> {
+    let kind = match (prefix.addr(), multicast) {
+        (IpAddr::V4(_), false) => KIND_IPV4_UNICAST,
+        (IpAddr::V6(_), false) => KIND_IPV6_UNICAST,
+        (IpAddr::V4(_), true) => KIND_IPV4_MULTICAST,
+        (IpAddr::V6(_), true) => KIND_IPV6_MULTICAST,
+    };
+    writer.write_all(&[kind, prefix.len()])?;
+    match prefix.addr() {
+        IpAddr::V4(addr) => writer.write_all(&addr.octets()),
+        IpAddr::V6(addr) => writer.write_all(&addr.octets()),
+    }
+}
+
+/// Write a peer of the ingress table.
+pub(super) fn write_peer<W: Write>(
+    writer: &mut W,
+    mui: u32,
+    remote_addr: Option<IpAddr>,
+    remote_asn: Option<Asn>,
+) -> io::Result<()> {
+    writer.write_all(&mui.to_be_bytes())?;
+    write_addr(writer, remote_addr)?;
+    match remote_asn {
+        Some(asn) => {
+            writer.write_all(&[1])?;
+            writer.write_all(&asn.into_u32().to_be_bytes())
+        }
+        None => writer.write_all(&[0]),
+    }
+}
+
+//------------ Helpers -------------------------------------------------------
+
+pub(super) fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+pub(super) fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(super) fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn read_addr<R: Read>(reader: &mut R) -> io::Result<Option<IpAddr>> {
+    match read_u8(reader)? {
+        0 => Ok(None),
+        4 => {
+            let mut buf = [0u8; 4];
+            reader.read_exact(&mut buf)?;
+            Ok(Some(Ipv4Addr::from(buf).into()))
+        }
+        6 => {
+            let mut buf = [0u8; 16];
+            reader.read_exact(&mut buf)?;
+            Ok(Some(Ipv6Addr::from(buf).into()))
+        }
+        n => Err(invalid_data(format!("unknown address family {n}"))),
+    }
+}
+
+fn write_addr<W: Write>(
+    writer: &mut W,
+    addr: Option<IpAddr>,
+) -> io::Result<()> {
+    match addr {
+        None => writer.write_all(&[0]),
+        Some(IpAddr::V4(addr)) => {
+            writer.write_all(&[4])?;
+            writer.write_all(&addr.octets())
+        }
+        Some(IpAddr::V6(addr)) => {
+            writer.write_all(&[6])?;
+            writer.write_all(&addr.octets())
+        }
+    }
+}
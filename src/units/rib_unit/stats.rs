@@ -0,0 +1,214 @@
+//! Statistics about the contents of the RIB.
+//!
+//! Unlike the counters in [`super::statistics`], which are updated as routes
+//! are inserted, these describe the RIB as it is at one point in time: how
+//! its prefixes are distributed over address families, origin ASNs and
+//! ingresses. They are computed by walking the whole RIB, either on request
+//! via the HTTP API, or periodically for the metrics.
+//!
+//! Withdrawn routes are not counted, and a prefix only counts if it has at
+//! least one route that is not withdrawn.
+//!
+//! The path attributes are stored with every route, rather than interned.
+//! The attribute statistics tell how many distinct sets of attributes there
+//! are among the routes, and so how much interning them would save.
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use inetnum::asn::Asn;
+use rotonda_store::{epoch, prefix_record::RouteStatus};
+use routecore::bgp::{
+    aspath::{Hop, HopPath},
+    types::AfiSafiType,
+};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::ingress::{self, IngressId};
+
+use super::rib::Rib;
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct StatsConfig {
+    /// How often to compute the statistics for the metrics.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "StatsConfig::default_interval_secs")]
+    pub interval_secs: Duration,
+
+    /// The number of origin ASNs to report, those with the most prefixes.
+    #[serde(default = "StatsConfig::default_top_origins")]
+    pub top_origins: usize,
+}
+
+impl StatsConfig {
+    fn default_interval_secs() -> Duration {
+        Duration::from_secs(300)
+    }
+
+    pub fn default_top_origins() -> usize {
+        10
+    }
+}
+
+//------------ RibStats ------------------------------------------------------
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RibStats {
+    /// When the statistics were computed.
+    pub time: DateTime<Utc>,
+
+    /// The number of prefixes per AFI/SAFI.
+    pub prefixes: BTreeMap<String, usize>,
+
+    /// The number of routes, i.e. (prefix, ingress) combinations.
+    pub routes: usize,
+
+    /// The number of distinct AS paths among the routes.
+    pub unique_as_paths: usize,
+
+    /// The origin ASNs with the most prefixes, most first.
+    pub origins: Vec<OriginStats>,
+
+    /// The number of routes per ingress, in ingress order.
+    pub ingresses: Vec<IngressStats>,
+
+    pub attributes: AttributeStats,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OriginStats {
+    pub asn: Asn,
+    pub prefixes: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct IngressStats {
+    pub ingress_id: IngressId,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingress_info: Option<ingress::IngressInfo>,
+
+    pub routes: usize,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AttributeStats {
+    /// The number of distinct sets of path attributes.
+    pub distinct: usize,
+
+    /// The size of the path attributes of all routes, in bytes.
+    pub bytes: usize,
+
+    /// The size of the distinct sets of path attributes, in bytes.
+    pub distinct_bytes: usize,
+}
+
+impl RibStats {
+    /// Compute the statistics for `rib`, reporting the `top_origins` origin
+    /// ASNs with the most prefixes.
+    pub fn compute(
+        rib: &Rib,
+        ingresses: &ingress::Register,
+        top_origins: usize,
+    ) -> Result<Self, String> {
+        let mut prefixes = BTreeMap::<String, usize>::new();
+        let mut routes = 0;
+        let mut as_paths = HashSet::new();
+        let mut origins = HashMap::<Asn, usize>::new();
+        let mut per_ingress = BTreeMap::<IngressId, usize>::new();
+        let mut distinct_attributes = HashSet::new();
+        let mut attributes = AttributeStats::default();
+
+        let guard = &epoch::pin();
+        for (multicast, prefix_record) in rib.prefix_records(guard) {
+            let prefix_record =
+                prefix_record.map_err(|err| err.to_string())?;
+            let mut prefix_origins = HashSet::new();
+            let mut active = false;
+            for record in &prefix_record.meta {
+                if record.status == RouteStatus::Withdrawn {
+                    continue;
+                }
+                active = true;
+                routes += 1;
+                *per_ingress.entry(record.multi_uniq_id).or_default() += 1;
+
+                let raw = record.meta.as_ref();
+                attributes.bytes += raw.len();
+                if distinct_attributes.insert(hash(raw)) {
+                    attributes.distinct_bytes += raw.len();
+                }
+
+                if let Some(hop_path) =
+                    record.meta.path_attributes().get::<HopPath>()
+                {
+                    if let Some(Ok(origin)) = hop_path
+                        .origin()
+                        .map(|hop| Hop::try_into_asn(hop.clone()))
+                    {
+                        prefix_origins.insert(origin);
+                    }
+                    as_paths.insert(hash(&hop_path));
+                }
+            }
+            if !active {
+                continue;
+            }
+            let afisafi = match (prefix_record.prefix.is_v4(), multicast) {
+                (true, false) => AfiSafiType::Ipv4Unicast,
+                (false, false) => AfiSafiType::Ipv6Unicast,
+                (true, true) => AfiSafiType::Ipv4Multicast,
+                (false, true) => AfiSafiType::Ipv6Multicast,
+            };
+            *prefixes.entry(afisafi.to_string()).or_default() += 1;
+            for origin in prefix_origins {
+                *origins.entry(origin).or_default() += 1;
+            }
+        }
+        attributes.distinct = distinct_attributes.len();
+
+        let mut origins = origins
+            .into_iter()
+            .map(|(asn, prefixes)| OriginStats { asn, prefixes })
+            .collect::<Vec<_>>();
+        origins.sort_by_key(|origin| {
+            (std::cmp::Reverse(origin.prefixes), origin.asn)
+        });
+        origins.truncate(top_origins);
+
+        let ingresses = per_ingress
+            .into_iter()
+            .map(|(ingress_id, routes)| IngressStats {
+                ingress_id,
+                ingress_info: ingresses.get(ingress_id),
+                routes,
+            })
+            .collect();
+
+        Ok(Self {
+            time: Utc::now(),
+            prefixes,
+            routes,
+            unique_as_paths: as_paths.len(),
+            origins,
+            ingresses,
+            attributes,
+        })
+    }
+}
+
+/// Hash `value`, so that distinct values can be counted without keeping
+/// them around.
+fn hash(value: &(impl Hash + ?Sized)) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
@@ -4,7 +4,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
 
 use crate::{
     common::status_reporter::{
@@ -15,7 +15,10 @@ use crate::{
     roto_runtime::types::FilterName,
 };
 
-use super::{metrics::RibUnitMetrics, rib::StoreInsertionEffect};
+use super::{
+    compaction::CompactionReport, consistency::ConsistencyReport, metrics::RibUnitMetrics,
+    rib::StoreInsertionEffect, stats::RibStats,
+};
 
 #[derive(Debug, Default)]
 pub struct RibUnitStatusReporter {
@@ -158,6 +161,181 @@ impl RibUnitStatusReporter {
             .store(num_unique_prefixes, SeqCst);
     }
 
+    pub fn bootstrap_loaded<P: Display>(
+        &self,
+        path: P,
+        routes: usize,
+        duration: Duration,
+    ) {
+        sr_log!(info: self, "Bootstrapped {} routes from {} in {}ms", routes, path, duration.as_millis());
+        self.metrics.num_bootstrapped_routes.store(routes, SeqCst);
+    }
+
+    pub fn bootstrap_failed<P: Display, E: Display>(&self, path: P, err: E) {
+        sr_log!(error: self, "Failed to bootstrap from {}: {}", path, err);
+    }
+
+    pub fn bootstrap_expired(&self, num_ingresses: usize) {
+        sr_log!(info: self, "Withdrew bootstrapped routes for {} ingresses", num_ingresses);
+        self.metrics.num_bootstrapped_routes.store(0, SeqCst);
+    }
+
+    pub fn routes_marked_stale(&self, ingress_id: IngressId, routes: usize) {
+        sr_log!(info: self, "Marked {} routes of ingress {} as stale", routes, ingress_id);
+        self.metrics.num_stale_routes.fetch_add(routes, SeqCst);
+    }
+
+    pub fn stale_routes_expired(
+        &self,
+        ingress_id: IngressId,
+        marked: usize,
+        expired: usize,
+    ) {
+        sr_log!(info: self, "Withdrew {} of {} stale routes of ingress {}", expired, marked, ingress_id);
+        self.metrics.num_stale_routes.fetch_sub(marked, SeqCst);
+        self.metrics.num_stale_routes_expired.fetch_add(expired, SeqCst);
+    }
+
+    pub fn memory_usage_high(&self, estimated: usize, max: usize) {
+        sr_log!(warn: self, "Estimated RIB memory use of {} bytes is approaching the limit of {} bytes", estimated, max);
+    }
+
+    pub fn memory_usage_normal(&self, estimated: usize, max: usize) {
+        sr_log!(info: self, "Estimated RIB memory use of {} bytes is back below the warning threshold for the limit of {} bytes", estimated, max);
+    }
+
+    pub fn memory_limit_reached(&self, max: usize) {
+        sr_log!(error: self, "RIB memory limit of {} bytes reached, dropping new paths", max);
+    }
+
+    pub fn path_rejected(&self) {
+        self.metrics.num_paths_rejected.fetch_add(1, SeqCst);
+    }
+
+    pub fn ingress_evicted(&self, ingress_id: IngressId, routes: usize) {
+        sr_log!(warn: self, "RIB memory limit reached, evicted ingress {} with {} routes", ingress_id, routes);
+        self.metrics.num_ingresses_evicted.fetch_add(1, SeqCst);
+    }
+
+    pub fn garbage_collected(
+        &self,
+        purged: usize,
+        reclaimed_bytes: usize,
+        duration: Duration,
+    ) {
+        if purged > 0 {
+            sr_log!(info: self, "Purged {} withdrawn routes, reclaiming an estimated {} bytes in {}ms", purged, reclaimed_bytes, duration.as_millis());
+        }
+        self.metrics.num_gc_runs.fetch_add(1, SeqCst);
+        self.metrics.num_withdrawn_routes_purged.fetch_add(purged, SeqCst);
+        self.metrics
+            .gc_reclaimed_bytes
+            .fetch_add(reclaimed_bytes, SeqCst);
+        self.metrics.last_gc_duration_millis.store(
+            u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            SeqCst,
+        );
+    }
+
+    pub fn garbage_collection_failed<E: Display>(&self, err: E) {
+        sr_log!(error: self, "Failed to purge withdrawn routes: {}", err);
+    }
+
+    pub fn stats_computed(&self, stats: RibStats, duration: Duration) {
+        sr_log!(debug: self, "Computed RIB statistics over {} routes in {}ms", stats.routes, duration.as_millis());
+        self.metrics.rib_stats.store(Some(Arc::new(stats)));
+    }
+
+    pub fn stats_failed<E: Display>(&self, err: E) {
+        sr_log!(error: self, "Failed to compute RIB statistics: {}", err);
+    }
+
+    pub fn consistency_checked(
+        &self,
+        report: Arc<ConsistencyReport>,
+        duration: Duration,
+    ) {
+        if report.total() > 0 {
+            sr_log!(warn: self, "Consistency check of {} routes found {} discrepancies, repaired {}, in {}ms", report.routes, report.total(), report.repaired, duration.as_millis());
+        } else {
+            sr_log!(debug: self, "Consistency check of {} routes found no discrepancies in {}ms", report.routes, duration.as_millis());
+        }
+        self.metrics.num_consistency_checks.fetch_add(1, SeqCst);
+        self.metrics
+            .num_consistency_repairs
+            .fetch_add(report.repaired, SeqCst);
+        self.metrics.last_consistency_check_duration_millis.store(
+            u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            SeqCst,
+        );
+        self.metrics.consistency.store(Some(report));
+    }
+
+    pub fn consistency_check_failed<E: Display>(&self, err: E) {
+        sr_log!(error: self, "Failed to check the consistency of the RIB: {}", err);
+    }
+
+    pub fn compacted(&self, report: Arc<CompactionReport>) {
+        sr_log!(info: self, "Compacted on-disk state ({}) from {} to {} bytes in {}ms", report.trigger.as_str(), report.before.total_bytes(), report.after.total_bytes(), report.duration_millis);
+        self.metrics.num_compactions.fetch_add(1, SeqCst);
+        self.metrics.compaction.store(Some(report));
+    }
+
+    pub fn compaction_failed<E: Display>(&self, err: E) {
+        sr_log!(error: self, "Failed to compact the on-disk state: {}", err);
+        self.metrics.num_compaction_failures.fetch_add(1, SeqCst);
+    }
+
+    pub fn snapshot_written<P: Display>(
+        &self,
+        path: P,
+        routes: usize,
+        size: u64,
+        duration: Duration,
+    ) {
+        sr_log!(info: self, "Wrote snapshot of {} routes ({} bytes) to {} in {}ms", routes, size, path, duration.as_millis());
+        self.metrics.num_snapshots_written.fetch_add(1, SeqCst);
+        self.metrics.last_snapshot_size_bytes.store(size, SeqCst);
+        self.metrics.last_snapshot_duration_millis.store(
+            u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            SeqCst,
+        );
+    }
+
+    pub fn snapshot_failed<E: Display>(&self, err: E) {
+        sr_log!(error: self, "Failed to write snapshot: {}", err);
+        self.metrics.num_snapshot_failures.fetch_add(1, SeqCst);
+    }
+
+    pub fn recovered(
+        &self,
+        snapshot_routes: usize,
+        wal_entries: usize,
+        duration: Duration,
+    ) {
+        sr_log!(info: self, "Recovered {} routes from the last snapshot and replayed {} write-ahead log entries in {}ms", snapshot_routes, wal_entries, duration.as_millis());
+        self.metrics.num_bootstrapped_routes.store(snapshot_routes, SeqCst);
+        self.metrics.num_wal_entries_replayed.store(wal_entries, SeqCst);
+    }
+
+    pub fn recovery_failed<E: Display>(&self, err: E) {
+        sr_log!(error: self, "Failed to recover the RIB: {}", err);
+    }
+
+    pub fn recovery_expired(&self, num_ingresses: usize) {
+        sr_log!(info: self, "Withdrew recovered routes for {} ingresses", num_ingresses);
+        self.metrics.num_bootstrapped_routes.store(0, SeqCst);
+    }
+
+    pub fn wal_entry_written(&self) {
+        self.metrics.num_wal_entries_written.fetch_add(1, SeqCst);
+    }
+
+    pub fn wal_write_failed<E: Display>(&self, err: E) {
+        sr_log!(error: self, "Failed to write to the write-ahead log: {}", err);
+        self.metrics.num_wal_write_failures.fetch_add(1, SeqCst);
+    }
+
     pub fn message_filtering_failure<T: Display>(&self, err: T) {
         sr_log!(error: self, "Filtering error: {}", err);
     }
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 use serde::Deserialize;
-use rotonda_store::rib::config::{MemoryOnlyConfig, Config as RibConfig};
+use rotonda_store::rib::config::MemoryOnlyConfig;
 
 /// Storage configuration for RIB units
 #[derive(Clone, Debug, Deserialize)]
@@ -8,7 +8,7 @@ use rotonda_store::rib::config::{MemoryOnlyConfig, Config as RibConfig};
 pub enum StorageConfig {
     /// In-memory storage only (default)
     #[serde(rename = "memory")]
-    Memory(MemoryOnlyConfig),
+    Memory(MemoryStorageConfig),
     
     /// On-disk storage with optional memory cache
     #[serde(rename = "disk")]
@@ -21,10 +21,17 @@ pub enum StorageConfig {
 
 impl Default for StorageConfig {
     fn default() -> Self {
-        StorageConfig::Memory(MemoryOnlyConfig::default())
+        StorageConfig::Memory(MemoryStorageConfig::default())
     }
 }
 
+/// Configuration for in-memory storage
+///
+/// This currently has no settings, but exists so that the `memory` storage
+/// type can be configured like the other types.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct MemoryStorageConfig {}
+
 /// Configuration for on-disk storage
 #[derive(Clone, Debug, Deserialize)]
 pub struct DiskStorageConfig {
@@ -46,9 +53,23 @@ pub struct DiskStorageConfig {
     #[serde(default = "DiskStorageConfig::default_cache_size")]
     pub cache_size: usize,
     
-    /// Background compaction interval in seconds
+    /// Background compaction interval in seconds, 0 to disable
     #[serde(default = "DiskStorageConfig::default_compaction_interval")]
     pub compaction_interval_secs: u64,
+
+    /// Compact as soon as the write-ahead log grows beyond this many bytes
+    #[serde(default)]
+    pub compaction_wal_size_bytes: Option<u64>,
+
+    /// Compact as soon as the write-ahead log holds changes made more than
+    /// this many seconds ago
+    #[serde(default)]
+    pub compaction_wal_age_secs: Option<u64>,
+
+    /// Withdraw the routes recovered on startup after this many seconds,
+    /// by which time the live feeds are expected to have reconverged
+    #[serde(default)]
+    pub recovery_expire_after_secs: Option<u64>,
 }
 
 impl DiskStorageConfig {
@@ -76,7 +97,8 @@ pub struct HybridStorageConfig {
     pub disk: DiskStorageConfig,
     
     /// Memory storage configuration
-    pub memory: MemoryOnlyConfig,
+    #[serde(default)]
+    pub memory: MemoryStorageConfig,
     
     /// Strategy for data placement
     #[serde(default = "HybridStorageConfig::default_placement_strategy")]
@@ -106,7 +128,7 @@ impl HybridStorageConfig {
 }
 
 /// Synchronization mode for disk writes
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum SyncMode {
     /// No explicit sync (fastest, least durable)
@@ -132,23 +154,63 @@ pub enum PlacementStrategy {
 }
 
 impl StorageConfig {
-    /// Convert to rotonda-store RibConfig
-    pub fn to_rib_config(&self) -> RibConfig {
+    /// Convert to rotonda-store RIB config
+    ///
+    /// The RIB itself is always kept in memory, whatever the storage type.
+    /// With `disk` and `hybrid` storage, it is persisted by the write-ahead
+    /// log and snapshots in the storage path instead, see
+    /// [`Self::ignored_settings`] for the settings that have no effect.
+    pub fn to_rib_config(&self) -> MemoryOnlyConfig {
         match self {
-            StorageConfig::Memory(config) => RibConfig::MemoryOnly(config.clone()),
+            StorageConfig::Memory(_config) => MemoryOnlyConfig,
             StorageConfig::Disk(_config) => {
                 // For now, fall back to memory-only until we implement disk storage
                 // TODO: Implement actual disk storage backend
-                RibConfig::MemoryOnly(MemoryOnlyConfig::default())
+                MemoryOnlyConfig
             },
             StorageConfig::Hybrid(_config) => {
                 // For now, fall back to memory-only until we implement hybrid storage
                 // TODO: Implement actual hybrid storage backend
-                RibConfig::MemoryOnly(MemoryOnlyConfig::default())
+                MemoryOnlyConfig
             },
         }
     }
     
+    /// The on-disk part of this storage configuration, if any
+    pub fn disk(&self) -> Option<&DiskStorageConfig> {
+        match self {
+            StorageConfig::Memory(_) => None,
+            StorageConfig::Disk(config) => Some(config),
+            StorageConfig::Hybrid(config) => Some(&config.disk),
+        }
+    }
+
+    /// The names of the settings that have no effect, because they only
+    /// apply to a disk-based store, which is not implemented.
+    ///
+    /// Only those differing from their defaults are returned.
+    pub fn ignored_settings(&self) -> Vec<&'static str> {
+        let mut res = Vec::new();
+        let disk = match self {
+            StorageConfig::Memory(_) => return res,
+            StorageConfig::Disk(disk) => disk,
+            StorageConfig::Hybrid(hybrid) => {
+                res.push("type = \"hybrid\"");
+                &hybrid.disk
+            }
+        };
+        if disk.max_size_bytes.is_some() {
+            res.push("max_size_bytes");
+        }
+        if disk.compression != DiskStorageConfig::default_compression() {
+            res.push("compression");
+        }
+        if disk.cache_size != DiskStorageConfig::default_cache_size() {
+            res.push("cache_size");
+        }
+        res
+    }
+
     /// Check if this storage configuration supports persistence
     pub fn is_persistent(&self) -> bool {
         matches!(self, StorageConfig::Disk(_) | StorageConfig::Hybrid(_))
@@ -187,6 +249,7 @@ mod tests {
         sync_mode = "normal"
         cache_size = 50000
         compaction_interval_secs = 7200
+        compaction_wal_size_bytes = 104857600
         "#;
         
         let config: StorageConfig = toml::from_str(toml).unwrap();
@@ -198,11 +261,57 @@ mod tests {
             assert!(matches!(disk_config.sync_mode, SyncMode::Normal));
             assert_eq!(disk_config.cache_size, 50000);
             assert_eq!(disk_config.compaction_interval_secs, 7200);
+            assert_eq!(
+                disk_config.compaction_wal_size_bytes,
+                Some(104857600)
+            );
+            assert_eq!(disk_config.compaction_wal_age_secs, None);
         } else {
             panic!("Expected DiskStorageConfig");
         }
     }
 
+    #[test]
+    fn ignored_settings_are_reported() {
+        let memory = StorageConfig::default();
+        assert!(memory.ignored_settings().is_empty());
+
+        let disk: StorageConfig = toml::from_str(
+            r#"
+            type = "disk"
+            path = "/var/lib/rotonda"
+            sync_mode = "full"
+            "#,
+        )
+        .unwrap();
+        assert!(disk.ignored_settings().is_empty());
+
+        let disk: StorageConfig = toml::from_str(
+            r#"
+            type = "disk"
+            path = "/var/lib/rotonda"
+            max_size_bytes = 1073741824
+            cache_size = 50000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(disk.ignored_settings(), ["max_size_bytes", "cache_size"]);
+
+        let hybrid: StorageConfig = toml::from_str(
+            r#"
+            type = "hybrid"
+            [disk]
+            path = "/var/lib/rotonda"
+            compression = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            hybrid.ignored_settings(),
+            ["type = \"hybrid\"", "compression"]
+        );
+    }
+
     #[test]
     fn test_hybrid_storage_config_deserialization() {
         let toml = r#"
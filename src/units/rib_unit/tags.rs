@@ -0,0 +1,107 @@
+//! User-defined tags on the routes in the RIB.
+//!
+//! The roto filter of a rib unit can attach tags to a route, e.g.
+//! `tags.set("classification", "customer")` or `tags.set_int("rank", 2)`.
+//! The tags are kept alongside the store, keyed on the prefix and ingress
+//! of the route, and are replaced every time the route is announced. Like
+//! its attributes, a withdrawn route keeps the tags it was last announced
+//! with.
+//!
+//! Tags only live in memory: they are not written to snapshots or the
+//! write-ahead log, so after a restart a route has no tags until it is
+//! announced again.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::RwLock,
+};
+
+use inetnum::addr::Prefix;
+
+use crate::{ingress::IngressId, roto_runtime::types::Tags};
+
+use super::index::RouteKey;
+
+//------------ RouteTags -----------------------------------------------------
+
+/// The tags of the routes in a RIB.
+#[derive(Debug, Default)]
+pub struct RouteTags {
+    tags: RwLock<HashMap<RouteKey, Tags>>,
+}
+
+impl RouteTags {
+    /// Replace the tags of the route for `prefix` learned from
+    /// `ingress_id`.
+    pub fn set(&self, prefix: Prefix, ingress_id: IngressId, tags: Tags) {
+        let mut routes = self.tags.write().unwrap();
+        if tags.is_empty() {
+            routes.remove(&(prefix, ingress_id));
+        } else {
+            routes.insert((prefix, ingress_id), tags);
+        }
+    }
+
+    /// The tags of the route for `prefix` learned from `ingress_id`.
+    pub fn get(&self, prefix: Prefix, ingress_id: IngressId) -> Option<Tags> {
+        self.tags
+            .read()
+            .unwrap()
+            .get(&(prefix, ingress_id))
+            .cloned()
+    }
+
+    /// The routes with tags that are not in `keys`.
+    pub fn keys_except(&self, keys: &HashSet<RouteKey>) -> Vec<RouteKey> {
+        let routes = self.tags.read().unwrap();
+        routes
+            .keys()
+            .filter(|key| !keys.contains(key))
+            .copied()
+            .collect()
+    }
+}
+
+//------------ TagFilter -----------------------------------------------------
+
+/// A tag to match routes on.
+///
+/// Written as `key` to match routes with the tag set to any value, or as
+/// `key=value` to match routes with the tag set to that value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TagFilter {
+    key: String,
+    value: Option<String>,
+}
+
+impl TagFilter {
+    pub fn matches(&self, tags: Option<&Tags>) -> bool {
+        let Some(value) = tags.and_then(|tags| tags.get(&self.key)) else {
+            return false;
+        };
+        self.value
+            .as_ref()
+            .is_none_or(|wanted| value.to_string() == *wanted)
+    }
+}
+
+impl FromStr for TagFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = match s.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (s, None),
+        };
+        if key.is_empty() {
+            return Err(format!(
+                "Invalid tag filter '{s}': missing tag name"
+            ));
+        }
+        Ok(Self {
+            key: key.to_string(),
+            value,
+        })
+    }
+}
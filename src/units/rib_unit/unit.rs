@@ -1,15 +1,15 @@
 use crate::{
     common::{
         frim::FrimMap,
-        status_reporter::{AnyStatusReporter, UnitStatusReporter},
+        status_reporter::{AnyStatusReporter, Named, UnitStatusReporter},
     }, comms::{
         AnyDirectUpdate, DirectLink, DirectUpdate, Gate, GateStatus, Link,
         Terminated, TriggerData,
-    }, ingress, manager::{Component, WaitPoint}, payload::{
+    }, http, ingress::{self, IngressInfo}, manager::{Component, WaitPoint}, metrics, payload::{
         Payload, RotondaPaMap, RotondaRoute, RouterId, Update, UpstreamStatus
-    }, roto_runtime::{self, types::{FilterName, InsertionInfo, Output, OutputStream, OutputStreamMessage, RotoOutputStream, RouteContext}, CompileListsFunc, Ctx, COMPILE_LISTS_FUNC_NAME}, tokio::TokioTaskMetrics, tracing::{BoundTracer, Tracer}, units::{rib_unit::rpki::MaxLenList, rtr::client::VrpUpdate, Unit}
+    }, roto_runtime::{self, filter_metrics::{self, ShadowConfig}, logger::ScriptLogger, reload::Reloadable, types::{FilterName, InsertionInfo, Output, OutputStream, OutputStreamMessage, Provenance, RotoOutputStream, RouteContext, Tags}, CompileListsFunc, Ctx, COMPILE_LISTS_FUNC_NAME}, tokio::TokioTaskMetrics, tracing::{BoundTracer, Tracer}, units::{flow_in::counters::TrafficCounters, rib_unit::rpki::MaxLenList, rtr::client::VrpUpdate, Unit}
 };
-use arc_swap::ArcSwap;
+use arc_swap::{ArcSwap, ArcSwapOption};
 use async_trait::async_trait;
 use roto::Verdict;
 use std::{collections::{HashMap, HashSet}, io::prelude::*, sync::{Mutex, RwLock}};
@@ -26,13 +26,16 @@ use routecore::bgp::{aspath::{Hop, HopPath}, types::AfiSafiType};
 use serde::Deserialize;
 use smallvec::{smallvec, SmallVec};
 use std::{
-    cell::RefCell, ops::Deref, str::FromStr, string::ToString, sync::Arc,
+    cell::RefCell, net::{IpAddr, Ipv4Addr}, ops::Deref, path::PathBuf, str::FromStr,
+    sync::atomic::Ordering::SeqCst,
+    string::ToString, sync::Arc, time::{Duration, Instant},
 };
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinSet;
 use uuid::Uuid;
 
 use super::{
-    http::PrefixesApi, metrics::RibUnitMetrics, rib::{Rib, RouteExtra, StoreInsertionEffect}, rpki::{RovStatus, RovStatusUpdate, RtrCache}, status_reporter::RibUnitStatusReporter, storage::StorageConfig
+    best_path::{self, BestPathConfig, BestPathOutput}, compaction::Compactor, filter_trace::{FilterTraceConfig, FilterTracer}, consistency::{CheckedRib, ConsistencyChecker, ConsistencyConfig}, flap::{FlapDamping, FlapDampingConfig}, gc::{self, GcConfig, WithdrawnSince}, graphql::{GraphQlApi, GraphQlConfig}, history::{HistoryConfig, RouteHistory}, http::PrefixesApi, index::IndexConfig, memory::{LimitPolicy, LimitState, MemoryConfig, MemoryUsage}, metrics::RibUnitMetrics, peer_down::{PeerDownAction, PeerDownConfig}, rib::{Rib, RouteExtra, StoreInsertionEffect}, rpki::{RovStatus, RovStatusUpdate, RtrCache}, shard::ShardConfig, snapshot::{self, BootstrapConfig, SnapshotConfig}, stats::{RibStats, StatsConfig}, status_reporter::RibUnitStatusReporter, storage::{DiskStorageConfig, StorageConfig}, wal::{self, Wal, WalEntry}
 };
 use super::{
     rib::StoreInsertionReport, statistics::RibMergeUpdateStatistics,
@@ -79,6 +82,36 @@ type RotoFuncPost = roto::TypedFunc<
 #[allow(dead_code)]
 pub const ROTO_FUNC_POST_FILTER_NAME: &str = "rib_in_post";
 
+/// The filters of the rib unit, taken from the same version of the script.
+#[derive(Clone, Default)]
+pub(super) struct RibFilters {
+    pub(super) pre: Option<RotoFuncPre>,
+    pub(super) vrp_update: Option<RotoFuncVrpUpdate>,
+    pub(super) vrp_update_post: Option<RotoFuncRovStatusUpdate>,
+}
+
+impl RibFilters {
+    fn load(c: &mut roto::Compiled) -> Self {
+        Self {
+            pre: c.get_function(ROTO_FUNC_PRE_FILTER_NAME)
+                .inspect_err(|_|
+                    warn!("Loaded Roto script has no filter for rib_in_pre")
+                )
+                .ok(),
+            vrp_update: c.get_function(ROTO_FUNC_VRP_UPDATE_FILTER_NAME)
+                .inspect_err(|_|
+                    warn!("Loaded Roto script has no filter for rib_in_vrp_update")
+                )
+                .ok(),
+            vrp_update_post: c.get_function(ROTO_FUNC_ROV_STATUS_UPDATE_NAME)
+                .inspect_err(|_|
+                    warn!("Loaded Roto script has no filter for rib_in_vrp_update_post")
+                )
+                .ok(),
+        }
+    }
+}
+
 impl From<UpsertReport> for InsertionInfo {
     fn from(value: UpsertReport) -> Self {
         Self {
@@ -137,9 +170,30 @@ impl Default for MoreSpecifics {
     }
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct QueryLimits {
+    #[serde(default)]
     pub more_specifics: MoreSpecifics,
+
+    /// The maximum number of routes returned by a search query, e.g. an AS
+    /// path regex query.
+    #[serde(default = "QueryLimits::default_max_search_results")]
+    pub max_search_results: usize,
+}
+
+impl QueryLimits {
+    pub fn default_max_search_results() -> usize {
+        1000
+    }
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        Self {
+            more_specifics: MoreSpecifics::default(),
+            max_search_results: Self::default_max_search_results(),
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Default, Deserialize)]
@@ -202,16 +256,122 @@ pub struct RibUnit {
     /// Storage configuration for this RIB unit
     #[serde(default)]
     pub storage: StorageConfig,
+
+    /// Secondary indexes to maintain on the RIB contents.
+    #[serde(default)]
+    pub index: IndexConfig,
+
+    /// Select the best path for each prefix across all ingresses.
+    #[serde(default)]
+    pub best_path: Option<BestPathConfig>,
+
+    /// What to do with the routes of a peer that went down.
+    #[serde(default)]
+    pub peer_down: PeerDownConfig,
+
+    /// Limit the estimated memory use of the RIB.
+    #[serde(default)]
+    pub memory: Option<MemoryConfig>,
+
+    /// Additional RIBs the roto filter can write routes to, by name. Each
+    /// is queried via the HTTP API at `<http_api_path>ribs/<name>/`.
+    #[serde(default)]
+    pub named_ribs: Vec<String>,
+
+    /// Periodically purge routes that have been withdrawn for a while.
+    #[serde(default)]
+    pub gc: Option<GcConfig>,
+
+    /// Snapshot to load into the RIB on startup, before live updates are
+    /// accepted.
+    #[serde(default)]
+    pub bootstrap: Option<BootstrapConfig>,
+
+    /// Periodically write a snapshot of the RIB to disk.
+    #[serde(default)]
+    pub snapshot: Option<SnapshotConfig>,
+
+    /// Keep earlier versions of the routes in the RIB, queryable via the
+    /// HTTP API at `<http_api_path>history/<prefix>`.
+    #[serde(default)]
+    pub history: Option<HistoryConfig>,
+
+    /// Periodically compute statistics about the contents of the RIB for
+    /// the metrics.
+    #[serde(default)]
+    pub stats: Option<StatsConfig>,
+
+    /// Spread the RIB over multiple stores, to let updates be inserted in
+    /// parallel.
+    #[serde(default)]
+    pub shards: Option<ShardConfig>,
+
+    /// Periodically check that the indexes and counters of the RIB agree
+    /// with its contents, reported via the HTTP API at
+    /// `<http_api_path>consistency`.
+    #[serde(default)]
+    pub consistency: Option<ConsistencyConfig>,
+
+    /// The flow-in unit whose traffic counts the roto filter can use and
+    /// prefix queries include.
+    #[serde(default)]
+    pub traffic: Option<String>,
+
+    /// Track the flapping of paths, for the roto filter to damp them.
+    #[serde(default)]
+    pub flap_damping: Option<FlapDampingConfig>,
+
+    /// Run the roto filter in shadow mode, passing on all routes unchanged.
+    #[serde(default)]
+    pub shadow: Option<ShadowConfig>,
+
+    /// Allow tracing the roto filter for single routes via the HTTP API at
+    /// `<http_api_path>filter-trace`.
+    #[serde(default)]
+    pub filter_trace: Option<FilterTraceConfig>,
+
+    /// Answer GraphQL queries over the RIB, its ingresses and the units and
+    /// their metrics via the HTTP API at `<http_api_path>graphql`.
+    #[serde(default)]
+    pub graphql: Option<GraphQlConfig>,
 }
 
 impl RibUnit {
+    /// The settings a running unit applies when they change.
+    ///
+    /// Changing any other setting restarts the unit.
+    const RECONFIGURABLE: &'static [&'static str] = &[
+        "sources",
+        "query_limits",
+        "filter_name",
+        "vrib_upstream",
+        "peer_down",
+        "shadow",
+    ];
+
+    /// Returns whether a running unit applies changes to the given
+    /// settings itself.
+    pub fn reconfigures_in_place(changed: &HashSet<String>) -> bool {
+        changed
+            .iter()
+            .all(|name| Self::RECONFIGURABLE.contains(&name.as_str()))
+    }
+
     pub async fn run(
         self,
         component: Component,
         gate: Gate,
         waitpoint: WaitPoint,
     ) -> Result<(), Terminated> {
-        RibUnitRunner::new(
+        let traffic = self
+            .traffic
+            .as_ref()
+            .map(|name| component.traffic_counters().get(name));
+        let unit_name = component.name().clone();
+        let metrics = component.metrics().cloned();
+        let http_resources = component.http_resources().clone();
+
+        let mut runner = RibUnitRunner::new(
             gate,
             component,
             self.http_api_path,
@@ -219,10 +379,80 @@ impl RibUnit {
             self.filter_name.unwrap_or_default(),
             self.rib_type,
             self.vrib_upstream,
+            &self.index,
+            self.best_path,
+            self.peer_down,
+            self.memory,
+            self.named_ribs,
+            &self.shards.unwrap_or_default(),
+            self.flap_damping,
         )
-        .map_err(|_| Terminated)?
-        .run(self.sources, waitpoint)
-        .await
+        .map_err(|_| Terminated)?;
+
+        if let Some(history) = self.history {
+            runner.enable_history(history);
+        }
+
+        if let Some(traffic) = traffic {
+            runner.enable_traffic(traffic);
+        }
+
+        runner.set_shadow(self.shadow);
+
+        if let Some(filter_trace) = self.filter_trace {
+            runner.enable_filter_trace(filter_trace, unit_name);
+        }
+
+        if let Some(graphql) = self.graphql {
+            runner.enable_graphql(graphql, metrics, http_resources);
+        }
+
+        let ignored = self.storage.ignored_settings();
+        if !ignored.is_empty() {
+            warn!(
+                "The RIB of unit '{}' is kept in memory, ignoring storage \
+                settings {}",
+                runner.status_reporter.name(),
+                ignored.join(", ")
+            );
+        }
+
+        match self.storage.disk() {
+            Some(disk) => {
+                runner
+                    .recover(disk, self.snapshot.as_ref(), self.bootstrap)
+                    .await
+            }
+            None => {
+                if let Some(bootstrap) = self.bootstrap {
+                    runner.bootstrap(bootstrap).await;
+                }
+            }
+        }
+
+        if let (Some(disk), Some(snapshot)) =
+            (self.storage.disk(), self.snapshot.as_ref())
+        {
+            runner.spawn_compactor(disk.clone(), snapshot.clone());
+        }
+
+        if let Some(snapshot) = self.snapshot {
+            runner.spawn_snapshotter(snapshot);
+        }
+
+        if let Some(gc) = self.gc {
+            runner.spawn_garbage_collector(gc);
+        }
+
+        if let Some(stats) = self.stats {
+            runner.spawn_stats_collector(stats);
+        }
+
+        if let Some(consistency) = self.consistency {
+            runner.spawn_consistency_checker(consistency);
+        }
+
+        runner.run(self.sources, waitpoint).await
     }
 
     fn default_http_api_path() -> String {
@@ -236,9 +466,7 @@ impl RibUnit {
 
 
 pub struct RibUnitRunner {
-    roto_function_pre: Option<RotoFuncPre>,
-    roto_function_vrp_update: Option<RotoFuncVrpUpdate>,
-    roto_function_vrp_update_post: Option<RotoFuncRovStatusUpdate>,
+    roto_filters: Arc<Reloadable<RibFilters>>,
     roto_function_post: Option<RotoFuncPost>,
     roto_context: Arc<Mutex<Ctx>>,
     gate: Arc<Gate>,
@@ -249,14 +477,31 @@ pub struct RibUnitRunner {
     query_limits: Arc<ArcSwap<QueryLimits>>,
     rib: Arc<ArcSwap<Rib>>, // XXX LH: why the ArcSwap here?
     rib_type: RibType,
+    best_path_output: BestPathOutput,
+    peer_down: Arc<ArcSwap<PeerDownConfig>>,
+    stale_ingresses: Arc<Mutex<HashMap<StaleKey, (Instant, usize)>>>,
+    memory_limit: Option<MemoryConfig>,
+    memory_limit_state: Arc<LimitState>,
+    rebuild_lock: Arc<RwLock<()>>,
+    wal: Option<Arc<Wal>>,
+
+    /// Entries appended to `wal` that are yet to be waited for.
+    unsynced: Mutex<Vec<wal::Appended>>,
+
+    history: Option<Arc<RouteHistory>>,
+    named_ribs: Vec<NamedRib>,
     ingress_register: Arc<ingress::Register>,
     rtr_cache: Arc<RtrCache>,
+    flap_damping: Arc<FlapDamping>,
+    shadow: ArcSwapOption<ShadowConfig>,
+    filter_tracer: Option<Arc<FilterTracer>>,
     filter_name: Arc<ArcSwap<FilterName>>,
     pending_vrib_query_results: Arc<PendingVirtualRibQueryResults>,
     rib_merge_update_stats: Arc<RibMergeUpdateStatistics>,
     status_reporter: Arc<RibUnitStatusReporter>,
     _process_metrics: Arc<TokioTaskMetrics>,
     tracer: Arc<Tracer>,
+    background_tasks: BackgroundTasks,
 }
 
 #[async_trait]
@@ -276,6 +521,77 @@ impl std::fmt::Debug for RibUnitRunner {
 
 impl AnyDirectUpdate for RibUnitRunner {}
 
+/// A RIB in addition to the main RIB of the unit, written to on request of
+/// the roto filter.
+struct NamedRib {
+    name: Arc<str>,
+    rib: Arc<ArcSwap<Rib>>,
+    #[allow(dead_code)]
+    // A strong ref needs to be held to http_processor but not used otherwise
+    // the HTTP resource manager will discard its registration
+    http_processor: Arc<PrefixesApi>,
+}
+
+/// The tasks a unit runs periodically alongside processing updates.
+///
+/// They are stopped when the unit terminates, so that a restarted unit does
+/// not share its write-ahead log and snapshot directory with the tasks of
+/// its predecessor, which would keep working on the old RIB.
+struct BackgroundTasks {
+    tasks: Mutex<JoinSet<()>>,
+    stop: watch::Sender<bool>,
+}
+
+impl Default for BackgroundTasks {
+    fn default() -> Self {
+        Self {
+            tasks: Default::default(),
+            stop: watch::Sender::new(false),
+        }
+    }
+}
+
+impl BackgroundTasks {
+    /// Spawns a task calling `tick` every `period`, the first time at
+    /// `start`.
+    fn spawn_every<F, Fut>(
+        &self,
+        start: tokio::time::Instant,
+        period: Duration,
+        mut tick: F,
+    ) where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let mut stop = self.stop.subscribe();
+        self.tasks.lock().unwrap().spawn(async move {
+            let mut interval = tokio::time::interval_at(start, period);
+            interval.set_missed_tick_behavior(
+                tokio::time::MissedTickBehavior::Skip,
+            );
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => tick().await,
+                    _ = stop.wait_for(|stop| *stop) => break,
+                }
+            }
+        });
+    }
+
+    /// Stops the tasks and waits for them to finish.
+    ///
+    /// A tick in progress is completed, so that, e.g., a snapshot is not
+    /// left half written.
+    async fn stop(&self) {
+        self.stop.send_replace(true);
+        let mut tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        while tasks.join_next().await.is_some() {}
+    }
+}
+
+/// The ingress, and optionally the single address family, that went down.
+type StaleKey = (ingress::IngressId, Option<AfiSafiType>);
+
 pub type QueryId = Uuid;
 pub type QueryOperationResult = Result<QueryResult<RotondaPaMap>, String>;
 pub type QueryOperationResultSender = oneshot::Sender<QueryOperationResult>;
@@ -292,10 +608,26 @@ impl RibUnitRunner {
         filter_name: FilterName,
         rib_type: RibType,
         vrib_upstream: Option<Link>,
+        index_config: &IndexConfig,
+        best_path: Option<BestPathConfig>,
+        peer_down: PeerDownConfig,
+        memory_limit: Option<MemoryConfig>,
+        named_ribs: Vec<String>,
+        shards: &ShardConfig,
+        flap_damping: Option<FlapDampingConfig>,
     ) -> Result<Self, PrefixStoreError> {
         let unit_name = component.name().clone();
         let gate = Arc::new(gate);
-        let rib = Arc::new(ArcSwap::from_pointee(Rib::new_physical()?));
+        let memory_usage: Arc<MemoryUsage> = Default::default();
+        let rib = Arc::new(ArcSwap::from_pointee(
+            Rib::new_physical(shards)?
+                .with_indexes(index_config)
+                .with_best_path(best_path.is_some())
+                .with_memory_usage(memory_usage.clone()),
+        ));
+        let best_path_output_enabled = best_path.is_some();
+        let best_path_output =
+            best_path.map(|cfg| cfg.output).unwrap_or_default();
         let rib_merge_update_stats: Arc<RibMergeUpdateStatistics> =
             Default::default();
         let pending_vrib_query_results = Arc::new(FrimMap::default());
@@ -307,9 +639,15 @@ impl RibUnitRunner {
         let metrics = Arc::new(RibUnitMetrics::new(
             &gate,
             rib_merge_update_stats.clone(),
+            memory_usage.clone(),
         ));
         component.register_metrics(metrics.clone());
 
+        let flap_damping = Arc::new(
+            flap_damping.map(FlapDamping::new).unwrap_or_default(),
+        );
+        component.register_metrics(flap_damping.clone());
+
         // Setup status reporting
         let status_reporter =
             Arc::new(RibUnitStatusReporter::new(&unit_name, metrics.clone()));
@@ -345,36 +683,45 @@ impl RibUnitRunner {
             );
         }
 
-        let roto_compiled = component.roto_compiled().clone();
-        let roto_function_pre: Option<RotoFuncPre> =
-            roto_compiled.clone().and_then(|c| {
-                let mut c = c.lock().unwrap();
-                c.get_function(ROTO_FUNC_PRE_FILTER_NAME)
-                .inspect_err(|_|
-                    warn!("Loaded Roto script has no filter for rib_in_pre")
-                )
-                .ok()
-            });
-
-        let roto_function_vrp_update: Option<RotoFuncVrpUpdate> =
-            roto_compiled.clone().and_then(|c| {
-                let mut c = c.lock().unwrap();
-                c.get_function(ROTO_FUNC_VRP_UPDATE_FILTER_NAME)
-                .inspect_err(|_|
-                    warn!("Loaded Roto script has no filter for rib_in_vrp_update")
-                )
-                .ok()
-            });
-
-        let roto_function_vrp_update_post: Option<RotoFuncRovStatusUpdate> =
-            roto_compiled.clone().and_then(|c| {
-                let mut c = c.lock().unwrap();
-                c.get_function(ROTO_FUNC_ROV_STATUS_UPDATE_NAME)
-                .inspect_err(|_|
-                    warn!("Loaded Roto script has no filter for rib_in_vrp_update_post")
-                )
-                .ok()
-            });
+        let named_ribs = match rib_type {
+            RibType::Physical => named_ribs
+                .into_iter()
+                .map(|name| {
+                    let rib = Arc::new(ArcSwap::from_pointee(
+                        Rib::new_physical(shards)?
+                            .with_indexes(index_config)
+                            .with_best_path(best_path_output_enabled)
+                            .with_memory_usage(memory_usage.clone()),
+                    ));
+                    let http_api_path =
+                        Arc::new(format!("{http_api_path}ribs/{name}/"));
+                    let http_processor = Arc::new(PrefixesApi::new(
+                        rib.clone(),
+                        http_api_path.clone(),
+                        query_limits.clone(),
+                        RibType::Physical,
+                        None,
+                        pending_vrib_query_results.clone(),
+                        component.ingresses(),
+                    ));
+                    component.register_sub_http_resource(
+                        http_processor.clone(),
+                        &http_api_path,
+                    );
+                    Ok(NamedRib {
+                        name: name.into(),
+                        rib,
+                        http_processor,
+                    })
+                })
+                .collect::<Result<Vec<_>, PrefixStoreError>>()?,
+            _ => {
+                if !named_ribs.is_empty() {
+                    warn!("Ignoring named RIBs for virtual RIB");
+                }
+                vec![]
+            }
+        };
 
         // The rib-in-post filter is not used yet.
         let roto_function_post: Option<RotoFuncPost> = None;
@@ -392,135 +739,1090 @@ impl RibUnitRunner {
 
         let mut roto_context = Ctx::new(
             RotoOutputStream::new_rced(),
-            rtr_cache.clone()
+            rtr_cache.clone(),
+            Default::default(),
         );
+        roto_context.flaps = flap_damping.clone();
+        roto_context.state = component.roto_state();
+        roto_context.logger =
+            Arc::new(ScriptLogger::new(component.name().clone()));
+
+        let roto_filters = Arc::new(Reloadable::new(
+            component.roto().clone(),
+            RibFilters::load,
+            &mut roto_context,
+        ));
+
+        let tracer = component.tracer().clone();
+
+        Ok(Self {
+            roto_filters,
+            roto_function_post,
+            roto_context: Arc::new(Mutex::new(roto_context)),
+            gate,
+            http_processor,
+            query_limits,
+            rib,
+            rib_type,
+            best_path_output,
+            peer_down: Arc::new(ArcSwap::from_pointee(peer_down)),
+            stale_ingresses: Default::default(),
+            memory_limit,
+            memory_limit_state: Default::default(),
+            rebuild_lock: Default::default(),
+            wal: None,
+            unsynced: Default::default(),
+            history: None,
+            named_ribs,
+            rtr_cache,
+            flap_damping,
+            shadow: Default::default(),
+            filter_tracer: None,
+            ingress_register: component.ingresses(),
+            status_reporter,
+            filter_name,
+            pending_vrib_query_results,
+            _process_metrics,
+            rib_merge_update_stats,
+            tracer,
+            background_tasks: Default::default(),
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn mock(
+        roto_script: &str,
+        rib_type: RibType,
+    ) -> Result<(Self, crate::comms::GateAgent), PrefixStoreError> {
+        //use crate::common::roto::RotoScriptOrigin;
+
+        use crate::roto_runtime::types::RotoScripts;
+
+        let roto_scripts = RotoScripts::default();
+        let (gate, gate_agent) = Gate::new(0);
+        let gate = gate.into();
+        let query_limits =
+            Arc::new(ArcSwap::from_pointee(QueryLimits::default()));
+        let rib = Rib::new_physical(&Default::default())?;
+        let status_reporter = RibUnitStatusReporter::default().into();
+        let pending_vrib_query_results = Arc::new(FrimMap::default());
+        let filter_name =
+            Arc::new(ArcSwap::from_pointee(FilterName::default()));
+        let _process_metrics = Arc::new(TokioTaskMetrics::new());
+        let rib_merge_update_stats: Arc<RibMergeUpdateStatistics> =
+            Default::default();
+
+        let shared_rib = Arc::new(ArcSwap::new(Arc::new(rib)));
+        let ingress_register = Arc::new(ingress::Register::new());
+        let http_processor = Arc::new(PrefixesApi::new(
+            shared_rib.clone(),
+            Arc::new("/prefixes/".to_string()),
+            query_limits.clone(),
+            rib_type,
+            None,
+            pending_vrib_query_results.clone(),
+            ingress_register.clone(),
+        ));
+        let tracer = Arc::new(Tracer::new());
+
+        let runner = Self {
+            gate,
+            http_processor,
+            query_limits,
+            rib: shared_rib,
+            rib_type,
+            best_path_output: BestPathOutput::default(),
+            peer_down: Default::default(),
+            stale_ingresses: Default::default(),
+            memory_limit: None,
+            memory_limit_state: Default::default(),
+            rebuild_lock: Default::default(),
+            wal: None,
+            unsynced: Default::default(),
+            history: None,
+            named_ribs: vec![],
+            status_reporter,
+            rtr_cache: Default::default(),
+            flap_damping: Default::default(),
+            shadow: Default::default(),
+            filter_tracer: None,
+            filter_name,
+            pending_vrib_query_results,
+            _process_metrics,
+            rib_merge_update_stats,
+            tracer,
+            background_tasks: Default::default(),
+            roto_filters: Arc::new(Reloadable::fixed(Default::default())),
+            roto_function_post: None,
+            ingress_register,
+            roto_context: Arc::new(Mutex::new(Ctx::empty())),
+        };
+
+        Ok((runner, gate_agent))
+    }
 
-        if let Some(c) = roto_compiled.clone() {
-            roto_context.prepare(&mut c.lock().unwrap());
+    fn http_api_path_for_rib_type(
+        http_api_path: &str,
+        rib_type: RibType,
+    ) -> (Arc<String>, bool) {
+        let http_api_path = http_api_path.trim_end_matches('/').to_string();
+        let (http_api_path, is_sub_resource) = match rib_type {
+            RibType::Physical | RibType::Virtual => {
+                (Arc::new(format!("{http_api_path}/")), false)
+            }
+            RibType::GeneratedVirtual(index) => {
+                (Arc::new(format!("{http_api_path}/{index}/")), true)
+            }
+        };
+        (http_api_path, is_sub_resource)
+    }
+
+    #[cfg(test)]
+    pub(super) fn status_reporter(&self) -> Arc<RibUnitStatusReporter> {
+        self.status_reporter.clone()
+    }
+
+    #[cfg(test)]
+    pub(super) fn gate(&self) -> Arc<Gate> {
+        self.gate.clone()
+    }
+
+    #[cfg(test)]
+    pub(super) fn rib(&self) -> Arc<Rib> {
+        self.rib.load().clone()
+    }
+
+    #[cfg(test)]
+    pub(super) fn http_processor(&self) -> Arc<PrefixesApi> {
+        self.http_processor.clone()
+    }
+
+    #[cfg(test)]
+    pub(super) fn ingresses(&self) -> Arc<ingress::Register> {
+        self.ingress_register.clone()
+    }
+
+    #[cfg(test)]
+    pub(super) async fn stop_background_tasks(&self) {
+        self.background_tasks.stop().await
+    }
+
+    /// Replace the (empty) mock RIB by one with the given indexes enabled.
+    #[cfg(test)]
+    pub(super) fn enable_indexes(&self, config: &IndexConfig) {
+        self.rib.store(Arc::new(
+            Rib::new_physical(&Default::default())
+                .unwrap()
+                .with_indexes(config),
+        ));
+    }
+
+    /// Replace the (empty) mock RIB by one sharded as given by `config`.
+    #[cfg(test)]
+    pub(super) fn rebuild_lock(&self) -> Arc<RwLock<()>> {
+        self.rebuild_lock.clone()
+    }
+
+    #[cfg(test)]
+    pub(super) fn enable_shards(&self, config: &ShardConfig) {
+        self.rib.store(Arc::new(Rib::new_physical(config).unwrap()));
+    }
+
+    /// Replace the (empty) mock RIB by one with best path selection enabled.
+    #[cfg(test)]
+    pub(super) fn enable_best_path(&mut self, output: BestPathOutput) {
+        self.rib.store(Arc::new(
+            Rib::new_physical(&Default::default())
+                .unwrap()
+                .with_best_path(true),
+        ));
+        self.best_path_output = output;
+    }
+
+    /// Add a named RIB, returning the HTTP processor to query it with.
+    #[cfg(test)]
+    pub(super) fn add_named_rib(&mut self, name: &str) -> Arc<PrefixesApi> {
+        let rib = Arc::new(ArcSwap::from_pointee(
+            Rib::new_physical(&Default::default()).unwrap(),
+        ));
+        let http_processor = Arc::new(PrefixesApi::new(
+            rib.clone(),
+            Arc::new(format!("/prefixes/ribs/{name}/")),
+            self.query_limits.clone(),
+            RibType::Physical,
+            None,
+            self.pending_vrib_query_results.clone(),
+            self.ingress_register.clone(),
+        ));
+        self.named_ribs.push(NamedRib {
+            name: name.into(),
+            rib,
+            http_processor: http_processor.clone(),
+        });
+        http_processor
+    }
+
+    #[cfg(test)]
+    pub(super) fn set_roto_function_pre(&mut self, roto_function: RotoFuncPre) {
+        self.roto_filters = Arc::new(Reloadable::fixed(RibFilters {
+            pre: Some(roto_function),
+            ..Default::default()
+        }));
+    }
+
+    #[cfg(test)]
+    pub(super) fn set_flap_damping(&mut self, config: FlapDampingConfig) {
+        self.flap_damping = Arc::new(FlapDamping::new(config));
+        self.roto_context.lock().unwrap().flaps = self.flap_damping.clone();
+    }
+
+    #[cfg(test)]
+    pub(super) fn set_memory_limit(&mut self, config: MemoryConfig) {
+        self.memory_limit = Some(config);
+    }
+
+    #[cfg(test)]
+    pub(super) fn set_peer_down(&self, config: PeerDownConfig) {
+        self.peer_down.store(Arc::new(config));
+    }
+
+    fn signal_withdraw(
+        &self,
+        ingress_id: ingress::IngressId,
+        specific_afisafi: Option<AfiSafiType>,
+    ) {
+        let peer_down = self.peer_down.load();
+        match peer_down.action {
+            PeerDownAction::Withdraw => {
+                let _rebuild_guard = self.rebuild_lock.read().unwrap();
+                for rib in self.all_ribs() {
+                    rib.load()
+                        .withdraw_for_ingress(ingress_id, specific_afisafi);
+                }
+                self.log_change(&WalEntry::WithdrawIngress {
+                    ingress_id,
+                    afisafi: specific_afisafi,
+                });
+            }
+            PeerDownAction::Stale => self.mark_stale(
+                ingress_id,
+                specific_afisafi,
+                peer_down.expire_after_secs,
+            ),
+            PeerDownAction::Retain => {
+                debug!("Retaining routes of ingress {ingress_id}");
+            }
         }
+    }
 
-        let tracer = component.tracer().clone();
+    /// Append `entry` to the write-ahead log and the route history, if
+    /// either is enabled.
+    ///
+    /// This must be called while holding the rebuild lock used for making
+    /// the change, so the change cannot end up in a snapshot taken before
+    /// the log is rotated, while being logged after the rotation, or vice
+    /// versa. The entry is synced by [`Self::wait_for_wal`], after letting
+    /// go of the lock.
+    fn log_change(&self, entry: &WalEntry) {
+        let appended = Self::record_change(
+            self.wal.as_deref(),
+            self.history.as_deref(),
+            &self.status_reporter,
+            entry,
+        );
+        self.unsynced.lock().unwrap().extend(appended);
+    }
+
+    /// Wait for the changes logged so far to be synced to disk, as far as
+    /// the sync mode of the write-ahead log asks for.
+    async fn wait_for_wal(&self) {
+        let unsynced = std::mem::take(&mut *self.unsynced.lock().unwrap());
+        for appended in unsynced {
+            Self::wal_synced(appended, &self.status_reporter).await;
+        }
+    }
+
+    fn record_change(
+        wal: Option<&Wal>,
+        history: Option<&RouteHistory>,
+        status_reporter: &RibUnitStatusReporter,
+        entry: &WalEntry,
+    ) -> Option<wal::Appended> {
+        let appended = wal.and_then(|wal| {
+            wal.append(entry)
+                .inspect_err(|err| status_reporter.wal_write_failed(err))
+                .ok()
+        });
+        if let Some(history) = history {
+            history.record(entry);
+        }
+        appended
+    }
+
+    async fn wal_synced(
+        appended: wal::Appended,
+        status_reporter: &RibUnitStatusReporter,
+    ) {
+        match appended.synced().await {
+            Ok(()) => status_reporter.wal_entry_written(),
+            Err(err) => status_reporter.wal_write_failed(err),
+        }
+    }
+
+    /// Start keeping the history of the routes in the main RIB.
+    pub(super) fn enable_history(&mut self, config: HistoryConfig) {
+        if self.rib_type != RibType::Physical {
+            warn!("Ignoring history configuration for virtual RIB");
+            return;
+        }
+        let history = Arc::new(RouteHistory::new(config));
+        self.http_processor.set_history(history.clone());
+        self.history = Some(history);
+    }
+
+    /// Allow tracing the roto filter via the HTTP API.
+    pub(super) fn enable_filter_trace(
+        &mut self,
+        config: FilterTraceConfig,
+        unit_name: Arc<str>,
+    ) {
+        let tracer = Arc::new(FilterTracer::new(
+            config,
+            unit_name,
+            self.roto_filters.clone(),
+            self.roto_context.clone(),
+        ));
+        self.http_processor.set_filter_tracer(tracer.clone());
+        self.filter_tracer = Some(tracer);
+    }
+
+    /// Answer GraphQL queries via the HTTP API.
+    pub(super) fn enable_graphql(
+        &self,
+        config: GraphQlConfig,
+        metrics: Option<metrics::Collection>,
+        http_resources: http::Resources,
+    ) {
+        self.http_processor.set_graphql(Arc::new(GraphQlApi::new(
+            config,
+            self.rib.clone(),
+            self.rib_type,
+            self.ingress_register.clone(),
+            metrics,
+            http_resources,
+        )));
+    }
+
+    /// Run the roto filter in shadow mode, or stop doing so.
+    pub(super) fn set_shadow(&self, shadow: Option<ShadowConfig>) {
+        self.shadow.store(shadow.map(Arc::new));
+    }
+
+    /// Use the traffic counted by a flow-in unit.
+    pub(super) fn enable_traffic(&mut self, traffic: Arc<TrafficCounters>) {
+        self.http_processor.set_traffic(traffic.clone());
+        for named in &self.named_ribs {
+            named.http_processor.set_traffic(traffic.clone());
+        }
+        self.roto_context.lock().unwrap().traffic = traffic;
+    }
+
+    /// The main RIB followed by the named RIBs.
+    fn all_ribs(&self) -> Vec<Arc<ArcSwap<Rib>>> {
+        std::iter::once(self.rib.clone())
+            .chain(self.named_ribs.iter().map(|named| named.rib.clone()))
+            .collect()
+    }
+
+    /// Mark the routes of an ingress that went down as stale, and spawn a
+    /// task that withdraws those not re-announced by the time they expire.
+    fn mark_stale(
+        &self,
+        ingress_id: ingress::IngressId,
+        specific_afisafi: Option<AfiSafiType>,
+        expire_after: Duration,
+    ) {
+        let ribs = self.all_ribs();
+        let mut marked = 0;
+        for (i, rib) in ribs.iter().enumerate() {
+            let _rebuild_guard = self.rebuild_lock.read().unwrap();
+            let rib = rib.load();
+            let entry = match rib.mark_stale(ingress_id, specific_afisafi) {
+                Ok(n) => {
+                    marked += n;
+                    WalEntry::MarkStale {
+                        ingress_id,
+                        afisafi: specific_afisafi,
+                    }
+                }
+                Err(err) => {
+                    error!(
+                        "Failed to mark routes of ingress {ingress_id} as stale, withdrawing them instead: {err}"
+                    );
+                    rib.withdraw_for_ingress(ingress_id, specific_afisafi);
+                    WalEntry::WithdrawIngress {
+                        ingress_id,
+                        afisafi: specific_afisafi,
+                    }
+                }
+            };
+            // Only the main RIB, which comes first, is logged.
+            if i == 0 {
+                self.log_change(&entry);
+            }
+        }
+        self.status_reporter.routes_marked_stale(ingress_id, marked);
+
+        // If the ingress goes down again before the routes expire, the
+        // expiry is pushed back and covers the routes of both times.
+        let key = (ingress_id, specific_afisafi);
+        let since = Instant::now();
+        {
+            let mut stale_ingresses = self.stale_ingresses.lock().unwrap();
+            let entry = stale_ingresses.entry(key).or_insert((since, 0));
+            *entry = (since, entry.1 + marked);
+        }
+
+        let stale_ingresses = self.stale_ingresses.clone();
+        let rebuild_lock = self.rebuild_lock.clone();
+        let wal = self.wal.clone();
+        let history = self.history.clone();
+        let status_reporter = self.status_reporter.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(expire_after).await;
+            let marked = {
+                let mut stale_ingresses = stale_ingresses.lock().unwrap();
+                match stale_ingresses.get(&key) {
+                    Some((t, marked)) if *t == since => {
+                        let marked = *marked;
+                        stale_ingresses.remove(&key);
+                        marked
+                    }
+                    _ => return,
+                }
+            };
+            let mut expired = 0;
+            let mut appended = None;
+            for (i, rib) in ribs.iter().enumerate() {
+                let _rebuild_guard = rebuild_lock.read().unwrap();
+                match rib.load().expire_stale(ingress_id, specific_afisafi) {
+                    Ok(n) => expired += n,
+                    Err(err) => error!(
+                        "Failed to withdraw stale routes of ingress {ingress_id}: {err}"
+                    ),
+                }
+                if i == 0 {
+                    appended = Self::record_change(
+                        wal.as_deref(),
+                        history.as_deref(),
+                        &status_reporter,
+                        &WalEntry::ExpireStale {
+                            ingress_id,
+                            afisafi: specific_afisafi,
+                        },
+                    );
+                }
+            }
+            if let Some(appended) = appended {
+                Self::wal_synced(appended, &status_reporter).await;
+            }
+            status_reporter.stale_routes_expired(ingress_id, marked, expired);
+        });
+    }
+
+    /// Load the configured snapshot into the RIB.
+    ///
+    /// The routes are registered under new ingresses that are children of a
+    /// single bootstrap ingress, so they can be withdrawn in one go once
+    /// `expire_after_secs` has passed.
+    pub(super) async fn bootstrap(&self, config: BootstrapConfig) {
+        if self.rib_type != RibType::Physical {
+            warn!("Ignoring bootstrap configuration for virtual RIB");
+            return;
+        }
+
+        let path: PathBuf = config.path.into();
+        let parent_id = self.ingress_register.register();
+        self.ingress_register.update_info(
+            parent_id,
+            IngressInfo::new()
+                .with_unit_name(self.status_reporter.name())
+                .with_filename(path.clone())
+                .with_desc("rib bootstrap snapshot"),
+        );
+
+        let t0 = Instant::now();
+        let rib = self.rib.load_full();
+        let ingresses = self.ingress_register.clone();
+        let format = config.format;
+        let load_path = path.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            snapshot::load(&load_path, format, &rib, &ingresses, parent_id)
+        })
+        .await;
+
+        match res {
+            Ok(Ok(routes)) => {
+                self.status_reporter.bootstrap_loaded(
+                    path.display(),
+                    routes,
+                    t0.elapsed(),
+                );
+            }
+            Ok(Err(err)) => {
+                self.status_reporter.bootstrap_failed(path.display(), err);
+                return;
+            }
+            Err(err) => {
+                self.status_reporter.bootstrap_failed(path.display(), err);
+                return;
+            }
+        }
+
+        if let Some(expire_after) = config.expire_after_secs {
+            self.spawn_expiry(
+                parent_id,
+                expire_after,
+                RibUnitStatusReporter::bootstrap_expired,
+            );
+        }
+    }
+
+    /// Spawn a task that withdraws the routes of the children of
+    /// `parent_id` once `expire_after` has passed, and then calls `report`
+    /// with the number of ingresses withdrawn.
+    fn spawn_expiry(
+        &self,
+        parent_id: ingress::IngressId,
+        expire_after: Duration,
+        report: fn(&RibUnitStatusReporter, usize),
+    ) {
+        let rib = self.rib.clone();
+        let rebuild_lock = self.rebuild_lock.clone();
+        let wal = self.wal.clone();
+        let history = self.history.clone();
+        let ingresses = self.ingress_register.clone();
+        let status_reporter = self.status_reporter.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(expire_after).await;
+            let ids = ingresses.ids_for_parent(parent_id);
+            let unsynced = {
+                let _rebuild_guard = rebuild_lock.read().unwrap();
+                ids.iter()
+                    .filter_map(|id| {
+                        rib.load().withdraw_for_ingress(*id, None);
+                        Self::record_change(
+                            wal.as_deref(),
+                            history.as_deref(),
+                            &status_reporter,
+                            &WalEntry::WithdrawIngress {
+                                ingress_id: *id,
+                                afisafi: None,
+                            },
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            };
+            for appended in unsynced {
+                Self::wal_synced(appended, &status_reporter).await;
+            }
+            report(&status_reporter, ids.len());
+        });
+    }
+
+    /// Restore the RIB from the most recent snapshot and the write-ahead
+    /// log, and start logging the changes made to the RIB from now on.
+    ///
+    /// The log is only truncated when a snapshot is written, so it is not
+    /// used without `snapshot` being configured. When there is no snapshot
+    /// to start from, `bootstrap` is loaded instead, if given.
+    pub(super) async fn recover(
+        &mut self,
+        disk: &DiskStorageConfig,
+        snapshot: Option<&SnapshotConfig>,
+        bootstrap: Option<BootstrapConfig>,
+    ) {
+        let (Some(snapshot), RibType::Physical) = (snapshot, self.rib_type)
+        else {
+            warn!(
+                "Not using a write-ahead log without periodic snapshots of a physical RIB"
+            );
+            if let Some(bootstrap) = bootstrap {
+                self.bootstrap(bootstrap).await;
+            }
+            return;
+        };
+
+        let unit_name = self.status_reporter.name().to_string();
+        let latest_snapshot =
+            match snapshot::list(&snapshot.directory, &unit_name) {
+                Ok(snapshots) => snapshots.into_iter().last(),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+                Err(err) => {
+                    self.status_reporter.recovery_failed(err);
+                    return;
+                }
+            };
+        let segments = match wal::segments(&disk.path, &unit_name) {
+            Ok(segments) => segments,
+            Err(err) => {
+                self.status_reporter.recovery_failed(err);
+                return;
+            }
+        };
+
+        // Open the log first, so changes made while bootstrapping are
+        // logged as well.
+        match Wal::open(
+            &disk.path,
+            &unit_name,
+            disk.sync_mode,
+            self.ingress_register.clone(),
+        ) {
+            Ok(wal) => self.wal = Some(Arc::new(wal)),
+            Err(err) => {
+                self.status_reporter.recovery_failed(err);
+                return;
+            }
+        }
+
+        if latest_snapshot.is_none() {
+            if let Some(bootstrap) = bootstrap {
+                self.bootstrap(bootstrap).await;
+            }
+            if segments.is_empty() {
+                return;
+            }
+        }
+
+        let parent_id = self.ingress_register.register();
+        self.ingress_register.update_info(
+            parent_id,
+            IngressInfo::new()
+                .with_unit_name(self.status_reporter.name())
+                .with_filename(disk.path.clone())
+                .with_desc("rib recovery"),
+        );
+
+        let t0 = Instant::now();
+        let rib = self.rib.load_full();
+        let ingresses = self.ingress_register.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            let routes = match latest_snapshot {
+                Some(latest) => snapshot::load(
+                    &latest.path,
+                    snapshot::SnapshotFormat::Native,
+                    &rib,
+                    &ingresses,
+                    parent_id,
+                )?,
+                None => 0,
+            };
+            let entries = wal::replay(&segments, &rib, &ingresses, parent_id)?;
+            Ok::<_, std::io::Error>((routes, entries))
+        })
+        .await;
+
+        match res {
+            Ok(Ok((routes, entries))) => {
+                self.status_reporter.recovered(routes, entries, t0.elapsed());
+            }
+            Ok(Err(err)) => {
+                self.status_reporter.recovery_failed(err);
+                return;
+            }
+            Err(err) => {
+                self.status_reporter.recovery_failed(err);
+                return;
+            }
+        }
+
+        if let Some(expire_after) = disk.recovery_expire_after_secs {
+            self.spawn_expiry(
+                parent_id,
+                Duration::from_secs(expire_after),
+                RibUnitStatusReporter::recovery_expired,
+            );
+        }
+    }
+
+    /// Spawn a task that periodically writes a snapshot of the RIB.
+    ///
+    /// The snapshot is written on a blocking thread, reading from the store
+    /// concurrently with ongoing updates, so the update path is not held up
+    /// other than for rotating the write-ahead log.
+    pub(super) fn spawn_snapshotter(&self, config: SnapshotConfig) {
+        if self.rib_type != RibType::Physical {
+            warn!("Ignoring snapshot configuration for virtual RIB");
+            return;
+        }
+        if config.interval_secs.is_zero() {
+            warn!("Ignoring snapshot configuration with zero interval");
+            return;
+        }
+
+        let rib = self.rib.clone();
+        let wal = self.wal.clone();
+        let rebuild_lock = self.rebuild_lock.clone();
+        let ingresses = self.ingress_register.clone();
+        let status_reporter = self.status_reporter.clone();
+        let unit_name = self.status_reporter.name().to_string();
+        self.http_processor.set_snapshots(snapshot::SnapshotLocation {
+            directory: config.directory.to_path_buf(),
+            unit_name: unit_name.clone(),
+        });
+
+        let period = config.interval_secs;
+        let start = tokio::time::Instant::now() + period;
+        self.background_tasks.spawn_every(start, period, move || {
+            let t0 = Instant::now();
+            let rib = rib.clone();
+            let wal = wal.clone();
+            let rebuild_lock = rebuild_lock.clone();
+            let ingresses = ingresses.clone();
+            let config = config.clone();
+            let unit_name = unit_name.clone();
+            let status_reporter = status_reporter.clone();
+            async move {
+                let reporter = status_reporter.clone();
+                let res = tokio::task::spawn_blocking(move || {
+                    Self::write_snapshot(
+                        &rib,
+                        wal.as_deref(),
+                        &rebuild_lock,
+                        &config,
+                        &unit_name,
+                        &ingresses,
+                        &reporter,
+                    )
+                })
+                .await;
+                match res {
+                    Ok(Ok(written)) => {
+                        status_reporter.snapshot_written(
+                            written.path.display(),
+                            written.routes,
+                            written.size,
+                            t0.elapsed(),
+                        );
+                    }
+                    Ok(Err(err)) => status_reporter.snapshot_failed(err),
+                    Err(err) => status_reporter.snapshot_failed(err),
+                }
+            }
+        });
+    }
+
+    /// Write a snapshot of `rib`.
+    ///
+    /// The write-ahead log is rotated right before, so that the segments
+    /// before the new one only hold changes that are in the snapshot. Those
+    /// segments are removed once the snapshot is safely on disk.
+    pub(super) fn write_snapshot(
+        rib: &ArcSwap<Rib>,
+        wal: Option<&Wal>,
+        rebuild_lock: &RwLock<()>,
+        config: &SnapshotConfig,
+        unit_name: &str,
+        ingresses: &ingress::Register,
+        status_reporter: &RibUnitStatusReporter,
+    ) -> std::io::Result<snapshot::WrittenSnapshot> {
+        let rotated = wal.and_then(|wal| {
+            let _rebuild_guard = rebuild_lock.write().unwrap();
+            wal.rotate()
+                .inspect_err(|err| status_reporter.wal_write_failed(err))
+                .ok()
+        });
+        let written =
+            snapshot::write_to_dir(config, unit_name, &rib.load(), ingresses)?;
+        if let (Some(wal), Some(seq)) = (wal, rotated) {
+            if let Err(err) = wal.remove_before(seq) {
+                warn!("Failed to remove write-ahead log segments: {err}");
+            }
+        }
+        Ok(written)
+    }
+
+    /// Spawn a task that compacts the on-disk state of the RIB whenever one
+    /// of the triggers in `disk` is met, and allow compacting on request
+    /// via the HTTP API.
+    ///
+    /// Compaction writes a snapshot as configured by `snapshot`. Without a
+    /// write-ahead log, see [`Self::recover`], there is nothing to compact.
+    pub(super) fn spawn_compactor(
+        &self,
+        disk: DiskStorageConfig,
+        snapshot: SnapshotConfig,
+    ) {
+        let Some(compactor) = self.enable_compactor(disk, snapshot) else {
+            return;
+        };
+        let Some(period) = compactor.check_interval() else {
+            return;
+        };
+        let status_reporter = self.status_reporter.clone();
+
+        let start = tokio::time::Instant::now() + period;
+        self.background_tasks.spawn_every(start, period, move || {
+            let compactor = compactor.clone();
+            let status_reporter = status_reporter.clone();
+            async move {
+                // Failures to compact are reported by the compactor.
+                let res = tokio::task::spawn_blocking(move || {
+                    compactor.compact_if_due()
+                })
+                .await;
+                if let Err(err) = res {
+                    status_reporter.compaction_failed(err);
+                }
+            }
+        });
+    }
 
-        Ok(Self {
-            roto_function_pre,
-            roto_function_vrp_update,
-            roto_function_vrp_update_post,
-            roto_function_post,
-            roto_context: Arc::new(Mutex::new(roto_context)),
-            gate,
-            http_processor,
-            query_limits,
-            rib,
-            rib_type,
-            rtr_cache,
-            ingress_register: component.ingresses(),
-            status_reporter,
-            filter_name,
-            pending_vrib_query_results,
-            _process_metrics,
-            rib_merge_update_stats,
-            tracer,
-        })
+    /// Create a compactor for the write-ahead log of the RIB, if any, and
+    /// make it available via the HTTP API.
+    pub(super) fn enable_compactor(
+        &self,
+        disk: DiskStorageConfig,
+        snapshot: SnapshotConfig,
+    ) -> Option<Arc<Compactor>> {
+        let wal = self.wal.clone()?;
+        let compactor = Arc::new(Compactor::new(
+            disk,
+            snapshot,
+            self.status_reporter.name().to_string(),
+            self.rib.clone(),
+            wal,
+            self.rebuild_lock.clone(),
+            self.ingress_register.clone(),
+            self.status_reporter.clone(),
+        ));
+        self.http_processor.set_compactor(compactor.clone());
+        Some(compactor)
     }
 
     #[cfg(test)]
-    pub(crate) fn mock(
-        roto_script: &str,
-        rib_type: RibType,
-    ) -> Result<(Self, crate::comms::GateAgent), PrefixStoreError> {
-        //use crate::common::roto::RotoScriptOrigin;
-
-        use crate::roto_runtime::types::RotoScripts;
+    pub(super) fn take_snapshot(
+        &self,
+        config: &SnapshotConfig,
+    ) -> std::io::Result<snapshot::WrittenSnapshot> {
+        Self::write_snapshot(
+            &self.rib,
+            self.wal.as_deref(),
+            &self.rebuild_lock,
+            config,
+            self.status_reporter.name(),
+            &self.ingress_register,
+            &self.status_reporter,
+        )
+    }
 
-        let roto_scripts = RotoScripts::default();
-        let (gate, gate_agent) = Gate::new(0);
-        let gate = gate.into();
-        let query_limits =
-            Arc::new(ArcSwap::from_pointee(QueryLimits::default()));
-        let rib = Rib::new_physical()?;
-        let status_reporter = RibUnitStatusReporter::default().into();
-        let pending_vrib_query_results = Arc::new(FrimMap::default());
-        let filter_name =
-            Arc::new(ArcSwap::from_pointee(FilterName::default()));
-        let _process_metrics = Arc::new(TokioTaskMetrics::new());
-        let rib_merge_update_stats: Arc<RibMergeUpdateStatistics> =
-            Default::default();
+    /// Spawn a task that periodically purges withdrawn routes from the
+    /// RIBs of this unit.
+    ///
+    /// The RIBs are rebuilt on a blocking thread, while updates continue to
+    /// be applied, see [`gc`].
+    pub(super) fn spawn_garbage_collector(&self, config: GcConfig) {
+        if self.rib_type != RibType::Physical {
+            warn!("Ignoring gc configuration for virtual RIB");
+            return;
+        }
+        if config.interval_secs.is_zero() {
+            warn!("Ignoring gc configuration with zero interval");
+            return;
+        }
 
-        let shared_rib = Arc::new(ArcSwap::new(Arc::new(rib)));
-        let http_processor = Arc::new(PrefixesApi::new(
-            shared_rib.clone(),
-            Arc::new("dummy".to_string()),
-            query_limits.clone(),
-            rib_type,
-            None,
-            pending_vrib_query_results.clone(),
-            Arc::default(), // ingress::Register
+        let ribs = self.all_ribs();
+        let since = Arc::new(Mutex::new(
+            ribs.iter().map(|_| WithdrawnSince::default()).collect::<Vec<_>>(),
         ));
-        let tracer = Arc::new(Tracer::new());
+        let rebuild_lock = self.rebuild_lock.clone();
+        let status_reporter = self.status_reporter.clone();
+
+        let period = config.interval_secs;
+        let start = tokio::time::Instant::now() + period;
+        self.background_tasks.spawn_every(start, period, move || {
+            let ribs = ribs.clone();
+            let since = since.clone();
+            let rebuild_lock = rebuild_lock.clone();
+            let status_reporter = status_reporter.clone();
+            async move {
+                let res = tokio::task::spawn_blocking(move || {
+                    let mut since = since.lock().unwrap();
+                    Self::collect_garbage(
+                        &ribs,
+                        &rebuild_lock,
+                        &mut since,
+                        config.retention_secs,
+                        &status_reporter,
+                    )
+                })
+                .await;
+                if let Err(err) = res {
+                    error!("Garbage collection task failed: {err}");
+                }
+            }
+        });
+    }
 
-        let runner = Self {
-            gate,
-            http_processor,
-            query_limits,
-            rib: shared_rib,
-            rib_type,
-            status_reporter,
-            rtr_cache: Default::default(),
-            filter_name,
-            pending_vrib_query_results,
-            _process_metrics,
-            rib_merge_update_stats,
-            tracer,
-            roto_function_pre: None,
-            roto_function_vrp_update: None,
-            roto_function_post: None,
-            roto_function_vrp_update_post: None,
-            ingress_register: Arc::new(ingress::Register::new()),
-            roto_context: Arc::new(Mutex::new(Ctx::empty())),
-        };
+    /// Spawn a task that periodically computes the statistics about the
+    /// contents of the RIB reported in the metrics.
+    pub(super) fn spawn_stats_collector(&self, config: StatsConfig) {
+        if self.rib_type != RibType::Physical {
+            warn!("Ignoring stats configuration for virtual RIB");
+            return;
+        }
+        if config.interval_secs.is_zero() {
+            warn!("Ignoring stats configuration with zero interval");
+            return;
+        }
 
-        Ok((runner, gate_agent))
+        let rib = self.rib.clone();
+        let ingresses = self.ingress_register.clone();
+        let status_reporter = self.status_reporter.clone();
+
+        let period = config.interval_secs;
+        let start = tokio::time::Instant::now();
+        self.background_tasks.spawn_every(start, period, move || {
+            let t0 = Instant::now();
+            let rib = rib.load_full();
+            let ingresses = ingresses.clone();
+            let status_reporter = status_reporter.clone();
+            async move {
+                let res = tokio::task::spawn_blocking(move || {
+                    RibStats::compute(&rib, &ingresses, config.top_origins)
+                })
+                .await;
+                match res {
+                    Ok(Ok(stats)) => {
+                        status_reporter.stats_computed(stats, t0.elapsed())
+                    }
+                    Ok(Err(err)) => status_reporter.stats_failed(err),
+                    Err(err) => status_reporter.stats_failed(err),
+                }
+            }
+        });
     }
 
-    fn http_api_path_for_rib_type(
-        http_api_path: &str,
-        rib_type: RibType,
-    ) -> (Arc<String>, bool) {
-        let http_api_path = http_api_path.trim_end_matches('/').to_string();
-        let (http_api_path, is_sub_resource) = match rib_type {
-            RibType::Physical | RibType::Virtual => {
-                (Arc::new(format!("{http_api_path}/")), false)
-            }
-            RibType::GeneratedVirtual(index) => {
-                (Arc::new(format!("{http_api_path}/{index}/")), true)
+    /// Spawn a task that periodically checks that the side tables and
+    /// counters of the RIBs of this unit agree with their contents.
+    ///
+    /// The RIBs are walked on a blocking thread, while updates continue to
+    /// be applied, see [`consistency`].
+    pub(super) fn spawn_consistency_checker(
+        &self,
+        config: ConsistencyConfig,
+    ) {
+        if self.rib_type != RibType::Physical {
+            warn!("Ignoring consistency configuration for virtual RIB");
+            return;
+        }
+        if config.interval_secs.is_zero() {
+            warn!("Ignoring consistency configuration with zero interval");
+            return;
+        }
+
+        let checker = self.enable_consistency_checker(config);
+        let ribs = self.named_all_ribs();
+        let rebuild_lock = self.rebuild_lock.clone();
+        let status_reporter = self.status_reporter.clone();
+
+        let period = checker.config().interval_secs;
+        let start = tokio::time::Instant::now() + period;
+        self.background_tasks.spawn_every(start, period, move || {
+            let t0 = Instant::now();
+            let checker = checker.clone();
+            let ribs = ribs.clone();
+            let rebuild_lock = rebuild_lock.clone();
+            let status_reporter = status_reporter.clone();
+            async move {
+                let res = tokio::task::spawn_blocking(move || {
+                    checker.check(&ribs, &rebuild_lock)
+                })
+                .await;
+                match res {
+                    Ok(Ok(report)) => status_reporter
+                        .consistency_checked(report, t0.elapsed()),
+                    Ok(Err(err)) => status_reporter.consistency_check_failed(err),
+                    Err(err) => status_reporter.consistency_check_failed(err),
+                }
             }
-        };
-        (http_api_path, is_sub_resource)
+        });
     }
 
-    #[cfg(test)]
-    pub(super) fn status_reporter(&self) -> Arc<RibUnitStatusReporter> {
-        self.status_reporter.clone()
+    /// Make the reports of a consistency checker with `config` available
+    /// via the HTTP API.
+    fn enable_consistency_checker(
+        &self,
+        config: ConsistencyConfig,
+    ) -> Arc<ConsistencyChecker> {
+        let checker = Arc::new(ConsistencyChecker::new(config));
+        self.http_processor.set_consistency(checker.clone());
+        checker
     }
 
-    #[cfg(test)]
-    pub(super) fn gate(&self) -> Arc<Gate> {
-        self.gate.clone()
+    /// The main RIB followed by the named RIBs, with their names.
+    fn named_all_ribs(&self) -> Vec<CheckedRib> {
+        std::iter::once((None, self.rib.clone()))
+            .chain(self.named_ribs.iter().map(|named| {
+                (Some(named.name.clone()), named.rib.clone())
+            }))
+            .collect()
     }
 
-    #[cfg(test)]
-    pub(super) fn rib(&self) -> Arc<Rib> {
-        self.rib.load().clone()
+    /// Run the garbage collector once over `ribs`.
+    fn collect_garbage(
+        ribs: &[Arc<ArcSwap<Rib>>],
+        rebuild_lock: &RwLock<()>,
+        since: &mut [WithdrawnSince],
+        retention: Duration,
+        status_reporter: &RibUnitStatusReporter,
+    ) {
+        let t0 = Instant::now();
+        let mut purged = 0;
+        let mut reclaimed_bytes = 0;
+        for (rib, since) in ribs.iter().zip(since.iter_mut()) {
+            match gc::collect(rib, rebuild_lock, since, retention) {
+                Ok(Some(collected)) => {
+                    purged += collected.purged;
+                    reclaimed_bytes += collected.reclaimed_bytes;
+                }
+                Ok(None) => {}
+                Err(err) => status_reporter.garbage_collection_failed(err),
+            }
+        }
+        status_reporter.garbage_collected(purged, reclaimed_bytes, t0.elapsed());
     }
 
-    fn signal_withdraw(
+    #[cfg(test)]
+    pub(super) fn run_garbage_collector(
         &self,
-        ingress_id: ingress::IngressId,
-        specific_afisafi: Option<AfiSafiType>,
+        since: &mut Vec<WithdrawnSince>,
+        retention: Duration,
     ) {
-        self.rib
-            .load()
-            .withdraw_for_ingress(ingress_id, specific_afisafi);
+        let ribs = self.all_ribs();
+        since.resize_with(ribs.len(), Default::default);
+        Self::collect_garbage(
+            &ribs,
+            &self.rebuild_lock,
+            since,
+            retention,
+            &self.status_reporter,
+        );
+    }
+
+    #[cfg(test)]
+    pub(super) fn run_consistency_check(
+        &self,
+        config: ConsistencyConfig,
+    ) -> Arc<super::consistency::ConsistencyReport> {
+        let checker = self.enable_consistency_checker(config);
+        let report = checker
+            .check(&self.named_all_ribs(), &self.rebuild_lock)
+            .unwrap();
+        self.status_reporter
+            .consistency_checked(report.clone(), Duration::ZERO);
+        report
     }
 
     pub async fn run(
@@ -539,7 +1841,12 @@ impl RibUnitRunner {
         // components that we are, ready to start. All units and targets start
         // together, otherwise data passed from one component to another may
         // be lost if the receiving component is not yet ready to accept it.
-        arc_self.gate.process_until(waitpoint.ready()).await?;
+        if let Err(err) =
+            arc_self.gate.process_until(waitpoint.ready()).await
+        {
+            arc_self.background_tasks.stop().await;
+            return Err(err);
+        }
 
         // Signal again once we are out of the process_until() so that anyone
         // waiting to send important gate status updates won't send them while
@@ -552,6 +1859,9 @@ impl RibUnitRunner {
                 Ok(status) => {
                     arc_self.status_reporter.gate_status_announced(&status);
                     match status {
+                        // Only the settings in `RibUnit::RECONFIGURABLE`
+                        // are applied here. The manager restarts the unit
+                        // when any of the others change.
                         GateStatus::Reconfiguring {
                             new_config:
                                 Unit::RibUnit(RibUnit {
@@ -562,6 +1872,24 @@ impl RibUnitRunner {
                                     //rib_keys: new_rib_keys,
                                     rib_type: new_rib_type,
                                     vrib_upstream: new_vrib_upstream,
+                                    storage: _,
+                                    index: _,
+                                    best_path: _,
+                                    peer_down: new_peer_down,
+                                    memory: _,
+                                    named_ribs: _,
+                                    bootstrap: _,
+                                    snapshot: _,
+                                    gc: _,
+                                    history: _,
+                                    stats: _,
+                                    shards: _,
+                                    consistency: _,
+                                    traffic: _,
+                                    flap_damping: _,
+                                    shadow: new_shadow,
+                                    filter_trace: _,
+                                    graphql: _,
                                 }),
                         } => {
                             arc_self.status_reporter.reconfigured();
@@ -633,6 +1961,12 @@ impl RibUnitRunner {
                                 .query_limits
                                 .store(Arc::new(new_query_limits));
 
+                            arc_self
+                                .peer_down
+                                .store(Arc::new(new_peer_down));
+
+                            arc_self.set_shadow(new_shadow);
+
                             // Register as a direct update receiver with the new
                             // set of linked gates.
                             arc_self
@@ -691,6 +2025,7 @@ impl RibUnitRunner {
                 }
 
                 Err(Terminated) => {
+                    arc_self.background_tasks.stop().await;
                     arc_self.status_reporter.terminated();
                     return Err(Terminated);
                 }
@@ -723,10 +2058,12 @@ impl RibUnitRunner {
                 ingress_ids
                     .iter()
                     .for_each(|&id| self.signal_withdraw(id, None));
+                self.wait_for_wal().await;
             }
 
             Update::Withdraw(ingress_id, maybe_afisafi) => {
-                self.signal_withdraw(ingress_id, maybe_afisafi)
+                self.signal_withdraw(ingress_id, maybe_afisafi);
+                self.wait_for_wal().await;
             }
 
             Update::OutputStream(..) => {
@@ -1020,7 +2357,7 @@ impl RibUnitRunner {
 
                                     let mut apply_vrp_update = true;
                                     if let RtrPayload::Origin(vrp) = payload {
-                                        if let Some(ref vrp_update_filter) = self.roto_function_vrp_update {
+                                        if let Some(vrp_update_filter) = self.roto_filters().vrp_update {
                                             let vrp_update = VrpUpdate {
                                                 action, vrp
                                             };
@@ -1028,8 +2365,12 @@ impl RibUnitRunner {
                                             let osms;
                                             {
                                             let mut ctx = self.roto_context.lock().unwrap();
+                                            ctx.logger.set_ingress(None);
 
-                                            match vrp_update_filter.call(&mut ctx, roto::Val(vrp_update)) {
+                                            match filter_metrics::shared().run(
+                                                ROTO_FUNC_VRP_UPDATE_FILTER_NAME,
+                                                || vrp_update_filter.call(&mut ctx, roto::Val(vrp_update)),
+                                            ) {
                                                 Verdict::Accept(_) => { },
                                                 Verdict::Reject(_) => {
                                                     apply_vrp_update = false
@@ -1130,10 +2471,11 @@ impl RibUnitRunner {
                                                     }
                                                 }
                                             }
-                                            if let Some(ref vrp_update_post) = self.roto_function_vrp_update_post {
+                                            if let Some(vrp_update_post) = self.roto_filters().vrp_update_post {
                                                 let osms;
                                                 {
                                                 let mut ctx = self.roto_context.lock().unwrap();
+                                                ctx.logger.set_ingress(None);
                                                 if !rov_updates.is_empty() {
                                                     for rov_update in rov_updates {
                                                         vrp_update_post.call(&mut ctx, roto::Val(rov_update));
@@ -1212,40 +2554,60 @@ impl RibUnitRunner {
             };
                 
             let osms;
-            { // scope for lock
+            let mut routes = Vec::new();
+            let mut routed = SmallVec::<[Payload; 8]>::new();
+
+            // The context is only locked while the filter runs. The RIBs
+            // are updated after releasing it, so that updates from other
+            // sources can be filtered meanwhile.
+            let filtered = {
+            if let Some(tracer) = &self.filter_tracer {
+                tracer.sample(&p.rx_value, ingress_id);
+            }
             let mut ctx = self.roto_context.lock().unwrap();
+            self.record_flap(&p);
+            ctx.logger.set_ingress(ingress_id);
 
-            if let Some(ref roto_function) = self.roto_function_pre {
+            let filtered = if let Some(roto_function) = self.roto_filters.get(&mut ctx).pre {
                 let Payload{ rx_value, context, trace_id, received } = p;
+                // In shadow mode the route is passed on as it came in.
+                let shadow = self.shadow.load_full();
+                let original = shadow.as_ref().map(|_| rx_value.clone());
                 let mutrr: roto_runtime::MutRotondaRoute = rx_value.into();
-                match roto_function.call(&mut ctx, roto::Val(mutrr.clone())) {
-                    roto::Verdict::Accept(_) => {
-                        let modified_rr = std::rc::Rc::into_inner(mutrr).unwrap().into_inner();
-                        p = Payload {
-                            rx_value: modified_rr,
-                            context,
-                            trace_id,
-                            received,
-                        };
-                        self.insert_payload(&p);
-                        res.push(p.clone());
+                ctx.ribs.lock().unwrap().take();
+                ctx.tags.lock().unwrap().take();
+                ctx.output.borrow_mut().take_routes();
+                let verdict = filter_metrics::shared().run(
+                    ROTO_FUNC_PRE_FILTER_NAME,
+                    || roto_function.call(&mut ctx, roto::Val(mutrr.clone())),
+                );
+                let selected_ribs = ctx.ribs.lock().unwrap().take();
+                let tags = ctx.tags.lock().unwrap().take();
+                routes = ctx.output.borrow_mut().take_routes();
+                let modified_rr = std::rc::Rc::into_inner(mutrr).unwrap().into_inner();
+                let accepted = match (verdict, &shadow) {
+                    (roto::Verdict::Accept(_), _) => true,
+                    (roto::Verdict::Reject(_), Some(shadow)) => {
+                        shadow.rejected(ROTO_FUNC_PRE_FILTER_NAME, || {
+                            best_path::prefix_of(&modified_rr)
+                        });
+                        true
                     }
-                    roto::Verdict::Reject(_) => {
+                    (roto::Verdict::Reject(_), None) => {
                         //debug!("roto::Verdict Reject, dropping {p:#?}");
-                        let modified_rr = std::rc::Rc::into_inner(mutrr).unwrap().into_inner();
-                        p = Payload {
-                            rx_value: modified_rr,
-                            context,
-                            trace_id,
-                            received,
-                        };
+                        false
                     }
-                }
+                };
+                p = Payload {
+                    rx_value: original.unwrap_or(modified_rr),
+                    context,
+                    trace_id,
+                    received,
+                };
+                Some((accepted, selected_ribs, tags))
             } else {
-                // default action accept
-                self.insert_payload(&p);
-                res.push(p.clone());
-            }
+                None
+            };
 
             let mut output_stream  = ctx.output.borrow_mut();
             osms = self.process_output_stream(
@@ -1253,8 +2615,34 @@ impl RibUnitRunner {
                 ingress_id,
                 &mut output_stream,
             );
+            filtered
+            };
+
+            match filtered {
+                Some((accepted, selected_ribs, tags)) => {
+                    if accepted {
+                        if routes.is_empty() {
+                            self.insert_and_select(&p, &tags, &mut res);
+                        } else {
+                            self.insert_and_select(&p, &tags, &mut routed);
+                        }
+                    }
+                    self.insert_named(&p, &selected_ribs, &tags);
+                }
+                None => {
+                    // default action accept
+                    self.insert_and_select(&p, &Tags::default(), &mut res);
+                }
+            }
+            self.wait_for_wal().await;
+            self.gate
+                .update_data_routed(Update::OutputStream(osms), &routes)
+                .await;
+            for p in routed {
+                self.gate
+                    .update_data_routed(Update::Single(p), &routes)
+                    .await;
             }
-            self.gate.update_data(Update::OutputStream(osms)).await;
         }
 
         match res.len() {
@@ -1274,9 +2662,270 @@ impl RibUnitRunner {
         Ok(())
     }
 
-    pub fn insert_payload(&self, payload: &Payload) {
+    /// Returns the roto filters of the current version of the script.
+    fn roto_filters(&self) -> RibFilters {
+        self.roto_filters.get(&mut self.roto_context.lock().unwrap())
+    }
+
+    /// Record the update in `payload` for flap damping, making its ingress
+    /// the one the roto filter asks about.
+    ///
+    /// This must be called with the roto context locked, so that no other
+    /// update changes the ingress before the filter has run.
+    fn record_flap(&self, payload: &Payload) {
+        let (route_status, ingress_id) = match &payload.context {
+            RouteContext::Fresh(ctx) => (ctx.status, ctx.provenance.ingress_id),
+            RouteContext::Mrt(ctx) => (ctx.status, ctx.provenance.ingress_id),
+            RouteContext::Reprocess => {
+                self.flap_damping.clear_current();
+                return;
+            }
+        };
+        self.flap_damping.record(
+            best_path::prefix_of(&payload.rx_value),
+            ingress_id,
+            route_status,
+            Instant::now(),
+        );
+    }
+
+    /// Write `payload` to the named RIBs `selected` by the roto filter, and
+    /// withdraw it from the named RIBs it is no longer selected for.
+    fn insert_named(
+        &self,
+        payload: &Payload,
+        selected: &[Arc<str>],
+        tags: &Tags,
+    ) {
+        if self.named_ribs.is_empty() {
+            return;
+        }
+
+        let (route_status, provenance) = match &payload.context {
+            RouteContext::Fresh(ctx) => (ctx.status, ctx.provenance),
+            RouteContext::Mrt(ctx) => (ctx.status, ctx.provenance),
+            RouteContext::Reprocess => return,
+        };
+
+        for name in selected {
+            if !self.named_ribs.iter().any(|named| named.name == *name) {
+                debug!("Ignoring unknown named RIB '{name}'");
+            }
+        }
+
+        let _rebuild_guard = self.rebuild_lock.read().unwrap();
+        for named in &self.named_ribs {
+            let rib = named.rib.load();
+            let route_status = match selected.contains(&named.name) {
+                true => route_status,
+                false => RouteStatus::Withdrawn,
+            };
+            if route_status == RouteStatus::Withdrawn
+                && !rib.has_path(&payload.rx_value, provenance.ingress_id)
+            {
+                continue;
+            }
+            if let Err(err) =
+                rib.insert(&payload.rx_value, route_status, provenance, 0)
+            {
+                error!("Failed to insert into named RIB '{}': {err}", named.name);
+            } else if route_status != RouteStatus::Withdrawn {
+                rib.set_tags(
+                    &payload.rx_value,
+                    provenance.ingress_id,
+                    tags.clone(),
+                );
+            }
+        }
+    }
+
+    /// Insert `payload`, with the `tags` the roto filter attached to it, into
+    /// the RIB and queue what should be sent downstream as a result.
+    ///
+    /// Normally that is the payload itself. When only best paths are to be
+    /// output, it is the new best path for the prefix if the insert changed
+    /// it, or nothing otherwise.
+    fn insert_and_select(
+        &self,
+        payload: &Payload,
+        tags: &Tags,
+        res: &mut SmallVec<[Payload; 8]>,
+    ) {
+        let rib = self.rib.load();
+        if self.best_path_output == BestPathOutput::All
+            || !rib.best_path_enabled()
+        {
+            self.insert_payload(payload, tags);
+            res.push(payload.clone());
+            return;
+        }
+
+        let prefix = best_path::prefix_of(&payload.rx_value);
+        let best_before = rib
+            .best_path(&prefix, &self.ingress_register)
+            .ok()
+            .flatten()
+            .map(|rec| rec.multi_uniq_id);
+        self.insert_payload(payload, tags);
+        let best_after = match rib.best_path(&prefix, &self.ingress_register)
+        {
+            Ok(best) => best,
+            Err(err) => {
+                error!("Failed to select best path for {prefix}: {err}");
+                return;
+            }
+        };
+
+        let (ingress_id, route_status) = match &payload.context {
+            RouteContext::Fresh(ctx) => (ctx.provenance.ingress_id, ctx.status),
+            RouteContext::Mrt(ctx) => (ctx.provenance.ingress_id, ctx.status),
+            RouteContext::Reprocess => return,
+        };
+
+        match best_after {
+            Some(best) if best.multi_uniq_id == ingress_id => {
+                res.push(payload.clone());
+            }
+            Some(best) if best_before != Some(best.multi_uniq_id) => {
+                // Another, previously known, path won. Announce it in place
+                // of the payload that caused the change.
+                let ingress_info =
+                    self.ingress_register.get(best.multi_uniq_id);
+                let provenance = Provenance::for_bgp(
+                    best.multi_uniq_id,
+                    ingress_info
+                        .as_ref()
+                        .and_then(|info| info.remote_addr)
+                        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                    ingress_info
+                        .and_then(|info| info.remote_asn)
+                        .unwrap_or(Asn::from_u32(0)),
+                );
+                res.push(Payload::with_received(
+                    best_path::with_pamap(&payload.rx_value, best.meta),
+                    RouteContext::for_mrt_dump(provenance),
+                    payload.trace_id,
+                    payload.received,
+                ));
+            }
+            None if best_before.is_some()
+                && route_status == RouteStatus::Withdrawn =>
+            {
+                // The last eligible path is gone, pass on the withdrawal.
+                res.push(payload.clone());
+            }
+            _ => {}
+        }
+    }
+
+    /// Decide whether `payload` may be inserted under the memory limit, if
+    /// one is configured.
+    fn admit(
+        &self,
+        payload: &Payload,
+        ingress_id: ingress::IngressId,
+        route_status: RouteStatus,
+    ) -> bool {
+        let Some(config) = &self.memory_limit else {
+            return true;
+        };
+        let state = &self.memory_limit_state;
+        if route_status == RouteStatus::Withdrawn {
+            return true;
+        }
+        if state.is_evicted(ingress_id) {
+            self.status_reporter.path_rejected();
+            return false;
+        }
+
+        // The limit applies to the main and named RIBs together.
+        let rib = self.rib.load();
+        let estimated = rib.memory_usage().unit().estimated_bytes();
+        let warn = estimated >= config.warn_bytes();
+        if warn != state.warned.swap(warn, SeqCst) {
+            match warn {
+                true => self
+                    .status_reporter
+                    .memory_usage_high(estimated, config.max_bytes),
+                false => self
+                    .status_reporter
+                    .memory_usage_normal(estimated, config.max_bytes),
+            }
+        }
+
+        // Updates to existing paths do not grow the RIB.
+        if estimated < config.max_bytes
+            || rib.has_path(&payload.rx_value, ingress_id)
+        {
+            state.rejecting.store(false, SeqCst);
+            return true;
+        }
+
+        if config.on_limit == LimitPolicy::Evict && self.evict_largest_ingress()
+        {
+            if state.is_evicted(ingress_id) {
+                self.status_reporter.path_rejected();
+                return false;
+            }
+            if self.rib.load().memory_usage().unit().estimated_bytes()
+                < config.max_bytes
+            {
+                return true;
+            }
+        }
+
+        if !state.rejecting.swap(true, SeqCst) {
+            self.status_reporter.memory_limit_reached(config.max_bytes);
+        }
+        self.status_reporter.path_rejected();
+        false
+    }
+
+    /// Rebuild the RIB without the routes of the ingress holding the most
+    /// paths, and drop its routes from now on. Returns whether an ingress
+    /// was evicted.
+    fn evict_largest_ingress(&self) -> bool {
+        let _rebuild_guard = self.rebuild_lock.write().unwrap();
         let rib = self.rib.load();
-        if !rib.is_physical() {
+        let route_counts = match rib.route_counts() {
+            Ok(route_counts) => route_counts,
+            Err(err) => {
+                error!("Failed to count routes per ingress: {err}");
+                return false;
+            }
+        };
+
+        let mut evicted = self.memory_limit_state.evicted.lock().unwrap();
+        let Some((largest, routes)) = route_counts
+            .into_iter()
+            .filter(|(ingress_id, _)| !evicted.contains(ingress_id))
+            .max_by_key(|&(ingress_id, routes)| {
+                (routes, std::cmp::Reverse(ingress_id))
+            })
+        else {
+            return false;
+        };
+
+        evicted.insert(largest);
+        match rib.rebuild_without(&evicted) {
+            Ok(new_rib) => {
+                self.rib.store(Arc::new(new_rib));
+                self.log_change(&WalEntry::WithdrawIngress {
+                    ingress_id: largest,
+                    afisafi: None,
+                });
+                self.status_reporter.ingress_evicted(largest, routes);
+                true
+            }
+            Err(err) => {
+                error!("Failed to rebuild RIB to evict ingress {largest}: {err}");
+                false
+            }
+        }
+    }
+
+    pub fn insert_payload(&self, payload: &Payload, tags: &Tags) {
+        if !self.rib.load().is_physical() {
             return;
         }
 
@@ -1297,10 +2946,32 @@ impl RibUnitRunner {
             }
         };
 
+        if !self.admit(payload, provenance.ingress_id, route_status) {
+            return;
+        }
+        let _rebuild_guard = self.rebuild_lock.read().unwrap();
+        let rib = self.rib.load();
+
         let ltime = 0_u64; // XXX should come from Payload
 
         match rib.insert(&payload.rx_value, route_status, provenance, ltime) {
             Ok(report) => {
+                // A withdrawn route keeps the tags it was announced with.
+                if route_status != RouteStatus::Withdrawn {
+                    rib.set_tags(
+                        &payload.rx_value,
+                        provenance.ingress_id,
+                        tags.clone(),
+                    );
+                }
+                if self.wal.is_some() || self.history.is_some() {
+                    self.log_change(&WalEntry::for_route(
+                        &payload.rx_value,
+                        route_status,
+                        provenance.ingress_id,
+                        ltime,
+                    ));
+                }
                 let post_insert = std::time::Instant::now();
                 let store_op_delay = pre_insert.duration_since(post_insert);
                 let propagation_delay = payload.received.duration_since(post_insert);
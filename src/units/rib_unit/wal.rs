@@ -0,0 +1,876 @@
+//! Write-ahead log of the changes made to the RIB between snapshots.
+//!
+//! With `disk` or `hybrid` storage, every change made to the main RIB of a
+//! unit is appended to a log in the storage directory. On startup, the most
+//! recent snapshot of the unit (see [`super::snapshot`]) is loaded and the
+//! log is replayed on top of it, restoring the RIB as it was when Rotonda
+//! stopped, without having to write a snapshot for every update.
+//!
+//! The log is made up of segments named `<unit>-<seq>.wal`. A new segment
+//! is started each time a snapshot is about to be written, and the segments
+//! before it are removed once the snapshot has been written. Every run also
+//! starts a new segment. Ingress IDs are only meaningful within a single
+//! run, so each segment describes the peers it refers to before their first
+//! use, in the same way the ingress table of a snapshot does. When
+//! replaying, a peer is matched on its address and ASN to the peers loaded
+//! from the snapshot, or else registered as a new ingress.
+//!
+//! A segment is `magic "RTNDWAL\0" | version (u8)` followed by entries of
+//! the form `len (u32) | crc32 (u32) | tag (u8) | body`. An entry cut short
+//! or failing its checksum, as left behind by a crash halfway through a
+//! write, ends the replay of its segment.
+//!
+//! How much of the log survives a crash depends on the `sync_mode`:
+//!
+//! * `none`: entries are buffered and only handed to the operating system
+//!   once the buffer is full, so recent changes are lost if Rotonda
+//!   crashes.
+//! * `normal`: entries are handed to the operating system as they are
+//!   written, and synced to disk at most once a second, so recent changes
+//!   are only lost if the system crashes.
+//! * `full`: every entry is synced to disk before the change is
+//!   acknowledged. Entries appended concurrently are synced together.
+//!
+//! Only the main RIB is logged, as only the main RIB is snapshotted.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use inetnum::addr::Prefix;
+use log::{debug, warn};
+use rotonda_store::prefix_record::{Record, RouteStatus};
+use routecore::bgp::types::AfiSafiType;
+use tokio::sync::oneshot;
+
+use crate::{
+    ingress::{self, IngressId, IngressInfo},
+    payload::{RotondaPaMap, RotondaRoute},
+};
+
+use super::{
+    best_path,
+    rib::Rib,
+    snapshot::{self, SnapshotPeer},
+    storage::SyncMode,
+};
+
+const MAGIC: &[u8; 8] = b"RTNDWAL\0";
+const VERSION: u8 = 1;
+
+/// File extension of the log segments.
+const SEGMENT_EXTENSION: &str = "wal";
+
+/// How often the log is synced to disk with [`SyncMode::Normal`].
+const NORMAL_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many entries can be queued for the writer.
+const QUEUE_LEN: usize = 4096;
+
+const TAG_PEER: u8 = 0;
+const TAG_INSERT: u8 = 1;
+const TAG_WITHDRAW_PREFIX: u8 = 2;
+const TAG_WITHDRAW_INGRESS: u8 = 3;
+const TAG_MARK_STALE: u8 = 4;
+const TAG_EXPIRE_STALE: u8 = 5;
+
+//------------ WalEntry ------------------------------------------------------
+
+/// A change made to the RIB.
+#[derive(Clone, Debug)]
+pub enum WalEntry {
+    /// A record was inserted as-is.
+    Insert {
+        prefix: Prefix,
+        multicast: bool,
+        record: Record<RotondaPaMap>,
+    },
+
+    /// The route for a prefix learned from an ingress was withdrawn.
+    WithdrawPrefix {
+        prefix: Prefix,
+        multicast: bool,
+        ingress_id: IngressId,
+    },
+
+    /// All routes of an ingress were withdrawn.
+    WithdrawIngress {
+        ingress_id: IngressId,
+        afisafi: Option<AfiSafiType>,
+    },
+
+    /// The active routes of an ingress were marked stale.
+    MarkStale {
+        ingress_id: IngressId,
+        afisafi: Option<AfiSafiType>,
+    },
+
+    /// The stale routes of an ingress were withdrawn.
+    ExpireStale {
+        ingress_id: IngressId,
+        afisafi: Option<AfiSafiType>,
+    },
+}
+
+impl WalEntry {
+    /// The entry for inserting `route` with [`Rib::insert`].
+    pub fn for_route(
+        route: &RotondaRoute,
+        status: RouteStatus,
+        ingress_id: IngressId,
+        ltime: u64,
+    ) -> Self {
+        let prefix = best_path::prefix_of(route);
+        let multicast = matches!(
+            route,
+            RotondaRoute::Ipv4Multicast(..) | RotondaRoute::Ipv6Multicast(..)
+        );
+        match status {
+            RouteStatus::Withdrawn => WalEntry::WithdrawPrefix {
+                prefix,
+                multicast,
+                ingress_id,
+            },
+            _ => WalEntry::Insert {
+                prefix,
+                multicast,
+                record: Record::new(
+                    ingress_id,
+                    ltime,
+                    status,
+                    route.rotonda_pamap().clone(),
+                ),
+            },
+        }
+    }
+
+    fn ingress_id(&self) -> IngressId {
+        match self {
+            WalEntry::Insert { record, .. } => record.multi_uniq_id,
+            WalEntry::WithdrawPrefix { ingress_id, .. }
+            | WalEntry::WithdrawIngress { ingress_id, .. }
+            | WalEntry::MarkStale { ingress_id, .. }
+            | WalEntry::ExpireStale { ingress_id, .. } => *ingress_id,
+        }
+    }
+
+    fn set_ingress_id(&mut self, id: IngressId) {
+        match self {
+            WalEntry::Insert { record, .. } => record.multi_uniq_id = id,
+            WalEntry::WithdrawPrefix { ingress_id, .. }
+            | WalEntry::WithdrawIngress { ingress_id, .. }
+            | WalEntry::MarkStale { ingress_id, .. }
+            | WalEntry::ExpireStale { ingress_id, .. } => *ingress_id = id,
+        }
+    }
+
+    /// Make the change to `rib`.
+    fn apply(&self, rib: &Rib) -> Result<(), String> {
+        match self {
+            WalEntry::Insert {
+                prefix,
+                multicast,
+                record,
+            } => rib
+                .insert_record(prefix, *multicast, record.clone())
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+            WalEntry::WithdrawPrefix {
+                prefix,
+                multicast,
+                ingress_id,
+            } => rib.withdraw_prefix(prefix, *multicast, *ingress_id),
+            WalEntry::WithdrawIngress {
+                ingress_id,
+                afisafi,
+            } => {
+                rib.withdraw_for_ingress(*ingress_id, *afisafi);
+                Ok(())
+            }
+            WalEntry::MarkStale {
+                ingress_id,
+                afisafi,
+            } => rib.mark_stale(*ingress_id, *afisafi).map(|_| ()),
+            WalEntry::ExpireStale {
+                ingress_id,
+                afisafi,
+            } => rib.expire_stale(*ingress_id, *afisafi).map(|_| ()),
+        }
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            WalEntry::Insert {
+                prefix,
+                multicast,
+                record,
+            } => {
+                writer.write_all(&[TAG_INSERT])?;
+                snapshot::write_record(writer, prefix, *multicast, record)
+            }
+            WalEntry::WithdrawPrefix {
+                prefix,
+                multicast,
+                ingress_id,
+            } => {
+                writer.write_all(&[TAG_WITHDRAW_PREFIX])?;
+                snapshot::write_prefix(writer, prefix, *multicast)?;
+                writer.write_all(&ingress_id.to_be_bytes())
+            }
+            WalEntry::WithdrawIngress {
+                ingress_id,
+                afisafi,
+            } => write_for_ingress(
+                writer,
+                TAG_WITHDRAW_INGRESS,
+                *ingress_id,
+                *afisafi,
+            ),
+            WalEntry::MarkStale {
+                ingress_id,
+                afisafi,
+            } => {
+                write_for_ingress(writer, TAG_MARK_STALE, *ingress_id, *afisafi)
+            }
+            WalEntry::ExpireStale {
+                ingress_id,
+                afisafi,
+            } => write_for_ingress(
+                writer,
+                TAG_EXPIRE_STALE,
+                *ingress_id,
+                *afisafi,
+            ),
+        }
+    }
+}
+
+fn write_for_ingress<W: Write>(
+    writer: &mut W,
+    tag: u8,
+    ingress_id: IngressId,
+    afisafi: Option<AfiSafiType>,
+) -> io::Result<()> {
+    writer.write_all(&[tag])?;
+    writer.write_all(&ingress_id.to_be_bytes())?;
+    match afisafi {
+        Some(afisafi) => {
+            let (afi, safi) = afisafi.into();
+            writer.write_all(&[1])?;
+            writer.write_all(&afi.to_be_bytes())?;
+            writer.write_all(&[safi])
+        }
+        None => writer.write_all(&[0]),
+    }
+}
+
+/// An entry as read back from a segment.
+enum Logged {
+    Peer(SnapshotPeer),
+    Change(WalEntry),
+}
+
+impl Logged {
+    fn read<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let tag = snapshot::read_u8(reader)?;
+        let entry = match tag {
+            TAG_PEER => return Ok(Logged::Peer(snapshot::read_peer(reader)?)),
+            TAG_INSERT => {
+                let kind = snapshot::read_u8(reader)?;
+                let (prefix, multicast, record) =
+                    snapshot::read_record(reader, kind)?;
+                WalEntry::Insert {
+                    prefix,
+                    multicast,
+                    record,
+                }
+            }
+            TAG_WITHDRAW_PREFIX => {
+                let kind = snapshot::read_u8(reader)?;
+                let (prefix, multicast) = snapshot::read_prefix(reader, kind)?;
+                WalEntry::WithdrawPrefix {
+                    prefix,
+                    multicast,
+                    ingress_id: snapshot::read_u32(reader)?,
+                }
+            }
+            TAG_WITHDRAW_INGRESS | TAG_MARK_STALE | TAG_EXPIRE_STALE => {
+                let ingress_id = snapshot::read_u32(reader)?;
+                let afisafi = match snapshot::read_u8(reader)? {
+                    0 => None,
+                    _ => {
+                        let mut afi = [0u8; 2];
+                        reader.read_exact(&mut afi)?;
+                        let safi = snapshot::read_u8(reader)?;
+                        Some(AfiSafiType::from((u16::from_be_bytes(afi), safi)))
+                    }
+                };
+                match tag {
+                    TAG_WITHDRAW_INGRESS => WalEntry::WithdrawIngress {
+                        ingress_id,
+                        afisafi,
+                    },
+                    TAG_MARK_STALE => WalEntry::MarkStale {
+                        ingress_id,
+                        afisafi,
+                    },
+                    _ => WalEntry::ExpireStale {
+                        ingress_id,
+                        afisafi,
+                    },
+                }
+            }
+            _ => {
+                return Err(snapshot::invalid_data(format!(
+                    "unknown log entry {tag}"
+                )))
+            }
+        };
+        Ok(Logged::Change(entry))
+    }
+}
+
+//------------ Wal -----------------------------------------------------------
+
+/// The write-ahead log of a RIB unit.
+///
+/// The segments are written by a dedicated thread, which is handed the
+/// entries through a bounded queue. It writes all entries queued at a time
+/// before syncing, so that with [`SyncMode::Full`] the changes made
+/// concurrently share a single sync. Appending itself never waits for the
+/// disk.
+pub struct Wal {
+    directory: PathBuf,
+    unit_name: String,
+    sync_mode: SyncMode,
+    commands: mpsc::SyncSender<Command>,
+
+    /// The error of a write not waited for, until the next append.
+    failed: Arc<Mutex<Option<String>>>,
+
+    writer: Option<thread::JoinHandle<()>>,
+}
+
+impl Wal {
+    /// Start a new segment in `directory`, following any segments of
+    /// `unit_name` already there.
+    pub fn open(
+        directory: &Path,
+        unit_name: &str,
+        sync_mode: SyncMode,
+        ingresses: Arc<ingress::Register>,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        let seq = segments(directory, unit_name)?
+            .last()
+            .map(|(seq, _)| seq + 1)
+            .unwrap_or(1);
+        let segment = Segment::create(directory, unit_name, seq)?;
+        let (commands, rx) = mpsc::sync_channel(QUEUE_LEN);
+        let failed = Arc::new(Mutex::new(None));
+        let writer = Writer {
+            directory: directory.to_path_buf(),
+            unit_name: unit_name.to_string(),
+            sync_mode,
+            ingresses,
+            segment,
+            dirty: false,
+            failed: failed.clone(),
+        };
+        let writer = thread::Builder::new()
+            .name(format!("wal-{unit_name}"))
+            .spawn(move || writer.run(rx))?;
+        Ok(Wal {
+            directory: directory.to_path_buf(),
+            unit_name: unit_name.to_string(),
+            sync_mode,
+            commands,
+            failed,
+            writer: Some(writer),
+        })
+    }
+
+    /// Append `entry` to the log.
+    ///
+    /// This returns once the entry is queued, and an error writing an
+    /// earlier entry not waited for is returned instead. With
+    /// [`SyncMode::Full`], the change must only be acknowledged once
+    /// [`Appended::synced`] says so, which is best awaited after releasing
+    /// any locks held while appending.
+    pub fn append(&self, entry: &WalEntry) -> io::Result<Appended> {
+        let entry = entry.clone();
+        if self.sync_mode != SyncMode::Full {
+            self.send(Command::Append(entry, None))?;
+            return match self.failed.lock().unwrap().take() {
+                Some(err) => Err(io::Error::other(err)),
+                None => Ok(Appended(None)),
+            };
+        }
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::Append(entry, Some(tx)))?;
+        Ok(Appended(Some(rx)))
+    }
+
+    /// Wait for the entries appended so far to be handed to the operating
+    /// system.
+    pub fn flush(&self) -> io::Result<()> {
+        let (tx, rx) = mpsc::channel();
+        self.send(Command::Flush(tx))?;
+        rx.recv().unwrap_or_else(|_| Err(stopped()))
+    }
+
+    /// Finish the current segment and start a new one. Returns the sequence
+    /// number of the new segment.
+    ///
+    /// Once a snapshot is written of the RIB as it was after rotating, the
+    /// segments before the new one can be removed.
+    pub fn rotate(&self) -> io::Result<u64> {
+        let (tx, rx) = mpsc::channel();
+        self.send(Command::Rotate(tx))?;
+        rx.recv().unwrap_or_else(|_| Err(stopped()))
+    }
+
+    /// Remove the segments before `seq`. Returns the number removed.
+    pub fn remove_before(&self, seq: u64) -> io::Result<usize> {
+        let mut removed = 0;
+        for (_, path) in segments(&self.directory, &self.unit_name)?
+            .into_iter()
+            .take_while(|(segment_seq, _)| *segment_seq < seq)
+        {
+            debug!("removing write-ahead log segment {}", path.display());
+            fs::remove_file(path)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    fn send(&self, command: Command) -> io::Result<()> {
+        self.commands.send(command).map_err(|_| stopped())
+    }
+}
+
+impl Drop for Wal {
+    /// Write and sync what is still queued before returning.
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Close);
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::other("write-ahead log writer has stopped")
+}
+
+//------------ Appended ------------------------------------------------------
+
+/// An entry queued by [`Wal::append`].
+#[derive(Debug)]
+pub struct Appended(Option<oneshot::Receiver<io::Result<()>>>);
+
+impl Appended {
+    /// Wait for the entry to be synced to disk, if the sync mode asks for
+    /// it.
+    pub async fn synced(self) -> io::Result<()> {
+        match self.0 {
+            Some(rx) => rx.await.unwrap_or_else(|_| Err(stopped())),
+            None => Ok(()),
+        }
+    }
+}
+
+//------------ Command -------------------------------------------------------
+
+/// What the writer of the log is asked to do.
+enum Command {
+    /// Write an entry, reporting back once it is synced if asked to.
+    Append(WalEntry, Option<oneshot::Sender<io::Result<()>>>),
+
+    /// Flush the entries written so far.
+    Flush(mpsc::Sender<io::Result<()>>),
+
+    /// Start a new segment, reporting back its sequence number.
+    Rotate(mpsc::Sender<io::Result<u64>>),
+
+    /// Sync and stop.
+    Close,
+}
+
+//------------ Writer --------------------------------------------------------
+
+/// The thread writing the log.
+struct Writer {
+    directory: PathBuf,
+    unit_name: String,
+    sync_mode: SyncMode,
+    ingresses: Arc<ingress::Register>,
+    segment: Segment,
+
+    /// Whether entries were written since the segment was last synced.
+    dirty: bool,
+
+    failed: Arc<Mutex<Option<String>>>,
+}
+
+impl Writer {
+    fn run(mut self, commands: mpsc::Receiver<Command>) {
+        while let Some(command) = self.next(&commands) {
+            // Write what else is queued before syncing once for all.
+            let mut batch = vec![command];
+            batch.extend(commands.try_iter().take(QUEUE_LEN));
+            if !self.process(batch) {
+                break;
+            }
+        }
+        if let Err(err) = self.segment.sync() {
+            warn!("Failed to sync write-ahead log: {err}");
+        }
+    }
+
+    /// Wait for the next command, syncing when due meanwhile.
+    ///
+    /// Returns `None` if the log was dropped.
+    fn next(
+        &mut self,
+        commands: &mpsc::Receiver<Command>,
+    ) -> Option<Command> {
+        loop {
+            if self.sync_mode != SyncMode::Normal || !self.dirty {
+                return commands.recv().ok();
+            }
+            let due = NORMAL_SYNC_INTERVAL
+                .saturating_sub(self.segment.last_sync.elapsed());
+            match commands.recv_timeout(due) {
+                Ok(command) => return Some(command),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    let res = self.sync();
+                    self.report(res, vec![]);
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    /// Carry out a batch of commands. Returns whether to continue.
+    fn process(&mut self, batch: Vec<Command>) -> bool {
+        let mut res = Ok(());
+        let mut synced = vec![];
+        for command in batch {
+            match command {
+                Command::Append(entry, reply) => {
+                    if res.is_ok() {
+                        res = self.write(&entry);
+                    }
+                    synced.extend(reply);
+                }
+                Command::Flush(reply) => {
+                    let flushed = self.segment.writer.flush();
+                    let _ =
+                        reply.send(res.as_ref().map_err(copy).and(flushed));
+                }
+                Command::Rotate(reply) => {
+                    res = res.and_then(|()| self.sync());
+                    self.report(res, std::mem::take(&mut synced));
+                    res = Ok(());
+                    let _ = reply.send(self.rotate());
+                }
+                Command::Close => {
+                    let res = res.and_then(|()| self.sync());
+                    self.report(res, synced);
+                    return false;
+                }
+            }
+        }
+        let res = res.and_then(|()| self.commit());
+        self.report(res, synced);
+        true
+    }
+
+    fn write(&mut self, entry: &WalEntry) -> io::Result<()> {
+        let mut body = Vec::new();
+        let ingress_id = entry.ingress_id();
+        if !self.segment.peers.contains(&ingress_id) {
+            let info = self.ingresses.get(ingress_id).unwrap_or_default();
+            body.push(TAG_PEER);
+            snapshot::write_peer(
+                &mut body,
+                ingress_id,
+                info.remote_addr,
+                info.remote_asn,
+            )?;
+            self.segment.write(&body)?;
+            self.segment.peers.insert(ingress_id);
+            body.clear();
+        }
+        entry.write(&mut body)?;
+        self.segment.write(&body)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Make the entries written durable as far as the sync mode asks.
+    fn commit(&mut self) -> io::Result<()> {
+        match self.sync_mode {
+            SyncMode::None => Ok(()),
+            SyncMode::Normal => {
+                if self.segment.last_sync.elapsed() >= NORMAL_SYNC_INTERVAL {
+                    self.sync()
+                } else {
+                    self.segment.writer.flush()
+                }
+            }
+            SyncMode::Full => self.sync(),
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.segment.sync()?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<u64> {
+        self.sync()?;
+        let seq = self.segment.seq + 1;
+        self.segment =
+            Segment::create(&self.directory, &self.unit_name, seq)?;
+        Ok(seq)
+    }
+
+    /// Passes the outcome of writing entries on to those waiting for it,
+    /// or else keeps an error for the next append.
+    fn report(
+        &self,
+        res: io::Result<()>,
+        synced: Vec<oneshot::Sender<io::Result<()>>>,
+    ) {
+        if synced.is_empty() {
+            if let Err(err) = res {
+                *self.failed.lock().unwrap() = Some(err.to_string());
+            }
+            return;
+        }
+        for reply in synced {
+            let _ = reply.send(res.as_ref().map(|_| ()).map_err(copy));
+        }
+    }
+}
+
+/// Returns an error like `err`, for reporting it more than once.
+fn copy(err: &io::Error) -> io::Error {
+    io::Error::new(err.kind(), err.to_string())
+}
+
+//------------ Segment -------------------------------------------------------
+
+/// The segment currently written to.
+struct Segment {
+    seq: u64,
+    writer: BufWriter<File>,
+
+    /// The ingresses described in this segment so far.
+    peers: HashSet<IngressId>,
+
+    last_sync: Instant,
+}
+
+impl Segment {
+    fn create(directory: &Path, unit_name: &str, seq: u64) -> io::Result<Self> {
+        let path = segment_path(directory, unit_name, seq);
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(Segment {
+            seq,
+            writer,
+            peers: HashSet::new(),
+            last_sync: Instant::now(),
+        })
+    }
+
+    fn write(&mut self, body: &[u8]) -> io::Result<()> {
+        self.writer.write_all(&(body.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&crc32fast::hash(body).to_be_bytes())?;
+        self.writer.write_all(body)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+fn segment_path(directory: &Path, unit_name: &str, seq: u64) -> PathBuf {
+    directory.join(format!("{unit_name}-{seq:020}.{SEGMENT_EXTENSION}"))
+}
+
+/// The log segments of `unit_name` in `directory`, oldest first.
+pub fn segments(
+    directory: &Path,
+    unit_name: &str,
+) -> io::Result<Vec<(u64, PathBuf)>> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(vec![])
+        }
+        Err(err) => return Err(err),
+    };
+    let prefix = format!("{unit_name}-");
+    let mut segments = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(seq) = name
+            .strip_prefix(&prefix)
+            .and_then(|name| {
+                name.strip_suffix(&format!(".{SEGMENT_EXTENSION}"))
+            })
+            .and_then(|seq| seq.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        segments.push((seq, entry.path()));
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+//------------ Replaying -----------------------------------------------------
+
+/// Replay the log `segments` into `rib`.
+///
+/// Peers not already registered as children of `parent`, for instance by
+/// loading a snapshot, are registered as new children of it. Returns the
+/// number of entries replayed.
+pub fn replay(
+    segments: &[(u64, PathBuf)],
+    rib: &Rib,
+    ingresses: &ingress::Register,
+    parent: IngressId,
+) -> io::Result<usize> {
+    let mut replayed = 0;
+    for (_, path) in segments {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(snapshot::invalid_data(format!(
+                "{} is not a Rotonda write-ahead log",
+                path.display()
+            )));
+        }
+        let version = snapshot::read_u8(&mut reader)?;
+        if version != VERSION {
+            return Err(snapshot::invalid_data(format!(
+                "unsupported write-ahead log version {version}"
+            )));
+        }
+
+        let mut muis: HashMap<u32, IngressId> = HashMap::new();
+        while let Some(logged) = read_entry(&mut reader, path)? {
+            match logged {
+                Logged::Peer(peer) => {
+                    let id = resolve_peer(ingresses, parent, &peer, path);
+                    muis.insert(peer.mui, id);
+                }
+                Logged::Change(mut entry) => {
+                    let id = *muis.entry(entry.ingress_id()).or_insert_with(
+                        || register_peer(ingresses, parent, None, path),
+                    );
+                    entry.set_ingress_id(id);
+                    entry.apply(rib).map_err(io::Error::other)?;
+                    replayed += 1;
+                }
+            }
+        }
+    }
+    Ok(replayed)
+}
+
+/// Read the next entry of a segment.
+///
+/// Returns `None` at the end of the segment, or at an entry that was not
+/// completely written.
+fn read_entry<R: Read>(
+    reader: &mut R,
+    path: &Path,
+) -> io::Result<Option<Logged>> {
+    let mut header = [0u8; 8];
+    match reader.read_exact(&mut header[..1]) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            return Ok(None)
+        }
+        Err(err) => return Err(err),
+    }
+    let mut body = vec![];
+    let res = reader.read_exact(&mut header[1..]).and_then(|_| {
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap());
+        body.resize(len as usize, 0);
+        reader.read_exact(&mut body)
+    });
+    match res {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            warn!("ignoring truncated entry at the end of {}", path.display());
+            return Ok(None);
+        }
+        Err(err) => return Err(err),
+    }
+    let crc = u32::from_be_bytes(header[4..].try_into().unwrap());
+    if crc32fast::hash(&body) != crc {
+        warn!("ignoring corrupt entry at the end of {}", path.display());
+        return Ok(None);
+    }
+    Logged::read(&mut body.as_slice()).map(Some)
+}
+
+/// The ingress to use for a peer described in the log.
+fn resolve_peer(
+    ingresses: &ingress::Register,
+    parent: IngressId,
+    peer: &SnapshotPeer,
+    path: &Path,
+) -> IngressId {
+    if peer.remote_addr.is_some() || peer.remote_asn.is_some() {
+        let existing = ingresses.find_all(|info| {
+            info.parent_ingress == Some(parent)
+                && info.remote_addr == peer.remote_addr
+                && info.remote_asn == peer.remote_asn
+        });
+        if let Some(id) = existing.into_iter().min() {
+            return id;
+        }
+    }
+    register_peer(ingresses, parent, Some(peer), path)
+}
+
+fn register_peer(
+    ingresses: &ingress::Register,
+    parent: IngressId,
+    peer: Option<&SnapshotPeer>,
+    path: &Path,
+) -> IngressId {
+    let id = ingresses.register();
+    let mut info = IngressInfo::new()
+        .with_parent(parent)
+        .with_filename(path.to_path_buf());
+    if let Some(addr) = peer.and_then(|peer| peer.remote_addr) {
+        info = info.with_remote_addr(addr);
+    }
+    if let Some(asn) = peer.and_then(|peer| peer.remote_asn) {
+        info = info.with_remote_asn(asn);
+    }
+    ingresses.update_info(id, info);
+    id
+}
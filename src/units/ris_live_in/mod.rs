@@ -0,0 +1,3 @@
+pub mod unit;
+
+pub use unit::RisLiveIn;
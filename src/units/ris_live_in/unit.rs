@@ -0,0 +1,930 @@
+//! Ingesting routes from RIS Live.
+//!
+//! [RIS Live] streams the BGP messages received by the RIPE NCC's Routing
+//! Information Service collectors over a websocket, as JSON. This unit
+//! connects to it, subscribes to the messages matching its configured
+//! filters, and turns the BGP UPDATE messages into routes. Every collector
+//! is registered as an ingress of the unit, and every peer as an ingress of
+//! its collector. When RIS Live reports a peer going down, all routes of
+//! that peer are withdrawn.
+//!
+//! The messages are subscribed to with the raw BGP message included, which
+//! is parsed like a BGP UPDATE received over an MRT feed.
+//!
+//! If the connection is lost, the unit reconnects with an exponential
+//! backoff and subscribes again, resuming the stream. The routes learned
+//! before are kept, as RIS Live has no way of replaying the table, so
+//! changes made while disconnected are only seen once a route changes
+//! again.
+//!
+//! [RIS Live]: https://ris-live.ripe.net/
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use futures::{SinkExt, StreamExt};
+use inetnum::{addr::Prefix, asn::Asn};
+use log::{debug, error, info, warn};
+use rotonda_store::prefix_record::RouteStatus;
+use routecore::bgp::message::{Message as BgpMsg, SessionConfig};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serde_with::serde_as;
+use smallvec::SmallVec;
+use tokio_tungstenite::{
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{header::USER_AGENT, HeaderValue},
+        Message,
+    },
+    Connector,
+};
+use url::Url;
+
+use crate::{
+    common::tls::TlsClientConfig,
+    comms::{Gate, GateMetrics, GateStatus, Terminated},
+    ingress::{self, IngressId, IngressInfo},
+    manager::{Component, WaitPoint},
+    metrics::{self, Metric, MetricType, MetricUnit},
+    payload::{Payload, RotondaRoute, Update},
+    roto_runtime::types::{
+        explode_announcements, explode_withdrawals, MrtContext, Provenance,
+        RouteContext,
+    },
+};
+
+/// How often to ping RIS Live to check the connection is alive.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for anything from RIS Live, including the answer to a
+/// ping, before giving up on the connection.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct RisLiveIn {
+    /// The RIS Live websocket endpoint, a `ws://` or `wss://` URL.
+    pub url: Url,
+
+    /// The certificates to check that of a `wss://` endpoint against.
+    #[serde(default)]
+    pub tls: Option<TlsClientConfig>,
+
+    /// The name to identify as to RIS Live.
+    #[serde(default = "RisLiveIn::default_client")]
+    pub client: String,
+
+    /// The messages to subscribe to. Without any, all messages from all
+    /// collectors are subscribed to.
+    #[serde(default)]
+    pub subscribe: Vec<Subscription>,
+
+    /// How long to wait before reconnecting after the connection failed.
+    /// The delay doubles with every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "RisLiveIn::default_reconnect_delay_secs")]
+    pub reconnect_delay_secs: Duration,
+
+    /// The longest to wait before reconnecting.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "RisLiveIn::default_max_reconnect_delay_secs")]
+    pub max_reconnect_delay_secs: Duration,
+}
+
+impl RisLiveIn {
+    fn default_client() -> String {
+        "rotonda".to_string()
+    }
+
+    fn default_reconnect_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_reconnect_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    /// Returns the TLS settings to connect with, if the URL needs any.
+    fn connector(&self) -> Result<Option<Connector>, String> {
+        match self.url.scheme() {
+            "ws" => return Ok(None),
+            "wss" => {}
+            scheme => return Err(format!("unsupported URL scheme {scheme}")),
+        }
+        let tls = self.tls.clone().unwrap_or_default();
+        if tls.server_name.is_some() {
+            // The websocket client checks the host of the URL.
+            return Err("server_name is not supported".into());
+        }
+        Ok(Some(Connector::Rustls(Arc::new(tls.rustls_config()?))))
+    }
+
+    pub async fn run(
+        self,
+        mut component: Component,
+        gate: Gate,
+        mut waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let connector = match self.connector() {
+            Ok(connector) => connector,
+            Err(err) => {
+                error!("Unit {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+        let metrics = Arc::new(RisLiveMetrics::new(&gate));
+        component.register_metrics(metrics.clone());
+
+        gate.process_until(waitpoint.ready()).await?;
+        waitpoint.running().await;
+
+        let ingresses = component.ingresses().clone();
+        let parent_id = ingresses.register();
+        ingresses.update_info(
+            parent_id,
+            IngressInfo::new()
+                .with_unit_name(component.name().as_ref())
+                .with_desc("ris-live-in unit"),
+        );
+
+        let converter =
+            Converter::new(ingresses, parent_id, "RIS Live collector");
+        let connection = tokio::spawn(Self::connect_loop(
+            self,
+            component.name().to_string(),
+            connector,
+            gate.clone(),
+            converter,
+            metrics,
+        ));
+
+        // The connection is handled in its own task, so here only the gate
+        // needs to be processed.
+        let res = loop {
+            match gate.process().await {
+                Ok(GateStatus::ReportLinks { report }) => {
+                    report.declare_source();
+                }
+                Ok(GateStatus::Reconfiguring { .. }) => {
+                    warn!(
+                        "Unit {}: reconfiguring ris-live-in requires a restart",
+                        component.name()
+                    );
+                }
+                Ok(_) => {}
+                Err(Terminated) => break Err(Terminated),
+            }
+        };
+        connection.abort();
+        res
+    }
+
+    /// Keep a connection to RIS Live, reconnecting whenever it is lost.
+    async fn connect_loop(
+        self,
+        name: String,
+        connector: Option<Connector>,
+        gate: Gate,
+        mut converter: Converter,
+        metrics: Arc<RisLiveMetrics>,
+    ) {
+        let mut url = self.url.clone();
+        url.query_pairs_mut().append_pair("client", &self.client);
+
+        let mut delay = self.reconnect_delay_secs;
+        loop {
+            debug!("Unit {name}: connecting to {url}");
+            let res = self
+                .session(&url, &connector, &gate, &mut converter, &metrics)
+                .await;
+            match res {
+                Ok(()) => {
+                    info!("Unit {name}: RIS Live closed the connection");
+                }
+                Err(err) => {
+                    warn!("Unit {name}: connection to RIS Live failed: {err}")
+                }
+            }
+            metrics.connected.store(false, SeqCst);
+
+            // A connection that got going resets the backoff.
+            if metrics.session_messages.swap(0, SeqCst) > 0 {
+                delay = self.reconnect_delay_secs;
+            }
+            info!("Unit {name}: reconnecting in {}s", delay.as_secs());
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(self.max_reconnect_delay_secs);
+        }
+    }
+
+    /// Run a single connection to RIS Live, until it is closed or fails.
+    async fn session(
+        &self,
+        url: &Url,
+        connector: &Option<Connector>,
+        gate: &Gate,
+        converter: &mut Converter,
+        metrics: &RisLiveMetrics,
+    ) -> std::io::Result<()> {
+        let mut request = url
+            .as_str()
+            .into_client_request()
+            .map_err(std::io::Error::other)?;
+        request.headers_mut().insert(
+            USER_AGENT,
+            HeaderValue::from_static(concat!(
+                "rotonda/",
+                env!("CARGO_PKG_VERSION")
+            )),
+        );
+        let (mut ws, _) = tokio_tungstenite::connect_async_tls_with_config(
+            request,
+            None,
+            true,
+            connector.clone(),
+        )
+        .await
+        .map_err(std::io::Error::other)?;
+        metrics.connected.store(true, SeqCst);
+        metrics.num_connects.fetch_add(1, SeqCst);
+
+        let subscriptions = if self.subscribe.is_empty() {
+            vec![Subscription::default()]
+        } else {
+            self.subscribe.clone()
+        };
+        for subscription in &subscriptions {
+            let msg = json!({
+                "type": "ris_subscribe",
+                "data": subscription.to_json(),
+            });
+            ws.send(Message::Text(msg.to_string()))
+                .await
+                .map_err(std::io::Error::other)?;
+        }
+
+        let mut ping = tokio::time::interval(PING_INTERVAL);
+        ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut last_received = Instant::now();
+        loop {
+            // Receiving is cancel safe, so can be raced against the timer.
+            let message = tokio::select! {
+                message = ws.next() => match message {
+                    Some(message) => {
+                        Some(message.map_err(std::io::Error::other)?)
+                    }
+                    None => return Ok(()),
+                },
+                _ = ping.tick() => None,
+            };
+            let Some(message) = message else {
+                if last_received.elapsed() > IDLE_TIMEOUT {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "no messages received",
+                    ));
+                }
+                ws.send(Message::Ping(vec![]))
+                    .await
+                    .map_err(std::io::Error::other)?;
+                continue;
+            };
+            last_received = Instant::now();
+
+            // Pings and closes are answered while receiving, the stream
+            // ending once the close has been.
+            let Message::Text(text) = message else {
+                continue;
+            };
+            metrics.num_messages.fetch_add(1, SeqCst);
+            metrics.session_messages.fetch_add(1, SeqCst);
+            metrics.last_message.store(Utc::now().timestamp(), SeqCst);
+            match converter.convert(&text) {
+                Ok(Converted::Update(update, announced, withdrawn)) => {
+                    metrics.num_announcements.fetch_add(announced, SeqCst);
+                    metrics.num_withdrawals.fetch_add(withdrawn, SeqCst);
+                    gate.update_data(update).await;
+                }
+                Ok(Converted::Ignored) => {}
+                Err(err) => {
+                    metrics.num_invalid_messages.fetch_add(1, SeqCst);
+                    debug!("Ignoring RIS Live message: {err}");
+                }
+            }
+        }
+    }
+}
+
+/// A subscription to RIS Live messages.
+///
+/// See the RIS Live manual for the meaning of the filters. Unset filters
+/// match all messages.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subscription {
+    /// The collector, e.g. "rrc00".
+    pub host: Option<String>,
+
+    /// The message type, e.g. "UPDATE" or "RIS_PEER_STATE".
+    #[serde(rename = "type")]
+    pub msg_type: Option<String>,
+
+    /// The prefixes to match.
+    #[serde(default)]
+    pub prefix: Vec<Prefix>,
+
+    /// Also match the more specifics of `prefix`, which RIS Live does by
+    /// default.
+    pub more_specific: Option<bool>,
+
+    /// Also match the less specifics of `prefix`.
+    pub less_specific: Option<bool>,
+
+    /// The ASN or AS path pattern to match, e.g. "64496" or "^64496,64497".
+    pub path: Option<String>,
+
+    /// The address of the peer.
+    pub peer: Option<IpAddr>,
+
+    /// Only match messages with this key, e.g. "announcements".
+    pub require: Option<String>,
+}
+
+impl Subscription {
+    /// The data of the `ris_subscribe` message for this subscription.
+    fn to_json(&self) -> Value {
+        let mut data = json!({
+            // The raw message is what is converted into routes.
+            "socketOptions": { "includeRaw": true },
+        });
+        let mut set = |key: &str, value: Value| {
+            data[key] = value;
+        };
+        if let Some(host) = &self.host {
+            set("host", host.as_str().into());
+        }
+        if let Some(msg_type) = &self.msg_type {
+            set("type", msg_type.as_str().into());
+        }
+        if !self.prefix.is_empty() {
+            set(
+                "prefix",
+                self.prefix.iter().map(|p| p.to_string()).collect(),
+            );
+        }
+        if let Some(more_specific) = self.more_specific {
+            set("moreSpecific", more_specific.into());
+        }
+        if let Some(less_specific) = self.less_specific {
+            set("lessSpecific", less_specific.into());
+        }
+        if let Some(path) = &self.path {
+            set("path", path.as_str().into());
+        }
+        if let Some(peer) = self.peer {
+            set("peer", peer.to_string().into());
+        }
+        if let Some(require) = &self.require {
+            set("require", require.as_str().into());
+        }
+        data
+    }
+}
+
+//------------ Converter -----------------------------------------------------
+
+/// The envelope of all messages from RIS Live.
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(rename = "type")]
+    msg_type: String,
+
+    #[serde(default)]
+    data: Value,
+}
+
+/// The data of a `ris_message`.
+#[derive(Deserialize)]
+struct RisMessage {
+    host: String,
+    peer: IpAddr,
+    peer_asn: String,
+
+    #[serde(rename = "type")]
+    msg_type: String,
+
+    /// The BGP message, hex encoded.
+    raw: Option<String>,
+
+    /// The new state of the peer for a `RIS_PEER_STATE` message.
+    state: Option<String>,
+}
+
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Converted {
+    /// The update to send downstream, with the number of announcements and
+    /// withdrawals in it.
+    Update(Update, usize, usize),
+    Ignored,
+}
+
+/// Turns RIS Live messages into updates.
+///
+/// Also used by the `nats-in`, `grpc-in` and `http-in` units, for messages
+/// in the RIS Live format, raw BGP UPDATEs and routes.
+pub(crate) struct Converter {
+    ingresses: Arc<ingress::Register>,
+    parent_id: IngressId,
+
+    /// The description of the ingresses of the collectors.
+    collector_desc: &'static str,
+
+    /// The ingresses of the collectors.
+    collectors: HashMap<String, IngressId>,
+
+    /// The ingresses of the peers, by collector, address and ASN.
+    peers: HashMap<(IngressId, IpAddr, Asn), IngressId>,
+}
+
+impl Converter {
+    pub(crate) fn new(
+        ingresses: Arc<ingress::Register>,
+        parent_id: IngressId,
+        collector_desc: &'static str,
+    ) -> Self {
+        Self {
+            ingresses,
+            parent_id,
+            collector_desc,
+            collectors: HashMap::new(),
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Convert a RIS Live message, as received over the websocket.
+    pub(crate) fn convert(
+        &mut self,
+        text: &str,
+    ) -> Result<Converted, String> {
+        let envelope: Envelope =
+            serde_json::from_str(text).map_err(|err| err.to_string())?;
+        match envelope.msg_type.as_str() {
+            "ris_message" => self.convert_message(envelope.data),
+            "ris_error" => {
+                let msg = envelope.data["message"].as_str().unwrap_or("");
+                error!("RIS Live reported an error: {msg}");
+                Ok(Converted::Ignored)
+            }
+            _ => Ok(Converted::Ignored),
+        }
+    }
+
+    /// Convert the data of a `ris_message`.
+    pub(crate) fn convert_message(
+        &mut self,
+        data: Value,
+    ) -> Result<Converted, String> {
+        let msg: RisMessage =
+            serde_json::from_value(data).map_err(|err| err.to_string())?;
+        let peer_asn = u32::from_str(&msg.peer_asn)
+            .map(Asn::from_u32)
+            .map_err(|_| format!("invalid peer ASN '{}'", msg.peer_asn))?;
+
+        match msg.msg_type.as_str() {
+            "UPDATE" => {}
+            "RIS_PEER_STATE" if msg.state.as_deref() == Some("down") => {
+                return Ok(self.peer_down(&msg.host, msg.peer, peer_asn));
+            }
+            _ => return Ok(Converted::Ignored),
+        }
+
+        let raw = msg.raw.as_deref().ok_or("UPDATE without raw message")?;
+        let raw = hex::decode(raw).map_err(|err| err.to_string())?;
+        self.convert_update(&msg.host, msg.peer, peer_asn, &raw)
+    }
+
+    /// Convert the BGP UPDATE `raw` received from `peer` via `host`.
+    pub(crate) fn convert_update(
+        &mut self,
+        host: &str,
+        peer: IpAddr,
+        peer_asn: Asn,
+        raw: &[u8],
+    ) -> Result<Converted, String> {
+        let upd = match BgpMsg::from_octets(
+            raw,
+            Some(&SessionConfig::modern()),
+        ) {
+            Ok(BgpMsg::Update(upd)) => upd,
+            Ok(_) => return Err("raw message is not an UPDATE".into()),
+            Err(err) => return Err(err.to_string()),
+        };
+        let announced =
+            explode_announcements(&upd).map_err(|err| err.to_string())?;
+        let withdrawn =
+            explode_withdrawals(&upd).map_err(|err| err.to_string())?;
+        Ok(self.convert_routes(host, peer, peer_asn, announced, withdrawn))
+    }
+
+    /// Convert the routes announced and withdrawn by `peer` via `host`.
+    pub(crate) fn convert_routes(
+        &mut self,
+        host: &str,
+        peer: IpAddr,
+        peer_asn: Asn,
+        announced: Vec<RotondaRoute>,
+        withdrawn: Vec<RotondaRoute>,
+    ) -> Converted {
+        let received = std::time::Instant::now();
+        let ingress_id = self.peer_ingress(host, peer, peer_asn);
+        let context = MrtContext {
+            status: RouteStatus::Active,
+            provenance: Provenance::for_bgp(ingress_id, peer, peer_asn),
+        };
+        let counts = (announced.len(), withdrawn.len());
+        let mut payloads = SmallVec::new();
+        payloads.extend(announced.into_iter().map(|rr| {
+            Payload::with_received(
+                rr,
+                RouteContext::Mrt(context.clone()),
+                None,
+                received,
+            )
+        }));
+        let context = MrtContext {
+            status: RouteStatus::Withdrawn,
+            ..context
+        };
+        payloads.extend(withdrawn.into_iter().map(|rr| {
+            Payload::with_received(
+                rr,
+                RouteContext::Mrt(context.clone()),
+                None,
+                received,
+            )
+        }));
+        Converted::Update(payloads.into(), counts.0, counts.1)
+    }
+
+    /// Withdraw all routes of `peer` at collector `host`.
+    pub(crate) fn peer_down(
+        &self,
+        host: &str,
+        peer: IpAddr,
+        peer_asn: Asn,
+    ) -> Converted {
+        // A peer we never heard of has no routes to withdraw.
+        let collector = self.collectors.get(host);
+        let peer = collector.and_then(|collector| {
+            self.peers.get(&(*collector, peer, peer_asn))
+        });
+        match peer {
+            Some(peer) => {
+                Converted::Update(Update::Withdraw(*peer, None), 0, 0)
+            }
+            None => Converted::Ignored,
+        }
+    }
+
+    /// The ingress of collector `host`, registering it if it is new.
+    pub(crate) fn collector_ingress(&mut self, host: &str) -> IngressId {
+        match self.collectors.get(host) {
+            Some(collector) => *collector,
+            None => {
+                let collector = self.ingresses.register();
+                self.ingresses.update_info(
+                    collector,
+                    IngressInfo::new()
+                        .with_parent(self.parent_id)
+                        .with_name(host)
+                        .with_desc(self.collector_desc),
+                );
+                self.collectors.insert(host.to_string(), collector);
+                collector
+            }
+        }
+    }
+
+    /// The ingress of `peer` at collector `host`, registering the collector
+    /// and the peer if they are new.
+    fn peer_ingress(
+        &mut self,
+        host: &str,
+        peer: IpAddr,
+        peer_asn: Asn,
+    ) -> IngressId {
+        let collector = self.collector_ingress(host);
+        *self
+            .peers
+            .entry((collector, peer, peer_asn))
+            .or_insert_with(|| {
+                let id = self.ingresses.register();
+                self.ingresses.update_info(
+                    id,
+                    IngressInfo::new()
+                        .with_parent(collector)
+                        .with_remote_addr(peer)
+                        .with_remote_asn(peer_asn),
+                );
+                id
+            })
+    }
+}
+
+//------------ RisLiveMetrics ------------------------------------------------
+
+#[derive(Debug, Default)]
+struct RisLiveMetrics {
+    gate: Arc<GateMetrics>,
+    connected: AtomicBool,
+    num_connects: AtomicUsize,
+    num_messages: AtomicUsize,
+    num_invalid_messages: AtomicUsize,
+    num_announcements: AtomicUsize,
+    num_withdrawals: AtomicUsize,
+
+    /// The unix timestamp of the last message received, 0 if none yet.
+    last_message: AtomicI64,
+
+    /// The number of messages received over the current connection.
+    session_messages: AtomicUsize,
+}
+
+impl RisLiveMetrics {
+    fn new(gate: &Gate) -> Self {
+        Self {
+            gate: gate.metrics(),
+            ..Default::default()
+        }
+    }
+
+    const CONNECTED_METRIC: Metric = Metric::new(
+        "ris_live_in_connected",
+        "whether the unit is connected to RIS Live",
+        MetricType::Gauge,
+        MetricUnit::State,
+    );
+    const NUM_CONNECTS_METRIC: Metric = Metric::new(
+        "ris_live_in_num_connects",
+        "the number of times a connection to RIS Live was established",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_MESSAGES_METRIC: Metric = Metric::new(
+        "ris_live_in_num_messages",
+        "the number of messages received from RIS Live",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_INVALID_MESSAGES_METRIC: Metric = Metric::new(
+        "ris_live_in_num_invalid_messages",
+        "the number of messages from RIS Live that could not be converted",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ANNOUNCEMENTS_METRIC: Metric = Metric::new(
+        "ris_live_in_num_announcements",
+        "the number of route announcements received from RIS Live",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WITHDRAWALS_METRIC: Metric = Metric::new(
+        "ris_live_in_num_withdrawals",
+        "the number of route withdrawals received from RIS Live",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const SINCE_LAST_MESSAGE_METRIC: Metric = Metric::new(
+        "ris_live_in_since_last_message",
+        "the number of seconds since the last message from RIS Live",
+        MetricType::Gauge,
+        MetricUnit::Second,
+    );
+}
+
+impl metrics::Source for RisLiveMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        self.gate.append(unit_name, target);
+        target.append_simple(
+            &Self::CONNECTED_METRIC,
+            Some(unit_name),
+            u8::from(self.connected.load(SeqCst)),
+        );
+        target.append_simple(
+            &Self::NUM_CONNECTS_METRIC,
+            Some(unit_name),
+            self.num_connects.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_INVALID_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_invalid_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ANNOUNCEMENTS_METRIC,
+            Some(unit_name),
+            self.num_announcements.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WITHDRAWALS_METRIC,
+            Some(unit_name),
+            self.num_withdrawals.load(SeqCst),
+        );
+        let last_message = self.last_message.load(SeqCst);
+        if last_message > 0 {
+            target.append_simple(
+                &Self::SINCE_LAST_MESSAGE_METRIC,
+                Some(unit_name),
+                Utc::now().timestamp() - last_message,
+            );
+        }
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use crate::{bgp::encode::RAW_UPDATE, tests::util::https};
+
+    use super::*;
+
+    fn ris_message(data: Value) -> String {
+        json!({ "type": "ris_message", "data": data }).to_string()
+    }
+
+    #[test]
+    fn config_deserialization() {
+        let toml = r#"
+        url = "ws://localhost:8080/v1/ws/"
+        reconnect_delay_secs = 5
+
+        [[subscribe]]
+        host = "rrc00"
+        type = "UPDATE"
+        prefix = ["192.0.2.0/24"]
+        more_specific = true
+        path = "64496"
+        "#;
+
+        let config: RisLiveIn = toml::from_str(toml).unwrap();
+        assert_eq!(config.client, "rotonda");
+        assert_eq!(config.reconnect_delay_secs, Duration::from_secs(5));
+        assert_eq!(config.max_reconnect_delay_secs, Duration::from_secs(60));
+        assert_eq!(
+            config.subscribe[0].to_json(),
+            json!({
+                "host": "rrc00",
+                "type": "UPDATE",
+                "prefix": ["192.0.2.0/24"],
+                "moreSpecific": true,
+                "path": "64496",
+                "socketOptions": { "includeRaw": true },
+            })
+        );
+    }
+
+    #[test]
+    fn updates_are_converted_into_routes() {
+        let ingresses = Arc::new(ingress::Register::default());
+        let parent_id = ingresses.register();
+        let mut converter = Converter::new(
+            ingresses.clone(),
+            parent_id,
+            "RIS Live collector",
+        );
+
+        let msg = ris_message(json!({
+            "timestamp": 1700000000.12,
+            "peer": "192.0.2.1",
+            "peer_asn": "65000",
+            "id": "1-2-3",
+            "host": "rrc00.ripe.net",
+            "type": "UPDATE",
+            "raw": RAW_UPDATE,
+        }));
+        let Ok(Converted::Update(Update::Bulk(payloads), 1, 1)) =
+            converter.convert(&msg)
+        else {
+            panic!("expected an announcement and a withdrawal");
+        };
+        assert_eq!(payloads.len(), 2);
+
+        // The peer is registered under its collector.
+        let peer = ingresses
+            .find_all(|info| info.remote_asn == Some(Asn::from_u32(65000)))
+            .pop()
+            .unwrap();
+        let collector = ingresses.get(peer).unwrap().parent_ingress.unwrap();
+        let info = ingresses.get(collector).unwrap();
+        assert_eq!(info.name.as_deref(), Some("rrc00.ripe.net"));
+        assert_eq!(info.parent_ingress, Some(parent_id));
+
+        // And its routes are withdrawn when it goes down.
+        let msg = ris_message(json!({
+            "timestamp": 1700000001.0,
+            "peer": "192.0.2.1",
+            "peer_asn": "65000",
+            "host": "rrc00.ripe.net",
+            "type": "RIS_PEER_STATE",
+            "state": "down",
+        }));
+        assert!(matches!(
+            converter.convert(&msg),
+            Ok(Converted::Update(Update::Withdraw(id, None), 0, 0))
+                if id == peer
+        ));
+
+        // Other messages are ignored or rejected.
+        let pong = json!({ "type": "pong", "data": null }).to_string();
+        assert!(matches!(converter.convert(&pong), Ok(Converted::Ignored)));
+        let msg = ris_message(json!({
+            "peer": "192.0.2.1",
+            "peer_asn": "65000",
+            "host": "rrc00.ripe.net",
+            "type": "UPDATE",
+        }));
+        assert!(converter.convert(&msg).is_err());
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn updates_are_received_over_wss() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws =
+                tokio_tungstenite::accept_async(stream).await.unwrap();
+            let subscribe = loop {
+                let message = ws.next().await.unwrap().unwrap();
+                if let Message::Text(text) = message {
+                    break text;
+                }
+            };
+            let msg = ris_message(json!({
+                "peer": "192.0.2.1",
+                "peer_asn": "65000",
+                "host": "rrc00.ripe.net",
+                "type": "UPDATE",
+                "raw": RAW_UPDATE,
+            }));
+            ws.send(Message::Text(msg)).await.unwrap();
+            ws.close(None).await.unwrap();
+            while ws.next().await.is_some() {}
+            serde_json::from_str::<Value>(&subscribe).unwrap()
+        });
+        let front = https::front(backend).await;
+        let config: RisLiveIn = toml::from_str(&format!(
+            r#"
+            url = "wss://localhost:{}/v1/ws/"
+            tls.ca = "{}"
+            "#,
+            front.port(),
+            https::ca_file().display()
+        ))
+        .unwrap();
+
+        let ingresses = Arc::new(ingress::Register::default());
+        let parent_id = ingresses.register();
+        let mut converter =
+            Converter::new(ingresses, parent_id, "RIS Live collector");
+        let (gate, _agent) = Gate::new(0);
+        let metrics = RisLiveMetrics::default();
+        let connector = config.connector().unwrap();
+        config
+            .session(&config.url, &connector, &gate, &mut converter, &metrics)
+            .await
+            .unwrap();
+
+        assert_eq!(server.await.unwrap()["type"], "ris_subscribe");
+        assert_eq!(metrics.num_connects.load(SeqCst), 1);
+        assert_eq!(metrics.num_announcements.load(SeqCst), 1);
+        assert_eq!(metrics.num_withdrawals.load(SeqCst), 1);
+
+        // Plain URLs need no TLS, other schemes are refused.
+        let with_url = |url: &str| RisLiveIn {
+            url: Url::parse(url).unwrap(),
+            ..config.clone()
+        };
+        assert!(with_url("ws://localhost/").connector().unwrap().is_none());
+        assert!(with_url("https://localhost/").connector().is_err());
+    }
+}
@@ -3,7 +3,14 @@
 //! There are two units in this module that act as an RTR client but use
 //! different transport protocols: [`Tcp`] uses plain, unencrypted TCP while
 //! [`Tls`] uses TLS.
+//!
+//! The client keeps the VRP set received from the server in the
+//! [`RtrCache`] registered under the unit's name, which roto filters of
+//! other units can validate routes against. Unless disabled, the updates to
+//! the set are also sent downstream, e.g. to RIB units that keep their own
+//! copy.
 
+use std::collections::HashSet;
 use std::fmt::Display;
 use std::io;
 use std::fs::File;
@@ -11,7 +18,7 @@ use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::sync::atomic;
-use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64};
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, AtomicUsize};
 use std::task::{Context, Poll};
 use std::time::Duration;
 use chrono::{TimeZone, Utc};
@@ -40,6 +47,7 @@ use crate::manager::Component;
 use crate::metrics::{Metric, MetricType, MetricUnit};
 use crate::payload;
 use crate::payload::Update;
+use crate::units::rib_unit::rpki::RtrCache;
 
 //------------ Tcp -----------------------------------------------------------
 
@@ -52,6 +60,10 @@ pub struct Tcp {
     /// How long to wait before connecting again if the connection is closed.
     #[serde(default = "Tcp::default_retry")]
     retry: u64,
+
+    /// Whether to send the updates to the VRP set downstream.
+    #[serde(default = "Tcp::default_forward")]
+    forward: bool,
 }
 
 impl Tcp {
@@ -60,6 +72,10 @@ impl Tcp {
         60
     }
 
+    fn default_forward() -> bool {
+        true
+    }
+
     /// Runs the unit.
     ///
     /// This method will only ever return if the RTR client encounters a fatal
@@ -76,7 +92,8 @@ impl Tcp {
         //waitpoint.running().await;
 
         RtrClient::run(
-            component, waitpoint, gate, self.retry, metrics.clone(),
+            component, waitpoint, gate, self.retry, self.forward,
+            metrics.clone(),
             || async {
                 Ok(RtrTcpStream {
                     sock: TcpStream::connect(&self.remote).await?,
@@ -283,10 +300,16 @@ where
             waitpoint: WaitPoint,
             mut gate: Gate,
             retry: u64,
+            forward: bool,
             metrics: Arc<RtrMetrics>,
             connect: Connect,
         ) -> Result<(), Terminated> {
-            let mut rtr_target = RtrTarget::new(component.name().clone());
+            let cache = component.rtr_caches().get(component.name());
+            component.register_metrics(cache.clone());
+            let mut rtr_target = RtrTarget::new(
+                component.name().clone(),
+                cache,
+            );
             component.register_metrics(metrics.clone());
             let mut this = Self::new(connect, retry, metrics);
 
@@ -344,7 +367,7 @@ where
                            return Err(Terminated);
                         }
                     };
-                    if let Some(update) = update {
+                    if let Some(update) = update.filter(|_| forward) {
                         gate.update_data(update).await;
                     }
                 }
@@ -393,8 +416,10 @@ where
                 }
             };
 
-            //let state = target.state;
-            Ok(Client::new(sock, target, None))
+            // Continue the session with a serial query if we have the data
+            // of an earlier connection.
+            let state = target.state;
+            Ok(Client::new(sock, target, state))
         }
 
         /// Updates the data set from upstream.
@@ -418,11 +443,22 @@ where
         ) -> Result<Result<Option<payload::Update>, io::Error>, Terminated> {
             let update_fut = async {
                 let update = client.update().await?;
+                let target = client.target_mut();
+                if let Err(err) = target.apply(update.clone(), Timing::default()) {
+                    // Start over with a reset query next time.
+                    target.state = None;
+                    client.send_error(err).await?;
+                    return Err(io::Error::other(
+                        format!("unacceptable update from server: {err}")
+                    ));
+                }
                 let state = client.state();
+                client.target_mut().state = state;
+                let counts = client.target().cache.counts();
                 //if update.is_definitely_empty() {
                 //    return Ok((state, None))
                 //}
-                Ok((state, Some(update)))
+                Ok((state, counts, Some(update)))
             };
             pin_mut!(update_fut);
 
@@ -439,7 +475,7 @@ where
                     }
                     Either::Right((res, _)) => {
                         let res = match res {
-                            Ok((state, res)) => {
+                            Ok((state, counts, res)) => {
                                 if let Some(state) = state {
                                     self.metrics.session.store(
                                         state.session().into(),
@@ -454,6 +490,7 @@ where
                                         atomic::Ordering::Relaxed
                                     );
                                 }
+                                self.metrics.set_counts(counts);
                                 Ok(res.map(payload::Update::Rtr))
                             }
                             Err(err) => Err(err)
@@ -487,7 +524,12 @@ where
 
 
 struct RtrTarget {
-    cache: RtrVerbs,
+    /// The VRP set received from the server.
+    cache: Arc<RtrCache>,
+
+    /// The state of the session the VRP set belongs to.
+    state: Option<State>,
+
     pub name: Arc<str>,
 }
 
@@ -552,9 +594,10 @@ pub enum RtrUpdate {
 }
 
 impl RtrTarget {
-    pub fn new(name: Arc<str>) -> Self {
+    pub fn new(name: Arc<str>, cache: Arc<RtrCache>) -> Self {
         Self {
-            cache: RtrVerbs::default(),
+            cache,
+            state: None,
             name,
         }
     }
@@ -570,17 +613,26 @@ impl PayloadTarget for RtrTarget {
             //RtrCache::default()
             RtrUpdate::Full(Default::default())
         } else {
-            // XXX this is unnecessarily expensive
             debug!("RTR delta");
-            //self.cache.clone()
-            RtrUpdate::Delta(self.cache.clone())
+            RtrUpdate::Delta(Default::default())
         }
     }
 
     fn apply(
-        &mut self, update: Self::Update, timing: Timing
+        &mut self, update: Self::Update, _timing: Timing
     ) -> Result<(), PayloadError> {
-        todo!()
+        match update {
+            RtrUpdate::Full(rtr_verbs) => {
+                let payloads = rtr_verbs.into_iter()
+                    .map(|(_, payload)| payload)
+                    .collect::<HashSet<_>>();
+                self.cache.apply_reset(&payloads);
+                Ok(())
+            }
+            RtrUpdate::Delta(rtr_verbs) => {
+                self.cache.apply_delta(&rtr_verbs.verbs)
+            }
+        }
     }
 }
 
@@ -754,6 +806,15 @@ struct RtrMetrics {
 
     /// The number of bytes written.
     bytes_written: AtomicU64,
+
+    /// The number of route origins in the VRP set.
+    route_origins: AtomicUsize,
+
+    /// The number of router keys in the VRP set.
+    router_keys: AtomicUsize,
+
+    /// The number of ASPAs in the VRP set.
+    aspas: AtomicUsize,
 }
 
 impl RtrMetrics {
@@ -765,9 +826,18 @@ impl RtrMetrics {
             updated: i64::MIN.into(),
             bytes_read: 0.into(),
             bytes_written: 0.into(),
+            route_origins: 0.into(),
+            router_keys: 0.into(),
+            aspas: 0.into(),
         }
     }
 
+    fn set_counts(&self, (route_origins, router_keys, aspas): (usize, usize, usize)) {
+        self.route_origins.store(route_origins, atomic::Ordering::Relaxed);
+        self.router_keys.store(router_keys, atomic::Ordering::Relaxed);
+        self.aspas.store(aspas, atomic::Ordering::Relaxed);
+    }
+
     fn inc_bytes_read(&self, count: u64) {
         self.bytes_read.fetch_add(count, atomic::Ordering::Relaxed);
     }
@@ -803,6 +873,18 @@ impl RtrMetrics {
         "bytes_written", "the number of bytes written",
         MetricType::Counter, MetricUnit::Total,
     );
+    const ROUTE_ORIGINS_METRIC: Metric = Metric::new(
+        "rtr_route_origins", "the number of route origins in the VRP set",
+        MetricType::Gauge, MetricUnit::Total,
+    );
+    const ROUTER_KEYS_METRIC: Metric = Metric::new(
+        "rtr_router_keys", "the number of router keys in the VRP set",
+        MetricType::Gauge, MetricUnit::Total,
+    );
+    const ASPAS_METRIC: Metric = Metric::new(
+        "rtr_aspas", "the number of ASPAs in the VRP set",
+        MetricType::Gauge, MetricUnit::Total,
+    );
 
     const ISO_DATE: &'static [chrono::format::Item<'static>] = &[
         chrono::format::Item::Numeric(
@@ -873,6 +955,18 @@ impl metrics::Source for RtrMetrics {
             &Self::BYTES_WRITTEN_METRIC, Some(unit_name),
             self.bytes_written.load(atomic::Ordering::Relaxed)
         );
+        target.append_simple(
+            &Self::ROUTE_ORIGINS_METRIC, Some(unit_name),
+            self.route_origins.load(atomic::Ordering::Relaxed)
+        );
+        target.append_simple(
+            &Self::ROUTER_KEYS_METRIC, Some(unit_name),
+            self.router_keys.load(atomic::Ordering::Relaxed)
+        );
+        target.append_simple(
+            &Self::ASPAS_METRIC, Some(unit_name),
+            self.aspas.load(atomic::Ordering::Relaxed)
+        );
     }
 }
 
@@ -0,0 +1,4 @@
+pub mod routes;
+pub mod unit;
+
+pub use unit::StaticRoutesIn;
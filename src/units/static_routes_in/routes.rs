@@ -0,0 +1,294 @@
+//! The static routes and the changes between two sets of them.
+//!
+//! Routes are configured in sets of prefixes sharing their path attributes
+//! and the peer they appear to be received from, in the unit configuration
+//! or a TOML file of the same shape:
+//!
+//! ```toml
+//! [[routes]]
+//! peer_address = "192.0.2.1"
+//! peer_asn = 65000
+//! prefixes = ["198.51.100.0/24", "203.0.113.0/24"]
+//!
+//! [routes.attributes]
+//! next_hop = "192.0.2.1"
+//! as_path = [65000]
+//! communities = ["65000:1"]
+//! ```
+//!
+//! The attributes are those of the `http-in` unit.
+
+use std::{collections::BTreeMap, net::IpAddr};
+
+use inetnum::addr::Prefix;
+use serde::Deserialize;
+
+use crate::payload::RotondaRoute;
+use crate::units::http_in::batch::{Attributes, PeerUpdate};
+
+//------------ RouteSet ------------------------------------------------------
+
+/// Prefixes announced by a peer with the same attributes.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RouteSet {
+    pub peer_address: IpAddr,
+    pub peer_asn: u32,
+    pub prefixes: Vec<Prefix>,
+
+    #[serde(default)]
+    pub attributes: Attributes,
+}
+
+impl RouteSet {
+    /// Returns the routes of the set.
+    pub fn routes(&self) -> Result<Vec<RotondaRoute>, String> {
+        update(
+            self.peer_address,
+            self.peer_asn,
+            &self.attributes,
+            self.prefixes.clone(),
+        )
+        .routes()
+        .map(|routes| routes.announced)
+    }
+}
+
+/// The contents of a routes file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutesFile {
+    #[serde(default)]
+    pub routes: Vec<RouteSet>,
+}
+
+//------------ Table ---------------------------------------------------------
+
+/// The routes of each peer.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Table {
+    peers: BTreeMap<(IpAddr, u32), BTreeMap<Prefix, Attributes>>,
+}
+
+impl Table {
+    /// Checks the route sets and collects their routes.
+    pub fn new<'a>(
+        sets: impl IntoIterator<Item = &'a RouteSet>,
+    ) -> Result<Self, String> {
+        let mut res = Table::default();
+        for set in sets {
+            // Turning the routes into payloads checks them.
+            set.routes()?;
+            let routes = res
+                .peers
+                .entry((set.peer_address, set.peer_asn))
+                .or_default();
+            for prefix in &set.prefixes {
+                if routes.insert(*prefix, set.attributes.clone()).is_some() {
+                    return Err(format!(
+                        "peer {}: prefix {prefix} is configured more than \
+                        once",
+                        set.peer_address
+                    ));
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// The number of routes.
+    pub fn num_routes(&self) -> usize {
+        self.peers.values().map(BTreeMap::len).sum()
+    }
+
+    /// Returns the updates that turn the routes of `self` into those of
+    /// `new`.
+    ///
+    /// Routes that are new or whose attributes changed are announced,
+    /// routes that are gone withdrawn. If all routes of a peer are gone, the
+    /// peer goes down.
+    pub fn changes(&self, new: &Table) -> Vec<PeerUpdate> {
+        let mut res = vec![];
+        for (&(addr, asn), old_routes) in &self.peers {
+            if !new.peers.contains_key(&(addr, asn)) {
+                let mut down =
+                    update(addr, asn, &Attributes::default(), vec![]);
+                down.peer_down = true;
+                res.push(down);
+                continue;
+            }
+            let new_routes = &new.peers[&(addr, asn)];
+            let withdraw = old_routes
+                .keys()
+                .filter(|prefix| !new_routes.contains_key(prefix))
+                .copied()
+                .collect::<Vec<_>>();
+            if !withdraw.is_empty() {
+                let mut withdrawal =
+                    update(addr, asn, &Attributes::default(), vec![]);
+                withdrawal.withdraw = withdraw;
+                res.push(withdrawal);
+            }
+        }
+        for (&(addr, asn), new_routes) in &new.peers {
+            let old_routes = self.peers.get(&(addr, asn));
+            // An update carries a single set of attributes, so group the
+            // announcements by them.
+            let mut groups: Vec<PeerUpdate> = vec![];
+            for (prefix, attributes) in new_routes {
+                if old_routes.and_then(|routes| routes.get(prefix))
+                    == Some(attributes)
+                {
+                    continue;
+                }
+                match groups
+                    .iter_mut()
+                    .find(|group| &group.attributes == attributes)
+                {
+                    Some(group) => group.announce.push(*prefix),
+                    None => groups.push(update(
+                        addr,
+                        asn,
+                        attributes,
+                        vec![*prefix],
+                    )),
+                }
+            }
+            res.extend(groups);
+        }
+        res
+    }
+}
+
+fn update(
+    addr: IpAddr,
+    asn: u32,
+    attributes: &Attributes,
+    announce: Vec<Prefix>,
+) -> PeerUpdate {
+    PeerUpdate {
+        peer_address: addr,
+        peer_asn: asn,
+        announce,
+        withdraw: vec![],
+        attributes: attributes.clone(),
+        peer_down: false,
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(toml: &str) -> Result<Table, String> {
+        let file: RoutesFile =
+            toml::from_str(toml).map_err(|err| err.to_string())?;
+        Table::new(&file.routes)
+    }
+
+    fn prefixes(list: &[&str]) -> Vec<Prefix> {
+        list.iter().map(|prefix| prefix.parse().unwrap()).collect()
+    }
+
+    const ROUTES: &str = r#"
+        [[routes]]
+        peer_address = "192.0.2.1"
+        peer_asn = 65000
+        prefixes = ["198.51.100.0/24", "203.0.113.0/24"]
+        attributes = { next_hop = "192.0.2.1", as_path = [65000] }
+
+        [[routes]]
+        peer_address = "192.0.2.1"
+        peer_asn = 65000
+        prefixes = ["2001:db8::/32"]
+        attributes = { next_hop = "2001:db8::1", as_path = [65000] }
+
+        [[routes]]
+        peer_address = "192.0.2.2"
+        peer_asn = 65001
+        prefixes = ["192.0.2.0/24"]
+        attributes = { next_hop = "192.0.2.2" }
+    "#;
+
+    #[test]
+    fn route_sets_are_checked() {
+        assert_eq!(table(ROUTES).unwrap().num_routes(), 4);
+
+        let missing_next_hop = r#"
+            [[routes]]
+            peer_address = "192.0.2.1"
+            peer_asn = 65000
+            prefixes = ["198.51.100.0/24"]
+        "#;
+        assert!(table(missing_next_hop).is_err());
+
+        let mixed_families = r#"
+            [[routes]]
+            peer_address = "192.0.2.1"
+            peer_asn = 65000
+            prefixes = ["198.51.100.0/24", "2001:db8::/32"]
+            attributes = { next_hop = "192.0.2.1" }
+        "#;
+        assert!(table(mixed_families).is_err());
+
+        let twice = format!(
+            "{ROUTES}
+            [[routes]]
+            peer_address = \"192.0.2.2\"
+            peer_asn = 65001
+            prefixes = [\"192.0.2.0/24\"]
+            attributes = {{ next_hop = \"192.0.2.2\", med = 10 }}
+            "
+        );
+        assert_eq!(
+            table(&twice).unwrap_err(),
+            "peer 192.0.2.2: prefix 192.0.2.0/24 is configured more than \
+            once"
+        );
+    }
+
+    #[test]
+    fn changes_are_found() {
+        let old = table(ROUTES).unwrap();
+
+        // Everything is new at first.
+        let changes = Table::default().changes(&old);
+        assert_eq!(changes.len(), 3);
+        assert_eq!(
+            changes[0].announce,
+            prefixes(&["198.51.100.0/24", "203.0.113.0/24"])
+        );
+        assert_eq!(changes[1].announce, prefixes(&["2001:db8::/32"]));
+        assert_eq!(changes[2].peer_asn, 65001);
+        assert!(old.changes(&old).is_empty());
+
+        // One route gets other attributes, one is removed and the second
+        // peer is gone.
+        let new = table(
+            r#"
+            [[routes]]
+            peer_address = "192.0.2.1"
+            peer_asn = 65000
+            prefixes = ["198.51.100.0/24"]
+            attributes = { next_hop = "192.0.2.1", as_path = [65000] }
+
+            [[routes]]
+            peer_address = "192.0.2.1"
+            peer_asn = 65000
+            prefixes = ["2001:db8::/32"]
+            attributes = { next_hop = "2001:db8::1", as_path = [65010] }
+            "#,
+        )
+        .unwrap();
+        let changes = old.changes(&new);
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].withdraw, prefixes(&["203.0.113.0/24"]));
+        assert!(changes[0].announce.is_empty());
+        assert_eq!(changes[1].peer_asn, 65001);
+        assert!(changes[1].peer_down);
+        assert_eq!(changes[2].announce, prefixes(&["2001:db8::/32"]));
+        assert_eq!(changes[2].attributes.as_path, [65010]);
+    }
+}
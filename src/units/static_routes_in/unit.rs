@@ -0,0 +1,447 @@
+//! Injecting static routes.
+//!
+//! This unit announces a fixed set of routes, for instance to advertise
+//! anchor prefixes or to test the targets and filters of a pipeline with
+//! known input. The routes are given in the unit configuration, in a TOML
+//! file, or both, as described in the [`routes`] module, and are announced
+//! when the unit starts.
+//!
+//! The file is checked for changes regularly, and the configuration when
+//! Rotonda is told to reload it. Only the differences are sent downstream:
+//! new routes and routes with changed attributes are announced, routes
+//! that are gone are withdrawn. If the routes cannot be read, e.g. because
+//! the file has an error, the routes stay as they are.
+//!
+//! [`routes`]: super::routes
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    comms::{Gate, GateMetrics, GateStatus, Terminated},
+    config::ConfigPath,
+    ingress::IngressInfo,
+    manager::{Component, WaitPoint},
+    metrics::{self, Metric, MetricType, MetricUnit},
+    units::{
+        ris_live_in::unit::{Converted, Converter},
+        Unit,
+    },
+};
+
+use super::routes::{RouteSet, RoutesFile, Table};
+
+/// The name of the ingress the peers of the routes are grouped under.
+const HOST: &str = "static";
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct StaticRoutesIn {
+    /// The routes to announce.
+    #[serde(default)]
+    pub routes: Vec<RouteSet>,
+
+    /// A TOML file with more routes to announce.
+    #[serde(default)]
+    pub file: Option<ConfigPath>,
+
+    /// How often to check the file for changes.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "StaticRoutesIn::default_reload_interval_secs")]
+    pub reload_interval_secs: Duration,
+}
+
+impl StaticRoutesIn {
+    fn default_reload_interval_secs() -> Duration {
+        Duration::from_secs(5)
+    }
+
+    pub async fn run(
+        self,
+        mut component: Component,
+        gate: Gate,
+        mut waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let metrics = Arc::new(StaticRoutesInMetrics::new(&gate));
+        component.register_metrics(metrics.clone());
+
+        let content = match self.read_file().await {
+            Ok(content) => content,
+            Err(err) => {
+                error!("Unit {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+        let table = match self.table(content.as_deref()) {
+            Ok(table) => table,
+            Err(err) => {
+                error!("Unit {}: {err}", component.name());
+                return Err(Terminated);
+            }
+        };
+
+        gate.process_until(waitpoint.ready()).await?;
+        waitpoint.running().await;
+
+        let ingresses = component.ingresses().clone();
+        let parent_id = ingresses.register();
+        ingresses.update_info(
+            parent_id,
+            IngressInfo::new()
+                .with_unit_name(component.name().as_ref())
+                .with_desc("static-routes-in unit"),
+        );
+
+        let mut interval = tokio::time::interval(self.reload_interval_secs);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut runner = StaticRoutesRunner {
+            config: self,
+            gate,
+            converter: Converter::new(ingresses, parent_id, "static routes"),
+            metrics,
+            table: Table::default(),
+            content,
+        };
+        runner.apply(table).await;
+        info!(
+            "Unit {}: announced {} static routes",
+            component.name(),
+            runner.table.num_routes()
+        );
+
+        loop {
+            tokio::select! {
+                status = runner.gate.process() => match status {
+                    Ok(GateStatus::ReportLinks { report }) => {
+                        report.declare_source();
+                    }
+                    Ok(GateStatus::Reconfiguring {
+                        new_config: Unit::StaticRoutesIn(new_config),
+                    }) => {
+                        runner.config = new_config;
+                        if let Err(err) = runner.reload().await {
+                            warn!(
+                                "Unit {}: keeping the current routes: {err}",
+                                component.name()
+                            );
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(Terminated) => return Err(Terminated),
+                },
+                _ = interval.tick(), if runner.config.file.is_some() => {
+                    if let Err(err) = runner.check_file().await {
+                        warn!(
+                            "Unit {}: keeping the current routes: {err}",
+                            component.name()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads the routes file, if there is one.
+    async fn read_file(&self) -> Result<Option<Vec<u8>>, String> {
+        let Some(path) = &self.file else {
+            return Ok(None);
+        };
+        tokio::fs::read(path)
+            .await
+            .map(Some)
+            .map_err(|err| format!("cannot read {}: {err}", path.display()))
+    }
+
+    /// Collects the configured routes and those of the file `content`.
+    fn table(&self, content: Option<&[u8]>) -> Result<Table, String> {
+        let file = match (content, &self.file) {
+            (Some(content), Some(path)) => {
+                let parse = || {
+                    let content = std::str::from_utf8(content)
+                        .map_err(|err| err.to_string())?;
+                    toml::from_str::<RoutesFile>(content)
+                        .map_err(|err| err.to_string())
+                };
+                parse().map_err(|err| format!("{}: {err}", path.display()))?
+            }
+            _ => RoutesFile { routes: vec![] },
+        };
+        Table::new(self.routes.iter().chain(&file.routes))
+    }
+}
+
+//------------ StaticRoutesRunner --------------------------------------------
+
+struct StaticRoutesRunner {
+    config: StaticRoutesIn,
+    gate: Gate,
+
+    /// Turns the routes into payloads, keeping the ingresses of the peers.
+    converter: Converter,
+
+    metrics: Arc<StaticRoutesInMetrics>,
+
+    /// The routes currently announced.
+    table: Table,
+
+    /// The contents of the routes file when it was last read.
+    content: Option<Vec<u8>>,
+}
+
+impl StaticRoutesRunner {
+    /// Reloads the routes if the file changed.
+    async fn check_file(&mut self) -> Result<(), String> {
+        let content = self.config.read_file().await?;
+        if content == self.content {
+            return Ok(());
+        }
+        // Remember the new content even if it is broken, so that the error
+        // is only reported once.
+        self.content = content;
+        self.reload().await
+    }
+
+    /// Reloads the routes from the configuration and the file.
+    async fn reload(&mut self) -> Result<(), String> {
+        let table = self.config.table(self.content.as_deref());
+        let table = table.inspect_err(|_| {
+            self.metrics.num_failed_reloads.fetch_add(1, SeqCst);
+        })?;
+        self.metrics.num_reloads.fetch_add(1, SeqCst);
+        self.apply(table).await;
+        Ok(())
+    }
+
+    /// Sends the changes from the current routes to `table` downstream.
+    async fn apply(&mut self, table: Table) {
+        let (mut announced, mut withdrawn) = (0, 0);
+        for update in self.table.changes(&table) {
+            let converted = if update.peer_down {
+                self.converter.peer_down(
+                    HOST,
+                    update.peer_address,
+                    update.peer_asn(),
+                )
+            } else {
+                // The routes of the table have been checked already.
+                let Ok(routes) = update.routes() else {
+                    continue;
+                };
+                self.converter.convert_routes(
+                    HOST,
+                    update.peer_address,
+                    update.peer_asn(),
+                    routes.announced,
+                    routes.withdrawn,
+                )
+            };
+            if let Converted::Update(update, ann, wd) = converted {
+                announced += ann;
+                withdrawn += wd;
+                self.gate.update_data(update).await;
+            }
+        }
+        self.metrics.num_announcements.fetch_add(announced, SeqCst);
+        self.metrics.num_withdrawals.fetch_add(withdrawn, SeqCst);
+        self.metrics.num_routes.store(table.num_routes(), SeqCst);
+        self.table = table;
+    }
+}
+
+//------------ StaticRoutesInMetrics -----------------------------------------
+
+#[derive(Debug, Default)]
+struct StaticRoutesInMetrics {
+    gate: Arc<GateMetrics>,
+    num_routes: AtomicUsize,
+    num_reloads: AtomicUsize,
+    num_failed_reloads: AtomicUsize,
+    num_announcements: AtomicUsize,
+    num_withdrawals: AtomicUsize,
+}
+
+impl StaticRoutesInMetrics {
+    fn new(gate: &Gate) -> Self {
+        Self {
+            gate: gate.metrics(),
+            ..Default::default()
+        }
+    }
+
+    const NUM_ROUTES_METRIC: Metric = Metric::new(
+        "static_routes_in_num_routes",
+        "the number of static routes currently announced",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const NUM_RELOADS_METRIC: Metric = Metric::new(
+        "static_routes_in_num_reloads",
+        "the number of times the routes were reloaded",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_FAILED_RELOADS_METRIC: Metric = Metric::new(
+        "static_routes_in_num_failed_reloads",
+        "the number of times the routes could not be reloaded",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ANNOUNCEMENTS_METRIC: Metric = Metric::new(
+        "static_routes_in_num_announcements",
+        "the number of route announcements sent",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WITHDRAWALS_METRIC: Metric = Metric::new(
+        "static_routes_in_num_withdrawals",
+        "the number of route withdrawals sent",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for StaticRoutesInMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        self.gate.append(unit_name, target);
+        target.append_simple(
+            &Self::NUM_ROUTES_METRIC,
+            Some(unit_name),
+            self.num_routes.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_RELOADS_METRIC,
+            Some(unit_name),
+            self.num_reloads.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_FAILED_RELOADS_METRIC,
+            Some(unit_name),
+            self.num_failed_reloads.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ANNOUNCEMENTS_METRIC,
+            Some(unit_name),
+            self.num_announcements.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WITHDRAWALS_METRIC,
+            Some(unit_name),
+            self.num_withdrawals.load(SeqCst),
+        );
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use inetnum::asn::Asn;
+
+    use crate::ingress;
+
+    use super::*;
+
+    #[test]
+    fn config_deserialization() {
+        let toml = r#"
+        type = "static-routes-in"
+        file = "/etc/rotonda/routes.toml"
+
+        [[routes]]
+        peer_address = "192.0.2.1"
+        peer_asn = 65000
+        prefixes = ["198.51.100.0/24"]
+        attributes = { next_hop = "192.0.2.1", communities = ["NO_EXPORT"] }
+        "#;
+        let Unit::StaticRoutesIn(config) = toml::from_str(toml).unwrap()
+        else {
+            panic!("expected a static-routes-in unit");
+        };
+        assert_eq!(config.routes.len(), 1);
+        assert_eq!(config.reload_interval_secs, Duration::from_secs(5));
+        let table = config.table(Some(b"")).unwrap();
+        assert_eq!(table.num_routes(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn file_changes_are_announced() {
+        let path = std::env::temp_dir()
+            .join(format!("rotonda-static-{}.toml", uuid::Uuid::new_v4()));
+        let routes = |prefixes: &str| {
+            format!(
+                "[[routes]]\n\
+                peer_address = \"192.0.2.1\"\n\
+                peer_asn = 65000\n\
+                prefixes = [{prefixes}]\n\
+                attributes = {{ next_hop = \"192.0.2.1\" }}\n"
+            )
+        };
+        std::fs::write(&path, routes("\"198.51.100.0/24\"")).unwrap();
+
+        let config = StaticRoutesIn {
+            routes: vec![],
+            file: Some(ConfigPath::from(PathBuf::from(&path))),
+            reload_interval_secs: Duration::from_secs(5),
+        };
+        let (gate, _agent) = Gate::new(0);
+        let ingresses = Arc::new(ingress::Register::default());
+        let parent_id = ingresses.register();
+        let mut runner = StaticRoutesRunner {
+            config,
+            gate: gate.clone(),
+            converter: Converter::new(
+                ingresses.clone(),
+                parent_id,
+                "static routes",
+            ),
+            metrics: Arc::new(StaticRoutesInMetrics::new(&gate)),
+            table: Table::default(),
+            content: None,
+        };
+        let counts = |runner: &StaticRoutesRunner| {
+            [
+                runner.metrics.num_routes.load(SeqCst),
+                runner.metrics.num_reloads.load(SeqCst),
+                runner.metrics.num_failed_reloads.load(SeqCst),
+                runner.metrics.num_announcements.load(SeqCst),
+                runner.metrics.num_withdrawals.load(SeqCst),
+            ]
+        };
+
+        runner.check_file().await.unwrap();
+        assert_eq!(counts(&runner), [1, 1, 0, 1, 0]);
+        assert!(ingresses
+            .find_all(|info| info.remote_asn == Some(Asn::from_u32(65000)))
+            .pop()
+            .is_some());
+
+        // Unchanged files are left alone.
+        runner.check_file().await.unwrap();
+        assert_eq!(counts(&runner), [1, 1, 0, 1, 0]);
+
+        // A broken file keeps the routes.
+        std::fs::write(&path, "[[routes]]\npeer_asn = 1\n").unwrap();
+        assert!(runner.check_file().await.is_err());
+        assert_eq!(counts(&runner), [1, 1, 1, 1, 0]);
+
+        std::fs::write(&path, routes("\"203.0.113.0/24\"")).unwrap();
+        runner.check_file().await.unwrap();
+        assert_eq!(counts(&runner), [1, 2, 1, 2, 1]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
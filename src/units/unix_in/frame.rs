@@ -0,0 +1,159 @@
+//! The framing of the messages sent to the `unix-in` unit.
+//!
+//! Every message is preceded by its length and type:
+//!
+//! ```text
+//! +-----------------+----------+---------------------------+
+//! | length (4)      | type (1) | body (length - 1)         |
+//! +-----------------+----------+---------------------------+
+//! ```
+//!
+//! The length, in network byte order, covers the type and the body. The
+//! types are:
+//!
+//! * 1: a BMP message. The BMP messages of a connection are treated as
+//!   coming from a single monitored router.
+//! * 2: a BGP UPDATE message, preceded by the address of the peer it was
+//!   received from, as 16 bytes with IPv4 addresses mapped into IPv6, and
+//!   the peer's four octet AS number.
+//! * 3: a batch of route updates in JSON, in the format accepted by the
+//!   `http-in` unit as described in the [`batch`] module.
+//!
+//! [`batch`]: crate::units::http_in::batch
+
+use std::{
+    io,
+    net::{IpAddr, Ipv6Addr},
+};
+
+use bytes::{Buf, Bytes};
+use inetnum::asn::Asn;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const BMP: u8 = 1;
+const BGP: u8 = 2;
+const JSON: u8 = 3;
+
+//------------ Frame ---------------------------------------------------------
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Frame {
+    Bmp(Bytes),
+    Bgp {
+        peer: IpAddr,
+        peer_asn: Asn,
+        msg: Bytes,
+    },
+    Json(Bytes),
+}
+
+impl Frame {
+    /// Parses the type and body of a frame.
+    pub fn parse(mut data: Bytes) -> Result<Self, String> {
+        if data.is_empty() {
+            return Err("empty frame".into());
+        }
+        match data.get_u8() {
+            BMP => Ok(Frame::Bmp(data)),
+            BGP => {
+                if data.len() < 20 {
+                    return Err("truncated BGP frame".into());
+                }
+                let mut addr = [0; 16];
+                data.copy_to_slice(&mut addr);
+                let peer = Ipv6Addr::from(addr).to_canonical();
+                let peer_asn = Asn::from_u32(data.get_u32());
+                Ok(Frame::Bgp {
+                    peer,
+                    peer_asn,
+                    msg: data,
+                })
+            }
+            JSON => Ok(Frame::Json(data)),
+            frame_type => Err(format!("unknown frame type {frame_type}")),
+        }
+    }
+}
+
+/// Reads the next frame from `reader`, returning `None` if the stream ended
+/// cleanly before it.
+pub async fn read_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+    max_size: usize,
+) -> io::Result<Option<Bytes>> {
+    let mut len = [0; 4];
+    let read = reader.read(&mut len).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    reader.read_exact(&mut len[read..]).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {len} bytes exceeds the maximum of {max_size}"),
+        ));
+    }
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data).await?;
+    Ok(Some(data.into()))
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// Encodes a frame of `frame_type` with `body`.
+    pub fn frame(frame_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut frame = ((body.len() + 1) as u32).to_be_bytes().to_vec();
+        frame.push(frame_type);
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    /// Encodes a BGP frame for `msg` from `peer`.
+    pub fn bgp_frame(peer: IpAddr, peer_asn: u32, msg: &[u8]) -> Vec<u8> {
+        let addr = match peer {
+            IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+            IpAddr::V6(addr) => addr,
+        };
+        let mut body = addr.octets().to_vec();
+        body.extend_from_slice(&peer_asn.to_be_bytes());
+        body.extend_from_slice(msg);
+        frame(BGP, &body)
+    }
+
+    #[tokio::test]
+    async fn frames_are_read_and_parsed() {
+        let peer = IpAddr::from([192, 0, 2, 1]);
+        let mut data = bgp_frame(peer, 65000, b"update");
+        data.extend_from_slice(&frame(JSON, b"{}"));
+        data.extend_from_slice(&frame(9, b""));
+        data.extend_from_slice(&frame(BMP, &[0; 100]));
+        let mut reader = &data[..];
+
+        let frame = read_frame(&mut reader, 64).await.unwrap().unwrap();
+        assert_eq!(
+            Frame::parse(frame),
+            Ok(Frame::Bgp {
+                peer,
+                peer_asn: Asn::from_u32(65000),
+                msg: Bytes::from_static(b"update"),
+            })
+        );
+        let frame = read_frame(&mut reader, 64).await.unwrap().unwrap();
+        assert_eq!(
+            Frame::parse(frame),
+            Ok(Frame::Json(Bytes::from_static(b"{}")))
+        );
+        let frame = read_frame(&mut reader, 64).await.unwrap().unwrap();
+        assert!(Frame::parse(frame).is_err());
+
+        // The BMP frame is too large.
+        assert!(read_frame(&mut reader, 64).await.is_err());
+        assert_eq!(read_frame(&mut &[][..], 64).await.unwrap(), None);
+        assert!(read_frame(&mut &[0, 0][..], 64).await.is_err());
+    }
+}
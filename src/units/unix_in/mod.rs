@@ -0,0 +1,4 @@
+mod frame;
+pub mod unit;
+
+pub use unit::UnixIn;
@@ -0,0 +1,638 @@
+//! Ingesting routes from local processes over a UNIX socket.
+//!
+//! This unit listens on a UNIX socket for tools running on the same host,
+//! which send BMP messages, BGP UPDATE messages and JSON batches of route
+//! updates without going through TCP. The messages are framed as described
+//! in the [`frame`] module.
+//!
+//! Every connection is registered as an ingress of the unit, named after
+//! the credentials of the connecting process: its name, process ID and user
+//! ID as reported by the kernel. The peers of BGP and JSON messages are
+//! registered as ingresses of their connection, as is the router sending
+//! the BMP messages, with its monitored peers below it in turn.
+//! Connections can be limited to processes of certain users. When a
+//! connection is closed, all routes received over it are withdrawn.
+//!
+//! [`frame`]: super::frame
+
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+    time::Instant,
+};
+
+use bytes::Bytes;
+use log::{debug, error, info, warn};
+use routecore::bmp::message::Message as BmpMsg;
+use serde::Deserialize;
+use tokio::net::{unix::UCred, UnixListener, UnixStream};
+
+use crate::{
+    common::net::bind_unix,
+    comms::{Gate, GateMetrics, GateStatus, Terminated},
+    ingress::{self, IngressId, IngressInfo},
+    manager::{Component, WaitPoint},
+    metrics::{self, Metric, MetricType, MetricUnit},
+    payload::{Update, UpstreamStatus},
+    units::{
+        bmp_tcp_in::{
+            metrics::BmpTcpInMetrics,
+            state_machine::{BmpState, BmpStateMachineMetrics, MessageType},
+            status_reporter::BmpTcpInStatusReporter,
+        },
+        http_in::batch::{Batch, PeerUpdate, Routes},
+        ris_live_in::unit::{Converted, Converter},
+    },
+};
+
+use super::frame::{read_frame, Frame};
+
+//------------ Configuration -------------------------------------------------
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UnixIn {
+    /// The path of the socket to listen on.
+    pub path: PathBuf,
+
+    /// The user IDs of the processes allowed to connect. Without any, every
+    /// process that can open the socket may connect.
+    #[serde(default)]
+    pub allowed_uids: Vec<u32>,
+
+    /// The largest message accepted, in bytes.
+    #[serde(default = "UnixIn::default_max_message_size")]
+    pub max_message_size: usize,
+}
+
+impl UnixIn {
+    fn default_max_message_size() -> usize {
+        16 * 1024 * 1024
+    }
+
+    pub async fn run(
+        self,
+        mut component: Component,
+        gate: Gate,
+        mut waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        let metrics = Arc::new(UnixInMetrics::new(&gate));
+        component.register_metrics(metrics.clone());
+        let bmp_metrics = Arc::new(BmpStateMachineMetrics::new());
+        component.register_metrics(bmp_metrics.clone());
+
+        gate.process_until(waitpoint.ready()).await?;
+        waitpoint.running().await;
+
+        let listener = match bind_unix(&self.path) {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!(
+                    "Unit {}: cannot listen on {}: {err}",
+                    component.name(),
+                    self.path.display()
+                );
+                return Err(Terminated);
+            }
+        };
+
+        let ingresses = component.ingresses().clone();
+        let parent_id = ingresses.register();
+        ingresses.update_info(
+            parent_id,
+            IngressInfo::new()
+                .with_unit_name(component.name().as_ref())
+                .with_desc("unix-in unit"),
+        );
+        let receiver = Arc::new(UnixInReceiver {
+            name: component.name().to_string(),
+            gate: gate.clone(),
+            ingresses,
+            parent_id,
+            metrics,
+            bmp_metrics,
+            bmp_status_reporter: Arc::new(BmpTcpInStatusReporter::new(
+                component.name(),
+                Arc::new(BmpTcpInMetrics::default()),
+            )),
+            config: self,
+        });
+        let accepting = tokio::spawn(receiver.accept(listener));
+
+        // The connections are handled in their own tasks, so here only the
+        // gate needs to be processed.
+        let res = loop {
+            match gate.process().await {
+                Ok(GateStatus::ReportLinks { report }) => {
+                    report.declare_source();
+                }
+                Ok(GateStatus::Reconfiguring { .. }) => {
+                    warn!(
+                        "Unit {}: reconfiguring unix-in requires a restart",
+                        component.name()
+                    );
+                }
+                Ok(_) => {}
+                Err(Terminated) => break Err(Terminated),
+            }
+        };
+        accepting.abort();
+        res
+    }
+}
+
+/// Describes a process by its credentials, e.g. `bgp-feeder (pid 4321, uid
+/// 1000)`.
+fn producer_name(cred: &UCred) -> String {
+    let Some(pid) = cred.pid() else {
+        return format!("uid {}", cred.uid());
+    };
+    match std::fs::read_to_string(format!("/proc/{pid}/comm")) {
+        Ok(comm) => {
+            format!("{} (pid {pid}, uid {})", comm.trim(), cred.uid())
+        }
+        Err(_) => format!("pid {pid}, uid {}", cred.uid()),
+    }
+}
+
+//------------ UnixInReceiver ------------------------------------------------
+
+struct UnixInReceiver {
+    config: UnixIn,
+    name: String,
+    gate: Gate,
+    ingresses: Arc<ingress::Register>,
+    parent_id: IngressId,
+    metrics: Arc<UnixInMetrics>,
+    bmp_metrics: Arc<BmpStateMachineMetrics>,
+    bmp_status_reporter: Arc<BmpTcpInStatusReporter>,
+}
+
+impl UnixInReceiver {
+    /// Accept the connections of allowed processes on `listener`.
+    async fn accept(self: Arc<Self>, listener: UnixListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    warn!("Unit {}: accepting failed: {err}", self.name);
+                    continue;
+                }
+            };
+            let cred = match stream.peer_cred() {
+                Ok(cred) => cred,
+                Err(err) => {
+                    warn!(
+                        "Unit {}: cannot get peer credentials: {err}",
+                        self.name
+                    );
+                    continue;
+                }
+            };
+            let producer = producer_name(&cred);
+            if !self.config.allowed_uids.is_empty()
+                && !self.config.allowed_uids.contains(&cred.uid())
+            {
+                self.metrics.num_rejected_connections.fetch_add(1, SeqCst);
+                warn!(
+                    "Unit {}: rejected connection from {producer}",
+                    self.name
+                );
+                continue;
+            }
+            tokio::spawn(self.clone().read(stream, producer));
+        }
+    }
+
+    /// Process the messages of a connection, until it is closed.
+    async fn read(self: Arc<Self>, mut stream: UnixStream, producer: String) {
+        info!("Unit {}: accepted connection from {producer}", self.name);
+        self.metrics.num_connections.fetch_add(1, SeqCst);
+        let mut connection = UnixInConnection::new(&self, &producer);
+        loop {
+            match read_frame(&mut stream, self.config.max_message_size).await
+            {
+                Ok(Some(data)) => connection.process(data).await,
+                Ok(None) => break,
+                Err(err) => {
+                    warn!(
+                        "Unit {}: reading from {producer} failed: {err}",
+                        self.name
+                    );
+                    break;
+                }
+            }
+        }
+        info!("Unit {}: connection from {producer} closed", self.name);
+        self.metrics.num_connections.fetch_sub(1, SeqCst);
+
+        // Withdraw the routes of all peers heard of over the connection,
+        // including those monitored over BMP.
+        let ingress_id = connection.ingress_id;
+        let peers = self.ingresses.descendants(ingress_id);
+        self.gate
+            .update_data(Update::WithdrawBulk(peers.into()))
+            .await;
+        self.gate
+            .update_data(Update::UpstreamStatusChange(
+                UpstreamStatus::EndOfStream { ingress_id },
+            ))
+            .await;
+    }
+}
+
+//------------ UnixInConnection ----------------------------------------------
+
+/// The state of a single connection.
+struct UnixInConnection<'a> {
+    receiver: &'a UnixInReceiver,
+
+    /// The name of the connection's ingress.
+    producer: &'a str,
+
+    ingress_id: IngressId,
+
+    /// The converter for BGP UPDATEs and JSON batches.
+    converter: Converter,
+
+    /// The state of the BMP messages, created with the first of them.
+    bmp: Option<BmpState>,
+}
+
+impl<'a> UnixInConnection<'a> {
+    fn new(receiver: &'a UnixInReceiver, producer: &'a str) -> Self {
+        let mut converter = Converter::new(
+            receiver.ingresses.clone(),
+            receiver.parent_id,
+            "UNIX socket connection",
+        );
+        let ingress_id = converter.collector_ingress(producer);
+        Self {
+            receiver,
+            producer,
+            ingress_id,
+            converter,
+            bmp: None,
+        }
+    }
+
+    /// Process a frame, sending its routes downstream.
+    async fn process(&mut self, data: Bytes) {
+        let metrics = &self.receiver.metrics;
+        metrics.num_messages.fetch_add(1, SeqCst);
+        let res = match Frame::parse(data) {
+            Ok(Frame::Bmp(msg)) => self.process_bmp(msg).await,
+            Ok(Frame::Bgp {
+                peer,
+                peer_asn,
+                msg,
+            }) => match self.converter.convert_update(
+                self.producer,
+                peer,
+                peer_asn,
+                &msg,
+            ) {
+                Ok(converted) => {
+                    self.send(converted).await;
+                    Ok(())
+                }
+                Err(err) => Err(err),
+            },
+            Ok(Frame::Json(body)) => self.process_batch(&body).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            metrics.num_invalid_messages.fetch_add(1, SeqCst);
+            debug!("Ignoring message from {}: {err}", self.producer);
+        }
+    }
+
+    async fn process_bmp(&mut self, msg: Bytes) -> Result<(), String> {
+        let msg = BmpMsg::from_octets(msg).map_err(|err| err.to_string())?;
+        let receiver = self.receiver;
+        let state = self.bmp.take().unwrap_or_else(|| {
+            // The router gets an ingress of its own, so its initiation
+            // message does not rename the connection.
+            let router_id = receiver.ingresses.register();
+            receiver.ingresses.update_info(
+                router_id,
+                IngressInfo::new().with_parent(self.ingress_id),
+            );
+            BmpState::new(
+                router_id,
+                Arc::new(self.producer.to_string()),
+                receiver.bmp_status_reporter.clone(),
+                receiver.bmp_metrics.clone(),
+                receiver.ingresses.clone(),
+            )
+        });
+        let res = state.process_msg(Instant::now(), msg, None);
+        let err = match res.message_type {
+            MessageType::RoutingUpdate { update } => {
+                receiver.gate.update_data(update).await;
+                None
+            }
+            MessageType::InvalidMessage { err, .. } => Some(err),
+            MessageType::Aborted => {
+                // Start afresh with the next message.
+                return Err("BMP processing aborted".into());
+            }
+            MessageType::StateTransition | MessageType::Other => None,
+        };
+        self.bmp = Some(res.next_state);
+        err.map_or(Ok(()), Err)
+    }
+
+    /// Process a JSON batch, which is checked completely before any of it
+    /// is sent downstream.
+    async fn process_batch(&mut self, body: &[u8]) -> Result<(), String> {
+        let batch: Batch =
+            serde_json::from_slice(body).map_err(|err| err.to_string())?;
+        let routes = batch
+            .updates
+            .iter()
+            .map(PeerUpdate::routes)
+            .collect::<Result<Vec<_>, _>>()?;
+        for (
+            update,
+            Routes {
+                announced,
+                withdrawn,
+            },
+        ) in batch.updates.iter().zip(routes)
+        {
+            let converted = if update.peer_down {
+                self.converter.peer_down(
+                    self.producer,
+                    update.peer_address,
+                    update.peer_asn(),
+                )
+            } else {
+                self.converter.convert_routes(
+                    self.producer,
+                    update.peer_address,
+                    update.peer_asn(),
+                    announced,
+                    withdrawn,
+                )
+            };
+            self.send(converted).await;
+        }
+        Ok(())
+    }
+
+    async fn send(&self, converted: Converted) {
+        if let Converted::Update(update, announced, withdrawn) = converted {
+            let metrics = &self.receiver.metrics;
+            metrics.num_announcements.fetch_add(announced, SeqCst);
+            metrics.num_withdrawals.fetch_add(withdrawn, SeqCst);
+            self.receiver.gate.update_data(update).await;
+        }
+    }
+}
+
+//------------ UnixInMetrics -------------------------------------------------
+
+#[derive(Debug, Default)]
+struct UnixInMetrics {
+    gate: Arc<GateMetrics>,
+    num_connections: AtomicUsize,
+    num_rejected_connections: AtomicUsize,
+    num_messages: AtomicUsize,
+    num_invalid_messages: AtomicUsize,
+    num_announcements: AtomicUsize,
+    num_withdrawals: AtomicUsize,
+}
+
+impl UnixInMetrics {
+    fn new(gate: &Gate) -> Self {
+        Self {
+            gate: gate.metrics(),
+            ..Default::default()
+        }
+    }
+
+    const NUM_CONNECTIONS_METRIC: Metric = Metric::new(
+        "unix_in_num_connections",
+        "the number of open connections",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const NUM_REJECTED_CONNECTIONS_METRIC: Metric = Metric::new(
+        "unix_in_num_rejected_connections",
+        "the number of connections from processes of users not allowed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_MESSAGES_METRIC: Metric = Metric::new(
+        "unix_in_num_messages",
+        "the number of messages received",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_INVALID_MESSAGES_METRIC: Metric = Metric::new(
+        "unix_in_num_invalid_messages",
+        "the number of messages that could not be processed",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ANNOUNCEMENTS_METRIC: Metric = Metric::new(
+        "unix_in_num_announcements",
+        "the number of route announcements in BGP and JSON messages",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WITHDRAWALS_METRIC: Metric = Metric::new(
+        "unix_in_num_withdrawals",
+        "the number of route withdrawals in BGP and JSON messages",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for UnixInMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        self.gate.append(unit_name, target);
+        target.append_simple(
+            &Self::NUM_CONNECTIONS_METRIC,
+            Some(unit_name),
+            self.num_connections.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_REJECTED_CONNECTIONS_METRIC,
+            Some(unit_name),
+            self.num_rejected_connections.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_INVALID_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_invalid_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ANNOUNCEMENTS_METRIC,
+            Some(unit_name),
+            self.num_announcements.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WITHDRAWALS_METRIC,
+            Some(unit_name),
+            self.num_withdrawals.load(SeqCst),
+        );
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, os::unix::fs::MetadataExt, time::Duration};
+
+    use inetnum::asn::Asn;
+    use tokio::io::AsyncWriteExt;
+
+    use crate::bgp::encode::{
+        mk_initiation_msg, mk_peer_up_notification_msg, mk_per_peer_header,
+        mk_route_monitoring_msg, Announcements, Prefixes, RAW_UPDATE,
+    };
+
+    use super::{
+        super::frame::tests::{bgp_frame, frame},
+        *,
+    };
+
+    fn mk_receiver(
+        path: &std::path::Path,
+        allowed_uids: &[u32],
+    ) -> (Arc<UnixInReceiver>, Arc<ingress::Register>) {
+        let (gate, _agent) = Gate::new(0);
+        let ingresses = Arc::new(ingress::Register::default());
+        let parent_id = ingresses.register();
+        let receiver = Arc::new(UnixInReceiver {
+            config: UnixIn {
+                path: path.into(),
+                allowed_uids: allowed_uids.into(),
+                max_message_size: UnixIn::default_max_message_size(),
+            },
+            name: "unix".into(),
+            metrics: Arc::new(UnixInMetrics::new(&gate)),
+            gate,
+            ingresses: ingresses.clone(),
+            parent_id,
+            bmp_metrics: Default::default(),
+            bmp_status_reporter: Arc::new(BmpTcpInStatusReporter::new(
+                "unix",
+                Default::default(),
+            )),
+        });
+        (receiver, ingresses)
+    }
+
+    #[test]
+    fn config_deserialization() {
+        let config: UnixIn = toml::from_str(
+            r#"
+            path = "/run/rotonda/unix-in.sock"
+            allowed_uids = [0, 1000]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.allowed_uids, [0, 1000]);
+        assert_eq!(config.max_message_size, 16 * 1024 * 1024);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn framed_messages_are_processed() {
+        let path = std::env::temp_dir()
+            .join(format!("rotonda-unix-in-{}.sock", uuid::Uuid::new_v4()));
+        let uid = std::fs::metadata("/proc/self").unwrap().uid();
+        let (receiver, ingresses) = mk_receiver(&path, &[uid]);
+        let accepting =
+            tokio::spawn(receiver.clone().accept(bind_unix(&path).unwrap()));
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        let mut data = bgp_frame(
+            IpAddr::from([192, 0, 2, 1]),
+            65000,
+            &hex::decode(RAW_UPDATE).unwrap(),
+        );
+        data.extend_from_slice(&frame(
+            3,
+            br#"{ "updates": [{ "peer_address": "2001:db8::1",
+                "peer_asn": 65001, "announce": ["2001:db8:1::/48"],
+                "attributes": { "next_hop": "2001:db8::1" } }]}"#,
+        ));
+        data.extend_from_slice(&frame(3, b"{}"));
+        let pph = mk_per_peer_header("192.0.2.2", 65002);
+        for msg in [
+            mk_initiation_msg("router", "a router"),
+            mk_peer_up_notification_msg(
+                &pph,
+                "192.0.2.3".parse().unwrap(),
+                179,
+                4567,
+                111,
+                222,
+                0,
+                0,
+                vec![],
+                false,
+            ),
+            mk_route_monitoring_msg(
+                &pph,
+                &Prefixes::default(),
+                &"e [65002] 192.0.2.2 none 203.0.113.0/24".parse().unwrap(),
+                &[],
+            ),
+        ] {
+            data.extend_from_slice(&frame(1, &msg));
+        }
+        stream.write_all(&data).await.unwrap();
+        drop(stream);
+
+        let metrics = receiver.metrics.clone();
+        while metrics.num_messages.load(SeqCst) < 6
+            || metrics.num_connections.load(SeqCst) > 0
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(metrics.num_invalid_messages.load(SeqCst), 1);
+        assert_eq!(metrics.num_announcements.load(SeqCst), 2);
+        assert_eq!(metrics.num_withdrawals.load(SeqCst), 1);
+
+        // The peers, including the BMP monitored one, descend from the
+        // connection, which is named after the connecting process.
+        let connection = ingresses
+            .find_all(|info| info.parent_ingress == Some(receiver.parent_id))
+            .pop()
+            .unwrap();
+        let info = ingresses.get(connection).unwrap();
+        assert!(info.name.unwrap().ends_with(&format!("uid {uid})")));
+        let peers = ingresses.descendants(connection);
+        for asn in [65000, 65001, 65002] {
+            assert!(peers.iter().any(|id| {
+                ingresses.get(*id).unwrap().remote_asn
+                    == Some(Asn::from_u32(asn))
+            }));
+        }
+        accepting.abort();
+        let _ = accepting.await;
+
+        // Processes of other users are turned away.
+        let (receiver, _) = mk_receiver(&path, &[uid + 1]);
+        let accepting =
+            tokio::spawn(receiver.clone().accept(bind_unix(&path).unwrap()));
+        let _stream = UnixStream::connect(&path).await.unwrap();
+        while receiver.metrics.num_rejected_connections.load(SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        accepting.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,710 @@
+//! Ingesting routes from ZeroMQ.
+//!
+//! This unit receives the messages of a [ZeroMQ] PUB or PUSH socket, with a
+//! SUB or PULL socket of its own, and turns them into routes. It connects
+//! to the configured endpoints, reconnecting with an exponential backoff
+//! when a connection is lost, and accepts connections from peers on the
+//! configured addresses. The sockets are those of the [zeromq] crate, so
+//! only the `tcp://` transport is supported.
+//!
+//! Messages are decoded according to the configured format, as described in
+//! the [`decoder`] module. A message may consist of a single frame holding
+//! its content, or of multiple frames: the topic, any number of headers as
+//! `Name: value` text, and the content, e.g. for the `bgpupdate` format:
+//!
+//! ```text
+//! bgp.rrc00 | Peer-Address: 192.0.2.1 | Peer-ASN: 65000 | <BGP UPDATE>
+//! ```
+//!
+//! The peers of BGP UPDATE messages are grouped by the topic of the
+//! message or, if it has only one frame, by the endpoint it was received
+//! from or the address it was received on.
+//!
+//! [ZeroMQ]: https://zeromq.org/
+//! [zeromq]: https://docs.rs/zeromq/
+//! [`decoder`]: crate::units::kafka_in::decoder
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures::{channel::mpsc, StreamExt};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use serde_with::serde_as;
+use tokio::sync::Mutex;
+use zeromq::{
+    PullSocket, Socket, SocketEvent, SocketRecv, SubSocket, ZmqError,
+    ZmqResult,
+};
+
+use crate::{
+    comms::{Gate, GateMetrics, GateStatus, Terminated},
+    ingress::IngressInfo,
+    manager::{Component, WaitPoint},
+    metrics::{self, Metric, MetricType, MetricUnit},
+    units::kafka_in::{
+        decoder::{Decoder, Message},
+        unit::MessageFormat,
+    },
+};
+
+//------------ Configuration -------------------------------------------------
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+pub struct ZmqIn {
+    /// The type of socket to receive with, `sub` or `pull`.
+    pub socket: SocketType,
+
+    /// The endpoints to connect to, e.g. `tcp://collector:5556`.
+    #[serde(default)]
+    pub connect: Vec<String>,
+
+    /// The addresses to accept connections from peers on.
+    #[serde(default)]
+    pub bind: Vec<SocketAddr>,
+
+    /// The topics a SUB socket subscribes to. Without any, all messages are
+    /// subscribed to.
+    #[serde(default)]
+    pub subscribe: Vec<String>,
+
+    /// The format of the messages.
+    #[serde(default = "ZmqIn::default_format")]
+    pub format: MessageFormat,
+
+    /// How long to wait before reconnecting to an endpoint after the
+    /// connection failed. The delay doubles with every failed attempt.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "ZmqIn::default_reconnect_delay_secs")]
+    pub reconnect_delay_secs: Duration,
+
+    /// The longest to wait before reconnecting.
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    #[serde(default = "ZmqIn::default_max_reconnect_delay_secs")]
+    pub max_reconnect_delay_secs: Duration,
+}
+
+/// The type of socket to receive with.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SocketType {
+    /// Subscribes to the messages of a PUB or XPUB socket.
+    Sub,
+
+    /// Receives the messages of a PUSH socket.
+    Pull,
+}
+
+impl ZmqIn {
+    fn default_format() -> MessageFormat {
+        MessageFormat::Json
+    }
+
+    fn default_reconnect_delay_secs() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn default_max_reconnect_delay_secs() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    pub async fn run(
+        self,
+        mut component: Component,
+        gate: Gate,
+        mut waitpoint: WaitPoint,
+    ) -> Result<(), Terminated> {
+        if self.connect.is_empty() && self.bind.is_empty() {
+            error!(
+                "Unit {}: at least one of 'connect' and 'bind' must be \
+                configured",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+        if let Some(endpoint) = self
+            .connect
+            .iter()
+            .find(|endpoint| tcp_endpoint(endpoint).is_none())
+        {
+            error!(
+                "Unit {}: invalid endpoint '{endpoint}', expected \
+                tcp://host:port",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+        if self.socket == SocketType::Pull && !self.subscribe.is_empty() {
+            error!(
+                "Unit {}: 'subscribe' requires a sub socket",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+        if let MessageFormat::Custom(format) = &self.format {
+            error!(
+                "Unit {}: unsupported message format '{format}'",
+                component.name()
+            );
+            return Err(Terminated);
+        }
+
+        let metrics = Arc::new(ZmqInMetrics::new(&gate));
+        component.register_metrics(metrics.clone());
+
+        gate.process_until(waitpoint.ready()).await?;
+        waitpoint.running().await;
+
+        let mut bound = Vec::new();
+        for addr in &self.bind {
+            let endpoint = format!("tcp://{addr}");
+            match ZmqSocket::bind(&self, &endpoint).await {
+                Ok(socket) => bound.push((socket, endpoint)),
+                Err(err) => {
+                    error!(
+                        "Unit {}: cannot listen on {addr}: {err}",
+                        component.name()
+                    );
+                    return Err(Terminated);
+                }
+            }
+        }
+
+        let ingresses = component.ingresses().clone();
+        let parent_id = ingresses.register();
+        ingresses.update_info(
+            parent_id,
+            IngressInfo::new()
+                .with_unit_name(component.name().as_ref())
+                .with_desc("zmq-in unit"),
+        );
+        let receiver = Arc::new(ZmqInReceiver {
+            decoder: Mutex::new(Decoder::new(
+                self.format.clone(),
+                gate.clone(),
+                ingresses,
+                parent_id,
+                "ZeroMQ topic",
+            )),
+            name: component.name().to_string(),
+            metrics,
+            config: self,
+        });
+        let mut tasks = receiver
+            .config
+            .connect
+            .iter()
+            .map(|endpoint| {
+                tokio::spawn(receiver.clone().connect_loop(endpoint.clone()))
+            })
+            .collect::<Vec<_>>();
+        tasks.extend(bound.into_iter().map(|(socket, endpoint)| {
+            tokio::spawn(receiver.clone().accept(socket, endpoint))
+        }));
+
+        // The connections are handled in their own tasks, so here only the
+        // gate needs to be processed.
+        let res = loop {
+            match gate.process().await {
+                Ok(GateStatus::ReportLinks { report }) => {
+                    report.declare_source();
+                }
+                Ok(GateStatus::Reconfiguring { .. }) => {
+                    warn!(
+                        "Unit {}: reconfiguring zmq-in requires a restart",
+                        component.name()
+                    );
+                }
+                Ok(_) => {}
+                Err(Terminated) => break Err(Terminated),
+            }
+        };
+        tasks.iter().for_each(|task| task.abort());
+        res
+    }
+}
+
+/// Returns the address of a `tcp://host:port` endpoint.
+fn tcp_endpoint(endpoint: &str) -> Option<&str> {
+    let addr = endpoint.strip_prefix("tcp://")?;
+    let (host, port) = addr.rsplit_once(':')?;
+    (!host.is_empty() && port.parse::<u16>().is_ok()).then_some(addr)
+}
+
+//------------ ZmqInReceiver -------------------------------------------------
+
+struct ZmqInReceiver {
+    config: ZmqIn,
+    name: String,
+
+    /// The decoder shared by all connections, so that the ingresses of the
+    /// topics don't depend on the connection they were received over.
+    decoder: Mutex<Decoder>,
+
+    metrics: Arc<ZmqInMetrics>,
+}
+
+impl ZmqInReceiver {
+    /// Keep a connection to `endpoint`, reconnecting whenever it is lost.
+    async fn connect_loop(self: Arc<Self>, endpoint: String) {
+        let mut delay = self.config.reconnect_delay_secs;
+        loop {
+            debug!("Unit {}: connecting to {endpoint}", self.name);
+            match ZmqSocket::connect(&self.config, &endpoint).await {
+                Ok((socket, events)) => {
+                    // A connection that got going resets the backoff.
+                    delay = self.config.reconnect_delay_secs;
+                    let err =
+                        self.receive(socket, events, &endpoint, true).await;
+                    warn!(
+                        "Unit {}: connection to {endpoint} failed: {err}",
+                        self.name
+                    );
+                }
+                Err(err) => {
+                    warn!(
+                        "Unit {}: cannot connect to {endpoint}: {err}",
+                        self.name
+                    );
+                }
+            }
+            info!(
+                "Unit {}: reconnecting to {endpoint} in {}s",
+                self.name,
+                delay.as_secs()
+            );
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(self.config.max_reconnect_delay_secs);
+        }
+    }
+
+    /// Receive from the peers connecting to a bound socket.
+    async fn accept(
+        self: Arc<Self>,
+        (socket, events): (ZmqSocket, mpsc::Receiver<SocketEvent>),
+        endpoint: String,
+    ) {
+        let err = self.receive(socket, events, &endpoint, false).await;
+        error!("Unit {}: socket on {endpoint} failed: {err}", self.name);
+    }
+
+    /// Receive messages until the socket fails.
+    ///
+    /// The events of the socket keep track of its connections. With
+    /// `single_peer`, the socket fails once its connection is lost.
+    async fn receive(
+        &self,
+        mut socket: ZmqSocket,
+        mut events: mpsc::Receiver<SocketEvent>,
+        endpoint: &str,
+        single_peer: bool,
+    ) -> ZmqError {
+        let mut peers = 0;
+        let err = loop {
+            // Receiving is cancel safe, so can be raced against the events.
+            tokio::select! {
+                msg = socket.recv() => match msg {
+                    Ok(msg) => self.process(msg.into_vec(), endpoint).await,
+                    Err(err) => break err,
+                },
+                event = events.next() => match event {
+                    Some(
+                        SocketEvent::Connected(peer, _)
+                        | SocketEvent::Accepted(peer, _),
+                    ) => {
+                        info!("Unit {}: connected to {peer}", self.name);
+                        self.metrics.num_connects.fetch_add(1, SeqCst);
+                        self.metrics.num_connections.fetch_add(1, SeqCst);
+                        peers += 1;
+                    }
+                    Some(SocketEvent::Disconnected(_)) if peers > 0 => {
+                        self.metrics.num_connections.fetch_sub(1, SeqCst);
+                        peers -= 1;
+                        if single_peer {
+                            break ZmqError::Other("disconnected");
+                        }
+                    }
+                    Some(_) => {}
+                    None => break ZmqError::Other("socket closed"),
+                },
+            }
+        };
+        self.metrics.num_connections.fetch_sub(peers, SeqCst);
+        err
+    }
+
+    /// Decode a message and send its routes downstream.
+    async fn process(&self, frames: Vec<Bytes>, endpoint: &str) {
+        self.metrics.num_messages.fetch_add(1, SeqCst);
+        let res = match ZmqMessage::new(frames, endpoint) {
+            Ok(msg) => self.decoder.lock().await.decode(&msg).await,
+            Err(err) => Err(err),
+        };
+        match res {
+            Ok((announced, withdrawn)) => {
+                self.metrics.num_announcements.fetch_add(announced, SeqCst);
+                self.metrics.num_withdrawals.fetch_add(withdrawn, SeqCst);
+            }
+            Err(err) => {
+                self.metrics.num_invalid_messages.fetch_add(1, SeqCst);
+                debug!("Ignoring message from {endpoint}: {err}");
+            }
+        }
+    }
+}
+
+//------------ ZmqSocket -----------------------------------------------------
+
+/// A socket of the configured type.
+enum ZmqSocket {
+    Sub(SubSocket),
+    Pull(PullSocket),
+}
+
+impl ZmqSocket {
+    /// Creates a socket, subscribing to the configured topics.
+    async fn new(config: &ZmqIn) -> ZmqResult<Self> {
+        Ok(match config.socket {
+            SocketType::Sub => {
+                let mut socket = SubSocket::new();
+                if config.subscribe.is_empty() {
+                    socket.subscribe("").await?;
+                }
+                for topic in &config.subscribe {
+                    socket.subscribe(topic).await?;
+                }
+                Self::Sub(socket)
+            }
+            SocketType::Pull => Self::Pull(PullSocket::new()),
+        })
+    }
+
+    /// Creates a socket connected to `endpoint`, with its events.
+    async fn connect(
+        config: &ZmqIn,
+        endpoint: &str,
+    ) -> ZmqResult<(Self, mpsc::Receiver<SocketEvent>)> {
+        let mut socket = Self::new(config).await?;
+        let events = socket.monitor();
+        match &mut socket {
+            Self::Sub(socket) => socket.connect(endpoint).await?,
+            Self::Pull(socket) => socket.connect(endpoint).await?,
+        }
+        Ok((socket, events))
+    }
+
+    /// Creates a socket bound to `endpoint`, with its events.
+    async fn bind(
+        config: &ZmqIn,
+        endpoint: &str,
+    ) -> ZmqResult<(Self, mpsc::Receiver<SocketEvent>)> {
+        let mut socket = Self::new(config).await?;
+        let events = socket.monitor();
+        match &mut socket {
+            Self::Sub(socket) => socket.bind(endpoint).await?,
+            Self::Pull(socket) => socket.bind(endpoint).await?,
+        };
+        Ok((socket, events))
+    }
+
+    fn monitor(&mut self) -> mpsc::Receiver<SocketEvent> {
+        match self {
+            Self::Sub(socket) => socket.monitor(),
+            Self::Pull(socket) => socket.monitor(),
+        }
+    }
+
+    async fn recv(&mut self) -> ZmqResult<zeromq::ZmqMessage> {
+        match self {
+            Self::Sub(socket) => socket.recv().await,
+            Self::Pull(socket) => socket.recv().await,
+        }
+    }
+}
+
+//------------ ZmqMessage ----------------------------------------------------
+
+/// A message received over ZeroMQ.
+struct ZmqMessage {
+    /// The topic or, for single frame messages, the endpoint.
+    source: String,
+
+    headers: Vec<(String, String)>,
+    payload: Bytes,
+}
+
+impl ZmqMessage {
+    /// Interprets the frames of a message received from `endpoint`.
+    fn new(mut frames: Vec<Bytes>, endpoint: &str) -> Result<Self, String> {
+        let payload = frames.pop().ok_or("empty message")?;
+        if frames.is_empty() {
+            return Ok(Self {
+                source: endpoint.into(),
+                headers: vec![],
+                payload,
+            });
+        }
+        let source = String::from_utf8_lossy(&frames[0]).into_owned();
+        let headers = frames[1..]
+            .iter()
+            .map(|frame| {
+                std::str::from_utf8(frame)
+                    .ok()
+                    .and_then(|header| header.split_once(':'))
+                    .map(|(name, value)| {
+                        (name.trim().to_string(), value.trim().to_string())
+                    })
+                    .ok_or_else(|| "invalid header frame".to_string())
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            source,
+            headers,
+            payload,
+        })
+    }
+}
+
+impl Message for ZmqMessage {
+    fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+//------------ ZmqInMetrics --------------------------------------------------
+
+#[derive(Debug, Default)]
+struct ZmqInMetrics {
+    gate: Arc<GateMetrics>,
+    num_connections: AtomicUsize,
+    num_connects: AtomicUsize,
+    num_messages: AtomicUsize,
+    num_invalid_messages: AtomicUsize,
+    num_announcements: AtomicUsize,
+    num_withdrawals: AtomicUsize,
+}
+
+impl ZmqInMetrics {
+    fn new(gate: &Gate) -> Self {
+        Self {
+            gate: gate.metrics(),
+            ..Default::default()
+        }
+    }
+
+    const NUM_CONNECTIONS_METRIC: Metric = Metric::new(
+        "zmq_in_num_connections",
+        "the number of established ZeroMQ connections",
+        MetricType::Gauge,
+        MetricUnit::Total,
+    );
+    const NUM_CONNECTS_METRIC: Metric = Metric::new(
+        "zmq_in_num_connects",
+        "the number of times a ZeroMQ connection was established",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_MESSAGES_METRIC: Metric = Metric::new(
+        "zmq_in_num_messages",
+        "the number of messages received over ZeroMQ",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_INVALID_MESSAGES_METRIC: Metric = Metric::new(
+        "zmq_in_num_invalid_messages",
+        "the number of messages from ZeroMQ that could not be decoded",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_ANNOUNCEMENTS_METRIC: Metric = Metric::new(
+        "zmq_in_num_announcements",
+        "the number of route announcements received over ZeroMQ",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+    const NUM_WITHDRAWALS_METRIC: Metric = Metric::new(
+        "zmq_in_num_withdrawals",
+        "the number of route withdrawals received over ZeroMQ",
+        MetricType::Counter,
+        MetricUnit::Total,
+    );
+}
+
+impl metrics::Source for ZmqInMetrics {
+    fn append(&self, unit_name: &str, target: &mut metrics::Target) {
+        self.gate.append(unit_name, target);
+        target.append_simple(
+            &Self::NUM_CONNECTIONS_METRIC,
+            Some(unit_name),
+            self.num_connections.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_CONNECTS_METRIC,
+            Some(unit_name),
+            self.num_connects.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_INVALID_MESSAGES_METRIC,
+            Some(unit_name),
+            self.num_invalid_messages.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_ANNOUNCEMENTS_METRIC,
+            Some(unit_name),
+            self.num_announcements.load(SeqCst),
+        );
+        target.append_simple(
+            &Self::NUM_WITHDRAWALS_METRIC,
+            Some(unit_name),
+            self.num_withdrawals.load(SeqCst),
+        );
+    }
+}
+
+//------------ Tests ---------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use inetnum::asn::Asn;
+
+    use crate::{bgp::encode::RAW_UPDATE, ingress};
+
+    use super::*;
+
+    #[test]
+    fn config_deserialization() {
+        let config: ZmqIn = toml::from_str(
+            r#"
+            socket = "sub"
+            connect = ["tcp://collector:5556"]
+            subscribe = ["bgp."]
+            format = "bgpupdate"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.socket, SocketType::Sub);
+        assert!(matches!(config.format, MessageFormat::BgpUpdate));
+        assert_eq!(config.max_reconnect_delay_secs, Duration::from_secs(60));
+
+        let config: ZmqIn = toml::from_str(
+            r#"
+            socket = "pull"
+            bind = ["0.0.0.0:5557"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.socket, SocketType::Pull);
+        assert!(matches!(config.format, MessageFormat::Json));
+
+        assert_eq!(
+            tcp_endpoint("tcp://collector:5556"),
+            Some("collector:5556")
+        );
+        assert!(tcp_endpoint("tcp://[2001:db8::1]:5556").is_some());
+        assert!(tcp_endpoint("ipc:///tmp/bgp").is_none());
+        assert!(tcp_endpoint("tcp://collector").is_none());
+    }
+
+    #[tokio::test]
+    async fn multipart_messages_are_decoded() {
+        let config: ZmqIn = toml::from_str(
+            r#"
+            socket = "sub"
+            connect = ["tcp://collector:5556"]
+            subscribe = ["bgp."]
+            format = "bgpupdate"
+            "#,
+        )
+        .unwrap();
+        let (gate, _agent) = Gate::new(0);
+        let ingresses = Arc::new(ingress::Register::default());
+        let parent_id = ingresses.register();
+        let receiver = Arc::new(ZmqInReceiver {
+            decoder: Mutex::new(Decoder::new(
+                config.format.clone(),
+                gate.clone(),
+                ingresses.clone(),
+                parent_id,
+                "ZeroMQ topic",
+            )),
+            name: "zmq".into(),
+            metrics: Arc::new(ZmqInMetrics::new(&gate)),
+            config,
+        });
+
+        // A BGP UPDATE with its peer in the headers, and one without.
+        let raw = Bytes::from(hex::decode(RAW_UPDATE).unwrap());
+        let frames =
+            ["bgp.rrc00", "Peer-Address: 192.0.2.1", "Peer-ASN: 65000"]
+                .map(Bytes::from);
+        let mut msg = frames.to_vec();
+        msg.push(raw.clone());
+        receiver.process(msg, "tcp://collector:5556").await;
+        receiver.process(vec![raw], "tcp://collector:5556").await;
+
+        let metrics = &receiver.metrics;
+        assert_eq!(metrics.num_messages.load(SeqCst), 2);
+        assert_eq!(metrics.num_invalid_messages.load(SeqCst), 1);
+        assert_eq!(metrics.num_announcements.load(SeqCst), 1);
+        assert_eq!(metrics.num_withdrawals.load(SeqCst), 1);
+
+        // The peer is registered under the topic.
+        let peer = ingresses
+            .find_all(|info| info.remote_asn == Some(Asn::from_u32(65000)))
+            .pop()
+            .unwrap();
+        let topic = ingresses.get(peer).unwrap().parent_ingress.unwrap();
+        let info = ingresses.get(topic).unwrap();
+        assert_eq!(info.name.as_deref(), Some("bgp.rrc00"));
+    }
+
+    #[test]
+    fn frames_are_interpreted() {
+        let msg = ZmqMessage::new(
+            vec![Bytes::from_static(b"x")],
+            "tcp://192.0.2.1:5556",
+        )
+        .unwrap();
+        assert_eq!(msg.source(), "tcp://192.0.2.1:5556");
+        assert_eq!(msg.payload(), b"x");
+
+        let msg = ZmqMessage::new(
+            ["bgp", "peer-asn:65000", "x"].map(Bytes::from).to_vec(),
+            "tcp://192.0.2.1:5556",
+        )
+        .unwrap();
+        assert_eq!(msg.source(), "bgp");
+        assert_eq!(msg.header("Peer-ASN"), Some("65000"));
+
+        assert!(ZmqMessage::new(vec![], "").is_err());
+        assert!(ZmqMessage::new(
+            ["bgp", "no header", "x"].map(Bytes::from).to_vec(),
+            ""
+        )
+        .is_err());
+    }
+}